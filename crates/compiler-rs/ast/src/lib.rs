@@ -53,6 +53,7 @@ pub enum Node {
     FieldExpr(FieldExpr),
     DerefExpr(DerefExpr),
     InheritedExpr(InheritedExpr),
+    MethodCall(MethodCall),  // Method call: object.Method(args), as an expression or statement
     AddressOfExpr(AddressOfExpr),
     EnumLiteralExpr(EnumLiteralExpr),  // Enum value reference (e.g., Color.Red)
     AnonymousFunction(AnonymousFunction),  // Anonymous function: function(params): return_type begin ... end
@@ -71,6 +72,7 @@ pub enum Node {
     ProceduralType(ProceduralType),
     InterfaceType(InterfaceType),
     EnumType(EnumType),
+    SubrangeType(SubrangeType),
     HelperType(HelperType),  // Class/Record helper: class helper for Type
     ObjectType(ObjectType),  // Old-style object (Turbo Pascal): object ... end
     
@@ -86,6 +88,7 @@ pub enum Node {
 pub struct Program {
     pub name: String,
     pub directives: Vec<Node>,  // Directive nodes (compiler directives)
+    pub uses: Option<UsesClause>, // Optional uses clause
     pub block: Box<Node>, // Block node
     pub span: Span,
 }
@@ -174,6 +177,7 @@ pub struct VarDecl {
 #[derive(Debug, Clone, PartialEq)]
 pub struct ConstDecl {
     pub name: String,
+    pub type_expr: Option<Box<Node>>, // Declared type of a typed constant (NAME: TYPE = value)
     pub value: Box<Node>,         // Expression node
     pub is_resourcestring: bool,  // true if declared with RESOURCESTRING
     pub span: Span,
@@ -193,6 +197,7 @@ pub struct TypeDecl {
     pub name: String,
     pub generic_params: Vec<GenericParam>, // Generic type parameters (e.g., `<T, U>`)
     pub type_expr: Box<Node>,     // Type node
+    pub distinct: bool,           // `= type T` declares a new type rather than an alias
     pub span: Span,
 }
 
@@ -208,6 +213,7 @@ pub struct ProcDecl {
     pub is_external: bool,         // true if EXTERNAL keyword is present
     pub external_name: Option<String>, // Optional external name for EXTERNAL declarations
     pub is_class_method: bool,     // true if CLASS keyword is present (class procedure)
+    pub binding: MethodBinding,    // VIRTUAL or OVERRIDE directive of a method
     pub span: Span,
 }
 
@@ -224,9 +230,21 @@ pub struct FuncDecl {
     pub is_external: bool,         // true if EXTERNAL keyword is present
     pub external_name: Option<String>, // Optional external name for EXTERNAL declarations
     pub is_class_method: bool,     // true if CLASS keyword is present (class function)
+    pub is_compiletime: bool,      // true if marked {$COMPILETIME} (evaluated at compile time)
+    pub is_pure: bool,             // true if marked {$PURE} (result depends on the arguments alone)
+    pub binding: MethodBinding,    // VIRTUAL or OVERRIDE directive of a method
     pub span: Span,
 }
 
+/// How calls of a method are bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MethodBinding {
+    #[default]
+    Static,    // Called directly
+    Virtual,   // VIRTUAL: gets a VMT slot, called through it
+    Override,  // OVERRIDE: replaces an inherited virtual method's VMT entry
+}
+
 /// Property declaration
 #[derive(Debug, Clone, PartialEq)]
 pub struct PropertyDecl {
@@ -264,7 +282,7 @@ pub struct OperatorDecl {
 pub struct Param {
     pub names: Vec<String>,        // Parameter names
     pub param_type: ParamType,     // Parameter passing mode
-    pub type_expr: Box<Node>,      // Type node
+    pub type_expr: Option<Box<Node>>, // Type node (None for untyped var/const/out parameters)
     pub default_value: Option<Box<Node>>, // Optional default value
     pub span: Span,
 }
@@ -304,6 +322,7 @@ pub struct ForStmt {
     pub direction: ForDirection,    // To or Downto
     pub end_expr: Box<Node>,         // Expression node (final value)
     pub body: Box<Node>,             // Statement or Block node
+    pub unroll: Option<u32>,         // Unroll factor from a preceding {$UNROLL n}
     pub span: Span,
 }
 
@@ -413,10 +432,11 @@ pub struct GotoStmt {
     pub span: Span,
 }
 
-/// Inline assembly statement: ASM [body] END
+/// Inline assembly statement: ASM [body] END ['reg', ...]
 #[derive(Debug, Clone, PartialEq)]
 pub struct AsmStmt {
-    pub body: String,  // Assembly code body (raw text between ASM and END)
+    pub body: String,  // Z80 assembly between ASM and END, a line per instruction, trimmed
+    pub clobbers: Vec<String>, // Registers the body changes, listed after END, lowercase
     pub span: Span,
 }
 
@@ -495,10 +515,12 @@ pub struct LiteralExpr {
 /// Literal value
 #[derive(Debug, Clone, PartialEq)]
 pub enum LiteralValue {
-    Integer(u16),
+    Integer(u32),
+    Real(f64),
     Char(u8),
     String(String),
     Boolean(bool),
+    Bytes(Vec<u8>), // $"00 FF 3A"
 }
 
 /// Identifier expression
@@ -547,6 +569,15 @@ pub struct InheritedExpr {
     pub span: Span,
 }
 
+/// Method call: object.Method [ ( args ) ]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodCall {
+    pub object: Box<Node>,          // Expression node (class instance)
+    pub method: String,             // Method name
+    pub args: Vec<Node>,            // Expression nodes
+    pub span: Span,
+}
+
 /// Address-of expression (@variable)
 #[derive(Debug, Clone, PartialEq)]
 pub struct AddressOfExpr {
@@ -574,7 +605,7 @@ pub struct AnonymousProcedure {
 /// Record type
 #[derive(Debug, Clone, PartialEq)]
 pub struct RecordType {
-    pub is_packed: bool,            // true if PACKED keyword is present
+    pub is_packed: bool,            // true if PACKED keyword is present or under {$ALIGN 1}
     pub fields: Vec<FieldDecl>,     // Field declarations
     pub variant: Option<VariantPart>, // Optional variant part (CASE)
     pub span: Span,
@@ -601,15 +632,16 @@ pub struct VariantPart {
 /// Variant case (one branch in CASE)
 #[derive(Debug, Clone, PartialEq)]
 pub struct Variant {
-    pub values: Vec<Node>,           // Case values (expressions or ranges)
+    pub values: Vec<Node>,           // Case values (expressions, or SubrangeType for ranges)
     pub fields: Vec<FieldDecl>,      // Fields for this variant
+    pub variant: Option<Box<VariantPart>>, // Optional nested variant part, after the fields
     pub span: Span,
 }
 
 /// Array type (static array: ARRAY [ index_type ] OF element_type)
 #[derive(Debug, Clone, PartialEq)]
 pub struct ArrayType {
-    pub is_packed: bool,            // true if PACKED keyword is present
+    pub is_packed: bool,            // true if PACKED keyword is present or under {$ALIGN 1}
     pub index_type: Box<Node>,      // Type node (index type)
     pub element_type: Box<Node>,    // Type node (element type)
     pub span: Span,
@@ -686,6 +718,14 @@ pub struct EnumType {
     pub span: Span,
 }
 
+/// Subrange type: low..high, e.g. 1..10, 'a'..'z' or Red..Green
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubrangeType {
+    pub low: Box<Node>,   // Constant expression for the lower bound
+    pub high: Box<Node>,  // Constant expression for the upper bound
+    pub span: Span,
+}
+
 /// Enum literal expression (enum value reference: Color.Red or just Red)
 #[derive(Debug, Clone, PartialEq)]
 pub struct EnumLiteralExpr {
@@ -824,6 +864,7 @@ impl Node {
             Node::FieldExpr(f) => f.span,
             Node::DerefExpr(d) => d.span,
             Node::InheritedExpr(i) => i.span,
+            Node::MethodCall(m) => m.span,
             Node::AddressOfExpr(a) => a.span,
             Node::AnonymousFunction(a) => a.span,
             Node::AnonymousProcedure(a) => a.span,
@@ -839,6 +880,7 @@ impl Node {
             Node::ProceduralType(p) => p.span,
             Node::InterfaceType(i) => i.span,
             Node::EnumType(e) => e.span,
+            Node::SubrangeType(s) => s.span,
             Node::HelperType(h) => h.span,
             Node::ObjectType(o) => o.span,
             Node::EnumLiteralExpr(e) => e.span,
@@ -846,6 +888,600 @@ impl Node {
             Node::Directive(d) => d.span,
         }
     }
+
+    /// Name of the node's variant, e.g. `"IfStmt"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Node::Program(_) => "Program",
+            Node::Unit(_) => "Unit",
+            Node::Library(_) => "Library",
+            Node::Block(_) => "Block",
+            Node::UsesClause(_) => "UsesClause",
+            Node::InterfaceSection(_) => "InterfaceSection",
+            Node::ImplementationSection(_) => "ImplementationSection",
+            Node::VarDecl(_) => "VarDecl",
+            Node::ConstDecl(_) => "ConstDecl",
+            Node::TypeDecl(_) => "TypeDecl",
+            Node::LabelDecl(_) => "LabelDecl",
+            Node::ProcDecl(_) => "ProcDecl",
+            Node::FuncDecl(_) => "FuncDecl",
+            Node::OperatorDecl(_) => "OperatorDecl",
+            Node::PropertyDecl(_) => "PropertyDecl",
+            Node::IfStmt(_) => "IfStmt",
+            Node::WhileStmt(_) => "WhileStmt",
+            Node::ForStmt(_) => "ForStmt",
+            Node::ForInStmt(_) => "ForInStmt",
+            Node::RepeatStmt(_) => "RepeatStmt",
+            Node::CaseStmt(_) => "CaseStmt",
+            Node::AssignStmt(_) => "AssignStmt",
+            Node::CallStmt(_) => "CallStmt",
+            Node::TryStmt(_) => "TryStmt",
+            Node::RaiseStmt(_) => "RaiseStmt",
+            Node::WithStmt(_) => "WithStmt",
+            Node::GotoStmt(_) => "GotoStmt",
+            Node::LabeledStmt(_) => "LabeledStmt",
+            Node::AsmStmt(_) => "AsmStmt",
+            Node::BinaryExpr(_) => "BinaryExpr",
+            Node::UnaryExpr(_) => "UnaryExpr",
+            Node::LiteralExpr(_) => "LiteralExpr",
+            Node::IdentExpr(_) => "IdentExpr",
+            Node::CallExpr(_) => "CallExpr",
+            Node::IndexExpr(_) => "IndexExpr",
+            Node::FieldExpr(_) => "FieldExpr",
+            Node::DerefExpr(_) => "DerefExpr",
+            Node::InheritedExpr(_) => "InheritedExpr",
+            Node::MethodCall(_) => "MethodCall",
+            Node::AddressOfExpr(_) => "AddressOfExpr",
+            Node::AnonymousFunction(_) => "AnonymousFunction",
+            Node::AnonymousProcedure(_) => "AnonymousProcedure",
+            Node::RecordType(_) => "RecordType",
+            Node::ArrayType(_) => "ArrayType",
+            Node::DynamicArrayType(_) => "DynamicArrayType",
+            Node::NamedType(_) => "NamedType",
+            Node::PointerType(_) => "PointerType",
+            Node::ClassType(_) => "ClassType",
+            Node::SetType(_) => "SetType",
+            Node::StringType(_) => "StringType",
+            Node::FileType(_) => "FileType",
+            Node::ProceduralType(_) => "ProceduralType",
+            Node::InterfaceType(_) => "InterfaceType",
+            Node::EnumType(_) => "EnumType",
+            Node::SubrangeType(_) => "SubrangeType",
+            Node::HelperType(_) => "HelperType",
+            Node::ObjectType(_) => "ObjectType",
+            Node::EnumLiteralExpr(_) => "EnumLiteralExpr",
+            Node::SetLiteral(_) => "SetLiteral",
+            Node::Directive(_) => "Directive",
+        }
+    }
+
+    /// Direct child nodes, in source order
+    ///
+    /// Children held in helper structs (parameters, case branches, record
+    /// fields, unit sections, class members) are listed as if they belonged
+    /// to this node.
+    pub fn children(&self) -> Vec<&Node> {
+        let mut children = Vec::new();
+        match self {
+            Node::Program(p) => {
+                children.extend(&p.directives);
+                children.push(&*p.block);
+            }
+            Node::Unit(u) => {
+                if let Some(interface) = &u.interface {
+                    children.extend(interface.children());
+                }
+                if let Some(implementation) = &u.implementation {
+                    children.extend(implementation.children());
+                }
+                children.extend(u.initialization.as_deref());
+                children.extend(u.finalization.as_deref());
+            }
+            Node::Library(l) => children.extend(l.block.as_deref()),
+            Node::Block(b) => {
+                for decls in [
+                    &b.directives,
+                    &b.label_decls,
+                    &b.const_decls,
+                    &b.type_decls,
+                    &b.var_decls,
+                    &b.threadvar_decls,
+                    &b.proc_decls,
+                    &b.func_decls,
+                    &b.operator_decls,
+                    &b.statements,
+                ] {
+                    children.extend(decls);
+                }
+            }
+            Node::InterfaceSection(i) => children.extend(i.children()),
+            Node::ImplementationSection(i) => children.extend(i.children()),
+            Node::VarDecl(v) => {
+                children.push(&*v.type_expr);
+                children.extend(v.absolute_address.as_deref());
+            }
+            Node::ConstDecl(c) => {
+                children.extend(c.type_expr.as_deref());
+                children.push(&*c.value);
+            }
+            Node::TypeDecl(t) => {
+                children.extend(t.generic_params.iter().filter_map(|g| g.constraint.as_deref()));
+                children.push(&*t.type_expr);
+            }
+            Node::ProcDecl(p) => {
+                children.extend(p.generic_params.iter().filter_map(|g| g.constraint.as_deref()));
+                push_params(&mut children, &p.params);
+                children.push(&*p.block);
+            }
+            Node::FuncDecl(f) => {
+                children.extend(f.generic_params.iter().filter_map(|g| g.constraint.as_deref()));
+                push_params(&mut children, &f.params);
+                children.push(&*f.return_type);
+                children.push(&*f.block);
+            }
+            Node::OperatorDecl(o) => {
+                push_params(&mut children, &o.params);
+                children.push(&*o.return_type);
+                children.push(&*o.block);
+            }
+            Node::PropertyDecl(p) => {
+                push_params(&mut children, &p.index_params);
+                children.push(&*p.property_type);
+                for expr in [&p.index_expr, &p.default_expr, &p.stored_expr] {
+                    children.extend(expr.as_deref());
+                }
+            }
+            Node::IfStmt(i) => {
+                children.push(&*i.condition);
+                children.push(&*i.then_block);
+                children.extend(i.else_block.as_deref());
+            }
+            Node::WhileStmt(w) => children.extend([&*w.condition, &*w.body]),
+            Node::ForStmt(f) => children.extend([&*f.start_expr, &*f.end_expr, &*f.body]),
+            Node::ForInStmt(f) => children.extend([&*f.collection_expr, &*f.body]),
+            Node::RepeatStmt(r) => {
+                children.extend(&r.statements);
+                children.push(&*r.condition);
+            }
+            Node::CaseStmt(c) => {
+                children.push(&*c.expr);
+                for branch in &c.cases {
+                    children.extend(&branch.values);
+                    children.push(&*branch.statement);
+                }
+                children.extend(c.else_branch.as_deref());
+            }
+            Node::AssignStmt(a) => children.extend([&*a.target, &*a.value]),
+            Node::CallStmt(c) => children.extend(&c.args),
+            Node::TryStmt(t) => {
+                children.extend(&t.try_block);
+                children.extend(t.except_block.iter().flatten());
+                for handler in &t.exception_handlers {
+                    children.extend([&*handler.exception_type, &*handler.handler]);
+                }
+                children.extend(t.exception_else.as_deref());
+                children.extend(t.finally_block.iter().flatten());
+            }
+            Node::RaiseStmt(r) => children.extend(r.exception.as_deref()),
+            Node::WithStmt(w) => {
+                children.extend(&w.records);
+                children.push(&*w.statement);
+            }
+            Node::LabeledStmt(l) => children.push(&*l.statement),
+            Node::BinaryExpr(b) => children.extend([&*b.left, &*b.right]),
+            Node::UnaryExpr(u) => children.push(&*u.expr),
+            Node::CallExpr(c) => children.extend(&c.args),
+            Node::IndexExpr(i) => children.extend([&*i.array, &*i.index]),
+            Node::FieldExpr(f) => children.push(&*f.record),
+            Node::DerefExpr(d) => children.push(&*d.pointer),
+            Node::InheritedExpr(i) => children.extend(&i.args),
+            Node::MethodCall(m) => {
+                children.push(&*m.object);
+                children.extend(&m.args);
+            }
+            Node::AddressOfExpr(a) => children.push(&*a.target),
+            Node::AnonymousFunction(a) => {
+                push_params(&mut children, &a.params);
+                children.extend([&*a.return_type, &*a.block]);
+            }
+            Node::AnonymousProcedure(a) => {
+                push_params(&mut children, &a.params);
+                children.push(&*a.block);
+            }
+            Node::RecordType(r) => {
+                push_fields(&mut children, &r.fields);
+                if let Some(variant) = &r.variant {
+                    children.push(&*variant.tag_type);
+                    for case in &variant.variants {
+                        children.extend(&case.values);
+                        push_fields(&mut children, &case.fields);
+                    }
+                    if let Some(fields) = &variant.else_variant {
+                        push_fields(&mut children, fields);
+                    }
+                }
+            }
+            Node::ArrayType(a) => children.extend([&*a.index_type, &*a.element_type]),
+            Node::DynamicArrayType(d) => children.push(&*d.element_type),
+            Node::SubrangeType(s) => children.extend([&*s.low, &*s.high]),
+            Node::NamedType(n) => children.extend(n.generic_args.iter().map(|arg| &**arg)),
+            Node::PointerType(p) => children.push(&*p.base_type),
+            Node::ClassType(c) => {
+                children.extend(c.meta_class_type.as_deref());
+                push_members(&mut children, &c.members);
+            }
+            Node::SetType(s) => children.push(&*s.element_type),
+            Node::StringType(s) => children.extend(s.length.as_deref()),
+            Node::FileType(f) => children.extend(f.element_type.as_deref()),
+            Node::ProceduralType(p) => {
+                push_params(&mut children, &p.params);
+                children.extend(p.return_type.as_deref());
+            }
+            Node::InterfaceType(i) => {
+                children.extend(&i.methods);
+                children.extend(&i.properties);
+            }
+            Node::HelperType(h) => {
+                children.push(&*h.target_type);
+                push_members(&mut children, &h.members);
+            }
+            Node::ObjectType(o) => push_members(&mut children, &o.members),
+            Node::SetLiteral(s) => {
+                for element in &s.elements {
+                    match element {
+                        SetElement::Value(value) => children.push(&**value),
+                        SetElement::Range { start, end } => children.extend([&**start, &**end]),
+                    }
+                }
+            }
+            Node::UsesClause(_)
+            | Node::LabelDecl(_)
+            | Node::GotoStmt(_)
+            | Node::AsmStmt(_)
+            | Node::LiteralExpr(_)
+            | Node::IdentExpr(_)
+            | Node::EnumType(_)
+            | Node::EnumLiteralExpr(_)
+            | Node::Directive(_) => {}
+        }
+        children
+    }
+
+    /// Direct child nodes, in source order, for passes rewriting the tree
+    /// in place (see [`Node::children`])
+    pub fn children_mut(&mut self) -> Vec<&mut Node> {
+        let mut children = Vec::new();
+        match self {
+            Node::Program(p) => {
+                children.extend(&mut p.directives);
+                children.push(&mut *p.block);
+            }
+            Node::Unit(u) => {
+                if let Some(interface) = &mut u.interface {
+                    children.extend(interface.children_mut());
+                }
+                if let Some(implementation) = &mut u.implementation {
+                    children.extend(implementation.children_mut());
+                }
+                children.extend(u.initialization.as_deref_mut());
+                children.extend(u.finalization.as_deref_mut());
+            }
+            Node::Library(l) => children.extend(l.block.as_deref_mut()),
+            Node::Block(b) => {
+                for decls in [
+                    &mut b.directives,
+                    &mut b.label_decls,
+                    &mut b.const_decls,
+                    &mut b.type_decls,
+                    &mut b.var_decls,
+                    &mut b.threadvar_decls,
+                    &mut b.proc_decls,
+                    &mut b.func_decls,
+                    &mut b.operator_decls,
+                    &mut b.statements,
+                ] {
+                    children.extend(decls);
+                }
+            }
+            Node::InterfaceSection(i) => children.extend(i.children_mut()),
+            Node::ImplementationSection(i) => children.extend(i.children_mut()),
+            Node::VarDecl(v) => {
+                children.push(&mut *v.type_expr);
+                children.extend(v.absolute_address.as_deref_mut());
+            }
+            Node::ConstDecl(c) => {
+                children.extend(c.type_expr.as_deref_mut());
+                children.push(&mut *c.value);
+            }
+            Node::TypeDecl(t) => {
+                children.extend(t.generic_params.iter_mut().filter_map(|g| g.constraint.as_deref_mut()));
+                children.push(&mut *t.type_expr);
+            }
+            Node::ProcDecl(p) => {
+                children.extend(p.generic_params.iter_mut().filter_map(|g| g.constraint.as_deref_mut()));
+                push_params_mut(&mut children, &mut p.params);
+                children.push(&mut *p.block);
+            }
+            Node::FuncDecl(f) => {
+                children.extend(f.generic_params.iter_mut().filter_map(|g| g.constraint.as_deref_mut()));
+                push_params_mut(&mut children, &mut f.params);
+                children.push(&mut *f.return_type);
+                children.push(&mut *f.block);
+            }
+            Node::OperatorDecl(o) => {
+                push_params_mut(&mut children, &mut o.params);
+                children.push(&mut *o.return_type);
+                children.push(&mut *o.block);
+            }
+            Node::PropertyDecl(p) => {
+                push_params_mut(&mut children, &mut p.index_params);
+                children.push(&mut *p.property_type);
+                for expr in [&mut p.index_expr, &mut p.default_expr, &mut p.stored_expr] {
+                    children.extend(expr.as_deref_mut());
+                }
+            }
+            Node::IfStmt(i) => {
+                children.push(&mut *i.condition);
+                children.push(&mut *i.then_block);
+                children.extend(i.else_block.as_deref_mut());
+            }
+            Node::WhileStmt(w) => children.extend([&mut *w.condition, &mut *w.body]),
+            Node::ForStmt(f) => children.extend([&mut *f.start_expr, &mut *f.end_expr, &mut *f.body]),
+            Node::ForInStmt(f) => children.extend([&mut *f.collection_expr, &mut *f.body]),
+            Node::RepeatStmt(r) => {
+                children.extend(&mut r.statements);
+                children.push(&mut *r.condition);
+            }
+            Node::CaseStmt(c) => {
+                children.push(&mut *c.expr);
+                for branch in &mut c.cases {
+                    children.extend(&mut branch.values);
+                    children.push(&mut *branch.statement);
+                }
+                children.extend(c.else_branch.as_deref_mut());
+            }
+            Node::AssignStmt(a) => children.extend([&mut *a.target, &mut *a.value]),
+            Node::CallStmt(c) => children.extend(&mut c.args),
+            Node::TryStmt(t) => {
+                children.extend(&mut t.try_block);
+                children.extend(t.except_block.iter_mut().flatten());
+                for handler in &mut t.exception_handlers {
+                    children.extend([&mut *handler.exception_type, &mut *handler.handler]);
+                }
+                children.extend(t.exception_else.as_deref_mut());
+                children.extend(t.finally_block.iter_mut().flatten());
+            }
+            Node::RaiseStmt(r) => children.extend(r.exception.as_deref_mut()),
+            Node::WithStmt(w) => {
+                children.extend(&mut w.records);
+                children.push(&mut *w.statement);
+            }
+            Node::LabeledStmt(l) => children.push(&mut *l.statement),
+            Node::BinaryExpr(b) => children.extend([&mut *b.left, &mut *b.right]),
+            Node::UnaryExpr(u) => children.push(&mut *u.expr),
+            Node::CallExpr(c) => children.extend(&mut c.args),
+            Node::IndexExpr(i) => children.extend([&mut *i.array, &mut *i.index]),
+            Node::FieldExpr(f) => children.push(&mut *f.record),
+            Node::DerefExpr(d) => children.push(&mut *d.pointer),
+            Node::InheritedExpr(i) => children.extend(&mut i.args),
+            Node::MethodCall(m) => {
+                children.push(&mut *m.object);
+                children.extend(&mut m.args);
+            }
+            Node::AddressOfExpr(a) => children.push(&mut *a.target),
+            Node::AnonymousFunction(a) => {
+                push_params_mut(&mut children, &mut a.params);
+                children.extend([&mut *a.return_type, &mut *a.block]);
+            }
+            Node::AnonymousProcedure(a) => {
+                push_params_mut(&mut children, &mut a.params);
+                children.push(&mut *a.block);
+            }
+            Node::RecordType(r) => {
+                push_fields_mut(&mut children, &mut r.fields);
+                if let Some(variant) = &mut r.variant {
+                    children.push(&mut *variant.tag_type);
+                    for case in &mut variant.variants {
+                        children.extend(&mut case.values);
+                        push_fields_mut(&mut children, &mut case.fields);
+                    }
+                    if let Some(fields) = &mut variant.else_variant {
+                        push_fields_mut(&mut children, fields);
+                    }
+                }
+            }
+            Node::ArrayType(a) => children.extend([&mut *a.index_type, &mut *a.element_type]),
+            Node::DynamicArrayType(d) => children.push(&mut *d.element_type),
+            Node::SubrangeType(s) => children.extend([&mut *s.low, &mut *s.high]),
+            Node::NamedType(n) => children.extend(n.generic_args.iter_mut().map(|arg| &mut **arg)),
+            Node::PointerType(p) => children.push(&mut *p.base_type),
+            Node::ClassType(c) => {
+                children.extend(c.meta_class_type.as_deref_mut());
+                push_members_mut(&mut children, &mut c.members);
+            }
+            Node::SetType(s) => children.push(&mut *s.element_type),
+            Node::StringType(s) => children.extend(s.length.as_deref_mut()),
+            Node::FileType(f) => children.extend(f.element_type.as_deref_mut()),
+            Node::ProceduralType(p) => {
+                push_params_mut(&mut children, &mut p.params);
+                children.extend(p.return_type.as_deref_mut());
+            }
+            Node::InterfaceType(i) => {
+                children.extend(&mut i.methods);
+                children.extend(&mut i.properties);
+            }
+            Node::HelperType(h) => {
+                children.push(&mut *h.target_type);
+                push_members_mut(&mut children, &mut h.members);
+            }
+            Node::ObjectType(o) => push_members_mut(&mut children, &mut o.members),
+            Node::SetLiteral(s) => {
+                for element in &mut s.elements {
+                    match element {
+                        SetElement::Value(value) => children.push(&mut **value),
+                        SetElement::Range { start, end } => children.extend([&mut **start, &mut **end]),
+                    }
+                }
+            }
+            Node::UsesClause(_)
+            | Node::LabelDecl(_)
+            | Node::GotoStmt(_)
+            | Node::AsmStmt(_)
+            | Node::LiteralExpr(_)
+            | Node::IdentExpr(_)
+            | Node::EnumType(_)
+            | Node::EnumLiteralExpr(_)
+            | Node::Directive(_) => {}
+        }
+        children
+    }
+
+}
+
+impl AsmStmt {
+    /// Pascal identifiers the body refers to as `@Name`, each with the byte
+    /// range of the reference; `;` comments are skipped
+    pub fn references(&self) -> Vec<(std::ops::Range<usize>, &str)> {
+        let mut references = vec![];
+        let mut chars = self.body.char_indices().peekable();
+        let mut in_string = false;
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '"' => in_string = !in_string,
+                '\n' => in_string = false,
+                ';' if !in_string => {
+                    while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                }
+                '@' if !in_string && chars.peek().is_some_and(|&(_, c)| c.is_alphabetic() || c == '_') => {
+                    let mut end = i + 1;
+                    while let Some((j, c)) = chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                        end = j + c.len_utf8();
+                    }
+                    references.push((i..end, &self.body[i + 1..end]));
+                }
+                _ => {}
+            }
+        }
+        references
+    }
+}
+
+impl InterfaceSection {
+    /// Declarations in source order
+    pub fn children(&self) -> Vec<&Node> {
+        [
+            &self.const_decls,
+            &self.type_decls,
+            &self.var_decls,
+            &self.proc_decls,
+            &self.func_decls,
+            &self.operator_decls,
+            &self.property_decls,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Declarations in source order, to rewrite in place
+    pub fn children_mut(&mut self) -> Vec<&mut Node> {
+        [
+            &mut self.const_decls,
+            &mut self.type_decls,
+            &mut self.var_decls,
+            &mut self.proc_decls,
+            &mut self.func_decls,
+            &mut self.operator_decls,
+            &mut self.property_decls,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl ImplementationSection {
+    /// Declarations in source order
+    pub fn children(&self) -> Vec<&Node> {
+        [
+            &self.const_decls,
+            &self.type_decls,
+            &self.var_decls,
+            &self.proc_decls,
+            &self.func_decls,
+            &self.operator_decls,
+            &self.property_decls,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Declarations in source order, to rewrite in place
+    pub fn children_mut(&mut self) -> Vec<&mut Node> {
+        [
+            &mut self.const_decls,
+            &mut self.type_decls,
+            &mut self.var_decls,
+            &mut self.proc_decls,
+            &mut self.func_decls,
+            &mut self.operator_decls,
+            &mut self.property_decls,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+fn push_params<'a>(children: &mut Vec<&'a Node>, params: &'a [Param]) {
+    for param in params {
+        children.extend(param.type_expr.as_deref());
+        children.extend(param.default_value.as_deref());
+    }
+}
+
+fn push_fields<'a>(children: &mut Vec<&'a Node>, fields: &'a [FieldDecl]) {
+    children.extend(fields.iter().map(|field| &*field.type_expr));
+}
+
+fn push_members<'a>(children: &mut Vec<&'a Node>, members: &'a [(Visibility, ClassMember)]) {
+    for (_, member) in members {
+        match member {
+            ClassMember::Field(node)
+            | ClassMember::Method(node)
+            | ClassMember::Property(node)
+            | ClassMember::Constructor(node)
+            | ClassMember::Destructor(node)
+            | ClassMember::Type(node)
+            | ClassMember::Const(node) => children.push(node),
+        }
+    }
+}
+
+fn push_params_mut<'a>(children: &mut Vec<&'a mut Node>, params: &'a mut [Param]) {
+    for param in params {
+        children.extend(param.type_expr.as_deref_mut());
+        children.extend(param.default_value.as_deref_mut());
+    }
+}
+
+fn push_fields_mut<'a>(children: &mut Vec<&'a mut Node>, fields: &'a mut [FieldDecl]) {
+    children.extend(fields.iter_mut().map(|field| &mut *field.type_expr));
+}
+
+fn push_members_mut<'a>(children: &mut Vec<&'a mut Node>, members: &'a mut [(Visibility, ClassMember)]) {
+    for (_, member) in members {
+        match member {
+            ClassMember::Field(node)
+            | ClassMember::Method(node)
+            | ClassMember::Property(node)
+            | ClassMember::Constructor(node)
+            | ClassMember::Destructor(node)
+            | ClassMember::Type(node)
+            | ClassMember::Const(node) => children.push(node),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -873,6 +1509,7 @@ mod tests {
         let program = Node::Program(Program {
             directives: vec![],
             name: "HelloWorld".to_string(),
+            uses: None,
             block: Box::new(block),
             span,
         });
@@ -969,6 +1606,7 @@ mod tests {
         let span = Span::new(0, 15, 1, 1);
         let const_decl = Node::ConstDecl(ConstDecl {
             name: "MAX_SIZE".to_string(),
+            type_expr: None,
             value: Box::new(Node::LiteralExpr(LiteralExpr {
                 value: LiteralValue::Integer(100),
                 span,
@@ -990,6 +1628,7 @@ mod tests {
                 generic_args: vec![],
                 span,
             })),
+            distinct: false,
             span,
         });
         assert_eq!(type_decl.span(), span);
@@ -1021,6 +1660,7 @@ mod tests {
             is_external: false,
             external_name: None,
             is_class_method: false,
+            binding: MethodBinding::Static,
             span,
         });
         assert_eq!(proc_decl.span(), span);
@@ -1045,11 +1685,11 @@ mod tests {
         let param = Param {
             names: vec!["x".to_string()],
             param_type: ParamType::Value,
-            type_expr: Box::new(Node::NamedType(NamedType {
+            type_expr: Some(Box::new(Node::NamedType(NamedType {
                 name: "integer".to_string(),
                 generic_args: vec![],
                 span,
-            })),
+            }))),
             default_value: None,
             span,
         };
@@ -1063,6 +1703,7 @@ mod tests {
             is_external: false,
             external_name: None,
             is_class_method: false,
+            binding: MethodBinding::Static,
             span,
         });
         assert_eq!(proc_decl.span(), span);
@@ -1099,6 +1740,9 @@ mod tests {
             is_external: false,
             external_name: None,
             is_class_method: false,
+            is_compiletime: false,
+            is_pure: false,
+            binding: MethodBinding::Static,
             span,
         });
         assert_eq!(func_decl.span(), span);
@@ -1232,6 +1876,7 @@ mod tests {
                 span,
             })),
             body: Box::new(body),
+            unroll: None,
             span,
         });
         assert_eq!(for_stmt.span(), span);
@@ -1260,6 +1905,7 @@ mod tests {
                 span,
             })),
             body: Box::new(body),
+            unroll: None,
             span,
         });
         assert_eq!(for_stmt.span(), span);
@@ -1781,6 +2427,7 @@ mod tests {
         let program = Node::Program(Program {
             directives: vec![],
             name: "TestProgram".to_string(),
+            uses: None,
             block: Box::new(block),
             span,
         });
@@ -1796,11 +2443,11 @@ mod tests {
         let param = Param {
             names: vec!["a".to_string(), "b".to_string()],
             param_type: ParamType::Value,
-            type_expr: Box::new(Node::NamedType(NamedType {
+            type_expr: Some(Box::new(Node::NamedType(NamedType {
                 name: "integer".to_string(),
                 generic_args: vec![],
                 span,
-            })),
+            }))),
             default_value: None,
             span,
         };
@@ -1855,6 +2502,9 @@ mod tests {
             is_external: false,
             external_name: None,
             is_class_method: false,
+            is_compiletime: false,
+            is_pure: false,
+            binding: MethodBinding::Static,
             span,
         });
 
@@ -2000,4 +2650,22 @@ mod tests {
         assert_eq!(UnaryOp::Not, UnaryOp::Not);
         assert_ne!(UnaryOp::Plus, UnaryOp::Minus);
     }
+
+    #[test]
+    fn test_kind_and_children() {
+        let span = Span::new(0, 5, 1, 1);
+        let ident = |name: &str| Node::IdentExpr(IdentExpr { name: name.to_string(), span });
+        let sum = Node::BinaryExpr(BinaryExpr {
+            op: BinaryOp::Add,
+            left: Box::new(ident("A")),
+            right: Box::new(ident("B")),
+            span,
+        });
+        let assign = Node::AssignStmt(AssignStmt { target: Box::new(ident("C")), value: Box::new(sum), span });
+        assert_eq!(assign.kind(), "AssignStmt");
+        let children = assign.children();
+        assert_eq!(children.iter().map(|c| c.kind()).collect::<Vec<_>>(), ["IdentExpr", "BinaryExpr"]);
+        assert_eq!(children[1].children().len(), 2);
+        assert!(children[0].children().is_empty());
+    }
 }
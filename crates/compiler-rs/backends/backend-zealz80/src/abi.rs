@@ -0,0 +1,168 @@
+//! Call frames of the Pascal calling convention
+//!
+//! Arguments are pushed left to right, so the last one sits just above the
+//! return address, and the callee removes them before returning. Every
+//! argument takes a 2-byte slot:
+//! - values of 1 or 2 bytes are passed in the slot, bytes in its low half
+//! - `var` and untyped `const` parameters, and values of any other size,
+//!   are passed as the address of the variable (the callee copies a value
+//!   it wants to change)
+//!
+//! Results of 1 byte come back in L, of 2 bytes in HL. Larger results are
+//! written through a hidden buffer address pushed after the last argument,
+//! so it is always at `ix+4`.
+//! IX, IY and SP are preserved; AF, BC, DE and HL are not.
+//!
+//! Routines compiled from Pascal are emitted as `_Name` (see
+//! [`routine_symbol`]); `external` routines link against their name as
+//! declared, or the string given after `external`.
+//!
+//! See `platforms/ZealZ80/ABI.md` for the full description.
+
+/// Size of every argument slot
+pub const SLOT_SIZE: u16 = 2;
+/// Offset of the last argument from SP on entry (above the return address)
+pub const ENTRY_ARGUMENT_OFFSET: u16 = 2;
+/// Offset of the last argument from IX after the standard prologue (above
+/// the saved IX and the return address)
+pub const FRAME_ARGUMENT_OFFSET: u16 = 4;
+/// Name of the hidden result buffer slot
+pub const RESULT_BUFFER_SLOT: &str = "(result)";
+
+/// Assembler symbol of a routine compiled from Pascal
+pub fn routine_symbol(name: &str) -> String {
+    format!("_{}", name)
+}
+
+/// How an argument travels in its slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Passing {
+    /// The value itself
+    Value,
+    /// The address of the variable
+    Address,
+}
+
+/// A parameter as declared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamSpec {
+    pub name: String,
+    /// `var` or untyped `const`/`var` parameter
+    pub by_reference: bool,
+    /// Size of the parameter's type, if known
+    pub size: Option<u16>,
+}
+
+/// Where a parameter lives in the frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSlot {
+    pub name: String,
+    pub passing: Passing,
+    /// Offset from SP on entry to the routine
+    pub entry_offset: u16,
+    /// Offset from IX after the standard prologue
+    pub frame_offset: u16,
+}
+
+/// Where a function leaves its result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultLocation {
+    /// Procedure
+    None,
+    /// Byte-sized result in L
+    L,
+    /// Word-sized result in HL
+    HL,
+    /// Written through the hidden buffer slot
+    Buffer,
+}
+
+/// Layout of a routine's arguments and result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+    /// Slots in push order, the hidden result buffer last
+    pub slots: Vec<FrameSlot>,
+    pub result: ResultLocation,
+}
+
+impl CallFrame {
+    /// Lay out a routine with `params`; `result_size` is None for a
+    /// procedure and `Some(None)` for a function whose result size is unknown
+    pub fn new(params: &[ParamSpec], result_size: Option<Option<u16>>) -> Self {
+        let result = match result_size {
+            None => ResultLocation::None,
+            Some(Some(1)) => ResultLocation::L,
+            Some(Some(2)) => ResultLocation::HL,
+            Some(_) => ResultLocation::Buffer,
+        };
+        let hidden = (result == ResultLocation::Buffer).then(|| (RESULT_BUFFER_SLOT.to_string(), Passing::Address));
+        let passed: Vec<(String, Passing)> = params
+            .iter()
+            .map(|param| {
+                let passing = match param.size {
+                    Some(1 | 2) if !param.by_reference => Passing::Value,
+                    _ => Passing::Address,
+                };
+                (param.name.clone(), passing)
+            })
+            .chain(hidden)
+            .collect();
+        let count = passed.len() as u16;
+        let slots = passed
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, passing))| {
+                // Slots pushed later sit closer to the return address
+                let above = (count - 1 - index as u16) * SLOT_SIZE;
+                FrameSlot {
+                    name,
+                    passing,
+                    entry_offset: ENTRY_ARGUMENT_OFFSET + above,
+                    frame_offset: FRAME_ARGUMENT_OFFSET + above,
+                }
+            })
+            .collect();
+        Self { slots, result }
+    }
+
+    /// Bytes of arguments the callee removes
+    pub fn argument_bytes(&self) -> u16 {
+        self.slots.len() as u16 * SLOT_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, by_reference: bool, size: Option<u16>) -> ParamSpec {
+        ParamSpec { name: name.to_string(), by_reference, size }
+    }
+
+    #[test]
+    fn test_last_argument_is_nearest() {
+        let frame = CallFrame::new(
+            &[param("A", false, Some(2)), param("B", false, Some(1)), param("C", true, Some(2))],
+            None,
+        );
+        let offsets: Vec<(&str, u16, u16)> =
+            frame.slots.iter().map(|s| (s.name.as_str(), s.entry_offset, s.frame_offset)).collect();
+        assert_eq!(offsets, vec![("A", 6, 8), ("B", 4, 6), ("C", 2, 4)]);
+        assert_eq!(frame.slots[2].passing, Passing::Address);
+        assert_eq!(frame.argument_bytes(), 6);
+        assert_eq!(frame.result, ResultLocation::None);
+    }
+
+    #[test]
+    fn test_large_values_and_results() {
+        let frame = CallFrame::new(&[param("R", false, Some(6)), param("S", false, None)], Some(Some(4)));
+        assert_eq!(frame.result, ResultLocation::Buffer);
+        assert_eq!(frame.slots[2].name, RESULT_BUFFER_SLOT);
+        assert_eq!(frame.slots[2].frame_offset, FRAME_ARGUMENT_OFFSET);
+        assert_eq!(frame.slots[0].frame_offset, 8);
+        assert!(frame.slots.iter().all(|s| s.passing == Passing::Address));
+
+        assert_eq!(CallFrame::new(&[], Some(Some(1))).result, ResultLocation::L);
+        assert_eq!(CallFrame::new(&[], Some(Some(2))).result, ResultLocation::HL);
+    }
+}
@@ -0,0 +1,182 @@
+//! 16-bit arithmetic idioms
+//!
+//! Adding or subtracting a constant is the most common arithmetic (loop
+//! steps, `Inc`, pointer offsets). `inc hl` takes 6 T-states and one byte,
+//! against 21 and four for `ld de, n` / `add hl, de`, so steps of up to
+//! [`MAX_INC_STEPS`] are repeated increments or decrements. A larger
+//! subtraction adds the negated constant, which needs no `or a` to clear the
+//! carry before `sbc hl, de`.
+//!
+//! Multiplication and division call runtime routines, with the left operand
+//! in HL and the right in DE and the result in HL. By constants they are
+//! first strength-reduced to shifts and adds (see [`ir::reduce_strength`])
+//! when [`strength_costs`] makes that cheaper: shifting left is `add hl, hl`,
+//! shifting right `srl h` / `rr l` (`sra h` when signed), and a shift by 8 is
+//! a byte move.
+
+use ir::{ArithCosts, Opcode};
+
+use crate::{OptimizationGoal, Z80Instruction, Z80Register};
+
+/// Largest step done with repeated `inc hl` / `dec hl`
+pub const MAX_INC_STEPS: u16 = 3;
+
+pub const MUL_ROUTINE: &str = "__mul16";
+/// Division and remainder, signed and unsigned
+pub const DIV_ROUTINE: &str = "__div16";
+pub const DIVU_ROUTINE: &str = "__divu16";
+pub const MOD_ROUTINE: &str = "__mod16";
+pub const MODU_ROUTINE: &str = "__modu16";
+
+/// T-states of the multiply and divide routines, on average
+const MUL_CYCLES: u32 = 550;
+const DIV_CYCLES: u32 = 900;
+
+/// Costs of the 16-bit sequences strength reduction chooses between: the
+/// second operand of an add or subtract is loaded into DE, and a multiply
+/// or divide loads its constant into DE and calls its routine
+pub fn strength_costs(goal: OptimizationGoal) -> ArithCosts {
+    match goal {
+        // T-states
+        OptimizationGoal::Speed => ArithCosts {
+            add: 20 + 11,
+            subtract: 20 + 4 + 15,
+            shift_left: 11,
+            shift_right: 8 + 8,
+            byte_shift: 4 + 7,
+            sign_mask: 11 + 15,
+            multiply: 10 + 17 + MUL_CYCLES,
+            divide: 10 + 17 + DIV_CYCLES,
+        },
+        // Bytes
+        OptimizationGoal::Size => ArithCosts {
+            add: 4 + 1,
+            subtract: 4 + 1 + 2,
+            shift_left: 1,
+            shift_right: 2 + 2,
+            byte_shift: 1 + 2,
+            sign_mask: 1 + 2,
+            multiply: 3 + 3,
+            divide: 3 + 3,
+        },
+    }
+}
+
+/// Runtime routine implementing the 16-bit `opcode`, if it has one
+pub fn routine(opcode: &Opcode) -> Option<&'static str> {
+    Some(match opcode {
+        Opcode::Mul => MUL_ROUTINE,
+        Opcode::Div => DIV_ROUTINE,
+        Opcode::DivU => DIVU_ROUTINE,
+        Opcode::Mod => MOD_ROUTINE,
+        Opcode::ModU => MODU_ROUTINE,
+        _ => return None,
+    })
+}
+
+/// HL = HL shifted left by `count` bits
+pub fn shift_left(count: u32) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut count = count.min(16);
+    let mut code = Vec::new();
+    if count >= 8 {
+        code.extend([LoadRegister { dst: H, src: L }, LoadImmediate { reg: L, value: 0 }]);
+        count -= 8;
+    }
+    code.extend(std::iter::repeat_n(Add { dst: HL, src: HL }, count as usize));
+    code
+}
+
+/// HL = HL shifted right by `count` bits, copying the sign bit in when
+/// `arithmetic`
+pub fn shift_right(count: u32, arithmetic: bool) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut count = count.min(16);
+    if arithmetic && count >= 15 {
+        // The sign goes into the carry, and `sbc hl, hl` spreads it
+        return vec![Add { dst: HL, src: HL }, Subtract { dst: HL, src: HL }];
+    }
+    let mut code = Vec::new();
+    if !arithmetic && count >= 8 {
+        code.extend([LoadRegister { dst: L, src: H }, LoadImmediate { reg: H, value: 0 }]);
+        count -= 8;
+    }
+    for _ in 0..count {
+        code.extend([ShiftRight { reg: H, arithmetic }, RotateRight { reg: L }]);
+    }
+    code
+}
+
+/// HL = HL + `delta`, modulo 65536. DE is clobbered when the step is too
+/// large for increments; the flags are only set then.
+pub fn add_constant(delta: i32) -> Vec<Z80Instruction> {
+    let delta = delta as u16;
+    let (steps, step) = if delta <= MAX_INC_STEPS {
+        (delta, Z80Instruction::Increment { reg: Z80Register::HL })
+    } else if delta.wrapping_neg() <= MAX_INC_STEPS {
+        (delta.wrapping_neg(), Z80Instruction::Decrement { reg: Z80Register::HL })
+    } else {
+        return vec![
+            Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: delta },
+            Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::DE },
+        ];
+    };
+    vec![step; steps as usize]
+}
+
+/// HL = HL - `reg`: `sbc hl, reg` with the carry cleared first
+pub fn subtract_register(reg: Z80Register) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::Or { reg: Z80Register::A },
+        Z80Instruction::Subtract { dst: Z80Register::HL, src: reg },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(code: &[Z80Instruction]) -> Vec<String> {
+        code.iter().map(|inst| inst.to_string()).collect()
+    }
+
+    #[test]
+    fn test_constant_steps_select_inc_or_add() {
+        assert!(add_constant(0).is_empty());
+        assert_eq!(text(&add_constant(1)), ["    inc hl"]);
+        assert_eq!(text(&add_constant(3)), ["    inc hl"; 3]);
+        assert_eq!(text(&add_constant(-2)), ["    dec hl"; 2]);
+        assert_eq!(text(&add_constant(4)), ["    ld de, 4", "    add hl, de"]);
+        // Subtracting 10 adds 65526
+        assert_eq!(text(&add_constant(-10)), ["    ld de, 65526", "    add hl, de"]);
+        assert_eq!(text(&subtract_register(Z80Register::BC)), ["    or a", "    sbc hl, bc"]);
+    }
+
+    #[test]
+    fn test_shifts_use_byte_moves_and_the_sign_mask() {
+        assert_eq!(text(&shift_left(2)), ["    add hl, hl"; 2]);
+        assert_eq!(text(&shift_left(9)), ["    ld h, l", "    ld l, 0", "    add hl, hl"]);
+        assert_eq!(text(&shift_right(1, false)), ["    srl h", "    rr l"]);
+        assert_eq!(text(&shift_right(8, false)), ["    ld l, h", "    ld h, 0"]);
+        assert_eq!(text(&shift_right(2, true)), ["    sra h", "    rr l", "    sra h", "    rr l"]);
+        assert_eq!(text(&shift_right(15, true)), ["    add hl, hl", "    sbc hl, hl"]);
+    }
+
+    #[test]
+    fn test_strength_costs_match_the_generated_shifts() {
+        let codegen = crate::CodeGenerator::new();
+        let cycles = |code: Vec<Z80Instruction>| code.iter().map(|inst| codegen.instruction_cycles(inst)).sum::<u32>();
+        let bytes = |code: Vec<Z80Instruction>| code.iter().map(|inst| codegen.instruction_size(inst) as u32).sum::<u32>();
+        let speed = strength_costs(OptimizationGoal::Speed);
+        let size = strength_costs(OptimizationGoal::Size);
+        for count in 1..16 {
+            assert_eq!(speed.shift(&Opcode::Shl, count), cycles(shift_left(count)), "shl {}", count);
+            assert_eq!(speed.shift(&Opcode::Shr, count), cycles(shift_right(count, false)), "shr {}", count);
+            assert_eq!(speed.shift(&Opcode::Sar, count), cycles(shift_right(count, true)), "sar {}", count);
+            assert_eq!(size.shift(&Opcode::Shl, count), bytes(shift_left(count)), "shl {}", count);
+            assert_eq!(size.shift(&Opcode::Sar, count), bytes(shift_right(count, true)), "sar {}", count);
+        }
+    }
+}
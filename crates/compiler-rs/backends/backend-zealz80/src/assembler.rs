@@ -0,0 +1,1032 @@
+//! Built-in Z80 assembler
+//!
+//! Generated code is assembled straight from [`Z80Instruction`]s; the lines
+//! of `asm` blocks (`Raw`) are parsed first, so both go through the same
+//! encoder. The result is the machine code, the offset of each label, and
+//! a fixup for each reference the linker resolves:
+//!
+//! - **Absolute** references (`jp`, `call`, `ld hl, label`, `ld a, (label)`,
+//!   `dw label`) always get a fixup, since the code moves with the section
+//!   it is linked into.
+//! - **Relative** references (`jr`, `djnz`) to a label of the same code are
+//!   resolved here; those to other labels get a fixup.
+//!
+//! Accepted syntax is that of the listings `asm` prints: one instruction
+//! per line, an optional `label:` before it, `;` comments, and numbers in
+//! decimal, `$FF`, `0xFF`, `0FFh`, `%1010` or `'A'`. Besides the documented
+//! Z80 instructions, the 16-bit loads the code generator emits between a
+//! register pair and another pair or a frame slot are expanded to two 8-bit
+//! loads:
+//!
+//! ```text
+//! ld hl, (ix-4)   ; ld l, (ix-4) / ld h, (ix-3)
+//! ld de, hl       ; ld d, h / ld e, l
+//! ```
+//!
+//! as are the frame setup `ld ix, sp` (`ld ix, 0` / `add ix, sp`) and
+//! `sub sp, hl`, which negates HL through A and adds SP to it.
+//!
+//! Directives are `db`/`defb`/`defm` (numbers and strings), `dw`/`defw`
+//! and `ds`/`defs` (a count and an optional fill byte).
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// How a fixup patches its field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixupKind {
+    /// Little-endian address of the symbol
+    Absolute16,
+    /// Signed displacement to the symbol from the end of the byte
+    Relative8,
+}
+
+/// A field the linker fills in with the address of a symbol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixup {
+    /// Offset of the field in the code
+    pub offset: u16,
+    pub symbol: String,
+    pub addend: i16,
+    pub kind: FixupKind,
+}
+
+/// Machine code assembled from a listing
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AssembledCode {
+    pub bytes: Vec<u8>,
+    /// Offset of each label, in definition order
+    pub labels: Vec<(String, u16)>,
+    pub fixups: Vec<Fixup>,
+}
+
+impl AssembledCode {
+    /// Offset of the label `name`
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(label, _)| label == name).map(|(_, offset)| *offset)
+    }
+}
+
+/// An instruction that cannot be assembled, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub instruction: String,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot assemble '{}': {}", self.instruction, self.message)
+    }
+}
+
+/// Assemble `instructions` into code starting at offset 0
+pub fn assemble(instructions: &[Z80Instruction]) -> Result<AssembledCode, AssembleError> {
+    let mut code = AssembledCode::default();
+    let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut relative = Vec::new();
+    for inst in instructions {
+        let error = |message: String| AssembleError { instruction: inst.to_string().trim().to_string(), message };
+        let lines = match inst {
+            Z80Instruction::Raw { text } => parse_line(text).map_err(error)?,
+            Z80Instruction::Label { name } => vec![Line::Label(name.clone())],
+            Z80Instruction::Comment { .. } => vec![],
+            _ => vec![Line::Statement(structured(inst))],
+        };
+        for line in lines {
+            let offset = code.bytes.len() as u16;
+            match line {
+                Line::Label(name) => {
+                    if labels.insert(name.clone(), offset).is_some() {
+                        return Err(error(format!("label '{}' is defined twice", name)));
+                    }
+                    code.labels.push((name, offset));
+                }
+                Line::Statement(statement) => {
+                    let encoded = encode(&statement).map_err(error)?;
+                    for (at, expr, kind) in encoded.fixups {
+                        let fixup = Fixup {
+                            offset: offset + at as u16,
+                            symbol: expr.symbol.unwrap_or_default(),
+                            addend: expr.value as i16,
+                            kind,
+                        };
+                        match kind {
+                            FixupKind::Relative8 => relative.push((fixup, inst)),
+                            FixupKind::Absolute16 => code.fixups.push(fixup),
+                        }
+                    }
+                    code.bytes.extend(encoded.bytes);
+                    if code.bytes.len() > u16::MAX as usize {
+                        return Err(error("the code does not fit in 64K".to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    // Relative references within the code need no linking
+    for (fixup, inst) in relative {
+        match labels.get(&fixup.symbol) {
+            Some(&target) => {
+                let displacement = target as i32 + fixup.addend as i32 - (fixup.offset as i32 + 1);
+                if !(-128..=127).contains(&displacement) {
+                    return Err(AssembleError {
+                        instruction: inst.to_string().trim().to_string(),
+                        message: format!("'{}' is out of range of a relative jump ({} bytes)", fixup.symbol, displacement),
+                    });
+                }
+                code.bytes[fixup.offset as usize] = displacement as u8;
+            }
+            None => code.fixups.push(fixup),
+        }
+    }
+    code.fixups.sort_by_key(|fixup| fixup.offset);
+    Ok(code)
+}
+
+/// Size in bytes of the code of `inst`, if it can be assembled on its own
+pub fn encoded_size(inst: &Z80Instruction) -> Option<usize> {
+    let lines = match inst {
+        Z80Instruction::Raw { text } => parse_line(text).ok()?,
+        Z80Instruction::Label { .. } | Z80Instruction::Comment { .. } => return Some(0),
+        _ => vec![Line::Statement(structured(inst))],
+    };
+    lines
+        .iter()
+        .map(|line| match line {
+            Line::Label(_) => Some(0),
+            Line::Statement(statement) => encode(statement).ok().map(|encoded| encoded.bytes.len()),
+        })
+        .sum()
+}
+
+/// Registers as the assembler names them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reg {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    A,
+    I,
+    R,
+    BC,
+    DE,
+    HL,
+    SP,
+    AF,
+    AltAF,
+    IX,
+    IY,
+}
+
+impl Reg {
+    fn from_name(name: &str) -> Option<Reg> {
+        Some(match name {
+            "b" => Reg::B,
+            "c" => Reg::C,
+            "d" => Reg::D,
+            "e" => Reg::E,
+            "h" => Reg::H,
+            "l" => Reg::L,
+            "a" => Reg::A,
+            "i" => Reg::I,
+            "r" => Reg::R,
+            "bc" => Reg::BC,
+            "de" => Reg::DE,
+            "hl" => Reg::HL,
+            "sp" => Reg::SP,
+            "af" => Reg::AF,
+            "af'" => Reg::AltAF,
+            "ix" => Reg::IX,
+            "iy" => Reg::IY,
+            _ => return None,
+        })
+    }
+
+    /// Code of an 8-bit register in opcodes, where 6 is `(hl)`
+    fn code(self) -> Option<u8> {
+        Some(match self {
+            Reg::B => 0,
+            Reg::C => 1,
+            Reg::D => 2,
+            Reg::E => 3,
+            Reg::H => 4,
+            Reg::L => 5,
+            Reg::A => 7,
+            _ => return None,
+        })
+    }
+
+    /// Prefix and code of a register pair in opcodes, where IX and IY take
+    /// the place of HL
+    fn pair(self) -> Option<(Option<u8>, u8)> {
+        Some(match self {
+            Reg::BC => (None, 0),
+            Reg::DE => (None, 1),
+            Reg::HL => (None, 2),
+            Reg::SP => (None, 3),
+            Reg::IX => (Some(0xDD), 2),
+            Reg::IY => (Some(0xFD), 2),
+            _ => return None,
+        })
+    }
+
+    /// High and low halves of BC, DE and HL
+    fn halves(self) -> Option<(Reg, Reg)> {
+        Some(match self {
+            Reg::BC => (Reg::B, Reg::C),
+            Reg::DE => (Reg::D, Reg::E),
+            Reg::HL => (Reg::H, Reg::L),
+            _ => return None,
+        })
+    }
+
+    fn from_register(reg: Z80Register) -> Reg {
+        match reg {
+            Z80Register::A => Reg::A,
+            Z80Register::B => Reg::B,
+            Z80Register::C => Reg::C,
+            Z80Register::D => Reg::D,
+            Z80Register::E => Reg::E,
+            Z80Register::H => Reg::H,
+            Z80Register::L => Reg::L,
+            Z80Register::AF => Reg::AF,
+            Z80Register::BC => Reg::BC,
+            Z80Register::DE => Reg::DE,
+            Z80Register::HL => Reg::HL,
+            Z80Register::IX => Reg::IX,
+            Z80Register::IY => Reg::IY,
+            Z80Register::SP => Reg::SP,
+        }
+    }
+}
+
+/// A number, or a symbol plus a number
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expr {
+    symbol: Option<String>,
+    value: i32,
+}
+
+impl Expr {
+    fn number(value: i32) -> Self {
+        Expr { symbol: None, value }
+    }
+
+    fn symbol(name: &str) -> Self {
+        Expr { symbol: Some(name.to_string()), value: 0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Register(Reg),
+    /// `(bc)`, `(de)`, `(hl)`, `(sp)` or `(c)`
+    Indirect(Reg),
+    /// `(ix+d)` or `(iy+d)`: the prefix and displacement
+    Indexed(u8, i32),
+    Immediate(Expr),
+    /// `(nn)`
+    Memory(Expr),
+    /// A condition other than `c`, which parses as the register
+    Condition(u8),
+    /// Bytes of a quoted string, for `db`
+    Text(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Statement {
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+impl Statement {
+    fn new(mnemonic: &str, operands: Vec<Operand>) -> Self {
+        Statement { mnemonic: mnemonic.to_string(), operands }
+    }
+}
+
+enum Line {
+    Label(String),
+    Statement(Statement),
+}
+
+fn register(reg: Z80Register) -> Operand {
+    Operand::Register(Reg::from_register(reg))
+}
+
+fn memory(addr: &MemoryAddress) -> Operand {
+    match addr {
+        MemoryAddress::Direct(address) => Operand::Memory(Expr::number(*address as i32)),
+        MemoryAddress::FrameRelative(offset) => Operand::Indexed(0xDD, *offset as i32),
+        MemoryAddress::RegisterIndirect(reg) => Operand::Indirect(Reg::from_register(*reg)),
+        MemoryAddress::Symbol(label) => Operand::Memory(symbol_expr(label)),
+    }
+}
+
+/// A symbol, possibly with an offset added: `Score+2`
+fn symbol_expr(text: &str) -> Expr {
+    parse_expr(text).unwrap_or_else(|_| Expr::symbol(text))
+}
+
+fn immediate(value: i32) -> Operand {
+    Operand::Immediate(Expr::number(value))
+}
+
+fn label(name: &str) -> Operand {
+    Operand::Immediate(symbol_expr(name))
+}
+
+fn condition(condition: Condition) -> Operand {
+    match condition {
+        Condition::NonZero => Operand::Condition(0),
+        Condition::Zero => Operand::Condition(1),
+        Condition::NoCarry => Operand::Condition(2),
+        Condition::Carry => Operand::Register(Reg::C),
+        Condition::Positive => Operand::Condition(6),
+        Condition::Sign => Operand::Condition(7),
+    }
+}
+
+/// The statement of an instruction the code generator built
+fn structured(inst: &Z80Instruction) -> Statement {
+    use Z80Instruction::*;
+    match inst {
+        LoadImmediate { reg, value } => Statement::new("ld", vec![register(*reg), immediate(*value as i32)]),
+        LoadAddress { reg, label: name } => Statement::new("ld", vec![register(*reg), label(name)]),
+        LoadRegister { dst, src } => Statement::new("ld", vec![register(*dst), register(*src)]),
+        LoadMemory { reg, addr } => Statement::new("ld", vec![register(*reg), memory(addr)]),
+        StoreMemory { addr, reg } => Statement::new("ld", vec![memory(addr), register(*reg)]),
+        Push { reg } => Statement::new("push", vec![register(*reg)]),
+        Pop { reg } => Statement::new("pop", vec![register(*reg)]),
+        Add { dst, src } => Statement::new("add", vec![register(*dst), register(*src)]),
+        AddWithCarry { dst, src } => Statement::new("adc", vec![register(*dst), register(*src)]),
+        Subtract { dst: Z80Register::HL, src } => Statement::new("sbc", vec![register(Z80Register::HL), register(*src)]),
+        Subtract { dst: Z80Register::SP, src } => Statement::new("sub", vec![register(Z80Register::SP), register(*src)]),
+        Subtract { src, .. } => Statement::new("sub", vec![register(*src)]),
+        Compare { value: Some(value), .. } => Statement::new("cp", vec![immediate(*value as i32)]),
+        Compare { reg, value: None } => Statement::new("cp", vec![register(*reg)]),
+        Or { reg } => Statement::new("or", vec![register(*reg)]),
+        Xor { value } => Statement::new("xor", vec![immediate(*value as i32)]),
+        BitTest { bit, reg } => Statement::new("bit", vec![immediate(*bit as i32), register(*reg)]),
+        ShiftRight { reg, arithmetic } => Statement::new(if *arithmetic { "sra" } else { "srl" }, vec![register(*reg)]),
+        RotateRight { reg } => Statement::new("rr", vec![register(*reg)]),
+        SetCarry => Statement::new("scf", vec![]),
+        ComplementCarry => Statement::new("ccf", vec![]),
+        Jump { label: name, near } => Statement::new(if *near { "jr" } else { "jp" }, vec![label(name)]),
+        JumpConditional { condition: cc, label: name, near } => {
+            Statement::new(if *near { "jr" } else { "jp" }, vec![condition(*cc), label(name)])
+        }
+        JumpIndirect => Statement::new("jp", vec![Operand::Indirect(Reg::HL)]),
+        Call { label: name } => Statement::new("call", vec![label(name)]),
+        Return => Statement::new("ret", vec![]),
+        Increment { reg } => Statement::new("inc", vec![register(*reg)]),
+        Decrement { reg } => Statement::new("dec", vec![register(*reg)]),
+        IncrementMemory { addr } => Statement::new("inc", vec![memory(addr)]),
+        DecrementMemory { addr } => Statement::new("dec", vec![memory(addr)]),
+        SetBit { bit, addr } => Statement::new("set", vec![immediate(*bit as i32), memory(addr)]),
+        ExchangeDeHl => Statement::new("ex", vec![Operand::Register(Reg::DE), Operand::Register(Reg::HL)]),
+        Ldi => Statement::new("ldi", vec![]),
+        Ldir => Statement::new("ldir", vec![]),
+        DisableInterrupts => Statement::new("di", vec![]),
+        EnableInterrupts => Statement::new("ei", vec![]),
+        SetInterruptMode { mode } => Statement::new("im", vec![immediate(*mode as i32)]),
+        LoadInterruptVector => Statement::new("ld", vec![Operand::Register(Reg::I), Operand::Register(Reg::A)]),
+        ReturnFromInterrupt => Statement::new("reti", vec![]),
+        PortIn { reg } => Statement::new("in", vec![register(*reg), Operand::Indirect(Reg::C)]),
+        PortOut { reg } => Statement::new("out", vec![Operand::Indirect(Reg::C), register(*reg)]),
+        Restart { vector } => Statement::new("rst", vec![immediate(*vector as i32)]),
+        DefineByte { value } => Statement::new("db", vec![immediate(*value as i32)]),
+        DefineWord { label: name } => Statement::new("dw", vec![label(name)]),
+        // Parsed or skipped by `assemble`
+        Label { .. } | Raw { .. } | Comment { .. } => Statement::new("nop", vec![]),
+    }
+}
+
+/// Parse a line of assembly: an optional label, then an optional statement
+fn parse_line(text: &str) -> Result<Vec<Line>, String> {
+    let mut text = strip_comment(text).trim();
+    let mut lines = Vec::new();
+    if let Some((name, rest)) = text.split_once(':')
+        && is_identifier(name.trim())
+        && !name.trim().is_empty()
+    {
+        lines.push(Line::Label(name.trim().to_string()));
+        text = rest.trim();
+    }
+    if text.is_empty() {
+        return Ok(lines);
+    }
+    let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    let operands = split_operands(rest)
+        .into_iter()
+        .map(|operand| parse_operand(operand, &mnemonic))
+        .collect::<Result<Vec<_>, _>>()?;
+    lines.push(Line::Statement(Statement { mnemonic, operands }));
+    Ok(lines)
+}
+
+/// `text` up to a `;` outside quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (';', None) => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Operands separated by commas outside quotes and parentheses
+fn split_operands(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    if text.is_empty() {
+        return vec![];
+    }
+    let mut operands = Vec::new();
+    let (mut quote, mut depth, mut start) = (None, 0, 0);
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"', None) => quote = Some(c),
+            // `af'` is a register, not the start of a character
+            ('\'', None) if !text[start..i].trim().eq_ignore_ascii_case("af") => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(text[start..].trim());
+    operands
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+fn parse_operand(text: &str, mnemonic: &str) -> Result<Operand, String> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(reg) = Reg::from_name(&lower) {
+        return Ok(Operand::Register(reg));
+    }
+    if matches!(mnemonic, "jp" | "jr" | "call" | "ret") {
+        let code = ["nz", "z", "nc", "c", "po", "pe", "p", "m"].iter().position(|name| *name == lower);
+        if let Some(code) = code {
+            return Ok(Operand::Condition(code as u8));
+        }
+    }
+    let quoted = text.len() > 2 && (text.starts_with('"') || text.starts_with('\'')) && text.ends_with(&text[..1]);
+    if quoted && parse_number(text).is_none() {
+        return Ok(Operand::Text(text[1..text.len() - 1].bytes().collect()));
+    }
+    if let Some(inner) = lower.strip_prefix('(').and_then(|inner| inner.strip_suffix(')')) {
+        let inner = inner.trim();
+        if let Some(reg @ (Reg::BC | Reg::DE | Reg::HL | Reg::SP | Reg::C)) = Reg::from_name(inner) {
+            return Ok(Operand::Indirect(reg));
+        }
+        for (name, prefix) in [("ix", 0xDD), ("iy", 0xFD)] {
+            if let Some(rest) = inner.strip_prefix(name) {
+                let rest = rest.trim();
+                if rest.is_empty() {
+                    return Ok(Operand::Indexed(prefix, 0));
+                }
+                if rest.starts_with(['+', '-']) {
+                    let displacement = parse_expr(&format!("0{}", rest))?;
+                    if displacement.symbol.is_some() {
+                        return Err("an index displacement must be a number".to_string());
+                    }
+                    return Ok(Operand::Indexed(prefix, displacement.value));
+                }
+            }
+        }
+        return Ok(Operand::Memory(parse_expr(&text.trim()[1..text.trim().len() - 1])?));
+    }
+    Ok(Operand::Immediate(parse_expr(text)?))
+}
+
+/// Terms added and subtracted, of which one may be a symbol added
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut expr = Expr::number(0);
+    let mut sign = 1;
+    let mut term = String::new();
+    let mut quote = false;
+    let add_term = |term: &str, sign: i32, expr: &mut Expr| -> Result<(), String> {
+        let term = term.trim();
+        if term.is_empty() {
+            return Err(format!("expected a number or symbol in '{}'", text.trim()));
+        }
+        match parse_number(term) {
+            Some(value) => expr.value += sign * value,
+            None if is_identifier(term) && sign > 0 && expr.symbol.is_none() => expr.symbol = Some(term.to_string()),
+            None => return Err(format!("invalid operand '{}'", text.trim())),
+        }
+        Ok(())
+    };
+    for c in text.chars() {
+        match c {
+            '\'' => {
+                quote = !quote;
+                term.push(c);
+            }
+            '+' | '-' if !quote && !term.trim().is_empty() => {
+                add_term(&term, sign, &mut expr)?;
+                sign = if c == '-' { -1 } else { 1 };
+                term.clear();
+            }
+            '-' if !quote => sign = -sign,
+            '+' if !quote => {}
+            _ => term.push(c),
+        }
+    }
+    add_term(&term, sign, &mut expr)?;
+    Ok(expr)
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix('$').or_else(|| lower.strip_prefix("0x")) {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_suffix('h').filter(|hex| hex.starts_with(|c: char| c.is_ascii_digit())) {
+        (hex, 16)
+    } else if let Some(binary) = lower.strip_prefix('%').or_else(|| lower.strip_prefix("0b")) {
+        (binary, 2)
+    } else if let Some(c) = text.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+        let mut chars = c.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii() => Some(c as i32),
+            _ => None,
+        };
+    } else {
+        (lower.as_str(), 10)
+    };
+    i32::from_str_radix(digits, radix).ok()
+}
+
+/// Bytes of a statement and the fields the symbols it refers to fill
+#[derive(Default)]
+struct Encoded {
+    bytes: Vec<u8>,
+    fixups: Vec<(usize, Expr, FixupKind)>,
+}
+
+impl Encoded {
+    fn new(bytes: &[u8]) -> Self {
+        Encoded { bytes: bytes.to_vec(), fixups: vec![] }
+    }
+
+    fn byte(mut self, expr: &Expr) -> Result<Self, String> {
+        self.bytes.push(byte(expr)?);
+        Ok(self)
+    }
+
+    fn displacement(mut self, displacement: i32) -> Result<Self, String> {
+        if !(-128..=127).contains(&displacement) {
+            return Err(format!("index displacement {} is out of range", displacement));
+        }
+        self.bytes.push(displacement as u8);
+        Ok(self)
+    }
+
+    fn word(mut self, expr: &Expr) -> Result<Self, String> {
+        if expr.symbol.is_some() {
+            self.fixups.push((self.bytes.len(), expr.clone(), FixupKind::Absolute16));
+            self.bytes.extend([0, 0]);
+        } else if (-32768..=65535).contains(&expr.value) {
+            self.bytes.extend((expr.value as u16).to_le_bytes());
+        } else {
+            return Err(format!("{} does not fit in 16 bits", expr.value));
+        }
+        Ok(self)
+    }
+
+    fn relative(mut self, expr: &Expr) -> Result<Self, String> {
+        if expr.symbol.is_none() {
+            return Err("a relative jump needs a label".to_string());
+        }
+        self.fixups.push((self.bytes.len(), expr.clone(), FixupKind::Relative8));
+        self.bytes.push(0);
+        Ok(self)
+    }
+
+    fn then(mut self, other: Encoded) -> Self {
+        let base = self.bytes.len();
+        self.fixups.extend(other.fixups.into_iter().map(|(at, expr, kind)| (base + at, expr, kind)));
+        self.bytes.extend(other.bytes);
+        self
+    }
+}
+
+fn byte(expr: &Expr) -> Result<u8, String> {
+    match expr.symbol {
+        Some(_) => Err("an 8-bit operand must be a number".to_string()),
+        None if (-128..=255).contains(&expr.value) => Ok(expr.value as u8),
+        None => Err(format!("{} does not fit in 8 bits", expr.value)),
+    }
+}
+
+/// Opcode prefix of an index register, if any
+fn prefixed(prefix: Option<u8>, bytes: &[u8]) -> Encoded {
+    let mut encoded = Encoded::new(&prefix.into_iter().collect::<Vec<_>>());
+    encoded.bytes.extend_from_slice(bytes);
+    encoded
+}
+
+/// Encode one statement
+fn encode(statement: &Statement) -> Result<Encoded, String> {
+    use Operand::*;
+    let no_such = || format!("no such instruction '{}' with these operands", statement.mnemonic);
+    let ops = statement.operands.as_slice();
+    let r8 = |op: &Operand| match op {
+        Register(reg) => reg.code(),
+        _ => None,
+    };
+    let rp = |op: &Operand| match op {
+        Register(reg) => reg.pair(),
+        _ => None,
+    };
+    let cond = |op: &Operand| match op {
+        Condition(code) => Some(*code),
+        Register(Reg::C) => Some(3),
+        _ => None,
+    };
+    let mnemonic = statement.mnemonic.as_str();
+    if let Some(bytes) = implied(mnemonic) {
+        return if ops.is_empty() { Ok(Encoded::new(bytes)) } else { Err(no_such()) };
+    }
+    let alu = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"].iter().position(|name| *name == mnemonic);
+    let rotate = ["rlc", "rrc", "rl", "rr", "sla", "sra", "sll", "srl"].iter().position(|name| *name == mnemonic);
+    let bits = ["bit", "res", "set"].iter().position(|name| *name == mnemonic);
+    let encoded = match (mnemonic, ops) {
+        ("ld", [dst, src]) => load(dst, src).ok_or_else(no_such)??,
+        ("push" | "pop", [Register(Reg::AF)]) => Encoded::new(&[if mnemonic == "push" { 0xF5 } else { 0xF1 }]),
+        ("push" | "pop", [op]) => match rp(op) {
+            Some((prefix, code)) if *op != Register(Reg::SP) => {
+                prefixed(prefix, &[if mnemonic == "push" { 0xC5 } else { 0xC1 } | code << 4])
+            }
+            _ => return Err(no_such()),
+        },
+        // 16-bit arithmetic
+        ("add", [Register(dst @ (Reg::HL | Reg::IX | Reg::IY)), src]) => {
+            let (prefix, _) = dst.pair().unwrap();
+            match src {
+                Register(reg) if *reg == *dst || matches!(reg, Reg::BC | Reg::DE | Reg::SP) => {
+                    let (_, code) = reg.pair().unwrap();
+                    prefixed(prefix, &[0x09 | code << 4])
+                }
+                _ => return Err(no_such()),
+            }
+        }
+        // SP - HL is SP + -HL
+        ("sub", [Register(Reg::SP), Register(Reg::HL)]) => Encoded::new(&[
+            0xAF, // xor a
+            0x95, // sub l
+            0x6F, // ld l, a
+            0x9F, // sbc a, a
+            0x94, // sub h
+            0x67, // ld h, a
+            0x39, // add hl, sp
+            0xF9, // ld sp, hl
+        ]),
+        ("adc" | "sbc", [Register(Reg::HL), op]) => match rp(op) {
+            Some((None, code)) => Encoded::new(&[0xED, if mnemonic == "adc" { 0x4A } else { 0x42 } | code << 4]),
+            _ => return Err(no_such()),
+        },
+        (_, [Register(Reg::A), op]) | (_, [op]) if alu.is_some() => {
+            let op_code = alu.unwrap() as u8;
+            match op {
+                Register(_) if r8(op).is_some() => Encoded::new(&[0x80 | op_code << 3 | r8(op).unwrap()]),
+                Indirect(Reg::HL) => Encoded::new(&[0x86 | op_code << 3]),
+                Indexed(prefix, d) => Encoded::new(&[*prefix, 0x86 | op_code << 3]).displacement(*d)?,
+                Immediate(expr) => Encoded::new(&[0xC6 | op_code << 3]).byte(expr)?,
+                _ => return Err(no_such()),
+            }
+        }
+        ("inc" | "dec", [op]) => {
+            let dec = (mnemonic == "dec") as u8;
+            match op {
+                Register(_) if r8(op).is_some() => Encoded::new(&[0x04 | dec | r8(op).unwrap() << 3]),
+                Register(_) if rp(op).is_some() => {
+                    let (prefix, code) = rp(op).unwrap();
+                    prefixed(prefix, &[0x03 | dec << 3 | code << 4])
+                }
+                Indirect(Reg::HL) => Encoded::new(&[0x34 | dec]),
+                Indexed(prefix, d) => Encoded::new(&[*prefix, 0x34 | dec]).displacement(*d)?,
+                _ => return Err(no_such()),
+            }
+        }
+        ("jp", [Immediate(target)]) => Encoded::new(&[0xC3]).word(target)?,
+        ("jp", [cc, Immediate(target)]) if cond(cc).is_some() => Encoded::new(&[0xC2 | cond(cc).unwrap() << 3]).word(target)?,
+        ("jp", [Indirect(Reg::HL)]) => Encoded::new(&[0xE9]),
+        ("jp", [Indexed(prefix, 0)]) => Encoded::new(&[*prefix, 0xE9]),
+        ("jr", [Immediate(target)]) => Encoded::new(&[0x18]).relative(target)?,
+        ("jr", [cc, Immediate(target)]) if cond(cc).is_some_and(|code| code < 4) => {
+            Encoded::new(&[0x20 | cond(cc).unwrap() << 3]).relative(target)?
+        }
+        ("djnz", [Immediate(target)]) => Encoded::new(&[0x10]).relative(target)?,
+        ("call", [Immediate(target)]) => Encoded::new(&[0xCD]).word(target)?,
+        ("call", [cc, Immediate(target)]) if cond(cc).is_some() => {
+            Encoded::new(&[0xC4 | cond(cc).unwrap() << 3]).word(target)?
+        }
+        ("ret", []) => Encoded::new(&[0xC9]),
+        ("ret", [cc]) if cond(cc).is_some() => Encoded::new(&[0xC0 | cond(cc).unwrap() << 3]),
+        ("rst", [Immediate(expr)]) => match byte(expr)? {
+            vector if vector % 8 == 0 && vector <= 0x38 => Encoded::new(&[0xC7 | vector]),
+            vector => return Err(format!("rst {} is not a restart vector", vector)),
+        },
+        ("im", [Immediate(expr)]) => match byte(expr)? {
+            0 => Encoded::new(&[0xED, 0x46]),
+            1 => Encoded::new(&[0xED, 0x56]),
+            2 => Encoded::new(&[0xED, 0x5E]),
+            mode => return Err(format!("there is no interrupt mode {}", mode)),
+        },
+        ("ex", [Register(Reg::DE), Register(Reg::HL)]) => Encoded::new(&[0xEB]),
+        ("ex", [Register(Reg::AF), Register(Reg::AltAF)]) => Encoded::new(&[0x08]),
+        ("ex", [Indirect(Reg::SP), op]) => match rp(op) {
+            Some((prefix, 2)) => prefixed(prefix, &[0xE3]),
+            _ => return Err(no_such()),
+        },
+        ("in", [Register(Reg::A), Memory(port)]) => Encoded::new(&[0xDB]).byte(port)?,
+        ("in", [op, Indirect(Reg::C)]) if r8(op).is_some() => Encoded::new(&[0xED, 0x40 | r8(op).unwrap() << 3]),
+        ("out", [Memory(port), Register(Reg::A)]) => Encoded::new(&[0xD3]).byte(port)?,
+        ("out", [Indirect(Reg::C), op]) if r8(op).is_some() => Encoded::new(&[0xED, 0x41 | r8(op).unwrap() << 3]),
+        (_, [op]) if rotate.is_some() => cb((rotate.unwrap() as u8) << 3, op).ok_or_else(no_such)??,
+        (_, [Immediate(bit), op]) if bits.is_some() => {
+            let bit = byte(bit)?;
+            if bit > 7 {
+                return Err(format!("there is no bit {}", bit));
+            }
+            cb((bits.unwrap() as u8 + 1) << 6 | bit << 3, op).ok_or_else(no_such)??
+        }
+        ("db" | "defb" | "defm", _) if !ops.is_empty() => {
+            let mut encoded = Encoded::default();
+            for op in ops {
+                encoded = match op {
+                    Text(bytes) => encoded.then(Encoded::new(bytes)),
+                    Immediate(expr) => encoded.byte(expr)?,
+                    _ => return Err(no_such()),
+                };
+            }
+            encoded
+        }
+        ("dw" | "defw", _) if !ops.is_empty() => {
+            let mut encoded = Encoded::default();
+            for op in ops {
+                let Immediate(expr) = op else {
+                    return Err(no_such());
+                };
+                encoded = encoded.word(expr)?;
+            }
+            encoded
+        }
+        ("ds" | "defs", [Immediate(count)]) | ("ds" | "defs", [Immediate(count), _]) => {
+            let fill = match ops.get(1) {
+                Some(Immediate(fill)) => byte(fill)?,
+                Some(_) => return Err(no_such()),
+                None => 0,
+            };
+            match count.symbol {
+                None if (0..=0xFFFF).contains(&count.value) => Encoded::new(&vec![fill; count.value as usize]),
+                _ => return Err("a space size must be a number".to_string()),
+            }
+        }
+        _ if mnemonic.chars().all(|c| c.is_ascii_alphabetic()) && mnemonic_known(mnemonic) => return Err(no_such()),
+        _ => return Err(format!("unknown instruction '{}'", mnemonic)),
+    };
+    Ok(encoded)
+}
+
+/// Bytes of the instructions without operands
+fn implied(mnemonic: &str) -> Option<&'static [u8]> {
+    Some(match mnemonic {
+        "nop" => &[0x00],
+        "halt" => &[0x76],
+        "di" => &[0xF3],
+        "ei" => &[0xFB],
+        "exx" => &[0xD9],
+        "rlca" => &[0x07],
+        "rrca" => &[0x0F],
+        "rla" => &[0x17],
+        "rra" => &[0x1F],
+        "daa" => &[0x27],
+        "cpl" => &[0x2F],
+        "scf" => &[0x37],
+        "ccf" => &[0x3F],
+        "neg" => &[0xED, 0x44],
+        "retn" => &[0xED, 0x45],
+        "reti" => &[0xED, 0x4D],
+        "rrd" => &[0xED, 0x67],
+        "rld" => &[0xED, 0x6F],
+        "ldi" => &[0xED, 0xA0],
+        "cpi" => &[0xED, 0xA1],
+        "ini" => &[0xED, 0xA2],
+        "outi" => &[0xED, 0xA3],
+        "ldd" => &[0xED, 0xA8],
+        "cpd" => &[0xED, 0xA9],
+        "ind" => &[0xED, 0xAA],
+        "outd" => &[0xED, 0xAB],
+        "ldir" => &[0xED, 0xB0],
+        "cpir" => &[0xED, 0xB1],
+        "inir" => &[0xED, 0xB2],
+        "otir" => &[0xED, 0xB3],
+        "lddr" => &[0xED, 0xB8],
+        "cpdr" => &[0xED, 0xB9],
+        "indr" => &[0xED, 0xBA],
+        "otdr" => &[0xED, 0xBB],
+        _ => return None,
+    })
+}
+
+/// Whether `mnemonic` names an instruction taking operands
+fn mnemonic_known(mnemonic: &str) -> bool {
+    const KNOWN: [&str; 38] = [
+        "ld", "push", "pop", "add", "adc", "sub", "sbc", "and", "xor", "or", "cp", "inc", "dec", "jp", "jr", "djnz",
+        "call", "ret", "rst", "im", "ex", "in", "out", "rlc", "rrc", "rl", "rr", "sla", "sra", "sll", "srl", "bit",
+        "res", "set", "db", "dw", "ds", "defb",
+    ];
+    KNOWN.contains(&mnemonic) || matches!(mnemonic, "defw" | "defs" | "defm")
+}
+
+/// A CB-prefixed operation `op` on a register or memory byte, if it can
+/// address `operand`
+fn cb(op: u8, operand: &Operand) -> Option<Result<Encoded, String>> {
+    Some(match operand {
+        Operand::Register(reg) => Ok(Encoded::new(&[0xCB, op | reg.code()?])),
+        Operand::Indirect(Reg::HL) => Ok(Encoded::new(&[0xCB, op | 6])),
+        Operand::Indexed(prefix, d) => Encoded::new(&[*prefix, 0xCB])
+            .displacement(*d)
+            .map(|encoded| encoded.then(Encoded::new(&[op | 6]))),
+        _ => return None,
+    })
+}
+
+/// Encode `ld dst, src`, if the operands make an instruction
+fn load(dst: &Operand, src: &Operand) -> Option<Result<Encoded, String>> {
+    use Operand::*;
+    Some(match (dst, src) {
+        (Register(Reg::A), Register(Reg::I)) => Ok(Encoded::new(&[0xED, 0x57])),
+        (Register(Reg::A), Register(Reg::R)) => Ok(Encoded::new(&[0xED, 0x5F])),
+        (Register(Reg::I), Register(Reg::A)) => Ok(Encoded::new(&[0xED, 0x47])),
+        (Register(Reg::R), Register(Reg::A)) => Ok(Encoded::new(&[0xED, 0x4F])),
+        (Register(Reg::A), Indirect(Reg::BC)) => Ok(Encoded::new(&[0x0A])),
+        (Register(Reg::A), Indirect(Reg::DE)) => Ok(Encoded::new(&[0x1A])),
+        (Indirect(Reg::BC), Register(Reg::A)) => Ok(Encoded::new(&[0x02])),
+        (Indirect(Reg::DE), Register(Reg::A)) => Ok(Encoded::new(&[0x12])),
+        (Register(Reg::A), Memory(address)) => Encoded::new(&[0x3A]).word(address),
+        (Memory(address), Register(Reg::A)) => Encoded::new(&[0x32]).word(address),
+        (Register(dst @ (Reg::IX | Reg::IY)), Register(Reg::SP)) => {
+            let prefix = dst.pair()?.0;
+            Ok(prefixed(prefix, &[0x21, 0, 0]).then(prefixed(prefix, &[0x39])))
+        }
+        (Register(Reg::SP), Register(src)) if src.pair().is_some_and(|(_, code)| code == 2) => {
+            Ok(prefixed(src.pair()?.0, &[0xF9]))
+        }
+        (Register(dst), Register(src)) => match (dst.code(), src.code()) {
+            (Some(dst), Some(src)) => Ok(Encoded::new(&[0x40 | dst << 3 | src])),
+            // A pair copied a byte at a time
+            _ => {
+                let ((dst_high, dst_low), (src_high, src_low)) = (dst.halves()?, src.halves()?);
+                load(&Register(dst_high), &Register(src_high))?
+                    .map(|high| high.then(load(&Register(dst_low), &Register(src_low)).unwrap().unwrap()))
+            }
+        },
+        (Register(reg), Immediate(value)) => match (reg.code(), reg.pair()) {
+            (Some(code), _) => Encoded::new(&[0x06 | code << 3]).byte(value),
+            (_, Some((prefix, code))) => prefixed(prefix, &[0x01 | code << 4]).word(value),
+            _ => return None,
+        },
+        (Register(reg), Indirect(Reg::HL)) => Ok(Encoded::new(&[0x46 | reg.code()? << 3])),
+        (Indirect(Reg::HL), Register(reg)) => Ok(Encoded::new(&[0x70 | reg.code()?])),
+        (Indirect(Reg::HL), Immediate(value)) => Encoded::new(&[0x36]).byte(value),
+        (Register(reg), Indexed(prefix, d)) => match reg.code() {
+            Some(code) => Encoded::new(&[*prefix, 0x46 | code << 3]).displacement(*d),
+            // A pair loaded a byte at a time, low byte first
+            None => {
+                let (high, low) = reg.halves()?;
+                load(&Register(low), &Indexed(*prefix, *d))?
+                    .and_then(|first| Ok(first.then(load(&Register(high), &Indexed(*prefix, d + 1)).unwrap()?)))
+            }
+        },
+        (Indexed(prefix, d), Register(reg)) => match reg.code() {
+            Some(code) => Encoded::new(&[*prefix, 0x70 | code]).displacement(*d),
+            None => {
+                let (high, low) = reg.halves()?;
+                load(&Indexed(*prefix, *d), &Register(low))?
+                    .and_then(|first| Ok(first.then(load(&Indexed(*prefix, d + 1), &Register(high)).unwrap()?)))
+            }
+        },
+        (Indexed(prefix, d), Immediate(value)) => {
+            Encoded::new(&[*prefix, 0x36]).displacement(*d).and_then(|encoded| encoded.byte(value))
+        }
+        (Register(reg), Memory(address)) => match reg.pair()? {
+            (prefix, 2) => prefixed(prefix, &[0x2A]).word(address),
+            (_, code) => Encoded::new(&[0xED, 0x4B | code << 4]).word(address),
+        },
+        (Memory(address), Register(reg)) => match reg.pair()? {
+            (prefix, 2) => prefixed(prefix, &[0x22]).word(address),
+            (_, code) => Encoded::new(&[0xED, 0x43 | code << 4]).word(address),
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(instructions: &[Z80Instruction]) -> Vec<u8> {
+        assemble(instructions).unwrap().bytes
+    }
+
+    fn raw(text: &str) -> Z80Instruction {
+        Z80Instruction::Raw { text: text.to_string() }
+    }
+
+    #[test]
+    fn test_assembles_generated_instructions() {
+        use Z80Instruction::*;
+        let code = assemble(&[
+            Label { name: "_main".to_string() },
+            LoadImmediate { reg: Z80Register::HL, value: 5 },
+            LoadMemory { reg: Z80Register::DE, addr: MemoryAddress::FrameRelative(-4) },
+            StoreMemory { addr: MemoryAddress::Symbol("Score".to_string()), reg: Z80Register::HL },
+            Add { dst: Z80Register::HL, src: Z80Register::DE },
+            Label { name: "loop".to_string() },
+            JumpConditional { condition: Condition::NonZero, label: "loop".to_string(), near: true },
+            Call { label: "Print".to_string() },
+            Return,
+        ])
+        .unwrap();
+        assert_eq!(code.bytes, [
+            0x21, 5, 0, // ld hl, 5
+            0xDD, 0x5E, 0xFC, 0xDD, 0x56, 0xFD, // ld de, (ix-4)
+            0x22, 0, 0, // ld (Score), hl
+            0x19, // add hl, de
+            0x20, 0xFE, // jr nz, loop
+            0xCD, 0, 0, // call Print
+            0xC9, // ret
+        ]);
+        assert_eq!(code.label("loop"), Some(13));
+        let fixups: Vec<_> = code.fixups.iter().map(|f| (f.offset, f.symbol.as_str(), f.kind)).collect();
+        assert_eq!(fixups, [(10, "Score", FixupKind::Absolute16), (16, "Print", FixupKind::Absolute16)]);
+    }
+
+    #[test]
+    fn test_symbol_addresses_may_have_an_offset() {
+        use Z80Instruction::*;
+        // The high word of the global LongInt Total
+        let code = assemble(&[
+            LoadMemory { reg: Z80Register::DE, addr: MemoryAddress::Symbol("Total+2".to_string()) },
+            LoadAddress { reg: Z80Register::HL, label: "Total+2".to_string() },
+        ])
+        .unwrap();
+        assert_eq!(code.bytes, [0xED, 0x5B, 0, 0, 0x21, 0, 0]);
+        let fixups: Vec<_> = code.fixups.iter().map(|f| (f.offset, f.symbol.as_str(), f.addend)).collect();
+        assert_eq!(fixups, [(2, "Total", 2), (5, "Total", 2)]);
+    }
+
+    #[test]
+    fn test_assembles_asm_block_lines() {
+        let code = assemble(&[
+            raw("start: ld a, ($4000)  ; read"),
+            raw("  ld (ix+2), 'A'"),
+            raw("  djnz start"),
+            raw("  res 7, (iy-1)"),
+            raw("  out ($FE), a"),
+            raw("  jr c, Done"),
+            raw("table: db 1, \"Hi\", 0FFh"),
+            raw("  dw table+2, %101"),
+        ])
+        .unwrap();
+        assert_eq!(code.bytes, [
+            0x3A, 0x00, 0x40, 0xDD, 0x36, 2, 0x41, 0x10, 0xF7, 0xFD, 0xCB, 0xFF, 0xBE, 0xD3, 0xFE, 0x38, 0, 1, b'H',
+            b'i', 0xFF, 0, 0, 5, 0,
+        ]);
+        let fixups: Vec<_> = code.fixups.iter().map(|f| (f.offset, f.symbol.as_str(), f.addend, f.kind)).collect();
+        assert_eq!(fixups, [(16, "Done", 0, FixupKind::Relative8), (21, "table", 2, FixupKind::Absolute16)]);
+
+        assert_eq!(bytes(&[raw("ex af, af'"), raw("ld sp, ix"), raw("sbc hl, bc")]), [0x08, 0xDD, 0xF9, 0xED, 0x42]);
+        assert_eq!(bytes(&[raw("ld ix, sp"), raw("sub sp, hl")]), [
+            0xDD, 0x21, 0, 0, 0xDD, 0x39, 0xAF, 0x95, 0x6F, 0x9F, 0x94, 0x67, 0x39, 0xF9,
+        ]);
+        let error = assemble(&[raw("ld (bc), hl")]).unwrap_err();
+        assert_eq!(error.to_string(), "Cannot assemble 'ld (bc), hl': no such instruction 'ld' with these operands");
+        assert!(assemble(&[raw("frob a")]).unwrap_err().message.contains("unknown instruction 'frob'"));
+        assert!(assemble(&[raw("x:"), raw("x:")]).is_err());
+    }
+}
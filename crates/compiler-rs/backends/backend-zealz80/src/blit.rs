@@ -0,0 +1,325 @@
+//! Compiler-generated blit routines
+//!
+//! Sprite copies are the hottest loops in most games, so instead of a single
+//! generic library routine the compiler emits one routine per sprite size.
+//! The shape of each routine depends on the optimization goal:
+//!
+//! - **Loop**: one `ldir` per row; smallest code, ~21 T-states per byte
+//! - **Unrolled**: one `ldi` per byte; ~16 T-states per byte, 2 bytes of code per pixel
+//! - **Push**: a "compiled sprite" whose pixels are immediate operands written
+//!   with `push`; ~5.5 T-states per byte, only legal when the pixel data is
+//!   known at compile time and the row width is even
+//!
+//! All routines use the Pascal convention (arguments pushed left to right,
+//! callee cleans). Copy routines take `(Src, Dest: word)`, compiled sprites
+//! take `(Dest: word)`.
+
+use crate::{Condition, OptimizationGoal, Z80Instruction, Z80Register};
+
+/// Largest sprite (in bytes) that is unrolled when optimizing for speed
+pub const MAX_UNROLLED_BYTES: u32 = 256;
+
+/// How a blit routine copies its pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlitStrategy {
+    /// `ldir` per row
+    Loop,
+    /// `ldi` per byte
+    Unrolled,
+    /// Pixel data as immediates written with `push`
+    Push,
+}
+
+/// Sprite geometry in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlitSpec {
+    pub width: u16,  // Bytes per sprite row
+    pub height: u16, // Rows
+    pub stride: u16, // Bytes per destination row
+}
+
+impl BlitSpec {
+    /// Parse a routine name of the form `Blit<W>x<H>` (case-insensitive)
+    pub fn from_routine_name(name: &str, stride: u16) -> Option<Self> {
+        let lower = name.to_ascii_lowercase();
+        let (width, height) = lower.strip_prefix("blit")?.split_once('x')?;
+        let width: u16 = width.parse().ok()?;
+        let height: u16 = height.parse().ok()?;
+        if width == 0 || height == 0 || width > stride || height > 255 {
+            return None;
+        }
+        Some(Self { width, height, stride })
+    }
+
+    /// Total sprite size in bytes
+    pub fn byte_count(&self) -> u32 {
+        self.width as u32 * self.height as u32
+    }
+}
+
+/// Choose how to blit a sprite
+///
+/// `data_known` is true when the pixels are available at compile time
+/// (embedded resources), which is required for the PUSH strategy.
+pub fn select_strategy(goal: OptimizationGoal, spec: &BlitSpec, data_known: bool) -> BlitStrategy {
+    match goal {
+        OptimizationGoal::Size => BlitStrategy::Loop,
+        OptimizationGoal::Speed if data_known && spec.width.is_multiple_of(2) => BlitStrategy::Push,
+        OptimizationGoal::Speed if spec.byte_count() <= MAX_UNROLLED_BYTES => BlitStrategy::Unrolled,
+        OptimizationGoal::Speed => BlitStrategy::Loop,
+    }
+}
+
+/// Generate a `(Src, Dest: word)` copy routine
+///
+/// `strategy` must be `Loop` or `Unrolled`; PUSH blits need the pixel data
+/// and are produced by [`generate_compiled_sprite`].
+pub fn generate_blit(name: &str, spec: &BlitSpec, strategy: BlitStrategy) -> Vec<Z80Instruction> {
+    let mut code = vec![
+        Z80Instruction::Label { name: name.to_string() },
+        Z80Instruction::Comment {
+            text: format!("{}x{} blit, stride {} ({:?})", spec.width, spec.height, spec.stride, strategy),
+        },
+        // Return address, Dest, Src
+        Z80Instruction::Pop { reg: Z80Register::BC },
+        Z80Instruction::Pop { reg: Z80Register::DE },
+        Z80Instruction::Pop { reg: Z80Register::HL },
+        Z80Instruction::Push { reg: Z80Register::BC },
+    ];
+    copy_rect(&mut code, name, spec, strategy, false);
+    code.push(Z80Instruction::Return);
+    code
+}
+
+/// Generate a `(Dest: word)` routine copying a tiled image from `data_label`
+///
+/// Image resources are stored as consecutive `tile`x`tile` tiles in
+/// row-major tile order, so each tile is copied as its own rectangle.
+pub fn generate_tiled_blit(
+    name: &str,
+    data_label: &str,
+    spec: &BlitSpec,
+    tile: u16,
+    strategy: BlitStrategy,
+) -> Vec<Z80Instruction> {
+    let tiles_across = spec.width / tile;
+    let tiles_down = spec.height / tile;
+    let tile_spec = BlitSpec { width: tile, height: tile, stride: spec.stride };
+    // After a tile DE is `tile` rows below its origin
+    let next_tile = tile.wrapping_sub(tile.wrapping_mul(spec.stride));
+    let next_row = 0u16.wrapping_sub((tiles_across - 1).wrapping_mul(tile));
+
+    let mut code = vec![
+        Z80Instruction::Label { name: name.to_string() },
+        Z80Instruction::Comment {
+            text: format!("{}x{} tiled blit, stride {} ({:?})", spec.width, spec.height, spec.stride, strategy),
+        },
+        // Return address, Dest
+        Z80Instruction::Pop { reg: Z80Register::BC },
+        Z80Instruction::Pop { reg: Z80Register::DE },
+        Z80Instruction::Push { reg: Z80Register::BC },
+        Z80Instruction::LoadAddress { reg: Z80Register::HL, label: data_label.to_string() },
+    ];
+    for ty in 0..tiles_down {
+        for tx in 0..tiles_across {
+            let last = tx + 1 == tiles_across && ty + 1 == tiles_down;
+            copy_rect(&mut code, &format!("{}_t{}", name, ty * tiles_across + tx), &tile_spec, strategy, !last);
+            if last {
+                break;
+            }
+            code.extend(advance_de(if tx + 1 < tiles_across { next_tile } else { next_row }));
+        }
+    }
+    code.push(Z80Instruction::Return);
+    code
+}
+
+/// Copy a `spec.width`x`spec.height` rectangle from HL (contiguous) to DE
+///
+/// With `advance_last` DE is left `spec.height` rows below its origin;
+/// otherwise the adjustment after the final row may be skipped.
+fn copy_rect(code: &mut Vec<Z80Instruction>, label_prefix: &str, spec: &BlitSpec, strategy: BlitStrategy, advance_last: bool) {
+    match strategy {
+        BlitStrategy::Unrolled => {
+            for row in 0..spec.height {
+                code.extend(std::iter::repeat_n(Z80Instruction::Ldi, spec.width as usize));
+                if advance_last || row + 1 < spec.height {
+                    code.extend(advance_de(spec.stride - spec.width));
+                }
+            }
+        }
+        BlitStrategy::Loop | BlitStrategy::Push => {
+            let loop_label = format!("{}_row", label_prefix);
+            code.push(Z80Instruction::LoadImmediate { reg: Z80Register::A, value: spec.height });
+            code.push(Z80Instruction::Label { name: loop_label.clone() });
+            code.push(Z80Instruction::Push { reg: Z80Register::DE });
+            code.push(Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: spec.width });
+            code.push(Z80Instruction::Ldir);
+            code.push(Z80Instruction::Pop { reg: Z80Register::DE });
+            code.extend(advance_de(spec.stride));
+            code.push(Z80Instruction::Decrement { reg: Z80Register::A });
+            code.push(Z80Instruction::JumpConditional {
+                condition: Condition::NonZero,
+                label: loop_label,
+                near: true,
+            });
+        }
+    }
+}
+
+/// Generate a `(Dest: word)` compiled sprite that writes `data` with `push`
+///
+/// Interrupts are disabled while SP points into the framebuffer, since an
+/// interrupt would otherwise push its return address over the sprite.
+/// Returns `None` if the sprite cannot be drawn this way.
+pub fn generate_compiled_sprite(name: &str, spec: &BlitSpec, data: &[u8]) -> Option<Vec<Z80Instruction>> {
+    if !spec.width.is_multiple_of(2) || data.len() != spec.byte_count() as usize {
+        return None;
+    }
+
+    let mut code = vec![
+        Z80Instruction::Label { name: name.to_string() },
+        Z80Instruction::Comment {
+            text: format!("{}x{} compiled sprite, stride {}", spec.width, spec.height, spec.stride),
+        },
+        // Return address, Dest
+        Z80Instruction::Pop { reg: Z80Register::BC },
+        Z80Instruction::Pop { reg: Z80Register::HL },
+        Z80Instruction::Push { reg: Z80Register::BC },
+        Z80Instruction::DisableInterrupts,
+        // Save SP in IX (IX itself is callee-saved)
+        Z80Instruction::Push { reg: Z80Register::IX },
+        Z80Instruction::LoadImmediate { reg: Z80Register::IX, value: 0 },
+        Z80Instruction::Add { dst: Z80Register::IX, src: Z80Register::SP },
+        // HL tracks the end of the current destination row
+        Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: spec.width },
+        Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::DE },
+        Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: spec.stride },
+    ];
+
+    let mut bc_value = None; // Runs of equal pixel pairs reuse BC
+    for (row_index, row) in data.chunks(spec.width as usize).enumerate() {
+        if row_index > 0 {
+            code.push(Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::DE });
+        }
+        code.push(Z80Instruction::LoadRegister { dst: Z80Register::SP, src: Z80Register::HL });
+        // `push` stores the high byte first, so walk the row backwards in pairs
+        for pair in row.rchunks(2) {
+            let value = pair[0] as u16 | (pair[1] as u16) << 8;
+            if bc_value != Some(value) {
+                code.push(Z80Instruction::LoadImmediate { reg: Z80Register::BC, value });
+                bc_value = Some(value);
+            }
+            code.push(Z80Instruction::Push { reg: Z80Register::BC });
+        }
+    }
+
+    code.extend([
+        Z80Instruction::LoadRegister { dst: Z80Register::SP, src: Z80Register::IX },
+        Z80Instruction::Pop { reg: Z80Register::IX },
+        Z80Instruction::EnableInterrupts,
+        Z80Instruction::Return,
+    ]);
+    Some(code)
+}
+
+/// `DE += offset`, clobbering BC
+fn advance_de(offset: u16) -> [Z80Instruction; 4] {
+    [
+        Z80Instruction::ExchangeDeHl,
+        Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: offset },
+        Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::BC },
+        Z80Instruction::ExchangeDeHl,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(width: u16, height: u16) -> BlitSpec {
+        BlitSpec { width, height, stride: 320 }
+    }
+
+    #[test]
+    fn test_from_routine_name() {
+        assert_eq!(BlitSpec::from_routine_name("Blit16x8", 320), Some(spec(16, 8)));
+        assert_eq!(BlitSpec::from_routine_name("BLIT8X8", 32), Some(BlitSpec { width: 8, height: 8, stride: 32 }));
+        assert_eq!(BlitSpec::from_routine_name("Blit64x8", 32), None);
+        assert_eq!(BlitSpec::from_routine_name("BlitSprite", 320), None);
+    }
+
+    #[test]
+    fn test_select_strategy() {
+        assert_eq!(select_strategy(OptimizationGoal::Size, &spec(16, 16), true), BlitStrategy::Loop);
+        assert_eq!(select_strategy(OptimizationGoal::Speed, &spec(16, 16), true), BlitStrategy::Push);
+        assert_eq!(select_strategy(OptimizationGoal::Speed, &spec(15, 16), true), BlitStrategy::Unrolled);
+        assert_eq!(select_strategy(OptimizationGoal::Speed, &spec(16, 16), false), BlitStrategy::Unrolled);
+        assert_eq!(select_strategy(OptimizationGoal::Speed, &spec(32, 32), false), BlitStrategy::Loop);
+    }
+
+    #[test]
+    fn test_unrolled_is_larger_than_loop() {
+        let unrolled = generate_blit("Blit8x8", &spec(8, 8), BlitStrategy::Unrolled);
+        let looped = generate_blit("Blit8x8", &spec(8, 8), BlitStrategy::Loop);
+        assert_eq!(unrolled.iter().filter(|i| **i == Z80Instruction::Ldi).count(), 64);
+        assert!(looped.contains(&Z80Instruction::Ldir));
+
+        let codegen = crate::CodeGenerator::new();
+        let size = |code: &[Z80Instruction]| code.iter().map(|i| codegen.instruction_size(i)).sum::<usize>();
+        assert!(size(&unrolled) > size(&looped));
+        assert_eq!(unrolled.last(), Some(&Z80Instruction::Return));
+    }
+
+    #[test]
+    fn test_compiled_sprite_push_order() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let code = generate_compiled_sprite("Ship_Draw", &spec(4, 2), &data).unwrap();
+        let words: Vec<u16> = code
+            .iter()
+            .filter_map(|i| match i {
+                Z80Instruction::LoadImmediate { reg: Z80Register::BC, value } => Some(*value),
+                _ => None,
+            })
+            .collect();
+        // Rows are written from their end backwards
+        assert_eq!(words, vec![0x0403, 0x0201, 0x0807, 0x0605]);
+        assert!(code.contains(&Z80Instruction::DisableInterrupts));
+        assert!(code.contains(&Z80Instruction::EnableInterrupts));
+    }
+
+    #[test]
+    fn test_tiled_blit() {
+        let code = generate_tiled_blit("Map_Draw", "Map", &spec(32, 32), 16, BlitStrategy::Loop);
+        assert!(code.contains(&Z80Instruction::LoadAddress { reg: Z80Register::HL, label: "Map".to_string() }));
+        assert_eq!(code.iter().filter(|i| **i == Z80Instruction::Ldir).count(), 4);
+        // Next tile: 16 - 16 * 320; next tile row: -16
+        let offsets: Vec<u16> = code
+            .windows(2)
+            .filter_map(|w| match w {
+                [Z80Instruction::ExchangeDeHl, Z80Instruction::LoadImmediate { reg: Z80Register::BC, value }] => Some(*value),
+                _ => None,
+            })
+            .filter(|&v| v != 320)
+            .collect();
+        assert_eq!(offsets, vec![16u16.wrapping_sub(5120), 0u16.wrapping_sub(16), 16u16.wrapping_sub(5120)]);
+    }
+
+    #[test]
+    fn test_compiled_sprite_requires_even_width() {
+        assert!(generate_compiled_sprite("Odd", &spec(3, 1), &[1, 2, 3]).is_none());
+        assert!(generate_compiled_sprite("Short", &spec(2, 2), &[1, 2]).is_none());
+    }
+
+    #[test]
+    fn test_compiled_sprite_reuses_bc() {
+        let code = generate_compiled_sprite("Solid", &spec(4, 2), &[7; 8]).unwrap();
+        let loads = code
+            .iter()
+            .filter(|i| matches!(i, Z80Instruction::LoadImmediate { reg: Z80Register::BC, .. }))
+            .count();
+        let pushes = code.iter().filter(|i| **i == Z80Instruction::Push { reg: Z80Register::BC }).count();
+        assert_eq!(loads, 1);
+        assert_eq!(pushes, 5); // Return address + 4 pixel pairs
+    }
+}
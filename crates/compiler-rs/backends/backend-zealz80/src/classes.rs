@@ -0,0 +1,107 @@
+//! Method calls and virtual dispatch
+//!
+//! `Self` is pushed before the arguments, so the method sees it as its
+//! first parameter, and the method removes them all like any routine. A
+//! static method is called by its symbol. A virtual call finds `Self` on
+//! the stack again, follows the VMT pointer in its first word to the
+//! method's slot and calls the entry through [`CALL_HL_ROUTINE`], since the
+//! Z80 has no indirect call:
+//!
+//! ```text
+//!     SP+2n   Self
+//!     ...
+//!     SP+0    last of the n arguments
+//! ```
+//!
+//! A VMT, named by [`types::vmt_symbol`], is a header word leading to the
+//! class's interfaces (see [`crate::interfaces`]) followed by a table of
+//! `dw` routine addresses, one per slot.
+
+use types::ClassLayout;
+
+use crate::abi::{self, SLOT_SIZE};
+use crate::{MemoryAddress, Z80Instruction, Z80Register};
+
+/// Call the routine whose address is in HL
+pub const CALL_HL_ROUTINE: &str = "__call_hl";
+
+/// Bytes of the VMT header, before the entry of slot 0
+pub const VMT_HEADER_SIZE: u16 = 2;
+
+/// Replace HL with the word it points to. DE is clobbered.
+pub(crate) fn load_word_at_hl() -> [Z80Instruction; 4] {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    [
+        LoadMemory { reg: E, addr: MemoryAddress::RegisterIndirect(HL) },
+        Increment { reg: HL },
+        LoadMemory { reg: D, addr: MemoryAddress::RegisterIndirect(HL) },
+        ExchangeDeHl,
+    ]
+}
+
+/// Call VMT slot `slot` of the object pushed below `arg_count` arguments.
+/// HL and DE are clobbered.
+pub fn virtual_call(slot: usize, arg_count: usize) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut code = vec![
+        LoadImmediate { reg: HL, value: arg_count as u16 * SLOT_SIZE },
+        Add { dst: HL, src: SP },
+    ];
+    // Self, then its VMT
+    code.extend(load_word_at_hl());
+    code.extend(load_word_at_hl());
+    code.push(LoadImmediate { reg: DE, value: VMT_HEADER_SIZE + slot as u16 * SLOT_SIZE });
+    code.push(Add { dst: HL, src: DE });
+    code.extend(load_word_at_hl());
+    code.push(Call { label: CALL_HL_ROUTINE.to_string() });
+    code
+}
+
+/// The VMT of `layout` as a labelled table: the header, then one routine
+/// address per slot
+pub fn vmt_table(layout: &ClassLayout) -> Vec<Z80Instruction> {
+    let header = if layout.interfaces.is_empty() { "0".to_string() } else { types::interface_table_symbol(&layout.name) };
+    let mut table = vec![
+        Z80Instruction::Label { name: types::vmt_symbol(&layout.name) },
+        Z80Instruction::DefineWord { label: header },
+    ];
+    table.extend(layout.vmt.iter().map(|entry| Z80Instruction::DefineWord { label: abi::routine_symbol(entry) }));
+    table
+}
+
+/// Generate the indirect call routine, with its public name
+pub fn generate_class_routines() -> Vec<(String, Vec<Z80Instruction>)> {
+    let call_hl = vec![Z80Instruction::Label { name: CALL_HL_ROUTINE.to_string() }, Z80Instruction::JumpIndirect];
+    vec![(CALL_HL_ROUTINE.to_string(), call_hl)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vmt_table_lists_slot_routines() {
+        let mut layout = ClassLayout::new("TShape", None, vec![]);
+        layout.vmt = vec!["TShape__Draw".to_string(), "TShape__Area".to_string()];
+        let text: Vec<String> = vmt_table(&layout).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["__vmt_TShape:", "    dw 0", "    dw _TShape__Draw", "    dw _TShape__Area"]);
+    }
+
+    #[test]
+    fn test_virtual_call_reads_self_vmt_and_slot() {
+        let text: Vec<String> = virtual_call(2, 1).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "    ld hl, 2", "    add hl, sp",
+                "    ld e, (hl)", "    inc hl", "    ld d, (hl)", "    ex de, hl",
+                "    ld e, (hl)", "    inc hl", "    ld d, (hl)", "    ex de, hl",
+                "    ld de, 6", "    add hl, de",
+                "    ld e, (hl)", "    inc hl", "    ld d, (hl)", "    ex de, hl",
+                "    call __call_hl",
+            ]
+        );
+    }
+}
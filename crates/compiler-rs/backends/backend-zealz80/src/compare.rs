@@ -0,0 +1,174 @@
+//! 16-bit comparison sequences
+//!
+//! Every sequence compares HL with DE and leaves the flags the way `cp` does
+//! for unsigned operands: Z when equal, C when HL < DE. Conditional jumps can
+//! then use the same condition codes whatever the signedness of the operands.
+//!
+//! - **Unsigned**: `or a` / `sbc hl, de`
+//! - **Signed**: flipping both sign bits maps -32768..32767 onto 0..65535
+//!   in order, after which the unsigned compare is correct
+//! - **Mixed**: a negative Integer is below every Word; otherwise both values
+//!   are in 0..32767 and the unsigned compare is correct
+//!
+//! Two cheaper forms cover the common tests:
+//! - **Equality**: operands of the same signedness are equal exactly when
+//!   their bits are, so only `or a` / `sbc hl, de` is needed
+//! - **Zero**: `ld a, h` / `or l` sets Z when HL is 0 and clears C, which is
+//!   the unsigned compare of HL with 0 without loading DE
+
+use types::ComparisonKind;
+
+use crate::{Condition, Z80Instruction, Z80Register};
+
+/// Compare HL with DE. `label` prefixes the local labels of mixed compares.
+/// HL, DE and A are clobbered.
+pub fn compare16(kind: ComparisonKind, label: &str) -> Vec<Z80Instruction> {
+    match kind {
+        ComparisonKind::Unsigned => unsigned(),
+        ComparisonKind::Signed => {
+            let mut code = Vec::new();
+            for reg in [Z80Register::H, Z80Register::D] {
+                code.push(Z80Instruction::LoadRegister { dst: Z80Register::A, src: reg });
+                code.push(Z80Instruction::Xor { value: 0x80 });
+                code.push(Z80Instruction::LoadRegister { dst: reg, src: Z80Register::A });
+            }
+            code.extend(unsigned());
+            code
+        }
+        // Negative HL: HL < DE. `bit` left Z clear; set C.
+        ComparisonKind::SignedUnsigned => mixed(Z80Register::H, vec![Z80Instruction::SetCarry], label),
+        // Negative DE: HL > DE. `bit` left Z clear; clear C.
+        ComparisonKind::UnsignedSigned => mixed(
+            Z80Register::D,
+            vec![Z80Instruction::SetCarry, Z80Instruction::ComplementCarry],
+            label,
+        ),
+    }
+}
+
+/// Compare HL with DE when only Z is tested: the unsigned compare, unless
+/// the operands differ in signedness
+pub fn equal16(kind: ComparisonKind, label: &str) -> Vec<Z80Instruction> {
+    match kind {
+        ComparisonKind::Unsigned | ComparisonKind::Signed => unsigned(),
+        _ => compare16(kind, label),
+    }
+}
+
+/// Compare HL with 0 as unsigned: Z when HL is 0, C clear. A is clobbered.
+pub fn zero_test() -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadRegister { dst: Z80Register::A, src: Z80Register::H },
+        Z80Instruction::Or { reg: Z80Register::L },
+    ]
+}
+
+fn unsigned() -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::Or { reg: Z80Register::A }, // Clear carry
+        Z80Instruction::Subtract { dst: Z80Register::HL, src: Z80Register::DE },
+    ]
+}
+
+/// Test the sign of the signed operand (high byte `sign`), falling back to
+/// the unsigned compare when it is not negative
+fn mixed(sign: Z80Register, negative: Vec<Z80Instruction>, label: &str) -> Vec<Z80Instruction> {
+    let unsigned_label = format!("{}_unsigned", label);
+    let done_label = format!("{}_done", label);
+    let mut code = vec![
+        Z80Instruction::BitTest { bit: 7, reg: sign },
+        Z80Instruction::JumpConditional { condition: Condition::Zero, label: unsigned_label.clone(), near: true },
+    ];
+    code.extend(negative);
+    code.push(Z80Instruction::Jump { label: done_label.clone(), near: true });
+    code.push(Z80Instruction::Label { name: unsigned_label });
+    code.extend(unsigned());
+    code.push(Z80Instruction::Label { name: done_label });
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a sequence on (HL, DE) and return the resulting (Z, C) flags
+    fn flags(code: &[Z80Instruction], mut hl: u16, mut de: u16) -> (bool, bool) {
+        let (mut a, mut z, mut c) = (0u8, false, false);
+        let mut pc = 0;
+        let jump = |label: &str| code.iter().position(|i| matches!(i, Z80Instruction::Label { name } if name == label)).unwrap();
+        while pc < code.len() {
+            match &code[pc] {
+                Z80Instruction::LoadRegister { dst: Z80Register::A, src: Z80Register::H } => a = (hl >> 8) as u8,
+                Z80Instruction::LoadRegister { dst: Z80Register::A, src: Z80Register::D } => a = (de >> 8) as u8,
+                Z80Instruction::LoadRegister { dst: Z80Register::H, src: Z80Register::A } => hl = (hl & 0xFF) | (a as u16) << 8,
+                Z80Instruction::LoadRegister { dst: Z80Register::D, src: Z80Register::A } => de = (de & 0xFF) | (a as u16) << 8,
+                Z80Instruction::Xor { value } => a ^= value,
+                Z80Instruction::Or { reg: Z80Register::A } => (z, c) = (a == 0, false),
+                Z80Instruction::Or { reg: Z80Register::L } => {
+                    a |= hl as u8;
+                    (z, c) = (a == 0, false);
+                }
+                Z80Instruction::Subtract { dst: Z80Register::HL, src: Z80Register::DE } => {
+                    let (result, borrow) = hl.overflowing_sub(de);
+                    (hl, z, c) = (result, result == 0, borrow);
+                }
+                Z80Instruction::BitTest { bit, reg } => {
+                    let value = if *reg == Z80Register::H { hl >> 8 } else { de >> 8 };
+                    z = value & (1 << bit) == 0;
+                }
+                Z80Instruction::SetCarry => c = true,
+                Z80Instruction::ComplementCarry => c = !c,
+                Z80Instruction::JumpConditional { condition: Condition::Zero, label, .. } if z => pc = jump(label),
+                Z80Instruction::Jump { label, .. } => pc = jump(label),
+                Z80Instruction::JumpConditional { .. } | Z80Instruction::Label { .. } => {}
+                other => panic!("unexpected {:?}", other),
+            }
+            pc += 1;
+        }
+        (z, c)
+    }
+
+    fn check(kind: ComparisonKind, left: i32, right: i32) {
+        let (z, c) = flags(&compare16(kind, "cmp_0"), left as u16, right as u16);
+        assert_eq!((z, c), (left == right, left < right), "{:?} {} vs {}", kind, left, right);
+    }
+
+    #[test]
+    fn test_zero_and_equality_tests() {
+        assert_eq!(
+            zero_test().iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+            ["    ld a, h", "    or l"]
+        );
+        for hl in [0, 1, 0x100, 0x8000, 0xFFFF] {
+            assert_eq!(flags(&zero_test(), hl, 0xFFFF), (hl == 0, false), "{}", hl);
+        }
+        for (l, r) in [(-1, -1), (-1, 1), (300, 300), (0, -32768)] {
+            let (z, _) = flags(&equal16(ComparisonKind::Signed, "cmp_0"), l as u16, r as u16);
+            assert_eq!(z, l == r);
+        }
+        assert_eq!(equal16(ComparisonKind::Signed, "cmp_0"), equal16(ComparisonKind::Unsigned, "cmp_0"));
+        // Integer -1 and Word 65535 have the same bits but differ
+        let (z, _) = flags(&equal16(ComparisonKind::SignedUnsigned, "cmp_0"), 0xFFFF, 0xFFFF);
+        assert!(!z);
+    }
+
+    #[test]
+    fn test_compare16_matches_mathematical_order() {
+        let signed = [-32768, -300, -1, 0, 1, 255, 32767];
+        let unsigned = [0, 1, 255, 32767, 32768, 40000, 65535];
+        for &l in &unsigned {
+            for &r in &unsigned {
+                check(ComparisonKind::Unsigned, l, r);
+            }
+        }
+        for &l in &signed {
+            for &r in &signed {
+                check(ComparisonKind::Signed, l, r);
+            }
+            for &r in &unsigned {
+                check(ComparisonKind::SignedUnsigned, l, r);
+                check(ComparisonKind::UnsignedSigned, r, l);
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Exception frames and unwinding
+//!
+//! A try statement pushes a frame of [`FRAME_SIZE`] bytes on the stack and
+//! makes it the innermost one:
+//!
+//! ```text
+//!     SP+4  handler address
+//!     SP+2  saved IX
+//!     SP+0  previous frame          <- __exc_frame
+//! ```
+//!
+//! Raising stores the value and the type descriptor, then unwinds to the
+//! innermost frame: SP is reset to it, the frame is popped, IX restored and
+//! `ret` jumps to the handler. Anything the routines in between pushed is
+//! discarded with the stack. An exception with no frame left stops the
+//! program.
+//!
+//! A type descriptor is a one-byte data symbol; only its address is used,
+//! so comparing descriptors is a 16-bit compare.
+
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Bytes of an exception frame: handler, IX and the previous frame
+pub const FRAME_SIZE: u16 = 6;
+/// Innermost exception frame, 0 when none (BSS)
+pub const FRAME_SYMBOL: &str = "__exc_frame";
+/// Value of the exception being raised or handled (BSS)
+pub const VALUE_SYMBOL: &str = "__exc_value";
+/// Type descriptor of the exception being raised or handled (BSS)
+pub const TYPE_SYMBOL: &str = "__exc_type";
+/// Raise the exception with value HL and type descriptor DE
+pub const RAISE_ROUTINE: &str = "__exc_raise";
+/// Where an exception no frame handles ends up
+pub const UNHANDLED_ROUTINE: &str = "__exc_unhandled";
+
+fn symbol(name: &str) -> MemoryAddress {
+    MemoryAddress::Symbol(name.to_string())
+}
+
+/// Push a frame whose handler is `handler`. HL is clobbered.
+pub fn enter(handler: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        LoadAddress { reg: HL, label: handler.to_string() },
+        Push { reg: HL },
+        Push { reg: IX },
+        LoadMemory { reg: HL, addr: symbol(FRAME_SYMBOL) },
+        Push { reg: HL },
+        LoadImmediate { reg: HL, value: 0 },
+        Add { dst: HL, src: SP },
+        StoreMemory { addr: symbol(FRAME_SYMBOL), reg: HL },
+    ]
+}
+
+/// Pop the innermost frame, on top of the stack. HL is clobbered.
+pub fn leave() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        Pop { reg: HL },
+        StoreMemory { addr: symbol(FRAME_SYMBOL), reg: HL },
+        Pop { reg: HL },
+        Pop { reg: HL },
+    ]
+}
+
+/// Raise the exception with value HL and type descriptor `type_label`
+pub fn raise(type_label: &str) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadAddress { reg: Z80Register::DE, label: type_label.to_string() },
+        Z80Instruction::Jump { label: RAISE_ROUTINE.to_string(), near: false },
+    ]
+}
+
+/// Raise the exception being handled again
+pub fn reraise() -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadMemory { reg: Z80Register::HL, addr: symbol(VALUE_SYMBOL) },
+        Z80Instruction::LoadMemory { reg: Z80Register::DE, addr: symbol(TYPE_SYMBOL) },
+        Z80Instruction::Jump { label: RAISE_ROUTINE.to_string(), near: false },
+    ]
+}
+
+/// Set Z when the exception being handled has type descriptor
+/// `type_label`. HL and DE are clobbered.
+pub fn is_type(type_label: &str) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadMemory { reg: Z80Register::HL, addr: symbol(TYPE_SYMBOL) },
+        Z80Instruction::LoadAddress { reg: Z80Register::DE, label: type_label.to_string() },
+        Z80Instruction::Or { reg: Z80Register::A },
+        Z80Instruction::Subtract { dst: Z80Register::HL, src: Z80Register::DE },
+    ]
+}
+
+/// Generate the raise and unhandled exception routines, each with its
+/// public name
+pub fn generate_exception_routines() -> Vec<(String, Vec<Z80Instruction>)> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+
+    let raise = vec![
+        Label { name: RAISE_ROUTINE.to_string() },
+        StoreMemory { addr: symbol(VALUE_SYMBOL), reg: HL },
+        ExchangeDeHl,
+        StoreMemory { addr: symbol(TYPE_SYMBOL), reg: HL },
+        LoadMemory { reg: HL, addr: symbol(FRAME_SYMBOL) },
+        LoadRegister { dst: A, src: H },
+        Or { reg: L },
+        JumpConditional { condition: Condition::Zero, label: UNHANDLED_ROUTINE.to_string(), near: false },
+        // Unwind to the frame and pop it; `ret` takes the handler address
+        LoadRegister { dst: SP, src: HL },
+        Pop { reg: HL },
+        StoreMemory { addr: symbol(FRAME_SYMBOL), reg: HL },
+        Pop { reg: IX },
+        Return,
+    ];
+
+    // Stop with interrupts off; the value and type are left for a debugger
+    let stop = format!("{}_stop", UNHANDLED_ROUTINE);
+    let unhandled = vec![
+        Label { name: UNHANDLED_ROUTINE.to_string() },
+        DisableInterrupts,
+        Label { name: stop.clone() },
+        Jump { label: stop, near: true },
+    ];
+
+    vec![(RAISE_ROUTINE.to_string(), raise), (UNHANDLED_ROUTINE.to_string(), unhandled)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_is_popped_by_leave_and_raise() {
+        let pushes = |code: &[Z80Instruction]| {
+            code.iter().filter(|inst| matches!(inst, Z80Instruction::Push { .. })).count() as u16 * 2
+        };
+        let pops = |code: &[Z80Instruction]| {
+            code.iter().filter(|inst| matches!(inst, Z80Instruction::Pop { .. })).count() as u16 * 2
+        };
+        assert_eq!(pushes(&enter("handler")), FRAME_SIZE);
+        assert_eq!(pops(&leave()), FRAME_SIZE);
+        // The raise routine pops all but the handler address, which `ret` takes
+        let routines = generate_exception_routines();
+        let (name, raise) = &routines[0];
+        assert_eq!(name, RAISE_ROUTINE);
+        assert_eq!(pops(raise) + 2, FRAME_SIZE);
+        assert_eq!(raise.last(), Some(&Z80Instruction::Return));
+    }
+}
@@ -0,0 +1,597 @@
+//! Binary file I/O
+//!
+//! A `File` variable is [`FILE_SIZE`] bytes: the OS handle (`$FF` when
+//! closed), the length of the assigned name, the record size and a pointer
+//! to the name. Names are copied into a zero-terminated buffer when the file
+//! is opened, so any character array can be assigned.
+//!
+//! Transfers and positions count records of the size given to `Reset` or
+//! `Rewrite` and are limited to 64 KB. Failures set the error code returned
+//! (and cleared) by `IOResult` instead of stopping the program.
+//!
+//! The routines differ per target only in how they reach the OS:
+//! - **Zeal OS**: `rst $08` with the syscall number in L and the device in H
+//! - **esxDOS** (ZX Spectrum with DivMMC/DivIDE): `rst $08` followed by the
+//!   function code, handle in A and buffers in IX
+//!
+//! Pascal interface (see `lib/files`):
+//! - `procedure Assign(var F: File; const Name; Length: Byte)`
+//! - `procedure Reset(var F: File; RecSize: Word)` and `Rewrite`
+//! - `procedure Close(var F: File)`
+//! - `procedure BlockRead(var F: File; var Buf; Count: Word; var Done: Word)` and `BlockWrite`
+//! - `procedure Seek(var F: File; RecNo: Word)`
+//! - `function FileSize(var F: File): Word`
+//! - `function IOResult: Word`
+
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Size of a `File` variable
+pub const FILE_SIZE: u16 = 6;
+/// Offset of the record size in a `File` variable
+const RECORD_SIZE_OFFSET: u16 = 2;
+/// Handle of a file that is not open
+const CLOSED_HANDLE: u8 = 0xFF;
+
+/// Last error, returned by `IOResult` (BSS, 1 byte)
+pub const ERROR_SYMBOL: &str = "__file_error";
+/// Zero-terminated name of the file being opened, reused for stat results (BSS)
+pub const PATH_SYMBOL: &str = "__file_path";
+/// Size of the path buffer: the longest name plus its terminator
+pub const PATH_SIZE: u16 = 256;
+/// Address of the `Done` argument during a transfer (BSS, 2 bytes)
+pub const DONE_SYMBOL: &str = "__file_done";
+/// Buffer address during a transfer (BSS, 2 bytes)
+pub const BUFFER_SYMBOL: &str = "__file_buffer";
+
+/// Pascal-visible routines
+pub const FILE_ROUTINES: [&str; 9] =
+    ["Assign", "Reset", "Rewrite", "Close", "BlockRead", "BlockWrite", "Seek", "FileSize", "IOResult"];
+
+const OPEN_SYMBOL: &str = "__file_open";
+const MULTIPLY_SYMBOL: &str = "__file_multiply";
+const DIVIDE_SYMBOL: &str = "__file_divide";
+const FAIL_SYMBOL: &str = "__file_fail";
+
+/// How the target's OS is called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystem {
+    /// Zeal 8-bit OS syscalls
+    ZealOs,
+    /// esxDOS hooks on the ZX Spectrum
+    EsxDos,
+}
+
+/// Zeal OS syscall numbers and `open` flags
+mod zeal {
+    pub const READ: u8 = 0;
+    pub const WRITE: u8 = 1;
+    pub const OPEN: u8 = 2;
+    pub const CLOSE: u8 = 3;
+    pub const DSTAT: u8 = 4;
+    pub const SEEK: u8 = 6;
+    pub const O_RDWR: u8 = 0x02;
+    pub const O_TRUNC: u8 = 0x04;
+    pub const O_CREAT: u8 = 0x10;
+    /// Offset of the 32-bit size in a stat result
+    pub const STAT_SIZE_OFFSET: u16 = 0;
+}
+
+/// esxDOS function codes and open modes
+mod esx {
+    pub const F_OPEN: u8 = 0x9A;
+    pub const F_CLOSE: u8 = 0x9B;
+    pub const F_READ: u8 = 0x9D;
+    pub const F_WRITE: u8 = 0x9E;
+    pub const F_SEEK: u8 = 0x9F;
+    pub const F_FSTAT: u8 = 0xA1;
+    pub const FA_READ_WRITE: u8 = 0x03;
+    pub const FA_CREATE_AL: u8 = 0x0C;
+    /// Default drive
+    pub const DRIVE: u8 = b'*';
+    /// Offset of the 32-bit size in an `F_FSTAT` result
+    pub const STAT_SIZE_OFFSET: u16 = 7;
+}
+
+/// Both file systems are entered through `rst $08`
+const SYSCALL_VECTOR: u8 = 0x08;
+
+fn symbol(name: &str) -> MemoryAddress {
+    MemoryAddress::Symbol(name.to_string())
+}
+
+fn label(name: &str) -> Z80Instruction {
+    Z80Instruction::Label { name: name.to_string() }
+}
+
+fn load_hl(reg: Z80Register) -> Z80Instruction {
+    Z80Instruction::LoadMemory { reg, addr: MemoryAddress::RegisterIndirect(Z80Register::HL) }
+}
+
+fn store_hl(reg: Z80Register) -> Z80Instruction {
+    Z80Instruction::StoreMemory { addr: MemoryAddress::RegisterIndirect(Z80Register::HL), reg }
+}
+
+/// Load the record size of the file at HL into BC; HL is preserved
+fn load_record_size() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut code = vec![Push { reg: HL }];
+    code.extend((0..RECORD_SIZE_OFFSET).map(|_| Increment { reg: HL }));
+    code.extend([load_hl(C), Increment { reg: HL }, load_hl(B), Pop { reg: HL }]);
+    code
+}
+
+impl FileSystem {
+    /// Open mode for `Reset` (existing file) or `Rewrite` (created or truncated)
+    fn open_mode(&self, create: bool) -> u8 {
+        match (self, create) {
+            (FileSystem::ZealOs, false) => zeal::O_RDWR,
+            (FileSystem::ZealOs, true) => zeal::O_RDWR | zeal::O_CREAT | zeal::O_TRUNC,
+            (FileSystem::EsxDos, false) => esx::FA_READ_WRITE,
+            (FileSystem::EsxDos, true) => esx::FA_READ_WRITE | esx::FA_CREATE_AL,
+        }
+    }
+
+    /// Call the OS with the handle in A and HL pointing at the file, which is
+    /// preserved. Zeal OS takes its remaining arguments in DE and BC, esxDOS
+    /// takes a buffer from DE (moved to IX) and a count or offset in BC(DE).
+    fn call(&self, zeal_syscall: u8, esx_function: u8, esx_buffer: bool) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        match self {
+            FileSystem::ZealOs => vec![
+                Push { reg: HL },
+                LoadRegister { dst: H, src: A },
+                LoadImmediate { reg: L, value: zeal_syscall as u16 },
+                Restart { vector: SYSCALL_VECTOR },
+                Pop { reg: HL },
+            ],
+            FileSystem::EsxDos => {
+                let mut code = vec![Push { reg: HL }];
+                if esx_buffer {
+                    code.extend([Push { reg: IX }, Push { reg: DE }, Pop { reg: IX }]);
+                }
+                code.extend([Restart { vector: SYSCALL_VECTOR }, DefineByte { value: esx_function }]);
+                if esx_buffer {
+                    code.push(Pop { reg: IX });
+                }
+                code.push(Pop { reg: HL });
+                code
+            }
+        }
+    }
+
+    /// Jump to `target` when the call succeeded; otherwise the error code is in A
+    fn on_success(&self, target: &str) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        match self {
+            FileSystem::ZealOs => vec![
+                Or { reg: Z80Register::A },
+                JumpConditional { condition: Condition::Zero, label: target.to_string(), near: true },
+            ],
+            FileSystem::EsxDos => {
+                vec![JumpConditional { condition: Condition::NoCarry, label: target.to_string(), near: true }]
+            }
+        }
+    }
+
+    /// `__file_open`: open the file at HL with mode A, storing its handle
+    fn open(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let copied = format!("{}_copied", OPEN_SYMBOL);
+        let opened = format!("{}_ok", OPEN_SYMBOL);
+        let mut code = vec![
+            label(OPEN_SYMBOL),
+            Push { reg: AF },
+            Push { reg: HL },
+            // Copy the name into the path buffer and terminate it
+            Increment { reg: HL },
+            load_hl(C),
+            LoadImmediate { reg: B, value: 0 },
+        ];
+        code.extend((1..4).map(|_| Increment { reg: HL }));
+        code.extend([
+            load_hl(E),
+            Increment { reg: HL },
+            load_hl(D),
+            ExchangeDeHl,
+            LoadAddress { reg: DE, label: PATH_SYMBOL.to_string() },
+            LoadRegister { dst: A, src: C },
+            Or { reg: A },
+            JumpConditional { condition: Condition::Zero, label: copied.clone(), near: true },
+            Ldir,
+            label(&copied),
+            LoadImmediate { reg: A, value: 0 },
+            StoreMemory { addr: MemoryAddress::RegisterIndirect(DE), reg: A },
+            Pop { reg: HL },
+            Pop { reg: AF },
+        ]);
+        match self {
+            FileSystem::ZealOs => code.extend([
+                Push { reg: HL },
+                LoadRegister { dst: H, src: A },
+                LoadImmediate { reg: L, value: zeal::OPEN as u16 },
+                LoadAddress { reg: BC, label: PATH_SYMBOL.to_string() },
+                Restart { vector: SYSCALL_VECTOR },
+                Pop { reg: HL },
+                // A negative result is an error code
+                BitTest { bit: 7, reg: A },
+                JumpConditional { condition: Condition::Zero, label: opened.clone(), near: true },
+            ]),
+            FileSystem::EsxDos => {
+                code.extend([
+                    Push { reg: HL },
+                    Push { reg: IX },
+                    LoadRegister { dst: B, src: A },
+                    LoadAddress { reg: IX, label: PATH_SYMBOL.to_string() },
+                    LoadImmediate { reg: A, value: esx::DRIVE as u16 },
+                    Restart { vector: SYSCALL_VECTOR },
+                    DefineByte { value: esx::F_OPEN },
+                    Pop { reg: IX },
+                    Pop { reg: HL },
+                ]);
+                code.extend(self.on_success(&opened));
+            }
+        }
+        code.extend([
+            StoreMemory { addr: symbol(ERROR_SYMBOL), reg: A },
+            LoadImmediate { reg: A, value: CLOSED_HANDLE as u16 },
+            label(&opened),
+            store_hl(A),
+            Return,
+        ]);
+        code
+    }
+
+    /// `Reset` or `Rewrite`: store the record size, then open
+    fn reset(&self, name: &str, create: bool) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let mut code = vec![
+            label(name),
+            // Return address, RecSize, F
+            Pop { reg: BC },
+            Pop { reg: DE },
+            Pop { reg: HL },
+            Push { reg: BC },
+            Push { reg: HL },
+        ];
+        code.extend((0..RECORD_SIZE_OFFSET).map(|_| Increment { reg: HL }));
+        code.extend([
+            store_hl(E),
+            Increment { reg: HL },
+            store_hl(D),
+            Pop { reg: HL },
+            LoadImmediate { reg: A, value: self.open_mode(create) as u16 },
+            Jump { label: OPEN_SYMBOL.to_string(), near: false },
+        ]);
+        code
+    }
+
+    /// `Close`: mark the file closed, then close its handle
+    fn close(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let mut code = vec![
+            label("Close"),
+            Pop { reg: BC },
+            Pop { reg: HL },
+            Push { reg: BC },
+            load_hl(B),
+            LoadImmediate { reg: A, value: CLOSED_HANDLE as u16 },
+            store_hl(A),
+            LoadRegister { dst: A, src: B },
+        ];
+        code.extend(self.call(zeal::CLOSE, esx::F_CLOSE, false));
+        code.extend(self.on_success("Close_ok"));
+        code.extend([Jump { label: FAIL_SYMBOL.to_string(), near: false }, label("Close_ok"), Return]);
+        code
+    }
+
+    /// `BlockRead` or `BlockWrite`: transfer `Count` records and store the
+    /// number of whole records transferred in `Done`
+    fn transfer(&self, name: &str, read: bool) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let done = format!("{}_done", name);
+        let (zeal_syscall, esx_function) = if read { (zeal::READ, esx::F_READ) } else { (zeal::WRITE, esx::F_WRITE) };
+        let mut code = vec![
+            label(name),
+            // Return address, Done, Count, Buf, F
+            Pop { reg: BC },
+            Pop { reg: HL },
+            StoreMemory { addr: symbol(DONE_SYMBOL), reg: HL },
+            Pop { reg: DE },
+            Pop { reg: HL },
+            StoreMemory { addr: symbol(BUFFER_SYMBOL), reg: HL },
+            Pop { reg: HL },
+            Push { reg: BC },
+        ];
+        // Bytes to transfer: Count * RecSize
+        code.extend(load_record_size());
+        code.extend([
+            Push { reg: HL },
+            Call { label: MULTIPLY_SYMBOL.to_string() },
+            LoadRegister { dst: B, src: H },
+            LoadRegister { dst: C, src: L },
+            Pop { reg: HL },
+            load_hl(A),
+            LoadMemory { reg: DE, addr: symbol(BUFFER_SYMBOL) },
+        ]);
+        code.extend(self.call(zeal_syscall, esx_function, true));
+        code.extend(self.on_success(&done));
+        code.extend([
+            StoreMemory { addr: symbol(ERROR_SYMBOL), reg: A },
+            LoadImmediate { reg: BC, value: 0 },
+            label(&done),
+            // Done := bytes transferred / RecSize
+            Push { reg: BC },
+        ]);
+        code.extend(load_record_size());
+        code.extend([
+            LoadRegister { dst: D, src: B },
+            LoadRegister { dst: E, src: C },
+            Pop { reg: HL },
+            Call { label: DIVIDE_SYMBOL.to_string() },
+            LoadMemory { reg: HL, addr: symbol(DONE_SYMBOL) },
+            store_hl(C),
+            Increment { reg: HL },
+            store_hl(B),
+            Return,
+        ]);
+        code
+    }
+
+    /// `Seek`: move to record `RecNo`
+    fn seek(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let mut code = vec![
+            label("Seek"),
+            // Return address, RecNo, F
+            Pop { reg: BC },
+            Pop { reg: DE },
+            Pop { reg: HL },
+            Push { reg: BC },
+        ];
+        code.extend(load_record_size());
+        code.extend([
+            Push { reg: HL },
+            Call { label: MULTIPLY_SYMBOL.to_string() },
+            // Offset in BCDE, from the start of the file
+            ExchangeDeHl,
+            Pop { reg: HL },
+            LoadImmediate { reg: BC, value: 0 },
+            load_hl(A),
+        ]);
+        match self {
+            FileSystem::ZealOs => code.extend([
+                Push { reg: HL },
+                LoadRegister { dst: H, src: A },
+                LoadImmediate { reg: A, value: 0 },
+                LoadImmediate { reg: L, value: zeal::SEEK as u16 },
+                Restart { vector: SYSCALL_VECTOR },
+                Pop { reg: HL },
+            ]),
+            FileSystem::EsxDos => code.extend([
+                Push { reg: HL },
+                LoadImmediate { reg: L, value: 0 },
+                Restart { vector: SYSCALL_VECTOR },
+                DefineByte { value: esx::F_SEEK },
+                Pop { reg: HL },
+            ]),
+        }
+        code.extend(self.on_success("Seek_ok"));
+        code.extend([Jump { label: FAIL_SYMBOL.to_string(), near: false }, label("Seek_ok"), Return]);
+        code
+    }
+
+    /// `FileSize`: size in whole records, 0 on failure
+    fn file_size(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let size_offset = match self {
+            FileSystem::ZealOs => zeal::STAT_SIZE_OFFSET,
+            FileSystem::EsxDos => esx::STAT_SIZE_OFFSET,
+        };
+        let mut code = vec![
+            label("FileSize"),
+            Pop { reg: BC },
+            Pop { reg: HL },
+            Push { reg: BC },
+            load_hl(A),
+            LoadAddress { reg: DE, label: PATH_SYMBOL.to_string() },
+        ];
+        code.extend(self.call(zeal::DSTAT, esx::F_FSTAT, true));
+        code.extend(self.on_success("FileSize_ok"));
+        code.extend([
+            StoreMemory { addr: symbol(ERROR_SYMBOL), reg: A },
+            LoadImmediate { reg: HL, value: 0 },
+            Return,
+            label("FileSize_ok"),
+        ]);
+        code.extend(load_record_size());
+        code.extend([
+            LoadRegister { dst: D, src: B },
+            LoadRegister { dst: E, src: C },
+            LoadAddress { reg: HL, label: PATH_SYMBOL.to_string() },
+        ]);
+        code.extend((0..size_offset).map(|_| Increment { reg: HL }));
+        code.extend([
+            load_hl(A),
+            Increment { reg: HL },
+            load_hl(H),
+            LoadRegister { dst: L, src: A },
+            Call { label: DIVIDE_SYMBOL.to_string() },
+            LoadRegister { dst: H, src: B },
+            LoadRegister { dst: L, src: C },
+            Return,
+        ]);
+        code
+    }
+}
+
+/// `Assign`: remember the name and record size 128 of a closed file
+fn assign() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        label("Assign"),
+        // Return address, Length, Name, F
+        Pop { reg: BC },
+        Pop { reg: DE },
+        LoadRegister { dst: A, src: E },
+        Pop { reg: DE },
+        Pop { reg: HL },
+        Push { reg: BC },
+        LoadRegister { dst: B, src: A },
+        LoadImmediate { reg: A, value: CLOSED_HANDLE as u16 },
+        store_hl(A),
+        Increment { reg: HL },
+        store_hl(B),
+        Increment { reg: HL },
+        LoadImmediate { reg: A, value: 128 },
+        store_hl(A),
+        Increment { reg: HL },
+        LoadImmediate { reg: A, value: 0 },
+        store_hl(A),
+        Increment { reg: HL },
+        store_hl(E),
+        Increment { reg: HL },
+        store_hl(D),
+        Return,
+    ]
+}
+
+/// `IOResult`: return and clear the last error
+fn io_result() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        label("IOResult"),
+        LoadMemory { reg: A, addr: symbol(ERROR_SYMBOL) },
+        LoadRegister { dst: L, src: A },
+        LoadImmediate { reg: H, value: 0 },
+        LoadImmediate { reg: A, value: 0 },
+        StoreMemory { addr: symbol(ERROR_SYMBOL), reg: A },
+        Return,
+    ]
+}
+
+/// Shared helpers: record the error in A, HL = DE * BC, BC = HL / DE
+fn helpers() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let multiply_loop = format!("{}_loop", MULTIPLY_SYMBOL);
+    let multiply_skip = format!("{}_skip", MULTIPLY_SYMBOL);
+    let divide_loop = format!("{}_loop", DIVIDE_SYMBOL);
+    let divide_done = format!("{}_done", DIVIDE_SYMBOL);
+    vec![
+        label(FAIL_SYMBOL),
+        StoreMemory { addr: symbol(ERROR_SYMBOL), reg: A },
+        Return,
+        // Shift-and-add over the 16 bits of DE
+        label(MULTIPLY_SYMBOL),
+        LoadImmediate { reg: HL, value: 0 },
+        LoadImmediate { reg: A, value: 16 },
+        label(&multiply_loop),
+        Add { dst: HL, src: HL },
+        ExchangeDeHl,
+        Add { dst: HL, src: HL },
+        ExchangeDeHl,
+        JumpConditional { condition: Condition::NoCarry, label: multiply_skip.clone(), near: true },
+        Add { dst: HL, src: BC },
+        label(&multiply_skip),
+        Decrement { reg: A },
+        JumpConditional { condition: Condition::NonZero, label: multiply_loop, near: true },
+        Return,
+        // Repeated subtraction: the quotient is at most the record count
+        label(DIVIDE_SYMBOL),
+        LoadImmediate { reg: BC, value: 0 },
+        LoadRegister { dst: A, src: D },
+        Or { reg: E },
+        JumpConditional { condition: Condition::Zero, label: divide_done.clone(), near: true },
+        label(&divide_loop),
+        Or { reg: A },
+        Subtract { dst: HL, src: DE },
+        JumpConditional { condition: Condition::Carry, label: divide_done.clone(), near: true },
+        Increment { reg: BC },
+        Jump { label: divide_loop, near: true },
+        label(&divide_done),
+        Return,
+    ]
+}
+
+/// Generate the file routines for `fs`, each with its public name
+pub fn generate_file_routines(fs: FileSystem) -> Vec<(String, Vec<Z80Instruction>)> {
+    vec![
+        ("Assign".to_string(), assign()),
+        (OPEN_SYMBOL.to_string(), fs.open()),
+        ("Reset".to_string(), fs.reset("Reset", false)),
+        ("Rewrite".to_string(), fs.reset("Rewrite", true)),
+        ("Close".to_string(), fs.close()),
+        ("BlockRead".to_string(), fs.transfer("BlockRead", true)),
+        ("BlockWrite".to_string(), fs.transfer("BlockWrite", false)),
+        ("Seek".to_string(), fs.seek()),
+        ("FileSize".to_string(), fs.file_size()),
+        ("IOResult".to_string(), io_result()),
+        (FAIL_SYMBOL.to_string(), helpers()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(code: &[Z80Instruction], pred: impl Fn(&Z80Instruction) -> bool) -> usize {
+        code.iter().filter(|i| pred(i)).count()
+    }
+
+    #[test]
+    fn test_file_size_matches_type() {
+        assert_eq!(types::Type::file(None).size(), Some(FILE_SIZE as usize));
+    }
+
+    #[test]
+    fn test_routines_pop_their_arguments() {
+        // (routine, arguments): each pops its arguments and re-pushes the return address
+        let arguments = [
+            ("Assign", 3),
+            ("Reset", 2),
+            ("Rewrite", 2),
+            ("Close", 1),
+            ("BlockRead", 4),
+            ("BlockWrite", 4),
+            ("Seek", 2),
+            ("FileSize", 1),
+            ("IOResult", 0),
+        ];
+        for fs in [FileSystem::ZealOs, FileSystem::EsxDos] {
+            let routines = generate_file_routines(fs);
+            for (name, args) in arguments {
+                let (_, code) = routines.iter().find(|(n, _)| n == name).unwrap();
+                let pushes = count(code, |i| matches!(i, Z80Instruction::Push { .. }));
+                let pops = count(code, |i| matches!(i, Z80Instruction::Pop { .. }));
+                assert_eq!(pops, pushes + args, "{:?} {}", fs, name);
+            }
+            let names: Vec<&str> = routines.iter().map(|(n, _)| n.as_str()).collect();
+            assert!(FILE_ROUTINES.iter().all(|r| names.contains(r)));
+        }
+    }
+
+    #[test]
+    fn test_os_calls_per_target() {
+        let zeal = generate_file_routines(FileSystem::ZealOs);
+        let esx = generate_file_routines(FileSystem::EsxDos);
+        let read = |routines: &[(String, Vec<Z80Instruction>)]| {
+            routines.iter().find(|(n, _)| n == "BlockRead").unwrap().1.clone()
+        };
+        // Zeal OS passes the syscall in L; esxDOS puts the function code after the rst
+        assert!(read(&zeal).contains(&Z80Instruction::LoadImmediate { reg: Z80Register::L, value: zeal::READ as u16 }));
+        assert!(!read(&zeal).iter().any(|i| matches!(i, Z80Instruction::DefineByte { .. })));
+        let code = read(&esx);
+        let rst = code.iter().position(|i| *i == Z80Instruction::Restart { vector: SYSCALL_VECTOR }).unwrap();
+        assert_eq!(code[rst + 1], Z80Instruction::DefineByte { value: esx::F_READ });
+    }
+}
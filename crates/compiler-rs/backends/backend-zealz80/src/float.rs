@@ -0,0 +1,92 @@
+//! Software floating point (`real`, `single`)
+//!
+//! The Z80 has no floating-point hardware, so each float instruction becomes
+//! a call to a runtime routine. Reals are 32-bit IEEE single precision; the
+//! routines share one convention:
+//! - **Left operand**: DE:HL (DE holds the high word)
+//! - **Right operand**: pushed on the stack, high word first; the routine
+//!   removes it, as Pascal callees do
+//! - **Result**: DE:HL, or HL for conversions to Integer
+//! - `__fcmp` returns flags the way `cp` does: Z when equal, C when the left
+//!   operand is below the right
+//!
+//! The routines come from the runtime library; the object file lists the
+//! ones a unit calls as externals.
+
+use ir::Opcode;
+
+use crate::{Z80Instruction, Z80Register};
+
+pub const FADD_ROUTINE: &str = "__fadd";
+pub const FSUB_ROUTINE: &str = "__fsub";
+pub const FMUL_ROUTINE: &str = "__fmul";
+pub const FDIV_ROUTINE: &str = "__fdiv";
+pub const FCMP_ROUTINE: &str = "__fcmp";
+/// Integer in HL to a real in DE:HL
+pub const ITOF_ROUTINE: &str = "__itof";
+/// Real in DE:HL to an Integer in HL, toward zero and to the nearest
+pub const FTRUNC_ROUTINE: &str = "__ftrunc";
+pub const FROUND_ROUTINE: &str = "__fround";
+
+/// Shape of a float instruction's operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatOperands {
+    /// `dst, left, right`
+    Binary,
+    /// `left, right`; only the flags are set
+    Compare,
+    /// `dst, src`
+    Convert,
+}
+
+/// Runtime routine implementing `opcode`, and the shape of its operands, or
+/// None if `opcode` is not a float instruction
+pub fn routine(opcode: &Opcode) -> Option<(&'static str, FloatOperands)> {
+    Some(match opcode {
+        Opcode::FAdd => (FADD_ROUTINE, FloatOperands::Binary),
+        Opcode::FSub => (FSUB_ROUTINE, FloatOperands::Binary),
+        Opcode::FMul => (FMUL_ROUTINE, FloatOperands::Binary),
+        Opcode::FDiv => (FDIV_ROUTINE, FloatOperands::Binary),
+        Opcode::FCmp => (FCMP_ROUTINE, FloatOperands::Compare),
+        Opcode::IToF => (ITOF_ROUTINE, FloatOperands::Convert),
+        Opcode::FTrunc => (FTRUNC_ROUTINE, FloatOperands::Convert),
+        Opcode::FRound => (FROUND_ROUTINE, FloatOperands::Convert),
+        _ => return None,
+    })
+}
+
+/// Load the bit pattern of a real constant into DE:HL
+pub fn load_constant(bits: i32) -> [Z80Instruction; 2] {
+    let bits = bits as u32;
+    [
+        Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: (bits >> 16) as u16 },
+        Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: bits as u16 },
+    ]
+}
+
+/// Pass the real in DE:HL as the right operand
+pub fn push_operand() -> [Z80Instruction; 2] {
+    [Z80Instruction::Push { reg: Z80Register::DE }, Z80Instruction::Push { reg: Z80Register::HL }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routines_and_constant_halves() {
+        assert_eq!(routine(&Opcode::FDiv), Some((FDIV_ROUTINE, FloatOperands::Binary)));
+        assert_eq!(routine(&Opcode::FCmp), Some((FCMP_ROUTINE, FloatOperands::Compare)));
+        assert_eq!(routine(&Opcode::FRound), Some((FROUND_ROUTINE, FloatOperands::Convert)));
+        assert_eq!(routine(&Opcode::Add), None);
+
+        // 1.5 is $3FC00000
+        assert_eq!(
+            load_constant(1.5f32.to_bits() as i32),
+            [
+                Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: 0x3FC0 },
+                Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: 0 },
+            ]
+        );
+    }
+}
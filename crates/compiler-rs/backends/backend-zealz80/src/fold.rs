@@ -0,0 +1,102 @@
+//! Operations folded into memory operands
+//!
+//! A statement updating a variable in place, `Inc(I)` or `Include(S, 3)`,
+//! would otherwise load the variable, change it in a register and store it
+//! back. The Z80 can increment, decrement and set bits of a byte in memory
+//! directly, at `(ix+d)` or `(hl)`:
+//!
+//! ```text
+//!     ADD (ix-2), (ix-2), 1       inc (ix-2)          ; 35 T-states, not 82
+//!                                 jr nz, fold_0
+//!                                 inc (ix-1)
+//!                             fold_0:
+//!     SETINCL (ix-8), 11, 11, 2   set 3, (ix-7)       ; no call to __set_incl
+//! ```
+//!
+//! A word is stepped a byte at a time, the carry into the high byte taken
+//! by testing the low one. The value is then left in memory only, so the
+//! step is not folded when the next instruction reads it back.
+
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Most elements of a constant range set with one `set` each, before the
+/// include routine is smaller
+pub const MAX_FOLDED_BITS: i32 = 4;
+
+/// Whether the frame slot of `size` bytes at `offset` is reachable with
+/// IX displacements
+pub fn displacement_fits(offset: i32, size: i32) -> bool {
+    offset >= i8::MIN as i32 && offset + size - 1 <= i8::MAX as i32
+}
+
+/// Increment the word at `offset` from IX
+pub fn increment(offset: i16, skip: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    vec![
+        IncrementMemory { addr: MemoryAddress::FrameRelative(offset) },
+        JumpConditional { condition: Condition::NonZero, label: skip.to_string(), near: true },
+        IncrementMemory { addr: MemoryAddress::FrameRelative(offset + 1) },
+        Label { name: skip.to_string() },
+    ]
+}
+
+/// Decrement the word at `offset` from IX, borrowing from the high byte
+/// when the low one is 0. A is clobbered.
+pub fn decrement(offset: i16, skip: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    vec![
+        LoadMemory { reg: Z80Register::A, addr: MemoryAddress::FrameRelative(offset) },
+        Or { reg: Z80Register::A },
+        JumpConditional { condition: Condition::NonZero, label: skip.to_string(), near: true },
+        DecrementMemory { addr: MemoryAddress::FrameRelative(offset + 1) },
+        Label { name: skip.to_string() },
+        DecrementMemory { addr: MemoryAddress::FrameRelative(offset) },
+    ]
+}
+
+/// Set the bits of the elements `first..=last` of the set at `offset` from
+/// IX
+pub fn set_bits(offset: i16, first: i32, last: i32) -> Vec<Z80Instruction> {
+    (first..=last)
+        .map(|n| Z80Instruction::SetBit { bit: (n & 7) as u8, addr: MemoryAddress::FrameRelative(offset + (n >> 3) as i16) })
+        .collect()
+}
+
+/// Set the bits of the elements `first..=last` of the set whose address is
+/// in HL, stepping HL through its bytes. DE is clobbered.
+pub fn set_bits_at_hl(first: i32, last: i32) -> Vec<Z80Instruction> {
+    let mut code = Vec::new();
+    let mut byte = 0;
+    for n in first..=last {
+        code.extend(crate::arith::add_constant((n >> 3) - byte));
+        byte = n >> 3;
+        code.push(Z80Instruction::SetBit { bit: (n & 7) as u8, addr: MemoryAddress::RegisterIndirect(Z80Register::HL) });
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folded_words_step_their_high_byte_on_a_carry() {
+        let text: Vec<String> = increment(-2, "fold_0").iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    inc (ix-2)", "    jr nz, fold_0", "    inc (ix-1)", "fold_0:"]);
+        let text: Vec<String> = decrement(4, "fold_1").iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(
+            text,
+            ["    ld a, (ix+4)", "    or a", "    jr nz, fold_1", "    dec (ix+5)", "fold_1:", "    dec (ix+4)"]
+        );
+        assert!(displacement_fits(-128, 2) && displacement_fits(126, 2));
+        assert!(!displacement_fits(127, 2) && !displacement_fits(-129, 1));
+    }
+
+    #[test]
+    fn test_set_bits_address_each_element_byte() {
+        let text: Vec<String> = set_bits(-8, 6, 9).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    set 6, (ix-8)", "    set 7, (ix-8)", "    set 0, (ix-7)", "    set 1, (ix-7)"]);
+        let text: Vec<String> = set_bits_at_hl(7, 8).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    set 7, (hl)", "    inc hl", "    set 0, (hl)"]);
+    }
+}
@@ -0,0 +1,767 @@
+//! Heap manager for class instances
+//!
+//! The heap is a BSS area of [`HEAP_SIZE`] bytes holding a chain of blocks,
+//! each a size word followed by the instance. The size counts the header
+//! and is even, so bit 0 marks a block in use; a zero header, as BSS
+//! starts, marks the unused rest of the heap:
+//!
+//! ```text
+//!     __heap      size | 1    in use
+//!                 ...
+//!                 size        free
+//!                 ...
+//!                 0           rest of the heap, up to __heap_end
+//! ```
+//!
+//! Allocation takes the first free block large enough, whole, or else
+//! extends the chain. Freeing clears the in-use bit; neighbouring free
+//! blocks are not merged, which suits programs creating instances of a
+//! few classes.
+//!
+//! The debug heap, selected by `{$DEBUGHEAP}` or `--debug-heap`, wraps the
+//! same allocator. Each instance sits between two [`CANARY`] words and is
+//! recorded in [`TABLE_SYMBOL`]; freeing an instance not in the table, or
+//! one whose canaries were overwritten, is runtime error 204. `ReportLeaks`
+//! prints how many instances are still allocated:
+//!
+//! ```text
+//!     size | 1    CANARY    instance    CANARY
+//! ```
+
+use crate::runtime_errors::{self, Console};
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Bytes of the heap (BSS)
+pub const HEAP_SIZE: u16 = 4096;
+/// Start of the heap (BSS)
+pub const HEAP_SYMBOL: &str = "__heap";
+/// End of the heap, just after its last byte
+pub const HEAP_END_SYMBOL: &str = "__heap_end";
+/// Allocate a zeroed block of HL bytes, header included, and set its first
+/// word to BC. Returns its instance in HL, or 0 when the heap is full.
+pub const NEW_ROUTINE: &str = "__new";
+/// Free the instance in HL, if not 0
+pub const DISPOSE_ROUTINE: &str = "__dispose";
+/// Switch selecting the debug heap, `{$DEBUGHEAP}` or `{$DEBUGHEAP ON}`
+pub const DEBUG_HEAP_SWITCH: &str = "DEBUGHEAP";
+/// Word before and after each instance of the debug heap
+pub const CANARY: u16 = 0xC0DE;
+/// Instances the debug heap tracks at once; allocating more fails as when
+/// the heap is full
+pub const TRACKED_BLOCKS: u16 = 64;
+/// Instances allocated on the debug heap, 0 for a free slot (BSS, 2 bytes
+/// each)
+pub const TABLE_SYMBOL: &str = "__heap_table";
+/// Print the number of instances still allocated on the debug heap
+pub const REPORT_LEAKS_ROUTINE: &str = "ReportLeaks";
+/// The allocator under the debug heap's checks
+const RAW_NEW_ROUTINE: &str = "__heap_new";
+const RAW_DISPOSE_ROUTINE: &str = "__heap_dispose";
+/// Find the table slot holding DE, leaving it in HL, or 0 if none does
+const FIND_ROUTINE: &str = "__heap_find";
+
+/// Bytes of the block holding an instance of `size` bytes
+pub fn block_size(size: u16) -> u16 {
+    size.max(2).next_multiple_of(2) + 2
+}
+
+/// Allocate an instance of `size` bytes whose VMT pointer is `vmt` (None
+/// for a class without one), leaving it in HL. BC and DE are clobbered.
+pub fn new_instance(size: u16, vmt: Option<&str>) -> Vec<Z80Instruction> {
+    vec![
+        match vmt {
+            Some(vmt) => Z80Instruction::LoadAddress { reg: Z80Register::BC, label: vmt.to_string() },
+            None => Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: 0 },
+        },
+        Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: block_size(size) },
+        Z80Instruction::Call { label: NEW_ROUTINE.to_string() },
+    ]
+}
+
+/// Generate the allocation and free routines, each with its public name
+pub fn generate_heap_routines() -> Vec<(String, Vec<Z80Instruction>)> {
+    let (new, dispose) = allocator(NEW_ROUTINE, DISPOSE_ROUTINE);
+    vec![(NEW_ROUTINE.to_string(), new), (DISPOSE_ROUTINE.to_string(), dispose)]
+}
+
+/// The allocation and free routines, named `new_name` and `dispose_name`
+fn allocator(new_name: &str, dispose_name: &str) -> (Vec<Z80Instruction>, Vec<Z80Instruction>) {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let label = |suffix: &str| format!("{}_{}", new_name, suffix);
+    let jump = |condition, suffix: &str| JumpConditional { condition, label: label(suffix), near: true };
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+
+    let new = vec![
+        Label { name: new_name.to_string() },
+        Push { reg: BC },
+        ExchangeDeHl,
+        LoadAddress { reg: HL, label: HEAP_SYMBOL.to_string() },
+        // BC := header of the block at HL
+        Label { name: label("next") },
+        LoadMemory { reg: C, addr: at_hl.clone() },
+        Increment { reg: HL },
+        LoadMemory { reg: B, addr: at_hl.clone() },
+        Decrement { reg: HL },
+        LoadRegister { dst: A, src: B },
+        Or { reg: C },
+        jump(Condition::Zero, "tail"),
+        BitTest { bit: 0, reg: C },
+        jump(Condition::NonZero, "skip"),
+        // A free block is taken if it holds DE bytes
+        Push { reg: HL },
+        LoadRegister { dst: H, src: B },
+        LoadRegister { dst: L, src: C },
+        Or { reg: A },
+        Subtract { dst: HL, src: DE },
+        Pop { reg: HL },
+        jump(Condition::NoCarry, "take"),
+        Label { name: label("skip") },
+        BitTest { bit: 0, reg: C },
+        jump(Condition::Zero, "step"),
+        Decrement { reg: BC },
+        Label { name: label("step") },
+        Add { dst: HL, src: BC },
+        Jump { label: label("next"), near: true },
+        // Extend the chain if the block and the zero header after it fit
+        Label { name: label("tail") },
+        Push { reg: HL },
+        Add { dst: HL, src: DE },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        LoadAddress { reg: BC, label: HEAP_END_SYMBOL.to_string() },
+        Or { reg: A },
+        Subtract { dst: HL, src: BC },
+        Pop { reg: HL },
+        jump(Condition::Zero, "fits"),
+        jump(Condition::NoCarry, "full"),
+        Label { name: label("fits") },
+        LoadRegister { dst: B, src: D },
+        LoadRegister { dst: C, src: E },
+        // Mark the block of BC bytes at HL in use and zero its instance
+        Label { name: label("take") },
+        Increment { reg: C },
+        StoreMemory { addr: at_hl.clone(), reg: C },
+        Increment { reg: HL },
+        StoreMemory { addr: at_hl.clone(), reg: B },
+        Increment { reg: HL },
+        Decrement { reg: C },
+        Decrement { reg: BC },
+        Decrement { reg: BC },
+        Push { reg: HL },
+        LoadRegister { dst: D, src: H },
+        LoadRegister { dst: E, src: L },
+        Increment { reg: DE },
+        LoadImmediate { reg: A, value: 0 },
+        StoreMemory { addr: at_hl.clone(), reg: A },
+        Decrement { reg: BC },
+        Ldir,
+        // Install the VMT pointer
+        Pop { reg: HL },
+        Pop { reg: DE },
+        StoreMemory { addr: at_hl.clone(), reg: E },
+        Increment { reg: HL },
+        StoreMemory { addr: at_hl.clone(), reg: D },
+        Decrement { reg: HL },
+        Return,
+        Label { name: label("full") },
+        Pop { reg: DE },
+        LoadImmediate { reg: HL, value: 0 },
+        Return,
+    ];
+
+    let done = format!("{}_done", dispose_name);
+    let dispose = vec![
+        Label { name: dispose_name.to_string() },
+        LoadRegister { dst: A, src: H },
+        Or { reg: L },
+        JumpConditional { condition: Condition::Zero, label: done.clone(), near: true },
+        Decrement { reg: HL },
+        Decrement { reg: HL },
+        // The in-use bit is the low bit of the size
+        LoadMemory { reg: A, addr: at_hl.clone() },
+        Decrement { reg: A },
+        StoreMemory { addr: at_hl, reg: A },
+        Label { name: done },
+        Return,
+    ];
+
+    (new, dispose)
+}
+
+/// Store CANARY at HL and HL - 1, going down
+fn store_canary() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+    vec![
+        LoadImmediate { reg: A, value: CANARY >> 8 },
+        StoreMemory { addr: at_hl.clone(), reg: A },
+        Decrement { reg: HL },
+        LoadImmediate { reg: A, value: CANARY & 0xFF },
+        StoreMemory { addr: at_hl, reg: A },
+    ]
+}
+
+/// Check for CANARY at HL and HL - 1, going down, jumping to `corrupt`
+/// if it is not there
+fn check_canary(corrupt: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+    let mismatch = JumpConditional { condition: Condition::NonZero, label: corrupt.to_string(), near: true };
+    vec![
+        LoadMemory { reg: A, addr: at_hl.clone() },
+        Compare { reg: A, value: Some((CANARY >> 8) as u8) },
+        mismatch.clone(),
+        Decrement { reg: HL },
+        LoadMemory { reg: A, addr: at_hl },
+        Compare { reg: A, value: Some(CANARY as u8) },
+        mismatch,
+    ]
+}
+
+/// From the first word of a raw block at HL, point HL at the last byte of
+/// the block. BC is clobbered.
+fn block_end() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+    vec![
+        Decrement { reg: HL },
+        LoadMemory { reg: B, addr: at_hl.clone() },
+        Decrement { reg: HL },
+        LoadMemory { reg: C, addr: at_hl },
+        // Clear the in-use bit
+        Decrement { reg: C },
+        Add { dst: HL, src: BC },
+        Decrement { reg: HL },
+    ]
+}
+
+/// Generate the debug heap's allocation and free routines, each with its
+/// public name, and the allocator they wrap. A corrupt or unknown instance
+/// is reported through [`runtime_errors::HEAP_ERROR_ROUTINE`].
+pub fn generate_debug_heap_routines() -> Vec<(String, Vec<Z80Instruction>)> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let label = |routine: &str, suffix: &str| format!("{}_{}", routine, suffix);
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+    let is_zero = [LoadRegister { dst: A, src: H }, Or { reg: L }];
+
+    let find = vec![
+        Label { name: FIND_ROUTINE.to_string() },
+        LoadAddress { reg: HL, label: TABLE_SYMBOL.to_string() },
+        LoadImmediate { reg: B, value: TRACKED_BLOCKS },
+        Label { name: label(FIND_ROUTINE, "next") },
+        LoadMemory { reg: A, addr: at_hl.clone() },
+        Compare { reg: E, value: None },
+        JumpConditional { condition: Condition::NonZero, label: label(FIND_ROUTINE, "skip"), near: true },
+        Increment { reg: HL },
+        LoadMemory { reg: A, addr: at_hl.clone() },
+        Decrement { reg: HL },
+        Compare { reg: D, value: None },
+        JumpConditional { condition: Condition::Zero, label: label(FIND_ROUTINE, "done"), near: true },
+        Label { name: label(FIND_ROUTINE, "skip") },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        Decrement { reg: B },
+        JumpConditional { condition: Condition::NonZero, label: label(FIND_ROUTINE, "next"), near: true },
+        LoadImmediate { reg: HL, value: 0 },
+        Label { name: label(FIND_ROUTINE, "done") },
+        Return,
+    ];
+
+    let full = label(NEW_ROUTINE, "full");
+    let mut new = vec![
+        Label { name: NEW_ROUTINE.to_string() },
+        Push { reg: BC },
+        Push { reg: HL },
+        // Take a free table slot first
+        LoadImmediate { reg: DE, value: 0 },
+        Call { label: FIND_ROUTINE.to_string() },
+    ];
+    new.extend(is_zero.clone());
+    new.extend([
+        JumpConditional { condition: Condition::Zero, label: full.clone(), near: true },
+        ExchangeDeHl,
+        Pop { reg: HL },
+        Push { reg: DE },
+        // Room for the canaries; the allocator installs the first
+        Increment { reg: HL },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        LoadImmediate { reg: BC, value: CANARY },
+        Call { label: RAW_NEW_ROUTINE.to_string() },
+        Pop { reg: DE },
+    ]);
+    new.extend(is_zero.clone());
+    new.extend([
+        JumpConditional { condition: Condition::Zero, label: label(NEW_ROUTINE, "none"), near: true },
+        // Record the instance in the slot
+        Push { reg: HL },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        ExchangeDeHl,
+        StoreMemory { addr: at_hl.clone(), reg: E },
+        Increment { reg: HL },
+        StoreMemory { addr: at_hl.clone(), reg: D },
+        Pop { reg: HL },
+        // The second canary ends the block, which may be larger than asked
+        Push { reg: HL },
+    ]);
+    new.extend(block_end());
+    new.extend(store_canary());
+    new.extend([
+        Pop { reg: HL },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        // Install the VMT pointer
+        Pop { reg: DE },
+        StoreMemory { addr: at_hl.clone(), reg: E },
+        Increment { reg: HL },
+        StoreMemory { addr: at_hl.clone(), reg: D },
+        Decrement { reg: HL },
+        Return,
+        Label { name: full },
+        Pop { reg: HL },
+        Label { name: label(NEW_ROUTINE, "none") },
+        Pop { reg: BC },
+        LoadImmediate { reg: HL, value: 0 },
+        Return,
+    ]);
+
+    let done = label(DISPOSE_ROUTINE, "done");
+    let corrupt = label(DISPOSE_ROUTINE, "corrupt");
+    let mut dispose = vec![Label { name: DISPOSE_ROUTINE.to_string() }];
+    dispose.extend(is_zero.clone());
+    dispose.extend([
+        JumpConditional { condition: Condition::Zero, label: done.clone(), near: true },
+        ExchangeDeHl,
+        Push { reg: DE },
+        Call { label: FIND_ROUTINE.to_string() },
+        Pop { reg: DE },
+    ]);
+    dispose.extend(is_zero);
+    dispose.extend([
+        // Freed already, or never allocated
+        JumpConditional { condition: Condition::Zero, label: runtime_errors::HEAP_ERROR_ROUTINE.to_string(), near: false },
+        LoadImmediate { reg: A, value: 0 },
+        StoreMemory { addr: at_hl.clone(), reg: A },
+        Increment { reg: HL },
+        StoreMemory { addr: at_hl, reg: A },
+        ExchangeDeHl,
+        Decrement { reg: HL },
+    ]);
+    dispose.extend(check_canary(&corrupt));
+    dispose.push(Push { reg: HL });
+    dispose.extend(block_end());
+    dispose.extend(check_canary(&label(DISPOSE_ROUTINE, "overrun")));
+    dispose.extend([
+        Pop { reg: HL },
+        Jump { label: RAW_DISPOSE_ROUTINE.to_string(), near: false },
+        Label { name: label(DISPOSE_ROUTINE, "overrun") },
+        Pop { reg: HL },
+        Label { name: corrupt },
+        Jump { label: runtime_errors::HEAP_ERROR_ROUTINE.to_string(), near: false },
+        Label { name: done },
+        Return,
+    ]);
+
+    let (raw_new, raw_dispose) = allocator(RAW_NEW_ROUTINE, RAW_DISPOSE_ROUTINE);
+    vec![
+        (NEW_ROUTINE.to_string(), new),
+        (DISPOSE_ROUTINE.to_string(), dispose),
+        (FIND_ROUTINE.to_string(), find),
+        (RAW_NEW_ROUTINE.to_string(), raw_new),
+        (RAW_DISPOSE_ROUTINE.to_string(), raw_dispose),
+    ]
+}
+
+/// Generate `ReportLeaks`, printing `Leaked instances: NNN` to `console`
+/// if any instance of the debug heap is still allocated. Without a
+/// console, as without the debug heap, it does nothing.
+pub fn generate_report_leaks_routine(console: Option<Console>) -> (String, Vec<Z80Instruction>) {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let name = REPORT_LEAKS_ROUTINE.to_string();
+    let Some(console) = console else {
+        return (name.clone(), vec![Label { name }, Return]);
+    };
+    let label = |suffix: &str| format!("{}_{}", REPORT_LEAKS_ROUTINE, suffix);
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+    let mut code = vec![
+        Label { name: name.clone() },
+        LoadAddress { reg: HL, label: TABLE_SYMBOL.to_string() },
+        LoadImmediate { reg: B, value: TRACKED_BLOCKS },
+        LoadImmediate { reg: C, value: 0 },
+        // C := slots in use
+        Label { name: label("next") },
+        LoadMemory { reg: A, addr: at_hl.clone() },
+        Increment { reg: HL },
+        LoadMemory { reg: E, addr: at_hl },
+        Increment { reg: HL },
+        Or { reg: E },
+        JumpConditional { condition: Condition::Zero, label: label("free"), near: true },
+        Increment { reg: C },
+        Label { name: label("free") },
+        Decrement { reg: B },
+        JumpConditional { condition: Condition::NonZero, label: label("next"), near: true },
+        LoadRegister { dst: A, src: C },
+        Or { reg: A },
+        JumpConditional { condition: Condition::Zero, label: label("done"), near: true },
+    ];
+    code.extend(runtime_errors::print_number(console, REPORT_LEAKS_ROUTINE));
+    code.extend([Label { name: label("done") }, Return]);
+    code.extend(runtime_errors::message_routines(console, REPORT_LEAKS_ROUTINE, b"Leaked instances: "));
+    (name, code)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const HEAP: u16 = 0x8000;
+
+    /// Machine running the heap routines: registers by name, flags Z and C
+    pub(crate) struct Machine {
+        pub(crate) memory: Vec<u8>,
+        regs: HashMap<Z80Register, u16>,
+        pub(crate) zero: bool,
+        carry: bool,
+        pub(crate) sp: u16,
+        /// Addresses of data symbols besides the heap's; code labels are
+        /// their instruction's index
+        pub(crate) symbols: HashMap<String, u16>,
+        /// Routine outside the code run that it jumped to, ending the run
+        pub(crate) exit: Option<String>,
+    }
+
+    impl Machine {
+        pub(crate) fn new() -> Self {
+            Machine {
+                memory: vec![0; 0x10000],
+                regs: HashMap::new(),
+                zero: false,
+                carry: false,
+                sp: 0xFFFE,
+                symbols: HashMap::new(),
+                exit: None,
+            }
+        }
+
+        pub(crate) fn get(&self, reg: Z80Register) -> u16 {
+            use Z80Register::*;
+            let pair = |hi, lo| (self.get(hi) << 8) | self.get(lo);
+            match reg {
+                AF => (self.get(A) << 8) | (self.zero as u16) << 6 | self.carry as u16,
+                BC => pair(B, C),
+                DE => pair(D, E),
+                HL => pair(H, L),
+                SP => self.sp,
+                _ => self.regs.get(&reg).copied().unwrap_or(0),
+            }
+        }
+
+        pub(crate) fn set(&mut self, reg: Z80Register, value: u16) {
+            use Z80Register::*;
+            let mut split = |hi, lo| {
+                self.regs.insert(hi, value >> 8);
+                self.regs.insert(lo, value & 0xFF);
+            };
+            match reg {
+                AF => {
+                    self.regs.insert(A, value >> 8);
+                    (self.zero, self.carry) = (value & 0x40 != 0, value & 1 != 0);
+                }
+                BC => split(B, C),
+                DE => split(D, E),
+                HL => split(H, L),
+                SP => self.sp = value,
+                IX | IY => {
+                    self.regs.insert(reg, value);
+                }
+                _ => {
+                    self.regs.insert(reg, value & 0xFF);
+                }
+            }
+        }
+
+        pub(crate) fn read_word(&self, address: u16) -> u16 {
+            self.memory[address as usize] as u16 | (self.memory[address as usize + 1] as u16) << 8
+        }
+
+        pub(crate) fn write_word(&mut self, address: u16, value: u16) {
+            self.memory[address as usize] = value as u8;
+            self.memory[address as usize + 1] = (value >> 8) as u8;
+        }
+
+        fn push(&mut self, value: u16) {
+            self.sp -= 2;
+            self.write_word(self.sp, value);
+        }
+
+        fn pop(&mut self) -> u16 {
+            let value = self.read_word(self.sp);
+            self.sp += 2;
+            value
+        }
+
+        /// Run `code` to its return, with the heap symbols at HEAP and `end`
+        pub(crate) fn run(&mut self, code: &[Z80Instruction], end: u16) {
+            self.run_from(code, 0, end);
+        }
+
+        /// Run `code` from the label `entry` to its return or a jump out of it
+        pub(crate) fn call(&mut self, code: &[Z80Instruction], entry: &str, end: u16) {
+            let start = code.iter().position(|inst| *inst == Z80Instruction::Label { name: entry.to_string() });
+            self.run_from(code, start.expect("entry"), end);
+        }
+
+        fn run_from(&mut self, code: &[Z80Instruction], start: usize, end: u16) {
+            use Z80Instruction::*;
+            let is_pair = |reg: &Z80Register| matches!(reg, Z80Register::BC | Z80Register::DE | Z80Register::HL);
+            let labels: HashMap<&str, usize> = code
+                .iter()
+                .enumerate()
+                .filter_map(|(i, inst)| match inst {
+                    Label { name } => Some((name.as_str(), i)),
+                    _ => None,
+                })
+                .collect();
+            let symbols = self.symbols.clone();
+            let symbol = |label: &str| match label {
+                HEAP_SYMBOL => HEAP,
+                HEAP_END_SYMBOL => end,
+                _ => labels.get(label).map(|&i| i as u16).or_else(|| symbols.get(label).copied()).unwrap_or(0x4000),
+            };
+            let base = self.sp;
+            self.exit = None;
+            let mut pc = start;
+            for _ in 0..100_000 {
+                let mut next = pc + 1;
+                match &code[pc] {
+                    Label { .. } => {}
+                    LoadImmediate { reg, value } => self.set(*reg, *value),
+                    LoadAddress { reg, label } => self.set(*reg, symbol(label)),
+                    LoadRegister { dst, src } => self.set(*dst, self.get(*src)),
+                    LoadMemory { reg, addr: MemoryAddress::RegisterIndirect(pair) } => {
+                        self.set(*reg, self.memory[self.get(*pair) as usize] as u16)
+                    }
+                    StoreMemory { addr: MemoryAddress::RegisterIndirect(pair), reg } => {
+                        let (address, value) = (self.get(*pair), self.get(*reg));
+                        self.memory[address as usize] = value as u8;
+                    }
+                    LoadMemory { reg, addr: MemoryAddress::Symbol(label) } if is_pair(reg) => {
+                        self.set(*reg, self.read_word(symbol(label)))
+                    }
+                    LoadMemory { reg, addr: MemoryAddress::Symbol(label) } => {
+                        self.set(*reg, self.memory[symbol(label) as usize] as u16)
+                    }
+                    StoreMemory { addr: MemoryAddress::Symbol(label), reg } if is_pair(reg) => {
+                        self.write_word(symbol(label), self.get(*reg))
+                    }
+                    StoreMemory { addr: MemoryAddress::Symbol(label), reg } => {
+                        self.memory[symbol(label) as usize] = self.get(*reg) as u8
+                    }
+                    Increment { reg } if is_pair(reg) => self.set(*reg, self.get(*reg).wrapping_add(1)),
+                    Decrement { reg } if is_pair(reg) => self.set(*reg, self.get(*reg).wrapping_sub(1)),
+                    Increment { reg } => {
+                        self.set(*reg, (self.get(*reg) + 1) & 0xFF);
+                        self.zero = self.get(*reg) == 0;
+                    }
+                    Decrement { reg } => {
+                        self.set(*reg, self.get(*reg).wrapping_sub(1) & 0xFF);
+                        self.zero = self.get(*reg) == 0;
+                    }
+                    Or { reg } => {
+                        let a = self.get(Z80Register::A) | self.get(*reg);
+                        self.set(Z80Register::A, a);
+                        (self.zero, self.carry) = (a == 0, false);
+                    }
+                    BitTest { bit, reg } => self.zero = self.get(*reg) & (1 << bit) == 0,
+                    Compare { reg, value } => {
+                        let (a, operand) = (self.get(Z80Register::A), value.map_or(self.get(*reg), u16::from));
+                        (self.zero, self.carry) = (a == operand, a < operand);
+                    }
+                    Subtract { dst: Z80Register::HL, src } => {
+                        let (hl, src) = (self.get(Z80Register::HL) as i32, self.get(*src) as i32 + self.carry as i32);
+                        self.set(Z80Register::HL, (hl - src) as u16);
+                        (self.zero, self.carry) = (hl == src, hl < src);
+                    }
+                    Add { dst: Z80Register::HL, src } => {
+                        let sum = self.get(Z80Register::HL) as u32 + self.get(*src) as u32;
+                        self.set(Z80Register::HL, sum as u16);
+                        self.carry = sum > 0xFFFF;
+                    }
+                    ExchangeDeHl => {
+                        let (de, hl) = (self.get(Z80Register::DE), self.get(Z80Register::HL));
+                        self.set(Z80Register::DE, hl);
+                        self.set(Z80Register::HL, de);
+                    }
+                    Push { reg } => self.push(self.get(*reg)),
+                    Pop { reg } => {
+                        let value = self.pop();
+                        self.set(*reg, value);
+                    }
+                    Ldir => loop {
+                        let (hl, de, bc) = (self.get(Z80Register::HL), self.get(Z80Register::DE), self.get(Z80Register::BC));
+                        self.memory[de as usize] = self.memory[hl as usize];
+                        self.set(Z80Register::HL, hl + 1);
+                        self.set(Z80Register::DE, de + 1);
+                        self.set(Z80Register::BC, bc - 1);
+                        if bc == 1 {
+                            break;
+                        }
+                    },
+                    Jump { label, .. } | JumpConditional { label, .. } | Call { label }
+                        if !labels.contains_key(label.as_str()) =>
+                    {
+                        let taken = match &code[pc] {
+                            JumpConditional { condition: Condition::Zero, .. } => self.zero,
+                            JumpConditional { condition: Condition::NonZero, .. } => !self.zero,
+                            JumpConditional { .. } => panic!("unexpected condition"),
+                            _ => true,
+                        };
+                        if taken {
+                            self.exit = Some(label.clone());
+                            return;
+                        }
+                    }
+                    Call { label } => {
+                        self.push(next as u16);
+                        next = labels[label.as_str()];
+                    }
+                    Jump { label, .. } => next = labels[label.as_str()],
+                    JumpConditional { condition, label, .. } => {
+                        let taken = match condition {
+                            Condition::Zero => self.zero,
+                            Condition::NonZero => !self.zero,
+                            Condition::Carry => self.carry,
+                            Condition::NoCarry => !self.carry,
+                            other => panic!("unexpected condition {:?}", other),
+                        };
+                        if taken {
+                            next = labels[label.as_str()];
+                        }
+                    }
+                    Return if self.sp == base => return,
+                    Return => next = self.pop() as usize,
+                    other => panic!("unexpected instruction {}", other),
+                }
+                pc = next;
+            }
+            panic!("no return");
+        }
+
+        fn new_instance(&mut self, size: u16, vmt: u16, end: u16) -> u16 {
+            let routines = generate_heap_routines();
+            self.set(Z80Register::BC, vmt);
+            self.set(Z80Register::HL, block_size(size));
+            self.run(&routines[0].1, end);
+            assert_eq!(self.sp, 0xFFFE, "stack unbalanced");
+            self.get(Z80Register::HL)
+        }
+
+        fn dispose(&mut self, instance: u16) {
+            let routines = generate_heap_routines();
+            self.set(Z80Register::HL, instance);
+            self.run(&routines[1].1, HEAP + 64);
+        }
+    }
+
+    #[test]
+    fn test_block_sizes_are_even_and_hold_the_header() {
+        assert_eq!(block_size(2), 4);
+        assert_eq!(block_size(5), 8);
+        assert_eq!(block_size(6), 8);
+        assert_eq!(block_size(0), 4);
+    }
+
+    #[test]
+    fn test_instances_are_zeroed_reused_and_bounded() {
+        let end = HEAP + 32;
+        let mut machine = Machine::new();
+        let a = machine.new_instance(6, 0x1234, end);
+        let b = machine.new_instance(4, 0, end);
+        assert_eq!((a, b), (HEAP + 2, HEAP + 10));
+        // The VMT pointer is installed, the fields zeroed
+        assert_eq!(&machine.memory[a as usize..a as usize + 6], [0x34, 0x12, 0, 0, 0, 0]);
+        machine.memory[a as usize + 4] = 0x55;
+
+        // A freed block is taken again, whole and zeroed, by a request it holds
+        machine.dispose(a);
+        assert_eq!(machine.new_instance(4, 0x4321, end), a);
+        assert_eq!(&machine.memory[a as usize..a as usize + 6], [0x21, 0x43, 0, 0, 0, 0]);
+
+        // Blocks of 8 and 6 bytes are used; 12 more and the last header fit
+        assert_eq!(machine.new_instance(10, 0, end), HEAP + 16);
+        assert_eq!(machine.new_instance(4, 0, end), 0);
+        machine.dispose(0);
+    }
+
+    const TABLE: usize = 0x4000;
+
+    fn debug_heap() -> Vec<Z80Instruction> {
+        generate_debug_heap_routines().into_iter().flat_map(|(_, code)| code).collect()
+    }
+
+    fn debug_new(machine: &mut Machine, size: u16, vmt: u16) -> u16 {
+        machine.set(Z80Register::BC, vmt);
+        machine.set(Z80Register::HL, block_size(size));
+        machine.call(&debug_heap(), NEW_ROUTINE, HEAP + 64);
+        assert_eq!(machine.sp, 0xFFFE, "stack unbalanced");
+        machine.get(Z80Register::HL)
+    }
+
+    /// Free `instance` on the debug heap, returning the routine it reported
+    /// an error through, if any
+    fn debug_dispose(machine: &mut Machine, instance: u16) -> Option<String> {
+        machine.set(Z80Register::HL, instance);
+        machine.call(&debug_heap(), DISPOSE_ROUTINE, HEAP + 64);
+        machine.exit.take()
+    }
+
+    #[test]
+    fn test_debug_heap_guards_and_tracks_instances() {
+        let mut machine = Machine::new();
+        let a = debug_new(&mut machine, 4, 0x1234);
+        let b = debug_new(&mut machine, 2, 0);
+        // Header, canary, instance and canary
+        assert_eq!((a, b), (HEAP + 4, HEAP + 14));
+        assert_eq!(&machine.memory[HEAP as usize..HEAP as usize + 10], [11, 0, 0xDE, 0xC0, 0x34, 0x12, 0, 0, 0xDE, 0xC0]);
+        assert_eq!(&machine.memory[TABLE..TABLE + 6], [0x04, 0x80, 0x0E, 0x80, 0, 0]);
+
+        // Freeing clears the slot and the block
+        assert_eq!(debug_dispose(&mut machine, a), None);
+        assert_eq!(&machine.memory[TABLE..TABLE + 2], [0, 0]);
+        assert_eq!(machine.memory[HEAP as usize], 10);
+        assert_eq!(debug_dispose(&mut machine, 0), None);
+
+        // Freeing twice, or a pointer never allocated, is an error
+        let heap_error = Some(runtime_errors::HEAP_ERROR_ROUTINE.to_string());
+        assert_eq!(debug_dispose(&mut machine, a), heap_error);
+        assert_eq!(debug_dispose(&mut machine, b + 2), heap_error);
+
+        // So is freeing an instance written past its end
+        machine.memory[b as usize + 2] = 0;
+        assert_eq!(debug_dispose(&mut machine, b), heap_error);
+        assert_eq!(machine.sp, 0xFFFE, "stack unbalanced");
+    }
+
+    #[test]
+    fn test_debug_heap_fails_when_the_table_is_full() {
+        let mut machine = Machine::new();
+        for slot in 0..TRACKED_BLOCKS as usize {
+            machine.memory[TABLE + 2 * slot] = 1;
+        }
+        assert_eq!(debug_new(&mut machine, 4, 0), 0);
+        assert_eq!(machine.memory[HEAP as usize], 0);
+
+        let (name, code) = generate_report_leaks_routine(None);
+        assert_eq!(name, REPORT_LEAKS_ROUTINE);
+        assert_eq!(code, [Z80Instruction::Label { name }, Z80Instruction::Return]);
+    }
+}
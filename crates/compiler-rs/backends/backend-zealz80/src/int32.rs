@@ -0,0 +1,110 @@
+//! 32-bit integers (`longint`, `cardinal`)
+//!
+//! A 32-bit value lives in DE:HL (DE holds the high word), or in memory low
+//! word first. Operations take the right operand from the stack and the left
+//! one in DE:HL, the convention of the soft-float routines (see
+//! [`crate::float`]):
+//! - **Addition, subtraction**: inline, a 16-bit add or subtract of each
+//!   half with the carry passed between them
+//! - **Multiplication, division, comparison**: runtime routines, which remove
+//!   the right operand; comparisons return flags the way `cp` does (Z when
+//!   equal, C when the left operand is below the right)
+
+use ir::Opcode;
+use types::ComparisonKind;
+
+use crate::{Condition, Z80Instruction, Z80Register};
+
+pub const LMUL_ROUTINE: &str = "__lmul";
+/// Division and remainder, signed and unsigned
+pub const LDIV_ROUTINE: &str = "__ldiv";
+pub const LDIVU_ROUTINE: &str = "__ldivu";
+pub const LMOD_ROUTINE: &str = "__lmod";
+pub const LMODU_ROUTINE: &str = "__lmodu";
+/// Comparisons, one per [`ComparisonKind`]
+pub const LCMP_ROUTINE: &str = "__lcmp";
+pub const LCMPU_ROUTINE: &str = "__lcmpu";
+pub const LCMPSU_ROUTINE: &str = "__lcmpsu";
+pub const LCMPUS_ROUTINE: &str = "__lcmpus";
+
+/// Runtime routine implementing `opcode`, or None if it is generated inline
+/// (or is not a 32-bit operation)
+pub fn routine(opcode: &Opcode) -> Option<&'static str> {
+    Some(match opcode {
+        Opcode::LMul => LMUL_ROUTINE,
+        Opcode::LDiv => LDIV_ROUTINE,
+        Opcode::LDivU => LDIVU_ROUTINE,
+        Opcode::LMod => LMOD_ROUTINE,
+        Opcode::LModU => LMODU_ROUTINE,
+        _ => return None,
+    })
+}
+
+/// Runtime routine comparing two 32-bit values promoted by `kind`
+pub fn compare_routine(kind: ComparisonKind) -> &'static str {
+    match kind {
+        ComparisonKind::Signed => LCMP_ROUTINE,
+        ComparisonKind::Unsigned => LCMPU_ROUTINE,
+        ComparisonKind::SignedUnsigned => LCMPSU_ROUTINE,
+        ComparisonKind::UnsignedSigned => LCMPUS_ROUTINE,
+    }
+}
+
+/// DE:HL += the value on the stack, which is removed. BC is clobbered.
+pub fn add_from_stack() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        Pop { reg: BC },
+        Add { dst: HL, src: BC },
+        ExchangeDeHl,
+        Pop { reg: BC },
+        AddWithCarry { dst: HL, src: BC },
+        ExchangeDeHl,
+    ]
+}
+
+/// DE:HL -= the value on the stack, which is removed. BC is clobbered.
+pub fn subtract_from_stack() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        Pop { reg: BC },
+        // `sbc` is the only 16-bit subtract; `or a` clears the carry first
+        Or { reg: A },
+        Subtract { dst: HL, src: BC },
+        ExchangeDeHl,
+        Pop { reg: BC },
+        Subtract { dst: HL, src: BC },
+        ExchangeDeHl,
+    ]
+}
+
+/// Sign-extend HL into DE. `label` names the local label the sequence
+/// needs.
+pub fn sign_extend(label: &str) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: 0 },
+        Z80Instruction::BitTest { bit: 7, reg: Z80Register::H },
+        Z80Instruction::JumpConditional { condition: Condition::Zero, label: label.to_string(), near: true },
+        Z80Instruction::Decrement { reg: Z80Register::DE },
+        Z80Instruction::Label { name: label.to_string() },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_add_and_subtract_carry_into_high_word() {
+        let add = add_from_stack();
+        assert_eq!(add[1], Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::BC });
+        assert_eq!(add[4], Z80Instruction::AddWithCarry { dst: Z80Register::HL, src: Z80Register::BC });
+        let sub = subtract_from_stack();
+        assert_eq!(sub.iter().filter(|i| matches!(i, Z80Instruction::Subtract { .. })).count(), 2);
+        assert_eq!(routine(&Opcode::LAdd), None);
+        assert_eq!(routine(&Opcode::LDivU), Some(LDIVU_ROUTINE));
+        assert_eq!(compare_routine(ComparisonKind::UnsignedSigned), LCMPUS_ROUTINE);
+    }
+}
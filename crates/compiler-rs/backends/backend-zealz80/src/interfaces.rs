@@ -0,0 +1,214 @@
+//! Interface queries and interface method calls
+//!
+//! An interface is identified at run time by the address of its one-byte
+//! descriptor, [`types::interface_id_symbol`]. The header word of a VMT
+//! points to the interface table of the class, or is 0 when it implements
+//! none. The table pairs each interface implemented with the class's IMT
+//! for it, and ends with a zero word:
+//!
+//! ```text
+//!     __vmt_TSquare:      dw __intf_TSquare
+//!     __intf_TSquare:     dw __iid_IShape, __imt_TSquare_IShape
+//!                         dw 0
+//!     __imt_TSquare_IShape:
+//!                         dw _TSquare__Draw, _TSquare__Area
+//! ```
+//!
+//! [`QUERY_ROUTINE`] finds the IMT of an interface for an instance, which
+//! `is` and `as` test and interface calls index like a VMT.
+
+use types::ClassLayout;
+
+use crate::abi::{self, SLOT_SIZE};
+use crate::classes::{self, CALL_HL_ROUTINE};
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Find the IMT of the interface whose descriptor is DE for the instance in
+/// HL. Returns it in HL, or 0 with Z set when HL is nil or its class does
+/// not implement the interface. A and BC are clobbered.
+pub const QUERY_ROUTINE: &str = "__intf_query";
+
+/// Query the instance in HL for the interface `iid`. DE, A and BC are
+/// clobbered.
+pub fn query(iid: &str) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadAddress { reg: Z80Register::DE, label: iid.to_string() },
+        Z80Instruction::Call { label: QUERY_ROUTINE.to_string() },
+    ]
+}
+
+/// `is`: after [`query`], HL := 1 if the interface was found, else 0
+pub fn found(skip: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    vec![
+        LoadImmediate { reg: Z80Register::HL, value: 0 },
+        JumpConditional { condition: Condition::Zero, label: skip.to_string(), near: true },
+        Increment { reg: Z80Register::HL },
+        Label { name: skip.to_string() },
+    ]
+}
+
+/// `as`: query the instance in HL for the interface `iid`, leaving it in HL
+/// if found, else 0
+pub fn cast(iid: &str, skip: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    let mut code = vec![Push { reg: Z80Register::HL }];
+    code.extend(query(iid));
+    code.extend([
+        Pop { reg: Z80Register::HL },
+        JumpConditional { condition: Condition::NonZero, label: skip.to_string(), near: true },
+        LoadImmediate { reg: Z80Register::HL, value: 0 },
+        Label { name: skip.to_string() },
+    ]);
+    code
+}
+
+/// Call IMT slot `slot` of the interface `iid` of the object pushed below
+/// `arg_count` arguments. All registers are clobbered.
+pub fn interface_call(iid: &str, slot: usize, arg_count: usize) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut code = vec![LoadImmediate { reg: HL, value: arg_count as u16 * SLOT_SIZE }, Add { dst: HL, src: SP }];
+    code.extend(classes::load_word_at_hl());
+    code.extend(query(iid));
+    if slot > 0 {
+        code.push(LoadImmediate { reg: DE, value: slot as u16 * SLOT_SIZE });
+        code.push(Add { dst: HL, src: DE });
+    }
+    code.extend(classes::load_word_at_hl());
+    code.push(Call { label: CALL_HL_ROUTINE.to_string() });
+    code
+}
+
+/// The interface table of `layout` and its IMTs, labelled
+pub fn interface_tables(layout: &ClassLayout) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    let mut tables = vec![Label { name: types::interface_table_symbol(&layout.name) }];
+    for (interface, _) in &layout.interfaces {
+        tables.push(DefineWord { label: types::interface_id_symbol(interface) });
+        tables.push(DefineWord { label: types::imt_symbol(&layout.name, interface) });
+    }
+    tables.push(DefineWord { label: "0".to_string() });
+    for (interface, imt) in &layout.interfaces {
+        tables.push(Label { name: types::imt_symbol(&layout.name, interface) });
+        tables.extend(imt.iter().map(|entry| DefineWord { label: abi::routine_symbol(entry) }));
+    }
+    tables
+}
+
+/// Generate the interface query routine, with its public name
+pub fn generate_interface_routines() -> Vec<(String, Vec<Z80Instruction>)> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let label = |suffix: &str| format!("{}_{}", QUERY_ROUTINE, suffix);
+    let jump = |condition, suffix: &str| JumpConditional { condition, label: label(suffix), near: true };
+    let at_hl = MemoryAddress::RegisterIndirect(HL);
+    // BC := the word at HL, leaving HL on its high byte
+    let load_bc = || [LoadMemory { reg: C, addr: at_hl.clone() }, Increment { reg: HL }, LoadMemory { reg: B, addr: at_hl.clone() }];
+    let bc_is_zero = || [LoadRegister { dst: A, src: B }, Or { reg: C }];
+
+    let mut code = vec![
+        Label { name: QUERY_ROUTINE.to_string() },
+        LoadRegister { dst: A, src: H },
+        Or { reg: L },
+        jump(Condition::Zero, "none"),
+    ];
+    // The VMT, then its header: the interface table
+    for _ in 0..2 {
+        code.extend(load_bc());
+        code.extend(bc_is_zero());
+        code.push(jump(Condition::Zero, "none"));
+        code.extend([LoadRegister { dst: H, src: B }, LoadRegister { dst: L, src: C }]);
+    }
+    code.push(Label { name: label("next") });
+    code.extend(load_bc());
+    code.push(Increment { reg: HL });
+    code.extend(bc_is_zero());
+    code.push(jump(Condition::Zero, "none"));
+    code.extend([
+        // Z when BC = DE; HL is kept on the IMT word
+        Push { reg: HL },
+        LoadRegister { dst: H, src: B },
+        LoadRegister { dst: L, src: C },
+        Or { reg: A },
+        Subtract { dst: HL, src: DE },
+        Pop { reg: HL },
+        jump(Condition::Zero, "found"),
+        Increment { reg: HL },
+        Increment { reg: HL },
+        Jump { label: label("next"), near: true },
+        Label { name: label("found") },
+    ]);
+    code.extend(load_bc());
+    code.extend([
+        LoadRegister { dst: H, src: B },
+        LoadRegister { dst: L, src: C },
+        // NZ: an IMT is never at address 0
+        LoadRegister { dst: A, src: H },
+        Or { reg: L },
+        Return,
+        // Reached with Z set
+        Label { name: label("none") },
+        LoadImmediate { reg: HL, value: 0 },
+        Return,
+    ]);
+    vec![(QUERY_ROUTINE.to_string(), code)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::tests::Machine;
+
+    #[test]
+    fn test_interface_tables_pair_descriptors_with_imts() {
+        let mut layout = ClassLayout::new("TSquare", None, vec![]);
+        layout.interfaces = vec![("IShape".to_string(), vec!["TSquare__Draw".to_string(), "TSquare__Area".to_string()])];
+        let text: Vec<String> = interface_tables(&layout).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "__intf_TSquare:",
+                "    dw __iid_IShape",
+                "    dw __imt_TSquare_IShape",
+                "    dw 0",
+                "__imt_TSquare_IShape:",
+                "    dw _TSquare__Draw",
+                "    dw _TSquare__Area",
+            ]
+        );
+        let text: Vec<String> = classes::vmt_table(&layout).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["__vmt_TSquare:", "    dw __intf_TSquare"]);
+    }
+
+    #[test]
+    fn test_query_finds_the_imt_of_an_implemented_interface() {
+        // Instance at 0x9000 -> VMT at 0x9100 -> table at 0x9200 of two
+        // interfaces, descriptors 0x9300 and 0x9301
+        let mut machine = Machine::new();
+        let mut store = |address: usize, words: &[u16]| {
+            for (i, word) in words.iter().enumerate() {
+                machine.memory[address + 2 * i..address + 2 * i + 2].copy_from_slice(&word.to_le_bytes());
+            }
+        };
+        store(0x9000, &[0x9100]);
+        store(0x9100, &[0x9200]);
+        store(0x9200, &[0x9300, 0xA000, 0x9301, 0xA010, 0]);
+        // An instance without a VMT
+        store(0x9400, &[0]);
+
+        let routine = &generate_interface_routines()[0].1;
+        let mut query = |instance: u16, iid: u16| {
+            machine.set(Z80Register::HL, instance);
+            machine.set(Z80Register::DE, iid);
+            machine.run(routine, 0);
+            assert_eq!(machine.sp, 0xFFFE, "stack unbalanced");
+            (machine.get(Z80Register::HL), machine.zero)
+        };
+        assert_eq!(query(0x9000, 0x9300), (0xA000, false));
+        assert_eq!(query(0x9000, 0x9301), (0xA010, false));
+        assert_eq!(query(0x9000, 0x9302), (0, true));
+        assert_eq!(query(0x9400, 0x9300), (0, true));
+        assert_eq!(query(0, 0x9300), (0, true));
+    }
+}
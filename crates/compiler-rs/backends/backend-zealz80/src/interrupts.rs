@@ -0,0 +1,170 @@
+//! Interrupt mode setup (IM1/IM2)
+//!
+//! In IM2 the CPU jumps through the word at `I * 256 + bus`, where `bus` is
+//! whatever byte sits on the data bus. Without a vectoring peripheral that
+//! byte is unpredictable, so the table is 257 bytes of one value `V`: every
+//! read yields `$VVVV`, where a jump to the real service routine is placed.
+//!
+//! Both addresses are left to the linker. The table is 256-byte aligned so
+//! its page can be loaded into `I`, and the jump is aligned to 257, which
+//! places it at an address of the form `$VVVV`. Each table byte is a
+//! high-byte relocation against the jump.
+
+use crate::{MemoryAddress, Z80Instruction, Z80Register};
+
+/// Vector table symbol (data section)
+pub const IM2_TABLE_SYMBOL: &str = "__im2_table";
+/// Vector table size: 256 possible bus values plus the high byte of the last word
+pub const IM2_TABLE_SIZE: u16 = 257;
+/// Vector table alignment, so that `I` addresses its first byte
+pub const IM2_TABLE_ALIGNMENT: u16 = 256;
+/// Jump to the service routine, placed at `$VVVV`
+pub const IM2_VECTOR_SYMBOL: &str = "__im2_vector";
+/// Alignment placing the jump at an address whose two bytes are equal
+pub const IM2_VECTOR_ALIGNMENT: u16 = 257;
+/// Startup routine loading `I` and switching to IM2
+pub const IM2_INSTALL_SYMBOL: &str = "__im2_install";
+/// Intrinsic installing a Pascal procedure as the interrupt handler
+pub const SET_IM2_HANDLER_SYMBOL: &str = "SetIM2Handler";
+
+/// Label of the patched `call` inside the service routine
+const IM2_CALL_LABEL: &str = "__im2_call";
+
+/// Interrupt mode selected for the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterruptMode {
+    /// Mode 1: fixed `rst $38` handler owned by the ROM or OS
+    #[default]
+    Im1,
+    /// Mode 2: vector table generated by the compiler
+    Im2,
+}
+
+impl InterruptMode {
+    /// Command-line name of the mode
+    pub fn name(&self) -> &'static str {
+        match self {
+            InterruptMode::Im1 => "im1",
+            InterruptMode::Im2 => "im2",
+        }
+    }
+
+    /// Look up a mode by its command-line name (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        [InterruptMode::Im1, InterruptMode::Im2]
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Generate the IM2 support routines, each with its public name
+///
+/// The service routine saves all registers and calls the installed handler,
+/// so handlers are ordinary parameterless Pascal procedures.
+pub fn generate_im2_routines() -> Vec<(String, Vec<Z80Instruction>)> {
+    let saved = [
+        Z80Register::AF,
+        Z80Register::BC,
+        Z80Register::DE,
+        Z80Register::HL,
+        Z80Register::IX,
+        Z80Register::IY,
+    ];
+
+    let vector = vec![
+        Z80Instruction::Label { name: IM2_VECTOR_SYMBOL.to_string() },
+        Z80Instruction::Jump { label: "__im2_isr".to_string(), near: false },
+    ];
+
+    let mut isr = vec![Z80Instruction::Label { name: "__im2_isr".to_string() }];
+    isr.extend(saved.iter().map(|&reg| Z80Instruction::Push { reg }));
+    // Operand patched by SetIM2Handler
+    isr.push(Z80Instruction::Label { name: IM2_CALL_LABEL.to_string() });
+    isr.push(Z80Instruction::Call { label: "__im2_default".to_string() });
+    isr.extend(saved.iter().rev().map(|&reg| Z80Instruction::Pop { reg }));
+    isr.extend([
+        Z80Instruction::EnableInterrupts,
+        Z80Instruction::ReturnFromInterrupt,
+        Z80Instruction::Label { name: "__im2_default".to_string() },
+        Z80Instruction::Return,
+    ]);
+
+    let install = vec![
+        Z80Instruction::Label { name: IM2_INSTALL_SYMBOL.to_string() },
+        Z80Instruction::DisableInterrupts,
+        Z80Instruction::LoadAddress { reg: Z80Register::HL, label: IM2_TABLE_SYMBOL.to_string() },
+        Z80Instruction::LoadRegister { dst: Z80Register::A, src: Z80Register::H },
+        Z80Instruction::LoadInterruptVector,
+        Z80Instruction::SetInterruptMode { mode: 2 },
+        Z80Instruction::EnableInterrupts,
+        Z80Instruction::Return,
+    ];
+
+    // procedure SetIM2Handler(Handler: word)
+    let set_handler = vec![
+        Z80Instruction::Label { name: SET_IM2_HANDLER_SYMBOL.to_string() },
+        Z80Instruction::Pop { reg: Z80Register::BC },
+        Z80Instruction::Pop { reg: Z80Register::DE },
+        Z80Instruction::Push { reg: Z80Register::BC },
+        Z80Instruction::LoadAddress { reg: Z80Register::HL, label: IM2_CALL_LABEL.to_string() },
+        Z80Instruction::Increment { reg: Z80Register::HL },
+        Z80Instruction::DisableInterrupts,
+        Z80Instruction::StoreMemory {
+            addr: MemoryAddress::RegisterIndirect(Z80Register::HL),
+            reg: Z80Register::E,
+        },
+        Z80Instruction::Increment { reg: Z80Register::HL },
+        Z80Instruction::StoreMemory {
+            addr: MemoryAddress::RegisterIndirect(Z80Register::HL),
+            reg: Z80Register::D,
+        },
+        Z80Instruction::EnableInterrupts,
+        Z80Instruction::Return,
+    ];
+
+    vec![
+        (IM2_VECTOR_SYMBOL.to_string(), vector),
+        ("__im2_isr".to_string(), isr),
+        (IM2_INSTALL_SYMBOL.to_string(), install),
+        (SET_IM2_HANDLER_SYMBOL.to_string(), set_handler),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_mode_names() {
+        assert_eq!(InterruptMode::from_name("IM2"), Some(InterruptMode::Im2));
+        assert_eq!(InterruptMode::from_name("im1"), Some(InterruptMode::Im1));
+        assert_eq!(InterruptMode::from_name("im0"), None);
+    }
+
+    #[test]
+    fn test_im2_alignments() {
+        // A 257-aligned address always has equal high and low bytes
+        for k in 0..=255u16 {
+            let address = k * IM2_VECTOR_ALIGNMENT;
+            assert_eq!(address >> 8, address & 0xFF);
+        }
+        assert_eq!(IM2_TABLE_SIZE, IM2_TABLE_ALIGNMENT + 1);
+    }
+
+    #[test]
+    fn test_im2_routines() {
+        let routines = generate_im2_routines();
+        let names: Vec<&str> = routines.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec![IM2_VECTOR_SYMBOL, "__im2_isr", IM2_INSTALL_SYMBOL, SET_IM2_HANDLER_SYMBOL]);
+
+        let (_, install) = &routines[2];
+        assert!(install.contains(&Z80Instruction::LoadInterruptVector));
+        assert!(install.contains(&Z80Instruction::SetInterruptMode { mode: 2 }));
+
+        let (_, isr) = &routines[1];
+        let pushes = isr.iter().filter(|i| matches!(i, Z80Instruction::Push { .. })).count();
+        let pops = isr.iter().filter(|i| matches!(i, Z80Instruction::Pop { .. })).count();
+        assert_eq!(pushes, pops);
+        assert!(isr.contains(&Z80Instruction::ReturnFromInterrupt));
+    }
+}
@@ -0,0 +1,52 @@
+//! Intrinsics: routine calls expanded inline from registered templates
+//!
+//! Platform SDK crates register a template under a routine name; the code
+//! generator then emits the template wherever the program calls that
+//! routine, instead of a `call`. Names match case-insensitively, as Pascal
+//! identifiers do.
+
+use std::collections::HashMap;
+
+use ir::Instruction;
+
+use crate::Z80Instruction;
+
+/// What a call to an intrinsic expands to
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntrinsicTemplate {
+    /// IR generated in place of the call. Calls inside the template are
+    /// emitted as calls, never expanded again.
+    Ir(Vec<Instruction>),
+    /// Assembly emitted in place of the call
+    Assembly(Vec<Z80Instruction>),
+}
+
+/// Intrinsics known to a code generator
+#[derive(Debug, Clone, Default)]
+pub struct IntrinsicRegistry {
+    templates: HashMap<String, IntrinsicTemplate>,
+}
+
+impl IntrinsicRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expand calls to `name` from `template`, replacing any earlier template
+    pub fn register(&mut self, name: &str, template: IntrinsicTemplate) {
+        self.templates.insert(name.to_lowercase(), template);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&IntrinsicTemplate> {
+        self.templates.get(&name.to_lowercase())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Registered names (lowercase) and their templates, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &IntrinsicTemplate)> {
+        self.templates.iter().map(|(name, template)| (name.as_str(), template))
+    }
+}
@@ -6,7 +6,7 @@
 //! # Architecture
 //!
 //! - **Frame Pointer**: IX (callee-saved)
-//! - **Return Value**: HL (16-bit), L (8-bit)
+//! - **Return Value**: DE:HL (32-bit), HL (16-bit), L (8-bit)
 //! - **Scratch Registers**: AF, BC, DE, HL
 //! - **Stack**: Grows downward, Pascal convention (callee cleans)
 //!
@@ -14,9 +14,50 @@
 //!
 //! See `platforms/ZealZ80/ABI.md` for complete ABI specification.
 
-use ir::{BasicBlock, Function, Instruction, Opcode, Program, Value};
+pub mod abi;
+pub mod arith;
+pub mod assembler;
+pub mod blit;
+pub mod classes;
+pub mod compare;
+pub mod exceptions;
+pub mod files;
+pub mod float;
+pub mod fold;
+pub mod heap;
+pub mod int32;
+pub mod interfaces;
+pub mod interrupts;
+pub mod intrinsics;
+pub mod params;
+pub mod ports;
+pub mod regalloc;
+pub mod runtime_errors;
+pub mod schedule;
+pub mod sets;
+pub mod strings;
+pub mod switch;
+pub mod tasks;
+pub mod timer;
+
+use float::FloatOperands;
+use intrinsics::{IntrinsicRegistry, IntrinsicTemplate};
+use ir::{BasicBlock, Condition as IRCondition, Function, Instruction, Opcode, OutlineCosts, Program, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tokens::Span;
+use types::ComparisonKind;
 use std::fmt;
 
+/// Code-size versus speed tradeoff for generated routines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationGoal {
+    /// Prefer fast code (unrolled loops, PUSH-based writes)
+    #[default]
+    Speed,
+    /// Prefer small code (loops, block instructions)
+    Size,
+}
+
 /// Z80 register names
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Z80Register {
@@ -56,6 +97,8 @@ impl fmt::Display for Z80Register {
 pub enum Z80Instruction {
     /// Load register with immediate: `ld reg, value`
     LoadImmediate { reg: Z80Register, value: u16 },
+    /// Load register with the address of a label: `ld reg, label`
+    LoadAddress { reg: Z80Register, label: String },
     /// Load register from register: `ld dst, src`
     LoadRegister { dst: Z80Register, src: Z80Register },
     /// Load register from memory: `ld reg, (addr)` or `ld reg, (ix+offset)`
@@ -68,20 +111,76 @@ pub enum Z80Instruction {
     Pop { reg: Z80Register },
     /// Add: `add hl, reg` or `add a, reg`
     Add { dst: Z80Register, src: Z80Register },
+    /// Add with carry: `adc hl, reg`
+    AddWithCarry { dst: Z80Register, src: Z80Register },
     /// Subtract: `sub reg` or `sbc hl, reg`
     Subtract { dst: Z80Register, src: Z80Register },
     /// Compare: `cp value` or `cp reg`
     Compare { reg: Z80Register, value: Option<u8> },
+    /// Bitwise or with A: `or reg`
+    Or { reg: Z80Register },
+    /// Bitwise exclusive or with A: `xor value`
+    Xor { value: u8 },
+    /// Test a bit: `bit n, reg`
+    BitTest { bit: u8, reg: Z80Register },
+    /// Shift right one bit: `srl reg`, or `sra reg` keeping the sign
+    ShiftRight { reg: Z80Register, arithmetic: bool },
+    /// Rotate right through the carry: `rr reg`
+    RotateRight { reg: Z80Register },
+    /// Set the carry flag: `scf`
+    SetCarry,
+    /// Complement the carry flag: `ccf`
+    ComplementCarry,
     /// Unconditional jump: `jp label` or `jr label`
     Jump { label: String, near: bool },
     /// Conditional jump: `jp cc, label` or `jr cc, label`
     JumpConditional { condition: Condition, label: String, near: bool },
+    /// Jump to the address in HL: `jp (hl)`
+    JumpIndirect,
     /// Call function: `call label`
     Call { label: String },
     /// Return: `ret`
     Return,
     /// Label definition: `label:`
     Label { name: String },
+    /// Increment register: `inc reg`
+    Increment { reg: Z80Register },
+    /// Decrement register: `dec reg`
+    Decrement { reg: Z80Register },
+    /// Increment a byte in memory: `inc (hl)` or `inc (ix+offset)`
+    IncrementMemory { addr: MemoryAddress },
+    /// Decrement a byte in memory: `dec (hl)` or `dec (ix+offset)`
+    DecrementMemory { addr: MemoryAddress },
+    /// Set a bit of a byte in memory: `set n, (hl)` or `set n, (ix+offset)`
+    SetBit { bit: u8, addr: MemoryAddress },
+    /// Exchange DE and HL: `ex de, hl`
+    ExchangeDeHl,
+    /// Block transfer one byte (HL) -> (DE), increment both, decrement BC: `ldi`
+    Ldi,
+    /// Repeat `ldi` until BC is zero: `ldir`
+    Ldir,
+    /// Disable maskable interrupts: `di`
+    DisableInterrupts,
+    /// Enable maskable interrupts: `ei`
+    EnableInterrupts,
+    /// Select interrupt mode 0, 1 or 2: `im n`
+    SetInterruptMode { mode: u8 },
+    /// Load the interrupt vector register from A: `ld i, a`
+    LoadInterruptVector,
+    /// Return from maskable interrupt: `reti`
+    ReturnFromInterrupt,
+    /// Read a byte from the I/O port at BC: `in reg, (c)`
+    PortIn { reg: Z80Register },
+    /// Write a byte to the I/O port at BC: `out (c), reg`
+    PortOut { reg: Z80Register },
+    /// Call the restart vector at `vector` (a multiple of 8): `rst vector`
+    Restart { vector: u8 },
+    /// Inline data byte, e.g. the function code after an esxDOS `rst $08`: `db value`
+    DefineByte { value: u8 },
+    /// Inline data word holding the address of a label, e.g. a jump table entry: `dw label`
+    DefineWord { label: String },
+    /// A line of an `asm` block, emitted as written
+    Raw { text: String },
     /// Comment: `; comment`
     Comment { text: String },
 }
@@ -95,6 +194,20 @@ pub enum MemoryAddress {
     FrameRelative(i16),
     /// Register indirect: `(hl)`, `(bc)`, `(de)`
     RegisterIndirect(Z80Register),
+    /// Address of a symbol, resolved by the linker: `(label)`
+    Symbol(String),
+}
+
+impl fmt::Display for MemoryAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryAddress::Direct(addr) => write!(f, "({})", addr),
+            MemoryAddress::FrameRelative(offset) if *offset >= 0 => write!(f, "(ix+{})", offset),
+            MemoryAddress::FrameRelative(offset) => write!(f, "(ix{})", offset),
+            MemoryAddress::RegisterIndirect(reg) => write!(f, "({})", reg),
+            MemoryAddress::Symbol(label) => write!(f, "({})", label),
+        }
+    }
 }
 
 /// Condition codes for conditional jumps
@@ -127,6 +240,41 @@ impl fmt::Display for Condition {
     }
 }
 
+/// Text of the comment marking where the code of a statement starts,
+/// followed by its source line and column: `; line 12:5`
+pub const LINE_MARKER: &str = "line ";
+
+/// The source line and column `inst` marks the start of, if it is a line
+/// marker
+pub fn marked_position(inst: &Z80Instruction) -> Option<(usize, usize)> {
+    match inst {
+        Z80Instruction::Comment { text } => {
+            let (line, column) = text.strip_prefix(LINE_MARKER)?.split_once(':')?;
+            Some((line.parse().ok()?, column.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Address of the memory operand at `offset` from `base`: a frame slot,
+/// or in a global variable, addressed by its symbol (see
+/// [`ir::global_symbol`])
+fn memory_address(base: &str, offset: i32) -> MemoryAddress {
+    match ir::global_symbol(base) {
+        Some(symbol) if offset == 0 => MemoryAddress::Symbol(symbol.to_string()),
+        Some(symbol) => MemoryAddress::Symbol(format!("{}{:+}", symbol, offset)),
+        None => MemoryAddress::FrameRelative(offset as i16),
+    }
+}
+
+/// Start of the comment left in place of code that cannot be generated
+/// yet (see [`CodeGenerator::unsupported`])
+pub const UNSUPPORTED_MARKER: &str = "TODO: ";
+
+fn is_unsupported(inst: &Z80Instruction) -> bool {
+    matches!(inst, Z80Instruction::Comment { text } if text.starts_with(UNSUPPORTED_MARKER))
+}
+
 /// Z80 code generator
 pub struct CodeGenerator {
     /// Current function being generated
@@ -136,21 +284,151 @@ pub struct CodeGenerator {
     /// Temporary counter for SSA temporaries
     #[allow(dead_code)] // Reserved for future SSA temporary generation
     temp_counter: usize,
+    /// Counter for local labels of multi-instruction sequences
+    label_counter: usize,
+    /// Frame slot IY is saved in, when the current function allocates it
+    iy_slot: Option<i16>,
+    /// Routines whose calls are expanded inline
+    intrinsics: IntrinsicRegistry,
+    /// Runtime routines the generated code calls, such as soft-float ones
+    runtime_calls: BTreeSet<&'static str>,
+    /// Type descriptors of the exceptions raised or handled
+    exception_types: BTreeSet<String>,
+    /// Whether the generated code pushes frames or raises exceptions
+    uses_exceptions: bool,
+    /// Whether the generated code calls methods through a VMT
+    uses_virtual_calls: bool,
+    /// Whether the generated code allocates or frees instances
+    uses_heap: bool,
+    /// Whether the generated code queries instances for interfaces
+    uses_interfaces: bool,
+    /// Whether the generated code calls a runtime error entry
+    uses_runtime_errors: bool,
+    /// Whether the code of each statement is preceded by a line marker
+    line_markers: bool,
+    /// Routines whose calls name the operand receiving their result last
+    result_functions: Vec<String>,
+    /// Symbol of each routine declared external, by label
+    externals: HashMap<String, String>,
+    /// Symbol and offset of the globals and global fields one byte wide
+    byte_globals: HashSet<(String, i32)>,
+    /// Runtime routines the main program calls before its prologue
+    startup: Vec<String>,
+    /// Parameters of the current function, which it removes on return
+    param_count: usize,
+    /// IR instructions the code generator cannot translate yet, and where
+    unsupported: Vec<(String, Option<Span>)>,
 }
 
 impl CodeGenerator {
     /// Create a new code generator
     pub fn new() -> Self {
+        Self::with_intrinsics(IntrinsicRegistry::new())
+    }
+
+    /// Create a code generator that expands calls to `intrinsics` inline
+    pub fn with_intrinsics(intrinsics: IntrinsicRegistry) -> Self {
         Self {
             current_function: None,
             local_offset: 0,
             temp_counter: 0,
+            label_counter: 0,
+            iy_slot: None,
+            intrinsics,
+            runtime_calls: BTreeSet::new(),
+            exception_types: BTreeSet::new(),
+            uses_exceptions: false,
+            uses_virtual_calls: false,
+            uses_heap: false,
+            uses_interfaces: false,
+            uses_runtime_errors: false,
+            line_markers: false,
+            result_functions: Vec::new(),
+            externals: HashMap::new(),
+            byte_globals: HashSet::new(),
+            startup: Vec::new(),
+            param_count: 0,
+            unsupported: Vec::new(),
         }
     }
 
+    /// Mark where the code of each statement starts (see [`marked_position`])
+    pub fn set_line_markers(&mut self, enabled: bool) {
+        self.line_markers = enabled;
+    }
+
+    /// What the code generated so far could not translate, such as
+    /// `WriteLn`, and where in the source; such code is left as a `TODO`
+    /// comment
+    pub fn unsupported(&self) -> &[(String, Option<Span>)] {
+        &self.unsupported
+    }
+
+    /// Runtime routines called by the code generated so far, in name order
+    pub fn runtime_calls(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.runtime_calls.iter().copied()
+    }
+
+    /// Whether the code generated so far needs the exception routines
+    pub fn uses_exceptions(&self) -> bool {
+        self.uses_exceptions
+    }
+
+    /// Whether the code generated so far needs the indirect call routine
+    pub fn uses_virtual_calls(&self) -> bool {
+        self.uses_virtual_calls
+    }
+
+    /// Whether the code generated so far needs the heap routines
+    pub fn uses_heap(&self) -> bool {
+        self.uses_heap
+    }
+
+    /// Whether the code generated so far needs the interface query routine
+    pub fn uses_interfaces(&self) -> bool {
+        self.uses_interfaces
+    }
+
+    /// Whether the code generated so far needs the runtime error routines
+    pub fn uses_runtime_errors(&self) -> bool {
+        self.uses_runtime_errors
+    }
+
+    /// Exception type descriptors the code generated so far refers to, in
+    /// name order
+    pub fn exception_types(&self) -> impl Iterator<Item = &str> + '_ {
+        self.exception_types.iter().map(String::as_str)
+    }
+
+    /// Bytes of a call to an outlined routine, and of the entry and exit of
+    /// a routine without locals (see [`ir::outline_sequences`])
+    pub fn outline_costs(&self) -> OutlineCosts {
+        let mut scratch = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+        let routine = Function::new(String::new(), None);
+        let frame: Vec<Z80Instruction> =
+            scratch.generate_prologue(&routine).into_iter().chain(scratch.generate_epilogue()).collect();
+        let call = Z80Instruction::Call { label: String::new() };
+        OutlineCosts { call: self.instruction_size(&call) as u32, routine: self.code_size(&frame) }
+    }
+
+    /// Bytes of the code generated for `inst` on its own
+    pub fn ir_instruction_size(&self, inst: &Instruction) -> u32 {
+        let mut scratch = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+        let code = scratch.generate_instruction(inst);
+        self.code_size(&code)
+    }
+
+    fn code_size(&self, code: &[Z80Instruction]) -> u32 {
+        code.iter().map(|inst| self.instruction_size(inst) as u32).sum()
+    }
+
     /// Generate Z80 assembly from IR program
     pub fn generate(&mut self, program: &Program) -> Vec<Z80Instruction> {
         let mut instructions = Vec::new();
+        self.result_functions = program.result_functions.clone();
+        self.externals = program.externals.iter().cloned().collect();
+        self.byte_globals = program.byte_globals.iter().cloned().collect();
+        self.startup = program.startup.clone();
 
         // Generate code for each function
         for function in &program.functions {
@@ -169,12 +447,27 @@ impl CodeGenerator {
         
         self.current_function = Some(function.name.clone());
         self.local_offset = 0;
+        self.param_count = function.params.len();
+
+        // Give temporaries registers, or frame slots under pressure
+        let results = self.result_functions.clone();
+        let (function, allocation) = regalloc::allocate(function, &results, |function| self.clobbers(function));
+        let function = &function;
+        self.iy_slot = allocation
+            .values()
+            .any(|location| *location == regalloc::Location::Register(Z80Register::IY))
+            .then(|| regalloc::frame_bottom(function) as i16 - 2);
 
         // Function label
         instructions.push(Z80Instruction::Label {
             name: self.mangle_name(&function.name),
         });
 
+        // The startup routines find the registers as the target left them
+        if function.name == ir::MAIN_FUNCTION {
+            instructions.extend(self.startup.iter().map(|routine| Z80Instruction::Call { label: routine.clone() }));
+        }
+
         // Function prologue
         instructions.extend(self.generate_prologue(function));
 
@@ -183,10 +476,14 @@ impl CodeGenerator {
             instructions.extend(self.generate_block(block));
         }
 
-        // Function epilogue
-        instructions.extend(self.generate_epilogue(function));
+        // Function epilogue, unless it ends returning
+        let last = function.blocks.iter().rev().find_map(|block| block.instructions.last());
+        if !last.is_some_and(|inst| inst.opcode == Opcode::Ret) {
+            instructions.extend(self.generate_epilogue());
+        }
 
         self.current_function = None;
+        self.param_count = 0;
         instructions
     }
 
@@ -225,13 +522,33 @@ impl CodeGenerator {
             });
         }
 
+        // IY is preserved across calls (see [`abi`])
+        if let Some(slot) = self.iy_slot {
+            instructions.extend([
+                Z80Instruction::Push { reg: Z80Register::IY },
+                Z80Instruction::Pop { reg: Z80Register::DE },
+                Z80Instruction::StoreMemory { addr: MemoryAddress::FrameRelative(slot), reg: Z80Register::DE },
+            ]);
+        }
+
         instructions
     }
 
     /// Generate function epilogue
-    fn generate_epilogue(&mut self, _function: &Function) -> Vec<Z80Instruction> {
+    fn generate_epilogue(&mut self) -> Vec<Z80Instruction> {
         let mut instructions = Vec::new();
 
+        // DE may hold the high word of the result
+        if let Some(slot) = self.iy_slot {
+            instructions.extend([
+                Z80Instruction::Push { reg: Z80Register::DE },
+                Z80Instruction::LoadMemory { reg: Z80Register::DE, addr: MemoryAddress::FrameRelative(slot) },
+                Z80Instruction::Push { reg: Z80Register::DE },
+                Z80Instruction::Pop { reg: Z80Register::IY },
+                Z80Instruction::Pop { reg: Z80Register::DE },
+            ]);
+        }
+
         // Restore SP from IX
         instructions.push(Z80Instruction::LoadRegister {
             dst: Z80Register::SP,
@@ -241,6 +558,13 @@ impl CodeGenerator {
         // Restore frame pointer
         instructions.push(Z80Instruction::Pop { reg: Z80Register::IX });
 
+        // Remove the arguments from under the return address, keeping HL
+        if self.param_count > 0 {
+            instructions.push(Z80Instruction::Pop { reg: Z80Register::BC });
+            instructions.extend((0..self.param_count).map(|_| Z80Instruction::Pop { reg: Z80Register::AF }));
+            instructions.push(Z80Instruction::Push { reg: Z80Register::BC });
+        }
+
         // Return
         instructions.push(Z80Instruction::Return);
 
@@ -257,37 +581,158 @@ impl CodeGenerator {
         });
 
         // Generate code for each instruction
-        for ir_inst in &block.instructions {
-            instructions.extend(self.generate_instruction(ir_inst));
+        let mut position = None;
+        for i in 0..block.instructions.len() {
+            let span = block.instructions[i].span;
+            if self.line_markers
+                && let Some(span) = span.filter(|span| Some((span.line, span.column)) != position)
+            {
+                let text = format!("{}{}:{}", LINE_MARKER, span.line, span.column);
+                instructions.push(Z80Instruction::Comment { text });
+                position = Some((span.line, span.column));
+            }
+            let code = self.generate_block_instruction(block, i);
+            if code.iter().any(is_unsupported) {
+                let what = match block.instructions[i].opcode {
+                    Opcode::Write | Opcode::WriteLn => "Write and WriteLn".to_string(),
+                    ref opcode => format!("{:?}", opcode),
+                };
+                if !self.unsupported.contains(&(what.clone(), span)) {
+                    self.unsupported.push((what, span));
+                }
+            }
+            instructions.extend(code);
         }
 
+        // Keep values in registers across statements
+        schedule::schedule(&mut instructions);
+
         instructions
     }
 
+    /// Generate code for the `i`th instruction of `block`, which may depend
+    /// on the instruction after it
+    fn generate_block_instruction(&mut self, block: &BasicBlock, i: usize) -> Vec<Z80Instruction> {
+        let ir_inst = &block.instructions[i];
+        // A compare followed by an (in)equality jump needs no ordering
+        let equality_only = matches!(
+            block.instructions.get(i + 1),
+            Some(Instruction { opcode: Opcode::CJump, operands, .. })
+                if matches!(operands.first(), Some(Value::Condition(IRCondition::Equal | IRCondition::NotEqual)))
+        );
+        if ir_inst.opcode == Opcode::Cmp {
+            self.generate_cmp(ir_inst, equality_only)
+        } else if let Some(folded) = self.fold_memory_operation(ir_inst, block.instructions.get(i + 1)) {
+            folded
+        } else {
+            self.generate_instruction(ir_inst)
+        }
+    }
+
+    /// Registers the code of each instruction of `function` writes, by
+    /// position in layout order (see [`regalloc`])
+    fn clobbers(&self, function: &Function) -> Vec<Vec<Z80Register>> {
+        let mut scratch = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| (0..block.instructions.len()).map(move |i| (block, i)))
+            .map(|(block, i)| {
+                let code = scratch.generate_block_instruction(block, i);
+                regalloc::pairs(&code.iter().flat_map(schedule::writes).collect::<Vec<_>>())
+            })
+            .collect()
+    }
+
     /// Generate code for an IR instruction
     fn generate_instruction(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
         match &inst.opcode {
             Opcode::Mov => self.generate_mov(inst),
+            Opcode::Mul | Opcode::Div | Opcode::DivU | Opcode::Mod | Opcode::ModU => self.generate_multiply(inst),
+            Opcode::Shl | Opcode::Shr | Opcode::Sar => self.generate_shift(inst),
+            Opcode::BAdd | Opcode::BSub | Opcode::BShl | Opcode::BShr => self.generate_byte_op(inst),
             Opcode::Add => self.generate_add(inst),
             Opcode::Sub => self.generate_sub(inst),
-            Opcode::Cmp => self.generate_cmp(inst),
+            Opcode::Cmp => self.generate_cmp(inst, false),
             Opcode::Jump => self.generate_jump(inst),
             Opcode::CJump => self.generate_cjump(inst),
             Opcode::Call => self.generate_call(inst),
             Opcode::Ret => self.generate_ret(inst),
             Opcode::Load => self.generate_load(inst),
             Opcode::Store => self.generate_store(inst),
+            Opcode::Addr => self.generate_address(inst),
+            Opcode::LoadAt | Opcode::StoreAt => self.generate_indirect(inst),
             Opcode::Push => self.generate_push(inst),
             Opcode::Pop => self.generate_pop(inst),
-            _ => {
-                // Unimplemented opcodes
-                vec![Z80Instruction::Comment {
-                    text: format!("TODO: {:?}", inst.opcode),
-                }]
+            Opcode::FAdd | Opcode::FSub | Opcode::FMul | Opcode::FDiv | Opcode::FCmp
+            | Opcode::IToF | Opcode::FTrunc | Opcode::FRound => self.generate_float(inst),
+            Opcode::LAdd | Opcode::LSub | Opcode::LMul | Opcode::LDiv | Opcode::LDivU
+            | Opcode::LMod | Opcode::LModU | Opcode::LCmp => self.generate_long(inst),
+            Opcode::SExt | Opcode::ZExt => self.generate_extend(inst),
+            Opcode::SetClear | Opcode::SetIncl | Opcode::SetCopy | Opcode::SetUnion | Opcode::SetDiff
+            | Opcode::SetInter | Opcode::SetIn | Opcode::SetEq | Opcode::SetSubset => self.generate_set(inst),
+            Opcode::StrCopy | Opcode::StrChar | Opcode::StrConcat | Opcode::StrCmp | Opcode::StrLength
+            | Opcode::StrSlice | Opcode::StrPos | Opcode::StrDelete | Opcode::StrInsert
+            | Opcode::StrCopyAt => self.generate_string(inst),
+            Opcode::Switch => self.generate_switch(inst),
+            Opcode::TryEnter | Opcode::TryLeave | Opcode::Raise | Opcode::Reraise | Opcode::ExcIs
+            | Opcode::ExcValue => self.generate_exception(inst),
+            Opcode::CallMethod => self.generate_method_call(inst),
+            Opcode::New | Opcode::Dispose => self.generate_heap_op(inst),
+            Opcode::CallIntf | Opcode::IntfIs | Opcode::IntfAs => self.generate_interface_op(inst),
+            Opcode::Asm => self.generate_asm(inst),
+            Opcode::In | Opcode::Out => self.generate_port(inst),
+            // Only interpreted so far (the host-test target)
+            Opcode::Write | Opcode::WriteLn | Opcode::ReadLn => {
+                vec![Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }]
             }
         }
     }
 
+    /// Generate an in-place update of a frame slot or set as direct memory
+    /// operations (see [`fold`]), if it is one and `next` does not read the
+    /// result back
+    fn fold_memory_operation(&mut self, inst: &Instruction, next: Option<&Instruction>) -> Option<Vec<Z80Instruction>> {
+        let frame_slot = |value: &Value| match value {
+            Value::Memory { base, offset } if base != tasks::THREADVAR_BASE && ir::global_symbol(base).is_none() => {
+                Some(*offset)
+            }
+            _ => None,
+        };
+        match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::Add | Opcode::Sub, [dst, src, Value::Immediate(step)]) if dst == src => {
+                let offset = frame_slot(dst).filter(|offset| fold::displacement_fits(*offset, 2))?;
+                if next.is_some_and(|next| next.operands.contains(dst)) {
+                    return None;
+                }
+                let step = if inst.opcode == Opcode::Sub { step.wrapping_neg() } else { *step };
+                if step != 1 && step != -1 {
+                    return None;
+                }
+                let skip = format!("fold_{}", self.label_counter);
+                self.label_counter += 1;
+                Some(if step == 1 { fold::increment(offset as i16, &skip) } else { fold::decrement(offset as i16, &skip) })
+            }
+            (Opcode::SetIncl, [set, Value::Immediate(first), Value::Immediate(last), Value::Immediate(bytes)]) => {
+                if last < first || last - first >= fold::MAX_FOLDED_BITS || *last >= 8 * bytes {
+                    return None;
+                }
+                match set {
+                    Value::Label(label) => {
+                        let mut instructions = vec![Z80Instruction::LoadAddress { reg: Z80Register::HL, label: label.clone() }];
+                        instructions.extend(fold::set_bits_at_hl(*first, *last));
+                        Some(instructions)
+                    }
+                    _ => {
+                        let offset = frame_slot(set).filter(|offset| fold::displacement_fits(*offset, *bytes))?;
+                        Some(fold::set_bits(offset as i16, *first, *last))
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
     /// Generate MOV instruction
     fn generate_mov(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
         if inst.operands.len() < 2 {
@@ -305,22 +750,13 @@ impl CodeGenerator {
                 }]
             }
             (Value::Register(dst_reg), Value::Register(src_reg)) => {
-                vec![Z80Instruction::LoadRegister {
-                    dst: self.parse_register(dst_reg),
-                    src: self.parse_register(src_reg),
-                }]
+                move_register(self.parse_register(dst_reg), self.parse_register(src_reg))
             }
-            (Value::Memory { base: _, offset }, Value::Immediate(imm)) => {
-                vec![
-                    Z80Instruction::LoadImmediate {
-                        reg: Z80Register::HL,
-                        value: *imm as u16,
-                    },
-                    Z80Instruction::StoreMemory {
-                        addr: MemoryAddress::FrameRelative(*offset as i16),
-                        reg: Z80Register::HL,
-                    },
-                ]
+            // Through HL between frame slots, globals and registers
+            (Value::Register(_) | Value::Memory { .. }, Value::Register(_) | Value::Memory { .. } | Value::Immediate(_)) => {
+                let mut instructions = self.load_value_into_hl(src);
+                instructions.extend(self.store_hl_to_value(dst));
+                instructions
             }
             _ => vec![Z80Instruction::Comment {
                 text: format!("TODO: MOV {:?} <- {:?}", dst, src),
@@ -343,22 +779,17 @@ impl CodeGenerator {
         
         // Add src2 to HL
         match src2 {
-            Value::Immediate(imm) => {
-                instructions.push(Z80Instruction::LoadImmediate {
-                    reg: Z80Register::DE,
-                    value: *imm as u16,
-                });
-                instructions.push(Z80Instruction::Add {
-                    dst: Z80Register::HL,
-                    src: Z80Register::DE,
-                });
-            }
-            Value::Register(reg) => {
+            Value::Immediate(imm) => instructions.extend(arith::add_constant(*imm)),
+            Value::Register(reg) if !is_index_register(self.parse_register(reg)) => {
                 instructions.push(Z80Instruction::Add {
                     dst: Z80Register::HL,
                     src: self.parse_register(reg),
                 });
             }
+            Value::Register(_) | Value::Memory { .. } => {
+                instructions.extend(self.load_value_into_de(src2));
+                instructions.push(Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::DE });
+            }
             _ => {
                 instructions.push(Z80Instruction::Comment {
                     text: format!("TODO: ADD src2 {:?}", src2),
@@ -372,6 +803,67 @@ impl CodeGenerator {
         instructions
     }
 
+    /// Generate a 16-bit multiply, divide or remainder as a call to its
+    /// runtime routine, with the left operand in HL and the right in DE
+    fn generate_multiply(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let ([dst, left, right], Some(routine)) = (inst.operands.as_slice(), arith::routine(&inst.opcode)) else {
+            return vec![];
+        };
+        let mut instructions = self.load_value_into_hl(right);
+        instructions.push(Z80Instruction::ExchangeDeHl);
+        instructions.extend(self.load_value_into_hl(left));
+        self.runtime_calls.insert(routine);
+        instructions.push(Z80Instruction::Call { label: routine.to_string() });
+        instructions.extend(self.store_hl_to_value(dst));
+        instructions
+    }
+
+    /// Generate SHL, SHR or SAR by a constant number of bits
+    fn generate_shift(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [dst, src, Value::Immediate(count)] = inst.operands.as_slice() else {
+            return vec![Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }];
+        };
+        let mut instructions = self.load_value_into_hl(src);
+        instructions.extend(match inst.opcode {
+            Opcode::Shl => arith::shift_left(*count as u32),
+            _ => arith::shift_right(*count as u32, inst.opcode == Opcode::Sar),
+        });
+        instructions.extend(self.store_hl_to_value(dst));
+        instructions
+    }
+
+    /// Generate an 8-bit BADD, BSUB, BSHL or BSHR in A, zero-extending the
+    /// result into HL to store it
+    fn generate_byte_op(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [dst, src1, src2] = inst.operands.as_slice() else {
+            return vec![];
+        };
+        let mut instructions = self.load_value_into_a(src1);
+        match (&inst.opcode, src2) {
+            (Opcode::BAdd, Value::Immediate(1)) => instructions.push(Z80Instruction::Increment { reg: Z80Register::A }),
+            (Opcode::BSub, Value::Immediate(1)) => instructions.push(Z80Instruction::Decrement { reg: Z80Register::A }),
+            (Opcode::BAdd | Opcode::BSub, _) => {
+                instructions.extend(self.load_byte(Z80Register::E, src2));
+                instructions.push(if inst.opcode == Opcode::BAdd {
+                    Z80Instruction::Add { dst: Z80Register::A, src: Z80Register::E }
+                } else {
+                    Z80Instruction::Subtract { dst: Z80Register::A, src: Z80Register::E }
+                });
+            }
+            (opcode, Value::Immediate(count)) => instructions.extend((0..*count).map(|_| match opcode {
+                Opcode::BShl => Z80Instruction::Add { dst: Z80Register::A, src: Z80Register::A },
+                _ => Z80Instruction::ShiftRight { reg: Z80Register::A, arithmetic: false },
+            })),
+            _ => {
+                return vec![Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }];
+            }
+        }
+        instructions.push(Z80Instruction::LoadRegister { dst: Z80Register::L, src: Z80Register::A });
+        instructions.push(Z80Instruction::LoadImmediate { reg: Z80Register::H, value: 0 });
+        instructions.extend(self.store_hl_to_value(dst));
+        instructions
+    }
+
     /// Generate SUB instruction
     fn generate_sub(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
         if inst.operands.len() < 3 {
@@ -387,21 +879,13 @@ impl CodeGenerator {
         
         // Subtract src2 from HL
         match src2 {
-            Value::Immediate(imm) => {
-                instructions.push(Z80Instruction::LoadImmediate {
-                    reg: Z80Register::DE,
-                    value: *imm as u16,
-                });
-                instructions.push(Z80Instruction::Subtract {
-                    dst: Z80Register::HL,
-                    src: Z80Register::DE,
-                });
+            Value::Immediate(imm) => instructions.extend(arith::add_constant(imm.wrapping_neg())),
+            Value::Register(reg) if !is_index_register(self.parse_register(reg)) => {
+                instructions.extend(arith::subtract_register(self.parse_register(reg)))
             }
-            Value::Register(reg) => {
-                instructions.push(Z80Instruction::Subtract {
-                    dst: Z80Register::HL,
-                    src: self.parse_register(reg),
-                });
+            Value::Register(_) | Value::Memory { .. } => {
+                instructions.extend(self.load_value_into_de(src2));
+                instructions.extend(arith::subtract_register(Z80Register::DE));
             }
             _ => {
                 instructions.push(Z80Instruction::Comment {
@@ -416,8 +900,9 @@ impl CodeGenerator {
         instructions
     }
 
-    /// Generate CMP instruction
-    fn generate_cmp(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+    /// Generate CMP instruction; `equality_only` when the flags are only
+    /// tested for equal or not equal
+    fn generate_cmp(&mut self, inst: &Instruction, equality_only: bool) -> Vec<Z80Instruction> {
         if inst.operands.len() < 2 {
             return vec![];
         }
@@ -425,6 +910,27 @@ impl CodeGenerator {
         let src1 = &inst.operands[0];
         let src2 = &inst.operands[1];
 
+        // CMP src1, src2, kind: 16-bit compare with signedness promotion
+        if let Some(Value::Compare(kind)) = inst.operands.get(2) {
+            let same_signedness = matches!(kind, ComparisonKind::Unsigned | ComparisonKind::Signed);
+            if *src2 == Value::Immediate(0) && (*kind == ComparisonKind::Unsigned || (equality_only && same_signedness)) {
+                let mut instructions = self.load_value_into_hl(src1);
+                instructions.extend(compare::zero_test());
+                return instructions;
+            }
+            let label = format!("cmp_{}", self.label_counter);
+            self.label_counter += 1;
+            let mut instructions = self.load_value_into_hl(src2);
+            instructions.push(Z80Instruction::ExchangeDeHl);
+            instructions.extend(self.load_value_into_hl(src1));
+            instructions.extend(if equality_only {
+                compare::equal16(*kind, &label)
+            } else {
+                compare::compare16(*kind, &label)
+            });
+            return instructions;
+        }
+
         // Load src1 into A
         let mut instructions = self.load_value_into_a(src1);
         
@@ -436,9 +942,10 @@ impl CodeGenerator {
                     value: Some(*imm as u8),
                 });
             }
-            Value::Register(_reg) => {
+            Value::Register(_) | Value::Memory { .. } => {
+                instructions.extend(self.load_byte(Z80Register::E, src2));
                 instructions.push(Z80Instruction::Compare {
-                    reg: Z80Register::A,
+                    reg: Z80Register::E,
                     value: None,
                 });
             }
@@ -491,32 +998,86 @@ impl CodeGenerator {
             _ => return vec![],
         };
 
-        // TODO: Map IR condition to Z80 condition code
-        // For now, use zero/non-zero
-        vec![
-            Z80Instruction::JumpConditional {
-                condition: Condition::Zero,
-                label: label_true,
-                near: false,
-            },
-            Z80Instruction::Jump {
-                label: label_false,
-                near: false,
-            },
-        ]
+        // The flags come from a preceding CMP (A - operand, unsigned)
+        let jump = |condition, label: &String| Z80Instruction::JumpConditional {
+            condition,
+            label: label.clone(),
+            near: false,
+        };
+        let mut instructions = match &inst.operands[0] {
+            Value::Condition(IRCondition::Equal) => vec![jump(Condition::Zero, &label_true)],
+            Value::Condition(IRCondition::NotEqual) => vec![jump(Condition::NonZero, &label_true)],
+            Value::Condition(IRCondition::Less) => vec![jump(Condition::Carry, &label_true)],
+            Value::Condition(IRCondition::GreaterEqual) => vec![jump(Condition::NoCarry, &label_true)],
+            Value::Condition(IRCondition::Greater) => vec![
+                jump(Condition::Zero, &label_false),
+                jump(Condition::NoCarry, &label_true),
+            ],
+            Value::Condition(IRCondition::LessEqual) => vec![
+                jump(Condition::Carry, &label_true),
+                jump(Condition::Zero, &label_true),
+            ],
+            // Boolean value already tested: zero/non-zero
+            _ => vec![jump(Condition::Zero, &label_true)],
+        };
+        instructions.push(Z80Instruction::Jump {
+            label: label_false,
+            near: false,
+        });
+        instructions
     }
 
     /// Generate CALL instruction
     fn generate_call(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        if let Some(Value::Label(label)) = inst.operands.first()
+            && let Some(template) = self.intrinsics.get(label).cloned()
+        {
+            return match template {
+                IntrinsicTemplate::Assembly(code) => code,
+                IntrinsicTemplate::Ir(body) => body
+                    .iter()
+                    .flat_map(|i| match i.opcode {
+                        Opcode::Call => self.generate_plain_call(i),
+                        _ => self.generate_instruction(i),
+                    })
+                    .collect(),
+            };
+        }
+        self.generate_plain_call(inst)
+    }
+
+    /// Generate CALL instruction, without intrinsic expansion: the
+    /// arguments are pushed left to right (see [`abi`]) and a result taken
+    /// from HL
+    fn generate_plain_call(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
         if inst.operands.is_empty() {
             return vec![];
         }
 
-        match &inst.operands[0] {
-            Value::Label(label) => {
-                vec![Z80Instruction::Call {
-                    label: self.mangle_name(label),
-                }]
+        match inst.operands.as_slice() {
+            [Value::Label(label)] if runtime_errors::ENTRIES.iter().any(|(entry, _)| entry == label) => {
+                self.uses_runtime_errors = true;
+                vec![Z80Instruction::Call { label: label.clone() }]
+            }
+            [Value::Label(label), operands @ ..] => {
+                let (args, result) = match operands {
+                    [args @ .., result] if self.result_functions.contains(label) => (args, Some(result)),
+                    _ => (operands, None),
+                };
+                let mut instructions = Vec::new();
+                for arg in args {
+                    instructions.extend(self.load_value_into_hl(arg));
+                    instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                }
+                let symbol = match self.externals.get(label) {
+                    Some(symbol) => symbol.clone(),
+                    None => self.mangle_name(label),
+                };
+                instructions.push(Z80Instruction::Call { label: symbol });
+                if let Some(result) = result {
+                    instructions.extend(self.store_hl_to_value(result));
+                }
+                instructions
             }
             _ => vec![Z80Instruction::Comment {
                 text: format!("TODO: CALL {:?}", inst.operands),
@@ -524,9 +1085,447 @@ impl CodeGenerator {
         }
     }
 
-    /// Generate RET instruction
-    fn generate_ret(&mut self, _inst: &Instruction) -> Vec<Z80Instruction> {
-        vec![Z80Instruction::Return]
+    /// Generate a float instruction as a call to its runtime routine
+    fn generate_float(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let Some((routine, shape)) = float::routine(&inst.opcode) else {
+            return vec![];
+        };
+        let mut instructions = Vec::new();
+        let (dst, src) = match (shape, inst.operands.as_slice()) {
+            (FloatOperands::Binary, [dst, left, right]) => {
+                instructions.extend(self.load_dword(right));
+                instructions.extend(float::push_operand());
+                (Some(dst), left)
+            }
+            (FloatOperands::Compare, [left, right]) => {
+                instructions.extend(self.load_dword(right));
+                instructions.extend(float::push_operand());
+                (None, left)
+            }
+            (FloatOperands::Convert, [dst, src]) => (Some(dst), src),
+            _ => return vec![],
+        };
+        if inst.opcode == Opcode::IToF {
+            instructions.extend(self.load_value_into_hl(src));
+        } else {
+            instructions.extend(self.load_dword(src));
+        }
+        self.runtime_calls.insert(routine);
+        instructions.push(Z80Instruction::Call { label: routine.to_string() });
+        match dst {
+            Some(dst) if matches!(inst.opcode, Opcode::FTrunc | Opcode::FRound) => {
+                instructions.extend(self.store_hl_to_value(dst));
+            }
+            Some(dst) => instructions.extend(self.store_dword(dst)),
+            None => {}
+        }
+        instructions
+    }
+
+    /// Generate 32-bit arithmetic or a comparison: the right operand goes on
+    /// the stack and the left one in DE:HL, as for float routines. Addition
+    /// and subtraction are inline; the rest call runtime routines.
+    fn generate_long(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let (dst, left, right, routine) = match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::LCmp, [left, right, Value::Compare(kind)]) => (None, left, right, Some(int32::compare_routine(*kind))),
+            (opcode, [dst, left, right]) => (Some(dst), left, right, int32::routine(opcode)),
+            _ => return vec![],
+        };
+        let mut instructions = self.load_dword(right);
+        instructions.extend(float::push_operand());
+        instructions.extend(self.load_dword(left));
+        match routine {
+            Some(routine) => {
+                self.runtime_calls.insert(routine);
+                instructions.push(Z80Instruction::Call { label: routine.to_string() });
+            }
+            None if inst.opcode == Opcode::LSub => instructions.extend(int32::subtract_from_stack()),
+            None => instructions.extend(int32::add_from_stack()),
+        }
+        if let Some(dst) = dst {
+            instructions.extend(self.store_dword(dst));
+        }
+        instructions
+    }
+
+    /// Generate SEXT or ZEXT: a 16-bit value widened into DE:HL
+    fn generate_extend(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [dst, src] = inst.operands.as_slice() else {
+            return vec![];
+        };
+        let mut instructions = self.load_value_into_hl(src);
+        if inst.opcode == Opcode::SExt {
+            let label = format!("sext_{}", self.label_counter);
+            self.label_counter += 1;
+            instructions.extend(int32::sign_extend(&label));
+        } else {
+            instructions.push(Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: 0 });
+        }
+        instructions.extend(self.store_dword(dst));
+        instructions
+    }
+
+    /// Generate a set operation (see [`sets`]): an inline copy, or a call
+    /// with the operands in HL, DE and BC and the size in A
+    fn generate_set(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let size = |value: &Value| match value {
+            Value::Immediate(n) => *n as u16,
+            _ => 0,
+        };
+        let mut instructions = Vec::new();
+        let (dst, bytes) = match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::SetCopy, [dst, src, bytes]) => {
+                instructions.extend(self.set_address_into_hl(dst));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(src));
+                instructions.extend(sets::copy(size(bytes)));
+                return instructions;
+            }
+            (Opcode::SetClear, [set, bytes]) => {
+                instructions.extend(self.set_address_into_hl(set));
+                (None, bytes)
+            }
+            (Opcode::SetIncl, [set, first, last, bytes]) => {
+                instructions.extend(self.load_value_into_hl(last));
+                instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                instructions.extend(self.load_value_into_hl(first));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(set));
+                instructions.push(Z80Instruction::Pop { reg: Z80Register::BC });
+                (None, bytes)
+            }
+            (Opcode::SetIn, [dst, element, set, bytes]) => {
+                instructions.extend(self.load_value_into_hl(element));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(set));
+                (Some(dst), bytes)
+            }
+            (Opcode::SetEq | Opcode::SetSubset, [dst, left, right, bytes]) => {
+                instructions.extend(self.set_address_into_hl(right));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(left));
+                (Some(dst), bytes)
+            }
+            (_, [dst, src, bytes]) => {
+                instructions.extend(self.set_address_into_hl(src));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(dst));
+                (None, bytes)
+            }
+            _ => return vec![],
+        };
+        let Some(routine) = sets::routine(&inst.opcode) else {
+            return vec![];
+        };
+        instructions.push(Z80Instruction::LoadImmediate { reg: Z80Register::A, value: size(bytes) });
+        self.runtime_calls.insert(routine);
+        instructions.push(Z80Instruction::Call { label: routine.to_string() });
+        if let Some(dst) = dst {
+            instructions.extend(self.store_hl_to_value(dst));
+        }
+        instructions
+    }
+
+    /// Generate SWITCH (see [`switch`]): the selector in HL, then a jump
+    /// table when the cases are dense, otherwise a compare chain
+    fn generate_switch(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [selector, Value::Label(default), cases @ ..] = inst.operands.as_slice() else {
+            return vec![Z80Instruction::Comment { text: format!("TODO: SWITCH {:?}", inst.operands) }];
+        };
+        let cases: Vec<switch::SwitchCase> = cases
+            .chunks(3)
+            .filter_map(|case| match case {
+                [Value::Immediate(low), Value::Immediate(high), Value::Label(label)] => {
+                    Some(switch::SwitchCase { low: *low, high: *high, label: label.clone() })
+                }
+                _ => None,
+            })
+            .collect();
+        let mut instructions = self.load_value_into_hl(selector);
+        if switch::use_jump_table(&cases) {
+            let table = format!("switch_{}", self.label_counter);
+            self.label_counter += 1;
+            instructions.extend(switch::jump_table(&cases, default, &table));
+        } else {
+            instructions.extend(switch::compare_chain(&cases, default));
+        }
+        instructions
+    }
+
+    /// Generate an exception operation (see [`exceptions`])
+    fn generate_exception(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        self.uses_exceptions = true;
+        match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::TryEnter, [Value::Label(handler)]) => exceptions::enter(handler),
+            (Opcode::TryLeave, []) => exceptions::leave(),
+            (Opcode::Raise, [value, Value::Label(type_label)]) => {
+                self.exception_types.insert(type_label.clone());
+                let mut instructions = self.load_value_into_hl(value);
+                instructions.extend(exceptions::raise(type_label));
+                instructions
+            }
+            (Opcode::Reraise, []) => exceptions::reraise(),
+            (Opcode::ExcIs, [Value::Label(type_label)]) => {
+                self.exception_types.insert(type_label.clone());
+                exceptions::is_type(type_label)
+            }
+            (Opcode::ExcValue, [dst]) => {
+                let mut instructions = vec![Z80Instruction::LoadMemory {
+                    reg: Z80Register::HL,
+                    addr: MemoryAddress::Symbol(exceptions::VALUE_SYMBOL.to_string()),
+                }];
+                instructions.extend(self.store_hl_to_value(dst));
+                instructions
+            }
+            _ => vec![Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }],
+        }
+    }
+
+    /// Generate a method call (see [`classes`]): `Self` and the arguments
+    /// are pushed, then the method is called by symbol or through its VMT
+    /// slot
+    fn generate_method_call(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [method, receiver, args @ ..] = inst.operands.as_slice() else {
+            return vec![];
+        };
+        let mut instructions = Vec::new();
+        for value in std::iter::once(receiver).chain(args) {
+            instructions.extend(self.load_value_into_hl(value));
+            instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+        }
+        match method {
+            Value::Label(label) => instructions.push(Z80Instruction::Call { label: self.mangle_name(label) }),
+            Value::Immediate(slot) => {
+                self.uses_virtual_calls = true;
+                instructions.extend(classes::virtual_call(*slot as usize, args.len()));
+            }
+            _ => instructions.push(Z80Instruction::Comment { text: format!("TODO: CALLMETHOD {:?}", method) }),
+        }
+        instructions
+    }
+
+    /// Generate an interface method call, with `Self` and the arguments
+    /// pushed as for a method, or an `is` or `as` query (see [`interfaces`])
+    fn generate_interface_op(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        self.uses_interfaces = true;
+        let mut instructions = Vec::new();
+        match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::CallIntf, [Value::Label(iid), Value::Immediate(slot), receiver, args @ ..]) => {
+                self.uses_virtual_calls = true;
+                for value in std::iter::once(receiver).chain(args) {
+                    instructions.extend(self.load_value_into_hl(value));
+                    instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                }
+                instructions.extend(interfaces::interface_call(iid, *slot as usize, args.len()));
+            }
+            (opcode, [dst, object, Value::Label(iid)]) => {
+                let skip = format!("intf_{}", self.label_counter);
+                self.label_counter += 1;
+                instructions.extend(self.load_value_into_hl(object));
+                if *opcode == Opcode::IntfIs {
+                    instructions.extend(interfaces::query(iid));
+                    instructions.extend(interfaces::found(&skip));
+                } else {
+                    instructions.extend(interfaces::cast(iid, &skip));
+                }
+                instructions.extend(self.store_hl_to_value(dst));
+            }
+            _ => instructions.push(Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }),
+        }
+        instructions
+    }
+
+    /// Generate NEW, allocating an instance with its VMT installed, or
+    /// DISPOSE, freeing one (see [`heap`])
+    fn generate_heap_op(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        self.uses_heap = true;
+        let mut instructions = Vec::new();
+        match inst.operands.as_slice() {
+            [dst, Value::Immediate(size), vmt] => {
+                let vmt = match vmt {
+                    Value::Label(vmt) => Some(vmt.as_str()),
+                    _ => None,
+                };
+                instructions.extend(heap::new_instance(*size as u16, vmt));
+                instructions.extend(self.store_hl_to_value(dst));
+            }
+            [object] => {
+                instructions.extend(self.load_value_into_hl(object));
+                instructions.push(Z80Instruction::Call { label: heap::DISPOSE_ROUTINE.to_string() });
+            }
+            _ => instructions.push(Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }),
+        }
+        instructions
+    }
+
+    /// Generate a port read or write; the port address goes in BC (see
+    /// [`ports`])
+    fn generate_port(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let port_into_bc = |port: &Value| match port {
+            Value::Immediate(port) => vec![Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: *port as u16 }],
+            port => {
+                let mut instructions = self.load_value_into_hl(port);
+                instructions.extend(ports::hl_into_bc());
+                instructions
+            }
+        };
+        let mut instructions = Vec::new();
+        match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::In, [dst, port, Value::Immediate(bytes)]) => {
+                instructions.extend(port_into_bc(port));
+                instructions.extend(ports::read(*bytes == 2));
+                instructions.extend(self.store_hl_to_value(dst));
+            }
+            (Opcode::Out, [port, src, Value::Immediate(bytes)]) => {
+                instructions.extend(port_into_bc(port));
+                instructions.extend(self.load_value_into_hl(src));
+                instructions.extend(ports::write(*bytes == 2));
+            }
+            _ => instructions.push(Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }),
+        }
+        instructions
+    }
+
+    /// Generate an `asm` block: its lines as written, with each `@Name`
+    /// replaced by a frame slot `ix+d`, a constant's value or a routine's
+    /// symbol. IX and IY are saved around the block if it clobbers them
+    fn generate_asm(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [Value::Asm(block)] = inst.operands.as_slice() else {
+            return vec![Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }];
+        };
+        let mut body = String::new();
+        let mut copied = 0;
+        for (range, value) in &block.references {
+            body.push_str(&block.body[copied..range.start]);
+            match value {
+                Value::Immediate(n) => body.push_str(&n.to_string()),
+                Value::Memory { base, offset } => match memory_address(base, *offset) {
+                    MemoryAddress::Symbol(label) => body.push_str(&label),
+                    _ => body.push_str(&format!("ix{:+}", offset)),
+                },
+                Value::Label(name) => body.push_str(&abi::routine_symbol(name)),
+                _ => body.push_str(&block.body[range.clone()]),
+            }
+            copied = range.end;
+        }
+        body.push_str(&block.body[copied..]);
+
+        let saved: Vec<Z80Register> = [("ix", Z80Register::IX), ("iy", Z80Register::IY)]
+            .into_iter()
+            .filter(|(name, _)| block.clobbers.iter().any(|c| c == name))
+            .map(|(_, reg)| reg)
+            .collect();
+        let mut instructions = vec![];
+        if !block.clobbers.is_empty() {
+            instructions.push(Z80Instruction::Comment { text: format!("asm clobbers {}", block.clobbers.join(", ")) });
+        }
+        instructions.extend(saved.iter().map(|&reg| Z80Instruction::Push { reg }));
+        instructions.extend(body.lines().map(|line| Z80Instruction::Raw { text: line.to_string() }));
+        instructions.extend(saved.iter().rev().map(|&reg| Z80Instruction::Pop { reg }));
+        instructions
+    }
+
+    /// Generate a string operation (see [`strings`]): an inline length, or
+    /// a call with the operands in HL, DE and BC and the length the
+    /// destination holds in A
+    fn generate_string(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let mut instructions = Vec::new();
+        let (dst, max) = match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::StrLength, [dst, s]) => {
+                instructions.extend(self.set_address_into_hl(s));
+                instructions.extend(strings::length());
+                instructions.extend(self.store_hl_to_value(dst));
+                return instructions;
+            }
+            (Opcode::StrChar, [s, ch]) => {
+                instructions.extend(self.load_value_into_hl(ch));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(s));
+                (None, None)
+            }
+            (Opcode::StrCopy | Opcode::StrConcat, [dst, src, max]) => {
+                instructions.extend(self.set_address_into_hl(src));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(dst));
+                (None, Some(max))
+            }
+            (Opcode::StrCopyAt, [address, src, max]) => {
+                instructions.extend(self.set_address_into_hl(src));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.load_value_into_hl(address));
+                (None, Some(max))
+            }
+            (Opcode::StrCmp, [dst, left, right, Value::Condition(condition)]) => {
+                instructions.extend(self.set_address_into_hl(right));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(left));
+                instructions.push(Z80Instruction::LoadImmediate {
+                    reg: Z80Register::A,
+                    value: strings::condition_mask(condition),
+                });
+                (Some(dst), None)
+            }
+            (Opcode::StrPos, [dst, sub, s]) => {
+                instructions.extend(self.set_address_into_hl(sub));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(s));
+                (Some(dst), None)
+            }
+            (Opcode::StrSlice, [dst, src, index, count, max]) => {
+                // The count stays on the stack for the routine
+                instructions.extend(self.load_value_into_hl(count));
+                instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                instructions.extend(self.load_value_into_hl(index));
+                instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                instructions.extend(self.set_address_into_hl(src));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(dst));
+                instructions.push(Z80Instruction::Pop { reg: Z80Register::BC });
+                (None, Some(max))
+            }
+            (Opcode::StrDelete, [s, index, count]) => {
+                instructions.extend(self.load_value_into_hl(count));
+                instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                instructions.extend(self.load_value_into_hl(index));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(s));
+                instructions.push(Z80Instruction::Pop { reg: Z80Register::BC });
+                (None, None)
+            }
+            (Opcode::StrInsert, [s, src, index, max]) => {
+                instructions.extend(self.load_value_into_hl(index));
+                instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                instructions.extend(self.set_address_into_hl(src));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.extend(self.set_address_into_hl(s));
+                instructions.push(Z80Instruction::Pop { reg: Z80Register::BC });
+                (None, Some(max))
+            }
+            _ => return vec![],
+        };
+        let Some(routine) = strings::routine(&inst.opcode) else {
+            return vec![];
+        };
+        if let Some(Value::Immediate(max)) = max {
+            instructions.push(Z80Instruction::LoadImmediate { reg: Z80Register::A, value: *max as u16 });
+        }
+        self.runtime_calls.insert(routine);
+        instructions.push(Z80Instruction::Call { label: routine.to_string() });
+        if let Some(dst) = dst {
+            instructions.extend(self.store_hl_to_value(dst));
+        }
+        instructions
+    }
+
+    /// Generate RET instruction: the result, if any, in HL, then the
+    /// epilogue
+    fn generate_ret(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let mut instructions = match inst.operands.first() {
+            Some(result) => self.load_value_into_hl(result),
+            None => vec![],
+        };
+        instructions.extend(self.generate_epilogue());
+        instructions
     }
 
     /// Generate LOAD instruction
@@ -539,11 +1538,15 @@ impl CodeGenerator {
         let src = &inst.operands[1];
 
         match (dst, src) {
-            (Value::Register(_), Value::Memory { base: _, offset }) => {
-                vec![Z80Instruction::LoadMemory {
-                    reg: Z80Register::HL,
-                    addr: MemoryAddress::FrameRelative(*offset as i16),
-                }]
+            (Value::Register(_) | Value::Memory { .. }, Value::Memory { base, offset }) if base == tasks::THREADVAR_BASE => {
+                let mut instructions = tasks::threadvar_load(*offset as u16);
+                instructions.extend(self.store_hl_to_value(dst));
+                instructions
+            }
+            (Value::Register(_) | Value::Memory { .. }, Value::Memory { .. }) => {
+                let mut instructions = self.load_value_into_hl(src);
+                instructions.extend(self.store_hl_to_value(dst));
+                instructions
             }
             _ => vec![Z80Instruction::Comment {
                 text: format!("TODO: LOAD {:?} <- {:?}", dst, src),
@@ -561,16 +1564,74 @@ impl CodeGenerator {
         let src = &inst.operands[1];
 
         match (dst, src) {
-            (Value::Memory { base: _, offset }, Value::Register(_)) => {
-                vec![Z80Instruction::StoreMemory {
-                    addr: MemoryAddress::FrameRelative(*offset as i16),
-                    reg: Z80Register::HL,
-                }]
+            (Value::Memory { base, offset }, Value::Register(_) | Value::Memory { .. } | Value::Immediate(_))
+                if base == tasks::THREADVAR_BASE =>
+            {
+                let mut instructions = self.load_value_into_hl(src);
+                instructions.extend(tasks::threadvar_store(*offset as u16));
+                instructions
+            }
+            (Value::Memory { .. }, Value::Register(_) | Value::Memory { .. } | Value::Immediate(_)) => {
+                let mut instructions = self.load_value_into_hl(src);
+                instructions.extend(self.store_hl_to_value(dst));
+                instructions
+            }
+            _ => vec![Z80Instruction::Comment {
+                text: format!("TODO: STORE {:?} <- {:?}", dst, src),
+            }],
+        }
+    }
+
+    /// Generate ADDR: the address of a variable, or of a routine by its
+    /// symbol, through HL
+    fn generate_address(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        let [dst, src] = inst.operands.as_slice() else {
+            return vec![];
+        };
+        let mut instructions = match src {
+            Value::Label(routine) => {
+                let label = self.externals.get(routine).cloned().unwrap_or_else(|| self.mangle_name(routine));
+                vec![Z80Instruction::LoadAddress { reg: Z80Register::HL, label }]
+            }
+            Value::Memory { base, offset } if base == tasks::THREADVAR_BASE => tasks::threadvar_address(*offset as u16),
+            Value::Memory { .. } => self.set_address_into_hl(src),
+            _ => return vec![Z80Instruction::Comment { text: format!("TODO: ADDR {:?}", src) }],
+        };
+        instructions.extend(self.store_hl_to_value(dst));
+        instructions
+    }
+
+    /// Generate LOADAT or STOREAT, reaching the byte or word at the address
+    /// a value holds through HL. DE is clobbered.
+    fn generate_indirect(&mut self, inst: &Instruction) -> Vec<Z80Instruction> {
+        use Z80Register::*;
+        let at_hl = MemoryAddress::RegisterIndirect(HL);
+        let mut instructions = Vec::new();
+        match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::LoadAt, [dst, address, Value::Immediate(size)]) => {
+                instructions.extend(self.load_value_into_hl(address));
+                if *size == 1 {
+                    instructions.push(Z80Instruction::LoadMemory { reg: L, addr: at_hl });
+                    instructions.push(Z80Instruction::LoadImmediate { reg: H, value: 0 });
+                } else {
+                    instructions.extend(classes::load_word_at_hl());
+                }
+                instructions.extend(self.store_hl_to_value(dst));
             }
-            _ => vec![Z80Instruction::Comment {
-                text: format!("TODO: STORE {:?} <- {:?}", dst, src),
-            }],
+            (Opcode::StoreAt, [address, src, Value::Immediate(size)]) => {
+                instructions.extend(self.load_value_into_hl(src));
+                instructions.push(Z80Instruction::Push { reg: HL });
+                instructions.extend(self.load_value_into_hl(address));
+                instructions.push(Z80Instruction::Pop { reg: DE });
+                instructions.push(Z80Instruction::StoreMemory { addr: at_hl.clone(), reg: E });
+                if *size != 1 {
+                    instructions.push(Z80Instruction::Increment { reg: HL });
+                    instructions.push(Z80Instruction::StoreMemory { addr: at_hl, reg: D });
+                }
+            }
+            _ => instructions.push(Z80Instruction::Comment { text: format!("TODO: {:?} {:?}", inst.opcode, inst.operands) }),
         }
+        instructions
     }
 
     /// Generate PUSH instruction
@@ -585,6 +1646,11 @@ impl CodeGenerator {
                     reg: self.parse_register(reg),
                 }]
             }
+            value @ (Value::Memory { .. } | Value::Immediate(_)) => {
+                let mut instructions = self.load_value_into_hl(value);
+                instructions.push(Z80Instruction::Push { reg: Z80Register::HL });
+                instructions
+            }
             _ => vec![Z80Instruction::Comment {
                 text: format!("TODO: PUSH {:?}", inst.operands),
             }],
@@ -603,6 +1669,11 @@ impl CodeGenerator {
                     reg: self.parse_register(reg),
                 }]
             }
+            value @ Value::Memory { .. } => {
+                let mut instructions = vec![Z80Instruction::Pop { reg: Z80Register::HL }];
+                instructions.extend(self.store_hl_to_value(value));
+                instructions
+            }
             _ => vec![Z80Instruction::Comment {
                 text: format!("TODO: POP {:?}", inst.operands),
             }],
@@ -620,29 +1691,172 @@ impl CodeGenerator {
                     value: *imm as u16,
                 }]
             }
-            Value::Register(reg) => {
-                vec![Z80Instruction::LoadRegister {
-                    dst: Z80Register::HL,
-                    src: self.parse_register(reg),
-                }]
+            Value::Register(reg) => move_register(Z80Register::HL, self.parse_register(reg)),
+            // A constant, such as a string, is its address
+            Value::Label(label) => vec![Z80Instruction::LoadAddress { reg: Z80Register::HL, label: label.clone() }],
+            Value::Memory { base, offset } if base == tasks::THREADVAR_BASE => {
+                let mut instructions = vec![Z80Instruction::Push { reg: Z80Register::DE }];
+                instructions.extend(tasks::threadvar_load(*offset as u16));
+                instructions.push(Z80Instruction::Pop { reg: Z80Register::DE });
+                instructions
+            }
+            Value::Memory { base, offset } if self.is_byte_global(base, *offset) => {
+                self.load_byte_global(Z80Register::L, Z80Register::H, memory_address(base, *offset))
             }
+            Value::Memory { base, offset } => vec![Z80Instruction::LoadMemory {
+                reg: Z80Register::HL,
+                addr: memory_address(base, *offset),
+            }],
             _ => vec![Z80Instruction::Comment {
                 text: format!("TODO: load {:?} into HL", value),
             }],
         }
     }
 
+    /// Load a value into DE, keeping HL
+    fn load_value_into_de(&self, value: &Value) -> Vec<Z80Instruction> {
+        match value {
+            Value::Immediate(imm) => vec![Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: *imm as u16 }],
+            Value::Register(reg) => move_register(Z80Register::DE, self.parse_register(reg)),
+            Value::Memory { base, offset } if base == tasks::THREADVAR_BASE => {
+                let mut instructions = vec![Z80Instruction::Push { reg: Z80Register::HL }];
+                instructions.extend(tasks::threadvar_load(*offset as u16));
+                instructions.push(Z80Instruction::ExchangeDeHl);
+                instructions.push(Z80Instruction::Pop { reg: Z80Register::HL });
+                instructions
+            }
+            Value::Memory { base, offset } if self.is_byte_global(base, *offset) => {
+                self.load_byte_global(Z80Register::E, Z80Register::D, memory_address(base, *offset))
+            }
+            Value::Memory { base, offset } => vec![Z80Instruction::LoadMemory {
+                reg: Z80Register::DE,
+                addr: memory_address(base, *offset),
+            }],
+            _ => vec![Z80Instruction::Comment {
+                text: format!("TODO: load {:?} into DE", value),
+            }],
+        }
+    }
+
+    /// Whether a memory operand is a global one byte wide, which is loaded
+    /// and stored through A so the byte after it is left alone
+    fn is_byte_global(&self, base: &str, offset: i32) -> bool {
+        ir::global_symbol(base).is_some_and(|symbol| self.byte_globals.contains(&(symbol.to_string(), offset)))
+    }
+
+    /// Load the byte global at `addr` into the pair `low` and `high`,
+    /// zero-extended: `ld a, (b); ld l, a; ld h, 0`
+    fn load_byte_global(&self, low: Z80Register, high: Z80Register, addr: MemoryAddress) -> Vec<Z80Instruction> {
+        vec![
+            Z80Instruction::LoadMemory { reg: Z80Register::A, addr },
+            Z80Instruction::LoadRegister { dst: low, src: Z80Register::A },
+            Z80Instruction::LoadImmediate { reg: high, value: 0 },
+        ]
+    }
+
+    /// Load the address of a set or string into HL. BC is clobbered.
+    fn set_address_into_hl(&self, value: &Value) -> Vec<Z80Instruction> {
+        match value {
+            Value::Memory { base, offset } => match memory_address(base, *offset) {
+                MemoryAddress::Symbol(label) => vec![Z80Instruction::LoadAddress { reg: Z80Register::HL, label }],
+                _ => sets::frame_address(*offset as i16),
+            },
+            Value::Label(label) => vec![Z80Instruction::LoadAddress { reg: Z80Register::HL, label: label.clone() }],
+            _ => vec![Z80Instruction::Comment {
+                text: format!("TODO: load address of {:?} into HL", value),
+            }],
+        }
+    }
+
+    /// Load a 32-bit value, a real or a LongInt, into DE:HL
+    fn load_dword(&self, value: &Value) -> Vec<Z80Instruction> {
+        match value {
+            Value::Immediate(bits) => float::load_constant(*bits).to_vec(),
+            Value::Memory { base, offset } => vec![
+                Z80Instruction::LoadMemory {
+                    reg: Z80Register::HL,
+                    addr: memory_address(base, *offset),
+                },
+                Z80Instruction::LoadMemory {
+                    reg: Z80Register::DE,
+                    addr: memory_address(base, *offset + 2),
+                },
+            ],
+            _ => {
+                let mut instructions = self.load_value_into_hl(value);
+                instructions.push(Z80Instruction::Comment {
+                    text: format!("TODO: load high word of {:?} into DE", value),
+                });
+                instructions
+            }
+        }
+    }
+
+    /// Store the 32-bit value in DE:HL to a value
+    fn store_dword(&self, value: &Value) -> Vec<Z80Instruction> {
+        match value {
+            Value::Memory { base, offset } => vec![
+                Z80Instruction::StoreMemory {
+                    addr: memory_address(base, *offset),
+                    reg: Z80Register::HL,
+                },
+                Z80Instruction::StoreMemory {
+                    addr: memory_address(base, *offset + 2),
+                    reg: Z80Register::DE,
+                },
+            ],
+            _ => {
+                let mut instructions = self.store_hl_to_value(value);
+                instructions.push(Z80Instruction::Comment {
+                    text: format!("TODO: store DE to high word of {:?}", value),
+                });
+                instructions
+            }
+        }
+    }
+
     /// Load a value into A register
     fn load_value_into_a(&self, value: &Value) -> Vec<Z80Instruction> {
+        self.load_byte(Z80Register::A, value)
+    }
+
+    /// Load the low byte of a value into an 8-bit register
+    fn load_byte(&self, reg: Z80Register, value: &Value) -> Vec<Z80Instruction> {
         match value {
             Value::Immediate(imm) => {
                 vec![Z80Instruction::LoadImmediate {
-                    reg: Z80Register::A,
-                    value: *imm as u16,
+                    reg,
+                    value: *imm as u8 as u16,
                 }]
             }
+            Value::Register(name) if is_index_register(self.parse_register(name)) => {
+                // Through L; HL is kept unless it is being loaded
+                let index = self.parse_register(name);
+                let keeps_hl = !matches!(reg, Z80Register::H | Z80Register::L);
+                let mut instructions = if keeps_hl { vec![Z80Instruction::Push { reg: Z80Register::HL }] } else { vec![] };
+                instructions.extend(move_register(Z80Register::HL, index));
+                instructions.push(Z80Instruction::LoadRegister { dst: reg, src: Z80Register::L });
+                if keeps_hl {
+                    instructions.push(Z80Instruction::Pop { reg: Z80Register::HL });
+                }
+                instructions
+            }
+            Value::Register(name) => {
+                let src = match self.parse_register(name) {
+                    Z80Register::BC => Z80Register::C,
+                    Z80Register::DE => Z80Register::E,
+                    Z80Register::HL => Z80Register::L,
+                    src => src,
+                };
+                vec![Z80Instruction::LoadRegister { dst: reg, src }]
+            }
+            // Little-endian: the low byte is at the value's address
+            Value::Memory { base, offset } => vec![Z80Instruction::LoadMemory {
+                reg,
+                addr: memory_address(base, *offset),
+            }],
             _ => vec![Z80Instruction::Comment {
-                text: format!("TODO: load {:?} into A", value),
+                text: format!("TODO: load {:?} into {}", value, reg),
             }],
         }
     }
@@ -650,15 +1864,15 @@ impl CodeGenerator {
     /// Store HL register to a value
     fn store_hl_to_value(&self, value: &Value) -> Vec<Z80Instruction> {
         match value {
-            Value::Register(reg) => {
-                vec![Z80Instruction::LoadRegister {
-                    dst: self.parse_register(reg),
-                    src: Z80Register::HL,
-                }]
-            }
-            Value::Memory { base: _, offset } => {
+            Value::Register(reg) => move_register(self.parse_register(reg), Z80Register::HL),
+            Value::Memory { base, offset } if base == tasks::THREADVAR_BASE => tasks::threadvar_store(*offset as u16),
+            Value::Memory { base, offset } if self.is_byte_global(base, *offset) => vec![
+                Z80Instruction::LoadRegister { dst: Z80Register::A, src: Z80Register::L },
+                Z80Instruction::StoreMemory { addr: memory_address(base, *offset), reg: Z80Register::A },
+            ],
+            Value::Memory { base, offset } => {
                 vec![Z80Instruction::StoreMemory {
-                    addr: MemoryAddress::FrameRelative(*offset as i16),
+                    addr: memory_address(base, *offset),
                     reg: Z80Register::HL,
                 }]
             }
@@ -691,13 +1905,14 @@ impl CodeGenerator {
 
     /// Mangle function name for Z80 (add underscore prefix for now)
     fn mangle_name(&self, name: &str) -> String {
-        format!("_{}", name)
+        abi::routine_symbol(name)
     }
 
     /// Calculate total size of local variables
-    fn calculate_local_size(&self, _function: &Function) -> usize {
-        // TODO: Calculate from function parameters and local variables
-        0
+    fn calculate_local_size(&self, function: &Function) -> usize {
+        // Locals and spill slots are below IX, then the saved IY
+        let bottom = self.iy_slot.map_or(0, i32::from).min(regalloc::frame_bottom(function));
+        bottom.unsigned_abs() as usize
     }
 
     /// Optimize jumps: Convert JP (absolute, 3 bytes) to JR (relative, 2 bytes) when possible.
@@ -793,7 +2008,11 @@ impl CodeGenerator {
 
     /// Calculate the size in bytes of a Z80 instruction.
     /// This is used for offset calculation during jump optimization.
-    fn instruction_size(&self, inst: &Z80Instruction) -> usize {
+    pub fn instruction_size(&self, inst: &Z80Instruction) -> usize {
+        // Exact when the assembler can encode it
+        if let Some(size) = assembler::encoded_size(inst) {
+            return size;
+        }
         match inst {
             // 1-byte instructions
             Z80Instruction::Return => 1,
@@ -811,7 +2030,14 @@ impl CodeGenerator {
             Z80Instruction::Push { .. } => 1,
             Z80Instruction::Pop { .. } => 1,
             Z80Instruction::Add { .. } => 1,
-            Z80Instruction::Subtract { .. } => 1,
+            Z80Instruction::AddWithCarry { .. } => 2, // adc hl, rr (ED prefix)
+            Z80Instruction::Subtract { dst, .. } => {
+                if *dst == Z80Register::HL {
+                    2 // sbc hl, rr (ED prefix)
+                } else {
+                    1
+                }
+            }
             Z80Instruction::Compare { value, .. } => {
                 if value.is_some() {
                     2 // cp, 8-bit immediate
@@ -840,21 +2066,145 @@ impl CodeGenerator {
             Z80Instruction::Call { .. } => 3,
             
             // Memory operations (variable size)
-            Z80Instruction::LoadMemory { addr, .. } => match addr {
+            Z80Instruction::LoadMemory { reg, addr } => match addr {
                 MemoryAddress::Direct(_) => 3, // ld reg, (nn)
                 MemoryAddress::FrameRelative(_) => 3, // ld reg, (ix+d)
                 MemoryAddress::RegisterIndirect(_) => 1, // ld reg, (hl)
+                MemoryAddress::Symbol(_) => direct_size(reg), // ld reg, (nn)
             },
-            Z80Instruction::StoreMemory { addr, .. } => match addr {
+            Z80Instruction::StoreMemory { addr, reg } => match addr {
                 MemoryAddress::Direct(_) => 3, // ld (nn), reg
                 MemoryAddress::FrameRelative(_) => 3, // ld (ix+d), reg
                 MemoryAddress::RegisterIndirect(_) => 1, // ld (hl), reg
+                MemoryAddress::Symbol(_) => direct_size(reg), // ld (nn), reg
             },
             
+            Z80Instruction::LoadAddress { reg, .. } => {
+                if matches!(reg, Z80Register::IX | Z80Register::IY) {
+                    4 // DD/FD prefix + opcode + 16-bit address
+                } else {
+                    3
+                }
+            }
+            Z80Instruction::Increment { reg } | Z80Instruction::Decrement { reg } => {
+                if matches!(reg, Z80Register::IX | Z80Register::IY) {
+                    2 // DD/FD prefix
+                } else {
+                    1
+                }
+            }
+            // DD prefix and displacement
+            Z80Instruction::IncrementMemory { addr } | Z80Instruction::DecrementMemory { addr } => {
+                if matches!(addr, MemoryAddress::FrameRelative(_)) { 3 } else { 1 }
+            }
+            Z80Instruction::SetBit { addr, .. } => if matches!(addr, MemoryAddress::FrameRelative(_)) { 4 } else { 2 },
+            Z80Instruction::ExchangeDeHl => 1,
+            Z80Instruction::Or { .. } | Z80Instruction::SetCarry | Z80Instruction::ComplementCarry => 1,
+            Z80Instruction::Xor { .. } => 2,
+            Z80Instruction::BitTest { .. }
+            | Z80Instruction::ShiftRight { .. }
+            | Z80Instruction::RotateRight { .. } => 2, // CB prefix
+            Z80Instruction::Ldi | Z80Instruction::Ldir => 2, // ED prefix
+            Z80Instruction::DisableInterrupts | Z80Instruction::EnableInterrupts => 1,
+            // ED prefix
+            Z80Instruction::SetInterruptMode { .. }
+            | Z80Instruction::LoadInterruptVector
+            | Z80Instruction::ReturnFromInterrupt
+            | Z80Instruction::PortIn { .. }
+            | Z80Instruction::PortOut { .. } => 2,
+            Z80Instruction::Restart { .. } | Z80Instruction::DefineByte { .. } | Z80Instruction::JumpIndirect => 1,
+            Z80Instruction::DefineWord { .. } => 2,
+
+            // Not parsed, so assume the longest instruction to keep `jr` in range
+            Z80Instruction::Raw { .. } => 4,
             // Comments don't generate code
             Z80Instruction::Comment { .. } => 0,
         }
     }
+
+    /// Estimate the T-states one execution of a Z80 instruction takes:
+    /// conditional jumps count as taken, and `ldir` as one byte moved
+    pub fn instruction_cycles(&self, inst: &Z80Instruction) -> u32 {
+        let is_pair = |reg: &Z80Register| {
+            matches!(reg, Z80Register::AF | Z80Register::BC | Z80Register::DE | Z80Register::HL | Z80Register::SP)
+        };
+        let is_index = |reg: &Z80Register| matches!(reg, Z80Register::IX | Z80Register::IY);
+        match inst {
+            // Not parsed, so unknown
+            Z80Instruction::Label { .. }
+            | Z80Instruction::Raw { .. }
+            | Z80Instruction::Comment { .. }
+            | Z80Instruction::DefineByte { .. }
+            | Z80Instruction::DefineWord { .. } => 0,
+            Z80Instruction::LoadImmediate { reg, .. } | Z80Instruction::LoadAddress { reg, .. } => {
+                if is_index(reg) {
+                    14
+                } else if is_pair(reg) {
+                    10
+                } else {
+                    7
+                }
+            }
+            // A pair moved between pairs is two 8-bit loads
+            Z80Instruction::LoadRegister { dst, .. } => if is_pair(dst) { 8 } else { 4 },
+            Z80Instruction::LoadMemory { reg, addr } | Z80Instruction::StoreMemory { addr, reg } => match addr {
+                MemoryAddress::Direct(_) | MemoryAddress::Symbol(_) => match reg {
+                    Z80Register::A => 13,
+                    Z80Register::HL => 16,
+                    _ => 20,
+                },
+                // Pairs are moved a byte at a time
+                MemoryAddress::FrameRelative(_) => if is_pair(reg) { 38 } else { 19 },
+                MemoryAddress::RegisterIndirect(_) => if is_pair(reg) { 14 } else { 7 },
+            },
+            Z80Instruction::Push { reg } => if is_index(reg) { 15 } else { 11 },
+            Z80Instruction::Pop { reg } => if is_index(reg) { 14 } else { 10 },
+            Z80Instruction::Add { dst, .. } => {
+                if is_index(dst) {
+                    15
+                } else if is_pair(dst) {
+                    11
+                } else {
+                    4
+                }
+            }
+            Z80Instruction::AddWithCarry { .. } => 15,
+            Z80Instruction::Subtract { dst, .. } => if *dst == Z80Register::HL { 15 } else { 4 },
+            Z80Instruction::Compare { value, .. } => if value.is_some() { 7 } else { 4 },
+            Z80Instruction::Or { .. } | Z80Instruction::SetCarry | Z80Instruction::ComplementCarry => 4,
+            Z80Instruction::Xor { .. } => 7,
+            Z80Instruction::BitTest { .. } | Z80Instruction::ShiftRight { .. } | Z80Instruction::RotateRight { .. } => 8,
+            Z80Instruction::Jump { near, .. } | Z80Instruction::JumpConditional { near, .. } => {
+                if *near { 12 } else { 10 }
+            }
+            Z80Instruction::Call { .. } => 17,
+            Z80Instruction::Return => 10,
+            Z80Instruction::Increment { reg } | Z80Instruction::Decrement { reg } => {
+                if is_index(reg) {
+                    10
+                } else if is_pair(reg) {
+                    6
+                } else {
+                    4
+                }
+            }
+            Z80Instruction::IncrementMemory { addr } | Z80Instruction::DecrementMemory { addr } => {
+                if matches!(addr, MemoryAddress::FrameRelative(_)) { 23 } else { 11 }
+            }
+            Z80Instruction::SetBit { addr, .. } => if matches!(addr, MemoryAddress::FrameRelative(_)) { 23 } else { 15 },
+            Z80Instruction::ExchangeDeHl
+            | Z80Instruction::JumpIndirect
+            | Z80Instruction::DisableInterrupts
+            | Z80Instruction::EnableInterrupts => 4,
+            Z80Instruction::Ldi => 16,
+            Z80Instruction::Ldir => 21,
+            Z80Instruction::SetInterruptMode { .. } => 8,
+            Z80Instruction::LoadInterruptVector => 9,
+            Z80Instruction::ReturnFromInterrupt => 14,
+            Z80Instruction::Restart { .. } => 11,
+            Z80Instruction::PortIn { .. } | Z80Instruction::PortOut { .. } => 12,
+        }
+    }
 }
 
 impl Default for CodeGenerator {
@@ -863,6 +2213,33 @@ impl Default for CodeGenerator {
     }
 }
 
+/// Size of `ld reg, (nn)` / `ld (nn), reg`: HL and A have short forms,
+/// other pairs need the ED prefix and index registers the DD/FD prefix
+/// Whether `reg` is IX or IY, which only move to and from other pairs
+/// through the stack
+fn is_index_register(reg: Z80Register) -> bool {
+    matches!(reg, Z80Register::IX | Z80Register::IY)
+}
+
+/// Copy register `src` to `dst`: nothing when they are the same, through
+/// the stack for an index register, otherwise a load
+fn move_register(dst: Z80Register, src: Z80Register) -> Vec<Z80Instruction> {
+    if dst == src {
+        vec![]
+    } else if is_index_register(dst) || is_index_register(src) {
+        vec![Z80Instruction::Push { reg: src }, Z80Instruction::Pop { reg: dst }]
+    } else {
+        vec![Z80Instruction::LoadRegister { dst, src }]
+    }
+}
+
+fn direct_size(reg: &Z80Register) -> usize {
+    match reg {
+        Z80Register::A | Z80Register::HL => 3,
+        _ => 4,
+    }
+}
+
 /// Format Z80 instructions as assembly text
 impl fmt::Display for Z80Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -886,6 +2263,7 @@ impl fmt::Display for Z80Instruction {
                     MemoryAddress::RegisterIndirect(reg_ind) => {
                         write!(f, "    ld {}, ({})", reg, reg_ind)
                     }
+                    MemoryAddress::Symbol(label) => write!(f, "    ld {}, ({})", reg, label),
                 }
             }
             Z80Instruction::StoreMemory { addr, reg } => {
@@ -901,6 +2279,7 @@ impl fmt::Display for Z80Instruction {
                     MemoryAddress::RegisterIndirect(reg_ind) => {
                         write!(f, "    ld ({}), {}", reg_ind, reg)
                     }
+                    MemoryAddress::Symbol(label) => write!(f, "    ld ({}), {}", label, reg),
                 }
             }
             Z80Instruction::Push { reg } => {
@@ -912,8 +2291,15 @@ impl fmt::Display for Z80Instruction {
             Z80Instruction::Add { dst, src } => {
                 write!(f, "    add {}, {}", dst, src)
             }
-            Z80Instruction::Subtract { dst: _, src } => {
-                write!(f, "    sub {}", src) // Z80 uses 'sub' for A, 'sbc hl' for HL
+            Z80Instruction::AddWithCarry { dst, src } => {
+                write!(f, "    adc {}, {}", dst, src)
+            }
+            Z80Instruction::Subtract { dst, src } => {
+                if *dst == Z80Register::HL {
+                    write!(f, "    sbc hl, {}", src)
+                } else {
+                    write!(f, "    sub {}", src)
+                }
             }
             Z80Instruction::Compare { reg, value } => {
                 if let Some(val) = value {
@@ -945,6 +2331,90 @@ impl fmt::Display for Z80Instruction {
             Z80Instruction::Label { name } => {
                 write!(f, "{}:", name)
             }
+            Z80Instruction::LoadAddress { reg, label } => {
+                write!(f, "    ld {}, {}", reg, label)
+            }
+            Z80Instruction::Increment { reg } => {
+                write!(f, "    inc {}", reg)
+            }
+            Z80Instruction::Decrement { reg } => {
+                write!(f, "    dec {}", reg)
+            }
+            Z80Instruction::IncrementMemory { addr } => {
+                write!(f, "    inc {}", addr)
+            }
+            Z80Instruction::DecrementMemory { addr } => {
+                write!(f, "    dec {}", addr)
+            }
+            Z80Instruction::SetBit { bit, addr } => {
+                write!(f, "    set {}, {}", bit, addr)
+            }
+            Z80Instruction::ExchangeDeHl => {
+                write!(f, "    ex de, hl")
+            }
+            Z80Instruction::Or { reg } => {
+                write!(f, "    or {}", reg)
+            }
+            Z80Instruction::Xor { value } => {
+                write!(f, "    xor {}", value)
+            }
+            Z80Instruction::BitTest { bit, reg } => {
+                write!(f, "    bit {}, {}", bit, reg)
+            }
+            Z80Instruction::ShiftRight { reg, arithmetic } => {
+                write!(f, "    {} {}", if *arithmetic { "sra" } else { "srl" }, reg)
+            }
+            Z80Instruction::RotateRight { reg } => {
+                write!(f, "    rr {}", reg)
+            }
+            Z80Instruction::SetCarry => {
+                write!(f, "    scf")
+            }
+            Z80Instruction::ComplementCarry => {
+                write!(f, "    ccf")
+            }
+            Z80Instruction::Ldi => {
+                write!(f, "    ldi")
+            }
+            Z80Instruction::Ldir => {
+                write!(f, "    ldir")
+            }
+            Z80Instruction::DisableInterrupts => {
+                write!(f, "    di")
+            }
+            Z80Instruction::EnableInterrupts => {
+                write!(f, "    ei")
+            }
+            Z80Instruction::SetInterruptMode { mode } => {
+                write!(f, "    im {}", mode)
+            }
+            Z80Instruction::LoadInterruptVector => {
+                write!(f, "    ld i, a")
+            }
+            Z80Instruction::ReturnFromInterrupt => {
+                write!(f, "    reti")
+            }
+            Z80Instruction::PortIn { reg } => {
+                write!(f, "    in {}, (c)", reg)
+            }
+            Z80Instruction::PortOut { reg } => {
+                write!(f, "    out (c), {}", reg)
+            }
+            Z80Instruction::Restart { vector } => {
+                write!(f, "    rst {}", vector)
+            }
+            Z80Instruction::DefineByte { value } => {
+                write!(f, "    db {}", value)
+            }
+            Z80Instruction::DefineWord { label } => {
+                write!(f, "    dw {}", label)
+            }
+            Z80Instruction::JumpIndirect => {
+                write!(f, "    jp (hl)")
+            }
+            // Labels start the line
+            Z80Instruction::Raw { text } if text.ends_with(':') => write!(f, "{}", text),
+            Z80Instruction::Raw { text } => write!(f, "    {}", text),
             Z80Instruction::Comment { text } => {
                 write!(f, "    ; {}", text)
             }
@@ -963,6 +2433,11 @@ mod tests {
         let program = Program {
             functions: vec![],
             globals: vec![],
+            pure_functions: vec![],
+            result_functions: vec![],
+            externals: vec![],
+            byte_globals: vec![],
+            startup: vec![],
         };
         let instructions = codegen.generate(&program);
         assert_eq!(instructions.len(), 0);
@@ -978,10 +2453,18 @@ mod tests {
             return_type: None,
             blocks: vec![BasicBlock::new(entry_label.clone())],
             entry_block: entry_label,
+            loops: vec![],
+            optimize: true,
+            locals: vec![],
         };
         let program = Program {
             functions: vec![function],
             globals: vec![],
+            pure_functions: vec![],
+            result_functions: vec![],
+            externals: vec![],
+            byte_globals: vec![],
+            startup: vec![],
         };
         let instructions = codegen.generate(&program);
         
@@ -989,8 +2472,291 @@ mod tests {
         assert!(instructions.len() > 0);
     }
 
+    #[test]
+    fn test_codegen_expands_intrinsics() {
+        let mut intrinsics = IntrinsicRegistry::new();
+        intrinsics.register("Beep", IntrinsicTemplate::Assembly(vec![Z80Instruction::Restart { vector: 0x38 }]));
+        intrinsics.register(
+            "Flash",
+            IntrinsicTemplate::Ir(vec![
+                Instruction::new(Opcode::Call, vec![Value::Label("Beep".to_string())]),
+                Instruction::new(Opcode::Ret, vec![]),
+            ]),
+        );
+        let mut codegen = CodeGenerator::with_intrinsics(intrinsics);
+        let call = |name: &str| Instruction::new(Opcode::Call, vec![Value::Label(name.to_string())]);
+
+        assert_eq!(codegen.generate_instruction(&call("BEEP")), [Z80Instruction::Restart { vector: 0x38 }]);
+        // Calls inside an IR template are not expanded again; RET leaves
+        // through the epilogue
+        assert_eq!(
+            codegen.generate_instruction(&call("flash")),
+            [
+                Z80Instruction::Call { label: codegen.mangle_name("Beep") },
+                Z80Instruction::LoadRegister { dst: Z80Register::SP, src: Z80Register::IX },
+                Z80Instruction::Pop { reg: Z80Register::IX },
+                Z80Instruction::Return,
+            ]
+        );
+        assert_eq!(codegen.generate_instruction(&call("Other")), [Z80Instruction::Call { label: codegen.mangle_name("Other") }]);
+    }
+
     // ===== Jump Optimization Tests =====
 
+    #[test]
+    fn test_float_ops_call_runtime_routines() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let half = Value::Immediate(0.5f32.to_bits() as i32);
+        let fmul = Instruction::new(Opcode::FMul, vec![local(-4), local(-8), half]);
+
+        assert_eq!(
+            codegen.generate_instruction(&fmul),
+            [
+                Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: 0x3F00 },
+                Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: 0 },
+                Z80Instruction::Push { reg: Z80Register::DE },
+                Z80Instruction::Push { reg: Z80Register::HL },
+                Z80Instruction::LoadMemory { reg: Z80Register::HL, addr: MemoryAddress::FrameRelative(-8) },
+                Z80Instruction::LoadMemory { reg: Z80Register::DE, addr: MemoryAddress::FrameRelative(-6) },
+                Z80Instruction::Call { label: float::FMUL_ROUTINE.to_string() },
+                Z80Instruction::StoreMemory { addr: MemoryAddress::FrameRelative(-4), reg: Z80Register::HL },
+                Z80Instruction::StoreMemory { addr: MemoryAddress::FrameRelative(-2), reg: Z80Register::DE },
+            ]
+        );
+        // Trunc leaves an Integer in HL, so there is nothing to store
+        let trunc = Instruction::new(Opcode::FTrunc, vec![Value::Register("hl".to_string()), local(-4)]);
+        assert_eq!(
+            codegen.generate_instruction(&trunc).last(),
+            Some(&Z80Instruction::Call { label: float::FTRUNC_ROUTINE.to_string() })
+        );
+        assert_eq!(codegen.runtime_calls().collect::<Vec<_>>(), [float::FMUL_ROUTINE, float::FTRUNC_ROUTINE]);
+    }
+
+    #[test]
+    fn test_long_ops_use_de_hl() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let ladd = Instruction::new(Opcode::LAdd, vec![local(-4), local(-8), Value::Immediate(100_000)]);
+        let code = codegen.generate_instruction(&ladd);
+        // 100000 is $000186A0
+        assert_eq!(code[0], Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: 1 });
+        assert_eq!(code[1], Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: 0x86A0 });
+        assert!(code.contains(&Z80Instruction::AddWithCarry { dst: Z80Register::HL, src: Z80Register::BC }));
+
+        let sext = Instruction::new(Opcode::SExt, vec![local(-4), Value::Register("hl".to_string())]);
+        assert!(codegen.generate_instruction(&sext).contains(&Z80Instruction::Label { name: "sext_0".to_string() }));
+        let lcmp = Instruction::new(Opcode::LCmp, vec![local(-4), local(-8), Value::Compare(types::ComparisonKind::Unsigned)]);
+        assert_eq!(
+            codegen.generate_instruction(&lcmp).last(),
+            Some(&Z80Instruction::Call { label: int32::LCMPU_ROUTINE.to_string() })
+        );
+        assert_eq!(codegen.runtime_calls().collect::<Vec<_>>(), [int32::LCMPU_ROUTINE]);
+    }
+
+    #[test]
+    fn test_raise_and_handler_test_record_exception_types() {
+        let mut codegen = CodeGenerator::new();
+        assert!(!codegen.uses_exceptions());
+        let label = |name: &str| Value::Label(name.to_string());
+        let raise = Instruction::new(Opcode::Raise, vec![Value::Immediate(3), label("__exc_type_eparse")]);
+        let text: Vec<String> = codegen.generate_instruction(&raise).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld hl, 3", "    ld de, __exc_type_eparse", "    jp __exc_raise"]);
+        codegen.generate_instruction(&Instruction::new(Opcode::ExcIs, vec![label("__exc_type_word")]));
+        assert!(codegen.uses_exceptions());
+        assert_eq!(codegen.exception_types().collect::<Vec<_>>(), ["__exc_type_eparse", "__exc_type_word"]);
+    }
+
+    #[test]
+    fn test_multiply_and_shift_operands() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let divide = Instruction::new(Opcode::DivU, vec![local(-2), local(-4), Value::Immediate(10)]);
+        let text: Vec<String> = codegen.generate_instruction(&divide).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld hl, 10", "    ex de, hl", "    ld hl, (ix-4)", "    call __divu16", "    ld (ix-2), hl"]);
+        assert_eq!(codegen.runtime_calls().collect::<Vec<_>>(), [arith::DIVU_ROUTINE]);
+
+        let shift = Instruction::new(Opcode::Sar, vec![local(-2), local(-4), Value::Immediate(1)]);
+        let text: Vec<String> = codegen.generate_instruction(&shift).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld hl, (ix-4)", "    sra h", "    rr l", "    ld (ix-2), hl"]);
+    }
+
+    #[test]
+    fn test_byte_ops_are_cheaper_than_their_16_bit_forms() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let add = Instruction::new(Opcode::BAdd, vec![local(-2), local(-4), Value::Immediate(48)]);
+        let code = codegen.generate_instruction(&add);
+        let text: Vec<String> = code.iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld a, (ix-4)", "    ld e, 48", "    add a, e", "    ld l, a", "    ld h, 0", "    ld (ix-2), hl"]);
+
+        let mut cost = |opcode, count| {
+            let code = codegen.generate_instruction(&Instruction::new(opcode, vec![local(-2), local(-4), Value::Immediate(count)]));
+            let cycles = code.iter().map(|inst| codegen.instruction_cycles(inst)).sum::<u32>();
+            (cycles, code.iter().map(|inst| codegen.instruction_size(inst)).sum::<usize>())
+        };
+        assert!(cost(Opcode::BAdd, 48).0 < cost(Opcode::Add, 48).0);
+        assert!(cost(Opcode::BSub, 1).0 < cost(Opcode::Sub, 1).0);
+        let (narrow, wide) = (cost(Opcode::BShr, 3), cost(Opcode::Shr, 3));
+        assert!(narrow.0 < wide.0 && narrow.1 < wide.1, "{:?} against {:?}", narrow, wide);
+    }
+
+    #[test]
+    fn test_new_installs_the_vmt_and_dispose_frees() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let new = Instruction::new(Opcode::New, vec![local(-2), Value::Immediate(5), Value::Label("__vmt_TShape".to_string())]);
+        let text: Vec<String> = codegen.generate_instruction(&new).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld bc, __vmt_TShape", "    ld hl, 8", "    call __new", "    ld (ix-2), hl"]);
+        assert!(codegen.uses_heap());
+
+        let dispose = Instruction::new(Opcode::Dispose, vec![local(-2)]);
+        let text: Vec<String> = codegen.generate_instruction(&dispose).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld hl, (ix-2)", "    call __dispose"]);
+    }
+
+    #[test]
+    fn test_port_io_loads_the_port_into_bc() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let read = Instruction::new(Opcode::In, vec![local(-2), Value::Immediate(0x10), Value::Immediate(1)]);
+        let text: Vec<String> = codegen.generate_instruction(&read).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld bc, 16", "    in a, (c)", "    ld l, a", "    ld h, 0", "    ld (ix-2), hl"]);
+
+        let write = Instruction::new(Opcode::Out, vec![local(-2), local(-4), Value::Immediate(2)]);
+        let text: Vec<String> = codegen.generate_instruction(&write).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text[..4], ["    ld hl, (ix-2)", "    ld b, h", "    ld c, l", "    ld hl, (ix-4)"]);
+        assert_eq!(text[4..], ["    ld a, l", "    out (c), a", "    inc bc", "    ld a, h", "    out (c), a"]);
+    }
+
+    #[test]
+    fn test_asm_block_substitutes_names_and_saves_ix() {
+        let mut codegen = CodeGenerator::new();
+        let body = "ld a, (@X)\nadd a, @Size\nloop:\ncall @Beep".to_string();
+        let references = vec![
+            (7..9, Value::Memory { base: "ix".to_string(), offset: -2 }),
+            (18..23, Value::Immediate(4)),
+            (35..40, Value::Label("Beep".to_string())),
+        ];
+        let block = ir::AsmBlock { body, references, clobbers: vec!["a".to_string(), "ix".to_string()] };
+        let asm = Instruction::new(Opcode::Asm, vec![Value::Asm(Box::new(block))]);
+        let text: Vec<String> = codegen.generate_instruction(&asm).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "    ; asm clobbers a, ix",
+                "    push ix",
+                "    ld a, (ix-2)",
+                "    add a, 4",
+                "loop:",
+                "    call _Beep",
+                "    pop ix",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_method_calls_push_self_before_arguments() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let call = Instruction::new(
+            Opcode::CallMethod,
+            vec![Value::Label("TShape__Move".to_string()), local(-2), Value::Immediate(1)],
+        );
+        let text: Vec<String> = codegen.generate_instruction(&call).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text, ["    ld hl, (ix-2)", "    push hl", "    ld hl, 1", "    push hl", "    call _TShape__Move"]);
+        assert!(!codegen.uses_virtual_calls());
+
+        let call = Instruction::new(Opcode::CallMethod, vec![Value::Immediate(1), local(-2)]);
+        let code = codegen.generate_instruction(&call);
+        assert_eq!(code[2..], classes::virtual_call(1, 0)[..]);
+        assert!(codegen.uses_virtual_calls());
+    }
+
+    #[test]
+    fn test_interface_calls_and_queries_use_the_query_routine() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let iid = || Value::Label("__iid_IShape".to_string());
+        let call = Instruction::new(Opcode::CallIntf, vec![iid(), Value::Immediate(1), local(-2), Value::Immediate(2)]);
+        let code = codegen.generate_instruction(&call);
+        assert_eq!(code[4..], interfaces::interface_call("__iid_IShape", 1, 1)[..]);
+        assert!(codegen.uses_interfaces() && codegen.uses_virtual_calls());
+
+        let is = Instruction::new(Opcode::IntfIs, vec![local(-4), local(-2), iid()]);
+        let text: Vec<String> = codegen.generate_instruction(&is).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "    ld hl, (ix-2)", "    ld de, __iid_IShape", "    call __intf_query",
+                "    ld hl, 0", "    jr z, intf_0", "    inc hl", "intf_0:",
+                "    ld (ix-4), hl",
+            ]
+        );
+        let cast = Instruction::new(Opcode::IntfAs, vec![local(-4), local(-2), iid()]);
+        let text: Vec<String> = codegen.generate_instruction(&cast).iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text[1..3], ["    push hl", "    ld de, __iid_IShape"]);
+        assert_eq!(text[4..7], ["    pop hl", "    jr nz, intf_1", "    ld hl, 0"]);
+    }
+
+    #[test]
+    fn test_set_ops_pass_addresses_in_hl_and_de() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let copy = Instruction::new(Opcode::SetCopy, vec![local(-4), local(-8), Value::Immediate(3)]);
+        let code = codegen.generate_instruction(&copy);
+        assert_eq!(code[4], Z80Instruction::ExchangeDeHl);
+        assert_eq!(&code[9..], [
+            Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: 3 },
+            Z80Instruction::Ldir,
+        ]);
+
+        let member = Instruction::new(
+            Opcode::SetIn,
+            vec![Value::Register("hl".to_string()), Value::Immediate(5), local(-4), Value::Immediate(3)],
+        );
+        let code = codegen.generate_instruction(&member);
+        assert_eq!(code[0], Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: 5 });
+        assert_eq!(&code[code.len() - 2..], [
+            Z80Instruction::LoadImmediate { reg: Z80Register::A, value: 3 },
+            Z80Instruction::Call { label: sets::SET_IN_ROUTINE.to_string() },
+        ]);
+        assert_eq!(codegen.runtime_calls().collect::<Vec<_>>(), [sets::SET_IN_ROUTINE]);
+    }
+
+    #[test]
+    fn test_string_ops_call_runtime_with_max_length_in_a() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        let copy = Instruction::new(
+            Opcode::StrCopy,
+            vec![local(-22), Value::Label("__str_0".to_string()), Value::Immediate(20)],
+        );
+        let code = codegen.generate_instruction(&copy);
+        assert_eq!(code[0], Z80Instruction::LoadAddress { reg: Z80Register::HL, label: "__str_0".to_string() });
+        assert_eq!(code[1], Z80Instruction::ExchangeDeHl);
+        assert_eq!(&code[6..], [
+            Z80Instruction::LoadImmediate { reg: Z80Register::A, value: 20 },
+            Z80Instruction::Call { label: strings::STR_COPY_ROUTINE.to_string() },
+        ]);
+
+        let compare = Instruction::new(
+            Opcode::StrCmp,
+            vec![Value::Register("hl".to_string()), local(-22), local(-44), Value::Condition(IRCondition::LessEqual)],
+        );
+        let code = codegen.generate_instruction(&compare);
+        assert!(code.contains(&Z80Instruction::LoadImmediate { reg: Z80Register::A, value: 0b011 }));
+
+        // Length reads the length byte inline
+        let length = Instruction::new(Opcode::StrLength, vec![Value::Register("hl".to_string()), local(-22)]);
+        let code = codegen.generate_instruction(&length);
+        assert_eq!(&code[4..7], strings::length().as_slice());
+        assert_eq!(
+            codegen.runtime_calls().collect::<Vec<_>>(),
+            [strings::STR_COMPARE_ROUTINE, strings::STR_COPY_ROUTINE]
+        );
+    }
+
     #[test]
     fn test_jump_optimization_convert_jp_to_jr() {
         let codegen = CodeGenerator::new();
@@ -1165,4 +2931,171 @@ mod tests {
             0
         );
     }
+
+    #[test]
+    fn test_threadvar_load_store() {
+        let mut codegen = CodeGenerator::new();
+        let threadvar = Value::Memory { base: tasks::THREADVAR_BASE.to_string(), offset: 2 };
+        let hl = Value::Register("HL".to_string());
+
+        let load = ir::Instruction::new(Opcode::Load, vec![hl.clone(), threadvar.clone()]);
+        assert_eq!(codegen.generate_instruction(&load), tasks::threadvar_load(2));
+        let store = ir::Instruction::new(Opcode::Store, vec![threadvar, hl]);
+        assert_eq!(codegen.generate_instruction(&store), tasks::threadvar_store(2));
+    }
+
+    #[test]
+    fn test_cjump_conditions() {
+        let mut codegen = CodeGenerator::new();
+        let cjump = |condition| ir::Instruction::new(Opcode::CJump, vec![
+            Value::Condition(condition),
+            Value::Label("for_exit".to_string()),
+            Value::Label("for_step".to_string()),
+        ]);
+        let asm = |instructions: Vec<Z80Instruction>| {
+            instructions.iter().map(|i| i.to_string().trim().to_string()).collect::<Vec<_>>()
+        };
+
+        // FOR loop exit test: taken on equality, before the counter is stepped
+        assert_eq!(
+            asm(codegen.generate_instruction(&cjump(IRCondition::Equal))),
+            ["jp z, for_exit", "jp for_step"]
+        );
+        // Empty-range test: end < start
+        assert_eq!(
+            asm(codegen.generate_instruction(&cjump(IRCondition::Less))),
+            ["jp c, for_exit", "jp for_step"]
+        );
+        assert_eq!(
+            asm(codegen.generate_instruction(&cjump(IRCondition::Greater))),
+            ["jp z, for_step", "jp nc, for_exit", "jp for_step"]
+        );
+    }
+
+    #[test]
+    fn test_block_selects_16_bit_idioms() {
+        let mut codegen = CodeGenerator::new();
+        let i = Value::Memory { base: "sp".to_string(), offset: -2 };
+        let label = |name: &str| Value::Label(name.to_string());
+        let kind = Value::Compare(types::ComparisonKind::Signed);
+        // I := I - 1; if I <> 0 then ... ; if I < 0 then ...
+        let mut block = ir::BasicBlock::new("loop".to_string());
+        block.add_instruction(ir::Instruction::new(Opcode::Sub, vec![i.clone(), i.clone(), Value::Immediate(1)]));
+        block.add_instruction(ir::Instruction::new(Opcode::Cmp, vec![i.clone(), Value::Immediate(0), kind.clone()]));
+        block.add_instruction(ir::Instruction::new(Opcode::CJump, vec![Value::Condition(IRCondition::NotEqual), label("loop"), label("done")]));
+        let asm: Vec<String> = codegen.generate_block(&block).iter().map(|i| i.to_string().trim().to_string()).collect();
+        assert_eq!(asm[1..], ["ld hl, (ix-2)", "dec hl", "ld (ix-2), hl", "ld a, h", "or l", "jp nz, loop", "jp done"]);
+
+        // Ordering a signed value still needs the full compare
+        let mut block = ir::BasicBlock::new("test".to_string());
+        block.add_instruction(ir::Instruction::new(Opcode::Cmp, vec![i.clone(), Value::Immediate(0), kind]));
+        block.add_instruction(ir::Instruction::new(Opcode::CJump, vec![Value::Condition(IRCondition::Less), label("neg"), label("done")]));
+        let code = codegen.generate_block(&block);
+        assert!(code.contains(&Z80Instruction::Xor { value: 0x80 }));
+    }
+
+    #[test]
+    fn test_temporaries_live_in_registers_and_iy_across_calls() {
+        let mut codegen = CodeGenerator::new();
+        let local = |offset| Value::Memory { base: "ix".to_string(), offset };
+        // Y := X + 1; Proc; X := X + Y, with X kept in t0 across the call
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = vec![
+            Instruction::new(Opcode::Mov, vec![Value::Temp(0), local(-2)]),
+            Instruction::new(Opcode::Add, vec![Value::Temp(1), Value::Temp(0), Value::Immediate(1)]),
+            Instruction::new(Opcode::Store, vec![local(-4), Value::Temp(1)]),
+            Instruction::new(Opcode::Call, vec![Value::Label("Proc".to_string())]),
+            Instruction::new(Opcode::Add, vec![Value::Temp(2), Value::Temp(0), local(-4)]),
+            Instruction::new(Opcode::Store, vec![local(-2), Value::Temp(2)]),
+        ];
+        let code = codegen.generate_function(&function);
+        let asm: Vec<String> = code.iter().map(|i| i.to_string().trim().to_string()).collect();
+        assert!(!asm.iter().any(|line| line.contains("TODO")), "{:#?}", asm);
+
+        // Only IY survives the call; it is saved below the locals
+        assert_eq!(asm[5..8], ["push iy", "pop de", "ld (ix-6), de"]);
+        assert_eq!(asm[9..13], ["ld hl, (ix-2)", "push hl", "pop iy", "push iy"]);
+        assert!(asm.contains(&"ld de, (ix-4)".to_string()), "{:#?}", asm);
+        assert_eq!(asm[asm.len() - 8..asm.len() - 3], ["push de", "ld de, (ix-6)", "push de", "pop iy", "pop de"]);
+    }
+
+    #[test]
+    fn test_block_folds_updates_into_memory_operands() {
+        let mut codegen = CodeGenerator::new();
+        let i = Value::Memory { base: "sp".to_string(), offset: -2 };
+        let set = Value::Memory { base: "sp".to_string(), offset: -6 };
+        let bytes = Value::Immediate(4);
+        let asm = |code: &[Z80Instruction]| code.iter().map(|i| i.to_string().trim().to_string()).collect::<Vec<_>>();
+        // Inc(I); S := S + [10]; Dec(I); Include(G, 2) of a global set
+        let mut block = ir::BasicBlock::new("body".to_string());
+        block.add_instruction(ir::Instruction::new(Opcode::Add, vec![i.clone(), i.clone(), Value::Immediate(1)]));
+        block.add_instruction(ir::Instruction::new(
+            Opcode::SetIncl,
+            vec![set.clone(), Value::Immediate(10), Value::Immediate(10), bytes.clone()],
+        ));
+        block.add_instruction(ir::Instruction::new(Opcode::Sub, vec![i.clone(), i.clone(), Value::Immediate(1)]));
+        block.add_instruction(ir::Instruction::new(
+            Opcode::SetIncl,
+            vec![Value::Label("_G".to_string()), Value::Immediate(2), Value::Immediate(2), bytes.clone()],
+        ));
+        let code = codegen.generate_block(&block);
+        assert_eq!(
+            asm(&code)[1..],
+            [
+                "inc (ix-2)", "jr nz, fold_0", "inc (ix-1)", "fold_0:",
+                "set 2, (ix-5)",
+                "ld a, (ix-2)", "or a", "jr nz, fold_1", "dec (ix-1)", "fold_1:", "dec (ix-2)",
+                "ld hl, _G", "set 2, (hl)",
+            ]
+        );
+        assert!(codegen.runtime_calls.is_empty());
+        let cycles = |code: &[Z80Instruction]| code.iter().map(|i| codegen.instruction_cycles(i)).sum::<u32>();
+        let step = ir::Instruction::new(Opcode::Add, vec![i.clone(), i.clone(), Value::Immediate(1)]);
+        assert!(cycles(&code[1..5]) < cycles(&CodeGenerator::new().generate_instruction(&step)));
+
+        // A step whose result is compared next stays in HL
+        let mut block = ir::BasicBlock::new("loop".to_string());
+        block.add_instruction(step);
+        block.add_instruction(ir::Instruction::new(Opcode::Cmp, vec![i.clone(), Value::Immediate(10)]));
+        assert!(asm(&codegen.generate_block(&block)).contains(&"inc hl".to_string()));
+
+        // Ranges too wide, and elements known only at run time, call the routine
+        let include = |first, last| {
+            let mut block = ir::BasicBlock::new("incl".to_string());
+            block.add_instruction(ir::Instruction::new(Opcode::SetIncl, vec![set.clone(), first, last, bytes.clone()]));
+            block
+        };
+        for block in [include(Value::Immediate(0), Value::Immediate(9)), include(Value::Register("hl".to_string()), Value::Immediate(3))] {
+            let code = CodeGenerator::new().generate_block(&block);
+            assert!(code.contains(&Z80Instruction::Call { label: sets::SET_INCL_ROUTINE.to_string() }));
+        }
+    }
+
+    #[test]
+    fn test_cmp_with_promotion() {
+        let mut codegen = CodeGenerator::new();
+        let cmp = ir::Instruction::new(Opcode::Cmp, vec![
+            Value::Immediate(-1),
+            Value::Immediate(40000),
+            Value::Compare(types::ComparisonKind::SignedUnsigned),
+        ]);
+        let code = codegen.generate_instruction(&cmp);
+        assert_eq!(code[..3], [
+            Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: 40000 },
+            Z80Instruction::ExchangeDeHl,
+            Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: 0xFFFF },
+        ]);
+        assert_eq!(code[3..], compare::compare16(types::ComparisonKind::SignedUnsigned, "cmp_0")[..]);
+    }
+
+    #[test]
+    fn test_outline_costs() {
+        let codegen = CodeGenerator::new();
+        // PUSH IX; LD IX,0; ADD IX,SP before the body, LD SP,IX; POP IX; RET after
+        let costs = codegen.outline_costs();
+        assert_eq!(costs, OutlineCosts { call: 3, routine: 13 });
+        let out = ir::Instruction::new(Opcode::Out, vec![Value::Immediate(0xFE), Value::Immediate(7), Value::Immediate(1)]);
+        assert_eq!(codegen.ir_instruction_size(&out), codegen.code_size(&CodeGenerator::new().generate_instruction(&out)));
+        assert!(codegen.ir_instruction_size(&out) > 0);
+    }
 }
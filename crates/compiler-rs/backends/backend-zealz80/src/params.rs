@@ -0,0 +1,235 @@
+//! Program parameters (`ParamCount`, `ParamStr`)
+//!
+//! The startup routine [`SAVE_SYMBOL`] records where the target left the
+//! parameter text; `_main` calls it before anything else touches the
+//! registers.
+//! Parameters are the space-separated words of that text:
+//! - **Zeal OS**: DE points at the text and BC holds its length
+//! - **esxDOS** (ZX Spectrum dot commands): HL points at the text, which
+//!   ends at a zero byte, a carriage return or `:`; HL is zero without any
+//! - **CP/M**: the command tail at `$0080`, a length byte followed by the text
+//!
+//! `ParamStr` copies a parameter into a shared buffer of [`BUFFER_SIZE`]
+//! bytes, length first, and returns its address in HL; the buffer is reused
+//! by the next call. `ParamStr(0)` and indices past `ParamCount` give an
+//! empty string, since none of the targets pass the program name.
+
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Start of the parameter text (BSS, 2 bytes)
+pub const TEXT_SYMBOL: &str = "__params_text";
+/// Length of the parameter text (BSS, 2 bytes)
+pub const LENGTH_SYMBOL: &str = "__params_length";
+/// Result buffer of `ParamStr` (BSS)
+pub const BUFFER_SYMBOL: &str = ir::PARAM_BUFFER;
+/// Size of the result buffer: a length byte and up to 255 characters
+pub const BUFFER_SIZE: u16 = 256;
+/// Startup routine recording the parameter text
+pub const SAVE_SYMBOL: &str = ir::PARAM_SAVE_ROUTINE;
+
+const SKIP_SYMBOL: &str = "__params_skip";
+const WORD_SYMBOL: &str = "__params_word";
+
+/// CP/M command tail: length byte, then the text
+const CPM_TAIL: u16 = 0x0080;
+/// Characters ending an esxDOS argument line besides the zero byte
+const ESX_TERMINATORS: [u8; 2] = [b'\r', b':'];
+
+/// Where the target passes the parameter text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamSource {
+    /// Zeal OS: text address in DE, length in BC
+    ZealOs,
+    /// esxDOS dot command: terminated text at HL, or zero
+    EsxDos,
+    /// CP/M command tail at `$0080`
+    CpmTail,
+}
+
+fn symbol(name: &str) -> MemoryAddress {
+    MemoryAddress::Symbol(name.to_string())
+}
+
+fn label(name: &str) -> Z80Instruction {
+    Z80Instruction::Label { name: name.to_string() }
+}
+
+fn jump_if(condition: Condition, target: &str) -> Z80Instruction {
+    Z80Instruction::JumpConditional { condition, label: target.to_string(), near: true }
+}
+
+impl ParamSource {
+    /// `__params_save`: store the text address and length
+    fn save(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let mut code = vec![label(SAVE_SYMBOL)];
+        match self {
+            ParamSource::ZealOs => code.push(StoreMemory { addr: symbol(TEXT_SYMBOL), reg: DE }),
+            ParamSource::EsxDos => {
+                let scan = format!("{}_scan", SAVE_SYMBOL);
+                let saved = format!("{}_done", SAVE_SYMBOL);
+                code.extend([
+                    StoreMemory { addr: symbol(TEXT_SYMBOL), reg: HL },
+                    LoadImmediate { reg: BC, value: 0 },
+                    LoadRegister { dst: A, src: H },
+                    Or { reg: L },
+                    jump_if(Condition::Zero, &saved),
+                    label(&scan),
+                    LoadMemory { reg: A, addr: MemoryAddress::RegisterIndirect(HL) },
+                    Or { reg: A },
+                    jump_if(Condition::Zero, &saved),
+                ]);
+                for terminator in ESX_TERMINATORS {
+                    code.extend([Compare { reg: A, value: Some(terminator) }, jump_if(Condition::Zero, &saved)]);
+                }
+                code.extend([
+                    Increment { reg: HL },
+                    Increment { reg: BC },
+                    Jump { label: scan, near: true },
+                    label(&saved),
+                ]);
+            }
+            ParamSource::CpmTail => code.extend([
+                LoadImmediate { reg: HL, value: CPM_TAIL + 1 },
+                StoreMemory { addr: symbol(TEXT_SYMBOL), reg: HL },
+                LoadMemory { reg: A, addr: MemoryAddress::Direct(CPM_TAIL) },
+                LoadRegister { dst: C, src: A },
+                LoadImmediate { reg: B, value: 0 },
+            ]),
+        }
+        code.extend([StoreMemory { addr: symbol(LENGTH_SYMBOL), reg: BC }, Return]);
+        code
+    }
+}
+
+/// `__params_skip` or `__params_word`: move HL past spaces, or past the
+/// rest of a word, with BC counting the characters left. `__params_skip`
+/// sets Zero only when the text is exhausted.
+fn scanner(name: &str, stop: Condition) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let done = format!("{}_done", name);
+    vec![
+        label(name),
+        LoadRegister { dst: A, src: B },
+        Or { reg: C },
+        jump_if(Condition::Zero, &done),
+        LoadMemory { reg: A, addr: MemoryAddress::RegisterIndirect(HL) },
+        Compare { reg: A, value: Some(b' ') },
+        jump_if(stop, &done),
+        Increment { reg: HL },
+        Decrement { reg: BC },
+        Jump { label: name.to_string(), near: true },
+        label(&done),
+        Return,
+    ]
+}
+
+/// `ParamCount`: the number of words in the text, in HL
+fn param_count() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        label("ParamCount"),
+        LoadMemory { reg: HL, addr: symbol(TEXT_SYMBOL) },
+        LoadMemory { reg: BC, addr: symbol(LENGTH_SYMBOL) },
+        LoadImmediate { reg: DE, value: 0 },
+        label("ParamCount_next"),
+        Call { label: SKIP_SYMBOL.to_string() },
+        jump_if(Condition::Zero, "ParamCount_done"),
+        Increment { reg: DE },
+        Call { label: WORD_SYMBOL.to_string() },
+        Jump { label: "ParamCount_next".to_string(), near: true },
+        label("ParamCount_done"),
+        ExchangeDeHl,
+        Return,
+    ]
+}
+
+/// `ParamStr(I)`: copy word `I` into the buffer and return its address
+fn param_str() -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        label("ParamStr"),
+        // Return address, I
+        Pop { reg: HL },
+        Pop { reg: DE },
+        Push { reg: HL },
+        LoadImmediate { reg: A, value: 0 },
+        StoreMemory { addr: symbol(BUFFER_SYMBOL), reg: A },
+        LoadRegister { dst: A, src: D },
+        Or { reg: E },
+        jump_if(Condition::Zero, "ParamStr_done"),
+        LoadMemory { reg: HL, addr: symbol(TEXT_SYMBOL) },
+        LoadMemory { reg: BC, addr: symbol(LENGTH_SYMBOL) },
+        label("ParamStr_find"),
+        Call { label: SKIP_SYMBOL.to_string() },
+        jump_if(Condition::Zero, "ParamStr_done"),
+        Decrement { reg: DE },
+        LoadRegister { dst: A, src: D },
+        Or { reg: E },
+        jump_if(Condition::Zero, "ParamStr_copy"),
+        Call { label: WORD_SYMBOL.to_string() },
+        Jump { label: "ParamStr_find".to_string(), near: true },
+        // Length = end - start, at most 255
+        label("ParamStr_copy"),
+        Push { reg: HL },
+        Call { label: WORD_SYMBOL.to_string() },
+        Pop { reg: DE },
+        Or { reg: A },
+        Subtract { dst: HL, src: DE },
+        LoadRegister { dst: A, src: H },
+        Or { reg: A },
+        jump_if(Condition::Zero, "ParamStr_length"),
+        LoadImmediate { reg: L, value: 0xFF },
+        label("ParamStr_length"),
+        LoadRegister { dst: C, src: L },
+        LoadImmediate { reg: B, value: 0 },
+        ExchangeDeHl,
+        LoadAddress { reg: DE, label: BUFFER_SYMBOL.to_string() },
+        LoadRegister { dst: A, src: C },
+        StoreMemory { addr: MemoryAddress::RegisterIndirect(DE), reg: A },
+        Increment { reg: DE },
+        Ldir,
+        label("ParamStr_done"),
+        LoadAddress { reg: HL, label: BUFFER_SYMBOL.to_string() },
+        Return,
+    ]
+}
+
+/// Generate the parameter routines for `source`, each with its public name
+pub fn generate_param_routines(source: ParamSource) -> Vec<(String, Vec<Z80Instruction>)> {
+    vec![
+        (SAVE_SYMBOL.to_string(), source.save()),
+        (SKIP_SYMBOL.to_string(), scanner(SKIP_SYMBOL, Condition::NonZero)),
+        (WORD_SYMBOL.to_string(), scanner(WORD_SYMBOL, Condition::Zero)),
+        ("ParamCount".to_string(), param_count()),
+        ("ParamStr".to_string(), param_str()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routine_names() {
+        let names: Vec<String> = generate_param_routines(ParamSource::ZealOs).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec![SAVE_SYMBOL, SKIP_SYMBOL, WORD_SYMBOL, "ParamCount", "ParamStr"]);
+    }
+
+    #[test]
+    fn test_save_per_source() {
+        let zeal = ParamSource::ZealOs.save();
+        assert!(zeal.contains(&Z80Instruction::StoreMemory { addr: symbol(TEXT_SYMBOL), reg: Z80Register::DE }));
+        let esx = ParamSource::EsxDos.save();
+        assert!(esx.contains(&Z80Instruction::Compare { reg: Z80Register::A, value: Some(b':') }));
+        let cpm = ParamSource::CpmTail.save();
+        assert!(cpm.contains(&Z80Instruction::LoadMemory { reg: Z80Register::A, addr: MemoryAddress::Direct(CPM_TAIL) }));
+        for code in [zeal, esx, cpm] {
+            assert_eq!(code.last(), Some(&Z80Instruction::Return));
+        }
+    }
+}
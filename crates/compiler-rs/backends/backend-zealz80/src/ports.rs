@@ -0,0 +1,56 @@
+//! Port I/O (`Port[]`, `PortW[]`)
+//!
+//! `in a, (c)` and `out (c), a` put all of BC on the address bus, so the
+//! whole port address is loaded into BC: peripherals decoding 8 bits see C,
+//! and those decoding 16 see both bytes. A word goes through the port and
+//! the one after it, low byte first:
+//! - **Read**: BC = port; returns the byte, zero-extended, or the word in HL
+//! - **Write**: BC = port, HL = value; the low byte is written, then for a
+//!   word the high byte
+
+use crate::{Z80Instruction, Z80Register};
+
+/// BC = HL
+pub fn hl_into_bc() -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadRegister { dst: Z80Register::B, src: Z80Register::H },
+        Z80Instruction::LoadRegister { dst: Z80Register::C, src: Z80Register::L },
+    ]
+}
+
+/// HL = the byte, or with `word` the word, read from the port at BC
+pub fn read(word: bool) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut code = vec![PortIn { reg: A }, LoadRegister { dst: L, src: A }];
+    if word {
+        code.extend([Increment { reg: BC }, PortIn { reg: A }, LoadRegister { dst: H, src: A }]);
+    } else {
+        code.push(LoadImmediate { reg: H, value: 0 });
+    }
+    code
+}
+
+/// Write the low byte of HL, or with `word` all of HL, to the port at BC
+pub fn write(word: bool) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut code = vec![LoadRegister { dst: A, src: L }, PortOut { reg: A }];
+    if word {
+        code.extend([Increment { reg: BC }, LoadRegister { dst: A, src: H }, PortOut { reg: A }]);
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_ports_use_the_next_port_for_the_high_byte() {
+        let text = |code: Vec<Z80Instruction>| code.iter().map(|inst| inst.to_string().trim().to_string()).collect::<Vec<_>>();
+        assert_eq!(text(read(false)), ["in a, (c)", "ld l, a", "ld h, 0"]);
+        assert_eq!(text(read(true)), ["in a, (c)", "ld l, a", "inc bc", "in a, (c)", "ld h, a"]);
+        assert_eq!(text(write(true)), ["ld a, l", "out (c), a", "inc bc", "ld a, h", "out (c), a"]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Runtime errors
+//!
+//! A failed check calls the entry routine of its error, such as
+//! `__range_error`, which loads the error code into A and jumps to
+//! [`ERROR_ROUTINE`]. That records the code in [`CODE_SYMBOL`], then acts on
+//! the program's [`RuntimeErrorStrategy`]:
+//! - **Halt**: prints `Runtime error NNN` and stops. Zeal OS programs exit
+//!   with the code; elsewhere the CPU loops with interrupts off.
+//! - **Handler**: calls the named `procedure(Code: Byte)`; if it returns,
+//!   so does the check, and the program goes on
+//! - **Code**: returns at once; `ErrorCode` returns the code and clears it
+//!
+//! Codes are Turbo Pascal's.
+
+use runtime_spec::RuntimeErrorStrategy;
+
+use crate::{abi, Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Value out of the bounds of its subrange under {$R+}
+pub const RANGE_ERROR: u8 = 201;
+/// Pointer dereferenced while nil
+pub const NIL_POINTER_ERROR: u8 = 210;
+/// Arithmetic overflow
+pub const OVERFLOW_ERROR: u8 = 215;
+/// Pointer freed twice, never allocated, or whose block was overwritten,
+/// as the debug heap finds
+pub const INVALID_POINTER_ERROR: u8 = 204;
+/// Failed assertion
+pub const ASSERTION_ERROR: u8 = 227;
+
+/// Handle the runtime error whose code is in A. All registers are
+/// clobbered.
+pub const ERROR_ROUTINE: &str = "__runtime_error";
+/// Code of the last runtime error, 0 when none (BSS, 1 byte)
+pub const CODE_SYMBOL: &str = "__runtime_error_code";
+pub const NIL_ERROR_ROUTINE: &str = "__nil_error";
+pub const ASSERT_ERROR_ROUTINE: &str = "__assert_error";
+pub const HEAP_ERROR_ROUTINE: &str = "__heap_error";
+
+/// Entry routines checks call, with the code each reports
+pub const ENTRIES: [(&str, u8); 5] = [
+    (ir::RANGE_ERROR_ROUTINE, RANGE_ERROR),
+    (ir::OVERFLOW_ERROR_ROUTINE, OVERFLOW_ERROR),
+    (NIL_ERROR_ROUTINE, NIL_POINTER_ERROR),
+    (ASSERT_ERROR_ROUTINE, ASSERTION_ERROR),
+    (HEAP_ERROR_ROUTINE, INVALID_POINTER_ERROR),
+];
+
+/// Zeal OS syscalls, entered through `rst $08` with the number in L
+const ZEAL_WRITE: u8 = 1;
+const ZEAL_EXIT: u8 = 15;
+const ZEAL_SYSCALL_VECTOR: u8 = 0x08;
+const ZEAL_STDOUT: u8 = 0;
+/// Spectrum ROM routine printing the character in A to the open channel
+const SPECTRUM_PRINT_VECTOR: u8 = 0x10;
+
+/// How the halt message reaches the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /// Zeal OS standard output
+    ZealOs,
+    /// The Spectrum ROM's print restart
+    Spectrum,
+}
+
+impl Console {
+    /// Print the character in A. All registers are clobbered.
+    pub(crate) fn put_char(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        match self {
+            // Write the byte pushed with A, which sits above F
+            Console::ZealOs => vec![
+                Push { reg: AF },
+                LoadImmediate { reg: HL, value: 1 },
+                Add { dst: HL, src: SP },
+                ExchangeDeHl,
+                LoadImmediate { reg: BC, value: 1 },
+                LoadImmediate { reg: H, value: ZEAL_STDOUT as u16 },
+                LoadImmediate { reg: L, value: ZEAL_WRITE as u16 },
+                Restart { vector: ZEAL_SYSCALL_VECTOR },
+                Pop { reg: AF },
+                Return,
+            ],
+            Console::Spectrum => vec![Restart { vector: SPECTRUM_PRINT_VECTOR }, Return],
+        }
+    }
+
+    fn newline(&self) -> u8 {
+        match self {
+            Console::ZealOs => b'\n',
+            Console::Spectrum => b'\r',
+        }
+    }
+}
+
+fn label(suffix: &str) -> String {
+    format!("{}_{}", ERROR_ROUTINE, suffix)
+}
+
+/// Print the text of [`message_routines`] for `prefix`, then the number in
+/// A and a newline. All registers are clobbered.
+pub(crate) fn print_number(console: Console, prefix: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let label = |suffix: &str| format!("{}_{}", prefix, suffix);
+    let call = |suffix: &str| Call { label: label(suffix) };
+
+    let mut code = vec![
+        Push { reg: AF },
+        LoadAddress { reg: HL, label: label("text") },
+        Label { name: label("print") },
+        LoadMemory { reg: A, addr: MemoryAddress::RegisterIndirect(HL) },
+        Or { reg: A },
+        JumpConditional { condition: Condition::Zero, label: label("digits"), near: true },
+        Push { reg: HL },
+        call("putc"),
+        Pop { reg: HL },
+        Increment { reg: HL },
+        Jump { label: label("print"), near: true },
+        Label { name: label("digits") },
+        Pop { reg: AF },
+    ];
+    for divisor in [100, 10, 1] {
+        code.extend([LoadImmediate { reg: C, value: divisor }, call("digit")]);
+    }
+    code.extend([LoadImmediate { reg: A, value: console.newline() as u16 }, call("putc")]);
+    code
+}
+
+/// The subroutines and zero-terminated `text` [`print_number`] uses for
+/// `prefix`, placed after the code calling them
+pub(crate) fn message_routines(console: Console, prefix: &str, text: &[u8]) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let label = |suffix: &str| format!("{}_{}", prefix, suffix);
+    // Print A div C as a digit, leaving A mod C in A
+    let mut code = vec![
+        Label { name: label("digit") },
+        LoadImmediate { reg: B, value: b'0' as u16 - 1 },
+        Label { name: label("divide") },
+        Increment { reg: B },
+        Subtract { dst: A, src: C },
+        JumpConditional { condition: Condition::NoCarry, label: label("divide"), near: true },
+        Add { dst: A, src: C },
+        Push { reg: AF },
+        LoadRegister { dst: A, src: B },
+        Call { label: label("putc") },
+        Pop { reg: AF },
+        Return,
+        Label { name: label("putc") },
+    ];
+    code.extend(console.put_char());
+    code.push(Label { name: label("text") });
+    code.extend(text.iter().map(|&value| DefineByte { value }));
+    code.push(DefineByte { value: 0 });
+    code
+}
+
+/// Stop the program after a runtime error
+fn stop(console: Option<Console>) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    match console {
+        Some(Console::ZealOs) => vec![
+            LoadMemory { reg: A, addr: MemoryAddress::Symbol(CODE_SYMBOL.to_string()) },
+            LoadRegister { dst: H, src: A },
+            LoadImmediate { reg: L, value: ZEAL_EXIT as u16 },
+            Restart { vector: ZEAL_SYSCALL_VECTOR },
+        ],
+        _ => vec![DisableInterrupts, Label { name: label("stop") }, Jump { label: label("stop"), near: true }],
+    }
+}
+
+/// Generate [`ERROR_ROUTINE`] for `strategy` and the entry routines, each
+/// with its public name. `console` is where a halting program prints its
+/// error, if the target has one.
+pub fn generate_runtime_error_routines(
+    strategy: &RuntimeErrorStrategy,
+    console: Option<Console>,
+) -> Vec<(String, Vec<Z80Instruction>)> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut code = vec![
+        Label { name: ERROR_ROUTINE.to_string() },
+        StoreMemory { addr: MemoryAddress::Symbol(CODE_SYMBOL.to_string()), reg: A },
+    ];
+    match strategy {
+        RuntimeErrorStrategy::Halt => {
+            if let Some(console) = console {
+                code.extend(print_number(console, ERROR_ROUTINE));
+            }
+            code.extend(stop(console));
+            if let Some(console) = console {
+                code.extend(message_routines(console, ERROR_ROUTINE, b"Runtime error "));
+            }
+        }
+        RuntimeErrorStrategy::Handler(handler) => code.extend([
+            LoadRegister { dst: L, src: A },
+            LoadImmediate { reg: H, value: 0 },
+            Push { reg: HL },
+            Call { label: abi::routine_symbol(handler) },
+            Return,
+        ]),
+        RuntimeErrorStrategy::Code => code.push(Return),
+    }
+
+    let mut routines = vec![(ERROR_ROUTINE.to_string(), code)];
+    for (entry, error) in ENTRIES {
+        let entry_code = vec![
+            Label { name: entry.to_string() },
+            LoadImmediate { reg: A, value: error as u16 },
+            Jump { label: ERROR_ROUTINE.to_string(), near: false },
+        ];
+        routines.push((entry.to_string(), entry_code));
+    }
+    routines
+}
+
+/// `ErrorCode`: the code of the last runtime error in HL, then cleared
+pub fn generate_error_code_routine() -> (String, Vec<Z80Instruction>) {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let code = MemoryAddress::Symbol(CODE_SYMBOL.to_string());
+    let name = "ErrorCode".to_string();
+    let routine = vec![
+        Label { name: name.clone() },
+        LoadMemory { reg: A, addr: code.clone() },
+        LoadRegister { dst: L, src: A },
+        LoadImmediate { reg: H, value: 0 },
+        LoadImmediate { reg: A, value: 0 },
+        StoreMemory { addr: code, reg: A },
+        Return,
+    ];
+    (name, routine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(code: &[Z80Instruction]) -> Vec<String> {
+        code.iter().map(|inst| inst.to_string()).collect()
+    }
+
+    #[test]
+    fn test_entries_pass_their_code_to_the_strategy() {
+        let routines = generate_runtime_error_routines(&RuntimeErrorStrategy::Code, None);
+        let names: Vec<&str> = routines.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, [ERROR_ROUTINE, "__range_error", "__overflow_error", "__nil_error", "__assert_error", "__heap_error"]);
+        assert_eq!(text(&routines[0].1), ["__runtime_error:", "    ld (__runtime_error_code), a", "    ret"]);
+        assert_eq!(text(&routines[1].1), ["__range_error:", "    ld a, 201", "    jp __runtime_error"]);
+
+        let handler = RuntimeErrorStrategy::Handler("OnError".to_string());
+        let routines = generate_runtime_error_routines(&handler, None);
+        assert_eq!(
+            text(&routines[0].1)[2..],
+            ["    ld l, a", "    ld h, 0", "    push hl", "    call _OnError", "    ret"]
+        );
+    }
+
+    #[test]
+    fn test_halt_prints_the_code_where_the_target_can() {
+        let routines = generate_runtime_error_routines(&RuntimeErrorStrategy::Halt, Some(Console::ZealOs));
+        let code = &routines[0].1;
+        let message: Vec<u8> = code
+            .iter()
+            .filter_map(|inst| match inst {
+                Z80Instruction::DefineByte { value } => Some(*value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(message, b"Runtime error \0");
+        // Hundreds, tens and units
+        let digits = code.iter().filter(|inst| **inst == Z80Instruction::Call { label: label("digit") }).count();
+        assert_eq!(digits, 3);
+        assert!(code.contains(&Z80Instruction::LoadImmediate { reg: Z80Register::L, value: ZEAL_EXIT as u16 }));
+
+        // Without a console, the program only stops
+        let routines = generate_runtime_error_routines(&RuntimeErrorStrategy::Halt, None);
+        assert_eq!(
+            text(&routines[0].1),
+            [
+                "__runtime_error:",
+                "    ld (__runtime_error_code), a",
+                "    di",
+                "__runtime_error_stop:",
+                "    jr __runtime_error_stop",
+            ]
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Register contents within a block
+//!
+//! Code is generated one IR instruction at a time, each reloading its
+//! operands, so consecutive statements often load a value that is already
+//! in a register: the variable just stored, or the same constant. Two passes
+//! run over the code of each block:
+//!
+//! - **Sinking**: a load moves down to the first instruction using its
+//!   register, past instructions independent of it, so registers are held
+//!   for as short a time as possible; a load overwritten before any use is
+//!   removed.
+//! - **Reload elision**: the contents of each register are tracked (an
+//!   immediate, the address of a label, or the value of a memory slot it
+//!   was loaded from or stored to) and a load of what the register already
+//!   holds is removed.
+//!
+//! Labels, jumps, calls and inline data end what is known, so only
+//! straight-line code is affected.
+
+use crate::{MemoryAddress, Z80Instruction, Z80Register, regalloc};
+
+/// What a register is known to hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Contents {
+    Immediate(u16),
+    Address(String),
+    Memory(MemoryAddress),
+}
+
+/// Registers an instruction reads and writes, and whether it writes memory
+/// or ends straight-line code
+#[derive(Debug, Default)]
+struct Effect {
+    reads: Vec<Z80Register>,
+    writes: Vec<Z80Register>,
+    memory: bool,
+    barrier: bool,
+}
+
+/// Sink the loads of `code` and remove the reloads of register contents
+pub fn schedule(code: &mut Vec<Z80Instruction>) {
+    sink_loads(code);
+    elide_reloads(code);
+}
+
+/// The register and contents an instruction loads, for loads whose source
+/// does not depend on another register than IX
+fn loaded(inst: &Z80Instruction) -> Option<(Z80Register, Contents)> {
+    match inst {
+        Z80Instruction::LoadImmediate { reg, value } => Some((*reg, Contents::Immediate(*value))),
+        Z80Instruction::LoadAddress { reg, label } => Some((*reg, Contents::Address(label.clone()))),
+        Z80Instruction::LoadMemory { reg, addr } if !matches!(addr, MemoryAddress::RegisterIndirect(_)) => {
+            Some((*reg, Contents::Memory(addr.clone())))
+        }
+        _ => None,
+    }
+}
+
+/// The 8-bit halves of a register pair
+fn halves(reg: Z80Register) -> &'static [Z80Register] {
+    match reg {
+        Z80Register::AF => &[Z80Register::A],
+        Z80Register::BC => &[Z80Register::B, Z80Register::C],
+        Z80Register::DE => &[Z80Register::D, Z80Register::E],
+        Z80Register::HL => &[Z80Register::H, Z80Register::L],
+        _ => &[],
+    }
+}
+
+/// Whether `a` and `b` share any bits
+fn overlaps(a: Z80Register, b: Z80Register) -> bool {
+    a == b || halves(a).contains(&b) || halves(b).contains(&a) || halves(a).iter().any(|h| halves(b).contains(h))
+}
+
+/// Whether writing `written` replaces all of `reg`
+fn covers(written: Z80Register, reg: Z80Register) -> bool {
+    written == reg || halves(written).contains(&reg)
+}
+
+/// Registers read to form `addr`
+fn address_registers(addr: &MemoryAddress) -> Vec<Z80Register> {
+    match addr {
+        MemoryAddress::FrameRelative(_) => vec![Z80Register::IX],
+        MemoryAddress::RegisterIndirect(reg) => vec![*reg],
+        MemoryAddress::Direct(_) | MemoryAddress::Symbol(_) => vec![],
+    }
+}
+
+fn effect(inst: &Z80Instruction) -> Effect {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let uses = |reads: Vec<Z80Register>, writes: Vec<Z80Register>| Effect { reads, writes, ..Default::default() };
+    match inst {
+        LoadImmediate { reg, .. } | LoadAddress { reg, .. } => uses(vec![], vec![*reg]),
+        LoadRegister { dst, src } => uses(vec![*src], vec![*dst]),
+        LoadMemory { reg, addr } => uses(address_registers(addr), vec![*reg]),
+        StoreMemory { addr, reg } => {
+            Effect { reads: [address_registers(addr), vec![*reg]].concat(), memory: true, ..Default::default() }
+        }
+        Push { reg } => Effect { reads: vec![*reg, SP], writes: vec![SP], memory: true, ..Default::default() },
+        Pop { reg } => uses(vec![SP], vec![*reg, SP]),
+        Add { dst, src } | AddWithCarry { dst, src } | Subtract { dst, src } => uses(vec![*dst, *src, A], vec![*dst]),
+        Compare { reg, .. } => uses(vec![A, *reg], vec![]),
+        // `or a` only sets the flags
+        Or { reg: A } => uses(vec![A], vec![]),
+        Or { reg } => uses(vec![A, *reg], vec![A]),
+        Xor { .. } => uses(vec![A], vec![A]),
+        BitTest { reg, .. } => uses(vec![*reg], vec![]),
+        ShiftRight { reg, .. } | RotateRight { reg } => uses(vec![*reg], vec![*reg]),
+        Increment { reg } | Decrement { reg } => uses(vec![*reg], vec![*reg]),
+        IncrementMemory { addr } | DecrementMemory { addr } | SetBit { addr, .. } => {
+            Effect { reads: address_registers(addr), memory: true, ..Default::default() }
+        }
+        ExchangeDeHl => uses(vec![DE, HL], vec![DE, HL]),
+        Ldi | Ldir => Effect { reads: vec![BC, DE, HL], writes: vec![BC, DE, HL], memory: true, barrier: false },
+        LoadInterruptVector => uses(vec![A], vec![]),
+        // Ports keep their order among themselves and memory accesses
+        PortIn { reg } => Effect { reads: vec![BC], writes: vec![*reg], memory: true, barrier: false },
+        PortOut { reg } => Effect { reads: vec![BC, *reg], memory: true, ..Default::default() },
+        SetCarry | ComplementCarry | DisableInterrupts | EnableInterrupts | SetInterruptMode { .. } | Comment { .. } => {
+            Effect::default()
+        }
+        Jump { .. }
+        | JumpConditional { .. }
+        | JumpIndirect
+        | Call { .. }
+        | Return
+        | ReturnFromInterrupt
+        | Restart { .. }
+        | Label { .. }
+        | DefineByte { .. }
+        | DefineWord { .. } => Effect { barrier: true, ..Default::default() },
+        // May do anything
+        Raw { .. } => Effect { barrier: true, memory: true, ..Default::default() },
+    }
+}
+
+/// Registers `inst` may change. A call or a jump through a register may
+/// change any register the ABI does not preserve, and raw code any register
+/// temporaries are allocated to (see [`crate::regalloc`])
+pub(crate) fn writes(inst: &Z80Instruction) -> Vec<Z80Register> {
+    match inst {
+        Z80Instruction::Call { .. } | Z80Instruction::Restart { .. } | Z80Instruction::JumpIndirect => {
+            regalloc::CALLER_SAVED.to_vec()
+        }
+        Z80Instruction::Raw { .. } => [regalloc::CALLER_SAVED.as_slice(), &regalloc::ALLOCATABLE].concat(),
+        inst => effect(inst).writes,
+    }
+}
+
+/// Move each load down to the first instruction using its register, and
+/// remove loads overwritten before any use
+fn sink_loads(code: &mut Vec<Z80Instruction>) {
+    for i in (0..code.len()).rev() {
+        let Some((reg, contents)) = loaded(&code[i]) else {
+            continue;
+        };
+        let sources = effect(&code[i]).reads;
+        let from_memory = matches!(contents, Contents::Memory(_));
+        let mut j = i + 1;
+        let mut dead = false;
+        while let Some(next) = code.get(j) {
+            let e = effect(next);
+            let blocked = e.barrier
+                || e.reads.iter().any(|r| overlaps(*r, reg))
+                || e.writes.iter().any(|w| sources.iter().any(|s| overlaps(*w, *s)))
+                || (from_memory && e.memory);
+            if blocked {
+                break;
+            }
+            if e.writes.iter().any(|w| overlaps(*w, reg)) {
+                dead = e.writes.iter().any(|w| covers(*w, reg));
+                break;
+            }
+            j += 1;
+        }
+        if dead {
+            code.remove(i);
+        } else if j > i + 1 {
+            let load = code.remove(i);
+            code.insert(j - 1, load);
+        }
+    }
+}
+
+/// Remove loads of what a register is known to hold
+fn elide_reloads(code: &mut Vec<Z80Instruction>) {
+    let mut known: Vec<(Z80Register, Contents)> = Vec::new();
+    code.retain(|inst| {
+        let e = effect(inst);
+        if e.barrier {
+            known.clear();
+            return true;
+        }
+        let load = loaded(inst);
+        if let Some(load) = &load
+            && known.contains(load)
+        {
+            return false;
+        }
+        if let Z80Instruction::ExchangeDeHl = inst {
+            let swap = |reg: Z80Register| match reg {
+                Z80Register::DE => Z80Register::HL,
+                Z80Register::HL => Z80Register::DE,
+                Z80Register::D => Z80Register::H,
+                Z80Register::H => Z80Register::D,
+                Z80Register::E => Z80Register::L,
+                Z80Register::L => Z80Register::E,
+                other => other,
+            };
+            for (reg, _) in known.iter_mut() {
+                *reg = swap(*reg);
+            }
+            return true;
+        }
+        known.retain(|(reg, contents)| {
+            let written = e.writes.iter().any(|w| overlaps(*w, *reg));
+            let stale = match contents {
+                Contents::Memory(addr) => {
+                    e.memory || e.writes.iter().any(|w| address_registers(addr).iter().any(|a| overlaps(*w, *a)))
+                }
+                _ => false,
+            };
+            !written && !stale
+        });
+        if let Some(load) = load {
+            known.push(load);
+        }
+        // The register stored holds the slot's new value
+        if let Z80Instruction::StoreMemory { addr, reg } = inst
+            && !matches!(addr, MemoryAddress::RegisterIndirect(_))
+        {
+            known.push((*reg, Contents::Memory(addr.clone())));
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Z80Instruction::*;
+    use Z80Register::*;
+
+    fn slot(offset: i16) -> MemoryAddress {
+        MemoryAddress::FrameRelative(offset)
+    }
+
+    #[test]
+    fn test_elides_reload_of_stored_and_constant_values() {
+        // X := 5; Y := X; Y := 5
+        let mut code = vec![
+            LoadImmediate { reg: HL, value: 5 },
+            StoreMemory { addr: slot(-2), reg: HL },
+            LoadMemory { reg: HL, addr: slot(-2) },
+            StoreMemory { addr: slot(-4), reg: HL },
+            LoadImmediate { reg: HL, value: 5 },
+            StoreMemory { addr: slot(-4), reg: HL },
+            Label { name: "next".to_string() },
+            LoadMemory { reg: HL, addr: slot(-4) },
+        ];
+        schedule(&mut code);
+        assert_eq!(
+            code,
+            [
+                LoadImmediate { reg: HL, value: 5 },
+                StoreMemory { addr: slot(-2), reg: HL },
+                StoreMemory { addr: slot(-4), reg: HL },
+                StoreMemory { addr: slot(-4), reg: HL },
+                Label { name: "next".to_string() },
+                LoadMemory { reg: HL, addr: slot(-4) },
+            ]
+        );
+
+        // A store elsewhere, or a write to part of the register, forgets it
+        let mut code = vec![
+            LoadMemory { reg: HL, addr: slot(-2) },
+            StoreMemory { addr: MemoryAddress::RegisterIndirect(DE), reg: A },
+            LoadMemory { reg: HL, addr: slot(-2) },
+            Increment { reg: L },
+            LoadMemory { reg: HL, addr: slot(-2) },
+            StoreMemory { addr: slot(-6), reg: HL },
+        ];
+        schedule(&mut code);
+        assert_eq!(code.iter().filter(|inst| matches!(inst, LoadMemory { .. })).count(), 3);
+    }
+
+    #[test]
+    fn test_sinks_loads_to_first_use_and_drops_dead_ones() {
+        let mut code = vec![
+            LoadImmediate { reg: DE, value: 1 },
+            LoadImmediate { reg: BC, value: 7 },
+            LoadMemory { reg: HL, addr: slot(-2) },
+            Add { dst: HL, src: DE },
+            LoadImmediate { reg: BC, value: 8 },
+            StoreMemory { addr: slot(-2), reg: HL },
+            Push { reg: BC },
+        ];
+        schedule(&mut code);
+        assert_eq!(
+            code,
+            [
+                LoadMemory { reg: HL, addr: slot(-2) },
+                LoadImmediate { reg: DE, value: 1 },
+                Add { dst: HL, src: DE },
+                StoreMemory { addr: slot(-2), reg: HL },
+                LoadImmediate { reg: BC, value: 8 },
+                Push { reg: BC },
+            ]
+        );
+
+        // Loads from memory stay before stores, and every load before a jump
+        let mut code = vec![
+            LoadMemory { reg: DE, addr: slot(-2) },
+            StoreMemory { addr: slot(-2), reg: HL },
+            LoadImmediate { reg: BC, value: 3 },
+            Jump { label: "out".to_string(), near: false },
+        ];
+        let expected = code.clone();
+        schedule(&mut code);
+        assert_eq!(code, expected);
+    }
+}
@@ -0,0 +1,78 @@
+//! Sets (`set of T`)
+//!
+//! A set is a bitset of `size` bytes in memory, one bit per element: the
+//! element numbered `n` from the set's first bit is bit `n and 7` of byte
+//! `n shr 3`. Copies are inline `ldir`; the other operations call runtime
+//! routines, which take the size in A:
+//! - **Clear**: HL = set
+//! - **Include**: HL = set, DE = first element, BC = last element; elements
+//!   outside the set's bytes are ignored
+//! - **Union, difference, intersection**: HL = destination, DE = the other
+//!   set, which is combined into the destination
+//! - **Membership**: HL = set, DE = element; returns HL = 1 if the element
+//!   is in the set, otherwise 0
+//! - **Equality, inclusion**: HL = left set, DE = right set; return HL = 1
+//!   or 0, inclusion when every element of the left set is in the right one
+
+use ir::Opcode;
+
+use crate::{Z80Instruction, Z80Register};
+
+pub const SET_CLEAR_ROUTINE: &str = "__set_clear";
+pub const SET_INCL_ROUTINE: &str = "__set_incl";
+pub const SET_UNION_ROUTINE: &str = "__set_union";
+pub const SET_DIFF_ROUTINE: &str = "__set_diff";
+pub const SET_INTER_ROUTINE: &str = "__set_inter";
+pub const SET_IN_ROUTINE: &str = "__set_in";
+pub const SET_EQ_ROUTINE: &str = "__set_eq";
+pub const SET_SUBSET_ROUTINE: &str = "__set_subset";
+
+/// Runtime routine implementing `opcode`, or None if it is generated inline
+/// (or is not a set operation)
+pub fn routine(opcode: &Opcode) -> Option<&'static str> {
+    Some(match opcode {
+        Opcode::SetClear => SET_CLEAR_ROUTINE,
+        Opcode::SetIncl => SET_INCL_ROUTINE,
+        Opcode::SetUnion => SET_UNION_ROUTINE,
+        Opcode::SetDiff => SET_DIFF_ROUTINE,
+        Opcode::SetInter => SET_INTER_ROUTINE,
+        Opcode::SetIn => SET_IN_ROUTINE,
+        Opcode::SetEq => SET_EQ_ROUTINE,
+        Opcode::SetSubset => SET_SUBSET_ROUTINE,
+        _ => return None,
+    })
+}
+
+/// HL = the address of the frame slot at `offset` from IX. BC is clobbered.
+pub fn frame_address(offset: i16) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        Push { reg: IX },
+        Pop { reg: HL },
+        LoadImmediate { reg: BC, value: offset as u16 },
+        Add { dst: HL, src: BC },
+    ]
+}
+
+/// Copy `size` bytes from (HL) to (DE)
+pub fn copy(size: u16) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: size },
+        Z80Instruction::Ldir,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_address_adds_offset_to_ix() {
+        let code = frame_address(-3);
+        assert_eq!(code[2], Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: 0xFFFD });
+        assert_eq!(code[3], Z80Instruction::Add { dst: Z80Register::HL, src: Z80Register::BC });
+        assert_eq!(routine(&Opcode::SetCopy), None);
+        assert_eq!(routine(&Opcode::SetSubset), Some(SET_SUBSET_ROUTINE));
+    }
+}
@@ -0,0 +1,79 @@
+//! Strings (`string[n]`)
+//!
+//! A string is a length byte followed by its characters. `Length` is
+//! inline; the other operations call runtime routines, which take the
+//! number of characters the destination holds in A and truncate to it:
+//! - **Copy, concatenation**: HL = destination, DE = source
+//! - **Char**: HL = destination, E = the character
+//! - **Compare**: HL = left, DE = right, A = the orderings that give True
+//!   (see [`condition_mask`]); returns HL = 1 or 0
+//! - **Copy(s, index, count)**: HL = destination, DE = source, BC = index,
+//!   count on the stack, removed by the routine
+//! - **Pos**: HL = string, DE = substring; returns HL = the position from
+//!   1, or 0 if it is not found
+//! - **Delete**: HL = string, DE = index, BC = count
+//! - **Insert**: HL = string, DE = the string inserted, BC = index
+
+use ir::{Condition, Opcode};
+
+use crate::{MemoryAddress, Z80Instruction, Z80Register};
+
+pub const STR_COPY_ROUTINE: &str = "__str_copy";
+pub const STR_CHAR_ROUTINE: &str = "__str_char";
+pub const STR_CONCAT_ROUTINE: &str = "__str_concat";
+pub const STR_COMPARE_ROUTINE: &str = "__str_compare";
+pub const STR_SLICE_ROUTINE: &str = "__str_slice";
+pub const STR_POS_ROUTINE: &str = "__str_pos";
+pub const STR_DELETE_ROUTINE: &str = "__str_delete";
+pub const STR_INSERT_ROUTINE: &str = "__str_insert";
+
+/// Runtime routine implementing `opcode`, or None if it is generated inline
+/// (or is not a string operation)
+pub fn routine(opcode: &Opcode) -> Option<&'static str> {
+    Some(match opcode {
+        Opcode::StrCopy | Opcode::StrCopyAt => STR_COPY_ROUTINE,
+        Opcode::StrChar => STR_CHAR_ROUTINE,
+        Opcode::StrConcat => STR_CONCAT_ROUTINE,
+        Opcode::StrCmp => STR_COMPARE_ROUTINE,
+        Opcode::StrSlice => STR_SLICE_ROUTINE,
+        Opcode::StrPos => STR_POS_ROUTINE,
+        Opcode::StrDelete => STR_DELETE_ROUTINE,
+        Opcode::StrInsert => STR_INSERT_ROUTINE,
+        _ => return None,
+    })
+}
+
+/// The orderings of two strings for which `condition` holds, passed to the
+/// compare routine: bit 0 for less, bit 1 for equal, bit 2 for greater
+pub fn condition_mask(condition: &Condition) -> u16 {
+    match condition {
+        Condition::Less => 0b001,
+        Condition::Equal => 0b010,
+        Condition::Greater => 0b100,
+        Condition::LessEqual => 0b011,
+        Condition::GreaterEqual => 0b110,
+        Condition::NotEqual => 0b101,
+    }
+}
+
+/// HL = the length of the string at HL
+pub fn length() -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadMemory { reg: Z80Register::A, addr: MemoryAddress::RegisterIndirect(Z80Register::HL) },
+        Z80Instruction::LoadRegister { dst: Z80Register::L, src: Z80Register::A },
+        Z80Instruction::LoadImmediate { reg: Z80Register::H, value: 0 },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition_masks_combine_orderings() {
+        assert_eq!(condition_mask(&Condition::LessEqual), condition_mask(&Condition::Less) | condition_mask(&Condition::Equal));
+        assert_eq!(condition_mask(&Condition::NotEqual), 0b111 & !condition_mask(&Condition::Equal));
+        assert_eq!(routine(&Opcode::StrLength), None);
+        assert_eq!(routine(&Opcode::StrCmp), Some(STR_COMPARE_ROUTINE));
+    }
+}
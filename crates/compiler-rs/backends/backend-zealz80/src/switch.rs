@@ -0,0 +1,159 @@
+//! Multiway branches (`SWITCH`, from CASE)
+//!
+//! The selector is in HL and each case is a range of ordinal values with the
+//! label it jumps to. Dense cases become a jump table: the selector less the
+//! lowest value is bounds-checked, then indexes a table of `dw` targets
+//! jumped through with `jp (hl)`. Sparse cases become a compare chain, one
+//! test per value or range. Both fall back to the default label.
+//!
+//! A range is tested with one unsigned compare: `selector - low` is below
+//! `high - low + 1` exactly when the selector is in the range, whatever the
+//! signedness of the values.
+
+use crate::{Condition, MemoryAddress, Z80Instruction, Z80Register};
+
+/// Fewest values for which a jump table is used
+pub const MIN_TABLE_VALUES: i64 = 4;
+/// Most entries a jump table may have
+pub const MAX_TABLE_ENTRIES: i64 = 256;
+
+/// Ordinal values `low..=high` of a case, and the label they jump to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwitchCase {
+    pub low: i32,
+    pub high: i32,
+    pub label: String,
+}
+
+/// Whether `cases` are dense enough for a jump table: at least
+/// [`MIN_TABLE_VALUES`] values covering at least half of a table of up to
+/// [`MAX_TABLE_ENTRIES`] entries
+pub fn use_jump_table(cases: &[SwitchCase]) -> bool {
+    let values: i64 = cases.iter().map(|case| case.high as i64 - case.low as i64 + 1).sum();
+    let entries = table_entries(cases);
+    values >= MIN_TABLE_VALUES && entries <= MAX_TABLE_ENTRIES && values * 2 >= entries
+}
+
+/// Entries of the table for `cases`, from the lowest value to the highest
+fn table_entries(cases: &[SwitchCase]) -> i64 {
+    let low = cases.iter().map(|case| case.low).min().unwrap_or(0) as i64;
+    let high = cases.iter().map(|case| case.high).max().unwrap_or(0) as i64;
+    high - low + 1
+}
+
+/// Jump to the case holding HL, through a table named `table`, or to
+/// `default`. HL and DE are clobbered.
+pub fn jump_table(cases: &[SwitchCase], default: &str, table: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let low = cases.iter().map(|case| case.low).min().unwrap_or(0);
+    let entries = table_entries(cases);
+    let mut code = Vec::new();
+    if low != 0 {
+        code.extend(subtract(low as u16));
+    }
+    // In the table when below the number of entries
+    code.extend(subtract(entries as u16));
+    code.push(JumpConditional { condition: Condition::NoCarry, label: default.to_string(), near: false });
+    code.extend([
+        Add { dst: HL, src: DE },
+        Add { dst: HL, src: HL },
+        LoadAddress { reg: DE, label: table.to_string() },
+        Add { dst: HL, src: DE },
+        LoadMemory { reg: E, addr: MemoryAddress::RegisterIndirect(HL) },
+        Increment { reg: HL },
+        LoadMemory { reg: D, addr: MemoryAddress::RegisterIndirect(HL) },
+        ExchangeDeHl,
+        JumpIndirect,
+        Label { name: table.to_string() },
+    ]);
+    for value in low as i64..low as i64 + entries {
+        let label = cases
+            .iter()
+            .find(|case| (case.low as i64..=case.high as i64).contains(&value))
+            .map_or(default, |case| &case.label);
+        code.push(DefineWord { label: label.to_string() });
+    }
+    code
+}
+
+/// Jump to the case holding HL, testing each in turn, or to `default`. DE
+/// is clobbered; HL is kept for the next test.
+pub fn compare_chain(cases: &[SwitchCase], default: &str) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let jump = |condition, label: &str| JumpConditional { condition, label: label.to_string(), near: false };
+    let mut code = Vec::new();
+    for case in cases {
+        let count = case.high as i64 - case.low as i64 + 1;
+        if count > u16::MAX as i64 {
+            // Every value
+            code.push(Jump { label: case.label.clone(), near: false });
+            return code;
+        }
+        code.extend(subtract(case.low as u16));
+        if count == 1 {
+            // `add hl, de` keeps Z
+            code.push(Add { dst: HL, src: DE });
+            code.push(jump(Condition::Zero, &case.label));
+        } else {
+            code.extend(subtract(count as u16));
+            code.push(jump(Condition::Carry, &case.label));
+            code.push(Add { dst: HL, src: DE });
+            code.push(LoadImmediate { reg: DE, value: case.low as u16 });
+            code.push(Add { dst: HL, src: DE });
+        }
+    }
+    code.push(Jump { label: default.to_string(), near: false });
+    code
+}
+
+/// HL = HL - `value`, with DE = `value` and C set when HL was below it
+fn subtract(value: u16) -> Vec<Z80Instruction> {
+    vec![
+        Z80Instruction::LoadImmediate { reg: Z80Register::DE, value },
+        Z80Instruction::Or { reg: Z80Register::A },
+        Z80Instruction::Subtract { dst: Z80Register::HL, src: Z80Register::DE },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn case(low: i32, high: i32, label: &str) -> SwitchCase {
+        SwitchCase { low, high, label: label.to_string() }
+    }
+
+    #[test]
+    fn test_dense_cases_use_jump_table() {
+        let dense = [case(1, 1, "a"), case(3, 5, "b")];
+        assert!(use_jump_table(&dense));
+        assert!(!use_jump_table(&[case(1, 1, "a"), case(100, 102, "b")]));
+        assert!(!use_jump_table(&[case(1, 1, "a"), case(3, 3, "b")]));
+
+        // Entries for 1..5, the gap at 2 going to the default
+        let code = jump_table(&dense, "other", "table");
+        let words: Vec<_> = code
+            .iter()
+            .filter_map(|inst| match inst {
+                Z80Instruction::DefineWord { label } => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(words, ["a", "other", "b", "b", "b"]);
+        assert!(code.contains(&Z80Instruction::JumpIndirect));
+    }
+
+    #[test]
+    fn test_compare_chain_tests_values_and_ranges() {
+        let code = compare_chain(&[case(-1, -1, "a"), case(10, 20, "b")], "other");
+        let text: Vec<String> = code.iter().map(|inst| inst.to_string()).collect();
+        assert_eq!(text[0], "    ld de, 65535");
+        assert_eq!(text[4], "    jp z, a");
+        // 10..20: HL - 10 below 11
+        assert_eq!(text[8], "    ld de, 11");
+        assert_eq!(text[11], "    jp c, b");
+        assert_eq!(text.last().unwrap(), "    jp other");
+    }
+}
@@ -0,0 +1,397 @@
+//! Cooperative task scheduler
+//!
+//! Tasks form a ring of task control blocks (TCBs), each holding the task's
+//! saved stack pointer and a pointer to the next TCB. `Yield` pushes all
+//! registers, saves SP in the current TCB, moves to the next one and pops
+//! its registers, so a switch costs one stack frame of [`TASK_CONTEXT_SIZE`]
+//! bytes per task.
+//!
+//! The main program is the first task. Stacks and TCBs come from pools
+//! sized by the compiler from `TaskStackSize` uses; nothing is allocated at
+//! run time beyond bumping the pool pointers.
+//!
+//! `threadvar`s live in a per-task block placed right after the TCB header
+//! and cleared by `CreateTask`. They are addressed off `__task_current`,
+//! which always points at the running task's TCB.
+//!
+//! By default a new task is linked right after the task that created it,
+//! so it runs next. With `{$ROUNDROBIN}` (or `--round-robin`) tasks are
+//! appended to the ring instead and run in creation order, main program
+//! first, whichever task created them; `Yield` also counts switches in
+//! `__task_switches`. The interleaving of a program then follows from its
+//! source alone, and a debugger can stop at the Nth switch.
+//!
+//! Pascal interface (see `lib/tasks`):
+//! - `procedure CreateTask(Entry, StackSize: Word)`
+//! - `procedure Yield`
+//! - `procedure StartTasks` (never returns; also where finished tasks end up)
+
+use crate::{MemoryAddress, Z80Instruction, Z80Register};
+
+/// Bytes pushed by `Yield`: return address plus AF, BC, DE, HL, IX, IY
+pub const TASK_CONTEXT_SIZE: u16 = 14;
+/// Size of a task control block header: saved SP and next pointer
+pub const TCB_SIZE: u16 = 4;
+/// IR memory base naming the running task's threadvar block
+pub const THREADVAR_BASE: &str = ir::THREADVAR_BASE;
+
+/// TCB of the main program (data section, initially a ring of one)
+pub const MAIN_TCB_SYMBOL: &str = "__task_main";
+/// Pointer to the running task's TCB (data section)
+pub const CURRENT_TASK_SYMBOL: &str = "__task_current";
+/// Top of the unused part of the stack pool (data section)
+pub const STACK_TOP_SYMBOL: &str = "__task_stack_top";
+/// Next free TCB (data section)
+pub const NEXT_TCB_SYMBOL: &str = "__task_tcb_next";
+/// Stack pool (BSS)
+pub const STACK_POOL_SYMBOL: &str = "__task_stacks";
+/// TCB pool (BSS)
+pub const TCB_POOL_SYMBOL: &str = "__task_tcbs";
+/// Last TCB in the ring, which new tasks follow in round-robin mode (data section)
+pub const LAST_TASK_SYMBOL: &str = "__task_last";
+/// Number of task switches so far in round-robin mode (data section)
+pub const TASK_SWITCHES_SYMBOL: &str = "__task_switches";
+/// Routines of the scheduler, as `lib/tasks` declares them
+pub const TASK_ROUTINES: [&str; 3] = ["CreateTask", "Yield", "StartTasks"];
+/// Switch selecting round-robin mode, `{$ROUNDROBIN}` or `{$ROUNDROBIN ON}`
+pub const ROUND_ROBIN_SWITCH: &str = "ROUNDROBIN";
+
+/// Registers saved by `Yield`, in push order
+const CONTEXT: [Z80Register; 6] = [
+    Z80Register::AF,
+    Z80Register::BC,
+    Z80Register::DE,
+    Z80Register::HL,
+    Z80Register::IX,
+    Z80Register::IY,
+];
+
+fn symbol(name: &str) -> MemoryAddress {
+    MemoryAddress::Symbol(name.to_string())
+}
+
+fn store_hl(reg: Z80Register) -> Z80Instruction {
+    Z80Instruction::StoreMemory { addr: MemoryAddress::RegisterIndirect(Z80Register::HL), reg }
+}
+
+fn load_hl(reg: Z80Register) -> Z80Instruction {
+    Z80Instruction::LoadMemory { reg, addr: MemoryAddress::RegisterIndirect(Z80Register::HL) }
+}
+
+/// Load the address of the threadvar at `offset` in the running task's
+/// block into HL. DE is clobbered.
+pub fn threadvar_address(offset: u16) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        LoadMemory { reg: HL, addr: symbol(CURRENT_TASK_SYMBOL) },
+        LoadImmediate { reg: DE, value: TCB_SIZE + offset },
+        Add { dst: HL, src: DE },
+    ]
+}
+
+/// Load the threadvar at `offset` in the running task's block into HL
+pub fn threadvar_load(offset: u16) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    let mut instructions = threadvar_address(offset);
+    instructions.extend([load_hl(E), Increment { reg: HL }, load_hl(D), ExchangeDeHl]);
+    instructions
+}
+
+/// Store HL into the threadvar at `offset` in the running task's block
+pub fn threadvar_store(offset: u16) -> Vec<Z80Instruction> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+    vec![
+        ExchangeDeHl,
+        LoadMemory { reg: HL, addr: symbol(CURRENT_TASK_SYMBOL) },
+        Push { reg: DE },
+        LoadImmediate { reg: DE, value: TCB_SIZE + offset },
+        Add { dst: HL, src: DE },
+        Pop { reg: DE },
+        store_hl(E),
+        Increment { reg: HL },
+        store_hl(D),
+    ]
+}
+
+/// Generate the scheduler routines, each with its public name
+///
+/// `threadvar_size` is the size of the per-task threadvar block that follows
+/// each TCB header. `round_robin` runs tasks in creation order and counts
+/// switches.
+pub fn generate_task_routines(threadvar_size: u16, round_robin: bool) -> Vec<(String, Vec<Z80Instruction>)> {
+    use Z80Instruction::*;
+    use Z80Register::*;
+
+    let mut yield_code = vec![Label { name: "Yield".to_string() }];
+    yield_code.extend(CONTEXT.iter().map(|&reg| Push { reg }));
+    if round_robin {
+        yield_code.extend([
+            LoadMemory { reg: HL, addr: symbol(TASK_SWITCHES_SYMBOL) },
+            Increment { reg: HL },
+            StoreMemory { addr: symbol(TASK_SWITCHES_SYMBOL), reg: HL },
+        ]);
+    }
+    yield_code.extend([
+        // Save SP in the current TCB
+        LoadImmediate { reg: HL, value: 0 },
+        Add { dst: HL, src: SP },
+        ExchangeDeHl,
+        LoadMemory { reg: HL, addr: symbol(CURRENT_TASK_SYMBOL) },
+        store_hl(E),
+        Increment { reg: HL },
+        store_hl(D),
+        Increment { reg: HL },
+        // Switch to the next TCB
+        load_hl(E),
+        Increment { reg: HL },
+        load_hl(D),
+        ExchangeDeHl,
+        StoreMemory { addr: symbol(CURRENT_TASK_SYMBOL), reg: HL },
+        // Restore its SP
+        load_hl(E),
+        Increment { reg: HL },
+        load_hl(D),
+        ExchangeDeHl,
+        LoadRegister { dst: SP, src: HL },
+    ]);
+    yield_code.extend(CONTEXT.iter().rev().map(|&reg| Pop { reg }));
+    yield_code.push(Return);
+
+    // Hand the CPU to the other tasks forever; finished tasks return here
+    let start_tasks = vec![
+        Label { name: "StartTasks".to_string() },
+        Call { label: "Yield".to_string() },
+        Jump { label: "StartTasks".to_string(), near: true },
+    ];
+
+    let mut create_task = vec![
+        Label { name: "CreateTask".to_string() },
+        // Return address, StackSize, Entry
+        Pop { reg: BC },
+        Pop { reg: DE },
+        Pop { reg: HL },
+        Push { reg: BC },
+        Push { reg: HL },
+        // Carve the stack from the top of the pool
+        LoadMemory { reg: HL, addr: symbol(STACK_TOP_SYMBOL) },
+        Push { reg: HL },
+        Compare { reg: A, value: None }, // cp a: clear carry for sbc
+        Subtract { dst: HL, src: DE },
+        StoreMemory { addr: symbol(STACK_TOP_SYMBOL), reg: HL },
+        Pop { reg: HL },
+        // Initial frame: StartTasks, Entry, then an arbitrary register context
+        Pop { reg: BC },
+        LoadAddress { reg: DE, label: "StartTasks".to_string() },
+        Decrement { reg: HL },
+        store_hl(D),
+        Decrement { reg: HL },
+        store_hl(E),
+        Decrement { reg: HL },
+        store_hl(B),
+        Decrement { reg: HL },
+        store_hl(C),
+        LoadImmediate { reg: DE, value: 0u16.wrapping_sub(TASK_CONTEXT_SIZE - 2) },
+        Add { dst: HL, src: DE },
+        // Take a TCB and store the initial SP
+        ExchangeDeHl,
+        LoadMemory { reg: HL, addr: symbol(NEXT_TCB_SYMBOL) },
+        Push { reg: HL },
+        store_hl(E),
+        Increment { reg: HL },
+        store_hl(D),
+        Increment { reg: HL },
+        // new.next := anchor.next, the anchor being the current task or
+        // in round-robin mode the last one
+        Push { reg: HL },
+        LoadMemory { reg: HL, addr: symbol(if round_robin { LAST_TASK_SYMBOL } else { CURRENT_TASK_SYMBOL }) },
+        Increment { reg: HL },
+        Increment { reg: HL },
+        load_hl(E),
+        Increment { reg: HL },
+        load_hl(D),
+        Pop { reg: BC },
+        LoadRegister { dst: A, src: E },
+        StoreMemory { addr: MemoryAddress::RegisterIndirect(BC), reg: A },
+        Increment { reg: BC },
+        LoadRegister { dst: A, src: D },
+        StoreMemory { addr: MemoryAddress::RegisterIndirect(BC), reg: A },
+        Increment { reg: BC },
+    ];
+    if threadvar_size == 0 {
+        create_task.push(StoreMemory { addr: symbol(NEXT_TCB_SYMBOL), reg: BC });
+    } else {
+        // Clear the threadvar block by copying its zeroed first byte forward
+        create_task.extend([
+            Push { reg: HL },
+            LoadRegister { dst: H, src: B },
+            LoadRegister { dst: L, src: C },
+            LoadRegister { dst: D, src: B },
+            LoadRegister { dst: E, src: C },
+            Increment { reg: DE },
+            LoadImmediate { reg: A, value: 0 },
+            store_hl(A),
+        ]);
+        if threadvar_size > 1 {
+            create_task.extend([LoadImmediate { reg: BC, value: threadvar_size - 1 }, Ldir]);
+        }
+        create_task.extend([
+            StoreMemory { addr: symbol(NEXT_TCB_SYMBOL), reg: DE },
+            Pop { reg: HL },
+        ]);
+    }
+    create_task.extend([
+        // anchor.next := new
+        Pop { reg: DE },
+        store_hl(D),
+        Decrement { reg: HL },
+        store_hl(E),
+    ]);
+    if round_robin {
+        create_task.extend([ExchangeDeHl, StoreMemory { addr: symbol(LAST_TASK_SYMBOL), reg: HL }]);
+    }
+    create_task.push(Return);
+
+    vec![
+        ("Yield".to_string(), yield_code),
+        ("StartTasks".to_string(), start_tasks),
+        ("CreateTask".to_string(), create_task),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Condition;
+    use crate::heap::tests::Machine;
+
+    const TRACE: u16 = 0x6200;
+    const TRACE_POINTER: &str = "trace";
+    const THREADVARS: u16 = 2;
+
+    fn count(code: &[Z80Instruction], pred: impl Fn(&Z80Instruction) -> bool) -> usize {
+        code.iter().filter(|i| pred(i)).count()
+    }
+
+    #[test]
+    fn test_yield_saves_full_context() {
+        let routines = generate_task_routines(0, false);
+        let (_, yield_code) = &routines[0];
+        let pushes = count(yield_code, |i| matches!(i, Z80Instruction::Push { .. }));
+        let pops = count(yield_code, |i| matches!(i, Z80Instruction::Pop { .. }));
+        assert_eq!(pushes, CONTEXT.len());
+        assert_eq!(pops, CONTEXT.len());
+        assert_eq!(TASK_CONTEXT_SIZE as usize, 2 + 2 * CONTEXT.len());
+    }
+
+    #[test]
+    fn test_create_task_balances_stack() {
+        let routines = generate_task_routines(0, false);
+        let (name, create) = &routines[2];
+        assert_eq!(name, "CreateTask");
+        // Three arguments popped (return address re-pushed), the rest balanced
+        let pushes = count(create, |i| matches!(i, Z80Instruction::Push { .. }));
+        let pops = count(create, |i| matches!(i, Z80Instruction::Pop { .. }));
+        assert_eq!(pops, pushes + 2);
+        assert_eq!(create.last(), Some(&Z80Instruction::Return));
+    }
+
+    #[test]
+    fn test_create_task_clears_threadvar_block() {
+        let routines = generate_task_routines(6, false);
+        let (_, create) = &routines[2];
+        assert!(create.contains(&Z80Instruction::LoadImmediate { reg: Z80Register::BC, value: 5 }));
+        assert!(create.contains(&Z80Instruction::Ldir));
+        let pushes = count(create, |i| matches!(i, Z80Instruction::Push { .. }));
+        let pops = count(create, |i| matches!(i, Z80Instruction::Pop { .. }));
+        assert_eq!(pops, pushes + 2);
+        // A one-byte block needs no copy
+        let routines = generate_task_routines(1, false);
+        assert!(!routines[2].1.contains(&Z80Instruction::Ldir));
+    }
+
+    /// A task that `rounds` times appends `id` to the trace and yields,
+    /// first creating the tasks in `creates`
+    fn task(id: u8, rounds: u8, creates: &[u8]) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let name = format!("task{}", id as char);
+        let mut code = vec![Label { name: name.clone() }];
+        for &child in creates {
+            code.extend([
+                LoadAddress { reg: HL, label: format!("task{}", child as char) },
+                Push { reg: HL },
+                LoadImmediate { reg: HL, value: 64 },
+                Push { reg: HL },
+                Call { label: "CreateTask".to_string() },
+            ]);
+        }
+        // B counts the rounds, so it must survive each switch
+        code.extend([
+            LoadImmediate { reg: B, value: rounds as u16 },
+            Label { name: format!("{}_loop", name) },
+            LoadImmediate { reg: A, value: id as u16 },
+            LoadMemory { reg: HL, addr: symbol(TRACE_POINTER) },
+            store_hl(A),
+            Increment { reg: HL },
+            StoreMemory { addr: symbol(TRACE_POINTER), reg: HL },
+            Call { label: "Yield".to_string() },
+            Decrement { reg: B },
+            JumpConditional { condition: Condition::NonZero, label: format!("{}_loop", name), near: true },
+            Return,
+        ]);
+        code
+    }
+
+    /// Run a program whose main task `M` creates tasks 1 and 2, task 1
+    /// creating task 3, each recording three rounds; the trace and the
+    /// switch count when the main task returns
+    fn run_tasks(round_robin: bool) -> (String, u16) {
+        let mut code: Vec<Z80Instruction> =
+            generate_task_routines(THREADVARS, round_robin).into_iter().flat_map(|(_, code)| code).collect();
+        code.extend(task(b'M', 3, b"12"));
+        code.extend(task(b'1', 3, b"3"));
+        code.extend(task(b'2', 3, b""));
+        code.extend(task(b'3', 3, b""));
+
+        let mut machine = Machine::new();
+        let state = [
+            (MAIN_TCB_SYMBOL, 0x6000, None),
+            (CURRENT_TASK_SYMBOL, 0x6010, Some(0x6000)),
+            (STACK_TOP_SYMBOL, 0x6012, Some(0x7000)),
+            (NEXT_TCB_SYMBOL, 0x6014, Some(0x6100)),
+            (LAST_TASK_SYMBOL, 0x6016, Some(0x6000)),
+            (TASK_SWITCHES_SYMBOL, 0x6018, Some(0)),
+            (TRACE_POINTER, 0x6020, Some(TRACE)),
+        ];
+        for (name, address, value) in state {
+            machine.symbols.insert(name.to_string(), address);
+            machine.write_word(address, value.unwrap_or(0));
+        }
+        machine.write_word(0x6002, 0x6000);
+
+        machine.call(&code, "taskM", 0);
+        assert_eq!(machine.sp, 0xFFFE, "main stack unbalanced");
+        assert_eq!(machine.read_word(0x6014), 0x6100 + 3 * (TCB_SIZE + THREADVARS));
+        let end = machine.read_word(0x6020);
+        let trace = String::from_utf8(machine.memory[TRACE as usize..end as usize].to_vec()).unwrap();
+        (trace, machine.read_word(0x6018))
+    }
+
+    #[test]
+    fn test_tasks_interleave_reproducibly() {
+        // Each new task runs right after the task that created it
+        assert_eq!(run_tasks(false), ("M213M213M213".to_string(), 0));
+        // Round-robin: creation order, counting every switch
+        assert_eq!(run_tasks(true), ("M123M123M123".to_string(), 12));
+    }
+
+    #[test]
+    fn test_threadvar_access_offsets_past_tcb_header() {
+        let offset = Z80Instruction::LoadImmediate { reg: Z80Register::DE, value: TCB_SIZE + 2 };
+        assert!(threadvar_load(2).contains(&offset));
+        assert!(threadvar_store(2).contains(&offset));
+    }
+}
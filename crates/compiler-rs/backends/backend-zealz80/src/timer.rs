@@ -0,0 +1,93 @@
+//! Tick counter (`GetTicks`, `TicksPerSecond`)
+//!
+//! `GetTicks` returns the low 16 bits of a free-running counter in HL, so
+//! intervals are measured by subtraction, which wraps correctly. The counter
+//! differs per target:
+//! - **Zeal OS**: the millisecond clock read with the `gettime` syscall
+//! - **ZX Spectrum**: the `FRAMES` system variable, counted by the ROM's
+//!   IM1 handler at 50 Hz; it stops under `--interrupt-mode im2` unless the
+//!   handler counts it too
+
+use crate::{MemoryAddress, Z80Instruction, Z80Register};
+
+/// Result of the Zeal OS `gettime` syscall (BSS, 2 bytes)
+pub const TICKS_SYMBOL: &str = "__timer_ticks";
+
+/// Zeal OS `gettime` syscall, entered through `rst $08`
+const ZEAL_GETTIME: u8 = 20;
+const ZEAL_SYSCALL_VECTOR: u8 = 0x08;
+/// Address of the Spectrum `FRAMES` system variable
+const FRAMES: u16 = 0x5C78;
+
+/// Which counter the target provides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerSource {
+    /// Zeal OS millisecond clock
+    ZealOs,
+    /// Spectrum 50 Hz frame counter
+    Frames,
+}
+
+impl TimerSource {
+    /// Counter increments per second
+    pub fn ticks_per_second(&self) -> u16 {
+        match self {
+            TimerSource::ZealOs => 1000,
+            TimerSource::Frames => 50,
+        }
+    }
+
+    /// `GetTicks`: the counter in HL
+    fn get_ticks(&self) -> Vec<Z80Instruction> {
+        use Z80Instruction::*;
+        use Z80Register::*;
+        let mut code = vec![Label { name: "GetTicks".to_string() }];
+        match self {
+            TimerSource::ZealOs => code.extend([
+                // Clock 0 into the result word
+                LoadAddress { reg: DE, label: TICKS_SYMBOL.to_string() },
+                LoadImmediate { reg: H, value: 0 },
+                LoadImmediate { reg: L, value: ZEAL_GETTIME as u16 },
+                Restart { vector: ZEAL_SYSCALL_VECTOR },
+                LoadMemory { reg: HL, addr: MemoryAddress::Symbol(TICKS_SYMBOL.to_string()) },
+            ]),
+            // A 16-bit load cannot be split by the interrupt
+            TimerSource::Frames => code.push(LoadMemory { reg: HL, addr: MemoryAddress::Direct(FRAMES) }),
+        }
+        code.push(Return);
+        code
+    }
+}
+
+/// Generate the timer routines for `source`, each with its public name
+pub fn generate_timer_routines(source: TimerSource) -> Vec<(String, Vec<Z80Instruction>)> {
+    let ticks_per_second = vec![
+        Z80Instruction::Label { name: "TicksPerSecond".to_string() },
+        Z80Instruction::LoadImmediate { reg: Z80Register::HL, value: source.ticks_per_second() },
+        Z80Instruction::Return,
+    ];
+    vec![
+        ("GetTicks".to_string(), source.get_ticks()),
+        ("TicksPerSecond".to_string(), ticks_per_second),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_ticks_per_source() {
+        let routines = generate_timer_routines(TimerSource::Frames);
+        let names: Vec<&str> = routines.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["GetTicks", "TicksPerSecond"]);
+        assert!(routines[0].1.contains(&Z80Instruction::LoadMemory {
+            reg: Z80Register::HL,
+            addr: MemoryAddress::Direct(FRAMES),
+        }));
+
+        let zeal = TimerSource::ZealOs.get_ticks();
+        assert!(zeal.contains(&Z80Instruction::Restart { vector: ZEAL_SYSCALL_VECTOR }));
+        assert_eq!(TimerSource::ZealOs.ticks_per_second(), 1000);
+    }
+}
@@ -0,0 +1,390 @@
+//! Structural AST diff (`spc ast-diff`)
+//!
+//! Compares two parses of a program declaration by declaration and
+//! statement by statement. Nodes are compared without their spans, so
+//! reformatting, comments and moved lines do not show up as changes.
+//! Declarations are matched by kind and name; statements in the same list
+//! are aligned by their longest common subsequence, and changed compound
+//! statements (`if`, loops, `case`, ...) are diffed recursively.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use ast::{Block, Node};
+
+/// What happened to a declaration or statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// One reported difference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Nesting below the top-level declarations
+    pub depth: usize,
+    /// Line in the old source, unless added
+    pub old_line: Option<usize>,
+    /// Line in the new source, unless removed
+    pub new_line: Option<usize>,
+    pub description: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self.kind {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Modified => '~',
+        };
+        let location = match (self.old_line, self.new_line) {
+            (Some(old), Some(new)) if old != new => format!("{} -> {}", old, new),
+            (Some(line), _) | (None, Some(line)) => line.to_string(),
+            (None, None) => String::new(),
+        };
+        write!(f, "{}{} {}: {}", "  ".repeat(self.depth), marker, location, self.description)
+    }
+}
+
+/// Differences from `old` to `new`, with the sources they were parsed from
+pub fn diff(old: &Node, new: &Node, old_source: &str, new_source: &str) -> Vec<Change> {
+    let mut differ = Differ { old_source, new_source, changes: Vec::new() };
+    differ.diff_root(old, new);
+    differ.changes
+}
+
+struct Differ<'a> {
+    old_source: &'a str,
+    new_source: &'a str,
+    changes: Vec<Change>,
+}
+
+impl Differ<'_> {
+    fn diff_root(&mut self, old: &Node, new: &Node) {
+        match (old, new) {
+            (Node::Program(o), Node::Program(n)) => {
+                if o.name != n.name {
+                    self.modified(old, new, 0, format!("program {} renamed to {}", o.name, n.name));
+                }
+                self.diff_block(&o.block, &n.block, 0);
+            }
+            (Node::Unit(o), Node::Unit(n)) => {
+                if o.name != n.name {
+                    self.modified(old, new, 0, format!("unit {} renamed to {}", o.name, n.name));
+                }
+                let (old_interface, old_implementation) = unit_sections(o);
+                let (new_interface, new_implementation) = unit_sections(n);
+                self.diff_declarations(&old_interface, &new_interface, 0, "interface ");
+                self.diff_declarations(&old_implementation, &new_implementation, 0, "");
+                for (old_part, new_part) in [(&o.initialization, &n.initialization), (&o.finalization, &n.finalization)] {
+                    match (old_part, new_part) {
+                        (Some(o), Some(n)) => self.diff_block(o, n, 0),
+                        (Some(o), None) => self.record(ChangeKind::Removed, Some(o), None, 0, self.snippet(o, true)),
+                        (None, Some(n)) => self.record(ChangeKind::Added, None, Some(n), 0, self.snippet(n, false)),
+                        (None, None) => {}
+                    }
+                }
+            }
+            (Node::Library(o), Node::Library(n)) => {
+                if let (Some(o), Some(n)) = (&o.block, &n.block) {
+                    self.diff_block(o, n, 0);
+                }
+            }
+            _ => self.modified(old, new, 0, format!("{} replaced by {}", old.kind(), new.kind())),
+        }
+    }
+
+    /// Declarations, then statements, of two blocks
+    fn diff_block(&mut self, old: &Node, new: &Node, depth: usize) {
+        let (Node::Block(o), Node::Block(n)) = (old, new) else {
+            return self.diff_statements(&[old], &[new], depth);
+        };
+        self.diff_declarations(&declarations(o), &declarations(n), depth, "");
+        let old_statements: Vec<&Node> = o.statements.iter().collect();
+        let new_statements: Vec<&Node> = n.statements.iter().collect();
+        self.diff_statements(&old_statements, &new_statements, depth);
+    }
+
+    /// Match declarations by kind and name; `prefix` marks unit interfaces
+    fn diff_declarations(&mut self, old: &[&Node], new: &[&Node], depth: usize, prefix: &str) {
+        let new_by_key = keyed(new);
+        let old_by_key = keyed(old);
+        for (key, old_decl) in keyed_in_order(old) {
+            let title = format!("{}{}", prefix, title(old_decl));
+            match new_by_key.get(&key) {
+                None => self.record(ChangeKind::Removed, Some(old_decl), None, depth, title),
+                Some(new_decl) if fingerprint(old_decl) != fingerprint(new_decl) => {
+                    self.modified(old_decl, new_decl, depth, title);
+                    if let (Some(old_block), Some(new_block)) = (routine_block(old_decl), routine_block(new_decl)) {
+                        self.diff_block(old_block, new_block, depth + 1);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, new_decl) in keyed_in_order(new) {
+            if !old_by_key.contains_key(&key) {
+                self.record(ChangeKind::Added, None, Some(new_decl), depth, format!("{}{}", prefix, title(new_decl)));
+            }
+        }
+    }
+
+    /// Align two statement lists and report what does not line up
+    fn diff_statements(&mut self, old: &[&Node], new: &[&Node], depth: usize) {
+        let old_prints: Vec<String> = old.iter().map(|node| fingerprint(node)).collect();
+        let new_prints: Vec<String> = new.iter().map(|node| fingerprint(node)).collect();
+        let (mut i, mut j) = (0, 0);
+        for (next_i, next_j) in common_subsequence(&old_prints, &new_prints).into_iter().chain([(old.len(), new.len())]) {
+            self.diff_gap(&old[i..next_i], &new[j..next_j], depth);
+            (i, j) = (next_i + 1, next_j + 1);
+        }
+    }
+
+    /// Statements between two matches: same-kind pairs are modifications
+    fn diff_gap(&mut self, old: &[&Node], new: &[&Node], depth: usize) {
+        let paired = old.len().min(new.len());
+        for (old_stmt, new_stmt) in old.iter().zip(new) {
+            if old_stmt.kind() == new_stmt.kind() {
+                self.diff_statement(old_stmt, new_stmt, depth);
+            } else {
+                self.record(ChangeKind::Removed, Some(old_stmt), None, depth, self.snippet(old_stmt, true));
+                self.record(ChangeKind::Added, None, Some(new_stmt), depth, self.snippet(new_stmt, false));
+            }
+        }
+        for old_stmt in &old[paired..] {
+            self.record(ChangeKind::Removed, Some(old_stmt), None, depth, self.snippet(old_stmt, true));
+        }
+        for new_stmt in &new[paired..] {
+            self.record(ChangeKind::Added, None, Some(new_stmt), depth, self.snippet(new_stmt, false));
+        }
+    }
+
+    /// Two differing statements of the same kind
+    fn diff_statement(&mut self, old: &Node, new: &Node, depth: usize) {
+        let old_body = nested_statements(old);
+        let new_body = nested_statements(new);
+        if old_body.is_empty() && new_body.is_empty() {
+            return self.modified(old, new, depth, self.snippet(new, false));
+        }
+        let description = if header(old) == header(new) {
+            format!("in {}", self.snippet(new, false))
+        } else {
+            self.snippet(new, false)
+        };
+        self.modified(old, new, depth, description);
+        self.diff_statements(&old_body, &new_body, depth + 1);
+    }
+
+    fn modified(&mut self, old: &Node, new: &Node, depth: usize, description: String) {
+        self.record(ChangeKind::Modified, Some(old), Some(new), depth, description);
+    }
+
+    fn record(&mut self, kind: ChangeKind, old: Option<&Node>, new: Option<&Node>, depth: usize, description: String) {
+        self.changes.push(Change {
+            kind,
+            depth,
+            old_line: old.map(|node| node.span().line),
+            new_line: new.map(|node| node.span().line),
+            description,
+        });
+    }
+
+    /// First line of the node's source text
+    fn snippet(&self, node: &Node, old: bool) -> String {
+        const WIDTH: usize = 60;
+        let source = if old { self.old_source } else { self.new_source };
+        let span = node.span();
+        let text = source.get(span.start..span.end).unwrap_or("").lines().next().unwrap_or("").trim();
+        if text.is_empty() {
+            return node.kind().to_string();
+        }
+        match text.char_indices().nth(WIDTH) {
+            Some((cut, _)) => format!("`{}...`", &text[..cut]),
+            None => format!("`{}`", text),
+        }
+    }
+}
+
+/// Interface and implementation declarations of a unit
+fn unit_sections(unit: &ast::Unit) -> (Vec<&Node>, Vec<&Node>) {
+    (
+        unit.interface.as_ref().map(|i| i.children()).unwrap_or_default(),
+        unit.implementation.as_ref().map(|i| i.children()).unwrap_or_default(),
+    )
+}
+
+/// Declarations of a block in declaration order
+fn declarations(block: &Block) -> Vec<&Node> {
+    [
+        &block.label_decls,
+        &block.const_decls,
+        &block.type_decls,
+        &block.var_decls,
+        &block.threadvar_decls,
+        &block.proc_decls,
+        &block.func_decls,
+        &block.operator_decls,
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// How a declaration is named in the report, e.g. `procedure Draw`
+fn title(decl: &Node) -> String {
+    let routine = |kind: &str, class_name: &Option<String>, name: &str| match class_name {
+        Some(class_name) => format!("{} {}.{}", kind, class_name, name),
+        None => format!("{} {}", kind, name),
+    };
+    match decl {
+        Node::VarDecl(v) => format!("var {}", v.names.join(", ")),
+        Node::ConstDecl(c) => format!("const {}", c.name),
+        Node::TypeDecl(t) => format!("type {}", t.name),
+        Node::LabelDecl(l) => format!("label {}", l.labels.join(", ")),
+        Node::ProcDecl(p) => routine("procedure", &p.class_name, &p.name),
+        Node::FuncDecl(f) => routine("function", &f.class_name, &f.name),
+        Node::OperatorDecl(o) => routine("operator", &o.class_name, &o.operator_name),
+        Node::PropertyDecl(p) => format!("property {}", p.name),
+        other => other.kind().to_string(),
+    }
+}
+
+/// Declarations with a key unique in their list: the case-insensitive
+/// title, numbered from the second declaration of the same name (a
+/// forward declaration and its body)
+fn keyed_in_order<'a>(decls: &[&'a Node]) -> Vec<(String, &'a Node)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    decls
+        .iter()
+        .map(|decl| {
+            let name = title(decl).to_lowercase();
+            let count = seen.entry(name.clone()).or_default();
+            *count += 1;
+            let key = if *count == 1 { name } else { format!("{}#{}", name, count) };
+            (key, *decl)
+        })
+        .collect()
+}
+
+fn keyed<'a>(decls: &[&'a Node]) -> HashMap<String, &'a Node> {
+    keyed_in_order(decls).into_iter().collect()
+}
+
+fn routine_block(decl: &Node) -> Option<&Node> {
+    match decl {
+        Node::ProcDecl(p) => Some(&p.block),
+        Node::FuncDecl(f) => Some(&f.block),
+        Node::OperatorDecl(o) => Some(&o.block),
+        _ => None,
+    }
+}
+
+fn is_statement(node: &Node) -> bool {
+    node.kind().ends_with("Stmt") || matches!(node, Node::Block(_))
+}
+
+/// Statements nested directly in a compound statement
+fn nested_statements(node: &Node) -> Vec<&Node> {
+    node.children().into_iter().filter(|child| is_statement(child)).collect()
+}
+
+/// A compound statement without its nested statements: the kind, the
+/// fields that are not nodes, and the other children (conditions, bounds,
+/// case labels)
+fn header(node: &Node) -> String {
+    let mut header = match node {
+        Node::ForStmt(f) => format!("For {} {:?}", f.var_name, f.direction),
+        Node::ForInStmt(f) => format!("ForIn {}", f.var_name),
+        Node::LabeledStmt(l) => format!("Labeled {}", l.label),
+        Node::TryStmt(t) => format!("Try {:?}", t.exception_handlers.iter().map(|h| &h.variable).collect::<Vec<_>>()),
+        other => other.kind().to_string(),
+    };
+    for child in node.children().into_iter().filter(|child| !is_statement(child)) {
+        header.push('|');
+        header.push_str(&fingerprint(child));
+    }
+    header
+}
+
+/// The node's structure without source positions
+fn fingerprint(node: &Node) -> String {
+    const SPAN: &str = "span: Span {";
+    let debug = format!("{:?}", node);
+    let mut print = String::with_capacity(debug.len());
+    let mut rest = debug.as_str();
+    while let Some(start) = rest.find(SPAN) {
+        print.push_str(&rest[..start]);
+        rest = &rest[start + SPAN.len()..];
+        // Spans hold only numbers, so the next brace closes them
+        rest = rest.find('}').map_or("", |end| &rest[end + 1..]);
+    }
+    print.push_str(rest);
+    print
+}
+
+/// Index pairs of a longest common subsequence of `old` and `new`
+fn common_subsequence(old: &[String], new: &[String]) -> Vec<(usize, usize)> {
+    // lengths[i][j]: LCS length of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        let old_ast = Parser::new(old).unwrap().parse().unwrap();
+        let new_ast = Parser::new(new).unwrap().parse().unwrap();
+        diff(&old_ast, &new_ast, old, new).iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_formatting_is_ignored() {
+        let old = "program P;\nvar A: integer;\nbegin\n  A := 1;\nend.\n";
+        let new = "program P;\n\n{ counter }\nvar\n  A : integer;\nbegin A:=1 end.\n";
+        assert!(changes(old, new).is_empty());
+    }
+
+    #[test]
+    fn test_declarations_and_statements() {
+        let old = "program P;\nvar A, B: integer;\nprocedure Q;\nbegin\n  A := 1;\n  if A > 0 then\n    B := 2;\nend;\nbegin\n  Q;\nend.\n";
+        let new = "program P;\nvar A, B: integer;\nconst K = 3;\nprocedure Q;\nbegin\n  A := 1;\n  if A > 0 then\n    B := K;\n  Q;\nend;\nbegin\n  Q;\nend.\n";
+        assert_eq!(
+            changes(old, new),
+            vec![
+                "~ 3 -> 4: procedure Q",
+                "  ~ 6 -> 7: in `if A > 0 then`",
+                "    ~ 7 -> 8: `B := K`",
+                "  + 9: `Q`",
+                "+ 3: const K",
+            ]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Build cache
+//!
+//! An entry is keyed on a hash of the source, the options that change the
+//! output and the directive symbols. Because the files the source includes
+//! are only known after parsing, each entry lists them with their content
+//! hashes, and is reused only while all of them are unchanged.
+//!
+//! Entry `<key>` is two files in the cache directory: `<key>.out`, the
+//! output of the build, and `<key>.deps`, one line per dependency (`dep
+//! <hash> <path>`) and per module to link with (`link <path>`).
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Directory `spc build` keeps its cache in, relative to the working directory
+pub const DEFAULT_CACHE_DIR: &str = ".spc-cache";
+
+/// 64-bit FNV-1a hash, stable across builds of the compiler
+pub fn content_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        // Length first, so ("ab", "c") and ("a", "bc") differ
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Output of an earlier build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedBuild {
+    pub output: Vec<u8>,
+    /// Modules the output must be linked with
+    pub linked_modules: Vec<PathBuf>,
+}
+
+/// Build outputs stored on disk
+#[derive(Debug, Clone)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The build stored under `key`, if every file it depended on is unchanged
+    pub fn lookup(&self, key: u64) -> Option<CachedBuild> {
+        let deps = fs::read_to_string(self.entry(key, "deps")).ok()?;
+        let mut linked_modules = Vec::new();
+        for line in deps.lines() {
+            match line.split_once(' ')? {
+                ("dep", rest) => {
+                    let (hash, path) = rest.split_once(' ')?;
+                    let content = fs::read(path).ok()?;
+                    if format!("{:016x}", content_hash(&[&content])) != hash {
+                        return None;
+                    }
+                }
+                ("link", path) => linked_modules.push(PathBuf::from(path)),
+                _ => return None,
+            }
+        }
+        let output = fs::read(self.entry(key, "out")).ok()?;
+        Some(CachedBuild { output, linked_modules })
+    }
+
+    /// Store a build under `key`, with the files it read besides the source
+    pub fn store(&self, key: u64, dependencies: &[PathBuf], build: &CachedBuild) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut deps = String::new();
+        for path in dependencies {
+            let content = fs::read(path)?;
+            deps.push_str(&format!("dep {:016x} {}\n", content_hash(&[&content]), path.display()));
+        }
+        for path in &build.linked_modules {
+            deps.push_str(&format!("link {}\n", path.display()));
+        }
+        // Output first: an entry without its dependency list is never used
+        fs::write(self.entry(key, "out"), &build.output)?;
+        fs::write(self.entry(key, "deps"), deps)
+    }
+
+    fn entry(&self, key: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", key, extension))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_entry_invalidated_by_dependency_change() {
+        let dir = std::env::temp_dir().join(format!("spc-cache-{}", std::process::id()));
+        let header = dir.join("header.inc");
+        let cache = BuildCache::new(dir.join("cache"));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&header, "const A = 1;").unwrap();
+
+        let key = content_hash(&[b"program P;", b"zealz80"]);
+        assert_ne!(key, content_hash(&[b"program P", b";zealz80"]));
+        assert_eq!(cache.lookup(key), None);
+
+        let build = CachedBuild { output: b"ZOF\0".to_vec(), linked_modules: vec![PathBuf::from("io.zof")] };
+        cache.store(key, std::slice::from_ref(&header), &build).unwrap();
+        assert_eq!(cache.lookup(key), Some(build));
+
+        fs::write(&header, "const A = 2;").unwrap();
+        assert_eq!(cache.lookup(key), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
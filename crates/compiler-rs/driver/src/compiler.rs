@@ -1,29 +1,183 @@
 //! Compiler pipeline orchestration
 
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
 
-use backend_zealz80::{CodeGenerator, Z80Instruction};
-use errors::Diagnostic;
-use ir::{IRBuilder, Program};
-use object_zealz80::{ObjectFile, Section, Symbol, SymbolType, SymbolVisibility};
+use ast::Node;
+use backend_zealz80::abi;
+use backend_zealz80::arith;
+use backend_zealz80::assembler::{self, FixupKind};
+use backend_zealz80::blit::{self, BlitSpec, BlitStrategy};
+use backend_zealz80::classes;
+use backend_zealz80::exceptions;
+use backend_zealz80::heap;
+use backend_zealz80::interfaces;
+use backend_zealz80::files::{self, FileSystem};
+use backend_zealz80::interrupts::{self, InterruptMode};
+use backend_zealz80::intrinsics::{IntrinsicRegistry, IntrinsicTemplate};
+use backend_zealz80::params::{self, ParamSource};
+use backend_zealz80::runtime_errors::{self, Console};
+use backend_zealz80::tasks;
+use backend_zealz80::timer::{self, TimerSource};
+use backend_zealz80::{CodeGenerator, OptimizationGoal, Z80Instruction};
+use errors::render::SourceRenderer;
+use errors::{Diagnostic, DiagnosticFormat};
+use ir::{interp, IRBuilder, Program};
+use lexer::{IdentifierPolicy, Lexer};
+use object_zealz80::map::MemoryMap;
+use object_zealz80::output::ImageFormat;
+use object_zealz80::{linker, DumpOptions, ObjectFile, Relocation, RelocationType, Section, Symbol, SymbolType, SymbolVisibility};
 use parser::Parser;
-use runtime_spec::{TargetPlatform, capabilities};
-use semantics::SemanticAnalyzer;
+use parser::minify::{self, MinifyOptions};
+use parser::upgrade;
+use resources::registers::{RegisterMap, REGISTER_MAP_EXTENSION};
+use resources::{Codepage, CompiledResource};
+use runtime_spec::{RuntimeErrorStrategy, TargetPlatform, capabilities};
+use semantics::{SemanticAnalyzer, UnitCache};
 use semantics::feature_checker;
+use semantics::generics::GenericInstantiator;
+use semantics::stack_usage::StackUsage;
+use semantics::warnings::{WarningChecker, WarningKind};
+
+use crate::ast_diff;
+use crate::cache::{self, BuildCache, CachedBuild};
+use crate::debug_info::{DebugInfo, LocalVariable, DEBUG_INFO_EXTENSION};
+use crate::hooks::PipelineHooks;
+use crate::profile::{self, Profile};
+use crate::stats::SourceStats;
+use crate::summary::ProgramSummary;
+use crate::symbol_dump::SymbolDump;
+use crate::symbol_index::{self, FileIndex, SymbolIndex};
+use crate::targets::{OutputFormat, TargetDefinition};
+use crate::trace_map::{LineTable, Trace, TraceMap};
+use crate::unit_graph::{BuildOutcome, BuildQueue, BuildReport, UnitGraph, UnitUses};
+use symbols::compiled_unit::{CompiledUnit, SPU_EXTENSION, SPU_MAGIC};
+use symbols::{ConstantValue, ParameterMode};
+use tokens::TokenKind;
+use types::{ClassLayout, InterfaceLayout, Type};
+
+/// Instructions an interpreted program may execute before it is stopped
+const INTERPRETER_STEP_LIMIT: usize = 10_000_000;
+
+/// Outcome of running a program on an interpreting target
+pub struct InterpretedRun {
+    /// Everything the program wrote
+    pub output: String,
+    /// Why it stopped early, if it did
+    pub error: Option<String>,
+}
 
 /// Compiler instance that orchestrates the compilation pipeline
 pub struct Compiler {
-    target: TargetPlatform,
+    target: TargetDefinition, // Machine being compiled for
     check_features: bool, // Whether to check feature compatibility
+    resources: Vec<CompiledResource>, // Resources compiled from the last parsed file
+    external_procs: Vec<ExternalRoutine>, // External procedures declared by the last parsed file
+    instance_routines: HashSet<String>, // Routines of the last parsed file made by instantiating generics
+    linked_modules: Vec<PathBuf>, // Modules named by {$L} and compiled units named by `uses` in the last parsed file
+    unit_interface: Option<(String, Vec<symbols::Symbol>)>, // Name and exported symbols when the last parsed file is a unit
+    optimization: OptimizationGoal,
+    optimization_level: u8, // 2 (-O2) adds common subexpression elimination
+    outline: bool, // -Oz: outline repeated instruction sequences into shared routines
+    outlined: Vec<ir::OutlinedRoutine>, // Routines outlined from the last parsed file
+    map_file: Option<String>, // --map: where build and link write the memory map
+    debug_info: bool, // -g: whether build writes the object's line table and locals next to it
+    interrupt_mode: InterruptMode,
+    task_stacks: Vec<u16>, // Stack sizes from the last parsed file's TaskStackSize uses
+    threadvar_size: u16,   // Per-task threadvar block size of the last parsed file
+    imports_tasks: bool,   // Whether the last parsed file uses a unit declaring the scheduler routines
+    uses_params: bool,     // Whether the last parsed file calls ParamCount or ParamStr
+    uses_timer: bool,      // Whether the last parsed file calls GetTicks or TicksPerSecond
+    uses_error_code: bool, // Whether the last parsed file calls ErrorCode
+    uses_report_leaks: bool, // Whether the last parsed file calls ReportLeaks
+    debug_heap_profile: bool, // Whether --debug-heap asked for the debug heap
+    debug_heap: bool,      // The last parsed file's {$DEBUGHEAP}, else --debug-heap
+    round_robin_profile: bool, // Whether --round-robin asked for round-robin tasks
+    round_robin: bool,     // The last parsed file's {$ROUNDROBIN}, else --round-robin
+    runtime_errors: RuntimeErrorStrategy, // The last parsed file's {$RUNTIMEERRORS}, else the target's
+    read_only_data: Vec<(String, Vec<u8>)>, // Typed constants and string literals of the last parsed file
+    variable_images: Vec<(String, Vec<u8>)>, // Initial contents of the last parsed file's structured variables
+    global_sizes: HashMap<String, usize>, // Size of each global variable of the last parsed file, by lowercase name
+    absolute_globals: Vec<(String, u16)>, // The last parsed file's ABSOLUTE global variables and their addresses
+    identifier_policy: IdentifierPolicy, // Which characters identifiers may contain
+    defines: Vec<String>, // Symbols defined with --define, besides the target's
+    hooks: PipelineHooks, // Embedder callbacks between pipeline stages
+    cache: Option<BuildCache>, // Where builds are cached, if anywhere
+    dependencies: Vec<PathBuf>, // Include, resource and compiled unit files the last parsed file read
+    intrinsics: IntrinsicRegistry, // Routines whose calls expand to registered templates
+    class_layouts: Vec<ClassLayout>, // Classes of the last parsed file, with their VMTs
+    interface_layouts: Vec<InterfaceLayout>, // Interfaces of the last parsed file
+    string_literals: Vec<String>, // String literals of the last parsed file, numbered as in its IR
+    console_input: Vec<String>, // Lines ReadLn reads on interpreting targets
+    summary: ProgramSummary, // Units, routines and variables of the last parsed file
+    symbols: SymbolDump, // Every symbol the last analyzed file declared
+    teach: bool, // Whether diagnostics explain the rule they report
+    error_format: DiagnosticFormat, // How diagnostics are printed
+    strict: bool, // Whether --strict selects the strict profile for every file
+    dead_code_warnings: bool, // Whether the code dead code elimination removes is reported
+    deny_warnings: bool, // Whether warnings are reported as errors
+    unit_cache: Option<UnitCache>, // Compiled units read so far, shared by the threads of a build-all
+    warnings: usize, // Warnings reported for the last compiled file
 }
 
 impl Compiler {
     /// Create a new compiler instance with default target (ZealZ80)
     pub fn new() -> Self {
         Self {
-            target: TargetPlatform::ZealZ80,
+            target: TargetDefinition::builtin(TargetPlatform::ZealZ80),
             check_features: true,
+            resources: vec![],
+            external_procs: vec![],
+            instance_routines: HashSet::new(),
+            linked_modules: vec![],
+            unit_interface: None,
+            optimization: OptimizationGoal::Speed,
+            optimization_level: 1,
+            outline: false,
+            outlined: Vec::new(),
+            map_file: None,
+            debug_info: false,
+            interrupt_mode: InterruptMode::Im1,
+            task_stacks: vec![],
+            threadvar_size: 0,
+            imports_tasks: false,
+            uses_params: false,
+            uses_timer: false,
+            uses_error_code: false,
+            uses_report_leaks: false,
+            debug_heap_profile: false,
+            debug_heap: false,
+            round_robin_profile: false,
+            round_robin: false,
+            runtime_errors: RuntimeErrorStrategy::Halt,
+            read_only_data: vec![],
+            variable_images: vec![],
+            global_sizes: HashMap::new(),
+            absolute_globals: vec![],
+            identifier_policy: IdentifierPolicy::Ascii,
+            defines: vec![],
+            hooks: PipelineHooks::default(),
+            cache: None,
+            dependencies: vec![],
+            intrinsics: IntrinsicRegistry::new(),
+            class_layouts: vec![],
+            interface_layouts: vec![],
+            string_literals: vec![],
+            console_input: vec![],
+            summary: ProgramSummary::default(),
+            symbols: SymbolDump::default(),
+            teach: false,
+            error_format: DiagnosticFormat::Human,
+            dead_code_warnings: false,
+            deny_warnings: false,
+            unit_cache: None,
+            warnings: 0,
+            strict: false,
         }
     }
     
@@ -31,8 +185,8 @@ impl Compiler {
     #[allow(dead_code)] // Public API method
     pub fn new_with_target(target: TargetPlatform) -> Self {
         Self {
-            target,
-            check_features: true,
+            target: TargetDefinition::builtin(target),
+            ..Self::new()
         }
     }
     
@@ -40,23 +194,167 @@ impl Compiler {
     #[allow(dead_code)] // Public API method
     pub fn new_without_feature_check(target: TargetPlatform) -> Self {
         Self {
-            target,
+            target: TargetDefinition::builtin(target),
             check_features: false,
+            ..Self::new()
         }
     }
     
     /// Get the current target platform
     #[allow(dead_code)] // Public API method
     pub fn target(&self) -> TargetPlatform {
-        self.target
+        self.target.platform
     }
     
-    /// Set the target platform
-    #[allow(dead_code)] // Public API method
-    pub fn set_target(&mut self, target: TargetPlatform) {
+    /// Set the machine to compile for
+    pub fn set_target(&mut self, target: TargetDefinition) {
         self.target = target;
     }
     
+    /// Set the code-size/speed tradeoff for generated code
+    pub fn set_optimization_goal(&mut self, goal: OptimizationGoal) {
+        self.optimization = goal;
+    }
+
+    /// Set how hard the IR is optimized: level 2 also eliminates common
+    /// subexpressions within basic blocks
+    pub fn set_optimization_level(&mut self, level: u8) {
+        self.optimization_level = level;
+    }
+
+    /// Set whether repeated instruction sequences are outlined into shared
+    /// routines (`-Oz`)
+    pub fn set_outlining(&mut self, enabled: bool) {
+        self.outline = enabled;
+    }
+
+    /// Set the file `build` and `link` write a memory map of the linked
+    /// program to
+    pub fn set_map_file(&mut self, path: Option<String>) {
+        self.map_file = path;
+    }
+
+    /// Set whether `build` writes debug info next to the object (`-g`)
+    pub fn set_debug_info(&mut self, enabled: bool) {
+        self.debug_info = enabled;
+    }
+
+    /// Set which characters identifiers may contain
+    pub fn set_identifier_policy(&mut self, policy: IdentifierPolicy) {
+        self.identifier_policy = policy;
+    }
+
+    /// Define a symbol for conditional compilation
+    pub fn define(&mut self, symbol: &str) {
+        self.defines.push(symbol.to_uppercase());
+    }
+
+    /// Use the debug heap, checking instances for corruption and tracking
+    /// leaks, unless a file turns it off with {$DEBUGHEAP OFF}
+    pub fn set_debug_heap(&mut self, enabled: bool) {
+        self.debug_heap_profile = enabled;
+    }
+
+    /// Run tasks in creation order and count task switches, unless a file
+    /// turns it off with {$ROUNDROBIN OFF}
+    pub fn set_round_robin(&mut self, enabled: bool) {
+        self.round_robin_profile = enabled;
+    }
+
+    /// Explain each diagnostic that has an error code with the rule it
+    /// reports, an example and a reference (see [`errors::codes`])
+    pub fn set_teaching(&mut self, enabled: bool) {
+        self.teach = enabled;
+    }
+
+    /// Print diagnostics as text, with their source lines, or as JSON lines
+    /// (`--error-format`)
+    pub fn set_error_format(&mut self, format: DiagnosticFormat) {
+        self.error_format = format;
+    }
+
+    /// Compile every file under the strict profile, as if it began with
+    /// {$MODE STRICT} (see [`semantics::STRICT_SWITCH`])
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    /// Warn about each unreachable statement and unused routine dead code
+    /// elimination removes (see [`semantics::dead_code`])
+    pub fn set_dead_code_warnings(&mut self, enabled: bool) {
+        self.dead_code_warnings = enabled;
+    }
+
+    /// Report every warning as an error, so it fails the build
+    pub fn set_deny_warnings(&mut self, enabled: bool) {
+        self.deny_warnings = enabled;
+    }
+
+    /// Whether the file `parser` read is compiled under the strict profile
+    fn is_strict(&self, parser: &Parser) -> bool {
+        self.strict || parser.switch(semantics::STRICT_SWITCH) == Some(true)
+    }
+
+    /// Set the Z80 interrupt mode set up at startup
+    pub fn set_interrupt_mode(&mut self, mode: InterruptMode) {
+        self.interrupt_mode = mode;
+    }
+
+    /// Run `hook` on the AST after each parse, before semantic analysis
+    #[allow(dead_code)] // Public API method
+    pub fn add_post_parse_hook(&mut self, hook: impl FnMut(&mut Node) + Send + 'static) {
+        self.hooks.post_parse.push(Box::new(hook));
+    }
+
+    /// Run `hook` on the AST and diagnostics after semantic analysis
+    #[allow(dead_code)] // Public API method
+    pub fn add_post_semantics_hook(&mut self, hook: impl FnMut(&Node, &mut Vec<Diagnostic>) + Send + 'static) {
+        self.hooks.post_semantics.push(Box::new(hook));
+    }
+
+    /// Run `hook` on the IR before code is generated from it
+    #[allow(dead_code)] // Public API method
+    pub fn add_pre_codegen_hook(&mut self, hook: impl FnMut(&mut Program) + Send + 'static) {
+        self.hooks.pre_codegen.push(Box::new(hook));
+    }
+
+    /// Expand calls to the routine `name` from `template` instead of
+    /// calling it; the routine is then not linked
+    #[allow(dead_code)] // Public API method
+    pub fn register_intrinsic(&mut self, name: &str, template: IntrinsicTemplate) {
+        self.intrinsics.register(name, template);
+    }
+
+    /// Set the lines ReadLn reads when the target interprets programs
+    pub fn set_console_input(&mut self, lines: Vec<String>) {
+        self.console_input = lines;
+    }
+
+    /// Units, routines and variables of the last file checked or compiled
+    pub fn summary(&self) -> &ProgramSummary {
+        &self.summary
+    }
+
+    /// Whether the target runs programs instead of building them
+    pub fn interprets(&self) -> bool {
+        self.target.output == OutputFormat::Interpreted
+    }
+
+    /// Reuse the output of earlier builds kept in `dir`, or always rebuild (None)
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.cache = dir.map(BuildCache::new);
+    }
+
+    /// What `-Oz` outlined from the last parsed file, if anything
+    pub fn outlining_report(&self) -> Option<String> {
+        if self.outlined.is_empty() {
+            return None;
+        }
+        let calls: usize = self.outlined.iter().map(|routine| routine.calls).sum();
+        let saved: u32 = self.outlined.iter().map(|routine| routine.bytes_saved).sum();
+        Some(format!("Outlined {} sequence(s) into {} routine(s), saving {} bytes", calls, self.outlined.len(), saved))
+    }
+
     /// Enable or disable feature checking
     #[allow(dead_code)] // Public API method
     pub fn set_feature_checking(&mut self, enabled: bool) {
@@ -65,13 +363,31 @@ impl Compiler {
 
     /// Compile a Pascal source file to an object file
     pub fn compile_file(&mut self, input_file: &str, output_file: Option<&str>) -> Result<(), String> {
-        // Read source file
-        let source = fs::read_to_string(input_file)
-            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        self.warnings = 0;
+        let source = self.read_source(input_file)?;
+
+        // Reuse the output of an identical earlier build
+        let cache_key = self.cache_key(input_file, &source);
+        if let Some(cached) = self.build_cache().and_then(|cache| cache.lookup(cache_key)) {
+            let output_path = output_file
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| self.default_output_file(input_file, cached.output.starts_with(SPU_MAGIC)));
+            fs::write(&output_path, &cached.output)
+                .map_err(|e| format!("Failed to create output file '{}': {}", output_path, e))?;
+            println!("Generated: {} (cached)", output_path);
+            for module in &cached.linked_modules {
+                println!("Link with: {}", module.display());
+            }
+            return self.write_build_map(&output_path, &cached.linked_modules);
+        }
 
         // Run compilation pipeline
         let (program, diagnostics) = self.compile_source(&source, Some(input_file.to_string()))?;
 
+        // Print diagnostics (warnings do not stop compilation)
+        self.print_diagnostics(&diagnostics);
+        self.warnings = diagnostics.iter().filter(|d| d.severity == errors::ErrorSeverity::Warning).count();
+
         // Check for errors
         let errors: Vec<&Diagnostic> = diagnostics
             .iter()
@@ -79,54 +395,392 @@ impl Compiler {
             .collect();
 
         if !errors.is_empty() {
-            self.print_diagnostics(&diagnostics);
             return Err(format!("Compilation failed with {} error(s)", errors.len()));
         }
 
         // Generate code
-        let mut codegen = CodeGenerator::new();
-        let instructions = codegen.generate(&program);
+        let mut codegen = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+        codegen.set_line_markers(self.debug_info);
+        let mut instructions = codegen.generate(&program);
+        self.check_generated(&codegen, input_file)?;
+        let mut routines = self.generate_blit_routines()?;
+        routines.extend(self.generate_interrupt_routines()?);
+        routines.extend(self.generate_task_routines()?);
+        routines.extend(self.generate_file_routines()?);
+        routines.extend(self.generate_param_routines()?);
+        routines.extend(self.generate_timer_routines()?);
+        routines.extend(self.generate_exception_routines(&codegen));
+        routines.extend(self.generate_class_routines(&codegen));
+        routines.extend(self.generate_heap_routines(&codegen)?);
+        routines.extend(self.generate_runtime_error_routines(&codegen));
+        instructions.extend(routines.iter().flat_map(|(_, code)| code.iter().cloned()));
 
         // Create object file
         let unit_name = self.extract_unit_name(input_file);
         let mut obj_file = ObjectFile::new(unit_name);
         
-        // Convert Z80 instructions to machine code (simplified - just emit assembly for now)
-        // TODO: Implement proper assembler
-        let code_bytes = self.instructions_to_bytes(&instructions)?;
-        obj_file.add_code(&code_bytes);
+        // Assemble the code; the linker fills in the addresses it refers to
+        let code = assembler::assemble(&instructions).map_err(|e| e.to_string())?;
+        obj_file.add_code(&code.bytes);
+        for fixup in &code.fixups {
+            obj_file.add_relocation(Relocation {
+                section: Section::Code,
+                offset: fixup.offset,
+                relocation_type: match fixup.kind {
+                    FixupKind::Absolute16 => RelocationType::Absolute16,
+                    FixupKind::Relative8 => RelocationType::Relative8,
+                },
+                symbol_name: fixup.symbol.clone(),
+                addend: fixup.addend,
+            });
+        }
 
-        // Add symbols
+        // Add symbols, each routine running to the next one
+        let mut starts: Vec<u16> = program
+            .functions
+            .iter()
+            .map(|function| abi::routine_symbol(&function.name))
+            .chain(routines.iter().map(|(name, _)| name.clone()))
+            .filter_map(|label| code.label(&label))
+            .collect();
+        starts.sort_unstable();
+        let extent = |label: &str| {
+            let start = code.label(label).unwrap_or(0);
+            let end = starts.iter().copied().find(|&next| next > start).unwrap_or(code.bytes.len() as u16);
+            (start, end.saturating_sub(start))
+        };
         for function in &program.functions {
+            let label = abi::routine_symbol(&function.name);
+            // Several units may instantiate a generic alike; the linker keeps one copy
+            if self.instance_routines.contains(&function.name) {
+                self.add_code_symbol(&mut obj_file, function.name.clone(), SymbolVisibility::Mergeable, extent(&label), 0);
+                self.add_code_symbol(&mut obj_file, label.clone(), SymbolVisibility::Mergeable, extent(&label), 0);
+            } else {
+                self.add_code_symbol(&mut obj_file, function.name.clone(), SymbolVisibility::Public, extent(&label), 0);
+                self.add_code_symbol(&mut obj_file, label.clone(), SymbolVisibility::Public, extent(&label), 0);
+            }
+        }
+        for (name, _) in &routines {
+            let alignment = if name == interrupts::IM2_VECTOR_SYMBOL {
+                interrupts::IM2_VECTOR_ALIGNMENT
+            } else {
+                0
+            };
+            self.add_code_symbol(&mut obj_file, name.clone(), SymbolVisibility::Public, extent(name), alignment);
+        }
+        // Other labels are only referred to from this object
+        for (name, offset) in &code.labels {
+            if !obj_file.symbols.iter().any(|symbol| symbol.name == *name) {
+                obj_file.add_symbol(Symbol {
+                    name: name.clone(),
+                    symbol_type: SymbolType::Function,
+                    visibility: SymbolVisibility::Private,
+                    section: Section::Code,
+                    offset: *offset,
+                    size: 0,
+                    alignment: 0,
+                });
+            }
+        }
+        // Externals the compiler does not generate or expand come from linked modules
+        for external in &self.external_procs {
+            if !routines.iter().any(|(name, _)| *name == external.name) && !self.intrinsics.contains(&external.name) {
+                obj_file.add_symbol(Symbol {
+                    name: external.symbol.clone(),
+                    symbol_type: SymbolType::External,
+                    visibility: SymbolVisibility::Public,
+                    section: Section::Code,
+                    offset: 0,
+                    size: 0,
+                    alignment: 0,
+                });
+            }
+        }
+        // Runtime routines, such as soft-float arithmetic, come from the runtime library
+        for routine in codegen.runtime_calls() {
             obj_file.add_symbol(Symbol {
-                name: function.name.clone(),
-                symbol_type: SymbolType::Function,
+                name: routine.to_string(),
+                symbol_type: SymbolType::External,
                 visibility: SymbolVisibility::Public,
                 section: Section::Code,
-                offset: 0, // TODO: Calculate actual offset
-                size: 0,   // TODO: Calculate actual size
+                offset: 0,
+                size: 0,
+                alignment: 0,
             });
         }
 
-        // Write object file
+        // Add embedded resources to the data section
+        self.add_resources(&mut obj_file)?;
+        self.add_read_only_data(&mut obj_file)?;
+        self.add_variable_images(&mut obj_file)?;
+        self.add_globals(&mut obj_file, &program)?;
+        if self.interrupt_mode == InterruptMode::Im2 {
+            self.add_im2_table(&mut obj_file)?;
+        }
+        if routines.iter().any(|(name, _)| name == "Yield") {
+            self.add_task_pools(&mut obj_file)?;
+        }
+        if routines.iter().any(|(name, _)| name == "BlockRead") {
+            self.add_file_state(&mut obj_file);
+        }
+        if self.uses_params {
+            self.add_param_state(&mut obj_file);
+        }
+        if self.uses_timer && self.target.platform == TargetPlatform::ZealZ80 {
+            let bss = obj_file.bss_size;
+            self.add_variable_symbol(&mut obj_file, timer::TICKS_SYMBOL, Section::Bss, bss, 2);
+            obj_file.set_bss_size(bss + 2);
+        }
+        if self.uses_runtime_errors(&codegen) || self.uses_error_code {
+            let bss = obj_file.bss_size;
+            self.add_variable_symbol(&mut obj_file, runtime_errors::CODE_SYMBOL, Section::Bss, bss, 1);
+            obj_file.set_bss_size(bss + 1);
+        }
+        if codegen.uses_exceptions() {
+            self.add_exception_state(&mut obj_file, &codegen);
+        }
+        self.add_vmts(&mut obj_file)?;
+        self.add_interface_tables(&mut obj_file)?;
+        if codegen.uses_heap() {
+            self.add_heap(&mut obj_file);
+        }
+
+        // Write object file; a unit's is wrapped with its interface in a compiled unit
         let output_path = output_file
             .map(|s| s.to_string())
-            .unwrap_or_else(|| self.default_output_file(input_file));
-        
-        let mut file = fs::File::create(&output_path)
-            .map_err(|e| format!("Failed to create output file '{}': {}", output_path, e))?;
-        
-        obj_file.write(&mut file)
+            .unwrap_or_else(|| self.default_output_file(input_file, self.unit_interface.is_some()));
+
+        let mut bytes = Vec::new();
+        obj_file.write(&mut bytes)
             .map_err(|e| format!("Failed to write object file: {}", e))?;
+        if let Some((name, interface)) = self.unit_interface.take() {
+            let unit = CompiledUnit { name, interface, object: bytes };
+            bytes = Vec::new();
+            unit.write(&mut bytes)
+                .map_err(|e| format!("Failed to write compiled unit: {}", e))?;
+        }
+        fs::write(&output_path, &bytes)
+            .map_err(|e| format!("Failed to create output file '{}': {}", output_path, e))?;
 
         println!("Generated: {}", output_path);
+        for module in &self.linked_modules {
+            println!("Link with: {}", module.display());
+        }
+        if let Some(report) = self.outlining_report() {
+            println!("{}", report);
+        }
+        self.write_build_map(&output_path, &self.linked_modules)?;
+        self.write_debug_info(&output_path, input_file, &program, &instructions, &codegen, &routines)?;
+
+        // Builds with warnings are not cached, so the warnings show again
+        if let Some(cache) = self.build_cache().filter(|_| diagnostics.is_empty()) {
+            let build = CachedBuild { output: bytes, linked_modules: self.linked_modules.clone() };
+            if let Err(e) = cache.store(cache_key, &self.dependencies, &build) {
+                eprintln!("Warning: Failed to update build cache: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The build cache, unless hooks may change the output in ways it cannot
+    /// see or debug info is to be written
+    fn build_cache(&self) -> Option<&BuildCache> {
+        self.cache.as_ref().filter(|_| self.hooks.is_empty() && !self.debug_info)
+    }
+
+    /// Hash of everything besides included files that decides a build's output
+    fn cache_key(&self, input_file: &str, source: &str) -> u64 {
+        // The whole definition: a --target-dir file may change any of it
+        let options = format!(
+            "{} {:?} {:?} {} {} {:?} {:?} {} {} {} {}",
+            env!("CARGO_PKG_VERSION"),
+            self.target,
+            self.optimization,
+            self.optimization_level,
+            self.outline,
+            self.interrupt_mode,
+            self.identifier_policy,
+            self.check_features,
+            self.debug_heap_profile,
+            self.round_robin_profile,
+            self.strict
+        );
+        let symbols = self.predefined_symbols().join(",");
+        let mut intrinsics: Vec<String> =
+            self.intrinsics.iter().map(|(name, template)| format!("{} {:?}", name, template)).collect();
+        intrinsics.sort();
+        let intrinsics = intrinsics.join("\n");
+        cache::content_hash(&[
+            options.as_bytes(),
+            input_file.as_bytes(),
+            symbols.as_bytes(),
+            intrinsics.as_bytes(),
+            source.as_bytes(),
+        ])
+    }
+
+    /// Link object files into a program image
+    ///
+    /// A `.com` output, or any output of a target whose format is `com`, is
+    /// loaded at $0100, any other at the target's load address, unless
+    /// `origin` says where. The image is written in `format` (see
+    /// [`object_zealz80::output`]). The target's startup object goes first,
+    /// and its ROM entry points resolve references as absolute symbols.
+    pub fn link(&self, input_files: &[String], output_file: &str, format: ImageFormat, origin: Option<u16>) -> Result<(), String> {
+        let startup = self.target.startup.iter().map(|path| path.display().to_string());
+        let objects = startup
+            .chain(input_files.iter().cloned())
+            .map(|input| read_object(&input))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let is_com = Path::new(output_file)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("com"));
+        let origin = match origin {
+            Some(origin) => origin,
+            None if is_com => linker::COM_ORIGIN,
+            None => self.target.load_address().ok_or_else(|| {
+                format!("Target '{}' has no known load address; link to a .com file or give --org", self.target.name)
+            })?,
+        };
+
+        if format == ImageFormat::ZealOs && (is_com || self.target.platform != TargetPlatform::ZealZ80) {
+            return Err(format!("Zeal OS executables are linked for target 'zealz80', not to '{}'", output_file));
+        }
+
+        let image = linker::link_with_absolutes(&objects, origin, &self.target.entry_points).map_err(|e| e.to_string())?;
+        let mut bytes = Vec::new();
+        format
+            .write(&image, &mut bytes)
+            .map_err(|e| format!("Failed to write {} output: {}", format, e))?;
+        fs::write(output_file, &bytes)
+            .map_err(|e| format!("Failed to write output file '{}': {}", output_file, e))?;
+        self.write_map(&objects, origin)?;
+
+        println!(
+            "Linked {} object(s) into {}: {} bytes at ${:04X}, BSS {} bytes at ${:04X}",
+            objects.len(),
+            output_file,
+            image.bytes.len(),
+            image.origin,
+            image.bss_size,
+            image.bss_start
+        );
+        Ok(())
+    }
+
+    /// Write the memory map of the object just built, linked after the
+    /// target's startup object and before `modules`, if one was asked for
+    fn write_build_map(&self, output_path: &str, modules: &[PathBuf]) -> Result<(), String> {
+        if self.map_file.is_none() {
+            return Ok(());
+        }
+        let origin = self.target.load_address().ok_or_else(|| {
+            format!("Target '{}' has no known load address to map the program at", self.target.name)
+        })?;
+        let objects = self
+            .target
+            .startup
+            .iter()
+            .chain(std::iter::once(&PathBuf::from(output_path)))
+            .chain(modules)
+            .map(|path| read_object(&path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.write_map(&objects, origin)
+    }
+
+    /// Write the debug info of the object just built next to it, if -g
+    /// asked for it (see debug_info.rs)
+    fn write_debug_info(
+        &self,
+        output_path: &str,
+        input_file: &str,
+        program: &Program,
+        instructions: &[Z80Instruction],
+        codegen: &CodeGenerator,
+        routines: &[(String, Vec<Z80Instruction>)],
+    ) -> Result<(), String> {
+        if !self.debug_info {
+            return Ok(());
+        }
+        // The object's code follows the target's startup code
+        let origin = self.target.load_address().unwrap_or(linker::COM_ORIGIN);
+        let mut objects = self
+            .target
+            .startup
+            .iter()
+            .map(|path| read_object(&path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        objects.push(read_object(output_path)?);
+        let (bases, _) = linker::layout(&objects, origin).map_err(|e| e.to_string())?;
+        let code_start = bases.last().map_or(origin, |bases| bases[Section::Code as usize]);
+
+        let mut routine_labels: HashSet<String> =
+            program.functions.iter().map(|f| abi::routine_symbol(&f.name)).collect();
+        routine_labels.extend(routines.iter().map(|(name, _)| name.clone()));
+        let locals = program
+            .functions
+            .iter()
+            .flat_map(|function| {
+                function.locals.iter().map(|(name, offset)| LocalVariable {
+                    routine: abi::routine_symbol(&function.name),
+                    name: name.clone(),
+                    offset: *offset,
+                })
+            })
+            .collect();
+        let info = DebugInfo {
+            source: input_file.to_string(),
+            origin,
+            table: LineTable::new(instructions, codegen, &routine_labels, code_start),
+            locals,
+        };
+        let path = Path::new(output_path).with_extension(DEBUG_INFO_EXTENSION);
+        fs::write(&path, info.to_string())
+            .map_err(|e| format!("Failed to write debug info '{}': {}", path.display(), e))?;
+        println!("Debug info: {}", path.display());
+        Ok(())
+    }
+
+    /// Write the memory map of `objects` linked at `origin` to the --map
+    /// file, if there is one
+    fn write_map(&self, objects: &[ObjectFile], origin: u16) -> Result<(), String> {
+        let Some(map_file) = &self.map_file else {
+            return Ok(());
+        };
+        let mut map = MemoryMap::layout(objects, origin).map_err(|e| e.to_string())?;
+        map.reserve("Heap", heap::HEAP_SYMBOL);
+        map.reserve("Task stacks", tasks::STACK_POOL_SYMBOL);
+        map.reserve("Task blocks", tasks::TCB_POOL_SYMBOL);
+        map.ram = self.target.ram_region(origin).map(|region| (region.start, region.size));
+        fs::write(map_file, map.to_string()).map_err(|e| format!("Failed to write memory map '{}': {}", map_file, e))?;
+        println!("Memory map: {}", map_file);
+        Ok(())
+    }
+
+    /// Print the sections, symbols and relocations of an object file or
+    /// compiled unit, as selected by `options`
+    pub fn objdump(&self, input_file: &str, options: &DumpOptions) -> Result<(), String> {
+        let object = read_object(input_file)?;
+        print!("{}", object.dump_with(options));
+        Ok(())
+    }
+
+    /// Write the raw contents of one section of an object file or compiled
+    /// unit to `output_file`
+    pub fn extract_section(&self, input_file: &str, section: Section, output_file: &str) -> Result<(), String> {
+        let object = read_object(input_file)?;
+        let bytes = object
+            .section_bytes(section)
+            .ok_or_else(|| format!("Section {} has no contents to extract, only a size", section.name()))?;
+        fs::write(output_file, bytes).map_err(|e| format!("Failed to write output file '{}': {}", output_file, e))?;
+        println!("Wrote {} bytes of {} to {}", bytes.len(), section.name(), output_file);
         Ok(())
     }
 
     /// Type check a file without generating code
     pub fn check_file(&mut self, input_file: &str) -> Result<(), String> {
-        let source = fs::read_to_string(input_file)
-            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let source = self.read_source(input_file)?;
 
         let (_, diagnostics) = self.compile_source(&source, Some(input_file.to_string()))?;
 
@@ -148,26 +802,83 @@ impl Compiler {
 
     /// Emit AST for debugging
     pub fn emit_ast(&mut self, input_file: &str) -> Result<(), String> {
-        let source = fs::read_to_string(input_file)
-            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let source = self.read_source(input_file)?;
 
         // Parse (parser has its own lexer)
-        let mut parser = Parser::new_with_file(&source, Some(input_file.to_string()))
+        let mut parser = self.create_parser(&source, Some(input_file.to_string()))
             .map_err(|e| format!("Parse error: {}", e))?;
-        let ast = parser.parse().map_err(|e| {
-            let diag = parser.error_to_diagnostic(&e);
-            format!("Parse error: {}", diag)
-        })?;
+        let ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
 
         // Print AST
         println!("{:#?}", ast);
         Ok(())
     }
 
+    /// Print the symbols declared in `input_file`, as a table or as JSON
+    pub fn emit_symbols(&mut self, input_file: &str, json: bool) -> Result<(), String> {
+        let source = self.read_source(input_file)?;
+        let (_, diagnostics) = self.compile_source(&source, Some(input_file.to_string()))?;
+        self.print_diagnostics(&diagnostics);
+        let errors = diagnostics.iter().filter(|d| d.severity == errors::ErrorSeverity::Error).count();
+        if errors > 0 {
+            return Err(format!("Compilation failed with {} error(s)", errors));
+        }
+
+        if json {
+            print!("{}", self.symbols.to_json());
+        } else {
+            print!("{}", self.symbols);
+        }
+        Ok(())
+    }
+
+    /// Run `input_file` on the IR interpreter with a scripted console: it
+    /// reads the lines set by [`Compiler::set_console_input`], and what it
+    /// writes is returned
+    pub fn run_interpreted(&mut self, input_file: &str) -> Result<InterpretedRun, String> {
+        let source = self.read_source(input_file)?;
+        let (program, diagnostics) = self.compile_source(&source, Some(input_file.to_string()))?;
+        self.print_diagnostics(&diagnostics);
+        let errors = diagnostics.iter().filter(|d| d.severity == errors::ErrorSeverity::Error).count();
+        if errors > 0 {
+            return Err(format!("Compilation failed with {} error(s)", errors));
+        }
+
+        let main = program
+            .functions
+            .iter()
+            .find(|function| function.name == ir::MAIN_FUNCTION)
+            .ok_or_else(|| format!("'{}' has no main program to run", input_file))?;
+        let mut state = interp::State {
+            console: interp::Console::new(&self.console_input, &self.string_literals),
+            vmts: self
+                .class_layouts
+                .iter()
+                .filter(|layout| layout.has_vmt())
+                .map(|layout| (types::vmt_symbol(&layout.name), layout.vmt.clone()))
+                .collect(),
+            ..Default::default()
+        };
+        // Read-only data and the images of variables are read a word at each
+        // offset, or a byte where the program reads one
+        for (name, bytes) in self.read_only_data.iter().chain(&self.variable_images) {
+            for (offset, low) in bytes.iter().enumerate() {
+                let high = bytes.get(offset + 1).copied().unwrap_or(0);
+                let value = if program.byte_globals.contains(&(name.clone(), offset as i32)) {
+                    *low as u32
+                } else {
+                    u16::from_le_bytes([*low, high]) as u32
+                };
+                state.memory.insert((ir::global_base(name), offset as i32), value);
+            }
+        }
+        let error = interp::interpret_with(main, &program.functions, &mut state, INTERPRETER_STEP_LIMIT).err();
+        Ok(InterpretedRun { output: state.console.output, error })
+    }
+
     /// Emit IR for debugging
     pub fn emit_ir(&mut self, input_file: &str) -> Result<(), String> {
-        let source = fs::read_to_string(input_file)
-            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let source = self.read_source(input_file)?;
 
         let (program, diagnostics) = self.compile_source(&source, Some(input_file.to_string()))?;
 
@@ -190,11 +901,90 @@ impl Compiler {
     }
 
     /// Emit assembly code
-    pub fn emit_assembly(&mut self, input_file: &str) -> Result<(), String> {
-        let source = fs::read_to_string(input_file)
-            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+    /// Print the assembly listing of `input_file`; with a `profile` of an
+    /// emulator run, annotated with execution counts and T-states and
+    /// followed by the size report of each routine (see profile.rs)
+    pub fn emit_assembly(&mut self, input_file: &str, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile
+            .map(|path| {
+                let text = fs::read_to_string(path).map_err(|e| format!("Failed to read profile '{}': {}", path, e))?;
+                Profile::parse(&text).map_err(|e| format!("{}: {}", path, e))
+            })
+            .transpose()?;
+        let source = self.read_source(input_file)?;
+        let mut codegen = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+        let (instructions, routine_labels) = self.assemble_listing(&source, input_file, &mut codegen)?;
 
-        let (program, diagnostics) = self.compile_source(&source, Some(input_file.to_string()))?;
+        // Print assembly
+        match &profile {
+            Some(profile) => {
+                let origin = self.target.load_address().unwrap_or(linker::COM_ORIGIN);
+                for line in profile::annotate(&instructions, &codegen, &routine_labels, profile, origin) {
+                    println!("{}", line);
+                }
+            }
+            None => {
+                for inst in &instructions {
+                    println!("{}", inst);
+                }
+            }
+        }
+
+        // Assembly modules are appended so the listing assembles on its own
+        for module in &self.linked_modules {
+            if is_assembly_module(module) {
+                let text = fs::read_to_string(module)
+                    .map_err(|e| format!("Failed to read linked module '{}': {}", module.display(), e))?;
+                println!("; {{$L {}}}", module.display());
+                print!("{}", text);
+            } else {
+                println!("; link with {}", module.display());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Map an emulator trace of `input_file` to its source lines and
+    /// routines, and print the execution histogram and call tree (see
+    /// trace_map.rs); `input_file` is the program or the debug info of
+    /// its build
+    pub fn trace_map(&mut self, trace_file: &str, input_file: &str) -> Result<(), String> {
+        for line in self.trace_report(trace_file, input_file)? {
+            println!("{}", line);
+        }
+        Ok(())
+    }
+
+    /// The lines `trace-map` prints
+    fn trace_report(&mut self, trace_file: &str, input_file: &str) -> Result<Vec<String>, String> {
+        let text = fs::read_to_string(trace_file).map_err(|e| format!("Failed to read trace '{}': {}", trace_file, e))?;
+        let trace = Trace::parse(&text).map_err(|e| format!("{}: {}", trace_file, e))?;
+        if Path::new(input_file).extension().is_some_and(|ext| ext.eq_ignore_ascii_case(DEBUG_INFO_EXTENSION)) {
+            let text = fs::read_to_string(input_file)
+                .map_err(|e| format!("Failed to read debug info '{}': {}", input_file, e))?;
+            let info = DebugInfo::parse(&text).map_err(|e| format!("{}: {}", input_file, e))?;
+            let source = self.read_source(&info.source)?;
+            return Ok(TraceMap::new(&trace, &info.table).report(&source));
+        }
+        let source = self.read_source(input_file)?;
+        let mut codegen = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+        codegen.set_line_markers(true);
+        let (instructions, routine_labels) = self.assemble_listing(&source, input_file, &mut codegen)?;
+        let origin = trace.origin.unwrap_or_else(|| self.target.load_address().unwrap_or(linker::COM_ORIGIN));
+        let table = LineTable::new(&instructions, &codegen, &routine_labels, origin);
+        Ok(TraceMap::new(&trace, &table).report(&source))
+    }
+
+    /// Compile `source` to the listing `asm` prints, with the runtime
+    /// routines it needs, and the labels starting a routine
+    fn assemble_listing(
+        &mut self,
+        source: &str,
+        input_file: &str,
+        codegen: &mut CodeGenerator,
+    ) -> Result<(Vec<Z80Instruction>, HashSet<String>), String> {
+        let (program, diagnostics) = self.compile_source(source, Some(input_file.to_string()))?;
 
         // Print diagnostics
         self.print_diagnostics(&diagnostics);
@@ -210,83 +1000,2746 @@ impl Compiler {
         }
 
         // Generate assembly
-        let mut codegen = CodeGenerator::new();
-        let instructions = codegen.generate(&program);
+        let mut instructions = codegen.generate(&program);
+        self.check_generated(codegen, input_file)?;
+        let routines = self
+            .generate_blit_routines()?
+            .into_iter()
+            .chain(self.generate_interrupt_routines()?)
+            .chain(self.generate_task_routines()?)
+            .chain(self.generate_file_routines()?)
+            .chain(self.generate_param_routines()?)
+            .chain(self.generate_timer_routines()?)
+            .chain(self.generate_exception_routines(codegen))
+            .chain(self.generate_class_routines(codegen))
+            .chain(self.generate_heap_routines(codegen)?)
+            .chain(self.generate_runtime_error_routines(codegen));
+        let mut routine_labels: HashSet<String> =
+            program.functions.iter().map(|f| abi::routine_symbol(&f.name)).collect();
+        for (name, code) in routines {
+            routine_labels.insert(name);
+            instructions.extend(code);
+        }
+        for layout in self.class_layouts.iter().filter(|layout| layout.has_vmt()) {
+            instructions.extend(classes::vmt_table(layout));
+        }
+        for layout in self.class_layouts.iter().filter(|layout| !layout.interfaces.is_empty()) {
+            instructions.extend(interfaces::interface_tables(layout));
+        }
+        for layout in &self.interface_layouts {
+            instructions.push(Z80Instruction::Label { name: types::interface_id_symbol(&layout.name) });
+            instructions.push(Z80Instruction::DefineByte { value: 0 });
+        }
 
-        // Print assembly
-        for inst in &instructions {
-            println!("{}", inst);
+        Ok((instructions, routine_labels))
+    }
+
+    /// Write the unit generated from a register description, by default
+    /// next to it with a .pas extension
+    pub fn generate_register_unit(&self, input_file: &str, output_file: Option<&str>) -> Result<(), String> {
+        let source = fs::read_to_string(input_file)
+            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let unit = register_unit(input_file, &source)?;
+        let output_path = output_file
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| PathBuf::from(input_file).with_extension("pas").to_string_lossy().to_string());
+        fs::write(&output_path, unit).map_err(|e| format!("Failed to write file '{}': {}", output_path, e))?;
+        println!("Generated: {}", output_path);
+        Ok(())
+    }
+
+    /// Source of `input_file`; a register description reads as the unit
+    /// generated from it, so it builds like any other unit
+    fn read_source(&self, input_file: &str) -> Result<String, String> {
+        let source = fs::read_to_string(input_file)
+            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let is_register_map = Path::new(input_file)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case(REGISTER_MAP_EXTENSION));
+        if is_register_map {
+            register_unit(input_file, &source)
+        } else {
+            Ok(source)
         }
+    }
 
+    /// Write `input_file` and its includes as one minified source
+    ///
+    /// Conditionals are resolved for the target and --define symbols.
+    pub fn minify(&mut self, input_file: &str, output_file: Option<&str>, shorten_identifiers: bool) -> Result<(), String> {
+        let source = fs::read_to_string(input_file)
+            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let options = MinifyOptions {
+            symbols: self.predefined_symbols(),
+            include_paths: vec![],
+            shorten_identifiers,
+            identifier_policy: self.identifier_policy,
+        };
+        let minified = minify::minify(&source, Some(input_file.to_string()), &options)
+            .map_err(|e| format!("Parse error: {}", e))?;
+        match output_file {
+            Some(path) => {
+                fs::write(path, &minified).map_err(|e| format!("Failed to write file '{}': {}", path, e))?;
+                println!("Minified {} bytes to {}", source.len(), minified.len());
+            }
+            None => print!("{}", minified),
+        }
         Ok(())
     }
 
-    /// Core compilation pipeline
-    fn compile_source(&mut self, source: &str, filename: Option<String>) -> Result<(Program, Vec<Diagnostic>), String> {
-        // 1. Parsing (parser has its own lexer)
-        let mut parser = Parser::new_with_file(source, filename.clone())
+    /// Rewrite the Turbo Pascal constructs of a file that SuperPascal has an
+    /// equivalent for, reporting each change and each construct left as is
+    pub fn upgrade_syntax(&mut self, input_file: &str, output_file: Option<&str>) -> Result<(), String> {
+        let source = fs::read_to_string(input_file)
+            .map_err(|e| format!("Failed to read file '{}': {}", input_file, e))?;
+        let upgraded = upgrade::upgrade(&source, self.identifier_policy)
             .map_err(|e| format!("Parse error: {}", e))?;
-        let ast = parser.parse().map_err(|e| {
-            let diag = parser.error_to_diagnostic(&e);
-            format!("Parse error: {}", diag)
-        })?;
+        let diagnostics: Vec<Diagnostic> =
+            upgraded.diagnostics.into_iter().map(|d| d.with_file(input_file.to_string())).collect();
+        self.print_diagnostics(&diagnostics);
+        let left = diagnostics.iter().filter(|d| d.severity != errors::ErrorSeverity::Note).count();
+        match output_file {
+            Some(path) => {
+                fs::write(path, &upgraded.source).map_err(|e| format!("Failed to write file '{}': {}", path, e))?;
+                println!("Upgraded {} construct(s) into {}, {} left to migrate by hand", diagnostics.len() - left, path, left);
+            }
+            None => print!("{}", upgraded.source),
+        }
+        Ok(())
+    }
 
-        // 3. Semantic Analysis
-        let mut analyzer = SemanticAnalyzer::new(filename.clone());
-        let mut diagnostics = analyzer.analyze(&ast);
-        
-        // 4. Feature Compatibility Checking
-        if self.check_features {
-            let capabilities = capabilities::get_capabilities(self.target);
-            let mut feature_checker = feature_checker::FeatureChecker::new(capabilities, filename);
-            feature_checker.check(&ast);
-            diagnostics.extend_from_slice(feature_checker.diagnostics());
+    /// Print the declarations and statements that differ between two files
+    pub fn ast_diff(&mut self, old_file: &str, new_file: &str) -> Result<(), String> {
+        let parse = |file: &str| -> Result<(String, Node), String> {
+            let source = fs::read_to_string(file).map_err(|e| format!("Failed to read file '{}': {}", file, e))?;
+            let mut parser = self.create_parser(&source, Some(file.to_string()))
+                .map_err(|e| format!("Parse error: {}", e))?;
+            let ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
+            Ok((source, ast))
+        };
+        let (old_source, old_ast) = parse(old_file)?;
+        let (new_source, new_ast) = parse(new_file)?;
+
+        let changes = ast_diff::diff(&old_ast, &new_ast, &old_source, &new_source);
+        if changes.is_empty() {
+            println!("No structural differences");
+        }
+        for change in changes {
+            println!("{}", change);
         }
+        Ok(())
+    }
 
-        // 5. IR Generation (simplified - for now, create empty program)
-        // TODO: Implement AST to IR conversion
-        let ir_builder = IRBuilder::new();
-        let program = ir_builder.into_program();
+    /// Print statistics of each source in `paths`, then their total
+    ///
+    /// Directories stand for every `.pas` file beneath them, so a corpus can
+    /// be measured in one run; files that fail to parse are reported and
+    /// left out of the total.
+    pub fn print_stats(&mut self, paths: &[String]) -> Result<(), String> {
+        let mut files = Vec::new();
+        for path in paths {
+            collect_sources(Path::new(path), &mut files)
+                .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        }
+        if files.is_empty() {
+            return Err("No Pascal sources found".to_string());
+        }
+        let mut total = SourceStats::default();
+        let mut failed = 0;
+        for file in &files {
+            match self.source_stats(file) {
+                Ok(stats) => {
+                    stats.print(&file.display().to_string());
+                    total.add(&stats);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failed += 1;
+                }
+            }
+        }
+        if files.len() > 1 {
+            total.print("total");
+        }
+        if failed > 0 {
+            return Err(format!("{} of {} file(s) could not be parsed", failed, files.len()));
+        }
+        Ok(())
+    }
 
-        Ok((program, diagnostics))
+    /// Print the dependency graph of the programs and units in `paths`
+    /// (files or directories) and the units they use, as DOT or JSON
+    pub fn graph_units(&self, paths: &[String], json: bool) -> Result<(), String> {
+        let graph = self.unit_graph(paths)?;
+        print!("{}", if json { graph.to_json() } else { graph.to_dot() });
+        Ok(())
     }
 
-    /// Print diagnostics to stderr
-    fn print_diagnostics(&self, diagnostics: &[Diagnostic]) {
-        for diagnostic in diagnostics {
-            eprintln!("{}", diagnostic);
+    /// Compile the programs and units in `dir` and the units they use, each
+    /// unit before anything using it, on up to `jobs` threads, then print
+    /// the outcome of each. A unit that fails only stops the units using
+    /// it. Hooks only run on this compiler, so with hooks registered units
+    /// are compiled one at a time.
+    pub fn build_all(&mut self, dir: &str, jobs: usize) -> Result<(), String> {
+        let graph = self.unit_graph(&[dir.to_string()])?;
+        if let Some(cycle) = graph.cycle() {
+            return Err(format!("Circular unit reference: {}", graph.path_text(&cycle)));
+        }
+        let order = graph.build_order().unwrap_or_default();
+        // Compiled units are read once for the whole build
+        self.unit_cache = Some(UnitCache::default());
+        let report = self.build_units(&graph, &order, jobs);
+        self.unit_cache = None;
+        println!();
+        print!("{}", report.to_table());
+        match report.failures() {
+            0 => Ok(()),
+            failures => Err(format!("{} of {} source(s) failed", failures, order.len())),
         }
     }
 
-    /// Extract unit name from file path
-    fn extract_unit_name(&self, file_path: &str) -> String {
-        PathBuf::from(file_path)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("main")
-            .to_string()
+    /// Compile the units of `graph` in `order` on up to `jobs` threads
+    fn build_units(&mut self, graph: &UnitGraph, order: &[usize], jobs: usize) -> BuildReport {
+        let source = |unit: usize| graph.units[unit].path.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+        let started = AtomicUsize::new(0);
+        let start = |unit: usize| {
+            let count = started.fetch_add(1, Ordering::Relaxed) + 1;
+            println!("[{}/{}] Compiling {} ({})", count, order.len(), graph.units[unit].name, source(unit));
+        };
+        // Record how compiling `unit` ended, releasing or skipping its users
+        let finish = |queue: &mut BuildQueue, report: &mut BuildReport, unit: usize, result: Result<(), String>, warnings| {
+            let name = &graph.units[unit].name;
+            match result {
+                Ok(()) => {
+                    queue.built(unit);
+                    report.add(name, &source(unit), BuildOutcome::Built { warnings });
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", source(unit), e);
+                    report.add(name, &source(unit), BuildOutcome::Failed { warnings });
+                    for user in queue.failed(unit) {
+                        let outcome = BuildOutcome::Skipped { uses: name.clone() };
+                        report.add(&graph.units[user].name, &source(user), outcome);
+                    }
+                }
+            }
+        };
+
+        let jobs = if self.hooks.is_empty() { jobs.clamp(1, order.len().max(1)) } else { 1 };
+        if jobs == 1 {
+            let mut queue = BuildQueue::new(graph, order);
+            let mut report = BuildReport::default();
+            while let Some(unit) = queue.next() {
+                start(unit);
+                let result = self.compile_file(&source(unit), None);
+                finish(&mut queue, &mut report, unit, result, self.warnings);
+            }
+            return report;
+        }
+
+        let state = Mutex::new((BuildQueue::new(graph, order), BuildReport::default()));
+        let released = Condvar::new();
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                let mut compiler = self.worker();
+                let (state, released, source, start, finish) = (&state, &released, &source, &start, &finish);
+                scope.spawn(move || loop {
+                    let mut guard = state.lock().unwrap_or_else(|e| e.into_inner());
+                    let unit = loop {
+                        if guard.0.is_finished() {
+                            return;
+                        }
+                        if let Some(unit) = guard.0.next() {
+                            break unit;
+                        }
+                        guard = released.wait(guard).unwrap_or_else(|e| e.into_inner());
+                    };
+                    // Announced while the queue is held, so the count runs in order
+                    start(unit);
+                    drop(guard);
+                    let result = compiler.compile_file(&source(unit), None);
+                    let mut guard = state.lock().unwrap_or_else(|e| e.into_inner());
+                    let (queue, report) = &mut *guard;
+                    finish(queue, report, unit, result, compiler.warnings);
+                    released.notify_all();
+                });
+            }
+        });
+        state.into_inner().unwrap_or_else(|e| e.into_inner()).1
     }
 
-    /// Generate default output filename
-    fn default_output_file(&self, input_file: &str) -> String {
-        PathBuf::from(input_file)
-            .with_extension("zof")
-            .to_string_lossy()
-            .to_string()
+    /// A compiler configured as this one, without its hooks, to compile on
+    /// another thread
+    fn worker(&self) -> Compiler {
+        Compiler {
+            target: self.target.clone(),
+            check_features: self.check_features,
+            optimization: self.optimization,
+            optimization_level: self.optimization_level,
+            outline: self.outline,
+            debug_info: self.debug_info,
+            interrupt_mode: self.interrupt_mode,
+            debug_heap_profile: self.debug_heap_profile,
+            round_robin_profile: self.round_robin_profile,
+            identifier_policy: self.identifier_policy,
+            defines: self.defines.clone(),
+            cache: self.cache.clone(),
+            intrinsics: self.intrinsics.clone(),
+            console_input: self.console_input.clone(),
+            teach: self.teach,
+            error_format: self.error_format,
+            strict: self.strict,
+            dead_code_warnings: self.dead_code_warnings,
+            deny_warnings: self.deny_warnings,
+            unit_cache: self.unit_cache.clone(),
+            ..Compiler::new()
+        }
     }
 
-    /// Convert Z80 instructions to bytes (simplified placeholder)
-    /// TODO: Implement proper assembler
-    fn instructions_to_bytes(&self, _instructions: &[Z80Instruction]) -> Result<Vec<u8>, String> {
-        // For now, return empty - proper assembly will be implemented later
-        // This is a placeholder that will be replaced with a real assembler
-        Ok(vec![])
+    /// Dependency graph of the programs and units in `paths` (files or
+    /// directories) and the units they use
+    fn unit_graph(&self, paths: &[String]) -> Result<UnitGraph, String> {
+        let mut files = Vec::new();
+        for path in paths {
+            collect_sources(Path::new(path), &mut files)
+                .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        }
+        if files.is_empty() {
+            return Err("No Pascal sources found".to_string());
+        }
+        UnitGraph::build(&files, |file| {
+            let name = file.display().to_string();
+            let source = fs::read_to_string(file).map_err(|e| format!("Failed to read file '{}': {}", name, e))?;
+            let mut parser = self.create_parser(&source, Some(name.clone()))
+                .map_err(|e| format!("Parse error: {}", e))?;
+            let ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
+            UnitUses::of(&ast).ok_or_else(|| format!("'{}' is not a program or unit", name))
+        })
     }
-}
 
-impl Default for Compiler {
-    fn default() -> Self {
-        Self::new()
+    /// The symbol index of the project in `dir`, brought up to date with its
+    /// sources and saved
+    fn symbol_index(&self, dir: &str) -> Result<SymbolIndex, String> {
+        let mut files = Vec::new();
+        collect_sources(Path::new(dir), &mut files).map_err(|e| format!("Failed to read '{}': {}", dir, e))?;
+        let mut sources = Vec::new();
+        for file in files {
+            let content = fs::read(&file).map_err(|e| format!("Failed to read file '{}': {}", file.display(), e))?;
+            sources.push((file, cache::content_hash(&[&content])));
+        }
+        let index_path = Path::new(dir).join(symbol_index::INDEX_FILE);
+        let mut index = SymbolIndex::load(&index_path);
+        let hashes = sources.clone();
+        index.update(&sources, |file| {
+            let name = file.display().to_string();
+            let source = fs::read_to_string(file).map_err(|e| format!("Failed to read file '{}': {}", name, e))?;
+            let mut lexer = Lexer::new(&source).with_identifier_policy(self.identifier_policy);
+            let mut tokens = Vec::new();
+            loop {
+                let token = lexer.next_token().map_err(|e| format!("{}: {}", name, e))?;
+                if token.kind == TokenKind::Eof {
+                    break;
+                }
+                tokens.push(token);
+            }
+            let mut parser = self.create_parser(&source, Some(name.clone()))
+                .map_err(|e| format!("Parse error: {}", e))?;
+            let ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
+            let hash = hashes.iter().find(|(path, _)| path == file).map_or(0, |(_, hash)| *hash);
+            Ok(FileIndex::build(&ast, &tokens, hash))
+        })?;
+        index.save(&index_path).map_err(|e| format!("Failed to write '{}': {}", index_path.display(), e))?;
+        Ok(index)
+    }
+
+    /// Print the declarations named `name` in the project in `dir`, each
+    /// with the places it is used
+    pub fn xref(&self, dir: &str, name: &str) -> Result<(), String> {
+        let index = self.symbol_index(dir)?;
+        let decls = index.declarations(name);
+        if decls.is_empty() {
+            return Err(format!("No declaration of '{}' found", name));
+        }
+        for (file, decl) in decls {
+            let found = index.decl(file, decl);
+            println!("{} {}  {}:{}:{}", found.kind, found.name, file.display(), found.span.line, found.span.column);
+            for (path, span) in index.uses(file, decl) {
+                println!("  {}:{}:{}", path.display(), span.line, span.column);
+            }
+        }
+        Ok(())
     }
-}
 
+    /// Rename the declaration `old_name` in the project in `dir`, and every
+    /// use of it, to `new_name`; `at` (`file:line`) picks among several
+    /// declarations of the name
+    pub fn rename(&self, dir: &str, old_name: &str, new_name: &str, at: Option<&str>) -> Result<(), String> {
+        let mut lexer = Lexer::new(new_name).with_identifier_policy(self.identifier_policy);
+        if !matches!(lexer.next_token().map(|token| token.kind), Ok(TokenKind::Identifier(_)))
+            || !matches!(lexer.next_token().map(|token| token.kind), Ok(TokenKind::Eof))
+        {
+            return Err(format!("'{}' is not an identifier", new_name));
+        }
+        let index = self.symbol_index(dir)?;
+        let mut decls = index.declarations(old_name);
+        if let Some(at) = at {
+            let (file, line) = at
+                .rsplit_once(':')
+                .and_then(|(file, line)| Some((file, line.parse::<usize>().ok()?)))
+                .ok_or_else(|| format!("Expected file:line after --at, found '{}'", at))?;
+            decls.retain(|(path, decl)| path.ends_with(file) && index.decl(path, *decl).span.line == line);
+        }
+        let (file, decl) = match decls[..] {
+            [] => return Err(format!("No declaration of '{}' found", old_name)),
+            [found] => found,
+            _ => {
+                let places: Vec<String> = decls
+                    .iter()
+                    .map(|(path, decl)| format!("{}:{}", path.display(), index.decl(path, *decl).span.line))
+                    .collect();
+                return Err(format!("'{}' is declared at {}; pick one with --at", old_name, places.join(", ")));
+            }
+        };
+        let edits = index.rename(file, decl, new_name)?;
+        for (path, spans) in &edits {
+            let mut source = fs::read_to_string(path).map_err(|e| format!("Failed to read file '{}': {}", path.display(), e))?;
+            for span in spans.iter().rev() {
+                source.replace_range(span.start..span.end, new_name);
+            }
+            fs::write(path, source).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+            println!("{}: {} change(s)", path.display(), spans.len());
+        }
+        // The renamed sources are indexed again
+        self.symbol_index(dir).map(|_| ())
+    }
+
+    /// Lex, parse and analyze `file`, timing each phase
+    fn source_stats(&self, file: &Path) -> Result<SourceStats, String> {
+        let name = file.display().to_string();
+        let source = fs::read_to_string(file).map_err(|e| format!("Failed to read file '{}': {}", name, e))?;
+        let mut stats = SourceStats::for_source(&source);
+
+        let start = Instant::now();
+        let mut lexer = Lexer::new(&source).with_identifier_policy(self.identifier_policy);
+        loop {
+            let token = lexer.next_token().map_err(|e| format!("{}: {}", name, e))?;
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            stats.tokens += 1;
+        }
+        stats.lex_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut parser = self.create_parser(&source, Some(name.clone()))
+            .map_err(|e| format!("Parse error: {}", e))?;
+        let mut ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
+        stats.parse_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut generics = GenericInstantiator::new(Some(name.clone()));
+        generics.instantiate(&mut ast);
+        let mut analyzer = SemanticAnalyzer::new(Some(name));
+        analyzer.set_pointer_math(parser.switch(semantics::POINTER_MATH_SWITCH) == Some(true));
+        analyzer.set_strict(self.is_strict(&parser));
+        stats.diagnostics = generics.diagnostics().len() + analyzer.analyze(&ast).len();
+        stats.analysis_time = start.elapsed();
+
+        stats.count_nodes(&ast);
+        Ok(stats)
+    }
+
+    /// Print the call frame of `routine`, or of every top-level routine
+    ///
+    /// `input` is a source file or a single routine heading such as
+    /// `"function Mix(A, B: byte): word"`.
+    pub fn print_abi(&mut self, input: &str, routine: Option<&str>) -> Result<(), String> {
+        let (source, filename) = if Path::new(input).is_file() {
+            let source = fs::read_to_string(input).map_err(|e| format!("Failed to read file '{}': {}", input, e))?;
+            (source, Some(input.to_string()))
+        } else {
+            (format!("program Abi;\n{}; external;\nbegin end.", input.trim().trim_end_matches(';')), None)
+        };
+        let mut parser = self.create_parser(&source, filename.clone())
+            .map_err(|e| format!("Parse error: {}", e))?;
+        let ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
+        let mut analyzer = SemanticAnalyzer::new(filename);
+        let diagnostics = analyzer.analyze(&ast);
+        self.print_diagnostics(&diagnostics);
+        if diagnostics.iter().any(|d| d.severity == errors::ErrorSeverity::Error) {
+            return Err("Type checking failed".to_string());
+        }
+
+        let routines: Vec<(&str, bool, &Option<String>)> = top_level_routines(&ast)
+            .into_iter()
+            .filter_map(|decl| match decl {
+                Node::ProcDecl(proc) => Some((proc.name.as_str(), proc.is_external, &proc.external_name)),
+                Node::FuncDecl(func) => Some((func.name.as_str(), func.is_external, &func.external_name)),
+                _ => None,
+            })
+            .filter(|(name, ..)| routine.is_none_or(|r| name.eq_ignore_ascii_case(r)))
+            .collect();
+        if routines.is_empty() {
+            return Err(match routine {
+                Some(name) => format!("Routine '{}' not found", name),
+                None => "No routines declared".to_string(),
+            });
+        }
+        for (name, is_external, external_name) in routines {
+            let Some((params, return_type)) = analyzer.routine_signature(name) else {
+                continue;
+            };
+            let specs: Vec<abi::ParamSpec> = params
+                .iter()
+                .map(|param| abi::ParamSpec {
+                    name: param.name.clone(),
+                    by_reference: param.passing_mode == ParameterMode::Var || param.param_type == Type::Untyped,
+                    size: param.param_type.size().map(|size| size as u16),
+                })
+                .collect();
+            let frame = abi::CallFrame::new(&specs, return_type.as_ref().map(|t| t.size().map(|size| size as u16)));
+            let symbol = match external_name {
+                Some(symbol) => symbol.clone(),
+                None if is_external => name.to_string(),
+                None => abi::routine_symbol(name),
+            };
+            let kind = if return_type.is_some() { "function" } else { "procedure" };
+            println!("{} {}: symbol {}", kind, name, symbol);
+            for slot in &frame.slots {
+                let description = match params.iter().find(|p| p.name == slot.name) {
+                    Some(param) => semantics::type_name(&param.param_type),
+                    None => "result buffer".to_string(),
+                };
+                let passing = match slot.passing {
+                    abi::Passing::Value => "value",
+                    abi::Passing::Address => "address",
+                };
+                println!(
+                    "  ix+{:<3} sp+{:<3} {:<12} {:<8} {}",
+                    slot.frame_offset, slot.entry_offset, slot.name, passing, description
+                );
+            }
+            let result = match frame.result {
+                abi::ResultLocation::None => "none".to_string(),
+                abi::ResultLocation::L => "L".to_string(),
+                abi::ResultLocation::HL => "HL".to_string(),
+                abi::ResultLocation::Buffer => format!("written through {}", abi::RESULT_BUFFER_SLOT),
+            };
+            println!("  result: {}", result);
+            println!("  callee removes {} bytes of arguments; preserves IX, IY", frame.argument_bytes());
+        }
+        Ok(())
+    }
+
+    /// Core compilation pipeline
+    fn compile_source(&mut self, source: &str, filename: Option<String>) -> Result<(Program, Vec<Diagnostic>), String> {
+        // 1. Parsing (parser has its own lexer)
+        let mut parser = self.create_parser(source, filename.clone())
+            .map_err(|e| format!("Parse error: {}", e))?;
+        let mut ast = parser.parse().map_err(|_| self.parse_failure(&parser))?;
+        self.hooks.run_post_parse(&mut ast);
+
+        // 2. Generic instantiation
+        let mut generics = GenericInstantiator::new(filename.clone());
+        generics.instantiate(&mut ast);
+        if generics.has_errors() {
+            return Ok((Program::new(), generics.diagnostics().to_vec()));
+        }
+        self.resources = parser.resources().to_vec();
+        let codepage = parser.codepage();
+        self.external_procs = collect_external_procs(&ast);
+        self.linked_modules = parser.linked_modules().to_vec();
+        let stack_usage = StackUsage::analyze(&ast);
+        let task_stack_sizes: Vec<(String, u16)> = stack_usage
+            .task_entries()
+            .iter()
+            .filter_map(|entry| Some((entry.clone(), stack_usage.task_stack_size(entry).ok()?)))
+            .collect();
+        self.task_stacks = task_stack_sizes.iter().map(|(_, size)| *size).collect();
+
+        // 3. Semantic Analysis
+        let mut analyzer = SemanticAnalyzer::new(filename.clone());
+        if let Some(cache) = &self.unit_cache {
+            analyzer.set_unit_cache(cache.clone());
+        }
+        // The switch applies to the whole unit in its final state
+        analyzer.set_pointer_math(parser.switch(semantics::POINTER_MATH_SWITCH) == Some(true));
+        let strict = self.is_strict(&parser);
+        analyzer.set_strict(strict);
+        let mut diagnostics = analyzer.analyze(&ast);
+        diagnostics.extend(analyzer.check_generic_constraints(generics.constraints()));
+        self.linked_modules.extend(analyzer.used_units().iter().cloned());
+        self.dependencies = parser.dependencies().to_vec();
+        self.dependencies.extend(analyzer.used_units().iter().cloned());
+        self.unit_interface = match &ast {
+            Node::Unit(unit) => Some((unit.name.clone(), analyzer.interface_symbols().to_vec())),
+            _ => None,
+        };
+        self.threadvar_size = analyzer.threadvar_block_size();
+        self.imports_tasks = analyzer.imported_symbols().iter().any(|symbol| {
+            matches!(symbol.kind, symbols::SymbolKind::Procedure { .. }) && task_routine(symbol.name()).is_some()
+        });
+        self.summary = ProgramSummary::collect(&ast, &analyzer);
+        self.symbols = SymbolDump::collect(&ast, &analyzer);
+        self.uses_params = analyzer.uses_params();
+        self.uses_timer = analyzer.uses_timer();
+        self.uses_error_code = analyzer.uses_error_code();
+        self.uses_report_leaks = analyzer.uses_report_leaks();
+        self.debug_heap = parser.switch(heap::DEBUG_HEAP_SWITCH).unwrap_or(self.debug_heap_profile);
+        self.round_robin = parser.switch(tasks::ROUND_ROBIN_SWITCH).unwrap_or(self.round_robin_profile);
+        self.runtime_errors = parser.runtime_errors().cloned().unwrap_or_else(|| self.target.runtime_errors.clone());
+        if let RuntimeErrorStrategy::Handler(handler) = &self.runtime_errors
+            && !matches!(analyzer.routine_signature(handler), Some((params, None)) if params.len() == 1)
+        {
+            diagnostics.push(
+                Diagnostic::new(
+                    errors::ErrorSeverity::Error,
+                    format!("Runtime error handler '{}' must be a procedure taking the error code", handler),
+                    ast.span(),
+                )
+                .with_file(filename.clone().unwrap_or_else(|| "unknown".to_string())),
+            );
+        }
+        if !self.uses_tasks() {
+            // Threadvar storage lives in the scheduler's task blocks
+            for threadvar in analyzer.threadvars() {
+                diagnostics.push(
+                    Diagnostic::new(
+                        errors::ErrorSeverity::Error,
+                        format!("Threadvar '{}' requires the task runtime (uses Tasks)", threadvar.name),
+                        threadvar.span,
+                    )
+                    .with_file(filename.clone().unwrap_or_else(|| "unknown".to_string())),
+                );
+            }
+        }
+        
+        self.read_only_data = analyzer
+            .typed_constants()
+            .iter()
+            .map(|c| (c.name.clone(), constant_bytes(&c.value, codepage)))
+            .chain(
+                analyzer
+                    .string_literals()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, text)| (format!("__str_{}", i), constant_bytes(&ConstantValue::String(text.clone()), codepage))),
+            )
+            .collect();
+        if let Some(ram) = self.target.ram_size() {
+            // Without ROM, read-only data shares RAM with the variables
+            let mut used = analyzer.global_variable_size();
+            if !self.target.has_rom() {
+                used += self.read_only_data.iter().map(|(_, bytes)| bytes.len() as u32).sum::<u32>();
+            }
+            if used > ram {
+                diagnostics.push(
+                    Diagnostic::new(
+                        errors::ErrorSeverity::Warning,
+                        format!(
+                            "Global data needs {} bytes but target '{}' has {} bytes of RAM",
+                            used,
+                            self.target.name,
+                            ram
+                        ),
+                        ast.span(),
+                    )
+                    .with_file(filename.clone().unwrap_or_else(|| "unknown".to_string())),
+                );
+            }
+        }
+
+        // 4. Feature Compatibility Checking
+        if self.check_features {
+            let capabilities = capabilities::get_capabilities(self.target.platform);
+            let mut feature_checker = feature_checker::FeatureChecker::new(capabilities, filename.clone());
+            feature_checker.check(&ast);
+            diagnostics.extend_from_slice(feature_checker.diagnostics());
+        }
+        explain_skipped_declarations(&mut diagnostics, parser.skipped_regions(), source);
+
+        // Unused declarations, reads before assignment and hidden names,
+        // less the kinds {$WARN name OFF} turned off
+        let mut warnings = WarningChecker::new(filename.clone());
+        for kind in WarningKind::ALL {
+            if parser.switch(&kind.switch()) == Some(false) {
+                warnings.disable(kind);
+            }
+        }
+        for name in self.resources.iter().flat_map(CompiledResource::declared_names) {
+            warnings.keep(&name);
+        }
+        warnings.check(&ast);
+        diagnostics.extend_from_slice(warnings.diagnostics());
+
+        // Pure functions, whose calls common subexpression elimination may
+        // reuse or drop, and {$PURE} ones that are not
+        let mut purity = semantics::purity::PurityAnalysis::new(filename.clone());
+        purity.analyze(&ast);
+        diagnostics.extend_from_slice(purity.diagnostics());
+        self.hooks.run_post_semantics(&ast, &mut diagnostics);
+
+        // Variable images, dead code elimination and constant parameters,
+        // unless {$OPTIMIZATION OFF}
+        self.variable_images.clear();
+        if parser.switch(ir::OPTIMIZATION_SWITCH) != Some(false) {
+            self.variable_images = analyzer
+                .fold_variable_images(&mut ast)
+                .iter()
+                .map(|image| (image.name.clone(), image_bytes(image, codepage)))
+                .collect();
+            let mut eliminator = semantics::dead_code::DeadCodeEliminator::new(filename.clone());
+            if let RuntimeErrorStrategy::Handler(handler) = &self.runtime_errors {
+                eliminator.keep(handler);
+            }
+            eliminator.eliminate(&mut ast);
+            if self.dead_code_warnings {
+                // Unused routines may be reported already
+                for diagnostic in eliminator.diagnostics() {
+                    if !diagnostics.contains(diagnostic) {
+                        diagnostics.push(diagnostic.clone());
+                    }
+                }
+            }
+            let mut propagator = semantics::parameters::ParameterPropagator::new();
+            if let RuntimeErrorStrategy::Handler(handler) = &self.runtime_errors {
+                propagator.keep(handler);
+            }
+            // Every unit making an instance makes the same one, and the
+            // linker keeps only one of them
+            for instance in generics.instances() {
+                propagator.keep(instance);
+            }
+            propagator.propagate(&mut ast, &analyzer);
+        }
+
+        // 5. IR Generation
+        let mut ir_builder = IRBuilder::new();
+        ir_builder.set_filename(filename.clone());
+        ir_builder.set_range_checks(strict);
+        ir_builder.set_overflow_checks(strict);
+        ir_builder.set_string_literals(analyzer.string_literals());
+        ir_builder.set_pure_functions(purity.pure_functions());
+        ir_builder.set_task_stack_sizes(task_stack_sizes);
+        ir_builder.set_class_layouts(analyzer.class_layouts());
+        self.class_layouts = analyzer.class_layouts().to_vec();
+        ir_builder.set_interface_layouts(analyzer.interface_layouts());
+        self.interface_layouts = analyzer.interface_layouts().to_vec();
+        // Names brought in by `uses` live in the objects of their units
+        for symbol in analyzer.imported_symbols() {
+            match &symbol.kind {
+                symbols::SymbolKind::Variable { var_type, .. } => ir_builder.declare_global(symbol.name(), var_type.clone()),
+                symbols::SymbolKind::Procedure { params, .. } => {
+                    ir_builder.declare_routine(symbol.name(), routine_params(params), None)
+                }
+                symbols::SymbolKind::Function { params, return_type, .. } => {
+                    ir_builder.declare_routine(symbol.name(), routine_params(params), Some(return_type.clone()))
+                }
+                _ => {}
+            }
+        }
+        // Typed constant arrays, such as the tables GenerateTable fills, are
+        // read-only data under their own names
+        for constant in analyzer.typed_constants() {
+            if matches!(constant.const_type, Type::Array { .. }) {
+                ir_builder.declare_global(&constant.name, constant.const_type.clone());
+            }
+        }
+        // Threadvars are reached a word at a time
+        for threadvar in analyzer.threadvars() {
+            if threadvar.size != 2 {
+                diagnostics.push(
+                    Diagnostic::new(
+                        errors::ErrorSeverity::Error,
+                        format!("Code generation does not support the threadvar '{}' of other than two bytes yet", threadvar.name),
+                        threadvar.span,
+                    )
+                    .with_file(filename.clone().unwrap_or_else(|| "unknown".to_string())),
+                );
+            }
+            if let Some(var_type) = analyzer.variable_type(&threadvar.name) {
+                ir_builder.declare_threadvar(&threadvar.name, var_type, threadvar.offset);
+            }
+        }
+        // A program with errors may not be complete enough to build
+        if !diagnostics.iter().any(|d| d.severity == errors::ErrorSeverity::Error) {
+            ir_builder.build(&ast);
+            diagnostics.extend_from_slice(ir_builder.diagnostics());
+        }
+        self.string_literals = ir_builder.string_literals().to_vec();
+        let mut program = ir_builder.into_program();
+        // The scheduler routines a used unit declares are generated in this
+        // object, and the file routines in the unit's, under their own names
+        for symbol in analyzer.imported_symbols() {
+            let task = task_routine(symbol.name()).filter(|_| self.uses_tasks());
+            if let Some(routine) = task.or_else(|| file_routine(symbol.name())) {
+                program.externals.push((symbol.name().to_string(), routine.to_string()));
+            }
+        }
+        self.global_sizes = program
+            .globals
+            .iter()
+            .filter_map(|(name, _)| Some((name.to_lowercase(), analyzer.variable_type(name)?.size()?)))
+            .collect();
+        self.absolute_globals = program
+            .globals
+            .iter()
+            .filter_map(|(name, _)| Some((name.clone(), analyzer.variable_address(name)?)))
+            .collect();
+        // Ordinals of one byte, here and in the units used, are reached a
+        // byte at a time
+        let byte_wide = |ty: &Type| ty.is_ordinal() && ty.size() == Some(1);
+        program.byte_globals.extend(
+            program
+                .globals
+                .iter()
+                .filter(|(name, _)| analyzer.variable_type(name).is_some_and(|ty| byte_wide(&ty)))
+                .map(|(name, _)| (name.clone(), 0)),
+        );
+        for symbol in analyzer.imported_symbols() {
+            if let symbols::SymbolKind::Variable { var_type, .. } = &symbol.kind
+                && byte_wide(var_type)
+            {
+                program.byte_globals.push((symbol.name().to_string(), 0));
+            }
+        }
+        ir::unroll_loops(&mut program, self.optimization == OptimizationGoal::Size);
+        ir::fold_constants(&mut program);
+        ir::remove_unreachable_code(&mut program);
+        ir::reduce_strength(&mut program, &arith::strength_costs(self.optimization));
+        if self.optimization_level >= 2 {
+            ir::eliminate_common_subexpressions(&mut program);
+        }
+        self.outlined.clear();
+        if self.outline {
+            let codegen = CodeGenerator::with_intrinsics(self.intrinsics.clone());
+            self.outlined =
+                ir::outline_sequences(&mut program, &codegen.outline_costs(), |inst| codegen.ir_instruction_size(inst));
+        }
+        ir::narrow_bytes(&mut program);
+        self.hooks.run_pre_codegen(&mut program);
+        self.instance_routines = program
+            .functions
+            .iter()
+            .filter(|function| generics.is_instance_routine(&function.name))
+            .map(|function| function.name.clone())
+            .collect();
+
+        if self.deny_warnings {
+            for diagnostic in diagnostics.iter_mut().filter(|d| d.severity == errors::ErrorSeverity::Warning) {
+                diagnostic.severity = errors::ErrorSeverity::Error;
+                diagnostic.notes.push("warnings are errors under --deny-warnings".to_string());
+            }
+        }
+        Ok((program, diagnostics))
+    }
+
+    /// Symbols defined for conditional compilation: the target's and --define
+    fn predefined_symbols(&self) -> Vec<String> {
+        let mut symbols = self.target.symbols.clone();
+        symbols.extend(self.defines.iter().cloned());
+        symbols
+    }
+
+    /// Create a parser with the target's conditional symbols and character
+    /// set predefined
+    fn create_parser(&self, source: &str, filename: Option<String>) -> errors::ParserResult<Parser> {
+        let mut parser =
+            Parser::new_with_identifier_policy(source, filename, self.predefined_symbols(), self.identifier_policy)?;
+        parser.mark_command_line_symbols(&self.defines);
+        if let Some(codepage) = target_codepage(self.target.platform) {
+            parser.set_codepage(codepage);
+        }
+        Ok(parser)
+    }
+
+    /// Generate blit routines for image resources and `Blit<W>x<H>` externals
+    ///
+    /// Returns each routine's public name with its code.
+    fn generate_blit_routines(&self) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        let images: Vec<&CompiledResource> = self
+            .resources
+            .iter()
+            .filter(|r| r.kind == resources::ResourceKind::Image)
+            .collect();
+        let blits: Vec<&String> = self
+            .external_procs
+            .iter()
+            .map(|p| &p.name)
+            .filter(|name| BlitSpec::from_routine_name(name, u16::MAX).is_some())
+            .collect();
+        if images.is_empty() && blits.is_empty() {
+            return Ok(vec![]);
+        }
+        let stride = self.target.framebuffer_stride.ok_or_else(|| {
+            format!("Generated blit routines are not available for target '{}'", self.target.name)
+        })?;
+
+        let mut routines = Vec::new();
+        for image in images {
+            let name = image.draw_symbol();
+            let spec = BlitSpec {
+                width: image.width as u16,
+                height: image.height as u16,
+                stride,
+            };
+            if spec.width > stride || spec.height > 255 {
+                return Err(format!("Image '{}' is too large to draw on target '{}'", image.name, self.target.name));
+            }
+            let code = match blit::select_strategy(self.optimization, &spec, true) {
+                BlitStrategy::Push => blit::generate_compiled_sprite(&name, &spec, &image.image_rows()),
+                _ => None,
+            };
+            // Without PUSH the sprite is copied from its embedded tiles
+            let code = code.unwrap_or_else(|| {
+                let strategy = blit::select_strategy(self.optimization, &spec, false);
+                blit::generate_tiled_blit(&name, &image.data_symbol(), &spec, resources::TILE_SIZE as u16, strategy)
+            });
+            routines.push((name, code));
+        }
+        for name in blits {
+            let spec = BlitSpec::from_routine_name(name, stride)
+                .ok_or_else(|| format!("'{}' does not fit the framebuffer of target '{}'", name, self.target.name))?;
+            let strategy = blit::select_strategy(self.optimization, &spec, false);
+            routines.push((name.clone(), blit::generate_blit(name, &spec, strategy)));
+        }
+        Ok(routines)
+    }
+
+    /// Generate the interrupt setup routines for the selected mode
+    fn generate_interrupt_routines(&self) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        match self.interrupt_mode {
+            InterruptMode::Im1 => {
+                if self.external_procs.iter().any(|p| p.name.eq_ignore_ascii_case(interrupts::SET_IM2_HANDLER_SYMBOL)) {
+                    return Err(format!("{} requires --interrupt-mode im2", interrupts::SET_IM2_HANDLER_SYMBOL));
+                }
+                Ok(vec![])
+            }
+            InterruptMode::Im2 => {
+                if !matches!(self.target.platform, TargetPlatform::ZealZ80 | TargetPlatform::ZXSpectrum) {
+                    return Err(format!("Interrupt mode im2 is not available for target '{}'", self.target.name));
+                }
+                Ok(interrupts::generate_im2_routines())
+            }
+        }
+    }
+
+    /// Generate the cooperative scheduler when the program uses it
+    fn generate_task_routines(&self) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        if !self.uses_tasks() {
+            return Ok(vec![]);
+        }
+        if !matches!(self.target.platform, TargetPlatform::ZealZ80 | TargetPlatform::ZXSpectrum) {
+            return Err(format!("Tasks are not available for target '{}'", self.target.name));
+        }
+        Ok(tasks::generate_task_routines(self.threadvar_size, self.round_robin))
+    }
+
+    /// Generate the file I/O routines when the program declares them
+    fn generate_file_routines(&self) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        if !self.external_procs.iter().any(|p| files::FILE_ROUTINES.iter().any(|r| p.name.eq_ignore_ascii_case(r))) {
+            return Ok(vec![]);
+        }
+        let fs = match self.target.platform {
+            TargetPlatform::ZealZ80 => FileSystem::ZealOs,
+            TargetPlatform::ZXSpectrum => FileSystem::EsxDos,
+            _ => return Err(format!("File I/O is not available for target '{}'", self.target.name)),
+        };
+        Ok(files::generate_file_routines(fs))
+    }
+
+    /// Add the error code, transfer state and path buffer of the file routines
+    fn add_file_state(&self, obj_file: &mut ObjectFile) {
+        let mut bss = obj_file.bss_size;
+        for (name, size) in [
+            (files::ERROR_SYMBOL, 1),
+            (files::DONE_SYMBOL, 2),
+            (files::BUFFER_SYMBOL, 2),
+            (files::PATH_SYMBOL, files::PATH_SIZE),
+        ] {
+            self.add_variable_symbol(obj_file, name, Section::Bss, bss, size);
+            bss += size;
+        }
+        obj_file.set_bss_size(bss);
+    }
+
+    /// Generate the program parameter routines when the program reads its parameters
+    fn generate_param_routines(&self) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        if !self.uses_params {
+            return Ok(vec![]);
+        }
+        let source = match self.target.platform {
+            TargetPlatform::ZealZ80 => ParamSource::ZealOs,
+            TargetPlatform::ZXSpectrum => ParamSource::EsxDos,
+            _ => return Err(format!("Program parameters are not available for target '{}'", self.target.name)),
+        };
+        Ok(params::generate_param_routines(source))
+    }
+
+    /// Generate the tick counter routines when the program reads the timer
+    fn generate_timer_routines(&self) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        if !self.uses_timer {
+            return Ok(vec![]);
+        }
+        let source = match self.target.platform {
+            TargetPlatform::ZealZ80 => TimerSource::ZealOs,
+            TargetPlatform::ZXSpectrum => TimerSource::Frames,
+            _ => return Err(format!("The timer is not available for target '{}'", self.target.name)),
+        };
+        Ok(timer::generate_timer_routines(source))
+    }
+
+    /// Generate the raise routine when the program uses exceptions
+    fn generate_exception_routines(&self, codegen: &CodeGenerator) -> Vec<(String, Vec<Z80Instruction>)> {
+        if !codegen.uses_exceptions() {
+            return vec![];
+        }
+        exceptions::generate_exception_routines()
+    }
+
+    /// Generate the indirect call routine when the program calls virtual
+    /// methods
+    fn generate_class_routines(&self, codegen: &CodeGenerator) -> Vec<(String, Vec<Z80Instruction>)> {
+        let mut routines = vec![];
+        if codegen.uses_virtual_calls() {
+            routines.extend(classes::generate_class_routines());
+        }
+        if codegen.uses_interfaces() {
+            routines.extend(interfaces::generate_interface_routines());
+        }
+        routines
+    }
+
+    /// Generate the allocation routines when the program creates or frees
+    /// instances, those of the debug heap if selected, and `ReportLeaks`
+    /// when the program calls it
+    fn generate_heap_routines(&self, codegen: &CodeGenerator) -> Result<Vec<(String, Vec<Z80Instruction>)>, String> {
+        let mut routines = vec![];
+        let tracked = self.debug_heap && codegen.uses_heap();
+        if tracked {
+            routines.extend(heap::generate_debug_heap_routines());
+        } else if codegen.uses_heap() {
+            routines.extend(heap::generate_heap_routines());
+        }
+        if self.uses_report_leaks {
+            let console = if tracked {
+                let console = self.console().ok_or_else(|| {
+                    format!("ReportLeaks is not available for target '{}', which has no console", self.target.name)
+                })?;
+                Some(console)
+            } else {
+                None
+            };
+            routines.push(heap::generate_report_leaks_routine(console));
+        }
+        Ok(routines)
+    }
+
+    /// Where the target prints runtime messages, if it can
+    fn console(&self) -> Option<Console> {
+        match self.target.platform {
+            TargetPlatform::ZealZ80 => Some(Console::ZealOs),
+            TargetPlatform::ZXSpectrum => Some(Console::Spectrum),
+            _ => None,
+        }
+    }
+
+    /// Whether the program checks for runtime errors, itself or through
+    /// the debug heap
+    fn uses_runtime_errors(&self, codegen: &CodeGenerator) -> bool {
+        codegen.uses_runtime_errors() || (self.debug_heap && codegen.uses_heap())
+    }
+
+    /// Generate the runtime error routines for the program's strategy when
+    /// the program checks for runtime errors or reads their code
+    fn generate_runtime_error_routines(&self, codegen: &CodeGenerator) -> Vec<(String, Vec<Z80Instruction>)> {
+        let mut routines = vec![];
+        if self.uses_runtime_errors(codegen) {
+            routines.extend(runtime_errors::generate_runtime_error_routines(&self.runtime_errors, self.console()));
+        }
+        if self.uses_error_code {
+            routines.push(runtime_errors::generate_error_code_routine());
+        }
+        routines
+    }
+
+    /// Add the VMT of each class with virtual methods or interfaces: the
+    /// header leading to its interface table, then a routine address per
+    /// slot, relocated against the method's symbol
+    fn add_vmts(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        for layout in self.class_layouts.iter().filter(|layout| layout.has_vmt()) {
+            let header = (!layout.interfaces.is_empty()).then(|| types::interface_table_symbol(&layout.name));
+            let entries = header.into_iter().chain(layout.vmt.iter().map(|entry| abi::routine_symbol(entry)));
+            let start = if layout.interfaces.is_empty() { 1 } else { 0 };
+            self.add_word_table(obj_file, types::vmt_symbol(&layout.name), 1 + layout.vmt.len(), start, entries)?;
+        }
+        Ok(())
+    }
+
+    /// Add the interface table and the IMTs of each class implementing
+    /// interfaces, and a one-byte descriptor per interface
+    fn add_interface_tables(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        for layout in self.class_layouts.iter().filter(|layout| !layout.interfaces.is_empty()) {
+            let pairs = layout.interfaces.iter().flat_map(|(interface, _)| {
+                [types::interface_id_symbol(interface), types::imt_symbol(&layout.name, interface)]
+            });
+            let words = 2 * layout.interfaces.len() + 1;
+            self.add_word_table(obj_file, types::interface_table_symbol(&layout.name), words, 0, pairs)?;
+            for (interface, imt) in &layout.interfaces {
+                let entries = imt.iter().map(|entry| abi::routine_symbol(entry));
+                self.add_word_table(obj_file, types::imt_symbol(&layout.name, interface), imt.len(), 0, entries)?;
+            }
+        }
+        let mut bss = obj_file.bss_size;
+        for layout in &self.interface_layouts {
+            self.add_variable_symbol(obj_file, &types::interface_id_symbol(&layout.name), Section::Bss, bss, 1);
+            bss += 1;
+        }
+        obj_file.set_bss_size(bss);
+        Ok(())
+    }
+
+    /// Add the table `name` of `words` words, those from `start` on holding
+    /// the addresses of `symbols` and the others 0
+    fn add_word_table(
+        &self,
+        obj_file: &mut ObjectFile,
+        name: String,
+        words: usize,
+        start: usize,
+        symbols: impl Iterator<Item = String>,
+    ) -> Result<(), String> {
+        let offset = self.add_data_symbol(obj_file, name, &vec![0u8; words * 2], 0)?;
+        for (index, symbol_name) in symbols.enumerate() {
+            obj_file.add_relocation(Relocation {
+                section: Section::Data,
+                offset: offset + (start + index) as u16 * 2,
+                relocation_type: RelocationType::Absolute16,
+                symbol_name,
+                addend: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add the heap, and its end for the allocator's bound, and the debug
+    /// heap's table of instances
+    fn add_heap(&self, obj_file: &mut ObjectFile) {
+        let mut bss = obj_file.bss_size;
+        self.add_variable_symbol(obj_file, heap::HEAP_SYMBOL, Section::Bss, bss, heap::HEAP_SIZE);
+        self.add_variable_symbol(obj_file, heap::HEAP_END_SYMBOL, Section::Bss, bss + heap::HEAP_SIZE, 0);
+        bss += heap::HEAP_SIZE;
+        if self.debug_heap {
+            let size = heap::TRACKED_BLOCKS * 2;
+            self.add_variable_symbol(obj_file, heap::TABLE_SYMBOL, Section::Bss, bss, size);
+            bss += size;
+        }
+        obj_file.set_bss_size(bss);
+    }
+
+    /// Add the exception state and a one-byte descriptor per exception type
+    fn add_exception_state(&self, obj_file: &mut ObjectFile, codegen: &CodeGenerator) {
+        let mut bss = obj_file.bss_size;
+        for name in [exceptions::FRAME_SYMBOL, exceptions::VALUE_SYMBOL, exceptions::TYPE_SYMBOL] {
+            self.add_variable_symbol(obj_file, name, Section::Bss, bss, 2);
+            bss += 2;
+        }
+        for name in codegen.exception_types() {
+            self.add_variable_symbol(obj_file, name, Section::Bss, bss, 1);
+            bss += 1;
+        }
+        obj_file.set_bss_size(bss);
+    }
+
+    /// Add the parameter text location and the `ParamStr` buffer
+    fn add_param_state(&self, obj_file: &mut ObjectFile) {
+        let mut bss = obj_file.bss_size;
+        for (name, size) in [
+            (params::TEXT_SYMBOL, 2),
+            (params::LENGTH_SYMBOL, 2),
+            (params::BUFFER_SYMBOL, params::BUFFER_SIZE),
+        ] {
+            self.add_variable_symbol(obj_file, name, Section::Bss, bss, size);
+            bss += size;
+        }
+        obj_file.set_bss_size(bss);
+    }
+
+    /// Whether the last parsed file is a program using the scheduler: one
+    /// declaring its routines, or using a unit that does. The scheduler
+    /// and its pools, sized from the program's `TaskStackSize` uses, go in
+    /// the program's object, so a unit declaring them leaves them to it
+    fn uses_tasks(&self) -> bool {
+        self.unit_interface.is_none()
+            && (self.imports_tasks || self.external_procs.iter().any(|p| task_routine(&p.name).is_some()))
+    }
+
+    /// Add the scheduler state and the stack and TCB pools
+    ///
+    /// The stack pool holds one `TaskStackSize` worth of stack per use, the
+    /// TCB pool one TCB and threadvar block per use. Round-robin mode adds
+    /// the last TCB and the switch count.
+    fn add_task_pools(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        let stack_pool = self
+            .task_stacks
+            .iter()
+            .try_fold(0i16, |total, &size| i16::try_from(size).ok().and_then(|size| total.checked_add(size)))
+            .ok_or_else(|| "Task stacks do not fit in memory".to_string())?;
+        let tcb_size = tasks::TCB_SIZE + self.threadvar_size;
+        let tcb_pool = u16::try_from(tcb_size as usize * self.task_stacks.len())
+            .ok()
+            .filter(|pool| pool.checked_add(stack_pool as u16).is_some())
+            .ok_or_else(|| "Task blocks do not fit in memory".to_string())?;
+
+        // Main program TCB: saved SP, next pointing back at itself, threadvars
+        let mut state = vec![
+            (tasks::MAIN_TCB_SYMBOL, 2, tasks::MAIN_TCB_SYMBOL, 0),
+            (tasks::CURRENT_TASK_SYMBOL, 0, tasks::MAIN_TCB_SYMBOL, 0),
+            (tasks::STACK_TOP_SYMBOL, 0, tasks::STACK_POOL_SYMBOL, stack_pool),
+            (tasks::NEXT_TCB_SYMBOL, 0, tasks::TCB_POOL_SYMBOL, 0),
+        ];
+        if self.round_robin {
+            state.push((tasks::LAST_TASK_SYMBOL, 0, tasks::MAIN_TCB_SYMBOL, 0));
+        }
+        for (name, pointer_offset, target, addend) in state {
+            let size = if name == tasks::MAIN_TCB_SYMBOL { tcb_size } else { pointer_offset + 2 };
+            let offset = obj_file.data.len() as u16;
+            obj_file.add_data(&vec![0u8; size as usize]);
+            self.add_variable_symbol(obj_file, name, Section::Data, offset, size);
+            obj_file.add_relocation(Relocation {
+                section: Section::Data,
+                offset: offset + pointer_offset,
+                relocation_type: RelocationType::Absolute16,
+                symbol_name: target.to_string(),
+                addend,
+            });
+        }
+
+        if self.round_robin {
+            let offset = obj_file.data.len() as u16;
+            obj_file.add_data(&[0, 0]);
+            self.add_variable_symbol(obj_file, tasks::TASK_SWITCHES_SYMBOL, Section::Data, offset, 2);
+        }
+
+        let bss = obj_file.bss_size;
+        self.add_variable_symbol(obj_file, tasks::STACK_POOL_SYMBOL, Section::Bss, bss, stack_pool as u16);
+        self.add_variable_symbol(obj_file, tasks::TCB_POOL_SYMBOL, Section::Bss, bss + stack_pool as u16, tcb_pool);
+        obj_file.set_bss_size(bss + stack_pool as u16 + tcb_pool);
+        Ok(())
+    }
+
+    /// Add a public variable symbol
+    fn add_variable_symbol(&self, obj_file: &mut ObjectFile, name: &str, section: Section, offset: u16, size: u16) {
+        obj_file.add_symbol(Symbol {
+            name: name.to_string(),
+            symbol_type: SymbolType::Variable,
+            visibility: SymbolVisibility::Public,
+            section,
+            offset,
+            size,
+            alignment: 0,
+        });
+    }
+
+    /// Add the 257-byte IM2 vector table, every byte pointing at the vector jump
+    fn add_im2_table(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        let table = vec![0u8; interrupts::IM2_TABLE_SIZE as usize];
+        let offset = self.add_data_symbol(
+            obj_file,
+            interrupts::IM2_TABLE_SYMBOL.to_string(),
+            &table,
+            interrupts::IM2_TABLE_ALIGNMENT,
+        )?;
+        for i in 0..interrupts::IM2_TABLE_SIZE {
+            obj_file.add_relocation(Relocation {
+                section: Section::Data,
+                offset: offset + i,
+                relocation_type: RelocationType::HighByte,
+                symbol_name: interrupts::IM2_VECTOR_SYMBOL.to_string(),
+                addend: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add a function symbol covering `extent` (offset and size) of the
+    /// code section
+    fn add_code_symbol(
+        &self,
+        obj_file: &mut ObjectFile,
+        name: String,
+        visibility: SymbolVisibility,
+        (offset, size): (u16, u16),
+        alignment: u16,
+    ) {
+        obj_file.add_symbol(Symbol {
+            name,
+            symbol_type: SymbolType::Function,
+            visibility,
+            section: Section::Code,
+            offset,
+            size,
+            alignment,
+        });
+    }
+
+    /// Place compiled resources in the data section, one public symbol each
+    fn add_resources(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        for resource in &self.resources {
+            self.add_data_symbol(obj_file, resource.data_symbol(), &resource.data, 0)?;
+            if !resource.palette.is_empty() {
+                self.add_data_symbol(obj_file, resource.palette_symbol(), &resource.palette_bytes(), 0)?;
+            }
+            // Music descriptors point at the module data
+            if let Some(descriptor) = resource.descriptor_bytes() {
+                let offset = self.add_data_symbol(obj_file, resource.name.clone(), &descriptor, 0)?;
+                obj_file.add_relocation(Relocation {
+                    section: Section::Data,
+                    offset,
+                    relocation_type: RelocationType::Absolute16,
+                    symbol_name: resource.data_symbol(),
+                    addend: 0,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Place typed constants and string literals in ROM (after the code) when
+    /// the target's memory map has ROM, otherwise in the data section
+    fn add_read_only_data(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        for (name, bytes) in &self.read_only_data {
+            if !self.target.has_rom() {
+                self.add_data_symbol(obj_file, name.clone(), bytes, 0)?;
+                continue;
+            }
+            let offset = u16::try_from(obj_file.code.len())
+                .ok()
+                .filter(|offset| (*offset as usize) + bytes.len() <= u16::MAX as usize)
+                .ok_or_else(|| format!("Constant '{}' does not fit in the code section", name))?;
+            obj_file.add_code(bytes);
+            obj_file.add_symbol(Symbol {
+                name: name.clone(),
+                symbol_type: SymbolType::Constant,
+                visibility: SymbolVisibility::Public,
+                section: Section::Code,
+                offset,
+                size: bytes.len() as u16,
+                alignment: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Place the structured variables the main program starts by filling
+    /// with constants in the data section, holding those constants
+    fn add_variable_images(&self, obj_file: &mut ObjectFile) -> Result<(), String> {
+        for (name, bytes) in &self.variable_images {
+            let offset = u16::try_from(obj_file.data.len())
+                .ok()
+                .filter(|offset| (*offset as usize) + bytes.len() <= u16::MAX as usize)
+                .ok_or_else(|| format!("Variable '{}' does not fit in the data section", name))?;
+            obj_file.add_data(bytes);
+            self.add_variable_symbol(obj_file, name, Section::Data, offset, bytes.len() as u16);
+        }
+        Ok(())
+    }
+
+    /// Reserve the global variables of `program` without an initial image
+    /// in the BSS section, one public symbol each
+    fn add_globals(&self, obj_file: &mut ObjectFile, program: &Program) -> Result<(), String> {
+        for (name, ty) in &program.globals {
+            // Resources and variable images have their bytes in the data
+            // section
+            let is_resource = self
+                .resources
+                .iter()
+                .flat_map(CompiledResource::variables)
+                .any(|(variable, _)| variable.eq_ignore_ascii_case(name));
+            if is_resource || self.variable_images.iter().any(|(image, _)| image.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            // ABSOLUTE variables live at their address, wherever they are
+            // used from
+            if let Some((_, address)) = self.absolute_globals.iter().find(|(absolute, _)| absolute == name) {
+                obj_file.absolutes.push((name.clone(), *address));
+                continue;
+            }
+            let size = self.global_sizes.get(&name.to_lowercase()).copied().or_else(|| ty.size()).unwrap_or(2);
+            let bss = obj_file.bss_size;
+            let end = u16::try_from(bss as usize + size)
+                .map_err(|_| format!("Variable '{}' does not fit in the BSS section", name))?;
+            self.add_variable_symbol(obj_file, name, Section::Bss, bss, size as u16);
+            obj_file.set_bss_size(end);
+        }
+        Ok(())
+    }
+
+    /// Fail with an error for each IR instruction `codegen` could not
+    /// translate
+    fn check_generated(&self, codegen: &CodeGenerator, input_file: &str) -> Result<(), String> {
+        let unsupported = codegen.unsupported();
+        if unsupported.is_empty() {
+            return Ok(());
+        }
+        let diagnostics: Vec<Diagnostic> = unsupported
+            .iter()
+            .map(|(what, span)| {
+                Diagnostic::new(
+                    errors::ErrorSeverity::Error,
+                    format!("Z80 code generation does not support {} yet", what),
+                    span.unwrap_or_else(|| tokens::Span::new(0, 0, 1, 1)),
+                )
+                .with_file(input_file.to_string())
+            })
+            .collect();
+        self.print_diagnostics(&diagnostics);
+        Err(format!("Compilation failed with {} error(s)", diagnostics.len()))
+    }
+
+    /// Append bytes to the data section under a public symbol, returning its offset
+    fn add_data_symbol(&self, obj_file: &mut ObjectFile, name: String, bytes: &[u8], alignment: u16) -> Result<u16, String> {
+        let offset = u16::try_from(obj_file.data.len())
+            .ok()
+            .filter(|offset| (*offset as usize) + bytes.len() <= u16::MAX as usize)
+            .ok_or_else(|| format!("Resource '{}' does not fit in the data section", name))?;
+        obj_file.add_data(bytes);
+        obj_file.add_symbol(Symbol {
+            name,
+            symbol_type: SymbolType::Constant,
+            visibility: SymbolVisibility::Public,
+            section: Section::Data,
+            offset,
+            size: bytes.len() as u16,
+            alignment,
+        });
+        Ok(offset)
+    }
+
+    /// Print diagnostics to stderr
+    fn print_diagnostics(&self, diagnostics: &[Diagnostic]) {
+        let mut sources = SourceRenderer::new();
+        for diagnostic in diagnostics {
+            eprintln!("{}", self.render(diagnostic, &mut sources));
+        }
+    }
+
+    /// Text of a diagnostic: a JSON object, its source lines underlined,
+    /// or with its explanation when teaching, set off by a blank line from
+    /// the next
+    fn render(&self, diagnostic: &Diagnostic, sources: &mut SourceRenderer) -> String {
+        match self.error_format {
+            DiagnosticFormat::Json => diagnostic.format_json(),
+            DiagnosticFormat::Pretty => sources.render(diagnostic),
+            DiagnosticFormat::Human if self.teach => format!("{}\n", diagnostic.format_teaching()),
+            DiagnosticFormat::Human => diagnostic.to_string(),
+        }
+    }
+
+    /// Error for a file that does not parse: its only syntax error, or a
+    /// count once all of them are printed as other diagnostics are
+    fn parse_failure(&self, parser: &Parser) -> String {
+        let diagnostics: Vec<Diagnostic> = parser.errors().iter().map(|e| parser.error_to_diagnostic(e)).collect();
+        if let [diagnostic] = diagnostics.as_slice()
+            && self.error_format == DiagnosticFormat::Human
+        {
+            return format!("Parse error: {}", self.render(diagnostic, &mut SourceRenderer::new()));
+        }
+        self.print_diagnostics(&diagnostics);
+        format!("Compilation failed with {} error(s)", diagnostics.len())
+    }
+
+    /// Extract unit name from file path
+    fn extract_unit_name(&self, file_path: &str) -> String {
+        PathBuf::from(file_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("main")
+            .to_string()
+    }
+
+    /// Generate default output filename: a compiled unit for a unit, an
+    /// object file otherwise
+    fn default_output_file(&self, input_file: &str, is_unit: bool) -> String {
+        let extension = if is_unit { SPU_EXTENSION } else { "zof" };
+        PathBuf::from(input_file)
+            .with_extension(extension)
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+/// Stored form of a constant: little-endian numbers, length-prefixed strings,
+/// text in the target character set
+fn constant_bytes(value: &ConstantValue, codepage: Option<Codepage>) -> Vec<u8> {
+    match value {
+        ConstantValue::Integer(i) => i.to_le_bytes().to_vec(),
+        ConstantValue::Word(w) => w.to_le_bytes().to_vec(),
+        ConstantValue::Byte(b) => vec![*b],
+        ConstantValue::LongInt(l) => l.to_le_bytes().to_vec(),
+        ConstantValue::Cardinal(c) => c.to_le_bytes().to_vec(),
+        ConstantValue::Real(r) => r.to_le_bytes().to_vec(),
+        ConstantValue::Boolean(b) => vec![*b as u8],
+        ConstantValue::Bytes(bytes) => bytes.clone(),
+        ConstantValue::Char(c) => vec![codepage.and_then(|cp| cp.encode_char(*c as char)).unwrap_or(*c)],
+        ConstantValue::String(text) => {
+            // The parser has checked literals against the code page
+            let encoded = codepage.and_then(|cp| cp.encode(text).ok()).unwrap_or_else(|| text.as_bytes().to_vec());
+            let bytes = &encoded[..encoded.len().min(255)];
+            let mut stored = vec![bytes.len() as u8];
+            stored.extend_from_slice(bytes);
+            stored
+        }
+    }
+}
+
+/// Bytes of a variable image: its values over zeroes
+fn image_bytes(image: &semantics::VariableImage, codepage: Option<Codepage>) -> Vec<u8> {
+    let mut bytes = vec![0u8; image.size as usize];
+    for (offset, value) in &image.values {
+        let value = constant_bytes(value, codepage);
+        let start = (*offset as usize).min(bytes.len());
+        let end = (start + value.len()).min(bytes.len());
+        bytes[start..end].copy_from_slice(&value[..end - start]);
+    }
+    bytes
+}
+
+/// Say which conditional skipped the declaration of a name reported as not
+/// found: "Identifier 'Beep' not found (declaration skipped by {$IFDEF SOUND}
+/// at line 3; SOUND is not defined)"
+fn explain_skipped_declarations(diagnostics: &mut [Diagnostic], regions: &[parser::SkippedRegion], source: &str) {
+    if regions.is_empty() {
+        return;
+    }
+    let declared: Vec<Vec<String>> = regions.iter().map(|region| region.declared_names(source)).collect();
+    for diagnostic in diagnostics.iter_mut().filter(|d| d.message.ends_with("' not found")) {
+        let Some(name) = diagnostic.message.split('\'').nth(1) else {
+            continue;
+        };
+        let skipped = regions
+            .iter()
+            .zip(&declared)
+            .find(|(_, names)| names.iter().any(|declared| declared.eq_ignore_ascii_case(name)));
+        if let Some((region, _)) = skipped {
+            diagnostic.message = format!("{} (declaration skipped by {})", diagnostic.message, region.explanation());
+        }
+    }
+}
+
+/// Read an object file, or the object file a compiled unit carries
+fn read_object(input: &str) -> Result<ObjectFile, String> {
+    let mut bytes = fs::read(input).map_err(|e| format!("Failed to open '{}': {}", input, e))?;
+    if Path::new(input).extension().is_some_and(|ext| ext.eq_ignore_ascii_case(SPU_EXTENSION)) {
+        bytes = CompiledUnit::read(&mut bytes.as_slice())
+            .map_err(|e| format!("Failed to read '{}': {}", input, e))?
+            .object;
+    }
+    ObjectFile::read(&mut bytes.as_slice()).map_err(|e| format!("Failed to read '{}': {}", input, e))
+}
+
+/// Character set of the machine's ROM font, if it is not plain ASCII/UTF-8
+fn target_codepage(target: TargetPlatform) -> Option<Codepage> {
+    match target {
+        TargetPlatform::ZXSpectrum => Some(Codepage::Zx),
+        _ => None,
+    }
+}
+
+/// Add `path`, or the `.pas` files beneath it in name order, to `files`
+fn collect_sources(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        fs::metadata(path)?;
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?.map(|entry| entry.map(|e| e.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let is_source = entry.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pas"));
+        if entry.is_dir() || is_source {
+            collect_sources(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Pascal unit generated from the register description `source`, named
+/// after the file unless the description names it
+fn register_unit(input_file: &str, source: &str) -> Result<String, String> {
+    let default_unit = Path::new(input_file).file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    RegisterMap::parse(source, &default_unit)
+        .map(|map| map.to_pascal())
+        .map_err(|e| format!("{}: {}", input_file, e))
+}
+
+/// Whether a {$L} module is assembly source rather than an object file
+fn is_assembly_module(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["asm", "s", "z80"].iter().any(|a| e.eq_ignore_ascii_case(a)))
+}
+
+/// An `external` routine: its Pascal name and the symbol it links against
+struct ExternalRoutine {
+    name: String,
+    symbol: String,
+}
+
+/// The parameters of an imported routine, as calls to it pass them
+fn routine_params(params: &[symbols::Parameter]) -> Vec<ir::RoutineParam> {
+    params
+        .iter()
+        .map(|param| ir::RoutineParam {
+            name: param.name.clone(),
+            param_type: param.param_type.clone(),
+            by_reference: param.passing_mode == ParameterMode::Var || param.param_type == Type::Untyped,
+        })
+        .collect()
+}
+
+/// Procedure and function declarations at the top level of a program or unit
+pub(crate) fn top_level_routines(ast: &Node) -> Vec<&Node> {
+    match ast {
+        Node::Program(program) => match program.block.as_ref() {
+            Node::Block(block) => block.proc_decls.iter().chain(block.func_decls.iter()).collect(),
+            _ => vec![],
+        },
+        Node::Unit(unit) => unit
+            .interface
+            .iter()
+            .flat_map(|i| i.proc_decls.iter().chain(i.func_decls.iter()))
+            .chain(unit.implementation.iter().flat_map(|i| i.proc_decls.iter().chain(i.func_decls.iter())))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The scheduler routine `name` stands for, if any
+fn task_routine(name: &str) -> Option<&'static str> {
+    tasks::TASK_ROUTINES.iter().copied().find(|routine| routine.eq_ignore_ascii_case(name))
+}
+
+/// The file routine `name` stands for, if any
+fn file_routine(name: &str) -> Option<&'static str> {
+    files::FILE_ROUTINES.iter().copied().find(|routine| routine.eq_ignore_ascii_case(name))
+}
+
+/// External procedures and functions declared at the top level of a
+/// program or unit; `external 'Symbol'` overrides the symbol
+fn collect_external_procs(ast: &Node) -> Vec<ExternalRoutine> {
+    top_level_routines(ast)
+        .into_iter()
+        .filter_map(|decl| match decl {
+            Node::ProcDecl(proc) if proc.is_external => Some((&proc.name, &proc.external_name)),
+            Node::FuncDecl(func) if func.is_external => Some((&func.name, &func.external_name)),
+            _ => None,
+        })
+        .map(|(name, external_name)| ExternalRoutine {
+            name: name.clone(),
+            symbol: external_name.clone().unwrap_or_else(|| name.clone()),
+        })
+        .collect()
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::TargetRegistry;
+    use object_zealz80::zealos::ZealOsExecutable;
+
+    /// A directory of its own for `test` under the system temporary
+    /// directory, emptied
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spc-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `source` as `name` in `dir`, returning its path
+    fn write(dir: &Path, name: &str, source: &str) -> String {
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        path.display().to_string()
+    }
+
+    fn compiler_for(target: &str) -> Compiler {
+        let mut compiler = Compiler::new();
+        compiler.set_target(TargetRegistry::builtin().find(target).unwrap().clone());
+        compiler
+    }
+
+    /// What `source` writes when run on the host-test target
+    fn run(test: &str, source: &str) -> InterpretedRun {
+        let dir = scratch(test);
+        let input = write(&dir, "program.pas", source);
+        let run = compiler_for("host-test").run_interpreted(&input).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        run
+    }
+
+    /// The listing `asm` prints for `source`, built by `compiler`
+    fn asm(mut compiler: Compiler, test: &str, source: &str) -> String {
+        let dir = scratch(test);
+        let input = write(&dir, "program.pas", source);
+        let mut codegen = CodeGenerator::with_intrinsics(compiler.intrinsics.clone());
+        let listing = compiler.assemble_listing(source, &input, &mut codegen);
+        fs::remove_dir_all(&dir).unwrap();
+        let (instructions, _) = listing.unwrap();
+        instructions.iter().map(|inst| format!("{}\n", inst)).collect()
+    }
+
+    /// Whether `listing` has the lines of `sequence` one after another
+    fn has_sequence(listing: &str, sequence: &[&str]) -> bool {
+        let lines: Vec<&str> = listing.lines().map(str::trim).collect();
+        lines.windows(sequence.len()).any(|window| window == sequence)
+    }
+
+    #[test]
+    fn test_host_test_runs_routines_enums_and_sets() {
+        let run = run(
+            "host-routines",
+            "program Routines;\n\
+             type Color = (Red, Green, Blue);\n\
+             var n: Integer; c: Color; d: set of 0..9;\n\
+             procedure P;\n\
+             begin\n  n := n + 1\nend;\n\
+             function Sq(X: Integer): Integer;\n\
+             begin\n  Sq := X * X\nend;\n\
+             begin\n\
+               n := 1;\n  P;\n  WriteLn(n);\n\
+               c := Green;\n  n := Ord(c);\n  WriteLn(n);\n  WriteLn(Ord(c));\n\
+               WriteLn(Sq(2));\n\
+               d := [1, 3];\n  if 3 in d then WriteLn('in');\n  if not (2 in d) then WriteLn('out')\n\
+             end.\n",
+        );
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "2\n1\n1\n4\nin\nout\n");
+    }
+
+    #[test]
+    fn test_set_operations_build_on_z80() {
+        let source = "program Sets;\n\
+                      type Color = (Red, Green, Blue, Cyan);\n\
+                      var s, t, u: set of Color; w: set of 0..20; n: Integer;\n\
+                      begin\n\
+                        s := [Red, Blue];\n  t := [Blue, Cyan];\n\
+                        u := s + t;\n  if Cyan in u then n := 1;\n\
+                        u := s - t;\n  if not (Blue in u) then n := n + 2;\n\
+                        u := s * t;\n  if u = [Blue] then n := n + 4;\n\
+                        s := s + [Green];\n  if Green in s then n := n + 8;\n\
+                        t := t + s;\n  if t = [Red..Cyan] then n := n + 16;\n\
+                        w := [1, 15];\n  if w = [1] then n := n + 32;\n  if w <= [1, 2, 15] then n := n + 64;\n\
+                        WriteLn(n)\n\
+                      end.\n";
+        let run = run("host-set-operations", source);
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "95\n");
+
+        // Operations reading their target are built in a frame temporary
+        let source = source.replace("WriteLn(n)", "n := n + 0");
+        let listing = asm(compiler_for("zealz80"), "z80-set-operations", &source);
+        for routine in ["__set_union", "__set_diff", "__set_inter", "__set_eq", "__set_subset"] {
+            assert!(listing.contains(&format!("call {}", routine)), "{}", listing);
+        }
+        assert!(has_sequence(&listing, &["push ix", "pop hl", "ld bc, 65534", "add hl, bc"]), "{}", listing);
+        assert!(!listing.contains("TODO"), "{}", listing);
+    }
+
+    #[test]
+    fn test_string_operations_build_on_z80() {
+        let source = "program Strings;\n\
+                      var s, t: string; u: string[5]; n: Integer;\n\
+                      begin\n\
+                        s := 'Hello';\n  t := ', world';\n\
+                        s := s + t;\n  u := Copy(s, 1, 3) + '!';\n\
+                        if s = 'Hello, world' then n := Length(s);\n\
+                        if Pos('wor', s + t) = 8 then n := n + 100;\n\
+                        WriteLn(s);\n  WriteLn(u);\n  WriteLn(n)\n\
+                      end.\n";
+        let run = run("host-string-operations", source);
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "Hello, world\nHel!\n112\n");
+
+        // `s + t` is built in a frame temporary of a whole string
+        let source = source.replace("WriteLn(s);\n  WriteLn(u);\n  WriteLn(n)", "n := n + 0");
+        let listing = asm(compiler_for("zealz80"), "z80-string-operations", &source);
+        assert!(has_sequence(&listing, &["push ix", "pop hl", "ld bc, 65280", "add hl, bc", "ld a, 255", "call __str_concat"]), "{}", listing);
+        assert!(listing.contains("call __str_compare"), "{}", listing);
+        assert!(!listing.contains("TODO"), "{}", listing);
+    }
+
+    #[test]
+    fn test_string_function_results_are_built_once() {
+        let run = run(
+            "host-string-function",
+            "program Names;\n\
+             var s: string; n: Integer;\n\
+             function Name: string;\n\
+             begin\n  Name := 'Ada'\nend;\n\
+             function Greet(Times: Integer): string[12];\n\
+             begin\n\
+               Result := 'Hi';\n\
+               if Times = 0 then Exit('none');\n\
+               while Times > 1 do\n  begin\n    Result := Result + '!';\n    Times := Times - 1\n  end\n\
+             end;\n\
+             begin\n\
+               s := Name();\n  WriteLn(s);\n\
+               s := Greet(3) + ' ' + Name;\n  WriteLn(s);\n\
+               WriteLn(Greet(0));\n\
+               n := Length(Greet(4));\n  WriteLn(n)\n\
+             end.\n",
+        );
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "Ada\nHi!! Ada\nnone\n5\n");
+    }
+
+    #[test]
+    fn test_z80_string_function_results_are_copied_to_the_callers_buffer() {
+        let source = "program Names;\n\
+                      var s: string;\n\
+                      function Name: string[5];\n\
+                      begin\n  Name := 'Ada'\nend;\n\
+                      begin\n  s := Name()\nend.\n";
+        let listing = asm(compiler_for("zealz80"), "z80-string-function", source);
+        assert!(has_sequence(&listing, &["push ix", "pop hl", "ld bc, 65530", "add hl, bc"]), "{}", listing);
+        assert!(has_sequence(&listing, &["push hl", "call _Name"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld a, 5", "ld hl, (ix+4)", "call __str_copy"]), "{}", listing);
+        assert!(!listing.contains("TODO"), "{}", listing);
+    }
+
+    #[test]
+    fn test_z80_build_emits_the_program_and_its_globals() {
+        let dir = scratch("z80-build");
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Count;\nvar n: Integer; b: Boolean;\nprocedure P;\nbegin\n  n := n + 1\nend;\nbegin\n  n := 1;\n  P;\n  b := n > 1\nend.\n",
+        );
+        let output = dir.join("program.o").display().to_string();
+        compiler_for("zealz80").compile_file(&input, Some(&output)).unwrap();
+        let object = read_object(&output).unwrap();
+        assert!(!object.code.is_empty());
+        let symbol = |name: &str| object.symbols.iter().find(|symbol| symbol.name == name);
+        assert!(symbol("_main").is_some_and(|main| main.size > 0));
+        assert!(symbol("_P").is_some_and(|p| p.size > 0));
+        assert!(symbol("n").is_some_and(|n| n.section == Section::Bss && n.size == 2));
+        assert!(symbol("b").is_some_and(|b| b.offset == 2 && b.size == 1));
+        assert_eq!(object.bss_size, 3);
+
+        // What the Z80 code generator cannot translate yet fails the build
+        let input = write(&dir, "hello.pas", "program Hello;\nbegin\n  WriteLn('Hello')\nend.\n");
+        let result = compiler_for("zealz80").compile_file(&input, Some(&output));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Err("Compilation failed with 1 error(s)".to_string()));
+    }
+
+    #[test]
+    fn test_byte_variables_are_loaded_and_stored_a_byte_at_a_time() {
+        let source = "program Bytes;\nvar b, c: Byte; n: Integer;\nbegin\n  c := 7;\n  b := 3;\n  n := b + c\nend.\n";
+        let listing = asm(compiler_for("zealz80"), "z80-byte-variables", source);
+        assert!(has_sequence(&listing, &["ld hl, 3", "ld a, l", "ld (b), a"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld a, (c)", "ld e, a", "ld d, 0"]), "{}", listing);
+        assert!(!listing.contains("ld (b), hl") && !listing.contains("ld (c), hl"), "{}", listing);
+        assert!(listing.contains("ld (n), hl"), "{}", listing);
+    }
+
+    #[test]
+    fn test_built_program_links_to_a_runnable_image() {
+        let dir = scratch("z80-link");
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Count;\nvar n: Integer;\nprocedure P;\nbegin\n  n := n + 1\nend;\nbegin\n  n := 1;\n  P\nend.\n",
+        );
+        let object = dir.join("program.o").display().to_string();
+        let image = dir.join("program.bin").display().to_string();
+        let map = dir.join("program.map").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&input, Some(&object)).unwrap();
+        compiler.set_map_file(Some(map.clone()));
+        compiler.link(std::slice::from_ref(&object), &image, ImageFormat::Binary, None).unwrap();
+        let bytes = fs::read(&image).unwrap();
+        let map = fs::read_to_string(&map).unwrap();
+        let linked = linker::link(&[read_object(&object).unwrap()], 0x4000).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The main routine starts the image, and stores to `n` in BSS
+        assert!(!bytes.is_empty());
+        assert_eq!(bytes, linked.bytes);
+        let address = |name: &str| linked.symbols.iter().find(|(symbol, _)| symbol == name).map(|(_, at)| *at);
+        assert_eq!(address("_main"), Some(0x4000));
+        assert!(address("_P").is_some());
+        let n = address("n").unwrap();
+        assert_eq!((n, linked.bss_size), (linked.bss_start, 2));
+        let [low, high] = n.to_le_bytes();
+        assert!(bytes.windows(3).any(|ld| ld == [0x22, low, high]));
+        assert!(map.contains("_main"));
+        assert!(map.lines().any(|line| line.contains("BSS") && line.contains(" n ")));
+    }
+
+    #[test]
+    fn test_units_instantiating_the_same_generic_link_one_instance() {
+        let dir = scratch("z80-link-generics");
+        let unit = |name: &str, bound: u8| {
+            format!(
+                "unit U{name};\ninterface\nfunction Next{name}(N: integer): integer;\nimplementation\n\
+                 function Max<T>(X, Y: T): T;\nbegin\n  if X > Y then Max := X else Max := Y\nend;\n\
+                 function Next{name}(N: integer): integer;\nbegin\n  Next{name} := Max(N, {bound})\nend;\nend.\n"
+            )
+        };
+        let ua = write(&dir, "ua.pas", &unit("A", 3));
+        let ub = write(&dir, "ub.pas", &unit("B", 5));
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Main;\nuses UA, UB;\nvar n: integer;\nbegin\n  n := NextA(n);\n  n := NextB(n)\nend.\n",
+        );
+        let objects: Vec<String> = ["program.o", "ua.spu", "ub.spu"].iter().map(|name| dir.join(name).display().to_string()).collect();
+        let image = dir.join("program.bin").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&ua, None).unwrap();
+        compiler.compile_file(&ub, None).unwrap();
+        compiler.compile_file(&input, Some(&objects[0])).unwrap();
+        compiler.link(&objects, &image, ImageFormat::Binary, None).unwrap();
+        let bytes = fs::read(&image).unwrap();
+        let units: Vec<ObjectFile> = objects.iter().map(|path| read_object(path).unwrap()).collect();
+        let linked = linker::link(&units, 0x4000).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Each unit makes the same instance, not one specialized for its calls
+        let instance = |object: &ObjectFile| {
+            let symbol = object.symbols.iter().find(|symbol| symbol.name == "_Max_of_integer").unwrap();
+            assert_eq!(symbol.visibility, SymbolVisibility::Mergeable);
+            object.code[symbol.offset as usize..(symbol.offset + symbol.size) as usize].to_vec()
+        };
+        assert_eq!(instance(&units[1]), instance(&units[2]));
+
+        // The image keeps one copy, which both units call with their bound
+        assert_eq!(bytes, linked.bytes);
+        let address = |name: &str| linked.symbols.iter().filter(|(symbol, _)| symbol == name).map(|(_, at)| *at).collect::<Vec<_>>();
+        assert_eq!(address("_main"), [0x4000]);
+        let max = address("_Max_of_integer");
+        assert_eq!(max.len(), 1);
+        let [low, high] = max[0].to_le_bytes();
+        assert_eq!(bytes.windows(3).filter(|call| *call == [0xCD, low, high]).count(), 2);
+        for bound in [3, 5] {
+            assert!(bytes.windows(3).any(|ld| ld == [0x21, bound, 0]));
+        }
+    }
+
+    #[test]
+    fn test_absolute_variables_resolve_to_their_address_without_bss() {
+        let dir = scratch("z80-link-absolutes");
+        let unit = write(
+            &dir,
+            "screen.pas",
+            "unit Screen;\ninterface\ntype\n  TScreenPixels = array[0..6143] of Byte;\nvar\n\
+             \x20 ScreenPixels: TScreenPixels absolute $4000;\n  PixelPair: Word absolute $4000;\n\
+             implementation\nend.\n",
+        );
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Main;\nuses Screen;\nvar Border: Byte absolute $5C48;\nbegin\n  PixelPair := 513;\n  Border := 2\nend.\n",
+        );
+        let objects: Vec<String> = ["program.o", "screen.spu"].iter().map(|name| dir.join(name).display().to_string()).collect();
+        let image = dir.join("program.bin").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&unit, None).unwrap();
+        compiler.compile_file(&input, Some(&objects[0])).unwrap();
+        compiler.link(&objects, &image, ImageFormat::Binary, None).unwrap();
+        let bytes = fs::read(&image).unwrap();
+        let units: Vec<ObjectFile> = objects.iter().map(|path| read_object(path).unwrap()).collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Neither object reserves room for them
+        assert_eq!((units[0].bss_size, units[1].bss_size), (0, 0));
+        assert_eq!(units[0].absolutes, [("Border".to_string(), 0x5C48)]);
+        assert_eq!(units[1].absolutes, [("ScreenPixels".to_string(), 0x4000), ("PixelPair".to_string(), 0x4000)]);
+
+        // ld (PixelPair), hl and ld (Border), a
+        assert!(bytes.windows(3).any(|ld| ld == [0x22, 0x00, 0x40]));
+        assert!(bytes.windows(3).any(|ld| ld == [0x32, 0x48, 0x5C]));
+    }
+
+    #[test]
+    fn test_programs_using_the_tasks_unit_get_the_scheduler_and_its_pools() {
+        let dir = scratch("z80-link-tasks");
+        let unit = write(&dir, "tasks.pas", include_str!("../../../../lib/tasks/tasks.pas"));
+        // The usage of lib/tasks/README.md
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Game;\nuses Tasks;\n\
+             procedure Enemies;\nbegin\n  while True do\n  begin\n    Yield;\n  end;\nend;\n\
+             procedure Music;\nbegin\n  while True do\n  begin\n    Yield;\n  end;\nend;\n\
+             begin\n  CreateTask(@Enemies, TaskStackSize(Enemies));\n  CreateTask(@Music, TaskStackSize(Music));\n\
+             \x20 while True do\n  begin\n    Yield;\n  end;\nend.\n",
+        );
+        let objects: Vec<String> = ["program.o", "tasks.spu"].iter().map(|name| dir.join(name).display().to_string()).collect();
+        let image = dir.join("program.bin").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&unit, None).unwrap();
+        compiler.compile_file(&input, Some(&objects[0])).unwrap();
+        compiler.link(&objects, &image, ImageFormat::Binary, None).unwrap();
+        let bytes = fs::read(&image).unwrap();
+        let program = read_object(&objects[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The program holds the scheduler, with a stack and a TCB per task
+        let size = |name: &str| program.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.size);
+        assert!(tasks::TASK_ROUTINES.iter().all(|routine| size(routine).is_some()));
+        let stacks = size(tasks::STACK_POOL_SYMBOL).unwrap();
+        assert_eq!(size(tasks::TCB_POOL_SYMBOL), Some(2 * tasks::TCB_SIZE));
+        assert!(stacks > 2 * tasks::TASK_CONTEXT_SIZE);
+
+        // Each CreateTask is given its entry and TaskStackSize of it
+        let [low, high] = (stacks / 2).to_le_bytes();
+        assert_eq!(bytes.windows(3).filter(|ld| *ld == [0x21, low, high]).count(), 2);
+        assert_eq!(program.relocations.iter().filter(|relocation| relocation.symbol_name == "CreateTask").count(), 2);
+        for entry in ["_Enemies", "_Music"] {
+            assert!(program.relocations.iter().any(|relocation| relocation.symbol_name == entry));
+        }
+    }
+
+    #[test]
+    fn test_threadvars_of_programs_using_the_tasks_unit_live_in_each_tcb() {
+        let dir = scratch("z80-link-threadvars");
+        let unit = write(&dir, "tasks.pas", include_str!("../../../../lib/tasks/tasks.pas"));
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Frames;\nuses Tasks;\nthreadvar Frame: Word;\n\
+             procedure Worker;\nbegin\n  while True do\n  begin\n    Frame := Frame + 1;\n    Yield;\n  end;\nend;\n\
+             begin\n  CreateTask(@Worker, TaskStackSize(Worker));\n  Frame := 5;\n  Yield;\nend.\n",
+        );
+        let objects: Vec<String> = ["program.o", "tasks.spu"].iter().map(|name| dir.join(name).display().to_string()).collect();
+        let image = dir.join("program.bin").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&unit, None).unwrap();
+        compiler.compile_file(&input, Some(&objects[0])).unwrap();
+        compiler.link(&objects, &image, ImageFormat::Binary, None).unwrap();
+        let program = read_object(&objects[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Each TCB is followed by its task's Frame, reached through the
+        // running task in both the worker and the main program
+        let size = |name: &str| program.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.size);
+        assert_eq!(size(tasks::TCB_POOL_SYMBOL), Some(tasks::TCB_SIZE + 2));
+        assert!(program.symbols.iter().all(|symbol| symbol.name != "Frame" && symbol.name != "_Frame"));
+        let current = |routine: &str| {
+            let start = program.symbols.iter().find(|symbol| symbol.name == routine).unwrap().offset;
+            let end = start + size(routine).unwrap();
+            program
+                .relocations
+                .iter()
+                .filter(|relocation| relocation.symbol_name == tasks::CURRENT_TASK_SYMBOL)
+                .filter(|relocation| (start..end).contains(&relocation.offset))
+                .count()
+        };
+        assert_eq!(current("_main"), 1);
+        assert_eq!(current("_Worker"), 2);
+    }
+
+    #[test]
+    fn test_host_test_passes_var_parameters_by_address() {
+        let run = run(
+            "host-var-params",
+            "program VarParams;\nvar A, B: Integer; Hits: Byte;\n\
+             procedure Swap(var X, Y: Integer);\nvar T: Integer;\nbegin\n  T := X;\n  X := Y;\n  Y := T\nend;\n\
+             procedure Order(var X, Y: Integer);\nbegin\n  if X > Y then Swap(X, Y)\nend;\n\
+             procedure Count(var N: Byte);\nbegin\n  Inc(N);\n  N := N + 10\nend;\n\
+             procedure Twice;\nvar L: Byte;\nbegin\n  L := 1;\n  Count(L);\n  Count(Hits);\n  WriteLn(L)\nend;\n\
+             begin\n  A := 7;\n  B := 3;\n  Order(A, B);\n  WriteLn(A, ' ', B);\n  Hits := 0;\n  Twice;\n  WriteLn(Hits)\nend.\n",
+        );
+        // Order passes on the addresses it was given; locals and globals
+        // are reached alike
+        assert_eq!(run.output, "3 7\n12\n11\n");
+    }
+
+    #[test]
+    fn test_untyped_parameters_are_passed_by_address() {
+        let dir = scratch("z80-link-untyped-params");
+        let unit = write(&dir, "files.pas", include_str!("../../../../lib/files/files.pas"));
+        let source = "program Load;\nuses Files;\nconst LevelName = 'LEVEL1.DAT';\n\
+                      var F: File; Map: array[0..7] of Byte; Done: Word;\n\
+                      procedure Fill(var Buf; Count: Word);\nbegin\n  asm\n    ld hl, (@Buf)\n    ld bc, (@Count)\n  end\nend;\n\
+                      procedure Clear(var Buf);\nbegin\n  Fill(Buf, 8)\nend;\n\
+                      begin\n  Assign(F, LevelName, 10);\n  Reset(F, 1);\n  BlockRead(F, Map, 8, Done);\n  Clear(Map);\n  Close(F)\nend.\n";
+        let input = write(&dir, "program.pas", source);
+        let objects: Vec<String> = ["program.o", "files.spu"].iter().map(|name| dir.join(name).display().to_string()).collect();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&unit, None).unwrap();
+        compiler.compile_file(&input, Some(&objects[0])).unwrap();
+        compiler.link(&objects, &dir.join("program.bin").display().to_string(), ImageFormat::Binary, None).unwrap();
+        let mut codegen = CodeGenerator::with_intrinsics(compiler.intrinsics.clone());
+        let (instructions, _) = compiler.assemble_listing(source, &input, &mut codegen).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let listing: String = instructions.iter().map(|inst| format!("{}\n", inst)).collect();
+
+        // Variables are passed as their address, a string constant as that
+        // of its length byte, to the file routines the unit generates
+        assert!(has_sequence(&listing, &["ld hl, __str_0", "push hl", "ld hl, 10", "push hl", "call Assign"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld hl, Map", "ld (ix-2), hl", "ld hl, Done", "ld (ix-4), hl"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld hl, (ix-4)", "push hl", "call BlockRead"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld hl, Map", "push hl"]), "{}", listing);
+        // Clear passes on the address it was given, which Fill's assembly
+        // reads from its slot
+        assert!(has_sequence(&listing, &["Clear_entry:", "ld hl, (ix+4)", "push hl", "ld hl, 8", "push hl", "call _Fill"]), "{}", listing);
+        assert!(has_sequence(&listing, &["Fill_entry:", "ld hl, (ix+6)", "ld bc, (ix+4)"]), "{}", listing);
+    }
+
+    #[test]
+    fn test_host_test_runs_without_program_parameters() {
+        let run = run(
+            "host-params",
+            "program Args;\nvar N: Integer; S: string;\n\
+             begin\n\
+               N := ParamCount;\n  WriteLn(N, ' ', ParamCount(), ' ', ParamCount);\n\
+               S := 'x' + ParamStr(1);\n  WriteLn(S, Length(ParamStr(0)));\n\
+               WriteLn('[', ParamStr(N), ']')\n\
+             end.\n",
+        );
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "0 0 0\nx0\n[]\n");
+    }
+
+    #[test]
+    fn test_program_parameters_call_the_parameter_routines() {
+        let dir = scratch("z80-params-object");
+        let source = "program Args;\nvar N: Integer; S: string;\n\
+                      begin\n  N := ParamCount;\n  N := ParamCount();\n  S := ParamStr(N);\n  N := Length(ParamStr(1))\nend.\n";
+        let input = write(&dir, "program.pas", source);
+        let object = dir.join("program.o").display().to_string();
+        compiler_for("zealz80").compile_file(&input, Some(&object)).unwrap();
+        let program = read_object(&object).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let listing = asm(compiler_for("zealz80"), "z80-params", source);
+
+        // The object holds the routines and the buffer they fill
+        for name in ["__params_save", "ParamCount", "ParamStr", "__params_buffer"] {
+            assert!(program.symbols.iter().any(|symbol| symbol.name == name), "{}", name);
+        }
+        // The parameter text is saved while the registers still hold it
+        assert!(has_sequence(&listing, &["_main:", "call __params_save", "push ix"]), "{}", listing);
+        assert_eq!(listing.matches("call ParamCount").count(), 2, "{}", listing);
+        // ParamStr leaves the parameter in its buffer, which a string
+        // operand is copied out of
+        assert!(has_sequence(&listing, &["ld (N), hl", "push hl", "call ParamStr", "ld hl, __params_buffer", "ex de, hl"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld bc, 65280", "add hl, bc", "ld a, 255", "call __str_copy"]), "{}", listing);
+        assert!(!listing.contains("TODO"), "{}", listing);
+    }
+
+    #[test]
+    fn test_host_test_indexes_arrays_with_range_checks() {
+        let run = run(
+            "host-arrays",
+            "program Arrays;\n{$R+}\n\
+             type TRow = array[1..3] of Integer;\n  TGrid = array[0..1] of TRow;\n\
+             var A: array[1..10] of Integer; B: array[-1..2] of Byte; G: TGrid; I, N: Integer;\n\
+             function Square(X: byte): word;\nbegin\n  Square := X * X\nend;\n\
+             const Squares = GenerateTable(Square, 0, 15);\n\
+             procedure Fill(var R: TRow; K: Byte);\nvar L: array[0..2] of Byte; J: Integer;\nbegin\n\
+               for J := 0 to 2 do L[J] := K;\n  L[2] := 9;\n  for J := 1 to 3 do R[J] := L[J - 1]\nend;\n\
+             begin\n\
+               A[3] := 6;\n  B[2] := 7;\n  B[-1] := 1;\n  I := 3;\n\
+               WriteLn(A[I] + B[2], ' ', B[I - 4]);\n\
+               for I := 1 to 10 do A[I] := I * 10;\n  WriteLn(A[1], ' ', A[10]);\n\
+               Fill(G[1], 4);\n  G[0][2] := G[1][3];\n  WriteLn(G[0][2], ' ', G[1][1]);\n\
+               N := 0;\n  for I := 0 to 15 do N := N + Squares[I];\n  WriteLn(Squares[3], ' ', N);\n\
+               I := 11;\n  A[I] := 1;\n  WriteLn('unreachable')\n\
+             end.\n",
+        );
+        assert_eq!(run.output, "13 1\n10 100\n9 4\n9 1240\n");
+        // A[11] is stopped by the index check
+        assert_eq!(run.error.as_deref(), Some("range check error"));
+    }
+
+    #[test]
+    fn test_z80_array_indexes_are_checked_and_loaded_through_their_address() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-arrays",
+            "program Arrays;\n{$R+}\n\
+             var A: array[1..10] of Integer; B: array[0..3] of Byte; I, N: Integer;\n\
+             function Square(N: byte): word;\nbegin\n  Square := N * N\nend;\n\
+             const Squares = GenerateTable(Square, 0, 15);\n\
+             begin\n  I := 2;\n  B[2] := 7;\n  N := A[I] + B[2];\n  N := Squares[I]\nend.\n",
+        );
+
+        // Each variable index is checked against the bounds first
+        assert_eq!(listing.matches("call __range_error").count(), 2, "{}", listing);
+        // then scaled and added to the array's address, and the element
+        // read through it
+        assert!(has_sequence(&listing, &["ld hl, (I)", "dec hl"]), "{}", listing);
+        assert!(listing.contains("ld hl, A\n"), "{}", listing);
+        assert!(listing.contains("ld hl, Squares\n"), "{}", listing);
+        assert_eq!(listing.matches("ld e, (hl)\n    inc hl\n    ld d, (hl)").count(), 2, "{}", listing);
+        // A byte element at a constant index is read as a byte
+        assert!(listing.contains("ld a, (B+2)"), "{}", listing);
+        assert!(!listing.contains("TODO"), "{}", listing);
+    }
+
+    #[test]
+    fn test_classes_compile_to_vmts_and_virtual_calls() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-classes",
+            "program Zoo;\n\
+             type\n\
+               TAnimal = class\n    Legs: Integer;\n    constructor Create;\n    function Count: Integer; virtual;\n  end;\n\
+               TDog = class(TAnimal)\n    function Count: Integer; override;\n  end;\n\
+             constructor TAnimal.Create;\nbegin\n  Legs := 4\nend;\n\
+             function TAnimal.Count: Integer;\nbegin\n  Result := Legs\nend;\n\
+             function TDog.Count: Integer;\nbegin\n  Result := 2\nend;\n\
+             var a: TAnimal; n: Integer;\n\
+             begin\n  a := TDog.Create;\n  n := a.Count\nend.\n",
+        );
+        // A TDog made for a TAnimal variable gets its own table, whose
+        // Count slot the call reads
+        assert!(has_sequence(&listing, &["ld bc, __vmt_TDog", "call __new"]));
+        assert!(listing.contains("call _TAnimal__Create"));
+        assert!(has_sequence(&listing, &["ld de, 2", "add hl, de"]));
+        assert!(listing.contains("call __call_hl"));
+        assert!(listing.lines().any(|line| line == "__vmt_TDog:"));
+        assert!(listing.contains("_TDog__Count"));
+        // Fields are a word past the table pointer
+        assert!(has_sequence(&listing, &["ld hl, (ix+4)", "inc hl", "inc hl"]));
+    }
+
+    #[test]
+    fn test_host_test_compares_integers_with_word_literals() {
+        let run = run(
+            "host-compare-word",
+            "program Compare;\nvar n: integer;\nbegin\n  n := -5;\n\
+             \x20 if n < 65000 then WriteLn('less') else WriteLn('not less');\n\
+             \x20 if 40000 > n then WriteLn('greater') else WriteLn('not greater')\nend.\n",
+        );
+        assert_eq!(run.output, "less\ngreater\n");
+    }
+
+    #[test]
+    fn test_host_test_calls_virtual_methods_through_the_vmt() {
+        let run = run(
+            "host-virtual",
+            "program Zoo;\n\
+             type\n\
+               TAnimal = class\n    Legs: Integer;\n    constructor Create;\n    function Count: Integer; virtual;\n  end;\n\
+               TDog = class(TAnimal)\n    function Count: Integer; override;\n  end;\n\
+             constructor TAnimal.Create;\nbegin\n  Legs := 4\nend;\n\
+             function TAnimal.Count: Integer;\nbegin\n  Result := Legs\nend;\n\
+             function TDog.Count: Integer;\nbegin\n  Result := Legs - 2\nend;\n\
+             var a, d: TAnimal;\n\
+             begin\n  a := TAnimal.Create;\n  d := TDog.Create;\n  WriteLn(a.Count);\n  WriteLn(d.Count)\nend.\n",
+        );
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "4\n2\n");
+    }
+
+    #[test]
+    fn test_constructors_allocate_and_destructors_free() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-constructors",
+            "program Boxes;\n\
+             type\n\
+               TBox = class\n    V: Integer;\n    constructor Create(AV: Integer);\n    destructor Destroy;\n  end;\n\
+             constructor TBox.Create(AV: Integer);\nbegin\n  V := AV\nend;\n\
+             destructor TBox.Destroy;\nbegin\nend;\n\
+             var b: TBox; n: Integer;\n\
+             begin\n  b := TBox.Create(7);\n  n := b.V;\n  b.Destroy\nend.\n",
+        );
+        // The constructor runs on the instance allocated for it, which the
+        // destructor call releases after running its body
+        assert!(has_sequence(&listing, &["ld bc, 0", "call __new"]));
+        assert!(has_sequence(&listing, &["push hl", "ld hl, 7", "push hl", "call _TBox__Create"]));
+        assert!(has_sequence(&listing, &["call _TBox__Destroy", "ld hl, (b)", "call __dispose"]));
+        assert!(listing.lines().any(|line| line == "__new:"));
+        assert!(listing.lines().any(|line| line == "__dispose:"));
+    }
+
+    #[test]
+    fn test_interface_calls_resolve_through_the_class_table() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-interfaces",
+            "program Shapes;\n\
+             type\n\
+               IShape = interface\n    function Area: Integer;\n  end;\n\
+               TSquare = class(IShape)\n    Side: Integer;\n    constructor Create(ASide: Integer);\n    function Area: Integer;\n  end;\n\
+             constructor TSquare.Create(ASide: Integer);\nbegin\n  Side := ASide\nend;\n\
+             function TSquare.Area: Integer;\nbegin\n  Result := Side + Side\nend;\n\
+             var s: TSquare; sh: IShape; n: Integer; f: Boolean;\n\
+             begin\n  s := TSquare.Create(3);\n  sh := s;\n  n := sh.Area();\n  f := s is IShape;\n  if f then n := 1\nend.\n",
+        );
+        // The call looks up the method table TSquare has for IShape
+        assert!(has_sequence(&listing, &["ld de, __iid_IShape", "call __intf_query"]));
+        assert!(listing.contains("call __call_hl"));
+        // `is` tests the zero flag the same lookup leaves
+        assert!(has_sequence(&listing, &["call __intf_query", "ld hl, 0"]));
+        assert!(listing.lines().any(|line| line == "__iid_IShape:"));
+        assert!(listing.contains("dw _TSquare__Area"));
+    }
+
+    #[test]
+    fn test_exceptions_unwind_to_the_handler() {
+        let source = "program Exc;\nvar n: Integer;\n\
+                      procedure Check(X: Integer);\nbegin\n  if X > 2 then raise 7;\n  n := n + X\nend;\n\
+                      begin\n  n := 0;\n\
+                        try\n    Check(1);\n    Check(5);\n    n := 100\n  except\n    n := n + 10\n  end;\n\
+                        try\n    n := n + 1\n  finally\n    n := n * 2\n  end;\n\
+                        WriteLn(n)\n\
+                      end.\n";
+        let run = run("host-exceptions", source);
+        assert_eq!(run.error, None);
+        assert_eq!(run.output, "24\n");
+
+        // The Z80 build pushes a handler frame and links the raise routine
+        let source = source.replace("WriteLn(n)", "n := n + 0");
+        let listing = asm(compiler_for("zealz80"), "z80-exceptions", &source);
+        assert!(has_sequence(&listing, &["ld hl, try_handler_0", "push hl", "push ix"]), "{}", listing);
+        assert!(listing.contains("jp __exc_raise"), "{}", listing);
+        assert!(!link_image("z80-exceptions-link", &source, ImageFormat::Binary, None).is_empty());
+    }
+
+    #[test]
+    fn test_unroll_hint_copies_the_loop_body() {
+        let source = |hint: &str| {
+            format!(
+                "program Sum;\nvar i, n: Integer;\nbegin\n  n := 0;\n  {}\n  for i := 1 to 8 do\n    n := n + i;\n  i := n\nend.\n",
+                hint
+            )
+        };
+        let listing = asm(compiler_for("zealz80"), "z80-unroll", &source("{$UNROLL 4}"));
+        assert!(has_sequence(&listing, &["jr for_body_0_u1", "for_body_0_u1:"]));
+        assert!(listing.contains("for_body_0_u3:"));
+        assert!(!listing.contains("for_body_0_u4:"));
+
+        // Optimizing for size keeps a single copy
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_optimization_goal(OptimizationGoal::Size);
+        let listing = asm(compiler, "z80-unroll-size", &source(""));
+        assert!(listing.contains("for_body_0:"));
+        assert!(!listing.contains("for_body_0_u1:"));
+    }
+
+    #[test]
+    fn test_value_in_hl_is_not_reloaded_by_the_next_statement() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-reloads",
+            "program Copy;\nvar n, i: Integer;\nbegin\n  n := 5;\n  i := n;\n  n := i + 1\nend.\n",
+        );
+        assert!(has_sequence(&listing, &["ld hl, 5", "ld (n), hl", "ld (i), hl", "inc hl", "ld (n), hl"]));
+        assert!(!listing.contains("ld hl, (n)"));
+        assert!(!listing.contains("ld hl, (i)"));
+    }
+
+    #[test]
+    fn test_counting_and_comparing_use_the_16_bit_idioms() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-idioms",
+            "program Count;\nvar n, i: Integer;\nbegin\n  n := 3;\n\
+             while n <> 0 do\n  begin\n    n := n - 1;\n    i := i + 1\n  end;\n\
+             if i < n then i := 0\nend.\n",
+        );
+        // Stepping by one, testing for zero and comparing without the
+        // runtime's compare routine
+        assert!(has_sequence(&listing, &["ld hl, (n)", "dec hl", "ld (n), hl"]));
+        assert!(has_sequence(&listing, &["ld hl, (i)", "inc hl", "ld (i), hl"]));
+        assert!(has_sequence(&listing, &["ld hl, (n)", "ld a, h", "or l", "jr nz, while_body_1"]));
+        assert!(has_sequence(&listing, &["or a", "sbc hl, de", "jr c, if_then_3"]));
+        assert!(!listing.contains("call __"));
+    }
+
+    #[test]
+    fn test_constant_multiply_and_divide_become_shifts_and_adds() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-strength",
+            "program Scale;\nvar n, m, k: Integer; w: Word;\nbegin\n  n := m * 8;\n  k := m * 10;\n  w := w div 4\nend.\n",
+        );
+        assert!(has_sequence(&listing, &["ld hl, (m)", "add hl, hl", "add hl, hl", "add hl, hl", "ld (n), hl"]));
+        // m * 10 is (m * 4 + m) * 2
+        assert!(has_sequence(&listing, &["ld de, (m)", "add hl, de"]));
+        assert!(has_sequence(&listing, &["add hl, hl", "ld (k), hl"]));
+        assert!(has_sequence(&listing, &["ld hl, (w)", "srl h", "rr l", "srl h", "rr l", "ld (w), hl"]));
+        assert!(!listing.contains("__mul16"));
+        assert!(!listing.contains("__div"));
+    }
+
+    #[test]
+    fn test_arithmetic_proven_to_fit_a_byte_runs_in_a() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-narrowing",
+            "program Digit;\nvar w, d: Word;\nbegin\n  d := w mod 10 + 48\nend.\n",
+        );
+        // A remainder by 10 is below 10, so the digit made from it is a byte
+        assert!(listing.contains("call __modu16"));
+        assert!(has_sequence(&listing, &["ld a, c", "ld e, 48", "add a, e", "ld l, a", "ld h, 0", "ld (d), hl"]));
+        assert!(!listing.contains("add hl, de"));
+    }
+
+    #[test]
+    fn test_in_place_updates_of_locals_fold_into_memory_operands() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-folding",
+            "program Fold;\nvar n: Integer; f: Boolean;\n\
+             procedure P;\nvar i: Integer; s: set of 0..7;\n\
+             begin\n  i := n;\n  Dec(i);\n  s := [3];\n  n := i;\n  f := 3 in s\nend;\n\
+             begin\n  P\nend.\n",
+        );
+        assert!(has_sequence(
+            &listing,
+            &["ld a, (ix-2)", "or a", "jr nz, fold_0", "dec (ix-1)", "fold_0:", "dec (ix-2)"]
+        ));
+        assert!(has_sequence(&listing, &["call __set_clear", "set 3, (ix-4)"]));
+        assert!(!listing.contains("call __set_incl"));
+    }
+
+    #[test]
+    fn test_temporary_live_across_a_call_is_kept_in_iy() {
+        let listing = asm(
+            compiler_for("zealz80"),
+            "z80-regalloc",
+            "program Sum;\nvar n: Integer;\n\
+             function F(X: Integer): Integer;\nbegin\n  F := X + 1\nend;\n\
+             begin\n  n := F(1) + F(2)\nend.\n",
+        );
+        // The first result waits in IY, which main saves, not in a spill slot
+        assert!(has_sequence(&listing, &["call _F", "push hl", "pop iy", "ld hl, 2", "push hl", "call _F"]));
+        assert!(has_sequence(&listing, &["push iy", "pop hl", "add hl, bc", "ld (n), hl"]));
+        assert!(!listing.contains("(ix-4)"));
+    }
+
+    #[test]
+    fn test_constants_fold_into_values_and_branches() {
+        let source = |switch: &str| {
+            format!(
+                "program Fold;\n{}var n: Integer;\n\
+                 procedure P;\nvar k: Integer;\n\
+                 begin\n  k := 5;\n  n := (k + 1) * 4;\n  if k > 4 then n := 1 else n := 2\nend;\n\
+                 begin\n  P\nend.\n",
+                switch
+            )
+        };
+        // Storing to a global leaves what is known of the frame
+        let listing = asm(compiler_for("zealz80"), "z80-constants", &source(""));
+        assert!(has_sequence(&listing, &["ld hl, 24", "ld (n), hl", "jr if_then_0"]));
+        assert!(!listing.contains("sbc hl, de"));
+
+        let listing = asm(compiler_for("zealz80"), "z80-constants-off", &source("{$OPTIMIZATION OFF}\n"));
+        assert!(!listing.contains("ld hl, 24"));
+        assert!(listing.contains("sbc hl, de"));
+    }
+
+    #[test]
+    fn test_o2_computes_a_repeated_expression_once() {
+        let source = "program Same;\nvar a, b, n: Integer;\nbegin\n  n := (a + b) - (b + a);\n  b := n\nend.\n";
+        let adds = |listing: &str| listing.lines().filter(|line| line.trim() == "add hl, de").count();
+        let listing = asm(compiler_for("zealz80"), "z80-cse-o1", source);
+        assert_eq!(adds(&listing), 2);
+
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_optimization_level(2);
+        let listing = asm(compiler, "z80-cse-o2", source);
+        assert_eq!(adds(&listing), 1);
+        // b + a is the sum already in BC
+        assert!(has_sequence(&listing, &["or a", "sbc hl, bc", "ld (n), hl"]));
+    }
+
+    #[test]
+    fn test_oz_outlines_statements_repeated_on_globals() {
+        let step = "  a := b + c;\n  d := a - 3;\n  c := d + b;\n";
+        let source = format!(
+            "program Steps;\nvar a, b, c, d: Integer;\nbegin\n{}  b := b + 1;\n{}  b := b + 2;\n{}end.\n",
+            step, step, step
+        );
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_outlining(true);
+        let listing = asm(compiler, "z80-outline", &source);
+        assert_eq!(listing.lines().filter(|line| line.trim() == "call ___outlined_0").count(), 3);
+        assert!(has_sequence(&listing, &["ld de, (c)", "ld hl, (b)", "add hl, de", "ld (a), hl"]));
+        assert_eq!(listing.matches("ld (a), hl").count(), 1);
+    }
+
+    /// The memory map `build --map` writes for `source`
+    fn build_map(test: &str, source: &str) -> String {
+        let dir = scratch(test);
+        let input = write(&dir, "program.pas", source);
+        let map = dir.join("program.map").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_map_file(Some(map.clone()));
+        compiler.compile_file(&input, Some(&dir.join("program.o").display().to_string())).unwrap();
+        let map = fs::read_to_string(&map).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        map
+    }
+
+    #[test]
+    fn test_build_map_lists_routines_and_globals() {
+        let map = build_map(
+            "z80-map",
+            "program Count;\nvar n: Integer; w: Word;\nprocedure P;\nbegin\n  n := n + 1\nend;\n\
+             begin\n  n := 1;\n  P;\n  w := 2\nend.\n",
+        );
+        assert!(map.starts_with("Memory map, origin $4000\n"), "{}", map);
+        let line = |name: &str| map.lines().find(|line| line.split_whitespace().nth(3) == Some(name)).map(str::to_string);
+        assert!(line("_main").is_some_and(|main| main.starts_with("  $4000")), "{}", map);
+        assert!(line("_P").is_some(), "{}", map);
+        // Labels within routines are not routines of their own
+        assert!(!map.contains("main_entry"), "{}", map);
+        let global = |name: &str| map.lines().find(|line| line.split_whitespace().nth(4) == Some(name)).map(str::to_string);
+        assert!(global("n").is_some_and(|n| n.contains("      2  BSS")), "{}", map);
+        assert!(global("w").is_some(), "{}", map);
+        assert!(map.contains("4 of BSS"), "{}", map);
+    }
+
+    #[test]
+    fn test_inline_assembly_reaches_globals_by_name() {
+        let source = "program Bump;\nvar n: Integer;\n\
+                      procedure Bump;\nbegin\n  asm\n    ld hl, (@n)\n    inc hl\n    ld (@n), hl\n  end\nend;\n\
+                      begin\n  n := 1;\n  Bump\nend.\n";
+        let listing = asm(compiler_for("zealz80"), "z80-inline-asm", source);
+        assert!(has_sequence(&listing, &["Bump_entry:", "ld hl, (n)", "inc hl", "ld (n), hl"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld (n), hl", "call _Bump"]), "{}", listing);
+
+        let map = build_map("z80-inline-asm-map", source);
+        assert!(map.lines().any(|line| line.contains(" _Bump ")), "{}", map);
+        assert!(map.lines().any(|line| line.contains("BSS      n ")), "{}", map);
+    }
+
+    #[test]
+    fn test_port_arrays_become_in_and_out() {
+        let source = "program Ports;\nvar n: Integer; w: Word;\n\
+                      begin\n  Port[$FE] := 7;\n  n := Port[$FE];\n  PortW[$10] := w\nend.\n";
+        let listing = asm(compiler_for("zealz80"), "z80-ports", source);
+        assert!(has_sequence(&listing, &["ld hl, 7", "ld a, l", "ld bc, 254", "out (c), a"]), "{}", listing);
+        assert!(has_sequence(&listing, &["in a, (c)", "ld l, a", "ld h, 0", "ld (n), hl"]), "{}", listing);
+        // A word goes out low byte first, to the next port the high one
+        assert!(has_sequence(&listing, &["ld bc, 16", "out (c), a", "inc bc", "ld a, h", "out (c), a"]), "{}", listing);
+
+        let map = build_map("z80-ports-map", source);
+        assert!(map.lines().any(|line| line.contains(" _main ")), "{}", map);
+        assert!(map.lines().any(|line| line.contains("BSS      n ")), "{}", map);
+    }
+
+    const COUNT_PROGRAM: &str =
+        "program Count;\nvar n: Integer;\nprocedure P;\nbegin\n  n := n + 1\nend;\nbegin\n  n := 1;\n  P\nend.\n";
+
+    /// What `link` writes for `source` built, in `format` at `origin`
+    fn link_image(test: &str, source: &str, format: ImageFormat, origin: Option<u16>) -> Vec<u8> {
+        let dir = scratch(test);
+        let input = write(&dir, "program.pas", source);
+        let object = dir.join("program.o").display().to_string();
+        let image = dir.join("program.img").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.compile_file(&input, Some(&object)).unwrap();
+        compiler.link(std::slice::from_ref(&object), &image, format, origin).unwrap();
+        let bytes = fs::read(&image).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        bytes
+    }
+
+    /// A 16x16 greyscale PNG, black, its pixels in a stored deflate block
+    fn black_png() -> Vec<u8> {
+        let chunk = |kind: &[u8], body: &[u8]| {
+            let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(body);
+            chunk.extend_from_slice(&[0; 4]); // CRC, not checked
+            chunk
+        };
+        let rows = [0u8; 16 * 17];
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend_from_slice(&(rows.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(rows.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(&rows);
+        zlib.extend_from_slice(&[0; 4]); // Adler-32, not checked
+        let mut header = [16u32.to_be_bytes(), 16u32.to_be_bytes()].concat();
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(chunk(b"IHDR", &header));
+        png.extend(chunk(b"IDAT", &zlib));
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    #[test]
+    fn test_resources_link_from_the_data_section_without_warnings() {
+        let dir = scratch("z80-resources");
+        fs::write(dir.join("level.bin"), [1, 2, 3, 4]).unwrap();
+        fs::write(dir.join("tiles.png"), black_png()).unwrap();
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Game;\n{$RESOURCE level.bin AS Level}\n{$RESOURCE tiles.png AS Tiles}\nvar n: Integer;\n\
+             begin\n  n := Level_Size + Tiles_Width;\n  n := n + Level\nend.\n",
+        );
+        let object = dir.join("program.o").display().to_string();
+        let image = dir.join("program.img").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_deny_warnings(true);
+        compiler.compile_file(&input, Some(&object)).unwrap();
+        // The data and palette are only in the data section, so the BSS
+        // holds n alone
+        let symbols = read_object(&object).unwrap().symbols;
+        for name in ["Level", "Tiles", "Tiles_Palette"] {
+            let defined: Vec<_> = symbols.iter().filter(|symbol| symbol.name == name).collect();
+            assert!(matches!(defined.as_slice(), [symbol] if symbol.section == Section::Data), "{}: {:?}", name, defined);
+        }
+        compiler.link(std::slice::from_ref(&object), &image, ImageFormat::Binary, None).unwrap();
+        let bytes = fs::read(&image).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(bytes.windows(4).any(|data| data == [1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_music_descriptor_fields_are_read_from_the_data_section() {
+        let dir = scratch("z80-music");
+        let mut module = b"ProTracker 3.6 compilation of ".to_vec();
+        module.resize(300, 0);
+        fs::write(dir.join("song.pt3"), module).unwrap();
+        let input = write(
+            &dir,
+            "program.pas",
+            "program Tune;\n{$RESOURCE song.pt3 AS Song}\nvar n: Integer; f: Byte;\n\
+             begin\n  n := Song.Size;\n  f := Song.Format;\n  Song.Format := 2\nend.\n",
+        );
+        let object = dir.join("program.o").display().to_string();
+        let image = dir.join("program.img").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_deny_warnings(true);
+        let source = fs::read_to_string(&input).unwrap();
+        let mut codegen = CodeGenerator::with_intrinsics(compiler.intrinsics.clone());
+        let (instructions, _) = compiler.assemble_listing(&source, &input, &mut codegen).unwrap();
+        let listing: String = instructions.iter().map(|inst| format!("{}\n", inst)).collect();
+        compiler.compile_file(&input, Some(&object)).unwrap();
+        compiler.link(std::slice::from_ref(&object), &image, ImageFormat::Binary, None).unwrap();
+        let symbols = read_object(&object).unwrap().symbols;
+        fs::remove_dir_all(&dir).unwrap();
+        for name in ["Song", "Song_Data"] {
+            assert_eq!(symbols.iter().filter(|symbol| symbol.name == name && symbol.section == Section::Data).count(), 1);
+        }
+        assert!(listing.contains("ld hl, (Song+2)"), "{}", listing);
+        // Format is the byte after Size, reached a byte at a time
+        assert!(has_sequence(&listing, &["ld a, (Song+4)", "ld l, a"]), "{}", listing);
+        assert!(has_sequence(&listing, &["ld a, l", "ld (Song+4), a"]), "{}", listing);
+    }
+
+    #[test]
+    fn test_zealos_executable_of_a_built_program() {
+        let bytes = link_image("z80-zealos", COUNT_PROGRAM, ImageFormat::ZealOs, None);
+        assert!(bytes.starts_with(b"ZEX\0"));
+        let executable = ZealOsExecutable::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!((executable.load_address, executable.entry), (0x4000, 0x4000));
+        assert_eq!(executable.bss_size, 2);
+        assert!(executable.relocatable);
+        // Each store to `n` and the call to P hold an address to relocate
+        let n = 0x4000 + executable.image.len() as u16;
+        let addresses: Vec<u16> = executable
+            .relocations
+            .iter()
+            .map(|&at| u16::from_le_bytes([executable.image[at as usize], executable.image[at as usize + 1]]))
+            .collect();
+        assert!(addresses.iter().filter(|&&address| address == n).count() >= 2, "{:?}", addresses);
+        assert!(executable.relocations.iter().any(|&at| executable.image[at as usize - 1] == 0xCD));
+    }
+
+    #[test]
+    fn test_hex_and_binary_images_of_a_built_program_at_an_origin() {
+        let binary = link_image("z80-bin", COUNT_PROGRAM, ImageFormat::Binary, Some(0x8000));
+        assert!(!binary.is_empty());
+        // The store to `n` after the code is addressed from the origin
+        let [low, high] = (0x8000 + binary.len() as u16).to_le_bytes();
+        assert!(binary.windows(3).any(|ld| ld == [0x22, low, high]));
+
+        let hex = String::from_utf8(link_image("z80-hex", COUNT_PROGRAM, ImageFormat::IntelHex, Some(0x8000))).unwrap();
+        assert!(hex.starts_with(":10800000"), "{}", hex);
+        assert!(hex.ends_with(":00000001FF\n"), "{}", hex);
+        let data: Vec<u8> = hex
+            .lines()
+            .filter(|record| &record[7..9] == "00")
+            .flat_map(|record| {
+                let count = usize::from_str_radix(&record[1..3], 16).unwrap();
+                (0..count).map(move |i| u8::from_str_radix(&record[9 + 2 * i..11 + 2 * i], 16).unwrap())
+            })
+            .collect();
+        assert_eq!(data, binary);
+    }
+
+    const LOCALS_PROGRAM: &str = "program Count;\nvar n: Integer;\nprocedure P;\nvar k: Integer;\nbegin\n  k := n;\n  n := k + 1\nend;\n\
+                                  begin\n  n := 1;\n  P\nend.\n";
+
+    #[test]
+    fn test_debug_info_of_a_built_program() {
+        let dir = scratch("z80-debug-info");
+        let input = write(&dir, "program.pas", LOCALS_PROGRAM);
+        let object = dir.join("program.o").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_debug_info(true);
+        compiler.compile_file(&input, Some(&object)).unwrap();
+        let text = fs::read_to_string(dir.join("program.dbg")).unwrap();
+        let linked = linker::link(&[read_object(&object).unwrap()], 0x4000).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let info = DebugInfo::parse(&text).unwrap();
+        assert_eq!((info.source.as_str(), info.origin), (input.as_str(), 0x4000));
+        let address = |name: &str| linked.symbols.iter().find(|(symbol, _)| symbol == name).map(|(_, at)| *at);
+        assert_eq!(info.table.routines, [("_main".to_string(), 0x4000), ("_P".to_string(), address("_P").unwrap())]);
+        // Every statement, in main and in P
+        let lines: Vec<usize> = info.table.lines.iter().map(|range| range.line).collect();
+        for line in [6, 7, 10, 11] {
+            assert!(lines.contains(&line), "{}", text);
+        }
+        assert!(info.table.lines.iter().all(|range| range.size > 0), "{}", text);
+        // The call to P returns after its three bytes
+        let call = info.table.lines.iter().find(|range| range.line == 11).unwrap().start;
+        assert_eq!(info.table.calls.get(&call), Some(&(call + 3)));
+        assert_eq!(info.locals, [LocalVariable { routine: "_P".to_string(), name: "k".to_string(), offset: -2 }]);
+    }
+
+    #[test]
+    fn test_trace_map_of_a_built_program() {
+        let dir = scratch("z80-trace-map");
+        let input = write(&dir, "program.pas", LOCALS_PROGRAM);
+        let object = dir.join("program.o").display().to_string();
+        let mut compiler = compiler_for("zealz80");
+        compiler.set_debug_info(true);
+        compiler.compile_file(&input, Some(&object)).unwrap();
+        let debug_info = dir.join("program.dbg").display().to_string();
+        let info = DebugInfo::parse(&fs::read_to_string(&debug_info).unwrap()).unwrap();
+
+        // Main's statements, with P called from the second and run through
+        let start = |line: usize| info.table.lines.iter().find(|range| range.line == line).unwrap().start;
+        let p = info.table.routines[1].1;
+        let addresses = [0x4000, start(10), start(11), p, start(6), start(7), start(11) + 3];
+        let trace: String = addresses.iter().map(|address| format!("${:04X}\n", address)).collect();
+        let trace_file = write(&dir, "trace.log", &trace);
+        let from_debug_info = compiler.trace_report(&trace_file, &debug_info).unwrap();
+        let from_source = compiler.trace_report(&trace_file, &input).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(from_debug_info, from_source);
+        let report = from_source.join("\n");
+        assert_eq!(from_source[0], "Trace: 7 instructions, 5 on Pascal lines");
+        for source in ["k := n;", "n := k + 1", "n := 1;", "P"] {
+            assert!(from_source.iter().any(|line| line.ends_with(&format!("  {}", source))), "{}", report);
+        }
+        let tree = &from_source[from_source.iter().position(|line| line == "Call tree:").unwrap() + 1..];
+        assert!(tree[0].trim_start().starts_with("_main"), "{}", report);
+        assert!(tree[1].trim_start().starts_with("_P") && tree[1].contains("1 call "), "{}", report);
+    }
+}
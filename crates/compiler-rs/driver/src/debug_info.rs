@@ -0,0 +1,162 @@
+//! Source-level debug info (`-g`)
+//!
+//! `build -g` writes a sidecar next to the object, with the extension
+//! `.dbg`: a text file an emulator or debugger reads to show the Pascal
+//! line and the local variables of the code it runs. One record per line,
+//! a keyword then its fields; `;` starts a comment:
+//!
+//! ```text
+//! ; SuperPascal debug info
+//! version 1
+//! source game.pas
+//! origin $4000
+//! routine $4000 main
+//! line $4003 6 12:5
+//! call $4009 $400C
+//! local main Score ix-2
+//! ```
+//!
+//! - `version`: of the format, first.
+//! - `source`: the Pascal file the lines are in.
+//! - `origin`: the address the program is loaded at.
+//! - `routine <address> <name>`: where a routine starts.
+//! - `line <address> <size> <line>:<column>`: the code of the statement
+//!   starting at a source line and column, `size` bytes from `address`.
+//! - `call <address> <return>`: a call, and the address it returns to.
+//! - `local <routine> <name> ix<offset>`: a variable of a routine, at a
+//!   signed offset from the frame pointer IX.
+//!
+//! Addresses are those of the program linked as `link` links it: after the
+//! target's startup code, at the target's load address.
+
+use std::fmt;
+
+use crate::profile::parse_address;
+use crate::trace_map::{LineRange, LineTable};
+
+/// Format version written and read
+pub const DEBUG_INFO_VERSION: u32 = 1;
+/// Extension of debug info sidecars
+pub const DEBUG_INFO_EXTENSION: &str = "dbg";
+
+/// A variable at a fixed offset of a routine's frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalVariable {
+    pub routine: String,
+    pub name: String,
+    /// Offset from IX
+    pub offset: i32,
+}
+
+/// Debug info of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Pascal file the lines are in
+    pub source: String,
+    pub origin: u16,
+    pub table: LineTable,
+    pub locals: Vec<LocalVariable>,
+}
+
+impl DebugInfo {
+    /// Parse debug info written by [`DebugInfo`]'s `Display`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut info = DebugInfo { source: String::new(), origin: 0, table: LineTable::default(), locals: vec![] };
+        let mut version = None;
+        for (number, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split(';').next().unwrap_or("").split_whitespace().collect();
+            let invalid = || format!("line {}: invalid record '{}'", number + 1, line.trim());
+            let address = |text: &str| parse_address(text).ok_or_else(invalid);
+            match fields.as_slice() {
+                [] => {}
+                ["version", number] => {
+                    let number: u32 = number.parse().map_err(|_| invalid())?;
+                    if number != DEBUG_INFO_VERSION {
+                        return Err(format!(
+                            "Unsupported debug info version: {} (this compiler reads {})",
+                            number, DEBUG_INFO_VERSION
+                        ));
+                    }
+                    version = Some(number);
+                }
+                _ if version.is_none() => return Err("Debug info does not start with its version".to_string()),
+                ["source", path @ ..] if !path.is_empty() => info.source = path.join(" "),
+                ["origin", origin] => info.origin = address(origin)?,
+                ["routine", start, name] => info.table.routines.push((name.to_string(), address(start)?)),
+                ["line", start, size, position] => {
+                    let (line, column) = position.split_once(':').ok_or_else(invalid)?;
+                    info.table.lines.push(LineRange {
+                        start: address(start)?,
+                        size: size.parse().map_err(|_| invalid())?,
+                        line: line.parse().map_err(|_| invalid())?,
+                        column: column.parse().map_err(|_| invalid())?,
+                    });
+                }
+                ["call", at, next] => {
+                    info.table.calls.insert(address(at)?, address(next)?);
+                }
+                ["local", routine, name, slot] => {
+                    let offset = slot.strip_prefix("ix").and_then(|offset| offset.parse().ok()).ok_or_else(invalid)?;
+                    info.locals.push(LocalVariable { routine: routine.to_string(), name: name.to_string(), offset });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if version.is_none() {
+            return Err("Debug info does not start with its version".to_string());
+        }
+        Ok(info)
+    }
+}
+
+impl fmt::Display for DebugInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "; SuperPascal debug info")?;
+        writeln!(f, "version {}", DEBUG_INFO_VERSION)?;
+        writeln!(f, "source {}", self.source)?;
+        writeln!(f, "origin ${:04X}", self.origin)?;
+        for (name, start) in &self.table.routines {
+            writeln!(f, "routine ${:04X} {}", start, name)?;
+        }
+        for range in &self.table.lines {
+            writeln!(f, "line ${:04X} {} {}:{}", range.start, range.size, range.line, range.column)?;
+        }
+        for (at, next) in &self.table.calls {
+            writeln!(f, "call ${:04X} ${:04X}", at, next)?;
+        }
+        for local in &self.locals {
+            writeln!(f, "local {} {} ix{:+}", local.routine, local.name, local.offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_debug_info_round_trip() {
+        let info = DebugInfo {
+            source: "game.pas".to_string(),
+            origin: 0x4000,
+            table: LineTable {
+                lines: vec![LineRange { start: 0x4003, size: 6, line: 12, column: 5 }],
+                routines: vec![("main".to_string(), 0x4000)],
+                calls: BTreeMap::from([(0x4009, 0x400C)]),
+            },
+            locals: vec![LocalVariable { routine: "main".to_string(), name: "Score".to_string(), offset: -2 }],
+        };
+        let text = info.to_string();
+        assert_eq!(
+            text,
+            "; SuperPascal debug info\nversion 1\nsource game.pas\norigin $4000\nroutine $4000 main\n\
+             line $4003 6 12:5\ncall $4009 $400C\nlocal main Score ix-2\n"
+        );
+        assert_eq!(DebugInfo::parse(&text).unwrap(), info);
+
+        assert!(DebugInfo::parse("version 2\n").unwrap_err().starts_with("Unsupported debug info version"));
+        assert!(DebugInfo::parse("version 1\nline $4000 6\n").unwrap_err().starts_with("line 2:"));
+    }
+}
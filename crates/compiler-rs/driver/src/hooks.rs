@@ -0,0 +1,102 @@
+//! Pipeline stage hooks
+//!
+//! Embedders register callbacks on the `Compiler` to inspect or rewrite the
+//! program between stages (for example to inject instrumentation) without
+//! forking the driver. Hooks of one stage run in registration order, and
+//! are `Send` so a compiler can be handed to another thread.
+
+use ast::Node;
+use errors::Diagnostic;
+use ir::Program;
+
+/// Runs after parsing; may rewrite the AST before it is analyzed
+pub type PostParseHook = Box<dyn FnMut(&mut Node) + Send>;
+/// Runs after semantic analysis; may add or drop diagnostics
+pub type PostSemanticsHook = Box<dyn FnMut(&Node, &mut Vec<Diagnostic>) + Send>;
+/// Runs on the finished IR before code is generated from it
+pub type PreCodegenHook = Box<dyn FnMut(&mut Program) + Send>;
+
+/// Callbacks registered for each pipeline stage
+#[derive(Default)]
+pub struct PipelineHooks {
+    pub post_parse: Vec<PostParseHook>,
+    pub post_semantics: Vec<PostSemanticsHook>,
+    pub pre_codegen: Vec<PreCodegenHook>,
+}
+
+impl PipelineHooks {
+    pub fn is_empty(&self) -> bool {
+        self.post_parse.is_empty() && self.post_semantics.is_empty() && self.pre_codegen.is_empty()
+    }
+
+    pub fn run_post_parse(&mut self, ast: &mut Node) {
+        for hook in &mut self.post_parse {
+            hook(ast);
+        }
+    }
+
+    pub fn run_post_semantics(&mut self, ast: &Node, diagnostics: &mut Vec<Diagnostic>) {
+        for hook in &mut self.post_semantics {
+            hook(ast, diagnostics);
+        }
+    }
+
+    pub fn run_pre_codegen(&mut self, program: &mut Program) {
+        for hook in &mut self.pre_codegen {
+            hook(program);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use errors::{Diagnostic, ErrorSeverity};
+
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn test_hooks_run_in_stage_order() {
+        let path = std::env::temp_dir().join(format!("spc-hooks-{}.pas", std::process::id()));
+        fs::write(&path, "program Hooks;\nvar n: integer;\nbegin\n  n := 1;\nend.\n").unwrap();
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut compiler = Compiler::new();
+        let seen = log.clone();
+        compiler.add_post_parse_hook(move |ast| {
+            // Rename the program, to check later stages see the rewritten tree
+            if let ast::Node::Program(program) = ast {
+                program.name = "Instrumented".to_string();
+            }
+            seen.lock().unwrap().push("parse".to_string());
+        });
+        let seen = log.clone();
+        compiler.add_post_semantics_hook(move |ast, diagnostics| {
+            if let ast::Node::Program(program) = ast {
+                seen.lock().unwrap().push(format!("semantics {} {}", program.name, diagnostics.len()));
+            }
+            let span = ast.span();
+            diagnostics.push(Diagnostic::new(ErrorSeverity::Error, "Rejected by hook".to_string(), span));
+        });
+        let seen = log.clone();
+        compiler.add_pre_codegen_hook(move |program| {
+            seen.lock().unwrap().push(format!("codegen {}", program.functions.len()));
+        });
+
+        let result = compiler.check_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result, Err("Type checking failed with 1 error(s)".to_string()));
+        assert_eq!(*log.lock().unwrap(), ["parse", "semantics Instrumented 0", "codegen 0"]);
+    }
+
+    #[test]
+    fn test_compiler_with_hooks_is_send() {
+        // build-all moves compilers to worker threads
+        fn send<T: Send>(_: &T) {}
+        let mut compiler = Compiler::new();
+        compiler.add_post_parse_hook(|_| {});
+        send(&compiler);
+    }
+}
@@ -10,14 +10,62 @@
 //! 6. Object File Generation (object-zealz80)
 
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
 
+mod ast_diff;
+mod cache;
 mod compiler;
+mod debug_info;
+mod hooks;
+mod profile;
+mod stats;
+mod summary;
+mod symbol_dump;
+mod symbol_index;
+mod targets;
+mod trace_map;
+mod unit_graph;
 
+use backend_zealz80::OptimizationGoal;
+use backend_zealz80::interrupts::InterruptMode;
 use compiler::Compiler;
+use errors::DiagnosticFormat;
+use lexer::IdentifierPolicy;
+use object_zealz80::output::ImageFormat;
+use object_zealz80::{DumpOptions, Section};
+use targets::TargetRegistry;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let target = take_option(&mut args, "--target");
+    let optimize_size = take_flag(&mut args, "-Os");
+    let optimize_more = take_flag(&mut args, "-O2");
+    let optimize_smallest = take_flag(&mut args, "-Oz");
+    let map_file = take_option(&mut args, "--map");
+    let debug_info = take_flag(&mut args, "-g");
+    let interrupt_mode = take_option(&mut args, "--interrupt-mode");
+    let unicode_identifiers = take_flag(&mut args, "--unicode-identifiers");
+    let shorten_identifiers = take_flag(&mut args, "--shorten-identifiers");
+    let no_cache = take_flag(&mut args, "--no-cache");
+    let debug_heap = take_flag(&mut args, "--debug-heap");
+    let round_robin = take_flag(&mut args, "--round-robin");
+    let console_input = take_option(&mut args, "--input");
+    let summary = take_flag(&mut args, "--summary");
+    let teach = take_flag(&mut args, "--teach");
+    let error_format = take_option(&mut args, "--error-format");
+    let strict = take_flag(&mut args, "--strict");
+    let warn_dead_code = take_flag(&mut args, "--warn-dead-code");
+    let deny_warnings = take_flag(&mut args, "--deny-warnings");
+    let mut defines = Vec::new();
+    while let Some(symbol) = take_option(&mut args, "--define") {
+        defines.push(symbol);
+    }
+    let mut target_dirs = Vec::new();
+    while let Some(dir) = take_option(&mut args, "--target-dir") {
+        target_dirs.push(dir);
+    }
     
     if args.len() < 2 {
         print_usage();
@@ -26,6 +74,93 @@ fn main() {
 
     let command = &args[1];
     let mut compiler = Compiler::new();
+    if optimize_size {
+        compiler.set_optimization_goal(OptimizationGoal::Size);
+    }
+    if optimize_more {
+        compiler.set_optimization_level(2);
+    }
+    if optimize_smallest {
+        compiler.set_optimization_goal(OptimizationGoal::Size);
+        compiler.set_outlining(true);
+    }
+    compiler.set_map_file(map_file);
+    compiler.set_debug_info(debug_info);
+    if unicode_identifiers {
+        compiler.set_identifier_policy(IdentifierPolicy::Unicode);
+    }
+    for symbol in &defines {
+        compiler.define(symbol);
+    }
+    if debug_heap {
+        compiler.set_debug_heap(true);
+    }
+    if round_robin {
+        compiler.set_round_robin(true);
+    }
+    if teach {
+        compiler.set_teaching(true);
+    }
+    if strict {
+        compiler.set_strict(true);
+    }
+    if let Some(name) = error_format {
+        match DiagnosticFormat::from_name(&name) {
+            Some(format) => compiler.set_error_format(format),
+            None => {
+                eprintln!("Error: Unknown error format '{}' (expected human, pretty or json)", name);
+                process::exit(1);
+            }
+        }
+    }
+    if warn_dead_code {
+        compiler.set_dead_code_warnings(true);
+    }
+    if deny_warnings {
+        compiler.set_deny_warnings(true);
+    }
+    if let Some(path) = console_input {
+        match fs::read_to_string(&path) {
+            Ok(text) => compiler.set_console_input(text.lines().map(str::to_string).collect()),
+            Err(e) => {
+                eprintln!("Error: Failed to read input '{}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    if !no_cache {
+        compiler.set_cache_dir(Some(PathBuf::from(cache::DEFAULT_CACHE_DIR)));
+    }
+
+    let mut registry = TargetRegistry::builtin();
+    for dir in &target_dirs {
+        if let Err(e) = registry.load_dir(Path::new(dir)) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(name) = target {
+        match registry.find(&name) {
+            Some(definition) => compiler.set_target(definition.clone()),
+            None => {
+                eprintln!("Error: Unknown target '{}'", name);
+                print_usage();
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(name) = interrupt_mode {
+        match InterruptMode::from_name(&name) {
+            Some(mode) => compiler.set_interrupt_mode(mode),
+            None => {
+                eprintln!("Error: Unknown interrupt mode '{}'", name);
+                print_usage();
+                process::exit(1);
+            }
+        }
+    }
 
     match command.as_str() {
         "build" | "compile" => {
@@ -36,6 +171,10 @@ fn main() {
             }
             let input_file = &args[2];
             let output_file = args.get(3).map(|s| s.as_str());
+            if compiler.interprets() {
+                run_interpreted(&mut compiler, input_file, output_file);
+                return;
+            }
             
             match compiler.compile_file(input_file, output_file) {
                 Ok(_) => {
@@ -58,6 +197,9 @@ fn main() {
             match compiler.check_file(input_file) {
                 Ok(_) => {
                     println!("Type checking successful");
+                    if summary {
+                        print!("{}", compiler.summary());
+                    }
                 }
                 Err(e) => {
                     eprintln!("Type checking failed: {}", e);
@@ -97,7 +239,29 @@ fn main() {
                 }
             }
         }
+        "emit-symbols" => {
+            let format = take_option(&mut args, "--format").unwrap_or_else(|| "human".to_string());
+            let json = match format.as_str() {
+                "human" => false,
+                "json" => true,
+                other => {
+                    eprintln!("Error: Unknown symbol format '{}' (expected human or json)", other);
+                    process::exit(1);
+                }
+            };
+            if args.len() < 3 {
+                eprintln!("Error: No input file specified");
+                print_usage();
+                process::exit(1);
+            }
+
+            if let Err(e) = compiler.emit_symbols(&args[2], json) {
+                eprintln!("Failed to emit symbols: {}", e);
+                process::exit(1);
+            }
+        }
         "asm" => {
+            let profile = take_option(&mut args, "--profile");
             if args.len() < 3 {
                 eprintln!("Error: No input file specified");
                 print_usage();
@@ -105,7 +269,7 @@ fn main() {
             }
             let input_file = &args[2];
             
-            match compiler.emit_assembly(input_file) {
+            match compiler.emit_assembly(input_file, profile.as_deref()) {
                 Ok(_) => {}
                 Err(e) => {
                     eprintln!("Failed to emit assembly: {}", e);
@@ -113,6 +277,274 @@ fn main() {
                 }
             }
         }
+        "trace-map" => {
+            if args.len() < 4 {
+                eprintln!("Error: Expected a trace and the program it traces");
+                print_usage();
+                process::exit(1);
+            }
+
+            if let Err(e) = compiler.trace_map(&args[2], &args[3]) {
+                eprintln!("Failed to map trace: {}", e);
+                process::exit(1);
+            }
+        }
+        "minify" => {
+            if args.len() < 3 {
+                eprintln!("Error: No input file specified");
+                print_usage();
+                process::exit(1);
+            }
+            let input_file = &args[2];
+            let output_file = args.get(3).map(|s| s.as_str());
+
+            match compiler.minify(input_file, output_file, shorten_identifiers) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to minify: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "upgrade-syntax" => {
+            if args.len() < 3 {
+                eprintln!("Error: No input file specified");
+                print_usage();
+                process::exit(1);
+            }
+            let input_file = &args[2];
+            let output_file = args.get(3).map(|s| s.as_str());
+
+            if let Err(e) = compiler.upgrade_syntax(input_file, output_file) {
+                eprintln!("Failed to upgrade: {}", e);
+                process::exit(1);
+            }
+        }
+        "regs" => {
+            if args.len() < 3 {
+                eprintln!("Error: No register description specified");
+                print_usage();
+                process::exit(1);
+            }
+            let input_file = &args[2];
+            let output_file = args.get(3).map(|s| s.as_str());
+
+            match compiler.generate_register_unit(input_file, output_file) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to generate register unit: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "ast-diff" => {
+            if args.len() < 4 {
+                eprintln!("Error: ast-diff needs an old and a new file");
+                print_usage();
+                process::exit(1);
+            }
+
+            match compiler.ast_diff(&args[2], &args[3]) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to diff: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "stats" => {
+            if args.len() < 3 {
+                eprintln!("Error: No input file specified");
+                print_usage();
+                process::exit(1);
+            }
+
+            match compiler.print_stats(&args[2..]) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to gather statistics: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "build-all" => {
+            let jobs = match take_option(&mut args, "--jobs").map(|n| n.parse::<usize>()) {
+                None => 1,
+                Some(Ok(jobs)) if jobs > 0 => jobs,
+                Some(_) => {
+                    eprintln!("Error: --jobs takes a positive number of threads");
+                    process::exit(1);
+                }
+            };
+            if args.len() < 3 {
+                eprintln!("Error: No project directory specified");
+                print_usage();
+                process::exit(1);
+            }
+
+            match compiler.build_all(&args[2], jobs) {
+                Ok(_) => {
+                    println!("Compilation successful");
+                }
+                Err(e) => {
+                    eprintln!("Compilation failed: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "graph-units" => {
+            let format = take_option(&mut args, "--format").unwrap_or_else(|| "dot".to_string());
+            let json = match format.as_str() {
+                "dot" => false,
+                "json" => true,
+                other => {
+                    eprintln!("Error: Unknown graph format '{}' (expected dot or json)", other);
+                    process::exit(1);
+                }
+            };
+            if args.len() < 3 {
+                eprintln!("Error: No input file specified");
+                print_usage();
+                process::exit(1);
+            }
+
+            match compiler.graph_units(&args[2..], json) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to graph units: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "xref" => {
+            if args.len() < 4 {
+                eprintln!("Error: Expected a project directory and a name");
+                print_usage();
+                process::exit(1);
+            }
+
+            if let Err(e) = compiler.xref(&args[2], &args[3]) {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        "rename" => {
+            let at = take_option(&mut args, "--at");
+            if args.len() < 5 {
+                eprintln!("Error: Expected a project directory, a name and its new name");
+                print_usage();
+                process::exit(1);
+            }
+
+            if let Err(e) = compiler.rename(&args[2], &args[3], &args[4], at.as_deref()) {
+                eprintln!("Rename failed: {}", e);
+                process::exit(1);
+            }
+        }
+        "abi" => {
+            if args.len() < 3 {
+                eprintln!("Error: No input file or routine heading specified");
+                print_usage();
+                process::exit(1);
+            }
+            let input = &args[2];
+            let routine = args.get(3).map(|s| s.as_str());
+
+            match compiler.print_abi(input, routine) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to lay out call frame: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "link" => {
+            let output_file = take_option(&mut args, "-o");
+            // --format is the older name of --emit
+            let emit = take_option(&mut args, "--emit").or_else(|| take_option(&mut args, "--format"));
+            let format = match emit {
+                None => ImageFormat::Binary,
+                Some(name) => ImageFormat::from_name(&name).unwrap_or_else(|| {
+                    let names: Vec<_> = ImageFormat::ALL.iter().map(ImageFormat::name).collect();
+                    eprintln!("Error: Unknown output format '{}' (expected {})", name, names.join(", "));
+                    process::exit(1);
+                }),
+            };
+            let origin = take_option(&mut args, "--org").map(|org| {
+                parse_org(&org).unwrap_or_else(|| {
+                    eprintln!("Error: Invalid origin '{}' (expected an address such as 0x8000, $8000 or 32768)", org);
+                    process::exit(1);
+                })
+            });
+            if args.len() < 3 {
+                eprintln!("Error: No object files specified");
+                print_usage();
+                process::exit(1);
+            }
+            let Some(output_file) = output_file else {
+                eprintln!("Error: No output file specified (-o <file>)");
+                print_usage();
+                process::exit(1);
+            };
+
+            match compiler.link(&args[2..], &output_file, format, origin) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to link: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "objdump" => {
+            let hex = take_flag(&mut args, "--hex");
+            let section = take_option(&mut args, "--section");
+            let symbol = take_option(&mut args, "--symbol");
+            let extract = take_option(&mut args, "--extract");
+            let output_file = take_option(&mut args, "-o");
+            if args.len() < 3 {
+                eprintln!("Error: No object file specified");
+                print_usage();
+                process::exit(1);
+            }
+            let section_named = |name: &str| {
+                Section::from_name(name).unwrap_or_else(|| {
+                    eprintln!("Error: Unknown section '{}' (expected code, data or bss)", name);
+                    process::exit(1);
+                })
+            };
+
+            if let Some(name) = extract {
+                let Some(output_file) = output_file else {
+                    eprintln!("Error: No output file specified (-o <file>)");
+                    print_usage();
+                    process::exit(1);
+                };
+                match compiler.extract_section(&args[2], section_named(&name), &output_file) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("Failed to extract section: {}", e);
+                        process::exit(1);
+                    }
+                }
+                return;
+            }
+            let options = DumpOptions { hex, section: section.as_deref().map(section_named), symbol };
+            match compiler.objdump(&args[2], &options) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to dump object file: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "targets" => {
+            if args.get(2).map(|s| s.as_str()) != Some("list") {
+                eprintln!("Error: Unknown targets command (expected 'targets list')");
+                print_usage();
+                process::exit(1);
+            }
+            list_targets(&registry);
+        }
         "help" | "--help" | "-h" => {
             print_usage();
         }
@@ -124,22 +556,185 @@ fn main() {
     }
 }
 
+/// Remove `--name value` or `--name=value` from the argument list, returning the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let index = args.iter().position(|a| a == name || a.starts_with(&prefix))?;
+    let arg = args.remove(index);
+    if let Some(value) = arg.strip_prefix(&prefix) {
+        Some(value.to_string())
+    } else if index < args.len() {
+        Some(args.remove(index))
+    } else {
+        None
+    }
+}
+
+/// An address given as `0x8000`, `$8000` or `32768`
+fn parse_org(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+        Some(digits) => u16::from_str_radix(digits, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Run a program on an interpreting target, writing what it wrote to
+/// `output_file` or stdout; exits with an error if it fails to compile or
+/// stops with a runtime error
+fn run_interpreted(compiler: &mut Compiler, input_file: &str, output_file: Option<&str>) {
+    let run = match compiler.run_interpreted(input_file) {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("Compilation failed: {}", e);
+            process::exit(1);
+        }
+    };
+    match output_file {
+        Some(path) => {
+            if let Err(e) = fs::write(path, &run.output) {
+                eprintln!("Error: Failed to create output file '{}': {}", path, e);
+                process::exit(1);
+            }
+            if let Some(report) = compiler.outlining_report() {
+                println!("{}", report);
+            }
+        }
+        None => print!("{}", run.output),
+    }
+    if let Some(error) = run.error {
+        eprintln!("Runtime error: {}", error);
+        process::exit(1);
+    }
+}
+
+/// Remove a boolean flag from the argument list, returning whether it was present
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Print the targets `--target` accepts, and where each is defined
+fn list_targets(registry: &TargetRegistry) {
+    for target in registry.iter() {
+        let defined_in = match &target.path {
+            Some(path) => path.display().to_string(),
+            None => "built-in".to_string(),
+        };
+        println!("{:<16}{:<16}{:<6}{} ({})", target.name, target.platform.name(), target.output.name(), target.description, defined_in);
+    }
+}
+
 fn print_usage() {
     println!("SuperPascal Compiler (spc)");
     println!();
     println!("Usage: spc <command> [options] <file>");
     println!();
     println!("Commands:");
-    println!("  build, compile <file> [output]  Compile Pascal source to object file");
+    println!("  build, compile <file> [output]  Compile Pascal source to object file (.spu for a unit)");
+    println!("                                  or a register description (.toml) to a unit");
+    println!("    [--map <file>]                and write where its routines and globals would be linked");
     println!("  check <file>                    Type check only (no code generation)");
+    println!("    [--summary]                   then list the units used, routines and variables");
     println!("  emit-ast <file>                 Emit AST (for debugging)");
     println!("  emit-ir <file>                  Emit IR (for debugging)");
+    println!("  emit-symbols <file>             Print every declared symbol with its type, scope, size and");
+    println!("    [--format human|json]         absolute address, as a table or JSON");
     println!("  asm <file>                      Emit assembly code");
+    println!("    [--profile <file>]            annotated with the counts of an emulator run, and a");
+    println!("                                  report of the bytes and T-states of each routine");
+    println!("  trace-map <trace> <file|dbg>    Map an emulator instruction trace to the program's source:");
+    println!("                                  instructions executed on each line, and the call tree");
+    println!("  minify <file> [output]          Emit one source without comments, includes or conditionals");
+    println!("  upgrade-syntax <file> [output]  Rewrite Turbo Pascal constructs and report those left as they are");
+    println!("  regs <file.toml> [output]       Generate a Pascal unit from a register description");
+    println!("  ast-diff <old> <new>            Report changed declarations and statements");
+    println!("  stats <file|dir>...             Count tokens, nodes and declarations; time each phase");
+    println!("  build-all <dir>                 Compile the programs and units in a directory, units first,");
+    println!("    [--jobs <n>]                  units that do not use each other on n threads, and tabulate");
+    println!("                                  which built, failed or were skipped");
+    println!("  graph-units <file|dir>...       Print the unit dependency graph, cycles in red (--format dot|json)");
+    println!("  xref <dir> <name>               Print where the declarations of a name are used, from the");
+    println!("                                  project's symbol index ({})", symbol_index::INDEX_FILE);
+    println!("  rename <dir> <name> <new>       Rename a declaration and its uses across the project");
+    println!("    [--at <file:line>]            picking the declaration on that line when there are several");
+    println!("  abi <file|heading> [routine]    Print the call frame of routines");
+    println!("  link <object>... -o <output>    Link object files and units into a .bin or .com image");
+    println!("    [--map <file>]                and write a map of its sections, routines, globals and free RAM");
+    println!("    [--emit bin|hex|zealos]       as a raw binary, Intel HEX for EPROM programmers, or a Zeal");
+    println!("                                  8-bit OS executable with a header, entry point and relocations");
+    println!("    [--org <address>]             linked at an address other than the target's load address");
+    println!("  objdump <object|unit>           Print the sections, symbols and relocations of an object");
+    println!("    [--hex] [--section <name>]    with section contents in hex, only CODE, DATA or BSS,");
+    println!("    [--symbol <text>]             or only symbols whose name contains <text>");
+    println!("    --extract <section> -o <file> Write the raw bytes of a section to a file");
+    println!("  targets list                    List the targets --target accepts");
     println!("  help                            Show this help message");
     println!();
+    println!("Options:");
+    println!("  --target <platform>             Target platform (default: zealz80)");
+    let builtin = TargetRegistry::builtin();
+    println!("                                  {}", builtin.iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", "));
+    println!("  --target-dir <dir>              Load target definitions (*.toml) from a directory (repeatable)");
+    println!("  --interrupt-mode <im1|im2>      Z80 interrupt setup (default: im1)");
+    println!("  -Os                             Optimize for size (default: speed)");
+    println!("  -O2                             Also reuse common subexpressions within basic blocks");
+    println!("  -Oz                             As -Os, and outline repeated code into shared routines");
+    println!("  -g                              Write the line table and local variables of a build to a");
+    println!("                                  .dbg file next to it, for emulators and debuggers");
+    println!("  --unicode-identifiers           Allow non-ASCII letters in identifiers");
+    println!("  --define <symbol>               Define a symbol for {{$IFDEF}} (repeatable)");
+    println!("  --shorten-identifiers           Rename declared identifiers when minifying");
+    println!("  --debug-heap                    Guard instances with canaries and track them for ReportLeaks");
+    println!("  --round-robin                   Run tasks in creation order and count switches in __task_switches");
+    println!("  --input <file>                  Lines ReadLn reads when --target host-test runs a program");
+    println!("  --teach                         Explain each error with the rule it breaks and an example");
+    println!("  --error-format <format>         Print diagnostics as text (human, the default), with their source");
+    println!("                                  lines underlined (pretty), or one JSON object per line (json)");
+    println!("  --strict                        As {{$MODE STRICT}}: range and overflow checks, no goto, else in");
+    println!("                                  every case over an enumeration, key warnings as errors");
+    println!("  --warn-dead-code                Warn about unreachable statements and unused routines removed");
+    println!("  --deny-warnings                 Report warnings as errors; {{$WARN name OFF}} turns a kind off");
+    println!("  --no-cache                      Rebuild even if {}/ holds an identical build", cache::DEFAULT_CACHE_DIR);
+    println!();
     println!("Examples:");
     println!("  spc build program.pas");
     println!("  spc check program.pas");
+    println!("  spc check --summary program.pas");
+    println!("  spc check --teach program.pas");
+    println!("  spc check --strict program.pas");
+    println!("  spc --error-format json check program.pas");
+    println!("  spc check --warn-dead-code program.pas");
     println!("  spc emit-ast program.pas");
+    println!("  spc emit-symbols --format json game.pas");
     println!("  spc asm program.pas");
+    println!("  spc build --target zxspectrum game.pas");
+    println!("  spc build --target host-test --input answers.txt quiz.pas quiz.out");
+    println!("  spc asm -Os game.pas");
+    println!("  spc build -O2 game.pas");
+    println!("  spc build -Oz game.pas");
+    println!("  spc asm --profile game.prof game.pas");
+    println!("  spc build -g game.pas");
+    println!("  spc trace-map trace.log game.pas");
+    println!("  spc trace-map trace.log game.dbg");
+    println!("  spc build sprites.pas");
+    println!("  spc link main.zof sprites.spu -o game.bin");
+    println!("  spc link main.zof sprites.spu -o game.bin --map game.map");
+    println!("  spc link --emit zealos main.zof -o game.zex");
+    println!("  spc link --emit hex --org 0x8000 main.zof -o game.hex");
+    println!("  spc objdump sprites.spu");
+    println!("  spc objdump --hex --section data sprites.spu");
+    println!("  spc objdump --extract code -o code.bin main.zof");
+    println!("  spc build video.toml");
+    println!("  spc stats examples/");
+    println!("  spc graph-units game.pas | dot -Tsvg -o units.svg");
+    println!("  spc build-all --jobs 4 game/");
+    println!("  spc xref game/ DrawSprite");
+    println!("  spc rename game/ Count Lives --at game/Player.pas:12");
+    println!("  spc targets list --target-dir machines/");
+    println!("  spc abi \"procedure Fill(var Buf; Count: word; Value: byte)\"");
 }
@@ -0,0 +1,169 @@
+//! Execution profiles (`spc asm --profile`)
+//!
+//! A profiled emulator run writes how often each instruction address was
+//! executed, one `<address> <count>` pair per line: the address in hex
+//! (`$8000`, `0x8000` or `8000`) and the count in decimal. An optional
+//! `origin <address>` line gives the address the listing was loaded at;
+//! `;` and `#` start comments.
+//!
+//! The listing is annotated with the count and estimated T-states of each
+//! instruction, hot lines are marked, and a size report lists the bytes,
+//! calls and T-states of each routine.
+
+use std::collections::{BTreeMap, HashSet};
+
+use backend_zealz80::{CodeGenerator, Z80Instruction};
+
+/// Share of all T-states from which a line is marked as hot
+pub const HOT_SHARE: f64 = 0.05;
+
+/// Execution counts by instruction address
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    pub origin: Option<u16>,
+    pub counts: BTreeMap<u16, u64>,
+}
+
+impl Profile {
+    /// Parse a profile written by the emulator
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut profile = Profile::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap_or("").trim();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                [keyword, address] if keyword.eq_ignore_ascii_case("origin") => {
+                    profile.origin = Some(parse_address(address).ok_or_else(|| {
+                        format!("line {}: invalid origin '{}'", number + 1, address)
+                    })?);
+                }
+                [address, count] => {
+                    let (Some(address), Ok(count)) = (parse_address(address), count.parse::<u64>()) else {
+                        return Err(format!("line {}: expected '<address> <count>', found '{}'", number + 1, line));
+                    };
+                    *profile.counts.entry(address).or_default() += count;
+                }
+                _ => return Err(format!("line {}: expected '<address> <count>', found '{}'", number + 1, line)),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+pub(crate) fn parse_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Bytes, calls and T-states of one routine
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RoutineProfile {
+    pub name: String,
+    pub bytes: usize,
+    /// Executions of the routine's first instruction
+    pub calls: u64,
+    pub cycles: u64,
+}
+
+/// The listing annotated with `profile`, and the size report of its
+/// routines. `routines` are the labels starting a routine; instructions
+/// before the first are reported under "(start)".
+pub fn annotate(
+    instructions: &[Z80Instruction],
+    codegen: &CodeGenerator,
+    routines: &HashSet<String>,
+    profile: &Profile,
+    origin: u16,
+) -> Vec<String> {
+    // Count and T-states of each instruction, at its address
+    let mut address = profile.origin.unwrap_or(origin);
+    let mut lines = Vec::with_capacity(instructions.len());
+    let mut report: Vec<RoutineProfile> = vec![RoutineProfile { name: "(start)".to_string(), ..Default::default() }];
+    for inst in instructions {
+        if let Z80Instruction::Label { name } = inst
+            && routines.contains(name)
+        {
+            report.push(RoutineProfile { name: name.clone(), ..Default::default() });
+        }
+        let size = codegen.instruction_size(inst);
+        let routine = report.last_mut().unwrap();
+        if size == 0 {
+            lines.push((inst, None));
+            continue;
+        }
+        let count = profile.counts.get(&address).copied().unwrap_or(0);
+        let cycles = count * codegen.instruction_cycles(inst) as u64;
+        if routine.bytes == 0 {
+            routine.calls = count;
+        }
+        routine.bytes += size;
+        routine.cycles += cycles;
+        lines.push((inst, Some((count, cycles))));
+        address = address.wrapping_add(size as u16);
+    }
+    report.retain(|routine| routine.bytes > 0);
+
+    let total: u64 = report.iter().map(|routine| routine.cycles).sum();
+    let share = |cycles: u64| if total == 0 { 0.0 } else { cycles as f64 / total as f64 };
+    let mut listing: Vec<String> = lines
+        .into_iter()
+        .map(|(inst, counts)| match counts {
+            Some((count, cycles)) => {
+                let hot = if share(cycles) >= HOT_SHARE { "  hot" } else { "" };
+                format!("{:<32}; {:>8}x {:>10} T{}", inst.to_string(), count, cycles, hot)
+            }
+            None => inst.to_string(),
+        })
+        .collect();
+
+    // Size report, hottest routine first
+    report.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.name.cmp(&b.name)));
+    listing.push(format!("; profile: {} T-states", total));
+    listing.push(format!("; {:<24} {:>6} {:>10} {:>12} {:>6}", "routine", "bytes", "calls", "T-states", "%"));
+    for routine in &report {
+        listing.push(format!(
+            "; {:<24} {:>6} {:>10} {:>12} {:>6.1}",
+            routine.name,
+            routine.bytes,
+            routine.calls,
+            routine.cycles,
+            share(routine.cycles) * 100.0
+        ));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend_zealz80::Z80Register;
+
+    #[test]
+    fn test_annotates_listing_with_counts_and_cycles() {
+        let profile = Profile::parse("origin $8000\n; PC counts\n8000 1\n$8003 100\n0x8004 100 # loop\n").unwrap();
+        assert_eq!(profile.origin, Some(0x8000));
+        assert!(Profile::parse("8000 many").unwrap_err().starts_with("line 1:"));
+
+        let instructions = [
+            Z80Instruction::Label { name: "main".to_string() },
+            Z80Instruction::Call { label: "fill".to_string() },
+            Z80Instruction::Label { name: "fill".to_string() },
+            Z80Instruction::Increment { reg: Z80Register::HL },
+            Z80Instruction::Return,
+        ];
+        let routines: HashSet<String> = ["main".to_string(), "fill".to_string()].into();
+        let listing = annotate(&instructions, &CodeGenerator::new(), &routines, &profile, 0);
+
+        // fill: 100 x (6 + 10) T-states, against 17 for the call in main
+        assert_eq!(listing[1], format!("{:<32}; {:>8}x {:>10} T", "    call fill", 1, 17));
+        assert!(listing[4].contains("100x       1000 T  hot"));
+        assert_eq!(listing[5], "; profile: 1617 T-states");
+        assert!(listing[7].starts_with("; fill                          2        100         1600   98.9"));
+        assert!(listing[8].starts_with("; main                          3          1           17"));
+    }
+}
@@ -0,0 +1,150 @@
+//! Source statistics (`spc stats`)
+//!
+//! Sizes up a file or a corpus of files: tokens, AST nodes by kind,
+//! declarations, and the time spent lexing, parsing and analyzing.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use ast::Node;
+
+/// Statistics of one file, or the sum over several
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceStats {
+    pub files: usize,
+    pub lines: usize,
+    pub bytes: usize,
+    pub tokens: usize,
+    /// AST nodes by [`Node::kind`]
+    pub nodes: BTreeMap<&'static str, usize>,
+    /// Declared names by kind ("variables", "procedures", ...)
+    pub declarations: BTreeMap<&'static str, usize>,
+    pub diagnostics: usize,
+    pub lex_time: Duration,
+    pub parse_time: Duration,
+    pub analysis_time: Duration,
+}
+
+impl SourceStats {
+    /// Count the lines and bytes of `source`
+    pub fn for_source(source: &str) -> Self {
+        SourceStats {
+            files: 1,
+            lines: source.lines().count(),
+            bytes: source.len(),
+            ..Default::default()
+        }
+    }
+
+    /// Count the nodes and declarations of `ast`
+    pub fn count_nodes(&mut self, ast: &Node) {
+        let mut pending = vec![ast];
+        while let Some(node) = pending.pop() {
+            *self.nodes.entry(node.kind()).or_default() += 1;
+            if let Some((kind, names)) = declared_names(node) {
+                *self.declarations.entry(kind).or_default() += names;
+            }
+            pending.extend(node.children());
+        }
+    }
+
+    /// Add the counts and timings of `other`
+    pub fn add(&mut self, other: &SourceStats) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.bytes += other.bytes;
+        self.tokens += other.tokens;
+        for (kind, count) in &other.nodes {
+            *self.nodes.entry(kind).or_default() += count;
+        }
+        for (kind, count) in &other.declarations {
+            *self.declarations.entry(kind).or_default() += count;
+        }
+        self.diagnostics += other.diagnostics;
+        self.lex_time += other.lex_time;
+        self.parse_time += other.parse_time;
+        self.analysis_time += other.analysis_time;
+    }
+
+    /// Print the report under `title`
+    pub fn print(&self, title: &str) {
+        println!("{}", title);
+        if self.files > 1 {
+            println!("  files        {}", self.files);
+        }
+        println!("  lines        {}", self.lines);
+        println!("  bytes        {}", self.bytes);
+        println!("  tokens       {}", self.tokens);
+        println!("  diagnostics  {}", self.diagnostics);
+        println!("  timing");
+        for (phase, time) in [("lex", self.lex_time), ("parse", self.parse_time), ("analyze", self.analysis_time)] {
+            println!("    {:<10} {:>10.3} ms{}", phase, millis(time), self.throughput(time));
+        }
+        println!("  declarations");
+        for (kind, count) in &self.declarations {
+            println!("    {:<22} {}", kind, count);
+        }
+        let total: usize = self.nodes.values().sum();
+        println!("  nodes        {}", total);
+        let mut nodes: Vec<(&&str, &usize)> = self.nodes.iter().collect();
+        // Most frequent first
+        nodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (kind, count) in nodes {
+            println!("    {:<22} {}", kind, count);
+        }
+    }
+
+    /// Lines per second over `time`, for the timing table
+    fn throughput(&self, time: Duration) -> String {
+        if time.is_zero() {
+            return String::new();
+        }
+        format!("  ({:.0} lines/s)", self.lines as f64 / time.as_secs_f64())
+    }
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+/// The declaration kind of `node` and how many names it declares
+fn declared_names(node: &Node) -> Option<(&'static str, usize)> {
+    match node {
+        Node::VarDecl(v) => Some(("variables", v.names.len())),
+        Node::ConstDecl(_) => Some(("constants", 1)),
+        Node::TypeDecl(_) => Some(("types", 1)),
+        Node::LabelDecl(l) => Some(("labels", l.labels.len())),
+        Node::ProcDecl(_) => Some(("procedures", 1)),
+        Node::FuncDecl(_) => Some(("functions", 1)),
+        Node::OperatorDecl(_) => Some(("operators", 1)),
+        Node::PropertyDecl(_) => Some(("properties", 1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    #[test]
+    fn test_counts_nodes_and_declarations() {
+        let source = "program P;\nvar A, B: integer;\nprocedure Q; begin end;\nbegin\n  A := B + 1;\nend.\n";
+        let ast = Parser::new(source).unwrap().parse().unwrap();
+        let mut stats = SourceStats::for_source(source);
+        stats.count_nodes(&ast);
+        assert_eq!(stats.lines, 6);
+        assert_eq!(stats.declarations["variables"], 2);
+        assert_eq!(stats.declarations["procedures"], 1);
+        assert_eq!(stats.nodes["Program"], 1);
+        assert_eq!(stats.nodes["AssignStmt"], 1);
+        assert_eq!(stats.nodes["BinaryExpr"], 1);
+
+        let mut total = SourceStats::default();
+        total.add(&stats);
+        total.add(&stats);
+        assert_eq!(total.files, 2);
+        assert_eq!(total.nodes["AssignStmt"], 2);
+        assert_eq!(total.declarations["variables"], 4);
+    }
+}
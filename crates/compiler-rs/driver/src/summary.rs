@@ -0,0 +1,202 @@
+//! Program summary (`spc check --summary`)
+//!
+//! What a checked program or unit is made of, as the semantic analysis saw
+//! it: the units it uses, its top-level routines with their signatures, and
+//! its program-level variables with an estimate of the RAM they take.
+//! Variables of unknown size are counted as two bytes, as the analysis does.
+
+use std::fmt;
+
+use ast::Node;
+use semantics::SemanticAnalyzer;
+use symbols::{Parameter, ParameterMode};
+
+use crate::compiler::top_level_routines;
+
+/// Summary of one analyzed program or unit
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProgramSummary {
+    /// Units named in `uses` clauses, in order
+    pub units: Vec<String>,
+    /// Headings of the top-level routines with types as declared, e.g.
+    /// `function Mix(A: byte; B: byte): word`
+    pub routines: Vec<String>,
+    pub variables: Vec<VariableSummary>,
+    /// Bytes of RAM taken by the variables, absolute ones excepted
+    pub variable_bytes: u32,
+}
+
+/// A program-level variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableSummary {
+    pub name: String,
+    pub type_name: String,
+    /// Bytes it takes, if known
+    pub size: Option<usize>,
+    /// Whether it is placed at a fixed address instead of taking RAM
+    pub absolute: bool,
+}
+
+impl ProgramSummary {
+    /// Summarize `ast` once `analyzer` has analyzed it
+    pub fn collect(ast: &Node, analyzer: &SemanticAnalyzer) -> Self {
+        let (uses, var_decls): (Vec<_>, Vec<&Node>) = match ast {
+            Node::Program(program) => (
+                program.uses.iter().collect(),
+                match program.block.as_ref() {
+                    Node::Block(block) => block.var_decls.iter().collect(),
+                    _ => vec![],
+                },
+            ),
+            Node::Unit(unit) => (
+                unit.interface.iter().filter_map(|i| i.uses.as_ref())
+                    .chain(unit.implementation.iter().filter_map(|i| i.uses.as_ref()))
+                    .collect(),
+                unit.interface.iter().flat_map(|i| i.var_decls.iter())
+                    .chain(unit.implementation.iter().flat_map(|i| i.var_decls.iter()))
+                    .collect(),
+            ),
+            _ => (vec![], vec![]),
+        };
+
+        let mut routines: Vec<String> = Vec::new();
+        let mut seen: Vec<String> = Vec::new();
+        for decl in top_level_routines(ast) {
+            let (name, declared_params, declared_result) = match decl {
+                Node::ProcDecl(proc) if proc.class_name.is_none() => (&proc.name, &proc.params, None),
+                Node::FuncDecl(func) if func.class_name.is_none() => (&func.name, &func.params, Some(func.return_type.as_ref())),
+                _ => continue,
+            };
+            // A unit's interface heading and its implementation are one routine
+            if seen.iter().any(|s| s.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            seen.push(name.clone());
+            if let Some((params, return_type)) = analyzer.routine_signature(name) {
+                let declared_types: Vec<_> = declared_params
+                    .iter()
+                    .flat_map(|param| param.names.iter().map(|_| param.type_expr.as_deref()))
+                    .collect();
+                let params: Vec<_> = params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| (param, declared_types.get(i).copied().flatten()))
+                    .collect();
+                let result = return_type.as_ref().map(|ty| type_name(declared_result, ty));
+                routines.push(heading(name, &params, result));
+            }
+        }
+
+        let variables = var_decls
+            .into_iter()
+            .filter_map(|decl| match decl {
+                Node::VarDecl(var) => Some(var),
+                _ => None,
+            })
+            .flat_map(|var| {
+                var.names.iter().filter_map(move |name| {
+                    let var_type = analyzer.variable_type(name)?;
+                    Some(VariableSummary {
+                        name: name.clone(),
+                        type_name: type_name(Some(&var.type_expr), &var_type),
+                        size: var_type.size(),
+                        absolute: var.absolute_address.is_some(),
+                    })
+                })
+            })
+            .collect();
+
+        ProgramSummary {
+            units: uses.into_iter().flat_map(|clause| clause.units.iter().cloned()).collect(),
+            routines,
+            variables,
+            variable_bytes: analyzer.global_variable_size(),
+        }
+    }
+}
+
+/// Name of `ty` as declared: the type named by `declared` if it names one,
+/// so that a record shows as `TPoint`, otherwise as the analysis describes it
+fn type_name(declared: Option<&Node>, ty: &::types::Type) -> String {
+    match declared {
+        Some(Node::NamedType(named)) if named.generic_args.is_empty() => named.name.clone(),
+        _ => semantics::type_name(ty),
+    }
+}
+
+/// `procedure Name(params)` or `function Name(params): Type`, each parameter
+/// with its declared type
+fn heading(name: &str, params: &[(&Parameter, Option<&Node>)], return_type: Option<String>) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|(param, declared)| {
+            let mode = match param.passing_mode {
+                ParameterMode::Value => "",
+                ParameterMode::Var => "var ",
+                ParameterMode::Const => "const ",
+            };
+            match &param.param_type {
+                ::types::Type::Untyped => format!("{}{}", mode, param.name),
+                param_type => format!("{}{}: {}", mode, param.name, type_name(*declared, param_type)),
+            }
+        })
+        .collect();
+    let params = if params.is_empty() { String::new() } else { format!("({})", params.join("; ")) };
+    match return_type {
+        Some(return_type) => format!("function {}{}: {}", name, params, return_type),
+        None => format!("procedure {}{}", name, params),
+    }
+}
+
+impl fmt::Display for ProgramSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.units.is_empty() {
+            writeln!(f, "Units: none")?;
+        } else {
+            writeln!(f, "Units: {}", self.units.join(", "))?;
+        }
+        writeln!(f, "Routines: {}", self.routines.len())?;
+        for routine in &self.routines {
+            writeln!(f, "  {}", routine)?;
+        }
+        writeln!(f, "Variables: {} ({} bytes)", self.variables.len(), self.variable_bytes)?;
+        for variable in &self.variables {
+            let size = match (variable.absolute, variable.size) {
+                (true, _) => "absolute".to_string(),
+                (false, Some(size)) => format!("{} bytes", size),
+                (false, None) => "size unknown".to_string(),
+            };
+            writeln!(f, "  {}: {}  {}", variable.name, variable.type_name, size)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_of_a_program() {
+        let source = "program P;
+             uses Tasks;
+             var Count: word; Buf: array[0..9] of byte; Screen: byte absolute $4000;
+             procedure Fill(var Dest; Len: word; const Value: byte); begin end;
+             function Mix(A, B: byte): word; begin Mix := A + B end;
+             begin end.";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(None);
+        analyzer.analyze(&ast);
+        let summary = ProgramSummary::collect(&ast, &analyzer);
+
+        assert_eq!(summary.units, ["Tasks"]);
+        assert_eq!(summary.routines, [
+            "procedure Fill(var Dest; Len: word; const Value: byte)",
+            "function Mix(A: byte; B: byte): word",
+        ]);
+        let sizes: Vec<_> = summary.variables.iter().map(|v| (v.name.as_str(), v.size, v.absolute)).collect();
+        assert_eq!(sizes, [("Count", Some(2), false), ("Buf", Some(10), false), ("Screen", Some(1), true)]);
+        assert_eq!(summary.variable_bytes, 12);
+        assert!(summary.to_string().contains("Variables: 3 (12 bytes)"), "{}", summary);
+    }
+}
@@ -0,0 +1,275 @@
+//! Symbol table dump (`spc emit-symbols`)
+//!
+//! Every name the semantic analysis of a program or unit declared, local
+//! ones included: its kind, its type, the routine it is local to, where it
+//! is declared, the bytes it takes and, for an `absolute` variable, its
+//! address. Printed as a table:
+//!
+//! ```text
+//! Name    Kind       Type                      Scope   Line:Col  Size  Address
+//! Screen  variable   Byte                      global  2:18         1  $4000
+//! Fill    procedure  (var Dest; Len: Word)     global  3:1
+//! Len     parameter  Word                      Fill    3:26         2
+//! Mix     function   (A: Byte; B: Byte): Word  global  8:1
+//! Result  variable   Word                      Mix     8:1          2
+//! ```
+//!
+//! or as a JSON array of the same fields, for tools.
+
+use std::fmt;
+
+use ast::Node;
+use errors::json_string;
+use semantics::SemanticAnalyzer;
+use symbols::{Parameter, ParameterMode, Symbol, SymbolKind};
+use tokens::Span;
+
+/// A declared name
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolEntry {
+    pub name: String,
+    /// `variable`, `parameter`, `constant`, `type`, `generic type`,
+    /// `procedure` or `function`
+    pub kind: &'static str,
+    /// Its type; a routine's parameters and result
+    pub type_name: String,
+    /// The routine it is local to, or `global`
+    pub scope: String,
+    pub span: Span,
+    /// Bytes a variable or a value of a type takes, if known
+    pub size: Option<usize>,
+    /// Address of a variable placed with `absolute`
+    pub address: Option<u16>,
+}
+
+/// The symbols of one analyzed program or unit, in declaration order
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SymbolDump {
+    pub entries: Vec<SymbolEntry>,
+}
+
+/// A routine of the source: its name as declared, where it is, and where
+/// its parameters are
+struct Routine {
+    name: String,
+    span: Span,
+    param_spans: Vec<Span>,
+}
+
+impl SymbolDump {
+    /// The symbols `analyzer` declared while analyzing `ast`
+    pub fn collect(ast: &Node, analyzer: &SemanticAnalyzer) -> Self {
+        let mut routines = Vec::new();
+        collect_routines(ast, &mut routines);
+        let entries = analyzer
+            .declared_symbols()
+            .into_iter()
+            .map(|symbol| entry(symbol, &routines, analyzer))
+            .collect();
+        SymbolDump { entries }
+    }
+
+    /// The entries as a JSON array, one object per line
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "  {{\"name\": {}, \"kind\": \"{}\", \"type\": {}, \"scope\": {}, \"span\": {{\"start\": {}, \"end\": {}, \"line\": {}, \"column\": {}}}, \"size\": {}, \"address\": {}}}",
+                    json_string(&entry.name),
+                    entry.kind,
+                    json_string(&entry.type_name),
+                    json_string(&entry.scope),
+                    entry.span.start,
+                    entry.span.end,
+                    entry.span.line,
+                    entry.span.column,
+                    optional(entry.size.map(|size| size.to_string())),
+                    optional(entry.address.map(|address| address.to_string()))
+                )
+            })
+            .collect();
+        if entries.is_empty() {
+            return "[]\n".to_string();
+        }
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+fn collect_routines(node: &Node, routines: &mut Vec<Routine>) {
+    let routine = |name: &str, class_name: &Option<String>, params: &[ast::Param], span: Span| Routine {
+        name: match class_name {
+            Some(class_name) => format!("{}.{}", class_name, name),
+            None => name.to_string(),
+        },
+        span,
+        param_spans: params.iter().map(|param| param.span).collect(),
+    };
+    match node {
+        Node::ProcDecl(proc) => routines.push(routine(&proc.name, &proc.class_name, &proc.params, proc.span)),
+        Node::FuncDecl(func) => routines.push(routine(&func.name, &func.class_name, &func.params, func.span)),
+        Node::OperatorDecl(operator) => {
+            routines.push(routine(&operator.operator_name, &operator.class_name, &operator.params, operator.span))
+        }
+        _ => {}
+    }
+    for child in node.children() {
+        collect_routines(child, routines);
+    }
+}
+
+fn entry(symbol: &Symbol, routines: &[Routine], analyzer: &SemanticAnalyzer) -> SymbolEntry {
+    let span = symbol.span();
+    // The innermost routine around the declaration, other than the one it
+    // declares; a function's result shares the span of the function
+    let is_routine = matches!(symbol.kind, SymbolKind::Procedure { .. } | SymbolKind::Function { .. });
+    let enclosing = routines
+        .iter()
+        .filter(|routine| routine.span.start <= span.start && span.start < routine.span.end)
+        .filter(|routine| !(is_routine && routine.span == span))
+        .min_by_key(|routine| routine.span.end - routine.span.start);
+    let scope = match enclosing {
+        Some(routine) if symbol.scope_level > 0 => routine.name.clone(),
+        _ => "global".to_string(),
+    };
+    let (kind, type_name, size, address) = match &symbol.kind {
+        SymbolKind::Variable { var_type, .. } => {
+            let is_param = enclosing.is_some_and(|routine| routine.param_spans.contains(&span));
+            let kind = if is_param { "parameter" } else { "variable" };
+            (kind, semantics::type_name(var_type), var_type.size(), analyzer.absolute_address(span))
+        }
+        SymbolKind::Constant { const_type, .. } => ("constant", semantics::type_name(const_type), None, None),
+        SymbolKind::TypeAlias { aliased_type, .. } => {
+            ("type", semantics::type_name(aliased_type), aliased_type.size(), None)
+        }
+        SymbolKind::GenericType { template_type, param_names, .. } => {
+            let type_name = format!("<{}> {}", param_names.join(", "), semantics::type_name(template_type));
+            ("generic type", type_name, None, None)
+        }
+        SymbolKind::Procedure { params, .. } => ("procedure", parameter_list(params), None, None),
+        SymbolKind::Function { params, return_type, .. } => {
+            ("function", format!("{}: {}", parameter_list(params), semantics::type_name(return_type)), None, None)
+        }
+    };
+    SymbolEntry { name: symbol.name().to_string(), kind, type_name, scope, span, size, address }
+}
+
+/// `(var A: byte; B: word)`, or nothing for a routine without parameters
+fn parameter_list(params: &[Parameter]) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+    let params: Vec<String> = params
+        .iter()
+        .map(|param| {
+            let mode = match param.passing_mode {
+                ParameterMode::Value => "",
+                ParameterMode::Var => "var ",
+                ParameterMode::Const => "const ",
+            };
+            match &param.param_type {
+                ::types::Type::Untyped => format!("{}{}", mode, param.name),
+                param_type => format!("{}{}: {}", mode, param.name, semantics::type_name(param_type)),
+            }
+        })
+        .collect();
+    format!("({})", params.join("; "))
+}
+
+impl fmt::Display for SymbolDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells: Vec<[String; 7]> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                [
+                    entry.name.clone(),
+                    entry.kind.to_string(),
+                    entry.type_name.clone(),
+                    entry.scope.clone(),
+                    format!("{}:{}", entry.span.line, entry.span.column),
+                    entry.size.map(|size| size.to_string()).unwrap_or_default(),
+                    entry.address.map(|address| format!("${:04X}", address)).unwrap_or_default(),
+                ]
+            })
+            .collect();
+        let header = ["Name", "Kind", "Type", "Scope", "Line:Col", "Size", "Address"].map(String::from);
+        let mut widths = header.clone().map(|cell| cell.chars().count());
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        for row in std::iter::once(&header).chain(&cells) {
+            let line = format!(
+                "{:w0$}  {:w1$}  {:w2$}  {:w3$}  {:w4$}  {:>w5$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                row[5],
+                row[6],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+                w4 = widths[4],
+                w5 = widths[5]
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_of_a_program() {
+        let source = "program P;
+const Base = $4000;
+type TPoint = record X, Y: byte; end;
+var Count: word; Screen: byte absolute Base;
+procedure Fill(var Dest; Len: word);
+var I: word;
+begin
+end;
+begin
+end.
+";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(None);
+        analyzer.analyze(&ast);
+        let dump = SymbolDump::collect(&ast, &analyzer);
+
+        let rows: Vec<_> = dump
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.kind, entry.scope.as_str(), entry.size, entry.address))
+            .collect();
+        assert_eq!(rows, [
+            ("Base", "constant", "global", None, None),
+            ("TPoint", "type", "global", Some(2), None),
+            ("Count", "variable", "global", Some(2), None),
+            ("Screen", "variable", "global", Some(1), Some(0x4000)),
+            ("Fill", "procedure", "global", None, None),
+            ("Dest", "parameter", "Fill", None, None),
+            ("Len", "parameter", "Fill", Some(2), None),
+            ("I", "variable", "Fill", Some(2), None),
+        ]);
+        assert_eq!(dump.entries[4].type_name, "(var Dest; Len: Word)");
+
+        let table = dump.to_string();
+        assert!(table.starts_with("Name    Kind       Type"), "{}", table);
+        assert!(table.contains("$4000"), "{}", table);
+        let json = dump.to_json();
+        assert!(json.contains(
+            "{\"name\": \"Screen\", \"kind\": \"variable\", \"type\": \"Byte\", \"scope\": \"global\", \"span\": {\"start\": 86, \"end\": 112, \"line\": 4, \"column\": 18}, \"size\": 1, \"address\": 16384}"
+        ), "{}", json);
+    }
+}
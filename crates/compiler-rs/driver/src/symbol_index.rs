@@ -0,0 +1,634 @@
+//! Project symbol index (`spc xref`, `spc rename`)
+//!
+//! For each source of a project the index lists the names it declares and
+//! every use of a name, resolved to the declaration it refers to through
+//! the scopes of the file. A use of a name a file does not declare is
+//! resolved when queried, to what the units it uses export. Spans are those
+//! of the name alone, so a rename replaces exactly the name.
+//!
+//! The index is kept in `.spc-index` in the project directory. Each run
+//! re-indexes only the sources whose content hash changed, so queries on a
+//! large project do not parse it all again:
+//!
+//! ```text
+//! file 5c1e0f83a9d27b44 src/Sprites.pas
+//! unit Sprites
+//! uses Screen
+//! decl procedure 1 212 216 12 11 0 904 Draw
+//! ref 610 30 5 0 Draw
+//! ref 655 32 3 - Plot
+//! ```
+//!
+//! A `decl` line gives the kind, whether a unit exports it, the byte range,
+//! line and column of the name, and the byte range of its scope; a `ref`
+//! line the offset, line and column of the use and the index of the
+//! declaration in the file, or `-` for a name declared elsewhere. Fields
+//! and methods, reached through a value, are not indexed, nor are names
+//! inside `with` statements, which may be fields.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ast::{Node, Param};
+use tokens::{Span, Token, TokenKind};
+
+/// File the index of a project is kept in, in the project directory
+pub const INDEX_FILE: &str = ".spc-index";
+
+/// A declared name
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedDecl {
+    pub name: String,
+    /// `variable`, `constant`, `type`, `enum value`, `procedure`,
+    /// `function` or `parameter`
+    pub kind: String,
+    /// Declared in a unit's interface
+    pub exported: bool,
+    pub span: Span,
+    /// Byte range of the routine or file it is declared in
+    pub scope: (usize, usize),
+}
+
+/// A use of a name
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedRef {
+    pub name: String,
+    pub span: Span,
+    /// Index of the declaration in the same file, if it is there
+    pub decl: Option<usize>,
+}
+
+/// The declarations and uses of one source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileIndex {
+    /// Content hash of the source indexed
+    pub hash: u64,
+    /// The unit it declares, if it is one
+    pub unit: Option<String>,
+    pub uses: Vec<String>,
+    pub decls: Vec<IndexedDecl>,
+    pub refs: Vec<IndexedRef>,
+}
+
+impl FileIndex {
+    /// Index the parsed `ast` of `source`, whose tokens are `tokens`
+    pub fn build(ast: &Node, tokens: &[Token], hash: u64) -> Self {
+        let mut indexer = Indexer { tokens, file: FileIndex { hash, ..FileIndex::default() }, scopes: vec![], with_depth: 0 };
+        let whole = (0, tokens.last().map_or(0, |token| token.span.end));
+        match ast {
+            Node::Program(program) => {
+                indexer.file.uses = program.uses.iter().flat_map(|uses| uses.units.clone()).collect();
+                indexer.scopes.push((vec![], whole));
+                indexer.block(&program.block);
+            }
+            Node::Unit(unit) => {
+                indexer.file.unit = Some(unit.name.clone());
+                let interface_uses = unit.interface.iter().flat_map(|interface| &interface.uses);
+                let implementation_uses = unit.implementation.iter().flat_map(|implementation| &implementation.uses);
+                indexer.file.uses = interface_uses.chain(implementation_uses).flat_map(|uses| uses.units.clone()).collect();
+                indexer.scopes.push((vec![], whole));
+                if let Some(interface) = &unit.interface {
+                    indexer.declarations(&interface.children(), true);
+                }
+                if let Some(implementation) = &unit.implementation {
+                    indexer.declarations(&implementation.children(), false);
+                }
+                for block in unit.initialization.iter().chain(&unit.finalization) {
+                    indexer.block(block);
+                }
+            }
+            _ => {}
+        }
+        indexer.file
+    }
+}
+
+/// Resolves the names of one file through its scopes
+struct Indexer<'a> {
+    tokens: &'a [Token],
+    file: FileIndex,
+    /// Declarations visible in each enclosing scope, with its byte range
+    scopes: Vec<(Vec<usize>, (usize, usize))>,
+    with_depth: usize,
+}
+
+impl Indexer<'_> {
+    /// Span of the identifier `name` in the source covered by `span`
+    fn name_span(&self, span: Span, name: &str) -> Option<Span> {
+        let first = self.tokens.partition_point(|token| token.span.start < span.start);
+        // The span of a heading may end before its name, but not past its `;`
+        let mut past_semicolon = false;
+        self.tokens[first..]
+            .iter()
+            .take_while(|token| {
+                let within = token.span.start < span.end || !past_semicolon;
+                past_semicolon |= token.kind == TokenKind::Semicolon;
+                within
+            })
+            .find(|token| matches!(&token.kind, TokenKind::Identifier(id) if id.eq_ignore_ascii_case(name)))
+            .map(|token| token.span)
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|(decls, _)| decls.iter())
+            .copied()
+            .find(|&decl| self.file.decls[decl].name.eq_ignore_ascii_case(name))
+    }
+
+    /// Declare `name`, found in the source of `span`, in the innermost scope;
+    /// a routine declared again, such as the body of a forward declaration,
+    /// is a use of the first declaration
+    fn declare(&mut self, name: &str, kind: &str, span: Span, exported: bool) {
+        let Some(span) = self.name_span(span, name) else {
+            return;
+        };
+        let Some((decls, scope)) = self.scopes.last() else {
+            return;
+        };
+        if let Some(&earlier) = decls.iter().find(|&&decl| self.file.decls[decl].name.eq_ignore_ascii_case(name)) {
+            self.file.refs.push(IndexedRef { name: name.to_string(), span, decl: Some(earlier) });
+            return;
+        }
+        let scope = *scope;
+        self.file.decls.push(IndexedDecl { name: name.to_string(), kind: kind.to_string(), exported, span, scope });
+        let index = self.file.decls.len() - 1;
+        if let Some((decls, _)) = self.scopes.last_mut() {
+            decls.push(index);
+        }
+    }
+
+    /// Record a use of `name`, found in the source of `span`
+    fn reference(&mut self, name: &str, span: Span) {
+        if self.with_depth > 0 {
+            return;
+        }
+        if let Some(span) = self.name_span(span, name) {
+            let decl = self.lookup(name);
+            self.file.refs.push(IndexedRef { name: name.to_string(), span, decl });
+        }
+    }
+
+    /// Declare the names `decls` declare, then index what is in them
+    fn declarations(&mut self, decls: &[&Node], exported: bool) {
+        for decl in decls {
+            match decl {
+                Node::VarDecl(var) => {
+                    for name in &var.names {
+                        self.declare(name, "variable", var.span, exported);
+                    }
+                }
+                Node::ConstDecl(constant) => self.declare(&constant.name, "constant", constant.span, exported),
+                Node::TypeDecl(type_decl) => {
+                    self.declare(&type_decl.name, "type", type_decl.span, exported);
+                    if let Node::EnumType(enum_type) = type_decl.type_expr.as_ref() {
+                        for value in &enum_type.values {
+                            self.declare(value, "enum value", enum_type.span, exported);
+                        }
+                    }
+                }
+                Node::ProcDecl(proc) if proc.class_name.is_none() => self.declare(&proc.name, "procedure", proc.span, exported),
+                Node::FuncDecl(func) if func.class_name.is_none() => self.declare(&func.name, "function", func.span, exported),
+                _ => {}
+            }
+        }
+        for decl in decls {
+            self.visit(decl);
+        }
+    }
+
+    fn block(&mut self, block: &Node) {
+        let Node::Block(block) = block else {
+            return self.visit(block);
+        };
+        let decls: Vec<&Node> = [
+            &block.const_decls,
+            &block.type_decls,
+            &block.var_decls,
+            &block.threadvar_decls,
+            &block.proc_decls,
+            &block.func_decls,
+            &block.operator_decls,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        self.declarations(&decls, false);
+        for statement in &block.statements {
+            self.visit(statement);
+        }
+    }
+
+    /// Index a routine body with its parameters, in a scope of its own
+    fn routine(&mut self, class_name: Option<&String>, params: &[Param], block: &Node, span: Span) {
+        if let Some(class_name) = class_name {
+            self.reference(class_name, span);
+        }
+        self.scopes.push((vec![], (span.start, span.end)));
+        for param in params {
+            for node in param.type_expr.iter().chain(&param.default_value) {
+                self.visit(node);
+            }
+            for name in &param.names {
+                self.declare(name, "parameter", param.span, false);
+            }
+        }
+        self.block(block);
+        self.scopes.pop();
+    }
+
+    fn visit(&mut self, node: &Node) {
+        match node {
+            Node::ProcDecl(proc) => self.routine(proc.class_name.as_ref(), &proc.params, &proc.block, proc.span),
+            Node::FuncDecl(func) => {
+                self.visit(&func.return_type);
+                self.routine(func.class_name.as_ref(), &func.params, &func.block, func.span);
+            }
+            Node::OperatorDecl(operator) => {
+                self.visit(&operator.return_type);
+                self.routine(None, &operator.params, &operator.block, operator.span);
+            }
+            Node::AnonymousFunction(function) => {
+                self.visit(&function.return_type);
+                self.routine(None, &function.params, &function.block, function.span);
+            }
+            Node::AnonymousProcedure(procedure) => self.routine(None, &procedure.params, &procedure.block, procedure.span),
+            Node::IdentExpr(ident) => self.reference(&ident.name, ident.span),
+            Node::NamedType(named) => {
+                self.reference(&named.name, named.span);
+                for arg in &named.generic_args {
+                    self.visit(arg);
+                }
+            }
+            Node::EnumLiteralExpr(literal) => {
+                if let Some(enum_type) = &literal.enum_type {
+                    self.reference(enum_type, literal.span);
+                }
+                self.reference(&literal.value, literal.span);
+            }
+            Node::ClassType(class) => {
+                for base in &class.base_classes {
+                    self.reference(base, class.span);
+                }
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+            Node::CallStmt(call) => {
+                self.reference(&call.name, call.span);
+                for arg in &call.args {
+                    self.visit(arg);
+                }
+            }
+            Node::CallExpr(call) => {
+                self.reference(&call.name, call.span);
+                for arg in &call.args {
+                    self.visit(arg);
+                }
+            }
+            Node::ForStmt(for_stmt) => {
+                self.reference(&for_stmt.var_name, for_stmt.span);
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+            Node::ForInStmt(for_in) => {
+                self.reference(&for_in.var_name, for_in.span);
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+            Node::WithStmt(with) => {
+                for record in &with.records {
+                    self.visit(record);
+                }
+                self.with_depth += 1;
+                self.visit(&with.statement);
+                self.with_depth -= 1;
+            }
+            _ => {
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+        }
+    }
+}
+
+/// The indexes of the sources of a project
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolIndex {
+    pub files: BTreeMap<PathBuf, FileIndex>,
+}
+
+impl SymbolIndex {
+    /// The index stored at `path`; empty when there is none or it cannot be
+    /// read, so that everything is indexed again
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path).ok().and_then(|text| Self::parse(&text)).unwrap_or_default()
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut index = SymbolIndex::default();
+        let mut current: Option<&mut FileIndex> = None;
+        for line in text.lines() {
+            let (tag, rest) = line.split_once(' ')?;
+            if tag == "file" {
+                let (hash, path) = rest.split_once(' ')?;
+                let file = FileIndex { hash: u64::from_str_radix(hash, 16).ok()?, ..FileIndex::default() };
+                current = Some(index.files.entry(PathBuf::from(path)).or_insert(file));
+                continue;
+            }
+            let file = current.as_deref_mut()?;
+            let fields: Vec<&str> = rest.split(' ').collect();
+            let number = |i: usize| fields.get(i).and_then(|field| field.parse::<usize>().ok());
+            match (tag, fields.len()) {
+                ("unit", 1) => file.unit = Some(rest.to_string()),
+                ("uses", _) => file.uses = fields.iter().map(|name| name.to_string()).collect(),
+                ("decl", 9) => file.decls.push(IndexedDecl {
+                    kind: fields[0].replace('_', " "),
+                    exported: fields[1] == "1",
+                    span: Span::new(number(2)?, number(3)?, number(4)?, number(5)?),
+                    scope: (number(6)?, number(7)?),
+                    name: fields[8].to_string(),
+                }),
+                ("ref", 5) => file.refs.push(IndexedRef {
+                    span: Span::new(number(0)?, number(0)? + fields[4].len(), number(1)?, number(2)?),
+                    decl: if fields[3] == "-" { None } else { Some(number(3)?) },
+                    name: fields[4].to_string(),
+                }),
+                _ => return None,
+            }
+        }
+        Some(index)
+    }
+
+    /// Write the index to `path`
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::new();
+        for (path, file) in &self.files {
+            text.push_str(&format!("file {:016x} {}\n", file.hash, path.display()));
+            if let Some(unit) = &file.unit {
+                text.push_str(&format!("unit {}\n", unit));
+            }
+            if !file.uses.is_empty() {
+                text.push_str(&format!("uses {}\n", file.uses.join(" ")));
+            }
+            for decl in &file.decls {
+                text.push_str(&format!(
+                    "decl {} {} {} {} {} {} {} {} {}\n",
+                    decl.kind.replace(' ', "_"),
+                    decl.exported as u8,
+                    decl.span.start,
+                    decl.span.end,
+                    decl.span.line,
+                    decl.span.column,
+                    decl.scope.0,
+                    decl.scope.1,
+                    decl.name
+                ));
+            }
+            for reference in &file.refs {
+                let decl = reference.decl.map_or("-".to_string(), |decl| decl.to_string());
+                text.push_str(&format!(
+                    "ref {} {} {} {} {}\n",
+                    reference.span.start, reference.span.line, reference.span.column, decl, reference.name
+                ));
+            }
+        }
+        fs::write(path, text)
+    }
+
+    /// Bring the index up to date with `sources`, given with their content
+    /// hashes: those whose hash changed are indexed again by `index`, and
+    /// those no longer there dropped. Returns how many were indexed.
+    pub fn update(
+        &mut self,
+        sources: &[(PathBuf, u64)],
+        mut index: impl FnMut(&Path) -> Result<FileIndex, String>,
+    ) -> Result<usize, String> {
+        self.files.retain(|path, _| sources.iter().any(|(source, _)| source == path));
+        let mut indexed = 0;
+        for (path, hash) in sources {
+            if self.files.get(path).is_some_and(|file| file.hash == *hash) {
+                continue;
+            }
+            self.files.insert(path.clone(), index(path)?);
+            indexed += 1;
+        }
+        Ok(indexed)
+    }
+
+    /// The declarations named `name`, as (file, index) pairs
+    pub fn declarations(&self, name: &str) -> Vec<(&Path, usize)> {
+        self.files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.decls
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, decl)| decl.name.eq_ignore_ascii_case(name))
+                    .map(move |(i, _)| (path.as_path(), i))
+            })
+            .collect()
+    }
+
+    pub fn decl(&self, file: &Path, decl: usize) -> &IndexedDecl {
+        &self.files[file].decls[decl]
+    }
+
+    /// The uses of declaration `decl` of `file`: in the file itself, and
+    /// for a name a unit exports, in the files using the unit
+    pub fn uses<'a>(&'a self, file: &'a Path, decl: usize) -> Vec<(&'a Path, Span)> {
+        let declaring = &self.files[file];
+        let name = &declaring.decls[decl].name;
+        let mut uses: Vec<(&Path, Span)> = declaring
+            .refs
+            .iter()
+            .filter(|reference| reference.decl == Some(decl))
+            .map(|reference| (file, reference.span))
+            .collect();
+        if let (Some(unit), true) = (&declaring.unit, declaring.decls[decl].exported) {
+            for (path, user) in &self.files {
+                if path == file || !user.uses.iter().any(|used| used.eq_ignore_ascii_case(unit)) {
+                    continue;
+                }
+                // A name declared in the user resolves there instead
+                uses.extend(
+                    user.refs
+                        .iter()
+                        .filter(|reference| reference.decl.is_none() && reference.name.eq_ignore_ascii_case(name))
+                        .map(|reference| (path.as_path(), reference.span)),
+                );
+            }
+        }
+        uses
+    }
+
+    /// The spans to replace with `new_name` to rename declaration `decl` of
+    /// `file`: the declaration and its uses, by file
+    pub fn rename(&self, file: &Path, decl: usize, new_name: &str) -> Result<BTreeMap<PathBuf, Vec<Span>>, String> {
+        let declaring = &self.files[file];
+        let target = &declaring.decls[decl];
+        let mut edits: BTreeMap<PathBuf, Vec<Span>> = BTreeMap::new();
+        edits.entry(file.to_path_buf()).or_default().push(target.span);
+        for (path, span) in self.uses(file, decl) {
+            edits.entry(path.to_path_buf()).or_default().push(span);
+        }
+        for spans in edits.values_mut() {
+            spans.sort_by_key(|span| span.start);
+            spans.dedup();
+        }
+
+        let within = |scope: (usize, usize), offset: usize| scope.0 <= offset && offset < scope.1;
+        let encloses = |outer: (usize, usize), inner: (usize, usize)| outer.0 <= inner.0 && inner.1 <= outer.1;
+        // A declaration of the new name where the renamed one is used would
+        // hide it; one in an enclosing scope is hidden by it instead
+        for (path, spans) in &edits {
+            let hiding = self.files[path].decls.iter().filter(|other| other.name.eq_ignore_ascii_case(new_name)).find(|other| {
+                *other != target
+                    && spans.iter().any(|span| within(other.scope, span.start))
+                    && (path != file || encloses(target.scope, other.scope))
+            });
+            if let Some(other) = hiding {
+                return Err(format!(
+                    "'{}' is already declared as a {} at {}:{}:{}",
+                    new_name,
+                    other.kind,
+                    path.display(),
+                    other.span.line,
+                    other.span.column
+                ));
+            }
+        }
+        // Nor may a use of the new name come to refer to the renamed one
+        let captured = declaring.refs.iter().find(|reference| {
+            reference.name.eq_ignore_ascii_case(new_name)
+                && within(target.scope, reference.span.start)
+                && reference.decl.is_none_or(|other| {
+                    let scope = declaring.decls[other].scope;
+                    scope != target.scope && encloses(scope, target.scope)
+                })
+        });
+        if let Some(reference) = captured {
+            return Err(format!(
+                "'{}' at {}:{}:{} would refer to the renamed {}",
+                reference.name,
+                file.display(),
+                reference.span.line,
+                reference.span.column,
+                target.kind
+            ));
+        }
+        Ok(edits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(source: &str) -> FileIndex {
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut lexer = lexer::Lexer::new(source);
+        let mut tokens = vec![];
+        while let Ok(token) = lexer.next_token() {
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        FileIndex::build(&ast, &tokens, crate::cache::content_hash(&[source.as_bytes()]))
+    }
+
+    #[test]
+    fn test_uses_resolve_through_scopes_and_units() {
+        let unit = "unit Screen;
+interface
+  procedure Plot(X: integer);
+implementation
+  var Count: integer;
+  procedure Plot(X: integer);
+  var Count: integer;
+  begin
+    Count := X
+  end;
+initialization
+  begin
+    Count := 0
+  end;
+end.
+";
+        let program = "program Game;
+uses Screen;
+var X: integer;
+begin
+  for X := 1 to 3 do Plot(X)
+end.
+";
+        let mut project = SymbolIndex::default();
+        project.files.insert(PathBuf::from("Screen.pas"), index(unit));
+        project.files.insert(PathBuf::from("Game.pas"), index(program));
+        let (screen, game) = (Path::new("Screen.pas"), Path::new("Game.pas"));
+
+        // The local Count hides the unit's
+        let counts = project.declarations("Count");
+        assert_eq!(counts.len(), 2);
+        let lines = |uses: Vec<(&Path, Span)>| uses.iter().map(|(path, span)| (path.display().to_string(), span.line)).collect::<Vec<_>>();
+        assert_eq!(lines(project.uses(counts[0].0, counts[0].1)), [("Screen.pas".to_string(), 13)]);
+        assert_eq!(lines(project.uses(counts[1].0, counts[1].1)), [("Screen.pas".to_string(), 9)]);
+
+        // The body of Plot is a use of its interface declaration, and the
+        // program's call one of the unit's export
+        let [(file, plot)] = project.declarations("Plot")[..] else { panic!() };
+        assert_eq!(file, screen);
+        assert_eq!(lines(project.uses(file, plot)), [("Screen.pas".to_string(), 6), ("Game.pas".to_string(), 5)]);
+
+        let edits = project.rename(file, plot, "Dot").unwrap();
+        let starts: Vec<usize> = edits[game].iter().map(|span| span.start).collect();
+        assert_eq!(starts, [program.find("Plot").unwrap()]);
+        assert_eq!(edits[screen].len(), 2);
+        assert_eq!(
+            project.rename(counts[1].0, counts[1].1, "x").unwrap_err(),
+            "'x' is already declared as a parameter at Screen.pas:6:18"
+        );
+        assert_eq!(
+            project.rename(file, plot, "x").unwrap_err(),
+            "'x' is already declared as a variable at Game.pas:3:5"
+        );
+        assert_eq!(
+            project.rename(counts[1].0, counts[1].1, "Plot").unwrap_err(),
+            "'Plot' at Screen.pas:6:13 would refer to the renamed variable"
+        );
+    }
+
+    #[test]
+    fn test_index_is_saved_and_updated_incrementally() {
+        let dir = std::env::temp_dir().join(format!("spc_symbol_index_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(INDEX_FILE);
+        let source = "program P;\nvar Total: integer;\nbegin\n  Total := 1\nend.\n";
+        let hash = crate::cache::content_hash(&[source.as_bytes()]);
+
+        let mut project = SymbolIndex::load(&path);
+        let sources = [(PathBuf::from("P.pas"), hash)];
+        assert_eq!(project.update(&sources, |_| Ok(index(source))), Ok(1));
+        project.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("decl variable 0 15 20 2 5 0 "));
+        assert!(saved.contains("ref 39 4 3 0 Total\n"));
+
+        // Unchanged sources are not indexed again
+        let mut reloaded = SymbolIndex::load(&path);
+        assert_eq!(reloaded, project);
+        assert_eq!(reloaded.update(&sources, |_| Err("indexed again".to_string())), Ok(0));
+        assert_eq!(reloaded.update(&[(PathBuf::from("P.pas"), hash + 1)], |_| Ok(FileIndex::default())), Ok(1));
+        assert_eq!(reloaded.update(&[], |_| Ok(FileIndex::default())), Ok(0));
+        assert!(reloaded.files.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
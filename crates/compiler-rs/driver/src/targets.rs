@@ -0,0 +1,505 @@
+//! Target definitions
+//!
+//! Everything the driver knows about a machine besides its code generator
+//! comes from a definition file, so supporting a new machine needs no new
+//! compiler:
+//!
+//! ```toml
+//! name = "zx81"                # the --target name
+//! description = "Sinclair ZX81"
+//! platform = "zxspectrum"      # code generator and runtime to use
+//! output = "bin"               # "bin": flat image at the first memory region,
+//!                              # "com": CP/M-style, loaded at $0100,
+//!                              # "interpret": run by the IR interpreter
+//! startup = "zx81/crt0.zof"    # linked before the program; relative to this file
+//! symbols = ["ZX81", "CPU_Z80"]  # predefined for {$IFDEF}
+//! framebuffer_stride = 32      # bytes per row, for generated blits
+//! runtime_errors = "halt"      # "halt", "code" or "handler Name"; see {$RUNTIMEERRORS}
+//!
+//! [[memory]]                   # regions programs may use, load region first
+//! name = "RAM"
+//! kind = "ram"                 # "ram" or "rom"
+//! start = 0x4000
+//! size = 0x4000
+//!
+//! [entry_points]               # ROM routines, linked as absolute symbols
+//! PRINT = 0x0010
+//! ```
+//!
+//! The built-in definitions live in `driver/targets/`. Files loaded from a
+//! `--target-dir` add targets, or replace the built-in of the same name.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use object_zealz80::linker::COM_ORIGIN;
+use resources::ResourceError;
+use resources::toml::{self, Table, Value, check_keys, integer, required, string, tables};
+use runtime_spec::{MemoryKind, MemoryRegion, RuntimeErrorStrategy, TargetPlatform};
+
+/// Extension of target definition files
+pub const TARGET_EXTENSION: &str = "toml";
+
+/// Definitions compiled into the driver, by file name
+const BUILTIN: [(&str, &str); 8] = [
+    ("zealz80.toml", include_str!("../targets/zealz80.toml")),
+    ("zxspectrum.toml", include_str!("../targets/zxspectrum.toml")),
+    ("intel8051.toml", include_str!("../targets/intel8051.toml")),
+    ("commanderx16.toml", include_str!("../targets/commanderx16.toml")),
+    ("foenix65c816.toml", include_str!("../targets/foenix65c816.toml")),
+    ("foenixa2560m.toml", include_str!("../targets/foenixa2560m.toml")),
+    ("raspberrypi5.toml", include_str!("../targets/raspberrypi5.toml")),
+    ("host-test.toml", include_str!("../targets/host-test.toml")),
+];
+
+/// Form of linked programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Flat image loaded at the start of the first memory region
+    Binary,
+    /// CP/M-style program loaded at $0100
+    Com,
+    /// Nothing is linked: building runs the program's IR with a scripted
+    /// console (see [`ir::interp`])
+    Interpreted,
+}
+
+impl OutputFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Binary => "bin",
+            OutputFormat::Com => "com",
+            OutputFormat::Interpreted => "interpret",
+        }
+    }
+}
+
+/// A machine programs can be compiled for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetDefinition {
+    pub name: String,
+    pub description: String,
+    /// Code generator and runtime the machine uses
+    pub platform: TargetPlatform,
+    pub output: OutputFormat,
+    /// Object file linked before the program
+    pub startup: Option<PathBuf>,
+    /// Conditional compilation symbols predefined for the target
+    pub symbols: Vec<String>,
+    /// Bytes per row of the linear framebuffer targeted by generated blits
+    pub framebuffer_stride: Option<u16>,
+    /// What happens on runtime errors unless the program chooses
+    pub runtime_errors: RuntimeErrorStrategy,
+    /// Memory regions available to programs; empty if unknown
+    pub memory_map: Vec<MemoryRegion>,
+    /// ROM routines and their addresses
+    pub entry_points: Vec<(String, u16)>,
+    /// File the definition was read from; None for built-ins
+    pub path: Option<PathBuf>,
+}
+
+/// A definition file that could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetError {
+    pub file: String,
+    pub message: String,
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid target definition '{}': {}", self.file, self.message)
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+impl TargetDefinition {
+    /// Read a definition; `path` is the file it came from, which relative
+    /// startup paths are resolved against
+    pub fn parse(source: &str, path: &Path) -> Result<Self, TargetError> {
+        parse_definition(source, path).map_err(|e| TargetError {
+            file: path.display().to_string(),
+            message: match e {
+                ResourceError::InvalidFormat(message) => message,
+                other => other.to_string(),
+            },
+        })
+    }
+
+    /// The built-in definition of `platform`
+    pub fn builtin(platform: TargetPlatform) -> Self {
+        TargetRegistry::builtin()
+            .find(platform.name())
+            .cloned()
+            .expect("every platform has a built-in definition")
+    }
+
+    /// Whether read-only data (typed constants, string literals) goes to ROM
+    pub fn has_rom(&self) -> bool {
+        self.memory_map.iter().any(|region| region.kind == MemoryKind::Rom)
+    }
+
+    /// Total RAM available to program variables, if the memory map is known
+    pub fn ram_size(&self) -> Option<u32> {
+        if self.memory_map.is_empty() {
+            return None;
+        }
+        Some(self.memory_map.iter().filter(|region| region.kind == MemoryKind::Ram).map(|region| region.size).sum())
+    }
+
+    /// The RAM region holding `address`, if the memory map has one
+    pub fn ram_region(&self, address: u16) -> Option<&MemoryRegion> {
+        self.memory_map
+            .iter()
+            .find(|region| region.kind == MemoryKind::Ram && (region.start..region.start + region.size).contains(&(address as u32)))
+    }
+
+    /// Address linked programs are loaded at, if known
+    pub fn load_address(&self) -> Option<u16> {
+        match self.output {
+            OutputFormat::Com => Some(COM_ORIGIN),
+            OutputFormat::Binary => self.memory_map.first().and_then(|region| u16::try_from(region.start).ok()),
+            OutputFormat::Interpreted => None,
+        }
+    }
+}
+
+/// The targets `--target` can name
+#[derive(Debug, Clone)]
+pub struct TargetRegistry {
+    targets: Vec<TargetDefinition>,
+}
+
+impl TargetRegistry {
+    /// The built-in definitions
+    pub fn builtin() -> Self {
+        let targets = BUILTIN
+            .iter()
+            .map(|(file, source)| {
+                let path = Path::new("targets").join(file);
+                TargetDefinition::parse(source, &path).unwrap_or_else(|e| panic!("{}", e))
+            })
+            .collect();
+        Self { targets }
+    }
+
+    /// Add every definition file in `dir`, replacing built-ins of the same
+    /// name; two files of `dir` may not define the same target
+    pub fn load_dir(&mut self, dir: &Path) -> Result<(), TargetError> {
+        let error = |message: String| TargetError { file: dir.display().to_string(), message };
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .map_err(|e| error(e.to_string()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(TARGET_EXTENSION)))
+            .collect();
+        paths.sort();
+
+        let mut loaded: Vec<TargetDefinition> = Vec::new();
+        for path in paths {
+            let source = fs::read_to_string(&path).map_err(|e| TargetError {
+                file: path.display().to_string(),
+                message: e.to_string(),
+            })?;
+            let mut definition = TargetDefinition::parse(&source, &path)?;
+            if let Some(first) = loaded.iter().find(|t| t.name.eq_ignore_ascii_case(&definition.name)) {
+                return Err(TargetError {
+                    file: path.display().to_string(),
+                    message: format!(
+                        "target '{}' is already defined in '{}'",
+                        definition.name,
+                        first.path.as_deref().unwrap_or(Path::new("")).display()
+                    ),
+                });
+            }
+            definition.path = Some(path);
+            loaded.push(definition);
+        }
+        for definition in loaded {
+            self.add(definition);
+        }
+        Ok(())
+    }
+
+    /// Add a definition, replacing any of the same name
+    pub fn add(&mut self, definition: TargetDefinition) {
+        match self.targets.iter_mut().find(|t| t.name.eq_ignore_ascii_case(&definition.name)) {
+            Some(existing) => *existing = definition,
+            None => self.targets.push(definition),
+        }
+    }
+
+    /// Look up a target by name (case-insensitive)
+    pub fn find(&self, name: &str) -> Option<&TargetDefinition> {
+        self.targets.iter().find(|t| t.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TargetDefinition> {
+        self.targets.iter()
+    }
+}
+
+fn parse_definition(source: &str, path: &Path) -> Result<TargetDefinition, ResourceError> {
+    let doc = toml::parse(source)?;
+    let context = match doc.get("name").and_then(Value::as_str) {
+        Some(name) => format!("target '{}'", name),
+        None => "target".to_string(),
+    };
+    let context = context.as_str();
+    check_keys(
+        &doc,
+        &["name", "description", "platform", "output", "startup", "symbols", "framebuffer_stride", "runtime_errors", "memory", "entry_points"],
+        context,
+    )?;
+
+    let name = required(string(&doc, "name", context)?, "name", context)?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(invalid(format!("{}: name may only contain letters, digits, '-' and '_'", context)));
+    }
+    let description = string(&doc, "description", context)?.unwrap_or_default();
+    let platform = required(string(&doc, "platform", context)?, "platform", context)?;
+    let platform = TargetPlatform::from_name(&platform).ok_or_else(|| {
+        let known: Vec<&str> = TargetPlatform::ALL.iter().map(|p| p.name()).collect();
+        invalid(format!("{}: unknown platform '{}', expected one of {}", context, platform, known.join(", ")))
+    })?;
+    let output = match string(&doc, "output", context)?.as_deref() {
+        None | Some("bin") => OutputFormat::Binary,
+        Some("com") => OutputFormat::Com,
+        Some("interpret") => OutputFormat::Interpreted,
+        Some(other) => {
+            return Err(invalid(format!(
+                "{}: output must be \"bin\", \"com\" or \"interpret\", found \"{}\"",
+                context, other
+            )));
+        }
+    };
+    let startup = match string(&doc, "startup", context)? {
+        Some(file) => {
+            let startup = path.parent().unwrap_or(Path::new("")).join(file);
+            if !startup.is_file() {
+                return Err(invalid(format!("{}: startup object '{}' does not exist", context, startup.display())));
+            }
+            Some(startup)
+        }
+        None => None,
+    };
+    let symbols = symbol_list(&doc, context)?;
+    let framebuffer_stride = integer(&doc, "framebuffer_stride", context, 1, 0xFFFF)?.map(|stride| stride as u16);
+    let runtime_errors = match string(&doc, "runtime_errors", context)? {
+        Some(text) => RuntimeErrorStrategy::parse(&text).ok_or_else(|| {
+            invalid(format!("{}: runtime_errors must be \"halt\", \"code\" or \"handler Name\", found \"{}\"", context, text))
+        })?,
+        None => RuntimeErrorStrategy::default(),
+    };
+
+    let mut memory_map: Vec<MemoryRegion> = Vec::new();
+    for table in tables(&doc, "memory", context)? {
+        let region = parse_region(table, context)?;
+        if memory_map.iter().any(|r| r.name.eq_ignore_ascii_case(&region.name)) {
+            return Err(invalid(format!("{}: memory region '{}' is defined twice", context, region.name)));
+        }
+        // ROM and RAM may be separate address spaces (as on the 8051)
+        if let Some(other) = memory_map
+            .iter()
+            .find(|r| r.kind == region.kind && r.start < region.start + region.size && region.start < r.start + r.size)
+        {
+            return Err(invalid(format!("{}: memory region '{}' overlaps '{}'", context, region.name, other.name)));
+        }
+        memory_map.push(region);
+    }
+
+    let mut entry_points = Vec::new();
+    if let Some(value) = doc.get("entry_points") {
+        let context = format!("{}, entry points", context);
+        let table = value
+            .as_table()
+            .ok_or_else(|| invalid(format!("{}: must be a table, found {}", context, value.type_name())))?;
+        for key in table.keys() {
+            check_symbol(key, "entry point", &context)?;
+            let address = integer(table, key, &context, 0, 0xFFFF)?.expect("key is in the table");
+            entry_points.push((key.clone(), address as u16));
+        }
+    }
+
+    Ok(TargetDefinition {
+        name,
+        description,
+        platform,
+        output,
+        startup,
+        symbols,
+        framebuffer_stride,
+        runtime_errors,
+        memory_map,
+        entry_points,
+        path: None,
+    })
+}
+
+fn parse_region(table: &Table, target: &str) -> Result<MemoryRegion, ResourceError> {
+    let context = match table.get("name").and_then(Value::as_str) {
+        Some(name) => format!("{}, memory region '{}'", target, name),
+        None => format!("{}, memory region", target),
+    };
+    check_keys(table, &["name", "kind", "start", "size"], &context)?;
+    let name = required(string(table, "name", &context)?, "name", &context)?;
+    let kind = match required(string(table, "kind", &context)?, "kind", &context)?.as_str() {
+        "ram" => MemoryKind::Ram,
+        "rom" => MemoryKind::Rom,
+        other => return Err(invalid(format!("{}: kind must be \"ram\" or \"rom\", found \"{}\"", context, other))),
+    };
+    let start = required(integer(table, "start", &context, 0, u32::MAX as i64)?, "start", &context)?;
+    let size = required(integer(table, "size", &context, 1, u32::MAX as i64)?, "size", &context)?;
+    if start + size > u32::MAX as i64 {
+        return Err(invalid(format!("{}: region ends beyond the address space", context)));
+    }
+    Ok(MemoryRegion::new(name, kind, start as u32, size as u32))
+}
+
+/// The `symbols` array
+fn symbol_list(doc: &Table, context: &str) -> Result<Vec<String>, ResourceError> {
+    let Some(value) = doc.get("symbols") else {
+        return Ok(vec![]);
+    };
+    let items = value
+        .as_array()
+        .and_then(|items| items.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| invalid(format!("{}: 'symbols' must be an array of strings", context)))?;
+    for symbol in &items {
+        check_symbol(symbol, "symbol", context)?;
+    }
+    Ok(items.into_iter().map(str::to_string).collect())
+}
+
+fn check_symbol(name: &str, what: &str, context: &str) -> Result<(), ResourceError> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid(format!("{}: {} '{}' is not an identifier", context, what, name)))
+    }
+}
+
+fn invalid(message: String) -> ResourceError {
+    ResourceError::InvalidFormat(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_definitions() {
+        let registry = TargetRegistry::builtin();
+        for platform in TargetPlatform::ALL {
+            let definition = registry.find(platform.name()).unwrap();
+            assert_eq!(definition.platform, platform);
+            assert!(definition.symbols.contains(&platform.name().to_uppercase()));
+        }
+
+        let spectrum = TargetDefinition::builtin(TargetPlatform::ZXSpectrum);
+        assert_eq!(spectrum.symbols, ["ZXSPECTRUM", "CPU_Z80"]);
+        assert_eq!(spectrum.framebuffer_stride, Some(32));
+        assert!(spectrum.entry_points.contains(&("ROM_CLS".to_string(), 0x0D6B)));
+        assert_eq!(spectrum.load_address(), Some(0x8000));
+        assert_eq!(spectrum.runtime_errors, RuntimeErrorStrategy::Halt);
+
+        let intel = TargetDefinition::builtin(TargetPlatform::Intel8051);
+        assert!(intel.has_rom());
+        assert_eq!(intel.ram_size(), Some(0x80));
+        let zeal = TargetDefinition::builtin(TargetPlatform::ZealZ80);
+        assert!(!zeal.has_rom());
+        assert_eq!(zeal.ram_size(), Some(0xC000));
+        let pi = TargetDefinition::builtin(TargetPlatform::RaspberryPi5);
+        assert_eq!((pi.ram_size(), pi.framebuffer_stride, pi.load_address()), (None, None, None));
+
+        let host = registry.find("host-test").unwrap();
+        assert_eq!((host.platform, host.output), (TargetPlatform::ZealZ80, OutputFormat::Interpreted));
+        assert_eq!(host.load_address(), None);
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let message = |source: &str| TargetDefinition::parse(source, Path::new("zx81.toml")).unwrap_err().to_string();
+        let base = "name = \"zx81\"\nplatform = \"zxspectrum\"\n";
+        assert_eq!(
+            message("platform = \"zxspectrum\""),
+            "Invalid target definition 'zx81.toml': target: missing 'name'"
+        );
+        assert_eq!(
+            message(&format!("{}memmory = []", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81': unknown key 'memmory'"
+        );
+        assert_eq!(
+            message("name = \"zx81\"\nplatform = \"zx81\""),
+            "Invalid target definition 'zx81.toml': target 'zx81': unknown platform 'zx81', expected one of \
+             zealz80, intel8051, commanderx16, foenix65c816, foenixa2560m, raspberrypi5, zxspectrum"
+        );
+        assert_eq!(
+            message(&format!("{}output = \"tap\"", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81': output must be \"bin\", \"com\" or \"interpret\", found \"tap\""
+        );
+        assert_eq!(
+            message(&format!("{}runtime_errors = \"ignore\"", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81': runtime_errors must be \"halt\", \"code\" or \"handler Name\", found \"ignore\""
+        );
+        assert_eq!(
+            message(&format!("{}symbols = [\"ZX-81\"]", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81': symbol 'ZX-81' is not an identifier"
+        );
+        assert_eq!(
+            message(&format!("{}[[memory]]\nname = \"RAM\"\nkind = \"ram\"\nstart = 0x4000\nsize = 0", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81', memory region 'RAM': 'size' is 0, outside 1..4294967295"
+        );
+        assert_eq!(
+            message(&format!(
+                "{}[[memory]]\nname = \"A\"\nkind = \"ram\"\nstart = 0x4000\nsize = 0x400\n\
+                 [[memory]]\nname = \"B\"\nkind = \"ram\"\nstart = 0x4200\nsize = 0x400",
+                base
+            )),
+            "Invalid target definition 'zx81.toml': target 'zx81': memory region 'B' overlaps 'A'"
+        );
+        assert_eq!(
+            message(&format!("{}[entry_points]\nPRINT = 0x10000", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81', entry points: 'PRINT' is 65536, outside 0..65535"
+        );
+        assert_eq!(
+            message(&format!("{}startup = \"crt0.zof\"", base)),
+            "Invalid target definition 'zx81.toml': target 'zx81': startup object 'crt0.zof' does not exist"
+        );
+        assert_eq!(
+            message("name = \"zx81\"\nname = \"zx82\""),
+            "Invalid target definition 'zx81.toml': line 2: key 'name' is defined twice"
+        );
+    }
+
+    #[test]
+    fn test_load_dir_adds_and_replaces_targets() {
+        let dir = std::env::temp_dir().join(format!("spc-targets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("crt0.zof"), []).unwrap();
+        fs::write(
+            dir.join("zx81.toml"),
+            "name = \"zx81\"\nplatform = \"zxspectrum\"\nstartup = \"crt0.zof\"\noutput = \"com\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("zeal.toml"), "name = \"ZealZ80\"\nplatform = \"zealz80\"\nsymbols = [\"ZEAL_V2\"]\n").unwrap();
+
+        let mut registry = TargetRegistry::builtin();
+        let result = registry.load_dir(&dir);
+        fs::write(dir.join("zx81-copy.toml"), "name = \"zx81\"\nplatform = \"zealz80\"\n").unwrap();
+        let duplicate = registry.clone().load_dir(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        let zx81 = registry.find("ZX81").unwrap();
+        assert_eq!(zx81.startup, Some(dir.join("crt0.zof")));
+        assert_eq!(zx81.load_address(), Some(COM_ORIGIN));
+        assert_eq!(zx81.path, Some(dir.join("zx81.toml")));
+        assert_eq!(registry.find("zealz80").unwrap().symbols, ["ZEAL_V2"]);
+        assert_eq!(registry.iter().count(), BUILTIN.len() + 1);
+        assert!(duplicate.unwrap_err().message.starts_with("target 'zx81' is already defined in"));
+    }
+}
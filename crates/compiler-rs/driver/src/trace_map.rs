@@ -0,0 +1,321 @@
+//! Emulator traces mapped to the source (`spc trace-map`)
+//!
+//! An emulator instruction trace lists the address of each instruction
+//! executed, one per line and in execution order: the address comes first,
+//! in hex (`$8000`, `0x8000`, `8000`, `PC=8000` or `8000:`), and anything
+//! after it (registers, disassembly) is ignored. As in a profile, an
+//! optional `origin <address>` line gives the address the program was
+//! loaded at, and `;` and `#` start comments.
+//!
+//! The line table of the program (where the code of each Pascal line, each
+//! routine and each call lies), compiled from its source or read from the
+//! debug info `build -g` wrote (see debug_info.rs), turns the trace into a
+//! histogram of the instructions executed on each line, and a call tree
+//! counting the calls of each routine from each caller and the
+//! instructions executed in them:
+//!
+//! ```text
+//! Trace: 6017 instructions, 6010 on Pascal lines
+//!
+//! Lines:
+//!   Line  Executed      %  Source
+//!      5      3000   49.9  ##########  Sum := Sum + I;
+//!
+//! Call tree:
+//!   main          1 call       6017 instructions
+//!     Fill      100 calls      5900 instructions
+//! ```
+//!
+//! A call is an executed `call` followed by anything but the instruction
+//! after it; it returns when that instruction is reached.
+
+use std::collections::{BTreeMap, HashSet};
+
+use backend_zealz80::{marked_position, CodeGenerator, Z80Instruction};
+
+use crate::profile::parse_address;
+
+/// Width of the histogram bar of the line executing the most instructions
+pub const BAR_WIDTH: usize = 10;
+
+/// Addresses executed, in order
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Trace {
+    pub origin: Option<u16>,
+    pub addresses: Vec<u16>,
+}
+
+impl Trace {
+    /// Parse a trace written by the emulator
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut trace = Trace::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap_or("").trim();
+            let mut fields = line.split_whitespace();
+            let Some(first) = fields.next() else {
+                continue;
+            };
+            if first.eq_ignore_ascii_case("origin") {
+                let address = fields.next().unwrap_or("");
+                trace.origin = Some(
+                    parse_address(address)
+                        .ok_or_else(|| format!("line {}: invalid origin '{}'", number + 1, address))?,
+                );
+                continue;
+            }
+            let address = first
+                .strip_prefix("PC=")
+                .or_else(|| first.strip_prefix("pc="))
+                .unwrap_or(first)
+                .trim_end_matches(':');
+            let address = parse_address(address)
+                .ok_or_else(|| format!("line {}: expected an instruction address, found '{}'", number + 1, line))?;
+            trace.addresses.push(address);
+        }
+        Ok(trace)
+    }
+}
+
+/// Code of the statement starting at a source line and column: `size`
+/// bytes from `start`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: u16,
+    pub size: u16,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where the code of each line, routine and call of a program lies
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineTable {
+    /// Code generated for statements, in address order
+    pub lines: Vec<LineRange>,
+    /// Start of each routine, in address order
+    pub routines: Vec<(String, u16)>,
+    /// Address of each call, and of the instruction after it
+    pub calls: BTreeMap<u16, u16>,
+}
+
+impl LineTable {
+    /// The table of a listing loaded at `origin`, whose code for each line
+    /// is preceded by a line marker (see [`CodeGenerator::set_line_markers`]).
+    /// `routines` are the labels starting a routine.
+    pub fn new(instructions: &[Z80Instruction], codegen: &CodeGenerator, routines: &HashSet<String>, origin: u16) -> Self {
+        let mut table = LineTable::default();
+        let mut address = origin;
+        let mut position = None;
+        for inst in instructions {
+            if let Some(marked) = marked_position(inst) {
+                position = Some(marked);
+                continue;
+            }
+            match inst {
+                Z80Instruction::Label { name } if routines.contains(name) => {
+                    table.routines.push((name.clone(), address));
+                    // A routine's entry belongs to no line until one is marked
+                    position = None;
+                }
+                Z80Instruction::Call { .. } => {
+                    table.calls.insert(address, address.wrapping_add(codegen.instruction_size(inst) as u16));
+                }
+                _ => {}
+            }
+            let size = codegen.instruction_size(inst) as u16;
+            if size == 0 {
+                continue;
+            }
+            if let Some((line, column)) = position {
+                match table.lines.last_mut() {
+                    Some(last)
+                        if (last.line, last.column) == (line, column) && last.start.wrapping_add(last.size) == address =>
+                    {
+                        last.size += size
+                    }
+                    _ => table.lines.push(LineRange { start: address, size, line, column }),
+                }
+            }
+            address = address.wrapping_add(size);
+        }
+        table
+    }
+
+    /// The source line whose code holds `address`
+    pub fn line_at(&self, address: u16) -> Option<usize> {
+        let index = self.lines.partition_point(|range| range.start <= address).checked_sub(1)?;
+        let range = &self.lines[index];
+        (address - range.start < range.size).then_some(range.line)
+    }
+
+    /// The routine starting at `address`
+    pub fn routine_at(&self, address: u16) -> Option<&str> {
+        self.routines.iter().find(|(_, start)| *start == address).map(|(name, _)| name.as_str())
+    }
+
+    /// The routine whose code holds `address`: the last starting before it
+    pub fn routine_containing(&self, address: u16) -> Option<&str> {
+        self.routines.iter().rev().find(|(_, start)| *start <= address).map(|(name, _)| name.as_str())
+    }
+}
+
+/// Calls of a routine from one caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallNode {
+    pub name: String,
+    pub calls: u64,
+    /// Instructions executed in the routine and the routines it calls
+    pub instructions: u64,
+    pub children: Vec<CallNode>,
+}
+
+impl CallNode {
+    fn new(name: String) -> Self {
+        CallNode { name, calls: 0, instructions: 0, children: vec![] }
+    }
+}
+
+/// A trace mapped to the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceMap {
+    /// Instructions executed
+    pub total: u64,
+    /// Instructions executed on each source line
+    pub lines: BTreeMap<usize, u64>,
+    /// Routine executing first, and those it called
+    pub calls: CallNode,
+}
+
+impl TraceMap {
+    /// Map `trace` with `table`
+    pub fn new(trace: &Trace, table: &LineTable) -> Self {
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        let first = trace.addresses.first().and_then(|&address| table.routine_containing(address));
+        let mut root = CallNode::new(first.unwrap_or("(trace)").to_string());
+        root.calls = 1;
+        // Path of child indexes from the root, with the return address of each call
+        let mut stack: Vec<(usize, u16)> = Vec::new();
+        let mut returning_to = None;
+        for &address in &trace.addresses {
+            match returning_to.take() {
+                Some(next) if address != next => {
+                    let name = table.routine_at(address).map_or_else(|| format!("${:04X}", address), str::to_string);
+                    let caller = node_at(&mut root, &stack);
+                    let index = match caller.children.iter().position(|child| child.name == name) {
+                        Some(index) => index,
+                        None => {
+                            caller.children.push(CallNode::new(name));
+                            caller.children.len() - 1
+                        }
+                    };
+                    caller.children[index].calls += 1;
+                    stack.push((index, next));
+                }
+                _ => {
+                    while let Some(&(_, next)) = stack.last()
+                        && next == address
+                    {
+                        stack.pop();
+                    }
+                }
+            }
+            let mut node = &mut root;
+            node.instructions += 1;
+            for &(index, _) in &stack {
+                node = &mut node.children[index];
+                node.instructions += 1;
+            }
+            if let Some(line) = table.line_at(address) {
+                *lines.entry(line).or_default() += 1;
+            }
+            returning_to = table.calls.get(&address).copied();
+        }
+        TraceMap { total: trace.addresses.len() as u64, lines, calls: root }
+    }
+
+    /// The histogram of the lines of `source`, then the call tree
+    pub fn report(&self, source: &str) -> Vec<String> {
+        let source: Vec<&str> = source.lines().collect();
+        let on_lines: u64 = self.lines.values().sum();
+        let mut report = vec![format!("Trace: {} instructions, {} on Pascal lines", self.total, on_lines)];
+
+        report.push(String::new());
+        report.push("Lines:".to_string());
+        report.push(format!("  {:>4}  {:>8}  {:>5}  Source", "Line", "Executed", "%"));
+        let most = self.lines.values().copied().max().unwrap_or(0);
+        for (&line, &executed) in &self.lines {
+            let share = if self.total == 0 { 0.0 } else { executed as f64 * 100.0 / self.total as f64 };
+            let bar = "#".repeat(((executed * BAR_WIDTH as u64).div_ceil(most.max(1))) as usize);
+            let text = source.get(line.wrapping_sub(1)).map_or("", |text| text.trim());
+            report.push(format!("  {:>4}  {:>8}  {:>5.1}  {:<BAR_WIDTH$}  {}", line, executed, share, bar, text));
+        }
+
+        report.push(String::new());
+        report.push("Call tree:".to_string());
+        let width = name_width(&self.calls, 0);
+        call_lines(&self.calls, 0, width, &mut report);
+        report
+    }
+}
+
+/// The node reached by following `path` from `root`
+fn node_at<'a>(root: &'a mut CallNode, path: &[(usize, u16)]) -> &'a mut CallNode {
+    path.iter().fold(root, |node, &(index, _)| &mut node.children[index])
+}
+
+/// Width of the indented names of `node` and those below it
+fn name_width(node: &CallNode, depth: usize) -> usize {
+    node.children.iter().map(|child| name_width(child, depth + 1)).fold(depth * 2 + node.name.len(), usize::max)
+}
+
+fn call_lines(node: &CallNode, depth: usize, width: usize, report: &mut Vec<String>) {
+    let name = format!("{}{}", "  ".repeat(depth), node.name);
+    let calls = if node.calls == 1 { "call " } else { "calls" };
+    report.push(format!("  {:<width$}  {:>6} {}  {:>10} instructions", name, node.calls, calls, node.instructions));
+    for child in &node.children {
+        call_lines(child, depth + 1, width, report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend_zealz80::{Z80Register, LINE_MARKER};
+
+    #[test]
+    fn test_trace_maps_to_lines_and_calls() {
+        let marker = |line: usize| Z80Instruction::Comment { text: format!("{}{}:3", LINE_MARKER, line) };
+        let instructions = [
+            Z80Instruction::Label { name: "main".to_string() },
+            marker(3),
+            Z80Instruction::Call { label: "fill".to_string() },
+            marker(4),
+            Z80Instruction::Return,
+            Z80Instruction::Label { name: "fill".to_string() },
+            marker(8),
+            Z80Instruction::Increment { reg: Z80Register::HL },
+            Z80Instruction::Return,
+        ];
+        let routines: HashSet<String> = ["main".to_string(), "fill".to_string()].into();
+        let table = LineTable::new(&instructions, &CodeGenerator::new(), &routines, 0x8000);
+        assert_eq!(table.routines, [("main".to_string(), 0x8000), ("fill".to_string(), 0x8004)]);
+        assert_eq!(table.calls.get(&0x8000), Some(&0x8003));
+        assert_eq!(table.line_at(0x8005), Some(8));
+        assert_eq!(table.line_at(0x8006), None);
+
+        let trace = Trace::parse("origin $8000\nPC=8000 call fill\n$8004: inc hl\n8005 ; ret\n0x8003\n").unwrap();
+        assert_eq!(trace.addresses, [0x8000, 0x8004, 0x8005, 0x8003]);
+        assert!(Trace::parse("ld a,5").unwrap_err().starts_with("line 1:"));
+
+        let map = TraceMap::new(&trace, &table);
+        assert_eq!(map.lines, BTreeMap::from([(3, 1), (4, 1), (8, 2)]));
+        assert_eq!((map.calls.name.as_str(), map.calls.instructions), ("main", 4));
+        assert_eq!((map.calls.children[0].name.as_str(), map.calls.children[0].calls), ("fill", 1));
+        assert_eq!(map.calls.children[0].instructions, 2);
+
+        let report = map.report("program P;\nbegin\n  Fill;\nend.\n\n\n\nprocedure Fill;");
+        assert_eq!(report[0], "Trace: 4 instructions, 4 on Pascal lines");
+        assert_eq!(report[4], "     3         1   25.0  #####       Fill;");
+        assert_eq!(report[9], "  main         1 call            4 instructions");
+        assert_eq!(report[10], "    fill       1 call            2 instructions");
+    }
+}
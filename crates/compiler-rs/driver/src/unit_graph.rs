@@ -0,0 +1,689 @@
+//! Unit dependency graph (`spc graph-units`, `spc build-all`)
+//!
+//! Starting from the files given, each program and unit is parsed and its
+//! `uses` clauses followed to the sources of the units they name: `Name.pas`
+//! (or `name.pas`) beside the file using it, else in the current
+//! directory. A unit with no source, such as one only compiled to `.spu`,
+//! is a node of its own that is not followed.
+//!
+//! Edges are interface or implementation uses; a program's uses, which
+//! nothing else sees, count as implementation uses. Units on a cycle are
+//! found as the strongly connected components of the graph. A cycle
+//! through interface uses alone cannot be compiled; one broken by an
+//! implementation use can.
+//!
+//! `spc build-all` compiles the sources of the graph in dependency order,
+//! so the compiled unit (.spu) of every unit exists before anything using
+//! it is compiled. Each unit is compiled whole, so no cycle can be built
+//! that way; one is reported with its path, such as `Sprites -> Screen ->
+//! Sprites`. With `--jobs N` units that do not use each other are compiled
+//! on up to N threads at once, each taken from a [`BuildQueue`] as soon as
+//! the units it uses are built.
+//!
+//! A unit that fails to compile does not stop the build: only the units
+//! using it, directly or not, are skipped. The build ends with a
+//! [`BuildReport`], one row per source with its outcome and warnings:
+//!
+//! ```text
+//! Unit     Source           Result                  Warnings
+//! Screen   src/Screen.pas   ok                             0
+//! Sprites  src/Sprites.pas  failed                         2
+//! Sound    src/Sound.pas    ok                             1
+//! Game     src/Game.pas     skipped (uses Sprites)         -
+//!
+//! 2 built, 1 failed, 1 skipped, 3 warning(s)
+//! ```
+//!
+//! The graph is written as Graphviz DOT, implementation edges dashed and
+//! cycles red, or as JSON:
+//!
+//! ```text
+//! digraph units {
+//!   "Game" [shape=box];
+//!   "Sprites";
+//!   "Game" -> "Sprites" [style=dashed];
+//!   "Sprites" -> "Screen" [color=red];
+//!   "Screen" -> "Sprites" [style=dashed, color=red];
+//! }
+//! ```
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use ast::Node;
+
+/// Which `uses` clause an edge comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsesSection {
+    Interface,
+    Implementation,
+}
+
+impl UsesSection {
+    fn name(self) -> &'static str {
+        match self {
+            UsesSection::Interface => "interface",
+            UsesSection::Implementation => "implementation",
+        }
+    }
+}
+
+/// A program or unit of the graph
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitNode {
+    pub name: String,
+    /// Its source, when one was found
+    pub path: Option<PathBuf>,
+    pub is_program: bool,
+    /// Whether it is on a dependency cycle
+    pub in_cycle: bool,
+}
+
+/// A use of the unit `to` by `from`, indices into [`UnitGraph::units`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub from: usize,
+    pub to: usize,
+    pub section: UsesSection,
+    /// Whether both ends are on the same cycle
+    pub in_cycle: bool,
+}
+
+/// The names a program or unit uses, by section
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitUses {
+    pub name: String,
+    pub is_program: bool,
+    pub uses: Vec<(String, UsesSection)>,
+}
+
+impl UnitUses {
+    /// The uses clauses of a parsed program or unit
+    pub fn of(ast: &Node) -> Option<Self> {
+        match ast {
+            Node::Program(program) => Some(UnitUses {
+                name: program.name.clone(),
+                is_program: true,
+                uses: names(program.uses.as_ref(), UsesSection::Implementation).collect(),
+            }),
+            Node::Unit(unit) => Some(UnitUses {
+                name: unit.name.clone(),
+                is_program: false,
+                uses: names(unit.interface.as_ref().and_then(|i| i.uses.as_ref()), UsesSection::Interface)
+                    .chain(names(unit.implementation.as_ref().and_then(|i| i.uses.as_ref()), UsesSection::Implementation))
+                    .collect(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Units and the uses between them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitGraph {
+    pub units: Vec<UnitNode>,
+    pub dependencies: Vec<Dependency>,
+}
+
+impl UnitGraph {
+    /// The graph of `files` and the units they use, read with `read`
+    pub fn build(
+        files: &[PathBuf],
+        mut read: impl FnMut(&Path) -> Result<UnitUses, String>,
+    ) -> Result<Self, String> {
+        let mut graph = UnitGraph::default();
+        let mut pending: Vec<PathBuf> = files.iter().rev().cloned().collect();
+        let mut read_files: Vec<PathBuf> = vec![];
+        let mut uses_of: Vec<(usize, UnitUses, PathBuf)> = vec![];
+        while let Some(path) = pending.pop() {
+            if read_files.contains(&path) {
+                continue;
+            }
+            read_files.push(path.clone());
+            let uses = read(&path)?;
+            let index = graph.unit(&uses.name);
+            graph.units[index].path = Some(path.clone());
+            graph.units[index].is_program = uses.is_program;
+            for (name, _) in &uses.uses {
+                if let Some(source) = unit_source(name, &path) {
+                    pending.push(source);
+                }
+            }
+            uses_of.push((index, uses, path));
+        }
+        uses_of.sort_by_key(|(index, ..)| *index);
+        for (from, uses, _) in uses_of {
+            for (name, section) in uses.uses {
+                let to = graph.unit(&name);
+                graph.dependencies.push(Dependency { from, to, section, in_cycle: false });
+            }
+        }
+        graph.mark_cycles();
+        Ok(graph)
+    }
+
+    /// Index of the unit `name`, added if it is new
+    fn unit(&mut self, name: &str) -> usize {
+        if let Some(index) = self.units.iter().position(|unit| unit.name.eq_ignore_ascii_case(name)) {
+            return index;
+        }
+        self.units.push(UnitNode { name: name.to_string(), path: None, is_program: false, in_cycle: false });
+        self.units.len() - 1
+    }
+
+    /// Mark the units and uses on cycles: the strongly connected components
+    /// of more than one unit, or of one using itself (Tarjan's algorithm)
+    fn mark_cycles(&mut self) {
+        let count = self.units.len();
+        let mut successors = vec![vec![]; count];
+        for dependency in &self.dependencies {
+            successors[dependency.from].push(dependency.to);
+        }
+        let mut index = vec![usize::MAX; count];
+        let mut low = vec![0; count];
+        let mut on_stack = vec![false; count];
+        let mut stack = vec![];
+        let mut component = vec![usize::MAX; count];
+        let mut next = 0;
+        let mut components = 0;
+        for root in 0..count {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            // (unit, next successor to visit)
+            let mut work = vec![(root, 0)];
+            while let Some(&(unit, edge)) = work.last() {
+                if edge == 0 && index[unit] == usize::MAX {
+                    index[unit] = next;
+                    low[unit] = next;
+                    next += 1;
+                    stack.push(unit);
+                    on_stack[unit] = true;
+                }
+                if let Some(&successor) = successors[unit].get(edge) {
+                    work.last_mut().unwrap().1 += 1;
+                    if index[successor] == usize::MAX {
+                        work.push((successor, 0));
+                    } else if on_stack[successor] {
+                        low[unit] = low[unit].min(index[successor]);
+                    }
+                    continue;
+                }
+                work.pop();
+                if let Some(&(parent, _)) = work.last() {
+                    low[parent] = low[parent].min(low[unit]);
+                }
+                if low[unit] == index[unit] {
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component[member] = components;
+                        if member == unit {
+                            break;
+                        }
+                    }
+                    components += 1;
+                }
+            }
+        }
+
+        let mut sizes = vec![0; components];
+        for unit in 0..count {
+            sizes[component[unit]] += 1;
+        }
+        for dependency in &mut self.dependencies {
+            dependency.in_cycle = component[dependency.from] == component[dependency.to]
+                && (sizes[component[dependency.from]] > 1 || dependency.from == dependency.to);
+        }
+        for dependency in &self.dependencies {
+            if dependency.in_cycle {
+                self.units[dependency.from].in_cycle = true;
+            }
+        }
+    }
+
+    /// A cycle of uses, from a unit back to itself, if there is one
+    pub fn cycle(&self) -> Option<Vec<usize>> {
+        let start = self.units.iter().position(|unit| unit.in_cycle)?;
+        // Uses on a cycle stay within its strongly connected component, so
+        // following them must come back to a unit already passed
+        let mut path = vec![start];
+        loop {
+            let unit = *path.last().unwrap();
+            let next = self.dependencies.iter().find(|d| d.in_cycle && d.from == unit)?.to;
+            if let Some(first) = path.iter().position(|&passed| passed == next) {
+                let mut cycle = path.split_off(first);
+                cycle.push(next);
+                return Some(cycle);
+            }
+            path.push(next);
+        }
+    }
+
+    /// The units with sources, each after the units it uses; None if there
+    /// is a cycle
+    pub fn build_order(&self) -> Option<Vec<usize>> {
+        let mut unbuilt_uses = vec![0; self.units.len()];
+        for dependency in &self.dependencies {
+            unbuilt_uses[dependency.from] += 1;
+        }
+        let mut order = vec![];
+        let mut ready: Vec<usize> = (0..self.units.len()).rev().filter(|&unit| unbuilt_uses[unit] == 0).collect();
+        while let Some(unit) = ready.pop() {
+            order.push(unit);
+            let mut users: Vec<usize> = vec![];
+            for dependency in self.dependencies.iter().filter(|d| d.to == unit) {
+                unbuilt_uses[dependency.from] -= 1;
+                if unbuilt_uses[dependency.from] == 0 {
+                    users.push(dependency.from);
+                }
+            }
+            // Build in the order the units were found when there is a choice
+            ready.extend(users);
+            ready.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        if order.len() < self.units.len() {
+            return None;
+        }
+        order.retain(|&unit| self.units[unit].path.is_some());
+        Some(order)
+    }
+
+    /// The names of `units`, joined by arrows
+    pub fn path_text(&self, units: &[usize]) -> String {
+        units.iter().map(|&unit| self.units[unit].name.as_str()).collect::<Vec<_>>().join(" -> ")
+    }
+
+    /// Graphviz DOT of the graph
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph units {\n");
+        for unit in &self.units {
+            let mut attributes = vec![];
+            if unit.is_program {
+                attributes.push("shape=box");
+            }
+            if unit.path.is_none() {
+                attributes.push("style=dotted");
+            }
+            if unit.in_cycle {
+                attributes.push("color=red");
+            }
+            dot.push_str(&format!("  {}{};\n", quoted(&unit.name), bracketed(&attributes)));
+        }
+        for dependency in &self.dependencies {
+            let mut attributes = vec![];
+            if dependency.section == UsesSection::Implementation {
+                attributes.push("style=dashed");
+            }
+            if dependency.in_cycle {
+                attributes.push("color=red");
+            }
+            dot.push_str(&format!(
+                "  {} -> {}{};\n",
+                quoted(&self.units[dependency.from].name),
+                quoted(&self.units[dependency.to].name),
+                bracketed(&attributes)
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// JSON of the graph: `units` and `dependencies` arrays
+    pub fn to_json(&self) -> String {
+        let units: Vec<String> = self
+            .units
+            .iter()
+            .map(|unit| {
+                let path = unit.path.as_ref().map_or("null".to_string(), |path| quoted(&path.display().to_string()));
+                format!(
+                    "    {{\"name\": {}, \"path\": {}, \"kind\": \"{}\", \"cycle\": {}}}",
+                    quoted(&unit.name),
+                    path,
+                    if unit.is_program { "program" } else { "unit" },
+                    unit.in_cycle
+                )
+            })
+            .collect();
+        let dependencies: Vec<String> = self
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                format!(
+                    "    {{\"from\": {}, \"to\": {}, \"section\": \"{}\", \"cycle\": {}}}",
+                    quoted(&self.units[dependency.from].name),
+                    quoted(&self.units[dependency.to].name),
+                    dependency.section.name(),
+                    dependency.in_cycle
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"units\": [\n{}\n  ],\n  \"dependencies\": [\n{}\n  ]\n}}\n",
+            units.join(",\n"),
+            dependencies.join(",\n")
+        )
+    }
+}
+
+/// Units of a build order waiting to be compiled, each released once the
+/// units it uses are built
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildQueue {
+    order: Vec<usize>,
+    /// Per position in the order: how many units it uses are not built yet,
+    /// and the positions of the units using it
+    unbuilt_uses: Vec<usize>,
+    users: Vec<Vec<usize>>,
+    /// Positions whose uses are all built, not yet taken
+    ready: BTreeSet<usize>,
+    unbuilt: usize,
+}
+
+impl BuildQueue {
+    /// The queue of `order`, a [`UnitGraph::build_order`] of `graph`
+    pub fn new(graph: &UnitGraph, order: &[usize]) -> Self {
+        let position = |unit: usize| order.iter().position(|&built| built == unit);
+        let mut unbuilt_uses = vec![0; order.len()];
+        let mut users: Vec<Vec<usize>> = vec![vec![]; order.len()];
+        for dependency in &graph.dependencies {
+            // Units without a source are not built here
+            if let (Some(from), Some(to)) = (position(dependency.from), position(dependency.to))
+                && !users[to].contains(&from)
+            {
+                users[to].push(from);
+                unbuilt_uses[from] += 1;
+            }
+        }
+        let ready = (0..order.len()).filter(|&position| unbuilt_uses[position] == 0).collect();
+        BuildQueue { order: order.to_vec(), unbuilt_uses, users, ready, unbuilt: order.len() }
+    }
+
+    /// Take the earliest unit in the order whose uses are all built
+    pub fn next(&mut self) -> Option<usize> {
+        let position = self.ready.pop_first()?;
+        Some(self.order[position])
+    }
+
+    /// Record that the taken `unit` is built, releasing the units using it
+    pub fn built(&mut self, unit: usize) {
+        let Some(position) = self.order.iter().position(|&built| built == unit) else {
+            return;
+        };
+        self.unbuilt -= 1;
+        for &user in &self.users[position] {
+            self.unbuilt_uses[user] -= 1;
+            if self.unbuilt_uses[user] == 0 {
+                self.ready.insert(user);
+            }
+        }
+    }
+
+    /// Record that the taken `unit` failed, giving up on the units using
+    /// it, directly or not, which are returned in the order
+    pub fn failed(&mut self, unit: usize) -> Vec<usize> {
+        let Some(position) = self.order.iter().position(|&built| built == unit) else {
+            return vec![];
+        };
+        self.unbuilt -= 1;
+        let mut skipped = BTreeSet::new();
+        let mut pending = self.users[position].clone();
+        while let Some(user) = pending.pop() {
+            // Users skipped by an earlier failure are already counted
+            if self.unbuilt_uses[user] != usize::MAX && skipped.insert(user) {
+                pending.extend(&self.users[user]);
+            }
+        }
+        // Neither ready nor waiting on another use: a user is released
+        // only by the last of its uses
+        self.unbuilt -= skipped.len();
+        for &user in &skipped {
+            self.unbuilt_uses[user] = usize::MAX;
+        }
+        skipped.into_iter().map(|position| self.order[position]).collect()
+    }
+
+    /// Whether every unit is built or given up on
+    pub fn is_finished(&self) -> bool {
+        self.unbuilt == 0
+    }
+}
+
+/// How compiling one source of a build ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildOutcome {
+    Built { warnings: usize },
+    Failed { warnings: usize },
+    /// Not compiled, since the named unit it uses failed
+    Skipped { uses: String },
+}
+
+/// The outcome of each source of a `spc build-all`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildReport {
+    /// Unit name, source and outcome, in the order they ended
+    pub rows: Vec<(String, String, BuildOutcome)>,
+}
+
+impl BuildReport {
+    pub fn add(&mut self, name: &str, source: &str, outcome: BuildOutcome) {
+        self.rows.push((name.to_string(), source.to_string(), outcome));
+    }
+
+    pub fn failures(&self) -> usize {
+        self.rows.iter().filter(|(_, _, outcome)| matches!(outcome, BuildOutcome::Failed { .. })).count()
+    }
+
+    /// The rows as aligned columns and a line of totals
+    pub fn to_table(&self) -> String {
+        let cells: Vec<[String; 4]> = self
+            .rows
+            .iter()
+            .map(|(name, source, outcome)| {
+                let (result, warnings) = match outcome {
+                    BuildOutcome::Built { warnings } => ("ok".to_string(), warnings.to_string()),
+                    BuildOutcome::Failed { warnings } => ("failed".to_string(), warnings.to_string()),
+                    BuildOutcome::Skipped { uses } => (format!("skipped (uses {})", uses), "-".to_string()),
+                };
+                [name.clone(), source.clone(), result, warnings]
+            })
+            .collect();
+        let header = ["Unit", "Source", "Result", "Warnings"].map(String::from);
+        let mut widths = header.clone().map(|cell| cell.chars().count());
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let mut table = String::new();
+        for row in std::iter::once(&header).chain(&cells) {
+            table.push_str(&format!(
+                "{:w0$}  {:w1$}  {:w2$}  {:>w3$}\n",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            ));
+        }
+
+        let count = |built: fn(&BuildOutcome) -> bool| self.rows.iter().filter(|(_, _, outcome)| built(outcome)).count();
+        let warnings: usize = self
+            .rows
+            .iter()
+            .map(|(_, _, outcome)| match outcome {
+                BuildOutcome::Built { warnings } | BuildOutcome::Failed { warnings } => *warnings,
+                BuildOutcome::Skipped { .. } => 0,
+            })
+            .sum();
+        table.push_str(&format!(
+            "\n{} built, {} failed, {} skipped, {} warning(s)\n",
+            count(|outcome| matches!(outcome, BuildOutcome::Built { .. })),
+            self.failures(),
+            count(|outcome| matches!(outcome, BuildOutcome::Skipped { .. })),
+            warnings
+        ));
+        table
+    }
+}
+
+/// The names of a uses clause, if there is one, each used in `section`
+fn names(clause: Option<&ast::UsesClause>, section: UsesSection) -> impl Iterator<Item = (String, UsesSection)> + '_ {
+    clause.into_iter().flat_map(move |clause| clause.units.iter().map(move |name| (name.clone(), section)))
+}
+
+/// Source of the unit `name` used by the file `user`
+fn unit_source(name: &str, user: &Path) -> Option<PathBuf> {
+    let dirs = [user.parent().unwrap_or(Path::new("")), Path::new("")];
+    let files = [format!("{}.pas", name), format!("{}.pas", name.to_lowercase())];
+    dirs.iter().flat_map(|dir| files.iter().map(move |file| dir.join(file))).find(|path| path.is_file())
+}
+
+/// `text` as a double-quoted string, as both DOT and JSON write them
+fn quoted(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn bracketed(attributes: &[&str]) -> String {
+    if attributes.is_empty() { String::new() } else { format!(" [{}]", attributes.join(", ")) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uses(name: &str, is_program: bool, uses: &[(&str, UsesSection)]) -> UnitUses {
+        UnitUses {
+            name: name.to_string(),
+            is_program,
+            uses: uses.iter().map(|(name, section)| (name.to_string(), *section)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_cycles_are_marked() {
+        use UsesSection::*;
+        let dir = std::env::temp_dir().join(format!("spc_unit_graph_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sources = [
+            ("Game", uses("Game", true, &[("Sprites", Implementation), ("Crt", Implementation)])),
+            ("Sprites", uses("Sprites", false, &[("Screen", Interface)])),
+            ("Screen", uses("Screen", false, &[("Sprites", Implementation)])),
+        ];
+        for (name, _) in &sources {
+            std::fs::write(dir.join(format!("{}.pas", name)), "").unwrap();
+        }
+        let graph = UnitGraph::build(&[dir.join("Game.pas")], |path| {
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            Ok(sources.iter().find(|(name, _)| *name == stem).unwrap().1.clone())
+        })
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = graph.units.iter().map(|unit| (unit.name.as_str(), unit.in_cycle)).collect();
+        assert_eq!(names, [("Game", false), ("Sprites", true), ("Screen", true), ("Crt", false)]);
+        assert!(graph.units[3].path.is_none());
+        let dot = graph.to_dot();
+        assert!(dot.contains("  \"Game\" [shape=box];\n"));
+        assert!(dot.contains("  \"Crt\" [style=dotted];\n"));
+        assert!(dot.contains("  \"Game\" -> \"Sprites\" [style=dashed];\n"));
+        assert!(dot.contains("  \"Sprites\" -> \"Screen\" [color=red];\n"));
+        assert!(dot.contains("  \"Screen\" -> \"Sprites\" [style=dashed, color=red];\n"));
+        assert!(graph
+            .to_json()
+            .contains("{\"from\": \"Sprites\", \"to\": \"Screen\", \"section\": \"interface\", \"cycle\": true}"));
+        assert_eq!(graph.path_text(&graph.cycle().unwrap()), "Sprites -> Screen -> Sprites");
+        assert_eq!(graph.build_order(), None);
+    }
+
+    #[test]
+    fn test_build_order_puts_units_before_their_users() {
+        use UsesSection::*;
+        let dir = std::env::temp_dir().join(format!("spc_unit_order_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sources = [
+            ("Game", uses("Game", true, &[("Sprites", Implementation), ("Sound", Implementation)])),
+            ("Sprites", uses("Sprites", false, &[("Screen", Interface), ("Crt", Implementation)])),
+            ("Sound", uses("Sound", false, &[])),
+            ("Screen", uses("Screen", false, &[])),
+        ];
+        for (name, _) in &sources {
+            std::fs::write(dir.join(format!("{}.pas", name)), "").unwrap();
+        }
+        let graph = UnitGraph::build(&[dir.join("Game.pas")], |path| {
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            Ok(sources.iter().find(|(name, _)| *name == stem).unwrap().1.clone())
+        })
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(graph.cycle(), None);
+        let order = graph.build_order().unwrap();
+        // Crt has no source, so it is not built
+        assert_eq!(graph.path_text(&order), "Sound -> Screen -> Sprites -> Game");
+
+        // Sound and Screen can be built at once; Game waits for both
+        let mut queue = BuildQueue::new(&graph, &order);
+        let (sound, screen) = (queue.next().unwrap(), queue.next().unwrap());
+        assert_eq!(graph.path_text(&[sound, screen]), "Sound -> Screen");
+        assert_eq!(queue.next(), None);
+        queue.built(screen);
+        let sprites = queue.next().unwrap();
+        queue.built(sprites);
+        assert_eq!(queue.next(), None);
+        queue.built(sound);
+        let game = queue.next().unwrap();
+        assert_eq!(graph.units[game].name, "Game");
+        assert!(!queue.is_finished());
+        queue.built(game);
+        assert!(queue.is_finished());
+
+        // A failed Screen skips Sprites and Game, which uses it; Sound is
+        // still built
+        let mut queue = BuildQueue::new(&graph, &order);
+        let (sound, screen) = (queue.next().unwrap(), queue.next().unwrap());
+        let skipped = queue.failed(screen);
+        assert_eq!(graph.path_text(&skipped), "Sprites -> Game");
+        assert!(!queue.is_finished());
+        assert_eq!(queue.next(), None);
+        queue.built(sound);
+        assert_eq!(queue.next(), None);
+        assert!(queue.is_finished());
+    }
+
+    #[test]
+    fn test_build_report_table() {
+        let mut report = BuildReport::default();
+        report.add("Screen", "src/Screen.pas", BuildOutcome::Built { warnings: 0 });
+        report.add("Sprites", "src/Sprites.pas", BuildOutcome::Failed { warnings: 2 });
+        report.add("Sound", "src/Sound.pas", BuildOutcome::Built { warnings: 1 });
+        report.add("Game", "src/Game.pas", BuildOutcome::Skipped { uses: "Sprites".to_string() });
+        assert_eq!(report.failures(), 1);
+        assert_eq!(
+            report.to_table(),
+            "Unit     Source           Result                  Warnings
+Screen   src/Screen.pas   ok                             0
+Sprites  src/Sprites.pas  failed                         2
+Sound    src/Sound.pas    ok                             1
+Game     src/Game.pas     skipped (uses Sprites)         -
+
+2 built, 1 failed, 1 skipped, 3 warning(s)
+"
+        );
+    }
+}
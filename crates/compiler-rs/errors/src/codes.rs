@@ -0,0 +1,174 @@
+//! Error code registry
+//!
+//! Each entry gives a class of diagnostics a stable code, recognised by the
+//! wording of the message, with what a learner needs to fix it: the rule of
+//! the language that was broken, a minimal program that follows it, and
+//! where the language specification describes it. `spc --teach` appends
+//! these to the diagnostics it prints (see [`crate::Diagnostic::format_teaching`]).
+
+/// A class of diagnostics and its explanation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    /// Stable code, e.g. `SP0001`
+    pub code: &'static str,
+    /// Fragments of the message; a message containing any of them has
+    /// this code
+    pub patterns: &'static [&'static str],
+    /// Short name of the rule
+    pub title: &'static str,
+    /// The rule in plain language
+    pub explanation: &'static str,
+    /// A minimal correct program or fragment
+    pub example: &'static str,
+    /// Section of the language specification describing the rule
+    pub reference: &'static str,
+}
+
+impl ErrorCode {
+    /// Whether a diagnostic with `message` is of this class
+    pub fn matches(&self, message: &str) -> bool {
+        self.patterns.iter().any(|pattern| message.contains(pattern))
+    }
+}
+
+/// All error codes. More specific patterns come first: a message is given
+/// the first code it matches
+pub const REGISTRY: &[ErrorCode] = &[
+    ErrorCode {
+        code: "SP0001",
+        patterns: &["Syntax error,", "Unexpected end of file", "Expected "],
+        title: "Syntax error",
+        explanation: "The compiler reads a program as declarations and statements in a fixed \
+            grammar, and stopped where the text no longer fits it. The token it expected is \
+            named; the mistake is often just before it, such as a missing semicolon between \
+            two statements or a missing `end`.",
+        example: "program Hello;\nbegin\n  WriteLn('one');\n  WriteLn('two')\nend.",
+        reference: "languageSpecification/02_Grammar.md, 2.1 Program",
+    },
+    ErrorCode {
+        code: "SP0002",
+        patterns: &["Unit '"],
+        title: "Unit not found",
+        explanation: "A unit named in `uses` must be compiled before the program using it: \
+            building unit.pas produces the compiled unit the program reads.",
+        example: "unit Maths;\ninterface\n  function Square(N: integer): integer;\nimplementation\n  \
+            function Square(N: integer): integer;\n  begin\n    Square := N * N\n  end;\nend.",
+        reference: "languageSpecification/04_Semantics.md, 12.3 Interface vs Implementation",
+    },
+    ErrorCode {
+        code: "SP0003",
+        patterns: &["' not found", "Unknown class '"],
+        title: "Unknown identifier",
+        explanation: "Every name must be declared before it is used, in the routine using it, \
+            in a routine around it, at program level, or in the interface of a unit named in \
+            `uses`. Names are not case-sensitive, but spelling matters.",
+        example: "var\n  Count: integer;\nbegin\n  Count := 0\nend.",
+        reference: "languageSpecification/04_Semantics.md, 2.2 Identifier Resolution",
+    },
+    ErrorCode {
+        code: "SP0004",
+        patterns: &["already declared", "Duplicate field '"],
+        title: "Duplicate declaration",
+        explanation: "A name can be declared only once in a scope: two variables, a variable \
+            and a type, or two fields of one record cannot share a name. A routine's local \
+            declarations may reuse a name from outside it, hiding the outer one.",
+        example: "var\n  Total: integer;\n  Average: real;",
+        reference: "languageSpecification/04_Semantics.md, 2.1 Scope Rules",
+    },
+    ErrorCode {
+        code: "SP0005",
+        patterns: &["Type mismatch: cannot assign"],
+        title: "Incompatible assignment",
+        explanation: "The value on the right of `:=` must be assignable to the variable on the \
+            left. Smaller integers widen to larger ones and integers to reals, but a value \
+            that may not fit, such as an Integer into a Byte or a Real into an Integer, needs \
+            an explicit conversion: a typecast, Trunc or Round.",
+        example: "var\n  B: byte;\n  I: integer;\nbegin\n  I := 200;\n  B := Byte(I)\nend.",
+        reference: "languageSpecification/03_TypeSystem.md, 7.1 Assignment Compatibility",
+    },
+    ErrorCode {
+        code: "SP0006",
+        patterns: &["Type mismatch: cannot return"],
+        title: "Incompatible function result",
+        explanation: "A function's result, assigned to its name or `Result`, must be assignable \
+            to the result type in its heading.",
+        example: "function Half(N: integer): integer;\nbegin\n  Half := N div 2\nend;",
+        reference: "languageSpecification/04_Semantics.md, 5.3 Return Values",
+    },
+    ErrorCode {
+        code: "SP0007",
+        patterns: &["Cannot assign to constant '"],
+        title: "Assignment to a constant",
+        explanation: "A constant names a value fixed when the program is compiled, so it cannot \
+            be assigned. Declare a variable, initialised from the constant, to hold a value \
+            that changes.",
+        example: "const\n  Limit = 10;\nvar\n  Remaining: integer;\nbegin\n  Remaining := Limit;\n  \
+            Remaining := Remaining - 1\nend.",
+        reference: "languageSpecification/04_Semantics.md, 11.1 Constant Evaluation",
+    },
+    ErrorCode {
+        code: "SP0008",
+        patterns: &["Cannot assign to for loop variable '"],
+        title: "Assignment to a for loop variable",
+        explanation: "A `for` loop steps its variable itself, so the body may read it but not \
+            assign it. To leave the loop early use `Break`, or use a `while` loop to control \
+            the variable yourself.",
+        example: "for I := 1 to 10 do\n  if Found(I) then\n    Break;",
+        reference: "languageSpecification/04_Semantics.md, 7.4 For Statement",
+    },
+    ErrorCode {
+        code: "SP0009",
+        patterns: &["must be of an ordinal type", "must be an ordinal type"],
+        title: "Ordinal type required",
+        explanation: "`for` loop variables, `case` selectors and set elements must be ordinal: \
+            a type whose values can be counted one after another, such as Integer, Byte, Char, \
+            Boolean, an enumeration or a subrange. Reals and strings are not ordinal.",
+        example: "var\n  I: integer;\nbegin\n  for I := 1 to 3 do\n    WriteLn(I)\nend.",
+        reference: "languageSpecification/03_TypeSystem.md, 3. Ordinal Types",
+    },
+    ErrorCode {
+        code: "SP0010",
+        patterns: &["Duplicate case label"],
+        title: "Duplicate case label",
+        explanation: "Each value may label only one branch of a `case` statement, so that which \
+            branch runs is never ambiguous. Combine the branches, or list each value once.",
+        example: "case Key of\n  'y', 'Y': Confirm;\n  'n', 'N': Cancel\nend;",
+        reference: "languageSpecification/04_Semantics.md, 7.5 Case Statement",
+    },
+    ErrorCode {
+        code: "SP0011",
+        patterns: &[" argument, found", " arguments, found", "expects at least 1 argument"],
+        title: "Wrong number of arguments",
+        explanation: "A call passes one argument for each parameter in the routine's heading, \
+            in order, unless the parameter has a default value.",
+        example: "procedure Move(DX, DY: integer);\nbegin\nend;\n\nbegin\n  Move(1, 2)\nend.",
+        reference: "languageSpecification/04_Semantics.md, 5.1 Parameter Passing",
+    },
+];
+
+/// The code of a diagnostic with `message`, if it has one
+pub fn lookup(message: &str) -> Option<&'static ErrorCode> {
+    REGISTRY.iter().find(|code| code.matches(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_find_their_codes() {
+        assert_eq!(lookup("Type mismatch: cannot assign Real to Integer").unwrap().code, "SP0005");
+        assert_eq!(lookup("Type mismatch: cannot return Real from a function returning Integer").unwrap().code, "SP0006");
+        assert_eq!(lookup("Identifier 'Cont' not found").unwrap().code, "SP0003");
+        assert_eq!(lookup("Syntax error, \";\" expected but \"WriteLn\" found").unwrap().code, "SP0001");
+        assert_eq!(lookup("Expected statement").unwrap().code, "SP0001");
+        assert_eq!(lookup("Unit 'Maths' not found: build Maths.pas to produce Maths.spu").unwrap().code, "SP0002");
+        assert!(lookup("Program too large").is_none());
+
+        // Codes are unique
+        let mut codes: Vec<_> = REGISTRY.iter().map(|code| code.code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), REGISTRY.len());
+    }
+}
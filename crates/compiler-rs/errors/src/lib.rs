@@ -5,6 +5,9 @@
 
 use tokens::Span;
 
+pub mod codes;
+pub mod render;
+
 /// Error severity levels (matching FreePascal)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ErrorSeverity {
@@ -33,6 +36,30 @@ impl ErrorSeverity {
     }
 }
 
+/// How the driver prints diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiagnosticFormat {
+    /// FPC-compatible lines with the enhanced details (see [`Diagnostic::format_enhanced`])
+    #[default]
+    Human,
+    /// The source lines each diagnostic points at, underlined (see [`render`])
+    Pretty,
+    /// One JSON object per line, for editors and CI (see [`Diagnostic::format_json`])
+    Json,
+}
+
+impl DiagnosticFormat {
+    /// Look up a format by its `--error-format` name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(DiagnosticFormat::Human),
+            "pretty" => Some(DiagnosticFormat::Pretty),
+            "json" => Some(DiagnosticFormat::Json),
+            _ => None,
+        }
+    }
+}
+
 /// Related location (for enhanced diagnostics)
 #[derive(Debug, Clone, PartialEq)]
 pub struct RelatedLocation {
@@ -44,6 +71,31 @@ pub struct RelatedLocation {
     pub file: Option<String>,
 }
 
+/// Machine-applicable edit: replace the source text covered by `span`
+/// (empty for insertions) with `replacement`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixIt {
+    /// Text range to replace, by byte offset
+    pub span: Span,
+    /// Replacement text
+    pub replacement: String,
+}
+
+impl FixIt {
+    /// Insert `text` at byte offset `offset`
+    pub fn insert(offset: usize, line: usize, column: usize, text: String) -> Self {
+        Self {
+            span: Span::new(offset, offset, line, column),
+            replacement: text,
+        }
+    }
+
+    /// Apply the edit to `source`
+    pub fn apply(&self, source: &str) -> String {
+        format!("{}{}{}", &source[..self.span.start], self.replacement, &source[self.span.end..])
+    }
+}
+
 /// Code snippet for error display
 #[derive(Debug, Clone, PartialEq)]
 pub struct CodeSnippet {
@@ -58,6 +110,9 @@ pub struct CodeSnippet {
 pub struct Diagnostic {
     /// Error severity
     pub severity: ErrorSeverity,
+    /// Stable code, e.g. `SP0005`; when unset, [`Diagnostic::code`] finds
+    /// it from the message
+    pub code: Option<String>,
     /// FPC-compatible message
     pub message: String,
     /// Error location
@@ -68,8 +123,12 @@ pub struct Diagnostic {
     // Enhanced fields (optional)
     /// Context information (e.g., "In procedure 'X'...")
     pub context: Option<String>,
+    /// Further facts about the error, each printed on its own line
+    pub notes: Vec<String>,
     /// Suggestion for fixing the error
     pub suggestion: Option<String>,
+    /// Edits implementing the suggestion
+    pub fix_its: Vec<FixIt>,
     /// Related locations (declarations, usages, etc.)
     pub related_locations: Vec<RelatedLocation>,
     /// Code snippet with highlighting
@@ -83,11 +142,14 @@ impl Diagnostic {
     pub fn new(severity: ErrorSeverity, message: String, span: Span) -> Self {
         Self {
             severity,
+            code: None,
             message,
             span,
             file: None,
             context: None,
+            notes: vec![],
             suggestion: None,
+            fix_its: vec![],
             related_locations: vec![],
             code_snippet: None,
             explanation: None,
@@ -100,6 +162,18 @@ impl Diagnostic {
         self
     }
 
+    /// Set the error code, overriding the one the message has in the registry
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    /// Add a note
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
     /// Add context information
     pub fn with_context(mut self, context: String) -> Self {
         self.context = Some(context);
@@ -112,6 +186,12 @@ impl Diagnostic {
         self
     }
 
+    /// Add a fix-it edit
+    pub fn with_fix_it(mut self, fix_it: FixIt) -> Self {
+        self.fix_its.push(fix_it);
+        self
+    }
+
     /// Add a related location
     pub fn with_related_location(mut self, location: RelatedLocation) -> Self {
         self.related_locations.push(location);
@@ -130,6 +210,11 @@ impl Diagnostic {
         self
     }
 
+    /// The error code: the one set, else the registry's for the message
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref().or_else(|| codes::lookup(&self.message).map(|code| code.code))
+    }
+
     /// Format as FPC-compatible message
     pub fn format_fpc(&self) -> String {
         let file = self.file.as_deref().unwrap_or("unknown");
@@ -152,11 +237,23 @@ impl Diagnostic {
             output.push_str(&format!("\n  └─ {}", context));
         }
 
+        for note in &self.notes {
+            output.push_str(&format!("\n  └─ Note: {}", note));
+        }
+
         // Add suggestion
         if let Some(suggestion) = &self.suggestion {
             output.push_str(&format!("\n  └─ Suggestion: {}", suggestion));
         }
 
+        // Add fix-its
+        for fix_it in &self.fix_its {
+            output.push_str(&format!(
+                "\n  └─ Fix ({},{}): {:?}",
+                fix_it.span.line, fix_it.span.column, fix_it.replacement
+            ));
+        }
+
         // Add related locations
         for location in &self.related_locations {
             let file = location.file.as_deref().unwrap_or("unknown");
@@ -166,16 +263,30 @@ impl Diagnostic {
             ));
         }
 
-        // Add code snippet
+        // Add code snippet, underlining every line of the highlighted span
         if let Some(snippet) = &self.code_snippet {
             output.push_str("\n");
+            let span = snippet.highlight_span;
             for (line_num, content) in &snippet.lines {
-                let marker = if snippet.highlight_span.line == *line_num {
-                    ">"
+                if !(span.line..=span.end_line).contains(line_num) {
+                    output.push_str(&format!("  {} | {}\n", line_num, content));
+                    continue;
+                }
+                output.push_str(&format!("> {} | {}\n", line_num, content));
+                let line_len = content.chars().count();
+                let start = if *line_num == span.line {
+                    span.column
                 } else {
-                    " "
+                    content.chars().take_while(|c| c.is_whitespace()).count() + 1
                 };
-                output.push_str(&format!("{} {} | {}\n", marker, line_num, content));
+                let end = if *line_num == span.end_line { span.end_column } else { line_len + 1 };
+                let end = end.min(line_len + 1).max(start + 1);
+                output.push_str(&format!(
+                    "  {} | {}{}\n",
+                    " ".repeat(line_num.to_string().len()),
+                    " ".repeat(start - 1),
+                    "^".repeat(end - start)
+                ));
             }
         }
 
@@ -193,6 +304,104 @@ impl Diagnostic {
 
         output
     }
+
+    /// Format as verbose message followed, when the message has an error
+    /// code, by the rule it breaks, an example following it and where the
+    /// language specification describes it (see [`codes`])
+    pub fn format_teaching(&self) -> String {
+        let mut output = self.format_verbose();
+        let Some(code) = codes::lookup(&self.message) else {
+            return output;
+        };
+        output.push_str(&format!("\n\n  [{}] {}\n", code.code, code.title));
+        for line in wrap(code.explanation, 76) {
+            output.push_str(&format!("  {}\n", line));
+        }
+        output.push_str("\n  For example:\n");
+        for line in code.example.lines() {
+            output.push_str(format!("    {}", line).trim_end());
+            output.push('\n');
+        }
+        output.push_str(&format!("\n  See {}", code.reference));
+        output
+    }
+
+    /// Format as a JSON object on one line: the code, severity, message
+    /// and span, then the notes, suggestion, fix-its and related locations
+    pub fn format_json(&self) -> String {
+        let optional = |text: Option<&str>| text.map_or("null".to_string(), json_string);
+        let list = |items: Vec<String>| format!("[{}]", items.join(","));
+        let fix_its = self
+            .fix_its
+            .iter()
+            .map(|fix_it| format!("{{\"span\":{},\"replacement\":{}}}", json_span(&fix_it.span), json_string(&fix_it.replacement)))
+            .collect();
+        let related = self
+            .related_locations
+            .iter()
+            .map(|location| {
+                format!(
+                    "{{\"message\":{},\"file\":{},\"span\":{}}}",
+                    json_string(&location.message),
+                    optional(location.file.as_deref()),
+                    json_span(&location.span)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"code\":{},\"severity\":{},\"message\":{},\"file\":{},\"span\":{},\"context\":{},\"notes\":{},\"suggestion\":{},\"fix_its\":{},\"related\":{}}}",
+            optional(self.code()),
+            json_string(&self.severity.as_str().to_lowercase()),
+            json_string(&self.message),
+            optional(self.file.as_deref()),
+            json_span(&self.span),
+            optional(self.context.as_deref()),
+            list(self.notes.iter().map(|note| json_string(note)).collect()),
+            optional(self.suggestion.as_deref()),
+            list(fix_its),
+            list(related)
+        )
+    }
+}
+
+/// `text` as a JSON string literal
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_span(span: &Span) -> String {
+    format!(
+        "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}}}",
+        span.start, span.end, span.line, span.column, span.end_line, span.end_column
+    )
+}
+
+/// Break `text` into lines of at most `width` characters at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
 }
 
 impl std::fmt::Display for Diagnostic {
@@ -226,6 +435,15 @@ pub enum ParserError {
 }
 
 impl ParserError {
+    /// Where the error was found
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::UnexpectedToken { span, .. }
+            | ParserError::UnexpectedEof { span, .. }
+            | ParserError::InvalidSyntax { span, .. } => *span,
+        }
+    }
+
     /// Convert to Diagnostic
     pub fn to_diagnostic(&self, file: Option<String>) -> Diagnostic {
         match self {
@@ -488,6 +706,23 @@ mod tests {
         assert!(enhanced.contains("> 10 |")); // Highlight marker
     }
 
+    #[test]
+    fn test_code_snippet_underlines_multiline_span() {
+        let span = Span::new(13, 27, 2, 8).with_end(3, 11);
+        let snippet = CodeSnippet {
+            lines: vec![
+                (1, "begin".to_string()),
+                (2, "  x := (1 +".to_string()),
+                (3, "    2 * y);".to_string()),
+            ],
+            highlight_span: span,
+        };
+        let diag = Diagnostic::new(ErrorSeverity::Error, "Test".to_string(), span).with_code_snippet(snippet);
+
+        let enhanced = diag.format_enhanced();
+        assert!(enhanced.contains("  1 | begin\n> 2 |   x := (1 +\n    |        ^^^^\n> 3 |     2 * y);\n    |     ^^^^^^\n"), "{}", enhanced);
+    }
+
     #[test]
     fn test_diagnostic_enhanced_format_no_enhancements() {
         // Enhanced format should still work even without enhancements
@@ -538,6 +773,54 @@ mod tests {
         assert_eq!(verbose, enhanced);
     }
 
+    #[test]
+    fn test_diagnostic_teaching_format_explains_the_rule() {
+        let span = Span::new(0, 10, 4, 3);
+        let diag = Diagnostic::new(ErrorSeverity::Error, "Cannot assign to constant 'Limit'".to_string(), span)
+            .with_file("test.pas".to_string());
+        let teaching = diag.format_teaching();
+        assert!(teaching.starts_with(&diag.format_verbose()));
+        assert!(teaching.contains("[SP0007] Assignment to a constant\n  A constant names a value"), "{}", teaching);
+        assert!(teaching.contains("  For example:\n    const\n      Limit = 10;\n"), "{}", teaching);
+        assert!(teaching.ends_with("See languageSpecification/04_Semantics.md, 11.1 Constant Evaluation"));
+        assert!(teaching.lines().all(|line| line.len() <= 80 && line == line.trim_end()));
+
+        // Messages without a code are unchanged
+        let other = Diagnostic::new(ErrorSeverity::Error, "Program too large".to_string(), span);
+        assert_eq!(other.format_teaching(), other.format_verbose());
+    }
+
+    // ===== JSON Format Tests =====
+
+    #[test]
+    fn test_format_json() {
+        let diag = Diagnostic::new(
+            ErrorSeverity::Error,
+            "Identifier 'Cont' not found".to_string(),
+            Span::new(20, 24, 3, 5),
+        )
+        .with_file("a \"b\".pas".to_string())
+        .with_note("skipped by {$IFDEF DEBUG}\nat line 2".to_string())
+        .with_fix_it(FixIt::insert(20, 3, 5, "Count".to_string()));
+        assert_eq!(diag.code(), Some("SP0003"));
+        assert_eq!(
+            diag.format_json(),
+            "{\"code\":\"SP0003\",\"severity\":\"error\",\"message\":\"Identifier 'Cont' not found\",\
+             \"file\":\"a \\\"b\\\".pas\",\
+             \"span\":{\"start\":20,\"end\":24,\"line\":3,\"column\":5,\"end_line\":3,\"end_column\":9},\
+             \"context\":null,\"notes\":[\"skipped by {$IFDEF DEBUG}\\nat line 2\"],\"suggestion\":null,\
+             \"fix_its\":[{\"span\":{\"start\":20,\"end\":20,\"line\":3,\"column\":5,\"end_line\":3,\"end_column\":5},\
+             \"replacement\":\"Count\"}],\"related\":[]}"
+        );
+        assert!(diag.format_enhanced().contains("\n  └─ Note: skipped by"));
+
+        // A code set explicitly wins over the registry's
+        let diag = diag.with_code("SP9999");
+        assert!(diag.format_json().starts_with("{\"code\":\"SP9999\","));
+        assert_eq!(DiagnosticFormat::from_name("json"), Some(DiagnosticFormat::Json));
+        assert_eq!(DiagnosticFormat::from_name("xml"), None);
+    }
+
     // ===== Parser Error Conversion Tests =====
 
     #[test]
@@ -604,6 +887,17 @@ mod tests {
 
     // ===== Related Location Tests =====
 
+    #[test]
+    fn test_fix_it_insert() {
+        let source = "case C of Red: X end";
+        let fix_it = FixIt::insert(16, 1, 17, "; Green: Y".to_string());
+        assert_eq!(fix_it.apply(source), "case C of Red: X; Green: Y end");
+
+        let diag = Diagnostic::new(ErrorSeverity::Warning, "Missing".to_string(), Span::new(0, 4, 1, 1))
+            .with_fix_it(fix_it);
+        assert!(diag.format_enhanced().contains("Fix (1,17): \"; Green: Y\""));
+    }
+
     #[test]
     fn test_related_location_creation() {
         let related = RelatedLocation {
@@ -0,0 +1,239 @@
+//! Source-annotated rendering of diagnostics
+//!
+//! With `--error-format pretty`, a diagnostic shows the lines it points at,
+//! as rustc does: the span is underlined with `^`, each related location in
+//! the same file with `-` and its message, and related locations in other
+//! files follow under their own `:::` heading. Notes, the context and the
+//! suggestion come last:
+//!
+//! ```text
+//! error[SP0004]: Variable 'Count' already declared
+//!  --> game.pas:4:3
+//!   |
+//! 2 |   Count: integer;
+//!   |   ----- first declared here
+//! 3 |   Lives: byte;
+//! 4 |   Count: byte;
+//!   |   ^^^^^
+//!   |
+//!   = help: Rename one of the variables
+//! ```
+//!
+//! A single line between two labels is shown; longer gaps are elided with
+//! `...`. A file that cannot be read is reported by location alone.
+
+use std::collections::HashMap;
+use std::fs;
+
+use tokens::Span;
+
+use crate::Diagnostic;
+
+/// Renders diagnostics with the source lines they point at, reading each
+/// file once
+#[derive(Debug, Default)]
+pub struct SourceRenderer {
+    /// Lines of each file read so far, or None if it could not be read
+    sources: HashMap<String, Option<Vec<String>>>,
+}
+
+/// An underlined span: `^` for the diagnostic's own, `-` with a message
+/// for a related location
+struct Label<'a> {
+    span: Span,
+    marker: char,
+    message: Option<&'a str>,
+}
+
+impl SourceRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `text` as the contents of `file` instead of reading it
+    pub fn add_source(&mut self, file: &str, text: &str) {
+        self.sources.insert(file.to_string(), Some(text.lines().map(str::to_string).collect()));
+    }
+
+    /// Render `diagnostic` with its source lines
+    pub fn render(&mut self, diagnostic: &Diagnostic) -> String {
+        let severity = diagnostic.severity.as_str().to_lowercase();
+        let mut output = match diagnostic.code() {
+            Some(code) => format!("{}[{}]: {}\n", severity, code, diagnostic.message),
+            None => format!("{}: {}\n", severity, diagnostic.message),
+        };
+        let file = diagnostic.file.as_deref().unwrap_or("unknown");
+
+        // Labels in the diagnostic's file, then each other file in order
+        let mut files: Vec<(&str, Vec<Label>)> = vec![(
+            file,
+            vec![Label { span: diagnostic.span, marker: '^', message: None }],
+        )];
+        for location in &diagnostic.related_locations {
+            let label = Label { span: location.span, marker: '-', message: Some(&location.message) };
+            let related_file = location.file.as_deref().unwrap_or(file);
+            match files.iter_mut().find(|(name, _)| *name == related_file) {
+                Some((_, labels)) => labels.push(label),
+                None => files.push((related_file, vec![label])),
+            }
+        }
+        let last_line = files
+            .iter()
+            .flat_map(|(_, labels)| labels.iter().map(|label| label.span.end_line.max(label.span.line)))
+            .max()
+            .unwrap_or(1);
+        let gutter = last_line.to_string().len();
+
+        let mut unread = vec![];
+        for (index, (name, labels)) in files.iter().enumerate() {
+            let arrow = if index == 0 { "-->" } else { ":::" };
+            let span = labels[0].span;
+            output.push_str(&format!("{}{} {}:{}:{}\n", " ".repeat(gutter), arrow, name, span.line, span.column));
+            match self.lines(name) {
+                Some(lines) => output.push_str(&snippet(lines, labels, gutter)),
+                None => unread.extend(labels.iter().filter_map(|label| {
+                    label.message.map(|message| format!("{} at {}:{}:{}", message, name, label.span.line, label.span.column))
+                })),
+            }
+        }
+
+        let notes = diagnostic.context.iter().chain(&diagnostic.notes).chain(&unread);
+        let mut footer: Vec<String> = notes.map(|note| format!("= note: {}", note)).collect();
+        footer.extend(diagnostic.suggestion.iter().map(|suggestion| format!("= help: {}", suggestion)));
+        footer.extend(diagnostic.fix_its.iter().map(|fix_it| {
+            format!("= fix ({},{}): {:?}", fix_it.span.line, fix_it.span.column, fix_it.replacement)
+        }));
+        if !footer.is_empty() {
+            output.push_str(&format!("{} |\n", " ".repeat(gutter)));
+            for line in footer {
+                output.push_str(&format!("{} {}\n", " ".repeat(gutter), line));
+            }
+        }
+        output
+    }
+
+    /// Lines of `file`, read the first time they are asked for
+    fn lines(&mut self, file: &str) -> Option<&Vec<String>> {
+        self.sources
+            .entry(file.to_string())
+            .or_insert_with(|| fs::read_to_string(file).ok().map(|text| text.lines().map(str::to_string).collect()))
+            .as_ref()
+    }
+}
+
+/// The lines of `lines` the `labels` cover, each followed by the markers
+/// of the labels on it
+fn snippet(lines: &[String], labels: &[Label], gutter: usize) -> String {
+    let blank = " ".repeat(gutter);
+    let mut shown: Vec<usize> = labels
+        .iter()
+        .flat_map(|label| label.span.line..=label.span.end_line.max(label.span.line))
+        .filter(|line| (1..=lines.len()).contains(line))
+        .collect();
+    shown.sort_unstable();
+    shown.dedup();
+
+    let mut output = format!("{} |\n", blank);
+    let mut previous: Option<usize> = None;
+    for line_num in shown {
+        match previous {
+            Some(previous) if line_num == previous + 2 => {
+                output.push_str(&format!("{:>gutter$} | {}\n", previous + 1, lines[previous]));
+            }
+            Some(previous) if line_num > previous + 2 => output.push_str("...\n"),
+            _ => {}
+        }
+        previous = Some(line_num);
+        let content = &lines[line_num - 1];
+        output.push_str(&format!("{:>gutter$} | {}\n", line_num, content));
+        for label in labels {
+            let span = label.span;
+            let end_line = span.end_line.max(span.line);
+            if !(span.line..=end_line).contains(&line_num) {
+                continue;
+            }
+            let line_len = content.chars().count();
+            let start = if line_num == span.line {
+                span.column.max(1)
+            } else {
+                content.chars().take_while(|c| c.is_whitespace()).count() + 1
+            };
+            let end = if line_num == end_line { span.end_column } else { line_len + 1 };
+            let end = end.min(line_len + 1).max(start + 1);
+            // Tabs before the span are kept so the markers line up
+            let indent: String =
+                content.chars().chain(std::iter::repeat(' ')).take(start - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            let mut markers = format!("{}{}", indent, label.marker.to_string().repeat(end - start));
+            if let Some(message) = label.message
+                && line_num == end_line
+            {
+                markers.push(' ');
+                markers.push_str(message);
+            }
+            output.push_str(&format!("{} | {}\n", blank, markers));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorSeverity, RelatedLocation};
+
+    #[test]
+    fn test_render_underlines_the_span_and_related_locations() {
+        let source = "program P;\nvar\n  Count: integer;\n  Lives: byte;\n\tCount: byte;\nbegin\nend.\n";
+        let mut renderer = SourceRenderer::new();
+        renderer.add_source("game.pas", source);
+        renderer.add_source("lib.pas", "unit Lib;\ninterface\nconst Limit = 3;\n");
+        let diagnostic = Diagnostic::new(
+            ErrorSeverity::Error,
+            "Variable 'Count' already declared".to_string(),
+            Span::new(38, 43, 5, 2),
+        )
+        .with_file("game.pas".to_string())
+        .with_related_location(RelatedLocation {
+            message: "first declared here".to_string(),
+            span: Span::new(17, 22, 3, 3),
+            file: None,
+        })
+        .with_related_location(RelatedLocation {
+            message: "limit declared here".to_string(),
+            span: Span::new(26, 31, 3, 7),
+            file: Some("lib.pas".to_string()),
+        })
+        .with_suggestion("Rename one of the variables".to_string());
+        assert_eq!(
+            renderer.render(&diagnostic),
+            "error[SP0004]: Variable 'Count' already declared
+ --> game.pas:5:2
+  |
+3 |   Count: integer;
+  |   ----- first declared here
+4 |   Lives: byte;
+5 | \tCount: byte;
+  | \t^^^^^
+ ::: lib.pas:3:7
+  |
+3 | const Limit = 3;
+  |       ----- limit declared here
+  |
+  = help: Rename one of the variables
+"
+        );
+
+        // Without the source, related locations become notes
+        let diagnostic = Diagnostic::new(ErrorSeverity::Warning, "Unused".to_string(), Span::new(0, 1, 12, 1))
+            .with_file("missing.pas".to_string())
+            .with_related_location(RelatedLocation {
+                message: "declared here".to_string(),
+                span: Span::new(0, 1, 2, 1),
+                file: None,
+            });
+        assert_eq!(
+            renderer.render(&diagnostic),
+            "warning: Unused\n  --> missing.pas:12:1\n   |\n   = note: declared here at missing.pas:2:1\n"
+        );
+    }
+}
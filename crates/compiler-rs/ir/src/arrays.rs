@@ -0,0 +1,228 @@
+//! Array element lowering
+//!
+//! An element of an array lies its index's distance from the low bound,
+//! times the element size, past the array. A constant index makes it a
+//! memory operand of its own, read and assigned like a variable; any other
+//! an address, which LOADAT and STOREAT reach it through, once the index is
+//! checked against the bounds under {$R+}. For
+//! `var A: array[1..10] of Integer`:
+//!
+//! ```text
+//!     A[3] := N                   STORE   [@A+4], N
+//!
+//!     N := A[I]                   SUB     t0, I, 1
+//!                                 MUL     t1, t0, 2
+//!                                 ADDR    t2, [@A]
+//!                                 ADD     t3, t2, t1
+//!                                 LOADAT  t4, t3, 2
+//!                                 STORE   N, t4
+//! ```
+//!
+//! The byte elements a constant index picks out of a global are listed in
+//! the program's byte globals, as record fields are; those of local arrays,
+//! whose frame slots are reached a word at a time, are reached through
+//! their address. Elements over two bytes, such as the rows of an array
+//! of arrays, can be passed by reference but are otherwise reported as not
+//! supported yet.
+
+use ast::Node;
+use types::Type;
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+/// Where an array, or an element of one, lies
+enum Location {
+    /// At a memory operand of its own
+    Memory { base: String, offset: i32 },
+    /// At the address a value holds
+    At(Value),
+}
+
+impl IRBuilder {
+    /// Type of `array` when it is an array variable, an element of one, or
+    /// a field of a record variable
+    fn array_type(&self, array: &Node) -> Option<Type> {
+        let ty = match array {
+            Node::IdentExpr(ident) => self.variable_types.get(&ident.name)?.clone(),
+            Node::IndexExpr(index) => self.element_type(index)?,
+            Node::FieldExpr(member) => self.record_field_type(member)?,
+            _ => return None,
+        };
+        self.resolve_type(&ty).filter(|ty| matches!(ty, Type::Array { .. })).cloned()
+    }
+
+    /// Type of the element `index` picks out of an array; None for other
+    /// indexing
+    pub(crate) fn element_type(&self, index: &ast::IndexExpr) -> Option<Type> {
+        match self.array_type(&index.array)? {
+            Type::Array { element_type, .. } => Some(*element_type),
+            _ => None,
+        }
+    }
+
+    /// Where the array `array` lies
+    fn array_location(&mut self, array: &Node) -> Option<Location> {
+        let variable = match array {
+            // A var parameter's slot holds the array's address
+            Node::IdentExpr(ident) if let Some(slot) = self.reference_slot(&ident.name) => return Some(Location::At(slot)),
+            Node::IdentExpr(ident) => self.get_variable_address(&ident.name),
+            Node::IndexExpr(index) => return self.element_location(index),
+            Node::FieldExpr(member) => self.build_record_field(member)?,
+            _ => return None,
+        };
+        match variable {
+            Value::Memory { base, offset } => Some(Location::Memory { base, offset }),
+            _ => None,
+        }
+    }
+
+    /// Where the element `index` picks out lies, checking a variable index
+    /// against the bounds under {$R+}
+    fn element_location(&mut self, index: &ast::IndexExpr) -> Option<Location> {
+        let Type::Array { index_type, element_type, .. } = self.array_type(&index.array)? else {
+            return None;
+        };
+        let index_type = self.resolve_type(&index_type).cloned().unwrap_or(*index_type);
+        let (low, high) = index_type.ordinal_bounds()?;
+        let size = self.resolve_type(&element_type).and_then(Type::size)? as i32;
+        let array = self.array_location(&index.array)?;
+        let value = self.build_expression(&index.index);
+        if let Value::Immediate(n) = value {
+            let offset = (n - low as i32) * size;
+            return Some(match array {
+                Location::Memory { base, offset: start } => Location::Memory { base, offset: start + offset },
+                Location::At(address) => Location::At(self.offset_address(address, Value::Immediate(offset))),
+            });
+        }
+        if self.range_checks {
+            let base = match &index_type {
+                Type::Subrange { base, .. } => (**base).clone(),
+                other => other.clone(),
+            };
+            self.build_range_check(value.clone(), &base, low, high, index.span);
+        }
+        let distance = match low {
+            0 => value,
+            low => {
+                let distance = self.new_temp();
+                self.emit(Instruction::new(Opcode::Sub, vec![distance.clone(), value, Value::Immediate(low as i32)]));
+                distance
+            }
+        };
+        let offset = self.scale(distance, size);
+        let address = match array {
+            Location::Memory { base, offset } => {
+                let address = self.new_temp();
+                self.emit(Instruction::new(Opcode::Addr, vec![address.clone(), Value::Memory { base, offset }]));
+                address
+            }
+            Location::At(address) => address,
+        };
+        Some(Location::At(self.offset_address(address, offset)))
+    }
+
+    /// `address` plus `offset` bytes
+    fn offset_address(&mut self, address: Value, offset: Value) -> Value {
+        if offset == Value::Immediate(0) {
+            return address;
+        }
+        let sum = self.new_temp();
+        self.emit(Instruction::new(Opcode::Add, vec![sum.clone(), address, offset]));
+        sum
+    }
+
+    /// Where the element `index` picks out lies, and its size; None, after
+    /// reporting it, if it cannot be reached yet
+    fn element(&mut self, index: &ast::IndexExpr) -> Option<(Location, i32)> {
+        let size = self.element_type(index).and_then(|ty| self.resolve_type(&ty).and_then(Type::size));
+        if !matches!(size, Some(1 | 2)) {
+            self.unsupported("elements of over two bytes", Some(index.span));
+            return None;
+        }
+        let Some(location) = self.element_location(index) else {
+            self.unsupported("indexing this array", Some(index.span));
+            return None;
+        };
+        let location = match location {
+            Location::Memory { base, offset } if size == Some(1) => match crate::global_symbol(&base) {
+                Some(symbol) => {
+                    let key = (symbol.to_string(), offset);
+                    if !self.program.byte_globals.contains(&key) {
+                        self.program.byte_globals.push(key);
+                    }
+                    Location::Memory { base, offset }
+                }
+                None => {
+                    let address = self.new_temp();
+                    self.emit(Instruction::new(Opcode::Addr, vec![address.clone(), Value::Memory { base, offset }]));
+                    Location::At(address)
+                }
+            },
+            location => location,
+        };
+        Some((location, size? as i32))
+    }
+
+    /// Build a read of the element `index` picks out of an array; None for
+    /// other indexing
+    pub(crate) fn build_element_load(&mut self, index: &ast::IndexExpr) -> Option<Value> {
+        self.element_type(index)?;
+        Some(match self.element(index) {
+            Some((Location::Memory { base, offset }, _)) => Value::Memory { base, offset },
+            Some((Location::At(address), size)) => {
+                let result = self.new_temp();
+                self.emit(
+                    Instruction::new(Opcode::LoadAt, vec![result.clone(), address, Value::Immediate(size)])
+                        .with_span(index.span),
+                );
+                result
+            }
+            None => self.new_temp(),
+        })
+    }
+
+    /// Address of the element `index` picks out of an array, of any size,
+    /// as a parameter passed by reference is given it; None for other
+    /// indexing
+    pub(crate) fn build_element_address(&mut self, index: &ast::IndexExpr) -> Option<Value> {
+        self.element_type(index)?;
+        match self.element_location(index)? {
+            Location::Memory { base, offset } => {
+                let address = self.new_temp();
+                self.emit(
+                    Instruction::new(Opcode::Addr, vec![address.clone(), Value::Memory { base, offset }])
+                        .with_span(index.span),
+                );
+                Some(address)
+            }
+            Location::At(address) => Some(address),
+        }
+    }
+
+    /// Build an assignment to an element of an array, checking the value
+    /// against the bounds of a subrange element under {$R+}; false if
+    /// `assign` assigns something else
+    pub(crate) fn build_element_assign(&mut self, assign: &ast::AssignStmt) -> bool {
+        let Node::IndexExpr(index) = assign.target.as_ref() else {
+            return false;
+        };
+        let Some(element_type) = self.element_type(index) else {
+            return false;
+        };
+        let Some((location, size)) = self.element(index) else {
+            return true;
+        };
+        let value = self.build_expression(&assign.value);
+        if self.range_checks
+            && let Some(Type::Subrange { base, low, high }) = self.resolve_type(&element_type).cloned()
+        {
+            self.build_range_check(value.clone(), &base, low, high, assign.span);
+        }
+        let inst = match location {
+            Location::Memory { base, offset } => Instruction::new(Opcode::Store, vec![Value::Memory { base, offset }, value]),
+            Location::At(address) => Instruction::new(Opcode::StoreAt, vec![address, value, Value::Immediate(size)]),
+        };
+        self.emit(inst.with_span(assign.span));
+        true
+    }
+}
@@ -0,0 +1,264 @@
+//! Method calls
+//!
+//! `object.Method(args)` passes the object as a hidden first argument,
+//! `Self`. A static method is called by its symbol, `Class__Method`; a
+//! virtual one through its slot in the VMT whose address is the first word
+//! of the instance, so the class of the object decides which method runs.
+//!
+//! ```text
+//!     S.Move(1, 2)                CALLMETHOD TShape__Move, S, 1, 2
+//!     S.Draw                      CALLMETHOD 0, S          ; VMT slot 0
+//!     I := S.Area                 CALLMETHOD 1, S
+//!                                 MOV t0, hl
+//! ```
+//!
+//! A constructor called on the class first allocates a zeroed instance on
+//! the heap with the class's VMT installed, then runs on it; the instance
+//! is the value of the call. A destructor runs, then its instance is freed:
+//!
+//! ```text
+//!     S := TShape.Create(1)       NEW t0, 6, __vmt_TShape  ; size, VMT (0 for none)
+//!                                 CALLMETHOD TShape__Create, t0, 1
+//!                                 MOV S, t0
+//!     S.Destroy                   CALLMETHOD 2, S
+//!                                 DISPOSE S
+//! ```
+//!
+//! A field is reached through the address of the instance, at its offset;
+//! in a method, a field or method named alone is one of `Self`:
+//!
+//! ```text
+//!     S.X := 1                    ADD t0, S, 2
+//!                                 STOREAT t0, 1, 2         ; address, value, size
+//!     N := Radius                 ADD t1, Self, 6
+//!                                 LOADAT t2, t1, 2
+//! ```
+
+use ast::Node;
+use tokens::Span;
+use types::{ClassLayout, ClassMethod, Field, MethodKind, Type};
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+impl IRBuilder {
+    /// Use the analyzer's class layouts, to find methods and their VMT slots
+    pub fn set_class_layouts(&mut self, layouts: &[ClassLayout]) {
+        self.class_layouts = layouts.to_vec();
+    }
+
+    /// Method `method` of the class of `object`
+    fn resolve_method(&self, object: &Node, method: &str) -> Option<ClassMethod> {
+        let class_name = match self.analyze_expression_type(object)? {
+            Type::Class { name } | Type::Named { name } => name,
+            _ => return None,
+        };
+        let layout = self.class_layouts.iter().find(|layout| layout.name.eq_ignore_ascii_case(&class_name))?;
+        layout.method(method).cloned()
+    }
+
+    /// Layout of the class of the instances `ty` holds
+    fn class_of(&self, ty: &Type) -> Option<&ClassLayout> {
+        let (Type::Class { name } | Type::Named { name }) = ty else {
+            return None;
+        };
+        self.class_layouts.iter().find(|layout| layout.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Layout of the class of the method being built, if it is one
+    fn self_class(&self) -> Option<&ClassLayout> {
+        self.class_of(self.variable_types.get(types::SELF_PARAMETER)?)
+    }
+
+    /// Field `name` of `Self`, when it is not a local or parameter of the
+    /// method being built
+    pub(crate) fn self_field(&self, name: &str) -> Option<Field> {
+        if self.variable_slots.contains_key(&name.to_lowercase()) {
+            return None;
+        }
+        self.self_class()?.field(name).cloned()
+    }
+
+    /// Method `name` of `Self`
+    pub(crate) fn self_method(&self, name: &str) -> Option<ClassMethod> {
+        self.self_class()?.method(name).cloned()
+    }
+
+    /// Field `name` of the instance `object`
+    fn object_field(&self, object: &Node, name: &str) -> Option<Field> {
+        self.class_of(&self.analyze_expression_type(object)?)?.field(name).cloned()
+    }
+
+    /// Type of `object.name`: a field, what a method returns, or the class
+    /// a constructor called on it creates
+    pub(crate) fn member_type(&self, object: &Node, name: &str) -> Option<Type> {
+        if let Some(layout) = self.named_class(object) {
+            layout.method(name).filter(|m| m.kind == MethodKind::Constructor)?;
+            return Some(Type::Class { name: layout.name.clone() });
+        }
+        if let Some(interface) = self.object_interface(object) {
+            return interface.method(name)?.return_type.clone();
+        }
+        let layout = self.class_of(&self.analyze_expression_type(object)?)?;
+        match layout.field(name) {
+            Some(field) => Some(*field.field_type.clone()),
+            None => layout.method(name)?.return_type.clone(),
+        }
+    }
+
+    /// Layout of the class `node` names, when it is a class name rather
+    /// than a variable
+    fn named_class(&self, node: &Node) -> Option<&ClassLayout> {
+        let Node::IdentExpr(ident) = node else {
+            return None;
+        };
+        if self.variable_types.contains_key(&ident.name) {
+            return None;
+        }
+        self.class_layouts.iter().find(|layout| layout.name.eq_ignore_ascii_case(&ident.name))
+    }
+
+    /// Call `method` on the instance `object` with `args`
+    fn emit_method_call(&mut self, method: &ClassMethod, object: Value, args: &[Node], span: Span) {
+        let target = match method.slot {
+            Some(slot) => Value::Immediate(slot as i32),
+            None => Value::Label(types::method_symbol(&method.owner, &method.name)),
+        };
+        let mut operands = vec![target, object];
+        for arg in args {
+            operands.push(self.build_expression(arg));
+        }
+        self.emit(Instruction::new(Opcode::CallMethod, operands).with_span(span));
+    }
+
+    /// Build `Class.Constructor(args)`, returning the temporary holding the
+    /// new instance; None if `class` does not name a class with that
+    /// constructor
+    pub(crate) fn build_constructor_call(&mut self, class: &Node, method: &str, args: &[Node], span: Span) -> Option<Value> {
+        let layout = self.named_class(class)?;
+        let method = layout.method(method).filter(|m| m.kind == MethodKind::Constructor)?.clone();
+        let vmt = if layout.has_vmt() { Value::Label(types::vmt_symbol(&layout.name)) } else { Value::Immediate(0) };
+        let size = Value::Immediate(layout.size as i32);
+        let instance = self.new_temp();
+        self.emit(Instruction::new(Opcode::New, vec![instance.clone(), size, vmt]).with_span(span));
+        self.emit_method_call(&method, instance.clone(), args, span);
+        Some(instance)
+    }
+
+    /// Build `object.name` when it names a field of an instance or a
+    /// method called without arguments; None if it names neither, or a
+    /// procedure
+    pub(crate) fn build_member(&mut self, member: &ast::FieldExpr) -> Option<Value> {
+        if let Some(field) = self.object_field(&member.record, &member.field) {
+            let object = self.build_expression(&member.record);
+            return Some(self.build_field_load(object, &field, member.span));
+        }
+        let call = ast::MethodCall { object: member.record.clone(), method: member.field.clone(), args: vec![], span: member.span };
+        if !self.resolves_method(&call) {
+            return None;
+        }
+        self.build_method_call(&call)
+    }
+
+    /// Build an assignment to a field, `object.Field := value` or, in a
+    /// method, `Field := value`; false if `assign` assigns no field
+    pub(crate) fn build_field_assign(&mut self, assign: &ast::AssignStmt) -> bool {
+        let (object, field) = match assign.target.as_ref() {
+            Node::FieldExpr(member) => match self.object_field(&member.record, &member.field) {
+                Some(field) => (self.build_expression(&member.record), field),
+                None => return false,
+            },
+            Node::IdentExpr(ident) => match self.self_field(&ident.name) {
+                Some(field) => (self.get_variable_address(types::SELF_PARAMETER), field),
+                None => return false,
+            },
+            _ => return false,
+        };
+        let value = self.build_expression(&assign.value);
+        if let Some(size) = self.field_size(&field, assign.span) {
+            let address = self.field_address(object, &field);
+            self.emit(Instruction::new(Opcode::StoreAt, vec![address, value, size]).with_span(assign.span));
+        }
+        true
+    }
+
+    /// Build reading `field` of the instance `object`
+    pub(crate) fn build_field_load(&mut self, object: Value, field: &Field, span: Span) -> Value {
+        let result = self.new_temp();
+        if let Some(size) = self.field_size(field, span) {
+            let address = self.field_address(object, field);
+            self.emit(Instruction::new(Opcode::LoadAt, vec![result.clone(), address, size]).with_span(span));
+        }
+        result
+    }
+
+    /// Address of `field` in the instance `object`
+    fn field_address(&mut self, object: Value, field: &Field) -> Value {
+        let address = self.new_temp();
+        let offset = Value::Immediate(field.offset.unwrap_or(0) as i32);
+        self.emit(Instruction::new(Opcode::Add, vec![address.clone(), object, offset]));
+        address
+    }
+
+    /// Size operand of a LOADAT or STOREAT of `field`; None, after
+    /// reporting it, for a field of over two bytes
+    fn field_size(&mut self, field: &Field, span: Span) -> Option<Value> {
+        match self.resolve_type(&field.field_type).and_then(Type::size) {
+            Some(size @ (1 | 2)) => Some(Value::Immediate(size as i32)),
+            _ => {
+                self.unsupported(&format!("the field '{}' of over two bytes", field.name), Some(span));
+                None
+            }
+        }
+    }
+
+    /// Call `method` of the class of the method being built on `Self`,
+    /// returning the temporary holding its result if it is a function
+    pub(crate) fn build_self_call(&mut self, method: &ClassMethod, args: &[Node], span: Span) -> Option<Value> {
+        let receiver = self.get_variable_address(types::SELF_PARAMETER);
+        self.emit_method_call(method, receiver, args, span);
+        self.method_result(method)
+    }
+
+    /// Whether `call` calls a method of a class or interface whose layout
+    /// this builder has
+    pub(crate) fn resolves_method(&self, call: &ast::MethodCall) -> bool {
+        match self.named_class(&call.object) {
+            Some(layout) => layout.method(&call.method).is_some_and(|m| m.kind == MethodKind::Constructor),
+            None => {
+                self.object_interface(&call.object).is_some_and(|interface| interface.method(&call.method).is_some())
+                    || self.resolve_method(&call.object, &call.method).is_some()
+            }
+        }
+    }
+
+    /// Build a method call, returning the temporary holding the result of
+    /// a function method, or the instance a constructor creates
+    pub(crate) fn build_method_call(&mut self, call: &ast::MethodCall) -> Option<Value> {
+        if self.named_class(&call.object).is_some() {
+            return self.build_constructor_call(&call.object, &call.method, &call.args, call.span);
+        }
+        if let Some(interface) = self.object_interface(&call.object) {
+            let method = interface.method(&call.method)?.clone();
+            let iid = Value::Label(types::interface_id_symbol(&interface.name));
+            let object = self.build_expression(&call.object);
+            self.emit_interface_call(&method, iid, object, &call.args, call.span);
+            return self.method_result(&method);
+        }
+        let method = self.resolve_method(&call.object, &call.method)?;
+        let object = self.build_expression(&call.object);
+        self.emit_method_call(&method, object.clone(), &call.args, call.span);
+        if method.kind == MethodKind::Destructor {
+            self.emit(Instruction::new(Opcode::Dispose, vec![object]).with_span(call.span));
+        }
+        self.method_result(&method)
+    }
+
+    /// The temporary holding the result of `method` just called, if it is a
+    /// function
+    pub(crate) fn method_result(&mut self, method: &ClassMethod) -> Option<Value> {
+        method.return_type.as_ref()?;
+        let result = self.new_temp();
+        self.emit(Instruction::new(Opcode::Mov, vec![result.clone(), Value::Register("hl".to_string())]));
+        Some(result)
+    }
+}
@@ -0,0 +1,127 @@
+//! Console I/O lowering
+//!
+//! `Write` and `WriteLn` write each argument with a WRITE naming how it is
+//! formatted, and `WriteLn` then ends the line; `ReadLn` reads one line
+//! into its arguments, split at spaces. For `I: Integer; C: Char`:
+//!
+//! ```text
+//!     WriteLn('I = ', I, C);  ReadLn(I)
+//!
+//!     WRITE   __str_0, string
+//!     WRITE   I, integer
+//!     WRITE   C, char
+//!     WRITELN
+//!     READLN  I, integer
+//! ```
+
+use ast::Node;
+use types::{PrimitiveType, Type};
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+/// How WRITE shows a value and READLN parses one; the operand naming it is
+/// a label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleFormat {
+    /// Signed 16-bit number
+    Integer,
+    /// Unsigned number (Byte, Word)
+    Word,
+    Char,
+    /// `TRUE` or `FALSE`
+    Boolean,
+    /// A string; WRITE only
+    String,
+}
+
+impl ConsoleFormat {
+    pub const ALL: [ConsoleFormat; 5] =
+        [ConsoleFormat::Integer, ConsoleFormat::Word, ConsoleFormat::Char, ConsoleFormat::Boolean, ConsoleFormat::String];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConsoleFormat::Integer => "integer",
+            ConsoleFormat::Word => "word",
+            ConsoleFormat::Char => "char",
+            ConsoleFormat::Boolean => "boolean",
+            ConsoleFormat::String => "string",
+        }
+    }
+
+    /// The format of a WRITE or READLN operand
+    pub fn from_operand(value: &Value) -> Option<Self> {
+        match value {
+            Value::Label(name) => Self::ALL.into_iter().find(|format| format.name() == name),
+            _ => None,
+        }
+    }
+
+    fn operand(&self) -> Value {
+        Value::Label(self.name().to_string())
+    }
+}
+
+impl IRBuilder {
+    /// Build `Write`, `WriteLn` or `ReadLn`; false if `call` is not one of
+    /// them. An argument without a console format is reported
+    pub(crate) fn build_console_procedure(&mut self, call: &ast::CallStmt) -> bool {
+        let name = call.name.to_ascii_lowercase();
+        match name.as_str() {
+            "write" | "writeln" => {
+                for arg in &call.args {
+                    let Some(format) = self.console_format(arg) else {
+                        self.unsupported(&format!("writing this value with {}", call.name), Some(arg.span()));
+                        continue;
+                    };
+                    let value = if format == ConsoleFormat::String {
+                        self.string_operand(arg)
+                    } else {
+                        self.build_expression(arg)
+                    };
+                    self.emit(Instruction::new(Opcode::Write, vec![value, format.operand()]).with_span(call.span));
+                }
+                if name == "writeln" {
+                    self.emit(Instruction::new(Opcode::WriteLn, vec![]).with_span(call.span));
+                }
+            }
+            "readln" => {
+                let mut operands = vec![];
+                for arg in &call.args {
+                    match (arg, self.console_format(arg)) {
+                        (Node::IdentExpr(ident), Some(format)) if format != ConsoleFormat::String => {
+                            operands.extend([self.get_variable_address(&ident.name), format.operand()])
+                        }
+                        _ => self.unsupported(&format!("reading this value with {}", call.name), Some(arg.span())),
+                    }
+                }
+                self.emit(Instruction::new(Opcode::ReadLn, operands).with_span(call.span));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// How `expr` is written or read
+    fn console_format(&self, expr: &Node) -> Option<ConsoleFormat> {
+        if self.is_string(expr) {
+            return Some(ConsoleFormat::String);
+        }
+        // 16-bit arithmetic has the type of its left operand
+        let ty = self.analyze_expression_type(expr).or_else(|| match expr {
+            Node::BinaryExpr(bin) => self.analyze_expression_type(&bin.left),
+            _ => None,
+        })?;
+        let ty = match self.resolve_type(&ty)? {
+            Type::Subrange { base, .. } | Type::Distinct { base, .. } => base.as_ref().clone(),
+            ty => ty.clone(),
+        };
+        match ty {
+            Type::Primitive(PrimitiveType::Integer) => Some(ConsoleFormat::Integer),
+            Type::Primitive(PrimitiveType::Byte | PrimitiveType::Word) => Some(ConsoleFormat::Word),
+            Type::Primitive(PrimitiveType::Char) => Some(ConsoleFormat::Char),
+            Type::Primitive(PrimitiveType::Boolean) => Some(ConsoleFormat::Boolean),
+            Type::Enum { .. } => Some(ConsoleFormat::Word),
+            _ => None,
+        }
+    }
+}
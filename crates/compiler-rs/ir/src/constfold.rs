@@ -0,0 +1,360 @@
+//! Constant folding and propagation
+//!
+//! Values known when compiling are substituted for the temporaries and
+//! frame slots holding them, arithmetic on constants is computed, and
+//! branches on constants become jumps:
+//!
+//! - **Temporaries** defined once, by a constant or by folded arithmetic,
+//!   hold that constant everywhere. A temporary defined more than once is
+//!   only known from its definition to the end of the block.
+//! - **Frame slots** assigned a constant hold it until the end of the block
+//!   or until something may change them: a write to an overlapping slot, or
+//!   any instruction that may write memory it does not name, such as a
+//!   call, a string or set operation, or inline assembly.
+//! - **Arithmetic** is evaluated as the interpreter does (see
+//!   [`crate::interp`]), wrapping at 16 bits; a division by zero is left for
+//!   the program to fail on.
+//! - **Branches**: a compare of two constants followed by a conditional jump
+//!   becomes a jump to the branch taken, and a switch on a constant a jump
+//!   to its case.
+//!
+//! ```text
+//!     MOV   (ix-2), 5                 MOV   (ix-2), 5
+//!     ADD   t0, (ix-2), 1             MOV   t0, 6
+//!     MUL   t1, t0, 4                 MOV   t1, 24
+//!     CMP   t1, 20, Signed            JUMP  big
+//!     CJUMP Greater, big, small
+//! ```
+//!
+//! Blocks left unreachable stay for dead code elimination. Functions built
+//! under `{$OPTIMIZATION OFF}` are left alone.
+
+use std::collections::HashMap;
+
+use crate::interp::{compare, evaluate};
+use crate::{global_symbol, Condition, Function, Instruction, Opcode, Program, Value};
+
+/// Fold the constants of the functions of `program` that may be optimized.
+/// Returns the number of instructions changed.
+pub fn fold_constants(program: &mut Program) -> usize {
+    program.functions.iter_mut().filter(|function| function.optimize).map(fold_function).sum()
+}
+
+/// Fold the constants of `function`
+fn fold_function(function: &mut Function) -> usize {
+    let mut changed = 0;
+    // Temporaries defined once, and the constant each holds once known;
+    // folding one may make another constant, so repeat until none is
+    let single = single_definitions(function);
+    let mut constants: HashMap<usize, i32> = HashMap::new();
+    loop {
+        let known_before = constants.len();
+        for block in &mut function.blocks {
+            changed += fold_block(&mut block.instructions, &single, &mut constants);
+        }
+        if constants.len() == known_before {
+            return changed;
+        }
+    }
+}
+
+/// Fold the constants of a block's `code`, recording in `constants` those
+/// found for temporaries in `single`
+fn fold_block(code: &mut Vec<Instruction>, single: &HashMap<usize, usize>, constants: &mut HashMap<usize, i32>) -> usize {
+    let mut changed = 0;
+    // Temporaries defined more than once, and frame slots by offset
+    let mut local_temps: HashMap<usize, i32> = HashMap::new();
+    let mut slots: HashMap<i32, i32> = HashMap::new();
+    let mut i = 0;
+    while i < code.len() {
+        let inst = &mut code[i];
+        if substitutes(&inst.opcode) {
+            let first = usize::from(writes_first(&inst.opcode));
+            for operand in inst.operands.iter_mut().skip(first) {
+                let known = match operand {
+                    Value::Temp(temp) => constants.get(temp).or_else(|| local_temps.get(temp)).copied(),
+                    Value::Memory { base, offset } if base == FRAME_BASE => slots.get(offset).copied(),
+                    _ => None,
+                };
+                if let Some(value) = known {
+                    *operand = Value::Immediate(value);
+                    changed += 1;
+                }
+            }
+        }
+
+        if let Some(value) = folded(inst)
+            && inst.opcode != Opcode::Mov
+        {
+            inst.opcode = Opcode::Mov;
+            inst.operands.truncate(1);
+            inst.operands.push(Value::Immediate(value));
+            changed += 1;
+        }
+        if let Some(target) = branch_taken(code, i) {
+            // A compare only sets the flags of the jump after it
+            let end = if code[i].opcode == Opcode::Cmp { i + 2 } else { i + 1 };
+            let mut jump = Instruction::new(Opcode::Jump, vec![target]);
+            jump.span = code[end - 1].span;
+            code.splice(i..end, [jump]);
+            changed += 1;
+            i += 1;
+            continue;
+        }
+
+        // What is known after the instruction
+        let inst = &code[i];
+        let constant = match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::Mov | Opcode::Store, [_, Value::Immediate(value)]) => Some(*value),
+            _ => None,
+        };
+        if !keeps_memory(&inst.opcode) {
+            slots.clear();
+        }
+        for written in written_operands(inst) {
+            match written {
+                Value::Temp(temp) => {
+                    local_temps.remove(temp);
+                    if let Some(value) = constant {
+                        if single.get(temp) == Some(&1) {
+                            constants.insert(*temp, value);
+                        } else {
+                            local_temps.insert(*temp, value);
+                        }
+                    }
+                }
+                Value::Memory { base, offset } if base == FRAME_BASE => {
+                    // A write of up to four bytes at `offset`
+                    slots.retain(|slot, _| *slot + 1 < *offset || *slot > *offset + 3);
+                    if let Some(value) = constant {
+                        slots.insert(*offset, value);
+                    }
+                }
+                // Globals lie outside every frame
+                Value::Memory { base, .. } if global_symbol(base).is_some() => {}
+                Value::Memory { .. } => slots.clear(),
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Base of frame slots: other bases may alias them
+pub(crate) const FRAME_BASE: &str = "ix";
+
+/// The constant `inst` computes, when its operands are all constants
+fn folded(inst: &Instruction) -> Option<i32> {
+    let [_, sources @ ..] = inst.operands.as_slice() else {
+        return None;
+    };
+    if !matches!(
+        inst.opcode,
+        Opcode::Mov | Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::DivU | Opcode::Mod
+            | Opcode::ModU | Opcode::Shl | Opcode::Shr | Opcode::Sar | Opcode::BAdd | Opcode::BSub
+            | Opcode::BShl | Opcode::BShr
+    ) {
+        return None;
+    }
+    let values = sources
+        .iter()
+        .map(|source| match source {
+            Value::Immediate(value) => Some(*value as u16),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    evaluate(&inst.opcode, &values).map(|value| value as i16 as i32)
+}
+
+/// The label the branch at `code[i]` always takes: a compare of constants
+/// followed by a conditional jump, or a switch on a constant
+fn branch_taken(code: &[Instruction], i: usize) -> Option<Value> {
+    match (&code[i].opcode, code[i].operands.as_slice()) {
+        (Opcode::Cmp, [Value::Immediate(left), Value::Immediate(right), rest @ ..]) => {
+            let jump = code.get(i + 1).filter(|next| next.opcode == Opcode::CJump)?;
+            let [Value::Condition(condition), if_true, if_false] = jump.operands.as_slice() else {
+                return None;
+            };
+            let (left, right) = (*left as u16, *right as u16);
+            let (zero, carry) = match rest {
+                [Value::Compare(kind)] => compare(*kind, left, right),
+                _ => (left as u8 == right as u8, (left as u8) < (right as u8)),
+            };
+            let taken = match condition {
+                Condition::Equal => zero,
+                Condition::NotEqual => !zero,
+                Condition::Less => carry,
+                Condition::GreaterEqual => !carry,
+                Condition::Greater => !carry && !zero,
+                Condition::LessEqual => carry || zero,
+            };
+            Some(if taken { if_true.clone() } else { if_false.clone() })
+        }
+        (Opcode::Switch, [Value::Immediate(selector), default, ranges @ ..]) => {
+            // As the interpreter: ranges of signed selectors may be negative
+            let within = |low: &Value, high: &Value| match (low, high) {
+                (Value::Immediate(low), Value::Immediate(high)) => {
+                    let selector = *selector as u16;
+                    (low..=high).contains(&&(selector as i32)) || (low..=high).contains(&&(selector as i16 as i32))
+                }
+                _ => false,
+            };
+            let range = ranges.chunks(3).find(|range| matches!(range, [low, high, _] if within(low, high)));
+            Some(range.map_or(default, |range| &range[2]).clone())
+        }
+        _ => None,
+    }
+}
+
+/// Number of instructions defining each temporary of `function`
+pub(crate) fn single_definitions(function: &Function) -> HashMap<usize, usize> {
+    let mut definitions: HashMap<usize, usize> = HashMap::new();
+    for inst in function.blocks.iter().flat_map(|block| &block.instructions) {
+        for written in written_operands(inst) {
+            if let Value::Temp(temp) = written {
+                *definitions.entry(*temp).or_default() += 1;
+            }
+        }
+    }
+    definitions
+}
+
+/// Whether the first operand of `opcode` is one it writes. Anything not
+/// known to only read it is taken as writing it
+fn writes_first(opcode: &Opcode) -> bool {
+    !matches!(
+        opcode,
+        Opcode::Cmp
+            | Opcode::LCmp
+            | Opcode::FCmp
+            | Opcode::Push
+            | Opcode::Ret
+            | Opcode::Switch
+            | Opcode::Raise
+            | Opcode::Out
+            | Opcode::StoreAt
+            | Opcode::StrCopyAt
+            | Opcode::Write
+            | Opcode::Jump
+            | Opcode::CJump
+    )
+}
+
+/// Operands `inst` writes
+pub(crate) fn written_operands(inst: &Instruction) -> Vec<&Value> {
+    match inst.opcode {
+        Opcode::ReadLn => inst.operands.iter().step_by(2).collect(),
+        ref opcode if writes_first(opcode) => inst.operands.first().into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Whether constants may be substituted for the operands `opcode` reads
+fn substitutes(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Mov | Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::DivU | Opcode::Mod
+            | Opcode::ModU | Opcode::Shl | Opcode::Shr | Opcode::Sar | Opcode::BAdd | Opcode::BSub
+            | Opcode::BShl | Opcode::BShr | Opcode::Store | Opcode::Cmp | Opcode::Switch | Opcode::Out
+    )
+}
+
+/// Whether `opcode` writes no memory but its written operand
+pub(crate) fn keeps_memory(opcode: &Opcode) -> bool {
+    substitutes(opcode)
+        || matches!(
+            opcode,
+            Opcode::Load | Opcode::LoadAt | Opcode::Jump | Opcode::CJump | Opcode::In | Opcode::Write | Opcode::WriteLn | Opcode::Push
+                | Opcode::Pop
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret, State};
+    use crate::BasicBlock;
+    use types::ComparisonKind;
+
+    fn inst(opcode: Opcode, operands: Vec<Value>) -> Instruction {
+        Instruction::new(opcode, operands)
+    }
+
+    fn slot(offset: i32) -> Value {
+        Value::Memory { base: "ix".to_string(), offset }
+    }
+
+    fn label(name: &str) -> Value {
+        Value::Label(name.to_string())
+    }
+
+    /// The example of the module documentation, with the two branches
+    /// writing the result
+    fn program() -> Program {
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = vec![
+            inst(Opcode::Mov, vec![slot(-2), Value::Immediate(5)]),
+            inst(Opcode::Add, vec![Value::Temp(0), slot(-2), Value::Immediate(1)]),
+            inst(Opcode::Mul, vec![Value::Temp(1), Value::Temp(0), Value::Immediate(4)]),
+            inst(Opcode::Cmp, vec![Value::Temp(1), Value::Immediate(20), Value::Compare(ComparisonKind::Signed)]),
+            inst(Opcode::CJump, vec![Value::Condition(Condition::Greater), label("big"), label("small")]),
+        ];
+        for (name, case) in [("big", 1), ("small", 2)] {
+            let mut block = BasicBlock::new(name.to_string());
+            block.add_instruction(inst(Opcode::Sub, vec![slot(-4), Value::Temp(1), Value::Immediate(case)]));
+            block.add_instruction(inst(Opcode::Ret, vec![]));
+            function.add_block(block);
+        }
+        let mut program = Program::new();
+        program.functions.push(function);
+        program
+    }
+
+    #[test]
+    fn test_folding_propagates_constants_and_branches() {
+        let original = program();
+        let mut folded = original.clone();
+        assert!(fold_constants(&mut folded) > 0);
+        let function = &folded.functions[0];
+        let code: Vec<_> = function.blocks[0].instructions.iter().map(|i| (i.opcode.clone(), i.operands.clone())).collect();
+        assert_eq!(
+            code,
+            vec![
+                (Opcode::Mov, vec![slot(-2), Value::Immediate(5)]),
+                (Opcode::Mov, vec![Value::Temp(0), Value::Immediate(6)]),
+                (Opcode::Mov, vec![Value::Temp(1), Value::Immediate(24)]),
+                (Opcode::Jump, vec![label("big")]),
+            ]
+        );
+        // t1 is known in other blocks: it is defined once
+        assert_eq!(function.blocks[1].instructions[0].operands, [slot(-4), Value::Immediate(23)]);
+
+        let run = |program: &Program| {
+            let mut state = State::default();
+            interpret(&program.functions[0], &mut state, 100).unwrap();
+            state
+        };
+        assert_eq!(run(&original), run(&folded));
+
+        // Left alone under {$OPTIMIZATION OFF}
+        let mut unoptimized = original.clone();
+        unoptimized.functions[0].optimize = false;
+        assert_eq!(fold_constants(&mut unoptimized), 0);
+    }
+
+    #[test]
+    fn test_unknown_writes_forget_slots() {
+        let mut folded = program();
+        let code = &mut folded.functions[0].blocks[0].instructions;
+        code.insert(1, inst(Opcode::Call, vec![label("p")]));
+        code.push(inst(Opcode::Switch, vec![Value::Immediate(-1), label("small"), Value::Immediate(-5), Value::Immediate(0), label("big")]));
+        fold_constants(&mut folded);
+        let code = &folded.functions[0].blocks[0].instructions;
+        // The call may change (ix-2), so nothing after it is known
+        assert_eq!(code[2].opcode, Opcode::Add);
+        assert_eq!(code[4].opcode, Opcode::Cmp);
+        // -1 is within -5..0
+        assert_eq!(code[6].operands, [label("big")]);
+    }
+}
@@ -0,0 +1,358 @@
+//! Common subexpression elimination within basic blocks
+//!
+//! Each block is value numbered: every value read gets a number, a copy
+//! shares the number of what it copies, and an instruction computing a
+//! pure function of numbered operands is looked up in a table of those
+//! computed earlier in the block. One found still held by its temporary
+//! is not computed again:
+//!
+//! ```text
+//!     SHL   t0, (ix-2), 1             SHL   t0, (ix-2), 1
+//!     ADD   t1, t0, 6                 ADD   t1, t0, 6
+//!     LOAD  t2, t1                    LOAD  t2, t1
+//!     MOV   t3, (ix-2)
+//!     SHL   t4, t3, 1
+//!     ADD   t5, 6, t4                 ; t5 is t1
+//!     LOAD  t6, t5                    ; t6 is t2
+//!     ADD   t7, t2, t6                ADD   t7, t2, t2
+//! ```
+//!
+//! - **Pure operations**: arithmetic, shifts, conversions and loads. The
+//!   operands of commutative operations are ordered, so `x + 6` and `6 + x`
+//!   match. A load also depends on memory, so it only matches one with no
+//!   possible write to memory in between.
+//! - **Writes**: a written temporary or frame slot gets a new number, so
+//!   nothing computed from its old value matches it. Any instruction that
+//!   may write memory it does not name (a store through a pointer, a call,
+//!   a string or set operation) renumbers every memory operand and register,
+//!   and every temporary it names.
+//! - **Pure calls**: a call of one of the program's pure functions (see
+//!   [`Program::pure_functions`]), `CALL f, args..., result`, is computed
+//!   from its label and arguments like an arithmetic operation and writes
+//!   no memory. One whose result nothing in the function reads is removed.
+//! - **Reuse**: a temporary defined once whose value an earlier one defined
+//!   once already holds is replaced by it everywhere, so the backend keeps
+//!   the earlier result in its register or spill slot. Others are given a
+//!   copy of the earlier result instead.
+//!
+//! Only run at `-O2`. Functions built under `{$OPTIMIZATION OFF}` are left
+//! alone.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::constfold::{keeps_memory, single_definitions, written_operands, FRAME_BASE};
+use crate::{Function, Instruction, Opcode, Program, Value};
+
+/// Eliminate the common subexpressions of the functions of `program` that
+/// may be optimized. Returns the number of instructions no longer computed.
+pub fn eliminate_common_subexpressions(program: &mut Program) -> usize {
+    let pure_functions = &program.pure_functions;
+    program
+        .functions
+        .iter_mut()
+        .filter(|function| function.optimize)
+        .map(|function| eliminate_in_function(function, pure_functions))
+        .sum()
+}
+
+/// Eliminate the common subexpressions of `function`
+fn eliminate_in_function(function: &mut Function, pure_functions: &[String]) -> usize {
+    let single = single_definitions(function);
+    let mut eliminated = 0;
+    let mut renamed: HashMap<usize, usize> = HashMap::new();
+    for block in &mut function.blocks {
+        let mut numbering = Numbering { pure_functions, ..Numbering::default() };
+        for inst in &mut block.instructions {
+            let Some(holder) = numbering.visit(inst) else {
+                continue;
+            };
+            eliminated += 1;
+            let result = result(inst, pure_functions).clone();
+            match (&result, &holder) {
+                (Value::Temp(temp), Value::Temp(earlier)) if single.get(temp) == Some(&1) && single.get(earlier) == Some(&1) => {
+                    renamed.insert(*temp, *earlier);
+                }
+                _ => {}
+            }
+            inst.opcode = Opcode::Mov;
+            inst.operands = vec![result, holder];
+        }
+    }
+    eliminated += remove_unused_calls(function, pure_functions);
+    if renamed.is_empty() {
+        return eliminated;
+    }
+
+    // Drop the copies of renamed temporaries, and read the earlier ones
+    for block in &mut function.blocks {
+        block.instructions.retain(|inst| {
+            inst.opcode != Opcode::Mov || !matches!(inst.operands.first(), Some(Value::Temp(temp)) if renamed.contains_key(temp))
+        });
+        for operand in block.instructions.iter_mut().flat_map(|inst| &mut inst.operands) {
+            if let Value::Temp(temp) = operand
+                && let Some(earlier) = renamed.get(temp)
+            {
+                *temp = *earlier;
+            }
+        }
+    }
+    eliminated
+}
+
+/// Drop the calls of pure functions whose results `function` never reads;
+/// returns the number dropped
+fn remove_unused_calls(function: &mut Function, pure_functions: &[String]) -> usize {
+    let mut removed = 0;
+    loop {
+        let mut read = HashSet::new();
+        for inst in function.blocks.iter().flat_map(|block| &block.instructions) {
+            let written: Vec<&Value> =
+                if is_pure_call(inst, pure_functions) { vec![result(inst, pure_functions)] } else { written_operands(inst) };
+            for operand in &inst.operands {
+                if let Value::Temp(temp) = operand
+                    && !written.contains(&operand)
+                {
+                    read.insert(*temp);
+                }
+            }
+        }
+        let before = removed;
+        for block in &mut function.blocks {
+            let count = block.instructions.len();
+            block.instructions.retain(|inst| {
+                !is_pure_call(inst, pure_functions) || matches!(result(inst, pure_functions), Value::Temp(temp) if read.contains(temp))
+            });
+            removed += count - block.instructions.len();
+        }
+        if removed == before {
+            return removed;
+        }
+    }
+}
+
+/// Whether `inst` calls one of `pure_functions` for a result
+fn is_pure_call(inst: &Instruction, pure_functions: &[String]) -> bool {
+    matches!((&inst.opcode, inst.operands.as_slice()), (Opcode::Call, [Value::Label(label), .., Value::Temp(_)])
+        if pure_functions.iter().any(|name| name.eq_ignore_ascii_case(label)))
+}
+
+/// The operand `inst` computes: the last of a pure call, else the first
+fn result<'a>(inst: &'a Instruction, pure_functions: &[String]) -> &'a Value {
+    if is_pure_call(inst, pure_functions) {
+        &inst.operands[inst.operands.len() - 1]
+    } else {
+        &inst.operands[0]
+    }
+}
+
+/// Value numbers of a block so far
+#[derive(Default)]
+struct Numbering<'a> {
+    /// Functions whose calls compute like operations
+    pure_functions: &'a [String],
+    numbers: HashMap<Value, usize>,
+    /// The number and holder of each computation: an opcode, the numbers of
+    /// its operands, and for loads the memory epoch
+    computed: HashMap<(Opcode, Vec<usize>, usize), (usize, Value)>,
+    /// Incremented by every possible write to memory
+    epoch: usize,
+    next: usize,
+}
+
+impl Numbering<'_> {
+    /// Number `inst`, returning the value holding what it computes if an
+    /// earlier instruction computed it
+    fn visit(&mut self, inst: &Instruction) -> Option<Value> {
+        let key = self.key(inst);
+        let pure_call = is_pure_call(inst, self.pure_functions);
+        if let Some(key) = &key
+            && let Some((number, holder)) = self.computed.get(key).cloned()
+            && self.numbers.get(&holder) == Some(&number)
+            && holder != *result(inst, self.pure_functions)
+        {
+            self.numbers.insert(result(inst, self.pure_functions).clone(), number);
+            return Some(holder);
+        }
+
+        // A copy shares the number of its source
+        let copied = match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::Mov, [_, source]) => Some(self.number(source)),
+            _ => None,
+        };
+        if !keeps_memory(&inst.opcode) && !pure(&inst.opcode) && !pure_call {
+            self.epoch += 1;
+            self.numbers.retain(|value, _| !matches!(value, Value::Memory { .. } | Value::Register(_)));
+            // Calls and the like may also return values in any temporary
+            // they name
+            for operand in &inst.operands {
+                if let Value::Temp(_) = operand {
+                    let number = self.fresh();
+                    self.numbers.insert(operand.clone(), number);
+                }
+            }
+        }
+        let written = if pure_call { vec![result(inst, self.pure_functions)] } else { written_operands(inst) };
+        for written in written {
+            match written {
+                Value::Memory { base, offset } if base == FRAME_BASE => {
+                    // A write of up to four bytes at `offset`
+                    self.numbers.retain(|value, _| {
+                        !matches!(value, Value::Memory { base, offset: slot }
+                            if base == FRAME_BASE && *slot + 1 >= *offset && *slot <= *offset + 3)
+                    });
+                }
+                Value::Memory { .. } => {
+                    self.epoch += 1;
+                    self.numbers.retain(|value, _| !matches!(value, Value::Memory { .. }));
+                }
+                _ => {}
+            }
+            let number = copied.unwrap_or_else(|| self.fresh());
+            self.numbers.insert(written.clone(), number);
+        }
+        if let Some(key) = key {
+            let holder = result(inst, self.pure_functions).clone();
+            self.computed.insert(key, (self.numbers[&holder], holder));
+        }
+        None
+    }
+
+    /// What a pure instruction computes
+    fn key(&mut self, inst: &Instruction) -> Option<(Opcode, Vec<usize>, usize)> {
+        let sources = match inst.operands.as_slice() {
+            [sources @ .., Value::Temp(_)] if is_pure_call(inst, self.pure_functions) => sources,
+            [Value::Temp(_), sources @ ..] if pure(&inst.opcode) => sources,
+            _ => return None,
+        };
+        if sources.is_empty() {
+            return None;
+        }
+        let mut numbers: Vec<usize> = sources.iter().map(|source| self.number(source)).collect();
+        if commutative(&inst.opcode) {
+            numbers.sort_unstable();
+        }
+        let epoch = if matches!(inst.opcode, Opcode::Load | Opcode::LoadAt) { self.epoch } else { 0 };
+        Some((inst.opcode.clone(), numbers, epoch))
+    }
+
+    /// The number of `value`, numbering it if it is new
+    fn number(&mut self, value: &Value) -> usize {
+        if let Some(number) = self.numbers.get(value) {
+            return *number;
+        }
+        let number = self.fresh();
+        self.numbers.insert(value.clone(), number);
+        number
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next
+    }
+}
+
+/// Whether `opcode` computes its first operand from the others alone (and
+/// memory, for a load)
+fn pure(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::DivU | Opcode::Mod | Opcode::ModU
+            | Opcode::Shl | Opcode::Shr | Opcode::Sar | Opcode::BAdd | Opcode::BSub | Opcode::BShl | Opcode::BShr
+            | Opcode::FAdd | Opcode::FSub | Opcode::FMul | Opcode::FDiv | Opcode::IToF | Opcode::FTrunc
+            | Opcode::FRound | Opcode::LAdd | Opcode::LSub | Opcode::LMul | Opcode::LDiv | Opcode::LDivU
+            | Opcode::LMod | Opcode::LModU | Opcode::SExt | Opcode::ZExt | Opcode::Load | Opcode::LoadAt
+    )
+}
+
+fn commutative(opcode: &Opcode) -> bool {
+    matches!(opcode, Opcode::Add | Opcode::Mul | Opcode::BAdd | Opcode::FAdd | Opcode::FMul | Opcode::LAdd | Opcode::LMul)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret, State};
+
+    fn inst(opcode: Opcode, operands: Vec<Value>) -> Instruction {
+        Instruction::new(opcode, operands)
+    }
+
+    fn slot(offset: i32) -> Value {
+        Value::Memory { base: "ix".to_string(), offset }
+    }
+
+    fn temp(n: usize) -> Value {
+        Value::Temp(n)
+    }
+
+    #[test]
+    fn test_repeated_address_computations_are_reused() {
+        // The example of the module documentation, then the same address
+        // after the slot it is computed from changes
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = vec![
+            inst(Opcode::Shl, vec![temp(0), slot(-2), Value::Immediate(1)]),
+            inst(Opcode::Add, vec![temp(1), temp(0), Value::Immediate(6)]),
+            inst(Opcode::Load, vec![temp(2), temp(1)]),
+            inst(Opcode::Mov, vec![temp(3), slot(-2)]),
+            inst(Opcode::Shl, vec![temp(4), temp(3), Value::Immediate(1)]),
+            inst(Opcode::Add, vec![temp(5), Value::Immediate(6), temp(4)]),
+            inst(Opcode::Load, vec![temp(6), temp(5)]),
+            inst(Opcode::Add, vec![temp(7), temp(2), temp(6)]),
+            inst(Opcode::Mov, vec![slot(-4), temp(7)]),
+            inst(Opcode::Mov, vec![slot(-2), Value::Immediate(3)]),
+            inst(Opcode::Shl, vec![temp(8), slot(-2), Value::Immediate(1)]),
+            inst(Opcode::Add, vec![temp(9), temp(8), Value::Immediate(6)]),
+            inst(Opcode::Mov, vec![slot(-6), temp(9)]),
+        ];
+        let mut program = Program::new();
+        program.functions.push(function);
+        let run = |program: &Program| {
+            let mut state = State::default();
+            state.write(&slot(-2), 5).unwrap();
+            state.write(&slot(16), 1000).unwrap();
+            interpret(&program.functions[0], &mut state, 100).unwrap();
+            (state.read(&slot(-4)).unwrap(), state.read(&slot(-6)).unwrap())
+        };
+        let before = run(&program);
+        let mut unoptimized = program.clone();
+
+        assert_eq!(eliminate_common_subexpressions(&mut program), 3);
+        assert_eq!(run(&program), before);
+        let code = &program.functions[0].blocks[0].instructions;
+        assert_eq!(code.len(), 10);
+        assert_eq!(code[4].operands, [temp(7), temp(2), temp(2)]);
+        // Not the same address once (ix-2) is 3
+        assert_eq!(code[7].operands, [temp(8), slot(-2), Value::Immediate(1)]);
+
+        unoptimized.functions[0].optimize = false;
+        assert_eq!(eliminate_common_subexpressions(&mut unoptimized), 0);
+    }
+
+    #[test]
+    fn test_pure_calls_are_reused_and_dropped() {
+        let label = |name: &str| Value::Label(name.to_string());
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = vec![
+            inst(Opcode::Mov, vec![temp(0), slot(-2)]),
+            inst(Opcode::Call, vec![label("Cube"), temp(0), temp(1)]),
+            inst(Opcode::Call, vec![label("Cube"), slot(-2), temp(2)]),
+            inst(Opcode::Add, vec![temp(3), temp(1), temp(2)]),
+            inst(Opcode::Mov, vec![slot(-4), temp(3)]),
+            inst(Opcode::Call, vec![label("Cube"), Value::Immediate(5), temp(4)]),
+            inst(Opcode::Call, vec![label("Log"), temp(0), temp(5)]),
+            inst(Opcode::Call, vec![label("Cube"), slot(-2), temp(6)]),
+        ];
+        let mut program = Program::new();
+        program.functions.push(function);
+        program.pure_functions = vec!["cube".to_string()];
+
+        // The second call reuses the first; the unused ones go
+        assert_eq!(eliminate_common_subexpressions(&mut program), 3);
+        let code = &program.functions[0].blocks[0].instructions;
+        assert_eq!(code.len(), 6);
+        assert_eq!(code[2], inst(Opcode::Mov, vec![temp(2), temp(1)]));
+        // Log may have changed (ix-2), and its own result stays
+        assert_eq!(code[5].operands[0], label("Log"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Exception lowering
+//!
+//! A try statement pushes an exception frame naming its handler block and
+//! pops it again when the protected statements finish. `raise` passes the
+//! value and the descriptor of its declared type to the runtime, which
+//! unwinds to the innermost frame: it restores the stack and frame pointer
+//! saved in the frame, pops it, and jumps to the handler. Handlers compare
+//! the descriptor with theirs; an exception nothing handles is raised again
+//! to the enclosing frame.
+//!
+//! ```text
+//!     try A except on E: EParse do B else C end
+//!
+//!     TRYENTER try_handler
+//!     <A>
+//!     TRYLEAVE
+//!     JUMP try_end
+//! try_handler:
+//!     EXCIS __exc_type_eparse
+//!     CJUMP EQ, try_on, try_next
+//! try_on:
+//!     EXCVALUE E
+//!     <B>
+//!     JUMP try_end
+//! try_next:
+//!     <C>                         ; RERAISE without an else part
+//! try_end:
+//! ```
+//!
+//! A finally part is built twice: after the protected statements, and in
+//! the handler, which then raises the exception again.
+
+use ast::Node;
+use types::Type;
+
+use crate::{Condition, IRBuilder, Instruction, Opcode, Value};
+
+/// Descriptor identifying exceptions of type `ty`: user types by name, so
+/// aliases of one type are distinct exceptions
+pub fn exception_type_label(ty: &Type) -> String {
+    let name = match ty {
+        Type::Named { name } => name.to_ascii_lowercase(),
+        Type::Primitive(prim) => format!("{:?}", prim).to_ascii_lowercase(),
+        Type::Pointer { .. } | Type::UntypedPointer => "pointer".to_string(),
+        _ => "value".to_string(),
+    };
+    format!("__exc_type_{}", name)
+}
+
+impl IRBuilder {
+    /// Build a try statement with an except or finally part
+    pub(crate) fn build_try_stmt(&mut self, try_stmt: &ast::TryStmt) {
+        let handler_label = self.new_label("try_handler");
+        let end_label = self.new_label("try_end");
+
+        self.emit(Instruction::new(Opcode::TryEnter, vec![Value::Label(handler_label.clone())]).with_span(try_stmt.span));
+        for stmt in &try_stmt.try_block {
+            self.build_node(stmt);
+        }
+        self.emit(Instruction::new(Opcode::TryLeave, vec![]));
+        for stmt in try_stmt.finally_block.iter().flatten() {
+            self.build_node(stmt);
+        }
+        self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(end_label.clone())]));
+
+        self.start_block(handler_label);
+        if let Some(finally_block) = &try_stmt.finally_block {
+            for stmt in finally_block {
+                self.build_node(stmt);
+            }
+            self.emit(Instruction::new(Opcode::Reraise, vec![]));
+        } else if let Some(except_block) = &try_stmt.except_block {
+            for stmt in except_block {
+                self.build_node(stmt);
+            }
+        } else {
+            for handler in &try_stmt.exception_handlers {
+                let handler_type = self.analyze_type_expr(&handler.exception_type);
+                let on_label = self.new_label("try_on");
+                let next_label = self.new_label("try_next");
+                self.emit(Instruction::new(
+                    Opcode::ExcIs,
+                    vec![Value::Label(exception_type_label(&handler_type))],
+                ));
+                self.emit(Instruction::new(
+                    Opcode::CJump,
+                    vec![
+                        Value::Condition(Condition::Equal),
+                        Value::Label(on_label.clone()),
+                        Value::Label(next_label.clone()),
+                    ],
+                ));
+                self.start_block(on_label);
+                if let Some(variable) = &handler.variable {
+                    self.variable_types.insert(variable.clone(), handler_type.clone());
+                    if self.routine.is_some() {
+                        self.allocate_local(variable, &handler_type);
+                    }
+                    let target = self.get_variable_address(variable);
+                    self.emit(Instruction::new(Opcode::ExcValue, vec![target]));
+                }
+                self.build_node(&handler.handler);
+                self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(end_label.clone())]));
+                self.start_block(next_label);
+            }
+            match &try_stmt.exception_else {
+                Some(else_stmt) => self.build_node(else_stmt),
+                None => self.emit(Instruction::new(Opcode::Reraise, vec![])),
+            }
+        }
+
+        self.start_block(end_label);
+    }
+
+    /// Build `raise E`, or a bare `raise` of the exception being handled
+    pub(crate) fn build_raise_stmt(&mut self, raise: &ast::RaiseStmt) {
+        let Some(exception) = &raise.exception else {
+            self.emit(Instruction::new(Opcode::Reraise, vec![]).with_span(raise.span));
+            return;
+        };
+        let (value, ty) = match exception.as_ref() {
+            // A typecast names the exception type: raise EParse(Line)
+            Node::CallExpr(call) if call.args.len() == 1 && self.type_decls.contains_key(&call.name) => {
+                (self.build_expression(&call.args[0]), Type::named(call.name.clone()))
+            }
+            Node::IdentExpr(ident) if let Some(ty) = self.variable_types.get(&ident.name).cloned() => {
+                (self.build_expression(exception), ty)
+            }
+            _ => {
+                let ty = self.analyze_expression_type(exception).unwrap_or(Type::integer());
+                (self.build_expression(exception), ty)
+            }
+        };
+        self.emit(
+            Instruction::new(Opcode::Raise, vec![value, Value::Label(exception_type_label(&ty))]).with_span(raise.span),
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Interface method calls and queries
+//!
+//! An interface reference is the instance itself. A call names the
+//! interface by the label of its descriptor and the method by its slot in
+//! the interface's method table (IMT); the table is found at run time
+//! among those of the instance's class:
+//!
+//! ```text
+//!     I.Draw                      CALLINTF __iid_IShape, 0, I
+//!     N := I.Area(2)              CALLINTF __iid_IShape, 1, I, 2
+//!                                 MOV t0, hl
+//!     F := X is IShape            INTFIS t1, X, __iid_IShape
+//!     I := X as IShape            INTFAS t2, X, __iid_IShape  ; X, or nil
+//! ```
+
+use ast::Node;
+use types::{ClassMethod, InterfaceLayout, Type};
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+impl IRBuilder {
+    /// Use the analyzer's interface layouts, to find the IMT slots of methods
+    pub fn set_interface_layouts(&mut self, layouts: &[InterfaceLayout]) {
+        self.interface_layouts = layouts.to_vec();
+    }
+
+    fn interface_layout(&self, name: &str) -> Option<&InterfaceLayout> {
+        self.interface_layouts.iter().find(|layout| layout.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Interface of the reference `object`, if it has an interface type
+    pub(crate) fn object_interface(&self, object: &Node) -> Option<&InterfaceLayout> {
+        match self.analyze_expression_type(object)? {
+            Type::Interface { name } | Type::Named { name } => self.interface_layout(&name),
+            _ => None,
+        }
+    }
+
+    /// Call the interface method `method` on the instance `object`
+    pub(crate) fn emit_interface_call(&mut self, method: &ClassMethod, iid: Value, object: Value, args: &[Node], span: tokens::Span) {
+        let mut operands = vec![iid, Value::Immediate(method.slot.unwrap_or(0) as i32), object];
+        for arg in args {
+            operands.push(self.build_expression(arg));
+        }
+        self.emit(Instruction::new(Opcode::CallIntf, operands).with_span(span));
+    }
+
+    /// Build `X is I` or `X as I` when `I` names an interface
+    pub(crate) fn build_interface_query(&mut self, bin: &ast::BinaryExpr) -> Option<Value> {
+        let opcode = match bin.op {
+            ast::BinaryOp::Is => Opcode::IntfIs,
+            ast::BinaryOp::As => Opcode::IntfAs,
+            _ => return None,
+        };
+        let Node::IdentExpr(ident) = bin.right.as_ref() else {
+            return None;
+        };
+        if self.variable_types.contains_key(&ident.name) {
+            return None;
+        }
+        let iid = Value::Label(types::interface_id_symbol(&self.interface_layout(&ident.name)?.name));
+        let object = self.build_expression(&bin.left);
+        let result = self.new_temp();
+        self.emit(Instruction::new(opcode, vec![result.clone(), object, iid]).with_span(bin.span));
+        Some(result)
+    }
+}
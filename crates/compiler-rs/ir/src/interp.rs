@@ -0,0 +1,937 @@
+//! IR interpreter
+//!
+//! Runs the IR of a program, to check that passes such as
+//! [`crate::reduce_strength`] and [`crate::narrow_bytes`] keep its meaning,
+//! and to run programs on the host. Registers hold 16-bit values;
+//! temporaries and variables (`Memory` operands, by base and offset) hold up
+//! to 32 bits, as LongInt, Cardinal and Real values take. The 16-bit forms
+//! compute on the low 16 bits and the 8-bit forms on the low bytes, and
+//! zero-extend their result, as the backend does. Sets and strings are
+//! bytes of their own, at the address of the variable or temporary holding
+//! them. NEW hands out numbered addresses, whose bytes and words LOADAT
+//! and STOREAT reach in a region of their own. ADDR gives each region it
+//! takes the address of a variable in a window of addresses below those,
+//! so LOADAT and STOREAT reach the variable through one, and the elements
+//! of an array through it plus their offset.
+//!
+//! A compare leaves the flags the way the backend's do: Z when equal, C when
+//! the left operand is below the right once promoted by the compare's kind.
+//! A compare without a kind compares the low bytes.
+//!
+//! A CALL of a routine with parameters or locals gets a frame of its own,
+//! its arguments at `ix+4` on (see routines.rs); other callees, such as
+//! routines outlined by [`crate::outline_sequences`], share their caller's.
+//! Every call has temporaries of its own. A raised exception unwinds to the
+//! innermost TRYENTER still active, across calls.
+//!
+//! Console I/O goes to a [`Console`] instead of a terminal: what the program
+//! writes is collected, and what it reads comes from a script of lines, so
+//! a run depends on nothing outside it. I/O ports are a map from address
+//! to byte, which unwritten ports read as 0. The host passes no program
+//! parameters: `ParamCount` gives 0 and `ParamStr` an empty string.
+
+use std::collections::{HashMap, VecDeque};
+
+use types::ComparisonKind;
+
+use crate::constfold::FRAME_BASE;
+use crate::{
+    Condition, ConsoleFormat, Function, Instruction, Opcode, Value, OVERFLOW_ERROR_ROUTINE, PARAM_BUFFER, PARAM_COUNT_ROUTINE,
+    PARAM_STR_ROUTINE, RANGE_ERROR_ROUTINE,
+};
+
+/// Values the interpreted code reads and writes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    pub registers: HashMap<String, u16>,
+    /// Variables, by the base and offset of their memory operand
+    pub memory: HashMap<(String, i32), u32>,
+    pub temps: HashMap<usize, u32>,
+    /// Bytes of the sets and strings, by region and offset
+    pub bytes: HashMap<(String, i32), u8>,
+    pub ports: HashMap<u16, u8>,
+    pub console: Console,
+    /// Frame `ix` addresses: 0 for the function run first, then one per
+    /// call, of the `frames` made so far
+    pub frame: usize,
+    pub frames: usize,
+    /// Next address NEW hands out
+    pub heap: u32,
+    /// Regions ADDR took the address of a variable in, the window of the
+    /// `n`th at [`VARIABLE_ADDRESSES`] + `n` * [`ADDRESS_WINDOW`]
+    pub addresses: Vec<String>,
+    /// The VMTs NEW may install: the symbol of each and the routine of
+    /// each of its slots. An instance's first word is 1 + the index of its
+    /// VMT here, 0 for none
+    pub vmts: Vec<(String, Vec<String>)>,
+    /// The exception being handled: its value and descriptor
+    pub exception: Option<(u32, String)>,
+}
+
+/// Console of an interpreted program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Console {
+    /// Lines READLN reads, in order
+    pub input: VecDeque<String>,
+    /// Everything written so far
+    pub output: String,
+    /// Text of the string literals, the `n`th labelled `__str_{n}`
+    pub strings: Vec<String>,
+}
+
+impl Console {
+    /// A console reading `input`, with the program's string literals
+    pub fn new(input: &[String], strings: &[String]) -> Self {
+        Self { input: input.iter().cloned().collect(), output: String::new(), strings: strings.to_vec() }
+    }
+}
+
+/// Why running a function stopped early
+enum Stop {
+    Error(String),
+    /// An exception, with its value and descriptor, no handler in the
+    /// function caught
+    Raise(u32, String),
+}
+
+impl From<String> for Stop {
+    fn from(error: String) -> Self {
+        Stop::Error(error)
+    }
+}
+
+impl From<&str> for Stop {
+    fn from(error: &str) -> Self {
+        Stop::Error(error.to_string())
+    }
+}
+
+/// Region of the memory operands holding what LOADAT and STOREAT reach,
+/// by address
+const HEAP_REGION: &str = "heap";
+
+/// Address of the bytes of a set or string: a region and an offset in it
+type Address = (String, i32);
+
+/// First of the addresses NEW hands out
+const HEAP_START: u32 = 0x8000;
+
+/// First of the addresses ADDR gives variables, below those NEW hands out
+pub const VARIABLE_ADDRESSES: u32 = 0x0800;
+/// Addresses of the variables of a region, offset 0 halfway through
+pub const ADDRESS_WINDOW: u32 = 0x0800;
+
+/// Text of the string literal `label`, if it is one
+fn literal<'a>(strings: &'a [String], label: &str) -> Option<&'a String> {
+    strings.get(label.strip_prefix("__str_")?.parse::<usize>().ok()?)
+}
+
+impl State {
+    /// Memory operands with `base` of the current frame
+    fn region(&self, base: &str) -> String {
+        if base == FRAME_BASE && self.frame > 0 {
+            format!("{}#{}", base, self.frame)
+        } else {
+            base.to_string()
+        }
+    }
+
+    pub fn read(&self, value: &Value) -> Result<u32, String> {
+        match value {
+            Value::Immediate(imm) => Ok(*imm as u32),
+            Value::Register(name) => Ok(self.registers.get(name).copied().unwrap_or(0) as u32),
+            Value::Memory { base, offset } => Ok(self.memory.get(&(self.region(base), *offset)).copied().unwrap_or(0)),
+            Value::Temp(temp) => self.temps.get(temp).copied().ok_or_else(|| format!("t{} read before it is set", temp)),
+            other => Err(format!("{:?} is not a value", other)),
+        }
+    }
+
+    pub fn write(&mut self, value: &Value, data: u32) -> Result<(), String> {
+        match value {
+            Value::Register(name) => {
+                self.registers.insert(name.clone(), data as u16);
+            }
+            Value::Memory { base, offset } => {
+                self.memory.insert((self.region(base), *offset), data);
+            }
+            Value::Temp(temp) => {
+                self.temps.insert(*temp, data);
+            }
+            other => return Err(format!("cannot write {:?}", other)),
+        }
+        Ok(())
+    }
+
+    fn read16(&self, value: &Value) -> Result<u16, String> {
+        self.read(value).map(|data| data as u16)
+    }
+
+    /// Address of the variable `value`, numbering it the first time
+    fn variable_address(&mut self, value: &Value) -> Result<u32, String> {
+        let Value::Memory { base, offset } = value else {
+            return Err(format!("{:?} is not a variable", value));
+        };
+        let region = self.region(base);
+        let index = match self.addresses.iter().position(|address| *address == region) {
+            Some(index) => index,
+            None => {
+                self.addresses.push(region.clone());
+                self.addresses.len() - 1
+            }
+        };
+        let window = VARIABLE_ADDRESSES + index as u32 * ADDRESS_WINDOW;
+        let half = (ADDRESS_WINDOW / 2) as i32;
+        if window + ADDRESS_WINDOW > HEAP_START || !(-half..half).contains(offset) {
+            return Err(format!("no address left for {}{:+}", region, offset));
+        }
+        Ok((window as i32 + half + offset) as u32)
+    }
+
+    /// Memory location LOADAT and STOREAT reach through the address
+    /// `value` holds: a variable ADDR took the address of, or else one NEW
+    /// handed out
+    fn location(&self, value: &Value) -> Result<(String, i32), String> {
+        let address = self.read(value)?;
+        if !(VARIABLE_ADDRESSES..HEAP_START).contains(&address) {
+            return Ok((HEAP_REGION.to_string(), address as u16 as i32));
+        }
+        let index = (address - VARIABLE_ADDRESSES) / ADDRESS_WINDOW;
+        let offset = ((address - VARIABLE_ADDRESSES) % ADDRESS_WINDOW) as i32 - (ADDRESS_WINDOW / 2) as i32;
+        let region = self.addresses.get(index as usize).ok_or_else(|| format!("no variable at {:#x}", address))?;
+        Ok((region.clone(), offset))
+    }
+
+    /// Where the set or string `value` is
+    fn address(&self, value: &Value) -> Result<Address, String> {
+        match value {
+            Value::Memory { base, offset } => Ok((self.region(base), *offset)),
+            Value::Temp(temp) => Ok((format!("t{}#{}", temp, self.frame), 0)),
+            Value::Label(label) => Ok((label.clone(), 0)),
+            other => Err(format!("{:?} is not an address", other)),
+        }
+    }
+
+    fn load_bytes(&self, value: &Value, count: usize) -> Result<Vec<u8>, String> {
+        let (region, offset) = self.address(value)?;
+        Ok((0..count as i32).map(|i| self.bytes.get(&(region.clone(), offset + i)).copied().unwrap_or(0)).collect())
+    }
+
+    fn store_bytes(&mut self, value: &Value, data: &[u8]) -> Result<(), String> {
+        let address = self.address(value)?;
+        self.store_bytes_at(address, data);
+        Ok(())
+    }
+
+    fn store_bytes_at(&mut self, (region, offset): Address, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.bytes.insert((region.clone(), offset + i as i32), *byte);
+        }
+    }
+
+    /// Characters of the string `value`: a literal, or a length byte and
+    /// the characters after it
+    fn string(&self, value: &Value) -> Result<Vec<u8>, String> {
+        if let Value::Label(label) = value {
+            let text = literal(&self.console.strings, label).ok_or_else(|| format!("no string {}", label))?;
+            return Ok(text.chars().map(|c| c as u8).collect());
+        }
+        let length = self.load_bytes(value, 1)?[0] as usize;
+        Ok(self.load_bytes(value, length + 1)?[1..].to_vec())
+    }
+
+    /// Store `text`, cut to `max` characters, in the string `value`
+    fn set_string(&mut self, value: &Value, text: &[u8], max: &Value) -> Result<(), String> {
+        let text = &text[..text.len().min(self.read16(max)? as usize)];
+        self.store_bytes(value, &[&[text.len() as u8], text].concat())
+    }
+
+    /// Write `value` to the console in `format`
+    fn write_console(&mut self, value: &Value, format: ConsoleFormat) -> Result<(), String> {
+        if format == ConsoleFormat::String {
+            let text = self.string(value).map_err(|_| format!("cannot write the string {:?}", value))?;
+            self.console.output.extend(text.iter().map(|c| *c as char));
+            return Ok(());
+        }
+        let data = self.read16(value)?;
+        let output = &mut self.console.output;
+        match format {
+            ConsoleFormat::Integer => output.push_str(&(data as i16).to_string()),
+            ConsoleFormat::Word => output.push_str(&data.to_string()),
+            ConsoleFormat::Char => output.push(data as u8 as char),
+            ConsoleFormat::Boolean => output.push_str(if data & 0xFF != 0 { "TRUE" } else { "FALSE" }),
+            ConsoleFormat::String => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Read the next line of the console into the (destination, format)
+    /// pairs of `operands`
+    fn read_line(&mut self, operands: &[Value]) -> Result<(), String> {
+        let line = self.console.input.pop_front().ok_or("READLN past the end of the input")?;
+        let mut fields = line.split_whitespace();
+        for pair in operands.chunks(2) {
+            let [dst, format] = pair else {
+                return Err(format!("READLN operands {:?} are not pairs", operands));
+            };
+            let format = ConsoleFormat::from_operand(format).ok_or_else(|| format!("{:?} is not a format", format))?;
+            let field = fields.next().unwrap_or("");
+            let data = match format {
+                ConsoleFormat::Char => field.chars().next().map_or(0, |c| c as u16),
+                ConsoleFormat::Boolean => match field.to_ascii_uppercase().as_str() {
+                    "TRUE" => 1,
+                    "FALSE" => 0,
+                    _ => return Err(format!("'{}' is not a Boolean", field)),
+                },
+                _ => field
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|n| (i16::MIN as i32..=u16::MAX as i32).contains(n))
+                    .ok_or_else(|| format!("'{}' is not a number", field))? as u16,
+            };
+            self.write(dst, data as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Run a set or string instruction
+    fn aggregate(&mut self, opcode: &Opcode, operands: &[Value]) -> Result<(), String> {
+        let size = |state: &State, size: &Value| state.read16(size).map(|size| size as usize);
+        let bit = |set: &[u8], element: i32| {
+            (0..8 * set.len() as i32).contains(&element) && set[element as usize / 8] & (1 << (element % 8)) != 0
+        };
+        match (opcode, operands) {
+            (Opcode::SetClear, [set, n]) => self.store_bytes(set, &vec![0; size(self, n)?]),
+            (Opcode::SetIncl, [set, first, last, n]) => {
+                let mut bytes = self.load_bytes(set, size(self, n)?)?;
+                let (first, last) = (self.read16(first)? as i16 as i32, self.read16(last)? as i16 as i32);
+                for element in first.max(0)..=last.min(8 * bytes.len() as i32 - 1) {
+                    bytes[element as usize / 8] |= 1 << (element % 8);
+                }
+                self.store_bytes(set, &bytes)
+            }
+            (Opcode::SetCopy, [dst, src, n]) => {
+                let bytes = self.load_bytes(src, size(self, n)?)?;
+                self.store_bytes(dst, &bytes)
+            }
+            (Opcode::SetUnion | Opcode::SetDiff | Opcode::SetInter, [dst, src, n]) => {
+                let n = size(self, n)?;
+                let (left, right) = (self.load_bytes(dst, n)?, self.load_bytes(src, n)?);
+                let bytes: Vec<u8> = left
+                    .iter()
+                    .zip(&right)
+                    .map(|(a, b)| match opcode {
+                        Opcode::SetUnion => a | b,
+                        Opcode::SetDiff => a & !b,
+                        _ => a & b,
+                    })
+                    .collect();
+                self.store_bytes(dst, &bytes)
+            }
+            (Opcode::SetIn, [dst, element, set, n]) => {
+                let bytes = self.load_bytes(set, size(self, n)?)?;
+                let element = self.read16(element)? as i16 as i32;
+                self.write(dst, bit(&bytes, element) as u32)
+            }
+            (Opcode::SetEq | Opcode::SetSubset, [dst, left, right, n]) => {
+                let n = size(self, n)?;
+                let (left, right) = (self.load_bytes(left, n)?, self.load_bytes(right, n)?);
+                let holds = match opcode {
+                    Opcode::SetEq => left == right,
+                    _ => left.iter().zip(&right).all(|(a, b)| a & !b == 0),
+                };
+                self.write(dst, holds as u32)
+            }
+            (Opcode::StrCopy, [dst, src, max]) => {
+                let text = self.string(src)?;
+                self.set_string(dst, &text, max)
+            }
+            (Opcode::StrCopyAt, [address, src, max]) => {
+                let text = self.string(src)?;
+                let text = &text[..text.len().min(self.read16(max)? as usize)];
+                let location = self.location(address)?;
+                self.store_bytes_at(location, &[&[text.len() as u8], text].concat());
+                Ok(())
+            }
+            (Opcode::StrChar, [dst, c]) => {
+                let c = self.read16(c)? as u8;
+                self.store_bytes(dst, &[1, c])
+            }
+            (Opcode::StrConcat, [dst, src, max]) => {
+                let text = [self.string(dst)?, self.string(src)?].concat();
+                self.set_string(dst, &text, max)
+            }
+            (Opcode::StrCmp, [dst, left, right, Value::Condition(condition)]) => {
+                let order = self.string(left)?.cmp(&self.string(right)?);
+                let holds = match condition {
+                    Condition::Equal => order.is_eq(),
+                    Condition::NotEqual => order.is_ne(),
+                    Condition::Less => order.is_lt(),
+                    Condition::LessEqual => order.is_le(),
+                    Condition::Greater => order.is_gt(),
+                    Condition::GreaterEqual => order.is_ge(),
+                };
+                self.write(dst, holds as u32)
+            }
+            (Opcode::StrLength, [dst, s]) => {
+                let length = self.string(s)?.len();
+                self.write(dst, length as u32)
+            }
+            (Opcode::StrSlice, [dst, src, index, count, max]) => {
+                let text = self.string(src)?;
+                let start = (self.read16(index)? as i16).max(1) as usize - 1;
+                let count = (self.read16(count)? as i16).max(0) as usize;
+                let slice = text.get(start..).unwrap_or(&[]);
+                let slice = slice[..count.min(slice.len())].to_vec();
+                self.set_string(dst, &slice, max)
+            }
+            (Opcode::StrPos, [dst, sub, s]) => {
+                let (sub, text) = (self.string(sub)?, self.string(s)?);
+                let position = match sub.is_empty() {
+                    true => 0,
+                    false => text.windows(sub.len()).position(|window| window == sub.as_slice()).map_or(0, |i| i + 1),
+                };
+                self.write(dst, position as u32)
+            }
+            (Opcode::StrDelete, [s, index, count]) => {
+                let mut text = self.string(s)?;
+                let start = self.read16(index)? as i16 as i32;
+                let count = self.read16(count)? as i16 as i32;
+                if start >= 1 && start as usize <= text.len() && count > 0 {
+                    let end = (start as usize - 1 + count as usize).min(text.len());
+                    text.drain(start as usize - 1..end);
+                }
+                self.set_string(s, &text, &Value::Immediate(255))
+            }
+            (Opcode::StrInsert, [s, src, index, max]) => {
+                let mut text = self.string(s)?;
+                let at = (self.read16(index)? as i16).clamp(1, text.len() as i16 + 1) as usize - 1;
+                text.splice(at..at, self.string(src)?);
+                self.set_string(s, &text, max)
+            }
+            _ => Err(format!("cannot interpret {:?} {:?}", opcode, operands)),
+        }
+    }
+}
+
+/// Run `function` from its entry block on `state` until it returns or
+/// falls off its last block, executing at most `step_limit` instructions
+pub fn interpret(function: &Function, state: &mut State, step_limit: usize) -> Result<(), String> {
+    interpret_with(function, &[], state, step_limit)
+}
+
+/// Run `function` as [`interpret`] does, running a call to one of `callees`
+/// (the routines of the program, or those outlined by
+/// [`crate::outline_sequences`]) too, for at most `step_limit` instructions
+/// in all
+pub fn interpret_with(function: &Function, callees: &[Function], state: &mut State, step_limit: usize) -> Result<(), String> {
+    let mut steps = step_limit;
+    match run(function, callees, state, &mut steps) {
+        Ok(_) => Ok(()),
+        Err(Stop::Error(error)) => Err(error),
+        Err(Stop::Raise(_, descriptor)) => Err(format!("unhandled exception {}", descriptor)),
+    }
+}
+
+/// Call `callee` with `args`, returning its result
+fn call(callee: &Function, args: &[u32], callees: &[Function], state: &mut State, steps: &mut usize) -> Result<Option<u32>, Stop> {
+    let temps = std::mem::take(&mut state.temps);
+    let frame = state.frame;
+    if !callee.params.is_empty() || !callee.locals.is_empty() {
+        state.frames += 1;
+        state.frame = state.frames;
+        let region = state.region(FRAME_BASE);
+        for (i, arg) in args.iter().enumerate() {
+            state.memory.insert((region.clone(), 4 + 2 * (args.len() - 1 - i) as i32), *arg);
+        }
+    }
+    let result = run(callee, callees, state, steps);
+    state.frame = frame;
+    state.temps = temps;
+    result
+}
+
+/// Run `function` on `state`, returning the value its RET gives
+fn run(function: &Function, callees: &[Function], state: &mut State, steps: &mut usize) -> Result<Option<u32>, Stop> {
+    let mut block = 0;
+    let mut index = 0;
+    // Z and C
+    let mut flags = (false, false);
+    // Handler blocks of the active TRYENTERs, innermost last
+    let mut handlers: Vec<usize> = vec![];
+    loop {
+        let Some(inst) = function.blocks.get(block).and_then(|b| b.instructions.get(index)) else {
+            // Fall into the next block, or off the end of the function
+            block += 1;
+            index = 0;
+            if block >= function.blocks.len() {
+                return Ok(None);
+            }
+            continue;
+        };
+        if *steps == 0 {
+            return Err(Stop::Error("no return within the step limit".to_string()));
+        }
+        *steps -= 1;
+        index += 1;
+        let jump = |label: &Value| match label {
+            Value::Label(label) => function
+                .blocks
+                .iter()
+                .position(|b| b.label == *label)
+                .ok_or_else(|| format!("no block {}", label)),
+            other => Err(format!("{:?} is not a label", other)),
+        };
+        let outcome = step(inst, callees, state, steps, &mut flags, &mut handlers, &jump);
+        match outcome {
+            Ok(Flow::Next) => {}
+            Ok(Flow::Jump(target)) => {
+                block = target;
+                index = 0;
+            }
+            Ok(Flow::Return(value)) => return Ok(value),
+            Err(Stop::Raise(value, descriptor)) if let Some(handler) = handlers.pop() => {
+                state.exception = Some((value, descriptor));
+                block = handler;
+                index = 0;
+            }
+            Err(stop) => return Err(stop),
+        }
+    }
+}
+
+/// Where running continues after an instruction
+enum Flow {
+    Next,
+    Jump(usize),
+    Return(Option<u32>),
+}
+
+fn step(
+    inst: &Instruction,
+    callees: &[Function],
+    state: &mut State,
+    steps: &mut usize,
+    flags: &mut (bool, bool),
+    handlers: &mut Vec<usize>,
+    jump: &dyn Fn(&Value) -> Result<usize, String>,
+) -> Result<Flow, Stop> {
+    let cannot = || Stop::Error(format!("cannot interpret {}", describe(inst)));
+    match (&inst.opcode, inst.operands.as_slice()) {
+        (Opcode::Jump, [target]) => return Ok(Flow::Jump(jump(target)?)),
+        (Opcode::CJump, [Value::Condition(condition), if_true, if_false]) => {
+            let (zero, carry) = *flags;
+            let taken = match condition {
+                Condition::Equal => zero,
+                Condition::NotEqual => !zero,
+                Condition::Less => carry,
+                Condition::GreaterEqual => !carry,
+                Condition::Greater => !carry && !zero,
+                Condition::LessEqual => carry || zero,
+            };
+            return Ok(Flow::Jump(jump(if taken { if_true } else { if_false })?));
+        }
+        (Opcode::Switch, [selector, default, ranges @ ..]) => {
+            // Ranges of signed selectors may be negative
+            let selector = state.read16(selector)?;
+            let within = |low: &Value, high: &Value| match (low, high) {
+                (Value::Immediate(low), Value::Immediate(high)) => {
+                    (low..=high).contains(&&(selector as i32)) || (low..=high).contains(&&(selector as i16 as i32))
+                }
+                _ => false,
+            };
+            let target = ranges
+                .chunks(3)
+                .find(|range| matches!(range, [low, high, _] if within(low, high)))
+                .map_or(default, |range| &range[2]);
+            return Ok(Flow::Jump(jump(target)?));
+        }
+        (Opcode::Ret, []) => return Ok(Flow::Return(None)),
+        (Opcode::Ret, [value]) => return Ok(Flow::Return(Some(state.read(value)?))),
+        (Opcode::Call, [Value::Label(label)]) if label == RANGE_ERROR_ROUTINE => return Err("range check error".into()),
+        (Opcode::Call, [Value::Label(label)]) if label == OVERFLOW_ERROR_ROUTINE => return Err("arithmetic overflow".into()),
+        (Opcode::Call, [Value::Label(label), result]) if label == PARAM_COUNT_ROUTINE => state.write(result, 0)?,
+        (Opcode::Call, [Value::Label(label), _]) if label == PARAM_STR_ROUTINE => {
+            state.store_bytes(&Value::Memory { base: crate::global_base(PARAM_BUFFER), offset: 0 }, &[0])?
+        }
+        (Opcode::Call, [Value::Label(label), operands @ ..]) => {
+            let callee = callees.iter().find(|callee| callee.name == *label).ok_or_else(cannot)?;
+            let (args, result) = match (&callee.return_type, operands) {
+                (Some(_), [args @ .., result]) => (args, Some(result)),
+                _ => (operands, None),
+            };
+            let args = args.iter().map(|arg| state.read(arg)).collect::<Result<Vec<_>, _>>()?;
+            let value = call(callee, &args, callees, state, steps)?;
+            if let Some(result) = result {
+                state.write(result, value.unwrap_or(0))?;
+            }
+        }
+        (Opcode::CallMethod, [target, operands @ ..]) => {
+            // A virtual method is the routine in its slot of the VMT of
+            // the instance; the result comes back in HL
+            let label = match (target, operands.first()) {
+                (Value::Label(label), _) => label,
+                (Value::Immediate(slot), Some(object)) => {
+                    let vmt = state.memory.get(&(HEAP_REGION.to_string(), state.read16(object)? as i32)).copied().unwrap_or(0);
+                    let slots = state.vmts.get((vmt as usize).wrapping_sub(1)).map(|(_, slots)| slots);
+                    slots.and_then(|slots| slots.get(*slot as usize)).ok_or_else(cannot)?
+                }
+                _ => return Err(cannot()),
+            };
+            let callee = callees.iter().find(|callee| callee.name == *label).ok_or_else(cannot)?;
+            let args = operands.iter().map(|arg| state.read(arg)).collect::<Result<Vec<_>, _>>()?;
+            let value = call(callee, &args, callees, state, steps)?;
+            state.registers.insert("hl".to_string(), value.unwrap_or(0) as u16);
+        }
+        (Opcode::New, [dst, size, vmt]) => {
+            state.heap = state.heap.max(HEAP_START);
+            let instance = state.heap;
+            state.heap += state.read16(size)?.max(1) as u32;
+            if let Value::Label(vmt) = vmt {
+                let index = state.vmts.iter().position(|(symbol, _)| symbol == vmt).ok_or_else(cannot)?;
+                state.memory.insert((HEAP_REGION.to_string(), instance as i32), index as u32 + 1);
+            }
+            state.write(dst, instance)?;
+        }
+        (Opcode::Dispose, [_]) => {}
+        (Opcode::LoadAt, [dst, address, size]) => {
+            let location = state.location(address)?;
+            let mask = if state.read16(size)? == 1 { 0xFF } else { 0xFFFF };
+            let value = state.memory.get(&location).copied().unwrap_or(0);
+            state.write(dst, value & mask)?;
+        }
+        (Opcode::StoreAt, [address, src, size]) => {
+            let location = state.location(address)?;
+            let mask = if state.read16(size)? == 1 { 0xFF } else { 0xFFFF };
+            let value = state.read(src)? & mask;
+            state.memory.insert(location, value);
+        }
+        (Opcode::Addr, [dst, variable @ Value::Memory { .. }]) => {
+            let address = state.variable_address(variable)?;
+            state.write(dst, address)?;
+        }
+        (Opcode::TryEnter, [handler]) => handlers.push(jump(handler)?),
+        (Opcode::TryLeave, []) => {
+            handlers.pop();
+        }
+        (Opcode::Raise, [value, Value::Label(descriptor)]) => return Err(Stop::Raise(state.read(value)?, descriptor.clone())),
+        (Opcode::Reraise, []) => {
+            let (value, descriptor) = state.exception.clone().ok_or("RERAISE without an exception")?;
+            return Err(Stop::Raise(value, descriptor));
+        }
+        (Opcode::ExcIs, [Value::Label(descriptor)]) => {
+            *flags = (state.exception.as_ref().is_some_and(|(_, raised)| raised == descriptor), false);
+        }
+        (Opcode::ExcValue, [dst]) => {
+            let value = state.exception.as_ref().map_or(0, |(value, _)| *value);
+            state.write(dst, value)?;
+        }
+        (Opcode::Write, [value, format]) => {
+            let format = ConsoleFormat::from_operand(format).ok_or_else(cannot)?;
+            state.write_console(value, format)?;
+        }
+        (Opcode::WriteLn, []) => state.console.output.push('\n'),
+        (Opcode::ReadLn, operands) => state.read_line(operands)?,
+        (Opcode::In, [dst, port, Value::Immediate(bytes)]) => {
+            let port = state.read16(port)?;
+            let data = (0..*bytes as u16)
+                .map(|i| (state.ports.get(&port.wrapping_add(i)).copied().unwrap_or(0) as u32) << (8 * i))
+                .sum();
+            state.write(dst, data)?;
+        }
+        (Opcode::Out, [port, src, Value::Immediate(bytes)]) => {
+            let (port, data) = (state.read16(port)?, state.read(src)?);
+            for i in 0..*bytes as u16 {
+                state.ports.insert(port.wrapping_add(i), (data >> (8 * i)) as u8);
+            }
+        }
+        (Opcode::Cmp, [left, right, rest @ ..]) => {
+            let (left, right) = (state.read16(left)?, state.read16(right)?);
+            *flags = match rest {
+                [Value::Compare(kind)] => compare(*kind, left, right),
+                _ => (left as u8 == right as u8, (left as u8) < (right as u8)),
+            };
+        }
+        (Opcode::LCmp, [left, right, Value::Compare(kind)]) => {
+            let (left, right) = (state.read(left)?, state.read(right)?);
+            let widen = |value: u32, signed: bool| if signed { value as i32 as i64 } else { value as i64 };
+            let (left_signed, right_signed) = match kind {
+                ComparisonKind::Signed => (true, true),
+                ComparisonKind::Unsigned => (false, false),
+                ComparisonKind::SignedUnsigned => (true, false),
+                ComparisonKind::UnsignedSigned => (false, true),
+            };
+            *flags = (left == right, widen(left, left_signed) < widen(right, right_signed));
+        }
+        (Opcode::FCmp, [left, right]) => {
+            let (left, right) = (f32::from_bits(state.read(left)?), f32::from_bits(state.read(right)?));
+            *flags = (left == right, left < right);
+        }
+        (
+            Opcode::SetClear
+            | Opcode::SetIncl
+            | Opcode::SetCopy
+            | Opcode::SetUnion
+            | Opcode::SetDiff
+            | Opcode::SetInter
+            | Opcode::SetIn
+            | Opcode::SetEq
+            | Opcode::SetSubset
+            | Opcode::StrCopy
+            | Opcode::StrChar
+            | Opcode::StrConcat
+            | Opcode::StrCmp
+            | Opcode::StrLength
+            | Opcode::StrSlice
+            | Opcode::StrPos
+            | Opcode::StrDelete
+            | Opcode::StrInsert
+            | Opcode::StrCopyAt,
+            operands,
+        ) => state.aggregate(&inst.opcode, operands)?,
+        // Moves keep all 32 bits
+        (Opcode::Mov | Opcode::Load | Opcode::Store, [dst, src]) => {
+            let value = state.read(src)?;
+            state.write(dst, value)?;
+        }
+        (opcode, [dst, operands @ ..]) => {
+            let operands = operands.iter().map(|v| state.read(v)).collect::<Result<Vec<_>, _>>()?;
+            let result = match evaluate_wide(opcode, &operands) {
+                Some(result) => result,
+                None => evaluate(opcode, &operands.iter().map(|v| *v as u16).collect::<Vec<_>>()).ok_or_else(cannot)? as u32,
+            };
+            state.write(dst, result)?;
+        }
+        _ => return Err(cannot()),
+    }
+    Ok(Flow::Next)
+}
+
+/// Flags of comparing `left` with `right` promoted by `kind`
+pub(crate) fn compare(kind: ComparisonKind, left: u16, right: u16) -> (bool, bool) {
+    let less = match kind {
+        ComparisonKind::Unsigned => left < right,
+        ComparisonKind::Signed => (left as i16) < (right as i16),
+        ComparisonKind::SignedUnsigned => (left as i16 as i32) < right as i32,
+        ComparisonKind::UnsignedSigned => (left as i32) < (right as i16 as i32),
+    };
+    (left == right, less)
+}
+
+/// Result of the arithmetic `opcode` on `operands`, or None if it is not
+/// interpreted (or divides by zero)
+pub(crate) fn evaluate(opcode: &Opcode, operands: &[u16]) -> Option<u16> {
+    let signed = |v: u16| v as i16;
+    Some(match (opcode, operands) {
+        (Opcode::Mov | Opcode::Load | Opcode::Store, [a]) => *a,
+        (Opcode::Add, [a, b]) => a.wrapping_add(*b),
+        (Opcode::Sub, [a, b]) => a.wrapping_sub(*b),
+        (Opcode::Mul, [a, b]) => a.wrapping_mul(*b),
+        (Opcode::Div, [a, b]) => signed(*a).checked_div(signed(*b))? as u16,
+        (Opcode::DivU, [a, b]) => a.checked_div(*b)?,
+        (Opcode::Mod, [a, b]) => signed(*a).checked_rem(signed(*b))? as u16,
+        (Opcode::ModU, [a, b]) => a.checked_rem(*b)?,
+        (Opcode::Shl, [a, b]) => a.checked_shl(*b as u32).unwrap_or(0),
+        (Opcode::Shr, [a, b]) => a.checked_shr(*b as u32).unwrap_or(0),
+        (Opcode::Sar, [a, b]) => (signed(*a) >> (*b).min(15)) as u16,
+        (Opcode::BAdd, [a, b]) => (*a as u8).wrapping_add(*b as u8) as u16,
+        (Opcode::BSub, [a, b]) => (*a as u8).wrapping_sub(*b as u8) as u16,
+        (Opcode::BShl, [a, b]) => (*a as u8).checked_shl(*b as u32).unwrap_or(0) as u16,
+        (Opcode::BShr, [a, b]) => (*a as u8).checked_shr(*b as u32).unwrap_or(0) as u16,
+        _ => return None,
+    })
+}
+
+/// Result of the 32-bit integer or real `opcode` on `operands`, or None
+/// if it is not one of them (or divides by zero)
+fn evaluate_wide(opcode: &Opcode, operands: &[u32]) -> Option<u32> {
+    let signed = |v: u32| v as i32;
+    let real = |v: u32| f32::from_bits(v);
+    Some(match (opcode, operands) {
+        (Opcode::LAdd, [a, b]) => a.wrapping_add(*b),
+        (Opcode::LSub, [a, b]) => a.wrapping_sub(*b),
+        (Opcode::LMul, [a, b]) => a.wrapping_mul(*b),
+        (Opcode::LDiv, [a, b]) => signed(*a).checked_div(signed(*b))? as u32,
+        (Opcode::LDivU, [a, b]) => a.checked_div(*b)?,
+        (Opcode::LMod, [a, b]) => signed(*a).checked_rem(signed(*b))? as u32,
+        (Opcode::LModU, [a, b]) => a.checked_rem(*b)?,
+        (Opcode::SExt, [a]) => *a as u16 as i16 as i32 as u32,
+        (Opcode::ZExt, [a]) => *a as u16 as u32,
+        (Opcode::FAdd, [a, b]) => (real(*a) + real(*b)).to_bits(),
+        (Opcode::FSub, [a, b]) => (real(*a) - real(*b)).to_bits(),
+        (Opcode::FMul, [a, b]) => (real(*a) * real(*b)).to_bits(),
+        (Opcode::FDiv, [a, b]) => (real(*a) / real(*b)).to_bits(),
+        (Opcode::IToF, [a]) => (*a as u16 as i16 as f32).to_bits(),
+        (Opcode::FTrunc, [a]) => real(*a).trunc() as i16 as u16 as u32,
+        (Opcode::FRound, [a]) => real(*a).round() as i16 as u16 as u32,
+        _ => return None,
+    })
+}
+
+fn describe(inst: &Instruction) -> String {
+    format!("{:?} {:?}", inst.opcode, inst.operands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicBlock;
+
+    #[test]
+    fn test_interpret_loop_and_byte_forms() {
+        // r := 0; i := 5; repeat r := r + i; i := i - 1 until i = 0; b := 250 +8 6
+        let mut function = Function::new("f".to_string(), None);
+        let reg = |name: &str| Value::Register(name.to_string());
+        let inst = |opcode, operands: Vec<Value>| Instruction::new(opcode, operands);
+        function.blocks[0].instructions = vec![
+            inst(Opcode::Mov, vec![reg("r"), Value::Immediate(0)]),
+            inst(Opcode::Mov, vec![reg("i"), Value::Immediate(5)]),
+        ];
+        let mut body = BasicBlock::new("body".to_string());
+        body.instructions = vec![
+            inst(Opcode::Add, vec![reg("r"), reg("r"), reg("i")]),
+            inst(Opcode::Sub, vec![reg("i"), reg("i"), Value::Immediate(1)]),
+            inst(Opcode::Cmp, vec![reg("i"), Value::Immediate(0), Value::Compare(ComparisonKind::Signed)]),
+            inst(
+                Opcode::CJump,
+                vec![Value::Condition(Condition::Greater), Value::Label("body".to_string()), Value::Label("done".to_string())],
+            ),
+        ];
+        let mut done = BasicBlock::new("done".to_string());
+        done.instructions = vec![inst(Opcode::BAdd, vec![reg("b"), Value::Immediate(250), Value::Immediate(6)])];
+        function.blocks.extend([body, done]);
+
+        let mut state = State::default();
+        interpret(&function, &mut state, 100).unwrap();
+        assert_eq!(state.registers["r"], 15);
+        assert_eq!(state.registers["i"], 0);
+        // 8-bit forms wrap at 256
+        assert_eq!(state.registers["b"], 0);
+        assert!(interpret(&function, &mut State::default(), 10).is_err());
+    }
+
+    #[test]
+    fn test_interpret_addresses_of_variables() {
+        // p := @n; p^ := 300; m := p^ (as a byte)
+        let mut function = Function::new("f".to_string(), None);
+        let n = Value::Memory { base: "ix".to_string(), offset: -2 };
+        let inst = |opcode, operands: Vec<Value>| Instruction::new(opcode, operands);
+        function.blocks[0].instructions = vec![
+            inst(Opcode::Addr, vec![Value::Temp(0), n.clone()]),
+            inst(Opcode::StoreAt, vec![Value::Temp(0), Value::Immediate(300), Value::Immediate(2)]),
+            inst(Opcode::LoadAt, vec![Value::Temp(1), Value::Temp(0), Value::Immediate(1)]),
+            inst(Opcode::Addr, vec![Value::Temp(2), n.clone()]),
+        ];
+
+        let mut state = State::default();
+        interpret(&function, &mut state, 10).unwrap();
+        assert_eq!(state.read(&n), Ok(300));
+        assert_eq!(state.temps[&1], 300 & 0xFF);
+        // A variable keeps its address, which no address NEW hands out is
+        assert_eq!(state.temps[&0], VARIABLE_ADDRESSES + ADDRESS_WINDOW / 2 - 2);
+        assert_eq!(state.temps[&2], state.temps[&0]);
+    }
+
+    #[test]
+    fn test_interpret_console() {
+        // ReadLn(N, C); WriteLn('N = ', N + 1, C, N = -4)
+        let mut function = Function::new("f".to_string(), None);
+        let n = Value::Memory { base: "ix".to_string(), offset: -2 };
+        let c = Value::Memory { base: "ix".to_string(), offset: -3 };
+        let format = |format: ConsoleFormat| Value::Label(format.name().to_string());
+        let inst = |opcode, operands: Vec<Value>| Instruction::new(opcode, operands);
+        function.blocks[0].instructions = vec![
+            inst(Opcode::ReadLn, vec![n.clone(), format(ConsoleFormat::Integer), c.clone(), format(ConsoleFormat::Char)]),
+            inst(Opcode::Add, vec![Value::Temp(0), n.clone(), Value::Immediate(1)]),
+            inst(Opcode::Write, vec![Value::Label("__str_0".to_string()), format(ConsoleFormat::String)]),
+            inst(Opcode::Write, vec![Value::Temp(0), format(ConsoleFormat::Integer)]),
+            inst(Opcode::Write, vec![c, format(ConsoleFormat::Char)]),
+            inst(Opcode::Write, vec![Value::Immediate(1), format(ConsoleFormat::Boolean)]),
+            inst(Opcode::WriteLn, vec![]),
+        ];
+
+        let mut state = State { console: Console::new(&["-5 x".to_string()], &["N = ".to_string()]), ..State::default() };
+        interpret(&function, &mut state, 100).unwrap();
+        assert_eq!(state.console.output, "N = -4xTRUE\n");
+
+        let mut state = State { console: Console::new(&[], &[]), ..State::default() };
+        assert_eq!(interpret(&function, &mut state, 100), Err("READLN past the end of the input".to_string()));
+    }
+
+    #[test]
+    fn test_interpret_calls_exceptions_sets_and_strings() {
+        let inst = |opcode, operands: Vec<Value>| Instruction::new(opcode, operands);
+        let label = |name: &str| Value::Label(name.to_string());
+        let param = Value::Memory { base: FRAME_BASE.to_string(), offset: 4 };
+        let global = |name: &str| Value::Memory { base: crate::global_base(name), offset: 0 };
+        let integer = label(ConsoleFormat::Integer.name());
+
+        // function Fact(N: Integer): Integer, recursively: each call has
+        // its own N
+        let mut fact = Function::new("Fact".to_string(), Some(types::Type::integer()));
+        fact.params.push(("N".to_string(), types::Type::integer()));
+        fact.blocks[0].instructions = vec![
+            inst(Opcode::Cmp, vec![param.clone(), Value::Immediate(1), Value::Compare(ComparisonKind::Signed)]),
+            inst(Opcode::CJump, vec![Value::Condition(Condition::LessEqual), label("base"), label("recurse")]),
+        ];
+        let mut recurse = BasicBlock::new("recurse".to_string());
+        recurse.instructions = vec![
+            inst(Opcode::Sub, vec![Value::Temp(0), param.clone(), Value::Immediate(1)]),
+            inst(Opcode::Call, vec![label("Fact"), Value::Temp(0), Value::Temp(1)]),
+            inst(Opcode::Mul, vec![Value::Temp(2), param, Value::Temp(1)]),
+            inst(Opcode::Ret, vec![Value::Temp(2)]),
+        ];
+        let mut base = BasicBlock::new("base".to_string());
+        base.instructions = vec![inst(Opcode::Ret, vec![Value::Immediate(1)])];
+        fact.blocks.extend([recurse, base]);
+
+        let mut boom = Function::new("Boom".to_string(), None);
+        boom.blocks[0].instructions = vec![inst(Opcode::Raise, vec![Value::Immediate(7), label("__exc_type_integer")])];
+
+        let mut main = Function::new("main".to_string(), None);
+        main.blocks[0].instructions = vec![
+            inst(Opcode::Call, vec![label("Fact"), Value::Immediate(5), Value::Temp(0)]),
+            inst(Opcode::Write, vec![Value::Temp(0), integer.clone()]),
+            inst(Opcode::TryEnter, vec![label("handler")]),
+            inst(Opcode::Call, vec![label("Boom")]),
+            inst(Opcode::TryLeave, vec![]),
+            inst(Opcode::Jump, vec![label("end")]),
+        ];
+        let mut handler = BasicBlock::new("handler".to_string());
+        handler.instructions = vec![
+            inst(Opcode::ExcIs, vec![label("__exc_type_integer")]),
+            inst(Opcode::CJump, vec![Value::Condition(Condition::Equal), label("on"), label("end")]),
+        ];
+        let mut on = BasicBlock::new("on".to_string());
+        on.instructions = vec![
+            inst(Opcode::ExcValue, vec![global("E")]),
+            inst(Opcode::Write, vec![global("E"), integer.clone()]),
+        ];
+        let mut end = BasicBlock::new("end".to_string());
+        end.instructions = vec![
+            // 9 in [3..9], then Length('ab' + 'ab' cut to 3 characters)
+            inst(Opcode::SetClear, vec![Value::Temp(1), Value::Immediate(2)]),
+            inst(Opcode::SetIncl, vec![Value::Temp(1), Value::Immediate(3), Value::Immediate(9), Value::Immediate(2)]),
+            inst(Opcode::SetIn, vec![Value::Temp(2), Value::Immediate(9), Value::Temp(1), Value::Immediate(2)]),
+            inst(Opcode::Write, vec![Value::Temp(2), label(ConsoleFormat::Boolean.name())]),
+            inst(Opcode::StrCopy, vec![global("S"), label("__str_0"), Value::Immediate(3)]),
+            inst(Opcode::StrConcat, vec![global("S"), label("__str_0"), Value::Immediate(3)]),
+            inst(Opcode::Write, vec![global("S"), label(ConsoleFormat::String.name())]),
+            inst(Opcode::LMul, vec![Value::Temp(3), Value::Immediate(100_000), Value::Immediate(3)]),
+        ];
+        main.blocks.extend([handler, on, end]);
+        let callees = [fact, boom];
+
+        let mut state = State { console: Console::new(&[], &["ab".to_string()]), ..State::default() };
+        interpret_with(&main, &callees, &mut state, 1000).unwrap();
+        assert_eq!(state.console.output, "1207TRUEaba");
+        assert_eq!(state.temps[&3], 300_000);
+
+        // Without the handler, the exception stops the run
+        main.blocks[0].instructions.retain(|inst| !matches!(inst.opcode, Opcode::TryEnter | Opcode::TryLeave));
+        let mut state = State { console: Console::new(&[], &["ab".to_string()]), ..State::default() };
+        assert_eq!(
+            interpret_with(&main, &callees, &mut state, 1000),
+            Err("unhandled exception __exc_type_integer".to_string())
+        );
+    }
+}
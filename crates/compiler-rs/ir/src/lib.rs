@@ -7,11 +7,43 @@
 //! - Easy to optimize
 //! - Easy to translate to target assembly
 
+mod arrays;
+mod classes;
+mod console;
+mod constfold;
+mod cse;
+mod exceptions;
+mod interfaces;
+pub mod interp;
+mod narrow;
+mod outline;
+mod overflow;
+mod params;
+mod ports;
+mod records;
+mod routines;
+mod sets;
+mod strength;
+mod strings;
+mod unreachable;
+mod unroll;
+
 use ast::Node;
 use tokens::Span;
-use types::Type;
+use types::{ClassLayout, ComparisonKind, InterfaceLayout, PrimitiveType, Type};
 use runtime::variant::VariantType as RuntimeVariantType;
 
+pub use console::ConsoleFormat;
+pub use constfold::fold_constants;
+pub use cse::eliminate_common_subexpressions;
+pub use narrow::narrow_bytes;
+pub use params::{PARAM_BUFFER, PARAM_COUNT_ROUTINE, PARAM_SAVE_ROUTINE, PARAM_STR_ROUTINE};
+pub use routines::RoutineParam;
+pub use outline::{outline_sequences, OutlineCosts, OutlinedRoutine, OUTLINED_PREFIX};
+pub use strength::{reduce_strength, ArithCosts};
+pub use unreachable::remove_unreachable_code;
+pub use unroll::{unroll_loops, CountedLoop, UNROLL_BUDGET};
+
 /// Represents an IR value (immediate, register, memory, temporary)
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Value {
@@ -25,10 +57,28 @@ pub enum Value {
     Temp(usize),
     /// Label reference
     Label(String),
+    /// Condition code (first operand of CJUMP)
+    Condition(Condition),
+    /// Operand promotion of a 16-bit compare (optional third operand of CMP)
+    Compare(ComparisonKind),
+    /// Inline assembly (operand of ASM)
+    Asm(Box<AsmBlock>),
+}
+
+/// An asm block, passed through to the backend as written
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AsmBlock {
+    /// Assembly lines, referring to Pascal identifiers as `@Name`
+    pub body: String,
+    /// Where each `@Name` is in the body and what it stands for: a
+    /// variable's address, a constant's value, else the label of a routine
+    pub references: Vec<(std::ops::Range<usize>, Value)>,
+    /// Registers the block changes, lowercase
+    pub clobbers: Vec<String>,
 }
 
 /// IR instruction opcodes
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Opcode {
     // Data movement
     Mov,  // MOV dst, src
@@ -36,10 +86,41 @@ pub enum Opcode {
     Add,  // ADD dst, src1, src2
     Sub,  // SUB dst, src1, src2
     Mul,  // MUL dst, src1, src2
-    Div,  // DIV dst, src1, src2
-    Mod,  // MOD dst, src1, src2
+    Div,  // DIV dst, src1, src2 (signed)
+    DivU, // DIVU dst, src1, src2 (unsigned)
+    Mod,  // MOD dst, src1, src2 (signed)
+    ModU, // MODU dst, src1, src2 (unsigned)
+    Shl,  // SHL dst, src, count
+    Shr,  // SHR dst, src, count (logical)
+    Sar,  // SAR dst, src, count (arithmetic)
+    // 8-bit forms, on operands proven to fit in a byte; the result is
+    // zero-extended (see narrow.rs)
+    BAdd, // BADD dst, src1, src2
+    BSub, // BSUB dst, src1, src2
+    BShl, // BSHL dst, src, count
+    BShr, // BSHR dst, src, count
+    // Single-precision reals, as 32-bit IEEE bit patterns
+    FAdd,    // FADD dst, src1, src2
+    FSub,    // FSUB dst, src1, src2
+    FMul,    // FMUL dst, src1, src2
+    FDiv,    // FDIV dst, src1, src2
+    FCmp,    // FCMP src1, src2 (sets condition flags)
+    IToF,    // ITOF dst, src (Integer to Real)
+    FTrunc,  // FTRUNC dst, src (Real to Integer, toward zero)
+    FRound,  // FROUND dst, src (Real to Integer, to nearest)
+    // 32-bit integers (LongInt, Cardinal)
+    LAdd,    // LADD dst, src1, src2
+    LSub,    // LSUB dst, src1, src2
+    LMul,    // LMUL dst, src1, src2
+    LDiv,    // LDIV dst, src1, src2 (signed)
+    LDivU,   // LDIVU dst, src1, src2 (unsigned)
+    LMod,    // LMOD dst, src1, src2 (signed)
+    LModU,   // LMODU dst, src1, src2 (unsigned)
+    LCmp,    // LCMP src1, src2, kind (sets condition flags)
+    SExt,    // SEXT dst, src (Integer to 32 bits, sign-extended)
+    ZExt,    // ZEXT dst, src (Byte or Word to 32 bits, zero-extended)
     // Comparison
-    Cmp,  // CMP src1, src2 (sets condition flags)
+    Cmp,  // CMP src1, src2 [, kind] (sets condition flags)
     // Control flow
     Jump,   // JUMP label
     CJump,  // CJUMP condition, label_true, label_false
@@ -48,13 +129,65 @@ pub enum Opcode {
     // Memory operations
     Load,   // LOAD dst, src (load from memory)
     Store,  // STORE dst, src (store to memory)
+    Addr,    // ADDR dst, src (the address of the variable src, or of the routine a label names)
+    LoadAt,  // LOADAT dst, address, size (load the byte or word at the address a value holds)
+    StoreAt, // STOREAT address, src, size (store the low byte or the word of src at the address a value holds)
     // Stack operations
     Push,   // PUSH src
     Pop,    // POP dst
+    // Sets, bitsets of `size` bytes; elements are numbered from the set's
+    // first bit (see sets.rs)
+    SetClear,   // SETCLEAR set, size
+    SetIncl,    // SETINCL set, first, last, size (adds elements first..last)
+    SetCopy,    // SETCOPY dst, src, size
+    SetUnion,   // SETUNION dst, src, size (dst := dst + src)
+    SetDiff,    // SETDIFF dst, src, size (dst := dst - src)
+    SetInter,   // SETINTER dst, src, size (dst := dst * src)
+    SetIn,      // SETIN dst, element, set, size (Boolean)
+    SetEq,      // SETEQ dst, left, right, size (Boolean)
+    SetSubset,  // SETSUBSET dst, left, right, size (Boolean: left <= right)
+    // Strings, a length byte followed by the characters; `max` is the
+    // number of characters the destination holds (see strings.rs)
+    StrCopy,    // STRCOPY dst, src, max
+    StrChar,    // STRCHAR dst, char (dst := the one-character string)
+    StrConcat,  // STRCONCAT dst, src, max (dst := dst + src)
+    StrCmp,     // STRCMP dst, left, right, condition (Boolean: left condition right)
+    StrLength,  // STRLENGTH dst, s
+    StrSlice,   // STRSLICE dst, src, index, count, max (dst := Copy(src, index, count))
+    StrPos,     // STRPOS dst, sub, s (position of sub in s from 1, or 0)
+    StrDelete,  // STRDELETE s, index, count
+    StrInsert,  // STRINSERT s, src, index, max (inserts src before s[index])
+    StrCopyAt,  // STRCOPYAT address, src, max (copies src to the string at the address a value holds)
+    // Multiway branch (CASE)
+    Switch,     // SWITCH selector, default, (low, high, label)... (jumps to the label of the range holding selector)
+    // Exceptions (see exceptions.rs)
+    TryEnter,   // TRYENTER handler (pushes an exception frame)
+    TryLeave,   // TRYLEAVE (pops the innermost exception frame)
+    Raise,      // RAISE value, type (unwinds to the innermost handler)
+    Reraise,    // RERAISE (raises the exception being handled again)
+    ExcIs,      // EXCIS type (sets EQ when the exception being handled has descriptor `type`)
+    ExcValue,   // EXCVALUE dst (the value of the exception being handled)
+    // Classes (see classes.rs)
+    CallMethod, // CALLMETHOD method, self, args... (method is a label, or the VMT slot of a virtual method)
+    New,        // NEW dst, size, vmt (a zeroed instance from the heap, its first word set to vmt unless 0)
+    Dispose,    // DISPOSE object (returns the instance to the heap)
+    // Interfaces (see interfaces.rs)
+    CallIntf,   // CALLINTF iid, slot, self, args... (calls IMT slot `slot` of the interface with descriptor `iid`)
+    IntfIs,     // INTFIS dst, object, iid (Boolean: the class of object implements the interface)
+    IntfAs,     // INTFAS dst, object, iid (object if its class implements the interface, else 0)
+    // Inline assembly
+    Asm,        // ASM block (see AsmBlock)
+    // Console (see console.rs)
+    Write,      // WRITE value, format (format is a ConsoleFormat label; a string value is its address)
+    WriteLn,    // WRITELN (ends the line)
+    ReadLn,     // READLN (dst, format)... (reads a line into each dst, split at spaces)
+    // Port I/O (see ports.rs)
+    In,         // IN dst, port, bytes (reads 1 or 2 bytes, from port and port + 1)
+    Out,        // OUT port, src, bytes (writes 1 or 2 bytes, to port and port + 1)
 }
 
 /// Condition codes for conditional jumps
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Condition {
     Equal,        // ==
     NotEqual,     // !=
@@ -123,6 +256,9 @@ pub struct Function {
     pub return_type: Option<Type>,
     pub blocks: Vec<BasicBlock>,
     pub entry_block: String, // Label of entry block
+    pub loops: Vec<CountedLoop>, // FOR loops, innermost first
+    pub optimize: bool, // Whether optimization passes may change it ({$OPTIMIZATION})
+    pub locals: Vec<(String, i32)>, // (name, offset from IX) of the declared variables
 }
 
 impl Function {
@@ -136,6 +272,9 @@ impl Function {
             return_type,
             blocks: vec![entry_block],
             entry_block: entry_label,
+            loops: vec![],
+            optimize: true,
+            locals: vec![],
         }
     }
 
@@ -153,6 +292,11 @@ impl Function {
 pub struct Program {
     pub functions: Vec<Function>,
     pub globals: Vec<(String, Type)>, // (name, type)
+    pub pure_functions: Vec<String>, // Called routines whose result depends on their arguments alone
+    pub result_functions: Vec<String>, // Called routines returning a result, which their CALL names last
+    pub externals: Vec<(String, String)>, // (name, symbol) of the routines declared external
+    pub byte_globals: Vec<(String, i32)>, // (symbol, offset) of the globals and global fields one byte wide, loaded and stored a byte at a time
+    pub startup: Vec<String>, // Runtime routines main calls before anything else, while the registers hold what the target passed
 }
 
 impl Program {
@@ -160,6 +304,20 @@ impl Program {
         Self {
             functions: vec![],
             globals: vec![],
+            pure_functions: vec![],
+            result_functions: vec![],
+            externals: vec![],
+            byte_globals: vec![],
+            startup: vec![],
+        }
+    }
+
+    /// The operand a call gives the result of the routine in, if the
+    /// routine returns one: `CALL Sq, 3, t0` gives it in `t0`
+    pub fn call_result<'a>(&self, inst: &'a Instruction) -> Option<&'a Value> {
+        match (&inst.opcode, inst.operands.as_slice()) {
+            (Opcode::Call, [Value::Label(label), .., result]) if self.result_functions.contains(label) => Some(result),
+            _ => None,
         }
     }
 
@@ -174,6 +332,54 @@ impl Default for Program {
     }
 }
 
+/// Base of the memory operands of global variables, followed by the
+/// variable's symbol: `Memory { base: "@Count", offset: 2 }` is the byte
+/// two past `Count`. Variables of routines are addressed from the frame
+/// pointer, `ix`.
+pub const GLOBAL_BASE_PREFIX: &str = "@";
+
+/// Base of the memory operands of the global variable `symbol`
+pub fn global_base(symbol: &str) -> String {
+    format!("{}{}", GLOBAL_BASE_PREFIX, symbol)
+}
+
+/// The global variable a memory operand with `base` lies in, if any
+pub fn global_symbol(base: &str) -> Option<&str> {
+    base.strip_prefix(GLOBAL_BASE_PREFIX)
+}
+
+/// Base of the memory operands of threadvars, at their offset in the
+/// running task's block
+pub const THREADVAR_BASE: &str = "__threadvars";
+
+/// Function holding the statements of the main program
+pub const MAIN_FUNCTION: &str = "main";
+
+/// Runtime routine called when a value is outside the bounds of its
+/// subrange under {$R+}; it does not return
+pub const RANGE_ERROR_ROUTINE: &str = "__range_error";
+/// Runtime routine called when integer arithmetic overflows under {$Q+};
+/// it does not return
+pub const OVERFLOW_ERROR_ROUTINE: &str = "__overflow_error";
+
+/// Types of the variables in scope, by name regardless of case
+#[derive(Debug, Clone, Default)]
+struct VariableTypes(std::collections::HashMap<String, Type>);
+
+impl VariableTypes {
+    fn get(&self, name: &str) -> Option<&Type> {
+        self.0.get(&name.to_lowercase())
+    }
+
+    fn contains_key(&self, name: &str) -> bool {
+        self.0.contains_key(&name.to_lowercase())
+    }
+
+    fn insert(&mut self, name: String, ty: Type) {
+        self.0.insert(name.to_lowercase(), ty);
+    }
+}
+
 /// IR builder for constructing IR from AST
 pub struct IRBuilder {
     program: Program,
@@ -182,7 +388,51 @@ pub struct IRBuilder {
     label_counter: usize,
     /// Variable type information (name -> type)
     /// Used to determine when to use Variant runtime functions
-    variable_types: std::collections::HashMap<String, Type>,
+    variable_types: VariableTypes,
+    /// Declared types (name -> type), to size pointer targets
+    type_decls: std::collections::HashMap<String, Type>,
+    /// Whether values stored into subrange variables are checked against
+    /// the bounds ({$R+})
+    range_checks: bool,
+    /// Whether integer arithmetic is checked for overflow ({$Q+})
+    overflow_checks: bool,
+    /// Whether the functions being built may be optimized ({$OPTIMIZATION})
+    optimize: bool,
+    /// String literals, numbered as the `__str_{n}` data holding them
+    string_literals: Vec<String>,
+    /// Class layouts, with the methods and VMT slots of each class
+    class_layouts: Vec<ClassLayout>,
+    /// Interface layouts, with the IMT slots of each method
+    interface_layouts: Vec<InterfaceLayout>,
+    /// Constants declared with an integer literal, for asm blocks
+    constants: std::collections::HashMap<String, i32>,
+    /// Text of the constants declared with a string literal (lowercase
+    /// name)
+    string_constants: std::collections::HashMap<String, String>,
+    /// Frame offset of each declared variable (lowercase name)
+    variable_slots: std::collections::HashMap<String, i32>,
+    /// Parameters of the routine being built whose slot holds the address
+    /// of their variable (lowercase name)
+    reference_params: std::collections::HashSet<String>,
+    /// Bytes of frame the declared variables take
+    frame_size: i32,
+    /// Span of the statement being built, given to the instructions
+    /// emitted without one
+    statement_span: Option<Span>,
+    /// Symbol of each global variable (lowercase name)
+    global_symbols: std::collections::HashMap<String, String>,
+    /// Offset of each threadvar in the task's block (lowercase name)
+    threadvar_slots: std::collections::HashMap<String, i32>,
+    /// Routines that may be called (lowercase name; see routines.rs)
+    routines: std::collections::HashMap<String, routines::Routine>,
+    /// Stack each task entry needs, for `TaskStackSize` (lowercase name)
+    task_stack_sizes: std::collections::HashMap<String, u16>,
+    /// The routine being built, if not the main program
+    routine: Option<routines::RoutineScope>,
+    /// File the diagnostics are reported in
+    filename: Option<String>,
+    /// What could not be built
+    diagnostics: Vec<errors::Diagnostic>,
 }
 
 impl IRBuilder {
@@ -192,10 +442,81 @@ impl IRBuilder {
             current_function: None,
             temp_counter: 0,
             label_counter: 0,
-            variable_types: std::collections::HashMap::new(),
+            variable_types: VariableTypes::default(),
+            type_decls: std::collections::HashMap::new(),
+            range_checks: false,
+            overflow_checks: false,
+            optimize: true,
+            string_literals: vec![],
+            class_layouts: vec![],
+            interface_layouts: vec![],
+            constants: std::collections::HashMap::new(),
+            string_constants: std::collections::HashMap::new(),
+            variable_slots: std::collections::HashMap::new(),
+            reference_params: std::collections::HashSet::new(),
+            frame_size: 0,
+            statement_span: None,
+            global_symbols: std::collections::HashMap::new(),
+            threadvar_slots: std::collections::HashMap::new(),
+            routines: std::collections::HashMap::new(),
+            task_stack_sizes: std::collections::HashMap::new(),
+            routine: None,
+            filename: None,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Name the file the diagnostics are reported in
+    pub fn set_filename(&mut self, filename: Option<String>) {
+        self.filename = filename;
+    }
+
+    /// What could not be built, such as statements code generation does
+    /// not support yet; the program built is incomplete when there are any
+    pub fn diagnostics(&self) -> &[errors::Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Report that `what`, at `span` or else the statement being built,
+    /// cannot be built
+    fn unsupported(&mut self, what: &str, span: Option<Span>) {
+        self.error(format!("Code generation does not support {} yet", what), span);
+    }
+
+    fn error(&mut self, message: String, span: Option<Span>) {
+        let span = span.or(self.statement_span).unwrap_or_else(|| Span::new(0, 0, 1, 1));
+        let diagnostic = errors::Diagnostic::new(errors::ErrorSeverity::Error, message, span)
+            .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string()));
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
         }
     }
 
+    /// Turn range checks on or off before building; {$R+} and {$R-}
+    /// directives in the source override this
+    pub fn set_range_checks(&mut self, enabled: bool) {
+        self.range_checks = enabled;
+    }
+
+    /// Turn overflow checks on or off before building; {$Q+} and {$Q-}
+    /// directives in the source override this
+    pub fn set_overflow_checks(&mut self, enabled: bool) {
+        self.overflow_checks = enabled;
+    }
+
+    /// Name the functions whose calls compute their result from their
+    /// arguments alone and do nothing else, for common subexpression
+    /// elimination
+    pub fn set_pure_functions(&mut self, names: &[String]) {
+        self.program.pure_functions = names.to_vec();
+    }
+
+    /// Give the stack each task entry needs, from the stack analysis, for
+    /// `TaskStackSize(Entry)`
+    pub fn set_task_stack_sizes(&mut self, sizes: impl IntoIterator<Item = (String, u16)>) {
+        self.task_stack_sizes = sizes.into_iter().map(|(entry, size)| (entry.to_lowercase(), size)).collect();
+    }
+
     /// Generate a new temporary value
     pub fn new_temp(&mut self) -> Value {
         let temp = self.temp_counter;
@@ -212,7 +533,9 @@ impl IRBuilder {
 
     /// Start building a new function
     pub fn start_function(&mut self, name: String, return_type: Option<Type>) {
-        self.current_function = Some(Function::new(name, return_type));
+        let mut function = Function::new(name, return_type);
+        function.optimize = self.optimize;
+        self.current_function = Some(function);
     }
 
     /// Finish the current function and add it to the program
@@ -227,14 +550,40 @@ impl IRBuilder {
         self.current_function.as_mut()
     }
 
-    /// Build IR from AST
+    /// Append an instruction to the block currently being built
+    fn emit(&mut self, mut inst: Instruction) {
+        if inst.span.is_none() {
+            inst.span = self.statement_span;
+        }
+        if let Some(block) = self.current_function_mut().and_then(|f| f.blocks.last_mut()) {
+            block.add_instruction(inst);
+        }
+    }
+
+    /// Start a new basic block; subsequent instructions are appended to it
+    fn start_block(&mut self, label: String) {
+        if let Some(func) = self.current_function_mut() {
+            func.add_block(BasicBlock::new(label));
+        }
+    }
+
+    /// Build IR from AST: a program's statements become the function
+    /// `main`, which comes first, and each routine of a program or unit a
+    /// function of its own (see routines.rs)
     pub fn build(&mut self, ast: &Node) -> Program {
         match ast {
             Node::Program(prog) => {
+                self.apply_switches(&prog.directives);
                 if let Node::Block(block) = prog.block.as_ref() {
+                    self.declare_routines(block.proc_decls.iter().chain(&block.func_decls));
+                    self.start_function(MAIN_FUNCTION.to_string(), None);
+                    self.statement_span = Some(prog.span);
                     self.build_block(block);
+                    self.finish_function();
+                    self.build_routines(block.proc_decls.iter().chain(&block.func_decls));
                 }
             }
+            Node::Unit(unit) => self.build_unit(unit),
             _ => {
                 // For other top-level nodes, build them directly
                 self.build_node(ast);
@@ -245,6 +594,13 @@ impl IRBuilder {
 
     /// Build a single AST node
     fn build_node(&mut self, node: &Node) {
+        let enclosing = self.statement_span;
+        // The frame temporaries of a statement are free after it
+        let frame_size = self.frame_size;
+        let statement = !matches!(node, Node::Block(_) | Node::VarDecl(_));
+        if statement {
+            self.statement_span = Some(node.span());
+        }
         match node {
             Node::Block(block) => {
                 self.build_block(block);
@@ -273,15 +629,81 @@ impl IRBuilder {
             Node::CaseStmt(case_stmt) => {
                 self.build_case_stmt(case_stmt);
             }
-            // Add other statement types as needed
-            _ => {
-                // For now, ignore unsupported nodes
+            Node::TryStmt(try_stmt) => {
+                self.build_try_stmt(try_stmt);
+            }
+            Node::RaiseStmt(raise) => {
+                self.build_raise_stmt(raise);
+            }
+            Node::MethodCall(call) => {
+                if self.resolves_method(call) {
+                    self.build_method_call(call);
+                } else {
+                    self.unsupported(&format!("the call of '{}'", call.method), None);
+                }
+            }
+            Node::AsmStmt(asm) => {
+                self.build_asm_stmt(asm);
+            }
+            Node::Directive(_) => {}
+            Node::WithStmt(_) => self.unsupported("with statements", None),
+            Node::GotoStmt(_) | Node::LabeledStmt(_) => self.unsupported("goto", None),
+            Node::ForInStmt(_) => self.unsupported("for-in loops", None),
+            _ => self.unsupported("this statement", None),
+        }
+        self.statement_span = enclosing;
+        if statement {
+            self.frame_size = frame_size;
+        }
+    }
+
+    /// Follow the {$R+}/{$R-} and {$RANGECHECKS ON|OFF} switches among
+    /// `directives`
+    fn apply_switches(&mut self, directives: &[Node]) {
+        for directive in directives {
+            let Node::Directive(directive) = directive else {
+                continue;
+            };
+            if let Some(enabled) = switch_state(&directive.content, &RANGE_CHECK_SWITCHES) {
+                self.range_checks = enabled;
+            }
+            if let Some(enabled) = switch_state(&directive.content, &OVERFLOW_CHECK_SWITCHES) {
+                self.overflow_checks = enabled;
+            }
+            if let Some(enabled) = switch_state(&directive.content, &[OPTIMIZATION_SWITCH]) {
+                self.optimize = enabled;
+                if let Some(function) = self.current_function.as_mut() {
+                    function.optimize = enabled;
+                }
             }
         }
     }
 
     /// Build a block (declarations and statements)
     fn build_block(&mut self, block: &ast::Block) {
+        self.apply_switches(&block.directives);
+        for decl in &block.const_decls {
+            let Node::ConstDecl(const_decl) = decl else {
+                continue;
+            };
+            match const_decl.value.as_ref() {
+                Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(value), .. }) => {
+                    self.constants.insert(const_decl.name.to_lowercase(), *value as i32);
+                }
+                Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::String(text), .. })
+                    if const_decl.type_expr.is_none() =>
+                {
+                    self.string_constants.insert(const_decl.name.to_lowercase(), text.clone());
+                }
+                _ => {}
+            }
+        }
+        for decl in &block.type_decls {
+            if let Node::TypeDecl(type_decl) = decl {
+                let declared = self.analyze_type_expr(&type_decl.type_expr);
+                self.type_decls.insert(type_decl.name.clone(), declared);
+            }
+        }
         // Build declarations first (to register variable types)
         for decl in &block.var_decls {
             self.build_node(decl);
@@ -297,9 +719,17 @@ impl IRBuilder {
         // Determine the type of the variable
         let var_type = self.analyze_type_expr(&var_decl.type_expr);
         
-        // Register variable types for later use
+        // Register variable types for later use. Those of routines get a
+        // frame slot, taking whole words as the backend stores them; the
+        // others are globals
         for name in &var_decl.names {
             self.variable_types.insert(name.clone(), var_type.clone());
+            if self.routine.is_some() {
+                self.allocate_local(name, &var_type);
+            } else {
+                self.declare_global(name, var_type.clone());
+                self.program.globals.push((name.clone(), var_type.clone()));
+            }
         }
 
         // Generate IR for variable allocation
@@ -314,18 +744,45 @@ impl IRBuilder {
             }
             
             // Then add instructions to the function
-            if let Some(func) = self.current_function_mut() {
-                let entry_label = func.entry_block.clone();
-                if let Some(entry) = func.get_block_mut(&entry_label) {
-                    for inst in instructions {
-                        entry.add_instruction(inst);
-                    }
-                }
+            for inst in instructions {
+                self.emit(inst);
             }
         }
         // For other types, allocation would be handled by the backend
     }
 
+    /// Give the variable `name` of a routine a frame slot, returning its
+    /// offset from the frame pointer
+    fn allocate_local(&mut self, name: &str, ty: &Type) -> i32 {
+        let size = self.resolve_type(ty).and_then(Type::size).unwrap_or(2).max(2) as i32;
+        self.frame_size += size;
+        self.variable_slots.insert(name.to_lowercase(), -self.frame_size);
+        if let Some(function) = self.current_function.as_mut() {
+            function.locals.push((name.to_string(), -self.frame_size));
+        }
+        -self.frame_size
+    }
+
+    /// A frame slot of `size` bytes below the variables, for a set or
+    /// string built in place
+    pub(crate) fn new_frame_temp(&mut self, size: usize) -> Value {
+        self.frame_size += size.max(2) as i32;
+        Value::Memory { base: constfold::FRAME_BASE.to_string(), offset: -self.frame_size }
+    }
+
+    /// Declare the global variable `name` of a unit the program uses; its
+    /// symbol is its name
+    pub fn declare_global(&mut self, name: &str, ty: Type) {
+        self.variable_types.insert(name.to_string(), ty);
+        self.global_symbols.insert(name.to_lowercase(), name.to_string());
+    }
+
+    /// Declare a threadvar, at `offset` in each task's block
+    pub fn declare_threadvar(&mut self, name: &str, ty: Type, offset: u16) {
+        self.variable_types.insert(name.to_string(), ty);
+        self.threadvar_slots.insert(name.to_lowercase(), offset as i32);
+    }
+
     /// Build an assignment statement
     fn build_assign_stmt(&mut self, assign: &ast::AssignStmt) {
         // Get target variable name and type (before any borrowing)
@@ -340,9 +797,46 @@ impl IRBuilder {
             .and_then(|name| self.variable_types.get(name))
             .cloned();
 
+        if self.build_port_write(assign)
+            || self.build_element_assign(assign)
+            || self.build_field_assign(assign)
+            || self.build_record_field_assign(assign)
+            || self.build_reference_assign(assign)
+        {
+            return;
+        }
+        if target_name.is_none() {
+            self.unsupported("assigning to this", Some(assign.target.span()));
+            return;
+        }
+        // Sets are built in place
+        if let Some(name) = &target_name
+            && let Some(set_type @ Type::Set { .. }) = target_type.as_ref().and_then(|t| self.resolve_type(t)).cloned()
+        {
+            self.build_set_assign(name, &assign.value, &set_type);
+            return;
+        }
+        // Strings are copied into the target
+        if let Some(name) = &target_name
+            && let Some(Type::String { max_length }) = target_type.as_ref().and_then(|t| self.resolve_type(t)).cloned()
+        {
+            self.build_string_assign(name, &assign.value, max_length);
+            return;
+        }
+
         // Build the value expression first (before borrowing func)
-        let value_result = self.build_expression(assign.value.as_ref());
+        let mut value_result = self.build_expression(assign.value.as_ref());
         let value_type = self.analyze_expression_type(assign.value.as_ref());
+        if target_type == Some(Type::real()) {
+            value_result = self.promote_to_real(assign.value.as_ref(), value_result);
+        } else if target_type.as_ref().is_some_and(Self::is_32_bit) {
+            value_result = self.widen(assign.value.as_ref(), value_result);
+        }
+        if self.range_checks
+            && let Some(Type::Subrange { base, low, high }) = target_type.as_ref().and_then(|t| self.resolve_type(t)).cloned()
+        {
+            self.build_range_check(value_result.clone(), &base, low, high, assign.span);
+        }
 
         // Generate instructions based on types (before borrowing func)
         let mut instructions = Vec::new();
@@ -425,13 +919,8 @@ impl IRBuilder {
         }
 
         // Add all instructions to the function (after generating them)
-        if let Some(func) = self.current_function_mut() {
-            let entry_label = func.entry_block.clone();
-            if let Some(entry) = func.get_block_mut(&entry_label) {
-                for inst in instructions {
-                    entry.add_instruction(inst);
-                }
-            }
+        for inst in instructions {
+            self.emit(inst);
         }
     }
 
@@ -441,52 +930,220 @@ impl IRBuilder {
             Node::LiteralExpr(lit) => {
                 match &lit.value {
                     ast::LiteralValue::Integer(i) => Value::Immediate(*i as i32),
+                    ast::LiteralValue::Real(r) => Value::Immediate((*r as f32).to_bits() as i32),
                     ast::LiteralValue::Boolean(b) => Value::Immediate(if *b { 1 } else { 0 }),
                     ast::LiteralValue::Char(c) => Value::Immediate(*c as i32),
-                    ast::LiteralValue::String(_) => {
-                        // String literals would need special handling
-                        Value::Immediate(0) // Placeholder
+                    ast::LiteralValue::String(text) => self.string_literal(text),
+                    ast::LiteralValue::Bytes(_) => {
+                        self.unsupported("byte array literals", Some(lit.span));
+                        self.new_temp()
                     }
                 }
             }
-            Node::IdentExpr(ident) => {
-                // Return the address/value of the variable
-                self.get_variable_address(&ident.name)
+            Node::IdentExpr(ident) => self.build_name(&ident.name, ident.span),
+            Node::EnumLiteralExpr(literal) => match self.enum_ordinal(&literal.value) {
+                Some(ordinal) => Value::Immediate(ordinal),
+                None => self.build_name(&literal.value, literal.span),
+            },
+            Node::IndexExpr(index) if self.port_width(&index.array).is_some() => self.build_port_read(index).unwrap(),
+            Node::IndexExpr(index) if let Some(value) = self.build_element_load(index) => value,
+            Node::CallExpr(call) if call.args.len() == 1 && Self::real_to_integer(&call.name).is_some() => {
+                let opcode = Self::real_to_integer(&call.name).unwrap();
+                let value = self.build_expression(&call.args[0]);
+                let result = self.new_temp();
+                self.emit(Instruction::new(opcode, vec![result.clone(), value]));
+                result
+            }
+            Node::CallExpr(call) if call.args.len() == 1 && Self::ordinal_step(&call.name).is_some() => {
+                // Ordinal values are held as their ordinal numbers, so Ord is a no-op
+                let step = Self::ordinal_step(&call.name).unwrap();
+                let value = self.build_expression(&call.args[0]);
+                if step == 0 {
+                    return value;
+                }
+                let is_long = self.analyze_expression_type(&call.args[0]).is_some_and(|t| Self::is_32_bit(&t));
+                let opcode = match (step > 0, is_long) {
+                    (true, false) => Opcode::Add,
+                    (true, true) => Opcode::LAdd,
+                    (false, false) => Opcode::Sub,
+                    (false, true) => Opcode::LSub,
+                };
+                let result = self.new_temp();
+                self.emit(Instruction::new(opcode, vec![result.clone(), value, Value::Immediate(1)]));
+                result
+            }
+            Node::CallExpr(call) if let Some(size) = self.task_stack_size(call) => Value::Immediate(size as i32),
+            Node::CallExpr(call) if let Some(result) = self.build_string_function(call) => result,
+            Node::CallExpr(call) if let Some(result) = self.build_param_call(call) => result,
+            Node::AddressOfExpr(address) if let Some(result) = self.build_address_of(address) => result,
+            // Typecasts between ordinal types and Chr keep the ordinal number
+            Node::CallExpr(call) if call.args.len() == 1 && self.ordinal_cast(&call.name).is_some() => {
+                self.build_expression(&call.args[0])
+            }
+            Node::MethodCall(call) if let Some(result) = self.build_method_call(call) => result,
+            Node::BinaryExpr(bin) if let Some(result) = self.build_interface_query(bin) => result,
+            Node::FieldExpr(member) if let Some(field) = self.build_record_field(member) => field,
+            Node::FieldExpr(member) if let Some(result) = self.build_member(member) => result,
+            Node::BinaryExpr(_) | Node::CallExpr(_) if self.is_string(expr) => self.string_operand(expr),
+            Node::BinaryExpr(bin) if self.is_string_comparison(bin) => self.build_string_comparison(bin),
+            Node::BinaryExpr(bin) if bin.op == ast::BinaryOp::In => self.build_set_membership(bin),
+            Node::BinaryExpr(bin) if self.is_set_comparison(bin) => self.build_set_comparison(bin),
+            Node::SetLiteral(_) | Node::BinaryExpr(_) if let Some(layout) = self.set_layout(expr) => {
+                self.build_set_value(expr, layout)
+            }
+            Node::BinaryExpr(_) | Node::UnaryExpr(_) if self.is_condition(expr) => self.build_boolean(expr),
+            Node::UnaryExpr(unary) if unary.op == ast::UnaryOp::Plus => self.build_expression(&unary.expr),
+            Node::UnaryExpr(unary)
+                if unary.op == ast::UnaryOp::Minus
+                    && !self.analyze_expression_type(&unary.expr).is_some_and(|t| t == Type::real() || Self::is_32_bit(&t)) =>
+            {
+                let value = self.build_expression(&unary.expr);
+                let result = self.new_temp();
+                self.emit(Instruction::new(Opcode::Sub, vec![result.clone(), Value::Immediate(0), value.clone()]));
+                if self.overflow_checks {
+                    self.build_overflow_check(&Opcode::Sub, &result, &Value::Immediate(0), &value, false, unary.span);
+                }
+                result
             }
             Node::BinaryExpr(bin) => {
                 let left = self.build_expression(bin.left.as_ref());
-                let right = self.build_expression(bin.right.as_ref());
+                let mut right = self.build_expression(bin.right.as_ref());
+                let left_type = self.analyze_expression_type(bin.left.as_ref());
+                let right_type = self.analyze_expression_type(bin.right.as_ref());
+                if left_type == Some(Type::real()) || right_type == Some(Type::real()) {
+                    return self.build_real_binary(bin, left, right);
+                }
+                if let Some(long_type) = Self::long_operation_type(left_type.as_ref(), right_type.as_ref()) {
+                    return self.build_long_binary(bin, left, right, &long_type);
+                }
+                // Typed pointers move by whole elements
+                if let Some(size) = left_type.as_ref().and_then(|t| self.pointer_element_size(t)) {
+                    let right_type = self.analyze_expression_type(bin.right.as_ref());
+                    if bin.op == ast::BinaryOp::Subtract && right_type == left_type {
+                        // p - q counts elements
+                        let bytes = self.new_temp();
+                        self.emit(Instruction::new(Opcode::Sub, vec![bytes.clone(), left, right]));
+                        if size == 1 {
+                            return bytes;
+                        }
+                        let result = self.new_temp();
+                        self.emit(Instruction::new(Opcode::Div, vec![result.clone(), bytes, Value::Immediate(size)]));
+                        return result;
+                    }
+                    right = self.scale(right, size);
+                }
                 let result = self.new_temp();
-                
-                if let Some(func) = self.current_function_mut() {
-                    let entry_label = func.entry_block.clone();
-                    if let Some(entry) = func.get_block_mut(&entry_label) {
-                        let opcode = match bin.op {
-                            ast::BinaryOp::Add => Opcode::Add,
-                            ast::BinaryOp::Subtract => Opcode::Sub,
-                            ast::BinaryOp::Multiply => Opcode::Mul,
-                            ast::BinaryOp::Divide => Opcode::Div,
-                            ast::BinaryOp::Mod => Opcode::Mod,
-                            _ => {
-                                // For comparison operators, we'd need different handling
-                                return result; // Placeholder
-                            }
-                        };
-                        entry.add_instruction(Instruction::new(
-                            opcode,
-                            vec![result.clone(), left, right],
-                        ));
+                // Literals are never negative, so an unsigned value divided by one stays unsigned
+                let is_unsigned = |ty: &Option<Type>| matches!(ty, Some(Type::Primitive(prim)) if !prim.is_signed());
+                let unsigned = is_unsigned(&left_type)
+                    && (is_unsigned(&right_type) || matches!(bin.right.as_ref(), Node::LiteralExpr(_)));
+                let opcode = match bin.op {
+                    ast::BinaryOp::Add => Opcode::Add,
+                    ast::BinaryOp::Subtract => Opcode::Sub,
+                    ast::BinaryOp::Multiply => Opcode::Mul,
+                    ast::BinaryOp::Divide | ast::BinaryOp::Div if unsigned => Opcode::DivU,
+                    ast::BinaryOp::Divide | ast::BinaryOp::Div => Opcode::Div,
+                    ast::BinaryOp::Mod if unsigned => Opcode::ModU,
+                    ast::BinaryOp::Mod => Opcode::Mod,
+                    _ => {
+                        self.unsupported(&format!("the operator {:?} on these operands", bin.op), Some(bin.span));
+                        return result;
                     }
+                };
+                self.emit(Instruction::new(
+                    opcode.clone(),
+                    vec![result.clone(), left.clone(), right.clone()],
+                ));
+                if self.overflow_checks {
+                    self.build_overflow_check(&opcode, &result, &left, &right, unsigned, bin.span);
                 }
                 result
             }
+            Node::CallExpr(call) => self.build_call(&call.name, &call.args, call.span).unwrap_or_else(|| self.new_temp()),
             _ => {
-                // For other expression types, return a placeholder
+                self.unsupported("this expression", Some(expr.span()));
                 self.new_temp()
             }
         }
     }
 
+    /// The value `name` stands for: a variable, a constant, a value of an
+    /// enumeration, or else what calling the function `name` returns
+    fn build_name(&mut self, name: &str, span: Span) -> Value {
+        let key = name.to_lowercase();
+        if let Some(field) = self.self_field(name) {
+            let receiver = self.get_variable_address(types::SELF_PARAMETER);
+            return self.build_field_load(receiver, &field, span);
+        }
+        if self.reference_params.contains(&key) {
+            return self.build_reference_load(name, span);
+        }
+        if self.variable_types.contains_key(name) || self.variable_slots.contains_key(&key) || self.global_symbols.contains_key(&key) {
+            return self.get_variable_address(name);
+        }
+        if let Some(value) = self.constants.get(&key) {
+            return Value::Immediate(*value);
+        }
+        if let Some(text) = self.string_constants.get(&key).cloned() {
+            return self.string_literal(&text);
+        }
+        if let Some(ordinal) = self.enum_ordinal(name) {
+            return Value::Immediate(ordinal);
+        }
+        if self.routine_result(name).is_some() || self.self_method(name).is_some_and(|m| m.return_type.is_some()) {
+            return self.build_call(name, &[], span).unwrap_or_else(|| self.new_temp());
+        }
+        if self.is_param_count(name) {
+            return self.build_param_count(span);
+        }
+        self.error(format!("Code generation found no variable '{}'", name), Some(span));
+        self.new_temp()
+    }
+
+    /// The enumeration holding the value `name`, and its ordinal number
+    fn enum_value(&self, name: &str) -> Option<(Type, i32)> {
+        self.type_decls.values().chain(self.variable_types.0.values()).find_map(|ty| match ty {
+            Type::Enum { values } => {
+                values.iter().position(|value| value.eq_ignore_ascii_case(name)).map(|ordinal| (ty.clone(), ordinal as i32))
+            }
+            _ => None,
+        })
+    }
+
+    fn enum_ordinal(&self, name: &str) -> Option<i32> {
+        self.enum_value(name).map(|(_, ordinal)| ordinal)
+    }
+
+    /// Type a typecast named `name` converts an ordinal value to, `Chr`
+    /// included; the value keeps its ordinal number
+    fn ordinal_cast(&self, name: &str) -> Option<Type> {
+        if name.eq_ignore_ascii_case("Chr") {
+            return Some(Type::char());
+        }
+        let ty = match name.to_lowercase().as_str() {
+            "integer" => Type::integer(),
+            "boolean" => Type::boolean(),
+            "char" => Type::char(),
+            "byte" => Type::byte(),
+            "word" => Type::word(),
+            _ => Type::named(self.type_decls.keys().find(|decl| decl.eq_ignore_ascii_case(name))?.clone()),
+        };
+        matches!(self.resolve_type(&ty)?, Type::Primitive(_) | Type::Enum { .. } | Type::Subrange { .. }).then_some(ty)
+    }
+
+    /// Result type of the call `call`
+    fn call_type(&self, call: &ast::CallExpr) -> Option<Type> {
+        let name = call.name.to_lowercase();
+        match name.as_str() {
+            "ord" | "trunc" | "round" | "length" | "pos" => Some(Type::integer()),
+            "taskstacksize" => Some(Type::word()),
+            "succ" | "pred" => call.args.first().and_then(|arg| self.analyze_expression_type(arg)),
+            _ if call.args.is_empty() && self.is_param_count(&call.name) => Some(Type::word()),
+            _ if self.is_param_str(call) => Some(Type::string(types::MAX_STRING_LENGTH)),
+            _ => self.ordinal_cast(&call.name).or_else(|| self.routine_result(&call.name).cloned()),
+        }
+    }
+
     /// Analyze a type expression to get the Type
     fn analyze_type_expr(&self, type_expr: &Node) -> Type {
         match type_expr {
@@ -497,71 +1154,830 @@ impl IRBuilder {
                     "char" => Type::char(),
                     "byte" => Type::byte(),
                     "word" => Type::word(),
+                    "longint" => Type::longint(),
+                    "cardinal" => Type::cardinal(),
+                    "real" | "single" => Type::real(),
                     "variant" => Type::variant(),
+                    "pointer" => Type::UntypedPointer,
+                    // Resolved on use, so pointers may name types declared later
+                    _ => Type::named(named.name.clone()),
+                }
+            }
+            Node::PointerType(pointer) => Type::pointer(self.analyze_type_expr(&pointer.base_type)),
+            Node::EnumType(enum_type) => Type::Enum { values: enum_type.values.clone() },
+            Node::StringType(string) => match string.length.as_deref().and_then(|length| self.bound_value(length)) {
+                Some((_, length)) => Type::string(length as usize),
+                None => Type::string(types::MAX_STRING_LENGTH),
+            },
+            Node::SetType(set) => {
+                let element_type = self.analyze_type_expr(&set.element_type);
+                Type::set(self.resolve_type(&element_type).cloned().unwrap_or(element_type))
+            }
+            Node::ArrayType(array) => {
+                let index_type = self.analyze_type_expr(&array.index_type);
+                let element_type = self.analyze_type_expr(&array.element_type);
+                let mut array = Type::array(
+                    self.resolve_type(&index_type).cloned().unwrap_or(index_type),
+                    self.resolve_type(&element_type).cloned().unwrap_or(element_type),
+                );
+                array.calculate_array_size();
+                array
+            }
+            Node::SubrangeType(subrange) => {
+                match (self.bound_value(&subrange.low), self.bound_value(&subrange.high)) {
+                    (Some((base, low)), Some((_, high))) => {
+                        // Integer bounds take the narrowest base that holds them
+                        let base = match base {
+                            Type::Primitive(PrimitiveType::Integer) if high > i16::MAX as i64 => {
+                                if low >= 0 && high <= u16::MAX as i64 { Type::word() } else { Type::longint() }
+                            }
+                            base => base,
+                        };
+                        Type::subrange(base, low, high)
+                    }
                     _ => Type::Error,
                 }
             }
+            Node::RecordType(record) => {
+                let mut fields = self.record_fields(&record.fields);
+                match &record.variant {
+                    Some(part) => {
+                        let variants = self.record_variants(part, &mut fields);
+                        Type::variant_record(fields, variants, record.is_packed)
+                    }
+                    None => {
+                        let mut laid_out = Type::record(fields);
+                        if record.is_packed {
+                            laid_out.calculate_packed_record_offsets();
+                        } else {
+                            laid_out.calculate_record_offsets();
+                        }
+                        laid_out
+                    }
+                }
+            }
             _ => Type::Error,
         }
     }
 
+    fn record_fields(&self, decls: &[ast::FieldDecl]) -> Vec<types::Field> {
+        decls
+            .iter()
+            .flat_map(|field| {
+                let field_type = self.analyze_type_expr(&field.type_expr);
+                field.names.iter().map(move |name| types::Field {
+                    name: name.clone(),
+                    field_type: Box::new(field_type.clone()),
+                    offset: None,
+                })
+            })
+            .collect()
+    }
+
+    /// The variants of `part`, with its tag field added to `fields`
+    fn record_variants(&self, part: &ast::VariantPart, fields: &mut Vec<types::Field>) -> types::RecordVariants {
+        if let Some(tag) = &part.tag_field {
+            fields.push(types::Field {
+                name: tag.clone(),
+                field_type: Box::new(self.analyze_type_expr(&part.tag_type)),
+                offset: None,
+            });
+        }
+        let mut variants: Vec<_> = part
+            .variants
+            .iter()
+            .map(|variant| {
+                let mut variant_fields = self.record_fields(&variant.fields);
+                let nested = variant.variant.as_ref().map(|nested| self.record_variants(nested, &mut variant_fields));
+                (variant_fields, nested)
+            })
+            .collect();
+        if let Some(else_fields) = &part.else_variant {
+            variants.push((self.record_fields(else_fields), None));
+        }
+        types::RecordVariants { variants }
+    }
+
+    /// Type and ordinal number of a subrange bound: a literal, a negated
+    /// integer literal, or a value of a declared enumeration
+    fn bound_value(&self, bound: &Node) -> Option<(Type, i64)> {
+        match bound {
+            Node::LiteralExpr(lit) => match lit.value {
+                ast::LiteralValue::Integer(i) => Some((Type::integer(), i as i64)),
+                ast::LiteralValue::Char(c) => Some((Type::char(), c as i64)),
+                ast::LiteralValue::Boolean(b) => Some((Type::boolean(), b as i64)),
+                _ => None,
+            },
+            Node::UnaryExpr(unary) if unary.op == ast::UnaryOp::Minus => {
+                let (base, value) = self.bound_value(&unary.expr)?;
+                Some((base, -value))
+            }
+            Node::IdentExpr(ident) => self.type_decls.values().find_map(|ty| match ty {
+                Type::Enum { values } => values
+                    .iter()
+                    .position(|value| value.eq_ignore_ascii_case(&ident.name))
+                    .map(|ordinal| (ty.clone(), ordinal as i64)),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
     /// Analyze an expression to determine its type
     fn analyze_expression_type(&self, expr: &Node) -> Option<Type> {
         match expr {
             Node::LiteralExpr(lit) => {
+                // As semantic analysis types them: above 32767 a literal
+                // only fits a Word
                 match &lit.value {
+                    ast::LiteralValue::Integer(i) if *i > i32::MAX as u32 => Some(Type::cardinal()),
+                    ast::LiteralValue::Integer(i) if *i > u16::MAX as u32 => Some(Type::longint()),
+                    ast::LiteralValue::Integer(i) if *i > i16::MAX as u32 => Some(Type::word()),
                     ast::LiteralValue::Integer(_) => Some(Type::integer()),
+                    ast::LiteralValue::Real(_) => Some(Type::real()),
                     ast::LiteralValue::Boolean(_) => Some(Type::boolean()),
                     ast::LiteralValue::Char(_) => Some(Type::char()),
                     ast::LiteralValue::String(_) => Some(Type::array(Type::integer(), Type::char())),
+                    ast::LiteralValue::Bytes(_) => Some(Type::array(Type::integer(), Type::byte())),
                 }
             }
-            Node::IdentExpr(ident) => {
-                self.variable_types.get(&ident.name).cloned()
+            Node::IdentExpr(ident) => self
+                .self_field(&ident.name)
+                .map(|field| *field.field_type)
+                .or_else(|| self.variable_types.get(&ident.name).cloned())
+                .or_else(|| self.constants.contains_key(&ident.name.to_lowercase()).then(Type::integer))
+                .or_else(|| self.enum_value(&ident.name).map(|(ty, _)| ty))
+                .or_else(|| self.routine_result(&ident.name).cloned())
+                .or_else(|| self.self_method(&ident.name)?.return_type)
+                .or_else(|| self.is_param_count(&ident.name).then(Type::word)),
+            Node::FieldExpr(member) => self.member_type(&member.record, &member.field).or_else(|| self.record_field_type(member)),
+            Node::MethodCall(call) => self.member_type(&call.object, &call.method),
+            Node::EnumLiteralExpr(literal) => self.enum_value(&literal.value).map(|(ty, _)| ty),
+            Node::CallExpr(call) => self.call_type(call),
+            Node::IndexExpr(index) if self.port_width(&index.array).is_some() => self.port_type(&index.array),
+            Node::IndexExpr(index) => self.element_type(index),
+            // Arithmetic with a Real operand is done on reals, otherwise
+            // with a 32-bit operand on 32 bits
+            Node::BinaryExpr(bin) if Self::is_arithmetic(bin.op) || matches!(bin.op, ast::BinaryOp::Div | ast::BinaryOp::Mod) => {
+                let left = self.analyze_expression_type(&bin.left);
+                let right = self.analyze_expression_type(&bin.right);
+                if Self::is_arithmetic(bin.op) && (left == Some(Type::real()) || right == Some(Type::real())) {
+                    return Some(Type::real());
+                }
+                Self::long_operation_type(left.as_ref(), right.as_ref())
+            }
+            Node::BinaryExpr(bin) if Self::comparison(bin.op).is_some() || bin.op == ast::BinaryOp::In => Some(Type::boolean()),
+            Node::BinaryExpr(bin) if matches!(bin.op, ast::BinaryOp::And | ast::BinaryOp::Or) => {
+                self.analyze_expression_type(&bin.left)
             }
+            Node::UnaryExpr(unary) if unary.op != ast::UnaryOp::AddressOf => self.analyze_expression_type(&unary.expr),
             _ => None,
         }
     }
 
+    /// Condition a comparison operator tests
+    fn comparison(op: ast::BinaryOp) -> Option<Condition> {
+        Some(match op {
+            ast::BinaryOp::Equal => Condition::Equal,
+            ast::BinaryOp::NotEqual => Condition::NotEqual,
+            ast::BinaryOp::Less => Condition::Less,
+            ast::BinaryOp::LessEqual => Condition::LessEqual,
+            ast::BinaryOp::Greater => Condition::Greater,
+            ast::BinaryOp::GreaterEqual => Condition::GreaterEqual,
+            _ => return None,
+        })
+    }
+
+    fn is_arithmetic(op: ast::BinaryOp) -> bool {
+        matches!(
+            op,
+            ast::BinaryOp::Add | ast::BinaryOp::Subtract | ast::BinaryOp::Multiply | ast::BinaryOp::Divide
+        )
+    }
+
+    /// Opcode of the `Trunc` or `Round` intrinsic named `name`
+    fn real_to_integer(name: &str) -> Option<Opcode> {
+        if name.eq_ignore_ascii_case("Trunc") {
+            Some(Opcode::FTrunc)
+        } else if name.eq_ignore_ascii_case("Round") {
+            Some(Opcode::FRound)
+        } else {
+            None
+        }
+    }
+
+    /// How far `Ord`, `Succ` or `Pred`, named `name`, moves its argument
+    fn ordinal_step(name: &str) -> Option<i32> {
+        if name.eq_ignore_ascii_case("Ord") {
+            Some(0)
+        } else if name.eq_ignore_ascii_case("Succ") {
+            Some(1)
+        } else if name.eq_ignore_ascii_case("Pred") {
+            Some(-1)
+        } else {
+            None
+        }
+    }
+
+    /// `value`, the result of `expr`, converted to a Real unless it is one
+    fn promote_to_real(&mut self, expr: &Node, value: Value) -> Value {
+        if self.analyze_expression_type(expr) == Some(Type::real()) {
+            return value;
+        }
+        if let Value::Immediate(i) = value {
+            return Value::Immediate((i as f32).to_bits() as i32);
+        }
+        let result = self.new_temp();
+        self.emit(Instruction::new(Opcode::IToF, vec![result.clone(), value]));
+        result
+    }
+
+    /// Build arithmetic or a comparison with a Real operand; the other
+    /// operand is converted to Real first
+    fn build_real_binary(&mut self, bin: &ast::BinaryExpr, left: Value, right: Value) -> Value {
+        let left = self.promote_to_real(&bin.left, left);
+        let right = self.promote_to_real(&bin.right, right);
+        let opcode = match bin.op {
+            ast::BinaryOp::Add => Opcode::FAdd,
+            ast::BinaryOp::Subtract => Opcode::FSub,
+            ast::BinaryOp::Multiply => Opcode::FMul,
+            ast::BinaryOp::Divide => Opcode::FDiv,
+            _ => {
+                self.emit(Instruction::new(Opcode::FCmp, vec![left, right]));
+                return self.flags_boolean(bin.op);
+            }
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(opcode, vec![result.clone(), left, right]));
+        result
+    }
+
+    fn is_32_bit(ty: &Type) -> bool {
+        matches!(ty.subrange_base(), Type::Primitive(prim) if prim.is_32_bit_integer())
+    }
+
+    /// Type of integer arithmetic done on 32 bits: the left operand's if it
+    /// is a LongInt or Cardinal, else the right one's, as semantic analysis
+    /// types it
+    fn long_operation_type(left: Option<&Type>, right: Option<&Type>) -> Option<Type> {
+        [left, right].into_iter().flatten().find(|t| Self::is_32_bit(t)).cloned()
+    }
+
+    /// `value`, the result of `expr`, widened to 32 bits unless it has them;
+    /// constants already hold their full value
+    fn widen(&mut self, expr: &Node, value: Value) -> Value {
+        let opcode = match self.analyze_expression_type(expr) {
+            _ if matches!(value, Value::Immediate(_)) => return value,
+            Some(ty) if Self::is_32_bit(&ty) => return value,
+            Some(Type::Primitive(prim)) if prim.is_signed() => Opcode::SExt,
+            _ => Opcode::ZExt,
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(opcode, vec![result.clone(), value]));
+        result
+    }
+
+    /// Build arithmetic or a comparison on 32 bits; narrower operands are
+    /// widened first
+    fn build_long_binary(&mut self, bin: &ast::BinaryExpr, left: Value, right: Value, long_type: &Type) -> Value {
+        let primitive = |ty: Option<Type>| match ty {
+            Some(Type::Primitive(prim)) => prim,
+            _ => PrimitiveType::LongInt,
+        };
+        let left_prim = primitive(self.analyze_expression_type(&bin.left));
+        let right_prim = primitive(self.analyze_expression_type(&bin.right));
+        let left = self.widen(&bin.left, left);
+        let right = self.widen(&bin.right, right);
+        let unsigned = *long_type == Type::cardinal();
+        let opcode = match bin.op {
+            ast::BinaryOp::Add => Opcode::LAdd,
+            ast::BinaryOp::Subtract => Opcode::LSub,
+            ast::BinaryOp::Multiply => Opcode::LMul,
+            ast::BinaryOp::Divide | ast::BinaryOp::Div if unsigned => Opcode::LDivU,
+            ast::BinaryOp::Divide | ast::BinaryOp::Div => Opcode::LDiv,
+            ast::BinaryOp::Mod if unsigned => Opcode::LModU,
+            ast::BinaryOp::Mod => Opcode::LMod,
+            ast::BinaryOp::Equal | ast::BinaryOp::NotEqual | ast::BinaryOp::Less
+            | ast::BinaryOp::LessEqual | ast::BinaryOp::Greater | ast::BinaryOp::GreaterEqual => {
+                let kind = Value::Compare(ComparisonKind::of(left_prim, right_prim));
+                self.emit(Instruction::new(Opcode::LCmp, vec![left, right, kind]));
+                return self.flags_boolean(bin.op);
+            }
+            _ => {
+                self.unsupported(&format!("the operator {:?} on 32-bit operands", bin.op), Some(bin.span));
+                return self.new_temp();
+            }
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(opcode, vec![result.clone(), left, right]));
+        result
+    }
+
+    /// Size of the element a typed pointer points to, or None for other types
+    fn pointer_element_size(&self, ty: &Type) -> Option<i32> {
+        let Type::Pointer { base_type } = self.resolve_type(ty)? else {
+            return None;
+        };
+        // Semantic analysis rejects pointers to types of unknown size
+        Some(self.resolve_type(base_type)?.size().unwrap_or(1) as i32)
+    }
+
+    /// Follow declared type names to the type they stand for
+    fn resolve_type<'a>(&'a self, mut ty: &'a Type) -> Option<&'a Type> {
+        while let Type::Named { name } = ty {
+            ty = self.type_decls.get(name)?;
+        }
+        Some(ty)
+    }
+
+    /// Check `value` against the bounds of a subrange of `base`:
+    ///
+    /// ```text
+    ///     CMP value, low
+    ///     CJUMP LT, range_error, range_low_ok
+    /// range_low_ok:
+    ///     CMP value, high
+    ///     CJUMP GT, range_error, range_ok
+    /// range_error:
+    ///     CALL __range_error            ; does not return
+    /// range_ok:
+    /// ```
+    ///
+    /// A bound at the limit of `base` needs no test, and a constant in
+    /// range none at all
+    fn build_range_check(&mut self, value: Value, base: &Type, low: i64, high: i64, span: Span) {
+        if let Value::Immediate(n) = value
+            && (low..=high).contains(&(n as i64))
+        {
+            return;
+        }
+        let (min, max) = base.ordinal_bounds().unwrap_or((i64::MIN, i64::MAX));
+        let tests: Vec<(Condition, i64)> = [(Condition::Less, low, low > min), (Condition::Greater, high, high < max)]
+            .into_iter()
+            .filter_map(|(condition, bound, needed)| needed.then_some((condition, bound)))
+            .collect();
+        if tests.is_empty() {
+            return;
+        }
+
+        let (opcode, kind) = match base.representation() {
+            Type::Primitive(prim) if prim.is_32_bit_integer() => (Opcode::LCmp, Some(ComparisonKind::of(*prim, *prim))),
+            Type::Primitive(prim) => (Opcode::Cmp, Some(ComparisonKind::of(*prim, *prim))),
+            _ => (Opcode::Cmp, None),
+        };
+        let error_label = self.new_label("range_error");
+        let ok_label = self.new_label("range_ok");
+        let count = tests.len();
+        for (i, (condition, bound)) in tests.into_iter().enumerate() {
+            let next_label = if i + 1 == count { ok_label.clone() } else { self.new_label("range_low_ok") };
+            let mut operands = vec![value.clone(), Value::Immediate(bound as i32)];
+            operands.extend(kind.map(Value::Compare));
+            self.emit(Instruction::new(opcode.clone(), operands).with_span(span));
+            self.emit(Instruction::new(
+                Opcode::CJump,
+                vec![Value::Condition(condition), Value::Label(error_label.clone()), Value::Label(next_label.clone())],
+            ));
+            if i + 1 < count {
+                self.start_block(next_label);
+            }
+        }
+        self.start_block(error_label);
+        self.emit(Instruction::new(Opcode::Call, vec![Value::Label(RANGE_ERROR_ROUTINE.to_string())]).with_span(span));
+        self.start_block(ok_label);
+    }
+
+    /// Multiply an element count by the element size
+    fn scale(&mut self, count: Value, size: i32) -> Value {
+        match count {
+            _ if size == 1 => count,
+            Value::Immediate(n) => Value::Immediate(n * size),
+            count => {
+                let bytes = self.new_temp();
+                self.emit(Instruction::new(Opcode::Mul, vec![bytes.clone(), count, Value::Immediate(size)]));
+                bytes
+            }
+        }
+    }
+
     /// Get the VariantType ID for a given Type
     fn get_variant_type_id(&self, ty: &Type) -> Value {
         let variant_type = RuntimeVariantType::from_type(ty);
         Value::Immediate(variant_type as i32)
     }
 
-    /// Get the address/value of a variable
-    fn get_variable_address(&self, _name: &str) -> Value {
-        // In a full implementation, this would look up the variable's memory location
-        // For now, use a simplified memory address
-        Value::Memory {
-            base: "sp".to_string(),
-            offset: 0, // Would be calculated based on variable's position
+    /// Get the address/value of a variable: a frame slot of the routine
+    /// being built, or a global
+    fn get_variable_address(&mut self, name: &str) -> Value {
+        let key = name.to_lowercase();
+        if self.reference_params.contains(&key) {
+            self.unsupported(&format!("this use of the var parameter '{}'", name), None);
+        }
+        if let Some(offset) = self.variable_slots.get(&key) {
+            return Value::Memory { base: constfold::FRAME_BASE.to_string(), offset: *offset };
+        }
+        if let Some(offset) = self.threadvar_slots.get(&key) {
+            return Value::Memory { base: THREADVAR_BASE.to_string(), offset: *offset };
+        }
+        let symbol = match self.global_symbols.get(&key) {
+            Some(symbol) => symbol.clone(),
+            None => {
+                // Names given a type without a declaration, such as an
+                // exception handler's variable outside a routine, are
+                // globals too
+                if !self.variable_types.contains_key(name) {
+                    self.error(format!("Code generation found no variable '{}'", name), None);
+                }
+                self.global_symbols.insert(key, name.to_string());
+                self.program.globals.push((name.to_string(), self.variable_types.get(name).cloned().unwrap_or(Type::integer())));
+                name.to_string()
+            }
+        };
+        Value::Memory { base: global_base(&symbol), offset: 0 }
+    }
+
+    /// Build an asm block, resolving the names it refers to
+    fn build_asm_stmt(&mut self, asm: &ast::AsmStmt) {
+        let mut references = vec![];
+        for (range, name) in asm.references() {
+            // A var parameter stands for its slot, holding the address
+            let value = if let Some(slot) = self.reference_slot(name) {
+                slot
+            } else if self.variable_types.contains_key(name) {
+                self.get_variable_address(name)
+            } else if let Some(value) = self.constants.get(&name.to_lowercase()) {
+                Value::Immediate(*value)
+            } else {
+                Value::Label(name.to_string())
+            };
+            references.push((range, value));
         }
+        let block = AsmBlock { body: asm.body.clone(), references, clobbers: asm.clobbers.clone() };
+        self.emit(Instruction::new(Opcode::Asm, vec![Value::Asm(Box::new(block))]).with_span(asm.span));
+    }
+
+    /// Build a procedure call statement: a standard procedure, or a call
+    /// of a routine (see routines.rs)
+    fn build_call_stmt(&mut self, call: &ast::CallStmt) {
+        let opcode = if call.name.eq_ignore_ascii_case("Inc") {
+            Opcode::Add
+        } else if call.name.eq_ignore_ascii_case("Dec") {
+            Opcode::Sub
+        } else if self.build_string_procedure(call) || self.build_console_procedure(call) || self.build_exit(call) {
+            return;
+        } else {
+            self.build_call(&call.name, &call.args, call.span);
+            return;
+        };
+        self.build_inc_dec(opcode, call);
     }
 
-    // Placeholder methods for other statement types
-    fn build_call_stmt(&mut self, _call: &ast::CallStmt) {
-        // TODO: Implement
-    }
+    /// Build `Inc(x [, n])` or `Dec(x [, n])` as `x := x +/- n`, with `n`
+    /// scaled by the element size when `x` is a typed pointer
+    fn build_inc_dec(&mut self, opcode: Opcode, call: &ast::CallStmt) {
+        let Some(target) = call.args.first() else {
+            return;
+        };
+        let mut step = match call.args.get(1) {
+            Some(step) => self.build_expression(step),
+            None => Value::Immediate(1),
+        };
+        if let Some(size) = self.analyze_expression_type(target).and_then(|t| self.pointer_element_size(&t)) {
+            step = self.scale(step, size);
+        }
+        if self.build_reference_inc_dec(opcode.clone(), target, step.clone(), call.span) {
+            return;
+        }
+        let var = self.build_expression(target);
+        self.emit(Instruction::new(opcode, vec![var.clone(), var, step]).with_span(call.span));
+    }
+
+    /// Build an IF statement:
+    ///
+    /// ```text
+    ///     <branch on condition to if_then, if_else>
+    /// if_then:
+    ///     <then>
+    ///     JUMP if_end
+    /// if_else:                          ; only with an ELSE
+    ///     <else>
+    /// if_end:
+    /// ```
+    fn build_if_stmt(&mut self, if_stmt: &ast::IfStmt) {
+        let then_label = self.new_label("if_then");
+        let end_label = self.new_label("if_end");
+        let else_label = if_stmt.else_block.as_ref().map(|_| self.new_label("if_else"));
+        self.build_branch(&if_stmt.condition, &then_label, else_label.as_ref().unwrap_or(&end_label));
+        self.start_block(then_label);
+        self.build_node(&if_stmt.then_block);
+        self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(end_label.clone())]));
+        if let (Some(else_label), Some(else_block)) = (else_label, &if_stmt.else_block) {
+            self.start_block(else_label);
+            self.build_node(else_block);
+        }
+        self.start_block(end_label);
+    }
+
+    /// Build a WHILE loop:
+    ///
+    /// ```text
+    /// while_test:
+    ///     <branch on condition to while_body, while_exit>
+    /// while_body:
+    ///     <body>
+    ///     JUMP while_test
+    /// while_exit:
+    /// ```
+    fn build_while_stmt(&mut self, while_stmt: &ast::WhileStmt) {
+        let test_label = self.new_label("while_test");
+        let body_label = self.new_label("while_body");
+        let exit_label = self.new_label("while_exit");
+        self.start_block(test_label.clone());
+        self.build_branch(&while_stmt.condition, &body_label, &exit_label);
+        self.start_block(body_label);
+        self.build_node(&while_stmt.body);
+        self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(test_label)]));
+        self.start_block(exit_label);
+    }
+
+    /// Build a FOR loop:
+    ///
+    /// ```text
+    ///     first := start; last := end       ; bounds evaluated once
+    ///     var := first
+    ///     CMP (last, first | first, last)   ; empty range -> exit
+    /// body:
+    ///     <body>
+    ///     CMP var, last                     ; exit test before stepping, so a
+    ///     CJUMP EQ, exit, step              ; loop ending at High(type) never wraps
+    /// step:
+    ///     var := var +/- 1
+    ///     JUMP body
+    /// exit:
+    /// ```
+    fn build_for_stmt(&mut self, for_stmt: &ast::ForStmt) {
+        let start = self.build_expression(for_stmt.start_expr.as_ref());
+        let end = self.build_expression(for_stmt.end_expr.as_ref());
+        let trip_count = match (&start, &end, for_stmt.direction) {
+            (Value::Immediate(start), Value::Immediate(end), ast::ForDirection::To) => Some((end - start + 1).max(0) as u32),
+            (Value::Immediate(start), Value::Immediate(end), ast::ForDirection::Downto) => Some((start - end + 1).max(0) as u32),
+            _ => None,
+        };
+        let first = self.new_temp();
+        let last = self.new_temp();
+        let var = self.get_variable_address(&for_stmt.var_name);
+
+        let body_label = self.new_label("for_body");
+        let step_label = self.new_label("for_step");
+        let exit_label = self.new_label("for_exit");
+
+        let (step_op, mut empty_test) = match for_stmt.direction {
+            ast::ForDirection::To => (Opcode::Add, vec![last.clone(), first.clone()]),
+            ast::ForDirection::Downto => (Opcode::Sub, vec![first.clone(), last.clone()]),
+        };
+        let mut exit_test = vec![var.clone(), last.clone()];
+        // Integer loops need a signed compare for the empty-range test
+        if let Some(Type::Primitive(prim)) = self.variable_types.get(&for_stmt.var_name).map(Type::subrange_base) {
+            let kind = Value::Compare(ComparisonKind::of(*prim, *prim));
+            empty_test.push(kind.clone());
+            exit_test.push(kind);
+        }
+
+        self.emit(Instruction::new(Opcode::Mov, vec![first.clone(), start]));
+        self.emit(Instruction::new(Opcode::Mov, vec![last.clone(), end]));
+        self.emit(Instruction::new(Opcode::Store, vec![var.clone(), first]));
+        self.emit(Instruction::new(Opcode::Cmp, empty_test));
+        self.emit(Instruction::new(
+            Opcode::CJump,
+            vec![
+                Value::Condition(Condition::Less),
+                Value::Label(exit_label.clone()),
+                Value::Label(body_label.clone()),
+            ],
+        ));
+
+        self.start_block(body_label.clone());
+        self.build_node(for_stmt.body.as_ref());
+        self.emit(Instruction::new(Opcode::Cmp, exit_test));
+        self.emit(Instruction::new(
+            Opcode::CJump,
+            vec![
+                Value::Condition(Condition::Equal),
+                Value::Label(exit_label.clone()),
+                Value::Label(step_label.clone()),
+            ],
+        ));
 
-    fn build_if_stmt(&mut self, _if_stmt: &ast::IfStmt) {
-        // TODO: Implement
+        self.start_block(step_label.clone());
+        self.emit(Instruction::new(step_op, vec![var.clone(), var, Value::Immediate(1)]));
+        self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(body_label.clone())]));
+
+        self.start_block(exit_label.clone());
+        if let Some(func) = self.current_function_mut() {
+            func.loops.push(CountedLoop {
+                body: body_label,
+                step: step_label,
+                exit: exit_label,
+                trip_count,
+                unroll: for_stmt.unroll,
+            });
+        }
     }
 
-    fn build_while_stmt(&mut self, _while_stmt: &ast::WhileStmt) {
-        // TODO: Implement
+    /// Build a REPEAT loop, whose body runs at least once:
+    ///
+    /// ```text
+    /// repeat_body:
+    ///     <statements>
+    ///     <branch on condition to repeat_exit, repeat_body>
+    /// repeat_exit:
+    /// ```
+    fn build_repeat_stmt(&mut self, repeat: &ast::RepeatStmt) {
+        let body_label = self.new_label("repeat_body");
+        let exit_label = self.new_label("repeat_exit");
+        self.start_block(body_label.clone());
+        for stmt in &repeat.statements {
+            self.build_node(stmt);
+        }
+        self.build_branch(&repeat.condition, &exit_label, &body_label);
+        self.start_block(exit_label);
     }
 
-    fn build_for_stmt(&mut self, _for_stmt: &ast::ForStmt) {
-        // TODO: Implement
+    /// Whether `expr` is a Boolean condition built as branches: a
+    /// comparison of ordinals, or `and`, `or` or `not` of Booleans
+    fn is_condition(&self, expr: &Node) -> bool {
+        let is_boolean = |e: &Node| self.analyze_expression_type(e) == Some(Type::boolean());
+        match expr {
+            // Sets and strings are compared by their runtime routines
+            Node::BinaryExpr(bin) if self.is_set_comparison(bin) || self.is_string_comparison(bin) => false,
+            Node::BinaryExpr(bin) if Self::comparison(bin.op).is_some() => [&bin.left, &bin.right].iter().all(|operand| {
+                !self.analyze_expression_type(operand).is_some_and(|t| t == Type::real() || Self::is_32_bit(&t))
+            }),
+            Node::BinaryExpr(bin) if matches!(bin.op, ast::BinaryOp::And | ast::BinaryOp::Or) => is_boolean(&bin.left),
+            Node::UnaryExpr(unary) if unary.op == ast::UnaryOp::Not => is_boolean(&unary.expr),
+            _ => false,
+        }
     }
 
-    fn build_repeat_stmt(&mut self, _repeat: &ast::RepeatStmt) {
-        // TODO: Implement
+    /// Jump to `if_true` or `if_false` on the Boolean `condition`; `and` and
+    /// `or` skip their right operand once the left decides the result
+    fn build_branch(&mut self, condition: &Node, if_true: &str, if_false: &str) {
+        let jump = |condition: Condition, if_true: &str, if_false: &str| {
+            Instruction::new(
+                Opcode::CJump,
+                vec![Value::Condition(condition), Value::Label(if_true.to_string()), Value::Label(if_false.to_string())],
+            )
+        };
+        match condition {
+            Node::BinaryExpr(bin) if self.is_condition(condition) && bin.op == ast::BinaryOp::And => {
+                let right_label = self.new_label("and");
+                self.build_branch(&bin.left, &right_label, if_false);
+                self.start_block(right_label);
+                self.build_branch(&bin.right, if_true, if_false);
+            }
+            Node::BinaryExpr(bin) if self.is_condition(condition) && bin.op == ast::BinaryOp::Or => {
+                let right_label = self.new_label("or");
+                self.build_branch(&bin.left, if_true, &right_label);
+                self.start_block(right_label);
+                self.build_branch(&bin.right, if_true, if_false);
+            }
+            Node::UnaryExpr(unary) if self.is_condition(condition) => self.build_branch(&unary.expr, if_false, if_true),
+            Node::BinaryExpr(bin) if self.is_condition(condition) => {
+                let left = self.build_expression(&bin.left);
+                let right = self.build_expression(&bin.right);
+                let primitive = |ty: Option<Type>| match ty.as_ref().map(Type::subrange_base) {
+                    Some(Type::Primitive(prim)) => Some(*prim),
+                    _ => None,
+                };
+                let mut operands = vec![left, right];
+                if let (Some(left), Some(right)) =
+                    (primitive(self.analyze_expression_type(&bin.left)), primitive(self.analyze_expression_type(&bin.right)))
+                {
+                    operands.push(Value::Compare(ComparisonKind::of(left, right)));
+                }
+                self.emit(Instruction::new(Opcode::Cmp, operands));
+                let condition = Self::comparison(bin.op).unwrap_or(Condition::NotEqual);
+                self.emit(jump(condition, if_true, if_false));
+            }
+            _ => {
+                let value = self.build_expression(condition);
+                self.emit(Instruction::new(Opcode::Cmp, vec![value, Value::Immediate(0)]));
+                self.emit(jump(Condition::NotEqual, if_true, if_false));
+            }
+        }
     }
 
-    fn build_case_stmt(&mut self, _case_stmt: &ast::CaseStmt) {
-        // TODO: Implement
+    /// The Boolean the comparison `op` gives on the flags just set, 1 or 0
+    fn flags_boolean(&mut self, op: ast::BinaryOp) -> Value {
+        let result = self.new_temp();
+        let true_label = self.new_label("true");
+        let false_label = self.new_label("false");
+        let end_label = self.new_label("boolean_end");
+        let condition = Self::comparison(op).unwrap_or(Condition::NotEqual);
+        self.emit(Instruction::new(
+            Opcode::CJump,
+            vec![Value::Condition(condition), Value::Label(true_label.clone()), Value::Label(false_label.clone())],
+        ));
+        for (label, value) in [(true_label, 1), (false_label, 0)] {
+            self.start_block(label);
+            self.emit(Instruction::new(Opcode::Mov, vec![result.clone(), Value::Immediate(value)]));
+            self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(end_label.clone())]));
+        }
+        self.start_block(end_label);
+        result
+    }
+
+    /// Build a condition as a Boolean value, 1 or 0
+    fn build_boolean(&mut self, condition: &Node) -> Value {
+        let result = self.new_temp();
+        let true_label = self.new_label("true");
+        let false_label = self.new_label("false");
+        let end_label = self.new_label("boolean_end");
+        self.build_branch(condition, &true_label, &false_label);
+        for (label, value) in [(true_label, 1), (false_label, 0)] {
+            self.start_block(label);
+            self.emit(Instruction::new(Opcode::Mov, vec![result.clone(), Value::Immediate(value)]));
+            self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(end_label.clone())]));
+        }
+        self.start_block(end_label);
+        result
+    }
+
+    /// Build a CASE statement. Labels that are literals or enumeration
+    /// values become the ranges of a SWITCH, which the backend turns into a
+    /// jump table or a compare chain; any other label is compared first:
+    ///
+    /// ```text
+    ///     CMP selector, N                   ; case I of N: A; 1, 3..5: B else C end
+    ///     CJUMP EQ, case_0, case_test
+    /// case_test:
+    ///     SWITCH selector, case_else, 1, 1, case_1, 3, 5, case_1
+    /// case_0:
+    ///     <A>
+    ///     JUMP case_end
+    /// case_1:
+    ///     <B>
+    ///     JUMP case_end
+    /// case_else:
+    ///     <C>
+    /// case_end:
+    /// ```
+    fn build_case_stmt(&mut self, case_stmt: &ast::CaseStmt) {
+        let selector = self.build_expression(&case_stmt.expr);
+        let kind = match self.analyze_expression_type(&case_stmt.expr).as_ref().map(Type::subrange_base) {
+            Some(Type::Primitive(prim)) => Some(Value::Compare(ComparisonKind::of(*prim, *prim))),
+            _ => None,
+        };
+        let branch_labels: Vec<String> = case_stmt.cases.iter().map(|_| self.new_label("case")).collect();
+        let else_label = self.new_label("case_else");
+        let end_label = self.new_label("case_end");
+
+        let mut switch = vec![selector.clone(), Value::Label(else_label.clone())];
+        for (branch, label) in case_stmt.cases.iter().zip(&branch_labels) {
+            for value in &branch.values {
+                let (low, high) = match value {
+                    Node::SubrangeType(range) => (range.low.as_ref(), range.high.as_ref()),
+                    _ => (value, value),
+                };
+                if let (Some((_, low)), Some((_, high))) = (self.bound_value(low), self.bound_value(high)) {
+                    switch.extend([Value::Immediate(low as i32), Value::Immediate(high as i32), Value::Label(label.clone())]);
+                    continue;
+                }
+                // Compare with the bounds; a range tests its upper bound in
+                // a block of its own, and both tests fail to the next label
+                let next = self.new_label("case_test");
+                let tests = if std::ptr::eq(low, high) {
+                    vec![(Condition::Equal, low)]
+                } else {
+                    vec![(Condition::GreaterEqual, low), (Condition::LessEqual, high)]
+                };
+                let count = tests.len();
+                for (i, (condition, bound)) in tests.into_iter().enumerate() {
+                    let bound = self.build_expression(bound);
+                    self.emit(Instruction::new(Opcode::Cmp, [vec![selector.clone(), bound], kind.clone().into_iter().collect()].concat()));
+                    let taken = if i + 1 == count { label.clone() } else { self.new_label("case_test") };
+                    self.emit(Instruction::new(
+                        Opcode::CJump,
+                        vec![Value::Condition(condition), Value::Label(taken.clone()), Value::Label(next.clone())],
+                    ));
+                    if i + 1 < count {
+                        self.start_block(taken);
+                    }
+                }
+                self.start_block(next);
+            }
+        }
+        self.emit(Instruction::new(Opcode::Switch, switch).with_span(case_stmt.span));
+
+        for (branch, label) in case_stmt.cases.iter().zip(branch_labels) {
+            self.start_block(label);
+            self.build_node(&branch.statement);
+            self.emit(Instruction::new(Opcode::Jump, vec![Value::Label(end_label.clone())]));
+        }
+        self.start_block(else_label);
+        if let Some(else_branch) = &case_stmt.else_branch {
+            self.build_node(else_branch);
+        }
+        self.start_block(end_label);
     }
 
     /// Get the built program
@@ -920,6 +2336,30 @@ impl IRBuilder {
     }
 }
 
+/// Names of the range check switch: {$R+}, {$RANGECHECKS OFF}, ...
+const RANGE_CHECK_SWITCHES: [&str; 3] = ["R", "RANGECHECKS", "RANGE_CHECK"];
+/// Names of the overflow check switch: {$Q+}, {$OVERFLOWCHECKS OFF}, ...
+const OVERFLOW_CHECK_SWITCHES: [&str; 2] = ["Q", "OVERFLOWCHECKS"];
+/// Name of the switch allowing optimization passes to change functions
+pub const OPTIMIZATION_SWITCH: &str = "OPTIMIZATION";
+
+/// State a directive sets for a switch named one of `names`: `NAME+` or
+/// `NAME ON` turn it on, `NAME-` or `NAME OFF` off
+fn switch_state(content: &str, names: &[&str]) -> Option<bool> {
+    let content = content.trim().to_uppercase();
+    let (name, state) = match content.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [name, state] => (*name, *state),
+        [switch] => switch.split_at_checked(switch.len().checked_sub(1)?)?,
+        _ => return None,
+    };
+    let enabled = match state {
+        "ON" | "+" => true,
+        "OFF" | "-" => false,
+        _ => return None,
+    };
+    names.contains(&name).then_some(enabled)
+}
+
 impl Default for IRBuilder {
     fn default() -> Self {
         Self::new()
@@ -1023,6 +2463,10 @@ mod tests {
             Opcode::Div, Opcode::Mod, Opcode::Cmp, Opcode::Jump,
             Opcode::CJump, Opcode::Call, Opcode::Ret, Opcode::Load,
             Opcode::Store, Opcode::Push, Opcode::Pop,
+            Opcode::FAdd, Opcode::FSub, Opcode::FMul, Opcode::FDiv,
+            Opcode::FCmp, Opcode::IToF, Opcode::FTrunc, Opcode::FRound,
+            Opcode::LAdd, Opcode::LSub, Opcode::LMul, Opcode::LDiv, Opcode::LDivU,
+            Opcode::LMod, Opcode::LModU, Opcode::LCmp, Opcode::SExt, Opcode::ZExt,
         ];
         
         for op in &ops {
@@ -1825,6 +3269,7 @@ mod tests {
         let program = Node::Program(ast::Program {
             name: "test".to_string(),
             directives: vec![],
+            uses: None,
             block: Box::new(Node::Block(ast::Block {
                 directives: vec![],
                 label_decls: vec![],
@@ -1872,4 +3317,766 @@ mod tests {
         // Verify Variant variable was registered
         assert_eq!(builder.variable_types.get("v"), Some(&Type::variant()));
     }
+
+    #[test]
+    fn test_build_range_checks_follow_directives() {
+        let span = Span::new(0, 10, 1, 1);
+        let literal = |value| Box::new(Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(value), span }));
+        let directive = |content: &str| Node::Directive(ast::Directive { content: content.to_string(), span });
+        let program = |switch: &str| Node::Program(ast::Program {
+            name: "Test".to_string(),
+            directives: vec![directive(switch)],
+            uses: None,
+            block: Box::new(Node::Block(ast::Block {
+                directives: vec![],
+                label_decls: vec![],
+                const_decls: vec![],
+                type_decls: vec![],
+                var_decls: vec![Node::VarDecl(ast::VarDecl {
+                    names: vec!["d".to_string()],
+                    type_expr: Box::new(Node::SubrangeType(ast::SubrangeType { low: literal(1), high: literal(10), span })),
+                    is_class_var: false,
+                    absolute_address: None,
+                    span,
+                })],
+                threadvar_decls: vec![],
+                proc_decls: vec![],
+                func_decls: vec![],
+                operator_decls: vec![],
+                statements: vec![
+                    Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("d")), value: Box::new(ident("n")), span }),
+                    Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("d")), value: literal(5), span }),
+                ],
+                span,
+            })),
+            span,
+        });
+        let build = |switch: &str| IRBuilder::new().build(&program(switch)).functions[0].clone();
+
+        // Only the variable is checked; the constant 5 is known to fit
+        let checked = build("R+");
+        let labels: Vec<_> = checked.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, ["main_entry", "range_low_ok_2", "range_error_0", "range_ok_1"]);
+        assert_eq!(opcodes(&checked.blocks[0]), [Opcode::Cmp, Opcode::CJump]);
+        assert_eq!(
+            checked.blocks[0].instructions[0].operands[1..],
+            [Value::Immediate(1), Value::Compare(ComparisonKind::Signed)]
+        );
+        assert_eq!(checked.blocks[1].instructions[1].operands[0], Value::Condition(Condition::Greater));
+        assert_eq!(checked.blocks[2].instructions[0].operands, [Value::Label(RANGE_ERROR_ROUTINE.to_string())]);
+        assert_eq!(opcodes(&checked.blocks[3]), [Opcode::Store, Opcode::Store]);
+
+        let unchecked = build("RANGECHECKS OFF");
+        assert_eq!(unchecked.blocks.len(), 1);
+        assert_eq!(opcodes(&unchecked.blocks[0]), [Opcode::Store, Opcode::Store]);
+    }
+
+    #[test]
+    fn test_build_optimization_follows_directives() {
+        let span = Span::new(0, 10, 1, 1);
+        let directive = |content: &str| Node::Directive(ast::Directive { content: content.to_string(), span });
+        let build = |switch: &str| {
+            let mut program = IRBuilder::new().build(&Node::Program(ast::Program {
+                name: "Test".to_string(),
+                directives: vec![directive(switch)],
+                uses: None,
+                block: Box::new(Node::Block(ast::Block {
+                    directives: vec![],
+                    label_decls: vec![],
+                    const_decls: vec![],
+                    type_decls: vec![],
+                    var_decls: vec![],
+                    threadvar_decls: vec![],
+                    proc_decls: vec![],
+                    func_decls: vec![],
+                    operator_decls: vec![],
+                    statements: vec![],
+                    span,
+                })),
+                span,
+            }));
+            program.functions[0].blocks[0]
+                .add_instruction(Instruction::new(Opcode::Add, vec![Value::Temp(0), Value::Immediate(2), Value::Immediate(3)]));
+            let changed = fold_constants(&mut program);
+            (changed, program.functions[0].blocks[0].instructions[0].opcode.clone())
+        };
+
+        assert_eq!(build("OPTIMIZATION ON"), (1, Opcode::Mov));
+        assert_eq!(build("optimization off"), (0, Opcode::Add));
+    }
+
+    #[test]
+    fn test_build_range_check_skips_bounds_of_base_type() {
+        let mut builder = IRBuilder::new();
+        builder.set_range_checks(true);
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("b".to_string(), Type::subrange(Type::byte(), 0, 99));
+        let span = Span::new(0, 1, 1, 1);
+        builder.build_assign_stmt(&ast::AssignStmt { target: Box::new(ident("b")), value: Box::new(ident("n")), span });
+        let func = builder.current_function_mut().unwrap();
+
+        // Bytes are never below 0, so only the upper bound is tested
+        let labels: Vec<_> = func.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, ["test_entry", "range_error_0", "range_ok_1"]);
+        assert_eq!(func.blocks[0].instructions[0].operands[1], Value::Immediate(99));
+        assert_eq!(func.blocks[0].instructions[1].operands[0], Value::Condition(Condition::Greater));
+    }
+
+    #[test]
+    fn test_array_elements_lower_to_operands_and_checked_addresses() {
+        // A[3] := N; N := A[I]; with A: array[1..10] of Integer under {$R+}
+        let mut builder = IRBuilder::new();
+        builder.set_range_checks(true);
+        let span = Span::new(0, 1, 1, 1);
+        let element = |index| Box::new(Node::IndexExpr(ast::IndexExpr { array: Box::new(ident("A")), index: Box::new(index), span }));
+        builder.declare_global("A", Type::array(Type::subrange(Type::integer(), 1, 10), Type::integer()));
+        builder.start_function("main".to_string(), None);
+        builder.build_node(&Node::AssignStmt(ast::AssignStmt { target: element(integer(3)), value: Box::new(ident("N")), span }));
+        builder.build_node(&Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("N")), value: element(ident("I")), span }));
+        assert_eq!(builder.analyze_expression_type(&element(ident("I"))), Some(Type::integer()));
+        builder.finish_function();
+
+        let program = builder.into_program();
+        let main = &program.functions[0];
+        // A constant index is folded into the operand
+        assert_eq!(main.blocks[0].instructions[0].opcode, Opcode::Store);
+        assert_eq!(main.blocks[0].instructions[0].operands[0], Value::Memory { base: global_base("A"), offset: 4 });
+        // A variable one is checked, then the element read through its
+        // address
+        let labels: Vec<_> = main.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert!(labels.iter().any(|label| label.starts_with("range_error_")), "{:?}", labels);
+        let last = main.blocks.last().unwrap();
+        assert_eq!(opcodes(last), [Opcode::Sub, Opcode::Mul, Opcode::Addr, Opcode::Add, Opcode::LoadAt, Opcode::Store]);
+        assert_eq!(last.instructions[4].operands[2], Value::Immediate(2));
+    }
+
+    fn for_stmt(direction: ast::ForDirection, start: u32, end: u32) -> ast::ForStmt {
+        let span = Span::new(0, 10, 1, 1);
+        let literal = |value| Box::new(Node::LiteralExpr(ast::LiteralExpr {
+            value: ast::LiteralValue::Integer(value),
+            span,
+        }));
+        ast::ForStmt {
+            var_name: "i".to_string(),
+            start_expr: literal(start),
+            direction,
+            end_expr: literal(end),
+            body: Box::new(Node::AssignStmt(ast::AssignStmt {
+                target: Box::new(Node::IdentExpr(ast::IdentExpr { name: "x".to_string(), span })),
+                value: Box::new(Node::IdentExpr(ast::IdentExpr { name: "i".to_string(), span })),
+                span,
+            })),
+            unroll: None,
+            span,
+        }
+    }
+
+    fn opcodes(block: &BasicBlock) -> Vec<Opcode> {
+        block.instructions.iter().map(|inst| inst.opcode.clone()).collect()
+    }
+
+    #[test]
+    fn test_build_for_to_tests_exit_before_increment() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.build_for_stmt(&for_stmt(ast::ForDirection::To, 65530, 65535));
+        let func = builder.current_function_mut().unwrap();
+
+        let labels: Vec<_> = func.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, ["test_entry", "for_body_0", "for_step_1", "for_exit_2"]);
+
+        // Bounds are evaluated once, then an empty range skips the loop
+        let entry = &func.blocks[0];
+        assert_eq!(
+            opcodes(entry),
+            [Opcode::Mov, Opcode::Mov, Opcode::Store, Opcode::Cmp, Opcode::CJump]
+        );
+        assert_eq!(entry.instructions[0].operands[1], Value::Immediate(65530));
+        assert_eq!(entry.instructions[1].operands[1], Value::Immediate(65535));
+        let (first, last) = (Value::Temp(0), Value::Temp(1));
+        assert_eq!(entry.instructions[2].operands[1], first);
+        assert_eq!(entry.instructions[3].operands, [last.clone(), first]);
+        assert_eq!(entry.instructions[4].operands[0], Value::Condition(Condition::Less));
+        assert_eq!(entry.instructions[4].operands[1], Value::Label("for_exit_2".to_string()));
+
+        // The exit test compares against the saved bound before stepping,
+        // so the loop terminates at High(type) instead of wrapping to 0
+        let body = &func.blocks[1];
+        assert_eq!(opcodes(body), [Opcode::Store, Opcode::Cmp, Opcode::CJump]);
+        assert_eq!(body.instructions[1].operands[1], last);
+        assert_eq!(body.instructions[2].operands[0], Value::Condition(Condition::Equal));
+        assert_eq!(body.instructions[2].operands[1], Value::Label("for_exit_2".to_string()));
+
+        let step = &func.blocks[2];
+        assert_eq!(opcodes(step), [Opcode::Add, Opcode::Jump]);
+        assert_eq!(step.instructions[1].operands, [Value::Label("for_body_0".to_string())]);
+        assert!(func.blocks[3].instructions.is_empty());
+    }
+
+    #[test]
+    fn test_build_for_downto() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.build_for_stmt(&for_stmt(ast::ForDirection::Downto, 10, 0));
+        let func = builder.current_function_mut().unwrap();
+
+        // Empty when start < end; steps down after the exit test
+        assert_eq!(func.blocks[0].instructions[3].operands, [Value::Temp(0), Value::Temp(1)]);
+        assert_eq!(opcodes(&func.blocks[2]), [Opcode::Sub, Opcode::Jump]);
+    }
+
+    #[test]
+    fn test_build_case_switches_on_constant_labels() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("i".to_string(), Type::integer());
+        let span = Span::new(0, 1, 1, 1);
+        let range = |low, high| Node::SubrangeType(ast::SubrangeType { low: Box::new(integer(low)), high: Box::new(integer(high)), span });
+        let assign = |value| Box::new(Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("j")), value: Box::new(integer(value)), span }));
+        // case I of N: J := 1; 1, 3..5: J := 2 else J := 3 end
+        builder.build_case_stmt(&ast::CaseStmt {
+            expr: Box::new(ident("i")),
+            cases: vec![
+                ast::CaseBranch { values: vec![ident("n")], statement: assign(1), span },
+                ast::CaseBranch { values: vec![integer(1), range(3, 5)], statement: assign(2), span },
+            ],
+            else_branch: Some(assign(3)),
+            span,
+        });
+        let func = builder.current_function_mut().unwrap();
+
+        let labels: Vec<_> = func.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, ["test_entry", "case_test_4", "case_0", "case_1", "case_else_2", "case_end_3"]);
+        // A label that is not a literal is compared before the switch
+        assert_eq!(opcodes(&func.blocks[0]), [Opcode::Cmp, Opcode::CJump]);
+        assert_eq!(func.blocks[0].instructions[1].operands[1], Value::Label("case_0".to_string()));
+        let switch = &func.blocks[1].instructions[0];
+        assert_eq!(switch.opcode, Opcode::Switch);
+        let label = |name: &str| Value::Label(name.to_string());
+        assert_eq!(
+            switch.operands[1..],
+            [
+                label("case_else_2"),
+                Value::Immediate(1), Value::Immediate(1), label("case_1"),
+                Value::Immediate(3), Value::Immediate(5), label("case_1"),
+            ]
+        );
+        // Branches leave through the end; the else block falls into it
+        assert_eq!(func.blocks[2].instructions.last().unwrap().operands, [label("case_end_3")]);
+        assert_ne!(func.blocks[4].instructions.last().map(|inst| &inst.opcode), Some(&Opcode::Jump));
+    }
+
+    #[test]
+    fn test_build_try_except_finally_and_raise() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.type_decls.insert("EParse".to_string(), Type::word());
+        let span = Span::new(0, 1, 1, 1);
+        let raise = Node::RaiseStmt(ast::RaiseStmt {
+            exception: Some(Box::new(Node::CallExpr(ast::CallExpr { name: "EParse".to_string(), args: vec![integer(3)], span }))),
+            span,
+        });
+        let assign = Box::new(Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("j")), value: Box::new(ident("e")), span }));
+        // try raise EParse(3) except on E: EParse do J := E end
+        builder.build_try_stmt(&ast::TryStmt {
+            try_block: vec![raise.clone()],
+            except_block: None,
+            finally_block: None,
+            exception_handlers: vec![ast::ExceptionHandler {
+                variable: Some("e".to_string()),
+                exception_type: Box::new(Node::NamedType(ast::NamedType { name: "EParse".to_string(), generic_args: vec![], span })),
+                handler: assign,
+                span,
+            }],
+            exception_else: None,
+            span,
+        });
+        // try raise EParse(3) finally end
+        builder.build_try_stmt(&ast::TryStmt {
+            try_block: vec![raise],
+            except_block: None,
+            finally_block: Some(vec![]),
+            exception_handlers: vec![],
+            exception_else: None,
+            span,
+        });
+        let func = builder.current_function_mut().unwrap();
+
+        let labels: Vec<_> = func.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            ["test_entry", "try_handler_0", "try_on_2", "try_next_3", "try_end_1", "try_handler_4", "try_end_5"]
+        );
+        assert_eq!(opcodes(&func.blocks[0]), [Opcode::TryEnter, Opcode::Raise, Opcode::TryLeave, Opcode::Jump]);
+        let label = |name: &str| Value::Label(name.to_string());
+        assert_eq!(func.blocks[0].instructions[1].operands, [Value::Immediate(3), label("__exc_type_eparse")]);
+        assert_eq!(opcodes(&func.blocks[1]), [Opcode::ExcIs, Opcode::CJump]);
+        assert_eq!(func.blocks[2].instructions[0].opcode, Opcode::ExcValue);
+        // Unhandled exceptions go to the enclosing frame
+        assert_eq!(opcodes(&func.blocks[3]), [Opcode::Reraise]);
+        assert_eq!(func.blocks[5].instructions.last().unwrap().opcode, Opcode::Reraise);
+    }
+
+    #[test]
+    fn test_build_static_and_virtual_method_calls() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        let mut shape = types::ClassLayout::new("TShape", None, vec![]);
+        let method = |name: &str, slot, return_type| types::ClassMethod {
+            name: name.to_string(),
+            kind: types::MethodKind::Method,
+            owner: String::new(),
+            slot,
+            param_count: 0,
+            return_type,
+        };
+        shape.declare_method(method("Move", None, None));
+        shape.declare_method(method("Area", Some(0), Some(Type::integer())));
+        builder.set_class_layouts(&[shape]);
+        builder.variable_types.insert("s".to_string(), Type::named("TShape".to_string()));
+        let span = Span::new(0, 1, 1, 1);
+        let call = |method: &str, args| ast::MethodCall { object: Box::new(ident("s")), method: method.to_string(), args, span };
+
+        // S.Move(1, 2); I := S.Area
+        assert_eq!(builder.build_method_call(&call("Move", vec![integer(1), integer(2)])), None);
+        let area = builder.build_method_call(&call("Area", vec![]));
+        assert!(matches!(area, Some(Value::Temp(_))));
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        assert_eq!(opcodes(block), [Opcode::CallMethod, Opcode::CallMethod, Opcode::Mov]);
+        // Static methods are called by symbol, virtual ones through their slot
+        assert_eq!(block.instructions[0].operands[0], Value::Label("TShape__Move".to_string()));
+        assert_eq!(block.instructions[0].operands.len(), 4);
+        assert_eq!(block.instructions[1].operands[0], Value::Immediate(0));
+        assert_eq!(block.instructions[2].operands[1], Value::Register("hl".to_string()));
+    }
+
+    #[test]
+    fn test_build_construction_and_destruction() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        let method = |name: &str, kind, slot| types::ClassMethod {
+            name: name.to_string(),
+            kind,
+            owner: String::new(),
+            slot,
+            param_count: 0,
+            return_type: None,
+        };
+        let mut shape = types::ClassLayout::new("TShape", None, vec![]);
+        shape.declare_method(method("Create", types::MethodKind::Constructor, None));
+        shape.declare_method(method("Destroy", types::MethodKind::Destructor, Some(0)));
+        let mut point = types::ClassLayout::new("TPoint", None, vec![]);
+        point.declare_method(method("Create", types::MethodKind::Constructor, None));
+        builder.set_class_layouts(&[shape, point]);
+        builder.variable_types.insert("s".to_string(), Type::Class { name: "TShape".to_string() });
+        let span = Span::new(0, 1, 1, 1);
+        let call = |object: &str, method: &str, args| ast::MethodCall {
+            object: Box::new(ident(object)),
+            method: method.to_string(),
+            args,
+            span,
+        };
+
+        // TShape.Create(1); TPoint.Create; S.Destroy
+        let shape = builder.build_method_call(&call("TShape", "Create", vec![integer(1)]));
+        assert!(matches!(shape, Some(Value::Temp(_))));
+        assert!(builder.build_constructor_call(&ident("TPoint"), "Create", &[], span).is_some());
+        assert_eq!(builder.build_method_call(&call("s", "Destroy", vec![])), None);
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        assert_eq!(
+            opcodes(block),
+            [Opcode::New, Opcode::CallMethod, Opcode::New, Opcode::CallMethod, Opcode::CallMethod, Opcode::Dispose]
+        );
+        // Size of the VMT pointer alone; a class without virtual methods has no VMT
+        assert_eq!(block.instructions[0].operands[1..], [Value::Immediate(2), Value::Label("__vmt_TShape".to_string())]);
+        assert_eq!(block.instructions[2].operands[2], Value::Immediate(0));
+        // The constructor runs on the new instance
+        assert_eq!(block.instructions[1].operands[1], shape.unwrap());
+        assert_eq!(block.instructions[5].operands, block.instructions[4].operands[1..]);
+    }
+
+    #[test]
+    fn test_build_interface_calls_and_queries() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        let method = |name: &str, return_type| types::ClassMethod {
+            name: name.to_string(),
+            kind: types::MethodKind::Method,
+            owner: String::new(),
+            slot: None,
+            param_count: 0,
+            return_type,
+        };
+        let mut shape = types::InterfaceLayout::new("IShape", &[]);
+        shape.declare_method(method("Draw", None));
+        shape.declare_method(method("Area", Some(Type::integer())));
+        builder.set_interface_layouts(&[shape]);
+        builder.variable_types.insert("i".to_string(), Type::named("IShape".to_string()));
+        builder.variable_types.insert("s".to_string(), Type::Class { name: "TSquare".to_string() });
+        let span = Span::new(0, 1, 1, 1);
+        let call = |method: &str, args| ast::MethodCall { object: Box::new(ident("i")), method: method.to_string(), args, span };
+        let query = |op| ast::BinaryExpr { op, left: Box::new(ident("s")), right: Box::new(ident("IShape")), span };
+
+        // I.Draw; N := I.Area(2); S is IShape; S as IShape
+        assert_eq!(builder.build_method_call(&call("Draw", vec![])), None);
+        assert!(matches!(builder.build_method_call(&call("Area", vec![integer(2)])), Some(Value::Temp(_))));
+        assert!(builder.build_interface_query(&query(ast::BinaryOp::Is)).is_some());
+        assert!(builder.build_interface_query(&query(ast::BinaryOp::As)).is_some());
+        assert!(builder.build_interface_query(&query(ast::BinaryOp::Add)).is_none());
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        assert_eq!(opcodes(block), [Opcode::CallIntf, Opcode::CallIntf, Opcode::Mov, Opcode::IntfIs, Opcode::IntfAs]);
+        let iid = Value::Label("__iid_IShape".to_string());
+        assert_eq!(block.instructions[1].operands[..2], [iid.clone(), Value::Immediate(1)]);
+        assert_eq!(block.instructions[1].operands[3], Value::Immediate(2));
+        assert_eq!(block.instructions[4].operands[2], iid);
+    }
+
+    fn ident(name: &str) -> Node {
+        Node::IdentExpr(ast::IdentExpr { name: name.to_string(), span: Span::new(0, 1, 1, 1) })
+    }
+
+    fn integer(value: u32) -> Node {
+        Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(value), span: Span::new(0, 1, 1, 1) })
+    }
+
+    /// Builder with `p, q: ^TPair` where TPair is four bytes
+    fn pointer_builder() -> IRBuilder {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        let field = |name: &str| types::Field { name: name.to_string(), field_type: Box::new(Type::word()), offset: None };
+        let mut pair = Type::record(vec![field("x"), field("y")]);
+        pair.calculate_record_offsets();
+        builder.type_decls.insert("TPair".to_string(), pair);
+        for name in ["p", "q"] {
+            builder.variable_types.insert(name.to_string(), Type::pointer(Type::named("TPair".to_string())));
+        }
+        builder.variable_types.insert("n".to_string(), Type::integer());
+        builder
+    }
+
+    #[test]
+    fn test_build_inc_scales_by_element_size() {
+        let mut builder = pointer_builder();
+        let call = |name: &str, args| ast::CallStmt { name: name.to_string(), args, span: Span::new(0, 1, 1, 1) };
+        builder.build_call_stmt(&call("Inc", vec![ident("p")]));
+        builder.build_call_stmt(&call("Dec", vec![ident("p"), integer(3)]));
+        builder.build_call_stmt(&call("Inc", vec![ident("p"), ident("n")]));
+        builder.build_call_stmt(&call("Inc", vec![ident("n"), integer(3)]));
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        assert_eq!(opcodes(block), [Opcode::Add, Opcode::Sub, Opcode::Mul, Opcode::Add, Opcode::Add]);
+        assert_eq!(block.instructions[0].operands[2], Value::Immediate(4));
+        assert_eq!(block.instructions[1].operands[2], Value::Immediate(12));
+        assert_eq!(block.instructions[2].operands[2], Value::Immediate(4));
+        assert_eq!(block.instructions[3].operands[2], block.instructions[2].operands[0]);
+        // Ordinals step by the count itself
+        assert_eq!(block.instructions[4].operands[2], Value::Immediate(3));
+    }
+
+    #[test]
+    fn test_build_real_arithmetic_converts_integers() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("r".to_string(), Type::real());
+        builder.variable_types.insert("n".to_string(), Type::integer());
+        let span = Span::new(0, 1, 1, 1);
+        let binary = |op, left, right| Node::BinaryExpr(ast::BinaryExpr { op, left: Box::new(left), right: Box::new(right), span });
+        let half = Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Real(0.5), span });
+        // r * n + 0.5, then r := 2 and n := Trunc(r)
+        builder.build_expression(&binary(
+            ast::BinaryOp::Add,
+            binary(ast::BinaryOp::Multiply, ident("r"), ident("n")),
+            half,
+        ));
+        builder.build_assign_stmt(&ast::AssignStmt { target: Box::new(ident("r")), value: Box::new(integer(2)), span });
+        let trunc = ast::CallExpr { name: "Trunc".to_string(), args: vec![ident("r")], span };
+        builder.build_expression(&Node::CallExpr(trunc));
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        assert_eq!(opcodes(block), [Opcode::IToF, Opcode::FMul, Opcode::FAdd, Opcode::Store, Opcode::FTrunc]);
+        assert_eq!(block.instructions[1].operands[2], block.instructions[0].operands[0]);
+        assert_eq!(block.instructions[2].operands[2], Value::Immediate(0.5f32.to_bits() as i32));
+        assert_eq!(block.instructions[3].operands[1], Value::Immediate(2.0f32.to_bits() as i32));
+    }
+
+    #[test]
+    fn test_build_long_arithmetic_widens_narrow_operands() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("l".to_string(), Type::longint());
+        builder.variable_types.insert("c".to_string(), Type::cardinal());
+        builder.variable_types.insert("i".to_string(), Type::integer());
+        builder.variable_types.insert("w".to_string(), Type::word());
+        let span = Span::new(0, 1, 1, 1);
+        let binary = |op, left, right| Node::BinaryExpr(ast::BinaryExpr { op, left: Box::new(left), right: Box::new(right), span });
+        // i * l + 100000, c div w, l < c, then l := i
+        builder.build_expression(&binary(
+            ast::BinaryOp::Add,
+            binary(ast::BinaryOp::Multiply, ident("i"), ident("l")),
+            integer(100_000),
+        ));
+        builder.build_expression(&binary(ast::BinaryOp::Div, ident("c"), ident("w")));
+        builder.build_expression(&binary(ast::BinaryOp::Less, ident("l"), ident("c")));
+        builder.build_assign_stmt(&ast::AssignStmt { target: Box::new(ident("l")), value: Box::new(ident("i")), span });
+        let blocks = &builder.current_function_mut().unwrap().blocks;
+        let block = &blocks[0];
+
+        assert_eq!(
+            opcodes(block),
+            [Opcode::SExt, Opcode::LMul, Opcode::LAdd, Opcode::ZExt, Opcode::LDivU, Opcode::LCmp, Opcode::CJump]
+        );
+        assert_eq!(block.instructions[2].operands[2], Value::Immediate(100_000));
+        assert_eq!(block.instructions[5].operands[2], Value::Compare(ComparisonKind::SignedUnsigned));
+        // The comparison gives a Boolean, 1 or 0
+        assert_eq!(block.instructions[6].operands[0], Value::Condition(Condition::Less));
+        assert_eq!(opcodes(blocks.last().unwrap()), [Opcode::SExt, Opcode::Store]);
+    }
+
+    #[test]
+    fn test_build_ordinal_functions() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("l".to_string(), Type::longint());
+        let span = Span::new(0, 1, 1, 1);
+        let call = |name: &str, arg| Node::CallExpr(ast::CallExpr { name: name.to_string(), args: vec![arg], span });
+        // Ord(c), Succ(c), Pred(l)
+        builder.build_expression(&call("Ord", ident("c")));
+        builder.build_expression(&call("Succ", ident("c")));
+        builder.build_expression(&call("Pred", ident("l")));
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        assert_eq!(opcodes(block), [Opcode::Add, Opcode::LSub]);
+        assert_eq!(block.instructions[0].operands[2], Value::Immediate(1));
+    }
+
+    #[test]
+    fn test_build_pointer_difference_counts_elements() {
+        let mut builder = pointer_builder();
+        let span = Span::new(0, 1, 1, 1);
+        let binary = |op, right| Node::BinaryExpr(ast::BinaryExpr { op, left: Box::new(ident("p")), right: Box::new(right), span });
+        builder.build_expression(&binary(ast::BinaryOp::Subtract, ident("q")));
+        builder.build_expression(&binary(ast::BinaryOp::Add, integer(2)));
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        let ops = opcodes(block);
+        let div = ops.iter().position(|op| *op == Opcode::Div).unwrap();
+        assert_eq!(ops[div - 1], Opcode::Sub);
+        assert_eq!(block.instructions[div].operands[2], Value::Immediate(4));
+        let add = block.instructions.iter().find(|inst| inst.opcode == Opcode::Add).unwrap();
+        assert_eq!(add.operands[2], Value::Immediate(8));
+    }
+
+    #[test]
+    fn test_build_for_integer_uses_signed_compare() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("i".to_string(), Type::integer());
+        builder.build_for_stmt(&for_stmt(ast::ForDirection::To, 0, 10));
+        let func = builder.current_function_mut().unwrap();
+
+        let empty_test = &func.blocks[0].instructions[3];
+        assert_eq!(empty_test.opcode, Opcode::Cmp);
+        assert_eq!(empty_test.operands[2], Value::Compare(ComparisonKind::Signed));
+    }
+
+    #[test]
+    fn test_build_branch_types_large_literals_as_word() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("n".to_string(), Type::integer());
+        let span = Span::new(0, 1, 1, 1);
+        // n < 65000 compares a signed Integer with an unsigned Word, n < 100 two Integers
+        for (literal, kind) in [(65_000, ComparisonKind::SignedUnsigned), (100, ComparisonKind::Signed)] {
+            let condition = Node::BinaryExpr(ast::BinaryExpr {
+                op: ast::BinaryOp::Less,
+                left: Box::new(ident("n")),
+                right: Box::new(integer(literal)),
+                span,
+            });
+            builder.build_branch(&condition, "yes", "no");
+            let block = builder.current_function_mut().unwrap().blocks.last().unwrap();
+            let compare = &block.instructions[block.instructions.len() - 2];
+            assert_eq!(compare.operands[2], Value::Compare(kind));
+        }
+    }
+
+    #[test]
+    fn test_build_sets_as_bitsets() {
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        let digits = Type::set(Type::subrange(Type::integer(), 8, 31));
+        builder.variable_types.insert("s".to_string(), digits.clone());
+        builder.variable_types.insert("a".to_string(), digits);
+        builder.variable_types.insert("x".to_string(), Type::integer());
+        let span = Span::new(0, 1, 1, 1);
+        let literal = Node::SetLiteral(ast::SetLiteral {
+            elements: vec![
+                ast::SetElement::Value(Box::new(ident("x"))),
+                ast::SetElement::Range { start: Box::new(integer(10)), end: Box::new(integer(40)) },
+            ],
+            span,
+        });
+        let binary = |op, left, right| Node::BinaryExpr(ast::BinaryExpr { op, left: Box::new(left), right: Box::new(right), span });
+        builder.build_assign_stmt(&ast::AssignStmt {
+            target: Box::new(ident("s")),
+            value: Box::new(binary(ast::BinaryOp::Add, literal, ident("a"))),
+            span,
+        });
+        let member = builder.build_expression(&binary(ast::BinaryOp::In, ident("x"), ident("s")));
+        let outside = builder.build_expression(&binary(ast::BinaryOp::In, integer(5), ident("s")));
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        // Elements are numbered from 8, and 40 is clipped to the set's last bit
+        assert_eq!(
+            opcodes(block),
+            [Opcode::SetClear, Opcode::Sub, Opcode::SetIncl, Opcode::SetIncl, Opcode::SetUnion, Opcode::Sub, Opcode::SetIn]
+        );
+        assert_eq!(block.instructions[0].operands[1], Value::Immediate(3));
+        assert_eq!(block.instructions[1].operands[2], Value::Immediate(8));
+        assert_eq!(block.instructions[3].operands[1..], [Value::Immediate(2), Value::Immediate(23), Value::Immediate(3)]);
+        assert_eq!(block.instructions[6].operands[0], member);
+        assert_eq!(outside, Value::Immediate(0));
+    }
+
+    #[test]
+    fn test_build_strings_through_runtime_operations() {
+        let mut builder = IRBuilder::new();
+        builder.set_string_literals(&["x".to_string(), "Hi ".to_string()]);
+        builder.start_function("test".to_string(), None);
+        builder.variable_types.insert("s".to_string(), Type::string(20));
+        builder.variable_types.insert("t".to_string(), Type::string(types::MAX_STRING_LENGTH));
+        builder.variable_types.insert("c".to_string(), Type::char());
+        let span = Span::new(0, 1, 1, 1);
+        let text = |text: &str| Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::String(text.to_string()), span });
+        let binary = |op, left, right| Node::BinaryExpr(ast::BinaryExpr { op, left: Box::new(left), right: Box::new(right), span });
+        let copy = Node::CallExpr(ast::CallExpr { name: "Copy".to_string(), args: vec![ident("t"), integer(2), integer(3)], span });
+        builder.build_assign_stmt(&ast::AssignStmt {
+            target: Box::new(ident("s")),
+            value: Box::new(binary(ast::BinaryOp::Add, binary(ast::BinaryOp::Add, text("Hi "), copy), ident("c"))),
+            span,
+        });
+        builder.build_expression(&binary(ast::BinaryOp::Less, ident("s"), text("x")));
+        builder.build_call_stmt(&ast::CallStmt { name: "Insert".to_string(), args: vec![text("ab"), ident("s"), integer(3)], span });
+        let block = &builder.current_function_mut().unwrap().blocks[0];
+
+        // Literals are numbered as the analyzer found them; results are cut to 20
+        assert_eq!(
+            opcodes(block),
+            [Opcode::StrCopy, Opcode::StrSlice, Opcode::StrConcat, Opcode::StrChar, Opcode::StrConcat, Opcode::StrCmp, Opcode::StrInsert]
+        );
+        assert_eq!(block.instructions[0].operands[1..], [Value::Label("__str_1".to_string()), Value::Immediate(20)]);
+        assert_eq!(block.instructions[1].operands[4], Value::Immediate(types::MAX_STRING_LENGTH as i32));
+        assert_eq!(block.instructions[5].operands[2..], [Value::Label("__str_0".to_string()), Value::Condition(Condition::Less)]);
+        assert_eq!(block.instructions[6].operands[1], Value::Label("__str_2".to_string()));
+        assert_eq!(block.instructions[6].operands[3], Value::Immediate(20));
+    }
+
+    #[test]
+    fn test_variant_record_layout() {
+        let builder = IRBuilder::new();
+        let span = Span::new(0, 1, 1, 1);
+        let named = |name: &str| Box::new(Node::NamedType(ast::NamedType { name: name.to_string(), generic_args: vec![], span }));
+        let field = |names: &[&str], type_name: &str| ast::FieldDecl {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            type_expr: named(type_name),
+            span,
+        };
+        // record Kind: byte; case byte of 0: (W: word); 1: (Lo, Hi: byte) else (L: longint) end
+        let record = Node::RecordType(ast::RecordType {
+            is_packed: false,
+            fields: vec![field(&["Kind"], "byte")],
+            variant: Some(ast::VariantPart {
+                tag_field: None,
+                tag_type: named("byte"),
+                variants: vec![
+                    ast::Variant { values: vec![integer(0)], fields: vec![field(&["W"], "word")], variant: None, span },
+                    ast::Variant { values: vec![integer(1)], fields: vec![field(&["Lo", "Hi"], "byte")], variant: None, span },
+                ],
+                else_variant: Some(vec![field(&["L"], "longint")]),
+                span,
+            }),
+            span,
+        });
+        let Type::Record { fields, size } = builder.analyze_type_expr(&record) else {
+            panic!("Expected a record");
+        };
+        let offsets: Vec<_> = fields.iter().map(|f| (f.name.as_str(), f.offset.unwrap())).collect();
+        assert_eq!(offsets, [("Kind", 0), ("W", 2), ("Lo", 1), ("Hi", 2), ("L", 2)]);
+        assert_eq!(size, Some(6));
+    }
+
+    #[test]
+    fn test_repeat_with_short_circuit_condition_runs_on_the_interpreter() {
+        // var I: Integer; I := 3; repeat WriteLn(I); I := I - 1 until (I = 0) or (I > 5)
+        let mut builder = IRBuilder::new();
+        let span = Span::new(0, 1, 1, 1);
+        let binary = |op, left, right| Node::BinaryExpr(ast::BinaryExpr { op, left: Box::new(left), right: Box::new(right), span });
+        let assign = |value| Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("I")), value: Box::new(value), span });
+        builder.start_function("main".to_string(), None);
+        for statement in [
+            Node::VarDecl(ast::VarDecl {
+                names: vec!["I".to_string()],
+                type_expr: Box::new(Node::NamedType(ast::NamedType { name: "Integer".to_string(), generic_args: vec![], span })),
+                absolute_address: None,
+                is_class_var: false,
+                span,
+            }),
+            assign(integer(3)),
+            Node::RepeatStmt(ast::RepeatStmt {
+                statements: vec![
+                    Node::CallStmt(ast::CallStmt { name: "WriteLn".to_string(), args: vec![ident("I")], span }),
+                    assign(binary(ast::BinaryOp::Subtract, ident("I"), integer(1))),
+                ],
+                condition: Box::new(binary(
+                    ast::BinaryOp::Or,
+                    binary(ast::BinaryOp::Equal, ident("I"), integer(0)),
+                    binary(ast::BinaryOp::Greater, ident("I"), integer(5)),
+                )),
+                span,
+            }),
+        ] {
+            builder.build_node(&statement);
+        }
+        builder.finish_function();
+
+        let program = builder.into_program();
+        let main = &program.functions[0];
+        let labels: Vec<_> = main.blocks.iter().map(|block| block.label.as_str()).collect();
+        assert!(labels.iter().any(|label| label.starts_with("or")), "{:?}", labels);
+        let mut state = interp::State::default();
+        interp::interpret(main, &mut state, 1000).unwrap();
+        assert_eq!(state.console.output, "3\n2\n1\n");
+    }
+
+    #[test]
+    fn test_port_pseudo_arrays_lower_to_in_and_out() {
+        // B := Port[$10]; PortW[$20] := B; with B a Byte
+        let mut builder = IRBuilder::new();
+        let span = Span::new(0, 1, 1, 1);
+        let port = |name: &str, address| {
+            Box::new(Node::IndexExpr(ast::IndexExpr { array: Box::new(ident(name)), index: Box::new(integer(address)), span }))
+        };
+        builder.start_function("main".to_string(), None);
+        builder.variable_types.insert("B".to_string(), Type::byte());
+        builder.build_node(&Node::AssignStmt(ast::AssignStmt { target: Box::new(ident("B")), value: port("Port", 0x10), span }));
+        builder.build_node(&Node::AssignStmt(ast::AssignStmt { target: port("PortW", 0x20), value: Box::new(ident("B")), span }));
+        assert_eq!(builder.analyze_expression_type(&port("portw", 0)), Some(Type::word()));
+        builder.finish_function();
+
+        let program = builder.into_program();
+        let main = &program.functions[0];
+        let block = &main.blocks[0];
+        assert_eq!(opcodes(block), [Opcode::In, Opcode::Store, Opcode::Out]);
+        assert_eq!(block.instructions[0].operands[1..], [Value::Immediate(0x10), Value::Immediate(1)]);
+        assert_eq!(block.instructions[2].operands[0], Value::Immediate(0x20));
+        assert_eq!(block.instructions[2].operands[2], Value::Immediate(2));
+
+        let mut state = interp::State::default();
+        state.ports.insert(0x10, 0xAB);
+        interp::interpret(main, &mut state, 100).unwrap();
+        assert_eq!((state.ports[&0x20], state.ports[&0x21]), (0xAB, 0));
+    }
 }
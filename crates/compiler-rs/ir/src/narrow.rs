@@ -0,0 +1,249 @@
+//! Byte-wide narrowing of 16-bit arithmetic
+//!
+//! A value-range analysis bounds every temporary by the unsigned interval
+//! its definitions can produce: constants, and operations whose result is
+//! limited by their operands, such as `x MODU 10` in `0..9` or `x SHR 8` in
+//! `0..255`. Other values, frame slots and registers included, may hold any
+//! 16-bit value.
+//!
+//! The analysis ignores control flow, joining every definition of a
+//! temporary, and iterates to a fixpoint. A temporary whose interval keeps
+//! growing, such as one counted around a loop, is widened to `0..65535`.
+//!
+//! Operations whose operands and result are proven to lie in `0..255` then
+//! run on a byte: ADD, SUB, SHL and SHR become their 8-bit forms, and a
+//! promoted CMP drops its kind, comparing low bytes alone, since every
+//! promotion agrees on non-negative bytes.
+//!
+//! ```text
+//!     MODU t0, x, 10          MODU t0, x, 10
+//!     ADD  t1, t0, 48         BADD t1, t0, 48       ; ld a, ... / add a, e
+//!     CMP  t1, 57, Signed     CMP  t1, 57           ; cp 57
+//! ```
+
+use std::collections::HashMap;
+
+use crate::{Function, Instruction, Opcode, Program, Value};
+
+/// Unsigned interval `(low, high)` of a 16-bit value
+type Range = (u32, u32);
+
+const FULL: Range = (0, 0xFFFF);
+const BYTE: u32 = 0xFF;
+
+/// Times an interval may grow before it is widened to FULL
+const WIDENING_LIMIT: u32 = 3;
+
+/// Narrow the 16-bit operations of `program` proven to fit in a byte to
+/// their 8-bit forms. Returns the number of instructions narrowed.
+pub fn narrow_bytes(program: &mut Program) -> usize {
+    let mut narrowed = 0;
+    for function in &mut program.functions {
+        let ranges = temp_ranges(function);
+        for inst in function.blocks.iter_mut().flat_map(|block| block.instructions.iter_mut()) {
+            if narrow(inst, &ranges) {
+                narrowed += 1;
+            }
+        }
+    }
+    narrowed
+}
+
+/// Intervals of the temporaries of `function`
+fn temp_ranges(function: &Function) -> HashMap<usize, Range> {
+    let mut ranges: HashMap<usize, Range> = HashMap::new();
+    let mut growth: HashMap<usize, u32> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for inst in function.blocks.iter().flat_map(|block| &block.instructions) {
+            let Some(Value::Temp(temp)) = inst.operands.first() else {
+                continue;
+            };
+            if reads_only(&inst.opcode) {
+                continue;
+            }
+            // An operand not yet defined leaves the result undefined for now
+            let Some(result) = transfer(inst, |temp| ranges.get(&temp).copied()) else {
+                continue;
+            };
+            let joined = match ranges.get(temp) {
+                Some(&(low, high)) => (low.min(result.0), high.max(result.1)),
+                None => result,
+            };
+            if ranges.get(temp) != Some(&joined) {
+                let count = growth.entry(*temp).or_insert(0);
+                *count += 1;
+                ranges.insert(*temp, if *count > WIDENING_LIMIT { FULL } else { joined });
+                changed = true;
+            }
+        }
+    }
+    ranges
+}
+
+/// Whether `opcode` only reads its first operand
+fn reads_only(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Cmp | Opcode::LCmp | Opcode::FCmp | Opcode::Push | Opcode::Ret | Opcode::Switch | Opcode::Raise
+            | Opcode::StoreAt | Opcode::StrCopyAt
+    )
+}
+
+/// Interval of the result of `inst`, given the intervals `range_of` its
+/// temporaries have so far; None while one of them is undefined
+fn transfer(inst: &Instruction, range_of: impl Fn(usize) -> Option<Range>) -> Option<Range> {
+    let operand = |value: &Value| match value {
+        Value::Immediate(imm) => Some((*imm as u16 as u32, *imm as u16 as u32)),
+        Value::Temp(temp) => range_of(*temp),
+        _ => Some(FULL),
+    };
+    let fits = |range: Range| if range.1 <= FULL.1 { range } else { FULL };
+    let result = match (&inst.opcode, inst.operands.as_slice()) {
+        (Opcode::Mov, [_, a]) => operand(a)?,
+        (opcode, [_, a, b]) => {
+            let ((a_low, a_high), (b_low, b_high)) = (operand(a)?, operand(b)?);
+            match opcode {
+                Opcode::Add | Opcode::BAdd => fits((a_low + b_low, a_high + b_high)),
+                Opcode::Sub | Opcode::BSub if a_low >= b_high => (a_low - b_high, a_high - b_low),
+                Opcode::Mul => fits((a_low * b_low, a_high * b_high)),
+                // Signed forms agree with the unsigned ones on non-negative operands
+                Opcode::DivU | Opcode::Div if b_low > 0 && (opcode == &Opcode::DivU || (a_high < 0x8000 && b_high < 0x8000)) => {
+                    (a_low / b_high, a_high / b_low)
+                }
+                Opcode::ModU | Opcode::Mod if b_high > 0 && (opcode == &Opcode::ModU || (a_high < 0x8000 && b_high < 0x8000)) => {
+                    (0, a_high.min(b_high - 1))
+                }
+                Opcode::Shl | Opcode::BShl if b_low == b_high && b_low < 16 => fits((a_low << b_low, a_high << b_low)),
+                Opcode::Shr | Opcode::BShr if b_low == b_high => (a_low >> b_low.min(16), a_high >> b_low.min(16)),
+                Opcode::Sar if b_low == b_high && a_high < 0x8000 => (a_low >> b_low.min(15), a_high >> b_low.min(15)),
+                _ => FULL,
+            }
+        }
+        _ => FULL,
+    };
+    Some(result)
+}
+
+/// Rewrite `inst` to its 8-bit form if `ranges` prove it fits in a byte
+fn narrow(inst: &mut Instruction, ranges: &HashMap<usize, Range>) -> bool {
+    let in_byte = |value: &Value| {
+        let range = match value {
+            Value::Immediate(imm) => (*imm as u16 as u32, *imm as u16 as u32),
+            Value::Temp(temp) => ranges.get(temp).copied().unwrap_or(FULL),
+            _ => FULL,
+        };
+        range.1 <= BYTE
+    };
+    let narrow_opcode = match inst.opcode {
+        Opcode::Add => Opcode::BAdd,
+        Opcode::Sub => Opcode::BSub,
+        Opcode::Shl => Opcode::BShl,
+        Opcode::Shr => Opcode::BShr,
+        Opcode::Cmp => match inst.operands.as_slice() {
+            [a, b, Value::Compare(_)] if in_byte(a) && in_byte(b) => {
+                inst.operands.truncate(2);
+                return true;
+            }
+            _ => return false,
+        },
+        _ => return false,
+    };
+    let [_, a, b] = inst.operands.as_slice() else {
+        return false;
+    };
+    let fits = in_byte(a)
+        && in_byte(b)
+        && transfer(inst, |temp| Some(ranges.get(&temp).copied().unwrap_or(FULL))).is_some_and(|range| range.1 <= BYTE);
+    if fits {
+        inst.opcode = narrow_opcode;
+    }
+    fits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret, State};
+    use types::ComparisonKind;
+
+    fn inst(opcode: Opcode, operands: Vec<Value>) -> Instruction {
+        Instruction::new(opcode, operands)
+    }
+
+    fn program(code: Vec<Instruction>) -> Program {
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = code;
+        let mut program = Program::new();
+        program.functions.push(function);
+        program
+    }
+
+    /// Digit character of `x mod 10`, halved, doubled and compared with '5'
+    fn digit_code() -> Vec<Instruction> {
+        let x = Value::Register("x".to_string());
+        let out = |name: &str| Value::Register(name.to_string());
+        vec![
+            inst(Opcode::ModU, vec![Value::Temp(0), x.clone(), Value::Immediate(10)]),
+            inst(Opcode::Add, vec![Value::Temp(1), Value::Temp(0), Value::Immediate(48)]),
+            inst(Opcode::Shr, vec![Value::Temp(2), Value::Temp(1), Value::Immediate(1)]),
+            inst(Opcode::Shl, vec![Value::Temp(3), Value::Temp(2), Value::Immediate(2)]),
+            inst(Opcode::Sub, vec![out("digit"), Value::Temp(1), Value::Temp(0)]),
+            inst(Opcode::Mov, vec![out("scaled"), Value::Temp(3)]),
+            inst(Opcode::Cmp, vec![Value::Temp(1), Value::Immediate(53), Value::Compare(ComparisonKind::Signed)]),
+            // Not narrowed: x is unbounded, and 8 * 57 needs 9 bits
+            inst(Opcode::Add, vec![out("sum"), x, Value::Temp(0)]),
+            inst(Opcode::Shl, vec![out("wide"), Value::Temp(1), Value::Immediate(3)]),
+        ]
+    }
+
+    #[test]
+    fn test_narrowing_bounded_arithmetic_to_bytes() {
+        let mut program = program(digit_code());
+        assert_eq!(narrow_bytes(&mut program), 5);
+        let opcodes: Vec<_> = program.functions[0].blocks[0].instructions.iter().map(|i| (i.opcode.clone(), i.operands.len())).collect();
+        assert_eq!(
+            opcodes,
+            vec![
+                (Opcode::ModU, 3),
+                (Opcode::BAdd, 3),
+                (Opcode::BShr, 3),
+                (Opcode::BShl, 3),
+                (Opcode::BSub, 3),
+                (Opcode::Mov, 2),
+                (Opcode::Cmp, 2),
+                (Opcode::Add, 3),
+                (Opcode::Shl, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_narrowed_code_matches_the_interpreter() {
+        let original = program(digit_code());
+        let mut narrowed = original.clone();
+        narrow_bytes(&mut narrowed);
+        for x in (0..=0xFFFFu32).step_by(97).chain([0xFFFF, 0x8000, 9, 10, 255, 256]) {
+            let run = |program: &Program| {
+                let mut state = State::default();
+                state.registers.insert("x".to_string(), x as u16);
+                interpret(&program.functions[0], &mut state, 100).unwrap();
+                state
+            };
+            assert_eq!(run(&original), run(&narrowed), "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn test_growing_ranges_are_widened() {
+        // t0 := 0; t0 := t0 + 1 around a loop never fits in a byte
+        let mut program = program(vec![
+            inst(Opcode::Mov, vec![Value::Temp(0), Value::Immediate(0)]),
+            inst(Opcode::Add, vec![Value::Temp(0), Value::Temp(0), Value::Immediate(1)]),
+            inst(Opcode::Cmp, vec![Value::Temp(0), Value::Immediate(10), Value::Compare(ComparisonKind::Unsigned)]),
+        ]);
+        assert_eq!(narrow_bytes(&mut program), 0);
+        assert_eq!(temp_ranges(&program.functions[0])[&0], FULL);
+    }
+}
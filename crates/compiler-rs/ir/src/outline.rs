@@ -0,0 +1,411 @@
+//! Outlining of repeated instruction sequences (`-Oz`)
+//!
+//! A run of instructions found in several places, in one function or in
+//! several, is moved into a routine of its own and each run replaced by a
+//! call to it:
+//!
+//! ```text
+//!     OUT   254, 7, 1                 CALL  __outlined_0
+//!     OUT   253, 3, 1                 ...
+//!     OUT   252, 1, 1                 CALL  __outlined_0
+//!     ...
+//!     OUT   254, 7, 1             __outlined_0:
+//!     OUT   253, 3, 1                 OUT   254, 7, 1
+//!     OUT   252, 1, 1                 OUT   253, 3, 1
+//!                                     OUT   252, 1, 1
+//! ```
+//!
+//! - **What moves**: straight-line code within a block that does not reach
+//!   the caller's frame: no frame slots or registers (globals are fine), no
+//!   branches, compares, stack operations, exception handling or inline
+//!   assembly. Temporaries must be defined within the run before they are
+//!   read, and not be read after it, so the routine computes everything it
+//!   needs itself. Temporaries are numbered from 0 in order of appearance,
+//!   so runs using different ones still match.
+//! - **Cost**: a run of `s` bytes found `n` times is outlined when the `n`
+//!   calls and the routine, with its entry and exit, take fewer bytes than
+//!   the `n` copies. Sizes come from the backend (see [`OutlineCosts`]).
+//! - **Order**: the sequences saving the most are outlined first; a run
+//!   already outlined is not part of another.
+//!
+//! Only run at `-Oz`. Functions built under `{$OPTIMIZATION OFF}` and the
+//! routines outlined are left alone.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{global_symbol, Function, Instruction, Opcode, Program, Value};
+
+/// Prefix of the name of an outlined routine
+pub const OUTLINED_PREFIX: &str = "__outlined_";
+
+/// Longest run of instructions considered
+const MAX_LENGTH: usize = 32;
+
+/// Costs of outlining, in bytes of target code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineCosts {
+    /// A call to an outlined routine
+    pub call: u32,
+    /// The entry and exit of a routine, around its body
+    pub routine: u32,
+}
+
+/// A routine outlined from repeated code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlinedRoutine {
+    pub name: String,
+    /// Instructions of its body
+    pub instructions: usize,
+    /// Runs replaced by a call to it
+    pub calls: usize,
+    /// Bytes saved, by `size` and the costs given to [`outline_sequences`]
+    pub bytes_saved: u32,
+}
+
+/// Where a run starts: function, block and instruction
+type Position = (usize, usize, usize);
+
+/// A run as it would be outlined: opcodes and operands, temporaries
+/// renumbered
+type Sequence = Vec<(Opcode, Vec<Value>)>;
+
+/// Runs of the same sequence
+struct Candidate {
+    sequence: Sequence,
+    starts: Vec<Position>,
+    /// Bytes of one run
+    size: u32,
+}
+
+/// Outline the sequences repeated in the functions of `program` that may be
+/// optimized, with `size` giving the bytes of target code of an
+/// instruction. Returns the routines added to `program`.
+pub fn outline_sequences(program: &mut Program, costs: &OutlineCosts, size: impl Fn(&Instruction) -> u32) -> Vec<OutlinedRoutine> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut found: HashMap<Sequence, usize> = HashMap::new();
+    for (f, function) in program.functions.iter().enumerate() {
+        if !function.optimize || function.name.starts_with(OUTLINED_PREFIX) {
+            continue;
+        }
+        let occurrences = temp_occurrences(function);
+        for (b, block) in function.blocks.iter().enumerate() {
+            let code = &block.instructions;
+            for start in 0..code.len() {
+                let end = (start + MAX_LENGTH).min(code.len());
+                let movable = code[start..end].iter().take_while(|inst| movable(inst)).count();
+                for length in 2..=movable {
+                    let Some(sequence) = sequence(&code[start..start + length], &occurrences) else {
+                        continue;
+                    };
+                    match found.get(&sequence) {
+                        Some(&i) => candidates[i].starts.push((f, b, start)),
+                        None => {
+                            let size = sequence
+                                .iter()
+                                .map(|(opcode, operands)| size(&Instruction::new(opcode.clone(), operands.clone())))
+                                .sum();
+                            found.insert(sequence.clone(), candidates.len());
+                            candidates.push(Candidate { sequence, starts: vec![(f, b, start)], size });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Those saving the most first, the longest of equal ones
+    let saving = |candidate: &Candidate, calls: usize| {
+        let calls = calls as u32;
+        (calls * candidate.size).saturating_sub(calls * costs.call + candidate.size + costs.routine)
+    };
+    for candidate in &mut candidates {
+        candidate.starts = disjoint(&candidate.starts, candidate.sequence.len(), &HashSet::new());
+    }
+    candidates.retain(|candidate| candidate.starts.len() >= 2 && saving(candidate, candidate.starts.len()) > 0);
+    candidates.sort_by_key(|candidate| {
+        (std::cmp::Reverse(saving(candidate, candidate.starts.len())), std::cmp::Reverse(candidate.sequence.len()))
+    });
+
+    let mut taken: HashSet<Position> = HashSet::new();
+    let mut replaced: Vec<(Position, usize, String)> = Vec::new();
+    let mut outlined = Vec::new();
+    for candidate in candidates {
+        let length = candidate.sequence.len();
+        let starts = disjoint(&candidate.starts, length, &taken);
+        let bytes_saved = saving(&candidate, starts.len());
+        if starts.len() < 2 || bytes_saved == 0 {
+            continue;
+        }
+        let name = format!("{}{}", OUTLINED_PREFIX, outlined.len());
+        for &(f, b, start) in &starts {
+            taken.extend((start..start + length).map(|i| (f, b, i)));
+            replaced.push(((f, b, start), length, name.clone()));
+        }
+        let mut routine = Function::new(name.clone(), None);
+        routine.blocks[0].instructions = candidate
+            .sequence
+            .into_iter()
+            .map(|(opcode, operands)| Instruction::new(opcode, operands))
+            .collect();
+        program.functions.push(routine);
+        outlined.push(OutlinedRoutine { name, instructions: length, calls: starts.len(), bytes_saved });
+    }
+
+    // Replace runs from the last, so the positions of the others hold
+    replaced.sort_by_key(|(position, _, _)| std::cmp::Reverse(*position));
+    for ((f, b, start), length, name) in replaced {
+        let code = &mut program.functions[f].blocks[b].instructions;
+        let span = code[start].span;
+        let mut call = Instruction::new(Opcode::Call, vec![Value::Label(name)]);
+        call.span = span;
+        code.splice(start..start + length, [call]);
+    }
+    outlined
+}
+
+/// The runs of `length` instructions from `starts`, in order, that overlap
+/// neither one before them nor the instructions `taken`
+fn disjoint(starts: &[Position], length: usize, taken: &HashSet<Position>) -> Vec<Position> {
+    let mut disjoint: Vec<Position> = Vec::new();
+    for &(f, b, start) in starts {
+        let overlaps = (start..start + length).any(|i| taken.contains(&(f, b, i)))
+            || disjoint.last().is_some_and(|&(lf, lb, last)| (lf, lb) == (f, b) && start < last + length);
+        if !overlaps {
+            disjoint.push((f, b, start));
+        }
+    }
+    disjoint
+}
+
+/// Whether `inst` may be moved into a routine of its own
+fn movable(inst: &Instruction) -> bool {
+    let opcode = !matches!(
+        inst.opcode,
+        Opcode::Jump
+            | Opcode::CJump
+            | Opcode::Switch
+            | Opcode::Ret
+            | Opcode::Cmp
+            | Opcode::FCmp
+            | Opcode::LCmp
+            | Opcode::Push
+            | Opcode::Pop
+            | Opcode::TryEnter
+            | Opcode::TryLeave
+            | Opcode::Raise
+            | Opcode::Reraise
+            | Opcode::ExcIs
+            | Opcode::ExcValue
+            | Opcode::Asm
+    );
+    opcode
+        && inst.operands.iter().all(|operand| match operand {
+            Value::Memory { base, .. } => global_symbol(base).is_some(),
+            Value::Register(_) | Value::Asm(_) => false,
+            _ => true,
+        })
+}
+
+/// Whether `opcode` only writes its first operand, computing it from the
+/// others. The operands of anything else are all taken as read, since the
+/// first may be an address (a string or set destination) or be read too
+fn defines_first(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Mov
+            | Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::DivU
+            | Opcode::Mod
+            | Opcode::ModU
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Sar
+            | Opcode::BAdd
+            | Opcode::BSub
+            | Opcode::BShl
+            | Opcode::BShr
+            | Opcode::FAdd
+            | Opcode::FSub
+            | Opcode::FMul
+            | Opcode::FDiv
+            | Opcode::IToF
+            | Opcode::FTrunc
+            | Opcode::FRound
+            | Opcode::LAdd
+            | Opcode::LSub
+            | Opcode::LMul
+            | Opcode::LDiv
+            | Opcode::LDivU
+            | Opcode::LMod
+            | Opcode::LModU
+            | Opcode::SExt
+            | Opcode::ZExt
+            | Opcode::Load
+            | Opcode::LoadAt
+            | Opcode::In
+            | Opcode::New
+            | Opcode::IntfIs
+            | Opcode::IntfAs
+            | Opcode::SetIn
+            | Opcode::SetEq
+            | Opcode::SetSubset
+            | Opcode::StrCmp
+            | Opcode::StrLength
+            | Opcode::StrPos
+    )
+}
+
+/// Number of operands naming each temporary of `function`
+fn temp_occurrences(function: &Function) -> HashMap<usize, usize> {
+    let mut occurrences: HashMap<usize, usize> = HashMap::new();
+    for operand in function.blocks.iter().flat_map(|block| &block.instructions).flat_map(|inst| &inst.operands) {
+        if let Value::Temp(temp) = operand {
+            *occurrences.entry(*temp).or_default() += 1;
+        }
+    }
+    occurrences
+}
+
+/// The sequence a run of `code` would be outlined as, unless it reads a
+/// temporary it does not define or one it defines is named outside it
+fn sequence(code: &[Instruction], occurrences: &HashMap<usize, usize>) -> Option<Sequence> {
+    let mut renumbered: HashMap<usize, usize> = HashMap::new();
+    let mut named: HashMap<usize, usize> = HashMap::new();
+    let mut sequence = Vec::with_capacity(code.len());
+    for inst in code {
+        let mut operands = inst.operands.clone();
+        for (i, operand) in operands.iter_mut().enumerate() {
+            let Value::Temp(temp) = operand else {
+                continue;
+            };
+            *named.entry(*temp).or_default() += 1;
+            let defined = match inst.opcode {
+                Opcode::ReadLn => i % 2 == 0,
+                ref opcode => i == 0 && defines_first(opcode),
+            };
+            let number = match renumbered.get(temp) {
+                Some(&number) => number,
+                None if defined => {
+                    let number = renumbered.len();
+                    renumbered.insert(*temp, number);
+                    number
+                }
+                None => return None,
+            };
+            *operand = Value::Temp(number);
+        }
+        sequence.push((inst.opcode.clone(), operands));
+    }
+    named.iter().all(|(temp, count)| occurrences.get(temp) == Some(count)).then_some(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret_with, Console, State};
+
+    fn label(name: &str) -> Value {
+        Value::Label(name.to_string())
+    }
+
+    /// Three bytes an instruction, as a `LD HL,nn` and `CALL` take
+    fn size(_: &Instruction) -> u32 {
+        3
+    }
+
+    const COSTS: OutlineCosts = OutlineCosts { call: 3, routine: 6 };
+
+    fn score_line() -> Vec<Instruction> {
+        vec![
+            Instruction::new(Opcode::Write, vec![label("__str_0"), label("string")]),
+            Instruction::new(Opcode::Write, vec![Value::Immediate(10), label("integer")]),
+            Instruction::new(Opcode::WriteLn, vec![]),
+        ]
+    }
+
+    fn program(code: Vec<Instruction>) -> Program {
+        let mut function = Function::new("main".to_string(), None);
+        function.blocks[0].instructions = code;
+        let mut program = Program::new();
+        program.add_function(function);
+        program
+    }
+
+    fn run(program: &Program) -> String {
+        let mut state = State { console: Console::new(&[], &["Score: ".to_string()]), ..Default::default() };
+        interpret_with(&program.functions[0], &program.functions, &mut state, 1000).unwrap();
+        state.console.output
+    }
+
+    #[test]
+    fn test_repeated_writes_are_outlined() {
+        let code = [score_line(), score_line(), score_line(), score_line()].concat();
+        let mut program = program(code);
+        let before = run(&program);
+
+        let outlined = outline_sequences(&mut program, &COSTS, size);
+        // 4 runs of 9 bytes become 4 calls and a routine of 9 + 6 bytes
+        assert_eq!(outlined, [OutlinedRoutine {
+            name: "__outlined_0".to_string(),
+            instructions: 3,
+            calls: 4,
+            bytes_saved: 36 - (12 + 9 + 6),
+        }]);
+        let main = &program.functions[0].blocks[0].instructions;
+        assert_eq!(main.len(), 4);
+        assert!(main.iter().all(|inst| inst.operands == [label("__outlined_0")]));
+        assert_eq!(program.functions[1].blocks[0].instructions, score_line());
+        assert_eq!(run(&program), before);
+        assert_eq!(before, "Score: 10\n".repeat(4));
+    }
+
+    #[test]
+    fn test_temporaries_are_matched_by_position() {
+        // t0 and t1 play the same part in both runs
+        let square = |temp: usize| {
+            vec![
+                Instruction::new(Opcode::In, vec![Value::Temp(temp), Value::Immediate(0x20), Value::Immediate(1)]),
+                Instruction::new(Opcode::Mul, vec![Value::Temp(temp), Value::Temp(temp), Value::Temp(temp)]),
+                Instruction::new(Opcode::Write, vec![Value::Temp(temp), label("integer")]),
+                Instruction::new(Opcode::WriteLn, vec![]),
+            ]
+        };
+        let mut program = program([square(0), square(1), square(2)].concat());
+        let outlined = outline_sequences(&mut program, &COSTS, size);
+        assert_eq!(outlined.len(), 1);
+        assert_eq!(outlined[0].calls, 3);
+        assert_eq!(program.functions[1].blocks[0].instructions, square(0));
+    }
+
+    #[test]
+    fn test_nothing_outlined_unless_it_pays() {
+        // Two runs of 9 bytes: 6 bytes of calls and a routine of 15
+        let mut unchanged = program([score_line(), score_line()].concat());
+        assert!(outline_sequences(&mut unchanged, &COSTS, size).is_empty());
+        assert_eq!(unchanged.functions.len(), 1);
+
+        // Frame slots belong to the caller
+        let store = || {
+            let slot = Value::Memory { base: "ix".to_string(), offset: -2 };
+            [score_line(), vec![Instruction::new(Opcode::Mov, vec![slot, Value::Immediate(1)])]].concat()
+        };
+        let mut slots = program([store(), store(), store(), store()].concat());
+        let outlined = outline_sequences(&mut slots, &COSTS, size);
+        assert_eq!(outlined[0].instructions, 3);
+
+        // t0 is read after its run
+        let mut code = Vec::new();
+        for _ in 0..4 {
+            code.push(Instruction::new(Opcode::Mov, vec![Value::Temp(0), Value::Immediate(7)]));
+            code.push(Instruction::new(Opcode::Write, vec![Value::Temp(0), label("integer")]));
+            code.push(Instruction::new(Opcode::WriteLn, vec![]));
+        }
+        code.push(Instruction::new(Opcode::Out, vec![Value::Immediate(0x20), Value::Temp(0), Value::Immediate(1)]));
+        let mut live_out = program(code);
+        let outlined = outline_sequences(&mut live_out, &COSTS, size);
+        assert!(outlined.is_empty(), "{:?}", outlined);
+    }
+}
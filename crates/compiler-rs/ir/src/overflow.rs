@@ -0,0 +1,218 @@
+//! Overflow checks
+//!
+//! Under {$Q+}, a 16-bit sum, difference or product whose true value does
+//! not fit its type calls [`OVERFLOW_ERROR_ROUTINE`]. Each test compares
+//! the wrapped result with an operand, so no wider arithmetic is needed:
+//!
+//! - **Signed sums and differences** move away from the left operand in
+//!   the direction of the sign of the right one; if they moved the other
+//!   way they wrapped. A constant right operand needs one test.
+//! - **Unsigned sums** wrapped if smaller than the left operand, and
+//!   **unsigned differences** if the right operand is the larger.
+//! - **Products** are divided back by the right operand: they overflowed
+//!   unless this gives the left operand. A right operand of 0 never
+//!   overflows, and -1 only for the most negative left operand, where the
+//!   division itself would overflow.
+//!
+//! For `R := A + B` with Integer operands:
+//!
+//! ```text
+//!     ADD   t0, A, B
+//!     CMP   B, 0, Signed
+//!     CJUMP LT, overflow_down_3, overflow_up_2
+//! overflow_up_2:
+//!     CMP   t0, A, Signed
+//!     CJUMP LT, overflow_error_0, overflow_ok_1
+//! overflow_down_3:
+//!     CMP   t0, A, Signed
+//!     CJUMP GT, overflow_error_0, overflow_ok_1
+//! overflow_error_0:
+//!     CALL  __overflow_error          ; does not return
+//! overflow_ok_1:
+//!     STORE R, t0
+//! ```
+
+use tokens::Span;
+use types::ComparisonKind;
+
+use crate::{Condition, IRBuilder, Instruction, Opcode, Value, OVERFLOW_ERROR_ROUTINE};
+
+impl IRBuilder {
+    /// Check `result`, computed by `opcode` (Add, Sub or Mul) from `left`
+    /// and `right`, for overflow; other opcodes need no check
+    pub(crate) fn build_overflow_check(&mut self, opcode: &Opcode, result: &Value, left: &Value, right: &Value, unsigned: bool, span: Span) {
+        // Products are commutative: divide by a constant where there is one
+        let (left, right) = match (opcode, left) {
+            (Opcode::Mul, Value::Immediate(_)) => (right, left),
+            _ => (left, right),
+        };
+        let constant = match right {
+            Value::Immediate(value) => Some(*value as i16),
+            _ => None,
+        };
+        let never = match opcode {
+            Opcode::Add | Opcode::Sub => constant == Some(0),
+            Opcode::Mul => matches!(constant, Some(0 | 1)),
+            _ => true,
+        };
+        if never {
+            return;
+        }
+
+        let error_label = self.new_label("overflow_error");
+        let ok_label = self.new_label("overflow_ok");
+        let kind = if unsigned { ComparisonKind::Unsigned } else { ComparisonKind::Signed };
+        let min = Value::Immediate(i16::MIN as i32);
+        match (opcode, unsigned) {
+            (Opcode::Add, true) => self.branch_on([result, left], kind, Condition::Less, [&error_label, &ok_label], span),
+            (Opcode::Sub, true) => self.branch_on([left, right], kind, Condition::Less, [&error_label, &ok_label], span),
+            (Opcode::Add | Opcode::Sub, false) => {
+                // How a result that moved the wrong way compares, for right
+                // operands above and below 0
+                let (up, down) = match opcode {
+                    Opcode::Add => (Condition::Less, Condition::Greater),
+                    _ => (Condition::Greater, Condition::Less),
+                };
+                match constant {
+                    Some(value) => {
+                        let condition = if value > 0 { up } else { down };
+                        self.branch_on([result, left], kind, condition, [&error_label, &ok_label], span);
+                    }
+                    None => {
+                        let positive = self.new_label("overflow_up");
+                        let negative = self.new_label("overflow_down");
+                        self.branch_on([right, &Value::Immediate(0)], kind, Condition::Less, [&negative, &positive], span);
+                        self.start_block(positive);
+                        self.branch_on([result, left], kind, up, [&error_label, &ok_label], span);
+                        self.start_block(negative);
+                        self.branch_on([result, left], kind, down, [&error_label, &ok_label], span);
+                    }
+                }
+            }
+            _ => {
+                if constant.is_none() {
+                    let nonzero = self.new_label("overflow_nonzero");
+                    self.branch_on([right, &Value::Immediate(0)], kind, Condition::Equal, [&ok_label, &nonzero], span);
+                    self.start_block(nonzero);
+                }
+                match constant {
+                    Some(-1) if !unsigned => {
+                        self.branch_on([left, &min], kind, Condition::Equal, [&error_label, &ok_label], span);
+                    }
+                    None if !unsigned => {
+                        let negate = self.new_label("overflow_negate");
+                        let divide = self.new_label("overflow_divide");
+                        self.branch_on([right, &Value::Immediate(-1)], kind, Condition::Equal, [&negate, &divide], span);
+                        self.start_block(negate);
+                        self.branch_on([left, &min], kind, Condition::Equal, [&error_label, &ok_label], span);
+                        self.start_block(divide);
+                        self.build_product_check(result, left, right, unsigned, [&error_label, &ok_label], span);
+                    }
+                    _ => self.build_product_check(result, left, right, unsigned, [&error_label, &ok_label], span),
+                }
+            }
+        }
+        self.start_block(error_label);
+        self.emit(Instruction::new(Opcode::Call, vec![Value::Label(OVERFLOW_ERROR_ROUTINE.to_string())]).with_span(span));
+        self.start_block(ok_label);
+    }
+
+    /// Divide the product `result` by `right`, neither 0 nor a signed -1,
+    /// and go to the first of `targets` unless that gives `left`
+    fn build_product_check(&mut self, result: &Value, left: &Value, right: &Value, unsigned: bool, targets: [&str; 2], span: Span) {
+        let quotient = self.new_temp();
+        let divide = if unsigned { Opcode::DivU } else { Opcode::Div };
+        self.emit(Instruction::new(divide, vec![quotient.clone(), result.clone(), right.clone()]).with_span(span));
+        let kind = if unsigned { ComparisonKind::Unsigned } else { ComparisonKind::Signed };
+        self.branch_on([&quotient, left], kind, Condition::NotEqual, targets, span);
+    }
+
+    /// Compare the `operands` and go to the first of `targets` when they
+    /// compare as `condition`, else to the second
+    fn branch_on(&mut self, operands: [&Value; 2], kind: ComparisonKind, condition: Condition, targets: [&str; 2], span: Span) {
+        let [a, b] = operands;
+        let [if_true, if_false] = targets;
+        self.emit(Instruction::new(Opcode::Cmp, vec![a.clone(), b.clone(), Value::Compare(kind)]).with_span(span));
+        self.emit(Instruction::new(
+            Opcode::CJump,
+            vec![Value::Condition(condition), Value::Label(if_true.to_string()), Value::Label(if_false.to_string())],
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::Node;
+    use types::Type;
+
+    use crate::interp::{interpret, State};
+    use crate::{IRBuilder, Program};
+
+    const VALUES: [i32; 12] = [0, 1, 2, -1, -2, 181, 182, -182, 255, 16384, 32767, -32768];
+
+    fn ident(name: &str) -> Box<Node> {
+        Box::new(Node::IdentExpr(ast::IdentExpr { name: name.to_string(), span: tokens::Span::new(0, 1, 1, 1) }))
+    }
+
+    /// `R := A op B` with checks, for A, B and R of type `ty` at -2, -4 and
+    /// -6, or with `literal` in place of B
+    fn program(op: ast::BinaryOp, ty: Type, literal: Option<u32>) -> Program {
+        let span = tokens::Span::new(0, 1, 1, 1);
+        let mut builder = IRBuilder::new();
+        builder.set_overflow_checks(true);
+        builder.start_function("main".to_string(), None);
+        for (name, offset) in [("A", -2), ("B", -4), ("R", -6)] {
+            builder.variable_types.insert(name.to_string(), ty.clone());
+            builder.variable_slots.insert(name.to_lowercase(), offset);
+        }
+        let right = match literal {
+            Some(value) => Box::new(Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(value), span })),
+            None => ident("B"),
+        };
+        let value = Box::new(Node::BinaryExpr(ast::BinaryExpr { op, left: ident("A"), right, span }));
+        builder.build_node(&Node::AssignStmt(ast::AssignStmt { target: ident("R"), value, span }));
+        builder.finish_function();
+        builder.into_program()
+    }
+
+    /// Whether `R := a op b` fails, given the operands' bits
+    fn overflows(program: &Program, a: u16, b: u16) -> bool {
+        let mut state = State::default();
+        let slot = |offset| crate::Value::Memory { base: "ix".to_string(), offset };
+        state.write(&slot(-2), a as u32).unwrap();
+        state.write(&slot(-4), b as u32).unwrap();
+        match interpret(&program.functions[0], &mut state, 100) {
+            Ok(()) => false,
+            Err(error) if error == "arithmetic overflow" => true,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    fn test_checks_fail_exactly_when_the_result_does_not_fit() {
+        type Exact = fn(i64, i64) -> i64;
+        let ops: [(ast::BinaryOp, Exact); 3] = [
+            (ast::BinaryOp::Add, |a, b| a + b),
+            (ast::BinaryOp::Subtract, |a, b| a - b),
+            (ast::BinaryOp::Multiply, |a, b| a * b),
+        ];
+        for (op, exact) in ops {
+            let signed = program(op, Type::integer(), None);
+            let unsigned = program(op, Type::word(), None);
+            for (a, b) in VALUES.iter().flat_map(|a| VALUES.iter().map(move |b| (*a as u16, *b as u16))) {
+                let value = exact(a as i16 as i64, b as i16 as i64);
+                assert_eq!(overflows(&signed, a, b), i16::try_from(value).is_err(), "{} {:?} {}", a as i16, op, b as i16);
+                let value = exact(a as i64, b as i64);
+                assert_eq!(overflows(&unsigned, a, b), u16::try_from(value).is_err(), "{} {:?} {}", a, op, b);
+            }
+            // A constant right operand takes one branch of the tests
+            for b in [0u32, 1, 2, 182] {
+                let constant = program(op, Type::integer(), Some(b));
+                for a in VALUES.map(|a| a as u16) {
+                    let value = exact(a as i16 as i64, b as i64);
+                    assert_eq!(overflows(&constant, a, 0), i16::try_from(value).is_err(), "{} {:?} {}", a as i16, op, b);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Program parameter lowering
+//!
+//! `ParamCount` and `ParamStr` call the routines of those names the
+//! backend generates for the target, unless the program declares routines
+//! of its own by those names. `ParamCount` gives the count like a function;
+//! `ParamStr` leaves the parameter in [`PARAM_BUFFER`], which the next call
+//! reuses, so a string operand is copied out of it first. For
+//! `N := ParamCount; S := ParamStr(N)`:
+//!
+//! ```text
+//!     CALL    ParamCount, t0
+//!     STORE   N, t0
+//!     CALL    ParamStr, [@N]
+//!     STRCOPY [@S], [@__params_buffer], 255
+//! ```
+//!
+//! A program calling either runs [`PARAM_SAVE_ROUTINE`] before anything
+//! else (see [`crate::Program::startup`]), while the registers still hold
+//! where the target left the parameter text.
+
+use tokens::Span;
+use types::MAX_STRING_LENGTH;
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+/// Function giving the number of program parameters
+pub const PARAM_COUNT_ROUTINE: &str = "ParamCount";
+/// Function copying the program parameter its argument numbers into
+/// [`PARAM_BUFFER`]
+pub const PARAM_STR_ROUTINE: &str = "ParamStr";
+/// String `ParamStr` gives the parameter in
+pub const PARAM_BUFFER: &str = "__params_buffer";
+/// Runtime routine recording where the target left the parameter text
+pub const PARAM_SAVE_ROUTINE: &str = "__params_save";
+
+impl IRBuilder {
+    /// Whether `name` stands for the `ParamCount` intrinsic
+    pub(crate) fn is_param_count(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(PARAM_COUNT_ROUTINE) && !self.routines.contains_key(&name.to_lowercase())
+    }
+
+    /// Whether `call` calls the `ParamStr` intrinsic
+    pub(crate) fn is_param_str(&self, call: &ast::CallExpr) -> bool {
+        call.name.eq_ignore_ascii_case(PARAM_STR_ROUTINE)
+            && call.args.len() == 1
+            && !self.routines.contains_key(&call.name.to_lowercase())
+    }
+
+    /// Build `ParamCount()` or `ParamStr(I)`; None for other calls
+    pub(crate) fn build_param_call(&mut self, call: &ast::CallExpr) -> Option<Value> {
+        if call.args.is_empty() && self.is_param_count(&call.name) {
+            Some(self.build_param_count(call.span))
+        } else if self.is_param_str(call) {
+            Some(self.build_param_str(call))
+        } else {
+            None
+        }
+    }
+
+    /// Build `ParamCount`, giving the number of parameters
+    pub(crate) fn build_param_count(&mut self, span: Span) -> Value {
+        self.use_param_routine(PARAM_COUNT_ROUTINE);
+        if !self.program.result_functions.iter().any(|name| name == PARAM_COUNT_ROUTINE) {
+            self.program.result_functions.push(PARAM_COUNT_ROUTINE.to_string());
+        }
+        let result = self.new_temp();
+        self.emit(
+            Instruction::new(Opcode::Call, vec![Value::Label(PARAM_COUNT_ROUTINE.to_string()), result.clone()])
+                .with_span(span),
+        );
+        result
+    }
+
+    /// Build `ParamStr(I)`, giving the buffer holding the parameter
+    pub(crate) fn build_param_str(&mut self, call: &ast::CallExpr) -> Value {
+        self.use_param_routine(PARAM_STR_ROUTINE);
+        let index = self.build_expression(&call.args[0]);
+        self.emit(
+            Instruction::new(Opcode::Call, vec![Value::Label(PARAM_STR_ROUTINE.to_string()), index]).with_span(call.span),
+        );
+        Value::Memory { base: crate::global_base(PARAM_BUFFER), offset: 0 }
+    }
+
+    /// `ParamStr(I)` copied into a string temporary, so a later call
+    /// leaves it as it is
+    pub(crate) fn param_str_operand(&mut self, call: &ast::CallExpr) -> Value {
+        let buffer = self.build_param_str(call);
+        let temp = self.new_frame_temp(MAX_STRING_LENGTH + 1);
+        let max = Value::Immediate(MAX_STRING_LENGTH as i32);
+        self.emit(Instruction::new(Opcode::StrCopy, vec![temp.clone(), buffer, max]));
+        temp
+    }
+
+    /// Call the parameter routine `name` under its own symbol, with the
+    /// parameter text saved at startup
+    fn use_param_routine(&mut self, name: &str) {
+        if !self.program.externals.iter().any(|(external, _)| external == name) {
+            self.program.externals.push((name.to_string(), name.to_string()));
+        }
+        if !self.program.startup.iter().any(|routine| routine == PARAM_SAVE_ROUTINE) {
+            self.program.startup.push(PARAM_SAVE_ROUTINE.to_string());
+        }
+    }
+}
+
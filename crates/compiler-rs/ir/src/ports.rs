@@ -0,0 +1,68 @@
+//! Port I/O lowering
+//!
+//! `Port[a]` and `PortW[a]` are pseudo-arrays of the Z80 I/O ports: reading
+//! one is an IN and assigning to one an OUT, of one byte for `Port` and two
+//! for `PortW` (ports a and a + 1, low byte first). For `B: Byte; W: Word`:
+//!
+//! ```text
+//!     B := Port[$10];  PortW[$20] := W
+//!
+//!     IN      t0, 16, 1
+//!     STORE   B, t0
+//!     OUT     32, W, 2
+//! ```
+
+use ast::Node;
+use types::Type;
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+const PORT: &str = "port";
+const PORTW: &str = "portw";
+
+impl IRBuilder {
+    /// Bytes each access to `array[...]` transfers when `array` is `Port` or
+    /// `PortW`; None for anything else, including variables of those names
+    pub(crate) fn port_width(&self, array: &Node) -> Option<i32> {
+        let Node::IdentExpr(ident) = array else {
+            return None;
+        };
+        if self.variable_types.contains_key(&ident.name) {
+            return None;
+        }
+        match ident.name.to_ascii_lowercase().as_str() {
+            PORT => Some(1),
+            PORTW => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Type of `Port[...]` (Byte) or `PortW[...]` (Word)
+    pub(crate) fn port_type(&self, array: &Node) -> Option<Type> {
+        self.port_width(array).map(|bytes| if bytes == 1 { Type::byte() } else { Type::word() })
+    }
+
+    /// Build a read of `Port[a]` or `PortW[a]`; None for other indexing
+    pub(crate) fn build_port_read(&mut self, index: &ast::IndexExpr) -> Option<Value> {
+        let bytes = self.port_width(&index.array)?;
+        let port = self.build_expression(&index.index);
+        let result = self.new_temp();
+        self.emit(Instruction::new(Opcode::In, vec![result.clone(), port, Value::Immediate(bytes)]).with_span(index.span));
+        Some(result)
+    }
+
+    /// Build `Port[a] := value` or `PortW[a] := value`; false if the target
+    /// is not a port
+    pub(crate) fn build_port_write(&mut self, assign: &ast::AssignStmt) -> bool {
+        let Node::IndexExpr(index) = assign.target.as_ref() else {
+            return false;
+        };
+        let Some(bytes) = self.port_width(&index.array) else {
+            return false;
+        };
+        let port = self.build_expression(&index.index);
+        let value = self.build_expression(&assign.value);
+        self.emit(Instruction::new(Opcode::Out, vec![port, value, Value::Immediate(bytes)]).with_span(assign.span));
+        true
+    }
+}
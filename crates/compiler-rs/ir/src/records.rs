@@ -0,0 +1,95 @@
+//! Record field lowering
+//!
+//! A field of a record variable is a memory operand of its own, at the
+//! field's offset from the variable, so it is read and assigned like a
+//! variable. For `var Song: TMusicData` (Data, Size: Word; Format: Byte):
+//!
+//! ```text
+//!     N := Song.Size              STORE N, [@Song+2]
+//!     Song.Format := 1            STORE [@Song+4], 1
+//! ```
+//!
+//! A byte field of a global is listed in the program's byte globals, so it
+//! is reached a byte at a time; those of local records, whose frame slots
+//! are reached a word at a time, are reported as not supported yet.
+
+use ast::Node;
+use types::Type;
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+impl IRBuilder {
+    /// Type of `member` when it is a field of a record variable, or of a
+    /// field of one
+    pub(crate) fn record_field_type(&self, member: &ast::FieldExpr) -> Option<Type> {
+        let (_, field_type) = self.record_field_offset(member)?;
+        Some(field_type)
+    }
+
+    /// Offset of `member` from the record variable it is a field of, and
+    /// its type
+    fn record_field_offset(&self, member: &ast::FieldExpr) -> Option<(i32, Type)> {
+        let (offset, record) = match member.record.as_ref() {
+            Node::IdentExpr(ident) => (0, self.variable_types.get(&ident.name)?.clone()),
+            Node::FieldExpr(inner) => self.record_field_offset(inner)?,
+            _ => return None,
+        };
+        let Type::Record { fields, .. } = self.resolve_type(&record)? else {
+            return None;
+        };
+        let field = fields.iter().find(|field| field.name.eq_ignore_ascii_case(&member.field))?;
+        Some((offset + field.offset? as i32, *field.field_type.clone()))
+    }
+
+    /// Name of the record variable `member` is a field of
+    fn record_variable(member: &ast::FieldExpr) -> Option<&str> {
+        match member.record.as_ref() {
+            Node::IdentExpr(ident) => Some(&ident.name),
+            Node::FieldExpr(inner) => Self::record_variable(inner),
+            _ => None,
+        }
+    }
+
+    /// Memory operand of `member` when it is a field of a record variable;
+    /// None for anything else
+    pub(crate) fn build_record_field(&mut self, member: &ast::FieldExpr) -> Option<Value> {
+        let (offset, field_type) = self.record_field_offset(member)?;
+        let variable = Self::record_variable(member)?.to_string();
+        let Value::Memory { base, offset: start } = self.get_variable_address(&variable) else {
+            return None;
+        };
+        let offset = start + offset;
+        if self.resolve_type(&field_type).and_then(Type::size) == Some(1) {
+            match crate::global_symbol(&base) {
+                Some(symbol) => {
+                    let key = (symbol.to_string(), offset);
+                    if !self.program.byte_globals.contains(&key) {
+                        self.program.byte_globals.push(key);
+                    }
+                }
+                None => self.unsupported(&format!("the byte field '{}' of a local record", member.field), Some(member.span)),
+            }
+        }
+        Some(Value::Memory { base, offset })
+    }
+
+    /// Build an assignment to a field of a record variable; false if
+    /// `assign` assigns something else
+    pub(crate) fn build_record_field_assign(&mut self, assign: &ast::AssignStmt) -> bool {
+        let Node::FieldExpr(member) = assign.target.as_ref() else {
+            return false;
+        };
+        let Some((_, field_type)) = self.record_field_offset(member) else {
+            return false;
+        };
+        if !matches!(self.resolve_type(&field_type).and_then(Type::size), Some(1 | 2)) {
+            self.unsupported(&format!("assigning the field '{}' of over two bytes", member.field), Some(assign.span));
+            return true;
+        }
+        let value = self.build_expression(&assign.value);
+        if let Some(target) = self.build_record_field(member) {
+            self.emit(Instruction::new(Opcode::Store, vec![target, value]).with_span(assign.span));
+        }
+        true
+    }
+}
@@ -0,0 +1,765 @@
+//! Routine lowering
+//!
+//! Each procedure and function becomes a function of its own, labelled
+//! with its name, or `Class__Method` for a method, whose first parameter is
+//! `Self`. A CALL names the routine, then its arguments, which are pushed
+//! left to right: of `n` parameters, the `i`th lies at `ix + 4 + 2 * (n - 1
+//! - i)`, above the saved frame pointer and the return address. A function
+//! keeps its result in a local that both `Result` and its name stand for,
+//! RET gives it back, and the CALL names the temporary receiving it last.
+//! For `function Sq(X: Integer): Integer; begin Sq := X * X end`:
+//!
+//! ```text
+//!     N := Sq(3)
+//!
+//!     CALL  Sq, 3, t0             ; main
+//!     STORE N, t0
+//!
+//! Sq_entry:                       ; X at ix+4, the result at ix-2
+//!     MUL   t1, [ix+4], [ix+4]
+//!     STORE [ix-2], t1
+//!     RET   [ix-2]
+//! ```
+//!
+//! `Exit` returns at once, `Exit(V)` with the result V.
+//!
+//! A `var` parameter, or an untyped `const` one, is passed as the address
+//! of its variable, which the callee reads and assigns through:
+//!
+//! ```text
+//!     Bump(N)                     ; procedure Bump(var X: Integer)
+//!
+//!     ADDR    t0, [@N]            ; main
+//!     CALL    Bump, t0
+//!
+//! Bump_entry:                     ; the address of X at ix+4
+//!     LOADAT  t1, [ix+4], 2
+//!     ADD     t2, t1, 1
+//!     STOREAT [ix+4], t2, 2
+//! ```
+//!
+//! An untyped parameter is only passed on or taken the address of, and a
+//! string constant given for one is passed as the address of its length
+//! byte.
+//!
+//! A function giving a string builds it in a local, copied on return to
+//! the buffer whose address the caller pushes after the arguments, at
+//! `ix+4`. The caller's buffer is a string temporary of its frame, as long
+//! as the function's result:
+//!
+//! ```text
+//!     S := Name()                 ; function Name: string[5]
+//!
+//!     ADDR      t0, [ix-6]        ; main
+//!     CALL      Name, t0
+//!     STRCOPY   [@S], [ix-6], 255
+//!
+//! Name_entry:                     ; the result at ix-6
+//!     ...
+//!     STRCOPYAT [ix+4], [ix-6], 5
+//!     RET
+//! ```
+//!
+//! Parameters passed by value over two bytes, other results over two
+//! bytes, and routines declared inside routines, are reported as not
+//! supported yet.
+
+use ast::Node;
+use tokens::Span;
+use types::Type;
+
+use crate::{constfold::FRAME_BASE, IRBuilder, Instruction, Opcode, Value};
+
+/// Procedure that returns from the routine it is called in
+const EXIT_INTRINSIC: &str = "Exit";
+/// Variable holding the result of the function it is used in
+const RESULT_VARIABLE: &str = "Result";
+/// Function giving the stack a task running a routine needs
+const TASK_STACK_SIZE_INTRINSIC: &str = "TaskStackSize";
+
+/// A parameter of a routine that may be called
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutineParam {
+    pub name: String,
+    pub param_type: Type,
+    /// Passed as the address of a variable (`var`, `out`, untyped `const`)
+    pub by_reference: bool,
+}
+
+/// A routine calls may name
+#[derive(Debug, Clone)]
+pub(crate) struct Routine {
+    /// Label of its function, or its Pascal name when it is external
+    label: String,
+    params: Vec<RoutineParam>,
+    /// Default value of each parameter, if it has one
+    defaults: Vec<Option<Node>>,
+    return_type: Option<Type>,
+}
+
+/// The routine being built
+#[derive(Debug, Clone)]
+pub(crate) struct RoutineScope {
+    /// Slot of a function's result
+    result: Option<Value>,
+    /// Characters a string result holds, when it is one
+    string_result: Option<usize>,
+}
+
+/// Slot of the address of the buffer a string result is copied to
+const RESULT_BUFFER_SLOT: i32 = 4;
+
+/// What a procedure or function declaration says about building it
+struct RoutineDecl<'a> {
+    name: &'a str,
+    class_name: Option<&'a str>,
+    params: &'a [ast::Param],
+    return_type: Option<&'a Node>,
+    block: &'a Node,
+    /// Declared forward, external or generic: it has no body to build here
+    bodiless: bool,
+    generic: bool,
+    is_class_method: bool,
+    external: Option<String>,
+    span: Span,
+}
+
+impl<'a> RoutineDecl<'a> {
+    fn of(node: &'a Node) -> Option<Self> {
+        match node {
+            Node::ProcDecl(proc) => Some(RoutineDecl {
+                name: &proc.name,
+                class_name: proc.class_name.as_deref(),
+                params: &proc.params,
+                return_type: None,
+                block: &proc.block,
+                bodiless: proc.is_forward || proc.is_external || !proc.generic_params.is_empty(),
+                generic: !proc.generic_params.is_empty(),
+                is_class_method: proc.is_class_method,
+                external: proc.is_external.then(|| proc.external_name.clone().unwrap_or_else(|| proc.name.clone())),
+                span: proc.span,
+            }),
+            Node::FuncDecl(func) => Some(RoutineDecl {
+                name: &func.name,
+                class_name: func.class_name.as_deref(),
+                params: &func.params,
+                return_type: Some(&func.return_type),
+                block: &func.block,
+                bodiless: func.is_forward || func.is_external || !func.generic_params.is_empty(),
+                generic: !func.generic_params.is_empty(),
+                is_class_method: func.is_class_method,
+                external: func.is_external.then(|| func.external_name.clone().unwrap_or_else(|| func.name.clone())),
+                span: func.span,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl IRBuilder {
+    /// Make the routines `decls` declare callable; methods are called
+    /// through CALLMETHOD instead
+    pub(crate) fn declare_routines<'a>(&mut self, decls: impl Iterator<Item = &'a Node>) {
+        for decl in decls.filter_map(RoutineDecl::of) {
+            if decl.generic || decl.class_name.is_some() {
+                continue;
+            }
+            let params = self.routine_params(decl.params);
+            let defaults = decl
+                .params
+                .iter()
+                .flat_map(|param| param.names.iter().map(|_| param.default_value.as_deref().cloned()))
+                .collect();
+            let return_type = decl.return_type.map(|ty| self.analyze_type_expr(ty));
+            if let Some(symbol) = decl.external
+                && !self.program.externals.iter().any(|(name, _)| name.eq_ignore_ascii_case(decl.name))
+            {
+                self.program.externals.push((decl.name.to_string(), symbol));
+            }
+            let routine = Routine { label: decl.name.to_string(), params, defaults, return_type };
+            self.routines.insert(decl.name.to_lowercase(), routine);
+        }
+    }
+
+    /// Declare the routine `name` of a unit the program uses
+    pub fn declare_routine(&mut self, name: &str, params: Vec<RoutineParam>, return_type: Option<Type>) {
+        let defaults = vec![None; params.len()];
+        let routine = Routine { label: name.to_string(), params, defaults, return_type };
+        self.routines.insert(name.to_lowercase(), routine);
+    }
+
+    fn routine_params(&self, params: &[ast::Param]) -> Vec<RoutineParam> {
+        params
+            .iter()
+            .flat_map(|param| {
+                let param_type = param.type_expr.as_deref().map_or(Type::Error, |ty| self.analyze_type_expr(ty));
+                let by_reference =
+                    matches!(param.param_type, ast::ParamType::Var | ast::ParamType::Out) || param.type_expr.is_none();
+                param.names.iter().map(move |name| RoutineParam {
+                    name: name.clone(),
+                    param_type: param_type.clone(),
+                    by_reference,
+                })
+            })
+            .collect()
+    }
+
+    /// Build each routine of `decls` that has a body into a function
+    pub(crate) fn build_routines<'a>(&mut self, decls: impl Iterator<Item = &'a Node>) {
+        for decl in decls.filter_map(RoutineDecl::of) {
+            if decl.bodiless {
+                continue;
+            }
+            let mut params = self.routine_params(decl.params);
+            let label = match decl.class_name {
+                Some(_) if decl.is_class_method => {
+                    self.unsupported("class methods", Some(decl.span));
+                    continue;
+                }
+                Some(class) => {
+                    let receiver = Type::Class { name: class.to_string() };
+                    params.insert(
+                        0,
+                        RoutineParam { name: types::SELF_PARAMETER.to_string(), param_type: receiver, by_reference: false },
+                    );
+                    types::method_symbol(class, decl.name)
+                }
+                None => decl.name.to_string(),
+            };
+            let return_type = decl.return_type.map(|ty| self.analyze_type_expr(ty));
+            self.build_routine(label, decl.name, params, return_type, decl.block, decl.span);
+        }
+    }
+
+    /// Build the body of the routine `name` as the function `label`, in a
+    /// scope of its own
+    fn build_routine(&mut self, label: String, name: &str, params: Vec<RoutineParam>, return_type: Option<Type>, block: &Node, span: Span) {
+        let variable_types = self.variable_types.clone();
+        let variable_slots = std::mem::take(&mut self.variable_slots);
+        let reference_params = std::mem::take(&mut self.reference_params);
+        let frame_size = std::mem::replace(&mut self.frame_size, 0);
+        let constants = self.constants.clone();
+        let string_constants = self.string_constants.clone();
+        let type_decls = self.type_decls.clone();
+
+        // Methods are called without a buffer for a string result
+        let method = params.first().is_some_and(|param| param.name == types::SELF_PARAMETER);
+        let string_result = return_type.as_ref().and_then(|ty| self.string_length(ty)).filter(|_| !method);
+        self.start_function(label, return_type.clone().filter(|_| string_result.is_none()));
+        self.statement_span = Some(span);
+        let count = params.len() as i32 + string_result.is_some() as i32;
+        for (i, param) in params.iter().enumerate() {
+            if param.by_reference {
+                self.reference_params.insert(param.name.to_lowercase());
+            } else if !self.fits_word(&param.param_type) {
+                self.unsupported(&format!("the parameter '{}' of over two bytes", param.name), Some(span));
+            }
+            let offset = 4 + 2 * (count - 1 - i as i32);
+            self.variable_types.insert(param.name.clone(), param.param_type.clone());
+            self.variable_slots.insert(param.name.to_lowercase(), offset);
+            if let Some(function) = self.current_function.as_mut() {
+                function.params.push((param.name.clone(), param.param_type.clone()));
+                function.locals.push((param.name.clone(), offset));
+            }
+        }
+        if string_result.is_some()
+            && let Some(function) = self.current_function.as_mut()
+        {
+            function.params.push((RESULT_VARIABLE.to_string(), Type::UntypedPointer));
+        }
+        let result = return_type.map(|ty| {
+            if string_result.is_none() && !self.fits_word(&ty) {
+                self.unsupported(&format!("the result of '{}', of over two bytes", name), Some(span));
+            }
+            let offset = self.allocate_local(name, &ty);
+            self.variable_slots.insert(RESULT_VARIABLE.to_lowercase(), offset);
+            self.variable_types.insert(name.to_string(), ty.clone());
+            self.variable_types.insert(RESULT_VARIABLE.to_string(), ty);
+            Value::Memory { base: FRAME_BASE.to_string(), offset }
+        });
+        let enclosing = self.routine.replace(RoutineScope { result, string_result });
+
+        if let Node::Block(block) = block {
+            if !block.proc_decls.is_empty() || !block.func_decls.is_empty() {
+                self.unsupported("routines declared inside routines", Some(span));
+            }
+            self.build_block(block);
+        }
+        self.statement_span = Some(span);
+        self.build_return(None);
+        self.finish_function();
+
+        self.routine = enclosing;
+        self.variable_types = variable_types;
+        self.variable_slots = variable_slots;
+        self.reference_params = reference_params;
+        self.frame_size = frame_size;
+        self.constants = constants;
+        self.string_constants = string_constants;
+        self.type_decls = type_decls;
+    }
+
+    /// Characters a string of `ty` holds; None if it is not a string
+    fn string_length(&self, ty: &Type) -> Option<usize> {
+        match self.resolve_type(ty)? {
+            Type::String { max_length } => Some(*max_length),
+            _ => None,
+        }
+    }
+
+    /// Return from the routine being built, giving its result, or
+    /// copying a string result to the caller's buffer
+    fn build_return(&mut self, span: Option<Span>) {
+        let Some(RoutineScope { result, string_result }) = self.routine.clone() else {
+            return;
+        };
+        let mut ret = match (result, string_result) {
+            (Some(result), Some(max)) => {
+                let buffer = Value::Memory { base: FRAME_BASE.to_string(), offset: RESULT_BUFFER_SLOT };
+                let copy = Instruction::new(Opcode::StrCopyAt, vec![buffer, result, Value::Immediate(max as i32)]);
+                self.emit(match span {
+                    Some(span) => copy.with_span(span),
+                    None => copy,
+                });
+                Instruction::new(Opcode::Ret, vec![])
+            }
+            (result, _) => Instruction::new(Opcode::Ret, result.into_iter().collect()),
+        };
+        if let Some(span) = span {
+            ret = ret.with_span(span);
+        }
+        self.emit(ret);
+    }
+
+    /// Whether values of `ty` are passed in a word
+    fn fits_word(&self, ty: &Type) -> bool {
+        self.resolve_type(ty).and_then(Type::size).is_some_and(|size| size <= 2)
+    }
+
+    /// Build a unit: its variables become globals and its routines
+    /// functions
+    pub(crate) fn build_unit(&mut self, unit: &ast::Unit) {
+        let interface = unit.interface.as_ref();
+        let implementation = unit.implementation.as_ref();
+        let decls = |section: fn(&ast::InterfaceSection) -> &Vec<Node>, other: fn(&ast::ImplementationSection) -> &Vec<Node>| {
+            interface.map(section).into_iter().flatten().chain(implementation.map(other).into_iter().flatten())
+        };
+        let block = ast::Block {
+            directives: vec![],
+            label_decls: vec![],
+            const_decls: decls(|i| &i.const_decls, |i| &i.const_decls).cloned().collect(),
+            type_decls: decls(|i| &i.type_decls, |i| &i.type_decls).cloned().collect(),
+            var_decls: decls(|i| &i.var_decls, |i| &i.var_decls).cloned().collect(),
+            threadvar_decls: vec![],
+            proc_decls: vec![],
+            func_decls: vec![],
+            operator_decls: vec![],
+            statements: vec![],
+            span: unit.span,
+        };
+        self.statement_span = Some(unit.span);
+        self.build_block(&block);
+        let routines = || decls(|i| &i.proc_decls, |i| &i.proc_decls).chain(decls(|i| &i.func_decls, |i| &i.func_decls));
+        self.declare_routines(routines());
+        // Interface routines are headings; the implementation has the bodies
+        let bodies = implementation.into_iter().flat_map(|i| i.proc_decls.iter().chain(&i.func_decls));
+        self.build_routines(bodies);
+        for part in unit.initialization.iter().chain(&unit.finalization) {
+            if matches!(part.as_ref(), Node::Block(block) if !block.statements.is_empty()) {
+                self.unsupported("the initialization and finalization of units", Some(part.span()));
+            }
+        }
+    }
+
+    /// Build a call of the routine `name`, returning the temporary
+    /// holding its result if it is a function; None, after reporting it,
+    /// if it cannot be called
+    pub(crate) fn build_call(&mut self, name: &str, args: &[Node], span: Span) -> Option<Value> {
+        if let Some(method) = self.self_method(name) {
+            return self.build_self_call(&method, args, span);
+        }
+        let Some(routine) = self.routines.get(&name.to_lowercase()).cloned() else {
+            self.unsupported(&format!("the call of '{}'", name), Some(span));
+            return None;
+        };
+        let mut operands = vec![Value::Label(routine.label.clone())];
+        for (i, param) in routine.params.iter().enumerate() {
+            if !param.by_reference && !self.fits_word(&param.param_type) {
+                self.unsupported(&format!("the parameter '{}' of '{}'", param.name, name), Some(span));
+                return None;
+            }
+            let Some(arg) = args.get(i).or(routine.defaults[i].as_ref()) else {
+                self.error(format!("Code generation found no value for the parameter '{}' of '{}'", param.name, name), Some(span));
+                return None;
+            };
+            if !param.by_reference {
+                operands.push(self.build_expression(arg));
+                continue;
+            }
+            let Some(address) = self.build_reference(arg) else {
+                self.unsupported(&format!("passing this to the var parameter '{}' of '{}'", param.name, name), Some(arg.span()));
+                return None;
+            };
+            operands.push(address);
+        }
+        if let Some(max) = routine.return_type.as_ref().and_then(|ty| self.string_length(ty)) {
+            // The result is copied to a string temporary, whose address
+            // is passed last
+            let buffer = self.new_frame_temp(max + 1);
+            let address = self.new_temp();
+            self.emit(Instruction::new(Opcode::Addr, vec![address.clone(), buffer.clone()]).with_span(span));
+            operands.push(address);
+            self.emit(Instruction::new(Opcode::Call, operands).with_span(span));
+            return Some(buffer);
+        }
+        let result = routine.return_type.as_ref().map(|_| self.new_temp());
+        if let Some(result) = &result {
+            operands.push(result.clone());
+            if !self.program.result_functions.contains(&routine.label) {
+                self.program.result_functions.push(routine.label.clone());
+            }
+        }
+        self.emit(Instruction::new(Opcode::Call, operands).with_span(span));
+        result
+    }
+
+    /// `TaskStackSize(Entry)`, the stack the analysis found a task running
+    /// Entry needs; None if `call` is not one
+    pub(crate) fn task_stack_size(&self, call: &ast::CallExpr) -> Option<u16> {
+        if !call.name.eq_ignore_ascii_case(TASK_STACK_SIZE_INTRINSIC) {
+            return None;
+        }
+        let [Node::IdentExpr(entry)] = call.args.as_slice() else {
+            return None;
+        };
+        self.task_stack_sizes.get(&entry.name.to_lowercase()).copied()
+    }
+
+    /// `@Name`, the address of a routine or variable; None for anything
+    /// else
+    pub(crate) fn build_address_of(&mut self, address: &ast::AddressOfExpr) -> Option<Value> {
+        let Node::IdentExpr(ident) = address.target.as_ref() else {
+            return None;
+        };
+        if let Some(slot) = self.reference_slot(&ident.name) {
+            let result = self.new_temp();
+            self.emit(Instruction::new(Opcode::Load, vec![result.clone(), slot]).with_span(address.span));
+            return Some(result);
+        }
+        let target = match self.routines.get(&ident.name.to_lowercase()) {
+            Some(routine) => Value::Label(routine.label.clone()),
+            None if self.variable_types.contains_key(&ident.name) => self.get_variable_address(&ident.name),
+            None => return None,
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(Opcode::Addr, vec![result.clone(), target]).with_span(address.span));
+        Some(result)
+    }
+
+    /// Slot of `name` when it is a parameter of the routine being built
+    /// passed by reference, holding the address of its variable
+    pub(crate) fn reference_slot(&self, name: &str) -> Option<Value> {
+        let key = name.to_lowercase();
+        if !self.reference_params.contains(&key) {
+            return None;
+        }
+        let offset = *self.variable_slots.get(&key)?;
+        Some(Value::Memory { base: FRAME_BASE.to_string(), offset })
+    }
+
+    /// Address `arg` passes to a parameter passed by reference: that of
+    /// its variable or array element, or of its characters for a string
+    /// constant; None if it has none
+    fn build_reference(&mut self, arg: &Node) -> Option<Value> {
+        let variable = match arg {
+            Node::IdentExpr(ident) if let Some(slot) = self.reference_slot(&ident.name) => {
+                // Passed on: the slot already holds the address
+                return Some(slot);
+            }
+            Node::IdentExpr(ident) if self.variable_types.contains_key(&ident.name) => self.get_variable_address(&ident.name),
+            Node::FieldExpr(member) => self.build_record_field(member)?,
+            Node::IndexExpr(index) if self.element_type(index).is_some() => return self.build_element_address(index),
+            _ => match self.build_expression(arg) {
+                label @ Value::Label(_) => return Some(label),
+                _ => return None,
+            },
+        };
+        let address = self.new_temp();
+        self.emit(Instruction::new(Opcode::Addr, vec![address.clone(), variable]).with_span(arg.span()));
+        Some(address)
+    }
+
+    /// Size of the variable the parameter `name`, passed by reference,
+    /// reaches; None, after reporting it, if it is not one or two bytes
+    fn reference_size(&mut self, name: &str, span: Span) -> Option<Value> {
+        let size = self.variable_types.get(name).cloned().and_then(|ty| self.resolve_type(&ty).and_then(Type::size));
+        match size {
+            Some(size @ (1 | 2)) => Some(Value::Immediate(size as i32)),
+            _ => {
+                self.unsupported(&format!("reading or assigning the parameter '{}' of other than one or two bytes", name), Some(span));
+                None
+            }
+        }
+    }
+
+    /// Build reading the variable the parameter `name`, passed by
+    /// reference, stands for
+    pub(crate) fn build_reference_load(&mut self, name: &str, span: Span) -> Value {
+        let result = self.new_temp();
+        if let (Some(slot), Some(size)) = (self.reference_slot(name), self.reference_size(name, span)) {
+            self.emit(Instruction::new(Opcode::LoadAt, vec![result.clone(), slot, size]).with_span(span));
+        }
+        result
+    }
+
+    /// Build an assignment to a parameter passed by reference; false if
+    /// `assign` assigns something else
+    pub(crate) fn build_reference_assign(&mut self, assign: &ast::AssignStmt) -> bool {
+        let Node::IdentExpr(ident) = assign.target.as_ref() else {
+            return false;
+        };
+        let Some(slot) = self.reference_slot(&ident.name) else {
+            return false;
+        };
+        let value = self.build_expression(&assign.value);
+        if let Some(size) = self.reference_size(&ident.name, assign.span) {
+            self.emit(Instruction::new(Opcode::StoreAt, vec![slot, value, size]).with_span(assign.span));
+        }
+        true
+    }
+
+    /// Build `Inc` or `Dec` (`opcode` Add or Sub) of a parameter passed by
+    /// reference; false if `target` is something else
+    pub(crate) fn build_reference_inc_dec(&mut self, opcode: Opcode, target: &Node, step: Value, span: Span) -> bool {
+        let Node::IdentExpr(ident) = target else {
+            return false;
+        };
+        let Some(slot) = self.reference_slot(&ident.name) else {
+            return false;
+        };
+        let value = self.build_reference_load(&ident.name, span);
+        let result = self.new_temp();
+        self.emit(Instruction::new(opcode, vec![result.clone(), value, step]).with_span(span));
+        if let Some(size) = self.reference_size(&ident.name, span) {
+            self.emit(Instruction::new(Opcode::StoreAt, vec![slot, result, size]).with_span(span));
+        }
+        true
+    }
+
+    /// Result type of the function `name`
+    pub(crate) fn routine_result(&self, name: &str) -> Option<&Type> {
+        self.routines.get(&name.to_lowercase())?.return_type.as_ref()
+    }
+
+    /// Build `Exit` or `Exit(V)`, returning from the routine being built;
+    /// false if `call` is not a call of Exit
+    pub(crate) fn build_exit(&mut self, call: &ast::CallStmt) -> bool {
+        if !call.name.eq_ignore_ascii_case(EXIT_INTRINSIC) {
+            return false;
+        }
+        let scope = self.routine.clone();
+        match (call.args.first(), scope.as_ref().and_then(|scope| scope.result.clone())) {
+            (Some(value), Some(_)) if let Some(max) = scope.as_ref().and_then(|scope| scope.string_result) => {
+                self.build_string_assign(RESULT_VARIABLE, value, max);
+            }
+            (Some(value), Some(result)) => {
+                let value = self.build_expression(value);
+                self.emit(Instruction::new(Opcode::Store, vec![result, value]).with_span(call.span));
+            }
+            _ => {}
+        }
+        self.build_return(Some(call.span));
+        // Statements after Exit start a block of their own, which nothing
+        // jumps to
+        let after = self.new_label("exit_after");
+        self.start_block(after);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret_with, State};
+    use crate::MAIN_FUNCTION;
+
+    fn span() -> Span {
+        Span::new(0, 1, 1, 1)
+    }
+
+    fn ident(name: &str) -> Box<Node> {
+        Box::new(Node::IdentExpr(ast::IdentExpr { name: name.to_string(), span: span() }))
+    }
+
+    fn integer(value: u32) -> Box<Node> {
+        Box::new(Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(value), span: span() }))
+    }
+
+    fn named(name: &str) -> Box<Node> {
+        Box::new(Node::NamedType(ast::NamedType { name: name.to_string(), generic_args: vec![], span: span() }))
+    }
+
+    fn assign(target: &str, value: Box<Node>) -> Node {
+        Node::AssignStmt(ast::AssignStmt { target: ident(target), value, span: span() })
+    }
+
+    fn call(name: &str, args: Vec<Node>) -> Node {
+        Node::CallStmt(ast::CallStmt { name: name.to_string(), args, span: span() })
+    }
+
+    fn block(var_decls: Vec<Node>, proc_decls: Vec<Node>, func_decls: Vec<Node>, statements: Vec<Node>) -> Box<Node> {
+        Box::new(Node::Block(ast::Block {
+            directives: vec![],
+            label_decls: vec![],
+            const_decls: vec![],
+            type_decls: vec![],
+            var_decls,
+            threadvar_decls: vec![],
+            proc_decls,
+            func_decls,
+            operator_decls: vec![],
+            statements,
+            span: span(),
+        }))
+    }
+
+    fn var(name: &str, type_name: &str) -> Node {
+        Node::VarDecl(ast::VarDecl {
+            names: vec![name.to_string()],
+            type_expr: named(type_name),
+            absolute_address: None,
+            is_class_var: false,
+            span: span(),
+        })
+    }
+
+    fn param(name: &str) -> ast::Param {
+        ast::Param {
+            names: vec![name.to_string()],
+            param_type: ast::ParamType::Value,
+            type_expr: Some(named("Integer")),
+            default_value: None,
+            span: span(),
+        }
+    }
+
+    /// program: var N: Integer;
+    ///   procedure Bump; begin N := N + 1 end;
+    ///   function Sq(X: Integer): Integer; var T: Integer;
+    ///   begin T := X * X; if T > 50 then Exit(0); Sq := T end;
+    /// begin N := 1; Bump; WriteLn(N); N := Sq(N + 1) + Sq(9); WriteLn(N) end
+    fn program() -> Node {
+        let bump = Node::ProcDecl(ast::ProcDecl {
+            name: "Bump".to_string(),
+            class_name: None,
+            generic_params: vec![],
+            params: vec![],
+            block: block(vec![], vec![], vec![], vec![assign("N", binary(ast::BinaryOp::Add, ident("n"), integer(1)))]),
+            is_forward: false,
+            is_external: false,
+            external_name: None,
+            is_class_method: false,
+            binding: ast::MethodBinding::Static,
+            span: span(),
+        });
+        let exit = Node::IfStmt(ast::IfStmt {
+            condition: binary(ast::BinaryOp::Greater, ident("T"), integer(50)),
+            then_block: Box::new(call("Exit", vec![*integer(0)])),
+            else_block: None,
+            span: span(),
+        });
+        let sq = Node::FuncDecl(ast::FuncDecl {
+            name: "Sq".to_string(),
+            class_name: None,
+            generic_params: vec![],
+            params: vec![param("X")],
+            return_type: named("Integer"),
+            block: block(
+                vec![var("T", "Integer")],
+                vec![],
+                vec![],
+                vec![assign("T", binary(ast::BinaryOp::Multiply, ident("X"), ident("x"))), exit, assign("Sq", ident("T"))],
+            ),
+            is_forward: false,
+            is_external: false,
+            external_name: None,
+            is_class_method: false,
+            is_compiletime: false,
+            is_pure: false,
+            binding: ast::MethodBinding::Static,
+            span: span(),
+        });
+        let sq_call = |arg: Box<Node>| Box::new(Node::CallExpr(ast::CallExpr { name: "Sq".to_string(), args: vec![*arg], span: span() }));
+        Node::Program(ast::Program {
+            name: "Test".to_string(),
+            directives: vec![],
+            uses: None,
+            block: block(
+                vec![var("N", "Integer")],
+                vec![bump],
+                vec![sq],
+                vec![
+                    assign("N", integer(1)),
+                    call("Bump", vec![]),
+                    call("WriteLn", vec![*ident("N")]),
+                    assign("N", binary(ast::BinaryOp::Add, sq_call(binary(ast::BinaryOp::Add, ident("N"), integer(1))), sq_call(integer(9)))),
+                    call("WriteLn", vec![*ident("N")]),
+                ],
+            ),
+            span: span(),
+        })
+    }
+
+    fn binary(op: ast::BinaryOp, left: Box<Node>, right: Box<Node>) -> Box<Node> {
+        Box::new(Node::BinaryExpr(ast::BinaryExpr { op, left, right, span: span() }))
+    }
+
+    #[test]
+    fn test_routines_become_functions_called_with_their_arguments() {
+        let mut builder = IRBuilder::new();
+        let program = builder.build(&program());
+        assert!(builder.diagnostics().is_empty(), "{:?}", builder.diagnostics());
+
+        let names: Vec<_> = program.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, [MAIN_FUNCTION, "Bump", "Sq"]);
+        assert_eq!(program.globals, [("N".to_string(), Type::integer())]);
+        assert_eq!(program.result_functions, ["Sq"]);
+        // X is above the frame, the result and T below it
+        let sq = &program.functions[2];
+        assert_eq!(sq.locals, [("X".to_string(), 4), ("Sq".to_string(), -2), ("T".to_string(), -4)]);
+        let result = Value::Memory { base: FRAME_BASE.to_string(), offset: -2 };
+        let last = sq.blocks.last().unwrap().instructions.last().unwrap();
+        assert_eq!((&last.opcode, last.operands.as_slice()), (&Opcode::Ret, [result].as_slice()));
+        let calls: Vec<_> = program.functions[0].blocks[0]
+            .instructions
+            .iter()
+            .filter(|inst| inst.opcode == Opcode::Call)
+            .map(|inst| inst.operands.len())
+            .collect();
+        // Bump, then Sq with an argument and a result, twice
+        assert_eq!(calls, [1, 3, 3]);
+
+        let mut state = State::default();
+        interpret_with(&program.functions[0], &program.functions, &mut state, 1000).unwrap();
+        // 2, then Sq(3) + 0, as Sq(9) exits early
+        assert_eq!(state.console.output, "2\n9\n");
+    }
+
+    #[test]
+    fn test_unknown_names_and_routines_are_reported() {
+        let mut builder = IRBuilder::new();
+        builder.set_filename(Some("test.pas".to_string()));
+        builder.start_function(MAIN_FUNCTION.to_string(), None);
+        builder.build_node(&assign("Missing", ident("Nowhere")));
+        builder.build_node(&call("Launch", vec![]));
+        let messages: Vec<_> = builder.diagnostics().iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Code generation found no variable 'Nowhere'",
+                "Code generation found no variable 'Missing'",
+                "Code generation does not support the call of 'Launch' yet",
+            ]
+        );
+    }
+}
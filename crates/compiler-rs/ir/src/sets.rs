@@ -0,0 +1,302 @@
+//! Set lowering
+//!
+//! A set is a bitset of whole bytes (see [`Type::set_layout`]): bit `n mod 8`
+//! of byte `n div 8` holds the element whose ordinal number is `first + n`,
+//! where `first` is a multiple of 8. Set expressions are built into a
+//! destination of a known layout, so operands with another layout, such as
+//! a `set of 0..9` combined with a `set of 0..20`, are copied into it first.
+//! For sets of `8..31`, which take 3 bytes:
+//!
+//! ```text
+//!     S := (A + [x]) * B
+//!
+//!     SETCOPY  S, A, 3
+//!     SETCLEAR t0, 3
+//!     SUB      t1, x, 8
+//!     SETINCL  t0, t1, t1, 3
+//!     SETUNION S, t0, 3
+//!     SETINTER S, B, 3
+//! ```
+//!
+//! Elements are passed relative to `first`; the runtime routines ignore
+//! elements outside the set's bytes. An assignment whose operations read
+//! the target is built into a temporary and copied.
+
+use ast::Node;
+use types::Type;
+
+use crate::{IRBuilder, Instruction, Opcode, Value};
+
+/// Layout of a set: the ordinal number of its first bit and its size in
+/// bytes. The empty set `[]` has size 0.
+pub(crate) type SetLayout = (i64, usize);
+
+/// Layout of a set literal with elements only known at run time
+const FULL_SET: SetLayout = (0, (types::MAX_SET_ORDINAL as usize + 1) / 8);
+
+/// The smallest layout holding the elements of both `a` and `b`
+fn union_layout(a: SetLayout, b: SetLayout) -> SetLayout {
+    match (a, b) {
+        (_, (_, 0)) => a,
+        ((_, 0), _) => b,
+        ((first1, size1), (first2, size2)) => {
+            let first = first1.min(first2);
+            let end = (first1 + 8 * size1 as i64).max(first2 + 8 * size2 as i64);
+            (first, ((end - first) / 8) as usize)
+        }
+    }
+}
+
+/// `value`, the address of a set, moved on by `bytes`
+fn byte_of(value: &Value, bytes: i64) -> Value {
+    match value {
+        Value::Memory { base, offset } => Value::Memory { base: base.clone(), offset: offset + bytes as i32 },
+        other => other.clone(),
+    }
+}
+
+fn is_set_operator(op: ast::BinaryOp) -> bool {
+    matches!(op, ast::BinaryOp::Add | ast::BinaryOp::Subtract | ast::BinaryOp::Multiply)
+}
+
+impl IRBuilder {
+    /// Layout of `expr` if it is a set
+    pub(crate) fn set_layout(&self, expr: &Node) -> Option<SetLayout> {
+        match expr {
+            Node::IdentExpr(ident) => {
+                let ty = self.variable_types.get(&ident.name)?;
+                self.resolve_type(ty)?.set_layout()
+            }
+            Node::SetLiteral(set) => Some(self.literal_layout(set)),
+            Node::BinaryExpr(bin) if is_set_operator(bin.op) => {
+                let left = self.set_layout(&bin.left)?;
+                Some(union_layout(left, self.set_layout(&bin.right)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Layout of a set literal: the bytes holding its constant elements, or
+    /// all of 0..255 if some are only known at run time
+    fn literal_layout(&self, set: &ast::SetLiteral) -> SetLayout {
+        let mut bounds: Option<(i64, i64)> = None;
+        for element in &set.elements {
+            let (start, end) = Self::element_bounds(element);
+            for bound in [start, end] {
+                let Some((_, value)) = self.bound_value(bound) else {
+                    return FULL_SET;
+                };
+                bounds = Some(bounds.map_or((value, value), |(low, high)| (low.min(value), high.max(value))));
+            }
+        }
+        match bounds {
+            Some((low, high)) => (low / 8 * 8, (high / 8 - low / 8 + 1) as usize),
+            None => (0, 0),
+        }
+    }
+
+    fn element_bounds(element: &ast::SetElement) -> (&Node, &Node) {
+        match element {
+            ast::SetElement::Value(value) => (value, value),
+            ast::SetElement::Range { start, end } => (start, end),
+        }
+    }
+
+    /// Whether `bin` compares two sets
+    pub(crate) fn is_set_comparison(&self, bin: &ast::BinaryExpr) -> bool {
+        matches!(
+            bin.op,
+            ast::BinaryOp::Equal | ast::BinaryOp::NotEqual | ast::BinaryOp::LessEqual | ast::BinaryOp::GreaterEqual
+        ) && self.set_layout(&bin.left).is_some()
+    }
+
+    /// Assign the set `value` to the variable `name`. An operation reading
+    /// the variable after it would be overwritten is built into a temporary
+    /// first.
+    pub(crate) fn build_set_assign(&mut self, name: &str, value: &Node, target_type: &Type) {
+        let Some(layout) = target_type.set_layout() else {
+            return;
+        };
+        let target = self.get_variable_address(name);
+        if matches!(value, Node::BinaryExpr(_)) && Self::mentions(value, name) {
+            let temp = self.new_frame_temp(layout.1);
+            self.build_set_into(value, &temp, layout);
+            self.emit(Instruction::new(Opcode::SetCopy, vec![target, temp, Value::Immediate(layout.1 as i32)]));
+        } else {
+            self.build_set_into(value, &target, layout);
+        }
+    }
+
+    /// Whether the set expression `expr` reads the variable `name`
+    fn mentions(expr: &Node, name: &str) -> bool {
+        match expr {
+            Node::IdentExpr(ident) => ident.name.eq_ignore_ascii_case(name),
+            Node::BinaryExpr(bin) => Self::mentions(&bin.left, name) || Self::mentions(&bin.right, name),
+            _ => false,
+        }
+    }
+
+    /// Build the set `expr` into `dst`, a set with `layout`
+    fn build_set_into(&mut self, expr: &Node, dst: &Value, layout: SetLayout) {
+        let size = Value::Immediate(layout.1 as i32);
+        match expr {
+            Node::SetLiteral(set) => {
+                self.emit(Instruction::new(Opcode::SetClear, vec![dst.clone(), size]));
+                for element in &set.elements {
+                    let (start, end) = Self::element_bounds(element);
+                    self.build_set_include(dst, start, end, layout);
+                }
+            }
+            Node::BinaryExpr(bin) if is_set_operator(bin.op) => {
+                self.build_set_into(&bin.left, dst, layout);
+                let right = self.set_operand(&bin.right, layout);
+                let opcode = match bin.op {
+                    ast::BinaryOp::Add => Opcode::SetUnion,
+                    ast::BinaryOp::Subtract => Opcode::SetDiff,
+                    _ => Opcode::SetInter,
+                };
+                self.emit(Instruction::new(opcode, vec![dst.clone(), right, size]));
+            }
+            _ => {
+                let src_layout = self.set_layout(expr).unwrap_or(layout);
+                let src = self.build_expression(expr);
+                self.copy_set(dst, layout, &src, src_layout);
+            }
+        }
+    }
+
+    /// The set `expr` with `layout`: a variable with that layout as it is,
+    /// anything else built into a temporary
+    fn set_operand(&mut self, expr: &Node, layout: SetLayout) -> Value {
+        if matches!(expr, Node::IdentExpr(_)) && self.set_layout(expr) == Some(layout) {
+            return self.build_expression(expr);
+        }
+        let temp = self.new_frame_temp(layout.1);
+        self.build_set_into(expr, &temp, layout);
+        temp
+    }
+
+    /// Copy the set `src` into `dst`: the bytes of the elements both
+    /// layouts hold, with the rest of `dst` cleared
+    fn copy_set(&mut self, dst: &Value, layout: SetLayout, src: &Value, src_layout: SetLayout) {
+        if src_layout == layout {
+            self.emit(Instruction::new(
+                Opcode::SetCopy,
+                vec![dst.clone(), src.clone(), Value::Immediate(layout.1 as i32)],
+            ));
+            return;
+        }
+        let end = |(first, size): SetLayout| first + 8 * size as i64;
+        let first = layout.0.max(src_layout.0);
+        let last = end(layout).min(end(src_layout));
+        if first > layout.0 || last < end(layout) {
+            self.emit(Instruction::new(Opcode::SetClear, vec![dst.clone(), Value::Immediate(layout.1 as i32)]));
+        }
+        if first < last {
+            self.emit(Instruction::new(
+                Opcode::SetCopy,
+                vec![
+                    byte_of(dst, (first - layout.0) / 8),
+                    byte_of(src, (first - src_layout.0) / 8),
+                    Value::Immediate(((last - first) / 8) as i32),
+                ],
+            ));
+        }
+    }
+
+    /// Add the elements `start..end` of a set literal to `dst`. Constant
+    /// elements outside `layout` are dropped here; others are left to the
+    /// runtime routine.
+    fn build_set_include(&mut self, dst: &Value, start: &Node, end: &Node, layout: SetLayout) {
+        let (first, size) = layout;
+        let low = self.build_expression(start);
+        let high = if std::ptr::eq(start, end) { low.clone() } else { self.build_expression(end) };
+        let (low, high) = match (low, high) {
+            (Value::Immediate(low), Value::Immediate(high)) => {
+                let low = (low as i64).max(first);
+                let high = (high as i64).min(first + 8 * size as i64 - 1);
+                if low > high {
+                    return;
+                }
+                (Value::Immediate((low - first) as i32), Value::Immediate((high - first) as i32))
+            }
+            (low, high) if low == high => {
+                let element = self.relative_element(low, first);
+                (element.clone(), element)
+            }
+            (low, high) => (self.relative_element(low, first), self.relative_element(high, first)),
+        };
+        self.emit(Instruction::new(Opcode::SetIncl, vec![dst.clone(), low, high, Value::Immediate(size as i32)]));
+    }
+
+    /// The ordinal `element` as numbered from a set's first bit
+    fn relative_element(&mut self, element: Value, first: i64) -> Value {
+        match element {
+            _ if first == 0 => element,
+            Value::Immediate(n) => Value::Immediate(n - first as i32),
+            element => {
+                let relative = self.new_temp();
+                self.emit(Instruction::new(Opcode::Sub, vec![relative.clone(), element, Value::Immediate(first as i32)]));
+                relative
+            }
+        }
+    }
+
+    /// Build `x in s`; a constant outside the set's bytes is never in it
+    pub(crate) fn build_set_membership(&mut self, bin: &ast::BinaryExpr) -> Value {
+        let (first, size) = self.set_layout(&bin.right).unwrap_or(FULL_SET);
+        let element = self.build_expression(&bin.left);
+        if size == 0 {
+            return Value::Immediate(0);
+        }
+        if let Value::Immediate(n) = element
+            && !(first..first + 8 * size as i64).contains(&(n as i64))
+        {
+            return Value::Immediate(0);
+        }
+        let set = self.set_operand(&bin.right, (first, size));
+        let element = self.relative_element(element, first);
+        let result = self.new_temp();
+        self.emit(Instruction::new(
+            Opcode::SetIn,
+            vec![result.clone(), element, set, Value::Immediate(size as i32)],
+        ));
+        result
+    }
+
+    /// Build `a = b`, `a <> b`, `a <= b` (a subset of b) or `a >= b` on sets,
+    /// both taken to the layout holding either
+    pub(crate) fn build_set_comparison(&mut self, bin: &ast::BinaryExpr) -> Value {
+        let layout = union_layout(
+            self.set_layout(&bin.left).unwrap_or(FULL_SET),
+            self.set_layout(&bin.right).unwrap_or(FULL_SET),
+        );
+        let layout = if layout.1 == 0 { (0, 1) } else { layout };
+        let left = self.set_operand(&bin.left, layout);
+        let right = self.set_operand(&bin.right, layout);
+        let (opcode, left, right) = match bin.op {
+            ast::BinaryOp::GreaterEqual => (Opcode::SetSubset, right, left),
+            ast::BinaryOp::LessEqual => (Opcode::SetSubset, left, right),
+            _ => (Opcode::SetEq, left, right),
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(
+            opcode,
+            vec![result.clone(), left, right, Value::Immediate(layout.1 as i32)],
+        ));
+        if bin.op != ast::BinaryOp::NotEqual {
+            return result;
+        }
+        let negated = self.new_temp();
+        self.emit(Instruction::new(Opcode::Sub, vec![negated.clone(), Value::Immediate(1), result]));
+        negated
+    }
+
+    /// Build a set-valued expression used as a value, e.g. an argument,
+    /// into a temporary of its own layout
+    pub(crate) fn build_set_value(&mut self, expr: &Node, layout: SetLayout) -> Value {
+        let temp = self.new_frame_temp(layout.1);
+        self.build_set_into(expr, &temp, layout);
+        temp
+    }
+}
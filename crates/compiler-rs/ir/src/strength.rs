@@ -0,0 +1,363 @@
+//! Strength reduction of multiplication and division by constants
+//!
+//! `MUL dst, x, c` becomes shifts and adds when the target's [`ArithCosts`]
+//! make them cheaper than its multiply. The constant is written in binary
+//! and in canonical signed-digit form, where runs of ones cost a single
+//! subtraction, and the cheaper is applied Horner style from the top digit:
+//!
+//! ```text
+//!     x * 10 = ((x SHL 2) + x) SHL 1      SHL t0, x, 2
+//!                                         ADD t1, t0, x
+//!                                         SHL dst, t1, 1
+//!     x * 15 = (x SHL 4) - x              SHL t0, x, 4
+//!                                         SUB dst, t0, x
+//! ```
+//!
+//! Products wrap at 16 bits, so the constant is taken modulo 65536 and the
+//! same digits serve signed and unsigned operands.
+//!
+//! Division by a power of two becomes a shift: logical for `DIVU`. A signed
+//! `DIV` truncates toward zero, so a negative dividend is first biased by
+//! `2^k - 1`, taken from its sign:
+//!
+//! ```text
+//!     x div 8                             SAR t0, x, 15     ; -1 or 0
+//!                                         SHR t1, t0, 13    ; 7 or 0
+//!                                         ADD t2, x, t1
+//!                                         SAR dst, t2, 3
+//! ```
+//!
+//! Other divisors keep the division: a reciprocal multiply needs the high
+//! word of a 32-bit product, which costs more than dividing on 8-bit
+//! targets.
+
+use crate::{Instruction, Opcode, Program, Value};
+
+/// Costs of the operations strength reduction chooses between, in the
+/// target's units (T-states, or bytes when optimizing for size)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArithCosts {
+    /// ADD of a second value
+    pub add: u32,
+    /// SUB of a second value
+    pub subtract: u32,
+    /// SHL by one bit
+    pub shift_left: u32,
+    /// SHR or SAR by one bit
+    pub shift_right: u32,
+    /// SHL or SHR by 8, a byte move, before any remaining bits
+    pub byte_shift: u32,
+    /// SAR by 15, the sign copied into every bit
+    pub sign_mask: u32,
+    /// MUL
+    pub multiply: u32,
+    /// DIV or DIVU
+    pub divide: u32,
+}
+
+impl ArithCosts {
+    /// Cost of shifting by `count` bits with `opcode` (SHL, SHR or SAR)
+    pub fn shift(&self, opcode: &Opcode, count: u32) -> u32 {
+        match opcode {
+            Opcode::Sar if count == 15 => self.sign_mask,
+            Opcode::Sar => count * self.shift_right,
+            _ => {
+                let per_bit = if *opcode == Opcode::Shl { self.shift_left } else { self.shift_right };
+                match count {
+                    8.. => self.byte_shift + (count - 8) * per_bit,
+                    _ => count * per_bit,
+                }
+            }
+        }
+    }
+
+    /// Cost of the instructions of a reduced sequence
+    fn of(&self, code: &[Instruction]) -> u32 {
+        code.iter()
+            .map(|inst| match (&inst.opcode, inst.operands.get(2)) {
+                (Opcode::Add, _) => self.add,
+                (Opcode::Sub, _) => self.subtract,
+                (opcode, Some(Value::Immediate(count))) if matches!(opcode, Opcode::Shl | Opcode::Shr | Opcode::Sar) => {
+                    self.shift(opcode, *count as u32)
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// Replace multiplications and divisions by constants in `program` with
+/// shifts and adds where `costs` make them cheaper. Returns the number of
+/// instructions replaced.
+pub fn reduce_strength(program: &mut Program, costs: &ArithCosts) -> usize {
+    let mut reduced = 0;
+    for function in &mut program.functions {
+        let mut next_temp = crate::unroll::max_temp(function).map_or(0, |max| max + 1);
+        for block in &mut function.blocks {
+            let mut code = Vec::with_capacity(block.instructions.len());
+            for inst in std::mem::take(&mut block.instructions) {
+                match reduce(&inst, costs, next_temp) {
+                    Some((sequence, temps)) => {
+                        next_temp += temps;
+                        reduced += 1;
+                        code.extend(sequence.into_iter().map(|step| match inst.span {
+                            Some(span) => step.with_span(span),
+                            None => step,
+                        }));
+                    }
+                    None => code.push(inst),
+                }
+            }
+            block.instructions = code;
+        }
+    }
+    reduced
+}
+
+/// The cheaper sequence replacing `inst` and the number of temporaries it
+/// takes from `next_temp`, or None to keep `inst`
+fn reduce(inst: &Instruction, costs: &ArithCosts, next_temp: usize) -> Option<(Vec<Instruction>, usize)> {
+    let (dst, x, constant, limit) = match (&inst.opcode, inst.operands.as_slice()) {
+        (Opcode::Mul, [_, Value::Immediate(_), Value::Immediate(_)]) => return None,
+        (Opcode::Mul, [dst, x, Value::Immediate(c)]) | (Opcode::Mul, [dst, Value::Immediate(c), x]) => {
+            (dst, x, *c, costs.multiply)
+        }
+        (Opcode::Div | Opcode::DivU, [dst, x, Value::Immediate(c)]) if !matches!(x, Value::Immediate(_)) => {
+            (dst, x, *c, costs.divide)
+        }
+        _ => return None,
+    };
+    let mut sequence = Sequence { code: vec![], next_temp, first_temp: next_temp };
+    match inst.opcode {
+        Opcode::Mul => {
+            let constant = constant as u16 as u32;
+            let binary = multiply(dst, x, &binary_digits(constant), next_temp);
+            let signed = multiply(dst, x, &signed_digits(constant), next_temp);
+            sequence = if costs.of(&signed.code) < costs.of(&binary.code) { signed } else { binary };
+        }
+        Opcode::DivU => {
+            let divisor = constant as u16;
+            if !divisor.is_power_of_two() {
+                return None;
+            }
+            sequence.shift(Opcode::Shr, x.clone(), divisor.trailing_zeros());
+            sequence.finish(dst, x);
+        }
+        _ => {
+            let divisor = constant as i16;
+            let magnitude = divisor.unsigned_abs();
+            if !magnitude.is_power_of_two() || divisor == i16::MIN {
+                return None;
+            }
+            let k = magnitude.trailing_zeros();
+            let mut quotient = x.clone();
+            if k > 0 {
+                // The bias is the sign bit when k is 1, else the low k bits of the sign mask
+                let bias = if k == 1 {
+                    sequence.emit(Opcode::Shr, x.clone(), Value::Immediate(15))
+                } else {
+                    let sign = sequence.emit(Opcode::Sar, x.clone(), Value::Immediate(15));
+                    sequence.emit(Opcode::Shr, sign, Value::Immediate(16 - k as i32))
+                };
+                let biased = sequence.emit(Opcode::Add, x.clone(), bias);
+                quotient = sequence.emit(Opcode::Sar, biased, Value::Immediate(k as i32));
+            }
+            if divisor < 0 {
+                sequence.emit(Opcode::Sub, Value::Immediate(0), quotient);
+            }
+            sequence.finish(dst, x);
+        }
+    }
+    if !sequence.is_move() && costs.of(&sequence.code) >= limit {
+        return None;
+    }
+    let temps = sequence.next_temp - sequence.first_temp;
+    Some((sequence.code, temps))
+}
+
+/// Instructions being built, each result in a new temporary
+struct Sequence {
+    code: Vec<Instruction>,
+    next_temp: usize,
+    first_temp: usize,
+}
+
+impl Sequence {
+    /// Append `opcode result, a, b` and return the result
+    fn emit(&mut self, opcode: Opcode, a: Value, b: Value) -> Value {
+        let result = Value::Temp(self.next_temp);
+        self.next_temp += 1;
+        self.code.push(Instruction::new(opcode, vec![result.clone(), a, b]));
+        result
+    }
+
+    /// Shift `value` by `count` bits, if any
+    fn shift(&mut self, opcode: Opcode, value: Value, count: u32) -> Value {
+        match count {
+            0 => value,
+            _ => self.emit(opcode, value, Value::Immediate(count as i32)),
+        }
+    }
+
+    /// Write the last result to `dst`, or copy `x` when there is nothing
+    /// to compute
+    fn finish(&mut self, dst: &Value, x: &Value) {
+        match self.code.last_mut() {
+            Some(last) => last.operands[0] = dst.clone(),
+            None => self.code.push(Instruction::new(Opcode::Mov, vec![dst.clone(), x.clone()])),
+        }
+    }
+
+    /// Whether the sequence is a single MOV, always cheaper than the operation
+    fn is_move(&self) -> bool {
+        matches!(self.code.as_slice(), [Instruction { opcode: Opcode::Mov, .. }])
+    }
+}
+
+/// `dst := x * (sum of the digits)`, with `digits` as (bit, negative)
+/// pairs, highest bit first
+fn multiply(dst: &Value, x: &Value, digits: &[(u32, bool)], next_temp: usize) -> Sequence {
+    let mut sequence = Sequence { code: vec![], next_temp, first_temp: next_temp };
+    let Some((&(top, negative), rest)) = digits.split_first() else {
+        sequence.finish(dst, &Value::Immediate(0));
+        return sequence;
+    };
+    let mut product = x.clone();
+    if negative {
+        product = sequence.emit(Opcode::Sub, Value::Immediate(0), x.clone());
+    }
+    let mut bit = top;
+    for &(next, negative) in rest {
+        product = sequence.shift(Opcode::Shl, product, bit - next);
+        let opcode = if negative { Opcode::Sub } else { Opcode::Add };
+        product = sequence.emit(opcode, product, x.clone());
+        bit = next;
+    }
+    sequence.shift(Opcode::Shl, product, bit);
+    sequence.finish(dst, x);
+    sequence
+}
+
+/// Set bits of `n`, highest first
+fn binary_digits(n: u32) -> Vec<(u32, bool)> {
+    (0..16).rev().filter(|bit| n & (1 << bit) != 0).map(|bit| (bit, false)).collect()
+}
+
+/// Non-adjacent form of `n`: digits of 1 or -1 with no two adjacent,
+/// highest first. Digits from bit 16 up are dropped, as products wrap.
+fn signed_digits(mut n: u32) -> Vec<(u32, bool)> {
+    let mut digits = Vec::new();
+    let mut bit = 0;
+    while n != 0 {
+        if n & 1 == 1 {
+            // ...11 becomes a -1 digit and a carry, ...01 a 1 digit
+            let negative = n & 3 == 3;
+            if bit < 16 {
+                digits.push((bit, negative));
+            }
+            n = if negative { n + 1 } else { n - 1 };
+        }
+        n >>= 1;
+        bit += 1;
+    }
+    digits.reverse();
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{interpret, State};
+    use crate::{BasicBlock, Function};
+
+    const COSTS: ArithCosts = ArithCosts {
+        add: 20,
+        subtract: 24,
+        shift_left: 11,
+        shift_right: 16,
+        byte_shift: 11,
+        sign_mask: 26,
+        multiply: 600,
+        divide: 1000,
+    };
+
+    /// Value of `code` for `x`, run by the IR interpreter
+    fn run(code: &[Instruction], x: u16) -> u16 {
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = code.to_vec();
+        let mut state = State::default();
+        state.registers.insert("x".to_string(), x);
+        interpret(&function, &mut state, 100).unwrap();
+        state.registers["dst"]
+    }
+
+    fn reduced(opcode: Opcode, constant: i32) -> Option<Vec<Instruction>> {
+        let x = Value::Register("x".to_string());
+        let inst = Instruction::new(opcode, vec![Value::Register("dst".to_string()), x, Value::Immediate(constant)]);
+        reduce(&inst, &COSTS, 0).map(|(code, _)| code)
+    }
+
+    #[test]
+    fn test_multiplications_by_constants_match_the_product() {
+        let samples = [0u16, 1, 2, 3, 7, 100, 255, 1000, 32767, 32768, 40000, 65535];
+        for constant in (-20..=300).chain([1000, 1024, 4095, 32767, -32768]) {
+            let Some(code) = reduced(Opcode::Mul, constant) else {
+                continue;
+            };
+            for x in samples {
+                assert_eq!(run(&code, x), x.wrapping_mul(constant as u16), "{} * {}", x, constant);
+            }
+        }
+        let opcodes = |constant| reduced(Opcode::Mul, constant).unwrap().iter().map(|i| i.opcode.clone()).collect::<Vec<_>>();
+        assert_eq!(opcodes(10), [Opcode::Shl, Opcode::Add, Opcode::Shl]);
+        // A run of ones costs one subtraction
+        assert_eq!(opcodes(15), [Opcode::Shl, Opcode::Sub]);
+        assert_eq!(opcodes(256), [Opcode::Shl]);
+        assert_eq!(opcodes(1), [Opcode::Mov]);
+        // Too many digits for the cost of a multiply
+        let cheap_multiply = ArithCosts { multiply: 200, ..COSTS };
+        let inst = Instruction::new(Opcode::Mul, vec![Value::Temp(1), Value::Temp(0), Value::Immediate(0x5555)]);
+        assert!(reduce(&inst, &cheap_multiply, 2).is_none());
+    }
+
+    #[test]
+    fn test_divisions_by_powers_of_two_truncate_toward_zero() {
+        let samples = [0u16, 1, 5, 7, 8, 9, 255, 32767, 32768, 32769, 65529, 65535];
+        for constant in [1, 2, 4, 8, 64, 256, 16384, -1, -2, -8, -1024] {
+            let signed = reduced(Opcode::Div, constant).unwrap();
+            let unsigned = (constant > 0).then(|| reduced(Opcode::DivU, constant).unwrap());
+            for x in samples {
+                let quotient = (x as i16 as i32 / constant) as u16;
+                assert_eq!(run(&signed, x), quotient, "{} div {}", x as i16, constant);
+                if let Some(unsigned) = &unsigned {
+                    assert_eq!(run(unsigned, x), x / constant as u16, "{} div {}", x, constant);
+                }
+            }
+        }
+        assert_eq!(reduced(Opcode::DivU, 8).unwrap().len(), 1);
+        assert_eq!(reduced(Opcode::Div, 8).unwrap().len(), 4);
+        assert!(reduced(Opcode::Div, 10).is_none());
+        assert!(reduced(Opcode::Div, 0).is_none());
+    }
+
+    #[test]
+    fn test_reduce_strength_keeps_temporaries_unique() {
+        let mut program = Program::new();
+        let mut function = Function::new("f".to_string(), None);
+        let mut block = BasicBlock::new("f_entry".to_string());
+        block.add_instruction(Instruction::new(Opcode::Mul, vec![Value::Temp(1), Value::Temp(0), Value::Immediate(10)]));
+        block.add_instruction(Instruction::new(Opcode::Mul, vec![Value::Temp(2), Value::Temp(1), Value::Immediate(6)]));
+        block.add_instruction(Instruction::new(Opcode::Div, vec![Value::Temp(3), Value::Temp(2), Value::Immediate(3)]));
+        function.blocks = vec![block];
+        program.add_function(function);
+
+        assert_eq!(reduce_strength(&mut program, &COSTS), 2);
+        let code = &program.functions[0].blocks[0].instructions;
+        let results: Vec<_> = code.iter().map(|inst| inst.operands[0].clone()).collect();
+        assert_eq!(
+            results,
+            [Value::Temp(4), Value::Temp(5), Value::Temp(1), Value::Temp(7), Value::Temp(8), Value::Temp(2), Value::Temp(3)]
+        );
+        assert_eq!(code.last().unwrap().opcode, Opcode::Div);
+    }
+}
@@ -0,0 +1,253 @@
+//! String lowering
+//!
+//! A string is a length byte followed by its characters, `max` of which
+//! fit the variable (see [`Type::String`]). String literals are read-only
+//! strings named `__str_{n}`, numbered like the analyzer's list of literals.
+//! String expressions are built into a destination holding `max`
+//! characters; operations truncate their result to it. For `S: string[20]`:
+//!
+//! ```text
+//!     S := 'Hi ' + Copy(T, 2, 3) + C
+//!
+//!     STRCOPY   S, __str_0, 20
+//!     STRSLICE  t0, T, 2, 3, 255
+//!     STRCONCAT S, t0, 20
+//!     STRCHAR   t1, C
+//!     STRCONCAT S, t1, 20
+//! ```
+//!
+//! An assignment whose operations read the target is built into a
+//! temporary and copied, as for sets.
+
+use ast::Node;
+use types::{Type, MAX_STRING_LENGTH};
+
+use crate::{Condition, IRBuilder, Instruction, Opcode, Value};
+
+impl IRBuilder {
+    /// Use the analyzer's string literals, in order, so `__str_{n}` names
+    /// the data emitted for the `n`th one
+    pub fn set_string_literals(&mut self, literals: &[String]) {
+        self.string_literals = literals.to_vec();
+    }
+
+    /// The string literals, with any the build added after the analyzer's
+    pub fn string_literals(&self) -> &[String] {
+        &self.string_literals
+    }
+
+    /// Label of the read-only string holding `text`
+    pub(crate) fn string_literal(&mut self, text: &str) -> Value {
+        let index = match self.string_literals.iter().position(|literal| literal == text) {
+            Some(index) => index,
+            None => {
+                self.string_literals.push(text.to_string());
+                self.string_literals.len() - 1
+            }
+        };
+        Value::Label(format!("__str_{}", index))
+    }
+
+    /// Whether `expr` is a string: a literal, a string variable, a
+    /// concatenation, `Copy` or `Concat`, or a call of a function giving
+    /// one
+    pub(crate) fn is_string(&self, expr: &Node) -> bool {
+        match expr {
+            Node::LiteralExpr(lit) => matches!(lit.value, ast::LiteralValue::String(_)),
+            Node::IdentExpr(ident) => self
+                .variable_types
+                .get(&ident.name)
+                .and_then(|ty| self.resolve_type(ty))
+                .is_some_and(|ty| matches!(ty, Type::String { .. })),
+            Node::BinaryExpr(bin) if bin.op == ast::BinaryOp::Add => {
+                self.is_string(&bin.left)
+                    || self.is_string(&bin.right)
+                    || (self.is_char(&bin.left) && self.is_char(&bin.right))
+            }
+            Node::CallExpr(call) => {
+                call.name.eq_ignore_ascii_case("Copy") || call.name.eq_ignore_ascii_case("Concat") || self.is_string_call(call)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `call` calls a function giving a string, or `ParamStr`
+    fn is_string_call(&self, call: &ast::CallExpr) -> bool {
+        self.is_param_str(call)
+            || self
+                .routine_result(&call.name)
+                .and_then(|ty| self.resolve_type(ty))
+                .is_some_and(|ty| matches!(ty, Type::String { .. }))
+    }
+
+    fn is_char(&self, expr: &Node) -> bool {
+        self.analyze_expression_type(expr) == Some(Type::char())
+    }
+
+    /// Whether `bin` compares two strings, or a string and a character
+    pub(crate) fn is_string_comparison(&self, bin: &ast::BinaryExpr) -> bool {
+        matches!(
+            bin.op,
+            ast::BinaryOp::Equal
+                | ast::BinaryOp::NotEqual
+                | ast::BinaryOp::Less
+                | ast::BinaryOp::LessEqual
+                | ast::BinaryOp::Greater
+                | ast::BinaryOp::GreaterEqual
+        ) && (self.is_string(&bin.left) || self.is_string(&bin.right))
+    }
+
+    /// Assign the string `value` to the variable `name`, of up to
+    /// `max_length` characters
+    pub(crate) fn build_string_assign(&mut self, name: &str, value: &Node, max_length: usize) {
+        let target = self.get_variable_address(name);
+        let max = Value::Immediate(max_length as i32);
+        if matches!(value, Node::BinaryExpr(_) | Node::CallExpr(_)) && Self::reads(value, name) {
+            let temp = self.new_frame_temp(MAX_STRING_LENGTH + 1);
+            self.build_string_into(value, &temp, MAX_STRING_LENGTH);
+            self.emit(Instruction::new(Opcode::StrCopy, vec![target, temp, max]));
+        } else {
+            self.build_string_into(value, &target, max_length);
+        }
+    }
+
+    /// Whether the string expression `expr` reads the variable `name`
+    fn reads(expr: &Node, name: &str) -> bool {
+        match expr {
+            Node::IdentExpr(ident) => ident.name.eq_ignore_ascii_case(name),
+            Node::BinaryExpr(bin) => Self::reads(&bin.left, name) || Self::reads(&bin.right, name),
+            Node::CallExpr(call) => call.args.iter().any(|arg| Self::reads(arg, name)),
+            _ => false,
+        }
+    }
+
+    /// Build the string `expr` into `dst`, which holds `max_length`
+    /// characters
+    fn build_string_into(&mut self, expr: &Node, dst: &Value, max_length: usize) {
+        let max = Value::Immediate(max_length as i32);
+        match expr {
+            Node::BinaryExpr(bin) if bin.op == ast::BinaryOp::Add => {
+                self.build_string_into(&bin.left, dst, max_length);
+                let right = self.string_operand(&bin.right);
+                self.emit(Instruction::new(Opcode::StrConcat, vec![dst.clone(), right, max]));
+            }
+            Node::CallExpr(call) if call.name.eq_ignore_ascii_case("Concat") && !call.args.is_empty() => {
+                self.build_string_into(&call.args[0], dst, max_length);
+                for arg in &call.args[1..] {
+                    let src = self.string_operand(arg);
+                    self.emit(Instruction::new(Opcode::StrConcat, vec![dst.clone(), src, max.clone()]));
+                }
+            }
+            Node::CallExpr(call) if call.name.eq_ignore_ascii_case("Copy") && call.args.len() == 3 => {
+                let src = self.string_operand(&call.args[0]);
+                let index = self.build_expression(&call.args[1]);
+                let count = self.build_expression(&call.args[2]);
+                self.emit(Instruction::new(Opcode::StrSlice, vec![dst.clone(), src, index, count, max]));
+            }
+            Node::CallExpr(call) if self.is_param_str(call) => {
+                let src = self.build_param_str(call);
+                self.emit(Instruction::new(Opcode::StrCopy, vec![dst.clone(), src, max]));
+            }
+            Node::CallExpr(call) if self.is_string_call(call) => {
+                let src = self.string_operand(expr);
+                self.emit(Instruction::new(Opcode::StrCopy, vec![dst.clone(), src, max]));
+            }
+            _ if self.is_char(expr) => {
+                let value = self.build_expression(expr);
+                self.emit(Instruction::new(Opcode::StrChar, vec![dst.clone(), value]));
+            }
+            _ => {
+                let src = self.build_expression(expr);
+                self.emit(Instruction::new(Opcode::StrCopy, vec![dst.clone(), src, max]));
+            }
+        }
+    }
+
+    /// The string `expr`: a variable or literal as it is, anything else,
+    /// such as an argument built from a concatenation, built into a
+    /// temporary
+    pub(crate) fn string_operand(&mut self, expr: &Node) -> Value {
+        match expr {
+            Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::String(text), .. }) => {
+                self.string_literal(text)
+            }
+            Node::IdentExpr(_) if self.is_string(expr) => self.build_expression(expr),
+            Node::CallExpr(call) if self.is_param_str(call) => self.param_str_operand(call),
+            // The string temporary the function's result is copied to
+            Node::CallExpr(call) if self.is_string_call(call) => {
+                self.build_call(&call.name, &call.args, call.span).unwrap_or_else(|| self.new_temp())
+            }
+            _ => {
+                let temp = self.new_frame_temp(MAX_STRING_LENGTH + 1);
+                self.build_string_into(expr, &temp, MAX_STRING_LENGTH);
+                temp
+            }
+        }
+    }
+
+    /// Build a comparison of two strings, giving a Boolean
+    pub(crate) fn build_string_comparison(&mut self, bin: &ast::BinaryExpr) -> Value {
+        let left = self.string_operand(&bin.left);
+        let right = self.string_operand(&bin.right);
+        let condition = match bin.op {
+            ast::BinaryOp::Equal => Condition::Equal,
+            ast::BinaryOp::NotEqual => Condition::NotEqual,
+            ast::BinaryOp::Less => Condition::Less,
+            ast::BinaryOp::LessEqual => Condition::LessEqual,
+            ast::BinaryOp::Greater => Condition::Greater,
+            _ => Condition::GreaterEqual,
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(
+            Opcode::StrCmp,
+            vec![result.clone(), left, right, Value::Condition(condition)],
+        ));
+        result
+    }
+
+    /// Build `Length(s)` or `Pos(sub, s)`; None for other calls
+    pub(crate) fn build_string_function(&mut self, call: &ast::CallExpr) -> Option<Value> {
+        let (opcode, strings) = match call.args.as_slice() {
+            [s] if call.name.eq_ignore_ascii_case("Length") => (Opcode::StrLength, vec![self.string_operand(s)]),
+            [sub, s] if call.name.eq_ignore_ascii_case("Pos") => {
+                (Opcode::StrPos, vec![self.string_operand(sub), self.string_operand(s)])
+            }
+            _ => return None,
+        };
+        let result = self.new_temp();
+        self.emit(Instruction::new(opcode, [vec![result.clone()], strings].concat()));
+        Some(result)
+    }
+
+    /// Build `Delete(s, index, count)` or `Insert(source, s, index)`;
+    /// false for other calls
+    pub(crate) fn build_string_procedure(&mut self, call: &ast::CallStmt) -> bool {
+        match call.args.as_slice() {
+            [Node::IdentExpr(s), index, count] if call.name.eq_ignore_ascii_case("Delete") => {
+                let target = self.get_variable_address(&s.name);
+                let index = self.build_expression(index);
+                let count = self.build_expression(count);
+                self.emit(Instruction::new(Opcode::StrDelete, vec![target, index, count]).with_span(call.span));
+            }
+            [source, Node::IdentExpr(s), index] if call.name.eq_ignore_ascii_case("Insert") => {
+                let Some(Type::String { max_length }) =
+                    self.variable_types.get(&s.name).and_then(|ty| self.resolve_type(ty)).cloned()
+                else {
+                    return false;
+                };
+                let source = self.string_operand(source);
+                let target = self.get_variable_address(&s.name);
+                let index = self.build_expression(index);
+                self.emit(
+                    Instruction::new(
+                        Opcode::StrInsert,
+                        vec![target, source, index, Value::Immediate(max_length as i32)],
+                    )
+                    .with_span(call.span),
+                );
+            }
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,138 @@
+//! Unreachable code removal
+//!
+//! Instructions after an unconditional transfer in their block, and blocks
+//! no path from the entry block reaches, are removed. A block is reached
+//! by a label naming it in a reached block (the targets of jumps,
+//! switches and exception handlers) or by falling through from the block
+//! before it. Constant folding leaves such blocks behind when it decides a
+//! branch:
+//!
+//! ```text
+//! f_entry:                          f_entry:
+//!     JUMP  big                         JUMP  big
+//! small:                            big:
+//!     MOV   t2, 2                       MOV   t2, 1
+//!     RET                               RET
+//! big:
+//!     MOV   t2, 1
+//!     RET
+//! ```
+//!
+//! Functions with inline assembly, which may jump to any label, and those
+//! built under `{$OPTIMIZATION OFF}` are left alone.
+
+use std::collections::HashSet;
+
+use crate::{Function, Opcode, Program, Value};
+
+/// Remove the unreachable code of the functions of `program` that may be
+/// optimized. Returns the number of instructions and blocks removed.
+pub fn remove_unreachable_code(program: &mut Program) -> usize {
+    program
+        .functions
+        .iter_mut()
+        .filter(|function| function.optimize && !has_asm(function))
+        .map(remove_from_function)
+        .sum()
+}
+
+/// Remove the unreachable code of `function`
+fn remove_from_function(function: &mut Function) -> usize {
+    let mut removed = 0;
+    for block in &mut function.blocks {
+        if let Some(end) = block.instructions.iter().position(|inst| transfers(&inst.opcode)) {
+            removed += block.instructions.len() - (end + 1);
+            block.instructions.truncate(end + 1);
+        }
+    }
+
+    let labels: Vec<String> = function.blocks.iter().map(|block| block.label.clone()).collect();
+    let mut reached = vec![false; labels.len()];
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+        if index >= labels.len() || reached[index] {
+            continue;
+        }
+        reached[index] = true;
+        let block = &function.blocks[index];
+        for operand in block.instructions.iter().flat_map(|inst| &inst.operands) {
+            if let Value::Label(label) = operand
+                && let Some(target) = labels.iter().position(|other| other == label)
+            {
+                pending.push(target);
+            }
+        }
+        if !block.instructions.last().is_some_and(|inst| transfers(&inst.opcode)) {
+            pending.push(index + 1);
+        }
+    }
+
+    let mut index = 0;
+    function.blocks.retain(|_| {
+        index += 1;
+        reached[index - 1]
+    });
+    removed += reached.iter().filter(|reached| !**reached).count();
+    let kept: HashSet<&str> = function.blocks.iter().map(|block| block.label.as_str()).collect();
+    function
+        .loops
+        .retain(|counted| [&counted.body, &counted.step, &counted.exit].iter().all(|label| kept.contains(label.as_str())));
+    removed
+}
+
+/// Whether `opcode` never goes on to the next instruction
+fn transfers(opcode: &Opcode) -> bool {
+    matches!(opcode, Opcode::Jump | Opcode::CJump | Opcode::Switch | Opcode::Ret | Opcode::Raise | Opcode::Reraise)
+}
+
+/// Whether `function` contains inline assembly
+fn has_asm(function: &Function) -> bool {
+    function.blocks.iter().flat_map(|block| &block.instructions).any(|inst| inst.opcode == Opcode::Asm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fold_constants, BasicBlock, Instruction};
+
+    fn label(name: &str) -> Value {
+        Value::Label(name.to_string())
+    }
+
+    #[test]
+    fn test_decided_branches_leave_no_dead_blocks() {
+        // if 3 > 2 then t0 := 1 else t0 := 2, then a loop of its own
+        let mut function = Function::new("f".to_string(), None);
+        function.blocks[0].instructions = vec![
+            Instruction::new(Opcode::Cmp, vec![Value::Immediate(3), Value::Immediate(2)]),
+            Instruction::new(Opcode::CJump, vec![Value::Condition(crate::Condition::Greater), label("then"), label("else")]),
+        ];
+        let block = |name: &str, code: Vec<Instruction>| {
+            let mut block = BasicBlock::new(name.to_string());
+            block.instructions = code;
+            block
+        };
+        function.add_block(block("else", vec![Instruction::new(Opcode::Mov, vec![Value::Temp(0), Value::Immediate(2)])]));
+        function.add_block(block("spin", vec![Instruction::new(Opcode::Jump, vec![label("spin")])]));
+        function.add_block(block(
+            "then",
+            vec![
+                Instruction::new(Opcode::Mov, vec![Value::Temp(0), Value::Immediate(1)]),
+                Instruction::new(Opcode::Ret, vec![]),
+                Instruction::new(Opcode::Mov, vec![Value::Temp(0), Value::Immediate(3)]),
+            ],
+        ));
+        let mut program = Program::new();
+        program.functions.push(function);
+
+        fold_constants(&mut program);
+        let mut unoptimized = program.clone();
+        assert_eq!(remove_unreachable_code(&mut program), 3);
+        let labels: Vec<_> = program.functions[0].blocks.iter().map(|block| block.label.as_str()).collect();
+        assert_eq!(labels, ["f_entry", "then"]);
+        assert_eq!(program.functions[0].blocks[1].instructions.len(), 2);
+
+        unoptimized.functions[0].optimize = false;
+        assert_eq!(remove_unreachable_code(&mut unoptimized), 0);
+    }
+}
@@ -0,0 +1,318 @@
+//! Loop unrolling
+//!
+//! A FOR loop is built as body blocks ending in the exit test and a step
+//! block jumping back (see `IRBuilder::build_for_stmt`), and recorded as a
+//! [`CountedLoop`]. Unrolling copies the body `factor` times; each copy
+//! steps the variable and falls into the next, so most iterations no longer
+//! jump back. A loop of `for i := 1 to 3` unrolled in full becomes
+//! straight-line code:
+//!
+//! ```text
+//! for_body_0:       <body>; ADD i, i, 1; JUMP for_body_0_u1
+//! for_body_0_u1:    <body>; ADD i, i, 1; JUMP for_body_0_u2
+//! for_body_0_u2:    <body>; JUMP for_exit_2
+//! ```
+//!
+//! Partially unrolled loops keep the exit test after each copy, unless the
+//! trip count is known to be a multiple of the factor, when only the last
+//! copy tests it. Temporaries used only in the body are renumbered in each
+//! copy.
+//!
+//! A loop is unrolled `n` times by an `{$UNROLL n}` hint (in full if `n`
+//! covers the trip count; 0 and 1 keep it rolled). Without a hint, only
+//! loops with a constant trip count are unrolled, in full, and only when
+//! the copies fit in [`UNROLL_BUDGET`] instructions and the build does not
+//! optimize for size.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{BasicBlock, Function, Instruction, Opcode, Program, Value};
+
+/// Most IR instructions a loop unrolled without a hint may take
+pub const UNROLL_BUDGET: usize = 64;
+
+/// A FOR loop as built, recorded for [`unroll_loops`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountedLoop {
+    /// First block of the body
+    pub body: String,
+    /// Block stepping the variable and jumping back to `body`
+    pub step: String,
+    /// Block after the loop
+    pub exit: String,
+    /// Number of iterations, when both bounds are constants
+    pub trip_count: Option<u32>,
+    /// Factor from an `{$UNROLL n}` hint
+    pub unroll: Option<u32>,
+}
+
+/// Unroll the FOR loops of `program` that have a hint or are small enough.
+/// `optimize_size` (`-Os`) leaves loops without a hint rolled. Returns the
+/// number of loops unrolled.
+pub fn unroll_loops(program: &mut Program, optimize_size: bool) -> usize {
+    let mut unrolled = 0;
+    for function in &mut program.functions {
+        // Inner loops are recorded first, so they are unrolled before the
+        // loops around them are measured
+        for counted in std::mem::take(&mut function.loops) {
+            match unroll_factor(function, &counted, optimize_size) {
+                Some(factor) => {
+                    unroll(function, &counted, factor);
+                    unrolled += 1;
+                }
+                None => function.loops.push(counted),
+            }
+        }
+    }
+    unrolled
+}
+
+/// Blocks of the body of `counted` in `function`: from its first block up to
+/// the step block
+fn body_range(function: &Function, counted: &CountedLoop) -> Option<std::ops::Range<usize>> {
+    let position = |label: &str| function.blocks.iter().position(|block| block.label == label);
+    let (body, step) = (position(&counted.body)?, position(&counted.step)?);
+    // The last body block ends in the exit test
+    let last = function.blocks[..step].last()?;
+    let ends_in_test = matches!(
+        last.instructions.as_slice(),
+        [.., Instruction { opcode: Opcode::Cmp, .. }, Instruction { opcode: Opcode::CJump, .. }]
+    );
+    (body < step && ends_in_test).then_some(body..step)
+}
+
+/// How many copies of the body `counted` should be unrolled into, or None
+/// to leave it rolled
+fn unroll_factor(function: &Function, counted: &CountedLoop, optimize_size: bool) -> Option<u32> {
+    let range = body_range(function, counted)?;
+    let factor = match (counted.unroll, counted.trip_count) {
+        (Some(factor), _) if factor < 2 => return None,
+        (Some(factor), Some(trips)) => factor.min(trips),
+        (Some(factor), None) => factor,
+        (None, Some(trips)) if !optimize_size && trips > 0 => {
+            let size: usize = function.blocks[range].iter().map(|block| block.instructions.len()).sum();
+            if size * trips as usize > UNROLL_BUDGET {
+                return None;
+            }
+            trips
+        }
+        (None, _) => return None,
+    };
+    (factor > 0).then_some(factor)
+}
+
+/// Replace the body of `counted` with `factor` copies
+fn unroll(function: &mut Function, counted: &CountedLoop, factor: u32) {
+    let Some(range) = body_range(function, counted) else {
+        return;
+    };
+    let full = counted.trip_count == Some(factor);
+    // Only the last copy tests for the exit when every run is `factor` iterations
+    let test_last_only = full || counted.trip_count.is_some_and(|trips| trips % factor == 0);
+    let step_code: Vec<Instruction> = function.blocks[range.end]
+        .instructions
+        .iter()
+        .filter(|inst| inst.opcode != Opcode::Jump)
+        .cloned()
+        .collect();
+    let body: Vec<BasicBlock> = function.blocks[range.clone()].to_vec();
+    let labels: HashSet<String> = body.iter().map(|block| block.label.clone()).collect();
+    let local_temps = local_temps(function, range.clone());
+    let mut next_temp = max_temp(function).map_or(0, |max| max + 1);
+
+    let copy_label = |label: &str, copy: u32| match copy {
+        0 => label.to_string(),
+        copy => format!("{}_u{}", label, copy),
+    };
+    let mut unrolled = Vec::new();
+    for copy in 0..factor {
+        let mut temps = HashMap::new();
+        if copy > 0 {
+            for &temp in &local_temps {
+                temps.insert(temp, next_temp);
+                next_temp += 1;
+            }
+        }
+        let rename = |value: &Value| match value {
+            Value::Label(label) if labels.contains(label) => Value::Label(copy_label(label, copy)),
+            Value::Temp(temp) => Value::Temp(*temps.get(temp).unwrap_or(temp)),
+            other => other.clone(),
+        };
+        let mut blocks: Vec<BasicBlock> = body
+            .iter()
+            .map(|block| BasicBlock {
+                label: copy_label(&block.label, copy),
+                instructions: block
+                    .instructions
+                    .iter()
+                    .map(|inst| Instruction { operands: inst.operands.iter().map(rename).collect(), ..inst.clone() })
+                    .collect(),
+                successors: block
+                    .successors
+                    .iter()
+                    .map(|label| if labels.contains(label) { copy_label(label, copy) } else { label.clone() })
+                    .collect(),
+            })
+            .collect();
+
+        let is_last = copy + 1 == factor;
+        let next_body = Value::Label(copy_label(&counted.body, if is_last { 0 } else { copy + 1 }));
+        let tail = &mut blocks.last_mut().expect("loop body has blocks").instructions;
+        if is_last && full {
+            // The last iteration leaves the variable at the final value
+            tail.truncate(tail.len() - 2);
+            tail.push(Instruction::new(Opcode::Jump, vec![Value::Label(counted.exit.clone())]));
+        } else if is_last {
+            // Keeps the exit test and the original step block back to the first copy
+        } else if test_last_only {
+            tail.truncate(tail.len() - 2);
+            tail.extend(step_code.iter().cloned());
+            tail.push(Instruction::new(Opcode::Jump, vec![next_body]));
+        } else {
+            // Exit, or step into the next copy
+            let step_label = format!("{}_u{}", counted.step, copy);
+            if let Some(Instruction { operands, .. }) = tail.last_mut() {
+                operands[2] = Value::Label(step_label.clone());
+            }
+            let mut step = BasicBlock::new(step_label);
+            step.instructions = step_code.clone();
+            step.instructions.push(Instruction::new(Opcode::Jump, vec![next_body]));
+            blocks.push(step);
+        }
+        unrolled.extend(blocks);
+    }
+
+    // A fully unrolled loop never steps back
+    let end = if full { range.end + 1 } else { range.end };
+    function.blocks.splice(range.start..end, unrolled);
+}
+
+/// Temporaries mentioned in `range` of the blocks and nowhere else
+fn local_temps(function: &Function, range: std::ops::Range<usize>) -> Vec<usize> {
+    let temps_of = |blocks: &[BasicBlock]| -> HashSet<usize> {
+        blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .flat_map(|inst| &inst.operands)
+            .filter_map(|operand| match operand {
+                Value::Temp(temp) => Some(*temp),
+                _ => None,
+            })
+            .collect()
+    };
+    let outside: HashSet<usize> = temps_of(&function.blocks[..range.start])
+        .union(&temps_of(&function.blocks[range.end..]))
+        .copied()
+        .collect();
+    let mut local: Vec<usize> = temps_of(&function.blocks[range]).difference(&outside).copied().collect();
+    local.sort_unstable();
+    local
+}
+
+pub(crate) fn max_temp(function: &Function) -> Option<usize> {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .flat_map(|inst| &inst.operands)
+        .filter_map(|operand| match operand {
+            Value::Temp(temp) => Some(*temp),
+            _ => None,
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IRBuilder;
+    use ast::Node;
+    use tokens::Span;
+
+    /// `for i := 1 to <end> do x := i + 1`, built into function `test`
+    fn program_with_loop(end: Node, unroll: Option<u32>) -> Program {
+        let span = Span::new(0, 1, 1, 1);
+        let ident = |name: &str| Node::IdentExpr(ast::IdentExpr { name: name.to_string(), span });
+        let one = || Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(1), span });
+        let mut builder = IRBuilder::new();
+        builder.start_function("test".to_string(), None);
+        builder.build_for_stmt(&ast::ForStmt {
+            var_name: "i".to_string(),
+            start_expr: Box::new(one()),
+            direction: ast::ForDirection::To,
+            end_expr: Box::new(end),
+            body: Box::new(Node::AssignStmt(ast::AssignStmt {
+                target: Box::new(ident("x")),
+                value: Box::new(Node::BinaryExpr(ast::BinaryExpr {
+                    op: ast::BinaryOp::Add,
+                    left: Box::new(ident("i")),
+                    right: Box::new(one()),
+                    span,
+                })),
+                span,
+            })),
+            unroll,
+            span,
+        });
+        builder.finish_function();
+        builder.into_program()
+    }
+
+    fn constant(value: u32) -> Node {
+        Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(value), span: Span::new(0, 1, 1, 1) })
+    }
+
+    fn labels(program: &Program) -> Vec<&str> {
+        program.functions[0].blocks.iter().map(|block| block.label.as_str()).collect()
+    }
+
+    #[test]
+    fn test_small_constant_loop_unrolls_in_full() {
+        let mut program = program_with_loop(constant(3), None);
+        assert_eq!(unroll_loops(&mut program, false), 1);
+
+        assert_eq!(labels(&program), ["test_entry", "for_body_0", "for_body_0_u1", "for_body_0_u2", "for_exit_2"]);
+        let blocks = &program.functions[0].blocks;
+        let tail = |block: &BasicBlock| block.instructions.last().unwrap().clone();
+        assert_eq!(tail(&blocks[1]).operands, [Value::Label("for_body_0_u1".to_string())]);
+        assert_eq!(tail(&blocks[3]).operands, [Value::Label("for_exit_2".to_string())]);
+        assert!(blocks[1..4].iter().all(|block| block.instructions.iter().all(|inst| inst.opcode != Opcode::Cmp)));
+        // Each copy computes `i + 1` into a temporary of its own
+        let dst = |block: &BasicBlock| block.instructions[0].operands[0].clone();
+        assert_ne!(dst(&blocks[1]), dst(&blocks[2]));
+        assert!(program.functions[0].loops.is_empty());
+    }
+
+    #[test]
+    fn test_unrolling_follows_budget_size_goal_and_hints() {
+        // Too large for the budget, and -Os
+        let mut program = program_with_loop(constant(100), None);
+        assert_eq!(unroll_loops(&mut program, false), 0);
+        let mut program = program_with_loop(constant(3), None);
+        assert_eq!(unroll_loops(&mut program, true), 0);
+        let mut program = program_with_loop(constant(3), Some(1));
+        assert_eq!(unroll_loops(&mut program, false), 0);
+        assert_eq!(program.functions[0].loops.len(), 1);
+
+        // A hint unrolls under -Os; 100 iterations in 4s test only after the last copy
+        let mut program = program_with_loop(constant(100), Some(4));
+        assert_eq!(unroll_loops(&mut program, true), 1);
+        assert_eq!(
+            labels(&program),
+            ["test_entry", "for_body_0", "for_body_0_u1", "for_body_0_u2", "for_body_0_u3", "for_step_1", "for_exit_2"]
+        );
+
+        // An unknown trip count tests for the exit after every copy
+        let end = Node::IdentExpr(ast::IdentExpr { name: "n".to_string(), span: Span::new(0, 1, 1, 1) });
+        let mut program = program_with_loop(end, Some(2));
+        assert_eq!(unroll_loops(&mut program, false), 1);
+        assert_eq!(
+            labels(&program),
+            ["test_entry", "for_body_0", "for_step_1_u0", "for_body_0_u1", "for_step_1", "for_exit_2"]
+        );
+        let blocks = &program.functions[0].blocks;
+        assert_eq!(blocks[1].instructions.last().unwrap().operands[2], Value::Label("for_step_1_u0".to_string()));
+        assert_eq!(blocks[2].instructions.last().unwrap().operands, [Value::Label("for_body_0_u1".to_string())]);
+        assert_eq!(blocks[4].instructions.last().unwrap().operands, [Value::Label("for_body_0".to_string())]);
+    }
+}
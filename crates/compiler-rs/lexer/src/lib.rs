@@ -9,30 +9,53 @@ use tokens::{lookup_keyword, Span, Token, TokenKind};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LexerError {
     /// Unterminated string literal
-    UnterminatedString { line: usize, column: usize },
+    UnterminatedString { offset: usize, line: usize, column: usize },
     /// Unterminated comment
-    UnterminatedComment { line: usize, column: usize },
+    UnterminatedComment { offset: usize, line: usize, column: usize },
     /// Invalid character
-    InvalidCharacter { ch: char, line: usize, column: usize },
+    InvalidCharacter { ch: char, offset: usize, line: usize, column: usize },
     /// Invalid escape sequence
-    InvalidEscape { seq: String, line: usize, column: usize },
+    InvalidEscape { seq: String, offset: usize, line: usize, column: usize },
+    /// Group of a byte array literal that is not whole bytes of hex digits
+    InvalidByteGroup { group: String, offset: usize, line: usize, column: usize },
+}
+
+impl LexerError {
+    /// Source span of the offending text: the escape sequence or character,
+    /// or the opening delimiter of an unterminated literal or comment
+    pub fn span(&self) -> Span {
+        match self {
+            LexerError::UnterminatedString { offset, line, column }
+            | LexerError::UnterminatedComment { offset, line, column } => Span::new(*offset, offset + 1, *line, *column),
+            LexerError::InvalidCharacter { ch, offset, line, column } => {
+                Span::new(*offset, offset + ch.len_utf8(), *line, *column).with_end(*line, column + 1)
+            }
+            LexerError::InvalidEscape { seq: text, offset, line, column }
+            | LexerError::InvalidByteGroup { group: text, offset, line, column } => {
+                Span::new(*offset, offset + text.len(), *line, *column).with_end(*line, column + text.chars().count())
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for LexerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LexerError::UnterminatedString { line, column } => {
+            LexerError::UnterminatedString { line, column, .. } => {
                 write!(f, "Unterminated string literal at {}:{}", line, column)
             }
-            LexerError::UnterminatedComment { line, column } => {
+            LexerError::UnterminatedComment { line, column, .. } => {
                 write!(f, "Unterminated comment at {}:{}", line, column)
             }
-            LexerError::InvalidCharacter { ch, line, column } => {
+            LexerError::InvalidCharacter { ch, line, column, .. } => {
                 write!(f, "Invalid character '{}' at {}:{}", ch, line, column)
             }
-            LexerError::InvalidEscape { seq, line, column } => {
+            LexerError::InvalidEscape { seq, line, column, .. } => {
                 write!(f, "Invalid escape sequence '{}' at {}:{}", seq, line, column)
             }
+            LexerError::InvalidByteGroup { group, line, column, .. } => {
+                write!(f, "Invalid byte '{}' in byte array literal at {}:{} (expected pairs of hex digits)", group, line, column)
+            }
         }
     }
 }
@@ -43,25 +66,72 @@ impl std::error::Error for LexerError {}
 pub struct Lexer {
     /// Source code
     source: Vec<char>,
-    /// Current position (byte offset)
+    /// Current position (index into `source`)
     position: usize,
+    /// Byte offset of the current position in the UTF-8 source
+    offset: usize,
     /// Current line (1-based)
     line: usize,
     /// Current column (1-based)
     column: usize,
     /// Lookahead buffer (for peek)
     lookahead: Option<Token>,
+    /// Which characters identifiers may contain
+    identifier_policy: IdentifierPolicy,
+    /// Whether ASM was the last token, so the next is its body
+    asm_pending: bool,
+}
+
+/// Which characters identifiers may contain. Comments and string literals
+/// accept any Unicode character under either policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdentifierPolicy {
+    /// Letters, digits and underscores from ASCII only
+    #[default]
+    Ascii,
+    /// Any Unicode letter or digit, and underscores
+    Unicode,
 }
 
 impl Lexer {
     /// Create a new lexer from source code
     pub fn new(source: &str) -> Self {
+        // A UTF-8 byte order mark is not part of the program
+        let bom = if source.starts_with('\u{FEFF}') { '\u{FEFF}'.len_utf8() } else { 0 };
         Self {
-            source: source.chars().collect(),
+            source: source[bom..].chars().collect(),
             position: 0,
+            offset: bom,
             line: 1,
             column: 1,
             lookahead: None,
+            identifier_policy: IdentifierPolicy::default(),
+            asm_pending: false,
+        }
+    }
+
+    /// Set which characters identifiers may contain
+    pub fn with_identifier_policy(mut self, policy: IdentifierPolicy) -> Self {
+        self.identifier_policy = policy;
+        self
+    }
+
+    /// Get the identifier policy
+    pub fn identifier_policy(&self) -> IdentifierPolicy {
+        self.identifier_policy
+    }
+
+    fn is_identifier_start(&self, ch: char) -> bool {
+        match self.identifier_policy {
+            IdentifierPolicy::Ascii => ch.is_ascii_alphabetic() || ch == '_',
+            IdentifierPolicy::Unicode => ch.is_alphabetic() || ch == '_',
+        }
+    }
+
+    fn is_identifier_char(&self, ch: char) -> bool {
+        match self.identifier_policy {
+            IdentifierPolicy::Ascii => ch.is_ascii_alphanumeric() || ch == '_',
+            IdentifierPolicy::Unicode => ch.is_alphanumeric() || ch == '_',
         }
     }
 
@@ -71,6 +141,10 @@ impl Lexer {
         if let Some(token) = self.lookahead.take() {
             return Ok(token);
         }
+        if self.asm_pending {
+            self.asm_pending = false;
+            return Ok(self.scan_asm_text());
+        }
 
         self.skip_whitespace();
         
@@ -88,25 +162,31 @@ impl Lexer {
         }
         
         self.skip_comments()?;
+        if self.at_directive() {
+            // A directive right after a comment
+            return self.next_token();
+        }
 
         // Check for EOF
         if self.is_at_end() {
             return Ok(Token::new(
                 TokenKind::Eof,
-                Span::at(self.position, self.line, self.column),
+                Span::at(self.offset, self.line, self.column),
             ));
         }
 
-        let start_pos = self.position;
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
         let ch = self.current_char();
 
-        let kind = if ch.is_ascii_alphabetic() || ch == '_' {
+        let kind = if self.is_identifier_start(ch) {
             self.scan_identifier_or_keyword()
         } else if ch.is_ascii_digit() {
             self.scan_number()?
+        } else if ch == '$' && self.peek_char() == Some('"') {
+            self.scan_byte_array()?
         } else if ch == '$' && self.peek_char().map_or(false, |c| c.is_ascii_hexdigit()) {
             // Pascal-style hex literal: $FF
             self.scan_hex_dollar()?
@@ -132,12 +212,53 @@ impl Lexer {
             self.scan_operator_or_delimiter()?
         };
 
-        let end_pos = self.position;
-        let span = Span::new(start_pos, end_pos, start_line, start_col);
+        let end_pos = self.offset;
+        let span = Span::new(start_pos, end_pos, start_line, start_col).with_end(self.line, self.column);
+        self.asm_pending = kind == TokenKind::KwAsm;
 
         Ok(Token::new(kind, span))
     }
 
+    /// Scan the body of an ASM block, as written, up to the END closing it.
+    /// END in a `;` comment or a quoted string does not close the block; a
+    /// quote without a match on its line, as in `ex af, af'`, is text.
+    fn scan_asm_text(&mut self) -> Token {
+        let (start_pos, start_line, start_col) = (self.offset, self.line, self.column);
+        let start = self.position;
+        while !self.is_at_end() {
+            let ch = self.current_char();
+            let after_word = self.position > start && self.is_identifier_char(self.source[self.position - 1]);
+            if !after_word
+                && self.source[self.position..].iter().take(3).collect::<String>().eq_ignore_ascii_case("end")
+                && !self.peek_char_at(3).is_some_and(|next| self.is_identifier_char(next))
+            {
+                break;
+            }
+            match ch {
+                ';' => {
+                    while !self.is_at_end() && self.current_char() != '\n' {
+                        self.advance();
+                    }
+                    continue;
+                }
+                '"' | '\'' => {
+                    let rest = &self.source[self.position + 1..];
+                    let line = &rest[..rest.iter().position(|&c| c == '\n').unwrap_or(rest.len())];
+                    if let Some(close) = line.iter().position(|&c| c == ch) {
+                        for _ in 0..=close {
+                            self.advance();
+                        }
+                    }
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+        let text: String = self.source[start..self.position].iter().collect();
+        let span = Span::new(start_pos, self.offset, start_line, start_col).with_end(self.line, self.column);
+        Token::new(TokenKind::AsmText(text), span)
+    }
+
     /// Peek at the next token without consuming it
     pub fn peek_token(&mut self) -> Result<&Token, LexerError> {
         if self.lookahead.is_none() {
@@ -190,6 +311,7 @@ impl Lexer {
                 self.column += 1;
             }
             self.position += 1;
+            self.offset += ch.len_utf8();
         }
     }
 
@@ -205,10 +327,22 @@ impl Lexer {
         }
     }
 
-    /// Skip comments (both { } and (* *) styles)
+    /// Whether a directive starts at the current position
+    fn at_directive(&self) -> bool {
+        !self.is_at_end()
+            && ((self.current_char() == '{' && self.peek_char() == Some('$'))
+                || (self.current_char() == '(' && self.peek_char() == Some('*') && self.peek_char_at(2) == Some('$')))
+    }
+
+    /// Skip comments ({ }, (* *) and // styles), stopping at a directive
     fn skip_comments(&mut self) -> Result<(), LexerError> {
         loop {
-            if self.current_char() == '{' {
+            if self.at_directive() {
+                break;
+            } else if self.current_char() == '/' && self.peek_char() == Some('/') {
+                self.skip_line_comment();
+                self.skip_whitespace();
+            } else if self.current_char() == '{' {
                 self.skip_comment_curly()?;
                 self.skip_whitespace();
             } else if self.current_char() == '(' && self.peek_char() == Some('*') {
@@ -223,6 +357,7 @@ impl Lexer {
 
     /// Skip curly brace comment { ... }
     fn skip_comment_curly(&mut self) -> Result<(), LexerError> {
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -237,13 +372,22 @@ impl Lexer {
         }
 
         Err(LexerError::UnterminatedComment {
+            offset: start_pos,
             line: start_line,
             column: start_col,
         })
     }
 
+    /// Skip line comment // ..., up to the end of the line
+    fn skip_line_comment(&mut self) {
+        while !self.is_at_end() && self.current_char() != '\n' {
+            self.advance();
+        }
+    }
+
     /// Skip paren-star comment (* ... *)
     fn skip_comment_paren(&mut self) -> Result<(), LexerError> {
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -260,6 +404,7 @@ impl Lexer {
         }
 
         Err(LexerError::UnterminatedComment {
+            offset: start_pos,
             line: start_line,
             column: start_col,
         })
@@ -267,7 +412,7 @@ impl Lexer {
 
     /// Scan compiler directive: {$...}
     fn scan_directive_curly(&mut self) -> Result<Token, LexerError> {
-        let start_pos = self.position;
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -287,23 +432,24 @@ impl Lexer {
         if directive_content.is_empty() {
             return Err(LexerError::InvalidCharacter {
                 ch: '}',
+                offset: start_pos,
                 line: start_line,
                 column: start_col,
             });
         }
 
-        let end_pos = self.position;
-        let span = Span::new(start_pos, end_pos, start_line, start_col);
+        let end_pos = self.offset;
+        let span = Span::new(start_pos, end_pos, start_line, start_col).with_end(self.line, self.column);
 
         Ok(Token::new(
-            TokenKind::Directive(directive_content.trim().to_string()),
+            TokenKind::Directive(directive_content),
             span,
         ))
     }
 
     /// Scan compiler directive: (*$...*)
     fn scan_directive_paren(&mut self) -> Result<Token, LexerError> {
-        let start_pos = self.position;
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -325,16 +471,17 @@ impl Lexer {
         if directive_content.is_empty() {
             return Err(LexerError::InvalidCharacter {
                 ch: '*',
+                offset: start_pos,
                 line: start_line,
                 column: start_col,
             });
         }
 
-        let end_pos = self.position;
-        let span = Span::new(start_pos, end_pos, start_line, start_col);
+        let end_pos = self.offset;
+        let span = Span::new(start_pos, end_pos, start_line, start_col).with_end(self.line, self.column);
 
         Ok(Token::new(
-            TokenKind::Directive(directive_content.trim().to_string()),
+            TokenKind::Directive(directive_content),
             span,
         ))
     }
@@ -344,7 +491,7 @@ impl Lexer {
         let start = self.position;
         while !self.is_at_end() {
             let ch = self.current_char();
-            if ch.is_ascii_alphanumeric() || ch == '_' {
+            if self.is_identifier_char(ch) {
                 self.advance();
             } else {
                 break;
@@ -355,8 +502,9 @@ impl Lexer {
         lookup_keyword(&text).unwrap_or(TokenKind::Identifier(text))
     }
 
-    /// Scan number (integer literal, decimal or hex)
+    /// Scan number (integer literal, decimal or hex, or real literal)
     fn scan_number(&mut self) -> Result<TokenKind, LexerError> {
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -379,13 +527,14 @@ impl Lexer {
             if self.position == start {
                 return Err(LexerError::InvalidCharacter {
                     ch: 'x',
+                    offset: start_pos,
                     line: start_line,
                     column: start_col,
                 });
             }
 
             let hex_str: String = self.source[start..self.position].iter().collect();
-            let value = u16::from_str_radix(&hex_str, 16).unwrap_or(0);
+            let value = u32::from_str_radix(&hex_str, 16).unwrap_or(0);
             return Ok(TokenKind::IntegerLiteral {
                 value,
                 is_hex: true,
@@ -404,8 +553,33 @@ impl Lexer {
             }
         }
 
+        // A fraction or exponent makes it a real; `1..5` is a range
+        let mut is_real = false;
+        if !self.is_at_end() && self.current_char() == '.' && self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+            is_real = true;
+            self.advance();
+            while !self.is_at_end() && self.current_char().is_ascii_digit() {
+                self.advance();
+            }
+        }
+        if !self.is_at_end() && matches!(self.current_char(), 'e' | 'E') {
+            let sign = matches!(self.peek_char(), Some('+' | '-')) as usize;
+            if self.peek_char_at(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                is_real = true;
+                for _ in 0..=sign {
+                    self.advance();
+                }
+                while !self.is_at_end() && self.current_char().is_ascii_digit() {
+                    self.advance();
+                }
+            }
+        }
+        if is_real {
+            return Ok(TokenKind::RealLiteral(self.source[start..self.position].iter().collect()));
+        }
+
         let dec_str: String = self.source[start..self.position].iter().collect();
-        let value = dec_str.parse::<u16>().unwrap_or(0);
+        let value = dec_str.parse::<u32>().unwrap_or(0);
         Ok(TokenKind::IntegerLiteral {
             value,
             is_hex: false,
@@ -414,6 +588,7 @@ impl Lexer {
 
     /// Scan Pascal-style hex literal ($FF)
     fn scan_hex_dollar(&mut self) -> Result<TokenKind, LexerError> {
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -432,13 +607,14 @@ impl Lexer {
         if self.position == start {
             return Err(LexerError::InvalidCharacter {
                 ch: '$',
+                offset: start_pos,
                 line: start_line,
                 column: start_col,
             });
         }
 
         let hex_str: String = self.source[start..self.position].iter().collect();
-        let value = u16::from_str_radix(&hex_str, 16).unwrap_or(0);
+        let value = u32::from_str_radix(&hex_str, 16).unwrap_or(0);
         Ok(TokenKind::IntegerLiteral {
             value,
             is_hex: true,
@@ -447,6 +623,7 @@ impl Lexer {
 
     /// Scan character or string literal (single quotes)
     fn scan_char_or_string(&mut self) -> Result<TokenKind, LexerError> {
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -454,6 +631,7 @@ impl Lexer {
 
         if self.is_at_end() {
             return Err(LexerError::UnterminatedString {
+                offset: start_pos,
                 line: start_line,
                 column: start_col,
             });
@@ -479,13 +657,15 @@ impl Lexer {
                     break;
                 }
             } else if ch == '\\' {
-                // Escape sequence
+                // Escape sequence; errors point at the backslash
+                let (offset, line, column) = (self.offset, self.line, self.column);
                 self.advance(); // Skip backslash
-                let escaped = self.scan_escape_sequence(start_line, start_col)?;
+                let escaped = self.scan_escape_sequence(offset, line, column)?;
                 chars.push(escaped);
                 is_char = false; // Escape sequences = string
             } else if ch == '\n' || ch == '\r' {
                 return Err(LexerError::UnterminatedString {
+                    offset: start_pos,
                     line: start_line,
                     column: start_col,
                 });
@@ -500,12 +680,14 @@ impl Lexer {
 
         if self.is_at_end() && self.source[self.position - 1] != '\'' {
             return Err(LexerError::UnterminatedString {
+                offset: start_pos,
                 line: start_line,
                 column: start_col,
             });
         }
 
-        if is_char && chars.len() == 1 {
+        // Characters beyond Latin-1 do not fit a Char and become strings
+        if is_char && chars.len() == 1 && (chars[0] as u32) <= 0xFF {
             Ok(TokenKind::CharLiteral(chars[0] as u8))
         } else {
             Ok(TokenKind::StringLiteral(chars.iter().collect()))
@@ -514,6 +696,7 @@ impl Lexer {
 
     /// Scan string literal (double quotes)
     fn scan_string_double(&mut self) -> Result<TokenKind, LexerError> {
+        let start_pos = self.offset;
         let start_line = self.line;
         let start_col = self.column;
 
@@ -536,12 +719,14 @@ impl Lexer {
                     break;
                 }
             } else if ch == '\\' {
-                // Escape sequence
+                // Escape sequence; errors point at the backslash
+                let (offset, line, column) = (self.offset, self.line, self.column);
                 self.advance(); // Skip backslash
-                let escaped = self.scan_escape_sequence(start_line, start_col)?;
+                let escaped = self.scan_escape_sequence(offset, line, column)?;
                 chars.push(escaped);
             } else if ch == '\n' || ch == '\r' {
                 return Err(LexerError::UnterminatedString {
+                    offset: start_pos,
                     line: start_line,
                     column: start_col,
                 });
@@ -553,6 +738,7 @@ impl Lexer {
 
         if self.is_at_end() && self.source[self.position - 1] != '"' {
             return Err(LexerError::UnterminatedString {
+                offset: start_pos,
                 line: start_line,
                 column: start_col,
             });
@@ -561,15 +747,59 @@ impl Lexer {
         Ok(TokenKind::StringLiteral(chars.iter().collect()))
     }
 
-    /// Scan escape sequence
+    /// Scan a byte array literal: `$"` followed by groups of hex digit pairs
+    /// separated by whitespace, e.g. `$"00 FF 3A"` or `$"00FF3A"`
+    fn scan_byte_array(&mut self) -> Result<TokenKind, LexerError> {
+        let (start_pos, start_line, start_col) = (self.offset, self.line, self.column);
+        self.advance(); // Skip '$'
+        self.advance(); // Skip opening quote
+
+        let mut bytes = Vec::new();
+        loop {
+            if self.is_at_end() || matches!(self.current_char(), '\n' | '\r') {
+                return Err(LexerError::UnterminatedString {
+                    offset: start_pos,
+                    line: start_line,
+                    column: start_col,
+                });
+            }
+            let ch = self.current_char();
+            if ch == '"' {
+                self.advance(); // Skip closing quote
+                return Ok(TokenKind::ByteArrayLiteral(bytes));
+            }
+            if ch.is_whitespace() {
+                self.advance();
+                continue;
+            }
+
+            // One group runs up to whitespace or the closing quote
+            let (offset, line, column) = (self.offset, self.line, self.column);
+            let mut group = String::new();
+            while !self.is_at_end() && !self.current_char().is_whitespace() && self.current_char() != '"' {
+                group.push(self.current_char());
+                self.advance();
+            }
+            if !group.len().is_multiple_of(2) || !group.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(LexerError::InvalidByteGroup { group, offset, line, column });
+            }
+            for i in (0..group.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(&group[i..i + 2], 16).unwrap());
+            }
+        }
+    }
+
+    /// Scan the escape sequence after a backslash at `offset`, `line`, `column`
     fn scan_escape_sequence(
         &mut self,
+        offset: usize,
         line: usize,
         column: usize,
     ) -> Result<char, LexerError> {
         if self.is_at_end() {
             return Err(LexerError::InvalidEscape {
                 seq: "\\".to_string(),
+                offset,
                 line,
                 column,
             });
@@ -588,6 +818,7 @@ impl Lexer {
             '0' => Ok('\0'),
             _ => Err(LexerError::InvalidEscape {
                 seq: format!("\\{}", ch),
+                offset,
                 line,
                 column,
             }),
@@ -698,6 +929,7 @@ impl Lexer {
             _ => {
                 return Err(LexerError::InvalidCharacter {
                     ch,
+                    offset: self.offset,
                     line: self.line,
                     column: self.column,
                 });
@@ -912,6 +1144,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_integer_literals_beyond_16_bits() {
+        let mut lexer = Lexer::new("65536 4294967295 $FFFFFFFF");
+        let mut values = vec![];
+        for _ in 0..3 {
+            match lexer.next_token().unwrap().kind {
+                TokenKind::IntegerLiteral { value, .. } => values.push(value),
+                other => panic!("Expected integer literal, found {:?}", other),
+            }
+        }
+        assert_eq!(values, [65536, u32::MAX, u32::MAX]);
+    }
+
+    #[test]
+    fn test_real_literals() {
+        let mut lexer = Lexer::new("3.14 1e3 2.5E-2 1..5 7.x");
+        let mut kinds = vec![];
+        while let Ok(token) = lexer.next_token() {
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            kinds.push(token.kind);
+        }
+        let int = |value| TokenKind::IntegerLiteral { value, is_hex: false };
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::RealLiteral("3.14".to_string()),
+                TokenKind::RealLiteral("1e3".to_string()),
+                TokenKind::RealLiteral("2.5E-2".to_string()),
+                int(1),
+                TokenKind::DotDot,
+                int(5),
+                int(7),
+                TokenKind::Dot,
+                TokenKind::Identifier("x".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn test_hex_literals() {
         let mut lexer = Lexer::new("0xFF $FF");
@@ -964,6 +1236,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unicode_source() {
+        let source = "\u{FEFF}{ Grüße }\ns := 'héllo €';\nc := 'é'; x";
+        let mut lexer = Lexer::new(source);
+        let s = lexer.next_token().unwrap();
+        // The BOM is skipped; offsets are bytes, columns are characters
+        assert_eq!(s.span, Span::new(15, 16, 2, 1));
+        lexer.next_token().unwrap();
+        let literal = lexer.next_token().unwrap();
+        assert_eq!(literal.kind, TokenKind::StringLiteral("héllo €".to_string()));
+        assert_eq!((literal.span.column, literal.span.end_column), (6, 15));
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::CharLiteral(0xE9));
+        lexer.next_token().unwrap();
+        let x = lexer.next_token().unwrap();
+        assert_eq!(&source[x.span.start..x.span.end], "x");
+        assert_eq!(x.span.column, 11);
+    }
+
+    #[test]
+    fn test_identifier_policy() {
+        let mut lexer = Lexer::new("größe");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Identifier("gr".to_string()));
+        assert!(matches!(lexer.next_token(), Err(LexerError::InvalidCharacter { ch: 'ö', .. })));
+
+        let mut lexer = Lexer::new("größe := 1").with_identifier_policy(IdentifierPolicy::Unicode);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Identifier("größe".to_string()));
+        assert_eq!(lexer.next_token().unwrap().span.column, 7);
+    }
+
     #[test]
     fn test_string_literals() {
         let mut lexer = Lexer::new("'hello' \"world\"");
@@ -1126,6 +1430,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_comments_line() {
+        let mut lexer = Lexer::new("// This is a comment { not a brace\nprogram // to the end\nP / 2");
+        let kinds: Vec<TokenKind> = std::iter::from_fn(|| Some(lexer.next_token().unwrap().kind))
+            .take_while(|kind| *kind != TokenKind::Eof)
+            .collect();
+        assert_eq!(
+            kinds,
+            [TokenKind::KwProgram, TokenKind::Identifier("P".to_string()), TokenKind::Slash, TokenKind::IntegerLiteral { value: 2, is_hex: false }]
+        );
+    }
+
     #[test]
     fn test_comments_multiline() {
         let mut lexer = Lexer::new("{ This is a\nmulti-line\ncomment } program");
@@ -1135,6 +1451,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_directive_after_comment() {
+        let mut lexer = Lexer::new("{ comment }\n{$DEFINE A} (* more *)(*$R+*) program");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Directive("DEFINE A".to_string()));
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Directive("R+".to_string()));
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::KwProgram);
+    }
+
     #[test]
     fn test_comments_nested_curly() {
         // Note: Pascal doesn't support nested comments - first } closes the comment
@@ -1512,6 +1836,29 @@ end.";
         }
     }
 
+    #[test]
+    fn test_error_invalid_escape_span() {
+        let mut lexer = Lexer::new("x := 'ab\\q';");
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        let error = lexer.next_token().unwrap_err();
+        assert_eq!(error.span(), Span::new(8, 10, 1, 9));
+    }
+
+    #[test]
+    fn test_byte_array_literal() {
+        let mut lexer = Lexer::new("$\"00 FF 3a\" $\"DEADbeef\" $\"\"");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::ByteArrayLiteral(vec![0x00, 0xFF, 0x3A]));
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::ByteArrayLiteral(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::ByteArrayLiteral(vec![]));
+
+        let error = Lexer::new("$\"00 F 3A\"").next_token().unwrap_err();
+        assert_eq!(error.span(), Span::new(5, 6, 1, 6));
+        let error = Lexer::new("$\"00 GG\"").next_token().unwrap_err();
+        assert!(matches!(error, LexerError::InvalidByteGroup { ref group, .. } if group == "GG"));
+        assert!(matches!(Lexer::new("$\"00\n").next_token(), Err(LexerError::UnterminatedString { .. })));
+    }
+
     #[test]
     fn test_error_invalid_hex_dollar() {
         let mut lexer = Lexer::new("$");
@@ -1537,6 +1884,18 @@ end.";
         assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
     }
 
+    #[test]
+    fn test_asm_block_is_captured_raw() {
+        let mut lexer = Lexer::new("asm\n  ex af, af' ; not the end\n  ld a, ';'\nEnd;");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::KwAsm);
+        assert_eq!(
+            lexer.next_token().unwrap().kind,
+            TokenKind::AsmText("\n  ex af, af' ; not the end\n  ld a, ';'\n".to_string())
+        );
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::KwEnd);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Semicolon);
+    }
+
     #[test]
     fn test_peek_token() {
         let mut lexer = Lexer::new("program begin");
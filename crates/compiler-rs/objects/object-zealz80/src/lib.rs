@@ -7,11 +7,19 @@
 //! # Format Overview
 //!
 //! .ZOF files contain:
-//! - **Header**: Magic number, version, flags
+//! - **Header**: Magic number, version, unit name
 //! - **Sections**: CODE, DATA, BSS
 //! - **Symbol Table**: Exported and imported symbols
 //! - **Relocation Entries**: Address fixups for linking
-//! - **Debug Info**: Source locations (optional)
+//! - **Init/Fini**: Unit initialization and finalization addresses
+//! - **Absolutes**: Symbols at a fixed address, such as `absolute` variables
+//!
+//! A mergeable symbol marks bytes several objects may hold alike, such as
+//! a generic instantiated by more than one unit: the linker keeps the first
+//! copy and drops the others (see [`linker::merge`]).
+//!
+//! Every field is little-endian, whatever the byte order of the host that
+//! wrote or reads the file.
 //!
 //! # Incremental Compilation
 //!
@@ -20,11 +28,20 @@
 //! - Link pre-compiled object files
 //! - Checksum-based dependency tracking
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+
+pub mod linker;
+pub mod map;
+pub mod output;
+pub mod zealos;
 
 /// ZOF file magic number: "ZOF\0" (Zeal Object File)
 pub const ZOF_MAGIC: &[u8] = b"ZOF\0";
-pub const ZOF_VERSION: u16 = 1;
+/// Format version written; 3 widened symbol and relocation name lengths
+/// to 16 bits, 4 added mergeable symbols, 5 absolute symbols
+pub const ZOF_VERSION: u16 = 5;
+/// Oldest format version still read
+pub const ZOF_MIN_VERSION: u16 = 2;
 
 /// Object file sections
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -54,6 +71,50 @@ impl Section {
             _ => None,
         }
     }
+
+    /// Section named `name` (`code`, `data` or `bss`, ignoring case)
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Section::Code, Section::Data, Section::Bss]
+            .into_iter()
+            .find(|section| section.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// What `ObjectFile::dump_with` lists
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DumpOptions {
+    /// Follow the listing with a hex dump of the CODE and DATA contents
+    pub hex: bool,
+    /// Only this section's size, symbols, relocations and contents
+    pub section: Option<Section>,
+    /// Only symbols whose name contains this text, ignoring case, and the
+    /// relocations that refer to them
+    pub symbol: Option<String>,
+}
+
+/// Hex dump of `bytes`, 16 to a line with their offset and the printable
+/// ones as ASCII:
+///
+/// ```text
+///   0000  CD 00 00 C9 48 69 00 00  00 00 00 00 00 00 00 00  |....Hi..........|
+/// ```
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02X} ", byte));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        out.push_str(&format!("  {:04X}  {:<49} |{}|\n", line * 16, hex, ascii));
+    }
+    out
 }
 
 /// Symbol type
@@ -71,6 +132,18 @@ pub enum SymbolType {
     External,
 }
 
+impl SymbolType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolType::Function => "function",
+            SymbolType::Variable => "variable",
+            SymbolType::Constant => "constant",
+            SymbolType::Type => "type",
+            SymbolType::External => "external",
+        }
+    }
+}
+
 /// Symbol visibility
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymbolVisibility {
@@ -78,6 +151,19 @@ pub enum SymbolVisibility {
     Public,
     /// Private (internal)
     Private,
+    /// Public, covering `size` bytes that other objects may define alike;
+    /// one copy is linked
+    Mergeable,
+}
+
+impl SymbolVisibility {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolVisibility::Public => "public",
+            SymbolVisibility::Private => "private",
+            SymbolVisibility::Mergeable => "merge",
+        }
+    }
 }
 
 /// Symbol entry in symbol table
@@ -89,6 +175,7 @@ pub struct Symbol {
     pub section: Section,
     pub offset: u16, // Offset within section
     pub size: u16,   // Size in bytes
+    pub alignment: u16, // Required address multiple when linked (0 or 1 = none)
 }
 
 /// Relocation type
@@ -106,6 +193,18 @@ pub enum RelocationType {
     LowByte,
 }
 
+impl RelocationType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RelocationType::Absolute16 => "abs16",
+            RelocationType::Relative8 => "rel8",
+            RelocationType::Relative16 => "rel16",
+            RelocationType::HighByte => "high",
+            RelocationType::LowByte => "low",
+        }
+    }
+}
+
 /// Relocation entry
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Relocation {
@@ -135,6 +234,9 @@ pub struct ObjectFile {
     pub init_address: Option<u16>,
     /// Unit finalization address (if any)
     pub fini_address: Option<u16>,
+    /// Public symbols at a fixed address, such as `absolute` variables;
+    /// they take no room in any section
+    pub absolutes: Vec<(String, u16)>,
 }
 
 impl ObjectFile {
@@ -149,6 +251,7 @@ impl ObjectFile {
             relocations: vec![],
             init_address: None,
             fini_address: None,
+            absolutes: vec![],
         }
     }
 
@@ -177,144 +280,220 @@ impl ObjectFile {
         self.relocations.push(relocation);
     }
 
+    /// Listing of the sections, symbols and relocations, for debugging link
+    /// problems (`spc objdump`)
+    pub fn dump(&self) -> String {
+        self.dump_with(&DumpOptions::default())
+    }
+
+    /// Listing restricted to a section or to matching symbols, optionally
+    /// followed by a hex dump of the section contents
+    pub fn dump_with(&self, options: &DumpOptions) -> String {
+        let in_section = |section: Section| options.section.is_none_or(|only| only == section);
+        let symbol_matches = |name: &str| {
+            options
+                .symbol
+                .as_ref()
+                .is_none_or(|pattern| name.to_lowercase().contains(&pattern.to_lowercase()))
+        };
+
+        let mut out = format!("Unit {}\n\nSections:\n", self.unit_name);
+        for (section, size) in [
+            (Section::Code, self.code.len()),
+            (Section::Data, self.data.len()),
+            (Section::Bss, self.bss_size as usize),
+        ] {
+            if in_section(section) {
+                out.push_str(&format!("  {:<6}{:>6} bytes\n", section.name(), size));
+            }
+        }
+
+        let symbols: Vec<&Symbol> = self
+            .symbols
+            .iter()
+            .filter(|symbol| (symbol.symbol_type == SymbolType::External || in_section(symbol.section)) && symbol_matches(&symbol.name))
+            .collect();
+        out.push_str(&format!("\nSymbols ({}):\n", Self::count(symbols.len(), self.symbols.len())));
+        for symbol in symbols {
+            let location = if symbol.symbol_type == SymbolType::External {
+                "-".to_string()
+            } else {
+                format!("{}:{:04X}", symbol.section.name(), symbol.offset)
+            };
+            out.push_str(&format!(
+                "  {:<11}{:>6}  {:<8}{:<9}{}",
+                location,
+                symbol.size,
+                symbol.visibility.name(),
+                symbol.symbol_type.name(),
+                symbol.name
+            ));
+            if symbol.alignment > 1 {
+                out.push_str(&format!(" (align {})", symbol.alignment));
+            }
+            out.push('\n');
+        }
+        // Absolute symbols belong to no section
+        if options.section.is_none() {
+            let absolutes = self.absolutes.iter().filter(|(name, _)| symbol_matches(name));
+            if absolutes.clone().next().is_some() {
+                out.push_str(&format!("\nAbsolutes ({}):\n", Self::count(absolutes.clone().count(), self.absolutes.len())));
+            }
+            for (name, address) in absolutes {
+                out.push_str(&format!("  ${:04X}  {}\n", address, name));
+            }
+        }
+
+        let relocations: Vec<&Relocation> = self
+            .relocations
+            .iter()
+            .filter(|relocation| in_section(relocation.section) && symbol_matches(&relocation.symbol_name))
+            .collect();
+        out.push_str(&format!("\nRelocations ({}):\n", Self::count(relocations.len(), self.relocations.len())));
+        for relocation in relocations {
+            let addend = match relocation.addend {
+                0 => String::new(),
+                addend => format!("{:+}", addend),
+            };
+            out.push_str(&format!(
+                "  {}:{:04X}  {:<6}{}{}\n",
+                relocation.section.name(),
+                relocation.offset,
+                relocation.relocation_type.name(),
+                relocation.symbol_name,
+                addend
+            ));
+        }
+
+        if in_section(Section::Code) {
+            for (label, address) in [("Init", self.init_address), ("Fini", self.fini_address)] {
+                if let Some(address) = address {
+                    out.push_str(&format!("{} ${:04X}\n", label, address));
+                }
+            }
+        }
+
+        if options.hex {
+            for section in [Section::Code, Section::Data] {
+                if let Some(bytes) = self.section_bytes(section).filter(|_| in_section(section)) {
+                    out.push_str(&format!("\nContents of {}:\n{}", section.name(), hex_dump(bytes)));
+                }
+            }
+        }
+        out
+    }
+
+    /// `shown`, or `shown of total` when a filter left some out
+    fn count(shown: usize, total: usize) -> String {
+        if shown == total {
+            shown.to_string()
+        } else {
+            format!("{} of {}", shown, total)
+        }
+    }
+
+    /// Bytes a section takes when linked
+    pub fn section_size(&self, section: Section) -> usize {
+        match section {
+            Section::Code => self.code.len(),
+            Section::Data => self.data.len(),
+            Section::Bss => self.bss_size as usize,
+        }
+    }
+
+    /// Contents of a section; BSS has none, only a size
+    pub fn section_bytes(&self, section: Section) -> Option<&[u8]> {
+        match section {
+            Section::Code => Some(&self.code),
+            Section::Data => Some(&self.data),
+            Section::Bss => None,
+        }
+    }
+
     /// Write object file to binary format
-    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        // Write header
+    ///
+    /// Fails rather than truncate a name, section or table too large for
+    /// its length field.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_all(ZOF_MAGIC)?;
-        writer.write_all(&ZOF_VERSION.to_le_bytes())?;
-
-        // Write unit name (length-prefixed string)
-        let unit_name_bytes = self.unit_name.as_bytes();
-        writer.write_all(&(unit_name_bytes.len() as u16).to_le_bytes())?;
-        writer.write_all(unit_name_bytes)?;
+        write_u16(writer, ZOF_VERSION)?;
+        write_string(writer, &self.unit_name)?;
 
-        // Write sections
-        // CODE section
-        writer.write_all(&(self.code.len() as u32).to_le_bytes())?;
+        // CODE and DATA with their bytes, BSS by size only
+        write_section_len(writer, self.code.len())?;
         writer.write_all(&self.code)?;
-
-        // DATA section
-        writer.write_all(&(self.data.len() as u32).to_le_bytes())?;
+        write_section_len(writer, self.data.len())?;
         writer.write_all(&self.data)?;
+        write_u16(writer, self.bss_size)?;
 
-        // BSS section (size only, no data)
-        writer.write_all(&self.bss_size.to_le_bytes())?;
-
-        // Write symbol table
-        writer.write_all(&(self.symbols.len() as u16).to_le_bytes())?;
+        write_count(writer, self.symbols.len())?;
         for symbol in &self.symbols {
-            self.write_symbol(writer, symbol)?;
+            write_symbol(writer, symbol)?;
         }
-
-        // Write relocation entries
-        writer.write_all(&(self.relocations.len() as u16).to_le_bytes())?;
+        write_count(writer, self.relocations.len())?;
         for relocation in &self.relocations {
-            self.write_relocation(writer, relocation)?;
+            write_relocation(writer, relocation)?;
         }
 
-        // Write init/fini addresses
-        writer.write_all(&[self.init_address.is_some() as u8])?;
-        if let Some(addr) = self.init_address {
-            writer.write_all(&addr.to_le_bytes())?;
-        }
-        writer.write_all(&[self.fini_address.is_some() as u8])?;
-        if let Some(addr) = self.fini_address {
-            writer.write_all(&addr.to_le_bytes())?;
+        for address in [self.init_address, self.fini_address] {
+            write_u8(writer, address.is_some() as u8)?;
+            if let Some(address) = address {
+                write_u16(writer, address)?;
+            }
         }
 
+        write_count(writer, self.absolutes.len())?;
+        for (name, address) in &self.absolutes {
+            write_string(writer, name)?;
+            write_u16(writer, *address)?;
+        }
         Ok(())
     }
 
-    /// Read object file from binary format
-    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
-        // Read and verify magic
+    /// Read object file from binary format, of this or an older version
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
         let mut magic = [0u8; 4];
         reader.read_exact(&mut magic)?;
-        if &magic != ZOF_MAGIC {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid ZOF magic number",
-            ));
+        if magic != ZOF_MAGIC {
+            return Err(invalid("Invalid ZOF magic number".to_string()));
         }
-
-        // Read version
-        let mut version_bytes = [0u8; 2];
-        reader.read_exact(&mut version_bytes)?;
-        let version = u16::from_le_bytes(version_bytes);
-        if version != ZOF_VERSION {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Unsupported,
-                format!("Unsupported ZOF version: {}", version),
+        let version = read_u16(reader)?;
+        if !(ZOF_MIN_VERSION..=ZOF_VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Unsupported ZOF version: {} (this compiler reads versions {} to {})",
+                    version, ZOF_MIN_VERSION, ZOF_VERSION
+                ),
             ));
         }
-
-        // Read unit name
-        let mut name_len_bytes = [0u8; 2];
-        reader.read_exact(&mut name_len_bytes)?;
-        let name_len = u16::from_le_bytes(name_len_bytes) as usize;
-        let mut name_bytes = vec![0u8; name_len];
-        reader.read_exact(&mut name_bytes)?;
-        let unit_name = String::from_utf8(name_bytes)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        // Read CODE section
-        let mut code_len_bytes = [0u8; 4];
-        reader.read_exact(&mut code_len_bytes)?;
-        let code_len = u32::from_le_bytes(code_len_bytes) as usize;
-        let mut code = vec![0u8; code_len];
-        reader.read_exact(&mut code)?;
-
-        // Read DATA section
-        let mut data_len_bytes = [0u8; 4];
-        reader.read_exact(&mut data_len_bytes)?;
-        let data_len = u32::from_le_bytes(data_len_bytes) as usize;
-        let mut data = vec![0u8; data_len];
-        reader.read_exact(&mut data)?;
-
-        // Read BSS size
-        let mut bss_size_bytes = [0u8; 2];
-        reader.read_exact(&mut bss_size_bytes)?;
-        let bss_size = u16::from_le_bytes(bss_size_bytes);
-
-        // Read symbol table
-        let mut symbol_count_bytes = [0u8; 2];
-        reader.read_exact(&mut symbol_count_bytes)?;
-        let symbol_count = u16::from_le_bytes(symbol_count_bytes);
-        let mut symbols = Vec::with_capacity(symbol_count as usize);
-        for _ in 0..symbol_count {
-            symbols.push(Self::read_symbol(reader)?);
+        let unit_name = read_string(reader)?;
+        let code = read_section(reader)?;
+        let data = read_section(reader)?;
+        let bss_size = read_u16(reader)?;
+
+        let symbols = (0..read_u16(reader)?)
+            .map(|_| read_symbol(reader, version))
+            .collect::<io::Result<Vec<_>>>()?;
+        let relocations = (0..read_u16(reader)?)
+            .map(|_| read_relocation(reader, version))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut addresses = [None, None];
+        for address in &mut addresses {
+            if read_flag(reader)? {
+                *address = Some(read_u16(reader)?);
+            }
         }
+        let [init_address, fini_address] = addresses;
 
-        // Read relocation entries
-        let mut reloc_count_bytes = [0u8; 2];
-        reader.read_exact(&mut reloc_count_bytes)?;
-        let reloc_count = u16::from_le_bytes(reloc_count_bytes);
-        let mut relocations = Vec::with_capacity(reloc_count as usize);
-        for _ in 0..reloc_count {
-            relocations.push(Self::read_relocation(reader)?);
+        let mut absolutes = vec![];
+        if version >= 5 {
+            for _ in 0..read_u16(reader)? {
+                absolutes.push((read_string(reader)?, read_u16(reader)?));
+            }
         }
 
-        // Read init/fini addresses
-        let mut has_init = [0u8; 1];
-        reader.read_exact(&mut has_init)?;
-        let init_address = if has_init[0] != 0 {
-            let mut addr_bytes = [0u8; 2];
-            reader.read_exact(&mut addr_bytes)?;
-            Some(u16::from_le_bytes(addr_bytes))
-        } else {
-            None
-        };
-
-        let mut has_fini = [0u8; 1];
-        reader.read_exact(&mut has_fini)?;
-        let fini_address = if has_fini[0] != 0 {
-            let mut addr_bytes = [0u8; 2];
-            reader.read_exact(&mut addr_bytes)?;
-            Some(u16::from_le_bytes(addr_bytes))
-        } else {
-            None
-        };
-
         Ok(Self {
             unit_name,
             code,
@@ -324,135 +503,175 @@ impl ObjectFile {
             relocations,
             init_address,
             fini_address,
+            absolutes,
         })
     }
+}
 
-    fn write_symbol<W: Write>(&self, _writer: &mut W, symbol: &Symbol) -> std::io::Result<()> {
-        // Name (length-prefixed)
-        let name_bytes = symbol.name.as_bytes();
-        _writer.write_all(&[name_bytes.len() as u8])?;
-        _writer.write_all(name_bytes)?;
+// Fields are little-endian whatever the host's byte order, and strings are a
+// u16 length followed by UTF-8 bytes
 
-        // Type, visibility, section (packed into 1 byte)
-        let section_val: u8 = symbol.section as u8;
-        let flags = (symbol.symbol_type as u8) | ((symbol.visibility as u8) << 4) | (section_val << 5);
-        _writer.write_all(&[flags])?;
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
 
-        // Offset, size
-        _writer.write_all(&symbol.offset.to_le_bytes())?;
-        _writer.write_all(&symbol.size.to_le_bytes())?;
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
 
-        Ok(())
-    }
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
 
-    fn read_symbol<R: Read>(reader: &mut R) -> std::io::Result<Symbol> {
-        // Name
-        let mut name_len = [0u8; 1];
-        reader.read_exact(&mut name_len)?;
-        let mut name_bytes = vec![0u8; name_len[0] as usize];
-        reader.read_exact(&mut name_bytes)?;
-        let name = String::from_utf8(name_bytes)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        // Flags
-        let mut flags = [0u8; 1];
-        reader.read_exact(&mut flags)?;
-        let symbol_type = match flags[0] & 0x0F {
-            0 => SymbolType::Function,
-            1 => SymbolType::Variable,
-            2 => SymbolType::Constant,
-            3 => SymbolType::Type,
-            4 => SymbolType::External,
-            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid symbol type")),
-        };
-        let visibility = if (flags[0] & 0x10) != 0 {
-            SymbolVisibility::Public
-        } else {
-            SymbolVisibility::Private
-        };
-        let section = Section::from_u8((flags[0] >> 5) & 0x03)
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid section"))?;
+fn write_count<W: Write>(writer: &mut W, count: usize) -> io::Result<()> {
+    let count = u16::try_from(count).map_err(|_| invalid(format!("Too many entries to store: {}", count)))?;
+    write_u16(writer, count)
+}
 
-        // Offset, size
-        let mut offset_bytes = [0u8; 2];
-        reader.read_exact(&mut offset_bytes)?;
-        let offset = u16::from_le_bytes(offset_bytes);
+fn write_section_len<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len).map_err(|_| invalid(format!("Section too large to store: {} bytes", len)))?;
+    writer.write_all(&len.to_le_bytes())
+}
 
-        let mut size_bytes = [0u8; 2];
-        reader.read_exact(&mut size_bytes)?;
-        let size = u16::from_le_bytes(size_bytes);
+fn write_string<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    let len = u16::try_from(text.len()).map_err(|_| invalid(format!("Name too long to store: {} bytes", text.len())))?;
+    write_u16(writer, len)?;
+    writer.write_all(text.as_bytes())
+}
 
-        Ok(Symbol {
-            name,
-            symbol_type,
-            visibility,
-            section,
-            offset,
-            size,
-        })
-    }
+/// Name, then type, visibility and section packed in one byte (bit 4 for
+/// private, bit 7 for mergeable), then offset, size and alignment
+fn write_symbol<W: Write>(writer: &mut W, symbol: &Symbol) -> io::Result<()> {
+    write_string(writer, &symbol.name)?;
+    let visibility = match symbol.visibility {
+        SymbolVisibility::Public => 0,
+        SymbolVisibility::Private => 0x10,
+        SymbolVisibility::Mergeable => 0x80,
+    };
+    let flags = (symbol.symbol_type as u8) | visibility | ((symbol.section as u8) << 5);
+    write_u8(writer, flags)?;
+    write_u16(writer, symbol.offset)?;
+    write_u16(writer, symbol.size)?;
+    write_u16(writer, symbol.alignment)
+}
 
-    fn write_relocation<W: Write>(&self, _writer: &mut W, reloc: &Relocation) -> std::io::Result<()> {
-        // Section, type (packed)
-        let section_val: u8 = reloc.section as u8;
-        let flags = section_val | ((reloc.relocation_type as u8) << 2);
-        _writer.write_all(&[flags])?;
+/// Section and type packed in one byte, then offset, symbol name and addend
+fn write_relocation<W: Write>(writer: &mut W, relocation: &Relocation) -> io::Result<()> {
+    write_u8(writer, (relocation.section as u8) | ((relocation.relocation_type as u8) << 2))?;
+    write_u16(writer, relocation.offset)?;
+    write_string(writer, &relocation.symbol_name)?;
+    writer.write_all(&relocation.addend.to_le_bytes())
+}
 
-        // Offset
-        _writer.write_all(&reloc.offset.to_le_bytes())?;
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
 
-        // Symbol name (length-prefixed)
-        let name_bytes = reloc.symbol_name.as_bytes();
-        _writer.write_all(&[name_bytes.len() as u8])?;
-        _writer.write_all(name_bytes)?;
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
 
-        // Addend
-        _writer.write_all(&reloc.addend.to_le_bytes())?;
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
 
-        Ok(())
+fn read_flag<R: Read>(reader: &mut R) -> io::Result<bool> {
+    match read_u8(reader)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        flag => Err(invalid(format!("Invalid flag: {}", flag))),
     }
+}
 
-    fn read_relocation<R: Read>(reader: &mut R) -> std::io::Result<Relocation> {
-        // Flags
-        let mut flags = [0u8; 1];
-        reader.read_exact(&mut flags)?;
-        let section = Section::from_u8(flags[0] & 0x03)
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid section"))?;
-        let relocation_type = match (flags[0] >> 2) & 0x07 {
-            0 => RelocationType::Absolute16,
-            1 => RelocationType::Relative8,
-            2 => RelocationType::Relative16,
-            3 => RelocationType::HighByte,
-            4 => RelocationType::LowByte,
-            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid relocation type")),
-        };
+/// `len` bytes, read in chunks so a corrupt length fails at the end of the
+/// file instead of allocating it all up front
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Object file ends inside a section"));
+    }
+    Ok(bytes)
+}
 
-        // Offset
-        let mut offset_bytes = [0u8; 2];
-        reader.read_exact(&mut offset_bytes)?;
-        let offset = u16::from_le_bytes(offset_bytes);
-
-        // Symbol name
-        let mut name_len = [0u8; 1];
-        reader.read_exact(&mut name_len)?;
-        let mut name_bytes = vec![0u8; name_len[0] as usize];
-        reader.read_exact(&mut name_bytes)?;
-        let symbol_name = String::from_utf8(name_bytes)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        // Addend
-        let mut addend_bytes = [0u8; 2];
-        reader.read_exact(&mut addend_bytes)?;
-        let addend = i16::from_le_bytes(addend_bytes);
-
-        Ok(Relocation {
-            section,
-            offset,
-            relocation_type,
-            symbol_name,
-            addend,
-        })
+fn read_section<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)? as usize;
+    read_bytes(reader, len)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = read_u16(reader)? as usize;
+    let bytes = read_bytes(reader, len)?;
+    String::from_utf8(bytes).map_err(|_| invalid("Invalid UTF-8 in name".to_string()))
+}
+
+/// A symbol or relocation name; version 2 gave these a one-byte length
+fn read_name<R: Read>(reader: &mut R, version: u16) -> io::Result<String> {
+    if version >= 3 {
+        return read_string(reader);
     }
+    let len = read_u8(reader)? as usize;
+    let bytes = read_bytes(reader, len)?;
+    String::from_utf8(bytes).map_err(|_| invalid("Invalid UTF-8 in name".to_string()))
+}
+
+fn read_symbol<R: Read>(reader: &mut R, version: u16) -> io::Result<Symbol> {
+    let name = read_name(reader, version)?;
+    let flags = read_u8(reader)?;
+    let symbol_type = match flags & 0x0F {
+        0 => SymbolType::Function,
+        1 => SymbolType::Variable,
+        2 => SymbolType::Constant,
+        3 => SymbolType::Type,
+        4 => SymbolType::External,
+        _ => return Err(invalid("Invalid symbol type".to_string())),
+    };
+    let visibility = match flags & 0x90 {
+        0x00 => SymbolVisibility::Public,
+        0x10 => SymbolVisibility::Private,
+        0x80 if version >= 4 => SymbolVisibility::Mergeable,
+        _ => return Err(invalid("Invalid symbol visibility".to_string())),
+    };
+    let section = Section::from_u8((flags >> 5) & 0x03).ok_or_else(|| invalid("Invalid section".to_string()))?;
+    Ok(Symbol {
+        name,
+        symbol_type,
+        visibility,
+        section,
+        offset: read_u16(reader)?,
+        size: read_u16(reader)?,
+        alignment: read_u16(reader)?,
+    })
+}
+
+fn read_relocation<R: Read>(reader: &mut R, version: u16) -> io::Result<Relocation> {
+    let flags = read_u8(reader)?;
+    let section = Section::from_u8(flags & 0x03).ok_or_else(|| invalid("Invalid section".to_string()))?;
+    let relocation_type = match (flags >> 2) & 0x07 {
+        0 => RelocationType::Absolute16,
+        1 => RelocationType::Relative8,
+        2 => RelocationType::Relative16,
+        3 => RelocationType::HighByte,
+        4 => RelocationType::LowByte,
+        _ => return Err(invalid("Invalid relocation type".to_string())),
+    };
+    let offset = read_u16(reader)?;
+    let symbol_name = read_name(reader, version)?;
+    let mut addend = [0u8; 2];
+    reader.read_exact(&mut addend)?;
+    Ok(Relocation {
+        section,
+        offset,
+        relocation_type,
+        symbol_name,
+        addend: i16::from_le_bytes(addend),
+    })
 }
 
 #[cfg(test)]
@@ -485,6 +704,7 @@ mod tests {
             section: Section::Code,
             offset: 0,
             size: 3,
+            alignment: 257,
         });
 
         obj.add_relocation(Relocation {
@@ -507,10 +727,154 @@ mod tests {
         assert_eq!(obj.code, obj2.code);
         assert_eq!(obj.data, obj2.data);
         assert_eq!(obj.bss_size, obj2.bss_size);
-        assert_eq!(obj.symbols.len(), obj2.symbols.len());
+        assert_eq!(obj.symbols, obj2.symbols);
         assert_eq!(obj.relocations.len(), obj2.relocations.len());
     }
 
+    #[test]
+    fn test_object_file_versions() {
+        let mut obj = ObjectFile::new("Long".to_string());
+        obj.add_symbol(Symbol {
+            name: "X".repeat(300),
+            symbol_type: SymbolType::External,
+            visibility: SymbolVisibility::Public,
+            section: Section::Code,
+            offset: 0,
+            size: 0,
+            alignment: 0,
+        });
+        obj.add_code(&[0xC9]);
+        obj.add_symbol(Symbol {
+            name: "Max_of_Integer".to_string(),
+            symbol_type: SymbolType::Function,
+            visibility: SymbolVisibility::Mergeable,
+            section: Section::Code,
+            offset: 0,
+            size: 1,
+            alignment: 0,
+        });
+        obj.absolutes.push(("ScreenPixels".to_string(), 0x4000));
+        let mut buffer = Vec::new();
+        obj.write(&mut buffer).unwrap();
+        assert_eq!(&buffer[4..6], &[5, 0]);
+        let read = ObjectFile::read(&mut buffer.as_slice()).unwrap();
+        assert_eq!(read.symbols[0].name.len(), 300);
+        assert_eq!(read.symbols[1], obj.symbols[1]);
+        assert_eq!(read.absolutes, obj.absolutes);
+
+        // Version 2: one-byte name lengths
+        let mut v2 = b"ZOF\0\x02\x00\x01\x00M".to_vec();
+        v2.extend([1, 0, 0, 0, 0xC9]); // CODE: ret
+        v2.extend([0, 0, 0, 0, 0, 0]); // no DATA or BSS
+        v2.extend([1, 0, 3, b'E', b'x', b't', 0x04, 0, 0, 0, 0, 0, 0]); // external Ext
+        v2.extend([1, 0, 0x00, 1, 0, 3, b'E', b'x', b't', 0, 0]); // abs16 at 1
+        v2.extend([1, 0, 0, 0]); // init at 0
+        let old = ObjectFile::read(&mut v2.as_slice()).unwrap();
+        assert_eq!((old.unit_name.as_str(), old.code.as_slice()), ("M", &[0xC9][..]));
+        assert_eq!(old.symbols[0].name, "Ext");
+        assert_eq!(old.relocations[0].symbol_name, "Ext");
+        assert_eq!(old.init_address, Some(0));
+        assert!(old.absolutes.is_empty());
+
+        let newer = b"ZOF\0\x63\x00".to_vec();
+        let error = ObjectFile::read(&mut newer.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported ZOF version: 99 (this compiler reads versions 2 to 5)");
+        let truncated = &buffer[..buffer.len() - 1];
+        assert!(ObjectFile::read(&mut &truncated[..]).is_err());
+    }
+
+    #[test]
+    fn test_dump_lists_sections_symbols_and_relocations() {
+        let mut obj = ObjectFile::new("Main".to_string());
+        obj.add_code(&[0xCD, 0, 0, 0xC9]);
+        obj.set_bss_size(2);
+        obj.add_symbol(Symbol {
+            name: "Main".to_string(),
+            symbol_type: SymbolType::Function,
+            visibility: SymbolVisibility::Public,
+            section: Section::Code,
+            offset: 0,
+            size: 4,
+            alignment: 0,
+        });
+        obj.add_symbol(Symbol {
+            name: "Table".to_string(),
+            symbol_type: SymbolType::Variable,
+            visibility: SymbolVisibility::Private,
+            section: Section::Bss,
+            offset: 0,
+            size: 2,
+            alignment: 256,
+        });
+        obj.add_relocation(Relocation {
+            section: Section::Code,
+            offset: 1,
+            relocation_type: RelocationType::Absolute16,
+            symbol_name: "Helper".to_string(),
+            addend: -2,
+        });
+        obj.absolutes.push(("Border".to_string(), 0x5C48));
+
+        assert_eq!(
+            obj.dump(),
+            "Unit Main\n\n\
+             Sections:\n  CODE       4 bytes\n  DATA       0 bytes\n  BSS        2 bytes\n\n\
+             Symbols (2):\n\
+             \x20 CODE:0000       4  public  function Main\n\
+             \x20 BSS:0000        2  private variable Table (align 256)\n\n\
+             Absolutes (1):\n  $5C48  Border\n\n\
+             Relocations (1):\n  CODE:0001  abs16 Helper-2\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_filters_and_hex() {
+        let mut obj = ObjectFile::new("Main".to_string());
+        obj.add_code(&[0xCD, 0, 0, 0xC9]);
+        obj.add_data(b"Hi, Z80 world!\0\x01\x02");
+        let symbol = |name: &str, section, symbol_type| Symbol {
+            name: name.to_string(),
+            symbol_type,
+            visibility: SymbolVisibility::Public,
+            section,
+            offset: 0,
+            size: 0,
+            alignment: 0,
+        };
+        obj.add_symbol(symbol("Main", Section::Code, SymbolType::Function));
+        obj.add_symbol(symbol("Greeting", Section::Data, SymbolType::Constant));
+        obj.add_symbol(symbol("PrintString", Section::Code, SymbolType::External));
+        obj.add_relocation(Relocation {
+            section: Section::Code,
+            offset: 1,
+            relocation_type: RelocationType::Absolute16,
+            symbol_name: "PrintString".to_string(),
+            addend: 0,
+        });
+
+        let data_only = obj.dump_with(&DumpOptions { section: Some(Section::Data), hex: true, ..Default::default() });
+        assert_eq!(
+            data_only,
+            "Unit Main\n\n\
+             Sections:\n  DATA      17 bytes\n\n\
+             Symbols (2 of 3):\n\
+             \x20 DATA:0000       0  public  constant Greeting\n\
+             \x20 -               0  public  external PrintString\n\n\
+             Relocations (0 of 1):\n\n\
+             Contents of DATA:\n\
+             \x20 0000  48 69 2C 20 5A 38 30 20  77 6F 72 6C 64 21 00 01  |Hi, Z80 world!..|\n\
+             \x20 0010  02                                                |.|\n"
+        );
+
+        let print = obj.dump_with(&DumpOptions { symbol: Some("print".to_string()), ..Default::default() });
+        assert!(print.contains("Symbols (1 of 3):\n  -               0  public  external PrintString\n"), "{}", print);
+        assert!(print.contains("Relocations (1):\n  CODE:0001  abs16 PrintString\n"), "{}", print);
+        assert!(!print.contains("Contents of"));
+        assert_eq!(Section::from_name("code"), Some(Section::Code));
+        assert_eq!(Section::from_name("text"), None);
+        assert_eq!(obj.section_bytes(Section::Bss), None);
+    }
+
     #[test]
     fn test_symbol_table() {
         let mut obj = ObjectFile::new("TestUnit".to_string());
@@ -521,6 +885,7 @@ mod tests {
             section: Section::Code,
             offset: 0,
             size: 10,
+            alignment: 0,
         });
         obj.add_symbol(Symbol {
             name: "PrivateVar".to_string(),
@@ -529,6 +894,7 @@ mod tests {
             section: Section::Data,
             offset: 0,
             size: 2,
+            alignment: 0,
         });
 
         assert_eq!(obj.symbols.len(), 2);
@@ -0,0 +1,511 @@
+//! Linker: combines object files into one Z80 image
+//!
+//! Sections are laid out in object order: all CODE sections first, then
+//! all DATA sections, then BSS. The first object's code therefore starts
+//! at the origin, so the program object goes first. Public symbols are
+//! visible to every object, private ones only to their own; `External`
+//! symbols must be defined by some other object, or be one of the
+//! absolute symbols the target provides (such as ROM entry points) or an
+//! object fixes (such as `absolute` variables).
+//!
+//! The image holds CODE and DATA; BSS follows it in memory and is not
+//! stored (the program clears it at start-up).
+//!
+//! Mergeable symbols are linked once: where several objects define one,
+//! the first keeps its bytes and the others lose theirs, with the symbols
+//! and relocations within them (see [`merge`]). The code around a dropped
+//! copy closes up, so no displacement resolved when it was assembled may
+//! cross it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{ObjectFile, RelocationType, Section, Symbol, SymbolType, SymbolVisibility};
+
+/// Load address of CP/M-style .com programs
+pub const COM_ORIGIN: u16 = 0x0100;
+
+/// A linked program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedImage {
+    /// Address of the first byte of `bytes`
+    pub origin: u16,
+    /// CODE and DATA, ready to load at `origin`
+    pub bytes: Vec<u8>,
+    /// Address and size of the uninitialized data after the image
+    pub bss_start: u16,
+    pub bss_size: u16,
+    /// Defined symbols and their addresses, sorted by address
+    pub symbols: Vec<(String, u16)>,
+    /// Offsets in `bytes` of the 16-bit addresses of symbols in the image,
+    /// which change if it is loaded elsewhere, in order
+    pub relocations: Vec<u16>,
+    /// Whether those are all: no address of the image was stored a byte
+    /// at a time
+    pub relocatable: bool,
+}
+
+/// Why objects could not be linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// A public symbol is defined by two units
+    DuplicateSymbol { name: String, first: String, second: String },
+    /// Copies of a mergeable symbol differ in size
+    MergeMismatch { name: String, first: String, second: String },
+    /// A symbol is referenced but not defined
+    UndefinedSymbol { name: String, unit: String },
+    /// A relative displacement does not fit its field
+    OutOfRange { name: String, unit: String, displacement: i32 },
+    /// Aligned symbols of one section cannot all be placed
+    Misaligned { name: String, unit: String, alignment: u16 },
+    /// A relocation points outside its section
+    BadRelocation { name: String, unit: String, offset: u16 },
+    /// The sections do not fit in the 64K address space
+    Overflow { size: u32 },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol { name, first, second } => {
+                write!(f, "Symbol '{}' is defined in both '{}' and '{}'", name, first, second)
+            }
+            LinkError::MergeMismatch { name, first, second } => write!(
+                f,
+                "Mergeable symbol '{}' has different sizes in '{}' and '{}'",
+                name, first, second
+            ),
+            LinkError::UndefinedSymbol { name, unit } => {
+                write!(f, "Undefined symbol '{}' referenced from '{}'", name, unit)
+            }
+            LinkError::OutOfRange { name, unit, displacement } => write!(
+                f,
+                "Relative reference to '{}' in '{}' is out of range ({} bytes)",
+                name, unit, displacement
+            ),
+            LinkError::Misaligned { name, unit, alignment } => write!(
+                f,
+                "Symbol '{}' in '{}' cannot be placed on a multiple of {}",
+                name, unit, alignment
+            ),
+            LinkError::BadRelocation { name, unit, offset } => write!(
+                f,
+                "Relocation of '{}' in '{}' at offset {} is outside its section",
+                name, unit, offset
+            ),
+            LinkError::Overflow { size } => write!(f, "Program needs {} bytes and does not fit in 64K", size),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Unit name reported for absolute symbols
+pub const ABSOLUTE_UNIT: &str = "<target>";
+
+/// Link `objects` into an image loaded at `origin`
+pub fn link(objects: &[ObjectFile], origin: u16) -> Result<LinkedImage, LinkError> {
+    link_with_absolutes(objects, origin, &[])
+}
+
+/// Link `objects` into an image loaded at `origin`, resolving references
+/// to `absolutes` (name and fixed address) as well as to the objects'
+/// public and absolute symbols
+pub fn link_with_absolutes(
+    objects: &[ObjectFile],
+    origin: u16,
+    absolutes: &[(String, u16)],
+) -> Result<LinkedImage, LinkError> {
+    let objects = &merge(objects)?[..];
+    let (bases, cursor) = layout(objects, origin)?;
+
+    // Public symbols of all objects; private ones are looked up per object
+    let mut globals: HashMap<&str, (u16, &str)> = HashMap::new();
+    let mut symbols = Vec::new();
+    for (name, address) in absolutes.iter().chain(objects.iter().flat_map(|object| &object.absolutes)) {
+        globals.insert(name, (*address, ABSOLUTE_UNIT));
+        symbols.push((name.clone(), *address));
+    }
+    for (index, object) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|s| s.symbol_type != SymbolType::External) {
+            let address = bases[index][symbol.section as usize].wrapping_add(symbol.offset);
+            if symbol.visibility != SymbolVisibility::Private
+                && let Some((_, first)) = globals.insert(&symbol.name, (address, &object.unit_name))
+            {
+                return Err(LinkError::DuplicateSymbol {
+                    name: symbol.name.clone(),
+                    first: first.to_string(),
+                    second: object.unit_name.clone(),
+                });
+            }
+            symbols.push((symbol.name.clone(), address));
+        }
+    }
+    symbols.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+    let undefined = |name: &str, unit: &str| LinkError::UndefinedSymbol {
+        name: name.to_string(),
+        unit: unit.to_string(),
+    };
+    for object in objects {
+        for symbol in object.symbols.iter().filter(|s| s.symbol_type == SymbolType::External) {
+            if !globals.contains_key(symbol.name.as_str()) {
+                return Err(undefined(&symbol.name, &object.unit_name));
+            }
+        }
+    }
+
+    // Concatenate the sections, then patch them in place
+    let mut bytes = Vec::new();
+    for section in [Section::Code, Section::Data] {
+        for (index, object) in objects.iter().enumerate() {
+            let base = bases[index][section as usize];
+            bytes.resize((base - origin) as usize, 0);
+            bytes.extend_from_slice(if section == Section::Code { &object.code } else { &object.data });
+        }
+    }
+
+    let mut moving = Vec::new();
+    let mut relocatable = true;
+    for (index, object) in objects.iter().enumerate() {
+        for relocation in &object.relocations {
+            let unit = object.unit_name.as_str();
+            let name = relocation.symbol_name.as_str();
+            let (target, defined_in) = object
+                .symbols
+                .iter()
+                .find(|s| s.name == name && s.symbol_type != SymbolType::External)
+                .map(|s| (bases[index][s.section as usize].wrapping_add(s.offset), unit))
+                .or_else(|| globals.get(name).copied())
+                .ok_or_else(|| undefined(name, unit))?;
+            let value = target.wrapping_add(relocation.addend as u16);
+
+            let section_size = match relocation.section {
+                Section::Code => object.code.len(),
+                Section::Data => object.data.len(),
+                Section::Bss => 0,
+            };
+            let width = match relocation.relocation_type {
+                RelocationType::Absolute16 | RelocationType::Relative16 => 2,
+                _ => 1,
+            };
+            if relocation.offset as usize + width > section_size {
+                return Err(LinkError::BadRelocation { name: name.to_string(), unit: unit.to_string(), offset: relocation.offset });
+            }
+            let address = bases[index][relocation.section as usize] + relocation.offset;
+            let at = (address - origin) as usize;
+
+            // Displacements count from the end of the field, as JR and DJNZ do
+            let displacement = value as i32 - (address as i32 + width as i32);
+            // Addresses in the image move with it, those the target provides do not
+            let moves = defined_in != ABSOLUTE_UNIT;
+            match relocation.relocation_type {
+                RelocationType::Absolute16 => {
+                    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+                    if moves {
+                        moving.push(at as u16);
+                    }
+                }
+                RelocationType::LowByte => {
+                    bytes[at] = value as u8;
+                    relocatable &= !moves;
+                }
+                RelocationType::HighByte => {
+                    bytes[at] = (value >> 8) as u8;
+                    relocatable &= !moves;
+                }
+                RelocationType::Relative8 => {
+                    if !(-128..=127).contains(&displacement) {
+                        return Err(LinkError::OutOfRange { name: name.to_string(), unit: unit.to_string(), displacement });
+                    }
+                    bytes[at] = displacement as u8;
+                }
+                RelocationType::Relative16 => bytes[at..at + 2].copy_from_slice(&(displacement as u16).to_le_bytes()),
+            }
+        }
+    }
+
+    moving.sort();
+    let bss_start = bases.first().map_or(origin, |base| base[Section::Bss as usize]);
+    Ok(LinkedImage {
+        origin,
+        bss_start,
+        bss_size: (cursor - bss_start as u32) as u16,
+        bytes,
+        symbols,
+        relocations: moving,
+        relocatable,
+    })
+}
+
+/// `objects` with the copies of each mergeable symbol after the first
+/// dropped: its bytes, the symbols defined in them and the relocations
+/// that patch them. References to a dropped copy resolve to the one kept.
+pub fn merge(objects: &[ObjectFile]) -> Result<Cow<'_, [ObjectFile]>, LinkError> {
+    let mut kept: HashMap<&str, (u16, &str)> = HashMap::new();
+    let mut merged: Option<Vec<ObjectFile>> = None;
+    for (index, object) in objects.iter().enumerate() {
+        let mut duplicates = Vec::new();
+        for symbol in object.symbols.iter().filter(|s| s.visibility == SymbolVisibility::Mergeable) {
+            match kept.get(symbol.name.as_str()) {
+                None => {
+                    kept.insert(&symbol.name, (symbol.size, &object.unit_name));
+                }
+                Some((size, _)) if *size == symbol.size => duplicates.push(symbol),
+                Some((_, first)) => {
+                    return Err(LinkError::MergeMismatch {
+                        name: symbol.name.clone(),
+                        first: first.to_string(),
+                        second: object.unit_name.clone(),
+                    });
+                }
+            }
+        }
+        if duplicates.is_empty() {
+            continue;
+        }
+        let merged = merged.get_or_insert_with(|| objects.to_vec());
+        let copy = &mut merged[index];
+        // From the end, so the offsets of those left are still right
+        duplicates.sort_by_key(|symbol| std::cmp::Reverse(symbol.offset));
+        for symbol in duplicates {
+            copy.symbols.retain(|s| !(s.name == symbol.name && s.visibility == SymbolVisibility::Mergeable));
+            drop_range(copy, symbol);
+        }
+    }
+    Ok(merged.map_or(Cow::Borrowed(objects), Cow::Owned))
+}
+
+/// Remove the bytes `symbol` covers from `object`, with what lies in them,
+/// and move what follows them down
+fn drop_range(object: &mut ObjectFile, symbol: &Symbol) {
+    let (section, start, size) = (symbol.section, symbol.offset, symbol.size);
+    let end = start.saturating_add(size);
+    if size == 0 || end as usize > object.section_size(section) {
+        return;
+    }
+    match section {
+        Section::Code => drop(object.code.drain(start as usize..end as usize)),
+        Section::Data => drop(object.data.drain(start as usize..end as usize)),
+        Section::Bss => object.bss_size -= size,
+    }
+    let inside = |offset: u16| (start..end).contains(&offset);
+    object
+        .symbols
+        .retain(|s| s.symbol_type == SymbolType::External || s.section != section || !inside(s.offset));
+    for s in object.symbols.iter_mut().filter(|s| s.symbol_type != SymbolType::External && s.section == section) {
+        if s.offset >= end {
+            s.offset -= size;
+        }
+    }
+    object.relocations.retain(|r| r.section != section || !inside(r.offset));
+    for r in object.relocations.iter_mut().filter(|r| r.section == section && r.offset >= end) {
+        r.offset -= size;
+    }
+    if section == Section::Code {
+        for address in [&mut object.init_address, &mut object.fini_address].into_iter().flatten() {
+            if *address >= end {
+                *address -= size;
+            }
+        }
+    }
+}
+
+/// Base address of the CODE, DATA and BSS sections of each of `objects`
+/// linked at `origin` (indexed by [`Section`]), and the address after the
+/// last
+pub fn layout(objects: &[ObjectFile], origin: u16) -> Result<(Vec<[u16; 3]>, u32), LinkError> {
+    let mut cursor = origin as u32;
+    let mut bases = vec![[0u16; 3]; objects.len()];
+    for section in [Section::Code, Section::Data, Section::Bss] {
+        for (index, object) in objects.iter().enumerate() {
+            cursor = align_section(object, section, cursor)?;
+            bases[index][section as usize] = cursor as u16;
+            cursor += object.section_size(section) as u32;
+            if cursor > 0x10000 {
+                return Err(LinkError::Overflow { size: cursor - origin as u32 });
+            }
+        }
+    }
+    Ok((bases, cursor))
+}
+
+/// Move `cursor` up until every aligned symbol of `object`'s `section` is
+/// on a multiple of its alignment
+fn align_section(object: &ObjectFile, section: Section, cursor: u32) -> Result<u32, LinkError> {
+    let aligned: Vec<_> = object
+        .symbols
+        .iter()
+        .filter(|s| s.section == section && s.symbol_type != SymbolType::External && s.alignment > 1)
+        .collect();
+    // The strictest alignment decides the base; the others must agree with it
+    let Some(strictest) = aligned.iter().max_by_key(|s| s.alignment) else {
+        return Ok(cursor);
+    };
+    let alignment = strictest.alignment as u32;
+    let address = (cursor + strictest.offset as u32).div_ceil(alignment) * alignment;
+    let base = address - strictest.offset as u32;
+    for symbol in aligned {
+        if !(base + symbol.offset as u32).is_multiple_of(symbol.alignment as u32) {
+            return Err(LinkError::Misaligned {
+                name: symbol.name.clone(),
+                unit: object.unit_name.clone(),
+                alignment: symbol.alignment,
+            });
+        }
+    }
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Relocation, Symbol};
+
+    fn symbol(name: &str, symbol_type: SymbolType, section: Section, offset: u16) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            symbol_type,
+            visibility: SymbolVisibility::Public,
+            section,
+            offset,
+            size: 0,
+            alignment: 0,
+        }
+    }
+
+    fn relocation(offset: u16, relocation_type: RelocationType, name: &str) -> Relocation {
+        Relocation { section: Section::Code, offset, relocation_type, symbol_name: name.to_string(), addend: 0 }
+    }
+
+    #[test]
+    fn test_link_resolves_across_objects() {
+        let mut main = ObjectFile::new("Main".to_string());
+        main.add_code(&[0xCD, 0, 0, 0x18, 0, 0xC9]); // call Helper; jr Done; ret
+        main.add_symbol(symbol("Helper", SymbolType::External, Section::Code, 0));
+        main.add_symbol(symbol("Done", SymbolType::Function, Section::Code, 5));
+        main.add_relocation(relocation(1, RelocationType::Absolute16, "Helper"));
+        main.add_relocation(relocation(4, RelocationType::Relative8, "Done"));
+
+        let mut lib = ObjectFile::new("Lib".to_string());
+        lib.add_code(&[0x21, 0, 0, 0xC9]); // ld hl, Table; ret
+        lib.add_data(&[1, 2, 3]);
+        lib.set_bss_size(4);
+        lib.add_symbol(symbol("Helper", SymbolType::Function, Section::Code, 0));
+        lib.add_symbol(symbol("Table", SymbolType::Variable, Section::Data, 0));
+        lib.add_relocation(relocation(1, RelocationType::Absolute16, "Table"));
+
+        let image = link(&[main, lib], 0x4000).unwrap();
+        assert_eq!(image.bytes, vec![0xCD, 0x06, 0x40, 0x18, 0x00, 0xC9, 0x21, 0x0A, 0x40, 0xC9, 1, 2, 3]);
+        assert_eq!((image.bss_start, image.bss_size), (0x400D, 4));
+        assert!(image.symbols.contains(&("Table".to_string(), 0x400A)));
+        // The call and the load move with the image; the JR does not
+        assert_eq!(image.relocations, [1, 7]);
+        assert!(image.relocatable);
+    }
+
+    #[test]
+    fn test_link_errors_and_alignment() {
+        let mut a = ObjectFile::new("A".to_string());
+        a.add_symbol(symbol("Missing", SymbolType::External, Section::Code, 0));
+        assert_eq!(
+            link(&[a], COM_ORIGIN),
+            Err(LinkError::UndefinedSymbol { name: "Missing".to_string(), unit: "A".to_string() })
+        );
+
+        let mut a = ObjectFile::new("A".to_string());
+        a.add_code(&[0]);
+        a.add_symbol(symbol("X", SymbolType::Function, Section::Code, 0));
+        let b = a.clone();
+        assert!(matches!(link(&[a.clone(), b], COM_ORIGIN), Err(LinkError::DuplicateSymbol { .. })));
+
+        let mut table = ObjectFile::new("T".to_string());
+        table.add_data(&[0; 4]);
+        table.add_symbol(Symbol { alignment: 256, ..symbol("Vectors", SymbolType::Variable, Section::Data, 0) });
+        let image = link(&[a, table], COM_ORIGIN).unwrap();
+        assert!(image.symbols.contains(&("Vectors".to_string(), 0x0200)));
+        assert_eq!(image.bytes.len(), 0x104);
+    }
+
+    #[test]
+    fn test_link_keeps_one_copy_of_mergeable_symbols() {
+        let instance = |object: &mut ObjectFile, offset: u16| {
+            object.add_symbol(Symbol {
+                visibility: SymbolVisibility::Mergeable,
+                size: 2,
+                ..symbol("Max_of_Integer", SymbolType::Function, Section::Code, offset)
+            });
+            object.add_symbol(Symbol {
+                visibility: SymbolVisibility::Private,
+                ..symbol("loop", SymbolType::Function, Section::Code, offset + 1)
+            });
+        };
+        let mut a = ObjectFile::new("A".to_string());
+        a.add_code(&[0xCD, 0, 0, 0x00, 0xC9]); // call Max_of_Integer; Max_of_Integer: nop; ret
+        instance(&mut a, 3);
+        a.add_relocation(relocation(1, RelocationType::Absolute16, "Max_of_Integer"));
+
+        let mut b = ObjectFile::new("B".to_string());
+        b.add_code(&[0x00, 0xC9, 0xC3, 0, 0]); // Max_of_Integer: nop; ret; Tail: jp Max_of_Integer
+        instance(&mut b, 0);
+        b.add_symbol(symbol("Tail", SymbolType::Function, Section::Code, 2));
+        b.add_relocation(relocation(3, RelocationType::Absolute16, "Max_of_Integer"));
+
+        let objects = [a.clone(), b.clone()];
+        let merged = merge(&objects).unwrap();
+        assert_eq!(merged[1].code, [0xC3, 0, 0]);
+        assert_eq!(merged[1].symbols, [symbol("Tail", SymbolType::Function, Section::Code, 0)]);
+        assert_eq!(merged[1].relocations, [relocation(1, RelocationType::Absolute16, "Max_of_Integer")]);
+
+        let image = link(&objects, 0x8000).unwrap();
+        assert_eq!(image.bytes, [0xCD, 0x03, 0x80, 0x00, 0xC9, 0xC3, 0x03, 0x80]);
+        assert!(image.symbols.contains(&("Tail".to_string(), 0x8005)));
+
+        b.symbols[0].size = 3;
+        assert_eq!(
+            link(&[a, b], 0x8000),
+            Err(LinkError::MergeMismatch { name: "Max_of_Integer".to_string(), first: "A".to_string(), second: "B".to_string() })
+        );
+    }
+
+    #[test]
+    fn test_link_resolves_absolute_symbols() {
+        let mut main = ObjectFile::new("Main".to_string());
+        main.add_code(&[0xCD, 0, 0]); // call PrintChar
+        main.add_symbol(symbol("PrintChar", SymbolType::External, Section::Code, 0));
+        main.add_relocation(relocation(1, RelocationType::Absolute16, "PrintChar"));
+
+        let absolutes = [("PrintChar".to_string(), 0x0010)];
+        let image = link_with_absolutes(std::slice::from_ref(&main), 0x8000, &absolutes).unwrap();
+        assert_eq!(image.bytes, vec![0xCD, 0x10, 0x00]);
+        assert_eq!(image.symbols[0], ("PrintChar".to_string(), 0x0010));
+        assert!(image.relocations.is_empty());
+
+        main.add_symbol(symbol("PrintChar", SymbolType::Function, Section::Code, 0));
+        assert_eq!(
+            link_with_absolutes(&[main], 0x8000, &absolutes),
+            Err(LinkError::DuplicateSymbol {
+                name: "PrintChar".to_string(),
+                first: ABSOLUTE_UNIT.to_string(),
+                second: "Main".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_link_resolves_absolute_symbols_of_objects() {
+        // The unit fixes ScreenPixels at $4000, the program writes to it
+        let mut screen = ObjectFile::new("ZXScreen".to_string());
+        screen.absolutes.push(("ScreenPixels".to_string(), 0x4000));
+        let mut main = ObjectFile::new("Main".to_string());
+        main.add_code(&[0x22, 0, 0]); // ld (ScreenPixels), hl
+        main.add_symbol(symbol("ScreenPixels", SymbolType::External, Section::Code, 0));
+        main.add_relocation(relocation(1, RelocationType::Absolute16, "ScreenPixels"));
+
+        let image = link(&[main, screen], 0x8000).unwrap();
+        assert_eq!(image.bytes, vec![0x22, 0x00, 0x40]);
+        assert_eq!((image.bss_size, image.relocations.len()), (0, 0));
+        assert!(image.symbols.contains(&("ScreenPixels".to_string(), 0x4000)));
+    }
+}
@@ -0,0 +1,269 @@
+//! Memory map of linked objects (`--map`)
+//!
+//! Where the linker places each object's sections, each routine and each
+//! global, what is reserved for the heap and task stacks, and how much of
+//! the RAM the program is loaded into is left for the stack:
+//!
+//! ```text
+//! Memory map, origin $4000
+//!
+//! Sections:
+//!   Start  End     Size  Section  Unit
+//!   $4000  $41FF    512  CODE     Game
+//!   $4200  $4210     17  DATA     Game
+//!   $4211  $5314   4356  BSS      Game
+//!
+//! Routines:
+//!   Start  End     Size  Name  Unit
+//!   $4000  $41FF    512  main  Game
+//!
+//! Globals:
+//!   Start  End     Size  Section  Name    Unit
+//!   $4211  $4212      2  BSS      Score   Game
+//!   $4215  $5214   4096  BSS      __heap  Game
+//!
+//! Reserved:
+//!   $4215  $5214   4096  Heap
+//!   $5315  $FFFF  44267  Stack and free RAM
+//!
+//! Total: 512 bytes of code, 17 of data, 4356 of BSS; 4885 of 49152 bytes of RAM used (9%)
+//! ```
+//!
+//! Sections are laid out as [`crate::linker`] lays them out, so the map of
+//! the objects given to `link` matches the image it writes.
+
+use std::fmt;
+
+use crate::linker::{layout, merge, LinkError};
+use crate::{ObjectFile, Section, Symbol, SymbolType, SymbolVisibility};
+
+/// A section of one object, placed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacedSection {
+    pub unit: String,
+    pub section: Section,
+    pub start: u16,
+    pub size: u16,
+}
+
+/// A routine, global or reservation, placed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacedSymbol {
+    pub name: String,
+    pub unit: String,
+    pub section: Section,
+    pub start: u16,
+    pub size: u32,
+}
+
+/// The layout of objects linked at an origin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub origin: u16,
+    /// Those not empty, in address order
+    pub sections: Vec<PlacedSection>,
+    /// Code symbols, each running to the next one or the end of its section
+    pub routines: Vec<PlacedSymbol>,
+    /// Data and BSS symbols
+    pub globals: Vec<PlacedSymbol>,
+    /// Areas set aside, such as the heap, named by what they hold
+    pub reserved: Vec<PlacedSymbol>,
+    /// Address after the last byte of BSS
+    pub end: u32,
+    /// First address and size of the RAM the program is loaded into, if known
+    pub ram: Option<(u32, u32)>,
+}
+
+impl MemoryMap {
+    /// The map of `objects` linked at `origin`
+    pub fn layout(objects: &[ObjectFile], origin: u16) -> Result<Self, LinkError> {
+        let objects = &merge(objects)?[..];
+        let (bases, end) = layout(objects, origin)?;
+        let mut sections = Vec::new();
+        let mut routines = Vec::new();
+        let mut globals = Vec::new();
+        for section in [Section::Code, Section::Data, Section::Bss] {
+            for (object, bases) in objects.iter().zip(&bases).filter(|(object, _)| object.section_size(section) > 0) {
+                let size = object.section_size(section) as u16;
+                sections.push(PlacedSection { unit: object.unit_name.clone(), section, start: bases[section as usize], size });
+            }
+        }
+        // Private code symbols are labels within routines
+        let listed = |symbol: &&Symbol| {
+            symbol.symbol_type != SymbolType::External
+                && !(symbol.section == Section::Code && symbol.visibility == SymbolVisibility::Private)
+        };
+        for (object, bases) in objects.iter().zip(&bases) {
+            for symbol in object.symbols.iter().filter(listed) {
+                let mut size = symbol.size as u32;
+                // Routines are only known by where they start
+                if symbol.section == Section::Code && size == 0 {
+                    let next = object
+                        .symbols
+                        .iter()
+                        .filter(listed)
+                        .filter(|other| other.section == Section::Code)
+                        .map(|other| other.offset)
+                        .filter(|&offset| offset > symbol.offset)
+                        .min()
+                        .unwrap_or(object.code.len() as u16);
+                    size = next.saturating_sub(symbol.offset) as u32;
+                }
+                let placed = PlacedSymbol {
+                    name: symbol.name.clone(),
+                    unit: object.unit_name.clone(),
+                    section: symbol.section,
+                    start: bases[symbol.section as usize].wrapping_add(symbol.offset),
+                    size,
+                };
+                match symbol.section {
+                    Section::Code => routines.push(placed),
+                    Section::Data | Section::Bss => globals.push(placed),
+                }
+            }
+        }
+        routines.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.name.cmp(&b.name)));
+        globals.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.name.cmp(&b.name)));
+        Ok(MemoryMap { origin, sections, routines, globals, reserved: vec![], end, ram: None })
+    }
+
+    /// Report the global `symbol`, if there is one, as reserved for `purpose`
+    pub fn reserve(&mut self, purpose: &str, symbol: &str) {
+        if let Some(global) = self.globals.iter().find(|global| global.name == symbol) {
+            self.reserved.push(PlacedSymbol { name: purpose.to_string(), ..global.clone() });
+        }
+    }
+
+    /// Bytes of `section` in all objects
+    pub fn section_total(&self, section: Section) -> u32 {
+        self.sections.iter().filter(|placed| placed.section == section).map(|placed| placed.size as u32).sum()
+    }
+
+    /// Bytes of RAM from the end of BSS to the end of the RAM the program
+    /// is loaded into, which the stack grows into
+    pub fn free(&self) -> Option<u32> {
+        self.ram.map(|(start, size)| (start + size).saturating_sub(self.end))
+    }
+}
+
+/// `$XXXX  $XXXX  size`, the end inclusive, or `-` when empty
+fn range(start: u32, size: u32) -> String {
+    let end = if size == 0 { "-".to_string() } else { format!("${:04X}", start + size - 1) };
+    format!("${:04X}  {:<6}{:>6}", start, end, size)
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Memory map, origin ${:04X}", self.origin)?;
+
+        let ranges = format!("{:<5}  {:<6}{:>6}", "Start", "End", "Size");
+        let width = |names: &mut dyn Iterator<Item = &str>, header: &str| {
+            names.map(|name| name.chars().count()).chain([header.len()]).max().unwrap_or(0)
+        };
+        writeln!(f, "\nSections:")?;
+        writeln!(f, "  {}  Section  Unit", ranges)?;
+        for placed in &self.sections {
+            writeln!(f, "  {}  {:<7}  {}", range(placed.start as u32, placed.size as u32), placed.section.name(), placed.unit)?;
+        }
+
+        writeln!(f, "\nRoutines:")?;
+        let name_width = width(&mut self.routines.iter().map(|routine| routine.name.as_str()), "Name");
+        writeln!(f, "  {}  {:<name_width$}  Unit", ranges, "Name")?;
+        for routine in &self.routines {
+            writeln!(f, "  {}  {:<name_width$}  {}", range(routine.start as u32, routine.size), routine.name, routine.unit)?;
+        }
+
+        writeln!(f, "\nGlobals:")?;
+        let name_width = width(&mut self.globals.iter().map(|global| global.name.as_str()), "Name");
+        writeln!(f, "  {}  Section  {:<name_width$}  Unit", ranges, "Name")?;
+        for global in &self.globals {
+            writeln!(
+                f,
+                "  {}  {:<7}  {:<name_width$}  {}",
+                range(global.start as u32, global.size),
+                global.section.name(),
+                global.name,
+                global.unit
+            )?;
+        }
+
+        writeln!(f, "\nReserved:")?;
+        for reserved in &self.reserved {
+            writeln!(f, "  {}  {}", range(reserved.start as u32, reserved.size), reserved.name)?;
+        }
+        if let Some(free) = self.free() {
+            writeln!(f, "  {}  Stack and free RAM", range(self.end, free))?;
+        }
+
+        write!(
+            f,
+            "\nTotal: {} bytes of code, {} of data, {} of BSS",
+            self.section_total(Section::Code),
+            self.section_total(Section::Data),
+            self.section_total(Section::Bss)
+        )?;
+        let used = self.end - self.origin as u32;
+        match self.ram {
+            Some((_, size)) if size > 0 => {
+                writeln!(f, "; {} of {} bytes of RAM used ({}%)", used, size, used * 100 / size)
+            }
+            _ => writeln!(f, "; {} bytes used", used),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, symbol_type: SymbolType, section: Section, offset: u16, size: u16) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            symbol_type,
+            visibility: SymbolVisibility::Public,
+            section,
+            offset,
+            size,
+            alignment: 0,
+        }
+    }
+
+    #[test]
+    fn test_map_of_linked_objects() {
+        let mut main = ObjectFile::new("Main".to_string());
+        main.add_code(&[0xCD, 0, 0, 0xC9, 0xC9]);
+        main.add_data(&[1, 2]);
+        main.set_bss_size(4);
+        main.add_symbol(symbol("main", SymbolType::Function, Section::Code, 0, 0));
+        main.add_symbol(symbol("Helper", SymbolType::Function, Section::Code, 4, 0));
+        // A label within main
+        let label = symbol("main_loop", SymbolType::Function, Section::Code, 3, 0);
+        main.add_symbol(Symbol { visibility: SymbolVisibility::Private, ..label });
+        main.add_symbol(symbol("Lives", SymbolType::Variable, Section::Data, 0, 2));
+        main.add_symbol(symbol("__heap", SymbolType::Variable, Section::Bss, 0, 4));
+        main.add_symbol(symbol("Missing", SymbolType::External, Section::Code, 0, 0));
+        let mut lib = ObjectFile::new("Lib".to_string());
+        lib.add_code(&[0xC9]);
+        lib.add_symbol(symbol("Lib", SymbolType::Function, Section::Code, 0, 0));
+
+        let mut map = MemoryMap::layout(&[main, lib], 0x8000).unwrap();
+        map.reserve("Heap", "__heap");
+        map.reserve("Task stacks", "__task_stacks");
+        map.ram = Some((0x8000, 0x8000));
+
+        let routines: Vec<_> = map.routines.iter().map(|r| (r.name.as_str(), r.start, r.size)).collect();
+        assert_eq!(routines, [("main", 0x8000, 4), ("Helper", 0x8004, 1), ("Lib", 0x8005, 1)]);
+        assert_eq!(map.globals[1].start, 0x8008);
+        assert_eq!(map.reserved.len(), 1);
+        assert_eq!(map.end, 0x800C);
+        assert_eq!(map.free(), Some(0x10000 - 0x800C));
+
+        let text = map.to_string();
+        assert!(text.contains("  $8000  $8003      4  main    Main\n"), "{}", text);
+        assert!(text.contains("  $8006  $8007      2  DATA     Lives   Main\n"), "{}", text);
+        assert!(text.contains("  $8008  $800B      4  Heap\n"), "{}", text);
+        assert!(text.contains("  $800C  $FFFF  32756  Stack and free RAM\n"), "{}", text);
+        assert!(!text.contains("DATA     Lib"), "{}", text);
+        assert!(text.ends_with("Total: 6 bytes of code, 2 of data, 4 of BSS; 12 of 32768 bytes of RAM used (0%)\n"), "{}", text);
+    }
+}
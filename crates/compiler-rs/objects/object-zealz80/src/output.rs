@@ -0,0 +1,108 @@
+//! Output formats of linked images (`link --emit`)
+//!
+//! - **bin**: the image's bytes as they sit in memory from its origin,
+//!   for emulators, loaders and EPROM programmers that take raw files.
+//! - **hex**: Intel HEX, the text format most EPROM programmers read;
+//!   records of up to 16 bytes each carry their own address, so the file
+//!   places the image at its origin by itself:
+//!
+//!   ```text
+//!   :10800000000102030405060708090A0B0C0D0E0FF8
+//!   :0280100010114D
+//!   :00000001FF
+//!   ```
+//!
+//! - **zealos**: a Zeal 8-bit OS executable (see [`crate::zealos`]).
+//!
+//! BSS is never written: it follows the image in memory, and startup code
+//! clears it.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::linker::LinkedImage;
+use crate::zealos::ZealOsExecutable;
+
+/// Bytes of data in each Intel HEX record
+pub const HEX_RECORD_SIZE: usize = 16;
+
+/// How a linked image is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Binary,
+    IntelHex,
+    ZealOs,
+}
+
+impl ImageFormat {
+    /// Every format, in the order listed to users
+    pub const ALL: [ImageFormat; 3] = [ImageFormat::Binary, ImageFormat::IntelHex, ImageFormat::ZealOs];
+
+    /// The format `--emit` names `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Binary => "bin",
+            ImageFormat::IntelHex => "hex",
+            ImageFormat::ZealOs => "zealos",
+        }
+    }
+
+    /// Write `image`; a Zeal OS executable starts at its origin
+    pub fn write<W: Write>(&self, image: &LinkedImage, writer: &mut W) -> io::Result<()> {
+        match self {
+            ImageFormat::Binary => writer.write_all(&image.bytes),
+            ImageFormat::IntelHex => write_intel_hex(&image.bytes, image.origin, writer),
+            ImageFormat::ZealOs => ZealOsExecutable::new(image, image.origin).write(writer),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Write `bytes`, loaded at `origin`, as Intel HEX data records and an end
+/// of file record
+pub fn write_intel_hex<W: Write>(bytes: &[u8], origin: u16, writer: &mut W) -> io::Result<()> {
+    for (i, chunk) in bytes.chunks(HEX_RECORD_SIZE).enumerate() {
+        let address = origin.wrapping_add((i * HEX_RECORD_SIZE) as u16);
+        hex_record(writer, address, 0x00, chunk)?;
+    }
+    hex_record(writer, 0, 0x01, &[])
+}
+
+/// `:LLAAAATT` then the data and a checksum making the bytes sum to zero
+fn hex_record<W: Write>(writer: &mut W, address: u16, record_type: u8, data: &[u8]) -> io::Result<()> {
+    let [high, low] = address.to_be_bytes();
+    let fields = [data.len() as u8, high, low, record_type];
+    let sum = fields.iter().chain(data).fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    let mut line = String::from(":");
+    for byte in fields.iter().chain(data).chain([&sum.wrapping_neg()]) {
+        line.push_str(&format!("{:02X}", byte));
+    }
+    writeln!(writer, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intel_hex_records() {
+        let bytes: Vec<u8> = (0..18).collect();
+        let mut hex = Vec::new();
+        write_intel_hex(&bytes, 0x8000, &mut hex).unwrap();
+        assert_eq!(
+            String::from_utf8(hex).unwrap(),
+            ":10800000000102030405060708090A0B0C0D0E0FF8\n:0280100010114D\n:00000001FF\n"
+        );
+        assert_eq!(ImageFormat::from_name("HEX"), Some(ImageFormat::IntelHex));
+        assert_eq!(ImageFormat::from_name("elf"), None);
+    }
+}
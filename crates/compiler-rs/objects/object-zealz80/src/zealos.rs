@@ -0,0 +1,148 @@
+//! Zeal 8-bit OS executables (`link --format zealos`)
+//!
+//! A linked image behind a header telling the loader where it was linked,
+//! where to start it, how much BSS to clear after it and which of its
+//! words are addresses to adjust when it is loaded elsewhere:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic "ZEX\0"
+//!      4     1  version (1)
+//!      5     1  flags: bit 0 set when the relocation table is complete
+//!      6     2  load address the image was linked at
+//!      8     2  entry point
+//!     10     2  image size
+//!     12     2  BSS size
+//!     14     2  relocation count
+//!     16        image, then one 16-bit image offset per relocation
+//! ```
+//!
+//! Every field is little-endian. A relocation is an absolute address of
+//! the image stored in it; moving the image by `n` bytes adds `n` to each.
+//! Addresses the target provides (ROM entry points) are not relocated. An
+//! image storing an address of its own a byte at a time cannot be moved,
+//! and has bit 0 of its flags clear.
+
+use std::io::{self, Read, Write};
+
+use crate::linker::LinkedImage;
+use crate::{invalid, read_bytes, read_u16, read_u8, write_u16, write_u8};
+
+/// Executable magic number: "ZEX\0" (Zeal executable)
+pub const ZEX_MAGIC: &[u8] = b"ZEX\0";
+/// Format version written and read
+pub const ZEX_VERSION: u8 = 1;
+/// Bytes before the image
+pub const ZEX_HEADER_SIZE: usize = 16;
+/// Flag set when the relocation table lists every address of the image
+pub const ZEX_RELOCATABLE: u8 = 0x01;
+
+/// A program as Zeal 8-bit OS loads it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZealOsExecutable {
+    /// Address the image was linked at
+    pub load_address: u16,
+    /// Address execution starts at
+    pub entry: u16,
+    /// CODE and DATA
+    pub image: Vec<u8>,
+    /// Bytes of BSS to clear after the image
+    pub bss_size: u16,
+    /// Offsets in `image` of the addresses to adjust when it moves
+    pub relocations: Vec<u16>,
+    /// Whether `relocations` lists them all
+    pub relocatable: bool,
+}
+
+impl ZealOsExecutable {
+    /// The executable of `image`, started at `entry`
+    pub fn new(image: &LinkedImage, entry: u16) -> Self {
+        ZealOsExecutable {
+            load_address: image.origin,
+            entry,
+            image: image.bytes.clone(),
+            bss_size: image.bss_size,
+            relocations: image.relocations.clone(),
+            relocatable: image.relocatable,
+        }
+    }
+
+    /// Write the header, image and relocation table
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let too_large = |what: &str| invalid(format!("Too many {} for a Zeal OS executable", what));
+        let image_size = u16::try_from(self.image.len()).map_err(|_| too_large("image bytes"))?;
+        let count = u16::try_from(self.relocations.len()).map_err(|_| too_large("relocations"))?;
+        writer.write_all(ZEX_MAGIC)?;
+        write_u8(writer, ZEX_VERSION)?;
+        write_u8(writer, if self.relocatable { ZEX_RELOCATABLE } else { 0 })?;
+        for field in [self.load_address, self.entry, image_size, self.bss_size, count] {
+            write_u16(writer, field)?;
+        }
+        writer.write_all(&self.image)?;
+        for offset in &self.relocations {
+            write_u16(writer, *offset)?;
+        }
+        Ok(())
+    }
+
+    /// Read an executable written by [`ZealOsExecutable::write`]
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != ZEX_MAGIC {
+            return Err(invalid("Invalid Zeal OS executable magic number".to_string()));
+        }
+        let version = read_u8(reader)?;
+        if version != ZEX_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported Zeal OS executable version: {} (this compiler reads {})", version, ZEX_VERSION),
+            ));
+        }
+        let flags = read_u8(reader)?;
+        let load_address = read_u16(reader)?;
+        let entry = read_u16(reader)?;
+        let image_size = read_u16(reader)?;
+        let bss_size = read_u16(reader)?;
+        let count = read_u16(reader)?;
+        let image = read_bytes(reader, image_size as usize)?;
+        let relocations = (0..count).map(|_| read_u16(reader)).collect::<io::Result<Vec<_>>>()?;
+        Ok(ZealOsExecutable {
+            load_address,
+            entry,
+            image,
+            bss_size,
+            relocations,
+            relocatable: flags & ZEX_RELOCATABLE != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executable_round_trip() {
+        let image = LinkedImage {
+            origin: 0x4000,
+            bytes: vec![0xCD, 0x03, 0x40, 0xC9],
+            bss_start: 0x4004,
+            bss_size: 8,
+            symbols: vec![],
+            relocations: vec![1],
+            relocatable: true,
+        };
+        let executable = ZealOsExecutable::new(&image, 0x4000);
+        let mut bytes = Vec::new();
+        executable.write(&mut bytes).unwrap();
+        assert_eq!(bytes[..ZEX_HEADER_SIZE], [
+            b'Z', b'E', b'X', 0, 1, 1, 0x00, 0x40, 0x00, 0x40, 4, 0, 8, 0, 1, 0
+        ]);
+        assert_eq!(bytes[ZEX_HEADER_SIZE..], [0xCD, 0x03, 0x40, 0xC9, 1, 0]);
+        assert_eq!(ZealOsExecutable::read(&mut bytes.as_slice()).unwrap(), executable);
+
+        bytes[4] = 2;
+        assert!(ZealOsExecutable::read(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -237,6 +237,28 @@ impl super::Parser {
         }))
     }
 
+    /// Parse the directives after a method heading: { VIRTUAL ; | OVERRIDE ; }
+    pub(crate) fn parse_method_binding(&mut self) -> ParserResult<ast::MethodBinding> {
+        let mut binding = ast::MethodBinding::Static;
+        while self.check(&TokenKind::KwVirtual) || self.check(&TokenKind::KwOverride) {
+            let directive = self.advance_and_get_token()?;
+            let given = if directive.kind == TokenKind::KwVirtual {
+                ast::MethodBinding::Virtual
+            } else {
+                ast::MethodBinding::Override
+            };
+            if binding != ast::MethodBinding::Static && binding != given {
+                return Err(ParserError::InvalidSyntax {
+                    message: "VIRTUAL and OVERRIDE cannot both be given".to_string(),
+                    span: directive.span,
+                });
+            }
+            binding = given;
+            self.consume(TokenKind::Semicolon, ";")?;
+        }
+        Ok(binding)
+    }
+
     /// Parse constructor declaration: CONSTRUCTOR identifier [ ( params ) ] ;
     fn parse_constructor_decl(&mut self) -> ParserResult<Node> {
         let start_span = self
@@ -262,6 +284,7 @@ impl super::Parser {
         };
 
         self.consume(TokenKind::Semicolon, ";")?;
+        let binding = self.parse_method_binding()?;
 
         // Create an empty block for forward declarations
         let empty_block = Node::Block(ast::Block {
@@ -289,6 +312,7 @@ impl super::Parser {
             is_external: false,
             external_name: None,
             is_class_method: false, // Constructors are not class methods
+            binding,
             span,
         }))
     }
@@ -318,6 +342,7 @@ impl super::Parser {
         };
 
         self.consume(TokenKind::Semicolon, ";")?;
+        let binding = self.parse_method_binding()?;
 
         // Create an empty block for forward declarations
         let empty_block = Node::Block(ast::Block {
@@ -345,6 +370,7 @@ impl super::Parser {
             is_external: false,
             external_name: None,
             is_class_method: false, // Destructors are not class methods
+            binding,
             span,
         }))
     }
@@ -607,6 +633,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_virtual_methods_and_method_calls() {
+        let source = r#"
+            program Test;
+            type
+                TShape = class
+                    function Area: integer; virtual;
+                    procedure Draw; virtual;
+                    destructor Destroy; virtual;
+                end;
+                TSquare = class(TShape)
+                    function Area: integer; override;
+                    procedure Grow(By: integer);
+                end;
+            var S: TShape;
+            begin
+                S.Draw;
+                S.Grow(2);
+                X := S.Area() + 1
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let Ok(Node::Program(program)) = parser.parse() else { panic!("Parse failed") };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected Block") };
+        let bindings = |decl: &Node| {
+            let Node::TypeDecl(type_decl) = decl else { panic!("Expected TypeDecl") };
+            let Node::ClassType(class_type) = type_decl.type_expr.as_ref() else { panic!("Expected ClassType") };
+            class_type
+                .members
+                .iter()
+                .map(|(_, member)| match member {
+                    ast::ClassMember::Method(Node::ProcDecl(p))
+                    | ast::ClassMember::Destructor(Node::ProcDecl(p)) => p.binding,
+                    ast::ClassMember::Method(Node::FuncDecl(f)) => f.binding,
+                    other => panic!("Unexpected member {:?}", other),
+                })
+                .collect::<Vec<_>>()
+        };
+        use ast::MethodBinding::*;
+        assert_eq!(bindings(&block.type_decls[0]), [Virtual, Virtual, Virtual]);
+        assert_eq!(bindings(&block.type_decls[1]), [Override, Static]);
+
+        let calls: Vec<_> = block.statements.iter().map(|stmt| match stmt {
+            Node::MethodCall(call) => (call.method.as_str(), call.args.len()),
+            Node::AssignStmt(assign) => match assign.value.as_ref() {
+                Node::BinaryExpr(binary) => match binary.left.as_ref() {
+                    Node::MethodCall(call) => (call.method.as_str(), call.args.len()),
+                    other => panic!("Expected MethodCall, got {:?}", other),
+                },
+                other => panic!("Expected BinaryExpr, got {:?}", other),
+            },
+            other => panic!("Unexpected statement {:?}", other),
+        }).collect();
+        assert_eq!(calls, [("Draw", 0), ("Grow", 1), ("Area", 0)]);
+
+        let source = "program T; type C = class procedure P; virtual; override; end; begin end.";
+        assert!(Parser::new(source).unwrap().parse().is_err());
+    }
+
     #[test]
     fn test_parse_meta_class() {
         let source = r#"
@@ -18,7 +18,7 @@ impl super::Parser {
             }
             Err(e) => Err(ParserError::InvalidSyntax {
                 message: format!("Lexer error: {}", e),
-                span: self.current.as_ref().map(|t| t.span).unwrap_or_else(|| Span::at(0, 1, 1)),
+                span: e.span(),
             }),
         }
     }
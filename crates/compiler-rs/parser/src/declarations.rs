@@ -84,6 +84,13 @@ impl super::Parser {
         // Semicolon
         self.consume(TokenKind::Semicolon, ";")?;
 
+        // Optional uses clause
+        let uses = if self.check(&TokenKind::KwUses) {
+            Some(self.parse_uses_clause()?)
+        } else {
+            None
+        };
+
         // Block
         let mut block = self.parse_block()?;
         
@@ -137,6 +144,7 @@ impl super::Parser {
         Ok(Node::Program(ast::Program {
             name,
             directives,
+            uses,
             block: Box::new(block),
             span,
         }))
@@ -154,7 +162,7 @@ impl super::Parser {
         };
 
         // Parse directive type
-        let directive_type = DirectiveEvaluator::parse_directive(&content);
+        let directive_type = DirectiveEvaluator::parse_directive_token(&content, token.span);
         
         // Evaluate directive
         let (should_include, should_skip) = self.directive_evaluator_mut().evaluate(&directive_type, token.span)?;
@@ -171,7 +179,7 @@ impl super::Parser {
                 if is_directive {
                     let else_token = self.current().unwrap();
                     let else_content = match &else_token.kind {
-                        TokenKind::Directive(content) => content.clone(),
+                        TokenKind::Directive(content) => content.trim().to_string(),
                         _ => return Ok(None),
                     };
                     let else_span = else_token.span;
@@ -197,6 +205,82 @@ impl super::Parser {
                 return Ok(None);
             }
         }
+
+        // Handle ERROR directive - abort compilation if reached in an active branch
+        if let DirectiveType::Error(message) = &directive_type {
+            if should_include {
+                return Err(ParserError::InvalidSyntax {
+                    message: format!("User-defined error: {}", message),
+                    span: token.span,
+                });
+            }
+            return Ok(None);
+        }
+
+        // Handle LINK directive - record the module for the linker
+        if let DirectiveType::Link(filename) = &directive_type {
+            if should_include {
+                let path = self.resolve_include_path(filename).map_err(|_| ParserError::InvalidSyntax {
+                    message: format!("Linked module not found: '{}'", filename),
+                    span: token.span,
+                })?;
+                if !self.linked_modules.contains(&path) {
+                    self.linked_modules.push(path);
+                }
+            }
+            return Ok(None);
+        }
+
+        // Handle COMPILETIME directive - mark the function declaration after it
+        if let DirectiveType::CompileTime = &directive_type {
+            if should_include {
+                if !self.check(&TokenKind::KwFunction) {
+                    return Err(ParserError::InvalidSyntax {
+                        message: "{$COMPILETIME} must be followed by a function declaration".to_string(),
+                        span: token.span,
+                    });
+                }
+                self.compiletime_pending = true;
+            }
+            return Ok(None);
+        }
+
+        // Handle PURE directive - mark the function declaration after it
+        if let DirectiveType::Pure = &directive_type {
+            if should_include {
+                if !self.check(&TokenKind::KwFunction) {
+                    return Err(ParserError::InvalidSyntax {
+                        message: "{$PURE} must be followed by a function declaration".to_string(),
+                        span: token.span,
+                    });
+                }
+                self.pure_pending = true;
+            }
+            return Ok(None);
+        }
+
+        // Handle UNROLL directive - mark the FOR loop after it
+        if let DirectiveType::Unroll(factor) = &directive_type {
+            if should_include {
+                if !self.check(&TokenKind::KwFor) {
+                    return Err(ParserError::InvalidSyntax {
+                        message: "{$UNROLL} must be followed by a FOR loop".to_string(),
+                        span: token.span,
+                    });
+                }
+                self.unroll_pending = Some(*factor);
+            }
+            return Ok(None);
+        }
+
+        // Handle RESOURCE directive - compile the asset and declare its symbols
+        if let DirectiveType::Resource { path, name } = &directive_type {
+            if should_include {
+                return self.handle_resource_directive(path, name, token.span);
+            } else {
+                return Ok(None);
+            }
+        }
         
         // Only include directive in AST if it's active or if it's a control directive
         // Control directives (IFDEF, IFNDEF, IF, ELSEIF, ELSE, ENDIF) are included for debugging
@@ -215,7 +299,7 @@ impl super::Parser {
         
         if include_in_ast {
             Ok(Some(Node::Directive(ast::Directive {
-                content,
+                content: content.trim().to_string(),
                 span: token.span,
             })))
         } else {
@@ -238,12 +322,16 @@ impl super::Parser {
             })?;
         let canonical_str = canonical_path.to_string_lossy().to_string();
         
-        if self.included_files.contains(&canonical_str) {
+        if self.include_chain.contains(&canonical_str) {
             return Err(ParserError::InvalidSyntax {
                 message: format!("Circular include detected: '{}'", filename),
                 span,
             });
         }
+        // Include once: a file already included in this translation unit is skipped
+        if self.included_files.contains(&canonical_str) {
+            return Ok(None);
+        }
         
         // Read the file
         let file_content = fs::read_to_string(&file_path)
@@ -254,69 +342,83 @@ impl super::Parser {
         
         // Mark file as included
         self.included_files.insert(canonical_str.clone());
+        self.dependencies.push(file_path.clone());
         
         // Create a new parser for the included file
         let included_filename = Some(file_path.to_string_lossy().to_string());
-        let mut included_parser = super::Parser::new_with_file_and_symbols(
+        let mut included_parser = super::Parser::new_with_identifier_policy(
             &file_content,
             included_filename.clone(),
             self.directive_evaluator().defined_symbols().iter().cloned().collect(),
+            self.lexer.identifier_policy(),
         )?;
         
-        // Copy include paths and included files to the new parser
+        // Copy include paths, included files and switches to the new parser
         included_parser.include_paths = self.include_paths.clone();
         included_parser.included_files = self.included_files.clone();
+        included_parser.include_chain = self.include_chain.clone();
+        included_parser.include_chain.push(canonical_str);
+        included_parser.directive_evaluator_mut().copy_switches_from(self.directive_evaluator());
         
         // Parse the included file - it can contain:
         // 1. A block (declarations and statements with BEGIN...END)
         // 2. Just declarations (for header files)
         // 3. Just statements (for code files)
         // Try to parse as declarations-only first (most common for header files)
-        let included_ast = included_parser.parse_declarations_only()?;
+        let included_ast = included_parser.parse_declarations_only();
+        self.errors.append(&mut included_parser.errors);
+        let included_ast = included_ast?;
+        self.resources.append(&mut included_parser.resources);
+        for path in included_parser.linked_modules {
+            if !self.linked_modules.contains(&path) {
+                self.linked_modules.push(path);
+            }
+        }
+        self.included_files = included_parser.included_files;
+        self.dependencies.append(&mut included_parser.dependencies);
+        // Switches set by the included file stay in effect unless it used {$PUSH}/{$POP}
+        self.directive_evaluator.copy_switches_from(&included_parser.directive_evaluator);
         
         // Return the included content
         // The included block will be merged into the current context by the caller
         Ok(Some(included_ast))
     }
     
+    /// Handle {$RESOURCE} directive - convert the asset and parse its generated declarations
+    fn handle_resource_directive(&mut self, filename: &str, name: &str, span: tokens::Span) -> ParserResult<Option<Node>> {
+        let file_path = self.resolve_include_path(filename).map_err(|_| ParserError::InvalidSyntax {
+            message: format!("Resource file not found: '{}'", filename),
+            span,
+        })?;
+
+        let resource = resources::compile_resource(&file_path, name).map_err(|e| ParserError::InvalidSyntax {
+            message: format!("Cannot compile resource '{}': {}", filename, e),
+            span,
+        })?;
+
+        // The TMusicData type is declared once, ahead of the first music resource
+        let mut generated = String::new();
+        let is_music = |r: &resources::CompiledResource| matches!(r.kind, resources::ResourceKind::Music(_));
+        if is_music(&resource) && !self.resources.iter().any(is_music) {
+            generated.push_str(resources::music::MUSIC_DATA_DECLARATIONS);
+        }
+        generated.push_str(&resource.declarations());
+
+        // Parse the generated declarations as if they were an included header
+        let mut generated_parser = super::Parser::new_with_file(
+            &generated,
+            Some(file_path.to_string_lossy().to_string()),
+        )?;
+        let declarations = generated_parser.parse_declarations_only()?;
+
+        self.resources.push(resource);
+        self.dependencies.push(file_path);
+        Ok(Some(declarations))
+    }
+
     /// Resolve include file path (check current directory, then include paths)
     fn resolve_include_path(&self, filename: &str) -> ParserResult<std::path::PathBuf> {
-        use std::path::PathBuf;
-        
-        // If filename is absolute, use it directly
-        let path = PathBuf::from(filename);
-        if path.is_absolute() {
-            if path.exists() {
-                return Ok(path);
-            }
-        }
-        
-        // Try relative to current file's directory
-        if let Some(ref current_file) = self.filename {
-            if let Some(parent) = std::path::Path::new(current_file).parent() {
-                let candidate = parent.join(filename);
-                if candidate.exists() {
-                    return Ok(candidate);
-                }
-            }
-        }
-        
-        // Try include paths
-        for include_path in &self.include_paths {
-            let candidate = PathBuf::from(include_path).join(filename);
-            if candidate.exists() {
-                return Ok(candidate);
-            }
-        }
-        
-        // Try current directory
-        let candidate = PathBuf::from(filename);
-        if candidate.exists() {
-            return Ok(candidate);
-        }
-        
-        // Not found
-        Err(ParserError::InvalidSyntax {
+        find_include_file(filename, self.filename.as_deref(), &self.include_paths).ok_or_else(|| ParserError::InvalidSyntax {
             message: format!("Include file not found: '{}'", filename),
             span: tokens::Span::at(0, 1, 1),
         })
@@ -340,14 +442,15 @@ impl super::Parser {
                         continue;
                     }
                 };
-                let directive_type = DirectiveEvaluator::parse_directive(&content);
+                let directive_type = DirectiveEvaluator::parse_directive_token(&content, token_span);
                 
                 // Evaluate the directive to update state
                 let (_, _) = self.directive_evaluator_mut().evaluate(&directive_type, token_span)?;
                 
                 match directive_type {
                     DirectiveType::IfDef(_)
-                    | DirectiveType::IfNDef(_) => {
+                    | DirectiveType::IfNDef(_)
+                    | DirectiveType::If(_) => {
                         depth += 1; // Nested conditional
                         self.advance()?;
                     }
@@ -439,12 +542,7 @@ impl super::Parser {
             // Check for BEGIN - if present, parse statements
             if self.check(&TokenKind::KwBegin) {
                 self.advance()?; // consume BEGIN
-                while !self.check(&TokenKind::KwEnd) && !self.check(&TokenKind::Eof) {
-                    statements.push(self.parse_statement()?);
-                    if self.check(&TokenKind::Semicolon) {
-                        self.advance()?;
-                    }
-                }
+                statements.extend(self.parse_statement_list(&[])?);
                 if self.check(&TokenKind::KwEnd) {
                     self.advance()?; // consume END
                 }
@@ -464,7 +562,7 @@ impl super::Parser {
                 var_decls.extend(self.parse_var_decls()?);
             } else if self.check(&TokenKind::KwThreadvar) {
                 threadvar_decls.extend(self.parse_threadvar_decls()?);
-            } else if self.check(&TokenKind::KwProcedure) {
+            } else if self.check_procedure_start() {
                 proc_decls.push(self.parse_procedure_decl()?);
             } else if self.check(&TokenKind::KwFunction) {
                 func_decls.push(self.parse_function_decl()?);
@@ -477,6 +575,7 @@ impl super::Parser {
                 // Unknown token - might be a statement (for code-only includes)
                 // Try to parse as statement, but if it fails, we're done
                 let _saved_pos = self.current().map(|t| t.span);
+                let recorded = self.errors.len();
                 match self.parse_statement() {
                     Ok(stmt) => {
                         statements.push(stmt);
@@ -486,6 +585,7 @@ impl super::Parser {
                     }
                     Err(_) => {
                         // Not a statement - we're done parsing
+                        self.errors.truncate(recorded);
                         break;
                     }
                 }
@@ -573,7 +673,7 @@ impl super::Parser {
                 var_decls.extend(self.parse_var_decls()?);
             } else if self.check(&TokenKind::KwThreadvar) {
                 threadvar_decls.extend(self.parse_threadvar_decls()?);
-            } else if self.check(&TokenKind::KwProcedure) {
+            } else if self.check_procedure_start() {
                 proc_decls.push(self.parse_procedure_decl()?);
             } else if self.check(&TokenKind::KwFunction) {
                 func_decls.push(self.parse_function_decl()?);
@@ -589,14 +689,7 @@ impl super::Parser {
 
         // Statements
         // Note: parse_statement is in statements.rs module
-        let mut statements = vec![];
-        while !self.check(&TokenKind::KwEnd) {
-            statements.push(self.parse_statement()?);
-            // Optional semicolon between statements
-            if self.check(&TokenKind::Semicolon) {
-                self.advance()?;
-            }
-        }
+        let statements = self.parse_statement_list(&[])?;
 
         // END
         let end_token = self.consume(TokenKind::KwEnd, "END")?;
@@ -667,9 +760,26 @@ impl super::Parser {
     /// Parse constant declarations: CONST const_decl { ; const_decl }
     pub(crate) fn parse_const_decls(&mut self) -> ParserResult<Vec<Node>> {
         self.consume(TokenKind::KwConst, "CONST")?;
+        self.parse_declaration_list(Self::parse_const_decl)
+    }
+
+    /// Parse declarations separated by semicolons while each starts with an
+    /// identifier, recovering from errors in each
+    fn parse_declaration_list(&mut self, mut parse: impl FnMut(&mut Self) -> ParserResult<Node>) -> ParserResult<Vec<Node>> {
         let mut decls = vec![];
         loop {
-            decls.push(self.parse_const_decl()?);
+            let start = self.position();
+            match parse(self) {
+                Ok(decl) => decls.push(decl),
+                Err(error) => {
+                    // Recovery skips the semicolon
+                    self.recover_declaration(error, start)?;
+                    if self.check(&TokenKind::Identifier(String::new())) {
+                        continue;
+                    }
+                    break;
+                }
+            }
             if !self.check(&TokenKind::Semicolon) {
                 break;
             }
@@ -681,7 +791,7 @@ impl super::Parser {
         Ok(decls)
     }
 
-    /// Parse single constant declaration: identifier = expression
+    /// Parse single constant declaration: identifier [: type] = expression
     fn parse_const_decl(&mut self) -> ParserResult<Node> {
         let start_span = self
             .current()
@@ -697,12 +807,23 @@ impl super::Parser {
             }),
         };
 
+        self.directive_evaluator_mut().declare(&name);
+
+        // Typed constant: identifier : type = expression
+        let type_expr = if self.check(&TokenKind::Colon) {
+            self.advance()?;
+            Some(Box::new(self.parse_type()?))
+        } else {
+            None
+        };
+
         self.consume(TokenKind::Equal, "=")?;
         let value = self.parse_expression()?;
 
         let span = start_span.merge(value.span());
         Ok(Node::ConstDecl(ast::ConstDecl {
             name,
+            type_expr,
             value: Box::new(value),
             is_resourcestring: false, // Set to true when parsing RESOURCESTRING section
             span,
@@ -712,57 +833,26 @@ impl super::Parser {
     /// Parse threadvar declarations: THREADVAR var_decl { ; var_decl }
     pub(crate) fn parse_threadvar_decls(&mut self) -> ParserResult<Vec<Node>> {
         self.consume(TokenKind::KwThreadvar, "THREADVAR")?;
-        let mut decls = vec![];
-        loop {
-            decls.push(self.parse_var_decl()?);
-            if !self.check(&TokenKind::Semicolon) {
-                break;
-            }
-            self.advance()?;
-            if !matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) {
-                break;
-            }
-        }
-        Ok(decls)
+        self.parse_declaration_list(Self::parse_var_decl)
     }
 
     /// Parse resourcestring declarations: RESOURCESTRING const_decl { ; const_decl }
     pub(crate) fn parse_resourcestring_decls(&mut self) -> ParserResult<Vec<Node>> {
         self.consume(TokenKind::KwResourcestring, "RESOURCESTRING")?;
-        let mut decls = vec![];
-        loop {
-            let mut const_decl = self.parse_const_decl()?;
+        self.parse_declaration_list(|parser| {
+            let mut const_decl = parser.parse_const_decl()?;
             // Mark as resourcestring
             if let Node::ConstDecl(ref mut c) = const_decl {
                 c.is_resourcestring = true;
             }
-            decls.push(const_decl);
-            if !self.check(&TokenKind::Semicolon) {
-                break;
-            }
-            self.advance()?;
-            if !matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) {
-                break;
-            }
-        }
-        Ok(decls)
+            Ok(const_decl)
+        })
     }
 
     /// Parse type declarations: TYPE type_decl { ; type_decl }
     pub(crate) fn parse_type_decls(&mut self) -> ParserResult<Vec<Node>> {
         self.consume(TokenKind::KwType, "TYPE")?;
-        let mut decls = vec![];
-        loop {
-            decls.push(self.parse_type_decl()?);
-            if !self.check(&TokenKind::Semicolon) {
-                break;
-            }
-            self.advance()?;
-            if !matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) {
-                break;
-            }
-        }
-        Ok(decls)
+        self.parse_declaration_list(Self::parse_type_decl)
     }
 
     /// Parse single type declaration: identifier = type
@@ -781,6 +871,8 @@ impl super::Parser {
             }),
         };
 
+        self.directive_evaluator_mut().declare(&name);
+
         // Check for generic type parameters: <T> or <T: constraint>
         let generic_params = if self.check(&TokenKind::Less) {
             self.parse_generic_type_parameters()?
@@ -789,6 +881,11 @@ impl super::Parser {
         };
 
         self.consume(TokenKind::Equal, "=")?;
+        // `= type T` declares a distinct type
+        let distinct = self.check(&TokenKind::KwType);
+        if distinct {
+            self.advance()?;
+        }
         let type_expr = self.parse_type()?;
 
         let span = start_span.merge(type_expr.span());
@@ -796,6 +893,7 @@ impl super::Parser {
             name,
             generic_params,
             type_expr: Box::new(type_expr),
+            distinct,
             span,
         }))
     }
@@ -808,18 +906,7 @@ impl super::Parser {
     /// Parse variable declarations with optional class var flag
     pub(crate) fn parse_var_decls_with_class_flag(&mut self, is_class_var: bool) -> ParserResult<Vec<Node>> {
         self.consume(TokenKind::KwVar, "VAR")?;
-        let mut decls = vec![];
-        loop {
-            decls.push(self.parse_var_decl_with_class_flag(is_class_var)?);
-            if !self.check(&TokenKind::Semicolon) {
-                break;
-            }
-            self.advance()?;
-            if !matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) {
-                break;
-            }
-        }
-        Ok(decls)
+        self.parse_declaration_list(|parser| parser.parse_var_decl_with_class_flag(is_class_var))
     }
 
     /// Parse single variable declaration: identifier_list : type [ABSOLUTE expression]
@@ -844,6 +931,7 @@ impl super::Parser {
                     span: name_token.span,
                 }),
             };
+            self.directive_evaluator_mut().declare(&name);
             names.push(name);
 
             if !self.check(&TokenKind::Comma) {
@@ -916,6 +1004,9 @@ impl super::Parser {
 
         // Parse method name: ClassName.MethodName or just MethodName
         let (class_name, name) = self.parse_qualified_name()?;
+        if class_name.is_none() {
+            self.directive_evaluator_mut().declare(&name);
+        }
 
         // Check for generic type parameters: <T> or <T: constraint>
         let generic_params = if self.check(&TokenKind::Less) {
@@ -958,6 +1049,7 @@ impl super::Parser {
             is_external: false,
             external_name: None,
             is_class_method: false, // Forward declarations can't be class methods
+            binding: ast::MethodBinding::Static,
             span,
         }))
     }
@@ -973,6 +1065,9 @@ impl super::Parser {
 
         // Parse method name: ClassName.MethodName or just MethodName
         let (class_name, name) = self.parse_qualified_name()?;
+        if class_name.is_none() {
+            self.directive_evaluator_mut().declare(&name);
+        }
 
         // Check for generic type parameters: <T> or <T: constraint>
         let generic_params = if self.check(&TokenKind::Less) {
@@ -1018,6 +1113,9 @@ impl super::Parser {
             is_external: false,
             external_name: None,
             is_class_method: false, // Forward declarations can't be class methods
+            is_compiletime: false,
+            is_pure: false,
+            binding: ast::MethodBinding::Static,
             span,
         }))
     }
@@ -1035,6 +1133,11 @@ impl super::Parser {
         self.parse_procedure_decl_impl(true)
     }
 
+    /// Whether a procedure, or the body of a constructor or destructor, starts here
+    pub(crate) fn check_procedure_start(&self) -> bool {
+        self.check(&TokenKind::KwProcedure) || self.check(&TokenKind::KwConstructor) || self.check(&TokenKind::KwDestructor)
+    }
+
     /// Internal implementation with context flag
     fn parse_procedure_decl_impl(&mut self, in_class_context: bool) -> ParserResult<Node> {
         let start_span = self
@@ -1050,10 +1153,18 @@ impl super::Parser {
             false
         };
 
-        self.consume(TokenKind::KwProcedure, "PROCEDURE")?;
+        // The body of a constructor or destructor is declared like a procedure's
+        if self.check(&TokenKind::KwConstructor) || self.check(&TokenKind::KwDestructor) {
+            self.advance()?;
+        } else {
+            self.consume(TokenKind::KwProcedure, "PROCEDURE")?;
+        }
 
         // Parse method name: ClassName.MethodName or just MethodName
         let (class_name, name) = self.parse_qualified_name()?;
+        if class_name.is_none() {
+            self.directive_evaluator_mut().declare(&name);
+        }
 
         // Check for generic type parameters: <T> or <T: constraint>
         let generic_params = if self.check(&TokenKind::Less) {
@@ -1116,6 +1227,7 @@ impl super::Parser {
                 is_external: false,
                 external_name: None,
                 is_class_method,
+                binding: ast::MethodBinding::Static,
                 span,
             }));
         } else if self.check(&TokenKind::KwLabel) ||
@@ -1139,6 +1251,7 @@ impl super::Parser {
                 is_external: false,
                 external_name: None,
                 is_class_method,
+                binding: ast::MethodBinding::Static,
                 span,
             }));
         } else if in_class_context {
@@ -1160,6 +1273,7 @@ impl super::Parser {
                 is_external: false,
                 external_name: None,
                 is_class_method,
+                binding: ast::MethodBinding::Static,
                 span,
             }));
         } else {
@@ -1182,6 +1296,7 @@ impl super::Parser {
             span: start_span,
         });
 
+        let binding = if in_class_context { self.parse_method_binding()? } else { ast::MethodBinding::Static };
         let span = start_span;
         Ok(Node::ProcDecl(ast::ProcDecl {
             name,
@@ -1193,6 +1308,7 @@ impl super::Parser {
             is_external,
             external_name,
             is_class_method,
+            binding,
             span,
         }))
     }
@@ -1214,6 +1330,9 @@ impl super::Parser {
             .map(|t| t.span)
             .unwrap_or_else(|| Span::at(0, 1, 1));
 
+        let is_compiletime = std::mem::take(&mut self.compiletime_pending);
+        let is_pure = std::mem::take(&mut self.pure_pending);
+
         // Check for CLASS keyword (class function)
         let is_class_method = if self.check(&TokenKind::KwClass) {
             self.advance()?; // consume CLASS
@@ -1226,6 +1345,9 @@ impl super::Parser {
 
         // Parse method name: ClassName.MethodName or just MethodName
         let (class_name, name) = self.parse_qualified_name()?;
+        if class_name.is_none() {
+            self.directive_evaluator_mut().declare(&name);
+        }
 
         // Check for generic type parameters: <T> or <T: constraint>
         let generic_params = if self.check(&TokenKind::Less) {
@@ -1291,6 +1413,9 @@ impl super::Parser {
                 is_external: false,
                 external_name: None,
                 is_class_method,
+                is_compiletime,
+                is_pure,
+                binding: ast::MethodBinding::Static,
                 span,
             }));
         } else if self.check(&TokenKind::KwLabel) ||
@@ -1315,6 +1440,9 @@ impl super::Parser {
                 is_external: false,
                 external_name: None,
                 is_class_method,
+                is_compiletime,
+                is_pure,
+                binding: ast::MethodBinding::Static,
                 span,
             }));
         } else if in_class_context {
@@ -1337,6 +1465,9 @@ impl super::Parser {
                 is_external: false,
                 external_name: None,
                 is_class_method,
+                is_compiletime,
+                is_pure,
+                binding: ast::MethodBinding::Static,
                 span,
             }));
         } else {
@@ -1359,6 +1490,7 @@ impl super::Parser {
             span: start_span,
         });
 
+        let binding = if in_class_context { self.parse_method_binding()? } else { ast::MethodBinding::Static };
         let span = start_span.merge(return_type.span());
         Ok(Node::FuncDecl(ast::FuncDecl {
             name,
@@ -1371,6 +1503,9 @@ impl super::Parser {
             is_external,
             external_name,
             is_class_method,
+            is_compiletime,
+            is_pure,
+            binding,
             span,
         }))
     }
@@ -1604,7 +1739,10 @@ impl super::Parser {
         Ok(params)
     }
 
-    /// Parse parameter: [VAR | CONST | CONSTREF | OUT] identifier_list : type [= default_value]
+    /// Parse parameter: [VAR | CONST | CONSTREF | OUT] identifier_list [: type [= default_value]]
+    ///
+    /// The type may only be omitted for by-reference parameters (untyped
+    /// `var Buf`), which pass just an address.
     pub(crate) fn parse_param(&mut self) -> ParserResult<ast::Param> {
         let start_span = self
             .current()
@@ -1628,6 +1766,7 @@ impl super::Parser {
         };
 
         let mut names = vec![];
+        let mut names_span;
         loop {
             let name_token = self.consume(TokenKind::Identifier(String::new()), "identifier")?;
             let name = match &name_token.kind {
@@ -1638,6 +1777,7 @@ impl super::Parser {
                 }),
             };
             names.push(name);
+            names_span = start_span.merge(name_token.span);
 
             if !self.check(&TokenKind::Comma) {
                 break;
@@ -1645,6 +1785,15 @@ impl super::Parser {
             self.advance()?;
         }
 
+        if param_type != ast::ParamType::Value && !self.check(&TokenKind::Colon) {
+            return Ok(ast::Param {
+                names,
+                param_type,
+                type_expr: None,
+                default_value: None,
+                span: names_span,
+            });
+        }
         self.consume(TokenKind::Colon, ":")?;
         let type_expr = self.parse_type()?;
 
@@ -1663,13 +1812,33 @@ impl super::Parser {
         Ok(ast::Param {
             names,
             param_type,
-            type_expr: Box::new(type_expr),
+            type_expr: Some(Box::new(type_expr)),
             default_value,
             span,
         })
     }
 }
 
+/// Find an included file: as an absolute path, next to `current_file`, in
+/// the include paths, or in the working directory
+pub(crate) fn find_include_file(filename: &str, current_file: Option<&str>, include_paths: &[String]) -> Option<std::path::PathBuf> {
+    use std::path::{Path, PathBuf};
+
+    let path = PathBuf::from(filename);
+    if path.is_absolute() && path.exists() {
+        return Some(path);
+    }
+    let beside_current = current_file
+        .and_then(|file| Path::new(file).parent())
+        .map(|parent| parent.join(filename));
+    beside_current
+        .into_iter()
+        .chain(include_paths.iter().map(|dir| PathBuf::from(dir).join(filename)))
+        .chain([path])
+        .find(|candidate| candidate.exists())
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::super::Parser;
@@ -2729,6 +2898,84 @@ mod tests {
 
     // ========== Advanced Declarations Tests ==========
 
+    #[test]
+    fn test_parse_typed_const() {
+        let source = r#"
+            program Test;
+            const
+                Limit = 10;
+                Title: string = 'Hello';
+            begin
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+
+        if let Ok(Node::Program(program)) = result
+            && let Node::Block(block) = program.block.as_ref()
+        {
+            assert_eq!(block.const_decls.len(), 2);
+            let Node::ConstDecl(limit) = &block.const_decls[0] else { panic!("expected const") };
+            assert!(limit.type_expr.is_none());
+            let Node::ConstDecl(title) = &block.const_decls[1] else { panic!("expected const") };
+            assert!(title.type_expr.is_some());
+        }
+    }
+
+    #[test]
+    fn test_parse_distinct_type() {
+        let source = r#"
+            program Test;
+            type
+                TCount = word;
+                THandle = type word;
+            begin
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+
+        if let Ok(Node::Program(program)) = result
+            && let Node::Block(block) = program.block.as_ref()
+        {
+            let distinct: Vec<bool> = block
+                .type_decls
+                .iter()
+                .map(|decl| matches!(decl, Node::TypeDecl(t) if t.distinct))
+                .collect();
+            assert_eq!(distinct, vec![false, true]);
+        }
+    }
+
+    #[test]
+    fn test_parse_untyped_params() {
+        let source = r#"
+            program Test;
+            procedure Move(const Source; var Dest; Count: word);
+            begin
+            end;
+            begin
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+
+        if let Ok(Node::Program(program)) = result
+            && let Node::Block(block) = program.block.as_ref()
+            && let Node::ProcDecl(proc) = &block.proc_decls[0]
+        {
+            let typed: Vec<bool> = proc.params.iter().map(|p| p.type_expr.is_some()).collect();
+            assert_eq!(typed, vec![false, false, true]);
+        }
+
+        // Value parameters always need a type
+        let mut parser = Parser::new("program Test; procedure P(Count); begin end; begin end.").unwrap();
+        assert!(parser.parse().is_err());
+    }
+
     #[test]
     fn test_parse_threadvar() {
         let source = r#"
@@ -3045,6 +3292,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_inactive_regions() {
+        let source = "{$IFDEF DEBUG}\nprogram Test1;\nbegin end.\n{$ELSE}\nprogram Test2;\nbegin end.\n{$ENDIF}\n";
+        let mut parser = Parser::new(source).unwrap();
+        parser.parse().unwrap();
+        let regions = parser.inactive_regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(&source[regions[0].clone()], "\nprogram Test1;\nbegin end.\n");
+    }
+
+    #[test]
+    fn test_parse_with_if_expression() {
+        let source = r#"
+            const Size = 3;
+            {$IF Declared(Size) and (OPTION * 2 >= COMPILER_VERSION - COMPILER_VERSION + 8)}
+            program Test1;
+            begin end.
+            {$ELSE}
+            program Test2;
+            begin end.
+            {$ENDIF}
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        parser.define_constant("Option", 4);
+        // The constant declaration is not a program; parse it to record the name
+        parser.parse_const_decls().unwrap();
+        if let Ok(Node::Program(program)) = parser.parse() {
+            assert_eq!(program.name, "Test1");
+        } else {
+            panic!("Expected Program node");
+        }
+
+        let mut parser = Parser::new("{$IF 1 + Defined(X)}\nprogram T; begin end.\n{$ENDIF}").unwrap();
+        match parser.parse() {
+            Err(errors::ParserError::InvalidSyntax { message, span }) => {
+                assert!(message.contains("'+' cannot be applied"), "{}", message);
+                assert_eq!((span.start, span.column), (7, 8));
+            }
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_with_define() {
         let source = r#"
@@ -3272,10 +3561,286 @@ mod tests {
         
         let result = parser.parse();
         assert!(result.is_ok(), "Parse failed: {:?}", result);
+        let dependencies = parser.dependencies();
+        assert_eq!(dependencies.len(), 2);
+        assert!(dependencies[0].ends_with("header1.pas") && dependencies[1].ends_with("header2.pas"));
         
         // Cleanup
         let _ = fs::remove_file(&include_file1);
         let _ = fs::remove_file(&include_file2);
         let _ = fs::remove_dir(include_dir);
     }
+
+    #[test]
+    fn test_parse_include_once_and_push_pop() {
+        use std::fs;
+        use std::path::Path;
+
+        let include_dir = Path::new("test_includes_once");
+        let _ = fs::create_dir_all(include_dir);
+        let header = include_dir.join("header.pas");
+        let scoped = include_dir.join("scoped.pas");
+        fs::write(&header, "const Shared = 1;\n{$Q+}\n").expect("Failed to write header");
+        fs::write(&scoped, "{$PUSH}\n{$R-}\nconst Local = 2;\n{$POP}\n").expect("Failed to write scoped");
+
+        let source = r#"
+            program Test;
+            {$R+}
+            {$INCLUDE 'test_includes_once/header.pas'}
+            {$INCLUDE 'test_includes_once/scoped.pas'}
+            {$INCLUDE 'test_includes_once/header.pas'}
+            begin end.
+        "#;
+        let mut parser = Parser::new_with_file_and_symbols(source, Some("test_main.pas".to_string()), vec![]).unwrap();
+        parser.include_paths.push(".".to_string());
+        let result = parser.parse();
+
+        let _ = fs::remove_file(&header);
+        let _ = fs::remove_file(&scoped);
+        let _ = fs::remove_dir(include_dir);
+
+        let Ok(Node::Program(program)) = result else {
+            panic!("Parse failed: {:?}", result);
+        };
+        let Node::Block(block) = program.block.as_ref() else {
+            panic!("Expected block");
+        };
+        // The second include of header.pas is skipped
+        assert_eq!(block.const_decls.len(), 2);
+        // header.pas turned Q on without {$PUSH}; scoped.pas restored R
+        assert_eq!(parser.switch("Q"), Some(true));
+        assert_eq!(parser.switch("R"), Some(true));
+    }
+
+    #[test]
+    fn test_parse_codepage_literals() {
+        use errors::ParserError;
+
+        let source = "program Test;\n{$CODEPAGE CP437}\nconst A = 'Olá'; B = 'é';\nbegin end.";
+        let mut parser = Parser::new(source).unwrap();
+        assert!(parser.parse().is_ok());
+        assert_eq!(parser.codepage(), Some(resources::Codepage::Cp437));
+
+        // The error points at the unmappable character
+        let source = "program Test;\n{$CODEPAGE ZX}\nconst A = 'a^b';\nbegin end.";
+        match Parser::new(source).unwrap().parse() {
+            Err(ParserError::InvalidSyntax { message, span }) => {
+                assert_eq!(message, "Character '^' (U+005E) is not in code page ZX");
+                assert_eq!((span.line, span.column, span.end - span.start), (3, 13, 1));
+            }
+            other => panic!("Expected error, got {:?}", other),
+        }
+
+        // The target default applies until a directive overrides it
+        let source = "program Test;\nconst A = '©';\n{$CODEPAGE CP850}\nconst B = '©';\nbegin end.";
+        let mut parser = Parser::new(source).unwrap();
+        parser.set_codepage(resources::Codepage::Cp437);
+        assert!(matches!(parser.parse(), Err(ParserError::InvalidSyntax { span, .. }) if span.line == 2));
+    }
+
+    #[test]
+    fn test_parse_resource_directive() {
+        use std::fs;
+        use std::path::Path;
+
+        let resource_dir = Path::new("test_resources_directive");
+        let _ = fs::create_dir_all(resource_dir);
+        let resource_file = resource_dir.join("level.bin");
+        fs::write(&resource_file, [1u8, 2, 3, 4]).expect("Failed to write resource file");
+
+        let source = r#"
+            program Test;
+            {$RESOURCE 'test_resources_directive/level.bin' AS Level}
+            begin end.
+        "#;
+
+        let mut parser = Parser::new_with_file_and_symbols(
+            source,
+            Some("test_main.pas".to_string()),
+            vec![],
+        ).unwrap();
+        parser.include_paths.push(".".to_string());
+
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+
+        assert_eq!(parser.resources().len(), 1);
+        assert_eq!(parser.resources()[0].name, "Level");
+        assert_eq!(parser.resources()[0].data, vec![1, 2, 3, 4]);
+
+        let Ok(Node::Program(program)) = result else {
+            panic!("Expected Program node");
+        };
+        let Node::Block(block) = program.block.as_ref() else {
+            panic!("Expected Block node");
+        };
+        assert!(block.const_decls.iter().any(|c| matches!(c, Node::ConstDecl(c) if c.name == "Level_Size")));
+        assert!(block.var_decls.iter().any(|v| matches!(v, Node::VarDecl(v) if v.names == vec!["Level".to_string()])));
+
+        let _ = fs::remove_file(&resource_file);
+        let _ = fs::remove_dir(resource_dir);
+    }
+
+    #[test]
+    fn test_parse_music_resources_share_type() {
+        use std::fs;
+        use std::path::Path;
+
+        let resource_dir = Path::new("test_resources_music");
+        let _ = fs::create_dir_all(resource_dir);
+        let song_file = resource_dir.join("song.ay");
+        fs::write(&song_file, b"ZXAYEMUL\x00\x03").expect("Failed to write resource file");
+
+        let source = r#"
+            program Test;
+            {$RESOURCE 'test_resources_music/song.ay' AS Title}
+            {$RESOURCE 'test_resources_music/song.ay' AS Ending}
+            begin end.
+        "#;
+
+        let mut parser = Parser::new_with_file_and_symbols(
+            source,
+            Some("test_main.pas".to_string()),
+            vec![],
+        ).unwrap();
+        parser.include_paths.push(".".to_string());
+
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+        assert_eq!(parser.resources().len(), 2);
+
+        let Ok(Node::Program(program)) = result else {
+            panic!("Expected Program node");
+        };
+        let Node::Block(block) = program.block.as_ref() else {
+            panic!("Expected Block node");
+        };
+        let music_types = block
+            .type_decls
+            .iter()
+            .filter(|t| matches!(t, Node::TypeDecl(t) if t.name == "TMusicData"))
+            .count();
+        assert_eq!(music_types, 1);
+        assert!(block.var_decls.iter().any(|v| matches!(v, Node::VarDecl(v) if v.names == vec!["Ending".to_string()])));
+
+        let _ = fs::remove_file(&song_file);
+        let _ = fs::remove_dir(resource_dir);
+    }
+
+    #[test]
+    fn test_parse_error_directive_guarded_by_target_symbol() {
+        let source = r#"
+            {$IFNDEF ZXSPECTRUM}
+              {$ERROR ZXScreen requires the ZX Spectrum target}
+            {$ENDIF}
+            unit ZXScreen;
+            interface
+            implementation
+            end.
+        "#;
+
+        let mut parser = Parser::new(source).unwrap();
+        let result = parser.parse();
+        assert!(format!("{:?}", result).contains("User-defined error: ZXScreen requires the ZX Spectrum target"));
+
+        let mut parser = Parser::new_with_file_and_symbols(source, None, vec!["ZXSPECTRUM".to_string()]).unwrap();
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+    }
+
+    #[test]
+    fn test_parse_resource_directive_missing_file() {
+        let source = r#"
+            program Test;
+            {$RESOURCE 'does_not_exist.png' AS Missing}
+            begin end.
+        "#;
+
+        let mut parser = Parser::new(source).unwrap();
+        let result = parser.parse();
+        assert!(result.is_err());
+        assert!(format!("{:?}", result).contains("Resource file not found"));
+    }
+
+    #[test]
+    fn test_parse_link_directive() {
+        use std::fs;
+        use std::path::Path;
+
+        let link_dir = Path::new("test_link_modules");
+        let _ = fs::create_dir_all(link_dir);
+        let module = link_dir.join("fast.asm");
+        fs::write(&module, "FastFill:\n    ret\n").expect("Failed to write module");
+
+        let source = r#"
+            program Test;
+            {$L 'test_link_modules/fast.asm'}
+            {$IFDEF NEVER} {$L 'missing.asm'} {$ENDIF}
+            procedure FastFill(Dest: word); external;
+            begin end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        parser.include_paths.push(".".to_string());
+        let result = parser.parse();
+        let linked = parser.linked_modules().to_vec();
+
+        let _ = fs::remove_file(&module);
+        let _ = fs::remove_dir(link_dir);
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(linked, vec![Path::new(".").join("test_link_modules/fast.asm")]);
+
+        let source = "program Test;\n{$L 'missing.asm'}\nbegin end.";
+        let result = Parser::new(source).unwrap().parse();
+        assert!(format!("{:?}", result).contains("Linked module not found: 'missing.asm'"));
+    }
+
+    #[test]
+    fn test_parse_compiletime_function() {
+        let source = r#"
+            program Test;
+            {$COMPILETIME}
+            function Square(N: integer): integer;
+            begin
+              Square := N * N
+            end;
+            function Cube(N: integer): integer;
+            begin
+              Cube := N * N * N
+            end;
+            begin
+            end.
+        "#;
+        let program = Parser::new(source).unwrap().parse().unwrap();
+        let Node::Program(program) = program else { panic!("Expected program") };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected block") };
+        let marked: Vec<_> = block
+            .func_decls
+            .iter()
+            .map(|decl| match decl {
+                Node::FuncDecl(f) => (f.name.as_str(), f.is_compiletime),
+                other => panic!("Expected function, found {:?}", other),
+            })
+            .collect();
+        assert_eq!(marked, [("Square", true), ("Cube", false)]);
+        assert!(block.directives.is_empty());
+
+        let source = "program Test;\n{$COMPILETIME}\nprocedure P; begin end;\nbegin end.";
+        let result = Parser::new(source).unwrap().parse();
+        assert!(format!("{:?}", result).contains("{$COMPILETIME} must be followed by a function declaration"));
+    }
+
+    #[test]
+    fn test_parse_pure_function() {
+        let source = "program Test;\n{$PURE}\nfunction Twice(N: integer): integer; begin Twice := N + N end;\nbegin end.";
+        let program = Parser::new(source).unwrap().parse().unwrap();
+        let Node::Program(program) = program else { panic!("Expected program") };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected block") };
+        let Node::FuncDecl(function) = &block.func_decls[0] else { panic!("Expected function") };
+        assert!(function.is_pure && !function.is_compiletime);
+
+        let source = "program Test;\n{$PURE}\nprocedure P; begin end;\nbegin end.";
+        let result = Parser::new(source).unwrap().parse();
+        assert!(format!("{:?}", result).contains("{$PURE} must be followed by a function declaration"));
+    }
 }
@@ -0,0 +1,374 @@
+//! Expression evaluation for {$IF} and {$ELSEIF}
+//!
+//! Expressions are tokenized and evaluated with a Pratt parser using Pascal
+//! operator precedence, highest first:
+//! - `NOT`, unary `-` and `+`
+//! - `*`, `DIV`, `MOD`, `AND`, `SHL`, `SHR`
+//! - `+`, `-`, `OR`, `XOR`
+//! - `=`, `<>`, `<`, `<=`, `>`, `>=`
+//!
+//! `AND`, `OR`, `XOR` and `NOT` are logical on booleans and bitwise on
+//! integers. The functions `Defined(X)`, `Declared(X)` and `SizeOf(T)` are
+//! available. Other identifiers name numeric constants such as
+//! `COMPILER_VERSION`; an identifier that is not a constant is true when it is
+//! a defined symbol, so `{$IF DEBUG}` works like `{$IFDEF DEBUG}`.
+
+use std::collections::{HashMap, HashSet};
+
+use errors::{ParserError, ParserResult};
+use tokens::Span;
+
+/// Value of a directive expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "Integer",
+            Value::Boolean(_) => "Boolean",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Integer(i64),
+    Identifier(String),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+    End,
+}
+
+/// Symbols and constants visible to directive expressions
+pub(crate) struct Environment<'a> {
+    pub defined: &'a HashSet<String>,
+    pub declared: &'a HashSet<String>,
+    pub constants: &'a HashMap<String, i64>,
+}
+
+/// Evaluate `source` as a boolean directive expression. `span` locates the
+/// first byte of `source`; errors point at the offending token.
+pub(crate) fn evaluate(source: &str, span: Span, env: &Environment) -> ParserResult<bool> {
+    let mut parser = ExprParser { source, base: span, env, tokens: tokenize(source, span)?, pos: 0 };
+    let start = parser.span();
+    let value = parser.expression(0)?;
+    if parser.peek() != &Token::End {
+        return Err(parser.error("Expected end of expression".to_string()));
+    }
+    match value {
+        Value::Boolean(b) => Ok(b),
+        Value::Integer(_) => Err(ParserError::InvalidSyntax {
+            message: "{$IF} expression must be Boolean, found Integer".to_string(),
+            span: start,
+        }),
+    }
+}
+
+/// Span of `len` bytes at `offset` within the expression
+fn sub_span(base: Span, offset: usize, len: usize) -> Span {
+    Span::new(base.start + offset, base.start + offset + len, base.line, base.column + offset)
+}
+
+fn tokenize(source: &str, span: Span) -> ParserResult<Vec<(Token, usize, usize)>> {
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let token = if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() || c == b'$' {
+            let radix = if c == b'$' { i += 1; 16 } else { 10 };
+            let digits = i;
+            while i < bytes.len() && (bytes[i] as char).is_digit(radix) {
+                i += 1;
+            }
+            let value = i64::from_str_radix(&source[digits..i], radix).map_err(|_| ParserError::InvalidSyntax {
+                message: format!("Invalid number '{}'", &source[start..i.max(start + 1)]),
+                span: sub_span(span, start, i.max(start + 1) - start),
+            })?;
+            Token::Integer(value)
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let word = source[start..i].to_uppercase();
+            match ["NOT", "AND", "OR", "XOR", "DIV", "MOD", "SHL", "SHR"].into_iter().find(|op| *op == word) {
+                Some(op) => Token::Operator(op),
+                None => Token::Identifier(word),
+            }
+        } else if c == b'(' {
+            i += 1;
+            Token::LeftParen
+        } else if c == b')' {
+            i += 1;
+            Token::RightParen
+        } else {
+            let rest = &source[i..];
+            let op = ["<>", "<=", ">=", "==", "!=", "=", "<", ">", "+", "-", "*"]
+                .into_iter()
+                .find(|op| rest.starts_with(op))
+                .ok_or_else(|| ParserError::InvalidSyntax {
+                    message: format!("Unexpected character '{}' in {{$IF}} expression", rest.chars().next().unwrap()),
+                    span: sub_span(span, start, 1),
+                })?;
+            i += op.len();
+            // C-style spellings are accepted for compatibility
+            Token::Operator(match op {
+                "==" => "=",
+                "!=" => "<>",
+                op => op,
+            })
+        };
+        tokens.push((token, start, i - start));
+    }
+    tokens.push((Token::End, source.len(), 0));
+    Ok(tokens)
+}
+
+/// Binding power of an infix operator
+fn infix_power(op: &str) -> Option<u8> {
+    match op {
+        "=" | "<>" | "<" | "<=" | ">" | ">=" => Some(1),
+        "+" | "-" | "OR" | "XOR" => Some(2),
+        "*" | "DIV" | "MOD" | "AND" | "SHL" | "SHR" => Some(3),
+        _ => None,
+    }
+}
+
+const PREFIX_POWER: u8 = 4;
+
+struct ExprParser<'a> {
+    source: &'a str,
+    base: Span,
+    env: &'a Environment<'a>,
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn span(&self) -> Span {
+        let (_, offset, len) = self.tokens[self.pos];
+        sub_span(self.base, offset, len)
+    }
+
+    fn next(&mut self) -> (Token, Span) {
+        let span = self.span();
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        (token, span)
+    }
+
+    fn error(&self, message: String) -> ParserError {
+        ParserError::InvalidSyntax { message, span: self.span() }
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> ParserResult<()> {
+        if *self.peek() != token {
+            let found = match self.peek() {
+                Token::End => "end of expression".to_string(),
+                _ => format!("'{}'", &self.source[self.tokens[self.pos].1..][..self.tokens[self.pos].2]),
+            };
+            return Err(self.error(format!("Expected {}, found {}", what, found)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expression(&mut self, min_power: u8) -> ParserResult<Value> {
+        let mut left = self.prefix()?;
+        while let Token::Operator(op) = *self.peek() {
+            let Some(power) = infix_power(op) else { break };
+            if power <= min_power {
+                break;
+            }
+            let (_, op_span) = self.next();
+            let right = self.expression(power)?;
+            left = binary(op, left, right, op_span)?;
+        }
+        Ok(left)
+    }
+
+    fn prefix(&mut self) -> ParserResult<Value> {
+        let (token, span) = self.next();
+        match token {
+            Token::Integer(n) => Ok(Value::Integer(n)),
+            Token::LeftParen => {
+                let value = self.expression(0)?;
+                self.expect(Token::RightParen, "')'")?;
+                Ok(value)
+            }
+            Token::Operator(op @ ("NOT" | "-" | "+")) => {
+                match (op, self.expression(PREFIX_POWER)?) {
+                    ("NOT", Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+                    ("NOT", Value::Integer(n)) => Ok(Value::Integer(!n)),
+                    ("-", Value::Integer(n)) => Ok(Value::Integer(-n)),
+                    ("+", Value::Integer(n)) => Ok(Value::Integer(n)),
+                    (op, value) => Err(ParserError::InvalidSyntax {
+                        message: format!("Operator '{}' cannot be applied to {}", op, value.type_name()),
+                        span,
+                    }),
+                }
+            }
+            Token::Identifier(name) => self.identifier(name),
+            Token::End => Err(ParserError::InvalidSyntax {
+                message: "Expected expression, found end of expression".to_string(),
+                span,
+            }),
+            Token::RightParen | Token::Operator(_) => Err(ParserError::InvalidSyntax {
+                message: format!("Expected expression, found '{}'", &self.source[span.start - self.base.start..span.end - self.base.start]),
+                span,
+            }),
+        }
+    }
+
+    fn identifier(&mut self, name: String) -> ParserResult<Value> {
+        match name.as_str() {
+            "TRUE" => return Ok(Value::Boolean(true)),
+            "FALSE" => return Ok(Value::Boolean(false)),
+            "DEFINED" | "DECLARED" | "SIZEOF" => {
+                self.expect(Token::LeftParen, "'(' after function name")?;
+                let (argument, arg_span) = self.next();
+                let Token::Identifier(argument) = argument else {
+                    return Err(ParserError::InvalidSyntax {
+                        message: format!("{} expects an identifier", name),
+                        span: arg_span,
+                    });
+                };
+                self.expect(Token::RightParen, "')'")?;
+                return match name.as_str() {
+                    "DEFINED" => Ok(Value::Boolean(self.env.defined.contains(&argument))),
+                    "DECLARED" => Ok(Value::Boolean(self.env.declared.contains(&argument))),
+                    _ => size_of(&argument).map(Value::Integer).ok_or_else(|| ParserError::InvalidSyntax {
+                        message: format!("SizeOf in {{$IF}} expects a built-in type, found '{}'", argument),
+                        span: arg_span,
+                    }),
+                };
+            }
+            _ => {}
+        }
+        if let Some(&value) = self.env.constants.get(&name) {
+            return Ok(Value::Integer(value));
+        }
+        Ok(Value::Boolean(self.env.defined.contains(&name)))
+    }
+}
+
+/// Size in bytes of a built-in type
+fn size_of(name: &str) -> Option<i64> {
+    match name {
+        "BYTE" | "SHORTINT" | "CHAR" | "BOOLEAN" => Some(1),
+        "INTEGER" | "WORD" | "SMALLINT" | "POINTER" => Some(2),
+        _ => None,
+    }
+}
+
+fn binary(op: &str, left: Value, right: Value, span: Span) -> ParserResult<Value> {
+    use Value::{Boolean, Integer};
+    let value = match (left, right) {
+        (Integer(l), Integer(r)) => match op {
+            "=" => Boolean(l == r),
+            "<>" => Boolean(l != r),
+            "<" => Boolean(l < r),
+            "<=" => Boolean(l <= r),
+            ">" => Boolean(l > r),
+            ">=" => Boolean(l >= r),
+            "+" => Integer(l.wrapping_add(r)),
+            "-" => Integer(l.wrapping_sub(r)),
+            "*" => Integer(l.wrapping_mul(r)),
+            "AND" => Integer(l & r),
+            "OR" => Integer(l | r),
+            "XOR" => Integer(l ^ r),
+            "SHL" => Integer(l.wrapping_shl(r as u32)),
+            "SHR" => Integer(l.wrapping_shr(r as u32)),
+            "DIV" | "MOD" if r == 0 => {
+                return Err(ParserError::InvalidSyntax { message: "Division by zero".to_string(), span });
+            }
+            "DIV" => Integer(l / r),
+            _ => Integer(l % r),
+        },
+        (Boolean(l), Boolean(r)) => match op {
+            "=" => Boolean(l == r),
+            "<>" | "XOR" => Boolean(l != r),
+            "AND" => Boolean(l && r),
+            "OR" => Boolean(l || r),
+            _ => return Err(mismatch(op, left, right, span)),
+        },
+        _ => return Err(mismatch(op, left, right, span)),
+    };
+    Ok(value)
+}
+
+fn mismatch(op: &str, left: Value, right: Value, span: Span) -> ParserError {
+    ParserError::InvalidSyntax {
+        message: format!("Operator '{}' cannot be applied to {} and {}", op, left.type_name(), right.type_name()),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> ParserResult<bool> {
+        let defined = HashSet::from(["DEBUG".to_string()]);
+        let declared = HashSet::from(["TSPRITE".to_string()]);
+        let constants = HashMap::from([("COMPILER_VERSION".to_string(), 100)]);
+        let env = Environment { defined: &defined, declared: &declared, constants: &constants };
+        evaluate(source, Span::new(10, 10 + source.len(), 1, 5), &env)
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        assert!(eval("1 + 2 * 3 = 7").unwrap());
+        assert!(eval("(1 + 2) * 3 = 9").unwrap());
+        assert!(eval("-(8 div 3) = -2").unwrap());
+        assert!(eval("$10 shr 4 = 1").unwrap());
+        assert!(eval("defined(DEBUG) and not Defined(RELEASE)").unwrap());
+        assert!(eval("DEBUG or RELEASE").unwrap());
+    }
+
+    #[test]
+    fn test_functions_and_constants() {
+        assert!(eval("COMPILER_VERSION >= 100").unwrap());
+        assert!(eval("Declared(TSprite)").unwrap());
+        assert!(!eval("Declared(TTile)").unwrap());
+        assert!(eval("SizeOf(Pointer) = 2").unwrap());
+    }
+
+    fn error(source: &str) -> (String, Span) {
+        match eval(source) {
+            Err(ParserError::InvalidSyntax { message, span }) => (message, span),
+            other => panic!("expected an error for {:?}, got {:?}", source, other),
+        }
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let (message, span) = error("1 + TRUE = 2");
+        assert_eq!(message, "Operator '+' cannot be applied to Integer and Boolean");
+        assert_eq!(span, Span::new(12, 13, 1, 7));
+
+        let (_, span) = error("SizeOf(TSprite) = 4");
+        assert_eq!(span, Span::new(17, 24, 1, 12));
+
+        let (message, _) = error("(1 = 1");
+        assert_eq!(message, "Expected ')', found end of expression");
+
+        let (message, _) = error("2 + 2");
+        assert_eq!(message, "{$IF} expression must be Boolean, found Integer");
+    }
+}
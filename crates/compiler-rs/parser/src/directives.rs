@@ -3,9 +3,16 @@
 //! This module handles evaluation of compiler directives like {$IFDEF}, {$DEFINE}, etc.
 //! It maintains a symbol table of defined symbols and evaluates conditional compilation blocks.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use errors::{ParserError, ParserResult};
-use tokens::Span;
+use lexer::Lexer;
+use tokens::{Span, TokenKind};
+
+use crate::directive_expr::{self, Environment};
+
+/// Switch {$ALIGN 1} turns on: records are packed, as if declared `packed`
+pub(crate) const PACKED_RECORDS_SWITCH: &str = "PACKRECORDS";
 
 /// Directive type parsed from directive content
 #[derive(Debug, Clone, PartialEq)]
@@ -15,9 +22,9 @@ pub enum DirectiveType {
     /// {$IFNDEF symbol} - if symbol is not defined
     IfNDef(String),
     /// {$IF expression} - if expression evaluates to true
-    If(String), // Expression string to evaluate
+    If(Condition),
     /// {$ELSEIF expression} - else if expression evaluates to true
-    ElseIf(String), // Expression string to evaluate
+    ElseIf(Condition),
     /// {$ELSE} - else branch
     Else,
     /// {$ENDIF} - end conditional block
@@ -28,10 +35,168 @@ pub enum DirectiveType {
     Undef(String),
     /// {$INCLUDE 'filename'} - include a file
     Include(String),
+    /// {$RESOURCE 'filename' AS Name} - compile an asset into constant data
+    Resource { path: String, name: String },
+    /// {$L 'filename'}, {$LINK 'filename'} - link an assembly or object module
+    /// defining `external` routines
+    Link(String),
+    /// {$ERROR message} - stop compilation with a user-defined error
+    Error(String),
+    /// {$R+}, {$RANGE_CHECK ON}, {$MODE STRICT} - turn a compiler switch on or off
+    Switch(String, bool),
+    /// {$CODEPAGE name} - character set string and char literals are
+    /// transcoded into
+    Codepage(resources::Codepage),
+    /// {$RUNTIMEERRORS HALT|CODE|HANDLER name} - what happens on a runtime
+    /// error, for the whole program
+    RuntimeErrors(runtime_spec::RuntimeErrorStrategy),
+    /// {$COMPILETIME} - evaluate the function declared next at compile time
+    /// when it is called with constant arguments
+    CompileTime,
+    /// {$PURE} - the function declared next has no effect but its result,
+    /// which depends on its arguments alone; asserts it of an external one
+    Pure,
+    /// {$UNROLL n} - unroll the FOR loop that follows `n` times; 0 and 1
+    /// keep it from being unrolled
+    Unroll(u32),
+    /// {$PUSH} - save the compiler switches
+    Push,
+    /// {$POP} - restore the switches saved by the matching {$PUSH}
+    Pop,
+    /// Malformed directive; `offset` and `len` locate the problem from the
+    /// start of the directive token
+    Invalid { message: String, offset: usize, len: usize },
     /// Other directives (passed through without evaluation)
     Other(String),
 }
 
+/// Expression of an {$IF} or {$ELSEIF} directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    /// Expression text
+    pub expr: String,
+    /// Offset of the expression from the start of the directive token
+    pub offset: usize,
+}
+
+impl Condition {
+    /// Take everything after the `keyword_len` bytes of the directive name;
+    /// `base` is the offset of `content` in the directive token
+    fn after_keyword(content: &str, keyword_len: usize, base: usize) -> Option<Self> {
+        let rest = &content[keyword_len..];
+        let expr = rest.trim();
+        if expr.is_empty() {
+            return None;
+        }
+        Some(Self {
+            expr: expr.to_string(),
+            offset: base + keyword_len + rest.len() - rest.trim_start().len(),
+        })
+    }
+}
+
+/// Where a conditional symbol got its current state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolOrigin {
+    /// Defined for the target
+    Predefined,
+    /// Defined with `--define`
+    CommandLine,
+    /// Defined by {$DEFINE} on the line
+    Defined(usize),
+    /// Removed by {$UNDEF} on the line
+    Undefined(usize),
+}
+
+/// Source skipped by conditional compilation, and the directive that
+/// skipped it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRegion {
+    /// Byte range of the skipped source
+    pub range: Range<usize>,
+    /// The directive as written in diagnostics, e.g. `{$IFDEF FOO}`
+    pub directive: String,
+    /// Line of the directive
+    pub line: usize,
+    /// The symbols the conditional tests, whether each was defined and why
+    pub symbols: Vec<(String, bool, Option<SymbolOrigin>)>,
+}
+
+impl SkippedRegion {
+    /// Why the region was skipped, e.g. `{$IFDEF FOO} at line 12; FOO is
+    /// not defined`
+    pub fn explanation(&self) -> String {
+        let mut text = format!("{} at line {}", self.directive, self.line);
+        for (symbol, defined, origin) in &self.symbols {
+            let state = match (defined, origin) {
+                (true, Some(SymbolOrigin::Predefined)) => "is predefined for the target".to_string(),
+                (true, Some(SymbolOrigin::CommandLine)) => format!("is defined by --define {}", symbol),
+                (true, Some(SymbolOrigin::Defined(line))) => format!("is defined by {{$DEFINE {}}} at line {}", symbol, line),
+                (false, Some(SymbolOrigin::Undefined(line))) => format!("is undefined by {{$UNDEF {}}} at line {}", symbol, line),
+                (true, _) => "is defined".to_string(),
+                (false, _) => "is not defined".to_string(),
+            };
+            text.push_str(&format!("; {} {}", symbol, state));
+        }
+        text
+    }
+
+    /// Names the skipped source declares: constants, types, variables,
+    /// labels and routines. `source` is the file the region is in.
+    pub fn declared_names(&self, source: &str) -> Vec<String> {
+        #[derive(PartialEq)]
+        enum Scan {
+            Outside,
+            /// In a declaration section, before the `:` or `=` of a declaration
+            Names,
+            /// After the `:` or `=`, up to the `;` ending the declaration
+            Declaration,
+            /// After a routine keyword
+            Routine,
+        }
+        let Some(text) = source.get(self.range.clone()) else {
+            return vec![];
+        };
+        let mut lexer = Lexer::new(text);
+        let mut names = vec![];
+        let mut pending = vec![];
+        let mut scan = Scan::Outside;
+        // Source that does not lex ends the scan
+        while let Ok(token) = lexer.next_token() {
+            match token.kind {
+                TokenKind::Eof => break,
+                TokenKind::Directive(_) => {}
+                TokenKind::KwVar | TokenKind::KwConst | TokenKind::KwType | TokenKind::KwThreadvar | TokenKind::KwLabel => {
+                    pending.clear();
+                    scan = Scan::Names;
+                }
+                TokenKind::KwProcedure | TokenKind::KwFunction | TokenKind::KwConstructor | TokenKind::KwDestructor => {
+                    scan = Scan::Routine;
+                }
+                TokenKind::Identifier(name) if scan == Scan::Routine => {
+                    names.push(name);
+                    scan = Scan::Outside;
+                }
+                TokenKind::Identifier(name) if scan == Scan::Names => pending.push(name),
+                TokenKind::Comma if scan == Scan::Names => {}
+                TokenKind::Colon | TokenKind::Equal if scan == Scan::Names => {
+                    names.append(&mut pending);
+                    scan = Scan::Declaration;
+                }
+                TokenKind::Semicolon if scan == Scan::Declaration => scan = Scan::Names,
+                // A label list ends without a type
+                TokenKind::Semicolon if scan == Scan::Names => names.append(&mut pending),
+                _ if scan == Scan::Names || scan == Scan::Routine => {
+                    pending.clear();
+                    scan = Scan::Outside;
+                }
+                _ => {}
+            }
+        }
+        names
+    }
+}
+
 /// Directive evaluator for conditional compilation
 pub struct DirectiveEvaluator {
     /// Set of defined symbols
@@ -40,6 +205,31 @@ pub struct DirectiveEvaluator {
     conditional_stack: Vec<bool>,
     /// Whether we're currently in an active branch
     is_active: bool,
+    /// Identifiers declared so far, for `Declared()` in {$IF}
+    declared_identifiers: HashSet<String>,
+    /// Numeric constants visible to {$IF}
+    constants: HashMap<String, i64>,
+    /// Compiler switches set by directives
+    switches: HashMap<String, bool>,
+    /// Switches saved by {$PUSH}
+    switch_stack: Vec<HashMap<String, bool>>,
+    /// Character set selected by {$CODEPAGE}
+    codepage: Option<resources::Codepage>,
+    /// Strategy selected by the last {$RUNTIMEERRORS}
+    runtime_errors: Option<runtime_spec::RuntimeErrorStrategy>,
+    /// Byte offset where the current inactive region started
+    inactive_start: Option<usize>,
+    /// Byte ranges skipped by conditional compilation, in source order
+    inactive_regions: Vec<Range<usize>>,
+    /// Where each symbol got its current state
+    symbol_origins: HashMap<String, SymbolOrigin>,
+    /// Symbols tested by each open conditional, innermost last
+    tested_symbols: Vec<Vec<String>>,
+    /// The directive that started the current inactive region and the
+    /// state of the symbols it tests
+    skip_cause: Option<SkippedRegion>,
+    /// Regions skipped by conditional compilation, in source order
+    skipped_regions: Vec<SkippedRegion>,
 }
 
 impl DirectiveEvaluator {
@@ -49,6 +239,18 @@ impl DirectiveEvaluator {
             defined_symbols: HashSet::new(),
             conditional_stack: Vec::new(),
             is_active: true, // Start active (no conditionals yet)
+            declared_identifiers: HashSet::new(),
+            constants: HashMap::from([("COMPILER_VERSION".to_string(), compiler_version())]),
+            switches: HashMap::new(),
+            switch_stack: Vec::new(),
+            codepage: None,
+            runtime_errors: None,
+            inactive_start: None,
+            inactive_regions: Vec::new(),
+            symbol_origins: HashMap::new(),
+            tested_symbols: Vec::new(),
+            skip_cause: None,
+            skipped_regions: Vec::new(),
         }
     }
 
@@ -56,82 +258,198 @@ impl DirectiveEvaluator {
     pub fn with_symbols(symbols: Vec<String>) -> Self {
         let mut evaluator = Self::new();
         for symbol in symbols {
+            evaluator.symbol_origins.insert(symbol.to_uppercase(), SymbolOrigin::Predefined);
             evaluator.defined_symbols.insert(symbol.to_uppercase());
         }
         evaluator
     }
 
-    /// Parse directive content into a DirectiveType
+    /// Attribute the predefined `symbols` to `--define` rather than the target
+    pub fn mark_command_line(&mut self, symbols: &[String]) {
+        for symbol in symbols {
+            self.symbol_origins.insert(symbol.to_uppercase(), SymbolOrigin::CommandLine);
+        }
+    }
+
+    /// Parse directive content into a DirectiveType, assuming the content
+    /// follows `{$` in the source
+    #[allow(dead_code)] // Public API method, may be used by external code
     pub fn parse_directive(content: &str) -> DirectiveType {
+        Self::parse_directive_at(content, 2)
+    }
+
+    /// Parse the content of a directive token. The content of `{$...}` starts
+    /// two characters into the token and that of `(*$...*)` three.
+    pub(crate) fn parse_directive_token(content: &str, span: Span) -> DirectiveType {
+        let delimiters = span.end - span.start - content.len();
+        Self::parse_directive_at(content, if delimiters >= 5 { 3 } else { 2 })
+    }
+
+    /// Parse directive content whose first character is `prefix` characters
+    /// into the directive token; offsets in the result are from the token start
+    fn parse_directive_at(content: &str, prefix: usize) -> DirectiveType {
+        let base = prefix + content.len() - content.trim_start().len();
         let content = content.trim();
-        let parts: Vec<&str> = content.split_whitespace().collect();
-        
+        let parts = words(content);
+
         if parts.is_empty() {
             return DirectiveType::Other(content.to_string());
         }
 
-        let directive_name = parts[0].to_uppercase();
-        
+        let directive_name = parts[0].1.to_uppercase();
+        // Point at the closing delimiter when an argument is missing
+        let missing = |what: &str| DirectiveType::Invalid {
+            message: format!("Expected {} after {{${}}}", what, directive_name),
+            offset: base + content.len(),
+            len: 1,
+        };
+        let symbol = |make: fn(String) -> DirectiveType| match parts.get(1) {
+            Some((_, symbol)) => make(symbol.to_uppercase()),
+            None => missing("a symbol"),
+        };
+
         match directive_name.as_str() {
-            "IFDEF" => {
-                if parts.len() >= 2 {
-                    DirectiveType::IfDef(parts[1].to_uppercase())
-                } else {
-                    DirectiveType::Other(content.to_string())
+            "IFDEF" => symbol(DirectiveType::IfDef),
+            "IFNDEF" => symbol(DirectiveType::IfNDef),
+            "IF" => match Condition::after_keyword(content, 2, base) {
+                Some(condition) => DirectiveType::If(condition),
+                None => missing("an expression"),
+            },
+            "ELSEIF" => match Condition::after_keyword(content, 6, base) {
+                Some(condition) => DirectiveType::ElseIf(condition),
+                None => missing("an expression"),
+            },
+            "ELSE" => DirectiveType::Else,
+            "ENDIF" | "END" => DirectiveType::EndIf,
+            "DEFINE" => symbol(DirectiveType::Define),
+            "UNDEF" | "UNDEFINE" => symbol(DirectiveType::Undef),
+            "INCLUDE" | "I" => {
+                // Extract filename from string literal or identifier
+                match parts.get(1) {
+                    Some((_, filename)) => DirectiveType::Include(unquote(filename)),
+                    None => missing("a file name"),
                 }
             }
-            "IFNDEF" => {
-                if parts.len() >= 2 {
-                    DirectiveType::IfNDef(parts[1].to_uppercase())
-                } else {
-                    DirectiveType::Other(content.to_string())
-                }
+            // {$L+} and {$L ON} are switches
+            "L" | "LINK" if parts.len() == 2 && !parts[1].1.eq_ignore_ascii_case("ON") && !parts[1].1.eq_ignore_ascii_case("OFF") => {
+                DirectiveType::Link(unquote(parts[1].1))
             }
-            "IF" => {
-                // Extract everything after "IF" as the expression
-                if let Some(expr_start) = content[2..].trim_start().find(|c: char| !c.is_whitespace()) {
-                    let expr = content[2 + expr_start..].trim().to_string();
-                    DirectiveType::If(expr)
-                } else {
-                    DirectiveType::Other(content.to_string())
+            "LINK" => missing("a file name"),
+            "RESOURCE" => {
+                // RESOURCE <file> AS <identifier>
+                let unexpected = |index: usize, expected: &str| {
+                    let (offset, word) = parts[index];
+                    DirectiveType::Invalid {
+                        message: format!("Expected {} in {{$RESOURCE}}, found '{}'", expected, word),
+                        offset: base + offset,
+                        len: word.len(),
+                    }
+                };
+                match parts.len() {
+                    1 => missing("a file name"),
+                    2 => missing("AS"),
+                    _ if !parts[2].1.eq_ignore_ascii_case("AS") => unexpected(2, "AS"),
+                    3 => missing("a resource name"),
+                    4 => DirectiveType::Resource {
+                        path: unquote(parts[1].1),
+                        name: parts[3].1.to_string(),
+                    },
+                    _ => unexpected(4, "end of directive"),
                 }
             }
-            "ELSEIF" => {
-                // Extract everything after "ELSEIF" as the expression
-                if let Some(expr_start) = content[6..].trim_start().find(|c: char| !c.is_whitespace()) {
-                    let expr = content[6 + expr_start..].trim().to_string();
-                    DirectiveType::ElseIf(expr)
-                } else {
-                    DirectiveType::Other(content.to_string())
-                }
+            "ERROR" => {
+                // Everything after ERROR is the message
+                DirectiveType::Error(content[5..].trim().to_string())
             }
-            "ELSE" => DirectiveType::Else,
-            "ENDIF" | "END" => DirectiveType::EndIf,
-            "DEFINE" => {
-                if parts.len() >= 2 {
-                    DirectiveType::Define(parts[1].to_uppercase())
-                } else {
-                    DirectiveType::Other(content.to_string())
+            "CODEPAGE" => match parts.get(1) {
+                Some(&(offset, name)) => match resources::Codepage::from_name(name) {
+                    Some(codepage) => DirectiveType::Codepage(codepage),
+                    None => DirectiveType::Invalid {
+                        message: format!("Unknown code page '{}' (expected ASCII, CP437, CP850 or ZX)", name),
+                        offset: base + offset,
+                        len: name.len(),
+                    },
+                },
+                None => missing("a code page name"),
+            },
+            "RUNTIMEERRORS" => match parts.get(1) {
+                Some(&(offset, _)) => {
+                    let strategy = &content[offset..];
+                    match runtime_spec::RuntimeErrorStrategy::parse(strategy) {
+                        Some(strategy) => DirectiveType::RuntimeErrors(strategy),
+                        None => DirectiveType::Invalid {
+                            message: format!(
+                                "Unknown runtime error strategy '{}' (expected HALT, CODE or HANDLER name)",
+                                strategy
+                            ),
+                            offset: base + offset,
+                            len: strategy.len(),
+                        },
+                    }
                 }
-            }
-            "UNDEF" | "UNDEFINE" => {
-                if parts.len() >= 2 {
-                    DirectiveType::Undef(parts[1].to_uppercase())
-                } else {
-                    DirectiveType::Other(content.to_string())
+                None => missing("a runtime error strategy"),
+            },
+            "COMPILETIME" if parts.len() == 1 => DirectiveType::CompileTime,
+            "PURE" if parts.len() == 1 => DirectiveType::Pure,
+            "UNROLL" => match parts.get(1) {
+                Some(&(offset, count)) => match count.parse() {
+                    Ok(factor) if parts.len() == 2 => DirectiveType::Unroll(factor),
+                    Ok(_) => {
+                        let (offset, word) = parts[2];
+                        DirectiveType::Invalid {
+                            message: format!("Expected end of directive in {{$UNROLL}}, found '{}'", word),
+                            offset: base + offset,
+                            len: word.len(),
+                        }
+                    }
+                    Err(_) => DirectiveType::Invalid {
+                        message: format!("Expected an unroll count in {{$UNROLL}}, found '{}'", count),
+                        offset: base + offset,
+                        len: count.len(),
+                    },
+                },
+                None => missing("an unroll count"),
+            },
+            // {$ALIGN 1} packs the records declared after it, {$ALIGN 2}
+            // aligns their word fields again
+            "ALIGN" | "A" | "PACKRECORDS" => match parts.get(1) {
+                Some(&(_, "1")) if parts.len() == 2 => DirectiveType::Switch(PACKED_RECORDS_SWITCH.to_string(), true),
+                Some(&(_, "2")) if parts.len() == 2 => DirectiveType::Switch(PACKED_RECORDS_SWITCH.to_string(), false),
+                Some(&(offset, _)) => DirectiveType::Invalid {
+                    message: format!("Expected 1 or 2 in {{${}}}, found '{}'", directive_name, &content[offset..]),
+                    offset: base + offset,
+                    len: content.len() - offset,
+                },
+                None => missing("an alignment of 1 or 2"),
+            },
+            // {$MODE STRICT} selects the strict profile, and any other mode
+            // the default one
+            "MODE" if parts.len() == 2 => DirectiveType::Switch("STRICT".to_string(), parts[1].1.eq_ignore_ascii_case("STRICT")),
+            // {$WARN UNUSED_VAR OFF} turns a kind of warning off for the file
+            "WARN" => match parts.as_slice() {
+                [_, (_, name), (_, state)] if state.eq_ignore_ascii_case("ON") || state.eq_ignore_ascii_case("OFF") => {
+                    DirectiveType::Switch(format!("WARN_{}", name.to_uppercase()), state.eq_ignore_ascii_case("ON"))
                 }
+                [_] => missing("a warning name"),
+                [_, _] => missing("ON or OFF"),
+                [_, _, (offset, word), ..] => DirectiveType::Invalid {
+                    message: format!("Expected ON or OFF in {{$WARN}}, found '{}'", word),
+                    offset: base + offset,
+                    len: word.len(),
+                },
+                [] => DirectiveType::Other(content.to_string()),
+            },
+            // {$DEBUGHEAP} and {$ROUNDROBIN} alone turn their mode on
+            "DEBUGHEAP" | "ROUNDROBIN" if parts.len() == 1 => DirectiveType::Switch(directive_name, true),
+            "PUSH" if parts.len() == 1 => DirectiveType::Push,
+            "POP" if parts.len() == 1 => DirectiveType::Pop,
+            _ if parts.len() == 2 && (parts[1].1.eq_ignore_ascii_case("ON") || parts[1].1.eq_ignore_ascii_case("OFF")) => {
+                DirectiveType::Switch(directive_name, parts[1].1.eq_ignore_ascii_case("ON"))
             }
-            "INCLUDE" | "I" => {
-                // Extract filename from string literal or identifier
-                if parts.len() >= 2 {
-                    let filename = parts[1]
-                        .trim_matches('\'')
-                        .trim_matches('"')
-                        .to_string();
-                    DirectiveType::Include(filename)
-                } else {
-                    DirectiveType::Other(content.to_string())
-                }
+            // Short switches: {$R+}, {$Q-}
+            _ if parts.len() == 1 && (directive_name.ends_with('+') || directive_name.ends_with('-')) => {
+                let (name, state) = directive_name.split_at(directive_name.len() - 1);
+                DirectiveType::Switch(name.to_string(), state == "+")
             }
             _ => DirectiveType::Other(content.to_string()),
         }
@@ -140,6 +458,60 @@ impl DirectiveEvaluator {
     /// Evaluate a directive and update state
     /// Returns (should_include_code, should_skip_until_else_or_endif)
     pub fn evaluate(&mut self, directive: &DirectiveType, span: Span) -> ParserResult<(bool, bool)> {
+        let was_active = self.is_active;
+        let result = self.evaluate_state(directive, span)?;
+        match directive {
+            DirectiveType::IfDef(symbol) | DirectiveType::IfNDef(symbol) => self.tested_symbols.push(vec![symbol.clone()]),
+            DirectiveType::If(condition) => self.tested_symbols.push(self.symbols_in(&condition.expr)),
+            DirectiveType::ElseIf(condition) => {
+                let tested = self.symbols_in(&condition.expr);
+                if let Some(symbols) = self.tested_symbols.last_mut() {
+                    symbols.extend(tested);
+                }
+            }
+            DirectiveType::EndIf => {
+                self.tested_symbols.pop();
+            }
+            _ => {}
+        }
+        // An inactive region runs from the end of the directive that disabled
+        // compilation to the start of the one that re-enabled it
+        if was_active && !self.is_active {
+            self.inactive_start = Some(span.end);
+            self.skip_cause = Some(self.skipped_by(directive, span));
+        } else if !was_active && self.is_active
+            && let Some(start) = self.inactive_start.take()
+        {
+            self.inactive_regions.push(start..span.start);
+            if let Some(mut region) = self.skip_cause.take() {
+                region.range = start..span.start;
+                self.skipped_regions.push(region);
+            }
+        }
+        Ok(result)
+    }
+
+    /// The region `directive` at `span` starts skipping, with an empty range
+    fn skipped_by(&self, directive: &DirectiveType, span: Span) -> SkippedRegion {
+        let directive_text = match directive {
+            DirectiveType::IfDef(symbol) => format!("{{$IFDEF {}}}", symbol),
+            DirectiveType::IfNDef(symbol) => format!("{{$IFNDEF {}}}", symbol),
+            DirectiveType::If(condition) => format!("{{$IF {}}}", condition.expr),
+            DirectiveType::ElseIf(condition) => format!("{{$ELSEIF {}}}", condition.expr),
+            DirectiveType::Else => "{$ELSE}".to_string(),
+            other => format!("{:?}", other),
+        };
+        let mut symbols: Vec<(String, bool, Option<SymbolOrigin>)> = vec![];
+        for symbol in self.tested_symbols.last().into_iter().flatten() {
+            if !symbols.iter().any(|(name, _, _)| name == symbol) {
+                let defined = self.defined_symbols.contains(symbol);
+                symbols.push((symbol.clone(), defined, self.symbol_origins.get(symbol).copied()));
+            }
+        }
+        SkippedRegion { range: span.end..span.end, directive: directive_text, line: span.line, symbols }
+    }
+
+    fn evaluate_state(&mut self, directive: &DirectiveType, span: Span) -> ParserResult<(bool, bool)> {
         match directive {
             DirectiveType::IfDef(symbol) => {
                 let is_defined = self.defined_symbols.contains(symbol);
@@ -153,13 +525,14 @@ impl DirectiveEvaluator {
                 self.is_active = self.is_active && !is_defined;
                 Ok((self.is_active, !self.is_active))
             }
-            DirectiveType::If(expr) => {
-                let expr_result = self.evaluate_expression(expr)?;
+            DirectiveType::If(condition) => {
+                // Expressions in skipped regions are not evaluated
+                let expr_result = self.is_active && self.evaluate_expression(condition, span)?;
                 self.conditional_stack.push(self.is_active);
                 self.is_active = self.is_active && expr_result;
                 Ok((self.is_active, !self.is_active))
             }
-            DirectiveType::ElseIf(expr) => {
+            DirectiveType::ElseIf(condition) => {
                 if self.conditional_stack.is_empty() {
                     return Err(ParserError::InvalidSyntax {
                         message: "{$ELSEIF} without matching {$IF}, {$IFDEF}, or {$IFNDEF}".to_string(),
@@ -175,7 +548,7 @@ impl DirectiveEvaluator {
                         self.is_active = false;
                     } else {
                         // We're inactive, check if this expression makes us active
-                        let expr_result = self.evaluate_expression(expr)?;
+                        let expr_result = self.evaluate_expression(condition, span)?;
                         self.is_active = expr_result;
                     }
                 } else {
@@ -208,29 +581,74 @@ impl DirectiveEvaluator {
                         span,
                     });
                 }
-                self.conditional_stack.pop();
-                // Restore active state from parent
-                if let Some(&parent_active) = self.conditional_stack.last() {
-                    self.is_active = parent_active;
-                } else {
-                    self.is_active = true; // No more conditionals, we're active
-                }
+                // Restore the state from before the matching conditional
+                self.is_active = self.conditional_stack.pop().unwrap_or(true);
                 Ok((true, false)) // ENDIF itself is always processed
             }
             DirectiveType::Define(symbol) => {
                 if self.is_active {
                     self.defined_symbols.insert(symbol.clone());
+                    self.symbol_origins.insert(symbol.clone(), SymbolOrigin::Defined(span.line));
                 }
                 Ok((true, false)) // DEFINE is always processed if active
             }
             DirectiveType::Undef(symbol) => {
                 if self.is_active {
                     self.defined_symbols.remove(symbol);
+                    self.symbol_origins.insert(symbol.clone(), SymbolOrigin::Undefined(span.line));
                 }
                 Ok((true, false)) // UNDEF is always processed if active
             }
-            DirectiveType::Include(_) => {
-                // Include handling will be done separately
+            DirectiveType::Include(_)
+            | DirectiveType::Resource { .. }
+            | DirectiveType::Link(_)
+            | DirectiveType::Error(_)
+            | DirectiveType::CompileTime
+            | DirectiveType::Pure
+            | DirectiveType::Unroll(_) => {
+                // Include, resource, link, error and compile-time handling will be done separately
+                Ok((self.is_active, !self.is_active))
+            }
+            DirectiveType::Switch(name, state) => {
+                if self.is_active {
+                    self.switches.insert(name.clone(), *state);
+                }
+                Ok((self.is_active, !self.is_active))
+            }
+            DirectiveType::Codepage(codepage) => {
+                if self.is_active {
+                    self.codepage = Some(*codepage);
+                }
+                Ok((self.is_active, !self.is_active))
+            }
+            DirectiveType::RuntimeErrors(strategy) => {
+                if self.is_active {
+                    self.runtime_errors = Some(strategy.clone());
+                }
+                Ok((self.is_active, !self.is_active))
+            }
+            DirectiveType::Push => {
+                if self.is_active {
+                    self.switch_stack.push(self.switches.clone());
+                }
+                Ok((self.is_active, !self.is_active))
+            }
+            DirectiveType::Pop => {
+                if self.is_active {
+                    self.switches = self.switch_stack.pop().ok_or_else(|| ParserError::InvalidSyntax {
+                        message: "{$POP} without matching {$PUSH}".to_string(),
+                        span,
+                    })?;
+                }
+                Ok((self.is_active, !self.is_active))
+            }
+            DirectiveType::Invalid { message, offset, len } => {
+                if self.is_active {
+                    return Err(ParserError::InvalidSyntax {
+                        message: message.clone(),
+                        span: Span::new(span.start + offset, span.start + offset + len, span.line, span.column + offset),
+                    });
+                }
                 Ok((self.is_active, !self.is_active))
             }
             DirectiveType::Other(_) => {
@@ -240,11 +658,51 @@ impl DirectiveEvaluator {
         }
     }
 
+    /// State of a compiler switch, or None if no directive has set it
+    pub fn switch(&self, name: &str) -> Option<bool> {
+        self.switches.get(&name.to_uppercase()).copied()
+    }
+
+    /// Take over the switches, code page and runtime error strategy of
+    /// `other`, e.g. after parsing an included file
+    pub(crate) fn copy_switches_from(&mut self, other: &DirectiveEvaluator) {
+        self.switches = other.switches.clone();
+        self.codepage = other.codepage;
+        self.runtime_errors = other.runtime_errors.clone();
+    }
+
+    /// Character set for literals at the current position
+    pub fn codepage(&self) -> Option<resources::Codepage> {
+        self.codepage
+    }
+
+    /// Select the character set used until the next {$CODEPAGE}
+    pub fn set_codepage(&mut self, codepage: resources::Codepage) {
+        self.codepage = Some(codepage);
+    }
+
+    /// Runtime error strategy selected by {$RUNTIMEERRORS}, if any
+    pub fn runtime_errors(&self) -> Option<&runtime_spec::RuntimeErrorStrategy> {
+        self.runtime_errors.as_ref()
+    }
+
     /// Check if we're currently in an active compilation branch
     pub fn is_active(&self) -> bool {
         self.is_active
     }
 
+    /// Byte ranges of the source that were skipped by conditional compilation.
+    /// The directives themselves are not included.
+    pub fn inactive_regions(&self) -> &[Range<usize>] {
+        &self.inactive_regions
+    }
+
+    /// Regions skipped by conditional compilation with the directive that
+    /// skipped each, in source order
+    pub fn skipped_regions(&self) -> &[SkippedRegion] {
+        &self.skipped_regions
+    }
+
     /// Check if a symbol is defined
     #[allow(dead_code)] // Public API method, may be used by external code
     pub fn is_defined(&self, symbol: &str) -> bool {
@@ -262,188 +720,73 @@ impl DirectiveEvaluator {
         !self.conditional_stack.is_empty()
     }
 
-    /// Evaluate a preprocessor expression
-    /// Supports: Defined(SYMBOL), integer comparisons, boolean operators
-    fn evaluate_expression(&self, expr: &str) -> ParserResult<bool> {
-        let expr = expr.trim();
-        
-        // Try to parse as boolean expression with AND/OR first (they can contain other expressions)
-        if let Some(boolean_result) = self.evaluate_boolean_expression(expr) {
-            return Ok(boolean_result);
-        }
-        
-        // Handle Defined(SYMBOL) function
-        if expr.starts_with("Defined(") && expr.ends_with(')') {
-            let symbol = expr[8..expr.len()-1].trim().to_uppercase();
-            return Ok(self.defined_symbols.contains(&symbol));
-        }
-        
-        // Handle NOT Defined(SYMBOL)
-        if expr.starts_with("NOT ") {
-            let rest = expr[4..].trim();
-            if rest.starts_with("Defined(") && rest.ends_with(')') {
-                let symbol = rest[8..rest.len()-1].trim().to_uppercase();
-                return Ok(!self.defined_symbols.contains(&symbol));
-            }
-        }
-        
-        // Handle boolean literals
-        if expr.eq_ignore_ascii_case("TRUE") {
-            return Ok(true);
-        }
-        if expr.eq_ignore_ascii_case("FALSE") {
-            return Ok(false);
-        }
-        
-        // Handle integer comparisons (e.g., "VER >= 200")
-        // For now, we'll support simple comparisons with predefined constants
-        // In a full implementation, we'd parse and evaluate arithmetic expressions
-        
-        // Try to parse as integer comparison
-        if let Some(comparison_result) = self.evaluate_integer_comparison(expr) {
-            return Ok(comparison_result);
-        }
-        
-        // Default: treat undefined symbols as false, defined as true
-        // This allows simple symbol checks like "{$IF DEBUG}"
-        let symbol = expr.to_uppercase();
-        Ok(self.defined_symbols.contains(&symbol))
-    }
-    
-    /// Evaluate integer comparison expression (e.g., "VER >= 200")
-    fn evaluate_integer_comparison(&self, expr: &str) -> Option<bool> {
-        // Simple pattern matching for common cases
-        // In a full implementation, we'd have a proper expression parser
-        
-        // Check for comparison operators
-        let operators = [">=", "<=", ">", "<", "=", "==", "<>", "!="];
-        for op in &operators {
-            if let Some(pos) = expr.find(op) {
-                let left = expr[..pos].trim();
-                let right = expr[pos + op.len()..].trim();
-                
-                // Try to parse as integers
-                if let (Ok(left_val), Ok(right_val)) = (left.parse::<i32>(), right.parse::<i32>()) {
-                    return Some(match *op {
-                        ">=" => left_val >= right_val,
-                        "<=" => left_val <= right_val,
-                        ">" => left_val > right_val,
-                        "<" => left_val < right_val,
-                        "=" | "==" => left_val == right_val,
-                        "<>" | "!=" => left_val != right_val,
-                        _ => return None,
-                    });
-                }
-                
-                // Check if left is a predefined constant (like VER)
-                // For now, we'll just return None and let the caller handle it
-                // In a full implementation, we'd have a constants table
-            }
-        }
-        
-        None
+    /// Record a declared identifier for `Declared()`
+    pub fn declare(&mut self, name: &str) {
+        self.declared_identifiers.insert(name.to_uppercase());
     }
-    
-    /// Evaluate boolean expression with AND/OR operators
-    /// This is called from evaluate_expression, so it should not call evaluate_expression recursively
-    fn evaluate_boolean_expression(&self, expr: &str) -> Option<bool> {
-        let expr_upper = expr.to_uppercase();
-        
-        // Handle NOT first (before AND/OR)
-        if expr_upper.starts_with("NOT ") {
-            let rest = expr_upper[4..].trim();
-            // Recursively evaluate the rest (but not through evaluate_expression to avoid circular call)
-            if let Some(val) = self.evaluate_boolean_expression(rest) {
-                return Some(!val);
-            }
-            // If not a boolean expression, try simple cases
-            if rest.starts_with("Defined(") && rest.ends_with(')') {
-                let symbol = rest[8..rest.len()-1].trim().to_uppercase();
-                return Some(!self.defined_symbols.contains(&symbol));
-            }
-            if rest.eq_ignore_ascii_case("TRUE") {
-                return Some(false);
-            }
-            if rest.eq_ignore_ascii_case("FALSE") {
-                return Some(true);
-            }
-            // Check if it's a simple symbol
-            if self.defined_symbols.contains(&rest.to_uppercase()) {
-                return Some(false);
-            }
-            return Some(true); // NOT undefined symbol = true
-        }
-        
-        // Split by OR first (lower precedence)
-        if expr_upper.contains(" OR ") {
-            let parts: Vec<&str> = expr_upper.split(" OR ").collect();
-            let mut result = false;
-            for part in parts {
-                // Each part might contain AND, so evaluate it recursively
-                let part_result = if part.contains(" AND ") {
-                    self.evaluate_boolean_expression(part.trim())
-                } else {
-                    self.evaluate_simple_expression(part.trim())
-                };
-                if let Some(val) = part_result {
-                    result = result || val;
-                } else {
-                    return None;
-                }
-            }
-            return Some(result);
-        }
-        
-        // Split by AND (higher precedence)
-        if expr_upper.contains(" AND ") {
-            let parts: Vec<&str> = expr_upper.split(" AND ").collect();
-            let mut result = true;
-            for part in parts {
-                if let Some(val) = self.evaluate_simple_expression(part.trim()) {
-                    result = result && val;
-                } else {
-                    return None;
-                }
+
+    /// Define or replace a numeric constant visible to {$IF}
+    pub fn define_constant(&mut self, name: &str, value: i64) {
+        self.constants.insert(name.to_uppercase(), value);
+    }
+
+    /// The conditional symbols an {$IF} expression tests: the arguments of
+    /// `Defined()` and names that are not constants
+    fn symbols_in(&self, expr: &str) -> Vec<String> {
+        let upper = expr.to_uppercase();
+        let mut symbols = vec![];
+        let mut argument = false;
+        for name in upper.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$')) {
+            match name {
+                "" => {}
+                "DECLARED" | "SIZEOF" => argument = true,
+                "DEFINED" | "AND" | "OR" | "XOR" | "NOT" | "DIV" | "MOD" | "SHL" | "SHR" | "TRUE" | "FALSE" => {}
+                _ if std::mem::take(&mut argument) => {}
+                _ if name.starts_with(|c: char| c.is_ascii_digit() || c == '$') || self.constants.contains_key(name) => {}
+                _ => symbols.push(name.to_string()),
             }
-            return Some(result);
-        }
-        
-        None
-    }
-    
-    /// Evaluate a simple expression (no AND/OR operators)
-    /// This is a helper to avoid circular calls
-    fn evaluate_simple_expression(&self, expr: &str) -> Option<bool> {
-        let expr = expr.trim();
-        let expr_upper = expr.to_uppercase();
-        
-        // Handle Defined(SYMBOL) function (case-insensitive for "Defined")
-        if expr_upper.starts_with("DEFINED(") && expr.ends_with(')') {
-            // Find the opening parenthesis (case-insensitive)
-            let open_paren = expr_upper.find('(').unwrap_or(0);
-            let close_paren = expr.len() - 1;
-            let symbol = expr[open_paren + 1..close_paren].trim().to_uppercase();
-            return Some(self.defined_symbols.contains(&symbol));
-        }
-        
-        // Handle boolean literals
-        if expr_upper == "TRUE" {
-            return Some(true);
-        }
-        if expr_upper == "FALSE" {
-            return Some(false);
-        }
-        
-        // Handle integer comparisons
-        if let Some(comparison_result) = self.evaluate_integer_comparison(expr) {
-            return Some(comparison_result);
         }
-        
-        // Default: treat as symbol check
-        Some(self.defined_symbols.contains(&expr_upper))
+        symbols
+    }
+
+    /// Evaluate the expression of an {$IF} or {$ELSEIF} directive at `span`
+    fn evaluate_expression(&self, condition: &Condition, span: Span) -> ParserResult<bool> {
+        let offset = condition.offset;
+        let expr_span = Span::new(
+            span.start + offset,
+            span.start + offset + condition.expr.len(),
+            span.line,
+            span.column + offset,
+        );
+        let env = Environment {
+            defined: &self.defined_symbols,
+            declared: &self.declared_identifiers,
+            constants: &self.constants,
+        };
+        directive_expr::evaluate(&condition.expr, expr_span, &env)
     }
 }
 
+/// Whitespace-separated words with their byte offsets
+fn words(content: &str) -> Vec<(usize, &str)> {
+    content
+        .split_whitespace()
+        .map(|word| (word.as_ptr() as usize - content.as_ptr() as usize, word))
+        .collect()
+}
+
+fn unquote(word: &str) -> String {
+    word.trim_matches('\'').trim_matches('"').to_string()
+}
+
+/// COMPILER_VERSION as major * 10000 + minor * 100 + patch
+fn compiler_version() -> i64 {
+    env!("CARGO_PKG_VERSION")
+        .split('.')
+        .take(3)
+        .fold(0, |version, part| version * 100 + part.parse::<i64>().unwrap_or(0))
+}
+
 impl Default for DirectiveEvaluator {
     fn default() -> Self {
         Self::new()
@@ -490,6 +833,99 @@ mod tests {
         assert!(matches!(directive, DirectiveType::Undef(ref s) if s == "FOO"));
     }
 
+    #[test]
+    fn test_parse_resource() {
+        let directive = DirectiveEvaluator::parse_directive("RESOURCE 'sprite.png' AS Sprites");
+        assert_eq!(
+            directive,
+            DirectiveType::Resource {
+                path: "sprite.png".to_string(),
+                name: "Sprites".to_string(),
+            }
+        );
+        // Malformed directives point at the closing brace or the offending word
+        let malformed = DirectiveEvaluator::parse_directive("RESOURCE sprite.png");
+        assert!(matches!(malformed, DirectiveType::Invalid { offset: 21, len: 1, .. }));
+        let malformed = DirectiveEvaluator::parse_directive("RESOURCE a.png FOR X");
+        assert!(matches!(malformed, DirectiveType::Invalid { offset: 17, len: 3, .. }));
+    }
+
+    #[test]
+    fn test_parse_link() {
+        assert_eq!(DirectiveEvaluator::parse_directive("L 'fast.asm'"), DirectiveType::Link("fast.asm".to_string()));
+        assert_eq!(DirectiveEvaluator::parse_directive("LINK fast.asm"), DirectiveType::Link("fast.asm".to_string()));
+        assert_eq!(DirectiveEvaluator::parse_directive("L+"), DirectiveType::Switch("L".to_string(), true));
+        assert!(matches!(DirectiveEvaluator::parse_directive("LINK"), DirectiveType::Invalid { .. }));
+    }
+
+    #[test]
+    fn test_invalid_directive_span() {
+        let mut evaluator = DirectiveEvaluator::new();
+        // "(*$ IFDEF *)" at offset 10: the symbol is missing before "*)"
+        let directive = DirectiveEvaluator::parse_directive_token(" IFDEF ", Span::new(10, 22, 3, 5));
+        match evaluator.evaluate(&directive, Span::new(10, 22, 3, 5)) {
+            Err(ParserError::InvalidSyntax { message, span }) => {
+                assert_eq!(message, "Expected a symbol after {$IFDEF}");
+                assert_eq!(span, Span::new(19, 20, 3, 14));
+            }
+            other => panic!("Expected error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_codepage() {
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("CODEPAGE cp437"),
+            DirectiveType::Codepage(resources::Codepage::Cp437)
+        );
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("CODEPAGE EBCDIC"),
+            DirectiveType::Invalid {
+                message: "Unknown code page 'EBCDIC' (expected ASCII, CP437, CP850 or ZX)".to_string(),
+                offset: 11,
+                len: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_runtime_errors() {
+        use runtime_spec::RuntimeErrorStrategy;
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("RUNTIMEERRORS HANDLER OnError"),
+            DirectiveType::RuntimeErrors(RuntimeErrorStrategy::Handler("OnError".to_string()))
+        );
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("RUNTIMEERRORS IGNORE"),
+            DirectiveType::Invalid {
+                message: "Unknown runtime error strategy 'IGNORE' (expected HALT, CODE or HANDLER name)".to_string(),
+                offset: 16,
+                len: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_debug_heap() {
+        let on = DirectiveType::Switch("DEBUGHEAP".to_string(), true);
+        assert_eq!(DirectiveEvaluator::parse_directive("DEBUGHEAP"), on);
+        assert_eq!(DirectiveEvaluator::parse_directive("debugheap on"), on);
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("DEBUGHEAP OFF"),
+            DirectiveType::Switch("DEBUGHEAP".to_string(), false)
+        );
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("RoundRobin"),
+            DirectiveType::Switch("ROUNDROBIN".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let directive = DirectiveEvaluator::parse_directive("ERROR Unit requires the ZX Spectrum target");
+        assert_eq!(directive, DirectiveType::Error("Unit requires the ZX Spectrum target".to_string()));
+    }
+
     #[test]
     fn test_evaluate_ifdef_true() {
         let mut evaluator = DirectiveEvaluator::with_symbols(vec!["DEBUG".to_string()]);
@@ -620,16 +1056,92 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_inactive_regions() {
+        let mut evaluator = DirectiveEvaluator::new();
+        evaluator.evaluate(&DirectiveType::IfDef("DEBUG".to_string()), Span::new(0, 14, 1, 1)).unwrap();
+        // Nested conditionals inside an inactive region do not split it
+        evaluator.evaluate(&DirectiveType::IfNDef("X".to_string()), Span::new(20, 31, 2, 1)).unwrap();
+        evaluator.evaluate(&DirectiveType::EndIf, Span::new(40, 48, 3, 1)).unwrap();
+        evaluator.evaluate(&DirectiveType::Else, Span::new(50, 57, 4, 1)).unwrap();
+        evaluator.evaluate(&DirectiveType::EndIf, Span::new(80, 88, 6, 1)).unwrap();
+        assert_eq!(evaluator.inactive_regions(), vec![14..50]);
+    }
+
+    #[test]
+    fn test_skipped_regions_record_their_cause() {
+        let source = "{$DEFINE FAST}\n{$IFNDEF FAST}\nvar Slow, Slower: integer;\nprocedure Crawl;\n{$ENDIF}\n{$IF Defined(DEBUG)}\n{$ENDIF}";
+        let mut evaluator = DirectiveEvaluator::with_symbols(vec!["DEBUG".to_string()]);
+        evaluator.mark_command_line(&["DEBUG".to_string()]);
+        let mut start = 0;
+        for (line, text) in source.lines().enumerate() {
+            if let Some(content) = text.strip_prefix("{$") {
+                let directive = DirectiveEvaluator::parse_directive(&content[..content.len() - 1]);
+                evaluator.evaluate(&directive, Span::new(start, start + text.len(), line + 1, 1)).unwrap();
+            }
+            start += text.len() + 1;
+        }
+
+        let regions = evaluator.skipped_regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].range, evaluator.inactive_regions()[0]);
+        assert_eq!(regions[0].explanation(), "{$IFNDEF FAST} at line 2; FAST is defined by {$DEFINE FAST} at line 1");
+        assert_eq!(regions[0].declared_names(source), ["Slow", "Slower", "Crawl"]);
+        assert!(evaluator.is_active());
+    }
+
+    #[test]
+    fn test_parse_switches() {
+        assert_eq!(DirectiveEvaluator::parse_directive("R+"), DirectiveType::Switch("R".to_string(), true));
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("range_check off"),
+            DirectiveType::Switch("RANGE_CHECK".to_string(), false)
+        );
+        assert_eq!(DirectiveEvaluator::parse_directive("MODE Strict"), DirectiveType::Switch("STRICT".to_string(), true));
+        assert_eq!(DirectiveEvaluator::parse_directive("MODE TP"), DirectiveType::Switch("STRICT".to_string(), false));
+        assert_eq!(DirectiveEvaluator::parse_directive("PUSH"), DirectiveType::Push);
+        assert_eq!(DirectiveEvaluator::parse_directive("POP"), DirectiveType::Pop);
+        assert_eq!(DirectiveEvaluator::parse_directive("ALIGN 1"), DirectiveType::Switch(PACKED_RECORDS_SWITCH.to_string(), true));
+        assert_eq!(DirectiveEvaluator::parse_directive("A 2"), DirectiveType::Switch(PACKED_RECORDS_SWITCH.to_string(), false));
+        assert_eq!(
+            DirectiveEvaluator::parse_directive("WARN unused_var OFF"),
+            DirectiveType::Switch("WARN_UNUSED_VAR".to_string(), false)
+        );
+        assert!(matches!(
+            DirectiveEvaluator::parse_directive("WARN UNUSED_VAR"),
+            DirectiveType::Invalid { ref message, .. } if message == "Expected ON or OFF after {$WARN}"
+        ));
+        assert!(matches!(
+            DirectiveEvaluator::parse_directive("ALIGN 4"),
+            DirectiveType::Invalid { ref message, offset: 8, len: 1 } if message == "Expected 1 or 2 in {$ALIGN}, found '4'"
+        ));
+    }
+
+    #[test]
+    fn test_push_pop_switches() {
+        let mut evaluator = DirectiveEvaluator::new();
+        let span = Span::at(0, 1, 1);
+        evaluator.evaluate(&DirectiveType::Switch("R".to_string(), true), span).unwrap();
+        evaluator.evaluate(&DirectiveType::Push, span).unwrap();
+        evaluator.evaluate(&DirectiveType::Switch("R".to_string(), false), span).unwrap();
+        evaluator.evaluate(&DirectiveType::Switch("Q".to_string(), true), span).unwrap();
+        assert_eq!(evaluator.switch("r"), Some(false));
+        evaluator.evaluate(&DirectiveType::Pop, span).unwrap();
+        assert_eq!(evaluator.switch("R"), Some(true));
+        assert_eq!(evaluator.switch("Q"), None);
+        assert!(evaluator.evaluate(&DirectiveType::Pop, span).is_err());
+    }
+
     #[test]
     fn test_parse_if() {
         let directive = DirectiveEvaluator::parse_directive("IF Defined(DEBUG)");
-        assert!(matches!(directive, DirectiveType::If(ref s) if s == "Defined(DEBUG)"));
+        assert!(matches!(directive, DirectiveType::If(ref c) if c.expr == "Defined(DEBUG)" && c.offset == 5));
     }
 
     #[test]
     fn test_parse_elseif() {
         let directive = DirectiveEvaluator::parse_directive("ELSEIF VER >= 200");
-        assert!(matches!(directive, DirectiveType::ElseIf(ref s) if s == "VER >= 200"));
+        assert!(matches!(directive, DirectiveType::ElseIf(ref c) if c.expr == "VER >= 200" && c.offset == 9));
     }
 
     #[test]
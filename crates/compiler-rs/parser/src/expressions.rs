@@ -57,9 +57,25 @@ impl super::Parser {
                     span: token.span,
                 }))
             }
+            Some(TokenKind::RealLiteral(text)) => {
+                let token = self.current().unwrap().clone();
+                // Reals are single precision
+                let value = text.parse::<f64>().ok().filter(|v| v.abs() <= f32::MAX as f64).ok_or_else(|| {
+                    ParserError::InvalidSyntax {
+                        message: format!("Real literal {} is out of range", text),
+                        span: token.span,
+                    }
+                })?;
+                self.advance()?;
+                Ok(Node::LiteralExpr(ast::LiteralExpr {
+                    value: ast::LiteralValue::Real(value),
+                    span: token.span,
+                }))
+            }
             Some(TokenKind::CharLiteral(value)) => {
                 let token = self.current().unwrap().clone();
                 let value = *value;
+                self.check_codepage(&(value as char).to_string(), token.span)?;
                 self.advance()?;
                 Ok(Node::LiteralExpr(ast::LiteralExpr {
                     value: ast::LiteralValue::Char(value),
@@ -69,12 +85,22 @@ impl super::Parser {
             Some(TokenKind::StringLiteral(value)) => {
                 let token = self.current().unwrap().clone();
                 let value_clone = value.clone();
+                self.check_codepage(&value_clone, token.span)?;
                 self.advance()?;
                 Ok(Node::LiteralExpr(ast::LiteralExpr {
                     value: ast::LiteralValue::String(value_clone),
                     span: token.span,
                 }))
             }
+            Some(TokenKind::ByteArrayLiteral(bytes)) => {
+                let token = self.current().unwrap().clone();
+                let bytes = bytes.clone();
+                self.advance()?;
+                Ok(Node::LiteralExpr(ast::LiteralExpr {
+                    value: ast::LiteralValue::Bytes(bytes),
+                    span: token.span,
+                }))
+            }
             Some(TokenKind::BooleanLiteral(value)) => {
                 let token = self.current().unwrap().clone();
                 let value = *value;
@@ -84,6 +110,14 @@ impl super::Parser {
                     span: token.span,
                 }))
             }
+            Some(TokenKind::KwTrue | TokenKind::KwFalse) => {
+                let token = self.current().unwrap().clone();
+                self.advance()?;
+                Ok(Node::LiteralExpr(ast::LiteralExpr {
+                    value: ast::LiteralValue::Boolean(token.kind == TokenKind::KwTrue),
+                    span: token.span,
+                }))
+            }
             Some(TokenKind::Plus) => {
                 self.advance()?;
                 let expr = self.parse_prefix()?;
@@ -248,6 +282,11 @@ impl super::Parser {
                     span,
                 }))
             }
+            Some(TokenKind::KwSelf) => {
+                // SELF: the instance a method was called on
+                let span = self.advance_and_get_token()?.span;
+                self.parse_postfix(Node::IdentExpr(ast::IdentExpr { name: "Self".to_string(), span }))
+            }
             Some(TokenKind::Identifier(_)) => {
                 // Could be identifier, function call, or array/record access
                 let name_token = self.current().unwrap().clone();
@@ -317,6 +356,10 @@ impl super::Parser {
                     }),
                 };
                 let span = expr.span().merge(field_token.span);
+                if self.check(&TokenKind::LeftParen) {
+                    expr = self.parse_method_call(expr, field, span)?;
+                    continue;
+                }
                 expr = Node::FieldExpr(ast::FieldExpr {
                     record: Box::new(expr),
                     field,
@@ -382,6 +425,13 @@ impl super::Parser {
         }
     }
 
+    /// Parse the arguments of a method call on `object`, if any
+    pub(crate) fn parse_method_call(&mut self, object: Node, method: String, span: Span) -> ParserResult<Node> {
+        let args = if self.check(&TokenKind::LeftParen) { self.parse_args()? } else { vec![] };
+        let span = args.last().map_or(span, |arg| span.merge(arg.span()));
+        Ok(Node::MethodCall(ast::MethodCall { object: Box::new(object), method, args, span }))
+    }
+
     /// Parse argument list: ( expression { , expression } )
     pub(crate) fn parse_args(&mut self) -> ParserResult<Vec<Node>> {
         self.consume(TokenKind::LeftParen, "(")?;
@@ -400,6 +450,29 @@ impl super::Parser {
         self.consume(TokenKind::RightParen, ")")?;
         Ok(args)
     }
+
+    /// Check that the literal `text` at `span` fits the active code page
+    fn check_codepage(&self, text: &str, span: Span) -> ParserResult<()> {
+        let Some(codepage) = self.directive_evaluator.codepage() else {
+            return Ok(());
+        };
+        let Err((offset, ch)) = codepage.encode(text) else {
+            return Ok(());
+        };
+        // Without doubled quotes or escapes the text is the source between
+        // the quotes, so the character can be pointed at exactly
+        let span = if span.end - span.start == text.len() + 2 {
+            let column = span.column + 1 + text[..offset].chars().count();
+            Span::new(span.start + 1 + offset, span.start + 1 + offset + ch.len_utf8(), span.line, column)
+                .with_end(span.line, column + 1)
+        } else {
+            span
+        };
+        Err(ParserError::InvalidSyntax {
+            message: format!("Character '{}' (U+{:04X}) is not in code page {}", ch, ch as u32, codepage.name()),
+            span,
+        })
+    }
 }
 
 #[cfg(test)]
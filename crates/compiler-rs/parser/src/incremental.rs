@@ -53,12 +53,12 @@ impl IncrementalParser {
 
         IncrementalParseResult {
             needs_full_reparse: true,
-            affected_span: Span {
-                start: change.start,
-                end: change.start + change.new_content.len(),
-                line: change.line,
-                column: change.column,
-            },
+            affected_span: Span::new(
+                change.start,
+                change.start + change.new_content.len(),
+                change.line,
+                change.column,
+            ),
         }
     }
 
@@ -12,15 +12,21 @@ mod classes;
 mod units;
 mod properties;
 mod directives;
+mod directive_expr;
+mod recovery;
 pub mod query;
 pub mod incremental;
+pub mod minify;
+pub mod printer;
+pub mod upgrade;
 
 use ast::Node;
 use errors::{CodeSnippet, Diagnostic, ParserError, ParserResult};
-use lexer::Lexer;
+use lexer::{IdentifierPolicy, Lexer};
 use tokens::{Span, Token, TokenKind};
 
 use crate::directives::DirectiveEvaluator;
+pub use crate::directives::{SkippedRegion, SymbolOrigin};
 
 /// Parser for SuperPascal programs
 pub struct Parser {
@@ -29,10 +35,27 @@ pub struct Parser {
     peek: Option<Token>,
     filename: Option<String>,
     directive_evaluator: DirectiveEvaluator,
-    /// Track included files to prevent circular includes
+    /// Canonical paths of every file included so far in this translation
+    /// unit; a file is only included once
     included_files: std::collections::HashSet<String>,
+    /// Files being parsed, outermost first, to report circular includes
+    include_chain: Vec<String>,
     /// Include search paths for resolving relative file paths
     include_paths: Vec<String>,
+    /// Resources compiled from {$RESOURCE} directives
+    resources: Vec<resources::CompiledResource>,
+    /// Modules named by {$L} directives, resolved against the include paths
+    linked_modules: Vec<std::path::PathBuf>,
+    /// Include and resource files read, in the order they were read
+    dependencies: Vec<std::path::PathBuf>,
+    /// Set by {$COMPILETIME} and taken by the function declaration after it
+    compiletime_pending: bool,
+    /// Set by {$PURE} and taken by the function declaration after it
+    pure_pending: bool,
+    /// Set by {$UNROLL n} and taken by the FOR loop after it
+    unroll_pending: Option<u32>,
+    /// Syntax errors recovered from so far (see `recovery`)
+    errors: Vec<ParserError>,
 }
 
 impl Parser {
@@ -52,20 +75,42 @@ impl Parser {
         filename: Option<String>,
         predefined_symbols: Vec<String>,
     ) -> ParserResult<Self> {
-        let lexer = Lexer::new(source);
-        let mut included_files = std::collections::HashSet::new();
-        // Add current file to included files to prevent self-inclusion
-        if let Some(ref fname) = filename {
-            included_files.insert(fname.clone());
-        }
+        Self::new_with_identifier_policy(source, filename, predefined_symbols, IdentifierPolicy::default())
+    }
+
+    /// Create a new parser whose identifiers follow `identifier_policy`
+    pub fn new_with_identifier_policy(
+        source: &str,
+        filename: Option<String>,
+        predefined_symbols: Vec<String>,
+        identifier_policy: IdentifierPolicy,
+    ) -> ParserResult<Self> {
+        let lexer = Lexer::new(source).with_identifier_policy(identifier_policy);
+        // Add current file to the include chain to prevent self-inclusion
+        let include_chain = filename
+            .iter()
+            .map(|fname| {
+                std::fs::canonicalize(fname)
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| fname.clone())
+            })
+            .collect();
         let mut parser = Self {
             lexer,
             current: None,
             peek: None,
             filename: filename.clone(),
             directive_evaluator: DirectiveEvaluator::with_symbols(predefined_symbols),
-            included_files,
+            included_files: std::collections::HashSet::new(),
+            include_chain,
             include_paths: vec![],
+            resources: vec![],
+            linked_modules: vec![],
+            dependencies: vec![],
+            compiletime_pending: false,
+            pure_pending: false,
+            unroll_pending: None,
+            errors: vec![],
         };
         // Prime the parser with first two tokens
         parser.advance()?;
@@ -83,6 +128,70 @@ impl Parser {
         self.include_paths = paths;
     }
 
+    /// State of a compiler switch such as `R` or `RANGE_CHECK` at the current
+    /// position, or None if no directive has set it
+    pub fn switch(&self, name: &str) -> Option<bool> {
+        self.directive_evaluator.switch(name)
+    }
+
+    /// Character set literals are transcoded into at the current position:
+    /// the last {$CODEPAGE}, else the one given to `set_codepage`
+    pub fn codepage(&self) -> Option<resources::Codepage> {
+        self.directive_evaluator.codepage()
+    }
+
+    /// Set the default character set, usually the target machine's, before
+    /// parsing; {$CODEPAGE} overrides it
+    pub fn set_codepage(&mut self, codepage: resources::Codepage) {
+        self.directive_evaluator.set_codepage(codepage);
+    }
+
+    /// What happens on runtime errors, if {$RUNTIMEERRORS} chose it
+    pub fn runtime_errors(&self) -> Option<&runtime_spec::RuntimeErrorStrategy> {
+        self.directive_evaluator.runtime_errors()
+    }
+
+    /// Define a numeric constant for {$IF} expressions, such as a build option
+    pub fn define_constant(&mut self, name: &str, value: i64) {
+        self.directive_evaluator.define_constant(name, value);
+    }
+
+    /// Get resources compiled from {$RESOURCE} directives
+    pub fn resources(&self) -> &[resources::CompiledResource] {
+        &self.resources
+    }
+
+    /// Assembly or object modules named by {$L} directives, in order
+    pub fn linked_modules(&self) -> &[std::path::PathBuf] {
+        &self.linked_modules
+    }
+
+    /// Include and resource files the source depends on, in the order they
+    /// were read
+    pub fn dependencies(&self) -> &[std::path::PathBuf] {
+        &self.dependencies
+    }
+
+    /// Byte ranges of this file skipped by conditional compilation, so editors
+    /// can grey out inactive `{$IFDEF}` regions exactly as the compiler saw them.
+    /// Ranges skipped inside included files are not reported.
+    pub fn inactive_regions(&self) -> &[std::ops::Range<usize>] {
+        self.directive_evaluator.inactive_regions()
+    }
+
+    /// Regions of this file skipped by conditional compilation, with the
+    /// directive that skipped each and where the symbols it tests got their
+    /// state
+    pub fn skipped_regions(&self) -> &[SkippedRegion] {
+        self.directive_evaluator.skipped_regions()
+    }
+
+    /// Attribute the predefined `symbols` to `--define` in
+    /// [`SkippedRegion::explanation`]
+    pub fn mark_command_line_symbols(&mut self, symbols: &[String]) {
+        self.directive_evaluator.mark_command_line(symbols);
+    }
+
     /// Get mutable reference to directive evaluator
     pub(crate) fn directive_evaluator_mut(&mut self) -> &mut DirectiveEvaluator {
         &mut self.directive_evaluator
@@ -216,7 +325,23 @@ impl Parser {
     // Core functionality is in core.rs
 
     /// Parse a complete program, unit, or library
+    ///
+    /// Parsing goes on after a syntax error; the first one is returned and
+    /// all of them are in [`errors`](Self::errors).
     pub fn parse(&mut self) -> ParserResult<Node> {
+        self.errors.clear();
+        let result = self.parse_module();
+        if let Err(error) = &result {
+            self.record_error(error.clone());
+        }
+        match self.errors.first() {
+            Some(error) => Err(error.clone()),
+            None => result,
+        }
+    }
+
+    /// Parse the program, unit or library the file holds
+    fn parse_module(&mut self) -> ParserResult<Node> {
         // Handle directives before PROGRAM/UNIT/LIBRARY
         // Directives may wrap the program declaration
         while self.check(&TokenKind::Directive(String::new())) {
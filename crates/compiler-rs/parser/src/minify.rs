@@ -0,0 +1,360 @@
+//! Source minification for distribution
+//!
+//! Produces a single source file from a program and its includes: comments
+//! and layout are dropped, `{$INCLUDE}` files are inlined once, and
+//! conditional directives are resolved for the predefined symbols, so only
+//! the active branches remain. Other directives (switches, `{$RESOURCE}`,
+//! `{$L}`) are kept; their file names are not rewritten.
+//!
+//! Tokens are copied from the source text, so literals keep their spelling.
+//! With [`MinifyOptions::shorten_identifiers`], every name declared in the
+//! program is renamed to a short one, most used first. Names are matched
+//! by spelling, so a declared name that also denotes something undeclared
+//! (a built-in routine, a name from another unit) must not be shortened;
+//! unit interfaces, `external` routines and resource names are kept.
+//!
+//! `{$IF Declared(...)}` is evaluated without the declarations, since they
+//! are only known after parsing.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use ast::Node;
+use errors::{ParserError, ParserResult};
+use lexer::{IdentifierPolicy, Lexer};
+use tokens::{Span, TokenKind};
+
+use crate::declarations::find_include_file;
+use crate::directives::{DirectiveEvaluator, DirectiveType};
+use crate::Parser;
+
+/// Longest output line; tokens move to the next line beyond it
+pub const MAX_LINE: usize = 250;
+
+/// How to minify
+#[derive(Debug, Clone, Default)]
+pub struct MinifyOptions {
+    /// Symbols defined for conditional compilation
+    pub symbols: Vec<String>,
+    /// Directories searched for included files
+    pub include_paths: Vec<String>,
+    /// Rename declared identifiers to short names
+    pub shorten_identifiers: bool,
+    pub identifier_policy: IdentifierPolicy,
+}
+
+/// A token of the output: its kind and its text
+#[derive(Debug, Clone, PartialEq)]
+struct Piece {
+    kind: TokenKind,
+    text: String,
+}
+
+/// Minify `source`, read from `filename`
+pub fn minify(source: &str, filename: Option<String>, options: &MinifyOptions) -> ParserResult<String> {
+    let mut minifier = Minifier {
+        evaluator: DirectiveEvaluator::with_symbols(options.symbols.clone()),
+        options,
+        included: HashSet::new(),
+        chain: Vec::new(),
+        pieces: Vec::new(),
+    };
+    minifier.process(source, filename.as_deref())?;
+    if minifier.evaluator.has_unmatched_conditionals() {
+        return Err(ParserError::InvalidSyntax {
+            message: "Unmatched {$IFDEF} or {$IFNDEF} - reached end of file".to_string(),
+            span: Span::at(source.len(), 1, 1),
+        });
+    }
+    let mut pieces = minifier.pieces;
+    if options.shorten_identifiers {
+        let output = render(&pieces);
+        let mut parser = Parser::new_with_identifier_policy(&output, filename, options.symbols.clone(), options.identifier_policy)?;
+        parser.set_include_paths(options.include_paths.clone());
+        let ast = parser.parse()?;
+        let resources: Vec<String> = parser.resources().iter().map(|r| r.name.to_lowercase()).collect();
+        shorten_identifiers(&mut pieces, &ast, &resources, options.identifier_policy);
+    }
+    Ok(render(&pieces))
+}
+
+struct Minifier<'a> {
+    evaluator: DirectiveEvaluator,
+    options: &'a MinifyOptions,
+    /// Canonical paths of the files included so far
+    included: HashSet<PathBuf>,
+    /// Files being read, to report circular includes
+    chain: Vec<PathBuf>,
+    pieces: Vec<Piece>,
+}
+
+impl Minifier<'_> {
+    fn process(&mut self, source: &str, filename: Option<&str>) -> ParserResult<()> {
+        let mut lexer = Lexer::new(source).with_identifier_policy(self.options.identifier_policy);
+        loop {
+            let token = lexer.next_token().map_err(|e| ParserError::InvalidSyntax {
+                message: format!("Lexer error: {}", e),
+                span: e.span(),
+            })?;
+            match &token.kind {
+                TokenKind::Eof => return Ok(()),
+                TokenKind::Directive(content) => self.directive(content, token.span, filename)?,
+                // An asm body keeps its lines, without their indentation
+                TokenKind::AsmText(text) if self.evaluator.is_active() => {
+                    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+                    self.pieces.push(Piece {
+                        kind: token.kind.clone(),
+                        text: format!("\n{}\n", lines.join("\n")),
+                    });
+                }
+                kind if self.evaluator.is_active() => {
+                    self.pieces.push(Piece {
+                        kind: kind.clone(),
+                        text: source[token.span.start..token.span.end].to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn directive(&mut self, content: &str, span: Span, filename: Option<&str>) -> ParserResult<()> {
+        let directive = DirectiveEvaluator::parse_directive_token(content, span);
+        let (active, _) = self.evaluator.evaluate(&directive, span)?;
+        if !active {
+            return Ok(());
+        }
+        match directive {
+            DirectiveType::IfDef(_)
+            | DirectiveType::IfNDef(_)
+            | DirectiveType::If(_)
+            | DirectiveType::ElseIf(_)
+            | DirectiveType::Else
+            | DirectiveType::EndIf
+            | DirectiveType::Define(_)
+            | DirectiveType::Undef(_) => Ok(()),
+            DirectiveType::Include(name) => self.include(&name, span, filename),
+            DirectiveType::Error(message) => Err(ParserError::InvalidSyntax {
+                message: format!("User-defined error: {}", message),
+                span,
+            }),
+            _ => {
+                self.pieces.push(Piece {
+                    kind: TokenKind::Directive(content.to_string()),
+                    text: format!("{{${}}}", content.trim()),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn include(&mut self, name: &str, span: Span, filename: Option<&str>) -> ParserResult<()> {
+        let error = |message: String| ParserError::InvalidSyntax { message, span };
+        let path = find_include_file(name, filename, &self.options.include_paths)
+            .ok_or_else(|| error(format!("Include file not found: '{}'", name)))?;
+        let canonical = std::fs::canonicalize(&path)
+            .map_err(|e| error(format!("Cannot resolve include path '{}': {}", name, e)))?;
+        if self.chain.contains(&canonical) {
+            return Err(error(format!("Circular include detected: '{}'", name)));
+        }
+        // Include once, as the parser does
+        if !self.included.insert(canonical.clone()) {
+            return Ok(());
+        }
+        let source = std::fs::read_to_string(&path).map_err(|e| error(format!("Cannot read include file '{}': {}", name, e)))?;
+        self.chain.push(canonical);
+        let result = self.process(&source, Some(&path.to_string_lossy()));
+        self.chain.pop();
+        result
+    }
+}
+
+/// Join the pieces, separating only tokens that would otherwise merge
+fn render(pieces: &[Piece]) -> String {
+    let mut output = String::new();
+    let mut line_length = 0;
+    let mut previous: Option<&Piece> = None;
+    for piece in pieces {
+        if let Some(previous) = previous {
+            if line_length + piece.text.len() >= MAX_LINE {
+                output.push('\n');
+                line_length = 0;
+            } else if needs_space(&previous.text, &piece.text) {
+                output.push(' ');
+                line_length += 1;
+            }
+        }
+        output.push_str(&piece.text);
+        line_length = match piece.text.rfind('\n') {
+            Some(newline) => piece.text.len() - newline - 1,
+            None => line_length + piece.text.len(),
+        };
+        previous = Some(piece);
+    }
+    output.push('\n');
+    output
+}
+
+/// Whether two adjacent token texts would lex differently without a space
+fn needs_space(left: &str, right: &str) -> bool {
+    let (Some(last), Some(first)) = (left.chars().last(), right.chars().next()) else {
+        return false;
+    };
+    let word = |ch: char| ch.is_alphanumeric() || ch == '_';
+    (word(last) && word(first))
+        // `(*` and `//` would open comments, `..` after a number a real
+        || matches!((last, first), ('(', '*') | ('/', '/') | ('.', '.'))
+        // A quote would continue the string
+        || (last == '\'' && first == '\'')
+}
+
+/// Rename the names declared in `ast`
+fn shorten_identifiers(pieces: &mut [Piece], ast: &Node, resources: &[String], policy: IdentifierPolicy) {
+    let mut declared = HashSet::new();
+    let mut kept = HashSet::new();
+    collect_declared(ast, &mut declared, &mut kept);
+    declared.retain(|name| !kept.contains(name) && !resources.iter().any(|resource| name.starts_with(resource.as_str())));
+
+    // Most used names get the shortest replacements
+    let mut uses: HashMap<String, usize> = HashMap::new();
+    let mut spelled = HashSet::new();
+    for piece in pieces.iter() {
+        if let TokenKind::Identifier(name) = &piece.kind {
+            let name = name.to_lowercase();
+            if declared.contains(&name) {
+                *uses.entry(name).or_default() += 1;
+            } else {
+                spelled.insert(name);
+            }
+        }
+    }
+    let mut by_use: Vec<(String, usize)> = uses.into_iter().collect();
+    by_use.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let mut names = ShortNames { next: 0, policy };
+    let renames: HashMap<String, String> = by_use
+        .into_iter()
+        .map(|(name, _)| {
+            let short = names.next_avoiding(&spelled);
+            (name, short)
+        })
+        .collect();
+    for piece in pieces.iter_mut() {
+        if let TokenKind::Identifier(name) = &piece.kind
+            && let Some(short) = renames.get(&name.to_lowercase())
+        {
+            piece.text = short.clone();
+        }
+    }
+}
+
+/// Record the names declared in the program in `declared`, and those that
+/// must keep their spelling in `kept`
+fn collect_declared(node: &Node, declared: &mut HashSet<String>, kept: &mut HashSet<String>) {
+    let add = |set: &mut HashSet<String>, name: &str| {
+        set.insert(name.to_lowercase());
+    };
+    let add_params = |declared: &mut HashSet<String>, params: &[ast::Param]| {
+        for param in params {
+            declared.extend(param.names.iter().map(|name| name.to_lowercase()));
+        }
+    };
+    match node {
+        Node::Unit(unit) => {
+            // Other units see the interface
+            if let Some(interface) = &unit.interface {
+                for decl in interface.children() {
+                    collect_declared(decl, kept, &mut HashSet::new());
+                }
+            }
+        }
+        Node::VarDecl(v) => v.names.iter().for_each(|name| add(declared, name)),
+        Node::ConstDecl(c) => add(declared, &c.name),
+        Node::TypeDecl(t) => add(declared, &t.name),
+        Node::LabelDecl(l) => l.labels.iter().for_each(|name| add(declared, name)),
+        Node::ProcDecl(p) => {
+            add(if p.is_external { &mut *kept } else { &mut *declared }, &p.name);
+            add_params(declared, &p.params);
+        }
+        Node::FuncDecl(f) => {
+            add(if f.is_external { &mut *kept } else { &mut *declared }, &f.name);
+            add_params(declared, &f.params);
+        }
+        Node::OperatorDecl(o) => add_params(declared, &o.params),
+        Node::AnonymousFunction(a) => add_params(declared, &a.params),
+        Node::AnonymousProcedure(a) => add_params(declared, &a.params),
+        Node::RecordType(r) => {
+            for field in &r.fields {
+                field.names.iter().for_each(|name| add(declared, name));
+            }
+        }
+        Node::EnumType(e) => e.values.iter().for_each(|name| add(declared, name)),
+        _ => {}
+    }
+    for child in node.children() {
+        collect_declared(child, declared, kept);
+    }
+}
+
+/// Replacement names: `a`..`z`, then `aa`, `ab`, ...
+struct ShortNames {
+    next: usize,
+    policy: IdentifierPolicy,
+}
+
+impl ShortNames {
+    /// The next name that is not a keyword or in `taken`
+    fn next_avoiding(&mut self, taken: &HashSet<String>) -> String {
+        loop {
+            let name = Self::name(self.next);
+            self.next += 1;
+            let is_identifier = matches!(
+                Lexer::new(&name).with_identifier_policy(self.policy).next_token().map(|t| t.kind),
+                Ok(TokenKind::Identifier(_))
+            );
+            if is_identifier && !taken.contains(&name) {
+                return name;
+            }
+        }
+    }
+
+    fn name(mut index: usize) -> String {
+        let mut name = Vec::new();
+        loop {
+            name.push(b'a' + (index % 26) as u8);
+            if index < 26 {
+                break;
+            }
+            index = index / 26 - 1;
+        }
+        name.reverse();
+        String::from_utf8(name).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_layout_and_resolves_conditionals() {
+        let source = "program P; { greeting }\n{$DEFINE LOUD}\nvar Count: integer;\nbegin\n  {$IFDEF LOUD} Count := 10; {$ELSE} Count := 1; {$ENDIF}\n  {$R+}\nend.\n";
+        let output = minify(source, None, &MinifyOptions::default()).unwrap();
+        assert_eq!(output, "program P;var Count:integer;begin Count:=10;{$R+}end.\n");
+    }
+
+    #[test]
+    fn test_shortens_declared_identifiers() {
+        let source = "program P;\nvar Count, Total: integer;\nprocedure Add(Amount: integer);\nbegin\n  Total := Total + Amount;\nend;\nbegin\n  Count := 1;\n  Add(Count);\n  WriteLn(Total);\nend.\n";
+        let options = MinifyOptions { shorten_identifiers: true, ..Default::default() };
+        let output = minify(source, None, &options).unwrap();
+        assert_eq!(output, "program P;var b,a:integer;procedure c(d:integer);begin a:=a+d;end;begin b:=1;c(b);WriteLn(a);end.\n");
+    }
+
+    #[test]
+    fn test_short_names() {
+        assert_eq!(ShortNames::name(0), "a");
+        assert_eq!(ShortNames::name(25), "z");
+        assert_eq!(ShortNames::name(26), "aa");
+        assert_eq!(ShortNames::name(52), "ba");
+    }
+}
@@ -0,0 +1,1174 @@
+//! Pretty-printer: writes an AST back as Pascal source
+//!
+//! Programs print as source the parser reads back into the same tree,
+//! spans aside: declarations, statements, expressions, and the data and
+//! procedural types. Parentheses follow the parser's operator precedence,
+//! so only the ones the tree needs are written. Nodes without a source form
+//! here (classes, interfaces, helpers, operators, anonymous routines) are
+//! written as a `{ Kind }` comment.
+//!
+//! Keywords are lowercase, one statement per line, indented by two spaces.
+
+use ast::{BinaryOp, Block, ForDirection, LiteralValue, MethodBinding, Node, Param, ParamType, SetElement, UnaryOp};
+
+/// Write `node` as Pascal source
+pub fn print(node: &Node) -> String {
+    let mut printer = Printer::default();
+    match node {
+        Node::Program(program) => {
+            for directive in &program.directives {
+                printer.node_line(directive, "", "");
+            }
+            printer.line(&format!("program {};", program.name));
+            if let Some(uses) = &program.uses {
+                printer.line(&format!("uses {};", uses.units.join(", ")));
+            }
+            printer.routine_body(&program.block, ".");
+        }
+        Node::Block(block) => printer.declarations(block),
+        _ if is_statement(node) => printer.statement(node, "", ""),
+        _ => printer.node_line(node, "", ""),
+    }
+    printer.out
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// A declaration, expression or type on a line of its own
+    fn node_line(&mut self, node: &Node, prefix: &str, suffix: &str) {
+        let text = match node {
+            Node::Directive(directive) => format!("{{${}}}", directive.content),
+            _ if is_type(node) => self.type_expr(node),
+            _ => expression(node),
+        };
+        self.line(&format!("{}{}{}", prefix, text, suffix));
+    }
+
+    /// Declarations, then `begin` statements `end` and `suffix`
+    fn routine_body(&mut self, block: &Node, suffix: &str) {
+        let Node::Block(block) = block else {
+            self.node_line(block, "", suffix);
+            return;
+        };
+        self.declarations(block);
+        self.line("begin");
+        self.indent += 1;
+        self.statements(&block.statements);
+        self.indent -= 1;
+        self.line(&format!("end{}", suffix));
+    }
+
+    fn declarations(&mut self, block: &Block) {
+        for directive in &block.directives {
+            self.node_line(directive, "", "");
+        }
+        for decl in &block.label_decls {
+            if let Node::LabelDecl(labels) = decl {
+                self.line(&format!("label {};", labels.labels.join(", ")));
+            }
+        }
+        let (resource_strings, consts): (Vec<&Node>, Vec<&Node>) = block
+            .const_decls
+            .iter()
+            .partition(|decl| matches!(decl, Node::ConstDecl(c) if c.is_resourcestring));
+        self.section("const", &consts);
+        self.section("resourcestring", &resource_strings);
+        self.section("type", &block.type_decls.iter().collect::<Vec<_>>());
+        self.section("var", &block.var_decls.iter().collect::<Vec<_>>());
+        self.section("threadvar", &block.threadvar_decls.iter().collect::<Vec<_>>());
+        for routine in block.proc_decls.iter().chain(&block.func_decls).chain(&block.operator_decls) {
+            self.routine(routine);
+        }
+    }
+
+    /// A `const`, `type` or `var` keyword and its declarations
+    fn section(&mut self, keyword: &str, decls: &[&Node]) {
+        if decls.is_empty() {
+            return;
+        }
+        self.line(keyword);
+        self.indent += 1;
+        for decl in decls {
+            let text = match decl {
+                Node::ConstDecl(c) => match &c.type_expr {
+                    Some(type_expr) => format!("{}: {} = {}", c.name, self.type_expr(type_expr), expression(&c.value)),
+                    None => format!("{} = {}", c.name, expression(&c.value)),
+                },
+                Node::TypeDecl(t) => {
+                    let generics = self.generic_params(&t.generic_params);
+                    let distinct = if t.distinct { "type " } else { "" };
+                    format!("{}{} = {}{}", t.name, generics, distinct, self.type_expr(&t.type_expr))
+                }
+                Node::VarDecl(v) => {
+                    let absolute = v
+                        .absolute_address
+                        .as_ref()
+                        .map(|address| format!(" absolute {}", expression(address)))
+                        .unwrap_or_default();
+                    format!("{}: {}{}", v.names.join(", "), self.type_expr(&v.type_expr), absolute)
+                }
+                other => format!("{{ {} }}", other.kind()),
+            };
+            self.line(&format!("{};", text));
+        }
+        self.indent -= 1;
+    }
+
+    fn routine(&mut self, node: &Node) {
+        let (heading, block, is_forward, is_external, external_name, binding) = match node {
+            Node::ProcDecl(p) => (
+                format!(
+                    "{}procedure {}{}{}",
+                    if p.is_class_method { "class " } else { "" },
+                    qualified(&p.class_name, &p.name),
+                    self.generic_params(&p.generic_params),
+                    self.params(&p.params)
+                ),
+                &p.block,
+                p.is_forward,
+                p.is_external,
+                &p.external_name,
+                p.binding,
+            ),
+            Node::FuncDecl(f) => (
+                format!(
+                    "{}function {}{}{}: {}",
+                    if f.is_class_method { "class " } else { "" },
+                    qualified(&f.class_name, &f.name),
+                    self.generic_params(&f.generic_params),
+                    self.params(&f.params),
+                    self.type_expr(&f.return_type)
+                ),
+                &f.block,
+                f.is_forward,
+                f.is_external,
+                &f.external_name,
+                f.binding,
+            ),
+            other => {
+                self.line(&format!("{{ {} }}", other.kind()));
+                return;
+            }
+        };
+        if matches!(node, Node::FuncDecl(f) if f.is_compiletime) {
+            self.line("{$COMPILETIME}");
+        }
+        if matches!(node, Node::FuncDecl(f) if f.is_pure) {
+            self.line("{$PURE}");
+        }
+        self.line(&format!("{};", heading));
+        match binding {
+            MethodBinding::Static => {}
+            MethodBinding::Virtual => self.line("virtual;"),
+            MethodBinding::Override => self.line("override;"),
+        }
+        if is_forward {
+            self.line("forward;");
+        } else if is_external {
+            match external_name {
+                Some(name) => self.line(&format!("external {};", quote(name))),
+                None => self.line("external;"),
+            }
+        } else {
+            self.routine_body(block, ";");
+        }
+    }
+
+    /// `(A, B: integer; var C)`, or nothing without parameters
+    fn params(&self, params: &[Param]) -> String {
+        if params.is_empty() {
+            return String::new();
+        }
+        let params: Vec<String> = params
+            .iter()
+            .map(|param| {
+                let mode = match param.param_type {
+                    ParamType::Value => "",
+                    ParamType::Var => "var ",
+                    ParamType::Const => "const ",
+                    ParamType::ConstRef => "constref ",
+                    ParamType::Out => "out ",
+                };
+                let mut text = format!("{}{}", mode, param.names.join(", "));
+                if let Some(type_expr) = &param.type_expr {
+                    text.push_str(&format!(": {}", self.type_expr(type_expr)));
+                }
+                if let Some(default) = &param.default_value {
+                    text.push_str(&format!(" = {}", expression(default)));
+                }
+                text
+            })
+            .collect();
+        format!("({})", params.join("; "))
+    }
+
+    /// Statements of a list, each ending in `;`
+    fn statements(&mut self, statements: &[Node]) {
+        for statement in statements {
+            self.statement(statement, "", ";");
+        }
+    }
+
+    /// `statement` with `prefix` before its first line and `suffix` after its last
+    fn statement(&mut self, node: &Node, prefix: &str, suffix: &str) {
+        match node {
+            Node::Block(block) => {
+                self.line(&format!("{}begin", prefix));
+                self.indent += 1;
+                self.statements(&block.statements);
+                self.indent -= 1;
+                self.line(&format!("end{}", suffix));
+            }
+            Node::AssignStmt(a) => {
+                self.line(&format!("{}{} := {}{}", prefix, expression(&a.target), expression(&a.value), suffix));
+            }
+            Node::CallStmt(c) => {
+                let name = if c.name.is_empty() { "inherited" } else { c.name.as_str() };
+                self.line(&format!("{}{}{}{}", prefix, name, arguments(&c.args, false), suffix));
+            }
+            Node::MethodCall(_) => {
+                self.line(&format!("{}{}{}", prefix, expression(node), suffix));
+            }
+            Node::IfStmt(i) => {
+                self.line(&format!("{}if {} then", prefix, expression(&i.condition)));
+                match &i.else_block {
+                    Some(else_block) => {
+                        self.body(&i.then_block, "");
+                        if matches!(else_block.as_ref(), Node::IfStmt(_)) {
+                            self.statement(else_block, "else ", suffix);
+                        } else {
+                            self.line("else");
+                            self.body(else_block, suffix);
+                        }
+                    }
+                    None => self.body(&i.then_block, suffix),
+                }
+            }
+            Node::WhileStmt(w) => {
+                self.line(&format!("{}while {} do", prefix, expression(&w.condition)));
+                self.body(&w.body, suffix);
+            }
+            Node::ForStmt(f) => {
+                let direction = match f.direction {
+                    ForDirection::To => "to",
+                    ForDirection::Downto => "downto",
+                };
+                let unroll = f.unroll.map(|factor| format!("{{$UNROLL {}}} ", factor)).unwrap_or_default();
+                self.line(&format!(
+                    "{}{}for {} := {} {} {} do",
+                    prefix,
+                    unroll,
+                    f.var_name,
+                    expression(&f.start_expr),
+                    direction,
+                    expression(&f.end_expr)
+                ));
+                self.body(&f.body, suffix);
+            }
+            Node::ForInStmt(f) => {
+                self.line(&format!("{}for {} in {} do", prefix, f.var_name, expression(&f.collection_expr)));
+                self.body(&f.body, suffix);
+            }
+            Node::RepeatStmt(r) => {
+                self.line(&format!("{}repeat", prefix));
+                self.indent += 1;
+                self.statements(&r.statements);
+                self.indent -= 1;
+                self.line(&format!("until {}{}", expression(&r.condition), suffix));
+            }
+            Node::CaseStmt(c) => {
+                self.line(&format!("{}case {} of", prefix, expression(&c.expr)));
+                self.indent += 1;
+                for branch in &c.cases {
+                    let values: Vec<String> = branch.values.iter().map(expression).collect();
+                    self.statement(&branch.statement, &format!("{}: ", values.join(", ")), ";");
+                }
+                if let Some(else_branch) = &c.else_branch {
+                    // The parser expects `end` right after the else statement
+                    self.line("else");
+                    self.body(else_branch, "");
+                }
+                self.indent -= 1;
+                self.line(&format!("end{}", suffix));
+            }
+            Node::TryStmt(t) => {
+                self.line(&format!("{}try", prefix));
+                self.indent += 1;
+                self.statements(&t.try_block);
+                self.indent -= 1;
+                if let Some(except_block) = &t.except_block {
+                    self.line("except");
+                    self.indent += 1;
+                    self.statements(except_block);
+                    self.indent -= 1;
+                } else if !t.exception_handlers.is_empty() || t.exception_else.is_some() {
+                    self.line("except");
+                    self.indent += 1;
+                    for handler in &t.exception_handlers {
+                        let variable = handler.variable.as_ref().map(|v| format!("{}: ", v)).unwrap_or_default();
+                        let heading = format!("on {}{} do ", variable, self.type_expr(&handler.exception_type));
+                        self.statement(&handler.handler, &heading, ";");
+                    }
+                    if let Some(else_statement) = &t.exception_else {
+                        self.line("else");
+                        self.body(else_statement, ";");
+                    }
+                    self.indent -= 1;
+                }
+                if let Some(finally_block) = &t.finally_block {
+                    self.line("finally");
+                    self.indent += 1;
+                    self.statements(finally_block);
+                    self.indent -= 1;
+                }
+                self.line(&format!("end{}", suffix));
+            }
+            Node::RaiseStmt(r) => match &r.exception {
+                Some(exception) => self.line(&format!("{}raise {}{}", prefix, expression(exception), suffix)),
+                None => self.line(&format!("{}raise{}", prefix, suffix)),
+            },
+            Node::WithStmt(w) => {
+                let records: Vec<String> = w.records.iter().map(expression).collect();
+                self.line(&format!("{}with {} do", prefix, records.join(", ")));
+                self.body(&w.statement, suffix);
+            }
+            Node::GotoStmt(g) => self.line(&format!("{}goto {}{}", prefix, g.label, suffix)),
+            Node::LabeledStmt(l) => self.statement(&l.statement, &format!("{}{}: ", prefix, l.label), suffix),
+            Node::AsmStmt(a) => {
+                self.line(&format!("{}asm", prefix));
+                self.indent += 1;
+                for line in a.body.lines() {
+                    self.line(line);
+                }
+                self.indent -= 1;
+                if a.clobbers.is_empty() {
+                    self.line(&format!("end{}", suffix));
+                } else {
+                    let clobbers: Vec<String> = a.clobbers.iter().map(|reg| format!("'{}'", reg)).collect();
+                    self.line(&format!("end [{}]{}", clobbers.join(", "), suffix));
+                }
+            }
+            other => self.line(&format!("{}{{ {} }}{}", prefix, other.kind(), suffix)),
+        }
+    }
+
+    /// The body of a compound statement: `begin` blocks stay at the heading's
+    /// indentation, single statements are indented under it
+    fn body(&mut self, node: &Node, suffix: &str) {
+        if matches!(node, Node::Block(_)) {
+            self.statement(node, "", suffix);
+        } else {
+            self.indent += 1;
+            self.statement(node, "", suffix);
+            self.indent -= 1;
+        }
+    }
+
+    /// A type; records span several lines, indented from the current line
+    fn type_expr(&self, node: &Node) -> String {
+        match node {
+            Node::NamedType(t) if t.generic_args.is_empty() => t.name.clone(),
+            Node::NamedType(t) => {
+                let args: Vec<String> = t.generic_args.iter().map(|arg| self.type_expr(arg)).collect();
+                format!("{}<{}>", t.name, args.join(", "))
+            }
+            Node::ArrayType(a) => format!(
+                "{}array[{}] of {}",
+                if a.is_packed { "packed " } else { "" },
+                self.type_expr(&a.index_type),
+                self.type_expr(&a.element_type)
+            ),
+            Node::DynamicArrayType(a) => format!("array of {}", self.type_expr(&a.element_type)),
+            Node::PointerType(p) => format!("^{}", self.type_expr(&p.base_type)),
+            Node::SetType(s) => format!("set of {}", self.type_expr(&s.element_type)),
+            Node::StringType(s) => match &s.length {
+                Some(length) => format!("string[{}]", expression(length)),
+                None => "string".to_string(),
+            },
+            Node::FileType(f) => match &f.element_type {
+                Some(element) => format!("file of {}", self.type_expr(element)),
+                None => "file".to_string(),
+            },
+            Node::EnumType(e) => format!("({})", e.values.join(", ")),
+            Node::SubrangeType(s) => format!("{}..{}", expression(&s.low), expression(&s.high)),
+            Node::ProceduralType(p) => {
+                let mut text = if p.is_function { "function" } else { "procedure" }.to_string();
+                text.push_str(&self.params(&p.params));
+                if let Some(return_type) = &p.return_type {
+                    text.push_str(&format!(": {}", self.type_expr(return_type)));
+                }
+                if p.is_method_pointer {
+                    text.push_str(" of object");
+                }
+                text
+            }
+            Node::RecordType(r) => {
+                let inner = "  ".repeat(self.indent + 1);
+                let mut text = format!("{}record\n", if r.is_packed { "packed " } else { "" });
+                for field in &r.fields {
+                    text.push_str(&format!("{}{};\n", inner, self.field(field)));
+                }
+                if let Some(variant) = &r.variant {
+                    let tag = variant.tag_field.as_ref().map(|tag| format!("{}: ", tag)).unwrap_or_default();
+                    text.push_str(&format!("{}case {}{} of\n", inner, tag, self.type_expr(&variant.tag_type)));
+                    for case in &variant.variants {
+                        text.push_str(&format!("{}  {};\n", inner, self.variant(case)));
+                    }
+                    if let Some(fields) = &variant.else_variant {
+                        text.push_str(&format!("{}  else ({});\n", inner, self.fields(fields)));
+                    }
+                }
+                text.push_str(&format!("{}end", "  ".repeat(self.indent)));
+                text
+            }
+            other => format!("{{ {} }}", other.kind()),
+        }
+    }
+
+    /// `<T; U: C>`, or nothing
+    fn generic_params(&self, params: &[ast::GenericParam]) -> String {
+        if params.is_empty() {
+            return String::new();
+        }
+        let params: Vec<String> = params
+            .iter()
+            .map(|param| match &param.constraint {
+                Some(constraint) => format!("{}: {}", param.name, self.type_expr(constraint)),
+                None => param.name.clone(),
+            })
+            .collect();
+        format!("<{}>", params.join("; "))
+    }
+
+    fn field(&self, field: &ast::FieldDecl) -> String {
+        format!("{}: {}", field.names.join(", "), self.type_expr(&field.type_expr))
+    }
+
+    fn fields(&self, fields: &[ast::FieldDecl]) -> String {
+        fields.iter().map(|field| self.field(field)).collect::<Vec<_>>().join("; ")
+    }
+
+    /// `labels: (fields)`, with a nested variant part on the same line
+    fn variant(&self, variant: &ast::Variant) -> String {
+        let labels: Vec<String> = variant
+            .values
+            .iter()
+            .map(|value| match value {
+                Node::SubrangeType(range) => format!("{}..{}", expression(&range.low), expression(&range.high)),
+                value => expression(value),
+            })
+            .collect();
+        let mut fields = self.fields(&variant.fields);
+        if let Some(nested) = &variant.variant {
+            let tag = nested.tag_field.as_ref().map(|tag| format!("{}: ", tag)).unwrap_or_default();
+            let mut cases: Vec<String> = nested.variants.iter().map(|case| self.variant(case)).collect();
+            if let Some(else_fields) = &nested.else_variant {
+                cases.push(format!("else ({})", self.fields(else_fields)));
+            }
+            if !fields.is_empty() {
+                fields.push_str("; ");
+            }
+            fields.push_str(&format!("case {}{} of {}", tag, self.type_expr(&nested.tag_type), cases.join("; ")));
+        }
+        format!("{}: ({})", labels.join(", "), fields)
+    }
+}
+
+/// An expression, parenthesized only where the parser's precedence needs it
+fn expression(node: &Node) -> String {
+    match node {
+        Node::LiteralExpr(literal) => match &literal.value {
+            LiteralValue::Integer(value) => value.to_string(),
+            // Debug keeps a fraction or exponent, so it reads back as a real
+            LiteralValue::Real(value) => format!("{:?}", value),
+            LiteralValue::Char(value) => quote(&char::from(*value).to_string()),
+            LiteralValue::String(value) => quote(value),
+            LiteralValue::Boolean(value) => (if *value { "true" } else { "false" }).to_string(),
+            LiteralValue::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("$\"{}\"", bytes.join(" "))
+            }
+        },
+        Node::IdentExpr(ident) => ident.name.clone(),
+        Node::CallExpr(call) => format!("{}{}", call.name, arguments(&call.args, true)),
+        Node::IndexExpr(index) => format!("{}[{}]", expression(&index.array), expression(&index.index)),
+        Node::FieldExpr(field) => format!("{}.{}", expression(&field.record), field.field),
+        Node::DerefExpr(deref) => format!("{}^", expression(&deref.pointer)),
+        Node::AddressOfExpr(address) => format!("@{}", operand(&address.target)),
+        Node::UnaryExpr(unary) => {
+            let op = match unary.op {
+                UnaryOp::Plus => "+",
+                UnaryOp::Minus => "-",
+                UnaryOp::Not => "not ",
+                UnaryOp::AddressOf => "@",
+            };
+            format!("{}{}", op, operand(&unary.expr))
+        }
+        Node::BinaryExpr(binary) => {
+            let level = precedence(binary.op);
+            // Operators are left-associative: a right operand of the same
+            // level needs parentheses, a left one does not
+            let left = match binary.left.as_ref() {
+                Node::BinaryExpr(left) if precedence(left.op) < level => format!("({})", expression(&binary.left)),
+                _ => expression(&binary.left),
+            };
+            let right = match binary.right.as_ref() {
+                Node::BinaryExpr(right) if precedence(right.op) <= level => format!("({})", expression(&binary.right)),
+                _ => expression(&binary.right),
+            };
+            format!("{} {} {}", left, operator(binary.op), right)
+        }
+        Node::SetLiteral(set) => {
+            let elements: Vec<String> = set
+                .elements
+                .iter()
+                .map(|element| match element {
+                    SetElement::Value(value) => expression(value),
+                    SetElement::Range { start, end } => format!("{}..{}", expression(start), expression(end)),
+                })
+                .collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Node::MethodCall(call) => format!("{}.{}{}", operand(&call.object), call.method, arguments(&call.args, false)),
+        Node::InheritedExpr(inherited) => {
+            let name = inherited.method_name.as_ref().map(|name| format!(" {}", name)).unwrap_or_default();
+            format!("inherited{}{}", name, arguments(&inherited.args, false))
+        }
+        Node::EnumLiteralExpr(e) => qualified(&e.enum_type, &e.value),
+        other => format!("{{ {} }}", other.kind()),
+    }
+}
+
+/// The operand of a prefix operator, which binds tighter than any binary one
+fn operand(node: &Node) -> String {
+    match node {
+        Node::BinaryExpr(_) | Node::UnaryExpr(_) | Node::AddressOfExpr(_) => format!("({})", expression(node)),
+        _ => expression(node),
+    }
+}
+
+/// `(a, b)`; an empty list is written only where it tells a call from a name
+fn arguments(args: &[Node], always: bool) -> String {
+    if args.is_empty() && !always {
+        return String::new();
+    }
+    let args: Vec<String> = args.iter().map(expression).collect();
+    format!("({})", args.join(", "))
+}
+
+/// Binding strength of `op`, as the parser assigns it
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Equal
+        | BinaryOp::NotEqual
+        | BinaryOp::Less
+        | BinaryOp::LessEqual
+        | BinaryOp::Greater
+        | BinaryOp::GreaterEqual
+        | BinaryOp::In
+        | BinaryOp::Is
+        | BinaryOp::As => 3,
+        BinaryOp::Add | BinaryOp::Subtract => 4,
+        BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Div | BinaryOp::Mod => 5,
+    }
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Subtract => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::Div => "div",
+        BinaryOp::Mod => "mod",
+        BinaryOp::Equal => "=",
+        BinaryOp::NotEqual => "<>",
+        BinaryOp::Less => "<",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::Greater => ">",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+        BinaryOp::In => "in",
+        BinaryOp::Is => "is",
+        BinaryOp::As => "as",
+    }
+}
+
+/// A string literal, with the escapes the lexer reads back
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("'");
+    for ch in text.chars() {
+        match ch {
+            '\'' => quoted.push_str("''"),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+fn qualified(qualifier: &Option<String>, name: &str) -> String {
+    match qualifier {
+        Some(qualifier) => format!("{}.{}", qualifier, name),
+        None => name.to_string(),
+    }
+}
+
+fn is_statement(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::IfStmt(_)
+            | Node::WhileStmt(_)
+            | Node::ForStmt(_)
+            | Node::ForInStmt(_)
+            | Node::RepeatStmt(_)
+            | Node::CaseStmt(_)
+            | Node::AssignStmt(_)
+            | Node::CallStmt(_)
+            | Node::MethodCall(_)
+            | Node::TryStmt(_)
+            | Node::RaiseStmt(_)
+            | Node::WithStmt(_)
+            | Node::GotoStmt(_)
+            | Node::LabeledStmt(_)
+            | Node::AsmStmt(_)
+    )
+}
+
+fn is_type(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::NamedType(_)
+            | Node::ArrayType(_)
+            | Node::DynamicArrayType(_)
+            | Node::PointerType(_)
+            | Node::SetType(_)
+            | Node::StringType(_)
+            | Node::FileType(_)
+            | Node::EnumType(_)
+            | Node::SubrangeType(_)
+            | Node::ProceduralType(_)
+            | Node::RecordType(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use ast::*;
+    use tokens::Span;
+
+    /// Debug form of `node` without spans, for structural comparison
+    fn structure(node: &Node) -> String {
+        let text = format!("{:?}", node);
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("span: Span {") {
+            out.push_str(&rest[..start]);
+            let end = start + rest[start..].find('}').unwrap() + 1;
+            rest = rest[end..].strip_prefix(", ").unwrap_or(&rest[end..]);
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn span() -> Span {
+        Span::at(0, 1, 1)
+    }
+
+    fn boxed(node: Node) -> Box<Node> {
+        Box::new(node)
+    }
+
+    const NAMES: &[&str] = &["Alpha", "Beta", "Gamma", "Delta", "Count", "Total", "Temp", "Buf", "X", "Y"];
+    const TYPES: &[&str] = &["integer", "byte", "word", "char", "boolean", "TPoint"];
+
+    /// Random trees of the shapes the parser builds from source
+    struct Generator {
+        state: u64,
+    }
+
+    impl Generator {
+        fn below(&mut self, n: usize) -> usize {
+            // xorshift64
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            (self.state % n as u64) as usize
+        }
+
+        fn chance(&mut self, percent: usize) -> bool {
+            self.below(100) < percent
+        }
+
+        fn name(&mut self) -> String {
+            NAMES[self.below(NAMES.len())].to_string()
+        }
+
+        fn names(&mut self) -> Vec<String> {
+            (0..1 + self.below(3)).map(|_| self.name()).collect()
+        }
+
+        fn some<T>(&mut self, max: usize, mut f: impl FnMut(&mut Self) -> T) -> Vec<T> {
+            (0..self.below(max + 1)).map(|_| f(self)).collect()
+        }
+
+        fn text(&mut self, len: usize) -> String {
+            (0..len).map(|_| [' ', 'a', 'Z', '0', '+', '\'', '\\', '\n'][self.below(8)]).collect()
+        }
+
+        fn program(&mut self) -> Node {
+            let uses = self.chance(20).then(|| UsesClause { units: self.names(), span: span() });
+            Node::Program(Program { name: self.name(), directives: vec![], uses, block: boxed(self.block(2)), span: span() })
+        }
+
+
+        /// A routine or program block; `depth` bounds nested routines
+        fn block(&mut self, depth: usize) -> Node {
+            let statements = self.some(3, |g| g.statement(2));
+            let mut block = empty_block(statements);
+            if self.chance(20) {
+                let labels = self.names();
+                block.label_decls.push(Node::LabelDecl(LabelDecl { labels, span: span() }));
+            }
+            block.const_decls = self.some(2, |g| {
+                let type_expr = g.chance(30).then(|| boxed(g.type_expr(1)));
+                Node::ConstDecl(ConstDecl {
+                    name: g.name(),
+                    type_expr,
+                    value: boxed(g.expression(2)),
+                    is_resourcestring: false,
+                    span: span(),
+                })
+            });
+            block.type_decls = self.some(2, |g| {
+                Node::TypeDecl(TypeDecl {
+                    name: g.name(),
+                    generic_params: vec![],
+                    type_expr: boxed(g.type_expr(2)),
+                    distinct: g.chance(20),
+                    span: span(),
+                })
+            });
+            block.var_decls = self.some(2, |g| {
+                Node::VarDecl(VarDecl {
+                    names: g.names(),
+                    type_expr: boxed(g.type_expr(2)),
+                    absolute_address: g.chance(10).then(|| boxed(g.expression(1))),
+                    is_class_var: false,
+                    span: span(),
+                })
+            });
+            if depth > 0 {
+                block.proc_decls = self.some(2, |g| g.routine(depth - 1, false));
+                block.func_decls = self.some(2, |g| g.routine(depth - 1, true));
+            }
+            Node::Block(block)
+        }
+
+        fn routine(&mut self, depth: usize, is_function: bool) -> Node {
+            let name = self.name();
+            let params = self.params();
+            let (is_forward, is_external) = match self.below(6) {
+                0 => (true, false),
+                1 => (false, true),
+                _ => (false, false),
+            };
+            let block = if is_forward || is_external {
+                Node::Block(empty_block(vec![]))
+            } else {
+                self.block(depth)
+            };
+            let external_name = (is_external && self.chance(50)).then(|| format!("_{}", self.name()));
+            if is_function {
+                Node::FuncDecl(FuncDecl {
+                    name,
+                    class_name: None,
+                    generic_params: vec![],
+                    params,
+                    return_type: boxed(self.named_type()),
+                    block: boxed(block),
+                    is_forward,
+                    is_external,
+                    external_name,
+                    is_class_method: false,
+                    is_compiletime: false,
+                    is_pure: false,
+                    binding: MethodBinding::Static,
+                    span: span(),
+                })
+            } else {
+                Node::ProcDecl(ProcDecl {
+                    name,
+                    class_name: None,
+                    generic_params: vec![],
+                    params,
+                    block: boxed(block),
+                    is_forward,
+                    is_external,
+                    external_name,
+                    is_class_method: false,
+                    binding: MethodBinding::Static,
+                    span: span(),
+                })
+            }
+        }
+
+        fn params(&mut self) -> Vec<Param> {
+            self.some(3, |g| {
+                let param_type =
+                    [ParamType::Value, ParamType::Var, ParamType::Const, ParamType::ConstRef, ParamType::Out][g.below(5)];
+                let untyped = param_type != ParamType::Value && g.chance(20);
+                let type_expr = (!untyped).then(|| boxed(g.type_expr(1)));
+                let default_value = (!untyped && g.chance(15)).then(|| boxed(g.expression(1)));
+                Param { names: g.names(), param_type, type_expr, default_value, span: span() }
+            })
+        }
+
+        fn named_type(&mut self) -> Node {
+            let name = TYPES[self.below(TYPES.len())].to_string();
+            Node::NamedType(NamedType { name, generic_args: vec![], span: span() })
+        }
+
+        fn enum_type(&mut self) -> Node {
+            Node::EnumType(EnumType { values: self.names(), span: span() })
+        }
+
+        fn subrange_type(&mut self) -> Node {
+            Node::SubrangeType(SubrangeType { low: boxed(self.integer()), high: boxed(self.integer()), span: span() })
+        }
+
+        fn fields(&mut self, max: usize) -> Vec<FieldDecl> {
+            self.some(max, |g| FieldDecl { names: g.names(), type_expr: boxed(g.type_expr(1)), span: span() })
+        }
+
+        fn type_expr(&mut self, depth: usize) -> Node {
+            if depth == 0 {
+                return self.named_type();
+            }
+            match self.below(11) {
+                0 => Node::ArrayType(ArrayType {
+                    is_packed: self.chance(20),
+                    index_type: boxed(match self.below(3) {
+                        0 => self.enum_type(),
+                        1 => self.subrange_type(),
+                        _ => self.named_type(),
+                    }),
+                    element_type: boxed(self.type_expr(depth - 1)),
+                    span: span(),
+                }),
+                1 => Node::DynamicArrayType(DynamicArrayType { element_type: boxed(self.type_expr(depth - 1)), span: span() }),
+                2 => Node::PointerType(PointerType { base_type: boxed(self.named_type()), span: span() }),
+                3 => Node::SetType(SetType { element_type: boxed(self.enum_type()), span: span() }),
+                4 => {
+                    let length = self.chance(50).then(|| boxed(self.integer()));
+                    Node::StringType(StringType { length, span: span() })
+                }
+                5 => {
+                    let element_type = self.chance(50).then(|| boxed(self.named_type()));
+                    Node::FileType(FileType { element_type, span: span() })
+                }
+                6 => self.enum_type(),
+                7 => {
+                    let is_function = self.chance(50);
+                    let params = self.some(2, |g| Param {
+                        names: g.names(),
+                        param_type: [ParamType::Value, ParamType::Var][g.below(2)],
+                        type_expr: Some(boxed(g.named_type())),
+                        default_value: None,
+                        span: span(),
+                    });
+                    Node::ProceduralType(ProceduralType {
+                        is_function,
+                        params,
+                        return_type: is_function.then(|| boxed(self.named_type())),
+                        is_method_pointer: self.chance(30),
+                        span: span(),
+                    })
+                }
+                8 => {
+                    let variant = self.chance(40).then(|| self.variant_part(2));
+                    Node::RecordType(RecordType { is_packed: self.chance(20), fields: self.fields(3), variant, span: span() })
+                }
+                _ => self.named_type(),
+            }
+        }
+
+        fn variant_part(&mut self, depth: usize) -> VariantPart {
+            VariantPart {
+                tag_field: self.chance(50).then(|| self.name()),
+                tag_type: boxed(self.named_type()),
+                variants: self.some(2, |g| Variant {
+                    values: (0..1 + g.below(2))
+                        .map(|_| {
+                            if g.chance(30) {
+                                Node::SubrangeType(SubrangeType {
+                                    low: boxed(g.integer()),
+                                    high: boxed(g.integer()),
+                                    span: span(),
+                                })
+                            } else {
+                                g.integer()
+                            }
+                        })
+                        .collect(),
+                    fields: g.fields(2),
+                    variant: (depth > 1 && g.chance(20)).then(|| Box::new(g.variant_part(depth - 1))),
+                    span: span(),
+                }),
+                else_variant: self.chance(30).then(|| self.fields(2)),
+                span: span(),
+            }
+        }
+
+        fn integer(&mut self) -> Node {
+            let value = if self.chance(70) { self.below(10) as u32 } else { self.below(1 << 20) as u32 };
+            Node::LiteralExpr(LiteralExpr { value: LiteralValue::Integer(value), span: span() })
+        }
+
+        fn literal(&mut self) -> Node {
+            let value = match self.below(6) {
+                0 | 1 => return self.integer(),
+                5 => LiteralValue::Real(self.below(100_000) as f64 / 100.0),
+                // A quote or escape reads back as a string
+                2 => LiteralValue::Char(b" aZ0+"[self.below(5)]),
+                // One-character strings read back as characters
+                3 => {
+                    let len = if self.chance(20) { 0 } else { 2 + self.below(5) };
+                    LiteralValue::String(self.text(len))
+                }
+                _ => LiteralValue::Boolean(self.chance(50)),
+            };
+            Node::LiteralExpr(LiteralExpr { value, span: span() })
+        }
+
+        /// A variable reference: a name with indexes, fields and dereferences
+        fn lvalue(&mut self, depth: usize) -> Node {
+            let mut node = Node::IdentExpr(IdentExpr { name: self.name(), span: span() });
+            for _ in 0..self.below(3) {
+                node = match self.below(3) {
+                    0 => Node::IndexExpr(IndexExpr { array: boxed(node), index: boxed(self.expression(depth)), span: span() }),
+                    1 => Node::FieldExpr(FieldExpr { record: boxed(node), field: self.name(), span: span() }),
+                    _ => Node::DerefExpr(DerefExpr { pointer: boxed(node), span: span() }),
+                };
+            }
+            node
+        }
+
+        fn expression(&mut self, depth: usize) -> Node {
+            if depth == 0 {
+                return if self.chance(50) { self.literal() } else { self.lvalue(0) };
+            }
+            match self.below(8) {
+                0 | 1 => {
+                    let op = [
+                        BinaryOp::Add,
+                        BinaryOp::Subtract,
+                        BinaryOp::Multiply,
+                        BinaryOp::Divide,
+                        BinaryOp::Div,
+                        BinaryOp::Mod,
+                        BinaryOp::Equal,
+                        BinaryOp::NotEqual,
+                        BinaryOp::Less,
+                        BinaryOp::LessEqual,
+                        BinaryOp::Greater,
+                        BinaryOp::GreaterEqual,
+                        BinaryOp::And,
+                        BinaryOp::Or,
+                        BinaryOp::In,
+                        BinaryOp::Is,
+                        BinaryOp::As,
+                    ][self.below(17)];
+                    Node::BinaryExpr(BinaryExpr {
+                        op,
+                        left: boxed(self.expression(depth - 1)),
+                        right: boxed(self.expression(depth - 1)),
+                        span: span(),
+                    })
+                }
+                2 => Node::UnaryExpr(UnaryExpr {
+                    op: [UnaryOp::Plus, UnaryOp::Minus, UnaryOp::Not][self.below(3)],
+                    expr: boxed(self.expression(depth - 1)),
+                    span: span(),
+                }),
+                3 => Node::AddressOfExpr(AddressOfExpr { target: boxed(self.lvalue(depth - 1)), span: span() }),
+                4 => Node::CallExpr(CallExpr { name: self.name(), args: self.some(2, |g| g.expression(depth - 1)), span: span() }),
+                5 => Node::SetLiteral(SetLiteral {
+                    elements: self.some(3, |g| {
+                        if g.chance(30) {
+                            SetElement::Range { start: boxed(g.expression(depth - 1)), end: boxed(g.expression(depth - 1)) }
+                        } else {
+                            SetElement::Value(boxed(g.expression(depth - 1)))
+                        }
+                    }),
+                    span: span(),
+                }),
+                6 => self.lvalue(depth - 1),
+                _ => self.literal(),
+            }
+        }
+
+        fn statement(&mut self, depth: usize) -> Node {
+            let simple = depth == 0;
+            match if simple { self.below(3) } else { self.below(14) } {
+                0 => Node::AssignStmt(AssignStmt { target: boxed(self.lvalue(1)), value: boxed(self.expression(2)), span: span() }),
+                1 => Node::CallStmt(CallStmt { name: self.name(), args: self.some(2, |g| g.expression(1)), span: span() }),
+                2 => Node::GotoStmt(GotoStmt { label: self.name(), span: span() }),
+                3 => Node::Block(empty_block(self.some(3, |g| g.statement(depth - 1)))),
+                4 => {
+                    let else_block = self.chance(50).then(|| boxed(self.statement(depth - 1)));
+                    let mut then_block = self.statement(depth - 1);
+                    // An else after an open `if` would belong to that `if`
+                    if else_block.is_some() && is_open(&then_block) {
+                        then_block = Node::Block(empty_block(vec![then_block]));
+                    }
+                    Node::IfStmt(IfStmt { condition: boxed(self.expression(2)), then_block: boxed(then_block), else_block, span: span() })
+                }
+                5 => Node::WhileStmt(WhileStmt { condition: boxed(self.expression(2)), body: boxed(self.statement(depth - 1)), span: span() }),
+                6 => Node::ForStmt(ForStmt {
+                    var_name: self.name(),
+                    start_expr: boxed(self.expression(1)),
+                    direction: if self.chance(50) { ForDirection::To } else { ForDirection::Downto },
+                    end_expr: boxed(self.expression(1)),
+                    body: boxed(self.statement(depth - 1)),
+                    unroll: self.chance(20).then(|| self.below(9) as u32),
+                    span: span(),
+                }),
+                7 => Node::ForInStmt(ForInStmt {
+                    var_name: self.name(),
+                    collection_expr: boxed(self.expression(1)),
+                    body: boxed(self.statement(depth - 1)),
+                    span: span(),
+                }),
+                8 => Node::RepeatStmt(RepeatStmt {
+                    statements: self.some(3, |g| g.statement(depth - 1)),
+                    condition: boxed(self.expression(2)),
+                    span: span(),
+                }),
+                9 => Node::CaseStmt(CaseStmt {
+                    expr: boxed(self.expression(1)),
+                    cases: (0..1 + self.below(3))
+                        .map(|_| CaseBranch {
+                            values: (0..1 + self.below(2)).map(|_| self.expression(1)).collect(),
+                            statement: boxed(self.statement(depth - 1)),
+                            span: span(),
+                        })
+                        .collect(),
+                    else_branch: self.chance(40).then(|| boxed(self.statement(depth - 1))),
+                    span: span(),
+                }),
+                10 => {
+                    let try_block = self.some(2, |g| g.statement(depth - 1));
+                    let mut stmt = TryStmt {
+                        try_block,
+                        except_block: None,
+                        finally_block: None,
+                        exception_handlers: vec![],
+                        exception_else: None,
+                        span: span(),
+                    };
+                    match self.below(3) {
+                        0 => stmt.finally_block = Some(self.some(2, |g| g.statement(depth - 1))),
+                        1 => stmt.except_block = Some(self.some(2, |g| g.statement(depth - 1))),
+                        _ => {
+                            stmt.exception_handlers = (0..1 + self.below(2))
+                                .map(|_| ExceptionHandler {
+                                    variable: self.chance(50).then(|| self.name()),
+                                    exception_type: boxed(self.named_type()),
+                                    handler: boxed(self.statement(depth - 1)),
+                                    span: span(),
+                                })
+                                .collect();
+                            stmt.exception_else = self.chance(30).then(|| boxed(self.statement(depth - 1)));
+                        }
+                    }
+                    Node::TryStmt(stmt)
+                }
+                11 => Node::RaiseStmt(RaiseStmt { exception: self.chance(60).then(|| boxed(self.expression(1))), span: span() }),
+                12 => Node::WithStmt(WithStmt {
+                    records: (0..1 + self.below(2)).map(|_| self.lvalue(1)).collect(),
+                    statement: boxed(self.statement(depth - 1)),
+                    span: span(),
+                }),
+                _ => Node::LabeledStmt(LabeledStmt { label: self.name(), statement: boxed(self.statement(depth - 1)), span: span() }),
+            }
+        }
+    }
+
+    fn empty_block(statements: Vec<Node>) -> Block {
+        Block {
+            directives: vec![],
+            label_decls: vec![],
+            const_decls: vec![],
+            type_decls: vec![],
+            var_decls: vec![],
+            threadvar_decls: vec![],
+            proc_decls: vec![],
+            func_decls: vec![],
+            operator_decls: vec![],
+            statements,
+            span: span(),
+        }
+    }
+
+    /// Whether `node` ends in an `if` without `else`
+    fn is_open(node: &Node) -> bool {
+        match node {
+            Node::IfStmt(i) => i.else_block.as_ref().is_none_or(|e| is_open(e)),
+            Node::WhileStmt(w) => is_open(&w.body),
+            Node::ForStmt(f) => is_open(&f.body),
+            Node::ForInStmt(f) => is_open(&f.body),
+            Node::WithStmt(w) => is_open(&w.statement),
+            Node::LabeledStmt(l) => is_open(&l.statement),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_print_layout() {
+        let source = "program P;\nconst N = 2;\nvar A, B: array[byte] of integer;\nprocedure Q(var X: integer; Y: byte = 1);\nbegin\n  if X > (Y + 1) * 2 then X := -(X - Y) else begin X := 0 end\nend;\nbegin\n  case A[1] of 1, 2: Q(B[0]); else A[0] := N end\nend.\n";
+        let ast = Parser::new(source).unwrap().parse().unwrap();
+        assert_eq!(
+            print(&ast),
+            "program P;\nconst\n  N = 2;\nvar\n  A, B: array[byte] of integer;\nprocedure Q(var X: integer; Y: byte = 1);\nbegin\n  if X > (Y + 1) * 2 then\n    X := -(X - Y)\n  else\n  begin\n    X := 0;\n  end;\nend;\nbegin\n  case A[1] of\n    1, 2: Q(B[0]);\n    else\n      A[0] := N\n  end;\nend.\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip_random_programs() {
+        let mut generator = Generator { state: 0x5EED_CAFE_F00D_1234 };
+        for case in 0..500 {
+            let ast = generator.program();
+            let source = print(&ast);
+            let reparsed = Parser::new(&source)
+                .and_then(|mut parser| parser.parse())
+                .unwrap_or_else(|e| panic!("case {}: {:?}\n{}", case, e, source));
+            assert_eq!(structure(&reparsed), structure(&ast), "case {}:\n{}", case, source);
+            // Printing is a fixed point
+            assert_eq!(print(&reparsed), source, "case {}", case);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Panic-mode error recovery
+//!
+//! A syntax error in a statement or a declaration does not end the parse.
+//! The error is recorded and tokens are skipped up to a point the
+//! enclosing list can resume at: past the next `;`, or before a keyword
+//! that starts a statement, a declaration section or ends a block. Every
+//! error is then reported in one run:
+//!
+//! ```text
+//!     begin
+//!       X := ;              { error: expression expected }
+//!       if X then Y := 1    { parsed again from here }
+//!       Z := (1 + 2;        { error: ")" expected }
+//!     end.
+//! ```
+//!
+//! Skipping always moves past the token the failed construct started at,
+//! so recovery cannot loop. An error at the same position as the last one
+//! recorded is a consequence of it and is dropped. The AST of a file with
+//! errors is incomplete, so [`Parser::parse`](super::Parser::parse) still
+//! fails, with the first error.
+
+use errors::{ParserError, ParserResult};
+use tokens::TokenKind;
+
+/// Tokens a statement list resumes at
+const STATEMENT_SYNC: &[TokenKind] = &[
+    TokenKind::KwBegin,
+    TokenKind::KwEnd,
+    TokenKind::KwIf,
+    TokenKind::KwWhile,
+    TokenKind::KwFor,
+    TokenKind::KwRepeat,
+    TokenKind::KwUntil,
+    TokenKind::KwCase,
+    TokenKind::KwWith,
+    TokenKind::KwTry,
+    TokenKind::KwExcept,
+    TokenKind::KwFinally,
+    TokenKind::KwGoto,
+];
+
+/// Tokens a declaration section resumes at
+const DECLARATION_SYNC: &[TokenKind] = &[
+    TokenKind::KwLabel,
+    TokenKind::KwConst,
+    TokenKind::KwResourcestring,
+    TokenKind::KwType,
+    TokenKind::KwVar,
+    TokenKind::KwThreadvar,
+    TokenKind::KwProcedure,
+    TokenKind::KwFunction,
+    TokenKind::KwOperator,
+    TokenKind::KwConstructor,
+    TokenKind::KwDestructor,
+    TokenKind::KwBegin,
+    TokenKind::KwEnd,
+    TokenKind::KwImplementation,
+];
+
+impl super::Parser {
+    /// Every syntax error the last [`parse`](Self::parse) found, in source
+    /// order
+    pub fn errors(&self) -> &[ParserError] {
+        &self.errors
+    }
+
+    /// Record `error` unless it follows from the last one recorded
+    pub(crate) fn record_error(&mut self, error: ParserError) {
+        if self.errors.last().is_none_or(|last| error.span().start > last.span().start) {
+            self.errors.push(error);
+        }
+    }
+
+    /// Start of the current token, where a construct about to be parsed
+    /// begins
+    pub(crate) fn position(&self) -> usize {
+        self.current().map_or(usize::MAX, |token| token.span.start)
+    }
+
+    /// Record `error` from the statement that began at `start` and skip to
+    /// where the next one can be parsed
+    pub(crate) fn recover_statement(&mut self, error: ParserError, start: usize) -> ParserResult<()> {
+        self.record_error(error);
+        self.synchronize(STATEMENT_SYNC, start)
+    }
+
+    /// Record `error` from the declaration that began at `start` and skip
+    /// to where the next one can be parsed
+    pub(crate) fn recover_declaration(&mut self, error: ParserError, start: usize) -> ParserResult<()> {
+        self.record_error(error);
+        self.synchronize(DECLARATION_SYNC, start)
+    }
+
+    /// Skip past the next `;`, or to a token in `sync` other than the one at
+    /// `start`, or to the end of the file
+    fn synchronize(&mut self, sync: &[TokenKind], start: usize) -> ParserResult<()> {
+        loop {
+            let moved = self.position() != start;
+            match self.current().map(|token| &token.kind) {
+                None | Some(TokenKind::Eof) => return Ok(()),
+                Some(TokenKind::Semicolon) => return self.advance(),
+                Some(kind) if moved && sync.contains(kind) => return Ok(()),
+                _ => self.advance()?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Parser;
+    use errors::ParserError;
+
+    fn errors(source: &str) -> Vec<(usize, String)> {
+        let mut parser = Parser::new(source).unwrap();
+        assert!(parser.parse().is_err());
+        parser
+            .errors()
+            .iter()
+            .map(|error| {
+                let message = match error {
+                    ParserError::UnexpectedToken { expected, .. } => format!("expected {}", expected),
+                    ParserError::UnexpectedEof { expected, .. } => format!("eof, expected {}", expected),
+                    ParserError::InvalidSyntax { message, .. } => message.clone(),
+                };
+                (error.span().line, message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parser_reports_every_statement_error() {
+        let source = "program P;
+            var X, Y: integer;
+            begin
+              X := ;
+              if X > 0 then Y := 1;
+              Y := (1 + 2;
+              repeat
+                X := X +
+              until X > 3;
+              Y := 2
+            end.";
+        let lines: Vec<usize> = errors(source).into_iter().map(|(line, _)| line).collect();
+        assert_eq!(lines, [4, 6, 9]);
+    }
+
+    #[test]
+    fn test_parser_reports_every_declaration_error() {
+        let source = "program P;
+            const
+              A = ;
+              B = 2;
+            type
+              T = array [1..] of byte;
+              U = integer;
+            var
+              X integer;
+              Y: integer;
+            begin
+              Y := B
+            end.";
+        let errors = errors(source);
+        assert_eq!(errors.iter().map(|(line, _)| *line).collect::<Vec<_>>(), [3, 6, 9]);
+        assert_eq!(errors[2].1, "expected :");
+    }
+}
@@ -16,193 +16,6 @@ impl super::Parser {
         } else if self.check(&TokenKind::KwWhile) {
             self.parse_while_statement()
         } else if self.check(&TokenKind::KwFor) {
-            // Check if it's a for..in loop (FOR identifier IN) or traditional for loop (FOR identifier :=)
-            // Pattern: FOR identifier [IN|:=]
-            // Advance and check the token after the identifier
-            self.advance()?; // consume FOR
-            if matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) {
-                // Check what comes after the identifier
-                if self.check_peek(&TokenKind::KwIn) {
-                    // It's a for..in loop - we've already consumed FOR, so parse from here
-                    // But we need to go back one token, so restore FOR
-                    let var_token = self.advance_and_get_token()?; // consume identifier and get it
-                    self.consume(TokenKind::KwIn, "IN")?;
-                    let collection_expr = self.parse_expression()?;
-                    self.consume(TokenKind::KwDo, "DO")?;
-                    let body = self.parse_statement()?;
-                    
-                    let start_span = self.current().map(|t| t.span).unwrap_or_else(|| Span::at(0, 1, 1));
-                    let span = start_span.merge(body.span());
-                    return Ok(Node::ForInStmt(ast::ForInStmt {
-                        var_name: match &var_token.kind {
-                            TokenKind::Identifier(name) => name.clone(),
-                            _ => return Err(ParserError::InvalidSyntax {
-                                message: "Expected identifier".to_string(),
-                                span: var_token.span,
-                            }),
-                        },
-                        collection_expr: Box::new(collection_expr),
-                        body: Box::new(body),
-                        span,
-                    }));
-                }
-            }
-            // Not a for..in loop, restore and parse as traditional for
-            // We've already consumed FOR, so we need to go back
-            // Actually, we can't easily restore, so let's parse traditional for from current state
-            // But we've already consumed FOR, so we need to handle this differently
-            // Let's just call parse_for_statement which expects FOR to be current
-            // But we've already consumed it, so we need to adjust
-            // Actually, the simplest is to not consume FOR here, and let parse_for_statement handle it
-            // But we already consumed it... Let me think
-            // Actually, I should not have consumed FOR. Let me fix this.
-            // We need to check without consuming. Since we already consumed FOR, we need to handle it.
-            // For now, let's just try to parse as traditional for and see what happens
-            // Actually, the issue is parse_for_statement expects FOR to be current, but we've already consumed it
-            // So we need to either:
-            // 1. Not consume FOR in the check (but we need to see what's after identifier)
-            // 2. Have parse_for_statement handle the case where FOR is already consumed
-            // 3. Use a different approach
-            
-            // Let's use approach 1: check without consuming FOR
-            // But we already consumed it, so we need to restore
-            // Actually, we can't easily restore the lexer state
-            // So let's use a simpler approach: parse_for_statement will handle FOR, but we've already consumed it
-            // So we need to adjust parse_for_statement or create a helper
-            
-            // Simplest: inline the check logic here without consuming FOR first
-            // But we already did... Let me revert this approach
-            
-            // New approach: Don't consume FOR, use peek to check
-            // But peek only shows one token ahead, and we need two
-            // So we need to advance to check, then somehow restore
-            
-            // Actually, the cleanest is to have parse_for_statement not consume FOR if it's already consumed
-            // But that's complex
-            
-            // Let's try: parse the identifier and check, if IN then parse for..in inline, otherwise parse traditional for
-            // But we need FOR to still be available for traditional for
-            
-            // I think the issue is my approach is too complex. Let me simplify:
-            // Just try to parse as for..in first (it will fail fast if not), then fall back to traditional for
-            // But that's not great for error messages
-            
-            // Actually, let me just fix the current code: we've consumed FOR, so we need to handle that
-            // For traditional for, it expects FOR to be current, so we're in a bad state
-            // Let me check if we can reconstruct the state or use a different method
-            
-            // Simplest fix: Don't consume FOR in the check. Instead, check peek tokens.
-            // But we only have one peek token...
-            
-            // OK, new plan: Check if peek (after FOR) is identifier, and peek of that (manually get next token from lexer)
-            // But that's complex too
-            
-            // Let me try the simplest: parse_for_statement should handle the case where we've already seen FOR
-            // Actually, let me just make parse_for_statement work whether FOR is consumed or not
-            // But that requires changing parse_for_statement signature
-            
-            // Final approach: Just check the pattern without consuming, using what we have
-            // If peek is identifier and we can somehow check what's after that...
-            // Actually, we can advance, check, then if it's not IN, we've already messed up the state
-            
-            // I think the real solution is to not consume FOR until we know which type it is
-            // So let's check peek tokens without consuming FOR
-            if self.check_peek(&TokenKind::Identifier(String::new())) {
-                // We have FOR (current) identifier (peek1)
-                // We need to check what's after identifier (peek2)
-                // But we only have one peek. So we need to advance to see peek2
-                // But then we can't easily go back
-                
-                // Let me try: advance to identifier, check peek for IN
-                self.advance()?; // Now current is identifier, peek is what comes after
-                if self.check_peek(&TokenKind::KwIn) {
-                    // It's for..in - we've consumed FOR and identifier is current
-                    // Continue parsing for..in from here
-                    let _var_token = self.advance_and_get_token()?; // Actually identifier is already current
-                    let var_name = match self.current().map(|t| &t.kind) {
-                        Some(TokenKind::Identifier(name)) => name.clone(),
-                        _ => return Err(ParserError::InvalidSyntax {
-                            message: "Expected identifier".to_string(),
-                            span: self.current().map(|t| t.span).unwrap_or_else(|| Span::at(0, 1, 1)),
-                        }),
-                    };
-                    self.advance()?; // consume identifier
-                    self.consume(TokenKind::KwIn, "IN")?;
-                    let collection_expr = self.parse_expression()?;
-                    self.consume(TokenKind::KwDo, "DO")?;
-                    let body = self.parse_statement()?;
-                    
-                    let start_span = self.current().map(|t| t.span).unwrap_or_else(|| Span::at(0, 1, 1));
-                    let span = start_span.merge(body.span());
-                    return Ok(Node::ForInStmt(ast::ForInStmt {
-                        var_name,
-                        collection_expr: Box::new(collection_expr),
-                        body: Box::new(body),
-                        span,
-                    }));
-                }
-                // Not IN, so it's traditional for - but we've consumed FOR and identifier
-                // We need to go back, but we can't easily
-                // So let's just continue parsing traditional for from here
-                // But parse_for_statement expects FOR to be current
-                // So we need to adjust: parse the rest as traditional for
-                // Actually, we can call a helper that parses the rest after FOR identifier :=
-                // But that's complex
-                
-                // Let me try: since we've consumed FOR and identifier is current,
-                // we can parse the rest: := expr TO/DOWNTO expr DO statement
-                let var_token = self.current().unwrap().clone();
-                let var_name = match &var_token.kind {
-                    TokenKind::Identifier(name) => name.clone(),
-                    _ => return Err(ParserError::InvalidSyntax {
-                        message: "Expected identifier".to_string(),
-                        span: var_token.span,
-                    }),
-                };
-                self.advance()?; // consume identifier
-                self.consume(TokenKind::Assign, ":=")?;
-                let start_expr = self.parse_expression()?;
-                let direction = if self.check(&TokenKind::KwTo) {
-                    self.advance()?;
-                    ast::ForDirection::To
-                } else if self.check(&TokenKind::KwDownto) {
-                    self.advance()?;
-                    ast::ForDirection::Downto
-                } else {
-                    return Err(ParserError::UnexpectedToken {
-                        expected: "TO or DOWNTO".to_string(),
-                        found: format!("{:?}", self.current().map(|t| &t.kind)),
-                        span: self.current().map(|t| t.span).unwrap_or_else(|| Span::at(0, 1, 1)),
-                    });
-                };
-                let end_expr = self.parse_expression()?;
-                self.consume(TokenKind::KwDo, "DO")?;
-                let body = self.parse_statement()?;
-                
-                let start_span = var_token.span;
-                let span = start_span.merge(body.span());
-                return Ok(Node::ForStmt(ast::ForStmt {
-                    var_name,
-                    start_expr: Box::new(start_expr),
-                    direction,
-                    end_expr: Box::new(end_expr),
-                    body: Box::new(body),
-                    span,
-                }));
-            }
-            // No identifier after FOR, must be error, but let parse_for_statement handle it
-            // But we've already consumed FOR, so we need to handle it
-            // Actually, if there's no identifier, parse_for_statement will error anyway
-            // But it expects FOR to be current
-            // So we have a problem
-            
-            // Let me just call parse_for_statement and see what happens
-            // Actually, we've consumed FOR, so current is the next token
-            // parse_for_statement expects FOR to be current
-            // So we can't call it directly
-            
-            // I think the cleanest solution is to not consume FOR in the check
-            // Let me rewrite this more carefully
             self.parse_for_statement()
         } else if self.check(&TokenKind::KwRepeat) {
             self.parse_repeat_statement()
@@ -226,6 +39,10 @@ impl super::Parser {
         } else if self.check(&TokenKind::KwAsm) {
             // ASM ... END
             self.parse_asm_statement()
+        } else if self.is_unroll_directive() {
+            // {$UNROLL n} applies to the FOR loop after it
+            self.parse_directive()?;
+            self.parse_statement()
         } else {
             // Check if this is a label: identifier or integer literal followed by colon
             let is_label = (matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) ||
@@ -237,8 +54,9 @@ impl super::Parser {
                 return self.parse_labeled_statement();
             }
             
-            // Could be assignment or procedure call (only for identifiers)
-            if matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) {
+            // Could be assignment or procedure call (only for identifiers and SELF)
+            if matches!(self.current().map(|t| &t.kind), Some(TokenKind::Identifier(_))) ||
+                (self.check(&TokenKind::KwSelf) && self.check_peek(&TokenKind::Dot)) {
                 // Check if it's an assignment by looking ahead for :=
             // Handle simple case: identifier :=
             // Handle pointer dereference: identifier ^ := (check two tokens ahead)
@@ -274,6 +92,9 @@ impl super::Parser {
                             value: Box::new(value),
                             span,
                         }))
+                    } else if let Node::FieldExpr(field) = target {
+                        // Method call: object.Method [ ( args ) ]
+                        self.parse_method_call(*field.record, field.field, field.span)
                     } else {
                         // Not an assignment after all - parse as call
                         // This shouldn't happen if our check is correct, but handle gracefully
@@ -299,6 +120,16 @@ impl super::Parser {
     }
 
     /// Parse if statement: IF expression THEN statement [ ELSE statement ]
+    /// Whether the current token is an {$UNROLL} directive, the only
+    /// directive allowed before a statement
+    fn is_unroll_directive(&self) -> bool {
+        matches!(
+            self.current().map(|t| &t.kind),
+            Some(TokenKind::Directive(content))
+                if content.split_whitespace().next().is_some_and(|name| name.eq_ignore_ascii_case("UNROLL"))
+        )
+    }
+
     fn parse_if_statement(&mut self) -> ParserResult<Node> {
         let start_span = self
             .current()
@@ -351,13 +182,15 @@ impl super::Parser {
         }))
     }
 
-    /// Parse for statement: FOR identifier := expression TO|DOWNTO expression DO statement
+    /// Parse for statement: FOR identifier := expression TO|DOWNTO expression DO statement,
+    /// or FOR identifier IN expression DO statement
     fn parse_for_statement(&mut self) -> ParserResult<Node> {
         let start_span = self
             .current()
             .map(|t| t.span)
             .unwrap_or_else(|| Span::at(0, 1, 1));
 
+        let unroll = self.unroll_pending.take();
         self.consume(TokenKind::KwFor, "FOR")?;
         let var_token = self.consume(TokenKind::Identifier(String::new()), "identifier")?;
         let var_name = match &var_token.kind {
@@ -368,6 +201,22 @@ impl super::Parser {
             }),
         };
 
+        // FOR identifier IN expression DO statement
+        if self.check(&TokenKind::KwIn) {
+            self.advance()?;
+            let collection_expr = self.parse_expression()?;
+            self.consume(TokenKind::KwDo, "DO")?;
+            let body = self.parse_statement()?;
+
+            let span = start_span.merge(body.span());
+            return Ok(Node::ForInStmt(ast::ForInStmt {
+                var_name,
+                collection_expr: Box::new(collection_expr),
+                body: Box::new(body),
+                span,
+            }));
+        }
+
         self.consume(TokenKind::Assign, ":=")?;
         let start_expr = self.parse_expression()?;
 
@@ -396,39 +245,7 @@ impl super::Parser {
             direction,
             end_expr: Box::new(end_expr),
             body: Box::new(body),
-            span,
-        }))
-    }
-
-    /// Parse for..in statement: FOR identifier IN expression DO statement
-    /// Note: Currently parsed inline in parse_statement, but kept for potential refactoring
-    #[allow(dead_code)]
-    pub(crate) fn parse_for_in_statement(&mut self) -> ParserResult<Node> {
-        let start_span = self
-            .current()
-            .map(|t| t.span)
-            .unwrap_or_else(|| Span::at(0, 1, 1));
-
-        self.consume(TokenKind::KwFor, "FOR")?;
-        let var_token = self.consume(TokenKind::Identifier(String::new()), "identifier")?;
-        let var_name = match &var_token.kind {
-            TokenKind::Identifier(name) => name.clone(),
-            _ => return Err(ParserError::InvalidSyntax {
-                message: "Expected identifier".to_string(),
-                span: var_token.span,
-            }),
-        };
-
-        self.consume(TokenKind::KwIn, "IN")?;
-        let collection_expr = self.parse_expression()?;
-        self.consume(TokenKind::KwDo, "DO")?;
-        let body = self.parse_statement()?;
-
-        let span = start_span.merge(body.span());
-        Ok(Node::ForInStmt(ast::ForInStmt {
-            var_name,
-            collection_expr: Box::new(collection_expr),
-            body: Box::new(body),
+            unroll,
             span,
         }))
     }
@@ -441,13 +258,7 @@ impl super::Parser {
             .unwrap_or_else(|| Span::at(0, 1, 1));
 
         self.consume(TokenKind::KwRepeat, "REPEAT")?;
-        let mut statements = vec![];
-        while !self.check(&TokenKind::KwUntil) {
-            statements.push(self.parse_statement()?);
-            if self.check(&TokenKind::Semicolon) {
-                self.advance()?;
-            }
-        }
+        let statements = self.parse_statement_list(&[TokenKind::KwUntil])?;
         self.consume(TokenKind::KwUntil, "UNTIL")?;
         let condition = self.parse_expression()?;
 
@@ -496,7 +307,8 @@ impl super::Parser {
         }))
     }
 
-    /// Parse case branch: case_value_list : statement
+    /// Parse case branch: case_label { , case_label } : statement, where a
+    /// label is a value or a range `low..high`
     fn parse_case_branch(&mut self) -> ParserResult<ast::CaseBranch> {
         let start_span = self
             .current()
@@ -505,7 +317,19 @@ impl super::Parser {
 
         let mut values = vec![];
         loop {
-            values.push(self.parse_expression()?);
+            let value = self.parse_expression()?;
+            if self.check(&TokenKind::DotDot) {
+                self.advance()?; // consume ..
+                let high = self.parse_expression()?;
+                let span = value.span().merge(high.span());
+                values.push(Node::SubrangeType(ast::SubrangeType {
+                    low: Box::new(value),
+                    high: Box::new(high),
+                    span,
+                }));
+            } else {
+                values.push(value);
+            }
             if !self.check(&TokenKind::Comma) {
                 break;
             }
@@ -560,9 +384,14 @@ impl super::Parser {
 
     /// Parse lvalue: identifier [ [ expression ] ] [ . identifier ] [ ^ ]
     fn parse_lvalue(&mut self) -> ParserResult<Node> {
-        let name_token = self.consume(TokenKind::Identifier(String::new()), "identifier")?;
+        let name_token = if self.check(&TokenKind::KwSelf) {
+            self.advance_and_get_token()?
+        } else {
+            self.consume(TokenKind::Identifier(String::new()), "identifier")?
+        };
         let name = match &name_token.kind {
             TokenKind::Identifier(name) => name.clone(),
+            TokenKind::KwSelf => "Self".to_string(),
             _ => return Err(ParserError::InvalidSyntax {
                 message: "Expected identifier".to_string(),
                 span: name_token.span,
@@ -632,14 +461,7 @@ impl super::Parser {
         self.consume(TokenKind::KwTry, "TRY")?;
 
         // Parse try block statements
-        let mut try_statements = vec![];
-        while !self.check(&TokenKind::KwExcept) && !self.check(&TokenKind::KwFinally) && !self.check(&TokenKind::KwEnd) {
-            try_statements.push(self.parse_statement()?);
-            // Optional semicolon between statements
-            if self.check(&TokenKind::Semicolon) {
-                self.advance()?;
-            }
-        }
+        let try_statements = self.parse_statement_list(&[TokenKind::KwExcept, TokenKind::KwFinally])?;
 
         let mut except_block = None;
         let mut finally_block = None;
@@ -671,27 +493,13 @@ impl super::Parser {
                 }
             } else {
                 // Simple except block with statements
-                let mut except_statements = vec![];
-                while !self.check(&TokenKind::KwEnd) {
-                    except_statements.push(self.parse_statement()?);
-                    if self.check(&TokenKind::Semicolon) {
-                        self.advance()?;
-                    }
-                }
-                except_block = Some(except_statements);
+                except_block = Some(self.parse_statement_list(&[])?);
             }
         } else if self.check(&TokenKind::KwFinally) {
             self.advance()?; // consume FINALLY
 
             // Parse finally block statements
-            let mut finally_statements = vec![];
-            while !self.check(&TokenKind::KwEnd) {
-                finally_statements.push(self.parse_statement()?);
-                if self.check(&TokenKind::Semicolon) {
-                    self.advance()?;
-                }
-            }
-            finally_block = Some(finally_statements);
+            finally_block = Some(self.parse_statement_list(&[])?);
         }
 
         let end_token = self.consume(TokenKind::KwEnd, "END")?;
@@ -762,8 +570,16 @@ impl super::Parser {
 
         self.consume(TokenKind::KwRaise, "RAISE")?;
 
-        // Optional exception expression
-        let exception = if !self.check(&TokenKind::Semicolon) && !self.check(&TokenKind::KwEnd) {
+        // Optional exception expression, absent before whatever ends the statement
+        let ends_statement = [
+            TokenKind::Semicolon,
+            TokenKind::KwEnd,
+            TokenKind::KwElse,
+            TokenKind::KwUntil,
+            TokenKind::KwExcept,
+            TokenKind::KwFinally,
+        ];
+        let exception = if !ends_statement.iter().any(|kind| self.check(kind)) {
             Some(Box::new(self.parse_expression()?))
         } else {
             None
@@ -867,6 +683,24 @@ impl super::Parser {
         }))
     }
 
+    /// Parse statements separated by optional semicolons up to END, the end
+    /// of the file or one of `terminators`, recovering from errors in each
+    pub(crate) fn parse_statement_list(&mut self, terminators: &[TokenKind]) -> ParserResult<Vec<Node>> {
+        let mut statements = vec![];
+        while !self.check(&TokenKind::KwEnd) && !self.check(&TokenKind::Eof) && !terminators.iter().any(|t| self.check(t)) {
+            let start = self.position();
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => self.recover_statement(error, start)?,
+            }
+            // Optional semicolon between statements
+            if self.check(&TokenKind::Semicolon) {
+                self.advance()?;
+            }
+        }
+        Ok(statements)
+    }
+
     /// Parse compound statement: BEGIN statements END
     fn parse_compound_statement(&mut self) -> ParserResult<Node> {
         let start_span = self
@@ -876,16 +710,7 @@ impl super::Parser {
 
         self.consume(TokenKind::KwBegin, "BEGIN")?;
 
-        // Parse statements
-        let mut statements = vec![];
-        while !self.check(&TokenKind::KwEnd) {
-            statements.push(self.parse_statement()?);
-            // Optional semicolon between statements
-            if self.check(&TokenKind::Semicolon) {
-                self.advance()?;
-            }
-        }
-
+        let statements = self.parse_statement_list(&[])?;
         let end_token = self.consume(TokenKind::KwEnd, "END")?;
         let span = start_span.merge(end_token.span);
 
@@ -1002,64 +827,56 @@ impl super::Parser {
         }))
     }
 
-    /// Parse inline assembly statement: ASM [body] END
-    /// The body is captured as raw text between ASM and END
+    /// Parse inline assembly statement: ASM [body] END ['reg', ...]
+    /// The lexer captures the body as written; the optional list names the
+    /// registers it clobbers
     fn parse_asm_statement(&mut self) -> ParserResult<Node> {
-        let start_span = self
-            .current()
-            .map(|t| t.span)
-            .unwrap_or_else(|| Span::at(0, 1, 1));
-
-        self.consume(TokenKind::KwAsm, "ASM")?;
+        let start_span = self.consume(TokenKind::KwAsm, "ASM")?.span;
 
-        // Collect all tokens until we find END
-        // We need to capture the raw text, so we'll collect tokens and reconstruct
-        let mut body_tokens = vec![];
-        let mut end_span = start_span;
-
-        // Parse until we find END
-        while !self.check(&TokenKind::KwEnd) {
-            if let Some(token) = self.current() {
-                // Store token for body reconstruction
-                body_tokens.push(token.clone());
-                end_span = token.span;
+        let body = match self.current().map(|t| &t.kind) {
+            Some(TokenKind::AsmText(text)) => {
+                let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+                let body = lines.join("\n");
                 self.advance()?;
-            } else {
-                return Err(ParserError::UnexpectedEof {
-                    expected: "END after ASM".to_string(),
-                    span: end_span,
-                });
+                body
             }
+            _ => String::new(),
+        };
+        if self.check(&TokenKind::Eof) {
+            return Err(ParserError::UnexpectedEof {
+                expected: "END after ASM".to_string(),
+                span: start_span,
+            });
         }
+        let mut span = start_span.merge(self.consume(TokenKind::KwEnd, "END")?.span);
 
-        // Consume END
-        let end_token = self.consume(TokenKind::KwEnd, "END")?;
-        let span = start_span.merge(end_token.span);
-
-        // Reconstruct the assembly body as a string
-        // For now, we'll just join token representations
-        // In a real implementation, we might want to preserve the original source text
-        let body = if body_tokens.is_empty() {
-            String::new()
-        } else {
-            // Simple reconstruction: join tokens with spaces
-            // This is a simplified approach - a real implementation might preserve original formatting
-            body_tokens.iter()
-                .map(|t| match &t.kind {
-                    TokenKind::Identifier(s) => s.clone(),
-                    TokenKind::IntegerLiteral { value, .. } => value.to_string(),
-                    TokenKind::StringLiteral(s) => format!("\"{}\"", s),
-                    TokenKind::CharLiteral(c) => format!("'{}'", c),
-                    _ => format!("{:?}", t.kind),
-                })
-                .collect::<Vec<_>>()
-                .join(" ")
-        };
+        let mut clobbers = vec![];
+        if self.check(&TokenKind::LeftBracket) {
+            self.advance()?;
+            loop {
+                let register = match self.current().map(|t| &t.kind) {
+                    Some(TokenKind::StringLiteral(name)) => name.to_lowercase(),
+                    Some(TokenKind::CharLiteral(ch)) => (*ch as char).to_ascii_lowercase().to_string(),
+                    _ => {
+                        let token = self.current().cloned();
+                        return Err(ParserError::UnexpectedToken {
+                            expected: "register name in quotes".to_string(),
+                            found: token.as_ref().map_or_else(|| "EOF".to_string(), |t| format!("{:?}", t.kind)),
+                            span: token.map_or(span, |t| t.span),
+                        });
+                    }
+                };
+                clobbers.push(register);
+                self.advance()?;
+                if !self.check(&TokenKind::Comma) {
+                    break;
+                }
+                self.advance()?;
+            }
+            span = span.merge(self.consume(TokenKind::RightBracket, "]")?.span);
+        }
 
-        Ok(Node::AsmStmt(ast::AsmStmt {
-            body,
-            span,
-        }))
+        Ok(Node::AsmStmt(ast::AsmStmt { body, clobbers, span }))
     }
 }
 
@@ -1068,6 +885,36 @@ mod tests {
     use super::super::Parser;
     use ast::Node;
 
+    #[test]
+    fn test_multiline_statement_span_end() {
+        let source = "program Test;\nbegin\n  if a then\n    b := 1\n  else\n    b := 22;\nend.";
+        let Ok(Node::Program(program)) = Parser::new(source).unwrap().parse() else {
+            panic!("Parse failed");
+        };
+        let Node::Block(block) = program.block.as_ref() else {
+            panic!("Expected block");
+        };
+        let span = block.statements[0].span();
+        assert_eq!((span.line, span.column), (3, 3));
+        assert_eq!((span.end_line, span.end_column), (6, 12));
+    }
+
+    #[test]
+    fn test_parse_case_ranges_and_multiple_labels() {
+        let source = "program T; begin case x of 1, 3, 5..9: a; 'a'..'z': b else c end end.";
+        let Ok(Node::Program(program)) = Parser::new(source).unwrap().parse() else {
+            panic!("Parse failed");
+        };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected block") };
+        let Node::CaseStmt(case) = &block.statements[0] else { panic!("Expected CaseStmt") };
+        assert_eq!(case.cases.len(), 2);
+        assert_eq!(case.cases[0].values.len(), 3);
+        assert!(matches!(case.cases[0].values[1], Node::LiteralExpr(_)));
+        assert!(matches!(case.cases[0].values[2], Node::SubrangeType(_)));
+        assert!(matches!(case.cases[1].values[..], [Node::SubrangeType(_)]));
+        assert!(case.else_branch.is_some());
+    }
+
     // ===== Exception Handling Tests =====
 
     #[test]
@@ -1944,6 +1791,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_asm_clobber_list() {
+        let mut parser = Parser::new("program Test; begin asm\n  ld hl, @X\n  inc (hl)\nend ['HL', 'a']; end.").unwrap();
+        let Ok(Node::Program(program)) = parser.parse() else { panic!("Parse failed") };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected Block") };
+        let Node::AsmStmt(asm) = &block.statements[0] else { panic!("Expected AsmStmt") };
+        assert_eq!(asm.body, "ld hl, @X\ninc (hl)");
+        assert_eq!(asm.clobbers, ["hl", "a"]);
+        assert_eq!(asm.references()[0].1, "X");
+    }
+
+    #[test]
+    fn test_parse_for_statement() {
+        let mut parser = Parser::new(
+            "program Test; begin for i := 10 downto 1 do x := i end.",
+        )
+        .unwrap();
+        let Ok(Node::Program(program)) = parser.parse() else {
+            panic!("Expected program");
+        };
+        let Node::Block(block) = program.block.as_ref() else {
+            panic!("Expected block");
+        };
+        let Node::ForStmt(for_stmt) = &block.statements[0] else {
+            panic!("Expected ForStmt, got: {:?}", block.statements[0]);
+        };
+        assert_eq!(for_stmt.var_name, "i");
+        assert_eq!(for_stmt.direction, ast::ForDirection::Downto);
+        assert!(matches!(for_stmt.body.as_ref(), Node::AssignStmt(_)));
+    }
+
     #[test]
     fn test_parse_for_in_statement() {
         let source = r#"
@@ -1995,4 +1873,37 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_parse_unroll_hint() {
+        let source = r#"
+            program Test;
+            begin
+                {$UNROLL 4}
+                for i := 1 to 16 do
+                    {$unroll 0} for j := 1 to 2 do x := i;
+                for i := 1 to 3 do x := i
+            end.
+        "#;
+        let program = Parser::new(source).unwrap().parse().unwrap();
+        let Node::Program(program) = program else { panic!("Expected program") };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected block") };
+        let unroll = |stmt: &Node| match stmt {
+            Node::ForStmt(f) => f.unroll,
+            other => panic!("Expected ForStmt, found {:?}", other),
+        };
+        assert_eq!(unroll(&block.statements[0]), Some(4));
+        let Node::ForStmt(outer) = &block.statements[0] else { unreachable!() };
+        assert_eq!(unroll(&outer.body), Some(0));
+        assert_eq!(unroll(&block.statements[1]), None);
+
+        for (source, error) in [
+            ("program T; begin {$UNROLL 2} x := 1 end.", "{$UNROLL} must be followed by a FOR loop"),
+            ("program T; begin {$UNROLL many} for i := 1 to 2 do x := i end.", "Expected an unroll count in {$UNROLL}, found 'many'"),
+            ("program T; begin {$UNROLL} for i := 1 to 2 do x := i end.", "Expected an unroll count after {$UNROLL}"),
+        ] {
+            let result = Parser::new(source).unwrap().parse();
+            assert!(format!("{:?}", result).contains(error), "{}: {:?}", source, result);
+        }
+    }
 }
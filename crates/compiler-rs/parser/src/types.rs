@@ -16,12 +16,13 @@ impl super::Parser {
             .map(|t| t.span)
             .unwrap_or_else(|| Span::at(0, 1, 1));
 
-        // Check for PACKED keyword (applies to RECORD or ARRAY)
+        // Check for PACKED keyword (applies to RECORD or ARRAY); under
+        // {$ALIGN 1} every record and array is packed
         let is_packed = if self.check(&TokenKind::KwPacked) {
             self.advance()?; // consume PACKED
             true
         } else {
-            false
+            self.switch(crate::directives::PACKED_RECORDS_SWITCH) == Some(true)
         };
 
         // Check for pointer type: ^type
@@ -153,6 +154,9 @@ impl super::Parser {
         } else if self.check(&TokenKind::KwProcedure) || self.check(&TokenKind::KwFunction) {
             // Procedural type: PROCEDURE [params] [OF OBJECT] or FUNCTION [params]: return_type [OF OBJECT]
             self.parse_procedural_type()
+        } else if self.at_subrange_type() {
+            // Subrange type: constant .. constant
+            self.parse_subrange_type()
         } else if self.check(&TokenKind::LeftParen) {
             // Enum type: ( identifier, identifier, ... )
             self.parse_enum_type()
@@ -195,6 +199,27 @@ impl super::Parser {
                     kind: TokenKind::Identifier("word".to_string()),
                     span: token.span,
                 }
+            } else if self.check(&TokenKind::KwLongint) {
+                let token = self.current().unwrap().clone();
+                self.advance()?;
+                Token {
+                    kind: TokenKind::Identifier("longint".to_string()),
+                    span: token.span,
+                }
+            } else if self.check(&TokenKind::KwCardinal) {
+                let token = self.current().unwrap().clone();
+                self.advance()?;
+                Token {
+                    kind: TokenKind::Identifier("cardinal".to_string()),
+                    span: token.span,
+                }
+            } else if self.check(&TokenKind::KwReal) {
+                let token = self.current().unwrap().clone();
+                self.advance()?;
+                Token {
+                    kind: TokenKind::Identifier("real".to_string()),
+                    span: token.span,
+                }
             } else if self.check(&TokenKind::KwString) {
                 let token = self.current().unwrap().clone();
                 self.advance()?;
@@ -329,7 +354,21 @@ impl super::Parser {
             // Check for constraint: <T: constraint> or <T; U: constraint>
             let constraint = if self.check(&TokenKind::Colon) {
                 self.advance()?; // consume :
-                Some(Box::new(self.parse_type()?))
+                // The keyword constraints `class`, `record` and `constructor`
+                let keyword = match self.current().map(|t| &t.kind) {
+                    Some(TokenKind::KwClass) => Some("class"),
+                    Some(TokenKind::KwRecord) => Some("record"),
+                    Some(TokenKind::KwConstructor) => Some("constructor"),
+                    _ => None,
+                };
+                match keyword {
+                    Some(name) if matches!(self.peek_token().map(|t| &t.kind), Some(TokenKind::Greater | TokenKind::Semicolon)) => {
+                        let span = self.current().map(|t| t.span).unwrap_or_else(|| Span::at(0, 1, 1));
+                        self.advance()?;
+                        Some(Box::new(Node::NamedType(ast::NamedType { name: name.to_string(), generic_args: vec![], span })))
+                    }
+                    _ => Some(Box::new(self.parse_type()?)),
+                }
             } else {
                 None
             };
@@ -401,6 +440,36 @@ impl super::Parser {
         }))
     }
 
+    /// Whether a type starts here with the lower bound of a subrange: a
+    /// literal, a signed constant, or an identifier followed by `..`
+    fn at_subrange_type(&self) -> bool {
+        match self.current().map(|t| &t.kind) {
+            Some(
+                TokenKind::IntegerLiteral { .. }
+                | TokenKind::CharLiteral(_)
+                | TokenKind::StringLiteral(_)
+                | TokenKind::BooleanLiteral(_)
+                | TokenKind::Minus
+                | TokenKind::Plus,
+            ) => true,
+            Some(TokenKind::Identifier(_)) => self.check_peek(&TokenKind::DotDot),
+            _ => false,
+        }
+    }
+
+    /// Parse subrange type: constant .. constant
+    fn parse_subrange_type(&mut self) -> ParserResult<Node> {
+        let low = self.parse_expression()?;
+        self.consume(TokenKind::DotDot, "..")?;
+        let high = self.parse_expression()?;
+        let span = low.span().merge(high.span());
+        Ok(Node::SubrangeType(ast::SubrangeType {
+            low: Box::new(low),
+            high: Box::new(high),
+            span,
+        }))
+    }
+
     /// Parse procedural type: PROCEDURE [params] [OF OBJECT] or FUNCTION [params]: return_type [OF OBJECT]
     fn parse_procedural_type(&mut self) -> ParserResult<Node> {
         let start_span = self
@@ -455,7 +524,7 @@ impl super::Parser {
                     params.push(ast::Param {
                         names: param_names,
                         param_type: param_mode,
-                        type_expr: Box::new(param_type),
+                        type_expr: Some(Box::new(param_type)),
                         default_value: None,
                         span: param_span,
                     });
@@ -674,7 +743,8 @@ impl super::Parser {
         let mut variants = vec![];
         let mut else_variant = None;
 
-        while !self.check(&TokenKind::KwElse) && !self.check(&TokenKind::KwEnd) {
+        // A nested variant part ends at the enclosing variant's ')'
+        while !self.check(&TokenKind::KwElse) && !self.check(&TokenKind::KwEnd) && !self.check(&TokenKind::RightParen) {
             let variant_start = self
                 .current()
                 .map(|t| t.span)
@@ -685,7 +755,18 @@ impl super::Parser {
             loop {
                 // Parse a case value (can be expression or range)
                 let value = self.parse_expression()?;
-                values.push(value);
+                if self.check(&TokenKind::DotDot) {
+                    self.advance()?; // consume ..
+                    let high = self.parse_expression()?;
+                    let span = value.span().merge(high.span());
+                    values.push(Node::SubrangeType(ast::SubrangeType {
+                        low: Box::new(value),
+                        high: Box::new(high),
+                        span,
+                    }));
+                } else {
+                    values.push(value);
+                }
 
                 if !self.check(&TokenKind::Comma) {
                     break;
@@ -696,9 +777,14 @@ impl super::Parser {
             self.consume(TokenKind::Colon, ":")?;
             self.consume(TokenKind::LeftParen, "(")?;
 
-            // Parse variant fields
+            // Parse variant fields, then an optional nested variant part
             let mut variant_fields = vec![];
+            let mut nested = None;
             while !self.check(&TokenKind::RightParen) {
+                if self.check(&TokenKind::KwCase) {
+                    nested = Some(Box::new(self.parse_variant_part()?));
+                    break;
+                }
                 variant_fields.push(self.parse_field_decl()?);
                 if self.check(&TokenKind::Semicolon) {
                     self.advance()?;
@@ -707,7 +793,9 @@ impl super::Parser {
 
             self.consume(TokenKind::RightParen, ")")?;
 
-            let variant_span = if let Some(last_field) = variant_fields.last() {
+            let variant_span = if let Some(nested) = &nested {
+                variant_start.merge(nested.span)
+            } else if let Some(last_field) = variant_fields.last() {
                 variant_start.merge(last_field.span)
             } else {
                 variant_start
@@ -716,6 +804,7 @@ impl super::Parser {
             variants.push(ast::Variant {
                 values,
                 fields: variant_fields,
+                variant: nested,
                 span: variant_span,
             });
 
@@ -1565,6 +1654,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_variant_record_ranges_and_nested_case() {
+        let source = r#"
+            program Test;
+            type Packet = record
+                case tag: byte of
+                    0..3, 7: (n: integer);
+                    4: (flags: byte; case wide: boolean of
+                          true: (p: longint);
+                          false: (q: byte));
+            end;
+            begin
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let result = parser.parse();
+        assert!(result.is_ok(), "Parse failed: {:?}", result);
+
+        let Ok(Node::Program(program)) = result else { unreachable!() };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected Block") };
+        let Node::TypeDecl(type_decl) = &block.type_decls[0] else { panic!("Expected TypeDecl") };
+        let Node::RecordType(record_type) = type_decl.type_expr.as_ref() else { panic!("Expected RecordType") };
+        let variant = record_type.variant.as_ref().unwrap();
+        assert!(matches!(variant.variants[0].values[0], Node::SubrangeType(_)));
+        assert!(matches!(variant.variants[0].values[1], Node::LiteralExpr(_)));
+        let nested = variant.variants[1].variant.as_ref().expect("nested variant part");
+        assert_eq!(variant.variants[1].fields[0].names, vec!["flags"]);
+        assert_eq!(nested.tag_field.as_deref(), Some("wide"));
+        assert_eq!(nested.variants.len(), 2);
+    }
+
     // ===== Interface Type Tests =====
 
     #[test]
@@ -1882,6 +2002,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_subrange_types() {
+        let source = r#"
+            program Test;
+            type
+                Digit = 0..9;
+                Lower = 'a'..'z';
+                Warm = Red..Green;
+                Offset = -8..7;
+                Table = array[1..10] of integer;
+            begin
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let Ok(Node::Program(program)) = parser.parse() else {
+            panic!("Parse failed");
+        };
+        let Node::Block(block) = program.block.as_ref() else {
+            panic!("Expected Block");
+        };
+        let types: Vec<&Node> = block
+            .type_decls
+            .iter()
+            .map(|decl| match decl {
+                Node::TypeDecl(type_decl) => type_decl.type_expr.as_ref(),
+                other => panic!("Expected TypeDecl, found {:?}", other),
+            })
+            .collect();
+        for subrange in &types[..4] {
+            assert!(matches!(subrange, Node::SubrangeType(_)), "Expected SubrangeType, found {:?}", subrange);
+        }
+        let Node::SubrangeType(warm) = types[2] else { unreachable!() };
+        assert!(matches!(warm.low.as_ref(), Node::IdentExpr(i) if i.name == "Red"));
+        assert!(matches!(warm.high.as_ref(), Node::IdentExpr(i) if i.name == "Green"));
+        let Node::ArrayType(table) = types[4] else {
+            panic!("Expected ArrayType, found {:?}", types[4]);
+        };
+        assert!(matches!(table.index_type.as_ref(), Node::SubrangeType(_)));
+    }
+
     #[test]
     fn test_parse_enum_type_single_value() {
         let source = r#"
@@ -2120,6 +2280,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_generic_keyword_constraints() {
+        let source = r#"
+            program Test;
+            type
+                TPair<K: record; V: class> = record
+                end;
+            begin
+            end.
+        "#;
+        let mut parser = Parser::new(source).unwrap();
+        let Ok(Node::Program(program)) = parser.parse() else { panic!("Parse failed") };
+        let Node::Block(block) = program.block.as_ref() else { panic!("Expected Block") };
+        let Node::TypeDecl(type_decl) = &block.type_decls[0] else { panic!("Expected TypeDecl") };
+        let constraints: Vec<&str> = type_decl
+            .generic_params
+            .iter()
+            .map(|param| match param.constraint.as_deref() {
+                Some(Node::NamedType(named)) => named.name.as_str(),
+                _ => panic!("Expected NamedType constraint"),
+            })
+            .collect();
+        assert_eq!(constraints, ["record", "class"]);
+    }
+
     #[test]
     fn test_parse_generic_type_instantiation_simple() {
         let source = r#"
@@ -2508,7 +2693,7 @@ mod tests {
             if let Node::Block(block) = program.block.as_ref() {
                 if let Node::ProcDecl(proc) = &block.proc_decls[0] {
                     assert_eq!(proc.params.len(), 1);
-                    if let Node::NamedType(param_type) = proc.params[0].type_expr.as_ref() {
+                    if let Some(Node::NamedType(param_type)) = proc.params[0].type_expr.as_deref() {
                         assert_eq!(param_type.name, "TList");
                         assert_eq!(param_type.generic_args.len(), 1);
                     } else {
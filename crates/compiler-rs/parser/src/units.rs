@@ -287,7 +287,7 @@ impl super::Parser {
                 type_decls.extend(self.parse_type_decls()?);
             } else if self.check(&TokenKind::KwVar) {
                 var_decls.extend(self.parse_var_decls()?);
-            } else if self.check(&TokenKind::KwProcedure) {
+            } else if self.check_procedure_start() {
                 proc_decls.push(self.parse_procedure_decl()?);
             } else if self.check(&TokenKind::KwFunction) {
                 func_decls.push(self.parse_function_decl()?);
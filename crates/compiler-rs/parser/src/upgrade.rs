@@ -0,0 +1,379 @@
+//! Migration of Turbo Pascal sources (`spc upgrade-syntax`)
+//!
+//! Legacy constructs with a SuperPascal equivalent are rewritten; the
+//! others are reported with their location. Edits replace the tokens of a
+//! construct in the source text, so comments and layout are kept:
+//!
+//! - `{$A+}` and `{$A-}` become `{$ALIGN 2}` and `{$ALIGN 1}`
+//! - directives with no meaning on the Z80 are removed: memory sizes
+//!   (`{$M}`), coprocessor and CPU switches (`{$N}`, `{$E}`, `{$G}`), far
+//!   calls and overlays (`{$F}`, `{$O}`) and the syntax switches `{$X}`,
+//!   `{$V}`, `{$P}` and `{$Y}`
+//! - `far;` and `near;` after a routine heading are removed
+//! - `string[255]` becomes `string`, which holds 255 characters
+//! - an `object` type with fields only becomes a `record`
+//!
+//! Left as written and reported: objects with methods, ancestors or
+//! visibility sections (classes are their counterpart, but instances are
+//! references), `interrupt` routines, `inline(...)` machine code, `Mem`
+//! arrays, segment:offset `absolute` addresses and string lengths outside
+//! 1..255. The result is parsed again, and a syntax error in it reported.
+
+use errors::{Diagnostic, ErrorSeverity, ParserError, ParserResult};
+use lexer::{IdentifierPolicy, Lexer};
+use tokens::{Span, Token, TokenKind};
+
+use crate::Parser;
+
+/// Switches `{$X+}`/`{$X-}` that have no meaning on the Z80
+const REMOVED_SWITCHES: &[char] = &['N', 'E', 'G', 'F', 'O', 'X', 'V', 'P', 'Y'];
+
+/// A migrated source
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    pub source: String,
+    /// Notes for the constructs rewritten and warnings for those left, in
+    /// source order
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Rewrite the legacy constructs of `source`
+pub fn upgrade(source: &str, identifier_policy: IdentifierPolicy) -> ParserResult<Upgrade> {
+    let mut lexer = Lexer::new(source).with_identifier_policy(identifier_policy);
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token().map_err(|e| ParserError::InvalidSyntax {
+            message: format!("Lexer error: {}", e),
+            span: e.span(),
+        })?;
+        if token.kind == TokenKind::Eof {
+            break;
+        }
+        tokens.push(token);
+    }
+
+    let mut migration = Migration { source, tokens: &tokens, edits: vec![], diagnostics: vec![] };
+    migration.scan();
+    let Migration { mut edits, mut diagnostics, .. } = migration;
+
+    edits.sort_by_key(|(span, _)| span.start);
+    let mut output = source.to_string();
+    for (span, replacement) in edits.iter().rev() {
+        output.replace_range(span.start..span.end, replacement);
+    }
+
+    let mut parser = Parser::new_with_identifier_policy(&output, None, vec![], identifier_policy)?;
+    if let Err(error) = parser.parse() {
+        let diagnostic = error.to_diagnostic(None);
+        diagnostics.push(Diagnostic::new(
+            ErrorSeverity::Warning,
+            format!("The upgraded source does not parse: {}", diagnostic.message),
+            source_span(source, &edits, diagnostic.span.start),
+        ));
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    Ok(Upgrade { source: output, diagnostics })
+}
+
+/// Where the output offset `offset` came from in `source`, given the
+/// `edits` made to it in order; the start of an edit for an offset in
+/// its replacement
+fn source_span(source: &str, edits: &[(Span, String)], offset: usize) -> Span {
+    let mut shift = 0isize;
+    let mut start = None;
+    for (span, replacement) in edits {
+        let output_start = span.start.saturating_add_signed(shift);
+        if offset < output_start {
+            break;
+        }
+        if offset < output_start + replacement.len() {
+            start = Some(span.start);
+            break;
+        }
+        shift += replacement.len() as isize - (span.end - span.start) as isize;
+    }
+    let start = start.unwrap_or_else(|| offset.saturating_add_signed(-shift)).min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line = source[..start].matches('\n').count() + 1;
+    Span::new(start, start + 1, line, source[line_start..start].chars().count() + 1)
+}
+
+struct Migration<'a> {
+    source: &'a str,
+    tokens: &'a [Token],
+    /// Replacements of source ranges, which do not overlap
+    edits: Vec<(Span, String)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Migration<'_> {
+    fn scan(&mut self) {
+        // Whether the tokens follow a routine heading, where routine
+        // directives such as `far;` go
+        let mut in_heading = false;
+        let mut after_heading = false;
+        let mut depth = 0;
+        for i in 0..self.tokens.len() {
+            let token = &self.tokens[i];
+            let after_directive = after_heading;
+            after_heading = false;
+            match &token.kind {
+                TokenKind::Directive(content) => self.directive(content, token.span),
+                TokenKind::KwProcedure | TokenKind::KwFunction | TokenKind::KwConstructor | TokenKind::KwDestructor => {
+                    in_heading = true
+                }
+                TokenKind::LeftParen => depth += 1,
+                TokenKind::RightParen => depth -= 1,
+                TokenKind::Semicolon if in_heading && depth == 0 => {
+                    in_heading = false;
+                    after_heading = true;
+                }
+                TokenKind::Identifier(name) if after_directive => {
+                    after_heading = self.routine_directive(i, name);
+                }
+                TokenKind::KwForward | TokenKind::KwExternal if after_directive => {
+                    in_heading = true;
+                }
+                TokenKind::KwString => self.string_length(i),
+                TokenKind::KwObject if !matches!(self.kind(i.wrapping_sub(1)), Some(TokenKind::KwOf)) => self.object(i),
+                TokenKind::KwAbsolute => {
+                    if let (Some(TokenKind::IntegerLiteral { .. }), Some(TokenKind::Colon)) = (self.kind(i + 1), self.kind(i + 2)) {
+                        self.warn(
+                            "Segment:offset address not migrated: the Z80 has one 64K address space".to_string(),
+                            token.span,
+                        );
+                    }
+                }
+                TokenKind::Identifier(name)
+                    if matches!(self.kind(i + 1), Some(TokenKind::LeftBracket))
+                        && ["Mem", "MemW", "MemL"].iter().any(|array| name.eq_ignore_ascii_case(array)) =>
+                {
+                    self.warn(
+                        format!("'{}' not migrated: declare a variable absolute at the address instead", name),
+                        token.span,
+                    );
+                }
+                TokenKind::Identifier(name)
+                    if name.eq_ignore_ascii_case("inline") && matches!(self.kind(i + 1), Some(TokenKind::LeftParen)) =>
+                {
+                    self.warn("Inline machine code not migrated: rewrite it as an asm block".to_string(), token.span);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn kind(&self, index: usize) -> Option<&TokenKind> {
+        self.tokens.get(index).map(|token| &token.kind)
+    }
+
+    fn note(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::new(ErrorSeverity::Note, message, span));
+    }
+
+    fn warn(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::new(ErrorSeverity::Warning, message, span));
+    }
+
+    /// Rewrite `{$A±}` and remove the directives with no meaning here
+    fn directive(&mut self, content: &str, span: Span) {
+        let content = content.trim();
+        let name = content.split_whitespace().next().unwrap_or("").to_uppercase();
+        let switch = match name.as_bytes() {
+            [letter, b'+' | b'-'] => Some(*letter as char),
+            _ => None,
+        };
+        if switch == Some('A') {
+            let replacement = if name.ends_with('+') { "{$ALIGN 2}" } else { "{$ALIGN 1}" };
+            self.note(format!("{{${}}} became {}", content, replacement), span);
+            self.edits.push((span, replacement.to_string()));
+        } else if name == "M" || switch.is_some_and(|letter| REMOVED_SWITCHES.contains(&letter)) {
+            self.note(format!("{{${}}} removed: it has no meaning on the Z80", content), span);
+            let line = self.whole_line(span);
+            self.edits.push((line, String::new()));
+        }
+    }
+
+    /// `span` widened to its whole line when nothing else is on it
+    fn whole_line(&self, span: Span) -> Span {
+        let start = self.source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.source[span.end..].find('\n').map_or(self.source.len(), |i| span.end + i + 1);
+        let blank = |text: &str| text.trim().is_empty();
+        if blank(&self.source[start..span.start]) && blank(&self.source[span.end..end]) {
+            Span::new(start, end, span.line, 1)
+        } else {
+            span
+        }
+    }
+
+    /// Handle the routine directive `name` at `index`; whether more may
+    /// follow
+    fn routine_directive(&mut self, index: usize, name: &str) -> bool {
+        let token = &self.tokens[index];
+        if !matches!(self.kind(index + 1), Some(TokenKind::Semicolon)) {
+            return false;
+        }
+        if name.eq_ignore_ascii_case("far") || name.eq_ignore_ascii_case("near") {
+            // From the end of the heading's `;` to the end of the directive's
+            let start = self.tokens[index - 1].span.end;
+            let end = self.tokens[index + 1].span.end;
+            self.note(format!("'{}' removed: the Z80 has only one kind of call", name.to_lowercase()), token.span);
+            self.edits.push((Span::new(start, end, token.span.line, token.span.column), String::new()));
+            true
+        } else if name.eq_ignore_ascii_case("interrupt") {
+            self.warn(
+                "Interrupt routine not migrated: Z80 interrupt handlers are set up with --interrupt-mode".to_string(),
+                token.span,
+            );
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Rewrite `string[255]`; report lengths a string cannot have
+    fn string_length(&mut self, index: usize) {
+        let (Some(TokenKind::LeftBracket), Some(TokenKind::IntegerLiteral { value, .. }), Some(TokenKind::RightBracket)) =
+            (self.kind(index + 1), self.kind(index + 2), self.kind(index + 3))
+        else {
+            return;
+        };
+        let string = self.tokens[index].span;
+        let close = self.tokens[index + 3].span;
+        match *value {
+            255 => {
+                self.note("'string[255]' became 'string'".to_string(), string);
+                self.edits.push((Span::new(string.end, close.end, string.line, string.column), String::new()));
+            }
+            1..=255 => {}
+            length => self.warn(
+                format!("String length {} not migrated: it must be from 1 to 255", length),
+                string.merge(close),
+            ),
+        }
+    }
+
+    /// Turn an object with fields only into a record, or report it
+    fn object(&mut self, index: usize) {
+        let span = self.tokens[index].span;
+        let mut depth = 1;
+        let mut reasons = vec![];
+        let members = match self.kind(index + 1) {
+            Some(TokenKind::Semicolon) => {
+                reasons.push("a forward declaration");
+                0..0
+            }
+            Some(TokenKind::LeftParen) => {
+                reasons.push("an ancestor");
+                index + 1..self.tokens.len()
+            }
+            _ => index + 1..self.tokens.len(),
+        };
+        for i in members {
+            match &self.tokens[i].kind {
+                TokenKind::KwRecord => depth += 1,
+                TokenKind::KwObject if !matches!(self.kind(i - 1), Some(TokenKind::KwOf)) => depth += 1,
+                TokenKind::KwEnd => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                TokenKind::KwProcedure | TokenKind::KwFunction | TokenKind::KwConstructor | TokenKind::KwDestructor
+                    if depth == 1
+                        && !matches!(self.kind(i - 1), Some(TokenKind::Colon | TokenKind::Equal))
+                        && !reasons.contains(&"methods") =>
+                {
+                    reasons.push("methods");
+                }
+                TokenKind::KwPrivate | TokenKind::KwProtected | TokenKind::KwPublic
+                    if depth == 1 && !reasons.contains(&"visibility sections") =>
+                {
+                    reasons.push("visibility sections");
+                }
+                _ => {}
+            }
+        }
+        if reasons.is_empty() {
+            self.note("Object type with fields only became a record".to_string(), span);
+            self.edits.push((span, "record".to_string()));
+        } else {
+            self.warn(
+                format!(
+                    "Object type with {} not migrated: make it a class, whose instances are references created by a constructor",
+                    reasons.join(" and ")
+                ),
+                span,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_rewrites_legacy_constructs() {
+        let source = "program Old;
+{$M 16384,0,655360}
+{$N+} { coprocessor }
+{$A+}
+type
+  TPoint = object
+    X, Y: integer;
+    Tag: string[255];
+  end;
+  TShape = object(TPoint)
+    procedure Draw;
+  end;
+procedure Beep; far;
+begin
+end;
+begin
+  Mem[$B800:0] := 1
+end.
+";
+        let upgrade = upgrade(source, IdentifierPolicy::default()).unwrap();
+        assert_eq!(
+            upgrade.source,
+            "program Old;
+ { coprocessor }
+{$ALIGN 2}
+type
+  TPoint = record
+    X, Y: integer;
+    Tag: string;
+  end;
+  TShape = object(TPoint)
+    procedure Draw;
+  end;
+procedure Beep;
+begin
+end;
+begin
+  Mem[$B800:0] := 1
+end.
+"
+        );
+        let report: Vec<(ErrorSeverity, usize, &str)> = upgrade
+            .diagnostics
+            .iter()
+            .map(|d| (d.severity, d.span.line, d.message.split(':').next().unwrap()))
+            .collect();
+        assert_eq!(
+            report,
+            [
+                (ErrorSeverity::Note, 2, "{$M 16384,0,655360} removed"),
+                (ErrorSeverity::Note, 3, "{$N+} removed"),
+                (ErrorSeverity::Note, 4, "{$A+} became {$ALIGN 2}"),
+                (ErrorSeverity::Note, 6, "Object type with fields only became a record"),
+                (ErrorSeverity::Note, 8, "'string[255]' became 'string'"),
+                (ErrorSeverity::Warning, 10, "Object type with an ancestor and methods not migrated"),
+                (ErrorSeverity::Note, 13, "'far' removed"),
+                (ErrorSeverity::Warning, 17, "'Mem' not migrated"),
+                (ErrorSeverity::Warning, 17, "The upgraded source does not parse"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Target character sets for string and char literals
+//!
+//! Sources are UTF-8; `{$CODEPAGE}` selects the character set literals are
+//! transcoded into at compile time. Control characters and printable ASCII
+//! map to themselves except where the target repurposes them (the Spectrum
+//! puts `↑`, `£` and `©` on `^`, `` ` `` and DEL).
+
+/// Character set of the target machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codepage {
+    /// 7-bit ASCII
+    Ascii,
+    /// IBM PC code page 437
+    Cp437,
+    /// IBM PC code page 850 (Western European)
+    Cp850,
+    /// ZX Spectrum character set, including the block graphics at 0x80-0x8F
+    Zx,
+}
+
+/// CP437 characters 0x80-0xFF
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// CP850 characters 0x80-0xFF
+const CP850_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{AD}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{A0}',
+];
+
+/// ZX Spectrum block graphics 0x80-0x8F; bits 0-3 light the top-right,
+/// top-left, bottom-right and bottom-left quarters
+const ZX_BLOCKS: [char; 16] = [
+    ' ', '▝', '▘', '▀', '▗', '▐', '▚', '▜', '▖', '▞', '▌', '▛', '▄', '▟', '▙', '█',
+];
+
+impl Codepage {
+    /// Look up a code page by its `{$CODEPAGE}` name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "ASCII" => Some(Codepage::Ascii),
+            "CP437" | "437" => Some(Codepage::Cp437),
+            "CP850" | "850" => Some(Codepage::Cp850),
+            "ZX" | "ZXSPECTRUM" => Some(Codepage::Zx),
+            _ => None,
+        }
+    }
+
+    /// Name as written in `{$CODEPAGE}`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codepage::Ascii => "ASCII",
+            Codepage::Cp437 => "CP437",
+            Codepage::Cp850 => "CP850",
+            Codepage::Zx => "ZX",
+        }
+    }
+
+    /// Target byte for `ch`, or `None` if the character set lacks it
+    pub fn encode_char(&self, ch: char) -> Option<u8> {
+        let high = match self {
+            Codepage::Ascii => return ch.is_ascii().then_some(ch as u8),
+            Codepage::Cp437 => &CP437_HIGH,
+            Codepage::Cp850 => &CP850_HIGH,
+            Codepage::Zx => {
+                return match ch {
+                    '↑' => Some(0x5E),
+                    '£' => Some(0x60),
+                    '©' => Some(0x7F),
+                    '^' | '`' | '\u{7F}' => None,
+                    _ if ch.is_ascii() => Some(ch as u8),
+                    // The blank block 0x80 is reached by a space
+                    _ => ZX_BLOCKS[1..].iter().position(|&c| c == ch).map(|i| 0x81 + i as u8),
+                };
+            }
+        };
+        if ch.is_ascii() {
+            return Some(ch as u8);
+        }
+        high.iter().position(|&c| c == ch).map(|i| 0x80 + i as u8)
+    }
+
+    /// Transcode `text`; on failure, returns the byte offset in `text` and
+    /// the first character the character set lacks
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, (usize, char)> {
+        text.char_indices()
+            .map(|(offset, ch)| self.encode_char(ch).ok_or((offset, ch)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Codepage::from_name("cp437"), Some(Codepage::Cp437));
+        assert_eq!(Codepage::from_name("850"), Some(Codepage::Cp850));
+        assert_eq!(Codepage::from_name("ZXSpectrum"), Some(Codepage::Zx));
+        assert_eq!(Codepage::from_name("latin1"), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(Codepage::Cp437.encode("Olá ░"), Ok(vec![b'O', b'l', 0xA0, b' ', 0xB0]));
+        assert_eq!(Codepage::Cp850.encode("©"), Ok(vec![0xB8]));
+        assert_eq!(Codepage::Cp437.encode("a©"), Err((1, '©')));
+        assert_eq!(Codepage::Ascii.encode("café"), Err((3, 'é')));
+    }
+
+    #[test]
+    fn test_encode_zx() {
+        assert_eq!(Codepage::Zx.encode("£1 ©↑"), Ok(vec![0x60, b'1', b' ', 0x7F, 0x5E]));
+        assert_eq!(Codepage::Zx.encode("▀█"), Ok(vec![0x83, 0x8F]));
+        assert_eq!(Codepage::Zx.encode("a^b"), Err((1, '^')));
+    }
+
+    #[test]
+    fn test_tables_have_no_duplicates() {
+        for table in [&CP437_HIGH, &CP850_HIGH] {
+            for (i, ch) in table.iter().enumerate() {
+                assert_eq!(table.iter().position(|c| c == ch), Some(i), "{:?}", ch);
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Minimal zlib/DEFLATE decoder (RFC 1950/1951)
+//!
+//! Only decompression is supported. This is enough to read the IDAT
+//! stream of PNG assets without pulling in external dependencies.
+
+use crate::ResourceError;
+
+/// Bit reader over a byte slice (LSB-first, as required by DEFLATE)
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, ResourceError> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| corrupt("unexpected end of compressed data"))?;
+            self.position += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u32 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Discard remaining bits of the current byte
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn read_byte(&mut self) -> Result<u8, ResourceError> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| corrupt("unexpected end of stored block"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+}
+
+/// Canonical Huffman decoding table
+struct Huffman {
+    counts: [u16; 16],  // Number of codes of each length
+    symbols: Vec<u16>,  // Symbols ordered by code
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, ResourceError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(message: &str) -> ResourceError {
+    ResourceError::InvalidFormat(format!("deflate: {}", message))
+}
+
+/// Decompress a zlib stream (2-byte header, DEFLATE data, Adler-32 trailer)
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, ResourceError> {
+    if data.len() < 2 {
+        return Err(corrupt("zlib stream too short"));
+    }
+    let cmf = data[0];
+    let flg = data[1];
+    if cmf & 0x0F != 8 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err(corrupt("invalid zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(corrupt("preset dictionaries are not supported"));
+    }
+    inflate(&data[2..])
+}
+
+/// Decompress raw DEFLATE data
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, ResourceError> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::new();
+
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut output)?,
+            1 => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err(corrupt("invalid block type")),
+        }
+        if is_final {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), ResourceError> {
+    reader.align_to_byte();
+    let len = reader.read_byte()? as u16 | (reader.read_byte()? as u16) << 8;
+    let nlen = reader.read_byte()? as u16 | (reader.read_byte()? as u16) << 8;
+    if len != !nlen {
+        return Err(corrupt("stored block length mismatch"));
+    }
+    for _ in 0..len {
+        output.push(reader.read_byte()?);
+    }
+    Ok(())
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), ResourceError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_table = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_table.decode(reader)?;
+        match symbol {
+            0..=15 => lengths.push(symbol as u8),
+            16 => {
+                let previous = *lengths.last().ok_or_else(|| corrupt("repeat with no previous length"))?;
+                let repeat = 3 + reader.bits(2)? as usize;
+                lengths.extend(std::iter::repeat_n(previous, repeat));
+            }
+            17 => {
+                let repeat = 3 + reader.bits(3)? as usize;
+                lengths.extend(std::iter::repeat_n(0, repeat));
+            }
+            _ => {
+                let repeat = 11 + reader.bits(7)? as usize;
+                lengths.extend(std::iter::repeat_n(0, repeat));
+            }
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(corrupt("code lengths overflow"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), ResourceError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let index = symbol - 257;
+            if index >= LENGTH_BASE.len() {
+                return Err(corrupt("invalid length symbol"));
+            }
+            let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+            let dist_symbol = distances.decode(reader)? as usize;
+            if dist_symbol >= DIST_BASE.len() {
+                return Err(corrupt("invalid distance symbol"));
+            }
+            let distance = DIST_BASE[dist_symbol] as usize + reader.bits(DIST_EXTRA[dist_symbol] as u32)? as usize;
+            if distance > output.len() {
+                return Err(corrupt("distance too far back"));
+            }
+            let start = output.len() - distance;
+            for i in 0..length {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_block() {
+        // zlib header, final stored block of 3 bytes, adler32
+        let data = [0x78, 0x01, 0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c', 0, 0, 0, 0];
+        assert_eq!(zlib_decompress(&data).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_fixed_huffman_block() {
+        // zlib.compress(b"aaaaaaaaaa")
+        let data = [0x78, 0x9c, 0x4b, 0x4c, 0x84, 0x01, 0x00, 0x14, 0xe1, 0x03, 0xcb];
+        assert_eq!(zlib_decompress(&data).unwrap(), b"aaaaaaaaaa");
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(zlib_decompress(&[0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! SuperPascal Resource Compiler
+//!
+//! Converts assets referenced by `{$RESOURCE file AS Name}` directives into
+//! constant data at build time, together with generated Pascal declarations
+//! describing the data (size, dimensions, palette).
+//!
+//! # Supported Formats
+//!
+//! - **PNG**: converted to the Zeal video board native layout — 16x16 tiles,
+//!   8 bits per pixel, tiles stored row-major — plus an RGB565 palette
+//! - **PT3/AY**: music modules embedded verbatim, described by a
+//!   `TMusicData` record for the player unit in `lib/audio`
+//! - **Anything else**: embedded as a raw binary blob
+
+pub mod charset;
+pub mod inflate;
+pub mod music;
+pub mod png;
+pub mod registers;
+pub mod toml;
+
+pub use charset::Codepage;
+pub use music::MusicFormat;
+
+use std::path::Path;
+
+/// Width and height of a native sprite/tile in pixels
+pub const TILE_SIZE: u32 = 16;
+
+/// Maximum palette size for 8bpp graphics
+pub const MAX_PALETTE_SIZE: usize = 256;
+
+/// Resource compilation error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceError {
+    /// File could not be read
+    Io(String),
+    /// Asset is malformed
+    InvalidFormat(String),
+    /// Asset is valid but cannot be converted for the target
+    Unsupported(String),
+}
+
+impl std::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceError::Io(msg) => write!(f, "I/O error: {}", msg),
+            ResourceError::InvalidFormat(msg) => write!(f, "Invalid resource: {}", msg),
+            ResourceError::Unsupported(msg) => write!(f, "Unsupported resource: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+/// Kind of compiled resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Raw binary blob
+    Binary,
+    /// Image converted to native tile layout
+    Image,
+    /// Music module played by the AY player unit
+    Music(MusicFormat),
+}
+
+/// A resource converted into constant data
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledResource {
+    pub name: String,          // Pascal identifier the data is bound to
+    pub kind: ResourceKind,
+    pub data: Vec<u8>,         // Converted bytes (placed in the DATA section)
+    pub width: u32,            // Image width in pixels (0 for binaries)
+    pub height: u32,           // Image height in pixels (0 for binaries)
+    pub palette: Vec<u16>,     // RGB565 palette (images only)
+}
+
+impl CompiledResource {
+    /// Create a raw binary resource
+    pub fn binary(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: ResourceKind::Binary,
+            data,
+            width: 0,
+            height: 0,
+            palette: vec![],
+        }
+    }
+
+    /// Number of 16x16 tiles in an image resource
+    pub fn tile_count(&self) -> u32 {
+        (self.width / TILE_SIZE) * (self.height / TILE_SIZE)
+    }
+
+    /// Name of the data symbol holding the converted bytes
+    ///
+    /// Music resources bind the resource name to their `TMusicData`
+    /// descriptor, so the module itself lives under `Name_Data`.
+    pub fn data_symbol(&self) -> String {
+        match self.kind {
+            ResourceKind::Music(_) => format!("{}_Data", self.name),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// `TMusicData` record image for music resources
+    ///
+    /// The leading address word is left zero; it must be relocated against
+    /// `data_symbol()`.
+    pub fn descriptor_bytes(&self) -> Option<Vec<u8>> {
+        match self.kind {
+            ResourceKind::Music(format) => {
+                let mut bytes = vec![0, 0];
+                bytes.extend_from_slice(&(self.data.len() as u16).to_le_bytes());
+                bytes.push(format as u8);
+                debug_assert_eq!(bytes.len(), music::MUSIC_DATA_SIZE);
+                Some(bytes)
+            }
+            _ => None,
+        }
+    }
+
+    /// Name of the data symbol holding the palette
+    pub fn palette_symbol(&self) -> String {
+        format!("{}_Palette", self.name)
+    }
+
+    /// Name of the compiler-generated routine drawing an image resource
+    pub fn draw_symbol(&self) -> String {
+        format!("{}_Draw", self.name)
+    }
+
+    /// Image pixels in row-major order, undoing the tile layout of `data`
+    pub fn image_rows(&self) -> Vec<u8> {
+        let tiles_across = self.width / TILE_SIZE;
+        let mut rows = Vec::with_capacity(self.data.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let tile = (y / TILE_SIZE) * tiles_across + x / TILE_SIZE;
+                let offset = tile * TILE_SIZE * TILE_SIZE + (y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE;
+                rows.push(self.data[offset as usize]);
+            }
+        }
+        rows
+    }
+
+    /// Palette encoded as little-endian RGB565 words
+    pub fn palette_bytes(&self) -> Vec<u8> {
+        self.palette.iter().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    /// Constants the declarations give the size and shape of the resource
+    /// by, with their values
+    fn constants(&self) -> Vec<(String, u32)> {
+        let mut constants = vec![(format!("{}_Size", self.name), self.data.len() as u32)];
+        if self.kind == ResourceKind::Image {
+            constants.push((format!("{}_Width", self.name), self.width));
+            constants.push((format!("{}_Height", self.name), self.height));
+            constants.push((format!("{}_TileCount", self.name), self.tile_count()));
+            constants.push((format!("{}_PaletteSize", self.name), self.palette.len() as u32));
+        }
+        constants
+    }
+
+    /// Variables the declarations bind to the data the object file places
+    /// for this resource, with their types
+    pub fn variables(&self) -> Vec<(String, &'static str)> {
+        let mut variables = vec![(self.data_symbol(), "byte")];
+        if let ResourceKind::Music(_) = self.kind {
+            variables.push((self.name.clone(), "TMusicData"));
+        }
+        if self.kind == ResourceKind::Image && !self.palette.is_empty() {
+            variables.push((self.palette_symbol(), "word"));
+        }
+        variables
+    }
+
+    /// Every identifier the declarations of this resource introduce,
+    /// those shared by music resources included
+    pub fn declared_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.constants().into_iter().map(|(name, _)| name).collect();
+        names.extend(self.variables().into_iter().map(|(name, _)| name));
+        match self.kind {
+            ResourceKind::Image => names.push(self.draw_symbol()),
+            ResourceKind::Music(_) => names.extend(music::MUSIC_DATA_NAMES.iter().map(|name| name.to_string())),
+            _ => {}
+        }
+        names
+    }
+
+    /// Generate the Pascal declarations exposing this resource
+    ///
+    /// The data itself is bound to a variable of the resource name placed on
+    /// its first byte, so `@Name` yields its address once the object file is
+    /// linked and `Name_Size` bytes may be read from there.
+    pub fn declarations(&self) -> String {
+        let mut src = String::new();
+        src.push_str("const\n");
+        for (name, value) in self.constants() {
+            src.push_str(&format!("  {} = {};\n", name, value));
+        }
+        src.push_str("var\n");
+        for (name, ty) in self.variables() {
+            src.push_str(&format!("  {}: {};\n", name, ty));
+        }
+        if self.kind == ResourceKind::Image {
+            src.push_str(&format!("procedure {}(Dest: word); external;\n", self.draw_symbol()));
+        }
+        src
+    }
+}
+
+/// Compile the resource at `path` and bind it to the Pascal identifier `name`
+pub fn compile_resource(path: &Path, name: &str) -> Result<CompiledResource, ResourceError> {
+    let data = std::fs::read(path)
+        .map_err(|e| ResourceError::Io(format!("cannot read '{}': {}", path.display(), e)))?;
+
+    if data.len() > u16::MAX as usize {
+        return Err(ResourceError::Unsupported(format!(
+            "'{}' is {} bytes, larger than the 64K address space",
+            path.display(),
+            data.len()
+        )));
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    if let Some(format) = extension.as_deref().and_then(MusicFormat::from_extension) {
+        music::validate(format, &data)?;
+        return Ok(CompiledResource {
+            kind: ResourceKind::Music(format),
+            ..CompiledResource::binary(name, data)
+        });
+    }
+
+    match extension.as_deref() {
+        Some("png") => convert_image(name, &png::decode(&data)?),
+        _ => Ok(CompiledResource::binary(name, data)),
+    }
+}
+
+/// Convert a decoded image into native 8bpp tiles and a palette
+pub fn convert_image(name: &str, image: &png::PngImage) -> Result<CompiledResource, ResourceError> {
+    if image.width == 0
+        || image.height == 0
+        || !image.width.is_multiple_of(TILE_SIZE)
+        || !image.height.is_multiple_of(TILE_SIZE)
+    {
+        return Err(ResourceError::Unsupported(format!(
+            "image is {}x{}, dimensions must be non-zero multiples of {}",
+            image.width, image.height, TILE_SIZE
+        )));
+    }
+
+    // Indexed images keep their palette; true-colour images get one built
+    // in order of first appearance
+    let (indices, palette): (Vec<u8>, Vec<[u8; 3]>) = match &image.indices {
+        Some(indices) => (indices.clone(), image.palette.clone()),
+        None => {
+            let mut palette: Vec<[u8; 3]> = Vec::new();
+            let mut indices = Vec::with_capacity(image.pixels.len());
+            for pixel in &image.pixels {
+                let rgb = [pixel[0], pixel[1], pixel[2]];
+                let index = match palette.iter().position(|c| *c == rgb) {
+                    Some(index) => index,
+                    None => {
+                        palette.push(rgb);
+                        palette.len() - 1
+                    }
+                };
+                if index >= MAX_PALETTE_SIZE {
+                    return Err(ResourceError::Unsupported(format!(
+                        "image uses more than {} colours",
+                        MAX_PALETTE_SIZE
+                    )));
+                }
+                indices.push(index as u8);
+            }
+            (indices, palette)
+        }
+    };
+
+    // Reorder pixels into 16x16 tiles, row-major within and across tiles
+    let width = image.width as usize;
+    let tile = TILE_SIZE as usize;
+    let mut data = Vec::with_capacity(indices.len());
+    for tile_y in 0..image.height as usize / tile {
+        for tile_x in 0..width / tile {
+            for y in 0..tile {
+                let row = (tile_y * tile + y) * width + tile_x * tile;
+                data.extend_from_slice(&indices[row..row + tile]);
+            }
+        }
+    }
+
+    Ok(CompiledResource {
+        name: name.to_string(),
+        kind: ResourceKind::Image,
+        data,
+        width: image.width,
+        height: image.height,
+        palette: palette.iter().map(|c| rgb565(c[0], c[1], c[2])).collect(),
+    })
+}
+
+/// Pack an 8-bit-per-channel colour into RGB565
+pub fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use png::PngImage;
+
+    fn solid_image(width: u32, height: u32) -> PngImage {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                // Left half red, right half blue
+                if x < width / 2 {
+                    pixels.push([255, 0, 0, 255]);
+                } else {
+                    pixels.push([0, 0, 255, (y % 2) as u8]);
+                }
+            }
+        }
+        PngImage {
+            width,
+            height,
+            pixels,
+            indices: None,
+            palette: vec![],
+        }
+    }
+
+    #[test]
+    fn test_rgb565() {
+        assert_eq!(rgb565(255, 255, 255), 0xFFFF);
+        assert_eq!(rgb565(255, 0, 0), 0xF800);
+        assert_eq!(rgb565(0, 0, 255), 0x001F);
+    }
+
+    #[test]
+    fn test_convert_image_tiles() {
+        let resource = convert_image("Sprites", &solid_image(32, 16)).unwrap();
+        assert_eq!(resource.kind, ResourceKind::Image);
+        assert_eq!(resource.tile_count(), 2);
+        assert_eq!(resource.data.len(), 512);
+        assert_eq!(resource.palette, vec![0xF800, 0x001F]);
+        // First tile is entirely left half (red), second entirely right (blue)
+        assert!(resource.data[..256].iter().all(|&p| p == 0));
+        assert!(resource.data[256..].iter().all(|&p| p == 1));
+        // Row-major: each row is 16 red pixels then 16 blue
+        let rows = resource.image_rows();
+        assert_eq!(&rows[..32], [[0u8; 16], [1u8; 16]].concat().as_slice());
+    }
+
+    #[test]
+    fn test_convert_image_rejects_odd_size() {
+        assert!(matches!(
+            convert_image("Bad", &solid_image(10, 16)),
+            Err(ResourceError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_binary_declarations() {
+        let resource = CompiledResource::binary("Level1", vec![1, 2, 3]);
+        let decls = resource.declarations();
+        assert!(decls.contains("Level1_Size = 3;"));
+        assert!(decls.contains("Level1: byte;"));
+        assert!(!decls.contains("Level1_Width"));
+    }
+
+    #[test]
+    fn test_image_declarations() {
+        let resource = convert_image("Sprites", &solid_image(16, 32)).unwrap();
+        let decls = resource.declarations();
+        assert!(decls.contains("Sprites_Width = 16;"));
+        assert!(decls.contains("Sprites_Height = 32;"));
+        assert!(decls.contains("Sprites_TileCount = 2;"));
+        assert!(decls.contains("Sprites_Palette: word;"));
+        assert!(decls.contains("procedure Sprites_Draw(Dest: word); external;"));
+        assert_eq!(resource.palette_bytes(), vec![0x00, 0xF8, 0x1F, 0x00]);
+    }
+
+    #[test]
+    fn test_music_declarations_and_descriptor() {
+        let resource = CompiledResource {
+            kind: ResourceKind::Music(MusicFormat::Pt3),
+            ..CompiledResource::binary("MySong", vec![0; 300])
+        };
+        let decls = resource.declarations();
+        assert!(decls.contains("MySong_Size = 300;"));
+        assert!(decls.contains("MySong_Data: byte;"));
+        assert!(decls.contains("MySong: TMusicData;"));
+        assert_eq!(resource.data_symbol(), "MySong_Data");
+        assert_eq!(resource.descriptor_bytes(), Some(vec![0, 0, 0x2C, 0x01, 1]));
+    }
+
+    #[test]
+    fn test_compile_invalid_music() {
+        let path = std::env::temp_dir().join("spc_resource_test.pt3");
+        std::fs::write(&path, b"not a tracker module").unwrap();
+        assert!(matches!(
+            compile_resource(&path, "Song"),
+            Err(ResourceError::InvalidFormat(_))
+        ));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_compile_raw_binary() {
+        let path = std::env::temp_dir().join("spc_resource_test.bin");
+        std::fs::write(&path, [0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        let resource = compile_resource(&path, "Blob").unwrap();
+        assert_eq!(resource.kind, ResourceKind::Binary);
+        assert_eq!(resource.data, vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
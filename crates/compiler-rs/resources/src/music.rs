@@ -0,0 +1,111 @@
+//! AY music module validation (PT3 and AY formats)
+//!
+//! Music blobs are embedded verbatim; the player unit interprets them at
+//! run time. This module only checks the headers so that a mistyped path or
+//! a corrupt file is caught at build time rather than on the machine.
+
+use crate::ResourceError;
+
+/// Music module format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MusicFormat {
+    /// ProTracker 3 / Vortex Tracker II module
+    Pt3 = 1,
+    /// ZXAYEMUL container
+    Ay = 2,
+}
+
+impl MusicFormat {
+    /// Format constant name as declared for Pascal code
+    pub fn constant_name(&self) -> &'static str {
+        match self {
+            MusicFormat::Pt3 => "MUSIC_FORMAT_PT3",
+            MusicFormat::Ay => "MUSIC_FORMAT_AY",
+        }
+    }
+
+    /// Determine the format from a file extension
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "pt3" => Some(MusicFormat::Pt3),
+            "ay" => Some(MusicFormat::Ay),
+            _ => None,
+        }
+    }
+}
+
+/// Minimum size of a PT3 module (header through the pattern table pointer)
+const PT3_HEADER_SIZE: usize = 0xC9;
+
+/// Verify that `data` is a well-formed module of the given format
+pub fn validate(format: MusicFormat, data: &[u8]) -> Result<(), ResourceError> {
+    match format {
+        MusicFormat::Pt3 => {
+            let has_magic = data.starts_with(b"ProTracker 3.") || data.starts_with(b"Vortex Tracker II");
+            if !has_magic {
+                return Err(ResourceError::InvalidFormat("pt3: missing ProTracker 3 header".to_string()));
+            }
+            if data.len() < PT3_HEADER_SIZE {
+                return Err(ResourceError::InvalidFormat(format!(
+                    "pt3: module is {} bytes, header alone is {}",
+                    data.len(),
+                    PT3_HEADER_SIZE
+                )));
+            }
+        }
+        MusicFormat::Ay => {
+            if !data.starts_with(b"ZXAYEMUL") {
+                return Err(ResourceError::InvalidFormat("ay: missing ZXAYEMUL header".to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pascal declarations shared by every music resource in a compilation unit
+///
+/// Layout must match `TMusicData` in `lib/audio/types.pas`.
+pub const MUSIC_DATA_DECLARATIONS: &str = "\
+const
+  MUSIC_FORMAT_PT3 = 1;
+  MUSIC_FORMAT_AY = 2;
+type
+  TMusicData = record
+    Data: word;
+    Size: word;
+    Format: byte;
+  end;
+";
+
+/// Identifiers [`MUSIC_DATA_DECLARATIONS`] declares
+pub const MUSIC_DATA_NAMES: [&str; 3] = ["MUSIC_FORMAT_PT3", "MUSIC_FORMAT_AY", "TMusicData"];
+
+/// Size in bytes of a `TMusicData` record image
+pub const MUSIC_DATA_SIZE: usize = 5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(MusicFormat::from_extension("PT3"), Some(MusicFormat::Pt3));
+        assert_eq!(MusicFormat::from_extension("ay"), Some(MusicFormat::Ay));
+        assert_eq!(MusicFormat::from_extension("png"), None);
+    }
+
+    #[test]
+    fn test_validate_pt3() {
+        let mut module = b"ProTracker 3.6 compilation of ".to_vec();
+        module.resize(PT3_HEADER_SIZE, 0);
+        assert!(validate(MusicFormat::Pt3, &module).is_ok());
+        assert!(validate(MusicFormat::Pt3, &module[..40]).is_err());
+        assert!(validate(MusicFormat::Pt3, b"not a module").is_err());
+    }
+
+    #[test]
+    fn test_validate_ay() {
+        assert!(validate(MusicFormat::Ay, b"ZXAYEMUL\x00\x03").is_ok());
+        assert!(validate(MusicFormat::Ay, b"ZXAYAMAD").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! PNG decoder for build-time asset conversion
+//!
+//! Supports non-interlaced images in the colour types commonly produced by
+//! pixel-art tools: greyscale, RGB, indexed, greyscale+alpha and RGBA.
+
+use crate::ResourceError;
+use crate::inflate;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Decoded PNG image
+#[derive(Debug, Clone, PartialEq)]
+pub struct PngImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels, row-major
+    pub pixels: Vec<[u8; 4]>,
+    /// Original palette indices (indexed images only)
+    pub indices: Option<Vec<u8>>,
+    /// Original palette (indexed images only)
+    pub palette: Vec<[u8; 3]>,
+}
+
+fn invalid(message: impl Into<String>) -> ResourceError {
+    ResourceError::InvalidFormat(format!("png: {}", message.into()))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Decode a PNG file held in memory
+pub fn decode(data: &[u8]) -> Result<PngImage, ResourceError> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(invalid("missing PNG signature"));
+    }
+
+    let mut position = PNG_SIGNATURE.len();
+    let mut header: Option<(u32, u32, u8, u8, u8)> = None;
+    let mut palette = Vec::new();
+    let mut idat = Vec::new();
+
+    while position + 8 <= data.len() {
+        let length = read_u32(&data[position..]) as usize;
+        let chunk_type = &data[position + 4..position + 8];
+        let body_start = position + 8;
+        let body_end = body_start + length;
+        if body_end + 4 > data.len() {
+            return Err(invalid("truncated chunk"));
+        }
+        let body = &data[body_start..body_end];
+
+        match chunk_type {
+            b"IHDR" => {
+                if body.len() < 13 {
+                    return Err(invalid("IHDR too short"));
+                }
+                // width, height, bit depth, colour type, interlace
+                header = Some((read_u32(body), read_u32(&body[4..]), body[8], body[9], body[12]));
+            }
+            b"PLTE" => {
+                palette = body.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+            }
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {} // Ancillary chunks are ignored
+        }
+        position = body_end + 4; // Skip CRC
+    }
+
+    let (width, height, bit_depth, color_type, interlace) =
+        header.ok_or_else(|| invalid("missing IHDR chunk"))?;
+    if interlace != 0 {
+        return Err(invalid("interlaced images are not supported"));
+    }
+
+    let channels = match color_type {
+        0 => 1, // Greyscale
+        2 => 3, // RGB
+        3 => 1, // Indexed
+        4 => 2, // Greyscale + alpha
+        6 => 4, // RGBA
+        _ => return Err(invalid(format!("unsupported colour type {}", color_type))),
+    };
+    let bits_ok = match color_type {
+        0 | 3 => matches!(bit_depth, 1 | 2 | 4 | 8),
+        _ => bit_depth == 8,
+    };
+    if !bits_ok {
+        return Err(invalid(format!(
+            "unsupported bit depth {} for colour type {}",
+            bit_depth, color_type
+        )));
+    }
+    if color_type == 3 && palette.is_empty() {
+        return Err(invalid("indexed image without PLTE chunk"));
+    }
+
+    let raw = inflate::zlib_decompress(&idat)?;
+    let bits_per_pixel = channels * bit_depth as usize;
+    let stride = (width as usize * bits_per_pixel).div_ceil(8);
+    let filter_bpp = bits_per_pixel.div_ceil(8).max(1);
+    if raw.len() < (stride + 1) * height as usize {
+        return Err(invalid("image data too short"));
+    }
+
+    // Undo scanline filters
+    let mut rows = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        let filter = raw[y * (stride + 1)];
+        let src = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let a = if x >= filter_bpp { rows[y * stride + x - filter_bpp] } else { 0 };
+            let b = if y > 0 { rows[(y - 1) * stride + x] } else { 0 };
+            let c = if x >= filter_bpp && y > 0 { rows[(y - 1) * stride + x - filter_bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(invalid(format!("invalid filter type {}", filter))),
+            };
+            rows[y * stride + x] = src[x].wrapping_add(predicted);
+        }
+    }
+
+    // Expand to RGBA
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    let mut indices = if color_type == 3 { Some(Vec::new()) } else { None };
+    for y in 0..height as usize {
+        let row = &rows[y * stride..(y + 1) * stride];
+        for x in 0..width as usize {
+            let pixel = match color_type {
+                0 | 3 => {
+                    let value = sample(row, x, bit_depth);
+                    if color_type == 3 {
+                        let entry = palette
+                            .get(value as usize)
+                            .ok_or_else(|| invalid("palette index out of range"))?;
+                        if let Some(ref mut idx) = indices {
+                            idx.push(value);
+                        }
+                        [entry[0], entry[1], entry[2], 255]
+                    } else {
+                        let grey = scale_to_byte(value, bit_depth);
+                        [grey, grey, grey, 255]
+                    }
+                }
+                2 => [row[x * 3], row[x * 3 + 1], row[x * 3 + 2], 255],
+                4 => [row[x * 2], row[x * 2], row[x * 2], row[x * 2 + 1]],
+                _ => [row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]],
+            };
+            pixels.push(pixel);
+        }
+    }
+
+    Ok(PngImage {
+        width,
+        height,
+        pixels,
+        indices,
+        palette,
+    })
+}
+
+/// Extract a sub-byte sample from a packed scanline
+fn sample(row: &[u8], x: usize, bit_depth: u8) -> u8 {
+    let depth = bit_depth as usize;
+    let bit = x * depth;
+    let byte = row[bit / 8];
+    let shift = 8 - depth - (bit % 8);
+    (byte >> shift) & ((1u16 << depth) - 1) as u8
+}
+
+fn scale_to_byte(value: u8, bit_depth: u8) -> u8 {
+    let max = (1u16 << bit_depth) - 1;
+    ((value as u16 * 255) / max) as u8
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a PNG from raw (already filtered) scanlines using a stored deflate block
+    fn build_png(width: u32, height: u32, color_type: u8, palette: &[[u8; 3]], scanlines: &[u8]) -> Vec<u8> {
+        fn chunk(out: &mut Vec<u8>, kind: &[u8], body: &[u8]) {
+            out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            out.extend_from_slice(kind);
+            out.extend_from_slice(body);
+            out.extend_from_slice(&[0, 0, 0, 0]); // CRC is not checked
+        }
+        let mut out = PNG_SIGNATURE.to_vec();
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+        chunk(&mut out, b"IHDR", &ihdr);
+        if !palette.is_empty() {
+            let plte: Vec<u8> = palette.iter().flatten().copied().collect();
+            chunk(&mut out, b"PLTE", &plte);
+        }
+        let len = scanlines.len() as u16;
+        let mut idat = vec![0x78, 0x01, 0x01];
+        idat.extend_from_slice(&len.to_le_bytes());
+        idat.extend_from_slice(&(!len).to_le_bytes());
+        idat.extend_from_slice(scanlines);
+        idat.extend_from_slice(&[0, 0, 0, 0]);
+        chunk(&mut out, b"IDAT", &idat);
+        chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    #[test]
+    fn test_decode_rgb() {
+        let png = build_png(2, 1, 2, &[], &[0, 255, 0, 0, 0, 0, 255]);
+        let image = decode(&png).unwrap();
+        assert_eq!(image.width, 2);
+        assert_eq!(image.pixels, vec![[255, 0, 0, 255], [0, 0, 255, 255]]);
+        assert!(image.indices.is_none());
+    }
+
+    #[test]
+    fn test_decode_indexed_with_sub_filter() {
+        // Sub filter: second pixel = 1 + 0 = 1
+        let png = build_png(2, 1, 3, &[[0, 0, 0], [255, 255, 255]], &[1, 1, 0]);
+        let image = decode(&png).unwrap();
+        assert_eq!(image.indices, Some(vec![1, 1]));
+        assert_eq!(image.pixels[1], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_reject_non_png() {
+        assert!(decode(b"GIF89a").is_err());
+    }
+}
@@ -0,0 +1,359 @@
+//! Memory-mapped register descriptions
+//!
+//! A TOML file describes a machine's I/O registers, SVD-style, and is turned
+//! into a Pascal unit: one variable per register placed at its address with
+//! `absolute`, and a Get/Set routine pair per bitfield. Setters read, modify
+//! and write back the whole register.
+//!
+//! ```toml
+//! unit = "Video"               # defaults to the file name
+//!
+//! [[register]]
+//! name = "Ctrl"
+//! address = 0xA000
+//! size = 8                     # 8 or 16 bits, default 8
+//! description = "Control"
+//!
+//! [[register.field]]
+//! name = "Mode"
+//! bit = 4                      # lowest bit of the field
+//! width = 3                    # default 1; 1-bit fields are booleans
+//! access = "rw"                # "r", "w" or "rw", default "rw"
+//! ```
+
+use std::collections::HashSet;
+
+use crate::toml::{self, Table, Value, check_keys, integer, required, string, tables};
+use crate::ResourceError;
+
+/// Extension of register description files
+pub const REGISTER_MAP_EXTENSION: &str = "toml";
+
+/// How a field may be accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl Access {
+    fn readable(self) -> bool {
+        self != Access::WriteOnly
+    }
+
+    fn writable(self) -> bool {
+        self != Access::ReadOnly
+    }
+}
+
+/// A bitfield of a register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub bit: u8,
+    pub width: u8,
+    pub access: Access,
+    pub description: Option<String>,
+}
+
+impl Field {
+    /// The field's bits within the register
+    pub fn mask(&self) -> u16 {
+        (((1u32 << self.width) - 1) << self.bit) as u16
+    }
+}
+
+/// A memory-mapped register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub name: String,
+    pub address: u16,
+    /// Width in bits: 8 or 16
+    pub size: u8,
+    pub description: Option<String>,
+    pub fields: Vec<Field>,
+}
+
+impl Register {
+    fn pascal_type(&self) -> &'static str {
+        if self.size == 8 { "byte" } else { "word" }
+    }
+
+    /// `value` as a Pascal hex literal as wide as the register
+    fn hex(&self, value: u16) -> String {
+        format!("${:0width$X}", value, width = self.size as usize / 4)
+    }
+
+    fn full_mask(&self) -> u16 {
+        if self.size == 8 { 0xFF } else { 0xFFFF }
+    }
+}
+
+/// The registers of one machine, generated into one unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterMap {
+    pub unit: String,
+    pub registers: Vec<Register>,
+}
+
+impl RegisterMap {
+    /// Read a register description; `default_unit` names the unit when the
+    /// description does not
+    pub fn parse(source: &str, default_unit: &str) -> Result<Self, ResourceError> {
+        let doc = toml::parse(source)?;
+        check_keys(&doc, &["unit", "register"], "description")?;
+        let unit = string(&doc, "unit", "description")?.unwrap_or_else(|| default_unit.to_string());
+        check_identifier(&unit, "unit name")?;
+
+        let mut registers = Vec::new();
+        for value in tables(&doc, "register", "description")? {
+            registers.push(parse_register(value)?);
+        }
+        if registers.is_empty() {
+            return Err(invalid("description has no [[register]] tables".to_string()));
+        }
+
+        // Registers and accessors share the unit's namespace
+        let mut names = HashSet::new();
+        for register in &registers {
+            let accessors = register.fields.iter().flat_map(|f| {
+                [format!("Get{}{}", register.name, f.name), format!("Set{}{}", register.name, f.name)]
+            });
+            for name in std::iter::once(register.name.clone()).chain(accessors) {
+                if !names.insert(name.to_lowercase()) {
+                    return Err(invalid(format!("identifier '{}' is generated twice", name)));
+                }
+            }
+        }
+        Ok(Self { unit, registers })
+    }
+
+    /// Generate the Pascal unit
+    pub fn to_pascal(&self) -> String {
+        let mut src = String::from("{ Generated from a register description by spc; do not edit }\n\n");
+        src.push_str(&format!("unit {};\n\ninterface\n\nvar\n", self.unit));
+        for register in &self.registers {
+            if let Some(description) = &register.description {
+                src.push_str(&format!("  {}\n", comment(description)));
+            }
+            src.push_str(&format!(
+                "  {}: {} absolute ${:04X};\n",
+                register.name,
+                register.pascal_type(),
+                register.address
+            ));
+        }
+
+        let mut headings = String::new();
+        let mut bodies = String::new();
+        for register in &self.registers {
+            for field in &register.fields {
+                self.field_accessors(register, field, &mut headings, &mut bodies);
+            }
+        }
+        if !headings.is_empty() {
+            src.push('\n');
+            src.push_str(&headings);
+        }
+        src.push_str("\nimplementation\n");
+        src.push_str(&bodies);
+        src.push_str("\nend.\n");
+        src
+    }
+
+    fn field_accessors(&self, register: &Register, field: &Field, headings: &mut String, bodies: &mut String) {
+        let reg = &register.name;
+        let mask = field.mask();
+        let scale = 1u32 << field.bit;
+        let value_type = if field.width == 1 { "boolean" } else { register.pascal_type() };
+
+        if let Some(description) = &field.description {
+            headings.push_str(&format!("{}\n", comment(description)));
+        }
+        if field.access.readable() {
+            let heading = format!("function Get{}{}: {};", reg, field.name, value_type);
+            let value = if field.width == 1 {
+                format!("({} and {}) <> 0", reg, register.hex(mask))
+            } else if mask == register.full_mask() {
+                reg.clone()
+            } else if field.bit == 0 {
+                format!("{} and {}", reg, register.hex(mask))
+            } else {
+                format!("({} and {}) div {}", reg, register.hex(mask), scale)
+            };
+            headings.push_str(&format!("{}\n", heading));
+            bodies.push_str(&format!("\n{}\nbegin\n  Get{}{} := {};\nend;\n", heading, reg, field.name, value));
+        }
+        if field.access.writable() {
+            let heading = format!("procedure Set{}{}(Value: {});", reg, field.name, value_type);
+            let keep = register.hex(register.full_mask() & !mask);
+            let statement = if field.width == 1 {
+                format!("if Value then {r} := {r} or {} else {r} := {r} and {}", register.hex(mask), keep, r = reg)
+            } else if mask == register.full_mask() {
+                format!("{} := Value", reg)
+            } else {
+                let low = register.hex(mask >> field.bit);
+                let bits = if field.bit == 0 {
+                    format!("(Value and {})", low)
+                } else {
+                    format!("((Value and {}) * {})", low, scale)
+                };
+                format!("{r} := ({r} and {}) or {}", keep, bits, r = reg)
+            };
+            headings.push_str(&format!("{}\n", heading));
+            bodies.push_str(&format!("\n{}\nbegin\n  {};\nend;\n", heading, statement));
+        }
+    }
+}
+
+fn parse_register(value: &Table) -> Result<Register, ResourceError> {
+    let context = match value.get("name").and_then(Value::as_str) {
+        Some(name) => format!("register '{}'", name),
+        None => "register".to_string(),
+    };
+    check_keys(value, &["name", "address", "size", "description", "field"], &context)?;
+    let name = required(string(value, "name", &context)?, "name", &context)?;
+    check_identifier(&name, "register name")?;
+    let address = required(integer(value, "address", &context, 0, 0xFFFF)?, "address", &context)? as u16;
+    let size = integer(value, "size", &context, 8, 16)?.unwrap_or(8) as u8;
+    if size != 8 && size != 16 {
+        return Err(invalid(format!("{}: size must be 8 or 16", context)));
+    }
+    let description = string(value, "description", &context)?;
+
+    let mut fields: Vec<Field> = Vec::new();
+    let mut used = 0u16;
+    for table in tables(value, "field", &context)? {
+        let field = parse_field(table, size, &context)?;
+        if fields.iter().any(|f| f.name.eq_ignore_ascii_case(&field.name)) {
+            return Err(invalid(format!("{}: field '{}' is defined twice", context, field.name)));
+        }
+        if used & field.mask() != 0 {
+            return Err(invalid(format!("{}: field '{}' overlaps another field", context, field.name)));
+        }
+        used |= field.mask();
+        fields.push(field);
+    }
+    Ok(Register { name, address, size, description, fields })
+}
+
+fn parse_field(value: &Table, size: u8, register: &str) -> Result<Field, ResourceError> {
+    let context = match value.get("name").and_then(Value::as_str) {
+        Some(name) => format!("{}, field '{}'", register, name),
+        None => format!("{}, field", register),
+    };
+    check_keys(value, &["name", "bit", "width", "access", "description"], &context)?;
+    let name = required(string(value, "name", &context)?, "name", &context)?;
+    check_identifier(&name, "field name")?;
+    let bit = required(integer(value, "bit", &context, 0, size as i64 - 1)?, "bit", &context)? as u8;
+    let width = integer(value, "width", &context, 1, size as i64)?.unwrap_or(1) as u8;
+    if bit + width > size {
+        return Err(invalid(format!("{}: bits {}..{} exceed the {}-bit register", context, bit, bit + width - 1, size)));
+    }
+    let access = match string(value, "access", &context)?.as_deref() {
+        None | Some("rw") => Access::ReadWrite,
+        Some("r") => Access::ReadOnly,
+        Some("w") => Access::WriteOnly,
+        Some(other) => {
+            return Err(invalid(format!("{}: access must be \"r\", \"w\" or \"rw\", found \"{}\"", context, other)));
+        }
+    };
+    let description = string(value, "description", &context)?;
+    Ok(Field { name, bit, width, access, description })
+}
+
+fn invalid(message: String) -> ResourceError {
+    ResourceError::InvalidFormat(message)
+}
+
+fn check_identifier(name: &str, what: &str) -> Result<(), ResourceError> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid(format!("{} '{}' is not a Pascal identifier", what, name)))
+    }
+}
+
+/// A Pascal comment holding `text`
+fn comment(text: &str) -> String {
+    format!("{{ {} }}", text.replace('}', ")"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO: &str = "
+        [[register]]
+        name = \"Ctrl\"
+        address = 0xA000
+        description = \"Display control\"
+
+        [[register.field]]
+        name = \"Enable\"
+        bit = 7
+        description = \"Display on\"
+
+        [[register.field]]
+        name = \"Mode\"
+        bit = 4
+        width = 3
+
+        [[register.field]]
+        name = \"Ready\"
+        bit = 0
+        access = \"r\"
+
+        [[register]]
+        name = \"Scroll\"
+        address = 0xA002
+        size = 16
+        fields = []
+        ";
+
+    #[test]
+    fn test_generate_unit() {
+        let map = RegisterMap::parse(&VIDEO.replace("fields = []", ""), "Video").unwrap();
+        assert_eq!(map.unit, "Video");
+        assert_eq!(map.registers[0].fields[1].mask(), 0x70);
+
+        let src = map.to_pascal();
+        assert!(src.contains("unit Video;"));
+        assert!(src.contains("  { Display control }\n  Ctrl: byte absolute $A000;\n"));
+        assert!(src.contains("  Scroll: word absolute $A002;\n"));
+        assert!(src.contains("{ Display on }\nfunction GetCtrlEnable: boolean;\nprocedure SetCtrlEnable(Value: boolean);\n"));
+        assert!(src.contains("GetCtrlEnable := (Ctrl and $80) <> 0;"));
+        assert!(src.contains("if Value then Ctrl := Ctrl or $80 else Ctrl := Ctrl and $7F;"));
+        assert!(src.contains("GetCtrlMode := (Ctrl and $70) div 16;"));
+        assert!(src.contains("Ctrl := (Ctrl and $8F) or ((Value and $07) * 16);"));
+        assert!(src.contains("function GetCtrlReady: boolean;"));
+        assert!(!src.contains("SetCtrlReady"));
+        assert!(src.ends_with("end.\n"));
+    }
+
+    #[test]
+    fn test_invalid_descriptions() {
+        let message = |source: &str| RegisterMap::parse(source, "Video").unwrap_err().to_string();
+        assert_eq!(
+            message(VIDEO),
+            "Invalid resource: register 'Scroll': unknown key 'fields'"
+        );
+        assert_eq!(
+            message("[[register]]\nname = \"A\"\naddress = 0x10000"),
+            "Invalid resource: register 'A': 'address' is 65536, outside 0..65535"
+        );
+        assert_eq!(
+            message("[[register]]\nname = \"A\"\naddress = 1\n[[register.field]]\nname = \"F\"\nbit = 6\nwidth = 3"),
+            "Invalid resource: register 'A', field 'F': bits 6..8 exceed the 8-bit register"
+        );
+        assert_eq!(
+            message("[[register]]\nname = \"A\"\naddress = 1\n[[register.field]]\nname = \"F\"\nbit = 0\nwidth = 2\n[[register.field]]\nname = \"G\"\nbit = 1"),
+            "Invalid resource: register 'A': field 'G' overlaps another field"
+        );
+        assert_eq!(message("unit = \"Video\""), "Invalid resource: description has no [[register]] tables");
+    }
+}
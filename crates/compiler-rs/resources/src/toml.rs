@@ -0,0 +1,439 @@
+//! Reader for the subset of TOML used by build description files
+//!
+//! Supported: `[table]` and `[[array.of.tables]]` headers, `key = value`
+//! pairs with bare, quoted or dotted keys, basic and literal strings,
+//! integers (decimal, `0x`, `0o`, `0b`, with `_` separators), booleans,
+//! arrays and inline tables, and `#` comments. Floats, dates and multi-line
+//! strings are not.
+
+use std::collections::BTreeMap;
+
+use crate::ResourceError;
+
+/// A table: keys in sorted order
+pub type Table = BTreeMap<String, Value>;
+
+/// A TOML value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// Name of the value's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a TOML document into its root table
+pub fn parse(source: &str) -> Result<Table, ResourceError> {
+    let mut reader = Reader { chars: source.chars().collect(), pos: 0, line: 1 };
+    reader.document().map_err(|msg| ResourceError::InvalidFormat(format!("line {}: {}", reader.line, msg)))
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Reader {
+    fn document(&mut self) -> Result<Table, String> {
+        let mut root = Table::new();
+        let mut current: Vec<String> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.pos += 1;
+                    let array = self.eat('[');
+                    let path = self.key()?;
+                    self.expect(']')?;
+                    if array {
+                        self.expect(']')?;
+                    }
+                    let (last, parents) = path.split_last().expect("keys have at least one part");
+                    let parent = table_at(&mut root, parents)?;
+                    if array {
+                        match parent.entry(last.clone()).or_insert_with(|| Value::Array(vec![])) {
+                            Value::Array(items) => items.push(Value::Table(Table::new())),
+                            _ => return Err(format!("'{}' is not an array of tables", path.join("."))),
+                        }
+                    } else if parent.contains_key(last) {
+                        return Err(format!("table '{}' is defined twice", path.join(".")));
+                    } else {
+                        parent.insert(last.clone(), Value::Table(Table::new()));
+                    }
+                    current = path;
+                }
+                Some(_) => {
+                    let table = table_at(&mut root, &current)?;
+                    self.key_value(table)?;
+                }
+            }
+            self.end_of_line()?;
+        }
+    }
+
+    /// `key = value`, inserted into `table`
+    fn key_value(&mut self, table: &mut Table) -> Result<(), String> {
+        let path = self.key()?;
+        self.skip_spaces();
+        self.expect('=')?;
+        self.skip_spaces();
+        let value = self.value()?;
+        let (last, parents) = path.split_last().expect("keys have at least one part");
+        let table = table_at(table, parents)?;
+        if table.contains_key(last) {
+            return Err(format!("key '{}' is defined twice", path.join(".")));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+
+    /// A bare, quoted or dotted key
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_spaces();
+            let part = match self.peek() {
+                Some(quote @ ('"' | '\'')) => self.string(quote)?,
+                _ => {
+                    let start = self.pos;
+                    while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return Err("expected a key".to_string());
+                    }
+                    self.chars[start..self.pos].iter().collect()
+                }
+            };
+            parts.push(part);
+            self.skip_spaces();
+            if !self.eat('.') {
+                return Ok(parts);
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => Ok(Value::String(self.string(quote)?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(']') {
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    if !self.eat(',') {
+                        self.skip_whitespace();
+                        self.expect(']')?;
+                        return Ok(Value::Array(items));
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut table = Table::new();
+                self.skip_spaces();
+                if self.eat('}') {
+                    return Ok(Value::Table(table));
+                }
+                loop {
+                    self.key_value(&mut table)?;
+                    self.skip_spaces();
+                    if !self.eat(',') {
+                        self.expect('}')?;
+                        return Ok(Value::Table(table));
+                    }
+                }
+            }
+            Some(_) => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')) {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => parse_integer(&word).map(Value::Integer).ok_or_else(|| format!("invalid value '{}'", word)),
+                }
+            }
+            None => Err("expected a value".to_string()),
+        }
+    }
+
+    /// A string opened by `quote`; only basic (`"`) strings have escapes
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                None | Some('\n') => return Err("unterminated string".to_string()),
+                Some(c) if c == quote => return Ok(text),
+                Some('\\') if quote == '"' => text.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    other => return Err(format!("invalid escape '\\{}'", other.unwrap_or(' '))),
+                }),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        match self.peek() {
+            None | Some('\n') | Some('#') => Ok(()),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Spaces, comments and newlines
+    fn skip_whitespace(&mut self) {
+        loop {
+            self.skip_spaces();
+            if !self.eat('\n') {
+                break;
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", c))
+        }
+    }
+}
+
+/// The table at `path` below `table`, created if missing. A path through an
+/// array of tables continues in its last table, as TOML headers do.
+fn table_at<'a>(table: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let Some((first, rest)) = path.split_first() else {
+        return Ok(table);
+    };
+    let next = match table.entry(first.clone()).or_insert_with(|| Value::Table(Table::new())) {
+        Value::Table(next) => next,
+        Value::Array(items) => match items.last_mut() {
+            Some(Value::Table(next)) => next,
+            _ => return Err(format!("'{}' is not a table", first)),
+        },
+        _ => return Err(format!("'{}' is not a table", first)),
+    };
+    table_at(next, rest)
+}
+
+/// Decimal, `0x`, `0o` or `0b` integer with optional sign and `_` separators
+fn parse_integer(word: &str) -> Option<i64> {
+    let digits = word.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        _ => (10, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i64::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+// Typed access to the keys of a description file; errors name `context`,
+// the part of the file being read
+
+/// Reject misspelt keys rather than silently ignore them
+pub fn check_keys(table: &Table, known: &[&str], context: &str) -> Result<(), ResourceError> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(invalid(format!("{}: unknown key '{}'", context, key))),
+        None => Ok(()),
+    }
+}
+
+pub fn required<T>(value: Option<T>, key: &str, context: &str) -> Result<T, ResourceError> {
+    value.ok_or_else(|| invalid(format!("{}: missing '{}'", context, key)))
+}
+
+pub fn string(table: &Table, key: &str, context: &str) -> Result<Option<String>, ResourceError> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(other) => Err(invalid(format!("{}: '{}' must be a string, found {}", context, key, other.type_name()))),
+    }
+}
+
+pub fn integer(table: &Table, key: &str, context: &str, min: i64, max: i64) -> Result<Option<i64>, ResourceError> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Integer(i)) if (min..=max).contains(i) => Ok(Some(*i)),
+        Some(Value::Integer(i)) => Err(invalid(format!("{}: '{}' is {}, outside {}..{}", context, key, i, min, max))),
+        Some(other) => Err(invalid(format!("{}: '{}' must be an integer, found {}", context, key, other.type_name()))),
+    }
+}
+
+/// The tables of the array of tables `key`, e.g. `[[register]]`
+pub fn tables<'a>(table: &'a Table, key: &str, context: &str) -> Result<Vec<&'a Table>, ResourceError> {
+    let Some(value) = table.get(key) else {
+        return Ok(vec![]);
+    };
+    value
+        .as_array()
+        .and_then(|items| items.iter().map(Value::as_table).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| invalid(format!("{}: '{}' must be an array of tables", context, key)))
+}
+
+fn invalid(message: String) -> ResourceError {
+    ResourceError::InvalidFormat(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tables_and_values() {
+        let doc = parse(
+            "# Machine description
+            name = \"Zeal 8-bit\"   # trailing comment
+            base = 0x8000
+            mask = 0b1010_0000
+            flags = [1, -2,
+                     +3]
+            point = { x = 1, 'y' = 'raw\\n' }
+            io.ports = true
+
+            [memory]
+            rom = 16_384
+
+            [[register]]
+            name = \"Mode\"
+            [[register.field]]
+            name = \"Enable\"
+            [[register]]
+            name = \"Status\"
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(doc["name"].as_str(), Some("Zeal 8-bit"));
+        assert_eq!(doc["base"].as_integer(), Some(0x8000));
+        assert_eq!(doc["mask"].as_integer(), Some(0xA0));
+        assert_eq!(doc["flags"], Value::Array(vec![Value::Integer(1), Value::Integer(-2), Value::Integer(3)]));
+        assert_eq!(doc["point"].as_table().unwrap()["y"].as_str(), Some("raw\\n"));
+        assert_eq!(doc["io"].as_table().unwrap()["ports"].as_bool(), Some(true));
+        assert_eq!(doc["memory"].as_table().unwrap()["rom"].as_integer(), Some(16384));
+
+        let registers = doc["register"].as_array().unwrap();
+        assert_eq!(registers.len(), 2);
+        let mode = registers[0].as_table().unwrap();
+        assert_eq!(mode["field"].as_array().unwrap()[0].as_table().unwrap()["name"].as_str(), Some("Enable"));
+        assert!(!registers[1].as_table().unwrap().contains_key("field"));
+    }
+
+    #[test]
+    fn test_parse_errors_report_line() {
+        let message = |source: &str| parse(source).unwrap_err().to_string();
+        assert_eq!(message("a = 1\na = 2"), "Invalid resource: line 2: key 'a' is defined twice");
+        assert_eq!(message("a = 1.5"), "Invalid resource: line 1: unexpected '.'");
+        assert_eq!(message("\n\nname = \"open"), "Invalid resource: line 3: unterminated string");
+        assert_eq!(message("[t]\n[t]"), "Invalid resource: line 2: table 't' is defined twice");
+    }
+}
@@ -64,6 +64,7 @@ pub enum LanguageFeature {
     GarbageCollection,    // GC support
     Multithreading,       // Thread support
     DynamicLinking,       // Dynamic library loading
+    Timer,                // GetTicks and TicksPerSecond
 }
 
 /// Backend capabilities configuration
@@ -99,6 +100,7 @@ pub fn get_capabilities(platform: TargetPlatform) -> BackendCapabilities {
         TargetPlatform::FoenixA2560M => foenix_a2560m_capabilities(),
         TargetPlatform::Intel8051 => intel8051_capabilities(),
         TargetPlatform::RaspberryPi5 => raspberry_pi5_capabilities(),
+        TargetPlatform::ZXSpectrum => zxspectrum_capabilities(),
     }
 }
 
@@ -124,6 +126,7 @@ fn zealz80_capabilities() -> BackendCapabilities {
     
     // Object Pascal (limited)
     features.insert(LanguageFeature::Classes);
+    features.insert(LanguageFeature::Interfaces); // Interface tables reached through the VMT
     features.insert(LanguageFeature::Properties);
     features.insert(LanguageFeature::MethodPointers);
     
@@ -133,20 +136,23 @@ fn zealz80_capabilities() -> BackendCapabilities {
     features.insert(LanguageFeature::GotoLabels);
     features.insert(LanguageFeature::InlineAssembly);
     features.insert(LanguageFeature::ForInLoops);
+    features.insert(LanguageFeature::ExceptionHandling); // Handler frames unwound by the raise routine
     
     // Advanced declarations
     features.insert(LanguageFeature::ForwardExternal);
     features.insert(LanguageFeature::Absolute);
+    features.insert(LanguageFeature::ThreadVar); // Per-task blocks of the cooperative scheduler
+    
+    // Runtime features
+    features.insert(LanguageFeature::Timer); // Zeal OS millisecond clock, Spectrum FRAMES
     
     // NOT SUPPORTED:
     // - DynamicArrays (no heap management)
     // - ProceduralTypes (complex for 8-bit)
-    // - Interfaces (too complex)
     // - OperatorOverloading (performance)
     // - Generics (too complex)
     // - AnonymousFunctions (too complex)
-    // - ExceptionHandling (no runtime support)
-    // - ThreadVar, ConstRef, OutParams, Resourcestring, DefaultParams
+    // - ConstRef, OutParams, Resourcestring, DefaultParams
     // - ClassMethods, ClassProperties, ClassVariables, ClassHelpers, NestedClasses
     // - ReferenceCounting, GarbageCollection, Multithreading, DynamicLinking
     
@@ -158,6 +164,17 @@ fn zealz80_capabilities() -> BackendCapabilities {
     }
 }
 
+/// ZXSpectrum (Z80 @ 3.5 MHz) - Retro 8-bit platform
+/// Same CPU as ZealZ80, so the same language subset applies
+fn zxspectrum_capabilities() -> BackendCapabilities {
+    BackendCapabilities {
+        platform: TargetPlatform::ZXSpectrum,
+        name: "ZXSpectrum".to_string(),
+        description: "Zilog Z80 @ 3.5 MHz - 48K/128K home computer with memory-mapped screen".to_string(),
+        ..zealz80_capabilities()
+    }
+}
+
 /// CommanderX16 (65C02 @ 8 MHz) - Retro 8-bit platform
 /// Similar to ZealZ80 but 6502-compatible
 fn commanderx16_capabilities() -> BackendCapabilities {
@@ -426,9 +443,17 @@ mod tests {
         assert!(caps.supports(LanguageFeature::Classes));
         assert!(!caps.supports(LanguageFeature::DynamicArrays));
         assert!(!caps.supports(LanguageFeature::Generics));
-        assert!(!caps.supports(LanguageFeature::ExceptionHandling));
+        assert!(caps.supports(LanguageFeature::ExceptionHandling));
+        assert!(caps.supports(LanguageFeature::Timer));
     }
     
+    #[test]
+    fn test_zxspectrum_capabilities() {
+        let caps = get_capabilities(TargetPlatform::ZXSpectrum);
+        assert_eq!(caps.platform, TargetPlatform::ZXSpectrum);
+        assert_eq!(caps.features, zealz80_capabilities().features);
+    }
+
     #[test]
     fn test_raspberry_pi5_capabilities() {
         let caps = raspberry_pi5_capabilities();
@@ -446,12 +471,12 @@ mod tests {
         let requested = vec![
             LanguageFeature::BasicTypes,
             LanguageFeature::Generics,
-            LanguageFeature::ExceptionHandling,
+            LanguageFeature::DynamicArrays,
         ];
         let unsupported = caps.unsupported(&requested);
         assert_eq!(unsupported.len(), 2);
         assert!(unsupported.contains(&LanguageFeature::Generics));
-        assert!(unsupported.contains(&LanguageFeature::ExceptionHandling));
+        assert!(unsupported.contains(&LanguageFeature::DynamicArrays));
     }
 }
 
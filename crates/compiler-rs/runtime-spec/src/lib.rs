@@ -21,6 +21,99 @@ pub enum TargetPlatform {
     FoenixA2560M,
     /// RaspberryPi5 - ARM Cortex-A76 @ 2.4 GHz
     RaspberryPi5,
+    /// ZXSpectrum - Zilog Z80 @ 3.5 MHz
+    ZXSpectrum,
+}
+
+impl TargetPlatform {
+    /// All supported target platforms
+    pub const ALL: [TargetPlatform; 7] = [
+        TargetPlatform::ZealZ80,
+        TargetPlatform::Intel8051,
+        TargetPlatform::CommanderX16,
+        TargetPlatform::Foenix65C816,
+        TargetPlatform::FoenixA2560M,
+        TargetPlatform::RaspberryPi5,
+        TargetPlatform::ZXSpectrum,
+    ];
+
+    /// Command-line name of the platform (as accepted by `--target`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            TargetPlatform::ZealZ80 => "zealz80",
+            TargetPlatform::Intel8051 => "intel8051",
+            TargetPlatform::CommanderX16 => "commanderx16",
+            TargetPlatform::Foenix65C816 => "foenix65c816",
+            TargetPlatform::FoenixA2560M => "foenixa2560m",
+            TargetPlatform::RaspberryPi5 => "raspberrypi5",
+            TargetPlatform::ZXSpectrum => "zxspectrum",
+        }
+    }
+
+    /// Look up a platform by its command-line name (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|platform| platform.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Kind of memory in a target's memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Read-only at run time (program code and constants)
+    Rom,
+    /// Read-write
+    Ram,
+}
+
+/// Memory region available to programs
+///
+/// Memory maps are part of a target's definition file, which the driver
+/// reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub kind: MemoryKind,
+    pub start: u32,
+    pub size: u32,
+}
+
+impl MemoryRegion {
+    pub fn new(name: impl Into<String>, kind: MemoryKind, start: u32, size: u32) -> Self {
+        Self { name: name.into(), kind, start, size }
+    }
+}
+
+/// What happens on a runtime error (range, overflow, nil pointer, failed
+/// assertion)
+///
+/// Chosen by a target's definition file and overridden by the
+/// `{$RUNTIMEERRORS}` directive.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RuntimeErrorStrategy {
+    /// Display the error code and stop the program
+    #[default]
+    Halt,
+    /// Call the named `procedure(Code: Byte)`; if it returns, the program
+    /// continues
+    Handler(String),
+    /// Record the error code for `ErrorCode` and continue
+    Code,
+}
+
+impl RuntimeErrorStrategy {
+    /// Read `halt`, `code` or `handler Name` (case-insensitive)
+    pub fn parse(text: &str) -> Option<Self> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            [word] if word.eq_ignore_ascii_case("halt") => Some(Self::Halt),
+            [word] if word.eq_ignore_ascii_case("code") => Some(Self::Code),
+            [word, name] if word.eq_ignore_ascii_case("handler") => Some(Self::Handler(name.to_string())),
+            _ => None,
+        }
+    }
 }
 
 /// Represents a calling convention
@@ -236,6 +329,13 @@ pub fn zealz80_abi() -> ABI {
     abi
 }
 
+/// Get ABI specification for ZX Spectrum (same Z80 conventions as ZealZ80)
+pub fn zxspectrum_abi() -> ABI {
+    let mut abi = zealz80_abi();
+    abi.platform = TargetPlatform::ZXSpectrum;
+    abi
+}
+
 /// Get ABI specification for Intel8051 (based on Turbo51)
 pub fn intel8051_abi() -> ABI {
     let mut abi = ABI::new(TargetPlatform::Intel8051);
@@ -282,6 +382,7 @@ pub fn commanderx16_abi() -> ABI {
 pub fn get_abi(platform: TargetPlatform) -> ABI {
     match platform {
         TargetPlatform::ZealZ80 => zealz80_abi(),
+        TargetPlatform::ZXSpectrum => zxspectrum_abi(),
         TargetPlatform::Intel8051 => intel8051_abi(),
         TargetPlatform::CommanderX16 => commanderx16_abi(),
         TargetPlatform::Foenix65C816 => {
@@ -309,6 +410,27 @@ mod tests {
         assert_ne!(TargetPlatform::ZealZ80, TargetPlatform::Intel8051);
     }
 
+    #[test]
+    fn test_target_platform_names() {
+        for platform in TargetPlatform::ALL {
+            assert_eq!(TargetPlatform::from_name(platform.name()), Some(platform));
+        }
+        assert_eq!(TargetPlatform::from_name("ZXSpectrum"), Some(TargetPlatform::ZXSpectrum));
+        assert_eq!(TargetPlatform::from_name("c64"), None);
+    }
+
+    #[test]
+    fn test_runtime_error_strategy_names() {
+        assert_eq!(RuntimeErrorStrategy::parse("HALT"), Some(RuntimeErrorStrategy::Halt));
+        assert_eq!(RuntimeErrorStrategy::parse("code"), Some(RuntimeErrorStrategy::Code));
+        assert_eq!(
+            RuntimeErrorStrategy::parse("Handler  OnError"),
+            Some(RuntimeErrorStrategy::Handler("OnError".to_string()))
+        );
+        assert_eq!(RuntimeErrorStrategy::parse("handler"), None);
+        assert_eq!(RuntimeErrorStrategy::parse("ignore"), None);
+    }
+
     #[test]
     fn test_calling_convention() {
         assert_eq!(CallingConvention::Pascal, CallingConvention::Pascal);
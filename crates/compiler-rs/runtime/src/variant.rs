@@ -22,6 +22,9 @@ pub enum VariantType {
     Array = 7,
     Record = 8,
     Pointer = 9,
+    Real = 10,
+    LongInt = 11,
+    Cardinal = 12,
 }
 
 impl VariantType {
@@ -34,6 +37,9 @@ impl VariantType {
                 types::PrimitiveType::Char => VariantType::Char,
                 types::PrimitiveType::Byte => VariantType::Byte,
                 types::PrimitiveType::Word => VariantType::Word,
+                types::PrimitiveType::Real => VariantType::Real,
+                types::PrimitiveType::LongInt => VariantType::LongInt,
+                types::PrimitiveType::Cardinal => VariantType::Cardinal,
             },
             Type::Array { .. } => VariantType::Array,
             Type::DynamicArray { .. } => VariantType::Array,
@@ -57,6 +63,9 @@ impl VariantType {
             VariantType::Array => 2,    // Pointer to array
             VariantType::Record => 2,   // Pointer to record
             VariantType::Pointer => 2,  // Pointer value
+            VariantType::Real => 4,     // Single precision
+            VariantType::LongInt => 4,  // 32-bit integer
+            VariantType::Cardinal => 4,
         }
     }
 }
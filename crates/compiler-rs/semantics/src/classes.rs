@@ -0,0 +1,360 @@
+//! Classes: instance layout, virtual method tables and method calls
+//!
+//! A class type is a reference to an instance laid out by its
+//! [`ClassLayout`]. `virtual` methods get a VMT slot, `override` replaces
+//! the entry of an inherited one, and other methods are static. Method
+//! bodies (`procedure TShape.Draw`) see `Self` and the fields of the class.
+//!
+//! A constructor is called on the class, `TShape.Create(1)`, and returns a
+//! new instance; called on an instance it only runs its body again. A
+//! destructor takes no parameters, and calling it frees the instance.
+//! An instance converts to any class its class derives from.
+
+use ast::Node;
+use ::types::{ClassLayout, ClassMethod, Field, MethodKind, Type, SELF_PARAMETER};
+use symbols::{Parameter, Symbol, SymbolKind};
+use crate::SemanticAnalyzer;
+use crate::core;
+
+impl SemanticAnalyzer {
+    /// Layouts of the classes declared so far
+    pub fn class_layouts(&self) -> &[ClassLayout] {
+        &self.class_layouts
+    }
+
+    pub(crate) fn class_layout(&self, name: &str) -> Option<&ClassLayout> {
+        self.class_layouts.iter().find(|layout| layout.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether a `value` instance converts to the class `target`: its class
+    /// derives from it
+    pub(crate) fn converts_to_ancestor(&self, value: &Type, target: &Type) -> bool {
+        let (Type::Class { name: class }, Type::Class { name: ancestor }) = (value, target) else {
+            return false;
+        };
+        let mut layout = self.class_layout(class);
+        while let Some(parent) = layout.and_then(|layout| layout.parent.as_deref()) {
+            if parent.eq_ignore_ascii_case(ancestor) {
+                return true;
+            }
+            layout = self.class_layout(parent);
+        }
+        false
+    }
+
+    /// Declare the class `name` and lay it out. The type is declared first
+    /// so fields and methods can refer to it; a forward `class;` declares
+    /// only the type.
+    pub(crate) fn analyze_class_decl(&mut self, decl: &ast::TypeDecl, class: &ast::ClassType) {
+        let completes_forward = self.core.symbol_table.lookup(&decl.name).is_some_and(|symbol| {
+            matches!(&symbol.kind, SymbolKind::TypeAlias { aliased_type: Type::Class { name }, .. } if *name == decl.name)
+        }) && self.class_layout(&decl.name).is_none();
+        if !completes_forward {
+            if self.core.symbol_table.exists_in_current_scope(&decl.name) {
+                self.add_redeclaration_error(format!("Type '{}' already declared", decl.name), &decl.name, decl.span);
+                return;
+            }
+            let symbol = Symbol {
+                kind: SymbolKind::TypeAlias {
+                    name: decl.name.clone(),
+                    aliased_type: Type::Class { name: decl.name.clone() },
+                    span: decl.span,
+                },
+                scope_level: self.core.symbol_table.scope_level(),
+            };
+            if let Err(e) = self.core.symbol_table.insert(symbol) {
+                self.core.add_error(e, decl.span);
+            }
+        }
+        if class.is_forward_decl {
+            return;
+        }
+
+        // The parent class comes first; the other bases are interfaces
+        let (parent_name, interfaces) = match class.base_classes.split_first() {
+            Some((first, rest)) if self.interface_layout(first).is_none() => (Some(first), rest),
+            _ => (None, class.base_classes.as_slice()),
+        };
+        let parent = match parent_name {
+            Some(base) => match self.class_layout(base) {
+                Some(parent) => Some(parent.clone()),
+                None => {
+                    self.core.add_error(format!("Base class '{}' is not a class", base), class.span);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut fields: Vec<Field> = Vec::new();
+        for (_, member) in &class.members {
+            let ast::ClassMember::Field(Node::VarDecl(var)) = member else {
+                continue;
+            };
+            let field_type = self.analyze_type(&var.type_expr);
+            for name in &var.names {
+                let inherited = parent.as_ref().is_some_and(|p| p.field(name).is_some());
+                if inherited || fields.iter().any(|f| f.name.eq_ignore_ascii_case(name)) {
+                    self.core.add_error(format!("Field '{}' already declared in class '{}'", name, decl.name), var.span);
+                    continue;
+                }
+                fields.push(Field { name: name.clone(), field_type: Box::new(field_type.clone()), offset: None });
+            }
+        }
+        let mut layout = ClassLayout::new(&decl.name, parent.as_ref(), fields);
+
+        let mut declared: Vec<String> = Vec::new();
+        for (_, member) in &class.members {
+            let (kind, method) = match member {
+                ast::ClassMember::Method(method) => (MethodKind::Method, method),
+                ast::ClassMember::Constructor(method) => (MethodKind::Constructor, method),
+                ast::ClassMember::Destructor(method) => (MethodKind::Destructor, method),
+                _ => continue,
+            };
+            let (name, params, return_type, binding, span) = match method {
+                Node::ProcDecl(p) => (&p.name, self.analyze_params(&p.params), None, p.binding, p.span),
+                Node::FuncDecl(f) => {
+                    let params = self.analyze_params(&f.params);
+                    (&f.name, params, Some(self.analyze_type(&f.return_type)), f.binding, f.span)
+                }
+                _ => continue,
+            };
+            if declared.iter().any(|d| d.eq_ignore_ascii_case(name)) {
+                self.core.add_error(format!("Method '{}' already declared in class '{}'", name, decl.name), span);
+                continue;
+            }
+            declared.push(name.clone());
+            if kind == MethodKind::Destructor && !params.is_empty() {
+                self.core.add_error(format!("Destructor '{}' cannot take parameters", name), span);
+            }
+
+            let inherited_slot = layout.method(name).and_then(|m| m.slot);
+            let slot = match binding {
+                ast::MethodBinding::Static => None,
+                ast::MethodBinding::Virtual => {
+                    if inherited_slot.is_some() {
+                        self.core.add_error(
+                            format!("Method '{}' is already virtual; use override to replace it", name),
+                            span,
+                        );
+                        continue;
+                    }
+                    Some(layout.vmt.len())
+                }
+                ast::MethodBinding::Override => {
+                    if inherited_slot.is_none() {
+                        self.core.add_error(
+                            format!("Method '{}' has no inherited virtual method to override", name),
+                            span,
+                        );
+                        continue;
+                    }
+                    inherited_slot
+                }
+            };
+            let symbol = ::types::method_symbol(&decl.name, name);
+            layout.declare_method(ClassMethod {
+                name: name.clone(),
+                kind,
+                owner: decl.name.clone(),
+                slot,
+                param_count: params.len(),
+                return_type,
+            });
+            self.method_params.insert(symbol.to_ascii_lowercase(), params);
+        }
+        self.implement_interfaces(&mut layout, interfaces, class.span);
+        self.class_layouts.push(layout);
+    }
+
+    /// Analyze the body of a method, `procedure TShape.Draw`, with `Self`
+    /// and the fields of the class in scope
+    pub(crate) fn analyze_method_body(&mut self, class_name: &str, decl: &Node) {
+        let (name, block, span) = match decl {
+            Node::ProcDecl(p) => (&p.name, &p.block, p.span),
+            Node::FuncDecl(f) => (&f.name, &f.block, f.span),
+            _ => return,
+        };
+        let Some(layout) = self.class_layout(class_name).cloned() else {
+            self.core.add_error(format!("Unknown class '{}'", class_name), span);
+            return;
+        };
+        let Some(method) = layout.method(name).filter(|m| m.owner.eq_ignore_ascii_case(&layout.name)).cloned() else {
+            self.core.add_error(format!("Method '{}' is not declared in class '{}'", name, layout.name), span);
+            return;
+        };
+        let params = self.method_params(&method);
+
+        self.core.symbol_table.enter_scope();
+        let self_type = Type::Class { name: layout.name.clone() };
+        // The first declaration of a name wins, so parameters shadow fields
+        let variables = params
+            .iter()
+            .map(|param| (param.name.clone(), param.param_type.clone()))
+            .chain(std::iter::once((SELF_PARAMETER.to_string(), self_type)))
+            .chain(layout.fields.iter().map(|field| (field.name.clone(), field.field_type.as_ref().clone())));
+        for (name, var_type) in variables.collect::<Vec<_>>() {
+            let symbol = Symbol {
+                kind: SymbolKind::Variable { name, var_type, span },
+                scope_level: self.core.symbol_table.scope_level(),
+            };
+            let _ = self.core.symbol_table.insert(symbol);
+        }
+        if let Some(return_type) = &method.return_type {
+            self.declare_result(return_type, span);
+            self.functions.push((method.name.clone(), return_type.clone()));
+        }
+        self.analyze_block(block);
+        if method.kind == MethodKind::Constructor {
+            self.check_fields_initialized(&layout, &method.name, &params, block, span);
+        }
+        if method.return_type.is_some() {
+            self.functions.pop();
+        }
+        self.core.symbol_table.exit_scope();
+    }
+
+    /// Warn when some path through the constructor `name` of `layout` leaves
+    /// a field the class declares unassigned. A field shadowed by one of the
+    /// `params` can only be assigned through `Self`.
+    fn check_fields_initialized(&mut self, layout: &ClassLayout, name: &str, params: &[Parameter], block: &Node, span: tokens::Span) {
+        let parent_size = layout.parent.as_deref().and_then(|parent| self.class_layout(parent)).map(|parent| parent.fields.len());
+        let own_fields = &layout.fields[parent_size.unwrap_or(0)..];
+        let uninitialized: Vec<&str> = own_fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .filter(|field| {
+                let shadowed = params.iter().any(|param| param.name.eq_ignore_ascii_case(field));
+                let is_field = |target: &Node| match target {
+                    Node::IdentExpr(i) => !shadowed && i.name.eq_ignore_ascii_case(field),
+                    Node::FieldExpr(f) => {
+                        f.field.eq_ignore_ascii_case(field)
+                            && matches!(f.record.as_ref(), Node::IdentExpr(i) if i.name.eq_ignore_ascii_case(SELF_PARAMETER))
+                    }
+                    _ => false,
+                };
+                let mut escapes = false;
+                !Self::definitely_assigns(block, &is_field, false, &mut escapes) || escapes
+            })
+            .collect();
+        if !uninitialized.is_empty() {
+            self.add_key_warning(
+                format!(
+                    "Constructor '{}' of class '{}' might not initialize {}",
+                    name,
+                    layout.name,
+                    uninitialized.iter().map(|field| format!("'{}'", field)).collect::<Vec<_>>().join(", ")
+                ),
+                span,
+            );
+        }
+    }
+
+    /// The class `node` names, if it is the name of a class type
+    pub(crate) fn class_type_name(&self, node: &Node) -> Option<String> {
+        let Node::IdentExpr(ident) = node else {
+            return None;
+        };
+        match &self.core.symbol_table.lookup(&ident.name)?.kind {
+            SymbolKind::TypeAlias { aliased_type: Type::Class { name }, .. } => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Analyze `Class.method(args)`, which must call a constructor, and
+    /// return the type of the new instance
+    pub(crate) fn analyze_constructor_call(&mut self, class_name: &str, method: &str, args: &[Node], span: tokens::Span) -> Option<Type> {
+        let Some(method) = self.class_layout(class_name).and_then(|layout| layout.method(method)).cloned() else {
+            self.core.add_error(format!("Class '{}' has no method '{}'", class_name, method), span);
+            return None;
+        };
+        if method.kind != MethodKind::Constructor {
+            self.core.add_error(
+                format!("Method '{}' is not a constructor; call it on an instance of '{}'", method.name, class_name),
+                span,
+            );
+            return None;
+        }
+        self.check_method_arguments(&method, args, span)?;
+        Some(Type::Class { name: class_name.to_string() })
+    }
+
+    /// Analyze a method call and return its method's result type, if any
+    pub(crate) fn analyze_method_call(&mut self, call: &ast::MethodCall) -> Option<Type> {
+        if let Some(class_name) = self.class_type_name(&call.object) {
+            return self.analyze_constructor_call(&class_name, &call.method, &call.args, call.span);
+        }
+        let object_type = self.analyze_expression(&call.object);
+        if let Type::Interface { name } = &object_type {
+            return self.analyze_interface_call(name, call);
+        }
+        let Type::Class { name: class_name } = &object_type else {
+            if object_type != Type::Error {
+                self.core.add_error(
+                    format!("Method call requires a class instance or interface, found {}", core::CoreAnalyzer::format_type(&object_type)),
+                    call.span,
+                );
+            }
+            return None;
+        };
+        let Some(method) = self.class_layout(class_name).and_then(|layout| layout.method(&call.method)).cloned() else {
+            self.core.add_error(format!("Class '{}' has no method '{}'", class_name, call.method), call.span);
+            return None;
+        };
+        self.check_method_arguments(&method, &call.args, call.span)?;
+        method.return_type
+    }
+
+    /// Check the arguments of a call of `method`; None if their number is wrong
+    pub(crate) fn check_method_arguments(&mut self, method: &ClassMethod, args: &[Node], span: tokens::Span) -> Option<()> {
+        let params = self.method_params(method);
+        if args.len() != params.len() {
+            self.core.add_error(
+                format!("Method '{}' expects {} arguments, found {}", method.name, params.len(), args.len()),
+                span,
+            );
+            return None;
+        }
+        for (arg, param) in args.iter().zip(params.iter()) {
+            self.check_argument(arg, param);
+        }
+        Some(())
+    }
+
+    /// Parameters of `method`, not counting Self
+    pub(crate) fn method_params(&self, method: &ClassMethod) -> Vec<Parameter> {
+        let symbol = ::types::method_symbol(&method.owner, &method.name).to_ascii_lowercase();
+        self.method_params.get(&symbol).cloned().unwrap_or_default()
+    }
+
+    /// Type of `object.name` on an instance of `class_name`: a field, or a
+    /// call of a function method without arguments
+    pub(crate) fn analyze_class_member(&mut self, class_name: &str, field: &ast::FieldExpr) -> Type {
+        let Some(layout) = self.class_layout(class_name) else {
+            self.core.add_error(format!("Class '{}' is declared but never defined", class_name), field.span);
+            return Type::Error;
+        };
+        if let Some(f) = layout.field(&field.field) {
+            return f.field_type.as_ref().clone();
+        }
+        match layout.method(&field.field) {
+            Some(ClassMethod { return_type: Some(return_type), param_count: 0, .. }) => return_type.clone(),
+            _ => {
+                self.core.add_error(format!("Class '{}' has no field '{}'", class_name, field.field), field.span);
+                Type::Error
+            }
+        }
+    }
+
+    /// Type of the assignable field `object.name` on an instance of
+    /// `class_name`
+    pub(crate) fn analyze_class_field(&mut self, class_name: &str, field: &ast::FieldExpr) -> Type {
+        match self.class_layout(class_name).and_then(|layout| layout.field(&field.field)) {
+            Some(f) => f.field_type.as_ref().clone(),
+            None => {
+                self.core.add_error(format!("Class '{}' has no field '{}'", class_name, field.field), field.span);
+                Type::Error
+            }
+        }
+    }
+}
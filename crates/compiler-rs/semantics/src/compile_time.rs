@@ -0,0 +1,693 @@
+//! Compile-time evaluation of `{$COMPILETIME}` functions
+//!
+//! A function marked `{$COMPILETIME}` may only compute with its value
+//! parameters, local variables and constants, and call other compile-time
+//! functions. A call whose arguments are all constant is then run here while
+//! compiling, and its result is a constant like any other, e.g. an entry of a
+//! lookup table. Steps and nested calls are limited so the build terminates.
+//!
+//! `GenerateTable(F, Low, High)` runs a function of one parameter that
+//! follows the same rules, marked or not, for each value from `Low` to
+//! `High`, and gives the results as a constant array stored as data.
+
+use std::collections::HashMap;
+
+use ast::Node;
+use symbols::{ConstantValue, Parameter, ParameterMode, SymbolKind};
+use ::types::{PrimitiveType, Type};
+
+use crate::SemanticAnalyzer;
+
+/// Statements and loop iterations one evaluation may run, counting those of
+/// the compile-time functions it calls
+pub const COMPILE_TIME_STEP_LIMIT: usize = 100_000;
+/// Compile-time calls that may be active at once, e.g. recursion depth
+pub const COMPILE_TIME_CALL_LIMIT: usize = 64;
+
+/// A `{$COMPILETIME}` function with the types of its parameters, local
+/// variables and result
+#[derive(Debug, Clone)]
+pub(crate) struct CompileTimeFunction {
+    decl: ast::FuncDecl,
+    params: Vec<(String, Type)>,
+    locals: Vec<(String, Type)>,
+    return_type: Type,
+}
+
+impl SemanticAnalyzer {
+    /// The compile-time function `name` calls here, if it calls one
+    pub(crate) fn compile_time_function(&self, name: &str) -> Option<&CompileTimeFunction> {
+        match self.core.symbol_table.lookup(name)?.kind {
+            SymbolKind::Function { .. } => self.compile_time_functions.get(&name.to_lowercase()),
+            _ => None,
+        }
+    }
+
+    /// Run a call to a compile-time function whose arguments are constant.
+    /// None when it is not such a call; an error says why evaluation stopped.
+    pub(crate) fn evaluate_compile_time_call(&self, call: &ast::CallExpr) -> Option<Result<ConstantValue, String>> {
+        let function = self.compile_time_function(&call.name)?;
+        let args = call
+            .args
+            .iter()
+            .map(|arg| self.evaluate_constant_expression(arg))
+            .collect::<Option<Vec<_>>>()?;
+        if args.len() != function.params.len() {
+            return None;
+        }
+        Some(Interpreter { analyzer: self, steps: 0, calls: 0 }.call(function, args))
+    }
+
+    /// Report a failed evaluation of a compile-time call
+    pub(crate) fn check_compile_time_call(&mut self, call: &ast::CallExpr) {
+        if let Some(Err(message)) = self.evaluate_compile_time_call(call) {
+            self.core.add_error(
+                format!("Cannot evaluate '{}' at compile time: {}", call.name, message),
+                call.span,
+            );
+        }
+    }
+
+    /// Check that the body of a `{$COMPILETIME}` function only computes
+    /// with its parameters, locals and constants, and record the function.
+    /// Called with the function's scope still entered.
+    pub(crate) fn declare_compile_time_function(&mut self, f: &ast::FuncDecl, params: &[Parameter], return_type: &Type) {
+        if let Some(function) = self.check_compile_time_function(f, params, return_type) {
+            self.compile_time_functions.insert(f.name.to_lowercase(), function);
+        }
+    }
+
+    /// Record whether an unmarked function of one parameter follows the
+    /// rules of compile-time functions, so `GenerateTable` can run it, and
+    /// if not the first rule it breaks. Nothing is reported here.
+    pub(crate) fn declare_table_function(&mut self, f: &ast::FuncDecl, params: &[Parameter], return_type: &Type) {
+        let diagnostics_before = self.core.diagnostics.len();
+        let function = self.check_compile_time_function(f, params, return_type);
+        let prefix = format!("Compile-time function '{}' ", f.name);
+        let problem = self.core.diagnostics.drain(diagnostics_before..).next().map(|diagnostic| {
+            diagnostic.message.strip_prefix(&prefix).unwrap_or(&diagnostic.message).to_string()
+        });
+        self.table_functions.insert(f.name.to_lowercase(), function.ok_or(problem.unwrap_or_default()));
+    }
+
+    /// The compile-time function `f` if it follows the rules; the rules it
+    /// breaks are reported
+    fn check_compile_time_function(
+        &mut self,
+        f: &ast::FuncDecl,
+        params: &[Parameter],
+        return_type: &Type,
+    ) -> Option<CompileTimeFunction> {
+        let diagnostics_before = self.core.diagnostics.len();
+        let is_value = |ty: &Type| ty.is_ordinal() || *ty.representation() == Type::real();
+        for param in params {
+            if param.passing_mode == ParameterMode::Var {
+                self.compile_time_error(f, format!("cannot have var parameter '{}'", param.name), param.span);
+            } else if !is_value(&param.param_type) {
+                self.compile_time_error(f, format!("parameter '{}' must be ordinal or real", param.name), param.span);
+            }
+        }
+        if !is_value(return_type) {
+            self.compile_time_error(f, "must return an ordinal or real type".to_string(), f.span);
+        }
+
+        let mut locals = vec![];
+        if let Node::Block(block) = f.block.as_ref() {
+            if !block.proc_decls.is_empty() || !block.func_decls.is_empty() {
+                self.compile_time_error(f, "cannot declare nested routines".to_string(), block.span);
+            }
+            for name in block.var_decls.iter().filter_map(|decl| match decl {
+                Node::VarDecl(v) => Some(&v.names),
+                _ => None,
+            }).flatten() {
+                let var_type = match self.core.symbol_table.lookup(name).map(|symbol| &symbol.kind) {
+                    Some(SymbolKind::Variable { var_type, .. }) => var_type.clone(),
+                    _ => Type::Error,
+                };
+                if !is_value(&var_type) {
+                    self.compile_time_error(f, format!("local variable '{}' must be ordinal or real", name), f.span);
+                }
+                locals.push((name.clone(), var_type));
+            }
+            for statement in &block.statements {
+                self.check_compile_time_node(f, statement);
+            }
+        }
+
+        (self.core.diagnostics.len() == diagnostics_before).then(|| CompileTimeFunction {
+            decl: f.clone(),
+            params: params.iter().map(|p| (p.name.clone(), p.param_type.clone())).collect(),
+            locals,
+            return_type: return_type.clone(),
+        })
+    }
+
+    /// The function `GenerateTable` is asked to run: a compile-time function
+    /// or an unmarked one following the rules, else why it cannot be run
+    fn table_function(&self, name: &str) -> Result<&CompileTimeFunction, String> {
+        if let Some(function) = self.compile_time_function(name) {
+            return Ok(function);
+        }
+        match self.core.symbol_table.lookup(name).map(|symbol| &symbol.kind) {
+            Some(SymbolKind::Function { params, .. }) if params.len() != 1 => {
+                Err(format!("it has {} parameters instead of 1", params.len()))
+            }
+            Some(SymbolKind::Function { .. }) => match self.table_functions.get(&name.to_lowercase()) {
+                Some(Ok(function)) => Ok(function),
+                Some(Err(problem)) => Err(format!("it {}", problem)),
+                None => Err("its body is not available".to_string()),
+            },
+            Some(_) => Err("it is not a function".to_string()),
+            None => Err("it is not declared".to_string()),
+        }
+    }
+
+    /// Run `GenerateTable(F, Low, High)`: the type of the table, an array of
+    /// F's results indexed by Low..High, and its value, the results stored
+    /// one after another as they are in memory
+    pub(crate) fn evaluate_generate_table(&self, call: &ast::CallExpr) -> Result<(Type, ConstantValue), String> {
+        let [function, low, high] = call.args.as_slice() else {
+            return Err(format!("expects 3 arguments, found {}", call.args.len()));
+        };
+        let Node::IdentExpr(function) = function else {
+            return Err("the first argument must name a function".to_string());
+        };
+        let table_function = self
+            .table_function(&function.name)
+            .map_err(|problem| format!("cannot run '{}' at compile time: {}", function.name, problem))?;
+        let bound = |expr: &Node| {
+            self.evaluate_constant_expression(expr)
+                .as_ref()
+                .and_then(Self::constant_ordinal)
+                .ok_or_else(|| "the bounds must be ordinal constants".to_string())
+        };
+        let (low, high) = (bound(low)?, bound(high)?);
+        if low > high {
+            return Err(format!("the lower bound {} is greater than the upper bound {}", low, high));
+        }
+        let (_, param_type) = &table_function.params[0];
+        if !param_type.is_ordinal() {
+            return Err(format!("the parameter of '{}' must be ordinal", function.name));
+        }
+        let mut interpreter = Interpreter { analyzer: self, steps: 0, calls: 0 };
+        let mut data = vec![];
+        for index in low..=high {
+            let value = ordinal_value(param_type, index)
+                .and_then(|argument| interpreter.call(table_function, vec![argument]))
+                .map_err(|message| format!("{}({}): {}", function.name, index, message))?;
+            data.extend(stored_bytes(&value));
+            interpreter.steps = 0;
+        }
+        // Integer tables are indexed like `array[Low..High]`
+        let index_base = match param_type.representation() {
+            Type::Primitive(
+                PrimitiveType::Integer
+                | PrimitiveType::Byte
+                | PrimitiveType::Word
+                | PrimitiveType::LongInt
+                | PrimitiveType::Cardinal,
+            ) => {
+                if high <= i16::MAX as i64 && low >= i16::MIN as i64 {
+                    Type::integer()
+                } else if low >= 0 && high <= u16::MAX as i64 {
+                    Type::word()
+                } else {
+                    Type::longint()
+                }
+            }
+            _ => param_type.subrange_base().clone(),
+        };
+        let mut table = Type::array(Type::subrange(index_base, low, high), table_function.return_type.clone());
+        table.calculate_array_size();
+        Ok((table, ConstantValue::Bytes(data)))
+    }
+
+    /// Analyze `GenerateTable(F, Low, High)`, which must be constant
+    pub(crate) fn analyze_generate_table(&mut self, call: &ast::CallExpr) -> Type {
+        for arg in call.args.iter().skip(1) {
+            if self.analyze_expression(arg) == Type::Error {
+                return Type::Error;
+            }
+        }
+        match self.evaluate_generate_table(call) {
+            Ok((table, _)) => table,
+            Err(message) => {
+                self.core.add_error(format!("{}: {}", crate::GENERATE_TABLE_INTRINSIC, message), call.span);
+                Type::Error
+            }
+        }
+    }
+
+    fn compile_time_error(&mut self, f: &ast::FuncDecl, message: String, span: tokens::Span) {
+        self.core.add_error(format!("Compile-time function '{}' {}", f.name, message), span);
+    }
+
+    /// Report statements and names a compile-time function may not use
+    fn check_compile_time_node(&mut self, f: &ast::FuncDecl, node: &Node) {
+        match node {
+            Node::IdentExpr(ident) => self.check_compile_time_name(f, &ident.name, ident.span),
+            Node::CallExpr(call) => {
+                self.check_compile_time_name(f, &call.name, call.span);
+                for arg in &call.args {
+                    self.check_compile_time_node(f, arg);
+                }
+            }
+            Node::CallStmt(call) => {
+                self.check_compile_time_name(f, &call.name, call.span);
+                for arg in &call.args {
+                    self.check_compile_time_node(f, arg);
+                }
+            }
+            Node::AssignStmt(assign) => {
+                match assign.target.as_ref() {
+                    Node::IdentExpr(target) if target.name.eq_ignore_ascii_case(&f.name) => {}
+                    Node::IdentExpr(target) => self.check_compile_time_name(f, &target.name, target.span),
+                    other => self.compile_time_error(f, "can only assign whole local variables".to_string(), other.span()),
+                }
+                self.check_compile_time_node(f, &assign.value);
+            }
+            Node::ForStmt(for_stmt) => {
+                self.check_compile_time_name(f, &for_stmt.var_name, for_stmt.span);
+                self.check_compile_time_node(f, &for_stmt.start_expr);
+                self.check_compile_time_node(f, &for_stmt.end_expr);
+                self.check_compile_time_node(f, &for_stmt.body);
+            }
+            Node::CaseStmt(case) => {
+                self.check_compile_time_node(f, &case.expr);
+                for branch in &case.cases {
+                    self.check_compile_time_node(f, &branch.statement);
+                }
+                if let Some(else_branch) = &case.else_branch {
+                    self.check_compile_time_node(f, else_branch);
+                }
+            }
+            Node::Block(_)
+            | Node::IfStmt(_)
+            | Node::WhileStmt(_)
+            | Node::RepeatStmt(_)
+            | Node::BinaryExpr(_)
+            | Node::UnaryExpr(_) => {
+                for child in node.children() {
+                    self.check_compile_time_node(f, child);
+                }
+            }
+            Node::LiteralExpr(_) => {}
+            other => self.compile_time_error(f, format!("cannot contain {}", other.kind()), other.span()),
+        }
+    }
+
+    /// Report a name that is not a local, constant, compile-time function
+    /// or pure intrinsic
+    fn check_compile_time_name(&mut self, f: &ast::FuncDecl, name: &str, span: tokens::Span) {
+        let local_level = self.core.symbol_table.scope_level();
+        let problem = match self.core.symbol_table.lookup(name) {
+            Some(symbol) => match &symbol.kind {
+                SymbolKind::Variable { .. } if symbol.scope_level == local_level => None,
+                SymbolKind::Variable { .. } => Some(format!("cannot use global variable '{}'", name)),
+                SymbolKind::Constant { value: Some(_), .. } | SymbolKind::TypeAlias { .. } => None,
+                SymbolKind::Function { .. }
+                    if name.eq_ignore_ascii_case(&f.name) || self.compile_time_functions.contains_key(&name.to_lowercase()) =>
+                {
+                    None
+                }
+                SymbolKind::Function { .. } | SymbolKind::Procedure { .. } => {
+                    Some(format!("cannot call '{}', which is not a compile-time function", name))
+                }
+                _ => Some(format!("cannot use '{}'", name)),
+            },
+            None if [
+                crate::EXIT_INTRINSIC,
+                crate::INC_INTRINSIC,
+                crate::DEC_INTRINSIC,
+                crate::ORD_INTRINSIC,
+                crate::SUCC_INTRINSIC,
+                crate::PRED_INTRINSIC,
+                crate::TRUNC_INTRINSIC,
+                crate::ROUND_INTRINSIC,
+            ]
+            .iter()
+            .any(|intrinsic| name.eq_ignore_ascii_case(intrinsic)) =>
+            {
+                None
+            }
+            // Unknown names are reported by the body's own analysis
+            None => None,
+        };
+        if let Some(problem) = problem {
+            self.compile_time_error(f, problem, span);
+        }
+    }
+}
+
+/// Variables of one compile-time call, by lowercase name. The result is the
+/// variable named after the function.
+struct Frame {
+    function: String,
+    variables: HashMap<String, (Type, Option<ConstantValue>)>,
+    constants: HashMap<String, ConstantValue>,
+}
+
+impl Frame {
+    fn key(&self, name: &str) -> String {
+        if name.eq_ignore_ascii_case(crate::RESULT_VARIABLE) {
+            self.function.clone()
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<Result<ConstantValue, String>> {
+        let key = self.key(name);
+        if let Some(value) = self.constants.get(&key) {
+            return Some(Ok(value.clone()));
+        }
+        let (_, value) = self.variables.get(&key)?;
+        Some(value.clone().ok_or_else(|| format!("'{}' is used before it is assigned", name)))
+    }
+
+    fn assign(&mut self, name: &str, value: ConstantValue) -> Result<(), String> {
+        let key = self.key(name);
+        let (var_type, slot) = self
+            .variables
+            .get_mut(&key)
+            .ok_or_else(|| format!("cannot assign '{}'", name))?;
+        *slot = Some(convert(value, var_type)?);
+        Ok(())
+    }
+
+    fn var_type(&self, name: &str) -> Option<Type> {
+        self.variables.get(&self.key(name)).map(|(var_type, _)| var_type.clone())
+    }
+}
+
+/// Whether a statement finished or left its function with `Exit`
+enum Flow {
+    Next,
+    Exit,
+}
+
+/// Tree-walking interpreter for compile-time functions
+struct Interpreter<'a> {
+    analyzer: &'a SemanticAnalyzer,
+    steps: usize,
+    calls: usize,
+}
+
+impl Interpreter<'_> {
+    fn call(&mut self, function: &CompileTimeFunction, args: Vec<ConstantValue>) -> Result<ConstantValue, String> {
+        if self.calls == COMPILE_TIME_CALL_LIMIT {
+            return Err(format!("more than {} nested calls", COMPILE_TIME_CALL_LIMIT));
+        }
+        let mut frame = Frame {
+            function: function.decl.name.to_lowercase(),
+            variables: HashMap::new(),
+            constants: HashMap::new(),
+        };
+        for ((name, param_type), value) in function.params.iter().zip(args) {
+            let value = convert(value, param_type)?;
+            frame.variables.insert(name.to_lowercase(), (param_type.clone(), Some(value)));
+        }
+        for (name, var_type) in &function.locals {
+            frame.variables.insert(name.to_lowercase(), (var_type.clone(), None));
+        }
+        frame.variables.insert(frame.function.clone(), (function.return_type.clone(), None));
+
+        let Node::Block(block) = function.decl.block.as_ref() else {
+            return Err(format!("'{}' has no body", function.decl.name));
+        };
+        for decl in &block.const_decls {
+            if let Node::ConstDecl(c) = decl {
+                let value = self.evaluate(&mut frame, &c.value)?;
+                frame.constants.insert(c.name.to_lowercase(), value);
+            }
+        }
+
+        self.calls += 1;
+        let flow = self.execute_all(&mut frame, &block.statements);
+        self.calls -= 1;
+        flow?;
+        frame
+            .value(crate::RESULT_VARIABLE)
+            .unwrap_or_else(|| Err(format!("'{}' has no result", function.decl.name)))
+            .map_err(|_| format!("'{}' returned without assigning its result", function.decl.name))
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > COMPILE_TIME_STEP_LIMIT {
+            return Err(format!("more than {} steps", COMPILE_TIME_STEP_LIMIT));
+        }
+        Ok(())
+    }
+
+    fn execute_all(&mut self, frame: &mut Frame, statements: &[Node]) -> Result<Flow, String> {
+        for statement in statements {
+            if let Flow::Exit = self.execute(frame, statement)? {
+                return Ok(Flow::Exit);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn execute(&mut self, frame: &mut Frame, statement: &Node) -> Result<Flow, String> {
+        self.step()?;
+        match statement {
+            Node::Block(block) => self.execute_all(frame, &block.statements),
+            Node::AssignStmt(assign) => {
+                let Node::IdentExpr(target) = assign.target.as_ref() else {
+                    return Err("only whole variables can be assigned".to_string());
+                };
+                let value = self.evaluate(frame, &assign.value)?;
+                frame.assign(&target.name, value)?;
+                Ok(Flow::Next)
+            }
+            Node::IfStmt(if_stmt) => {
+                if self.condition(frame, &if_stmt.condition)? {
+                    self.execute(frame, &if_stmt.then_block)
+                } else if let Some(else_block) = &if_stmt.else_block {
+                    self.execute(frame, else_block)
+                } else {
+                    Ok(Flow::Next)
+                }
+            }
+            Node::WhileStmt(while_stmt) => {
+                while self.condition(frame, &while_stmt.condition)? {
+                    if let Flow::Exit = self.execute(frame, &while_stmt.body)? {
+                        return Ok(Flow::Exit);
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            Node::RepeatStmt(repeat) => loop {
+                if let Flow::Exit = self.execute_all(frame, &repeat.statements)? {
+                    return Ok(Flow::Exit);
+                }
+                if self.condition(frame, &repeat.condition)? {
+                    return Ok(Flow::Next);
+                }
+                self.step()?;
+            },
+            Node::ForStmt(for_stmt) => {
+                let var_type = frame
+                    .var_type(&for_stmt.var_name)
+                    .ok_or_else(|| format!("cannot assign '{}'", for_stmt.var_name))?;
+                let start = self.ordinal(frame, &for_stmt.start_expr)?;
+                let end = self.ordinal(frame, &for_stmt.end_expr)?;
+                let values: Box<dyn Iterator<Item = i64>> = match for_stmt.direction {
+                    ast::ForDirection::To => Box::new(start..=end),
+                    ast::ForDirection::Downto => Box::new((end..=start).rev()),
+                };
+                for ordinal in values {
+                    frame.assign(&for_stmt.var_name, ordinal_value(&var_type, ordinal)?)?;
+                    if let Flow::Exit = self.execute(frame, &for_stmt.body)? {
+                        return Ok(Flow::Exit);
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            Node::CaseStmt(case) => {
+                let selector = self.ordinal(frame, &case.expr)?;
+                for branch in &case.cases {
+                    for label in &branch.values {
+                        let matches = match label {
+                            Node::SubrangeType(range) => {
+                                (self.ordinal(frame, &range.low)?..=self.ordinal(frame, &range.high)?).contains(&selector)
+                            }
+                            _ => self.ordinal(frame, label)? == selector,
+                        };
+                        if matches {
+                            return self.execute(frame, &branch.statement);
+                        }
+                    }
+                }
+                match &case.else_branch {
+                    Some(else_branch) => self.execute(frame, else_branch),
+                    None => Ok(Flow::Next),
+                }
+            }
+            Node::CallStmt(call) if call.name.eq_ignore_ascii_case(crate::EXIT_INTRINSIC) => {
+                if let Some(value) = call.args.first() {
+                    let value = self.evaluate(frame, value)?;
+                    frame.assign(crate::RESULT_VARIABLE, value)?;
+                }
+                Ok(Flow::Exit)
+            }
+            Node::CallStmt(call)
+                if call.name.eq_ignore_ascii_case(crate::INC_INTRINSIC)
+                    || call.name.eq_ignore_ascii_case(crate::DEC_INTRINSIC) =>
+            {
+                let Some(Node::IdentExpr(target)) = call.args.first() else {
+                    return Err(format!("{} needs a variable", call.name));
+                };
+                let amount = match call.args.get(1) {
+                    Some(amount) => self.ordinal(frame, amount)?,
+                    None => 1,
+                };
+                let amount = if call.name.eq_ignore_ascii_case(crate::DEC_INTRINSIC) { -amount } else { amount };
+                let current = self.ordinal(frame, &call.args[0])?;
+                let var_type = frame.var_type(&target.name).unwrap_or(Type::Error);
+                frame.assign(&target.name, ordinal_value(&var_type, current + amount)?)?;
+                Ok(Flow::Next)
+            }
+            other => Err(format!("{} cannot be run at compile time", other.kind())),
+        }
+    }
+
+    fn evaluate(&mut self, frame: &mut Frame, expr: &Node) -> Result<ConstantValue, String> {
+        match expr {
+            Node::IdentExpr(ident) => match frame.value(&ident.name) {
+                Some(value) => value,
+                None => self.constant(expr),
+            },
+            Node::BinaryExpr(bin) => {
+                let left = widen(self.evaluate(frame, &bin.left)?);
+                let right = widen(self.evaluate(frame, &bin.right)?);
+                let (left, right) = match bin.op {
+                    // `/` always divides reals
+                    ast::BinaryOp::Divide => (
+                        SemanticAnalyzer::real_value(&left).map_or(left, ConstantValue::Real),
+                        SemanticAnalyzer::real_value(&right).map_or(right, ConstantValue::Real),
+                    ),
+                    _ => (left, right),
+                };
+                if matches!(bin.op, ast::BinaryOp::Div | ast::BinaryOp::Mod | ast::BinaryOp::Divide)
+                    && SemanticAnalyzer::real_value(&right) == Some(0.0)
+                {
+                    return Err("division by zero".to_string());
+                }
+                self.analyzer
+                    .eval_binary(&bin.op, &left, &right)
+                    .ok_or_else(|| "arithmetic overflow".to_string())
+            }
+            Node::UnaryExpr(unary) => {
+                let operand = widen(self.evaluate(frame, &unary.expr)?);
+                self.analyzer
+                    .eval_unary(&unary.op, &operand)
+                    .ok_or_else(|| "arithmetic overflow".to_string())
+            }
+            Node::CallExpr(call) => {
+                let args = call
+                    .args
+                    .iter()
+                    .map(|arg| self.evaluate(frame, arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(function) = self.analyzer.compile_time_function(&call.name) {
+                    return self.call(function, args);
+                }
+                match args.as_slice() {
+                    [value] if let Some(target) = self.analyzer.typecast_target(&call.name) => cast(value.clone(), &target),
+                    [value] => SemanticAnalyzer::eval_intrinsic(&call.name, value)
+                        .ok_or_else(|| format!("{} has no result for this argument", call.name)),
+                    _ => Err(format!("'{}' cannot be called at compile time", call.name)),
+                }
+            }
+            _ => self.constant(expr),
+        }
+    }
+
+    /// Value of an expression outside the function, e.g. a global constant
+    fn constant(&self, expr: &Node) -> Result<ConstantValue, String> {
+        self.analyzer
+            .evaluate_constant_expression(expr)
+            .ok_or_else(|| format!("{} has no constant value", expr.kind()))
+    }
+
+    fn condition(&mut self, frame: &mut Frame, expr: &Node) -> Result<bool, String> {
+        self.step()?;
+        match self.evaluate(frame, expr)? {
+            ConstantValue::Boolean(b) => Ok(b),
+            _ => Err("condition is not a Boolean".to_string()),
+        }
+    }
+
+    fn ordinal(&mut self, frame: &mut Frame, expr: &Node) -> Result<i64, String> {
+        let value = self.evaluate(frame, expr)?;
+        SemanticAnalyzer::constant_ordinal(&value).ok_or_else(|| "value is not ordinal".to_string())
+    }
+}
+
+/// 8- and 16-bit integers as LongInt, so arithmetic on them neither wraps
+/// nor mixes types; the result is checked when it is assigned
+fn widen(value: ConstantValue) -> ConstantValue {
+    match value {
+        ConstantValue::Integer(_) | ConstantValue::Byte(_) | ConstantValue::Word(_) => {
+            ConstantValue::LongInt(SemanticAnalyzer::integer_value(&value).unwrap_or_default() as i32)
+        }
+        other => other,
+    }
+}
+
+/// `value` as a value of `target`, or an error when it is out of range
+fn convert(value: ConstantValue, target: &Type) -> Result<ConstantValue, String> {
+    if *target.representation() == Type::real() {
+        return SemanticAnalyzer::real_value(&value)
+            .map(ConstantValue::Real)
+            .ok_or_else(|| "value is not numeric".to_string());
+    }
+    match SemanticAnalyzer::constant_ordinal(&value) {
+        Some(ordinal) if target.ordinal_bounds().is_some() => ordinal_value(target, ordinal),
+        _ => Ok(value),
+    }
+}
+
+/// A table entry as stored: little-endian, one byte for Byte, Char and
+/// Boolean
+fn stored_bytes(value: &ConstantValue) -> Vec<u8> {
+    match value {
+        ConstantValue::Integer(i) => i.to_le_bytes().to_vec(),
+        ConstantValue::Word(w) => w.to_le_bytes().to_vec(),
+        ConstantValue::LongInt(l) => l.to_le_bytes().to_vec(),
+        ConstantValue::Cardinal(c) => c.to_le_bytes().to_vec(),
+        ConstantValue::Real(r) => r.to_le_bytes().to_vec(),
+        ConstantValue::Byte(b) | ConstantValue::Char(b) => vec![*b],
+        ConstantValue::Boolean(b) => vec![*b as u8],
+        ConstantValue::String(_) | ConstantValue::Bytes(_) => vec![],
+    }
+}
+
+/// `T(value)`: an ordinal keeps the low bits that fit `target`, like the
+/// typecast at run time
+fn cast(value: ConstantValue, target: &Type) -> Result<ConstantValue, String> {
+    match (SemanticAnalyzer::constant_ordinal(&value), target.ordinal_bounds()) {
+        (Some(ordinal), Some((low, high))) => ordinal_value(target, low + (ordinal - low).rem_euclid(high - low + 1)),
+        _ => convert(value, target),
+    }
+}
+
+/// The value of ordinal type `target` whose ordinal number is `ordinal`
+fn ordinal_value(target: &Type, ordinal: i64) -> Result<ConstantValue, String> {
+    let (low, high) = target.ordinal_bounds().unwrap_or((i64::MIN, i64::MAX));
+    if ordinal < low || ordinal > high {
+        return Err(format!("{} is out of the range of {}", ordinal, crate::type_name(target)));
+    }
+    Ok(match target.representation() {
+        Type::Primitive(PrimitiveType::Byte) => ConstantValue::Byte(ordinal as u8),
+        Type::Primitive(PrimitiveType::Char) => ConstantValue::Char(ordinal as u8),
+        Type::Primitive(PrimitiveType::Boolean) => ConstantValue::Boolean(ordinal != 0),
+        Type::Primitive(PrimitiveType::Word) => ConstantValue::Word(ordinal as u16),
+        Type::Primitive(PrimitiveType::LongInt) => ConstantValue::LongInt(ordinal as i32),
+        Type::Primitive(PrimitiveType::Cardinal) => ConstantValue::Cardinal(ordinal as u32),
+        // Integers and enumerations
+        _ => ConstantValue::Integer(ordinal as i16),
+    })
+}
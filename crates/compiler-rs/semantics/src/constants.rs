@@ -1,7 +1,13 @@
 //! Constant folding and evaluation
+//!
+//! Constants are computed as the target computes them: Integer and Word
+//! arithmetic wraps at 16 bits, Byte at 8, LongInt and Cardinal at 32
+//! (see [`crate::portability`] for the warnings when that differs from the
+//! exact value).
 
 use ast::Node;
 use symbols::{ConstantValue, SymbolKind};
+use ::types::{PrimitiveType, Type};
 use crate::SemanticAnalyzer;
 
 impl SemanticAnalyzer {
@@ -10,10 +16,20 @@ impl SemanticAnalyzer {
     pub(crate) fn evaluate_constant_expression(&self, expr: &Node) -> Option<ConstantValue> {
         match expr {
             Node::LiteralExpr(lit) => match &lit.value {
-                ast::LiteralValue::Integer(i) => Some(ConstantValue::Integer(*i as i16)),
+                // Literals above 32767 only fit a Word, above 65535 a LongInt
+                // and above 2147483647 a Cardinal
+                ast::LiteralValue::Integer(i) => Some(if let Ok(i) = i16::try_from(*i) {
+                    ConstantValue::Integer(i)
+                } else if let Ok(w) = u16::try_from(*i) {
+                    ConstantValue::Word(w)
+                } else {
+                    i32::try_from(*i).map_or(ConstantValue::Cardinal(*i), ConstantValue::LongInt)
+                }),
+                ast::LiteralValue::Real(r) => Some(ConstantValue::Real(*r as f32)),
                 ast::LiteralValue::Boolean(b) => Some(ConstantValue::Boolean(*b)),
                 ast::LiteralValue::Char(c) => Some(ConstantValue::Char(*c)),
                 ast::LiteralValue::String(s) => Some(ConstantValue::String(s.clone())),
+                ast::LiteralValue::Bytes(b) => Some(ConstantValue::Bytes(b.clone())),
             },
             Node::IdentExpr(i) => {
                 // Check if identifier is a constant
@@ -31,106 +47,199 @@ impl SemanticAnalyzer {
                 // Evaluate both operands
                 let left = self.evaluate_constant_expression(&bin.left)?;
                 let right = self.evaluate_constant_expression(&bin.right)?;
-
-                // Evaluate binary operation
-                match bin.op {
-                    ast::BinaryOp::Add => self.eval_add(&left, &right),
-                    ast::BinaryOp::Subtract => self.eval_subtract(&left, &right),
-                    ast::BinaryOp::Multiply => self.eval_multiply(&left, &right),
-                    ast::BinaryOp::Divide | ast::BinaryOp::Div => self.eval_divide(&left, &right),
-                    ast::BinaryOp::Mod => self.eval_mod(&left, &right),
-                    ast::BinaryOp::Equal => Some(ConstantValue::Boolean(left == right)),
-                    ast::BinaryOp::NotEqual => Some(ConstantValue::Boolean(left != right)),
-                    ast::BinaryOp::Less => self.eval_less(&left, &right),
-                    ast::BinaryOp::LessEqual => self.eval_less_equal(&left, &right),
-                    ast::BinaryOp::Greater => self.eval_greater(&left, &right),
-                    ast::BinaryOp::GreaterEqual => self.eval_greater_equal(&left, &right),
-                    ast::BinaryOp::And => self.eval_and(&left, &right),
-                    ast::BinaryOp::Or => self.eval_or(&left, &right),
-                    ast::BinaryOp::In => {
-                        // Set membership: IN operator evaluation not yet implemented for constant expressions
-                        None
-                    }
-                    ast::BinaryOp::Is => {
-                        // Type checking: IS operator evaluation not yet implemented for constant expressions
-                        None
-                    }
-                    ast::BinaryOp::As => {
-                        // Type casting: AS operator evaluation not yet implemented for constant expressions
-                        None
-                    }
-                }
+                self.eval_binary(&bin.op, &left, &right)
             }
             Node::UnaryExpr(unary) => {
                 let operand = self.evaluate_constant_expression(&unary.expr)?;
-                match unary.op {
-                    ast::UnaryOp::Plus => Some(operand), // Unary plus is no-op
-                    ast::UnaryOp::Minus => self.eval_unary_minus(&operand),
-                    ast::UnaryOp::Not => self.eval_not(&operand),
-                    ast::UnaryOp::AddressOf => {
-                        // Address-of operator: @variable
-                        // Cannot be evaluated at compile time
-                        None
-                    }
-                }
+                self.eval_unary(&unary.op, &operand)
+            }
+            Node::CallExpr(call)
+                if call.name.eq_ignore_ascii_case(crate::GENERATE_TABLE_INTRINSIC)
+                    && self.core.symbol_table.lookup(&call.name).is_none() =>
+            {
+                self.evaluate_generate_table(call).ok().map(|(_, table)| table)
+            }
+            Node::CallExpr(call) if self.compile_time_function(&call.name).is_some() => {
+                self.evaluate_compile_time_call(call)?.ok()
+            }
+            Node::CallExpr(call) if call.args.len() == 1 && self.core.symbol_table.lookup(&call.name).is_none() => {
+                let value = self.evaluate_constant_expression(&call.args[0])?;
+                Self::eval_intrinsic(&call.name, &value)
             }
             _ => None, // Not a constant expression
         }
     }
 
+    /// Convert a constant to `target` if its value fits, e.g. the literal 10 to Word
+    pub(crate) fn coerce_constant(&self, value: &ConstantValue, target: &::types::Type) -> Option<ConstantValue> {
+        use ::types::PrimitiveType;
+        if let (ConstantValue::Bytes(_), ::types::Type::Array { element_type, .. }) = (value, target)
+            && element_type.as_ref() == &::types::Type::byte()
+        {
+            return Some(value.clone());
+        }
+        let ::types::Type::Primitive(prim) = target else {
+            return None;
+        };
+        match (value, prim) {
+            (ConstantValue::Integer(i), PrimitiveType::Integer) => Some(ConstantValue::Integer(*i)),
+            (ConstantValue::Integer(i), PrimitiveType::Word) => u16::try_from(*i).ok().map(ConstantValue::Word),
+            (ConstantValue::Integer(i), PrimitiveType::Byte) => u8::try_from(*i).ok().map(ConstantValue::Byte),
+            (ConstantValue::Word(w), PrimitiveType::Word) => Some(ConstantValue::Word(*w)),
+            (ConstantValue::Byte(b), PrimitiveType::Byte) => Some(ConstantValue::Byte(*b)),
+            (ConstantValue::Boolean(b), PrimitiveType::Boolean) => Some(ConstantValue::Boolean(*b)),
+            (ConstantValue::Char(c), PrimitiveType::Char) => Some(ConstantValue::Char(*c)),
+            (_, PrimitiveType::LongInt) => i32::try_from(Self::integer_value(value)?).ok().map(ConstantValue::LongInt),
+            (_, PrimitiveType::Cardinal) => u32::try_from(Self::integer_value(value)?).ok().map(ConstantValue::Cardinal),
+            (_, PrimitiveType::Real) => Self::real_value(value).map(ConstantValue::Real),
+            _ => None,
+        }
+    }
+
+    /// Apply a binary operator to constant operands
+    pub(crate) fn eval_binary(&self, op: &ast::BinaryOp, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        match op {
+            ast::BinaryOp::Add => self.eval_add(left, right),
+            ast::BinaryOp::Subtract => self.eval_subtract(left, right),
+            ast::BinaryOp::Multiply => self.eval_multiply(left, right),
+            ast::BinaryOp::Divide | ast::BinaryOp::Div => self.eval_divide(left, right),
+            ast::BinaryOp::Mod => self.eval_mod(left, right),
+            ast::BinaryOp::Equal => Some(ConstantValue::Boolean(left == right)),
+            ast::BinaryOp::NotEqual => Some(ConstantValue::Boolean(left != right)),
+            ast::BinaryOp::Less => self.eval_less(left, right),
+            ast::BinaryOp::LessEqual => self.eval_less_equal(left, right),
+            ast::BinaryOp::Greater => self.eval_greater(left, right),
+            ast::BinaryOp::GreaterEqual => self.eval_greater_equal(left, right),
+            ast::BinaryOp::And => self.eval_and(left, right),
+            ast::BinaryOp::Or => self.eval_or(left, right),
+            ast::BinaryOp::In => {
+                // Set membership: IN operator evaluation not yet implemented for constant expressions
+                None
+            }
+            ast::BinaryOp::Is => {
+                // Type checking: IS operator evaluation not yet implemented for constant expressions
+                None
+            }
+            ast::BinaryOp::As => {
+                // Type casting: AS operator evaluation not yet implemented for constant expressions
+                None
+            }
+        }
+    }
+
+    /// Apply a unary operator to a constant operand
+    pub(crate) fn eval_unary(&self, op: &ast::UnaryOp, operand: &ConstantValue) -> Option<ConstantValue> {
+        match op {
+            ast::UnaryOp::Plus => Some(operand.clone()), // Unary plus is no-op
+            ast::UnaryOp::Minus => self.eval_unary_minus(operand),
+            ast::UnaryOp::Not => self.eval_not(operand),
+            ast::UnaryOp::AddressOf => {
+                // Address-of operator: @variable
+                // Cannot be evaluated at compile time
+                None
+            }
+        }
+    }
+
+    /// `Ord`, `Succ`, `Pred`, `Trunc` or `Round` of a constant, or None for
+    /// other names and values without a result
+    pub(crate) fn eval_intrinsic(name: &str, value: &ConstantValue) -> Option<ConstantValue> {
+        if name.eq_ignore_ascii_case(crate::TRUNC_INTRINSIC) || name.eq_ignore_ascii_case(crate::ROUND_INTRINSIC) {
+            let real = Self::real_value(value)?;
+            let integer = if name.eq_ignore_ascii_case(crate::ROUND_INTRINSIC) { real.round() } else { real.trunc() };
+            return (i16::MIN as f32..=i16::MAX as f32)
+                .contains(&integer)
+                .then_some(ConstantValue::Integer(integer as i16));
+        }
+        let ordinal = Self::constant_ordinal(value)?;
+        if name.eq_ignore_ascii_case(crate::ORD_INTRINSIC) {
+            Some(Self::ordinal_constant(value, ordinal))
+        } else if name.eq_ignore_ascii_case(crate::SUCC_INTRINSIC) {
+            Self::with_ordinal(value, ordinal + 1)
+        } else if name.eq_ignore_ascii_case(crate::PRED_INTRINSIC) {
+            Self::with_ordinal(value, ordinal - 1)
+        } else {
+            None
+        }
+    }
+
     // Helper functions for constant evaluation
     pub(crate) fn eval_add(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, cardinal)) = Self::long_operands(left, right) {
+            return Some(Self::long_constant(l + r, cardinal));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Real(l + r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => {
-                Some(ConstantValue::Integer(l.saturating_add(*r)))
+                Some(ConstantValue::Integer(l.wrapping_add(*r)))
             }
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => {
-                Some(ConstantValue::Byte(l.saturating_add(*r)))
+                Some(ConstantValue::Byte(l.wrapping_add(*r)))
             }
             (ConstantValue::Word(l), ConstantValue::Word(r)) => {
-                Some(ConstantValue::Word(l.saturating_add(*r)))
+                Some(ConstantValue::Word(l.wrapping_add(*r)))
             }
             _ => None,
         }
     }
 
     pub(crate) fn eval_subtract(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, cardinal)) = Self::long_operands(left, right) {
+            return Some(Self::long_constant(l - r, cardinal));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Real(l - r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => {
-                Some(ConstantValue::Integer(l.saturating_sub(*r)))
+                Some(ConstantValue::Integer(l.wrapping_sub(*r)))
             }
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => {
-                Some(ConstantValue::Byte(l.saturating_sub(*r)))
+                Some(ConstantValue::Byte(l.wrapping_sub(*r)))
             }
             (ConstantValue::Word(l), ConstantValue::Word(r)) => {
-                Some(ConstantValue::Word(l.saturating_sub(*r)))
+                Some(ConstantValue::Word(l.wrapping_sub(*r)))
             }
             _ => None,
         }
     }
 
     pub(crate) fn eval_multiply(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, cardinal)) = Self::long_operands(left, right) {
+            return Some(Self::long_constant(l.wrapping_mul(r), cardinal));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Real(l * r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => {
-                Some(ConstantValue::Integer(l.saturating_mul(*r)))
+                Some(ConstantValue::Integer(l.wrapping_mul(*r)))
             }
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => {
-                Some(ConstantValue::Byte(l.saturating_mul(*r)))
+                Some(ConstantValue::Byte(l.wrapping_mul(*r)))
             }
             (ConstantValue::Word(l), ConstantValue::Word(r)) => {
-                Some(ConstantValue::Word(l.saturating_mul(*r)))
+                Some(ConstantValue::Word(l.wrapping_mul(*r)))
             }
             _ => None,
         }
     }
 
     pub(crate) fn eval_divide(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, cardinal)) = Self::long_operands(left, right) {
+            return (r != 0).then(|| Self::long_constant(l / r, cardinal));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Real(l / r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => {
                 if *r == 0 {
                     None // Division by zero
                 } else {
-                    Some(ConstantValue::Integer(l / r))
+                    Some(ConstantValue::Integer(l.wrapping_div(*r)))
                 }
             }
             (ConstantValue::Word(l), ConstantValue::Word(r)) => {
@@ -145,12 +254,15 @@ impl SemanticAnalyzer {
     }
 
     pub(crate) fn eval_mod(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, cardinal)) = Self::long_operands(left, right) {
+            return (r != 0).then(|| Self::long_constant(l % r, cardinal));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => {
                 if *r == 0 {
                     None // Modulo by zero
                 } else {
-                    Some(ConstantValue::Integer(l % r))
+                    Some(ConstantValue::Integer(l.wrapping_rem(*r)))
                 }
             }
             (ConstantValue::Word(l), ConstantValue::Word(r)) => {
@@ -165,6 +277,12 @@ impl SemanticAnalyzer {
     }
 
     pub(crate) fn eval_less(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, _)) = Self::long_operands(left, right) {
+            return Some(ConstantValue::Boolean(l < r));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Boolean(l < r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => Some(ConstantValue::Boolean(l < r)),
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => Some(ConstantValue::Boolean(l < r)),
@@ -174,6 +292,12 @@ impl SemanticAnalyzer {
     }
 
     pub(crate) fn eval_less_equal(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, _)) = Self::long_operands(left, right) {
+            return Some(ConstantValue::Boolean(l <= r));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Boolean(l <= r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => Some(ConstantValue::Boolean(l <= r)),
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => Some(ConstantValue::Boolean(l <= r)),
@@ -183,6 +307,12 @@ impl SemanticAnalyzer {
     }
 
     pub(crate) fn eval_greater(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, _)) = Self::long_operands(left, right) {
+            return Some(ConstantValue::Boolean(l > r));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Boolean(l > r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => Some(ConstantValue::Boolean(l > r)),
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => Some(ConstantValue::Boolean(l > r)),
@@ -192,6 +322,12 @@ impl SemanticAnalyzer {
     }
 
     pub(crate) fn eval_greater_equal(&self, left: &ConstantValue, right: &ConstantValue) -> Option<ConstantValue> {
+        if let Some((l, r, _)) = Self::long_operands(left, right) {
+            return Some(ConstantValue::Boolean(l >= r));
+        }
+        if let Some((l, r)) = Self::real_operands(left, right) {
+            return Some(ConstantValue::Boolean(l >= r));
+        }
         match (left, right) {
             (ConstantValue::Integer(l), ConstantValue::Integer(r)) => Some(ConstantValue::Boolean(l >= r)),
             (ConstantValue::Byte(l), ConstantValue::Byte(r)) => Some(ConstantValue::Boolean(l >= r)),
@@ -220,7 +356,11 @@ impl SemanticAnalyzer {
 
     pub(crate) fn eval_unary_minus(&self, operand: &ConstantValue) -> Option<ConstantValue> {
         match operand {
-            ConstantValue::Integer(i) => Some(ConstantValue::Integer(-i)),
+            ConstantValue::Integer(i) => Some(ConstantValue::Integer(i.wrapping_neg())),
+            ConstantValue::Real(r) => Some(ConstantValue::Real(-r)),
+            ConstantValue::LongInt(_) | ConstantValue::Cardinal(_) => {
+                Some(Self::long_constant(-Self::integer_value(operand)?, false))
+            }
             _ => None,
         }
     }
@@ -231,4 +371,211 @@ impl SemanticAnalyzer {
             _ => None,
         }
     }
+
+    /// Warn about a comparison between a variable and a constant that the
+    /// variable's type decides on its own, e.g. `B < 0` for a Byte
+    pub(crate) fn check_constant_comparison(&mut self, bin: &ast::BinaryExpr, left: &Type, right: &Type) {
+        let (op, value, prim) = match (
+            self.evaluate_constant_expression(&bin.left),
+            self.evaluate_constant_expression(&bin.right),
+            left,
+            right,
+        ) {
+            (None, Some(value), Type::Primitive(prim), _) => (bin.op, value, *prim),
+            (Some(value), None, _, Type::Primitive(prim)) => (Self::mirror_comparison(&bin.op), value, *prim),
+            _ => return,
+        };
+        let Some(value) = Self::constant_ordinal(&value) else {
+            return;
+        };
+        let (lo, hi) = prim.range();
+        let outcome = match op {
+            ast::BinaryOp::Less => Self::decided(hi < value, lo >= value),
+            ast::BinaryOp::LessEqual => Self::decided(hi <= value, lo > value),
+            ast::BinaryOp::Greater => Self::decided(lo > value, hi <= value),
+            ast::BinaryOp::GreaterEqual => Self::decided(lo >= value, hi < value),
+            ast::BinaryOp::Equal => Self::decided(lo == value && hi == value, value < lo || value > hi),
+            ast::BinaryOp::NotEqual => Self::decided(value < lo || value > hi, lo == value && hi == value),
+            _ => None,
+        };
+        if let Some(outcome) = outcome {
+            self.core.add_warning(
+                format!(
+                    "Comparison is always {}: {:?} values are in {}..{}",
+                    outcome, prim, lo, hi
+                ),
+                bin.span,
+            );
+        }
+    }
+
+    /// Whether `expr` is an integer constant in the range of `target`, a
+    /// Byte, Word or Cardinal or a subrange of one, so it can stand for a
+    /// value of that type
+    pub(crate) fn constant_fits(&self, expr: &Node, target: &Type) -> bool {
+        let Type::Primitive(prim @ (PrimitiveType::Byte | PrimitiveType::Word | PrimitiveType::Cardinal)) =
+            target.subrange_base()
+        else {
+            return false;
+        };
+        let (lo, hi) = prim.range();
+        self.evaluate_constant_expression(expr)
+            .and_then(|value| Self::integer_value(&value))
+            .is_some_and(|value| (lo..=hi).contains(&value))
+    }
+
+    /// Report `expr` if it is a constant outside the bounds of `target`, a
+    /// subrange or enumeration, or a set literal with such an element
+    pub(crate) fn check_constant_in_range(&mut self, expr: &Node, target: &Type) {
+        // The elements of a set literal are checked against the set's base type
+        if let (Node::SetLiteral(set), Type::Set { element_type }) = (expr, target.representation()) {
+            for element in &set.elements {
+                match element {
+                    ast::SetElement::Value(value) => self.check_constant_in_range(value, element_type),
+                    ast::SetElement::Range { start, end } => {
+                        self.check_constant_in_range(start, element_type);
+                        self.check_constant_in_range(end, element_type);
+                    }
+                }
+            }
+            return;
+        }
+        if !matches!(target, Type::Subrange { .. } | Type::Enum { .. }) {
+            return;
+        }
+        let (Some((low, high)), Some(value)) = (
+            target.ordinal_bounds(),
+            self.evaluate_constant_expression(expr).as_ref().and_then(Self::constant_ordinal),
+        ) else {
+            return;
+        };
+        if !(low..=high).contains(&value) {
+            self.core.add_error(
+                format!(
+                    "Constant {} is out of the range {}",
+                    crate::core::CoreAnalyzer::format_ordinal(target, value),
+                    crate::core::CoreAnalyzer::format_type(target)
+                ),
+                expr.span(),
+            );
+        }
+    }
+
+    fn decided(always_true: bool, always_false: bool) -> Option<bool> {
+        if always_true {
+            Some(true)
+        } else if always_false {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// `c < x` is `x > c`
+    fn mirror_comparison(op: &ast::BinaryOp) -> ast::BinaryOp {
+        match op {
+            ast::BinaryOp::Less => ast::BinaryOp::Greater,
+            ast::BinaryOp::LessEqual => ast::BinaryOp::GreaterEqual,
+            ast::BinaryOp::Greater => ast::BinaryOp::Less,
+            ast::BinaryOp::GreaterEqual => ast::BinaryOp::LessEqual,
+            other => *other,
+        }
+    }
+
+    pub(crate) fn constant_ordinal(value: &ConstantValue) -> Option<i64> {
+        match value {
+            ConstantValue::Integer(i) => Some(*i as i64),
+            ConstantValue::Byte(b) | ConstantValue::Char(b) => Some(*b as i64),
+            ConstantValue::Word(w) => Some(*w as i64),
+            ConstantValue::LongInt(l) => Some(*l as i64),
+            ConstantValue::Cardinal(c) => Some(*c as i64),
+            ConstantValue::Boolean(b) => Some(*b as i64),
+            ConstantValue::Real(_) | ConstantValue::String(_) | ConstantValue::Bytes(_) => None,
+        }
+    }
+
+    /// `Ord` of `value`, whose ordinal number is `ordinal`: an Integer, or
+    /// the value itself for integers
+    fn ordinal_constant(value: &ConstantValue, ordinal: i64) -> ConstantValue {
+        match value {
+            ConstantValue::Char(_) | ConstantValue::Boolean(_) => ConstantValue::Integer(ordinal as i16),
+            _ => value.clone(),
+        }
+    }
+
+    /// The constant of `value`'s type whose ordinal number is `ordinal`, if
+    /// there is one
+    fn with_ordinal(value: &ConstantValue, ordinal: i64) -> Option<ConstantValue> {
+        match value {
+            ConstantValue::Integer(_) => i16::try_from(ordinal).ok().map(ConstantValue::Integer),
+            ConstantValue::Byte(_) => u8::try_from(ordinal).ok().map(ConstantValue::Byte),
+            ConstantValue::Char(_) => u8::try_from(ordinal).ok().map(ConstantValue::Char),
+            ConstantValue::Word(_) => u16::try_from(ordinal).ok().map(ConstantValue::Word),
+            ConstantValue::LongInt(_) => i32::try_from(ordinal).ok().map(ConstantValue::LongInt),
+            ConstantValue::Cardinal(_) => u32::try_from(ordinal).ok().map(ConstantValue::Cardinal),
+            ConstantValue::Boolean(_) => match ordinal {
+                0 => Some(ConstantValue::Boolean(false)),
+                1 => Some(ConstantValue::Boolean(true)),
+                _ => None,
+            },
+            ConstantValue::Real(_) | ConstantValue::String(_) | ConstantValue::Bytes(_) => None,
+        }
+    }
+
+    /// A numeric constant as a real
+    pub(crate) fn real_value(value: &ConstantValue) -> Option<f32> {
+        match value {
+            ConstantValue::Real(r) => Some(*r),
+            ConstantValue::Integer(i) => Some(*i as f32),
+            ConstantValue::Byte(b) => Some(*b as f32),
+            ConstantValue::Word(w) => Some(*w as f32),
+            ConstantValue::LongInt(l) => Some(*l as f32),
+            ConstantValue::Cardinal(c) => Some(*c as f32),
+            _ => None,
+        }
+    }
+
+    /// An integer constant of any size
+    pub(crate) fn integer_value(value: &ConstantValue) -> Option<i64> {
+        match value {
+            ConstantValue::Integer(i) => Some(*i as i64),
+            ConstantValue::Byte(b) => Some(*b as i64),
+            ConstantValue::Word(w) => Some(*w as i64),
+            ConstantValue::LongInt(l) => Some(*l as i64),
+            ConstantValue::Cardinal(c) => Some(*c as i64),
+            _ => None,
+        }
+    }
+
+    /// Both operands widened, when at least one is a 32-bit integer, and
+    /// whether either is a Cardinal
+    fn long_operands(left: &ConstantValue, right: &ConstantValue) -> Option<(i64, i64, bool)> {
+        let is_long = |v: &ConstantValue| matches!(v, ConstantValue::LongInt(_) | ConstantValue::Cardinal(_));
+        if !is_long(left) && !is_long(right) {
+            return None;
+        }
+        let cardinal = matches!(left, ConstantValue::Cardinal(_)) || matches!(right, ConstantValue::Cardinal(_));
+        Some((Self::integer_value(left)?, Self::integer_value(right)?, cardinal))
+    }
+
+    /// A 32-bit result: a Cardinal when an operand was one and the value
+    /// fits, otherwise a LongInt; one that fits neither wraps, as it does
+    /// on the target
+    fn long_constant(value: i64, cardinal: bool) -> ConstantValue {
+        match (u32::try_from(value), i32::try_from(value)) {
+            (Ok(c), _) if cardinal => ConstantValue::Cardinal(c),
+            (_, Ok(l)) => ConstantValue::LongInt(l),
+            _ if cardinal => ConstantValue::Cardinal(value as u32),
+            _ => ConstantValue::LongInt(value as i32),
+        }
+    }
+
+    /// Both operands as reals, when at least one is a real and the other
+    /// numeric; integers are promoted as they are at run time
+    fn real_operands(left: &ConstantValue, right: &ConstantValue) -> Option<(f32, f32)> {
+        if !matches!(left, ConstantValue::Real(_)) && !matches!(right, ConstantValue::Real(_)) {
+            return None;
+        }
+        Some((Self::real_value(left)?, Self::real_value(right)?))
+    }
 }
@@ -24,12 +24,48 @@ impl CoreAnalyzer {
 
     /// Add an error diagnostic
     pub fn add_error(&mut self, message: String, span: Span) {
+        let diag = self.error(message, span);
+        self.diagnostics.push(diag);
+    }
+
+    /// Create an error diagnostic, to be extended (e.g. with related
+    /// locations) and pushed
+    pub fn error(&self, message: String, span: Span) -> Diagnostic {
         use errors::ErrorSeverity;
-        let diag = Diagnostic::new(ErrorSeverity::Error, message, span)
-            .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string()));
+        Diagnostic::new(ErrorSeverity::Error, message, span)
+            .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string()))
+    }
+
+    /// Add a warning diagnostic
+    pub fn add_warning(&mut self, message: String, span: Span) {
+        let diag = self.warning(message, span);
         self.diagnostics.push(diag);
     }
 
+    /// Create a warning diagnostic, to be extended (e.g. with fix-its) and pushed
+    pub fn warning(&self, message: String, span: Span) -> Diagnostic {
+        use errors::ErrorSeverity;
+        Diagnostic::new(ErrorSeverity::Warning, message, span)
+            .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string()))
+    }
+
+    /// Format the value of ordinal type `ty` whose ordinal number is `ordinal`
+    pub(super) fn format_ordinal(ty: &Type, ordinal: i64) -> String {
+        match ty.representation() {
+            Type::Primitive(::types::PrimitiveType::Char) => match u8::try_from(ordinal) {
+                Ok(c) if c.is_ascii_graphic() || c == b' ' => format!("'{}'", c as char),
+                _ => format!("#{}", ordinal),
+            },
+            Type::Primitive(::types::PrimitiveType::Boolean) => (if ordinal == 0 { "False" } else { "True" }).to_string(),
+            Type::Enum { values } => usize::try_from(ordinal)
+                .ok()
+                .and_then(|i| values.get(i))
+                .cloned()
+                .unwrap_or_else(|| ordinal.to_string()),
+            _ => ordinal.to_string(),
+        }
+    }
+
     /// Format a type for error messages
     pub(super) fn format_type(ty: &Type) -> String {
         match ty {
@@ -55,6 +91,19 @@ impl CoreAnalyzer {
                 let arg_strs: Vec<String> = args.iter().map(|t| Self::format_type(t)).collect();
                 format!("{}<{}>", generic_name, arg_strs.join(", "))
             }
+            Type::Enum { values } => format!("({})", values.join(", ")),
+            Type::Distinct { name, .. } | Type::Class { name } | Type::Interface { name } => name.clone(),
+            Type::Subrange { base, low, high } => {
+                format!("{}..{}", Self::format_ordinal(base, *low), Self::format_ordinal(base, *high))
+            }
+            Type::Set { element_type } if **element_type == Type::Error => "empty set".to_string(),
+            Type::Set { element_type } => format!("set of {}", Self::format_type(element_type)),
+            Type::String { max_length } if *max_length == ::types::MAX_STRING_LENGTH => "String".to_string(),
+            Type::String { max_length } => format!("String[{}]", max_length),
+            Type::UntypedPointer => "Pointer".to_string(),
+            Type::Untyped => "untyped".to_string(),
+            Type::File { element_type: None } => "file".to_string(),
+            Type::File { element_type: Some(element_type) } => format!("file of {}", Self::format_type(element_type)),
             Type::Variant => "Variant".to_string(),
         }
     }
@@ -0,0 +1,331 @@
+//! Dead code elimination
+//!
+//! Run on an analyzed program or unit, before it is lowered, to drop code
+//! that can never run:
+//!
+//! - **Unreachable statements**: those after an `Exit`, `raise` or `goto`
+//!   in the same statement list, up to the next labeled statement, which a
+//!   `goto` may still reach. A compound statement or an `if` whose every
+//!   branch ends so ends the list too.
+//! - **Unused routines**: procedures and functions the program's statements
+//!   (or a unit's initialization and finalization) never reach through
+//!   calls or references such as `@Proc`. Routines a unit exports in its
+//!   interface, methods, and external routines are always kept.
+//!
+//! ```text
+//!     procedure Unused; begin end;      { dropped: never called }
+//!     procedure Log(N: integer);
+//!     begin
+//!       if N < 0 then Exit;
+//!       WriteLn(N);
+//!       Exit;
+//!       WriteLn('done')                 { dropped: after Exit }
+//!     end;
+//!     begin
+//!       Log(1)
+//!     end.
+//! ```
+//!
+//! Each removal is recorded as a warning, which the driver reports when
+//! asked to (`--warn-dead-code`).
+
+use std::collections::HashSet;
+
+use ast::Node;
+use errors::{Diagnostic, ErrorSeverity};
+use tokens::Span;
+
+use crate::EXIT_INTRINSIC;
+
+/// Removes unreachable statements and unused routines
+pub struct DeadCodeEliminator {
+    filename: Option<String>,
+    roots: Vec<String>, // Lowercase names of routines used from outside the code
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl DeadCodeEliminator {
+    pub fn new(filename: Option<String>) -> Self {
+        Self { filename, roots: vec![], diagnostics: vec![] }
+    }
+
+    /// Keep the routine `name`, which is used in a way the code does not
+    /// show, such as a runtime error handler named by a directive
+    pub fn keep(&mut self, name: &str) {
+        self.roots.push(name.to_lowercase());
+    }
+
+    /// Remove the dead code of a program or unit
+    pub fn eliminate(&mut self, ast: &mut Node) {
+        self.remove_unreachable_statements(ast);
+        let mut used = self.roots.iter().cloned().collect();
+        used_routines(ast, &mut used);
+        self.remove_unused_routines(ast, &used);
+        self.diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    }
+
+    /// A warning for each piece of code removed
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn warn(&mut self, message: String, span: Span) {
+        self.diagnostics.push(
+            Diagnostic::new(ErrorSeverity::Warning, message, span)
+                .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string())),
+        );
+    }
+
+    /// Remove the statements after an Exit, raise or goto in each statement
+    /// list of `node`
+    fn remove_unreachable_statements(&mut self, node: &mut Node) {
+        match node {
+            Node::Block(block) => self.prune(&mut block.statements),
+            Node::RepeatStmt(repeat) => self.prune(&mut repeat.statements),
+            Node::TryStmt(try_stmt) => {
+                self.prune(&mut try_stmt.try_block);
+                for statements in try_stmt.except_block.iter_mut().chain(try_stmt.finally_block.iter_mut()) {
+                    self.prune(statements);
+                }
+            }
+            _ => {}
+        }
+        for child in node.children_mut() {
+            self.remove_unreachable_statements(child);
+        }
+    }
+
+    fn prune(&mut self, statements: &mut Vec<Node>) {
+        let mut i = 0;
+        while i < statements.len() {
+            if let Some(ending) = ending(&statements[i]) {
+                let end = (i + 1..statements.len()).find(|&j| has_label(&statements[j])).unwrap_or(statements.len());
+                if end > i + 1 {
+                    self.warn(format!("Unreachable code after {}", ending), statements[i + 1].span());
+                    statements.drain(i + 1..end);
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// Remove the routines declared in `node` whose names are not in `used`
+    fn remove_unused_routines(&mut self, node: &mut Node, used: &HashSet<String>) {
+        let mut unused = vec![];
+        let mut keep = |decl: &Node| {
+            let Some(name) = removable_name(decl) else {
+                return true;
+            };
+            let kept = used.contains(&name.to_lowercase());
+            if !kept {
+                unused.push((routine_kind(decl), name.to_string(), decl.span()));
+            }
+            kept
+        };
+        match node {
+            Node::Block(block) => {
+                block.proc_decls.retain(&mut keep);
+                block.func_decls.retain(&mut keep);
+            }
+            Node::Unit(unit) => {
+                if let Some(implementation) = &mut unit.implementation {
+                    implementation.proc_decls.retain(&mut keep);
+                    implementation.func_decls.retain(&mut keep);
+                }
+            }
+            _ => {}
+        }
+        for (kind, name, span) in unused {
+            self.warn(format!("{} '{}' is never used", kind, name), span);
+        }
+        for child in node.children_mut() {
+            self.remove_unused_routines(child, used);
+        }
+    }
+}
+
+/// What ends every path through `statement`, if something does: the
+/// statements after it in its list never run
+fn ending(statement: &Node) -> Option<&'static str> {
+    match statement {
+        Node::CallStmt(call) if call.name.eq_ignore_ascii_case(EXIT_INTRINSIC) => Some(EXIT_INTRINSIC),
+        Node::RaiseStmt(_) => Some("raise"),
+        Node::GotoStmt(_) => Some("goto"),
+        Node::Block(block) => block.statements.iter().find_map(ending),
+        Node::LabeledStmt(labeled) => ending(&labeled.statement),
+        Node::IfStmt(if_stmt) => {
+            let then_ending = ending(&if_stmt.then_block)?;
+            if_stmt.else_block.as_deref().and_then(ending).map(|_| then_ending)
+        }
+        _ => None,
+    }
+}
+
+/// Whether `node` is or contains a labeled statement
+pub(crate) fn has_label(node: &Node) -> bool {
+    matches!(node, Node::LabeledStmt(_)) || node.children().into_iter().any(has_label)
+}
+
+/// Name of a routine declaration that may be removed when unused
+fn removable_name(decl: &Node) -> Option<&str> {
+    match decl {
+        Node::ProcDecl(proc) if proc.class_name.is_none() && !proc.is_external => Some(&proc.name),
+        Node::FuncDecl(func) if func.class_name.is_none() && !func.is_external => Some(&func.name),
+        _ => None,
+    }
+}
+
+fn routine_kind(decl: &Node) -> &'static str {
+    match decl {
+        Node::FuncDecl(_) => "Function",
+        _ => "Procedure",
+    }
+}
+
+/// Add to `used` the lowercase names of the routines reachable from the
+/// code of `ast` outside routines, from the routines a unit exports, and
+/// from those already in `used` or that cannot be removed
+fn used_routines(ast: &Node, used: &mut HashSet<String>) {
+    let mut routines = vec![];
+    collect_routines(ast, &mut routines);
+    references(ast, used);
+    if let Node::Unit(unit) = ast {
+        for decl in unit.interface.iter().flat_map(|interface| interface.proc_decls.iter().chain(&interface.func_decls)) {
+            used.extend(routine_name(decl).map(str::to_lowercase));
+        }
+    }
+
+    // Follow the references of each routine reached until no more are
+    let mut reached = vec![false; routines.len()];
+    loop {
+        let mut changed = false;
+        for (routine, reached) in routines.iter().zip(&mut reached) {
+            let name = routine_name(routine).unwrap_or_default().to_lowercase();
+            if !*reached && (removable_name(routine).is_none() || used.contains(&name)) {
+                *reached = true;
+                changed = true;
+                for child in routine.children() {
+                    references(child, used);
+                }
+            }
+        }
+        if !changed {
+            return;
+        }
+    }
+}
+
+fn routine_name(decl: &Node) -> Option<&str> {
+    match decl {
+        Node::ProcDecl(proc) => Some(&proc.name),
+        Node::FuncDecl(func) => Some(&func.name),
+        _ => None,
+    }
+}
+
+/// Every procedure and function declared in `node`, at any depth
+fn collect_routines<'a>(node: &'a Node, routines: &mut Vec<&'a Node>) {
+    if matches!(node, Node::ProcDecl(_) | Node::FuncDecl(_)) {
+        routines.push(node);
+    }
+    for child in node.children() {
+        collect_routines(child, routines);
+    }
+}
+
+/// Add the lowercase names `node` refers to, outside the routines declared
+/// in it, to `names`
+fn references(node: &Node, names: &mut HashSet<String>) {
+    match node {
+        Node::ProcDecl(_) | Node::FuncDecl(_) => return,
+        Node::IdentExpr(ident) => {
+            names.insert(ident.name.to_lowercase());
+        }
+        Node::CallExpr(call) => {
+            names.insert(call.name.to_lowercase());
+        }
+        Node::CallStmt(call) => {
+            names.insert(call.name.to_lowercase());
+        }
+        Node::MethodCall(call) => {
+            names.insert(call.method.to_lowercase());
+        }
+        Node::AsmStmt(asm) => names.extend(asm.references().into_iter().map(|(_, name)| name.to_lowercase())),
+        _ => {}
+    }
+    for child in node.children() {
+        references(child, names);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eliminate(source: &str) -> (Node, Vec<String>) {
+        let mut ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut eliminator = DeadCodeEliminator::new(None);
+        eliminator.eliminate(&mut ast);
+        let messages = eliminator.diagnostics().iter().map(|d| d.message.clone()).collect();
+        (ast, messages)
+    }
+
+    #[test]
+    fn test_unreachable_statements_and_unused_routines_are_removed() {
+        let (ast, messages) = eliminate(
+            "program P;
+             label 1;
+             var N: integer;
+             procedure Unused; begin end;
+             procedure OnlyAfterExit; begin end;
+             procedure Recursive(K: integer); begin if K > 0 then Recursive(K - 1) end;
+             function Log(K: integer): integer;
+               procedure Inner; begin Recursive(K) end;
+             begin
+               if K < 0 then Exit;
+               Inner;
+               begin Log := K; Exit end;
+               OnlyAfterExit;
+               N := 2
+             end;
+             begin
+               N := Log(1);
+               goto 1;
+               N := 3;
+               1: N := 4
+             end.",
+        );
+        assert_eq!(
+            messages,
+            [
+                "Procedure 'Unused' is never used",
+                "Procedure 'OnlyAfterExit' is never used",
+                "Unreachable code after Exit",
+                "Unreachable code after goto",
+            ]
+        );
+
+        let Node::Program(program) = &ast else { panic!() };
+        let Node::Block(block) = program.block.as_ref() else { panic!() };
+        let names: Vec<_> = block.proc_decls.iter().filter_map(routine_name).collect();
+        assert_eq!(names, ["Recursive"]);
+        assert_eq!(block.func_decls.len(), 1);
+        // The labeled statement after the goto stays
+        assert_eq!(block.statements.len(), 3);
+    }
+
+    #[test]
+    fn test_exported_routines_are_kept() {
+        let (_, messages) = eliminate(
+            "unit U;
+             interface
+               procedure Visible;
+             implementation
+               procedure Shared; begin end;
+               procedure Hidden; begin end;
+               procedure Visible; begin Shared end;
+             end.",
+        );
+        assert_eq!(messages, ["Procedure 'Hidden' is never used"]);
+    }
+}
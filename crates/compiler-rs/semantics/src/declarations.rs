@@ -1,27 +1,119 @@
 //! Declaration analysis (const, type, var, proc, func)
 
 use ast::Node;
-use symbols::{Parameter, ParameterMode, Symbol, SymbolKind};
+use symbols::{ConstantValue, Parameter, ParameterMode, Symbol, SymbolKind};
 use crate::SemanticAnalyzer;
+use crate::core;
+use ::types::Type;
 
 impl SemanticAnalyzer {
+    /// Report `name`, declared again at `span`, pointing at the declaration
+    /// it clashes with: in this file, or in the source of the unit that
+    /// exports it
+    pub(crate) fn add_redeclaration_error(&mut self, message: String, name: &str, span: tokens::Span) {
+        let mut diagnostic = self.core.error(message, span);
+        if let Some(first) = self.core.symbol_table.lookup_current_scope(name)
+            && first.span().line > 0
+        {
+            diagnostic = diagnostic.with_related_location(errors::RelatedLocation {
+                message: "first declared here".to_string(),
+                span: first.span(),
+                file: match self.unit_symbols.get(&name.to_lowercase()) {
+                    Some(path) => Some(path.display().to_string()),
+                    None => self.core.filename.clone(),
+                },
+            });
+        }
+        self.core.diagnostics.push(diagnostic);
+    }
+
     /// Analyze constant declaration
     pub(crate) fn analyze_const_decl(&mut self, decl: &Node) {
         if let Node::ConstDecl(c) = decl {
             // Check if constant already exists
             if self.core.symbol_table.exists_in_current_scope(&c.name) {
-                self.core.add_error(
-                    format!("Constant '{}' already declared", c.name),
-                    c.span,
-                );
+                self.add_redeclaration_error(format!("Constant '{}' already declared", c.name), &c.name, c.span);
                 return;
             }
 
             // Analyze the constant value expression
-            let const_type = self.analyze_expression(&c.value);
+            let mut const_type = self.analyze_expression(&c.value);
 
             // Evaluate constant value (constant folding)
-            let const_value = self.evaluate_constant_expression(&c.value);
+            let mut const_value = self.evaluate_constant_expression(&c.value);
+
+            // Typed constants are stored data with the declared type
+            if let Some(type_expr) = &c.type_expr {
+                let declared = self.analyze_type(type_expr);
+                // A generated table fits an array of the same elements and size
+                let mut reported = false;
+                let coerced = match (&const_value, &const_type, &declared) {
+                    (
+                        Some(value),
+                        Type::Array { element_type: from, size: from_size, .. },
+                        Type::Array { element_type: to, size: to_size, .. },
+                    ) if Self::is_generated_table(&c.value) => {
+                        if from.equals(to) && from_size == to_size {
+                            Some(Some(value.clone()))
+                        } else {
+                            let count = |ty: &Type| match ty {
+                                Type::Array { index_type, .. } => {
+                                    index_type.ordinal_bounds().map_or(0, |(low, high)| high - low + 1)
+                                }
+                                _ => 0,
+                            };
+                            self.core.add_error(
+                                format!(
+                                    "Type mismatch: constant '{}' holds {} elements of {}, the table has {} of {}",
+                                    c.name,
+                                    count(&declared),
+                                    core::CoreAnalyzer::format_type(to),
+                                    count(&const_type),
+                                    core::CoreAnalyzer::format_type(from)
+                                ),
+                                c.value.span(),
+                            );
+                            reported = true;
+                            Some(None)
+                        }
+                    }
+                    _ => const_value.as_ref().map(|value| self.coerce_constant(value, &declared)),
+                };
+                match coerced {
+                    Some(Some(value)) => {
+                        self.typed_constants.push(crate::TypedConstant {
+                            name: c.name.clone(),
+                            const_type: declared.clone(),
+                            value: value.clone(),
+                            span: c.span,
+                        });
+                        const_value = Some(value);
+                    }
+                    Some(None) if declared != Type::Error && !reported => self.core.add_error(
+                        format!(
+                            "Type mismatch: cannot initialize {} constant '{}' with {}",
+                            core::CoreAnalyzer::format_type(&declared),
+                            c.name,
+                            core::CoreAnalyzer::format_type(&const_type)
+                        ),
+                        c.value.span(),
+                    ),
+                    Some(None) => {}
+                    None => self.core.add_error(
+                        format!("Typed constant '{}' requires a constant value", c.name),
+                        c.value.span(),
+                    ),
+                }
+                const_type = declared;
+            } else if let Some(value @ ConstantValue::Bytes(_)) = &const_value {
+                // Byte arrays are stored data even without a declared type
+                self.typed_constants.push(crate::TypedConstant {
+                    name: c.name.clone(),
+                    const_type: const_type.clone(),
+                    value: value.clone(),
+                    span: c.span,
+                });
+            }
 
             // Create and insert symbol
             let symbol = Symbol {
@@ -40,15 +132,30 @@ impl SemanticAnalyzer {
         }
     }
 
+    fn is_generated_table(value: &Node) -> bool {
+        matches!(value, Node::CallExpr(call) if call.name.eq_ignore_ascii_case(crate::GENERATE_TABLE_INTRINSIC))
+    }
+
     /// Analyze type declaration
     pub(crate) fn analyze_type_decl(&mut self, decl: &Node) {
         if let Node::TypeDecl(t) = decl {
+            if let Node::ClassType(class) = t.type_expr.as_ref()
+                && !class.is_meta_class
+                && t.generic_params.is_empty()
+            {
+                self.analyze_class_decl(t, class);
+                return;
+            }
+            if let Node::InterfaceType(interface) = t.type_expr.as_ref()
+                && t.generic_params.is_empty()
+            {
+                self.analyze_interface_decl(t, interface);
+                return;
+            }
+
             // Check if type already exists
             if self.core.symbol_table.exists_in_current_scope(&t.name) {
-                self.core.add_error(
-                    format!("Type '{}' already declared", t.name),
-                    t.span,
-                );
+                self.add_redeclaration_error(format!("Type '{}' already declared", t.name), &t.name, t.span);
                 return;
             }
 
@@ -97,7 +204,10 @@ impl SemanticAnalyzer {
 
             // Non-generic type declaration
             // Analyze the type expression
-            let type_expr = self.analyze_type(&t.type_expr);
+            let mut type_expr = self.analyze_type(&t.type_expr);
+            if t.distinct {
+                type_expr = Type::distinct(t.name.clone(), type_expr);
+            }
 
             // Create and insert symbol
             let symbol = Symbol {
@@ -120,15 +230,22 @@ impl SemanticAnalyzer {
         if let Node::VarDecl(v) = decl {
             // Analyze the type
             let var_type = self.analyze_type(&v.type_expr);
+            let global_storage = self.core.symbol_table.is_global_scope() && v.absolute_address.is_none();
+            if let Some(address) = v
+                .absolute_address
+                .as_ref()
+                .and_then(|address| self.evaluate_constant_expression(address))
+                .and_then(|address| Self::integer_value(&address))
+                .and_then(|address| u16::try_from(address).ok())
+            {
+                self.absolute_addresses.push((v.span, address));
+            }
 
             // Create symbols for each variable name
             for name in &v.names {
                 // Check if variable already exists
                 if self.core.symbol_table.exists_in_current_scope(name) {
-                    self.core.add_error(
-                        format!("Variable '{}' already declared", name),
-                        v.span,
-                    );
+                    self.add_redeclaration_error(format!("Variable '{}' already declared", name), name, v.span);
                     continue;
                 }
 
@@ -143,38 +260,58 @@ impl SemanticAnalyzer {
 
                 if let Err(e) = self.core.symbol_table.insert(symbol) {
                     self.core.add_error(e, v.span);
+                } else if global_storage {
+                    self.global_variable_size += var_type.size().unwrap_or(2) as u32;
                 }
             }
         }
     }
 
-    /// Analyze procedure declaration
-    pub(crate) fn analyze_proc_decl(&mut self, decl: &Node) {
-        if let Node::ProcDecl(p) = decl {
-            // Check if procedure already exists
-            if self.core.symbol_table.exists_in_current_scope(&p.name) {
+    /// Analyze threadvar declaration: a variable with one copy per task
+    pub(crate) fn analyze_threadvar_decl(&mut self, decl: &Node) {
+        if let Node::VarDecl(v) = decl {
+            if !self.core.symbol_table.is_global_scope() {
                 self.core.add_error(
-                    format!("Procedure '{}' already declared", p.name),
-                    p.span,
+                    format!("Threadvar '{}' must be declared at program level", v.names.join(", ")),
+                    v.span,
                 );
                 return;
             }
+            // Names rejected as duplicates get no storage
+            let fresh: Vec<String> = v
+                .names
+                .iter()
+                .enumerate()
+                .filter(|(i, name)| {
+                    !self.core.symbol_table.exists_in_current_scope(name) && !v.names[..*i].contains(name)
+                })
+                .map(|(_, name)| name.clone())
+                .collect();
+            self.analyze_var_decl(decl);
+            for name in fresh {
+                let size = match self.core.symbol_table.lookup(&name).map(|s| &s.kind) {
+                    Some(SymbolKind::Variable { var_type, .. }) => var_type.size().unwrap_or(2) as u16,
+                    _ => 2,
+                };
+                let offset = self.threadvar_block_size();
+                self.threadvars.push(crate::ThreadVar { name, offset, size, span: v.span });
+            }
+        }
+    }
 
-            // Analyze parameters
-            let params = self.analyze_params(&p.params);
-
-            // Create symbol
-            let symbol = Symbol {
-                kind: SymbolKind::Procedure {
-                    name: p.name.clone(),
-                    params: params.clone(),
-                    span: p.span,
-                },
-                scope_level: self.core.symbol_table.scope_level(),
+    /// Analyze procedure declaration
+    pub(crate) fn analyze_proc_decl(&mut self, decl: &Node) {
+        if let Node::ProcDecl(p) = decl {
+            if let Some(class_name) = &p.class_name {
+                self.analyze_method_body(class_name, decl);
+                return;
+            }
+            let Some(params) = self.declare_procedure(p) else {
+                return;
             };
-
-            if let Err(e) = self.core.symbol_table.insert(symbol) {
-                self.core.add_error(e, p.span);
+            if p.is_forward {
+                self.add_forward(&p.name, p.span);
+                return;
             }
 
             // Analyze procedure body (enter new scope)
@@ -203,42 +340,22 @@ impl SemanticAnalyzer {
     /// Analyze function declaration
     pub(crate) fn analyze_func_decl(&mut self, decl: &Node) {
         if let Node::FuncDecl(f) = decl {
-            // Check if function already exists
-            if self.core.symbol_table.exists_in_current_scope(&f.name) {
-                self.core.add_error(
-                    format!("Function '{}' already declared", f.name),
-                    f.span,
-                );
+            if let Some(class_name) = &f.class_name {
+                self.analyze_method_body(class_name, decl);
                 return;
             }
-
-            // Analyze parameters
-            let params = self.analyze_params(&f.params);
-            let params_clone = params.clone();
-
-            // Analyze return type
-            let return_type = self.analyze_type(&f.return_type);
-            let return_type_clone = return_type.clone();
-
-            // Create symbol
-            let symbol = Symbol {
-                kind: SymbolKind::Function {
-                    name: f.name.clone(),
-                    params: params_clone.clone(),
-                    return_type: return_type_clone,
-                    span: f.span,
-                },
-                scope_level: self.core.symbol_table.scope_level(),
+            let Some((params, return_type)) = self.declare_function(f) else {
+                return;
             };
-
-            if let Err(e) = self.core.symbol_table.insert(symbol) {
-                self.core.add_error(e, f.span);
+            if f.is_forward {
+                self.add_forward(&f.name, f.span);
+                return;
             }
 
             // Analyze function body (enter new scope)
             self.core.symbol_table.enter_scope();
             // Add parameters to scope
-            for param in &params_clone {
+            for param in &params {
                 for name in &param.name.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>() {
                     if !name.is_empty() {
                         let param_symbol = Symbol {
@@ -253,8 +370,127 @@ impl SemanticAnalyzer {
                     }
                 }
             }
+            self.declare_result(&return_type, f.span);
+            self.functions.push((f.name.clone(), return_type.clone()));
             self.analyze_block(&f.block);
+            if f.is_compiletime {
+                self.declare_compile_time_function(f, &params, &return_type);
+            } else if params.len() == 1 {
+                self.declare_table_function(f, &params, &return_type);
+            }
+            self.functions.pop();
             self.core.symbol_table.exit_scope();
+
+            if !f.is_external {
+                self.check_result_assigned(&f.name, &f.block, f.span);
+            }
+        }
+    }
+
+    /// Declare a procedure from its heading and return its parameters, or
+    /// None when the name is already taken. The body of a routine declared
+    /// by an earlier forward or interface heading keeps that declaration.
+    pub(crate) fn declare_procedure(&mut self, p: &ast::ProcDecl) -> Option<Vec<Parameter>> {
+        let completes_forward = self.complete_forward(&p.name);
+        if !completes_forward && self.core.symbol_table.exists_in_current_scope(&p.name) {
+            self.add_redeclaration_error(format!("Procedure '{}' already declared", p.name), &p.name, p.span);
+            return None;
+        }
+
+        let params = self.analyze_params(&p.params);
+        if !completes_forward {
+            let symbol = Symbol {
+                kind: SymbolKind::Procedure {
+                    name: p.name.clone(),
+                    params: params.clone(),
+                    span: p.span,
+                },
+                scope_level: self.core.symbol_table.scope_level(),
+            };
+            if let Err(e) = self.core.symbol_table.insert(symbol) {
+                self.core.add_error(e, p.span);
+            }
+        }
+        Some(params)
+    }
+
+    /// Declare a function from its heading and return its parameters and
+    /// result type, or None when the name is already taken
+    pub(crate) fn declare_function(&mut self, f: &ast::FuncDecl) -> Option<(Vec<Parameter>, Type)> {
+        let completes_forward = self.complete_forward(&f.name);
+        if !completes_forward && self.core.symbol_table.exists_in_current_scope(&f.name) {
+            self.add_redeclaration_error(format!("Function '{}' already declared", f.name), &f.name, f.span);
+            return None;
+        }
+
+        let params = self.analyze_params(&f.params);
+        let return_type = self.analyze_type(&f.return_type);
+        if !completes_forward {
+            let symbol = Symbol {
+                kind: SymbolKind::Function {
+                    name: f.name.clone(),
+                    params: params.clone(),
+                    return_type: return_type.clone(),
+                    span: f.span,
+                },
+                scope_level: self.core.symbol_table.scope_level(),
+            };
+            if let Err(e) = self.core.symbol_table.insert(symbol) {
+                self.core.add_error(e, f.span);
+            }
+        }
+        Some((params, return_type))
+    }
+
+    /// Record a routine heading whose body comes later in the same scope
+    pub(crate) fn add_forward(&mut self, name: &str, span: tokens::Span) {
+        let scope_level = self.core.symbol_table.scope_level();
+        self.forward_routines.push((name.to_string(), scope_level, span));
+    }
+
+    /// Whether `name` has a heading in the current scope awaiting its body;
+    /// if so the body is now found
+    fn complete_forward(&mut self, name: &str) -> bool {
+        let scope_level = self.core.symbol_table.scope_level();
+        let found = self.forward_routines.iter().position(|(n, level, _)| n == name && *level == scope_level);
+        found.map(|index| self.forward_routines.remove(index)).is_some()
+    }
+
+    /// Report headings of the current scope whose bodies never appeared
+    pub(crate) fn check_forwards_resolved(&mut self) {
+        let scope_level = self.core.symbol_table.scope_level();
+        let (open, outer): (Vec<_>, Vec<_>) = std::mem::take(&mut self.forward_routines)
+            .into_iter()
+            .partition(|(_, level, _)| *level == scope_level);
+        self.forward_routines = outer;
+        for (name, _, span) in open {
+            self.core.add_error(format!("Routine '{}' is declared but has no body", name), span);
+        }
+    }
+
+    /// Declare the implicit `Result` variable of a function body. A parameter
+    /// named `Result` takes precedence.
+    pub(crate) fn declare_result(&mut self, return_type: &Type, span: tokens::Span) {
+        let symbol = Symbol {
+            kind: SymbolKind::Variable {
+                name: crate::RESULT_VARIABLE.to_string(),
+                var_type: return_type.clone(),
+                span,
+            },
+            scope_level: self.core.symbol_table.scope_level(),
+        };
+        let _ = self.core.symbol_table.insert(symbol);
+    }
+
+    /// Warn when some path through a function body leaves without assigning
+    /// `Result` (or the function name)
+    pub(crate) fn check_result_assigned(&mut self, name: &str, block: &Node, span: tokens::Span) {
+        let mut escapes = false;
+        if !Self::assigns_result(block, name, false, &mut escapes) || escapes {
+            self.add_key_warning(
+                format!("Function '{}' might not assign its result on every path", name),
+                span,
+            );
         }
     }
 
@@ -262,8 +498,11 @@ impl SemanticAnalyzer {
     pub(crate) fn analyze_params(&mut self, params: &[ast::Param]) -> Vec<Parameter> {
         params
             .iter()
-            .map(|p| {
-                let param_type = self.analyze_type(&p.type_expr);
+            .flat_map(|p| {
+                let param_type = match &p.type_expr {
+                    Some(type_expr) => self.analyze_type(type_expr),
+                    None => Type::Untyped,
+                };
                 let passing_mode = match p.param_type {
                     ast::ParamType::Value => ParameterMode::Value,
                     ast::ParamType::Var => ParameterMode::Var,
@@ -271,12 +510,16 @@ impl SemanticAnalyzer {
                     ast::ParamType::ConstRef => ParameterMode::Const, // ConstRef is similar to Const
                     ast::ParamType::Out => ParameterMode::Var,        // Out is similar to Var (reference)
                 };
-                Parameter {
-                    name: p.names.join(", "), // Join multiple names
-                    param_type,
-                    passing_mode,
-                    span: p.span,
-                }
+                // One parameter per name: `A, B: Word` takes two arguments
+                p.names
+                    .iter()
+                    .map(|name| Parameter {
+                        name: name.clone(),
+                        param_type: param_type.clone(),
+                        passing_mode,
+                        span: p.span,
+                    })
+                    .collect::<Vec<_>>()
             })
             .collect()
     }
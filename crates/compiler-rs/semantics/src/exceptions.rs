@@ -0,0 +1,98 @@
+//! Exception analysis: `try ... except`, `try ... finally` and `raise`
+//!
+//! An exception is an ordinal or pointer value. Handlers match it by the
+//! name of its declared type, so `type EParse = Word; EFile = Word;` are
+//! distinct exceptions raised as `raise EParse(Line)`.
+
+use ast::Node;
+use ::types::Type;
+use symbols::{Symbol, SymbolKind};
+use crate::SemanticAnalyzer;
+use crate::core;
+
+impl SemanticAnalyzer {
+    /// Analyze a try statement; handlers bind their variable in a scope of
+    /// their own
+    pub(crate) fn analyze_try_stmt(&mut self, try_stmt: &ast::TryStmt) {
+        for stmt in &try_stmt.try_block {
+            self.analyze_statement(stmt);
+        }
+
+        self.except_depth += 1;
+        for stmt in try_stmt.except_block.iter().flatten() {
+            self.analyze_statement(stmt);
+        }
+        for handler in &try_stmt.exception_handlers {
+            let handler_type = self.analyze_exception_type(&handler.exception_type);
+            self.core.symbol_table.enter_scope();
+            if let Some(variable) = &handler.variable {
+                let symbol = Symbol {
+                    kind: SymbolKind::Variable { name: variable.clone(), var_type: handler_type, span: handler.span },
+                    scope_level: self.core.symbol_table.scope_level(),
+                };
+                let _ = self.core.symbol_table.insert(symbol);
+            }
+            self.analyze_statement(&handler.handler);
+            self.core.symbol_table.exit_scope();
+        }
+        if let Some(else_stmt) = &try_stmt.exception_else {
+            self.analyze_statement(else_stmt);
+        }
+        self.except_depth -= 1;
+
+        for stmt in try_stmt.finally_block.iter().flatten() {
+            self.analyze_statement(stmt);
+        }
+    }
+
+    /// Type of `on E: T do`: a type name of an ordinal or pointer type
+    fn analyze_exception_type(&mut self, type_expr: &Node) -> Type {
+        if !matches!(type_expr, Node::NamedType(n) if n.generic_args.is_empty()) {
+            self.core.add_error("Exception type must be a type name".to_string(), type_expr.span());
+            return Type::Error;
+        }
+        let exception_type = self.analyze_type(type_expr);
+        if Self::is_raisable(&exception_type) {
+            return exception_type;
+        }
+        self.core.add_error(
+            format!(
+                "Exception type must be an ordinal or pointer type, found {}",
+                core::CoreAnalyzer::format_type(&exception_type)
+            ),
+            type_expr.span(),
+        );
+        Type::Error
+    }
+
+    /// Analyze `raise E`, or a bare `raise` re-raising the exception being
+    /// handled
+    pub(crate) fn analyze_raise_stmt(&mut self, raise: &ast::RaiseStmt) {
+        let Some(exception) = &raise.exception else {
+            if self.except_depth == 0 {
+                self.core.add_error(
+                    "Raise without an exception is only allowed in an exception handler".to_string(),
+                    raise.span,
+                );
+            }
+            return;
+        };
+        let exception_type = self.analyze_expression(exception);
+        if !Self::is_raisable(&exception_type) {
+            self.core.add_error(
+                format!(
+                    "Raise requires an ordinal or pointer value, found {}",
+                    core::CoreAnalyzer::format_type(&exception_type)
+                ),
+                exception.span(),
+            );
+        }
+    }
+
+    /// Whether values of `ty` can be raised: they fit the 16-bit exception
+    /// value
+    fn is_raisable(ty: &Type) -> bool {
+        let fits = ty.size().is_some_and(|size| size <= 2);
+        (ty.is_ordinal() && fits) || matches!(ty, Type::Pointer { .. } | Type::UntypedPointer)
+    }
+}
@@ -2,23 +2,554 @@
 
 use std::collections::HashSet;
 use ast::Node;
-use symbols::{Symbol, SymbolKind};
-use ::types::Type;
+use symbols::{ConstantValue, Symbol, SymbolKind};
+use ::types::{PrimitiveType, Type};
 use crate::SemanticAnalyzer;
 use crate::core;
+use crate::stack_usage;
 
 impl SemanticAnalyzer {
+    /// Type of `@Routine`: its 16-bit code address (e.g. for interrupt handlers)
+    fn routine_address_type(&self, target: &Node) -> Option<Type> {
+        let Node::IdentExpr(ident) = target else {
+            return None;
+        };
+        let symbol = self.core.symbol_table.lookup(&ident.name)?;
+        match symbol.kind {
+            SymbolKind::Procedure { .. } | SymbolKind::Function { .. } => Some(Type::word()),
+            _ => None,
+        }
+    }
+
+    /// Type of `@x` for a variable of type `target`
+    fn address_type(target: Type) -> Type {
+        match target {
+            // The address of an untyped parameter has no element type either
+            Type::Untyped => Type::UntypedPointer,
+            target => Type::pointer(target),
+        }
+    }
+
+    /// Analyze `TaskStackSize(Routine)`: the stack a task running the routine needs
+    fn analyze_task_stack_size(&mut self, call: &ast::CallExpr) -> Type {
+        let [entry] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("{} expects 1 argument, found {}", stack_usage::TASK_STACK_SIZE_INTRINSIC, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        };
+        if self.routine_address_type(entry).is_none() {
+            self.core.add_error(
+                format!("{} expects a procedure or function name", stack_usage::TASK_STACK_SIZE_INTRINSIC),
+                entry.span(),
+            );
+            return Type::Error;
+        }
+        if let (Node::IdentExpr(ident), Some(usage)) = (entry, &self.stack_usage)
+            && let Err(message) = usage.task_stack_size(&ident.name)
+        {
+            self.core.add_error(message, entry.span());
+            return Type::Error;
+        }
+        Type::word()
+    }
+
+    /// Analyze `SizeOf(x)` for a variable or declared type
+    fn analyze_sizeof(&mut self, call: &ast::CallExpr) -> Type {
+        let [value] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("{} expects 1 argument, found {}", crate::SIZEOF_INTRINSIC, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        };
+        let value_type = match value {
+            Node::IdentExpr(ident) if let Some(named) = self.typecast_target(&ident.name) => named,
+            _ => self.analyze_expression(value),
+        };
+        if value_type == Type::Untyped {
+            self.core.add_error(
+                "SizeOf is not available for an untyped parameter; pass the size separately".to_string(),
+                value.span(),
+            );
+            return Type::Error;
+        }
+        Type::word()
+    }
+
+    /// Type of an intrinsic that takes no arguments, such as `ParamCount`,
+    /// or None if `name` is not one
+    fn analyze_nullary_intrinsic(&mut self, name: &str) -> Option<Type> {
+        if name.eq_ignore_ascii_case(crate::PARAM_COUNT_INTRINSIC) {
+            self.uses_params = true;
+        } else if name.eq_ignore_ascii_case(crate::GET_TICKS_INTRINSIC)
+            || name.eq_ignore_ascii_case(crate::TICKS_PER_SECOND_INTRINSIC)
+        {
+            self.uses_timer = true;
+        } else if name.eq_ignore_ascii_case(crate::ERROR_CODE_INTRINSIC) {
+            self.uses_error_code = true;
+        } else {
+            return None;
+        }
+        Some(Type::word())
+    }
+
+    /// Analyze `ParamStr(i)`: parameter `i` as a string whose element 0 holds its length
+    fn analyze_param_str(&mut self, call: &ast::CallExpr) -> Type {
+        let [index] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("{} expects 1 argument, found {}", crate::PARAM_STR_INTRINSIC, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        };
+        let index_type = self.analyze_expression(index);
+        if !index_type.is_ordinal() {
+            self.core.add_error(
+                format!(
+                    "{} expects an ordinal index, found {}",
+                    crate::PARAM_STR_INTRINSIC,
+                    core::CoreAnalyzer::format_type(&index_type)
+                ),
+                index.span(),
+            );
+            return Type::Error;
+        }
+        self.uses_params = true;
+        Type::array(Type::byte(), Type::char())
+    }
+
+    /// Analyze the index of a static array access: an enumeration or
+    /// subrange index type takes only its own values, and a constant index
+    /// must lie within its bounds
+    pub(crate) fn analyze_array_index(&mut self, index: &Node, index_type: &Type) {
+        let value_type = self.analyze_expression(index);
+        if value_type == Type::Error {
+            return;
+        }
+        if matches!(index_type, Type::Subrange { .. } | Type::Enum { .. }) && !value_type.is_assignable_to(index_type) {
+            self.core.add_error(
+                format!(
+                    "Array index must be {}, found {}",
+                    core::CoreAnalyzer::format_type(index_type),
+                    core::CoreAnalyzer::format_type(&value_type)
+                ),
+                index.span(),
+            );
+            return;
+        }
+        self.check_constant_in_range(index, index_type);
+    }
+
+    /// Element type of `Port` (Byte) or `PortW` (Word) when `array` names
+    /// one of them and no declaration hides it
+    pub(crate) fn port_type(&self, array: &Node) -> Option<Type> {
+        let Node::IdentExpr(ident) = array else {
+            return None;
+        };
+        if self.core.symbol_table.lookup(&ident.name).is_some() {
+            return None;
+        }
+        if ident.name.eq_ignore_ascii_case(crate::PORT_INTRINSIC) {
+            Some(Type::byte())
+        } else if ident.name.eq_ignore_ascii_case(crate::PORTW_INTRINSIC) {
+            Some(Type::word())
+        } else {
+            None
+        }
+    }
+
+    /// Analyze `Port[address]` or `PortW[address]`; None for other indexing
+    pub(crate) fn analyze_port(&mut self, idx: &ast::IndexExpr) -> Option<Type> {
+        let element_type = self.port_type(&idx.array)?;
+        let address_type = self.analyze_expression(&idx.index);
+        if !address_type.is_assignable_to(&Type::integer()) {
+            self.core.add_error(
+                format!("Port address must be an integer, found {}", core::CoreAnalyzer::format_type(&address_type)),
+                idx.index.span(),
+            );
+        }
+        Some(element_type)
+    }
+
+    /// Check an argument against its parameter. Untyped parameters take the
+    /// address of any variable, whatever its type.
+    pub(crate) fn check_argument(&mut self, arg: &Node, param: &symbols::Parameter) {
+        if param.param_type == Type::Untyped {
+            if !matches!(arg, Node::IdentExpr(_) | Node::IndexExpr(_) | Node::FieldExpr(_) | Node::DerefExpr(_)) {
+                self.core.add_error(
+                    format!("Untyped parameter '{}' requires a variable argument", param.name),
+                    arg.span(),
+                );
+            }
+            self.analyze_expression(arg);
+            return;
+        }
+        let arg_type = self.analyze_expression(arg);
+        if !arg_type.is_assignable_to(&param.param_type)
+            && !self.constant_fits(arg, &param.param_type)
+            && !self.converts_to_interface(&arg_type, &param.param_type)
+            && !self.converts_to_ancestor(&arg_type, &param.param_type)
+        {
+            self.core.add_error(
+                format!(
+                    "Argument type mismatch: expected {}, found {}",
+                    core::CoreAnalyzer::format_type(&param.param_type),
+                    core::CoreAnalyzer::format_type(&arg_type)
+                ),
+                arg.span(),
+            );
+        }
+    }
+
+    /// Type named by `name` when it is used as a typecast, e.g. `THandle(0)`
+    pub(crate) fn typecast_target(&self, name: &str) -> Option<Type> {
+        match &self.core.symbol_table.lookup(name)?.kind {
+            SymbolKind::TypeAlias { aliased_type, .. } => Some(aliased_type.clone()),
+            _ => None,
+        }
+    }
+
+    /// Analyze `T(value)`: converts between ordinal types, or reinterprets a
+    /// value of the same size, such as a distinct type and its base
+    fn analyze_typecast(&mut self, call: &ast::CallExpr, target: Type) -> Type {
+        let [value] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("Typecast to '{}' expects 1 argument, found {}", call.name, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        };
+        let source = self.analyze_expression(value);
+        let ordinal = source.is_ordinal() && target.is_ordinal();
+        // An untyped parameter is viewed as any type of known size
+        let untyped = source == Type::Untyped && target.size().is_some();
+        if !ordinal && !untyped && (source.size().is_none() || source.size() != target.size()) && source != Type::Error {
+            self.core.add_error(
+                format!(
+                    "Invalid typecast from {} to {}",
+                    core::CoreAnalyzer::format_type(&source),
+                    core::CoreAnalyzer::format_type(&target)
+                ),
+                call.span,
+            );
+            return Type::Error;
+        }
+        target
+    }
+
+    /// Type of an arithmetic or bitwise operation on integers: that of the
+    /// left operand, when the right one is assignable to it or is a constant
+    /// in its range (`B and $0F` stays a Byte). A narrower left operand
+    /// widens to a 32-bit right one (`I + L` is a LongInt).
+    fn integer_operation_type(&self, bin: &ast::BinaryExpr, left_type: &Type, right_type: &Type) -> Option<Type> {
+        let same_or_narrower = [Type::longint(), Type::cardinal(), Type::integer(), Type::word(), Type::byte()]
+            .into_iter()
+            .find(|t| left_type.equals(t) && (right_type.is_assignable_to(t) || self.constant_fits(&bin.right, t)));
+        same_or_narrower.or_else(|| {
+            [Type::longint(), Type::cardinal()]
+                .into_iter()
+                .find(|t| right_type.equals(t) && (left_type.is_assignable_to(t) || self.constant_fits(&bin.left, t)))
+        })
+    }
+
+    /// Type of `+`, `-`, `*` or `/` with a real operand: Real, when the other
+    /// operand is a real or an integer (promoted)
+    fn real_operation_type(bin: &ast::BinaryExpr, left_type: &Type, right_type: &Type) -> Option<Type> {
+        let real = Type::real();
+        let arithmetic = matches!(
+            bin.op,
+            ast::BinaryOp::Add | ast::BinaryOp::Subtract | ast::BinaryOp::Multiply | ast::BinaryOp::Divide
+        );
+        let operands = (left_type.equals(&real) || right_type.equals(&real))
+            && left_type.is_assignable_to(&real)
+            && right_type.is_assignable_to(&real);
+        (arithmetic && operands).then_some(real)
+    }
+
+    /// Analyze `Trunc(x)` or `Round(x)`: a real (or integer) to Integer
+    fn analyze_real_to_integer(&mut self, call: &ast::CallExpr) -> Type {
+        let [value] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("{} expects 1 argument, found {}", call.name, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        };
+        let value_type = self.analyze_expression(value);
+        if !value_type.is_assignable_to(&Type::real()) && value_type != Type::Error {
+            self.core.add_error(
+                format!(
+                    "{} requires a real argument, found {}",
+                    call.name,
+                    core::CoreAnalyzer::format_type(&value_type)
+                ),
+                call.span,
+            );
+            return Type::Error;
+        }
+        Type::integer()
+    }
+
+    /// Analyze `Ord(x)`, `Succ(x)` or `Pred(x)`. `Ord` of an enumeration,
+    /// Char or Boolean is an Integer, and an integer is its own ordinal;
+    /// the others keep the type of `x`.
+    fn analyze_ordinal_function(&mut self, call: &ast::CallExpr) -> Type {
+        let [value] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("{} expects 1 argument, found {}", call.name, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        };
+        let value_type = self.analyze_expression(value);
+        if value_type == Type::Error {
+            return Type::Error;
+        }
+        if !value_type.is_ordinal() {
+            self.core.add_error(
+                format!(
+                    "{} requires an ordinal argument, found {}",
+                    call.name,
+                    core::CoreAnalyzer::format_type(&value_type)
+                ),
+                call.span,
+            );
+            return Type::Error;
+        }
+        self.check_character_ordinal(value);
+        if call.name.eq_ignore_ascii_case(crate::ORD_INTRINSIC) {
+            let is_integer = matches!(
+                value_type.representation(),
+                Type::Primitive(
+                    PrimitiveType::Integer
+                        | PrimitiveType::Byte
+                        | PrimitiveType::Word
+                        | PrimitiveType::LongInt
+                        | PrimitiveType::Cardinal
+                )
+            );
+            return if is_integer { value_type } else { Type::integer() };
+        }
+
+        // The first value has no predecessor and the last no successor
+        if let (Type::Enum { values }, Some(ConstantValue::Integer(ordinal))) =
+            (value_type.representation(), self.evaluate_constant_expression(value))
+        {
+            let is_succ = call.name.eq_ignore_ascii_case(crate::SUCC_INTRINSIC);
+            let ordinal = ordinal as usize;
+            if (is_succ && ordinal + 1 >= values.len()) || (!is_succ && ordinal == 0) {
+                self.core.add_error(
+                    format!(
+                        "{}({}) is out of the range of {}",
+                        call.name,
+                        values.get(ordinal).map_or("?", |v| v.as_str()),
+                        core::CoreAnalyzer::format_type(&value_type)
+                    ),
+                    call.span,
+                );
+                return Type::Error;
+            }
+        }
+        value_type
+    }
+
+    /// Analyze `p + n`, `p - n` or `p - q`: pointers move by whole elements,
+    /// and only under {$POINTERMATH ON}
+    fn analyze_pointer_arithmetic(&mut self, bin: &ast::BinaryExpr, left_type: Type, right_type: &Type) -> Type {
+        let is_integer = right_type.is_assignable_to(&Type::integer()) || right_type.is_assignable_to(&Type::word());
+        let result = match bin.op {
+            ast::BinaryOp::Add | ast::BinaryOp::Subtract if is_integer => left_type.clone(),
+            ast::BinaryOp::Subtract if right_type.equals(&left_type) => Type::integer(),
+            _ => {
+                self.core.add_error(
+                    format!(
+                        "Invalid pointer arithmetic on {} and {}",
+                        core::CoreAnalyzer::format_type(&left_type),
+                        core::CoreAnalyzer::format_type(right_type)
+                    ),
+                    bin.span,
+                );
+                return Type::Error;
+            }
+        };
+        if let Err(message) = Self::pointer_step(&left_type) {
+            self.core.add_error(message, bin.span);
+            return Type::Error;
+        }
+        if !self.pointer_math {
+            self.core.add_error(
+                format!("Pointer arithmetic requires {{${} ON}}; use Inc or Dec", crate::POINTER_MATH_SWITCH),
+                bin.span,
+            );
+            return Type::Error;
+        }
+        result
+    }
+
+    /// Analyze a set literal such as `[1, 3..5]`: the elements are ordinals
+    /// of one type. Integer elements have no set type of their own, so the
+    /// literal is a set of the subrange its constant elements span, or of
+    /// 0..255 if some are variables; `[]` is a set of Error, which fits any
+    /// set.
+    fn analyze_set_literal(&mut self, set: &ast::SetLiteral) -> Type {
+        let elements = set.elements.iter().flat_map(|element| match element {
+            ast::SetElement::Value(value) => vec![value.as_ref()],
+            ast::SetElement::Range { start, end } => vec![start.as_ref(), end.as_ref()],
+        });
+        let mut element_type: Option<Type> = None;
+        let mut bounds: Option<(i64, i64)> = None;
+        let mut all_constant = true;
+        for element in elements.collect::<Vec<_>>() {
+            let ty = self.analyze_expression(element).subrange_base().clone();
+            if ty == Type::Error {
+                return Type::Error;
+            }
+            if !ty.is_ordinal() {
+                self.core.add_error(
+                    format!("Set elements must be ordinal, found {}", core::CoreAnalyzer::format_type(&ty)),
+                    element.span(),
+                );
+                return Type::Error;
+            }
+            element_type = match element_type {
+                None => Some(ty),
+                Some(previous) if ty.is_assignable_to(&previous) => Some(previous),
+                Some(previous) if previous.is_assignable_to(&ty) => Some(ty),
+                Some(previous) => {
+                    self.core.add_error(
+                        format!(
+                            "Set elements must have the same type, found {} and {}",
+                            core::CoreAnalyzer::format_type(&previous),
+                            core::CoreAnalyzer::format_type(&ty)
+                        ),
+                        element.span(),
+                    );
+                    return Type::Error;
+                }
+            };
+            match self.evaluate_constant_expression(element).as_ref().and_then(Self::constant_ordinal) {
+                Some(value) if !(0..=::types::MAX_SET_ORDINAL).contains(&value) => {
+                    self.core.add_error(
+                        format!("Set element {} is out of the range 0..{}", value, ::types::MAX_SET_ORDINAL),
+                        element.span(),
+                    );
+                    return Type::Error;
+                }
+                Some(value) => {
+                    bounds = Some(bounds.map_or((value, value), |(low, high)| (low.min(value), high.max(value))));
+                }
+                None => all_constant = false,
+            }
+        }
+        let Some(element_type) = element_type else {
+            return Type::set(Type::Error);
+        };
+        let set_type = Type::set(element_type.clone());
+        if set_type.set_layout().is_some() {
+            return set_type;
+        }
+        let (low, high) = match bounds {
+            Some(bounds) if all_constant => bounds,
+            _ => (0, ::types::MAX_SET_ORDINAL),
+        };
+        Type::set(Type::subrange(element_type, low, high))
+    }
+
+    /// Type of `a + b`, `a - b` or `a * b` on sets, or of the set `a` and `b`
+    /// are compared as: the common element type, or for integer elements
+    /// the subrange covering both
+    fn set_operation_type(&mut self, bin: &ast::BinaryExpr, left_type: &Type, right_type: &Type) -> Type {
+        let (Type::Set { element_type: left }, Type::Set { element_type: right }) =
+            (left_type.representation(), right_type.representation())
+        else {
+            self.core.add_error(
+                format!(
+                    "Set operation requires two sets, found {} and {}",
+                    core::CoreAnalyzer::format_type(left_type),
+                    core::CoreAnalyzer::format_type(right_type)
+                ),
+                bin.span,
+            );
+            return Type::Error;
+        };
+        if !left_type.is_assignable_to(right_type) {
+            self.core.add_error(
+                format!(
+                    "Set operation requires compatible sets, found {} and {}",
+                    core::CoreAnalyzer::format_type(left_type),
+                    core::CoreAnalyzer::format_type(right_type)
+                ),
+                bin.span,
+            );
+            return Type::Error;
+        }
+        if **right == Type::Error || left.equals(right) {
+            return left_type.clone();
+        }
+        if **left == Type::Error {
+            return right_type.clone();
+        }
+        match (left.ordinal_bounds(), right.ordinal_bounds()) {
+            (Some((low1, high1)), Some((low2, high2))) => {
+                Type::set(Type::subrange(left.subrange_base().clone(), low1.min(low2), high1.max(high2)))
+            }
+            _ => Type::Error,
+        }
+    }
+
+    /// Analyze `x in s`: `s` is a set and `x` an ordinal its elements can hold
+    fn analyze_in(&mut self, bin: &ast::BinaryExpr, left_type: &Type, right_type: &Type) -> Type {
+        if *right_type == Type::Error {
+            return Type::Error;
+        }
+        if !matches!(right_type.representation(), Type::Set { .. }) {
+            self.core.add_error(
+                format!("Right operand of IN must be a set, found {}", core::CoreAnalyzer::format_type(right_type)),
+                bin.span,
+            );
+            return Type::Error;
+        }
+        if !left_type.is_ordinal() || !Type::set(left_type.clone()).is_assignable_to(right_type) {
+            self.core.add_error(
+                format!(
+                    "Left operand of IN must be an element of {}, found {}",
+                    core::CoreAnalyzer::format_type(right_type),
+                    core::CoreAnalyzer::format_type(left_type)
+                ),
+                bin.span,
+            );
+            return Type::Error;
+        }
+        Type::boolean()
+    }
+
     /// Analyze expression
     pub(crate) fn analyze_expression(&mut self, expr: &Node) -> Type {
         match expr {
             Node::LiteralExpr(lit) => match &lit.value {
+                // Literals beyond a Word need 32 bits
+                ast::LiteralValue::Integer(i) if *i > u16::MAX as u32 => {
+                    if *i > i32::MAX as u32 { Type::cardinal() } else { Type::longint() }
+                }
                 ast::LiteralValue::Integer(_) => Type::integer(),
+                ast::LiteralValue::Real(_) => Type::real(),
                 ast::LiteralValue::Boolean(_) => Type::boolean(),
                 ast::LiteralValue::Char(_) => Type::char(),
-                ast::LiteralValue::String(_) => {
+                ast::LiteralValue::String(text) => {
+                    if !self.string_literals.contains(text) {
+                        self.string_literals.push(text.clone());
+                    }
                     // String literals are arrays of char
                     Type::array(Type::integer(), Type::char())
                 }
+                // Byte array literals are arrays of byte of known size
+                ast::LiteralValue::Bytes(bytes) => Type::Array {
+                    index_type: Box::new(Type::integer()),
+                    element_type: Box::new(Type::byte()),
+                    size: Some(bytes.len()),
+                },
             },
             Node::IdentExpr(i) => {
                 if let Some(symbol) = self.core.symbol_table.lookup(&i.name) {
@@ -34,6 +565,8 @@ impl SemanticAnalyzer {
                             Type::Error
                         }
                     }
+                } else if let Some(result) = self.analyze_nullary_intrinsic(&i.name) {
+                    result
                 } else {
                     self.core.add_error(
                         format!("Identifier '{}' not found", i.name),
@@ -42,18 +575,61 @@ impl SemanticAnalyzer {
                     Type::Error
                 }
             }
+            Node::BinaryExpr(bin)
+                if matches!(bin.op, ast::BinaryOp::Is | ast::BinaryOp::As)
+                    && let Some(interface) = self.interface_type_name(&bin.right) =>
+            {
+                self.analyze_interface_query(bin, &interface)
+            }
             Node::BinaryExpr(bin) => {
-                let left_type = self.analyze_expression(&bin.left);
-                let right_type = self.analyze_expression(&bin.right);
+                let left_type = self.analyze_expression(&bin.left).subrange_base().clone();
+                let right_type = self.analyze_expression(&bin.right).subrange_base().clone();
+                self.check_constant_overflow(expr);
+                if matches!(
+                    bin.op,
+                    ast::BinaryOp::Less | ast::BinaryOp::LessEqual | ast::BinaryOp::Greater | ast::BinaryOp::GreaterEqual
+                ) {
+                    self.check_character_ordinal(&bin.left);
+                    self.check_character_ordinal(&bin.right);
+                }
 
                 match bin.op {
                     ast::BinaryOp::Add | ast::BinaryOp::Subtract | ast::BinaryOp::Multiply
                     | ast::BinaryOp::Divide | ast::BinaryOp::Div | ast::BinaryOp::Mod => {
-                        // Arithmetic operations
-                        if left_type.equals(&Type::integer()) && right_type.is_assignable_to(&Type::integer()) {
-                            Type::integer()
-                        } else if left_type.equals(&Type::word()) && right_type.is_assignable_to(&Type::word()) {
-                            Type::word()
+                        // Arithmetic operations; a distinct type only combines with itself
+                        if matches!(left_type.representation(), Type::Set { .. }) {
+                            if matches!(bin.op, ast::BinaryOp::Add | ast::BinaryOp::Subtract | ast::BinaryOp::Multiply) {
+                                self.set_operation_type(bin, &left_type, &right_type)
+                            } else {
+                                self.core.add_error(
+                                    "Sets only support '+', '-' and '*'".to_string(),
+                                    bin.span,
+                                );
+                                Type::Error
+                            }
+                        } else if matches!(left_type, Type::Pointer { .. } | Type::UntypedPointer) {
+                            self.analyze_pointer_arithmetic(bin, left_type, &right_type)
+                        } else if bin.op == ast::BinaryOp::Add
+                            && let Some(result) = Self::string_concat_type(&left_type, &right_type)
+                        {
+                            result
+                        } else if let Type::Distinct { base, .. } = &left_type
+                            && right_type.equals(&left_type)
+                            && matches!(base.representation(), Type::Primitive(_))
+                        {
+                            left_type
+                        } else if let Some(result) = self.integer_operation_type(bin, &left_type, &right_type) {
+                            result
+                        } else if let Some(result) = Self::real_operation_type(bin, &left_type, &right_type) {
+                            result
+                        } else if matches!(bin.op, ast::BinaryOp::Div | ast::BinaryOp::Mod)
+                            && (left_type.equals(&Type::real()) || right_type.equals(&Type::real()))
+                        {
+                            self.core.add_error(
+                                "'div' and 'mod' require integer operands; use '/' for reals".to_string(),
+                                bin.span,
+                            );
+                            Type::Error
                         } else {
                             self.core.add_error(
                                 format!(
@@ -68,8 +644,22 @@ impl SemanticAnalyzer {
                     }
                     ast::BinaryOp::Equal | ast::BinaryOp::NotEqual | ast::BinaryOp::Less
                     | ast::BinaryOp::LessEqual | ast::BinaryOp::Greater | ast::BinaryOp::GreaterEqual => {
-                        // Comparison operations return boolean
-                        if left_type.is_assignable_to(&right_type) || right_type.is_assignable_to(&left_type) {
+                        // Comparison operations return boolean; sets compare for
+                        // equality and inclusion
+                        if matches!(left_type.representation(), Type::Set { .. }) {
+                            if matches!(bin.op, ast::BinaryOp::Less | ast::BinaryOp::Greater) {
+                                self.core.add_error(
+                                    "Sets can only be compared with '=', '<>', '<=' and '>='".to_string(),
+                                    bin.span,
+                                );
+                                Type::Error
+                            } else if self.set_operation_type(bin, &left_type, &right_type) == Type::Error {
+                                Type::Error
+                            } else {
+                                Type::boolean()
+                            }
+                        } else if left_type.is_assignable_to(&right_type) || right_type.is_assignable_to(&left_type) {
+                            self.check_constant_comparison(bin, &left_type, &right_type);
                             Type::boolean()
                         } else {
                             self.core.add_error(
@@ -84,22 +674,20 @@ impl SemanticAnalyzer {
                         }
                     }
                     ast::BinaryOp::And | ast::BinaryOp::Or => {
-                        // Logical operations
+                        // Logical operations, or bitwise on integers
                         if left_type.equals(&Type::boolean()) && right_type.equals(&Type::boolean()) {
                             Type::boolean()
+                        } else if let Some(result) = self.integer_operation_type(bin, &left_type, &right_type) {
+                            result
                         } else {
                             self.core.add_error(
-                                "Logical operations require boolean operands".to_string(),
+                                "Logical operations require boolean or integer operands".to_string(),
                                 bin.span,
                             );
                             Type::Error
                         }
                     }
-                    ast::BinaryOp::In => {
-                        // Set membership: left IN right (right must be a set type)
-                        // For now, return boolean (proper type checking would verify right is a set)
-                        Type::boolean()
-                    }
+                    ast::BinaryOp::In => self.analyze_in(bin, &left_type, &right_type),
                     ast::BinaryOp::Is => {
                         // Type checking: left IS right (right must be a type)
                         // Returns boolean
@@ -114,11 +702,22 @@ impl SemanticAnalyzer {
                 }
             }
             Node::UnaryExpr(unary) => {
+                if unary.op == ast::UnaryOp::AddressOf
+                    && let Some(address_type) = self.routine_address_type(&unary.expr)
+                {
+                    return address_type;
+                }
                 let expr_type = self.analyze_expression(&unary.expr);
+                self.check_constant_overflow(expr);
+                let operand_type = expr_type.subrange_base().clone();
                 match unary.op {
                     ast::UnaryOp::Plus | ast::UnaryOp::Minus => {
+                        let expr_type = operand_type;
                         // Unary plus/minus
-                        if expr_type.equals(&Type::integer()) || expr_type.equals(&Type::word()) {
+                        if [Type::integer(), Type::word(), Type::longint(), Type::cardinal(), Type::real()]
+                            .iter()
+                            .any(|t| expr_type.equals(t))
+                        {
                             expr_type
                         } else {
                             self.core.add_error(
@@ -129,22 +728,22 @@ impl SemanticAnalyzer {
                         }
                     }
                     ast::UnaryOp::Not => {
-                        // Logical not
-                        if expr_type.equals(&Type::boolean()) {
-                            Type::boolean()
+                        let expr_type = operand_type;
+                        // Logical not, or bitwise on integers
+                        if [Type::boolean(), Type::integer(), Type::word(), Type::byte(), Type::longint(), Type::cardinal()]
+                            .iter()
+                            .any(|t| expr_type.equals(t))
+                        {
+                            expr_type
                         } else {
                             self.core.add_error(
-                                "Unary 'not' requires boolean type".to_string(),
+                                "Unary 'not' requires boolean or integer type".to_string(),
                                 unary.span,
                             );
                             Type::Error
                         }
                     }
-                    ast::UnaryOp::AddressOf => {
-                        // Address-of operator: @variable
-                        // Returns a pointer to the target type
-                        Type::pointer(expr_type)
-                    }
+                    ast::UnaryOp::AddressOf => Self::address_type(expr_type),
                 }
             }
             Node::CallExpr(call) => {
@@ -174,20 +773,50 @@ impl SemanticAnalyzer {
 
                     // Check argument types
                     for (arg, param) in call.args.iter().zip(params.iter()) {
-                        let arg_type = self.analyze_expression(arg);
-                        if !arg_type.is_assignable_to(&param.param_type) {
-                            self.core.add_error(
-                                format!(
-                                    "Argument type mismatch: expected {}, found {}",
-                                    core::CoreAnalyzer::format_type(&param.param_type),
-                                    core::CoreAnalyzer::format_type(&arg_type)
-                                ),
-                                arg.span(),
-                            );
-                        }
+                        self.check_argument(arg, param);
                     }
+                    self.check_compile_time_call(call);
 
                     return_type
+                } else if call.name.eq_ignore_ascii_case(stack_usage::TASK_STACK_SIZE_INTRINSIC) {
+                    self.analyze_task_stack_size(call)
+                } else if call.name.eq_ignore_ascii_case(crate::GENERATE_TABLE_INTRINSIC)
+                    && self.core.symbol_table.lookup(&call.name).is_none()
+                {
+                    self.analyze_generate_table(call)
+                } else if call.name.eq_ignore_ascii_case(crate::SIZEOF_INTRINSIC)
+                    && self.core.symbol_table.lookup(&call.name).is_none()
+                {
+                    self.analyze_sizeof(call)
+                } else if self.core.symbol_table.lookup(&call.name).is_none()
+                    && let Some(result) = self.analyze_nullary_intrinsic(&call.name)
+                {
+                    if !call.args.is_empty() {
+                        self.core.add_error(
+                            format!("{} expects 0 arguments, found {}", call.name, call.args.len()),
+                            call.span,
+                        );
+                        return Type::Error;
+                    }
+                    result
+                } else if call.name.eq_ignore_ascii_case(crate::PARAM_STR_INTRINSIC)
+                    && self.core.symbol_table.lookup(&call.name).is_none()
+                {
+                    self.analyze_param_str(call)
+                } else if self.core.symbol_table.lookup(&call.name).is_none()
+                    && [crate::TRUNC_INTRINSIC, crate::ROUND_INTRINSIC].iter().any(|n| call.name.eq_ignore_ascii_case(n))
+                {
+                    self.analyze_real_to_integer(call)
+                } else if self.core.symbol_table.lookup(&call.name).is_none()
+                    && [crate::ORD_INTRINSIC, crate::SUCC_INTRINSIC, crate::PRED_INTRINSIC]
+                        .iter()
+                        .any(|n| call.name.eq_ignore_ascii_case(n))
+                {
+                    self.analyze_ordinal_function(call)
+                } else if self.core.symbol_table.lookup(&call.name).is_none() && Self::is_string_function(&call.name) {
+                    self.analyze_string_function(call)
+                } else if let Some(target) = self.typecast_target(&call.name) {
+                    self.analyze_typecast(call, target)
                 } else if self.core.symbol_table.lookup(&call.name).is_some() {
                     self.core.add_error(
                         format!("'{}' is not a function", call.name),
@@ -203,13 +832,21 @@ impl SemanticAnalyzer {
                 }
             }
             Node::IndexExpr(idx) => {
+                if let Some(port_type) = self.analyze_port(idx) {
+                    return port_type;
+                }
                 let array_type = self.analyze_expression(&idx.array);
                 match array_type {
-                    Type::Array { element_type, .. } | Type::DynamicArray { element_type } => {
+                    Type::Array { index_type, element_type, .. } => {
+                        self.analyze_array_index(&idx.index, &index_type);
+                        *element_type
+                    }
+                    Type::DynamicArray { element_type } => {
                         let _index_type = self.analyze_expression(&idx.index);
                         // Check index type (for now, we assume integer indexing)
                         *element_type
                     }
+                    Type::String { max_length } => self.analyze_string_index(&idx.index, max_length),
                     _ => {
                         self.core.add_error(
                             "Index expression must be applied to an array".to_string(),
@@ -220,7 +857,14 @@ impl SemanticAnalyzer {
                 }
             }
             Node::FieldExpr(field) => {
+                // TShape.Create
+                if let Some(class_name) = self.class_type_name(&field.record) {
+                    return self.analyze_constructor_call(&class_name, &field.field, &[], field.span).unwrap_or(Type::Error);
+                }
                 let record_type = self.analyze_expression(&field.record);
+                if let Type::Class { name } = &record_type {
+                    return self.analyze_class_member(name, field);
+                }
                 if let Type::Record { fields, .. } = record_type {
                     if let Some(f) = fields.iter().find(|f| f.name == field.field) {
                         f.field_type.as_ref().clone()
@@ -239,11 +883,44 @@ impl SemanticAnalyzer {
                     Type::Error
                 }
             }
+            Node::DerefExpr(deref) => match self.analyze_expression(&deref.pointer) {
+                Type::Pointer { base_type } => *base_type,
+                Type::Error => Type::Error,
+                Type::UntypedPointer => {
+                    self.core.add_error(
+                        "Cannot dereference an untyped Pointer; cast it to a typed pointer".to_string(),
+                        deref.span,
+                    );
+                    Type::Error
+                }
+                other => {
+                    self.core.add_error(
+                        format!("Cannot dereference {}", core::CoreAnalyzer::format_type(&other)),
+                        deref.span,
+                    );
+                    Type::Error
+                }
+            },
             Node::AddressOfExpr(addr) => {
+                if let Some(address_type) = self.routine_address_type(&addr.target) {
+                    return address_type;
+                }
                 // Address-of operator: @variable
                 // Returns a pointer to the target type
                 let target_type = self.analyze_expression(&addr.target);
-                Type::pointer(target_type)
+                Self::address_type(target_type)
+            }
+            Node::MethodCall(call) => {
+                let reported = self.core.diagnostics.len();
+                match self.analyze_method_call(call) {
+                    Some(return_type) => return_type,
+                    None => {
+                        if self.core.diagnostics.len() == reported {
+                            self.core.add_error(format!("Procedure '{}' does not return a value", call.method), call.span);
+                        }
+                        Type::Error
+                    }
+                }
             }
             Node::InheritedExpr(_inherited) => {
                 // INHERITED [method_name] [args]
@@ -283,13 +960,16 @@ impl SemanticAnalyzer {
                         }
                     }
                 }
+                self.declare_result(&return_type, anon_func.span);
                 
                 // Detect captured variables: variables from outer scopes referenced in the body
                 // This is done by analyzing identifiers in the body and checking their scope
                 let captured_vars = self.detect_captured_variables(&anon_func.block, outer_scope_level, anon_scope_level);
                 
                 // Analyze the function body
+                self.functions.push((String::new(), return_type.clone()));
                 self.analyze_block(&anon_func.block);
+                self.functions.pop();
                 
                 // Exit scope
                 self.core.symbol_table.exit_scope();
@@ -352,6 +1032,7 @@ impl SemanticAnalyzer {
                 // TODO: Create proper procedural type representation
                 Type::Error // Procedures in expression context need special handling
             }
+            Node::SetLiteral(set) => self.analyze_set_literal(set),
             _ => {
                 self.core.add_error(
                     "Invalid expression".to_string(),
@@ -135,6 +135,10 @@ impl FeatureChecker {
             // For..in loops
             Node::ForInStmt(_) => Some(LanguageFeature::ForInLoops),
             
+            // Timer intrinsics
+            Node::CallExpr(call) if is_timer_intrinsic(&call.name) => Some(LanguageFeature::Timer),
+            Node::IdentExpr(ident) if is_timer_intrinsic(&ident.name) => Some(LanguageFeature::Timer),
+            
             // Nested routines (check in declarations)
             // This would be detected during declaration analysis
             
@@ -432,6 +436,14 @@ fn feature_name(feature: LanguageFeature) -> &'static str {
         LanguageFeature::GarbageCollection => "Garbage Collection",
         LanguageFeature::Multithreading => "Multithreading",
         LanguageFeature::DynamicLinking => "Dynamic Linking",
+        LanguageFeature::Timer => "Timer",
     }
 }
 
+/// Whether `name` is one of the timer intrinsics
+fn is_timer_intrinsic(name: &str) -> bool {
+    [crate::GET_TICKS_INTRINSIC, crate::TICKS_PER_SECOND_INTRINSIC]
+        .iter()
+        .any(|intrinsic| name.eq_ignore_ascii_case(intrinsic))
+}
+
@@ -0,0 +1,805 @@
+//! Monomorphization of generic types and routines
+//!
+//! Generics are instantiated before analysis: each distinct list of type
+//! arguments given to a generic type, or inferred for a call to a generic
+//! routine, makes one copy of its declaration with the type parameters
+//! replaced, named by [`::types::generic_instance_symbol`]. The analyzer and
+//! the IR builder then see ordinary declarations:
+//!
+//! ```text
+//!     type TBox<T> = record             type TBox_of_Integer = record
+//!       Value: T;                         Value: Integer;
+//!     end;                              end;
+//!     function Max<T>(A, B: T): T;      function Max_of_Integer(A, B: Integer): Integer;
+//!     var Box: TBox<Integer>;           var Box: TBox_of_Integer;
+//!     Box.Value := Max(I, 3);           Box.Value := Max_of_Integer(I, 3);
+//! ```
+//!
+//! A generic class is instantiated with the methods implemented for it
+//! (`procedure TStack.Push(X: T)`). Type arguments are type names or
+//! strings. Those of a routine are inferred from the arguments of each
+//! call: literals, which give way to any other argument, and variables,
+//! parameters, constants and function results whose declared type is
+//! visible.
+//!
+//! Instances replace their template, after any type declared beside it
+//! that they name; a template that is never instantiated is dropped
+//! unchecked. Constraints on type parameters (`T: class`) are checked once
+//! the program is analyzed (see
+//! [`SemanticAnalyzer::check_generic_constraints`](crate::SemanticAnalyzer::check_generic_constraints)).
+
+use std::collections::{HashMap, HashSet};
+
+use ast::Node;
+use errors::{Diagnostic, ErrorSeverity};
+use tokens::Span;
+
+/// Deepest nesting of instances within instances, such as `TList<TList<T>>`
+/// within `TList<T>`, before instantiation is taken to be unbounded
+pub const MAX_INSTANTIATION_DEPTH: usize = 16;
+
+/// A constraint on a type parameter, to check against the type argument of
+/// an instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenericConstraint {
+    pub param: String,
+    pub constraint: Node,
+    pub argument: Node,
+    pub span: Span,
+}
+
+struct Template {
+    /// The generic type, procedure or function
+    decl: Node,
+    /// Implementations of the methods of a generic class
+    methods: Vec<Node>,
+    /// Instances of the declaration, then of its methods, in the order made
+    instances: Vec<Node>,
+    method_instances: Vec<Node>,
+}
+
+/// Instantiates the generics a program or unit uses
+pub struct GenericInstantiator {
+    filename: Option<String>,
+    templates: HashMap<String, Template>,        // Keyed by lowercase name
+    instantiated: HashSet<String>,               // Lowercase instance names
+    instance_results: HashMap<String, Node>,     // Result types of function instances
+    types: HashMap<String, Node>,                // Type declarations and instances
+    scopes: Vec<HashMap<String, Node>>,          // Declared types of the names visible
+    constraints: Vec<GenericConstraint>,
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Name and type parameters of a generic declaration
+fn generic_decl(node: &Node) -> Option<(&str, &[ast::GenericParam])> {
+    match node {
+        Node::TypeDecl(t) if !t.generic_params.is_empty() => Some((&t.name, &t.generic_params)),
+        Node::ProcDecl(p) if !p.generic_params.is_empty() && p.class_name.is_none() => Some((&p.name, &p.generic_params)),
+        Node::FuncDecl(f) if !f.generic_params.is_empty() && f.class_name.is_none() => Some((&f.name, &f.generic_params)),
+        _ => None,
+    }
+}
+
+/// Class of a method implementation
+fn method_class(node: &Node) -> Option<&str> {
+    match node {
+        Node::ProcDecl(p) => p.class_name.as_deref(),
+        Node::FuncDecl(f) => f.class_name.as_deref(),
+        _ => None,
+    }
+}
+
+fn named_type(name: &str, span: Span) -> Node {
+    Node::NamedType(ast::NamedType { name: name.to_string(), generic_args: vec![], span })
+}
+
+/// How a type argument is spelled, to compare arguments and name instances
+fn spelling(node: &Node) -> Option<String> {
+    match node {
+        Node::NamedType(n) if n.generic_args.is_empty() => Some(n.name.clone()),
+        Node::NamedType(n) => {
+            let args: Option<Vec<String>> = n.generic_args.iter().map(|arg| spelling(arg)).collect();
+            Some(::types::generic_instance_symbol(&n.name, &args?))
+        }
+        Node::StringType(s) => match s.length.as_deref() {
+            None => Some("string".to_string()),
+            Some(Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(n), .. })) => {
+                Some(format!("string{}", n))
+            }
+            Some(_) => None,
+        },
+        _ => None,
+    }
+}
+
+fn same_type(a: &Node, b: &Node) -> bool {
+    match (spelling(a), spelling(b)) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(&b),
+        _ => false,
+    }
+}
+
+/// Replace the type parameters in `node` by their arguments
+fn substitute(node: &mut Node, arguments: &HashMap<String, Node>) {
+    match node {
+        Node::NamedType(named) if named.generic_args.is_empty() => {
+            if let Some(argument) = arguments.get(&named.name.to_lowercase()) {
+                *node = argument.clone();
+            }
+            return;
+        }
+        // `SizeOf(T)`, `T(X)`
+        Node::IdentExpr(ident) => {
+            if let Some(Node::NamedType(argument)) = arguments.get(&ident.name.to_lowercase()) {
+                ident.name = argument.name.clone();
+            }
+        }
+        Node::CallExpr(call) => {
+            if let Some(Node::NamedType(argument)) = arguments.get(&call.name.to_lowercase()) {
+                call.name = argument.name.clone();
+            }
+        }
+        _ => {}
+    }
+    for child in node.children_mut() {
+        substitute(child, arguments);
+    }
+}
+
+/// Rename the references to the routine `from` in `node` to `to`
+fn rename(node: &mut Node, from: &str, to: &str) {
+    match node {
+        Node::IdentExpr(ident) if ident.name.eq_ignore_ascii_case(from) => ident.name = to.to_string(),
+        Node::CallExpr(call) if call.name.eq_ignore_ascii_case(from) => call.name = to.to_string(),
+        Node::CallStmt(call) if call.name.eq_ignore_ascii_case(from) => call.name = to.to_string(),
+        _ => {}
+    }
+    for child in node.children_mut() {
+        rename(child, from, to);
+    }
+}
+
+/// Lowercase names of the types `node` refers to
+fn referenced_types(node: &Node, names: &mut HashSet<String>) {
+    if let Node::NamedType(named) = node {
+        names.insert(named.name.to_lowercase());
+    }
+    for child in node.children() {
+        referenced_types(child, names);
+    }
+}
+
+impl GenericInstantiator {
+    pub fn new(filename: Option<String>) -> Self {
+        Self {
+            filename,
+            templates: HashMap::new(),
+            instantiated: HashSet::new(),
+            instance_results: HashMap::new(),
+            types: HashMap::new(),
+            scopes: Vec::new(),
+            constraints: Vec::new(),
+            depth: 0,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Instantiate the generics `ast` uses, replacing their templates by
+    /// the instances
+    pub fn instantiate(&mut self, ast: &mut Node) {
+        self.collect(ast);
+        if self.templates.is_empty() {
+            return;
+        }
+        self.collect_methods(ast);
+        self.rewrite(ast);
+        self.replace_templates(ast);
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == ErrorSeverity::Error)
+    }
+
+    /// Lowercase names of the instances made, of generic routines and classes
+    pub fn instances(&self) -> impl Iterator<Item = &str> {
+        self.instantiated.iter().map(String::as_str)
+    }
+
+    /// Whether `name` is a routine made by instantiation: an instance of a
+    /// generic routine or a method of an instance of a generic class
+    pub fn is_instance_routine(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let class = name.split_once("__").map(|(class, _)| class);
+        self.instantiated.contains(&name) || class.is_some_and(|class| self.instantiated.contains(class))
+    }
+
+    /// Constraints the type arguments of the instances made must satisfy
+    pub fn constraints(&self) -> &[GenericConstraint] {
+        &self.constraints
+    }
+
+    fn add_error(&mut self, message: String, span: Span) {
+        let diag = Diagnostic::new(ErrorSeverity::Error, message, span)
+            .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string()));
+        self.diagnostics.push(diag);
+    }
+
+    fn collect(&mut self, node: &Node) {
+        let Some((name, _)) = generic_decl(node) else {
+            if let Node::TypeDecl(t) = node {
+                self.types.insert(t.name.to_lowercase(), (*t.type_expr).clone());
+            }
+            for child in node.children() {
+                self.collect(child);
+            }
+            return;
+        };
+        let key = name.to_lowercase();
+        if self.templates.contains_key(&key) {
+            self.add_error(format!("Generic '{}' already declared", name), node.span());
+            return;
+        }
+        let template = Template { decl: node.clone(), methods: vec![], instances: vec![], method_instances: vec![] };
+        self.templates.insert(key, template);
+    }
+
+    /// Collect the method implementations of generic classes
+    fn collect_methods(&mut self, node: &Node) {
+        if let Some(class) = method_class(node)
+            && let Some(template) = self.templates.get_mut(&class.to_lowercase())
+        {
+            template.methods.push(node.clone());
+            return;
+        }
+        for child in node.children() {
+            self.collect_methods(child);
+        }
+    }
+
+    fn is_template(&self, node: &Node) -> bool {
+        generic_decl(node).is_some()
+            || method_class(node).is_some_and(|class| {
+                self.templates.get(&class.to_lowercase()).is_some_and(|t| matches!(t.decl, Node::TypeDecl(_)))
+            })
+    }
+
+    /// Replace the uses of generics in `node`, outside templates, by their
+    /// instances
+    fn rewrite(&mut self, node: &mut Node) {
+        if self.is_template(node) {
+            return;
+        }
+        let scope = self.declared_names(node);
+        if let Some(scope) = &scope {
+            self.scopes.push(scope.clone());
+        }
+        for child in node.children_mut() {
+            self.rewrite(child);
+        }
+        match node {
+            Node::NamedType(named) if !named.generic_args.is_empty() => {
+                if let Some(name) = self.instantiate_type(named) {
+                    *node = named_type(&name, named.span);
+                }
+            }
+            Node::CallExpr(call) => {
+                if let Some(name) = self.instantiate_call(&call.name, &call.args, call.span) {
+                    call.name = name;
+                }
+            }
+            Node::CallStmt(call) => {
+                if let Some(name) = self.instantiate_call(&call.name, &call.args, call.span) {
+                    call.name = name;
+                }
+            }
+            _ => {}
+        }
+        if scope.is_some() {
+            self.scopes.pop();
+        }
+    }
+
+    /// Declared types of the names a block or routine declares, by
+    /// lowercase name
+    fn declared_names(&self, node: &Node) -> Option<HashMap<String, Node>> {
+        let mut names = HashMap::new();
+        let mut declare = |name: &str, type_expr: &Node| {
+            names.insert(name.to_lowercase(), type_expr.clone());
+        };
+        let params = match node {
+            Node::Block(block) => {
+                for decl in &block.var_decls {
+                    if let Node::VarDecl(var) = decl {
+                        var.names.iter().for_each(|name| declare(name, &var.type_expr));
+                    }
+                }
+                for decl in block.const_decls.iter().chain(&block.func_decls) {
+                    match decl {
+                        Node::ConstDecl(ast::ConstDecl { name, type_expr: Some(type_expr), .. }) => declare(name, type_expr),
+                        Node::FuncDecl(f) if f.generic_params.is_empty() => declare(&f.name, &f.return_type),
+                        _ => {}
+                    }
+                }
+                return Some(names);
+            }
+            Node::ProcDecl(p) => &p.params,
+            Node::FuncDecl(f) => {
+                declare(&f.name, &f.return_type);
+                declare(crate::RESULT_VARIABLE, &f.return_type);
+                &f.params
+            }
+            _ => return None,
+        };
+        for param in params {
+            if let Some(type_expr) = &param.type_expr {
+                param.names.iter().for_each(|name| declare(name, type_expr));
+            }
+        }
+        Some(names)
+    }
+
+    fn lookup(&self, name: &str) -> Option<Node> {
+        let key = name.to_lowercase();
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&key))
+            .or_else(|| self.instance_results.get(&key))
+            .cloned()
+    }
+
+    /// Declared type of `expr`, if it can be told without analysis, and
+    /// whether it is only that of a literal
+    fn expression_type(&self, expr: &Node) -> Option<(Node, bool)> {
+        let span = expr.span();
+        match expr {
+            Node::LiteralExpr(literal) => {
+                let name = match &literal.value {
+                    ast::LiteralValue::Integer(_) => "integer",
+                    ast::LiteralValue::Real(_) => "real",
+                    ast::LiteralValue::Char(_) => "char",
+                    ast::LiteralValue::Boolean(_) => "boolean",
+                    ast::LiteralValue::String(_) => {
+                        return Some((Node::StringType(ast::StringType { length: None, span }), true));
+                    }
+                    ast::LiteralValue::Bytes(_) => return None,
+                };
+                Some((named_type(name, span), true))
+            }
+            Node::IdentExpr(ident) => self.lookup(&ident.name).map(|t| (t, false)),
+            Node::CallExpr(call) => self.lookup(&call.name).map(|t| (t, false)),
+            Node::UnaryExpr(unary) => self.expression_type(&unary.expr),
+            Node::BinaryExpr(bin) => match bin.op {
+                ast::BinaryOp::Equal
+                | ast::BinaryOp::NotEqual
+                | ast::BinaryOp::Less
+                | ast::BinaryOp::LessEqual
+                | ast::BinaryOp::Greater
+                | ast::BinaryOp::GreaterEqual
+                | ast::BinaryOp::In
+                | ast::BinaryOp::Is => Some((named_type("boolean", span), false)),
+                ast::BinaryOp::Divide => Some((named_type("real", span), false)),
+                _ => {
+                    let left = self.expression_type(&bin.left);
+                    match left {
+                        Some((_, true)) | None => self.expression_type(&bin.right).or(left),
+                        left => left,
+                    }
+                }
+            },
+            Node::IndexExpr(index) => match self.resolve(self.expression_type(&index.array)?.0) {
+                Node::ArrayType(array) => Some((*array.element_type, false)),
+                Node::StringType(_) => Some((named_type("char", span), false)),
+                _ => None,
+            },
+            Node::FieldExpr(field) => {
+                let declares = |names: &[String]| names.iter().any(|n| n.eq_ignore_ascii_case(&field.field));
+                let field_type = match self.resolve(self.expression_type(&field.record)?.0) {
+                    Node::RecordType(record) => record.fields.into_iter().find(|f| declares(&f.names))?.type_expr,
+                    Node::ClassType(class) => class.members.into_iter().find_map(|(_, member)| match member {
+                        ast::ClassMember::Field(Node::VarDecl(var)) if declares(&var.names) => Some(var.type_expr),
+                        _ => None,
+                    })?,
+                    _ => return None,
+                };
+                Some((*field_type, false))
+            }
+            _ => None,
+        }
+    }
+
+    /// The definition of the type `type_expr` names, through aliases
+    fn resolve(&self, mut type_expr: Node) -> Node {
+        for _ in 0..MAX_INSTANTIATION_DEPTH {
+            let Some(definition) = spelling(&type_expr).and_then(|name| self.types.get(&name.to_lowercase())) else {
+                break;
+            };
+            type_expr = definition.clone();
+        }
+        type_expr
+    }
+
+    /// Instantiate the generic type `named` names, returning the instance
+    /// name; other types are left to the analyzer
+    fn instantiate_type(&mut self, named: &ast::NamedType) -> Option<String> {
+        let key = named.name.to_lowercase();
+        let params = match &self.templates.get(&key)?.decl {
+            Node::TypeDecl(t) => t.generic_params.len(),
+            _ => {
+                self.add_error(format!("'{}' is not a generic type", named.name), named.span);
+                return None;
+            }
+        };
+        if named.generic_args.len() != params {
+            self.add_error(
+                format!(
+                    "Generic type '{}' expects {} type arguments, found {}",
+                    named.name,
+                    params,
+                    named.generic_args.len()
+                ),
+                named.span,
+            );
+            return None;
+        }
+        let arguments = named.generic_args.iter().map(|arg| (**arg).clone()).collect();
+        self.instantiate_template(&key, arguments, named.span)
+    }
+
+    /// Instantiate the generic routine `name` called with `args`, inferring
+    /// its type arguments, returning the instance name
+    fn instantiate_call(&mut self, name: &str, args: &[Node], span: Span) -> Option<String> {
+        let key = name.to_lowercase();
+        let (params, formals) = match &self.templates.get(&key)?.decl {
+            Node::ProcDecl(p) => (p.generic_params.clone(), p.params.clone()),
+            Node::FuncDecl(f) => (f.generic_params.clone(), f.params.clone()),
+            _ => return None,
+        };
+        let formal_types = formals.iter().flat_map(|param| param.names.iter().map(move |_| param.type_expr.as_deref()));
+        let mut bindings: Vec<Option<(Node, bool)>> = vec![None; params.len()];
+        for (arg, formal) in args.iter().zip(formal_types) {
+            let Some(Node::NamedType(formal)) = formal else {
+                continue;
+            };
+            let Some(i) = params.iter().position(|p| p.name.eq_ignore_ascii_case(&formal.name)) else {
+                continue;
+            };
+            let Some((actual, literal)) = self.expression_type(arg) else {
+                continue;
+            };
+            match &bindings[i] {
+                None | Some((_, true)) if !literal || bindings[i].is_none() => bindings[i] = Some((actual, literal)),
+                Some((bound, false)) if !literal && !same_type(bound, &actual) => {
+                    let message = format!(
+                        "Conflicting type arguments for '{}' in call to '{}': {} and {}",
+                        params[i].name,
+                        name,
+                        spelling(bound).unwrap_or_default(),
+                        spelling(&actual).unwrap_or_default()
+                    );
+                    self.add_error(message, arg.span());
+                    return None;
+                }
+                _ => {}
+            }
+        }
+        let mut arguments = Vec::new();
+        for (param, binding) in params.iter().zip(bindings) {
+            let Some((argument, _)) = binding else {
+                self.add_error(format!("Cannot infer type argument '{}' of '{}'", param.name, name), span);
+                return None;
+            };
+            arguments.push(argument);
+        }
+        self.instantiate_template(&key, arguments, span)
+    }
+
+    /// The instance of the template `key` for `arguments`, made if it is
+    /// the first use of them
+    fn instantiate_template(&mut self, key: &str, arguments: Vec<Node>, span: Span) -> Option<String> {
+        let mut names = Vec::new();
+        for argument in &arguments {
+            let Some(name) = spelling(argument) else {
+                self.add_error("Type arguments of generics must be type names".to_string(), argument.span());
+                return None;
+            };
+            names.push(name);
+        }
+        let template = &self.templates[key];
+        let (name, params) = generic_decl(&template.decl)?;
+        let instance = ::types::generic_instance_symbol(name, &names);
+        if !self.instantiated.insert(instance.to_lowercase()) {
+            return Some(instance);
+        }
+        if self.depth >= MAX_INSTANTIATION_DEPTH {
+            self.add_error(format!("Instantiation of '{}' is nested too deeply", instance), span);
+            return None;
+        }
+        for (param, argument) in params.iter().zip(&arguments) {
+            if let Some(constraint) = &param.constraint {
+                self.constraints.push(GenericConstraint {
+                    param: param.name.clone(),
+                    constraint: (**constraint).clone(),
+                    argument: argument.clone(),
+                    span,
+                });
+            }
+        }
+        let substitutions: HashMap<String, Node> =
+            params.iter().map(|p| p.name.to_lowercase()).zip(arguments).collect();
+
+        let mut decl = template.decl.clone();
+        match &mut decl {
+            Node::TypeDecl(t) => {
+                t.name = instance.clone();
+                t.generic_params.clear();
+            }
+            Node::ProcDecl(p) => {
+                p.name = instance.clone();
+                p.generic_params.clear();
+            }
+            Node::FuncDecl(f) => {
+                f.name = instance.clone();
+                f.generic_params.clear();
+            }
+            _ => {}
+        }
+        substitute(&mut decl, &substitutions);
+        match &mut decl {
+            Node::TypeDecl(t) => {
+                self.types.insert(instance.to_lowercase(), (*t.type_expr).clone());
+            }
+            // The result, and recursive calls
+            routine => rename(routine, name, &instance),
+        }
+        let mut methods = template.methods.clone();
+        for method in &mut methods {
+            match method {
+                Node::ProcDecl(p) => p.class_name = Some(instance.clone()),
+                Node::FuncDecl(f) => f.class_name = Some(instance.clone()),
+                _ => {}
+            }
+            substitute(method, &substitutions);
+        }
+        if let Node::FuncDecl(f) = &decl {
+            self.instance_results.insert(instance.to_lowercase(), (*f.return_type).clone());
+        }
+
+        // Generics the instance uses in turn, in the scope of its template
+        self.depth += 1;
+        let scopes = std::mem::take(&mut self.scopes);
+        self.rewrite(&mut decl);
+        for method in &mut methods {
+            self.rewrite(method);
+        }
+        self.scopes = scopes;
+        self.depth -= 1;
+
+        let template = self.templates.get_mut(key)?;
+        template.instances.push(decl);
+        template.method_instances.extend(methods);
+        Some(instance)
+    }
+
+    /// Replace each template by its instances
+    fn replace_templates(&mut self, node: &mut Node) {
+        match node {
+            Node::Block(block) => {
+                self.replace_types(&mut block.type_decls);
+                self.replace_routines(&mut block.proc_decls);
+                self.replace_routines(&mut block.func_decls);
+            }
+            Node::Unit(unit) => {
+                if let Some(interface) = &mut unit.interface {
+                    self.replace_types(&mut interface.type_decls);
+                    self.replace_routines(&mut interface.proc_decls);
+                    self.replace_routines(&mut interface.func_decls);
+                }
+                if let Some(implementation) = &mut unit.implementation {
+                    self.replace_types(&mut implementation.type_decls);
+                    self.replace_routines(&mut implementation.proc_decls);
+                    self.replace_routines(&mut implementation.func_decls);
+                }
+            }
+            _ => {}
+        }
+        for child in node.children_mut() {
+            self.replace_templates(child);
+        }
+    }
+
+    /// Instances of the template `decl`: those of a generic declaration, or
+    /// for the first method implementation of a generic class of this kind
+    /// (procedure or function), those of all its methods of that kind
+    fn take_instances(&mut self, decl: &Node) -> Vec<Node> {
+        if let Some((name, _)) = generic_decl(decl) {
+            return self.templates.get_mut(&name.to_lowercase()).map(|t| std::mem::take(&mut t.instances)).unwrap_or_default();
+        }
+        let Some(template) = method_class(decl).and_then(|class| self.templates.get_mut(&class.to_lowercase())) else {
+            return vec![];
+        };
+        let (same_kind, others) = std::mem::take(&mut template.method_instances)
+            .into_iter()
+            .partition(|method| std::mem::discriminant(method) == std::mem::discriminant(decl));
+        template.method_instances = others;
+        same_kind
+    }
+
+    fn replace_routines(&mut self, decls: &mut Vec<Node>) {
+        let mut replaced = Vec::new();
+        for decl in std::mem::take(decls) {
+            if self.is_template(&decl) {
+                replaced.extend(self.take_instances(&decl));
+            } else {
+                replaced.push(decl);
+            }
+        }
+        *decls = replaced;
+    }
+
+    /// Replace the generic types among `decls` by their instances, each
+    /// placed after the types beside it that it names
+    fn replace_types(&mut self, decls: &mut Vec<Node>) {
+        let mut pending: Vec<(Node, String)> = Vec::new();
+        for decl in std::mem::take(decls) {
+            if self.is_template(&decl) {
+                pending.extend(self.take_instances(&decl).into_iter().map(|instance| {
+                    let name = if let Node::TypeDecl(t) = &instance { t.name.to_lowercase() } else { String::new() };
+                    (instance, name)
+                }));
+            } else {
+                let name = if let Node::TypeDecl(t) = &decl { t.name.to_lowercase() } else { String::new() };
+                pending.push((decl, name));
+            }
+        }
+        let instances: HashSet<String> = self.instantiated.clone();
+        let declared: HashSet<String> = pending.iter().map(|(_, name)| name.clone()).collect();
+        let mut placed: HashSet<String> = HashSet::new();
+        let mut waiting: Vec<(Node, String, HashSet<String>)> = Vec::new();
+        for (decl, name) in pending {
+            if !instances.contains(&name) {
+                placed.insert(name);
+                decls.push(decl);
+            } else {
+                let mut names = HashSet::new();
+                referenced_types(&decl, &mut names);
+                names.retain(|n| *n != name && declared.contains(n));
+                waiting.push((decl, name, names));
+            }
+            // Place the instances whose types are now all declared
+            while let Some(i) = waiting.iter().position(|(_, _, names)| names.is_subset(&placed)) {
+                let (decl, name, _) = waiting.remove(i);
+                placed.insert(name);
+                decls.push(decl);
+            }
+        }
+        decls.extend(waiting.into_iter().map(|(decl, _, _)| decl));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SemanticAnalyzer;
+    use parser::Parser;
+
+    fn instantiate(source: &str) -> (Node, GenericInstantiator) {
+        let mut ast = Parser::new(source).unwrap().parse().unwrap();
+        let mut generics = GenericInstantiator::new(None);
+        generics.instantiate(&mut ast);
+        (ast, generics)
+    }
+
+    fn block(ast: &Node) -> &ast::Block {
+        match ast {
+            Node::Program(ast::Program { block, .. }) => match block.as_ref() {
+                Node::Block(block) => block,
+                _ => unreachable!(),
+            },
+            _ => panic!("not a program"),
+        }
+    }
+
+    fn names(decls: &[Node]) -> Vec<String> {
+        decls
+            .iter()
+            .map(|decl| match decl {
+                Node::TypeDecl(t) => t.name.clone(),
+                Node::ProcDecl(p) => format!("{}.{}", p.class_name.as_deref().unwrap_or(""), p.name),
+                Node::FuncDecl(f) => f.name.clone(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_types_are_instantiated_once_per_argument_list() {
+        let (ast, generics) = instantiate(
+            "program P;
+             type
+               TStack<T> = class
+                 Items: array[0..7] of T;
+                 procedure Push(X: T);
+               end;
+               TPoint = record X, Y: integer; end;
+             var A, B: TStack<TPoint>; C: TStack<byte>;
+             procedure TStack.Push(X: T); begin end;
+             begin end.",
+        );
+        assert!(generics.diagnostics().is_empty());
+        let block = block(&ast);
+        // An instance waits for the types it names
+        assert_eq!(names(&block.type_decls), ["TStack_of_byte", "TPoint", "TStack_of_TPoint"]);
+        assert_eq!(names(&block.proc_decls), ["TStack_of_TPoint.Push", "TStack_of_byte.Push"]);
+        let Node::ProcDecl(push) = &block.proc_decls[1] else { unreachable!() };
+        assert!(matches!(push.params[0].type_expr.as_deref(), Some(Node::NamedType(t)) if t.name == "byte"));
+        let Node::VarDecl(var) = &block.var_decls[0] else { unreachable!() };
+        assert!(matches!(var.type_expr.as_ref(), Node::NamedType(t) if t.name == "TStack_of_TPoint"));
+        assert!(generics.is_instance_routine("TStack_of_TPoint__Push"));
+    }
+
+    #[test]
+    fn test_routine_type_arguments_are_inferred_from_calls() {
+        let (ast, generics) = instantiate(
+            "program P;
+             type TBox<T> = record Value: T; end;
+             var W: word; B: TBox<char>;
+             function Max<T>(A, B: T): T;
+             begin if A > B then Max := A else Max := B end;
+             begin
+               W := Max(3, W);
+               B.Value := Max(B.Value, 'z');
+               W := Max(Max(W, 1), 2)
+             end.",
+        );
+        assert!(generics.diagnostics().is_empty(), "{:?}", generics.diagnostics());
+        let block = block(&ast);
+        assert_eq!(names(&block.func_decls), ["Max_of_word", "Max_of_char"]);
+        assert!(generics.is_instance_routine("max_of_word") && !generics.is_instance_routine("Max"));
+        // Each instance assigns its own result
+        let mut analyzer = SemanticAnalyzer::new(None);
+        let diagnostics = analyzer.analyze(&ast);
+        assert!(diagnostics.iter().all(|d| d.severity != ErrorSeverity::Error), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_uninferable_and_conflicting_arguments_are_errors() {
+        let (_, generics) = instantiate(
+            "program P;
+             var W: word; I: integer;
+             procedure Swap<T>(var A, B: T); begin end;
+             procedure Clear<T>(Count: byte); begin end;
+             begin Swap(W, I); Clear(3) end.",
+        );
+        let messages: Vec<&str> = generics.diagnostics().iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Conflicting type arguments for 'T' in call to 'Swap': word and integer",
+                "Cannot infer type argument 'T' of 'Clear'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constraints_are_checked_after_analysis() {
+        let source = "program P;
+             type
+               TObj = class end;
+               TRef<T: class> = record Item: T; end;
+             var A: TRef<TObj>; B: TRef<integer>;
+             begin end.";
+        let (ast, generics) = instantiate(source);
+        assert_eq!(generics.constraints().len(), 2);
+        let mut analyzer = SemanticAnalyzer::new(None);
+        analyzer.analyze(&ast);
+        let diagnostics = analyzer.check_generic_constraints(generics.constraints());
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("'T' must be a class type, but 'Integer' is not"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Initial images of structured variables
+//!
+//! A program often opens by filling global tables and records with
+//! constants. Run after analysis, [`SemanticAnalyzer::fold_variable_images`]
+//! moves those assignments into images of the variables, which the driver
+//! places in the data section, so the program starts with the values in
+//! place instead of running the code that stores them:
+//!
+//! ```text
+//!     var Table: array[1..3] of integer;      Table: DW 10, 20, 30
+//!         Origin: record X, Y: byte end;      Origin: DB 4, 0
+//!     begin
+//!       Table[1] := 10;                       { folded }
+//!       Table[2] := 20;                       { folded }
+//!       Table[3] := 30;                       { folded }
+//!       Origin.X := 4;                        { folded }
+//!       WriteLn(Table[2]);                    { runs: not an assignment }
+//!       Table[1] := 0                         { runs: after other code }
+//!     end.
+//! ```
+//!
+//! Only the assignments before the first other statement are folded:
+//! until then, nothing has read or changed the variables. Targets are
+//! arrays and records declared by the program, indexed by constants and
+//! assigned constants of the element's type; bytes no assignment sets
+//! start as zero, as uninitialized variables do.
+
+use ast::Node;
+use symbols::ConstantValue;
+use types::Type;
+
+use crate::SemanticAnalyzer;
+
+/// Initial contents of a program-level variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableImage {
+    pub name: String,
+    pub size: u16,
+    /// Values stored at offsets of the variable, in assignment order
+    pub values: Vec<(u16, ConstantValue)>,
+}
+
+impl SemanticAnalyzer {
+    /// Remove the assignments of constants to the structured variables that
+    /// open the main program of the analyzed `ast`, returning the images of
+    /// the variables they set
+    pub fn fold_variable_images(&self, ast: &mut Node) -> Vec<VariableImage> {
+        let Node::Program(program) = ast else {
+            return vec![];
+        };
+        let Node::Block(block) = program.block.as_mut() else {
+            return vec![];
+        };
+        let declared: Vec<&str> = block
+            .var_decls
+            .iter()
+            .filter_map(|decl| match decl {
+                Node::VarDecl(var) if var.absolute_address.is_none() => Some(var),
+                _ => None,
+            })
+            .flat_map(|var| var.names.iter().map(String::as_str))
+            .collect();
+
+        let mut images: Vec<VariableImage> = vec![];
+        let mut folded = 0;
+        for statement in &block.statements {
+            let Some((name, offset, value)) = self.constant_store(statement, &declared) else {
+                break;
+            };
+            let index = match images.iter().position(|image| image.name.eq_ignore_ascii_case(&name)) {
+                Some(index) => index,
+                None => {
+                    let size = self.variable_type(&name).and_then(|t| t.size()).unwrap_or(0) as u16;
+                    images.push(VariableImage { name, size, values: vec![] });
+                    images.len() - 1
+                }
+            };
+            images[index].values.push((offset, value));
+            folded += 1;
+        }
+        block.statements.drain(..folded);
+        images
+    }
+
+    /// The variable, offset and value of an assignment of a constant to an
+    /// element of a structured variable among `declared`
+    fn constant_store(&self, statement: &Node, declared: &[&str]) -> Option<(String, u16, ConstantValue)> {
+        let Node::AssignStmt(assign) = statement else {
+            return None;
+        };
+        let (name, offset, target_type) = self.element(&assign.target)?;
+        if !declared.iter().any(|d| d.eq_ignore_ascii_case(&name))
+            || !matches!(self.variable_type(&name)?, Type::Array { .. } | Type::Record { .. })
+        {
+            return None;
+        }
+        let value = self.evaluate_constant_expression(&assign.value)?;
+        if let Type::Subrange { low, high, .. } = &target_type
+            && !(*low..=*high).contains(&Self::constant_ordinal(&value)?)
+        {
+            return None;
+        }
+        let value = match (value, target_type.subrange_base()) {
+            (ConstantValue::String(text), Type::String { max_length }) if text.len() <= *max_length => {
+                ConstantValue::String(text)
+            }
+            (ConstantValue::Char(c), Type::String { max_length }) if *max_length > 0 => {
+                ConstantValue::String((c as char).to_string())
+            }
+            (value, base) => self.coerce_constant(&value, base)?,
+        };
+        Some((name, offset, value))
+    }
+
+    /// The variable an access path such as `A[2].X` starts from, the offset
+    /// of the element it names and the element's type
+    fn element(&self, target: &Node) -> Option<(String, u16, Type)> {
+        match target {
+            Node::IdentExpr(ident) => Some((ident.name.clone(), 0, self.variable_type(&ident.name)?)),
+            Node::IndexExpr(index) => {
+                let (name, offset, array) = self.element(&index.array)?;
+                let Type::Array { index_type, element_type, .. } = array else {
+                    return None;
+                };
+                let (low, high) = index_type.ordinal_bounds()?;
+                let ordinal = Self::constant_ordinal(&self.evaluate_constant_expression(&index.index)?)?;
+                if !(low..=high).contains(&ordinal) {
+                    return None;
+                }
+                let element_offset = (ordinal - low) * element_type.size()? as i64;
+                Some((name, offset.checked_add(u16::try_from(element_offset).ok()?)?, *element_type))
+            }
+            Node::FieldExpr(field) => {
+                let (name, offset, record) = self.element(&field.record)?;
+                let Type::Record { fields, .. } = record else {
+                    return None;
+                };
+                let found = fields.into_iter().find(|f| f.name.eq_ignore_ascii_case(&field.field))?;
+                Some((name, offset.checked_add(found.offset? as u16)?, *found.field_type))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opening_constant_stores_become_images() {
+        let mut ast = parser::Parser::new(
+            "program P;
+             const Base = 10;
+             type Point = record X, Y: integer; Tag: char; Name: string[4]; end;
+             var Table: array[1..3] of integer; Spot: Point; Grid: array[0..1] of array[0..1] of byte; N: integer;
+             begin
+               Table[1] := Base; Table[3] := -Base;
+               Spot.Y := 7; Spot.Tag := 'a'; Spot.Name := 'abc';
+               Grid[1][0] := 5;
+               N := 1;
+               Table[2] := 20
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(None);
+        assert!(analyzer.analyze(&ast).is_empty());
+
+        let images = analyzer.fold_variable_images(&mut ast);
+        let image = |name: &str| images.iter().find(|image| image.name == name).unwrap();
+        assert_eq!(images.len(), 3);
+        assert_eq!(image("Table").size, 6);
+        assert_eq!(image("Table").values, [(0, ConstantValue::Integer(10)), (4, ConstantValue::Integer(-10))]);
+        assert_eq!(
+            image("Spot").values,
+            [(2, ConstantValue::Integer(7)), (4, ConstantValue::Char(b'a')), (5, ConstantValue::String("abc".to_string()))]
+        );
+        assert_eq!(image("Grid").values, [(2, ConstantValue::Byte(5))]);
+
+        // The scalar assignment ends the opening run, so the last store stays
+        let Node::Program(program) = &ast else { panic!() };
+        let Node::Block(block) = program.block.as_ref() else { panic!() };
+        assert_eq!(block.statements.len(), 2);
+    }
+}
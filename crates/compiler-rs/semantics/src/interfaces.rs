@@ -0,0 +1,201 @@
+//! Interfaces: declarations, implementing classes and interface queries
+//!
+//! `type IShape = interface(IDrawable) ... end` lists methods, inherited
+//! ones first, which a class naming the interface after its parent,
+//! `class(TBase, IShape)`, must implement with the same parameters and
+//! result. A class implements the interfaces its parent does, and those
+//! each of its interfaces extends.
+//!
+//! An instance converts to an interface its class implements. `X is IShape`
+//! tests at run time whether the class of the instance `X` implements the
+//! interface, and `X as IShape` is then the instance, or nil.
+
+use ast::Node;
+use ::types::{ClassLayout, ClassMethod, InterfaceLayout, MethodKind, Type};
+use symbols::{Symbol, SymbolKind};
+use crate::SemanticAnalyzer;
+use crate::core;
+
+impl SemanticAnalyzer {
+    /// Layouts of the interfaces declared so far
+    pub fn interface_layouts(&self) -> &[InterfaceLayout] {
+        &self.interface_layouts
+    }
+
+    pub(crate) fn interface_layout(&self, name: &str) -> Option<&InterfaceLayout> {
+        self.interface_layouts.iter().find(|layout| layout.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Declare the interface `name` with the methods it inherits and those
+    /// it declares
+    pub(crate) fn analyze_interface_decl(&mut self, decl: &ast::TypeDecl, interface: &ast::InterfaceType) {
+        if self.core.symbol_table.exists_in_current_scope(&decl.name) {
+            self.add_redeclaration_error(format!("Type '{}' already declared", decl.name), &decl.name, decl.span);
+            return;
+        }
+        let symbol = Symbol {
+            kind: SymbolKind::TypeAlias {
+                name: decl.name.clone(),
+                aliased_type: Type::Interface { name: decl.name.clone() },
+                span: decl.span,
+            },
+            scope_level: self.core.symbol_table.scope_level(),
+        };
+        if let Err(e) = self.core.symbol_table.insert(symbol) {
+            self.core.add_error(e, decl.span);
+        }
+
+        let mut bases = Vec::new();
+        for base in &interface.base_interfaces {
+            match self.interface_layout(base) {
+                Some(layout) => bases.push(layout.clone()),
+                None => self.core.add_error(format!("Base interface '{}' is not an interface", base), interface.span),
+            }
+        }
+        let mut layout = InterfaceLayout::new(&decl.name, &bases.iter().collect::<Vec<_>>());
+
+        for method in &interface.methods {
+            let (name, params, return_type, span) = match method {
+                Node::ProcDecl(p) => (&p.name, self.analyze_params(&p.params), None, p.span),
+                Node::FuncDecl(f) => {
+                    let params = self.analyze_params(&f.params);
+                    (&f.name, params, Some(self.analyze_type(&f.return_type)), f.span)
+                }
+                _ => continue,
+            };
+            if layout.method(name).is_some() {
+                self.core.add_error(format!("Method '{}' already declared in interface '{}'", name, decl.name), span);
+                continue;
+            }
+            layout.declare_method(ClassMethod {
+                name: name.clone(),
+                kind: MethodKind::Method,
+                owner: decl.name.clone(),
+                slot: None,
+                param_count: params.len(),
+                return_type,
+            });
+            self.method_params.insert(::types::method_symbol(&decl.name, name).to_ascii_lowercase(), params);
+        }
+        self.interface_layouts.push(layout);
+    }
+
+    /// Implement the interfaces `names` the class of `layout` declares, and
+    /// again those it inherits, with its own methods
+    pub(crate) fn implement_interfaces(&mut self, layout: &mut ClassLayout, names: &[String], span: tokens::Span) {
+        let mut implemented: Vec<InterfaceLayout> =
+            layout.interfaces.iter().filter_map(|(name, _)| self.interface_layout(name).cloned()).collect();
+        for name in names {
+            let Some(interface) = self.interface_layout(name).cloned() else {
+                self.core.add_error(
+                    format!("Class '{}' cannot implement '{}', which is not an interface", layout.name, name),
+                    span,
+                );
+                continue;
+            };
+            if !self.check_implementation(layout, &interface, span) {
+                continue;
+            }
+            let ancestors = interface.ancestors.iter().filter_map(|ancestor| self.interface_layout(ancestor).cloned());
+            implemented.extend(ancestors.collect::<Vec<_>>());
+            implemented.push(interface);
+        }
+        for interface in &implemented {
+            // Every method was checked, here or by the parent class
+            let _ = layout.implement(interface);
+        }
+    }
+
+    /// Check that `layout` has a method matching each method of
+    /// `interface`, reporting those it lacks
+    fn check_implementation(&mut self, layout: &ClassLayout, interface: &InterfaceLayout, span: tokens::Span) -> bool {
+        let mut complete = true;
+        for wanted in &interface.methods {
+            let Some(method) = layout.method(&wanted.name).filter(|m| m.kind == MethodKind::Method) else {
+                self.core.add_error(
+                    format!(
+                        "Class '{}' does not implement method '{}' of interface '{}'",
+                        layout.name, wanted.name, interface.name
+                    ),
+                    span,
+                );
+                complete = false;
+                continue;
+            };
+            let (params, wanted_params) = (self.method_params(method), self.method_params(wanted));
+            let same_params = params.len() == wanted_params.len()
+                && params.iter().zip(&wanted_params).all(|(param, wanted)| {
+                    param.passing_mode == wanted.passing_mode && param.param_type.equals(&wanted.param_type)
+                });
+            let same_result = match (&method.return_type, &wanted.return_type) {
+                (Some(result), Some(wanted)) => result.equals(wanted),
+                (None, None) => true,
+                _ => false,
+            };
+            if !same_params || !same_result {
+                self.core.add_error(
+                    format!(
+                        "Method '{}.{}' does not match '{}.{}'",
+                        layout.name, method.name, wanted.owner, wanted.name
+                    ),
+                    span,
+                );
+                complete = false;
+            }
+        }
+        complete
+    }
+
+    /// Whether a `value` reference converts to the interface `target`: its
+    /// class implements the interface, or its interface extends it
+    pub(crate) fn converts_to_interface(&self, value: &Type, target: &Type) -> bool {
+        let Type::Interface { name } = target else {
+            return false;
+        };
+        match value {
+            Type::Class { name: class } => self.class_layout(class).is_some_and(|layout| layout.implements(name)),
+            Type::Interface { name: interface } => self.interface_layout(interface).is_some_and(|layout| layout.extends(name)),
+            _ => false,
+        }
+    }
+
+    /// The interface `node` names, if it is the name of an interface type
+    pub(crate) fn interface_type_name(&self, node: &Node) -> Option<String> {
+        let Node::IdentExpr(ident) = node else {
+            return None;
+        };
+        match &self.core.symbol_table.lookup(&ident.name)?.kind {
+            SymbolKind::TypeAlias { aliased_type: Type::Interface { name }, .. } => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Analyze `X is I` or `X as I` for the interface `interface`
+    pub(crate) fn analyze_interface_query(&mut self, bin: &ast::BinaryExpr, interface: &str) -> Type {
+        let object_type = self.analyze_expression(&bin.left);
+        if !matches!(object_type, Type::Class { .. } | Type::Interface { .. } | Type::Error) {
+            self.core.add_error(
+                format!(
+                    "Interface query requires a class or interface reference, found {}",
+                    core::CoreAnalyzer::format_type(&object_type)
+                ),
+                bin.span,
+            );
+        }
+        match bin.op {
+            ast::BinaryOp::Is => Type::boolean(),
+            _ => Type::Interface { name: interface.to_string() },
+        }
+    }
+
+    /// Analyze a call of a method of the interface `name`, returning its
+    /// result type
+    pub(crate) fn analyze_interface_call(&mut self, name: &str, call: &ast::MethodCall) -> Option<Type> {
+        let Some(method) = self.interface_layout(name).and_then(|layout| layout.method(&call.method)).cloned() else {
+            self.core.add_error(format!("Interface '{}' has no method '{}'", name, call.method), call.span);
+            return None;
+        };
+        self.check_method_arguments(&method, &call.args, call.span)?;
+        method.return_type
+    }
+}
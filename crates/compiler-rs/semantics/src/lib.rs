@@ -13,8 +13,22 @@ mod statements;
 mod expressions;
 mod types;
 mod constants;
+mod compile_time;
 mod lvalues;
+mod strings;
+mod exceptions;
+mod classes;
+mod interfaces;
+mod units;
+mod images;
+mod portability;
+pub mod dead_code;
+pub mod parameters;
+pub mod purity;
+pub mod warnings;
+pub mod generics;
 pub mod feature_checker;
+pub mod stack_usage;
 
 // Declaration analysis functions are in declarations.rs module
 // They extend SemanticAnalyzer via impl blocks
@@ -23,9 +37,124 @@ use ast::Node;
 use errors::Diagnostic;
 use symbols::SymbolTable;
 
+/// Implicit variable holding a function's return value
+pub const RESULT_VARIABLE: &str = "Result";
+/// Intrinsic leaving the current routine, optionally with a function result
+pub const EXIT_INTRINSIC: &str = "Exit";
+/// Intrinsics adding to or subtracting from an ordinal or typed pointer
+/// variable; pointers move by whole elements
+pub const INC_INTRINSIC: &str = "Inc";
+pub const DEC_INTRINSIC: &str = "Dec";
+/// Switch allowing `p + n`, `p - n` and `p - q` on typed pointers
+pub const POINTER_MATH_SWITCH: &str = "POINTERMATH";
+/// Switch selecting the strict profile, which {$MODE STRICT} sets: range
+/// and overflow checks on, no goto, an else in every case over an
+/// enumeration, and key warnings reported as errors
+pub const STRICT_SWITCH: &str = "STRICT";
+/// Intrinsic giving the size in bytes of a variable or type
+pub const SIZEOF_INTRINSIC: &str = "SizeOf";
+/// Intrinsics giving the number of program parameters and the `i`th one
+/// (from 1) as a length-prefixed `array[Byte] of Char`
+pub const PARAM_COUNT_INTRINSIC: &str = "ParamCount";
+pub const PARAM_STR_INTRINSIC: &str = "ParamStr";
+/// Intrinsics reading the target's tick counter and its rate
+pub const GET_TICKS_INTRINSIC: &str = "GetTicks";
+pub const TICKS_PER_SECOND_INTRINSIC: &str = "TicksPerSecond";
+/// Intrinsic returning and clearing the code of the last runtime error under
+/// {$RUNTIMEERRORS CODE}
+pub const ERROR_CODE_INTRINSIC: &str = "ErrorCode";
+/// Intrinsics writing values to the console, and also ending the line, and
+/// reading a line of the console into variables
+pub const WRITE_INTRINSIC: &str = "Write";
+pub const WRITELN_INTRINSIC: &str = "WriteLn";
+pub const READLN_INTRINSIC: &str = "ReadLn";
+/// Pseudo-arrays of the Z80 I/O ports, indexed by port address: `Port[a]`
+/// reads or writes a byte and `PortW[a]` a word (ports a and a + 1)
+pub const PORT_INTRINSIC: &str = "Port";
+pub const PORTW_INTRINSIC: &str = "PortW";
+/// Registers an asm block may list as clobbered
+pub const ASM_REGISTERS: [&str; 13] = ["a", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "ix", "iy"];
+/// Intrinsic procedure printing the instances still allocated on the debug
+/// heap; it does nothing without it
+pub const REPORT_LEAKS_INTRINSIC: &str = "ReportLeaks";
+/// Intrinsic procedure failing the build unless a record field is at the
+/// offset given: `AssertOffset(TRec.Field, 4)`. It generates no code.
+pub const ASSERT_OFFSET_INTRINSIC: &str = "AssertOffset";
+/// Intrinsics converting a real to Integer, toward zero and to the nearest
+pub const TRUNC_INTRINSIC: &str = "Trunc";
+pub const ROUND_INTRINSIC: &str = "Round";
+/// Intrinsics giving the ordinal number of an ordinal value, and the value
+/// after or before it in its type
+pub const ORD_INTRINSIC: &str = "Ord";
+pub const SUCC_INTRINSIC: &str = "Succ";
+pub const PRED_INTRINSIC: &str = "Pred";
+/// Intrinsic running a function over a range of values while compiling,
+/// giving the results as a constant array: `GenerateTable(F, 0, 255)`
+pub const GENERATE_TABLE_INTRINSIC: &str = "GenerateTable";
+/// String intrinsics, counting positions from 1: the functions `Length(s)`,
+/// `Copy(s, index, count)`, `Pos(sub, s)` and `Concat(s1, s2, ...)`, and
+/// the procedures `Delete(s, index, count)` and `Insert(source, s, index)`
+pub const LENGTH_INTRINSIC: &str = "Length";
+pub const COPY_INTRINSIC: &str = "Copy";
+pub const POS_INTRINSIC: &str = "Pos";
+pub const CONCAT_INTRINSIC: &str = "Concat";
+pub const DELETE_INTRINSIC: &str = "Delete";
+pub const INSERT_INTRINSIC: &str = "Insert";
+pub use compile_time::{COMPILE_TIME_CALL_LIMIT, COMPILE_TIME_STEP_LIMIT};
+pub use images::VariableImage;
+pub use units::UnitCache;
+
+/// Name of a type as written in diagnostics
+pub fn type_name(ty: &::types::Type) -> String {
+    core::CoreAnalyzer::format_type(ty)
+}
+
+/// Thread-local variable, stored in each task's variable block
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadVar {
+    pub name: String,
+    pub offset: u16, // Offset within the per-task block
+    pub size: u16,
+    pub span: tokens::Span,
+}
+
+/// Typed constant: read-only data placed in ROM when the target has it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedConstant {
+    pub name: String,
+    pub const_type: ::types::Type,
+    pub value: symbols::ConstantValue,
+    pub span: tokens::Span,
+}
+
 /// Semantic analyzer
 pub struct SemanticAnalyzer {
     core: core::CoreAnalyzer,
+    stack_usage: Option<stack_usage::StackUsage>, // Computed at the start of analyze()
+    threadvars: Vec<ThreadVar>,
+    typed_constants: Vec<TypedConstant>,
+    string_literals: Vec<String>, // Distinct string literals, in order of appearance
+    global_variable_size: u32,    // Bytes of program-level variables
+    absolute_addresses: Vec<(tokens::Span, u16)>, // Addresses of ABSOLUTE variables, by declaration span
+    for_loop_vars: Vec<String>,   // Control variables of enclosing FOR loops
+    except_depth: usize,          // Enclosing exception handlers, where a bare `raise` re-raises
+    class_layouts: Vec<::types::ClassLayout>, // Classes declared so far
+    interface_layouts: Vec<::types::InterfaceLayout>, // Interfaces declared so far
+    method_params: std::collections::HashMap<String, Vec<symbols::Parameter>>, // Method parameters by lowercase method symbol
+    functions: Vec<(String, ::types::Type)>, // Enclosing function bodies (name, return type)
+    pointer_math: bool,           // {$POINTERMATH ON}: arithmetic operators on typed pointers
+    strict: bool,                 // {$MODE STRICT} or --strict: legacy constructs and key warnings are errors
+    uses_params: bool,            // ParamCount or ParamStr is called
+    uses_timer: bool,             // GetTicks or TicksPerSecond is called
+    uses_error_code: bool,        // ErrorCode is called
+    uses_report_leaks: bool,      // ReportLeaks is called
+    forward_routines: Vec<(String, usize, tokens::Span)>, // Headings awaiting a body (name, scope level)
+    interface_symbols: Vec<symbols::Symbol>, // Symbols a unit's interface exports
+    used_units: Vec<std::path::PathBuf>, // Compiled units loaded for `uses`
+    unit_symbols: std::collections::HashMap<String, std::path::PathBuf>, // Sources of the units exporting used names, by lowercase name
+    unit_cache: Option<UnitCache>, // Compiled units already read by this or other analyzers
+    compile_time_functions: std::collections::HashMap<String, compile_time::CompileTimeFunction>, // {$COMPILETIME} functions by lowercase name
+    table_functions: std::collections::HashMap<String, Result<compile_time::CompileTimeFunction, String>>, // Other one-parameter functions GenerateTable may run, or why not
 }
 
 impl SemanticAnalyzer {
@@ -33,41 +162,284 @@ impl SemanticAnalyzer {
     pub fn new(filename: Option<String>) -> Self {
         Self {
             core: core::CoreAnalyzer::new(filename),
+            stack_usage: None,
+            threadvars: vec![],
+            typed_constants: vec![],
+            string_literals: vec![],
+            global_variable_size: 0,
+            absolute_addresses: vec![],
+            for_loop_vars: vec![],
+            except_depth: 0,
+            class_layouts: vec![],
+            interface_layouts: vec![],
+            method_params: std::collections::HashMap::new(),
+            functions: vec![],
+            pointer_math: false,
+            strict: false,
+            uses_params: false,
+            uses_timer: false,
+            uses_error_code: false,
+            uses_report_leaks: false,
+            forward_routines: vec![],
+            interface_symbols: vec![],
+            used_units: vec![],
+            unit_symbols: std::collections::HashMap::new(),
+            unit_cache: None,
+            compile_time_functions: std::collections::HashMap::new(),
+            table_functions: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Allow arithmetic operators on typed pointers, as set by {$POINTERMATH ON}
+    pub fn set_pointer_math(&mut self, enabled: bool) {
+        self.pointer_math = enabled;
+    }
+
+    /// Analyze under the strict profile (see [`STRICT_SWITCH`])
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    /// Share the compiled units read for `uses` with other analyzers
+    pub fn set_unit_cache(&mut self, cache: UnitCache) {
+        self.unit_cache = Some(cache);
+    }
+
+    /// Add a warning the strict profile reports as an error
+    pub(crate) fn add_key_warning(&mut self, message: String, span: tokens::Span) {
+        if self.strict {
+            self.core.add_error(format!("{} (strict mode)", message), span);
+        } else {
+            self.core.add_warning(message, span);
         }
     }
 
-    /// Analyze a program AST
+    /// Typed constants, in declaration order
+    pub fn typed_constants(&self) -> &[TypedConstant] {
+        &self.typed_constants
+    }
+
+    /// Distinct string literals, in order of appearance
+    pub fn string_literals(&self) -> &[String] {
+        &self.string_literals
+    }
+
+    /// Bytes of RAM taken by program-level variables
+    pub fn global_variable_size(&self) -> u32 {
+        self.global_variable_size
+    }
+
+    /// Layout of the per-task variable block, in declaration order
+    pub fn threadvars(&self) -> &[ThreadVar] {
+        &self.threadvars
+    }
+
+    /// Size of the per-task variable block
+    pub fn threadvar_block_size(&self) -> u16 {
+        self.threadvars.last().map_or(0, |v| v.offset + v.size)
+    }
+
+    /// Whether the program reads its parameters, so the target must supply them
+    pub fn uses_params(&self) -> bool {
+        self.uses_params
+    }
+
+    /// Whether the program reads the tick counter
+    pub fn uses_timer(&self) -> bool {
+        self.uses_timer
+    }
+
+    /// Whether the program reads the code of the last runtime error
+    pub fn uses_error_code(&self) -> bool {
+        self.uses_error_code
+    }
+
+    /// Whether the program reports the instances it leaked
+    pub fn uses_report_leaks(&self) -> bool {
+        self.uses_report_leaks
+    }
+
+    /// Symbols exported by an analyzed unit's interface, in declaration order
+    pub fn interface_symbols(&self) -> &[symbols::Symbol] {
+        &self.interface_symbols
+    }
+
+    /// Every symbol the analyzed program or unit declared, in order, with
+    /// those local to routines; names brought in by `uses` are left out
+    pub fn declared_symbols(&self) -> Vec<&symbols::Symbol> {
+        self.core
+            .symbol_table
+            .declared()
+            .iter()
+            .filter(|symbol| symbol.scope_level > 0 || !self.unit_symbols.contains_key(&symbol.name().to_lowercase()))
+            .collect()
+    }
+
+    /// Symbols brought in by `uses`, in name order
+    pub fn imported_symbols(&self) -> Vec<&symbols::Symbol> {
+        let mut imported: Vec<&symbols::Symbol> = self
+            .core
+            .symbol_table
+            .declared()
+            .iter()
+            .filter(|symbol| symbol.scope_level == 0 && self.unit_symbols.contains_key(&symbol.name().to_lowercase()))
+            .collect();
+        imported.sort_by_key(|symbol| symbol.name().to_lowercase());
+        imported
+    }
+
+    /// Address of the ABSOLUTE variables declared at `span`, if it is a
+    /// constant the analysis could evaluate
+    pub fn absolute_address(&self, span: tokens::Span) -> Option<u16> {
+        self.absolute_addresses.iter().find(|(declared, _)| *declared == span).map(|(_, address)| *address)
+    }
+
+    /// Address of the ABSOLUTE variable `name` of the file analyzed, if it
+    /// is one
+    pub fn variable_address(&self, name: &str) -> Option<u16> {
+        match &self.core.symbol_table.lookup(name)?.kind {
+            symbols::SymbolKind::Variable { span, .. } if !self.unit_symbols.contains_key(&name.to_lowercase()) => {
+                self.absolute_address(*span)
+            }
+            _ => None,
+        }
+    }
+
+    /// Compiled unit files loaded for `uses` clauses; their objects must be linked
+    pub fn used_units(&self) -> &[std::path::PathBuf] {
+        &self.used_units
+    }
+
+    /// Parameters and result type (None for a procedure) of a program-level
+    /// routine, once the program has been analyzed
+    pub fn routine_signature(&self, name: &str) -> Option<(Vec<symbols::Parameter>, Option<::types::Type>)> {
+        match &self.core.symbol_table.lookup(name)?.kind {
+            symbols::SymbolKind::Procedure { params, .. } => Some((params.clone(), None)),
+            symbols::SymbolKind::Function { params, return_type, .. } => Some((params.clone(), Some(return_type.clone()))),
+            _ => None,
+        }
+    }
+
+    /// Type of a program-level variable, once the program has been analyzed
+    pub fn variable_type(&self, name: &str) -> Option<::types::Type> {
+        match &self.core.symbol_table.lookup(name)?.kind {
+            symbols::SymbolKind::Variable { var_type, .. } => Some(var_type.clone()),
+            _ => None,
+        }
+    }
+
+    /// Analyze a program or unit AST
     pub fn analyze(&mut self, program: &Node) -> Vec<Diagnostic> {
         self.core.diagnostics.clear();
         self.core.symbol_table = SymbolTable::new();
-
-        if let Node::Program(prog) = program {
-            // Analyze the program block
-            self.analyze_block(&prog.block);
+        self.stack_usage = Some(stack_usage::StackUsage::analyze(program));
+        self.threadvars.clear();
+        self.typed_constants.clear();
+        self.string_literals.clear();
+        self.global_variable_size = 0;
+        self.absolute_addresses.clear();
+        self.uses_params = false;
+        self.uses_timer = false;
+        self.uses_error_code = false;
+        self.uses_report_leaks = false;
+        self.forward_routines.clear();
+        self.interface_symbols.clear();
+        self.used_units.clear();
+
+        match program {
+            Node::Program(prog) => {
+                if let Some(uses) = &prog.uses {
+                    self.analyze_uses(uses);
+                }
+                // Analyze the program block
+                self.analyze_block(&prog.block);
+            }
+            Node::Unit(unit) => self.analyze_unit(unit),
+            _ => {}
         }
 
         self.core.diagnostics.clone()
     }
 
+    /// Check the type arguments of generic instances against the
+    /// constraints on their parameters, once the program has been analyzed
+    pub fn check_generic_constraints(&mut self, constraints: &[generics::GenericConstraint]) -> Vec<Diagnostic> {
+        self.core.diagnostics.clear();
+        for c in constraints {
+            let constraint = match &c.constraint {
+                Node::ClassType(_) => ::types::Type::Named { name: "class".to_string() },
+                Node::RecordType(_) => ::types::Type::Named { name: "record".to_string() },
+                Node::NamedType(n) if ["class", "record", "constructor"].iter().any(|k| n.name.eq_ignore_ascii_case(k)) => {
+                    ::types::Type::Named { name: n.name.to_lowercase() }
+                }
+                node => self.analyze_type(node),
+            };
+            let argument = self.analyze_type(&c.argument);
+            if constraint != ::types::Type::Error {
+                self.validate_generic_constraint(&argument, &constraint, &c.param, c.span);
+            }
+        }
+        self.core.diagnostics.clone()
+    }
+
     /// Analyze a block (declarations and statements)
     fn analyze_block(&mut self, block: &Node) {
         if let Node::Block(blk) = block {
+            // Declarations after the first {$COMPILETIME} function, or the
+            // first function a constant generates a table from, may call
+            // it, so they are analyzed in source order with the routines
+            let table_functions = Self::table_function_names(blk);
+            let compile_time_start = blk
+                .func_decls
+                .iter()
+                .filter_map(|decl| match decl {
+                    Node::FuncDecl(f) if f.is_compiletime || table_functions.iter().any(|n| n.eq_ignore_ascii_case(&f.name)) => {
+                        Some(f.span.start)
+                    }
+                    _ => None,
+                })
+                .min()
+                .unwrap_or(usize::MAX);
+            type Analyze = fn(&mut SemanticAnalyzer, &Node);
+            let sections: [(&[Node], Analyze); 4] = [
+                (&blk.const_decls, Self::analyze_const_decl),
+                (&blk.type_decls, Self::analyze_type_decl),
+                (&blk.var_decls, Self::analyze_var_decl),
+                (&blk.threadvar_decls, Self::analyze_threadvar_decl),
+            ];
+            let mut deferred: Vec<(&Node, Analyze)> = vec![];
+
             // First, process all declarations
-            for const_decl in &blk.const_decls {
-                self.analyze_const_decl(const_decl);
+            for (decls, analyze) in sections {
+                for decl in decls {
+                    if decl.span().start < compile_time_start {
+                        analyze(self, decl);
+                    } else {
+                        deferred.push((decl, analyze));
+                    }
+                }
             }
-            for type_decl in &blk.type_decls {
-                self.analyze_type_decl(type_decl);
-            }
-            for var_decl in &blk.var_decls {
-                self.analyze_var_decl(var_decl);
-            }
-            for proc_decl in &blk.proc_decls {
-                self.analyze_proc_decl(proc_decl);
-            }
-            for func_decl in &blk.func_decls {
-                self.analyze_func_decl(func_decl);
+            if deferred.is_empty() {
+                for proc_decl in &blk.proc_decls {
+                    self.analyze_proc_decl(proc_decl);
+                }
+                for func_decl in &blk.func_decls {
+                    self.analyze_func_decl(func_decl);
+                }
+            } else {
+                let routines: Vec<(&Node, Analyze)> = blk
+                    .proc_decls
+                    .iter()
+                    .map(|decl| (decl, Self::analyze_proc_decl as Analyze))
+                    .chain(blk.func_decls.iter().map(|decl| (decl, Self::analyze_func_decl as Analyze)))
+                    .collect();
+                deferred.extend(routines);
+                deferred.sort_by_key(|(decl, _)| decl.span().start);
+                for (decl, analyze) in deferred {
+                    analyze(self, decl);
+                }
             }
+            self.check_forwards_resolved();
 
             // Then, analyze statements
             for stmt in &blk.statements {
@@ -76,6 +448,25 @@ impl SemanticAnalyzer {
         }
     }
 
+    /// Functions the constants of `blk` generate tables from
+    fn table_function_names(blk: &ast::Block) -> Vec<&str> {
+        blk.const_decls
+            .iter()
+            .filter_map(|decl| match decl {
+                Node::ConstDecl(c) => match c.value.as_ref() {
+                    Node::CallExpr(call) if call.name.eq_ignore_ascii_case(GENERATE_TABLE_INTRINSIC) => {
+                        match call.args.first() {
+                            Some(Node::IdentExpr(function)) => Some(function.name.as_str()),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
     // Declaration analysis functions moved to declarations.rs module
 
     // Type analysis functions moved to types.rs module
@@ -94,7 +485,7 @@ mod tests {
     use ast::*;
     use tokens::Span;
     use symbols::{ConstantValue, Symbol, SymbolKind};
-    use ::types::Type;
+    use ::types::{MethodKind, Type};
 
     #[test]
     fn test_semantic_analyzer_new() {
@@ -127,6 +518,7 @@ mod tests {
         let program = Node::Program(Program {
             directives: vec![],
             name: "Test".to_string(),
+            uses: None,
             block: Box::new(block),
             span,
         });
@@ -307,6 +699,7 @@ mod tests {
         let program = Node::Program(Program {
             directives: vec![],
             name: "Test".to_string(),
+            uses: None,
             block: Box::new(block),
             span,
         });
@@ -370,6 +763,7 @@ mod tests {
         let program = Node::Program(Program {
             directives: vec![],
             name: "Test".to_string(),
+            uses: None,
             block: Box::new(block),
             span,
         });
@@ -400,6 +794,7 @@ mod tests {
                 })),
                 span,
             })),
+            distinct: false,
             span,
         });
 
@@ -480,11 +875,11 @@ mod tests {
         let anon_func = Node::AnonymousFunction(ast::AnonymousFunction {
             params: vec![ast::Param {
                 names: vec!["x".to_string()],
-                type_expr: Box::new(Node::NamedType(ast::NamedType {
+                type_expr: Some(Box::new(Node::NamedType(ast::NamedType {
                     name: "integer".to_string(),
                     generic_args: vec![],
                     span,
-                })),
+                }))),
                 param_type: ast::ParamType::Value,
                 default_value: None,
                 span,
@@ -549,11 +944,11 @@ mod tests {
         let anon_proc = Node::AnonymousProcedure(ast::AnonymousProcedure {
             params: vec![ast::Param {
                 names: vec!["x".to_string()],
-                type_expr: Box::new(Node::NamedType(ast::NamedType {
+                type_expr: Some(Box::new(Node::NamedType(ast::NamedType {
                     name: "integer".to_string(),
                     generic_args: vec![],
                     span,
-                })),
+                }))),
                 param_type: ast::ParamType::Value,
                 default_value: None,
                 span,
@@ -616,11 +1011,11 @@ mod tests {
         let anon_func = Node::AnonymousFunction(ast::AnonymousFunction {
             params: vec![ast::Param {
                 names: vec!["x".to_string()],
-                type_expr: Box::new(Node::NamedType(ast::NamedType {
+                type_expr: Some(Box::new(Node::NamedType(ast::NamedType {
                     name: "integer".to_string(),
                     generic_args: vec![],
                     span,
-                })),
+                }))),
                 param_type: ast::ParamType::Value,
                 default_value: None,
                 span,
@@ -1033,6 +1428,7 @@ mod tests {
                 span,
             })),
             generic_params: vec![],
+            distinct: false,
             span,
         });
 
@@ -1082,4 +1478,1309 @@ mod tests {
         // Should have no errors
         assert_eq!(diagnostics.len(), 0);
     }
+
+    #[test]
+    fn test_address_of_procedure_is_word() {
+        let span = Span::new(0, 10, 1, 1);
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let _ = analyzer.core.symbol_table.insert(symbols::Symbol {
+            kind: symbols::SymbolKind::Procedure {
+                name: "Tick".to_string(),
+                params: vec![],
+                span,
+            },
+            scope_level: 0,
+        });
+
+        let addr = Node::AddressOfExpr(ast::AddressOfExpr {
+            target: Box::new(Node::IdentExpr(ast::IdentExpr {
+                name: "Tick".to_string(),
+                span,
+            })),
+            span,
+        });
+
+        assert_eq!(analyzer.analyze_expression(&addr), Type::word());
+        assert!(analyzer.core.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_task_stack_size_intrinsic() {
+        let analyze = |source: &str| {
+            let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+            SemanticAnalyzer::new(Some("test.pas".to_string())).analyze(&ast)
+        };
+
+        let diagnostics = analyze(
+            "program P;
+             var S: word;
+             procedure Worker; var X: integer; begin X := 1 end;
+             begin S := TaskStackSize(Worker) end.",
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        let diagnostics = analyze(
+            "program P;
+             var S: word;
+             procedure Worker; begin Worker end;
+             begin S := TaskStackSize(Worker) end.",
+        );
+        assert!(diagnostics.iter().any(|d| d.message.contains("recursive")));
+    }
+
+    #[test]
+    fn test_threadvar_block_layout() {
+        let ast = parser::Parser::new(
+            "program P;
+             threadvar Score: integer; Lives, Level: byte;
+             begin Score := 1 end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        let layout: Vec<(&str, u16)> = analyzer.threadvars().iter().map(|t| (t.name.as_str(), t.offset)).collect();
+        assert_eq!(layout, vec![("Score", 0), ("Lives", 2), ("Level", 3)]);
+        assert_eq!(analyzer.threadvar_block_size(), 4);
+    }
+
+    #[test]
+    fn test_typed_constants_are_read_only_data() {
+        let ast = parser::Parser::new(
+            "program P;
+             const Limit: word = 10; Greeting = 'Hi';
+             var X: integer; Flags: byte;
+             begin Limit := 3 end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("Cannot assign to constant 'Limit'"));
+        let constants = analyzer.typed_constants();
+        assert_eq!(constants.len(), 1);
+        assert_eq!(constants[0].value, ConstantValue::Word(10));
+        assert_eq!(analyzer.string_literals(), ["Hi"]);
+        assert_eq!(analyzer.global_variable_size(), 3);
+    }
+
+    #[test]
+    fn test_byte_array_constants_are_read_only_data() {
+        let ast = parser::Parser::new(
+            "program P;
+             const Header = $\"5A 45 41 4C\"; Table: array[byte] of byte = $\"00FF3A\";
+             Bad: array[byte] of char = $\"00 01\";
+             begin end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("cannot initialize"), "{}", diagnostics[0].message);
+        let constants: Vec<_> = analyzer.typed_constants().iter().map(|c| (c.name.as_str(), &c.value)).collect();
+        assert_eq!(
+            constants,
+            vec![
+                ("Header", &ConstantValue::Bytes(b"ZEAL".to_vec())),
+                ("Table", &ConstantValue::Bytes(vec![0x00, 0xFF, 0x3A])),
+            ]
+        );
+        assert_eq!(analyzer.typed_constants()[0].const_type.size(), Some(4));
+    }
+
+    #[test]
+    fn test_distinct_types_are_not_interchangeable() {
+        let ast = parser::Parser::new(
+            "program P;
+             type TCount = word; THandle = type word; TFile = type word;
+             var C: TCount; W: word; H, H2: THandle; F: TFile;
+             begin
+               C := W; W := C;
+               H := THandle(3); H2 := H + H; W := TCount(H);
+               H := W;
+               F := H;
+               W := H
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("cannot assign Word to THandle"), "{}", messages[0]);
+        assert!(messages[1].contains("cannot assign THandle to TFile"), "{}", messages[1]);
+        assert!(messages[2].contains("cannot assign THandle to Word"), "{}", messages[2]);
+    }
+
+    #[test]
+    fn test_untyped_parameters_pass_addresses() {
+        let ast = parser::Parser::new(
+            "program P;
+             type TBytes = array[byte] of byte; PBytes = ^TBytes; TWord = word;
+             var A: TBytes; W: word; V: byte;
+             procedure Fill(var Buf; Count: word; Value: byte);
+             var B: PBytes;
+             begin
+               B := @Buf;
+               B^[0] := Value; W := TWord(Buf);
+               W := SizeOf(A);
+               W := SizeOf(Buf);
+               W := Buf
+             end;
+             begin
+               Fill(A, SizeOf(A), V); Fill(W, W, V);
+               Fill(W + W, W, V)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("SizeOf is not available for an untyped parameter"), "{}", messages[0]);
+        assert!(messages[1].contains("cannot assign untyped to Word"), "{}", messages[1]);
+        assert!(messages[2].contains("Untyped parameter 'Buf' requires a variable argument"), "{}", messages[2]);
+    }
+
+    #[test]
+    fn test_program_parameters() {
+        let ast = parser::Parser::new(
+            "program P;
+             type TParam = array[byte] of char;
+             var S: TParam; N: word;
+             begin
+               N := ParamCount; N := ParamCount();
+               S := ParamStr(N); S := ParamStr(1);
+               S := ParamStr(S); N := ParamCount(1)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("ParamStr expects an ordinal index"), "{}", messages[0]);
+        assert!(messages[1].contains("ParamCount expects 0 arguments"), "{}", messages[1]);
+        assert!(analyzer.uses_params());
+    }
+
+    #[test]
+    fn test_timer_intrinsics() {
+        let ast = parser::Parser::new(
+            "program P;
+             var Start, Elapsed: word;
+             begin
+               Start := GetTicks;
+               Elapsed := (GetTicks() - Start) div TicksPerSecond
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        assert!(analyzer.analyze(&ast).is_empty());
+        assert!(analyzer.uses_timer());
+
+        // Only targets with a tick counter accept them
+        use runtime_spec::{TargetPlatform, capabilities};
+        for (target, errors) in [(TargetPlatform::ZXSpectrum, 0), (TargetPlatform::CommanderX16, 3)] {
+            let mut checker = feature_checker::FeatureChecker::new(capabilities::get_capabilities(target), None);
+            checker.check(&ast);
+            assert_eq!(checker.diagnostics().len(), errors, "{:?}", target);
+        }
+    }
+
+    #[test]
+    fn test_report_leaks_intrinsic() {
+        let ast = parser::Parser::new("program P; begin ReportLeaks; ReportLeaks(1) end.").unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("ReportLeaks expects 0 arguments, found 1"), "{}", diagnostics[0].message);
+        assert!(analyzer.uses_report_leaks());
+    }
+
+    #[test]
+    fn test_redeclaration_points_at_first_declaration() {
+        let source = "program P;
+             const Max = 3;
+             var Count: integer;
+                 Count: byte;
+             procedure Max; begin end;
+             begin
+             end.";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("p.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+        let first: Vec<(&str, usize, Option<&str>)> = diagnostics
+            .iter()
+            .flat_map(|d| &d.related_locations)
+            .map(|related| (related.message.as_str(), related.span.line, related.file.as_deref()))
+            .collect();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(first, [("first declared here", 3, Some("p.pas")), ("first declared here", 2, Some("p.pas"))]);
+    }
+
+    #[test]
+    fn test_align_and_assert_offset() {
+        let source = "program P;
+             type TWord = record Kind: byte; Value: integer; end;
+             {$PUSH} {$ALIGN 1}
+             type TSprite = record X: byte; Y: integer; Attr: TWord; end;
+             {$POP}
+             type TAfter = record Kind: byte; Value: integer; end;
+             var S: TSprite;
+             begin
+               AssertOffset(TWord.Value, 2);
+               AssertOffset(S.Attr.Value, 5);
+               AssertOffset(TAfter.Value, 1);
+               AssertOffset(TSprite.Y.Low, 1);
+               AssertOffset(TSprite.Y, 1 + 1)
+             end.";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(None);
+        let messages: Vec<String> = analyzer.analyze(&ast).into_iter().map(|d| d.message).collect();
+        assert_eq!(
+            messages,
+            [
+                "Field 'TAfter.Value' is at offset 2, not 1",
+                "'TSprite.Y' is not a record",
+                "Field 'TSprite.Y' is at offset 1, not 2"
+            ]
+        );
+        assert_eq!(analyzer.variable_type("S").unwrap().size(), Some(7));
+    }
+
+    #[test]
+    fn test_asm_references_and_clobbers() {
+        let source = "program P;
+             type TByte = byte;
+             var Count: byte;
+             procedure Tick; begin end;
+             begin
+               asm
+                 ld a, (@Count) ; @Ignored in a comment
+                 call @Tick
+                 ld hl, @TByte
+                 ld de, @Missing
+               end ['a', 'hl', 'sp']
+             end.";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let messages: Vec<String> = analyzer.analyze(&ast).into_iter().map(|d| d.message).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("'TByte' is a type"), "{}", messages[0]);
+        assert!(messages[1].contains("Identifier 'Missing' not found"), "{}", messages[1]);
+        assert!(messages[2].contains("Unknown register 'sp'"), "{}", messages[2]);
+    }
+
+    #[test]
+    fn test_port_pseudo_arrays() {
+        let source = "program P;
+             var B: byte; W: word; C: char;
+             begin
+               B := Port[$10]; Port[B] := B; W := PortW[W]; PortW[$20] := W;
+               C := Port[1];
+               B := Port[C];
+             end.";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let messages: Vec<String> = analyzer.analyze(&ast).into_iter().map(|d| d.message).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("Type mismatch"), "{}", messages[0]);
+        assert!(messages[1].contains("Port address must be an integer, found Char"), "{}", messages[1]);
+    }
+
+    #[test]
+    fn test_pointer_arithmetic_rules() {
+        let source = "program P;
+             type PInt = ^integer;
+             var P, Q: PInt; U: pointer; N: integer;
+             begin
+               Inc(P); Dec(P, N); Inc(N, 2); P^ := 5;
+               P := U; U := P;
+               Inc(U);
+               P := P + 1; N := P - Q
+             end.";
+        let analyze = |pointer_math| {
+            let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+            let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+            analyzer.set_pointer_math(pointer_math);
+            analyzer.analyze(&ast).into_iter().map(|d| d.message).collect::<Vec<_>>()
+        };
+
+        let messages = analyze(false);
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("requires a cast to a typed pointer"), "{}", messages[0]);
+        assert!(messages[1].contains("{$POINTERMATH ON}"), "{}", messages[1]);
+        assert!(messages[2].contains("{$POINTERMATH ON}"), "{}", messages[2]);
+
+        let messages = analyze(true);
+        assert_eq!(messages.len(), 1, "{:?}", messages);
+    }
+
+    #[test]
+    fn test_strict_profile_rules() {
+        let source = "program P;
+             type Color = (Red, Green, Blue);
+             var C: Color; N: integer;
+             function F(X: integer): integer;
+             begin
+               if X > 0 then F := X
+             end;
+             begin
+               case C of Red, Green, Blue: N := 1 end;
+               case C of Red: N := 2 else N := 3 end
+             end.";
+        let analyze = |strict| {
+            let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+            let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+            analyzer.set_strict(strict);
+            analyzer.analyze(&ast).into_iter().map(|d| (d.severity, d.message)).collect::<Vec<_>>()
+        };
+
+        // The first case handles every value, so only F is warned about
+        let diagnostics = analyze(false);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].0, errors::ErrorSeverity::Warning);
+
+        let diagnostics = analyze(true);
+        assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+        assert!(diagnostics.iter().all(|(severity, _)| *severity == errors::ErrorSeverity::Error));
+        assert!(diagnostics[0].1.contains("might not assign its result"), "{}", diagnostics[0].1);
+        assert!(diagnostics[1].1.contains("needs an else branch"), "{}", diagnostics[1].1);
+    }
+
+    #[test]
+    fn test_for_loop_control_variable_rules() {
+        let ast = parser::Parser::new(
+            "program P;
+             var G: integer;
+             procedure Q;
+             var I: integer;
+             begin
+               for G := 1 to 10 do I := G;
+               for I := 1 to 10 do I := 5;
+               for I := 10 downto 1 do G := I
+             end;
+             begin end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "For loop variable 'G' must be a local variable",
+                "Cannot assign to for loop variable 'I'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_for_loop_bounds_are_constants_that_fit_the_variable() {
+        let ast = parser::Parser::new(
+            "program P;
+             var W, V: word; B: byte;
+             begin
+               for W := 65530 to 65535 do V := W;
+               for B := 250 to 255 do V := B;
+               for B := 250 to 256 do V := B
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["For loop end value type Integer not compatible with loop variable type Byte"]);
+    }
+
+    #[test]
+    fn test_constant_comparison_warnings() {
+        let ast = parser::Parser::new(
+            "program P;
+             var B: byte; W: word; I: integer;
+             begin
+               if B < 0 then I := 1;
+               if 65535 >= W then I := 2;
+               if I < 0 then I := 3;
+               if W > I then I := 4
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Comparison is always false: Byte values are in 0..255",
+                "Comparison is always true: Word values are in 0..65535",
+            ]
+        );
+        assert!(diagnostics.iter().all(|d| d.severity == errors::ErrorSeverity::Warning));
+    }
+
+    #[test]
+    fn test_byte_and_word_operations_with_constants() {
+        let ast = parser::Parser::new(
+            "program P;
+             var B: byte; W: word; I: integer; F: boolean;
+             procedure Q(X: byte; Y: word); begin end;
+             begin
+               B := 5;
+               B := (B and $8F) or ((B and 7) * 16);
+               W := not W + 1;
+               I := I and 255;
+               F := (B and 4) <> 0;
+               B := 256;
+               B := I;
+               F := F or 1;
+               Q(200, 65535)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Type mismatch: cannot assign Integer to Byte",
+                "Type mismatch: cannot assign Integer to Byte",
+                "Logical operations require boolean or integer operands",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_real_arithmetic_and_conversions() {
+        let ast = parser::Parser::new(
+            "program P;
+             const Half = 1 / 2.0;
+             var R: real; S: single; I: integer;
+             begin
+               R := 3.14 * I + Half;
+               S := -R / 2;
+               R := 1e-3;
+               I := Trunc(R) + Round(S * 10);
+               if R < S then I := 0;
+               I := R;
+               R := R div 2;
+               I := Trunc(True)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Type mismatch: cannot assign Real to Integer",
+                "'div' and 'mod' require integer operands; use '/' for reals",
+                "Trunc requires a real argument, found Boolean",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_longint_and_cardinal_arithmetic() {
+        let ast = parser::Parser::new(
+            "program P;
+             const Big = 100000 * 3; Max = $FFFFFFFF;
+             var L: longint; C: cardinal; I: integer; W: word;
+             begin
+               L := I * 2 + Big;
+               L := I + L;
+               C := W;
+               C := Max div 2;
+               C := C + 1;
+               L := -L mod 7;
+               if L < C then L := C;
+               I := L;
+               C := I
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Type mismatch: cannot assign LongInt to Integer",
+                "Type mismatch: cannot assign Integer to Cardinal",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enumeration_ordinal_functions() {
+        let ast = parser::Parser::new(
+            "program P;
+             type TColor = (Red, Green, Blue);
+             const Code = Ord('A') + 1;
+             var C: TColor; N: integer; W: word; R: real;
+             begin
+               C := Succ(Red);
+               N := Ord(C) + Code;
+               W := Ord(W);
+               C := Succ(Blue);
+               C := Pred(Red);
+               N := Ord(R);
+               N := Succ(N, 2);
+               case C of
+                 Red: N := 1;
+                 Pred(Blue): N := 2
+               end
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Succ(Blue) is out of the range of (Red, Green, Blue)",
+                "Pred(Red) is out of the range of (Red, Green, Blue)",
+                "Ord requires an ordinal argument, found Real",
+                "Succ expects 1 argument, found 2",
+                "Case statement does not handle enumeration values: Blue",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subrange_types() {
+        let ast = parser::Parser::new(
+            "program P;
+             type
+               TColor = (Red, Green, Blue);
+               TWarm = Red..Green;
+               TDigit = 0..9;
+               TLetter = 'a'..'z';
+               TBig = 0..40000;
+               TEmpty = 9..0;
+               TMixed = 'a'..9;
+             var D: TDigit; L: TLetter; W: TWarm; B: TBig; N: integer;
+               Table: array[1..10] of integer; Shades: array[TWarm] of byte;
+             begin
+               D := 5;
+               N := D * 2 + Ord(L);
+               D := N;
+               D := 10;
+               L := 'A';
+               W := Blue;
+               B := 40000;
+               for D := 0 to 9 do Table[D + 1] := D;
+               Table[0] := 1;
+               Shades[Green] := 2;
+               Shades[Blue] := 3;
+               Shades[1] := 4
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Lower bound of subrange 9..0 is greater than the upper bound",
+                "Subrange bounds must have the same type, found Char and Integer",
+                "Constant 10 is out of the range 0..9",
+                "Constant 'A' is out of the range 'a'..'z'",
+                "Constant Blue is out of the range Red..Green",
+                "Constant 0 is out of the range 1..10",
+                "Constant Blue is out of the range Red..Green",
+                "Array index must be Red..Green, found Integer",
+            ]
+        );
+        let size = |name: &str| match analyzer.core.symbol_table.lookup(name).map(|s| &s.kind) {
+            Some(SymbolKind::Variable { var_type, .. }) => var_type.size(),
+            other => panic!("Expected variable {}, found {:?}", name, other),
+        };
+        assert_eq!(size("Table"), Some(20));
+        assert_eq!(size("Shades"), Some(2));
+        assert_eq!(size("B"), Some(2));
+    }
+
+    #[test]
+    fn test_function_result_assignment() {
+        let ast = parser::Parser::new(
+            "program P;
+             function Sign(A: integer): integer;
+             begin
+               if A < 0 then Exit(-1);
+               Sign := 1
+             end;
+             function Early(A: integer): integer;
+             begin
+               if A < 0 then Exit;
+               Result := A
+             end;
+             function Partial(A: integer): integer;
+             begin
+               if A > 0 then begin Result := A end
+             end;
+             begin end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Function 'Early' might not assign its result on every path",
+                "Function 'Partial' might not assign its result on every path",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try_except_finally_and_raise() {
+        let analyze = |body: &str| {
+            let source = format!(
+                "program P;
+                 type EParse = word; PNode = ^integer;
+                 var I: integer; R: real; P: PNode;
+                 begin {} end.",
+                body
+            );
+            let ast = parser::Parser::new(&source).unwrap().parse().unwrap();
+            SemanticAnalyzer::new(Some("test.pas".to_string()))
+                .analyze(&ast)
+                .into_iter()
+                .map(|d| d.message)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(analyze(
+            "try
+               try raise EParse(3) finally I := 0 end
+             except
+               on E: EParse do I := E;
+               on PNode do raise
+             else
+               raise
+             end;
+             raise P"
+        )
+        .is_empty());
+        assert_eq!(
+            analyze("raise; raise R; try I := 1 except on E: real do I := 2; on E: ^integer do I := 3 end; I := E"),
+            [
+                "Raise without an exception is only allowed in an exception handler",
+                "Raise requires an ordinal or pointer value, found Real",
+                "Exception type must be an ordinal or pointer type, found Real",
+                "Exception type must be a type name",
+                "Identifier 'E' not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_case_ranges_must_be_disjoint_constants() {
+        let analyze = |source: &str| {
+            let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+            SemanticAnalyzer::new(Some("test.pas".to_string()))
+                .analyze(&ast)
+                .into_iter()
+                .map(|d| d.message)
+                .collect::<Vec<_>>()
+        };
+        let program = |labels: &str| {
+            format!(
+                "program P; type TColor = (Red, Green, Blue); var I, J: integer; C: char; K: TColor;
+                 begin {} end.",
+                labels
+            )
+        };
+
+        assert!(analyze(&program(
+            "case I of 1, 3, 5..9: J := 1; 10..20, 30: J := 2 end;
+             case C of 'a'..'z': J := 1; '0'..'9', '_': J := 2 end;
+             case K of Red..Green: J := 1; Blue: J := 2 end"
+        ))
+        .is_empty());
+        assert_eq!(
+            analyze(&program(
+                "case I of 1..5: J := 1; 5: J := 2; 9..7: J := 3; J: J := 4 end;
+                 case C of 'a'..'z': J := 1; 'x'..'~': J := 2 end;
+                 case K of Red..Green: J := 1 end"
+            )),
+            [
+                "Duplicate case label 5",
+                "Case label range 9..7 is empty",
+                "Case label must be a constant",
+                "Duplicate case label 'x'",
+                "Case statement does not handle enumeration values: Blue",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_case_over_enum_missing_values_fix_it() {
+        let source = "program P;
+             type TColor = (Red, Green, Blue);
+             var C: TColor; I: integer;
+             begin
+               C := Green;
+               case C of
+                 Red: I := 1;
+               end;
+               case C of
+                 Red: I := 1
+               else
+                 I := 2
+               end
+             end.";
+        let analyze = |source: &str| {
+            let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+            SemanticAnalyzer::new(Some("test.pas".to_string())).analyze(&ast)
+        };
+
+        let diagnostics = analyze(source);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Case statement does not handle enumeration values: Green, Blue"
+        );
+
+        // The fix-it goes after the last branch's statement, lined up with
+        // it, and makes the case exhaustive
+        let fix_it = &diagnostics[0].fix_its[0];
+        assert_eq!((fix_it.span.line, fix_it.span.column), (7, 29));
+        let fixed = fix_it.apply(source);
+        assert!(fixed.contains("Red: I := 1;\n                 Green, Blue: begin end;"), "{}", fixed);
+        assert!(analyze(&fixed).is_empty());
+    }
+
+    #[test]
+    fn test_compile_time_functions() {
+        let ast = parser::Parser::new(
+            "program P;
+             var G: longint;
+             {$COMPILETIME}
+             function Factorial(N: integer): longint;
+             begin
+               if N <= 1 then Result := 1 else Result := N * Factorial(N - 1)
+             end;
+             {$COMPILETIME}
+             function SumTo(N: integer): integer;
+             var I: integer;
+             begin
+               SumTo := 0;
+               for I := 1 to N do SumTo := SumTo + I
+             end;
+             {$COMPILETIME}
+             function Forever(N: integer): integer;
+             begin
+               repeat N := N + 0 until N < 0;
+               Forever := N
+             end;
+             {$COMPILETIME}
+             function Deep(N: integer): integer;
+             begin
+               Deep := Deep(N + 1)
+             end;
+             {$COMPILETIME}
+             function Impure(var N: integer): integer;
+             begin
+               G := N;
+               Impure := SumTo(N)
+             end;
+             const
+               F10 = Factorial(10);
+               S = SumTo(100);
+               Big = SumTo(400);
+               A = Forever(1);
+               B = Deep(1);
+             var Table: array[0..S - 5000] of byte;
+             begin
+               G := Factorial(7)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Compile-time function 'Impure' cannot have var parameter 'N'",
+                "Compile-time function 'Impure' cannot use global variable 'G'",
+                "Cannot evaluate 'SumTo' at compile time: 32896 is out of the range of Integer",
+                "Cannot evaluate 'Forever' at compile time: more than 100000 steps",
+                "Cannot evaluate 'Deep' at compile time: more than 64 nested calls",
+            ]
+        );
+        let value = |name: &str| match analyzer.core.symbol_table.lookup(name).map(|s| &s.kind) {
+            Some(SymbolKind::Constant { value, .. }) => value.clone(),
+            other => panic!("Expected constant {}, found {:?}", name, other),
+        };
+        assert_eq!(value("F10"), Some(ConstantValue::LongInt(3628800)));
+        assert_eq!(value("S"), Some(ConstantValue::Integer(5050)));
+        assert_eq!(value("Big"), None);
+        match analyzer.core.symbol_table.lookup("Table").map(|s| &s.kind) {
+            Some(SymbolKind::Variable { var_type, .. }) => assert_eq!(var_type.size(), Some(51)),
+            other => panic!("Expected variable Table, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_types_and_operators() {
+        let ast = parser::Parser::new(
+            "program P;
+             type
+               TColor = (Red, Green, Blue);
+               TColors = set of TColor;
+               TDigits = set of 0..9;
+               TBig = set of integer;
+             var
+               C: TColors;
+               D: TDigits;
+               S: set of char;
+               B: boolean;
+               N: integer;
+             begin
+               C := [Red, Blue] + [Green] - [Red];
+               D := [1, 3..5] * D;
+               S := ['a'..'z', '_'];
+               B := (Green in C) and (N in D) and ('x' in S) and (C <= []);
+               D := [1, 12];
+               B := 3 in N;
+               B := 'a' in D;
+               B := D < D;
+               C := D
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Set base type must be an ordinal type with values from 0 to 255, found Integer",
+                "Constant 12 is out of the range 0..9",
+                "Right operand of IN must be a set, found Integer",
+                "Left operand of IN must be an element of set of 0..9, found Char",
+                "Sets can only be compared with '=', '<>', '<=' and '>='",
+                "Type mismatch: cannot assign set of 0..9 to set of (Red, Green, Blue)",
+            ]
+        );
+        match analyzer.core.symbol_table.lookup("S").map(|s| &s.kind) {
+            Some(SymbolKind::Variable { var_type, .. }) => assert_eq!(var_type.size(), Some(32)),
+            other => panic!("Expected variable S, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_generate_table() {
+        let ast = parser::Parser::new(
+            "program P;
+             var G: integer;
+             function Square(N: byte): word;
+             begin
+               Square := N * N
+             end;
+             function Scaled(N: integer): integer;
+             begin
+               Scaled := N * 1000
+             end;
+             function Impure(N: integer): integer;
+             begin
+               Impure := N + G
+             end;
+             const
+               Squares = GenerateTable(Square, 0, 255);
+               Small: array[0..3] of integer = GenerateTable(Scaled, -1, 2);
+               Short: array[0..2] of integer = GenerateTable(Scaled, 0, 3);
+               A = GenerateTable(Impure, 0, 3);
+               B = GenerateTable(Scaled, 0, 40);
+               C = GenerateTable(Square, 0, 256);
+             begin
+               G := Small[1]
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Type mismatch: constant 'Short' holds 3 elements of Integer, the table has 4 of Integer",
+                "GenerateTable: cannot run 'Impure' at compile time: it cannot use global variable 'G'",
+                "GenerateTable: Scaled(33): 33000 is out of the range of Integer",
+                "GenerateTable: Square(256): 256 is out of the range of Byte",
+            ]
+        );
+        let constant = |name: &str| match analyzer.core.symbol_table.lookup(name).map(|s| &s.kind) {
+            Some(SymbolKind::Constant { const_type, value, .. }) => (const_type.clone(), value.clone()),
+            other => panic!("Expected constant {}, found {:?}", name, other),
+        };
+        let (squares, Some(ConstantValue::Bytes(data))) = constant("Squares") else {
+            panic!("Expected a table");
+        };
+        assert_eq!(squares.size(), Some(512));
+        assert_eq!(&data[6..8], &9u16.to_le_bytes());
+        assert_eq!(&data[510..], &65025u16.to_le_bytes());
+        let (_, small) = constant("Small");
+        assert_eq!(small, Some(ConstantValue::Bytes([-1000i16, 0, 1000, 2000].iter().flat_map(|v| v.to_le_bytes()).collect())));
+    }
+
+    #[test]
+    fn test_string_types_and_intrinsics() {
+        let ast = parser::Parser::new(
+            "program P;
+             var
+               S: string;
+               T: string[10];
+               C: char;
+               N: integer;
+             begin
+               T := 'abc' + S + C;
+               S := Concat(S, T, 'x');
+               S := Copy(T, 2, N);
+               N := Length(S) + Pos('b', T);
+               C := T[N];
+               Delete(S, 1, 2);
+               Insert('ab', T, 3);
+               if (S = T) or (S < 'z') then S := C;
+               C := T[11];
+               N := Length(N);
+               Delete(N, 1, 1);
+               T := 5
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Constant 11 is out of the range 0..10",
+                "Length requires a string argument, found Integer",
+                "Delete requires a string variable, found Integer",
+                "Type mismatch: cannot assign Integer to String[10]",
+            ]
+        );
+        match analyzer.core.symbol_table.lookup("T").map(|s| &s.kind) {
+            Some(SymbolKind::Variable { var_type, .. }) => assert_eq!(var_type.size(), Some(11)),
+            other => panic!("Expected variable T, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_variant_records() {
+        let ast = parser::Parser::new(
+            "program P;
+             type
+               TKind = (Circle, Rect);
+               TShape = record
+                 X, Y: integer;
+                 case Kind: TKind of
+                   Circle: (Radius: integer);
+                   Rect: (Width, Height: integer; case Filled: boolean of true: (Color: byte))
+               end;
+               TReg = record
+                 case byte of
+                   0..1: (W: word);
+                   2: (Lo, Hi: byte)
+               end;
+               TBad = record
+                 case Tag: TKind of
+                   1: (N: integer);
+                   Rect: (N: byte)
+               end;
+             var S: TShape;
+             begin
+               S.Kind := Rect;
+               S.Height := S.Radius
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Variant label of type Integer does not match tag type (Circle, Rect)",
+                "Duplicate field 'N' in record",
+            ]
+        );
+        let layout = |name: &str| match analyzer.core.symbol_table.lookup(name).map(|s| &s.kind) {
+            Some(SymbolKind::TypeAlias { aliased_type: Type::Record { fields, size }, .. }) => (
+                fields.iter().map(|f| (f.name.clone(), f.offset.unwrap())).collect::<Vec<_>>(),
+                size.unwrap(),
+            ),
+            other => panic!("Expected record type {}, found {:?}", name, other),
+        };
+        let (fields, size) = layout("TShape");
+        let offsets: Vec<_> = fields.iter().map(|(name, offset)| format!("{}@{}", name, offset)).collect();
+        // Radius overlaps Width; Filled and Color follow Height
+        assert_eq!(offsets, ["X@0", "Y@2", "Kind@4", "Radius@6", "Width@6", "Height@8", "Filled@10", "Color@11"]);
+        assert_eq!(size, 12);
+        let (fields, size) = layout("TReg");
+        assert_eq!(fields, [("W".to_string(), 0), ("Lo".to_string(), 0), ("Hi".to_string(), 1)]);
+        assert_eq!(size, 2);
+    }
+
+    #[test]
+    fn test_class_layouts_and_virtual_methods() {
+        let ast = parser::Parser::new(
+            "program P;
+             type
+               TShape = class
+                 X, Y: integer;
+                 procedure Move(DX, DY: integer);
+                 procedure Draw; virtual;
+                 function Area: integer; virtual;
+               end;
+               TCircle = class(TShape)
+                 Radius: integer;
+                 procedure Draw; override;
+                 procedure Grow; override;
+                 procedure Move(DX, DY: integer); virtual;
+               end;
+             procedure TShape.Move(DX, DY: integer);
+             begin X := X + DX; Self.Y := Y + DY end;
+             procedure TShape.Draw; begin end;
+             function TShape.Area: integer; begin Result := 0 end;
+             procedure TCircle.Draw; begin Radius := Self.Area end;
+             var S: TShape; I: integer;
+             begin
+               S.Move(1, 2);
+               S.Draw;
+               I := S.Area + S.X;
+               S.Fly;
+               S.Move(1);
+               I := S.Draw()
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Method 'Grow' has no inherited virtual method to override",
+                "Class 'TShape' has no method 'Fly'",
+                "Method 'Move' expects 2 arguments, found 1",
+                "Procedure 'Draw' does not return a value",
+            ]
+        );
+        let layouts = analyzer.class_layouts();
+        let offsets = |layout: &::types::ClassLayout| {
+            layout.fields.iter().map(|f| format!("{}@{}", f.name, f.offset.unwrap())).collect::<Vec<_>>()
+        };
+        // Fields follow the VMT pointer; a subclass extends its parent
+        assert_eq!(offsets(&layouts[0]), ["X@2", "Y@4"]);
+        assert_eq!(offsets(&layouts[1]), ["X@2", "Y@4", "Radius@6"]);
+        assert_eq!(layouts[1].size, 8);
+        // Overrides replace their slot; new virtual methods are appended
+        assert_eq!(layouts[0].vmt, ["TShape__Draw", "TShape__Area"]);
+        assert_eq!(layouts[1].vmt, ["TCircle__Draw", "TShape__Area", "TCircle__Move"]);
+    }
+
+    #[test]
+    fn test_constructors_and_destructors() {
+        let ast = parser::Parser::new(
+            "program P;
+             type
+               TShape = class
+                 X, Y: integer;
+                 constructor Create(AX, AY: integer);
+                 destructor Destroy; virtual;
+                 destructor Close(Now: boolean);
+                 procedure Draw;
+               end;
+               TCircle = class(TShape)
+                 Radius: integer;
+                 constructor Create(R: integer);
+               end;
+             constructor TShape.Create(AX, AY: integer);
+             begin X := AX; if AY > 0 then Self.Y := AY end;
+             destructor TShape.Destroy; begin end;
+             procedure TShape.Draw; begin end;
+             constructor TCircle.Create(R: integer);
+             begin Radius := R end;
+             var S: TShape; C: TCircle;
+             begin
+               S := TShape.Create(1, 2);
+               C := TCircle.Create(3);
+               C.Draw;
+               S.Destroy;
+               S := TShape.Draw;
+               S := TShape.Create(1)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Destructor 'Close' cannot take parameters",
+                // Y is only assigned on one path; inherited fields are the parent constructor's
+                "Constructor 'Create' of class 'TShape' might not initialize 'Y'",
+                "Method 'Draw' is not a constructor; call it on an instance of 'TShape'",
+                "Method 'Create' expects 2 arguments, found 1",
+            ]
+        );
+        let kinds: Vec<_> = analyzer.class_layouts()[0].methods.iter().map(|m| m.kind).collect();
+        assert_eq!(kinds, [MethodKind::Constructor, MethodKind::Destructor, MethodKind::Destructor, MethodKind::Method]);
+    }
+
+    #[test]
+    fn test_interfaces_and_implementing_classes() {
+        let ast = parser::Parser::new(
+            "program P;
+             type
+               IDrawable = interface
+                 procedure Draw;
+               end;
+               IShape = interface(IDrawable)
+                 function Area(Scale: integer): integer;
+                 procedure Draw;
+               end;
+               TSquare = class(IShape)
+                 Side: integer;
+                 procedure Draw;
+                 function Area(Scale: integer): integer;
+               end;
+               TBig = class(TSquare)
+                 procedure Draw;
+               end;
+               TDot = class(IShape)
+                 function Area: integer;
+               end;
+               TNone = class(TSquare, TBig) end;
+             procedure TSquare.Draw; begin end;
+             function TSquare.Area(Scale: integer): integer; begin Result := Side * Scale end;
+             procedure TBig.Draw; begin end;
+             function TDot.Area: integer; begin Result := 0 end;
+             var S: TSquare; B: TBig; D: IDrawable; I: IShape; N: integer; F: boolean;
+             begin
+               I := S;
+               D := I;
+               I := B;
+               D.Draw;
+               N := I.Area(2);
+               F := D is IShape;
+               I := D as IShape;
+               I := D;
+               D.Area(1);
+               F := N is IShape
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some("test.pas".to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Method 'Draw' already declared in interface 'IShape'",
+                "Class 'TDot' does not implement method 'Draw' of interface 'IShape'",
+                "Method 'TDot.Area' does not match 'IShape.Area'",
+                "Class 'TNone' cannot implement 'TBig', which is not an interface",
+                "Type mismatch: cannot assign IDrawable to IShape",
+                "Interface 'IDrawable' has no method 'Area'",
+                "Interface query requires a class or interface reference, found Integer",
+            ]
+        );
+        let interfaces = analyzer.interface_layouts();
+        assert_eq!(interfaces[1].ancestors, ["IDrawable"]);
+        // A class implements the interfaces its interfaces extend, and those
+        // of its parent with its own methods
+        let layouts = analyzer.class_layouts();
+        assert_eq!(
+            layouts[0].interfaces,
+            [
+                ("IDrawable".to_string(), vec!["TSquare__Draw".to_string()]),
+                ("IShape".to_string(), vec!["TSquare__Draw".to_string(), "TSquare__Area".to_string()]),
+            ]
+        );
+        assert_eq!(layouts[1].interfaces[1].1, ["TBig__Draw", "TSquare__Area"]);
+    }
 }
@@ -12,7 +12,26 @@ impl SemanticAnalyzer {
             Node::IdentExpr(i) => {
                 if let Some(symbol) = self.core.symbol_table.lookup(&i.name) {
                     if let SymbolKind::Variable { var_type, .. } = &symbol.kind {
-                        var_type.clone()
+                        let var_type = var_type.clone();
+                        if self.for_loop_vars.iter().any(|v| v.eq_ignore_ascii_case(&i.name)) {
+                            self.core.add_error(
+                                format!("Cannot assign to for loop variable '{}'", i.name),
+                                i.span,
+                            );
+                        }
+                        var_type
+                    } else if let SymbolKind::Function { name, return_type, .. } = &symbol.kind
+                        && self.functions.iter().any(|(f, _)| f.eq_ignore_ascii_case(name))
+                    {
+                        // Assignment to the function name sets its result
+                        return_type.clone()
+                    } else if let SymbolKind::Constant { .. } = &symbol.kind {
+                        // Typed constants may live in ROM
+                        self.core.add_error(
+                            format!("Cannot assign to constant '{}'", i.name),
+                            i.span,
+                        );
+                        Type::Error
                     } else {
                         self.core.add_error(
                             format!("'{}' is not a variable", i.name),
@@ -28,15 +47,23 @@ impl SemanticAnalyzer {
                     Type::Error
                 }
             }
+            Node::DerefExpr(_) => self.analyze_expression(lvalue),
             Node::IndexExpr(idx) => {
+                if let Some(port_type) = self.analyze_port(idx) {
+                    return port_type;
+                }
                 let array_type = self.analyze_expression(&idx.array);
                 match array_type {
-                    Type::Array { element_type, .. } | Type::DynamicArray { element_type } => {
+                    Type::Array { index_type, element_type, .. } => {
+                        self.analyze_array_index(&idx.index, &index_type);
+                        *element_type
+                    }
+                    Type::DynamicArray { element_type } => {
                         let _index_type = self.analyze_expression(&idx.index);
-                        // Check index type is compatible with array index type
                         // For now, we assume integer indexing
                         *element_type
                     }
+                    Type::String { max_length } => self.analyze_string_index(&idx.index, max_length),
                     _ => {
                         self.core.add_error(
                             "Index expression must be applied to an array".to_string(),
@@ -48,6 +75,9 @@ impl SemanticAnalyzer {
             }
             Node::FieldExpr(field) => {
                 let record_type = self.analyze_expression(&field.record);
+                if let Type::Class { name } = &record_type {
+                    return self.analyze_class_field(name, field);
+                }
                 if let Type::Record { fields, .. } = record_type {
                     // Find field
                     if let Some(f) = fields.iter().find(|f| f.name == field.field) {
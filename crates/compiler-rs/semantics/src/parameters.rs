@@ -0,0 +1,636 @@
+//! Interprocedural constant propagation of parameters
+//!
+//! Run on an analyzed program or unit after dead code elimination, so that
+//! only calls which may run count. A value parameter every call passes the
+//! same constant, a configuration flag most often, is replaced by that
+//! constant in the routine's body and dropped from the routine and its
+//! calls; an `if` or `while` whose condition the constant decides is then
+//! reduced to the branch taken:
+//!
+//! ```text
+//!     procedure Plot(X: byte; Clip: boolean);   procedure Plot(X: byte);
+//!     begin                                     begin
+//!       if Clip then                              Port[$10] := X
+//!         Port[$10] := X                        end;
+//!       else
+//!         Port[$11] := X
+//!     end;
+//!     begin                                     begin
+//!       Plot(1, true);                            Plot(1);
+//!       Plot(2, Clipping)                         Plot(2)
+//!     end.                                      end.      { const Clipping = true }
+//! ```
+//!
+//! - **Routines**: procedures and functions declared once, other than
+//!   methods, external and generic routines and those a unit exports, that
+//!   the code only calls: a reference such as `@Plot`, or one from inline
+//!   assembly, may call them with anything.
+//! - **Parameters**: value parameters passed the same integer, Boolean or
+//!   Char constant by every call, as a constant expression or by leaving
+//!   out the same default. The routine never assigns them, takes their
+//!   address or passes them by reference, and has no declaration or `with`
+//!   that could hide them.
+//! - **Branches** are only reduced when the code removed holds no label a
+//!   `goto` may reach.
+
+use std::collections::{HashMap, HashSet};
+
+use ast::{BinaryOp, LiteralValue, Node, ParamType, UnaryOp};
+use symbols::ConstantValue;
+use tokens::Span;
+
+use crate::dead_code::has_label;
+use crate::{SemanticAnalyzer, WRITELN_INTRINSIC, WRITE_INTRINSIC};
+
+/// A parameter replaced by the constant every call passed it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstantParameter {
+    pub routine: String,
+    pub parameter: String,
+    pub value: ConstantValue,
+}
+
+/// Propagates the constants passed to routines into their bodies
+pub struct ParameterPropagator {
+    roots: Vec<String>, // Lowercase names of routines called from outside the code
+    propagated: Vec<ConstantParameter>,
+}
+
+/// What the calls seen so far pass a parameter
+#[derive(Debug, Clone, PartialEq)]
+enum Argument {
+    Unseen,
+    Constant(ConstantValue),
+    Varies,
+}
+
+/// A routine whose parameters may be propagated
+struct Candidate {
+    name: String,
+    /// Name, passing mode and default of each parameter, in order
+    params: Vec<(String, ParamType, Option<Node>)>,
+    arguments: Vec<Argument>,
+    calls: usize,
+    /// Referred to other than by a call, or hidden by a declaration
+    excluded: bool,
+}
+
+impl Default for ParameterPropagator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParameterPropagator {
+    pub fn new() -> Self {
+        Self { roots: vec![], propagated: vec![] }
+    }
+
+    /// Leave the routine `name` alone: it is called in a way the code does
+    /// not show, such as a runtime error handler named by a directive
+    pub fn keep(&mut self, name: &str) {
+        self.roots.push(name.to_lowercase());
+    }
+
+    /// Propagate the constant parameters of a program or unit `analyzer`
+    /// analyzed
+    pub fn propagate(&mut self, ast: &mut Node, analyzer: &SemanticAnalyzer) {
+        let mut routines = vec![];
+        collect_routines(ast, &mut routines);
+        let modes: HashMap<String, Vec<ParamType>> = routines
+            .iter()
+            .map(|decl| (routine_name(decl).to_lowercase(), flatten(decl).into_iter().map(|(_, mode, _)| mode).collect()))
+            .collect();
+        let mut scan = Scan { analyzer, candidates: self.candidates(ast, &routines), locals: vec![], routines: vec![], with_depth: 0 };
+        scan.visit(ast);
+
+        let mut propagated: Vec<(String, Vec<(usize, ConstantValue)>)> = vec![];
+        for (key, candidate) in scan.candidates {
+            if candidate.excluded || candidate.calls == 0 {
+                continue;
+            }
+            let decl = routines.iter().find(|decl| routine_name(decl).eq_ignore_ascii_case(&key)).expect("candidate is declared");
+            let positions: Vec<(usize, ConstantValue)> = candidate
+                .arguments
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, argument)| match argument {
+                    Argument::Constant(value) => Some((i, value)),
+                    _ => None,
+                })
+                .filter(|(i, _)| {
+                    let (name, mode, _) = &candidate.params[*i];
+                    *mode == ParamType::Value && is_read_only(name, routine_block(decl), &modes)
+                })
+                .collect();
+            if !positions.is_empty() {
+                for (i, value) in &positions {
+                    let parameter = candidate.params[*i].0.clone();
+                    self.propagated.push(ConstantParameter { routine: candidate.name.clone(), parameter, value: value.clone() });
+                }
+                propagated.push((key, positions));
+            }
+        }
+
+        let spans: HashMap<String, Span> = routines.iter().map(|decl| (routine_name(decl).to_lowercase(), decl.span())).collect();
+        for (key, positions) in &propagated {
+            rewrite(ast, key, positions);
+        }
+        self.propagated.sort_by_key(|parameter| spans.get(&parameter.routine.to_lowercase()).map(|span| span.start));
+    }
+
+    /// The parameters replaced, in declaration order
+    pub fn propagated(&self) -> &[ConstantParameter] {
+        &self.propagated
+    }
+
+    /// The routines of `ast` whose parameters may be propagated, by
+    /// lowercase name
+    fn candidates(&self, ast: &Node, routines: &[&Node]) -> HashMap<String, Candidate> {
+        let mut declared: HashMap<String, usize> = HashMap::new();
+        for decl in routines {
+            *declared.entry(routine_name(decl).to_lowercase()).or_default() += 1;
+        }
+        let mut exported = HashSet::new();
+        if let Node::Unit(unit) = ast {
+            for decl in unit.interface.iter().flat_map(|interface| interface.proc_decls.iter().chain(&interface.func_decls)) {
+                exported.insert(routine_name(decl).to_lowercase());
+            }
+        }
+        routines
+            .iter()
+            .filter(|decl| is_plain(decl))
+            .map(|decl| (routine_name(decl).to_lowercase(), decl))
+            .filter(|(key, _)| declared[key] == 1 && !exported.contains(key) && !self.roots.contains(key))
+            .map(|(key, decl)| {
+                let params = flatten(decl);
+                let arguments = vec![Argument::Unseen; params.len()];
+                let candidate = Candidate { name: routine_name(decl).to_string(), params, arguments, calls: 0, excluded: false };
+                (key, candidate)
+            })
+            .filter(|(_, candidate)| candidate.params.iter().any(|(_, mode, _)| *mode == ParamType::Value))
+            .collect()
+    }
+}
+
+/// Walks the code recording what each call of a candidate passes
+struct Scan<'a> {
+    analyzer: &'a SemanticAnalyzer,
+    candidates: HashMap<String, Candidate>,
+    /// Lowercase names declared by each routine around the code
+    locals: Vec<HashSet<String>>,
+    /// Lowercase names of the routines around the code
+    routines: Vec<String>,
+    with_depth: usize,
+}
+
+impl Scan<'_> {
+    fn visit(&mut self, node: &Node) {
+        match node {
+            Node::ProcDecl(_) | Node::FuncDecl(_) => {
+                let locals = declared_names(node);
+                for name in &locals {
+                    if let Some(candidate) = self.candidates.get_mut(name) {
+                        candidate.excluded = true;
+                    }
+                }
+                self.locals.push(locals);
+                self.routines.push(routine_name(node).to_lowercase());
+                for child in node.children() {
+                    self.visit(child);
+                }
+                self.routines.pop();
+                self.locals.pop();
+                return;
+            }
+            Node::WithStmt(_) => {
+                self.with_depth += 1;
+                for child in node.children() {
+                    self.visit(child);
+                }
+                self.with_depth -= 1;
+                return;
+            }
+            Node::CallStmt(call) => self.call(&call.name, &call.args),
+            Node::CallExpr(call) => self.call(&call.name, &call.args),
+            // A function's own name is its result inside it
+            Node::IdentExpr(ident) if !self.routines.contains(&ident.name.to_lowercase()) => {
+                self.exclude(&ident.name);
+            }
+            Node::AsmStmt(asm) => {
+                for (_, name) in asm.references() {
+                    self.exclude(name);
+                }
+            }
+            _ => {}
+        }
+        for child in node.children() {
+            self.visit(child);
+        }
+    }
+
+    fn exclude(&mut self, name: &str) {
+        if let Some(candidate) = self.candidates.get_mut(&name.to_lowercase()) {
+            candidate.excluded = true;
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Node]) {
+        let values: Vec<Option<ConstantValue>> = args.iter().map(|arg| self.constant(arg)).collect();
+        let analyzer = self.analyzer;
+        let Some(candidate) = self.candidates.get_mut(&name.to_lowercase()) else {
+            return;
+        };
+        if args.len() > candidate.params.len() {
+            candidate.excluded = true;
+            return;
+        }
+        candidate.calls += 1;
+        for (i, (argument, (_, _, default))) in candidate.arguments.iter_mut().zip(&candidate.params).enumerate() {
+            let value = match values.get(i) {
+                Some(value) => value.clone(),
+                None => default.as_ref().and_then(|default| analyzer.evaluate_constant_expression(default)),
+            };
+            *argument = match (&*argument, value.filter(|value| literal(value, Span::new(0, 0, 0, 0)).is_some())) {
+                (Argument::Unseen, Some(value)) => Argument::Constant(value),
+                (Argument::Constant(seen), Some(value)) if *seen == value => Argument::Constant(value),
+                _ => Argument::Varies,
+            };
+        }
+    }
+
+    /// The value of a constant argument; names a local declaration or a
+    /// `with` may hide make it unknown
+    fn constant(&self, arg: &Node) -> Option<ConstantValue> {
+        let mut names = HashSet::new();
+        identifiers(arg, &mut names);
+        let hidden = |name: &String| self.with_depth > 0 || self.locals.iter().any(|locals| locals.contains(name));
+        if names.iter().any(hidden) {
+            return None;
+        }
+        self.analyzer.evaluate_constant_expression(arg)
+    }
+}
+
+/// Whether a routine's parameters may be changed: not a method, external
+/// or generic
+fn is_plain(decl: &Node) -> bool {
+    match decl {
+        Node::ProcDecl(proc) => proc.class_name.is_none() && !proc.is_external && !proc.is_forward && proc.generic_params.is_empty(),
+        Node::FuncDecl(func) => func.class_name.is_none() && !func.is_external && !func.is_forward && func.generic_params.is_empty(),
+        _ => false,
+    }
+}
+
+fn routine_name(decl: &Node) -> &str {
+    match decl {
+        Node::ProcDecl(proc) => &proc.name,
+        Node::FuncDecl(func) => &func.name,
+        _ => "",
+    }
+}
+
+fn routine_block(decl: &Node) -> &Node {
+    match decl {
+        Node::ProcDecl(proc) => &proc.block,
+        Node::FuncDecl(func) => &func.block,
+        _ => decl,
+    }
+}
+
+fn routine_params(decl: &Node) -> &[ast::Param] {
+    match decl {
+        Node::ProcDecl(proc) => &proc.params,
+        Node::FuncDecl(func) => &func.params,
+        _ => &[],
+    }
+}
+
+/// Name, passing mode and default of each parameter of a routine, in order
+fn flatten(decl: &Node) -> Vec<(String, ParamType, Option<Node>)> {
+    routine_params(decl)
+        .iter()
+        .flat_map(|param| {
+            let default = param.default_value.as_deref().cloned();
+            param.names.iter().map(move |name| (name.clone(), param.param_type, default.clone()))
+        })
+        .collect()
+}
+
+/// Every procedure and function declared in `node`, at any depth
+fn collect_routines<'a>(node: &'a Node, routines: &mut Vec<&'a Node>) {
+    if matches!(node, Node::ProcDecl(_) | Node::FuncDecl(_)) {
+        routines.push(node);
+    }
+    for child in node.children() {
+        collect_routines(child, routines);
+    }
+}
+
+/// Lowercase names a routine declares for its body: its parameters and
+/// local declarations
+fn declared_names(decl: &Node) -> HashSet<String> {
+    let mut names: HashSet<String> = flatten(decl).into_iter().map(|(name, _, _)| name.to_lowercase()).collect();
+    if let Node::Block(block) = routine_block(decl) {
+        let decls = [&block.var_decls, &block.const_decls, &block.type_decls, &block.proc_decls, &block.func_decls];
+        for local in decls.into_iter().flatten() {
+            match local {
+                Node::VarDecl(var) => names.extend(var.names.iter().map(|name| name.to_lowercase())),
+                Node::ConstDecl(constant) => {
+                    names.insert(constant.name.to_lowercase());
+                }
+                Node::TypeDecl(type_decl) => {
+                    names.insert(type_decl.name.to_lowercase());
+                }
+                Node::ProcDecl(_) | Node::FuncDecl(_) => {
+                    names.insert(routine_name(local).to_lowercase());
+                }
+                _ => {}
+            }
+        }
+    }
+    names
+}
+
+/// Add the lowercase identifiers `node` reads to `names`
+fn identifiers(node: &Node, names: &mut HashSet<String>) {
+    if let Node::IdentExpr(ident) = node {
+        names.insert(ident.name.to_lowercase());
+    }
+    for child in node.children() {
+        identifiers(child, names);
+    }
+}
+
+fn mentions(node: &Node, name: &str) -> bool {
+    matches!(node, Node::IdentExpr(ident) if ident.name.eq_ignore_ascii_case(name))
+        || node.children().into_iter().any(|child| mentions(child, name))
+}
+
+/// Whether `name` is only read in `body`, and nothing in it could hide it
+fn is_read_only(name: &str, body: &Node, modes: &HashMap<String, Vec<ParamType>>) -> bool {
+    // An argument the callee may write to
+    let written_by = |callee: &str, args: &[Node], statement: bool| {
+        args.iter().enumerate().any(|(i, arg)| {
+            if !is_lvalue(arg) || !mentions(arg, name) {
+                return false;
+            }
+            match modes.get(&callee.to_lowercase()) {
+                Some(modes) => !matches!(modes.get(i), Some(ParamType::Value | ParamType::Const | ParamType::ConstRef)),
+                None => statement && !callee.eq_ignore_ascii_case(WRITE_INTRINSIC) && !callee.eq_ignore_ascii_case(WRITELN_INTRINSIC),
+            }
+        })
+    };
+    let unsafe_here = match body {
+        Node::WithStmt(_) => true,
+        Node::VarDecl(var) => var.names.iter().any(|var| var.eq_ignore_ascii_case(name)),
+        Node::ConstDecl(constant) => constant.name.eq_ignore_ascii_case(name),
+        Node::TypeDecl(type_decl) => type_decl.name.eq_ignore_ascii_case(name),
+        Node::ProcDecl(_) | Node::FuncDecl(_) => declared_names(body).contains(&name.to_lowercase()),
+        Node::AsmStmt(asm) => asm.references().into_iter().any(|(_, reference)| reference.eq_ignore_ascii_case(name)),
+        Node::AssignStmt(assign) => mentions(&assign.target, name),
+        Node::ForStmt(for_stmt) => for_stmt.var_name.eq_ignore_ascii_case(name),
+        Node::ForInStmt(for_in) => for_in.var_name.eq_ignore_ascii_case(name),
+        Node::AddressOfExpr(address) => mentions(&address.target, name),
+        Node::UnaryExpr(unary) => unary.op == UnaryOp::AddressOf && mentions(&unary.expr, name),
+        Node::CallStmt(call) => written_by(&call.name, &call.args, true),
+        Node::CallExpr(call) => written_by(&call.name, &call.args, false),
+        Node::MethodCall(call) => call.args.iter().any(|arg| is_lvalue(arg) && mentions(arg, name)),
+        _ => false,
+    };
+    !unsafe_here && body.children().into_iter().all(|child| is_read_only(name, child, modes))
+}
+
+fn is_lvalue(node: &Node) -> bool {
+    matches!(node, Node::IdentExpr(_) | Node::IndexExpr(_) | Node::FieldExpr(_) | Node::DerefExpr(_))
+}
+
+/// `value` as an expression, if it is an integer, Boolean or Char
+fn literal(value: &ConstantValue, span: Span) -> Option<Node> {
+    let literal = |value| Node::LiteralExpr(ast::LiteralExpr { value, span });
+    let integer = |n: i64| {
+        let magnitude = literal(LiteralValue::Integer(n.unsigned_abs() as u32));
+        if n < 0 {
+            Node::UnaryExpr(ast::UnaryExpr { op: UnaryOp::Minus, expr: Box::new(magnitude), span })
+        } else {
+            magnitude
+        }
+    };
+    match value {
+        ConstantValue::Integer(n) => Some(integer(*n as i64)),
+        ConstantValue::Byte(n) => Some(integer(*n as i64)),
+        ConstantValue::Word(n) => Some(integer(*n as i64)),
+        ConstantValue::LongInt(n) => Some(integer(*n as i64)),
+        ConstantValue::Cardinal(n) => Some(integer(*n as i64)),
+        ConstantValue::Boolean(b) => Some(literal(LiteralValue::Boolean(*b))),
+        ConstantValue::Char(c) => Some(literal(LiteralValue::Char(*c))),
+        _ => None,
+    }
+}
+
+/// Substitute the constants at `positions` for the parameters of the
+/// routine `key` (lowercase) and drop them from it and its calls
+fn rewrite(node: &mut Node, key: &str, positions: &[(usize, ConstantValue)]) {
+    let drop_arguments = |args: &mut Vec<Node>| {
+        for (i, _) in positions.iter().rev() {
+            if *i < args.len() {
+                args.remove(*i);
+            }
+        }
+    };
+    match node {
+        Node::CallStmt(call) if call.name.eq_ignore_ascii_case(key) => drop_arguments(&mut call.args),
+        Node::CallExpr(call) if call.name.eq_ignore_ascii_case(key) => drop_arguments(&mut call.args),
+        Node::ProcDecl(_) | Node::FuncDecl(_) if routine_name(node).eq_ignore_ascii_case(key) => {
+            let params = flatten(node);
+            let (params_list, block) = match node {
+                Node::ProcDecl(proc) => (&mut proc.params, &mut proc.block),
+                Node::FuncDecl(func) => (&mut func.params, &mut func.block),
+                _ => unreachable!(),
+            };
+            for (i, value) in positions {
+                substitute(block, &params[*i].0, value);
+            }
+            prune(block);
+            let mut index = 0;
+            for param in params_list.iter_mut() {
+                param.names.retain(|_| {
+                    index += 1;
+                    !positions.iter().any(|(i, _)| *i == index - 1)
+                });
+            }
+            params_list.retain(|param| !param.names.is_empty());
+        }
+        _ => {}
+    }
+    for child in node.children_mut() {
+        rewrite(child, key, positions);
+    }
+}
+
+/// Replace the reads of `name` in `node` by `value`
+fn substitute(node: &mut Node, name: &str, value: &ConstantValue) {
+    if let Node::IdentExpr(ident) = node
+        && ident.name.eq_ignore_ascii_case(name)
+        && let Some(literal) = literal(value, ident.span)
+    {
+        *node = literal;
+        return;
+    }
+    for child in node.children_mut() {
+        substitute(child, name, value);
+    }
+}
+
+/// Reduce the `if` and `while` statements of `node` whose conditions are
+/// constant to the code that runs
+fn prune(node: &mut Node) {
+    for child in node.children_mut() {
+        prune(child);
+    }
+    let empty = |span| {
+        Node::Block(ast::Block {
+            directives: vec![],
+            label_decls: vec![],
+            const_decls: vec![],
+            type_decls: vec![],
+            var_decls: vec![],
+            threadvar_decls: vec![],
+            proc_decls: vec![],
+            func_decls: vec![],
+            operator_decls: vec![],
+            statements: vec![],
+            span,
+        })
+    };
+    let replacement = match node {
+        Node::IfStmt(if_stmt) => {
+            let (taken, removed) = match condition(&if_stmt.condition) {
+                Some(true) => (Some(&*if_stmt.then_block), if_stmt.else_block.as_deref()),
+                Some(false) => (if_stmt.else_block.as_deref(), Some(&*if_stmt.then_block)),
+                None => return,
+            };
+            if removed.is_some_and(has_label) {
+                return;
+            }
+            taken.cloned().unwrap_or_else(|| empty(if_stmt.span))
+        }
+        Node::WhileStmt(while_stmt) if condition(&while_stmt.condition) == Some(false) && !has_label(&while_stmt.body) => {
+            empty(while_stmt.span)
+        }
+        _ => return,
+    };
+    *node = replacement;
+}
+
+/// The value of a condition of literals
+fn condition(expr: &Node) -> Option<bool> {
+    match expr {
+        Node::LiteralExpr(literal) => match literal.value {
+            LiteralValue::Boolean(b) => Some(b),
+            _ => None,
+        },
+        Node::UnaryExpr(unary) if unary.op == UnaryOp::Not => condition(&unary.expr).map(|b| !b),
+        Node::BinaryExpr(binary) => {
+            // `false and X` and `true or X` need no X that calls nothing
+            let pure = || !has_call(&binary.right);
+            match binary.op {
+                BinaryOp::And => match condition(&binary.left) {
+                    Some(false) if pure() => Some(false),
+                    Some(true) => condition(&binary.right),
+                    _ => None,
+                },
+                BinaryOp::Or => match condition(&binary.left) {
+                    Some(true) if pure() => Some(true),
+                    Some(false) => condition(&binary.right),
+                    _ => None,
+                },
+                op => {
+                    let (left, right) = (ordinal(&binary.left)?, ordinal(&binary.right)?);
+                    match op {
+                        BinaryOp::Equal => Some(left == right),
+                        BinaryOp::NotEqual => Some(left != right),
+                        BinaryOp::Less => Some(left < right),
+                        BinaryOp::LessEqual => Some(left <= right),
+                        BinaryOp::Greater => Some(left > right),
+                        BinaryOp::GreaterEqual => Some(left >= right),
+                        _ => None,
+                    }
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The ordinal value of a literal
+fn ordinal(expr: &Node) -> Option<i64> {
+    match expr {
+        Node::LiteralExpr(literal) => match literal.value {
+            LiteralValue::Integer(n) => Some(n as i64),
+            LiteralValue::Char(c) => Some(c as i64),
+            LiteralValue::Boolean(b) => Some(b as i64),
+            _ => None,
+        },
+        Node::UnaryExpr(unary) if unary.op == UnaryOp::Minus => ordinal(&unary.expr).map(|n| -n),
+        Node::UnaryExpr(unary) if unary.op == UnaryOp::Plus => ordinal(&unary.expr),
+        _ => None,
+    }
+}
+
+fn has_call(node: &Node) -> bool {
+    matches!(node, Node::CallExpr(_) | Node::MethodCall(_)) || node.children().into_iter().any(has_call)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn propagate(source: &str) -> (Node, Vec<ConstantParameter>) {
+        let mut ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(None);
+        analyzer.analyze(&ast);
+        let mut propagator = ParameterPropagator::new();
+        propagator.propagate(&mut ast, &analyzer);
+        (ast, propagator.propagated().to_vec())
+    }
+
+    #[test]
+    fn test_constant_parameters_are_propagated() {
+        let (ast, propagated) = propagate(
+            "program P;
+             const Clipping = true;
+             var N: integer;
+             procedure Plot(X: byte; Clip: boolean; Color: integer);
+             begin
+               if Clip then N := X else N := 0;
+               while Color <> 2 do N := N + 1
+             end;
+             procedure Both(K: integer); begin N := K end;
+             procedure Flip(F: boolean); begin F := not F end;
+             begin
+               Plot(1, Clipping, 2);
+               Plot(2, true, 2);
+               Both(1);
+               Both(2);
+               Flip(true)
+             end.",
+        );
+        let names: Vec<_> = propagated.iter().map(|p| (p.routine.as_str(), p.parameter.as_str(), p.value.clone())).collect();
+        assert_eq!(names, [("Plot", "Clip", ConstantValue::Boolean(true)), ("Plot", "Color", ConstantValue::Integer(2))]);
+
+        let Node::Program(program) = &ast else { panic!() };
+        let Node::Block(block) = program.block.as_ref() else { panic!() };
+        let Node::ProcDecl(plot) = &block.proc_decls[0] else { panic!() };
+        assert_eq!(plot.params.len(), 1);
+        assert_eq!(plot.params[0].names, ["X"]);
+        let Node::Block(body) = plot.block.as_ref() else { panic!() };
+        // The else branch and the loop are gone
+        assert!(matches!(&body.statements[0], Node::AssignStmt(assign) if matches!(assign.value.as_ref(), Node::IdentExpr(_))));
+        assert!(matches!(&body.statements[1], Node::Block(empty) if empty.statements.is_empty()));
+        let Node::CallStmt(call) = &block.statements[0] else { panic!() };
+        assert_eq!(call.args.len(), 1);
+        let Node::CallStmt(call) = &block.statements[4] else { panic!() };
+        assert_eq!(call.args.len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Cross-compilation sanity checks
+//!
+//! The compiler runs on a 32- or 64-bit host but computes constants as the
+//! Z80 target does (see [`crate::constants`]). Constructs whose value would
+//! differ if the host's arithmetic or character set were used are reported
+//! as warnings while they are analyzed:
+//!
+//! - **Overflow**: a constant expression whose exact value does not fit
+//!   its type. The target wraps it, e.g. an Integer product at 16 bits.
+//! - **Characters above #127**: a character literal beyond ASCII is read
+//!   as its Latin-1 code, so the ordinal taken while compiling (by `Ord`,
+//!   `Succ`, `Pred` or an ordering comparison) is the host's. The target's
+//!   character set may place the character elsewhere, and text transcoded
+//!   by `{$CODEPAGE}` holds the target's byte instead.
+//!
+//! ```text
+//!     const
+//!       Frames = 60 * 600;      { warning: 36000 wraps to -29536 }
+//!       Limit = 600 * 50;       { 30000 fits an Integer }
+//!       Accent = Ord('é');      { warning: 233 is the host's code }
+//! ```
+
+use ast::Node;
+use symbols::ConstantValue;
+
+use crate::SemanticAnalyzer;
+
+impl SemanticAnalyzer {
+    /// Warn when the constant arithmetic `expr` overflows its type, so the
+    /// value the target computes is not the exact one
+    pub(crate) fn check_constant_overflow(&mut self, expr: &Node) {
+        let operand = |analyzer: &Self, expr: &Node| {
+            analyzer
+                .evaluate_constant_expression(expr)
+                .as_ref()
+                .and_then(Self::integer_value)
+                .map(i128::from)
+        };
+        let exact = match expr {
+            Node::BinaryExpr(bin) => {
+                let (Some(left), Some(right)) = (operand(self, &bin.left), operand(self, &bin.right)) else {
+                    return;
+                };
+                match bin.op {
+                    ast::BinaryOp::Add => left + right,
+                    ast::BinaryOp::Subtract => left - right,
+                    ast::BinaryOp::Multiply => left * right,
+                    ast::BinaryOp::Divide | ast::BinaryOp::Div if right != 0 => left / right,
+                    ast::BinaryOp::Mod if right != 0 => left % right,
+                    _ => return,
+                }
+            }
+            Node::UnaryExpr(unary) if unary.op == ast::UnaryOp::Minus => {
+                let Some(value) = operand(self, &unary.expr) else {
+                    return;
+                };
+                -value
+            }
+            _ => return,
+        };
+        let Some(value) = self.evaluate_constant_expression(expr) else {
+            return;
+        };
+        if let Some(folded) = Self::integer_value(&value)
+            && i128::from(folded) != exact
+        {
+            self.core.add_warning(
+                format!(
+                    "Constant expression overflows {}: {} wraps to {} on the target",
+                    integer_type_name(&value),
+                    exact,
+                    folded
+                ),
+                expr.span(),
+            );
+        }
+    }
+
+    /// Warn when `expr`, whose ordinal is taken while compiling, is a
+    /// character literal beyond ASCII
+    pub(crate) fn check_character_ordinal(&mut self, expr: &Node) {
+        if let Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Char(c), span }) = expr
+            && !c.is_ascii()
+        {
+            self.core.add_warning(
+                format!(
+                    "'{}' is #{} in the host's Latin-1; the target's character set may give it another code",
+                    *c as char, c
+                ),
+                *span,
+            );
+        }
+    }
+}
+
+fn integer_type_name(value: &ConstantValue) -> &'static str {
+    match value {
+        ConstantValue::Byte(_) => "Byte",
+        ConstantValue::Word(_) => "Word",
+        ConstantValue::LongInt(_) => "LongInt",
+        ConstantValue::Cardinal(_) => "Cardinal",
+        _ => "Integer",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symbols::SymbolKind;
+
+    #[test]
+    fn test_constants_fold_as_the_target_computes_them() {
+        let source = "program P;
+             const
+               Frames = 60 * 600;
+               Limit = 600 * 50;
+               Accent = Ord('é');
+               Lowest = -(-32767 - 1);
+               Big = 100000 * 100000;
+             var C: char;
+             begin
+               if C > 'é' then C := 'a'
+             end.";
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(None);
+        let messages: Vec<String> = analyzer.analyze(&ast).into_iter().map(|d| d.message).collect();
+        assert_eq!(
+            messages,
+            [
+                "Constant expression overflows Integer: 36000 wraps to -29536 on the target",
+                "'é' is #233 in the host's Latin-1; the target's character set may give it another code",
+                "Constant expression overflows Integer: 32768 wraps to -32768 on the target",
+                "Constant expression overflows LongInt: 10000000000 wraps to 1410065408 on the target",
+                "'é' is #233 in the host's Latin-1; the target's character set may give it another code",
+            ]
+        );
+        let value = |name: &str| match &analyzer.core.symbol_table.lookup(name).unwrap().kind {
+            SymbolKind::Constant { value, .. } => value.clone(),
+            _ => None,
+        };
+        assert_eq!(value("Frames"), Some(ConstantValue::Integer(-29536)));
+        assert_eq!(value("Limit"), Some(ConstantValue::Integer(30000)));
+        assert_eq!(value("Lowest"), Some(ConstantValue::Integer(-32768)));
+        assert_eq!(value("Big"), Some(ConstantValue::LongInt(1410065408)));
+    }
+}
@@ -0,0 +1,358 @@
+//! Purity inference
+//!
+//! Run on an analyzed program or unit to find its pure functions: those
+//! whose result depends on their arguments alone and that do nothing else,
+//! so a second call with the same arguments may reuse the result of the
+//! first, and a call whose result is unused may be dropped. The driver
+//! passes them to the IR, whose common subexpression elimination does so.
+//!
+//! ```text
+//!     function Cube(N: integer): integer;     { pure }
+//!     begin Cube := N * N * N end;
+//!     function Next: integer;                 { reads and assigns Seed }
+//!     begin Seed := Seed * 75 + 74; Next := Seed end;
+//!     {$PURE}
+//!     function Sine(A: byte): byte; external; { pure: asserted }
+//! ```
+//!
+//! A function is pure unless it:
+//!
+//! - takes a parameter by reference (`var`, `out`, or untyped)
+//! - reads or assigns a variable that is not its own, including one of an
+//!   enclosing routine, or reads or writes through a pointer
+//! - takes an address, or contains inline assembly or a `raise`
+//! - performs I/O (console output and input, `Port` and `PortW`)
+//! - calls a routine other than a pure function or a pure intrinsic such
+//!   as `Abs` or `Ord`, or calls a method
+//!
+//! Calls between functions are followed, recursion included. Methods and
+//! generic functions are never pure. `{$PURE}` before a function asserts
+//! that it is: an external one is taken at its word, and one whose body
+//! breaks a rule above is reported with a warning and not treated as pure.
+
+use std::collections::{HashMap, HashSet};
+
+use ast::{Node, ParamType, UnaryOp};
+use errors::{Diagnostic, ErrorSeverity};
+
+use crate::{
+    DEC_INTRINSIC, EXIT_INTRINSIC, INC_INTRINSIC, PORTW_INTRINSIC, PORT_INTRINSIC, READLN_INTRINSIC, RESULT_VARIABLE,
+    SIZEOF_INTRINSIC, WRITELN_INTRINSIC, WRITE_INTRINSIC,
+};
+
+/// Intrinsic functions computing their result from their arguments alone
+pub const PURE_INTRINSICS: [&str; 19] = [
+    "Abs", "Odd", "Ord", "Chr", "Succ", "Pred", "Lo", "Hi", "Swap", "Sqr", "Trunc", "Round", SIZEOF_INTRINSIC, "Length",
+    "Low", "High", "Copy", "Pos", "UpCase",
+];
+
+/// Finds the pure functions of a program or unit
+pub struct PurityAnalysis {
+    filename: Option<String>,
+    pure: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// What the analysis knows of a program or unit: its variables and
+/// routines by lowercase name
+struct Declarations<'a> {
+    variables: HashSet<String>,
+    routines: HashMap<String, Vec<&'a Node>>,
+}
+
+impl PurityAnalysis {
+    pub fn new(filename: Option<String>) -> Self {
+        Self { filename, pure: vec![], diagnostics: vec![] }
+    }
+
+    /// Find the pure functions of `ast`
+    pub fn analyze(&mut self, ast: &Node) {
+        let mut declarations = Declarations { variables: HashSet::new(), routines: HashMap::new() };
+        collect(ast, &mut declarations);
+
+        // Every candidate is pure until its body shows otherwise; repeat
+        // until no call makes another one impure
+        let candidates: Vec<&ast::FuncDecl> = declarations
+            .routines
+            .values()
+            .filter(|decls| decls.iter().filter(|decl| !is_forward(decl)).count() == 1)
+            .flat_map(|decls| decls.iter().copied())
+            .filter_map(|decl| match decl {
+                Node::FuncDecl(func) if !func.is_forward && func.class_name.is_none() && func.generic_params.is_empty() => {
+                    Some(func)
+                }
+                _ => None,
+            })
+            .filter(|func| !func.is_external || asserted(&declarations, &func.name))
+            .collect();
+        let mut pure: HashSet<String> = candidates.iter().map(|func| func.name.to_lowercase()).collect();
+        let mut reasons: HashMap<String, String> = HashMap::new();
+        loop {
+            let mut changed = false;
+            for func in candidates.iter().filter(|func| !func.is_external) {
+                let key = func.name.to_lowercase();
+                if !pure.contains(&key) {
+                    continue;
+                }
+                if let Some(reason) = impurity(func, &declarations, &pure) {
+                    pure.remove(&key);
+                    reasons.insert(key, reason);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut functions: Vec<&&ast::FuncDecl> = candidates.iter().filter(|func| pure.contains(&func.name.to_lowercase())).collect();
+        functions.sort_by_key(|func| func.span.start);
+        for func in functions {
+            self.pure.push(func.name.clone());
+            self.pure.extend(func.external_name.clone());
+        }
+        let mut unproven: Vec<_> = declarations
+            .routines
+            .values()
+            .flatten()
+            .filter_map(|decl| match decl {
+                Node::FuncDecl(func) if func.is_pure && !func.is_external => Some(func),
+                _ => None,
+            })
+            .filter(|func| !pure.contains(&func.name.to_lowercase()))
+            .collect();
+        unproven.sort_by_key(|func| func.span.start);
+        for func in unproven {
+            let reason = reasons.get(&func.name.to_lowercase()).cloned().unwrap_or_else(|| "cannot be shown pure".to_string());
+            self.warn(format!("Function '{}' is marked {{$PURE}} but {}", func.name, reason), func.span);
+        }
+    }
+
+    /// Names of the pure functions, in declaration order; an external one's
+    /// external name too
+    pub fn pure_functions(&self) -> &[String] {
+        &self.pure
+    }
+
+    /// A warning for each `{$PURE}` function whose body is not pure
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn warn(&mut self, message: String, span: tokens::Span) {
+        self.diagnostics.push(
+            Diagnostic::new(ErrorSeverity::Warning, message, span)
+                .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string())),
+        );
+    }
+}
+
+fn is_forward(decl: &Node) -> bool {
+    matches!(decl, Node::ProcDecl(proc) if proc.is_forward) || matches!(decl, Node::FuncDecl(func) if func.is_forward)
+}
+
+/// Whether a declaration of the function `name` is marked {$PURE}
+fn asserted(declarations: &Declarations, name: &str) -> bool {
+    declarations.routines[&name.to_lowercase()].iter().any(|decl| matches!(decl, Node::FuncDecl(func) if func.is_pure))
+}
+
+/// Record the variables, parameters and routines declared in `node`
+fn collect<'a>(node: &'a Node, declarations: &mut Declarations<'a>) {
+    match node {
+        Node::VarDecl(var) => declarations.variables.extend(var.names.iter().map(|name| name.to_lowercase())),
+        // A typed constant may be assigned
+        Node::ConstDecl(constant) if constant.type_expr.is_some() => {
+            declarations.variables.insert(constant.name.to_lowercase());
+        }
+        Node::ProcDecl(proc) => {
+            declarations.routines.entry(proc.name.to_lowercase()).or_default().push(node);
+            declarations.variables.extend(proc.params.iter().flat_map(|param| &param.names).map(|name| name.to_lowercase()));
+        }
+        Node::FuncDecl(func) => {
+            declarations.routines.entry(func.name.to_lowercase()).or_default().push(node);
+            declarations.variables.extend(func.params.iter().flat_map(|param| &param.names).map(|name| name.to_lowercase()));
+        }
+        _ => {}
+    }
+    for child in node.children() {
+        collect(child, declarations);
+    }
+}
+
+/// Why `func` is not pure, given the functions in `pure`, if it is not
+fn impurity(func: &ast::FuncDecl, declarations: &Declarations, pure: &HashSet<String>) -> Option<String> {
+    if let Some(param) = func
+        .params
+        .iter()
+        .find(|param| matches!(param.param_type, ParamType::Var | ParamType::Out) || param.type_expr.is_none())
+    {
+        return Some(format!("takes '{}' by reference", param.names.join(", ")));
+    }
+    let mut locals: HashSet<String> = func.params.iter().flat_map(|param| &param.names).map(|name| name.to_lowercase()).collect();
+    locals.insert(func.name.to_lowercase());
+    locals.insert(RESULT_VARIABLE.to_lowercase());
+    let Node::Block(block) = func.block.as_ref() else {
+        return None;
+    };
+    for decl in block.var_decls.iter().chain(&block.const_decls).chain(&block.type_decls) {
+        match decl {
+            Node::VarDecl(var) => locals.extend(var.names.iter().map(|name| name.to_lowercase())),
+            Node::ConstDecl(constant) => {
+                locals.insert(constant.name.to_lowercase());
+            }
+            Node::TypeDecl(type_decl) => {
+                locals.insert(type_decl.name.to_lowercase());
+            }
+            _ => {}
+        }
+    }
+    let body = Body { name: func.name.to_lowercase(), locals, declarations, pure };
+    block.statements.iter().find_map(|statement| body.impurity(statement))
+}
+
+/// The body of a function being checked
+struct Body<'a> {
+    /// Lowercase name of the function
+    name: String,
+    /// Lowercase names of its parameters, result and local declarations
+    locals: HashSet<String>,
+    declarations: &'a Declarations<'a>,
+    pure: &'a HashSet<String>,
+}
+
+impl Body<'_> {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.contains(&name.to_lowercase())
+    }
+
+    /// Why `node` keeps the function from being pure, if it does
+    fn impurity(&self, node: &Node) -> Option<String> {
+        match node {
+            Node::AssignStmt(assign) => match root(&assign.target) {
+                Some(name) if !self.is_local(name) => return Some(format!("assigns '{}', which is not its own", name)),
+                None => return Some("writes through a pointer".to_string()),
+                _ => {}
+            },
+            Node::ForStmt(for_stmt) if !self.is_local(&for_stmt.var_name) => {
+                return Some(format!("assigns '{}', which is not its own", for_stmt.var_name));
+            }
+            Node::ForInStmt(for_in) if !self.is_local(&for_in.var_name) => {
+                return Some(format!("assigns '{}', which is not its own", for_in.var_name));
+            }
+            Node::IndexExpr(index)
+                if matches!(index.array.as_ref(), Node::IdentExpr(ident)
+                    if ident.name.eq_ignore_ascii_case(PORT_INTRINSIC) || ident.name.eq_ignore_ascii_case(PORTW_INTRINSIC)) =>
+            {
+                return Some("performs I/O".to_string());
+            }
+            Node::IdentExpr(ident) if !self.is_local(&ident.name) => {
+                let key = ident.name.to_lowercase();
+                if self.declarations.variables.contains(&key) {
+                    return Some(format!("reads '{}', which is not its own", ident.name));
+                }
+                if self.declarations.routines.contains_key(&key) && !self.pure.contains(&key) {
+                    return Some(format!("calls '{}', which is not pure", ident.name));
+                }
+            }
+            Node::CallStmt(call) => {
+                if let Some(reason) = self.call_impurity(&call.name, &call.args) {
+                    return Some(reason);
+                }
+            }
+            Node::CallExpr(call) => {
+                if let Some(reason) = self.call_impurity(&call.name, &call.args) {
+                    return Some(reason);
+                }
+            }
+            Node::DerefExpr(_) => return Some("reads through a pointer".to_string()),
+            Node::AddressOfExpr(_) => return Some("takes an address".to_string()),
+            Node::UnaryExpr(unary) if unary.op == UnaryOp::AddressOf => return Some("takes an address".to_string()),
+            Node::AsmStmt(_) => return Some("contains inline assembly".to_string()),
+            Node::RaiseStmt(_) => return Some("raises an exception".to_string()),
+            Node::MethodCall(call) => return Some(format!("calls the method '{}'", call.method)),
+            Node::InheritedExpr(_) => return Some("calls an inherited method".to_string()),
+            _ => {}
+        }
+        node.children().into_iter().find_map(|child| self.impurity(child))
+    }
+
+    fn call_impurity(&self, name: &str, args: &[Node]) -> Option<String> {
+        let key = name.to_lowercase();
+        let is = |intrinsic: &str| name.eq_ignore_ascii_case(intrinsic);
+        // Inside a function its name is its result, but calls still call it
+        if self.declarations.routines.contains_key(&key) && (key == self.name || !self.is_local(name)) {
+            return (!self.pure.contains(&key)).then(|| format!("calls '{}', which is not pure", name));
+        }
+        if is(WRITE_INTRINSIC) || is(WRITELN_INTRINSIC) || is(READLN_INTRINSIC) {
+            return Some("performs I/O".to_string());
+        }
+        if is(INC_INTRINSIC) || is(DEC_INTRINSIC) {
+            return match args.first().map(root) {
+                Some(Some(name)) if self.is_local(name) => None,
+                Some(Some(name)) => Some(format!("assigns '{}', which is not its own", name)),
+                _ => Some("writes through a pointer".to_string()),
+            };
+        }
+        if is(EXIT_INTRINSIC) || PURE_INTRINSICS.iter().any(|intrinsic| is(intrinsic)) {
+            return None;
+        }
+        Some(format!("calls '{}'", name))
+    }
+}
+
+/// The variable an assignment to `target` changes, or None when it writes
+/// through a pointer
+fn root(target: &Node) -> Option<&str> {
+    match target {
+        Node::IdentExpr(ident) => Some(&ident.name),
+        Node::IndexExpr(index) => root(&index.array),
+        Node::FieldExpr(field) => root(&field.record),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pure_functions_are_found() {
+        let ast = parser::Parser::new(
+            "program P;
+             var Seed: integer;
+             function Cube(N: integer): integer;
+             var I: integer;
+             begin
+               I := N * N;
+               Cube := I * N
+             end;
+             function CubeSum(A, B: integer): integer;
+             begin CubeSum := Cube(A) + Cube(Abs(B)) end;
+             function Next: integer;
+             begin Seed := Seed * 75 + 74; Next := Seed end;
+             function Scaled(N: integer): integer;
+             begin Scaled := N * Next end;
+             function Shown(N: integer): integer;
+             begin WriteLn(N); Shown := N end;
+             function Fact(N: integer): integer;
+             begin if N = 0 then Fact := 1 else Fact := N * Fact(N - 1) end;
+             {$PURE}
+             function Sine(A: integer): integer; external;
+             function Cosine(A: integer): integer; external;
+             {$PURE}
+             function Peek(A: integer): integer;
+             begin Peek := Port[A] end;
+             begin
+               Seed := CubeSum(1, 2) + Scaled(3) + Shown(4) + Fact(5) + Sine(6) + Cosine(7) + Peek(8)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        let mut analysis = PurityAnalysis::new(None);
+        analysis.analyze(&ast);
+        assert_eq!(analysis.pure_functions(), ["Cube", "CubeSum", "Fact", "Sine"]);
+        let messages: Vec<_> = analysis.diagnostics().iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["Function 'Peek' is marked {$PURE} but performs I/O"]);
+    }
+}
@@ -0,0 +1,385 @@
+//! Static stack usage analysis
+//!
+//! Estimates the deepest stack a routine can reach: its own frame plus the
+//! deepest frame among the routines it calls. Frames are sized from the
+//! declared parameter, result and local variable types. Routines without a
+//! visible body (externals, runtime calls) get a fixed allowance.
+//!
+//! Recursion has no static bound and is reported as an error.
+
+use std::collections::{HashMap, HashSet};
+
+use ast::Node;
+
+/// Per-frame overhead: return address and saved frame pointer
+pub const FRAME_OVERHEAD: u16 = 4;
+/// Stack allowed for a call to a routine whose body is not visible
+pub const EXTERNAL_CALL_ALLOWANCE: u16 = 16;
+/// Room for a register context pushed on top of a task's deepest frame
+/// (task switch or interrupt)
+pub const TASK_CONTEXT_RESERVE: u16 = 16;
+/// Intrinsic returning the stack a task entry point needs
+pub const TASK_STACK_SIZE_INTRINSIC: &str = "TaskStackSize";
+
+/// Size assumed for types the analysis cannot resolve
+const DEFAULT_TYPE_SIZE: u16 = 2;
+
+struct RoutineInfo {
+    name: String,
+    frame: u16,
+    calls: Calls,
+}
+
+/// Stack usage of every routine in a program or unit
+pub struct StackUsage {
+    routines: HashMap<String, RoutineInfo>, // Keyed by lowercase name
+    task_entries: Vec<String>,              // `TaskStackSize` arguments, one per use
+}
+
+impl StackUsage {
+    /// Analyze all routines declared in a program or unit, including nested ones
+    pub fn analyze(ast: &Node) -> Self {
+        let mut type_decls = HashMap::new();
+        let mut routine_decls = Vec::new();
+        let mut main_calls = Calls::default();
+        match ast {
+            Node::Program(program) => {
+                collect_block(&program.block, &mut type_decls, &mut routine_decls);
+                collect_calls(&program.block, &mut main_calls);
+            }
+            Node::Unit(unit) => {
+                let sections = unit
+                    .interface
+                    .iter()
+                    .map(|i| (&i.type_decls, &i.proc_decls, &i.func_decls))
+                    .chain(unit.implementation.iter().map(|i| (&i.type_decls, &i.proc_decls, &i.func_decls)));
+                for (types, procs, funcs) in sections {
+                    collect_types(types, &mut type_decls);
+                    for decl in procs.iter().chain(funcs) {
+                        collect_routine(decl, &mut type_decls, &mut routine_decls);
+                    }
+                }
+                if let Some(init) = &unit.initialization {
+                    collect_calls(init, &mut main_calls);
+                }
+            }
+            _ => {}
+        }
+
+        let mut routines = HashMap::new();
+        let mut task_entries = main_calls.task_entries;
+        for decl in routine_decls {
+            if let Some(info) = routine_info(decl, &type_decls) {
+                task_entries.extend(info.calls.task_entries.iter().cloned());
+                routines.insert(info.name.to_lowercase(), info);
+            }
+        }
+        Self { routines, task_entries }
+    }
+
+    /// Entry points passed to `TaskStackSize`, once per use
+    pub fn task_entries(&self) -> &[String] {
+        &self.task_entries
+    }
+
+    /// Size of a routine's own frame, if its body is visible
+    pub fn frame_size(&self, name: &str) -> Option<u16> {
+        self.routines.get(&name.to_lowercase()).map(|info| info.frame)
+    }
+
+    /// Deepest stack reached by a call to `name`, including the call itself
+    pub fn max_depth(&self, name: &str) -> Result<u16, String> {
+        self.depth(name, &mut HashSet::new())
+    }
+
+    /// Stack to reserve for a task running `entry`
+    pub fn task_stack_size(&self, entry: &str) -> Result<u16, String> {
+        Ok(self.max_depth(entry)?.saturating_add(TASK_CONTEXT_RESERVE))
+    }
+
+    fn depth(&self, name: &str, active: &mut HashSet<String>) -> Result<u16, String> {
+        let key = name.to_lowercase();
+        let Some(info) = self.routines.get(&key) else {
+            return Ok(EXTERNAL_CALL_ALLOWANCE);
+        };
+        if !active.insert(key.clone()) {
+            return Err(format!("Routine '{}' is recursive; its stack usage cannot be bounded", info.name));
+        }
+        let mut deepest = 0u16;
+        for callee in &info.calls.callees {
+            deepest = deepest.max(self.depth(callee, active)?);
+        }
+        active.remove(&key);
+        Ok(info.frame.saturating_add(deepest))
+    }
+}
+
+fn collect_types<'a>(decls: &'a [Node], type_decls: &mut HashMap<String, &'a Node>) {
+    for decl in decls {
+        if let Node::TypeDecl(t) = decl {
+            type_decls.insert(t.name.to_lowercase(), t.type_expr.as_ref());
+        }
+    }
+}
+
+fn collect_block<'a>(block: &'a Node, type_decls: &mut HashMap<String, &'a Node>, routines: &mut Vec<&'a Node>) {
+    if let Node::Block(blk) = block {
+        collect_types(&blk.type_decls, type_decls);
+        for decl in blk.proc_decls.iter().chain(&blk.func_decls) {
+            collect_routine(decl, type_decls, routines);
+        }
+    }
+}
+
+fn collect_routine<'a>(decl: &'a Node, type_decls: &mut HashMap<String, &'a Node>, routines: &mut Vec<&'a Node>) {
+    let (block, is_body) = match decl {
+        Node::ProcDecl(p) => (&p.block, !p.is_external && !p.is_forward),
+        Node::FuncDecl(f) => (&f.block, !f.is_external && !f.is_forward),
+        _ => return,
+    };
+    if is_body {
+        routines.push(decl);
+        collect_block(block, type_decls, routines);
+    }
+}
+
+fn routine_info(decl: &Node, type_decls: &HashMap<String, &Node>) -> Option<RoutineInfo> {
+    let (name, params, block, result) = match decl {
+        Node::ProcDecl(p) => (&p.name, &p.params, &p.block, None),
+        Node::FuncDecl(f) => (&f.name, &f.params, &f.block, Some(f.return_type.as_ref())),
+        _ => return None,
+    };
+    let mut frame = FRAME_OVERHEAD;
+    for param in params {
+        // By-reference parameters pass an address
+        let size = match (param.param_type, &param.type_expr) {
+            (ast::ParamType::Value, Some(type_expr)) => type_size(type_expr, type_decls, 0),
+            _ => 2,
+        };
+        frame = frame.saturating_add(size.saturating_mul(param.names.len() as u16));
+    }
+    if let Some(result) = result {
+        frame = frame.saturating_add(type_size(result, type_decls, 0));
+    }
+    let mut calls = Calls::default();
+    if let Node::Block(blk) = block.as_ref() {
+        for var in &blk.var_decls {
+            if let Node::VarDecl(v) = var
+                && v.absolute_address.is_none()
+            {
+                let size = type_size(&v.type_expr, type_decls, 0);
+                frame = frame.saturating_add(size.saturating_mul(v.names.len() as u16));
+            }
+        }
+        for stmt in &blk.statements {
+            collect_calls(stmt, &mut calls);
+        }
+    }
+    Some(RoutineInfo { name: name.clone(), frame, calls })
+}
+
+/// Estimated size of a type in bytes
+fn type_size(type_expr: &Node, type_decls: &HashMap<String, &Node>, depth: usize) -> u16 {
+    if depth > 16 {
+        return DEFAULT_TYPE_SIZE;
+    }
+    match type_expr {
+        Node::NamedType(named) => match named.name.to_lowercase().as_str() {
+            "byte" | "shortint" | "char" | "boolean" => 1,
+            "integer" | "word" | "smallint" | "pointer" => 2,
+            "longint" | "cardinal" | "longword" | "dword" | "single" | "real" => 4,
+            "string" => 256,
+            other => type_decls
+                .get(other)
+                .map(|t| type_size(t, type_decls, depth + 1))
+                .unwrap_or(DEFAULT_TYPE_SIZE),
+        },
+        Node::RecordType(record) => record
+            .fields
+            .iter()
+            .map(|f| type_size(&f.type_expr, type_decls, depth + 1).saturating_mul(f.names.len() as u16))
+            .fold(0u16, u16::saturating_add),
+        Node::ArrayType(array) => {
+            let count = match array.index_type.as_ref() {
+                Node::NamedType(index) => match index.name.to_lowercase().as_str() {
+                    "byte" | "char" => 256,
+                    "boolean" => 2,
+                    _ => 1,
+                },
+                Node::SubrangeType(range) => match (literal_ordinal(&range.low), literal_ordinal(&range.high)) {
+                    (Some(low), Some(high)) if low <= high => u16::try_from(high - low + 1).unwrap_or(u16::MAX),
+                    _ => 1,
+                },
+                _ => 1,
+            };
+            type_size(&array.element_type, type_decls, depth + 1).saturating_mul(count)
+        }
+        Node::StringType(s) => match s.length.as_deref() {
+            Some(Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(n), .. })) => {
+                u16::try_from(*n).unwrap_or(u16::MAX).saturating_add(1)
+            }
+            _ => 256,
+        },
+        _ => DEFAULT_TYPE_SIZE,
+    }
+}
+
+/// Value of an integer or character literal bound
+fn literal_ordinal(node: &Node) -> Option<i64> {
+    match node {
+        Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Integer(n), .. }) => Some(*n as i64),
+        Node::LiteralExpr(ast::LiteralExpr { value: ast::LiteralValue::Char(c), .. }) => Some(*c as i64),
+        _ => None,
+    }
+}
+
+/// Routines called from statements, and task entry points named by `TaskStackSize`
+#[derive(Default)]
+struct Calls {
+    callees: Vec<String>,
+    task_entries: Vec<String>,
+}
+
+/// Collect the calls made from a statement or expression
+fn collect_calls(node: &Node, calls: &mut Calls) {
+    let mut visit = |n: &Node| collect_calls(n, calls);
+    match node {
+        Node::Block(b) => b.statements.iter().for_each(visit),
+        Node::CallStmt(c) => {
+            c.args.iter().for_each(&mut visit);
+            calls.callees.push(c.name.clone());
+        }
+        Node::CallExpr(c) if c.name.eq_ignore_ascii_case(TASK_STACK_SIZE_INTRINSIC) => {
+            if let [Node::IdentExpr(entry)] = c.args.as_slice() {
+                calls.task_entries.push(entry.name.clone());
+            }
+        }
+        Node::CallExpr(c) => {
+            c.args.iter().for_each(&mut visit);
+            calls.callees.push(c.name.clone());
+        }
+        Node::IfStmt(s) => {
+            visit(&s.condition);
+            visit(&s.then_block);
+            s.else_block.iter().for_each(|n| visit(n));
+        }
+        Node::WhileStmt(s) => {
+            visit(&s.condition);
+            visit(&s.body);
+        }
+        Node::ForStmt(s) => {
+            visit(&s.start_expr);
+            visit(&s.end_expr);
+            visit(&s.body);
+        }
+        Node::ForInStmt(s) => {
+            visit(&s.collection_expr);
+            visit(&s.body);
+        }
+        Node::RepeatStmt(s) => {
+            s.statements.iter().for_each(&mut visit);
+            visit(&s.condition);
+        }
+        Node::CaseStmt(s) => {
+            visit(&s.expr);
+            for branch in &s.cases {
+                visit(&branch.statement);
+            }
+            s.else_branch.iter().for_each(|n| visit(n));
+        }
+        Node::AssignStmt(s) => {
+            visit(&s.target);
+            visit(&s.value);
+        }
+        Node::TryStmt(s) => {
+            s.try_block.iter().for_each(&mut visit);
+            s.except_block.iter().flatten().for_each(&mut visit);
+            s.finally_block.iter().flatten().for_each(&mut visit);
+            for handler in &s.exception_handlers {
+                visit(&handler.handler);
+            }
+            s.exception_else.iter().for_each(|n| visit(n));
+        }
+        Node::WithStmt(s) => {
+            s.records.iter().for_each(&mut visit);
+            visit(&s.statement);
+        }
+        Node::LabeledStmt(s) => visit(&s.statement),
+        Node::BinaryExpr(e) => {
+            visit(&e.left);
+            visit(&e.right);
+        }
+        Node::UnaryExpr(e) => visit(&e.expr),
+        Node::IndexExpr(e) => {
+            visit(&e.array);
+            visit(&e.index);
+        }
+        Node::FieldExpr(e) => visit(&e.record),
+        Node::DerefExpr(e) => visit(&e.pointer),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    fn analyze(source: &str) -> StackUsage {
+        let mut parser = Parser::new(source).unwrap();
+        StackUsage::analyze(&parser.parse().unwrap())
+    }
+
+    #[test]
+    fn test_frame_size_counts_params_and_locals() {
+        let usage = analyze(
+            "program P;
+             procedure Work(A: integer; var B: byte);
+             var C: byte; D: word;
+             begin end;
+             begin end.",
+        );
+        // Overhead + A (2) + @B (2) + C (1) + D (2)
+        assert_eq!(usage.frame_size("work"), Some(FRAME_OVERHEAD + 7));
+    }
+
+    #[test]
+    fn test_max_depth_follows_deepest_call() {
+        let usage = analyze(
+            "program P;
+             procedure Leaf; var X: word; begin end;
+             procedure Shallow; begin end;
+             procedure Middle; begin Leaf end;
+             procedure Top; begin Shallow; Middle end;
+             begin end.",
+        );
+        let leaf = FRAME_OVERHEAD + 2;
+        assert_eq!(usage.max_depth("Top"), Ok(FRAME_OVERHEAD * 2 + leaf));
+        // Unknown routines get the fixed allowance
+        assert_eq!(usage.max_depth("Missing"), Ok(EXTERNAL_CALL_ALLOWANCE));
+    }
+
+    #[test]
+    fn test_recursion_is_unbounded() {
+        let usage = analyze(
+            "program P;
+             procedure Loop; begin Loop end;
+             begin end.",
+        );
+        assert!(usage.max_depth("Loop").unwrap_err().contains("recursive"));
+    }
+
+    #[test]
+    fn test_task_entries_collected_per_use() {
+        let usage = analyze(
+            "program P;
+             var S: word;
+             procedure Worker; begin end;
+             procedure Spawn; begin S := TaskStackSize(Worker) end;
+             begin S := TaskStackSize(Worker) + TaskStackSize(Spawn) end.",
+        );
+        assert_eq!(usage.task_entries(), ["Worker", "Spawn", "Worker"]);
+        // The intrinsic is not a call
+        assert_eq!(usage.max_depth("Spawn"), Ok(FRAME_OVERHEAD));
+    }
+}
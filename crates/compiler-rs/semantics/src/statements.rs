@@ -17,6 +17,23 @@ impl SemanticAnalyzer {
             Node::ForStmt(f) => self.analyze_for_stmt(f),
             Node::RepeatStmt(r) => self.analyze_repeat_stmt(r),
             Node::CaseStmt(c) => self.analyze_case_stmt(c),
+            Node::TryStmt(t) => self.analyze_try_stmt(t),
+            Node::RaiseStmt(r) => self.analyze_raise_stmt(r),
+            Node::MethodCall(m) => {
+                self.analyze_method_call(m);
+            }
+            Node::AsmStmt(a) => self.analyze_asm_stmt(a),
+            Node::GotoStmt(_) | Node::LabeledStmt(_) if self.strict => {
+                self.core.add_error(
+                    "goto and labels are not allowed in strict mode; use Break, Continue or Exit".to_string(),
+                    stmt.span(),
+                );
+            }
+            Node::Block(b) => {
+                for stmt in &b.statements {
+                    self.analyze_statement(stmt);
+                }
+            }
             _ => {
                 self.core.add_error(
                     "Unsupported statement type".to_string(),
@@ -26,6 +43,33 @@ impl SemanticAnalyzer {
         }
     }
 
+    /// Analyze an asm block: each `@Name` must be a variable, constant or
+    /// routine, and each clobbered register a Z80 one
+    fn analyze_asm_stmt(&mut self, asm: &ast::AsmStmt) {
+        for (_, name) in asm.references() {
+            match self.core.symbol_table.lookup(name).map(|symbol| &symbol.kind) {
+                Some(SymbolKind::TypeAlias { .. } | SymbolKind::GenericType { .. }) => self.core.add_error(
+                    format!("'{}' is a type and cannot be referenced in an asm block", name),
+                    asm.span,
+                ),
+                Some(_) => {}
+                None => self.core.add_error(format!("Identifier '{}' not found", name), asm.span),
+            }
+        }
+        for register in &asm.clobbers {
+            if !crate::ASM_REGISTERS.contains(&register.as_str()) {
+                self.core.add_error(
+                    format!(
+                        "Unknown register '{}' in the clobber list of an asm block (expected one of {})",
+                        register,
+                        crate::ASM_REGISTERS.join(", ")
+                    ),
+                    asm.span,
+                );
+            }
+        }
+    }
+
     /// Analyze assignment statement
     pub(crate) fn analyze_assignment(&mut self, assign: &ast::AssignStmt) {
         // Analyze target (lvalue)
@@ -35,7 +79,11 @@ impl SemanticAnalyzer {
         let value_type = self.analyze_expression(&assign.value);
 
         // Check assignment compatibility
-        if !value_type.is_assignable_to(&target_type) {
+        if !value_type.is_assignable_to(&target_type)
+            && !self.constant_fits(&assign.value, &target_type)
+            && !self.converts_to_interface(&value_type, &target_type)
+            && !self.converts_to_ancestor(&value_type, &target_type)
+        {
             self.core.add_error(
                 format!(
                     "Type mismatch: cannot assign {} to {}",
@@ -44,11 +92,56 @@ impl SemanticAnalyzer {
                 ),
                 assign.span,
             );
+        } else if value_type != Type::Error {
+            self.check_constant_in_range(&assign.value, &target_type);
         }
     }
 
     /// Analyze call statement (procedure call)
     pub(crate) fn analyze_call_stmt(&mut self, call: &ast::CallStmt) {
+        if call.name.eq_ignore_ascii_case(crate::EXIT_INTRINSIC)
+            && self.core.symbol_table.lookup(&call.name).is_none()
+        {
+            self.analyze_exit(call);
+            return;
+        }
+        if (call.name.eq_ignore_ascii_case(crate::INC_INTRINSIC) || call.name.eq_ignore_ascii_case(crate::DEC_INTRINSIC))
+            && self.core.symbol_table.lookup(&call.name).is_none()
+        {
+            self.analyze_inc_dec(call);
+            return;
+        }
+        if Self::is_string_procedure(&call.name) && self.core.symbol_table.lookup(&call.name).is_none() {
+            self.analyze_string_procedure(call);
+            return;
+        }
+        if [crate::WRITE_INTRINSIC, crate::WRITELN_INTRINSIC, crate::READLN_INTRINSIC]
+            .iter()
+            .any(|name| call.name.eq_ignore_ascii_case(name))
+            && self.core.symbol_table.lookup(&call.name).is_none()
+        {
+            self.analyze_console_procedure(call);
+            return;
+        }
+        if call.name.eq_ignore_ascii_case(crate::ASSERT_OFFSET_INTRINSIC)
+            && self.core.symbol_table.lookup(&call.name).is_none()
+        {
+            self.analyze_assert_offset(call);
+            return;
+        }
+        if call.name.eq_ignore_ascii_case(crate::REPORT_LEAKS_INTRINSIC)
+            && self.core.symbol_table.lookup(&call.name).is_none()
+        {
+            self.uses_report_leaks = true;
+            if !call.args.is_empty() {
+                self.core.add_error(
+                    format!("{} expects 0 arguments, found {}", call.name, call.args.len()),
+                    call.span,
+                );
+            }
+            return;
+        }
+
         // Look up procedure
         let params_opt = self.core.symbol_table.lookup(&call.name).and_then(|symbol| {
             if let SymbolKind::Procedure { params, .. } = &symbol.kind {
@@ -75,17 +168,7 @@ impl SemanticAnalyzer {
 
             // Check argument types
             for (arg, param) in call.args.iter().zip(params.iter()) {
-                let arg_type = self.analyze_expression(arg);
-                if !arg_type.is_assignable_to(&param.param_type) {
-                    self.core.add_error(
-                        format!(
-                            "Argument type mismatch: expected {}, found {}",
-                            core::CoreAnalyzer::format_type(&param.param_type),
-                            core::CoreAnalyzer::format_type(&arg_type)
-                        ),
-                        arg.span(),
-                    );
-                }
+                self.check_argument(arg, param);
             }
         } else if self.core.symbol_table.lookup(&call.name).is_some() {
             self.core.add_error(
@@ -100,6 +183,218 @@ impl SemanticAnalyzer {
         }
     }
 
+    /// Check `AssertOffset(TRec.Field, n)`: the field that a path from a
+    /// record type or variable names must be `n` bytes into it, so a layout
+    /// change that would break assembly or hardware structures relying on it
+    /// stops the build
+    fn analyze_assert_offset(&mut self, call: &ast::CallStmt) {
+        let [path, expected] = call.args.as_slice() else {
+            self.core.add_error(
+                format!("{} expects 2 arguments, found {}", call.name, call.args.len()),
+                call.span,
+            );
+            return;
+        };
+        let Some(expected) = self.evaluate_constant_expression(expected).and_then(|value| Self::constant_ordinal(&value))
+        else {
+            self.core.add_error(format!("{} expects a constant offset", call.name), expected.span());
+            return;
+        };
+        match self.field_offset(path) {
+            Ok((offset, _)) if offset as i64 == expected => {}
+            Ok((offset, _)) => self.core.add_error(
+                format!("Field '{}' is at offset {}, not {}", field_path(path), offset, expected),
+                path.span(),
+            ),
+            Err(message) => self.core.add_error(message, path.span()),
+        }
+    }
+
+    /// The offset and type of the field a path such as `TRec.Inner.X` names,
+    /// from the start of the record type or variable it starts from
+    fn field_offset(&mut self, path: &Node) -> Result<(usize, Type), String> {
+        match path {
+            Node::IdentExpr(ident) => match self.typecast_target(&ident.name) {
+                Some(named) => Ok((0, named)),
+                None => Ok((0, self.analyze_expression(path))),
+            },
+            Node::FieldExpr(field) => {
+                let (offset, record) = self.field_offset(&field.record)?;
+                let Type::Record { fields, .. } = record else {
+                    return Err(format!("'{}' is not a record", field_path(&field.record)));
+                };
+                let found = fields
+                    .into_iter()
+                    .find(|f| f.name.eq_ignore_ascii_case(&field.field))
+                    .ok_or_else(|| format!("'{}' has no field '{}'", field_path(&field.record), field.field))?;
+                Ok((offset + found.offset.unwrap_or(0), *found.field_type))
+            }
+            _ => Err(format!("{} expects a record field, such as TRec.Field", crate::ASSERT_OFFSET_INTRINSIC)),
+        }
+    }
+
+    /// Analyze `Inc(x)`, `Inc(x, n)`, `Dec(x)` or `Dec(x, n)` on an ordinal or
+    /// typed pointer variable
+    fn analyze_inc_dec(&mut self, call: &ast::CallStmt) {
+        let (target, step) = match call.args.as_slice() {
+            [target] => (target, None),
+            [target, step] => (target, Some(step)),
+            _ => {
+                self.core.add_error(
+                    format!("{} expects 1 or 2 arguments, found {}", call.name, call.args.len()),
+                    call.span,
+                );
+                return;
+            }
+        };
+        let target_type = self.analyze_lvalue(target);
+        if let Some(step) = step {
+            let step_type = self.analyze_expression(step);
+            if !step_type.is_assignable_to(&Type::integer()) && !step_type.is_assignable_to(&Type::word()) {
+                self.core.add_error(
+                    format!("{} step must be an integer, found {}", call.name, core::CoreAnalyzer::format_type(&step_type)),
+                    step.span(),
+                );
+            }
+        }
+        match &target_type {
+            Type::Pointer { .. } => {
+                if let Err(message) = Self::pointer_step(&target_type) {
+                    self.core.add_error(message, target.span());
+                }
+            }
+            Type::UntypedPointer => self.core.add_error(
+                format!("{} of an untyped Pointer requires a cast to a typed pointer", call.name),
+                target.span(),
+            ),
+            _ if target_type.is_ordinal() => {}
+            _ => self.core.add_error(
+                format!(
+                    "{} requires an ordinal or typed pointer variable, found {}",
+                    call.name,
+                    core::CoreAnalyzer::format_type(&target_type)
+                ),
+                target.span(),
+            ),
+        }
+    }
+
+    /// Analyze `Write`, `WriteLn` or `ReadLn`: ordinal values and strings are
+    /// written, and ordinal variables read
+    fn analyze_console_procedure(&mut self, call: &ast::CallStmt) {
+        let reads = call.name.eq_ignore_ascii_case(crate::READLN_INTRINSIC);
+        for arg in &call.args {
+            let arg_type = if reads { self.analyze_lvalue(arg) } else { self.analyze_expression(arg) };
+            let allowed = arg_type.is_ordinal() || (!reads && arg_type.is_string_like());
+            if !allowed && arg_type != Type::Error {
+                self.core.add_error(
+                    format!("{} cannot {} {}", call.name, if reads { "read" } else { "write" }, core::CoreAnalyzer::format_type(&arg_type)),
+                    arg.span(),
+                );
+            }
+        }
+    }
+
+    /// Bytes a typed pointer moves per element: the size of its base type
+    pub(crate) fn pointer_step(pointer: &Type) -> Result<usize, String> {
+        match pointer {
+            Type::Pointer { base_type } => base_type.size().ok_or_else(|| {
+                format!("Cannot move a pointer to {}: its size is unknown", core::CoreAnalyzer::format_type(base_type))
+            }),
+            _ => Err("Pointer arithmetic requires a typed pointer".to_string()),
+        }
+    }
+
+    /// Analyze `Exit` or `Exit(value)`; the value form sets the function result
+    fn analyze_exit(&mut self, call: &ast::CallStmt) {
+        let Some(value) = call.args.first() else {
+            return;
+        };
+        let value_type = self.analyze_expression(value);
+        match self.functions.last().map(|(_, t)| t.clone()) {
+            _ if call.args.len() > 1 => self.core.add_error(
+                format!("Exit expects at most 1 argument, found {}", call.args.len()),
+                call.span,
+            ),
+            Some(return_type)
+                if !value_type.is_assignable_to(&return_type)
+                    && !self.converts_to_interface(&value_type, &return_type)
+                    && !self.converts_to_ancestor(&value_type, &return_type) =>
+            self.core.add_error(
+                format!(
+                    "Type mismatch: cannot return {} from a function returning {}",
+                    core::CoreAnalyzer::format_type(&value_type),
+                    core::CoreAnalyzer::format_type(&return_type)
+                ),
+                value.span(),
+            ),
+            Some(_) => {}
+            None => self.core.add_error(
+                "Exit with a value is only allowed in a function".to_string(),
+                call.span,
+            ),
+        }
+    }
+
+    /// Whether the function result is definitely assigned after `stmt`, given
+    /// whether it was before. Sets `escapes` when a bare `Exit` leaves the
+    /// function before the result is assigned.
+    pub(crate) fn assigns_result(stmt: &Node, function: &str, assigned: bool, escapes: &mut bool) -> bool {
+        let is_result = |target: &Node| {
+            matches!(target, Node::IdentExpr(i)
+                if i.name.eq_ignore_ascii_case(crate::RESULT_VARIABLE) || i.name.eq_ignore_ascii_case(function))
+        };
+        Self::definitely_assigns(stmt, &is_result, assigned, escapes)
+    }
+
+    /// Whether an assignment to a target `is_target` accepts definitely
+    /// happens after `stmt`, given whether one did before. Sets `escapes`
+    /// when a bare `Exit` leaves the routine before it does.
+    pub(crate) fn definitely_assigns(stmt: &Node, is_target: &dyn Fn(&Node) -> bool, assigned: bool, escapes: &mut bool) -> bool {
+        let sequence = |stmts: &[Node], escapes: &mut bool| {
+            stmts.iter().fold(assigned, |assigned, s| Self::definitely_assigns(s, is_target, assigned, escapes))
+        };
+        match stmt {
+            Node::AssignStmt(a) => assigned || is_target(&a.target),
+            Node::CallStmt(c) if c.name.eq_ignore_ascii_case(crate::EXIT_INTRINSIC) => {
+                if c.args.is_empty() && !assigned {
+                    *escapes = true;
+                }
+                true // Nothing after an Exit runs
+            }
+            Node::RaiseStmt(_) => true,
+            Node::Block(b) => sequence(&b.statements, escapes),
+            Node::RepeatStmt(r) => sequence(&r.statements, escapes),
+            Node::IfStmt(i) => {
+                let then_assigned = Self::definitely_assigns(&i.then_block, is_target, assigned, escapes);
+                let else_assigned = match &i.else_block {
+                    Some(e) => Self::definitely_assigns(e, is_target, assigned, escapes),
+                    None => assigned,
+                };
+                then_assigned && else_assigned
+            }
+            Node::CaseStmt(c) => {
+                let mut all = c.else_branch.as_ref().map_or(assigned, |e| {
+                    Self::definitely_assigns(e, is_target, assigned, escapes)
+                });
+                for branch in &c.cases {
+                    all &= Self::definitely_assigns(&branch.statement, is_target, assigned, escapes);
+                }
+                all
+            }
+            // The body may not run at all
+            Node::WhileStmt(w) => {
+                Self::definitely_assigns(&w.body, is_target, assigned, escapes);
+                assigned
+            }
+            Node::ForStmt(f) => {
+                Self::definitely_assigns(&f.body, is_target, assigned, escapes);
+                assigned
+            }
+            _ => assigned,
+        }
+    }
+
     /// Analyze if statement
     pub(crate) fn analyze_if_stmt(&mut self, if_stmt: &ast::IfStmt) {
         let condition_type = self.analyze_expression(&if_stmt.condition);
@@ -167,19 +462,38 @@ impl SemanticAnalyzer {
     /// Analyze for statement
     pub(crate) fn analyze_for_stmt(&mut self, for_stmt: &ast::ForStmt) {
         // Check loop variable exists and is assignable
-        let var_type_opt = self.core.symbol_table.lookup(&for_stmt.var_name).and_then(|symbol| {
+        let current_level = self.core.symbol_table.scope_level();
+        let var_opt = self.core.symbol_table.lookup(&for_stmt.var_name).and_then(|symbol| {
             if let SymbolKind::Variable { var_type, .. } = &symbol.kind {
-                Some(var_type.clone())
+                Some((var_type.clone(), symbol.scope_level == current_level))
             } else {
                 None
             }
         });
 
-        if let Some(var_type) = var_type_opt {
+        if let Some((var_type, is_local)) = var_opt {
+            // The control variable must be a local ordinal (Pascal 6.8.3.9)
+            if !is_local {
+                self.core.add_error(
+                    format!("For loop variable '{}' must be a local variable", for_stmt.var_name),
+                    for_stmt.span,
+                );
+            }
+            if !var_type.is_ordinal() {
+                self.core.add_error(
+                    format!(
+                        "For loop variable '{}' must be of an ordinal type, found {}",
+                        for_stmt.var_name,
+                        core::CoreAnalyzer::format_type(&var_type)
+                    ),
+                    for_stmt.span,
+                );
+            }
+
             let start_type = self.analyze_expression(&for_stmt.start_expr);
             let end_type = self.analyze_expression(&for_stmt.end_expr);
 
-            if !start_type.is_assignable_to(&var_type) {
+            if !start_type.is_assignable_to(&var_type) && !self.constant_fits(&for_stmt.start_expr, &var_type) {
                 self.core.add_error(
                     format!(
                         "For loop start value type {} not compatible with loop variable type {}",
@@ -190,7 +504,7 @@ impl SemanticAnalyzer {
                 );
             }
 
-            if !end_type.is_assignable_to(&var_type) {
+            if !end_type.is_assignable_to(&var_type) && !self.constant_fits(&for_stmt.end_expr, &var_type) {
                 self.core.add_error(
                     format!(
                         "For loop end value type {} not compatible with loop variable type {}",
@@ -212,7 +526,10 @@ impl SemanticAnalyzer {
             );
         }
 
+        // The control variable is read-only inside the body
+        self.for_loop_vars.push(for_stmt.var_name.clone());
         self.analyze_statement(&for_stmt.body);
+        self.for_loop_vars.pop();
     }
 
     /// Analyze repeat statement
@@ -235,7 +552,7 @@ impl SemanticAnalyzer {
         // Case expression must be ordinal type (integer, byte, word, char, boolean)
         if !matches!(
             expr_type,
-            Type::Primitive(_) | Type::Named { .. }
+            Type::Primitive(_) | Type::Named { .. } | Type::Enum { .. }
         ) {
             self.core.add_error(
                 "Case expression must be an ordinal type".to_string(),
@@ -243,25 +560,130 @@ impl SemanticAnalyzer {
             );
         }
 
+        // Ordinal ranges of the labels so far, which must be disjoint
+        let mut labels: Vec<(i64, i64)> = vec![];
         for case_branch in &case_stmt.cases {
             for value in &case_branch.values {
-                let value_type = self.analyze_expression(value);
-                if !value_type.equals(&expr_type) {
+                let Some((low, high)) = self.analyze_case_label(value, &expr_type) else {
+                    continue;
+                };
+                if let Some(&(other_low, _)) =
+                    labels.iter().find(|(other_low, other_high)| low <= *other_high && *other_low <= high)
+                {
                     self.core.add_error(
                         format!(
-                            "Case value type {} does not match expression type {}",
-                            core::CoreAnalyzer::format_type(&value_type),
-                            core::CoreAnalyzer::format_type(&expr_type)
+                            "Duplicate case label {}",
+                            core::CoreAnalyzer::format_ordinal(&expr_type, low.max(other_low))
                         ),
                         value.span(),
                     );
                 }
+                labels.push((low, high));
             }
             self.analyze_statement(&case_branch.statement);
         }
 
         if let Some(else_stmt) = &case_stmt.else_branch {
             self.analyze_statement(else_stmt);
+        } else if let Type::Enum { values } = &expr_type {
+            if self.strict {
+                self.core.add_error(
+                    "Case over an enumeration needs an else branch in strict mode".to_string(),
+                    case_stmt.span,
+                );
+            } else {
+                self.check_case_exhaustive(case_stmt, values, &labels);
+            }
+        }
+    }
+
+    /// Analyze a case label, a value or a range `low..high` of constants
+    /// matching the case expression; its ordinal range, or None if invalid
+    fn analyze_case_label(&mut self, label: &Node, expr_type: &Type) -> Option<(i64, i64)> {
+        let bounds = match label {
+            Node::SubrangeType(range) => vec![range.low.as_ref(), range.high.as_ref()],
+            _ => vec![label],
+        };
+        let mut ordinals = vec![];
+        for bound in bounds {
+            let value_type = self.analyze_expression(bound);
+            if value_type == Type::Error {
+                return None;
+            }
+            if !value_type.equals(expr_type) {
+                self.core.add_error(
+                    format!(
+                        "Case value type {} does not match expression type {}",
+                        core::CoreAnalyzer::format_type(&value_type),
+                        core::CoreAnalyzer::format_type(expr_type)
+                    ),
+                    bound.span(),
+                );
+                return None;
+            }
+            let Some(ordinal) = self.evaluate_constant_expression(bound).as_ref().and_then(Self::constant_ordinal)
+            else {
+                self.core.add_error("Case label must be a constant".to_string(), bound.span());
+                return None;
+            };
+            ordinals.push(ordinal);
         }
+        let (low, high) = (ordinals[0], *ordinals.last().unwrap());
+        if low > high {
+            self.core.add_error(
+                format!(
+                    "Case label range {}..{} is empty",
+                    core::CoreAnalyzer::format_ordinal(expr_type, low),
+                    core::CoreAnalyzer::format_ordinal(expr_type, high)
+                ),
+                label.span(),
+            );
+            return None;
+        }
+        Some((low, high))
+    }
+
+    /// Warn about enumeration values handled by no label of an `else`-less
+    /// case, with a fix-it adding an empty branch for them
+    fn check_case_exhaustive(&mut self, case_stmt: &ast::CaseStmt, values: &[String], labels: &[(i64, i64)]) {
+        let missing: Vec<&str> = values
+            .iter()
+            .enumerate()
+            .filter(|(ordinal, _)| !labels.iter().any(|(low, high)| (*low..=*high).contains(&(*ordinal as i64))))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        let labels = missing.join(", ");
+        let mut diag = self.core.warning(
+            format!("Case statement does not handle enumeration values: {}", labels),
+            case_stmt.span,
+        );
+        // Insert the branch right after the last one's statement, indented
+        // one step in from `case`
+        if let Some(last) = case_stmt.cases.last() {
+            let anchor = last.statement.span();
+            let indent = " ".repeat(case_stmt.span.column + 1);
+            diag = diag
+                .with_suggestion(format!("Add a branch for {} or an else branch", labels))
+                .with_fix_it(errors::FixIt::insert(
+                    anchor.end,
+                    anchor.end_line,
+                    anchor.end_column,
+                    format!(";\n{}{}: begin end", indent, labels),
+                ));
+        }
+        self.core.diagnostics.push(diag);
+    }
+}
+
+/// Source text of a field path such as `TRec.Inner.X`
+fn field_path(path: &Node) -> String {
+    match path {
+        Node::IdentExpr(ident) => ident.name.clone(),
+        Node::FieldExpr(field) => format!("{}.{}", field_path(&field.record), field.field),
+        _ => "?".to_string(),
     }
 }
@@ -0,0 +1,152 @@
+//! String analysis: `string[n]` types, indexing, concatenation and the
+//! string intrinsics
+
+use ast::Node;
+use ::types::Type;
+use crate::SemanticAnalyzer;
+use crate::core;
+
+impl SemanticAnalyzer {
+    /// Analyze `string` or `string[n]`: the length must be a constant from
+    /// 1 to 255, and plain `string` holds 255 characters
+    pub(crate) fn analyze_string_type(&mut self, string: &ast::StringType) -> Type {
+        let Some(length) = &string.length else {
+            return Type::string(::types::MAX_STRING_LENGTH);
+        };
+        self.analyze_expression(length);
+        match self.evaluate_constant_expression(length).as_ref().and_then(Self::constant_ordinal) {
+            Some(n) if (1..=::types::MAX_STRING_LENGTH as i64).contains(&n) => Type::string(n as usize),
+            _ => {
+                self.core.add_error(
+                    format!("String length must be a constant from 1 to {}", ::types::MAX_STRING_LENGTH),
+                    length.span(),
+                );
+                Type::Error
+            }
+        }
+    }
+
+    /// Type of `s[i]` for a string `s`: a Char. Index 0 is the length byte.
+    pub(crate) fn analyze_string_index(&mut self, index: &Node, max_length: usize) -> Type {
+        self.analyze_array_index(index, &Type::subrange(Type::integer(), 0, max_length as i64));
+        Type::char()
+    }
+
+    /// Type of `left + right` when both are strings, characters or string
+    /// literals; None for any other operands
+    pub(crate) fn string_concat_type(left: &Type, right: &Type) -> Option<Type> {
+        (left.is_string_like() && right.is_string_like()).then(|| Type::string(::types::MAX_STRING_LENGTH))
+    }
+
+    /// Check if `name` is one of the string intrinsic functions
+    pub(crate) fn is_string_function(name: &str) -> bool {
+        [crate::LENGTH_INTRINSIC, crate::COPY_INTRINSIC, crate::POS_INTRINSIC, crate::CONCAT_INTRINSIC]
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n))
+    }
+
+    /// Check if `name` is one of the string intrinsic procedures
+    pub(crate) fn is_string_procedure(name: &str) -> bool {
+        name.eq_ignore_ascii_case(crate::DELETE_INTRINSIC) || name.eq_ignore_ascii_case(crate::INSERT_INTRINSIC)
+    }
+
+    /// Analyze `Length(s)`, `Copy(s, index, count)`, `Pos(sub, s)` or
+    /// `Concat(s1, s2, ...)`
+    pub(crate) fn analyze_string_function(&mut self, call: &ast::CallExpr) -> Type {
+        let (arity, result) = if call.name.eq_ignore_ascii_case(crate::LENGTH_INTRINSIC) {
+            (1, Type::integer())
+        } else if call.name.eq_ignore_ascii_case(crate::COPY_INTRINSIC) {
+            (3, Type::string(::types::MAX_STRING_LENGTH))
+        } else if call.name.eq_ignore_ascii_case(crate::POS_INTRINSIC) {
+            (2, Type::integer())
+        } else {
+            // Concat takes any number of strings from one
+            if call.args.is_empty() {
+                self.core.add_error(format!("{} expects at least 1 argument", call.name), call.span);
+                return Type::Error;
+            }
+            let mut valid = true;
+            for arg in &call.args {
+                valid &= self.check_string_argument(&call.name, arg);
+            }
+            return if valid { Type::string(::types::MAX_STRING_LENGTH) } else { Type::Error };
+        };
+        if call.args.len() != arity {
+            self.core.add_error(
+                format!("{} expects {} arguments, found {}", call.name, arity, call.args.len()),
+                call.span,
+            );
+            return Type::Error;
+        }
+        let valid = if call.name.eq_ignore_ascii_case(crate::COPY_INTRINSIC) {
+            let source = self.check_string_argument(&call.name, &call.args[0]);
+            let index = self.check_position_argument(&call.name, &call.args[1]);
+            source & index & self.check_position_argument(&call.name, &call.args[2])
+        } else {
+            let mut valid = true;
+            for arg in &call.args {
+                valid &= self.check_string_argument(&call.name, arg);
+            }
+            valid
+        };
+        if valid { result } else { Type::Error }
+    }
+
+    /// Analyze `Delete(s, index, count)` or `Insert(source, s, index)`; `s`
+    /// must be a string variable
+    pub(crate) fn analyze_string_procedure(&mut self, call: &ast::CallStmt) {
+        if call.args.len() != 3 {
+            self.core.add_error(
+                format!("{} expects 3 arguments, found {}", call.name, call.args.len()),
+                call.span,
+            );
+            return;
+        }
+        let (target, positions) = if call.name.eq_ignore_ascii_case(crate::DELETE_INTRINSIC) {
+            (&call.args[0], &call.args[1..])
+        } else {
+            self.check_string_argument(&call.name, &call.args[0]);
+            (&call.args[1], &call.args[2..])
+        };
+        let target_type = self.analyze_lvalue(target);
+        if !matches!(target_type, Type::String { .. } | Type::Error) {
+            self.core.add_error(
+                format!(
+                    "{} requires a string variable, found {}",
+                    call.name,
+                    core::CoreAnalyzer::format_type(&target_type)
+                ),
+                target.span(),
+            );
+        }
+        for position in positions {
+            self.check_position_argument(&call.name, position);
+        }
+    }
+
+    /// Report `arg` unless it is a string, character or string literal
+    fn check_string_argument(&mut self, name: &str, arg: &Node) -> bool {
+        let arg_type = self.analyze_expression(arg);
+        if arg_type.is_string_like() || arg_type == Type::Error {
+            return arg_type != Type::Error;
+        }
+        self.core.add_error(
+            format!("{} requires a string argument, found {}", name, core::CoreAnalyzer::format_type(&arg_type)),
+            arg.span(),
+        );
+        false
+    }
+
+    /// Report `arg` unless it is an integer: a character position or count
+    fn check_position_argument(&mut self, name: &str, arg: &Node) -> bool {
+        let arg_type = self.analyze_expression(arg);
+        if arg_type.is_assignable_to(&Type::integer()) {
+            return arg_type != Type::Error;
+        }
+        self.core.add_error(
+            format!("{} requires an integer position, found {}", name, core::CoreAnalyzer::format_type(&arg_type)),
+            arg.span(),
+        );
+        false
+    }
+}
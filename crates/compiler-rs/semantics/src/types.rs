@@ -1,15 +1,15 @@
 //! Type analysis (named types, arrays, records, etc.)
 
 use ast::Node;
-use symbols::SymbolKind;
-use ::types::{Field, Type};
-use crate::SemanticAnalyzer;
+use symbols::{ConstantValue, Symbol, SymbolKind};
+use ::types::{Field, RecordVariants, Type};
+use crate::{core, SemanticAnalyzer};
 use std::collections::HashMap;
 
 impl SemanticAnalyzer {
     /// Validate that a type argument satisfies its constraint
     /// Returns true if the constraint is satisfied, false otherwise
-    fn validate_generic_constraint(&mut self, arg_type: &Type, constraint: &Type, param_name: &str, span: tokens::Span) -> bool {
+    pub(crate) fn validate_generic_constraint(&mut self, arg_type: &Type, constraint: &Type, param_name: &str, span: tokens::Span) -> bool {
         // If arg_type is Error, skip constraint validation (error already reported)
         if *arg_type == Type::Error {
             return true; // Don't add additional constraint errors
@@ -21,10 +21,8 @@ impl SemanticAnalyzer {
                 match name.as_str() {
                     "class" => {
                         // T: class - arg_type must be a class type
-                        // For now, we check if it's a Named type (classes are typically Named types)
-                        // TODO: More sophisticated class type checking when class types are fully implemented
                         match arg_type {
-                            Type::Named { .. } => true,
+                            Type::Named { .. } | Type::Class { .. } => true,
                             _ => {
                                 self.core.add_error(
                                     format!(
@@ -57,10 +55,9 @@ impl SemanticAnalyzer {
                     }
                     "constructor" => {
                         // T: constructor - arg_type must have a constructor
-                        // For now, we assume all class types have constructors
-                        // TODO: More sophisticated constructor checking
+                        // All class types have one, if only TObject's
                         match arg_type {
-                            Type::Named { .. } => true,
+                            Type::Named { .. } | Type::Class { .. } => true,
                             _ => {
                                 self.core.add_error(
                                     format!(
@@ -108,6 +105,23 @@ impl SemanticAnalyzer {
                     }
                 }
             }
+            Type::Interface { name } => {
+                // T: IInterface - arg_type must implement or extend it
+                if arg_type == constraint || self.converts_to_interface(arg_type, constraint) {
+                    true
+                } else {
+                    self.core.add_error(
+                        format!(
+                            "Generic type parameter '{}' must implement '{}', but '{}' does not",
+                            param_name,
+                            name,
+                            crate::core::CoreAnalyzer::format_type(arg_type)
+                        ),
+                        span,
+                    );
+                    false
+                }
+            }
             _ => {
                 // Constraint is a type - check if arg_type is assignable to constraint
                 if arg_type.is_assignable_to(constraint) {
@@ -198,33 +212,214 @@ impl SemanticAnalyzer {
             Node::ArrayType(a) => {
                 let index_type = self.analyze_type_with_generic_params(&a.index_type, generic_params);
                 let element_type = self.analyze_type_with_generic_params(&a.element_type, generic_params);
-                Type::array(index_type, element_type)
+                let mut array = Type::array(index_type, element_type);
+                array.calculate_array_size();
+                array
             }
             Node::DynamicArrayType(d) => {
                 let element_type = self.analyze_type_with_generic_params(&d.element_type, generic_params);
                 Type::dynamic_array(element_type)
             }
-            Node::RecordType(r) => {
-                let fields: Vec<Field> = r
-                    .fields
-                    .iter()
-                    .map(|f| {
-                        let field_type = self.analyze_type_with_generic_params(&f.type_expr, generic_params);
-                        Field {
-                            name: f.names[0].clone(),
-                            field_type: Box::new(field_type),
-                            offset: None,
-                        }
-                    })
-                    .collect();
-                let mut record = Type::record(fields);
-                record.calculate_record_offsets();
-                record
-            }
+            Node::RecordType(r) => self.analyze_record_type(r, generic_params),
             _ => self.analyze_type(type_expr),
         }
     }
 
+    /// Analyze a record type. The variants of a variant part overlap after
+    /// the fixed fields and the tag field (see [`Type::variant_record`]).
+    /// A packed record, which {$ALIGN 1} makes every record, has no padding.
+    fn analyze_record_type(&mut self, record: &ast::RecordType, generic_params: &[String]) -> Type {
+        let mut fields = self.analyze_fields(&record.fields, generic_params);
+        let record_type = match &record.variant {
+            Some(part) => {
+                let variants = self.analyze_variant_part(part, &mut fields, generic_params);
+                Type::variant_record(fields, variants, record.is_packed)
+            }
+            None if record.is_packed => {
+                let mut record_type = Type::record(fields);
+                record_type.calculate_packed_record_offsets();
+                record_type
+            }
+            None => {
+                let mut record_type = Type::record(fields);
+                record_type.calculate_record_offsets();
+                record_type
+            }
+        };
+        if let Type::Record { fields, .. } = &record_type {
+            for (i, field) in fields.iter().enumerate() {
+                if fields[..i].iter().any(|other| other.name.eq_ignore_ascii_case(&field.name)) {
+                    self.core.add_error(format!("Duplicate field '{}' in record", field.name), record.span);
+                }
+            }
+        }
+        record_type
+    }
+
+    /// One field per name of each declaration
+    fn analyze_fields(&mut self, decls: &[ast::FieldDecl], generic_params: &[String]) -> Vec<Field> {
+        let mut fields = vec![];
+        for decl in decls {
+            let field_type = self.analyze_type_with_generic_params(&decl.type_expr, generic_params);
+            fields.extend(decl.names.iter().map(|name| Field {
+                name: name.clone(),
+                field_type: Box::new(field_type.clone()),
+                offset: None,
+            }));
+        }
+        fields
+    }
+
+    /// Analyze a variant part, adding its tag field to `fields`. The tag type
+    /// must be ordinal and each label a constant of it; the `else` variant
+    /// is laid out as one more variant.
+    fn analyze_variant_part(
+        &mut self,
+        part: &ast::VariantPart,
+        fields: &mut Vec<Field>,
+        generic_params: &[String],
+    ) -> RecordVariants {
+        let tag_type = self.analyze_type_with_generic_params(&part.tag_type, generic_params);
+        if !tag_type.is_ordinal() {
+            self.core.add_error(
+                format!("Variant tag type must be ordinal, found {}", core::CoreAnalyzer::format_type(&tag_type)),
+                part.tag_type.span(),
+            );
+        }
+        if let Some(tag) = &part.tag_field {
+            fields.push(Field { name: tag.clone(), field_type: Box::new(tag_type.clone()), offset: None });
+        }
+        let mut variants = RecordVariants::default();
+        for variant in &part.variants {
+            for label in &variant.values {
+                match label {
+                    Node::SubrangeType(range) => {
+                        self.check_variant_label(&range.low, &tag_type);
+                        self.check_variant_label(&range.high, &tag_type);
+                    }
+                    label => self.check_variant_label(label, &tag_type),
+                }
+            }
+            // A nested tag field follows the variant's own fields
+            let mut variant_fields = self.analyze_fields(&variant.fields, generic_params);
+            let nested = variant
+                .variant
+                .as_ref()
+                .map(|nested| self.analyze_variant_part(nested, &mut variant_fields, generic_params));
+            variants.variants.push((variant_fields, nested));
+        }
+        if let Some(else_fields) = &part.else_variant {
+            variants.variants.push((self.analyze_fields(else_fields, generic_params), None));
+        }
+        variants
+    }
+
+    /// A variant label must be a constant of the tag type
+    fn check_variant_label(&mut self, label: &Node, tag_type: &Type) {
+        let label_type = self.analyze_expression(label);
+        if label_type == Type::Error || *tag_type == Type::Error {
+            return;
+        }
+        if self.evaluate_constant_expression(label).as_ref().and_then(Self::constant_ordinal).is_none() {
+            self.core.add_error("Variant labels must be constants".to_string(), label.span());
+        } else if !label_type.is_assignable_to(tag_type) && !self.constant_fits(label, tag_type) {
+            self.core.add_error(
+                format!(
+                    "Variant label of type {} does not match tag type {}",
+                    core::CoreAnalyzer::format_type(&label_type),
+                    core::CoreAnalyzer::format_type(tag_type)
+                ),
+                label.span(),
+            );
+        } else {
+            self.check_constant_in_range(label, tag_type);
+        }
+    }
+
+    /// Analyze a subrange type: both bounds must be constants of one ordinal
+    /// type, the lower not above the upper
+    fn analyze_subrange_type(&mut self, subrange: &ast::SubrangeType) -> Type {
+        let low_type = self.analyze_expression(&subrange.low);
+        let high_type = self.analyze_expression(&subrange.high);
+        if low_type == Type::Error || high_type == Type::Error {
+            return Type::Error;
+        }
+        if !low_type.is_ordinal() || !high_type.is_ordinal() {
+            self.core.add_error(
+                format!(
+                    "Subrange bounds must be ordinal, found {} and {}",
+                    core::CoreAnalyzer::format_type(&low_type),
+                    core::CoreAnalyzer::format_type(&high_type)
+                ),
+                subrange.span,
+            );
+            return Type::Error;
+        }
+        // The wider of two integer types, e.g. Integer for 0..Size
+        let mut base = if high_type.is_assignable_to(&low_type) {
+            low_type
+        } else if low_type.is_assignable_to(&high_type) {
+            high_type
+        } else {
+            self.core.add_error(
+                format!(
+                    "Subrange bounds must have the same type, found {} and {}",
+                    core::CoreAnalyzer::format_type(&low_type),
+                    core::CoreAnalyzer::format_type(&high_type)
+                ),
+                subrange.span,
+            );
+            return Type::Error;
+        };
+
+        let bound = |this: &Self, expr: &Node| {
+            this.evaluate_constant_expression(expr).as_ref().and_then(Self::constant_ordinal)
+        };
+        let (Some(low), Some(high)) = (bound(self, &subrange.low), bound(self, &subrange.high)) else {
+            self.core.add_error("Subrange bounds must be constants".to_string(), subrange.span);
+            return Type::Error;
+        };
+        if low > high {
+            self.core.add_error(
+                format!(
+                    "Lower bound of subrange {}..{} is greater than the upper bound",
+                    core::CoreAnalyzer::format_ordinal(&base, low),
+                    core::CoreAnalyzer::format_ordinal(&base, high)
+                ),
+                subrange.span,
+            );
+            return Type::Error;
+        }
+        // Literals up to 65535 are typed Integer; wider bounds need a wider base
+        if base == Type::integer() && high > i16::MAX as i64 {
+            base = if low >= 0 && high <= u16::MAX as i64 { Type::word() } else { Type::longint() };
+        }
+        Type::subrange(base, low, high)
+    }
+
+    /// Analyze a set type: the base type must be ordinal with ordinal
+    /// numbers within 0..255, so an Integer set needs a subrange
+    fn analyze_set_type(&mut self, set: &ast::SetType) -> Type {
+        let element_type = self.analyze_type(&set.element_type);
+        if element_type == Type::Error {
+            return Type::Error;
+        }
+        let found = core::CoreAnalyzer::format_type(&element_type);
+        let set_type = Type::set(element_type);
+        if set_type.set_layout().is_none() {
+            self.core.add_error(
+                format!(
+                    "Set base type must be an ordinal type with values from 0 to {}, found {}",
+                    ::types::MAX_SET_ORDINAL,
+                    found
+                ),
+                set.span,
+            );
+            return Type::Error;
+        }
+        set_type
+    }
+
     /// Analyze type expression
     pub(crate) fn analyze_type(&mut self, type_expr: &Node) -> Type {
         match type_expr {
@@ -329,10 +524,14 @@ impl SemanticAnalyzer {
                             "integer" => Type::integer(),
                             "byte" => Type::byte(),
                             "word" => Type::word(),
+                            "longint" => Type::longint(),
+                            "cardinal" => Type::cardinal(),
+                            "real" | "single" | "Single" => Type::real(),
                             "boolean" => Type::boolean(),
                             "char" => Type::char(),
                             "variant" => Type::variant(),
                             "Variant" => Type::variant(),
+                            "pointer" | "Pointer" => Type::UntypedPointer,
                             _ => {
                                 self.core.add_error(
                                     format!("Type '{}' not found", n.name),
@@ -344,32 +543,44 @@ impl SemanticAnalyzer {
                     }
                 }
             }
+            Node::PointerType(p) => Type::pointer(self.analyze_type(&p.base_type)),
+            Node::FileType(f) => Type::file(f.element_type.as_ref().map(|e| self.analyze_type(e))),
             Node::ArrayType(a) => {
                 let index_type = self.analyze_type(&a.index_type);
                 let element_type = self.analyze_type(&a.element_type);
-                Type::array(index_type, element_type)
+                let mut array = Type::array(index_type, element_type);
+                array.calculate_array_size();
+                array
             }
+            Node::SubrangeType(s) => self.analyze_subrange_type(s),
+            Node::SetType(s) => self.analyze_set_type(s),
+            Node::StringType(s) => self.analyze_string_type(s),
             Node::DynamicArrayType(d) => {
                 let element_type = self.analyze_type(&d.element_type);
                 Type::dynamic_array(element_type)
             }
-            Node::RecordType(r) => {
-                let fields: Vec<Field> = r
-                    .fields
-                    .iter()
-                    .map(|f| {
-                        let field_type = self.analyze_type(&f.type_expr);
-                        Field {
-                            name: f.names[0].clone(), // Use first name for now
-                            field_type: Box::new(field_type),
-                            offset: None,
-                        }
-                    })
-                    .collect();
-                let mut record = Type::record(fields);
-                record.calculate_record_offsets();
-                record
+            Node::EnumType(e) => {
+                let enum_type = Type::Enum { values: e.values.clone() };
+                // Each value is a constant of the enumeration, numbered from 0
+                for (ordinal, value) in e.values.iter().enumerate() {
+                    if self.core.symbol_table.exists_in_current_scope(value) {
+                        self.core.add_error(format!("Enumeration value '{}' already declared", value), e.span);
+                        continue;
+                    }
+                    let symbol = Symbol {
+                        kind: SymbolKind::Constant {
+                            name: value.clone(),
+                            const_type: enum_type.clone(),
+                            value: Some(ConstantValue::Integer(ordinal as i16)),
+                            span: e.span,
+                        },
+                        scope_level: self.core.symbol_table.scope_level(),
+                    };
+                    let _ = self.core.symbol_table.insert(symbol);
+                }
+                enum_type
             }
+            Node::RecordType(r) => self.analyze_record_type(r, &[]),
             _ => {
                 self.core.add_error("Invalid type expression".to_string(), type_expr.span());
                 Type::Error
@@ -0,0 +1,234 @@
+//! Units: `uses` resolution and analysis of interface and implementation
+//!
+//! A used unit is never reparsed: its interface symbols come from the
+//! compiled unit (.spu) that `spc build` wrote for it, found next to the
+//! file being analyzed or in the current directory.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ast::{Node, Unit, UsesClause};
+use symbols::compiled_unit::{CompiledUnit, SPU_EXTENSION};
+
+use crate::SemanticAnalyzer;
+
+/// Compiled units already read, by path. Analyzers building the units of a
+/// project on several threads share one, so each unit is read once.
+pub type UnitCache = Arc<Mutex<HashMap<PathBuf, CompiledUnit>>>;
+
+impl SemanticAnalyzer {
+    /// Analyze a unit, recording the symbols its interface exports
+    pub(crate) fn analyze_unit(&mut self, unit: &Unit) {
+        if let Some(interface) = &unit.interface {
+            if let Some(uses) = &interface.uses {
+                self.analyze_uses(uses);
+            }
+            let imported: HashSet<String> =
+                self.core.symbol_table.current_scope_symbols().iter().map(|s| s.name().to_string()).collect();
+
+            for const_decl in &interface.const_decls {
+                self.analyze_const_decl(const_decl);
+            }
+            for type_decl in &interface.type_decls {
+                self.analyze_type_decl(type_decl);
+            }
+            for var_decl in &interface.var_decls {
+                self.analyze_var_decl(var_decl);
+            }
+            // Interface routines are headings; the implementation has the bodies
+            for decl in interface.proc_decls.iter().chain(&interface.func_decls) {
+                match decl {
+                    Node::ProcDecl(p) if self.declare_procedure(p).is_some() => self.add_forward(&p.name, p.span),
+                    Node::FuncDecl(f) if self.declare_function(f).is_some() => self.add_forward(&f.name, f.span),
+                    _ => {}
+                }
+            }
+
+            let mut exported: Vec<_> = self
+                .core
+                .symbol_table
+                .current_scope_symbols()
+                .into_iter()
+                .filter(|s| !imported.contains(s.name()))
+                .cloned()
+                .collect();
+            exported.sort_by_key(|s| s.span().start);
+            self.interface_symbols = exported;
+        }
+
+        if let Some(implementation) = &unit.implementation {
+            if let Some(uses) = &implementation.uses {
+                self.analyze_uses(uses);
+            }
+            for const_decl in &implementation.const_decls {
+                self.analyze_const_decl(const_decl);
+            }
+            for type_decl in &implementation.type_decls {
+                self.analyze_type_decl(type_decl);
+            }
+            for var_decl in &implementation.var_decls {
+                self.analyze_var_decl(var_decl);
+            }
+            for proc_decl in &implementation.proc_decls {
+                self.analyze_proc_decl(proc_decl);
+            }
+            for func_decl in &implementation.func_decls {
+                self.analyze_func_decl(func_decl);
+            }
+        }
+        self.check_forwards_resolved();
+
+        for block in unit.initialization.iter().chain(&unit.finalization) {
+            self.analyze_block(block);
+        }
+    }
+
+    /// Bring the interface symbols of every unit in `uses` into scope. When
+    /// two units export the same name, the one listed last wins.
+    pub(crate) fn analyze_uses(&mut self, uses: &UsesClause) {
+        let mut loaded = Vec::new();
+        for name in &uses.units {
+            match self.load_unit(name) {
+                Ok((path, unit)) => {
+                    self.used_units.push(path.clone());
+                    loaded.push((path, unit));
+                }
+                Err(message) => self.core.add_error(message, uses.span),
+            }
+        }
+        for (path, unit) in loaded.into_iter().rev() {
+            for symbol in unit.interface {
+                // Taken names belong to a unit listed later
+                let name = symbol.name().to_lowercase();
+                if self.core.symbol_table.insert(symbol).is_ok() {
+                    self.unit_symbols.insert(name, path.with_extension("pas"));
+                }
+            }
+        }
+    }
+
+    /// Find and read the compiled unit `name`
+    fn load_unit(&self, name: &str) -> Result<(PathBuf, CompiledUnit), String> {
+        let path = self.find_unit(name).ok_or_else(|| {
+            format!("Unit '{}' not found: build {}.pas to produce {}.{}", name, name, name, SPU_EXTENSION)
+        })?;
+        let cached = self.unit_cache.as_ref().and_then(|cache| cache.lock().ok()?.get(&path).cloned());
+        let unit = match cached {
+            Some(unit) => unit,
+            None => {
+                let bytes = fs::read(&path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+                let unit = CompiledUnit::read(&mut bytes.as_slice())
+                    .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+                if let Some(mut cache) = self.unit_cache.as_ref().and_then(|cache| cache.lock().ok()) {
+                    cache.insert(path.clone(), unit.clone());
+                }
+                unit
+            }
+        };
+        if !unit.name.eq_ignore_ascii_case(name) {
+            return Err(format!("'{}' holds unit '{}', not '{}'", path.display(), unit.name, name));
+        }
+        Ok((path, unit))
+    }
+
+    /// `<name>.spu`, or its lowercase spelling, next to the file being
+    /// analyzed or in the current directory
+    fn find_unit(&self, name: &str) -> Option<PathBuf> {
+        let source_dir = self.core.filename.as_deref().and_then(|f| Path::new(f).parent()).map(Path::to_path_buf);
+        let file_names = [name.to_string(), name.to_lowercase()].map(|n| format!("{}.{}", n, SPU_EXTENSION));
+        source_dir
+            .into_iter()
+            .chain([PathBuf::new()])
+            .flat_map(|dir| file_names.iter().map(move |file| dir.join(file)))
+            .find(|path| path.is_file())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use errors::ErrorSeverity;
+    use parser::Parser;
+
+    fn analyze(source: &str, filename: &Path) -> (SemanticAnalyzer, Vec<errors::Diagnostic>) {
+        let ast = Parser::new(source).unwrap().parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new(Some(filename.display().to_string()));
+        let diagnostics = analyzer.analyze(&ast);
+        (analyzer, diagnostics)
+    }
+
+    fn errors(diagnostics: &[errors::Diagnostic]) -> Vec<&str> {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == ErrorSeverity::Error)
+            .map(|d| d.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_uses_loads_compiled_unit_interface() {
+        let dir = std::env::temp_dir().join(format!("spc-units-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let unit_source = "unit Counters;
+            interface
+            const Limit = 10;
+            var Count: integer;
+            function Twice(a: integer): integer;
+            procedure Reset;
+            implementation
+            var Hidden: integer;
+            function Twice(a: integer): integer;
+            begin
+              Twice := a * 2;
+            end;
+            procedure Reset;
+            begin
+              Count := 0;
+            end;
+            end.";
+        let (analyzer, diagnostics) = analyze(unit_source, &dir.join("counters.pas"));
+        assert!(errors(&diagnostics).is_empty(), "{:?}", diagnostics);
+        let names: Vec<&str> = analyzer.interface_symbols().iter().map(|s| s.name()).collect();
+        assert_eq!(names, ["Limit", "Count", "Twice", "Reset"]);
+
+        let compiled = CompiledUnit {
+            name: "Counters".to_string(),
+            interface: analyzer.interface_symbols().to_vec(),
+            object: vec![],
+        };
+        let mut file = fs::File::create(dir.join("counters.spu")).unwrap();
+        compiled.write(&mut file).unwrap();
+
+        let program = "program Main;
+            uses Counters;
+            var n: integer;
+            begin
+              Reset;
+              n := Twice(Limit) + Count;
+              n := Hidden;
+            end.";
+        let (analyzer, diagnostics) = analyze(program, &dir.join("main.pas"));
+        assert_eq!(errors(&diagnostics).len(), 1, "{:?}", diagnostics);
+        assert!(errors(&diagnostics)[0].contains("Hidden"));
+        assert_eq!(analyzer.used_units(), [dir.join("counters.spu")]);
+
+        let (_, diagnostics) = analyze("program Main; uses Missing; begin end.", &dir.join("main.pas"));
+        assert!(errors(&diagnostics)[0].starts_with("Unit 'Missing' not found"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_interface_routine_without_body() {
+        let source = "unit Broken;
+            interface
+            procedure Missing;
+            implementation
+            end.";
+        let (_, diagnostics) = analyze(source, Path::new("broken.pas"));
+        assert_eq!(errors(&diagnostics), ["Routine 'Missing' is declared but has no body"]);
+    }
+}
@@ -0,0 +1,602 @@
+//! Warnings about code that compiles but is probably not what was meant
+//!
+//! Run on an analyzed program or unit, before dead code elimination, to
+//! report:
+//!
+//! - **Unused declarations**: variables, constants and routines nothing
+//!   refers to. A routine calling only itself is unused. What a unit exports
+//!   in its interface, methods and external routines are never reported.
+//! - **Reads before assignment**: a local variable of a routine read before
+//!   any assignment to it in the text of the routine. Passing it to a `var`
+//!   or `out` parameter, taking its address or naming it in a `with` or an
+//!   `asm` block counts as assigning it; globals start as zero.
+//! - **Hidden identifiers**: a parameter or local declaration with the name
+//!   of one in an enclosing scope, which it hides.
+//!
+//! ```text
+//!     var Count: integer;               { never used }
+//!     procedure Show(Count: integer);   { hides the global Count }
+//!     var Total: integer;
+//!     begin
+//!       WriteLn(Total)                  { read before it is assigned }
+//!     end;
+//! ```
+//!
+//! Each kind has a name for `{$WARN name OFF}`, which turns it off for the
+//! file: `UNUSED_VAR`, `UNUSED_CONST`, `UNUSED_PROC`, `USE_BEFORE_DEF` and
+//! `HIDDEN_IDENT`. The driver's `--deny-warnings` makes every warning an
+//! error.
+
+use std::collections::HashSet;
+
+use ast::{Node, Param, ParamType};
+use errors::{Diagnostic, ErrorSeverity, RelatedLocation};
+use tokens::Span;
+
+use crate::{WRITELN_INTRINSIC, WRITE_INTRINSIC};
+
+/// Prefix of the switches `{$WARN name ON|OFF}` sets, e.g. `WARN_UNUSED_VAR`
+pub const WARN_SWITCH_PREFIX: &str = "WARN_";
+
+/// A kind of warning, which `{$WARN name OFF}` turns off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    UnusedVariable,
+    UnusedConstant,
+    UnusedRoutine,
+    ReadBeforeAssignment,
+    HiddenIdentifier,
+}
+
+impl WarningKind {
+    pub const ALL: [WarningKind; 5] = [
+        WarningKind::UnusedVariable,
+        WarningKind::UnusedConstant,
+        WarningKind::UnusedRoutine,
+        WarningKind::ReadBeforeAssignment,
+        WarningKind::HiddenIdentifier,
+    ];
+
+    /// Name of the kind in `{$WARN}`
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => "UNUSED_VAR",
+            WarningKind::UnusedConstant => "UNUSED_CONST",
+            WarningKind::UnusedRoutine => "UNUSED_PROC",
+            WarningKind::ReadBeforeAssignment => "USE_BEFORE_DEF",
+            WarningKind::HiddenIdentifier => "HIDDEN_IDENT",
+        }
+    }
+
+    /// The switch `{$WARN name ON|OFF}` sets for the kind
+    pub fn switch(self) -> String {
+        format!("{}{}", WARN_SWITCH_PREFIX, self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeclKind {
+    Variable,
+    Constant,
+    Type,
+    Procedure,
+    Function,
+    Parameter,
+}
+
+impl DeclKind {
+    fn name(self) -> &'static str {
+        match self {
+            DeclKind::Variable => "variable",
+            DeclKind::Constant => "constant",
+            DeclKind::Type => "type",
+            DeclKind::Procedure => "procedure",
+            DeclKind::Function => "function",
+            DeclKind::Parameter => "parameter",
+        }
+    }
+}
+
+struct Declaration {
+    name: String,
+    kind: DeclKind,
+    span: Span,
+    /// Exported from a unit, so used from outside the file
+    exported: bool,
+    used: bool,
+    assigned: bool,
+    /// Per parameter of a routine, whether the argument may be assigned
+    by_reference: Vec<bool>,
+}
+
+/// Reports unused declarations, reads before assignment and hidden
+/// identifiers
+pub struct WarningChecker {
+    filename: Option<String>,
+    disabled: HashSet<WarningKind>,
+    scopes: Vec<Vec<Declaration>>,
+    /// Scope of the innermost routine, whose variables reads are checked in
+    routine_scope: Option<usize>,
+    /// Enclosing `with` statements, whose fields any name may be
+    with_depth: usize,
+    /// Names declared for the program rather than by it, lowercase, which
+    /// are never reported unused
+    kept: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl WarningChecker {
+    pub fn new(filename: Option<String>) -> Self {
+        Self {
+            filename,
+            disabled: HashSet::new(),
+            scopes: vec![],
+            routine_scope: None,
+            with_depth: 0,
+            kept: HashSet::new(),
+            diagnostics: vec![],
+        }
+    }
+
+    /// Stop reporting warnings of `kind`
+    pub fn disable(&mut self, kind: WarningKind) {
+        self.disabled.insert(kind);
+    }
+
+    /// Never report `name` unused, as the declarations of a resource
+    pub fn keep(&mut self, name: &str) {
+        self.kept.insert(name.to_lowercase());
+    }
+
+    /// Check a program, library or unit
+    pub fn check(&mut self, ast: &Node) {
+        match ast {
+            Node::Program(program) => self.main_block(&program.block),
+            Node::Library(library) => {
+                if let Some(block) = &library.block {
+                    self.main_block(block);
+                }
+            }
+            Node::Unit(unit) => {
+                self.scopes.push(vec![]);
+                if let Some(interface) = &unit.interface {
+                    for decl in interface.children() {
+                        self.declare(decl, true);
+                    }
+                }
+                if let Some(implementation) = &unit.implementation {
+                    let decls = implementation.children();
+                    for &decl in &decls {
+                        self.declare(decl, false);
+                    }
+                    for decl in decls {
+                        self.visit(decl);
+                    }
+                }
+                for block in unit.initialization.iter().chain(&unit.finalization) {
+                    self.visit(block);
+                }
+                self.close_scope();
+            }
+            _ => {}
+        }
+        // The instances of a generic routine are copies of it, and warn
+        // about the template's text once for each
+        self.diagnostics.sort_by(|a, b| (a.span.start, &a.message).cmp(&(b.span.start, &b.message)));
+        self.diagnostics.dedup_by(|a, b| a.span == b.span && a.message == b.message);
+    }
+
+    /// A warning for each problem found
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn warn(&mut self, kind: WarningKind, message: String, span: Span) -> Option<&mut Diagnostic> {
+        if self.disabled.contains(&kind) {
+            return None;
+        }
+        self.diagnostics.push(
+            Diagnostic::new(ErrorSeverity::Warning, message, span)
+                .with_file(self.filename.clone().unwrap_or_else(|| "unknown".to_string())),
+        );
+        self.diagnostics.last_mut()
+    }
+
+    /// The outermost block of a program or library
+    fn main_block(&mut self, block: &Node) {
+        self.scopes.push(vec![]);
+        self.block(block);
+        self.close_scope();
+    }
+
+    /// Declare the declarations of `block` in the current scope, then check
+    /// them and its statements
+    fn block(&mut self, block: &Node) {
+        let Node::Block(block) = block else {
+            return self.visit(block);
+        };
+        let decls: Vec<&Node> = [
+            &block.const_decls,
+            &block.type_decls,
+            &block.var_decls,
+            &block.threadvar_decls,
+            &block.proc_decls,
+            &block.func_decls,
+            &block.operator_decls,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        for &decl in &decls {
+            self.declare(decl, false);
+        }
+        for decl in decls {
+            self.visit(decl);
+        }
+        for statement in &block.statements {
+            self.visit(statement);
+        }
+    }
+
+    /// Add the names `decl` declares to the current scope
+    fn declare(&mut self, decl: &Node, exported: bool) {
+        match decl {
+            Node::VarDecl(var) => {
+                for name in &var.names {
+                    self.add(name, DeclKind::Variable, var.span, exported, vec![]);
+                }
+            }
+            Node::ConstDecl(constant) => self.add(&constant.name, DeclKind::Constant, constant.span, exported, vec![]),
+            Node::TypeDecl(type_decl) => self.add(&type_decl.name, DeclKind::Type, type_decl.span, exported, vec![]),
+            Node::ProcDecl(proc) if proc.class_name.is_none() => {
+                // Externals are kept for whoever links them
+                let exported = exported || proc.is_external;
+                self.add(&proc.name, DeclKind::Procedure, proc.span, exported, by_reference(&proc.params));
+            }
+            Node::FuncDecl(func) if func.class_name.is_none() => {
+                let exported = exported || func.is_external;
+                self.add(&func.name, DeclKind::Function, func.span, exported, by_reference(&func.params));
+            }
+            _ => {}
+        }
+    }
+
+    fn add(&mut self, name: &str, kind: DeclKind, span: Span, exported: bool, by_reference: Vec<bool>) {
+        // Only a routine's own variables can be read unassigned
+        let assigned = kind != DeclKind::Variable || self.routine_scope.is_none_or(|scope| scope + 1 != self.scopes.len());
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        // A routine's forward or interface declaration, then its body,
+        // which is where it may call itself
+        if let Some(earlier) = scope.iter_mut().find(|decl| decl.name.eq_ignore_ascii_case(name)) {
+            earlier.exported |= exported;
+            earlier.span = span;
+            return;
+        }
+        scope.push(Declaration {
+            name: name.to_string(),
+            kind,
+            span,
+            exported,
+            used: false,
+            assigned,
+            by_reference,
+        });
+        let outer = self.scopes[..self.scopes.len() - 1]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().find(|decl| decl.name.eq_ignore_ascii_case(name)))
+            .map(|decl| (decl.kind, decl.span));
+        if let Some((outer_kind, outer_span)) = outer {
+            let message = format!("{} '{}' hides the {} of an enclosing scope", capitalized(kind.name()), name, outer_kind.name());
+            let file = self.filename.clone();
+            if let Some(diagnostic) = self.warn(WarningKind::HiddenIdentifier, message, span) {
+                diagnostic.related_locations.push(RelatedLocation {
+                    message: "hidden declaration here".to_string(),
+                    span: outer_span,
+                    file,
+                });
+            }
+        }
+    }
+
+    /// Report the unused declarations of the innermost scope and leave it
+    fn close_scope(&mut self) {
+        for decl in self.scopes.pop().unwrap_or_default() {
+            if decl.used || decl.exported || self.kept.contains(&decl.name.to_lowercase()) {
+                continue;
+            }
+            let (kind, message) = match decl.kind {
+                DeclKind::Variable => (WarningKind::UnusedVariable, format!("Variable '{}' is declared but never used", decl.name)),
+                DeclKind::Constant => (WarningKind::UnusedConstant, format!("Constant '{}' is declared but never used", decl.name)),
+                // Worded as dead code elimination words the routines it drops
+                DeclKind::Procedure => (WarningKind::UnusedRoutine, format!("Procedure '{}' is never used", decl.name)),
+                DeclKind::Function => (WarningKind::UnusedRoutine, format!("Function '{}' is never used", decl.name)),
+                DeclKind::Type | DeclKind::Parameter => continue,
+            };
+            self.warn(kind, message, decl.span);
+        }
+    }
+
+    /// Check a routine body with its parameters, in a scope of its own
+    fn routine(&mut self, params: &[Param], block: &Node, span: Span) {
+        let outer_routine = self.routine_scope;
+        self.scopes.push(vec![]);
+        self.routine_scope = Some(self.scopes.len() - 1);
+        for param in params {
+            for name in &param.names {
+                self.add(name, DeclKind::Parameter, span, false, vec![]);
+            }
+            for node in param.type_expr.iter().chain(&param.default_value) {
+                self.visit(node);
+            }
+        }
+        self.block(block);
+        self.close_scope();
+        self.routine_scope = outer_routine;
+    }
+
+    /// The declaration `name` refers to, innermost first, with the index of
+    /// its scope
+    fn lookup(&mut self, name: &str) -> Option<(usize, &mut Declaration)> {
+        self.scopes
+            .iter_mut()
+            .enumerate()
+            .rev()
+            .find_map(|(index, scope)| scope.iter_mut().find(|decl| decl.name.eq_ignore_ascii_case(name)).map(|decl| (index, decl)))
+    }
+
+    /// Record a use of `name` at `span`; a routine using itself does not
+    /// count
+    fn reference(&mut self, name: &str, span: Span) {
+        if let Some((_, decl)) = self.lookup(name) {
+            let recursive = matches!(decl.kind, DeclKind::Procedure | DeclKind::Function)
+                && decl.span.start <= span.start
+                && span.start < decl.span.end;
+            decl.used |= !recursive;
+        }
+    }
+
+    /// Record a read of the variable `name` at `span`
+    fn read(&mut self, name: &str, span: Span) {
+        self.reference(name, span);
+        let checked = self.with_depth == 0;
+        let routine_scope = self.routine_scope;
+        let Some((scope, decl)) = self.lookup(name) else {
+            return;
+        };
+        if decl.assigned || !checked || Some(scope) != routine_scope {
+            return;
+        }
+        // Reported once
+        decl.assigned = true;
+        let message = format!("Variable '{}' may be read before it is assigned", decl.name);
+        self.warn(WarningKind::ReadBeforeAssignment, message, span);
+    }
+
+    /// Record a use of `name` that may assign it
+    fn assign(&mut self, name: &str, span: Span) {
+        self.reference(name, span);
+        if let Some((_, decl)) = self.lookup(name) {
+            decl.assigned = true;
+        }
+    }
+
+    /// Check an assignment target or an argument that may be assigned:
+    /// the variable it is part of is assigned, and indices and pointers in
+    /// it are read
+    fn target(&mut self, node: &Node) {
+        match node {
+            Node::IdentExpr(ident) => self.assign(&ident.name, ident.span),
+            Node::FieldExpr(field) => self.target(&field.record),
+            Node::IndexExpr(index) => {
+                self.target(&index.array);
+                self.visit(&index.index);
+            }
+            _ => self.visit(node),
+        }
+    }
+
+    /// Check the arguments of a call to `name`
+    fn arguments(&mut self, name: &str, args: &[Node], span: Span) {
+        self.reference(name, span);
+        let by_reference: Option<Vec<bool>> = if [WRITE_INTRINSIC, WRITELN_INTRINSIC].iter().any(|write| name.eq_ignore_ascii_case(write)) {
+            Some(vec![])
+        } else {
+            self.lookup(name).and_then(|(_, decl)| {
+                matches!(decl.kind, DeclKind::Procedure | DeclKind::Function).then(|| decl.by_reference.clone())
+            })
+        };
+        for (i, arg) in args.iter().enumerate() {
+            // Intrinsics and routines of other units may assign any variable
+            // passed to them
+            match &by_reference {
+                Some(by_reference) if !by_reference.get(i).copied().unwrap_or(false) => self.visit(arg),
+                _ => self.target(arg),
+            }
+        }
+    }
+
+    fn visit(&mut self, node: &Node) {
+        match node {
+            Node::ProcDecl(proc) => self.routine(&proc.params, &proc.block, proc.span),
+            Node::FuncDecl(func) => {
+                self.visit(&func.return_type);
+                self.routine(&func.params, &func.block, func.span);
+            }
+            Node::OperatorDecl(operator) => {
+                self.visit(&operator.return_type);
+                self.routine(&operator.params, &operator.block, operator.span);
+            }
+            Node::AnonymousFunction(function) => {
+                self.visit(&function.return_type);
+                self.routine(&function.params, &function.block, function.span);
+            }
+            Node::AnonymousProcedure(procedure) => self.routine(&procedure.params, &procedure.block, procedure.span),
+            Node::IdentExpr(ident) => self.read(&ident.name, ident.span),
+            Node::AssignStmt(assign) => {
+                self.visit(&assign.value);
+                self.target(&assign.target);
+            }
+            Node::ForStmt(for_stmt) => {
+                self.visit(&for_stmt.start_expr);
+                self.visit(&for_stmt.end_expr);
+                self.assign(&for_stmt.var_name, for_stmt.span);
+                self.visit(&for_stmt.body);
+            }
+            Node::ForInStmt(for_in) => {
+                self.visit(&for_in.collection_expr);
+                self.assign(&for_in.var_name, for_in.span);
+                self.visit(&for_in.body);
+            }
+            Node::CallStmt(call) => self.arguments(&call.name, &call.args, call.span),
+            Node::CallExpr(call) => self.arguments(&call.name, &call.args, call.span),
+            Node::MethodCall(call) => {
+                // A method, such as an object's constructor, may set up
+                // the instance it is called on
+                self.target(&call.object);
+                for arg in &call.args {
+                    self.target(arg);
+                }
+            }
+            Node::AddressOfExpr(address) => self.target(&address.target),
+            Node::WithStmt(with) => {
+                for record in &with.records {
+                    self.target(record);
+                }
+                self.with_depth += 1;
+                self.visit(&with.statement);
+                self.with_depth -= 1;
+            }
+            Node::AsmStmt(asm) => {
+                for (_, name) in asm.references() {
+                    self.assign(name, asm.span);
+                }
+            }
+            Node::TryStmt(try_stmt) => {
+                for statement in try_stmt.try_block.iter().chain(try_stmt.except_block.iter().flatten()) {
+                    self.visit(statement);
+                }
+                for handler in &try_stmt.exception_handlers {
+                    self.visit(&handler.exception_type);
+                    // The exception variable is local to its handler
+                    self.scopes.push(vec![]);
+                    if let Some(variable) = &handler.variable {
+                        self.add(variable, DeclKind::Parameter, handler.span, false, vec![]);
+                    }
+                    self.visit(&handler.handler);
+                    self.close_scope();
+                }
+                for statement in try_stmt.exception_else.iter().map(|node| &**node).chain(try_stmt.finally_block.iter().flatten()) {
+                    self.visit(statement);
+                }
+            }
+            _ => {
+                for child in node.children() {
+                    self.visit(child);
+                }
+            }
+        }
+    }
+}
+
+/// Per parameter, whether the routine may assign the argument given
+fn by_reference(params: &[Param]) -> Vec<bool> {
+    params
+        .iter()
+        .flat_map(|param| param.names.iter().map(|_| matches!(param.param_type, ParamType::Var | ParamType::Out)))
+        .collect()
+}
+
+fn capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(source: &str, disabled: &[WarningKind]) -> Vec<String> {
+        let ast = parser::Parser::new(source).unwrap().parse().unwrap();
+        let mut checker = WarningChecker::new(None);
+        for &kind in disabled {
+            checker.disable(kind);
+        }
+        checker.check(&ast);
+        checker.diagnostics().iter().map(|d| d.message.clone()).collect()
+    }
+
+    #[test]
+    fn test_unused_read_before_assignment_and_hidden() {
+        let source = "program P;
+             const Unused = 1; Size = 4;
+             var Count: integer; Table: array[1..Size] of integer;
+             procedure Fill(var Buf: integer); begin Buf := 0 end;
+             procedure Never; begin Never end;
+             function Sum(Count: integer): integer;
+             var Total, I, J, K: integer;
+             begin
+               Fill(J);
+               K := Table[1];
+               for I := 1 to Count do Total := Total + I;
+               Sum := Total + J + K
+             end;
+             begin
+               WriteLn(Sum(3))
+             end.";
+        assert_eq!(
+            check(source, &[]),
+            [
+                "Constant 'Unused' is declared but never used",
+                "Variable 'Count' is declared but never used",
+                "Procedure 'Never' is never used",
+                "Parameter 'Count' hides the variable of an enclosing scope",
+                "Variable 'Total' may be read before it is assigned",
+            ]
+        );
+        assert_eq!(
+            check(source, &[WarningKind::UnusedVariable, WarningKind::UnusedConstant, WarningKind::HiddenIdentifier]),
+            ["Procedure 'Never' is never used", "Variable 'Total' may be read before it is assigned"]
+        );
+    }
+
+    #[test]
+    fn test_instances_of_a_generic_warn_once() {
+        let mut ast = parser::Parser::new(
+            "program P;
+             var B: integer; C: byte;
+             function Max<T>(A, B: T): T;
+             begin
+               if A > B then Max := A else Max := B
+             end;
+             begin
+               B := Max(B, 2);
+               C := Max(C, C)
+             end.",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+        crate::generics::GenericInstantiator::new(None).instantiate(&mut ast);
+        let mut checker = WarningChecker::new(None);
+        checker.check(&ast);
+        let messages: Vec<_> = checker.diagnostics().iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["Parameter 'B' hides the variable of an enclosing scope"]);
+    }
+
+    #[test]
+    fn test_unit_exports_and_forward_declarations_are_not_reported() {
+        let source = "unit U;
+             interface
+               var Shared: integer;
+               procedure Visible;
+             implementation
+               procedure Tidy; forward;
+               procedure Visible; begin Tidy end;
+               procedure Tidy; begin Shared := 1 end;
+             end.";
+        assert!(check(source, &[]).is_empty());
+    }
+}
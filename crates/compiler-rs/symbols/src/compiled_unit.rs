@@ -0,0 +1,610 @@
+//! Compiled unit (.spu) files
+//!
+//! `spc build` writes one for every unit it compiles. A program or unit
+//! that names the unit in its `uses` clause loads the interface symbols
+//! from it instead of reparsing the source, and the embedded object file
+//! is what gets linked.
+//!
+//! Layout (little-endian):
+//! - magic `SPU\0`, format version (u16)
+//! - unit name
+//! - interface symbol count (u16), then each symbol
+//! - object file length (u32), then the object file bytes
+//!
+//! Strings are a u16 length followed by UTF-8 bytes.
+
+use std::io::{self, Read, Write};
+
+use tokens::Span;
+use types::{Field, PrimitiveType, Type};
+
+use crate::{ConstantValue, Parameter, ParameterMode, Symbol, SymbolKind};
+
+/// Compiled unit file magic number
+pub const SPU_MAGIC: &[u8] = b"SPU\0";
+/// Compiled unit file format version
+pub const SPU_VERSION: u16 = 1;
+/// File extension of compiled units
+pub const SPU_EXTENSION: &str = "spu";
+
+/// A compiled unit: its interface and its object code
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledUnit {
+    pub name: String,
+    /// Symbols declared in the interface section, in declaration order
+    pub interface: Vec<Symbol>,
+    /// The unit's object file (ZOF)
+    pub object: Vec<u8>,
+}
+
+impl CompiledUnit {
+    /// Write the compiled unit
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(SPU_MAGIC)?;
+        write_u16(writer, SPU_VERSION)?;
+        write_string(writer, &self.name)?;
+        write_len(writer, self.interface.len())?;
+        for symbol in &self.interface {
+            write_symbol(writer, symbol)?;
+        }
+        write_u32(writer, self.object.len() as u32)?;
+        writer.write_all(&self.object)
+    }
+
+    /// Read a compiled unit
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != SPU_MAGIC {
+            return Err(invalid("Invalid SPU magic number".to_string()));
+        }
+        let version = read_u16(reader)?;
+        if version != SPU_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported SPU version: {}", version),
+            ));
+        }
+        let name = read_string(reader)?;
+        let count = read_u16(reader)?;
+        let interface = (0..count).map(|_| read_symbol(reader)).collect::<io::Result<_>>()?;
+        let mut object = vec![0u8; read_u32(reader)? as usize];
+        reader.read_exact(&mut object)?;
+        Ok(Self { name, interface, object })
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_i64<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_len<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    let len = u16::try_from(len).map_err(|_| invalid(format!("Too many entries to store: {}", len)))?;
+    write_u16(writer, len)
+}
+
+fn write_string<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    write_len(writer, text.len())?;
+    writer.write_all(text.as_bytes())
+}
+
+fn write_strings<W: Write>(writer: &mut W, texts: &[String]) -> io::Result<()> {
+    write_len(writer, texts.len())?;
+    texts.iter().try_for_each(|text| write_string(writer, text))
+}
+
+/// Sizes and offsets are stored as u32, absent as `u32::MAX`
+fn write_size<W: Write>(writer: &mut W, size: Option<usize>) -> io::Result<()> {
+    write_u32(writer, size.map_or(u32::MAX, |s| s as u32))
+}
+
+fn write_span<W: Write>(writer: &mut W, span: &Span) -> io::Result<()> {
+    [span.start, span.end, span.line, span.column, span.end_line, span.end_column]
+        .iter()
+        .try_for_each(|&n| write_u32(writer, n as u32))
+}
+
+fn write_symbol<W: Write>(writer: &mut W, symbol: &Symbol) -> io::Result<()> {
+    match &symbol.kind {
+        SymbolKind::Variable { name, var_type, span } => {
+            write_u8(writer, 0)?;
+            write_string(writer, name)?;
+            write_type(writer, var_type)?;
+            write_span(writer, span)
+        }
+        SymbolKind::Constant { name, const_type, value, span } => {
+            write_u8(writer, 1)?;
+            write_string(writer, name)?;
+            write_type(writer, const_type)?;
+            match value {
+                Some(value) => {
+                    write_u8(writer, 1)?;
+                    write_constant(writer, value)?;
+                }
+                None => write_u8(writer, 0)?,
+            }
+            write_span(writer, span)
+        }
+        SymbolKind::TypeAlias { name, aliased_type, span } => {
+            write_u8(writer, 2)?;
+            write_string(writer, name)?;
+            write_type(writer, aliased_type)?;
+            write_span(writer, span)
+        }
+        SymbolKind::GenericType { name, param_names, param_constraints, template_type, span } => {
+            write_u8(writer, 3)?;
+            write_string(writer, name)?;
+            write_strings(writer, param_names)?;
+            write_len(writer, param_constraints.len())?;
+            for constraint in param_constraints {
+                match constraint {
+                    Some(constraint) => {
+                        write_u8(writer, 1)?;
+                        write_type(writer, constraint)?;
+                    }
+                    None => write_u8(writer, 0)?,
+                }
+            }
+            write_type(writer, template_type)?;
+            write_span(writer, span)
+        }
+        SymbolKind::Procedure { name, params, span } => {
+            write_u8(writer, 4)?;
+            write_string(writer, name)?;
+            write_params(writer, params)?;
+            write_span(writer, span)
+        }
+        SymbolKind::Function { name, params, return_type, span } => {
+            write_u8(writer, 5)?;
+            write_string(writer, name)?;
+            write_params(writer, params)?;
+            write_type(writer, return_type)?;
+            write_span(writer, span)
+        }
+    }
+}
+
+fn write_params<W: Write>(writer: &mut W, params: &[Parameter]) -> io::Result<()> {
+    write_len(writer, params.len())?;
+    for param in params {
+        write_string(writer, &param.name)?;
+        write_type(writer, &param.param_type)?;
+        write_u8(writer, param.passing_mode as u8)?;
+        write_span(writer, &param.span)?;
+    }
+    Ok(())
+}
+
+fn write_constant<W: Write>(writer: &mut W, value: &ConstantValue) -> io::Result<()> {
+    match value {
+        ConstantValue::Integer(n) => {
+            write_u8(writer, 0)?;
+            write_u16(writer, *n as u16)
+        }
+        ConstantValue::Byte(n) => {
+            write_u8(writer, 1)?;
+            write_u8(writer, *n)
+        }
+        ConstantValue::Word(n) => {
+            write_u8(writer, 2)?;
+            write_u16(writer, *n)
+        }
+        ConstantValue::Boolean(b) => {
+            write_u8(writer, 3)?;
+            write_u8(writer, *b as u8)
+        }
+        ConstantValue::Char(c) => {
+            write_u8(writer, 4)?;
+            write_u8(writer, *c)
+        }
+        ConstantValue::String(text) => {
+            write_u8(writer, 5)?;
+            write_string(writer, text)
+        }
+        ConstantValue::Bytes(bytes) => {
+            write_u8(writer, 6)?;
+            write_len(writer, bytes.len())?;
+            writer.write_all(bytes)
+        }
+        ConstantValue::Real(r) => {
+            write_u8(writer, 7)?;
+            write_u32(writer, r.to_bits())
+        }
+        ConstantValue::LongInt(n) => {
+            write_u8(writer, 8)?;
+            write_u32(writer, *n as u32)
+        }
+        ConstantValue::Cardinal(n) => {
+            write_u8(writer, 9)?;
+            write_u32(writer, *n)
+        }
+    }
+}
+
+fn write_type<W: Write>(writer: &mut W, ty: &Type) -> io::Result<()> {
+    match ty {
+        Type::Primitive(primitive) => {
+            write_u8(writer, 0)?;
+            write_u8(writer, *primitive as u8)
+        }
+        Type::Array { index_type, element_type, size } => {
+            write_u8(writer, 1)?;
+            write_type(writer, index_type)?;
+            write_type(writer, element_type)?;
+            write_size(writer, *size)
+        }
+        Type::DynamicArray { element_type } => {
+            write_u8(writer, 2)?;
+            write_type(writer, element_type)
+        }
+        Type::Record { fields, size } => {
+            write_u8(writer, 3)?;
+            write_len(writer, fields.len())?;
+            for field in fields {
+                write_string(writer, &field.name)?;
+                write_type(writer, &field.field_type)?;
+                write_size(writer, field.offset)?;
+            }
+            write_size(writer, *size)
+        }
+        Type::Pointer { base_type } => {
+            write_u8(writer, 4)?;
+            write_type(writer, base_type)
+        }
+        Type::UntypedPointer => write_u8(writer, 5),
+        Type::File { element_type } => {
+            write_u8(writer, 6)?;
+            match element_type {
+                Some(element_type) => {
+                    write_u8(writer, 1)?;
+                    write_type(writer, element_type)
+                }
+                None => write_u8(writer, 0),
+            }
+        }
+        Type::Untyped => write_u8(writer, 7),
+        Type::Named { name } => {
+            write_u8(writer, 8)?;
+            write_string(writer, name)
+        }
+        Type::Generic { name, param_names, template } => {
+            write_u8(writer, 9)?;
+            write_string(writer, name)?;
+            write_strings(writer, param_names)?;
+            write_type(writer, template)
+        }
+        Type::Instantiated { generic_name, args } => {
+            write_u8(writer, 10)?;
+            write_string(writer, generic_name)?;
+            write_len(writer, args.len())?;
+            args.iter().try_for_each(|arg| write_type(writer, arg))
+        }
+        Type::Enum { values } => {
+            write_u8(writer, 11)?;
+            write_strings(writer, values)
+        }
+        Type::Distinct { name, base } => {
+            write_u8(writer, 12)?;
+            write_string(writer, name)?;
+            write_type(writer, base)
+        }
+        Type::Variant => write_u8(writer, 13),
+        Type::Error => write_u8(writer, 14),
+        Type::Subrange { base, low, high } => {
+            write_u8(writer, 15)?;
+            write_type(writer, base)?;
+            write_i64(writer, *low)?;
+            write_i64(writer, *high)
+        }
+        Type::Set { element_type } => {
+            write_u8(writer, 16)?;
+            write_type(writer, element_type)
+        }
+        Type::String { max_length } => {
+            write_u8(writer, 17)?;
+            write_u8(writer, *max_length as u8)
+        }
+        Type::Class { name } => {
+            write_u8(writer, 18)?;
+            write_string(writer, name)
+        }
+        Type::Interface { name } => {
+            write_u8(writer, 19)?;
+            write_string(writer, name)
+        }
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut bytes = vec![0u8; read_u16(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid("Invalid UTF-8 in name".to_string()))
+}
+
+fn read_strings<R: Read>(reader: &mut R) -> io::Result<Vec<String>> {
+    (0..read_u16(reader)?).map(|_| read_string(reader)).collect()
+}
+
+fn read_size<R: Read>(reader: &mut R) -> io::Result<Option<usize>> {
+    let size = read_u32(reader)?;
+    Ok((size != u32::MAX).then_some(size as usize))
+}
+
+fn read_span<R: Read>(reader: &mut R) -> io::Result<Span> {
+    Ok(Span {
+        start: read_u32(reader)? as usize,
+        end: read_u32(reader)? as usize,
+        line: read_u32(reader)? as usize,
+        column: read_u32(reader)? as usize,
+        end_line: read_u32(reader)? as usize,
+        end_column: read_u32(reader)? as usize,
+    })
+}
+
+fn read_flag<R: Read>(reader: &mut R) -> io::Result<bool> {
+    match read_u8(reader)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        flag => Err(invalid(format!("Invalid flag: {}", flag))),
+    }
+}
+
+fn read_symbol<R: Read>(reader: &mut R) -> io::Result<Symbol> {
+    let kind = match read_u8(reader)? {
+        0 => SymbolKind::Variable { name: read_string(reader)?, var_type: read_type(reader)?, span: read_span(reader)? },
+        1 => SymbolKind::Constant {
+            name: read_string(reader)?,
+            const_type: read_type(reader)?,
+            value: if read_flag(reader)? { Some(read_constant(reader)?) } else { None },
+            span: read_span(reader)?,
+        },
+        2 => SymbolKind::TypeAlias { name: read_string(reader)?, aliased_type: read_type(reader)?, span: read_span(reader)? },
+        3 => SymbolKind::GenericType {
+            name: read_string(reader)?,
+            param_names: read_strings(reader)?,
+            param_constraints: (0..read_u16(reader)?)
+                .map(|_| if read_flag(reader)? { read_type(reader).map(Some) } else { Ok(None) })
+                .collect::<io::Result<_>>()?,
+            template_type: read_type(reader)?,
+            span: read_span(reader)?,
+        },
+        4 => SymbolKind::Procedure { name: read_string(reader)?, params: read_params(reader)?, span: read_span(reader)? },
+        5 => SymbolKind::Function {
+            name: read_string(reader)?,
+            params: read_params(reader)?,
+            return_type: read_type(reader)?,
+            span: read_span(reader)?,
+        },
+        tag => return Err(invalid(format!("Invalid symbol kind: {}", tag))),
+    };
+    // Imported symbols belong to the importer's global scope
+    Ok(Symbol { kind, scope_level: 0 })
+}
+
+fn read_params<R: Read>(reader: &mut R) -> io::Result<Vec<Parameter>> {
+    (0..read_u16(reader)?)
+        .map(|_| {
+            Ok(Parameter {
+                name: read_string(reader)?,
+                param_type: read_type(reader)?,
+                passing_mode: match read_u8(reader)? {
+                    0 => ParameterMode::Value,
+                    1 => ParameterMode::Var,
+                    2 => ParameterMode::Const,
+                    mode => return Err(invalid(format!("Invalid parameter mode: {}", mode))),
+                },
+                span: read_span(reader)?,
+            })
+        })
+        .collect()
+}
+
+fn read_constant<R: Read>(reader: &mut R) -> io::Result<ConstantValue> {
+    Ok(match read_u8(reader)? {
+        0 => ConstantValue::Integer(read_u16(reader)? as i16),
+        1 => ConstantValue::Byte(read_u8(reader)?),
+        2 => ConstantValue::Word(read_u16(reader)?),
+        3 => ConstantValue::Boolean(read_flag(reader)?),
+        4 => ConstantValue::Char(read_u8(reader)?),
+        5 => ConstantValue::String(read_string(reader)?),
+        6 => {
+            let mut bytes = vec![0u8; read_u16(reader)? as usize];
+            reader.read_exact(&mut bytes)?;
+            ConstantValue::Bytes(bytes)
+        }
+        7 => ConstantValue::Real(f32::from_bits(read_u32(reader)?)),
+        8 => ConstantValue::LongInt(read_u32(reader)? as i32),
+        9 => ConstantValue::Cardinal(read_u32(reader)?),
+        tag => return Err(invalid(format!("Invalid constant kind: {}", tag))),
+    })
+}
+
+fn read_type<R: Read>(reader: &mut R) -> io::Result<Type> {
+    Ok(match read_u8(reader)? {
+        0 => Type::Primitive(match read_u8(reader)? {
+            0 => PrimitiveType::Integer,
+            1 => PrimitiveType::Byte,
+            2 => PrimitiveType::Word,
+            3 => PrimitiveType::Boolean,
+            4 => PrimitiveType::Char,
+            5 => PrimitiveType::Real,
+            6 => PrimitiveType::LongInt,
+            7 => PrimitiveType::Cardinal,
+            primitive => return Err(invalid(format!("Invalid primitive type: {}", primitive))),
+        }),
+        1 => Type::Array {
+            index_type: Box::new(read_type(reader)?),
+            element_type: Box::new(read_type(reader)?),
+            size: read_size(reader)?,
+        },
+        2 => Type::DynamicArray { element_type: Box::new(read_type(reader)?) },
+        3 => Type::Record {
+            fields: (0..read_u16(reader)?)
+                .map(|_| {
+                    Ok(Field {
+                        name: read_string(reader)?,
+                        field_type: Box::new(read_type(reader)?),
+                        offset: read_size(reader)?,
+                    })
+                })
+                .collect::<io::Result<_>>()?,
+            size: read_size(reader)?,
+        },
+        4 => Type::Pointer { base_type: Box::new(read_type(reader)?) },
+        5 => Type::UntypedPointer,
+        6 => Type::File {
+            element_type: if read_flag(reader)? { Some(Box::new(read_type(reader)?)) } else { None },
+        },
+        7 => Type::Untyped,
+        8 => Type::Named { name: read_string(reader)? },
+        9 => Type::Generic {
+            name: read_string(reader)?,
+            param_names: read_strings(reader)?,
+            template: Box::new(read_type(reader)?),
+        },
+        10 => Type::Instantiated {
+            generic_name: read_string(reader)?,
+            args: (0..read_u16(reader)?).map(|_| read_type(reader)).collect::<io::Result<_>>()?,
+        },
+        11 => Type::Enum { values: read_strings(reader)? },
+        12 => Type::Distinct { name: read_string(reader)?, base: Box::new(read_type(reader)?) },
+        13 => Type::Variant,
+        14 => Type::Error,
+        15 => Type::Subrange {
+            base: Box::new(read_type(reader)?),
+            low: read_i64(reader)?,
+            high: read_i64(reader)?,
+        },
+        16 => Type::set(read_type(reader)?),
+        17 => Type::string(read_u8(reader)? as usize),
+        18 => Type::Class { name: read_string(reader)? },
+        19 => Type::Interface { name: read_string(reader)? },
+        tag => return Err(invalid(format!("Invalid type kind: {}", tag))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compiled_unit_round_trip() {
+        let span = Span::new(10, 20, 3, 5);
+        let point = Type::Record {
+            fields: vec![
+                Field { name: "X".to_string(), field_type: Box::new(Type::integer()), offset: Some(0) },
+                Field { name: "Y".to_string(), field_type: Box::new(Type::integer()), offset: Some(2) },
+            ],
+            size: Some(4),
+        };
+        let symbol = |kind| Symbol { kind, scope_level: 0 };
+        let unit = CompiledUnit {
+            name: "Geometry".to_string(),
+            interface: vec![
+                symbol(SymbolKind::Constant {
+                    name: "Origin".to_string(),
+                    const_type: Type::integer(),
+                    value: Some(ConstantValue::Integer(-1)),
+                    span,
+                }),
+                symbol(SymbolKind::Constant {
+                    name: "Pi".to_string(),
+                    const_type: Type::real(),
+                    value: Some(ConstantValue::Real(std::f32::consts::PI)),
+                    span,
+                }),
+                symbol(SymbolKind::Constant {
+                    name: "Far".to_string(),
+                    const_type: Type::longint(),
+                    value: Some(ConstantValue::LongInt(-100_000)),
+                    span,
+                }),
+                symbol(SymbolKind::TypeAlias { name: "TPoint".to_string(), aliased_type: point.clone(), span }),
+                symbol(SymbolKind::TypeAlias {
+                    name: "TOffset".to_string(),
+                    aliased_type: Type::subrange(Type::longint(), -100_000, 100_000),
+                    span,
+                }),
+                symbol(SymbolKind::TypeAlias {
+                    name: "TDigits".to_string(),
+                    aliased_type: Type::set(Type::subrange(Type::char(), 48, 57)),
+                    span,
+                }),
+                symbol(SymbolKind::TypeAlias {
+                    name: "TName".to_string(),
+                    aliased_type: Type::string(20),
+                    span,
+                }),
+                symbol(SymbolKind::Variable {
+                    name: "Path".to_string(),
+                    var_type: Type::Array {
+                        index_type: Box::new(Type::byte()),
+                        element_type: Box::new(Type::Named { name: "TPoint".to_string() }),
+                        size: None,
+                    },
+                    span,
+                }),
+                symbol(SymbolKind::Function {
+                    name: "Distance".to_string(),
+                    params: vec![Parameter {
+                        name: "A, B".to_string(),
+                        param_type: Type::Pointer { base_type: Box::new(point) },
+                        passing_mode: ParameterMode::Const,
+                        span,
+                    }],
+                    return_type: Type::Enum { values: vec!["Near".to_string(), "Far".to_string()] },
+                    span,
+                }),
+            ],
+            object: b"ZOF\0\x02\x00".to_vec(),
+        };
+
+        let mut bytes = Vec::new();
+        unit.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], SPU_MAGIC);
+        assert_eq!(CompiledUnit::read(&mut bytes.as_slice()).unwrap(), unit);
+
+        bytes[4] = 99;
+        assert!(CompiledUnit::read(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -13,6 +13,8 @@ use std::collections::HashMap;
 use tokens::Span;
 use types::Type;
 
+pub mod compiled_unit;
+
 /// Symbol kind
 #[derive(Debug, Clone, PartialEq)]
 pub enum SymbolKind {
@@ -67,6 +69,10 @@ pub enum ConstantValue {
     Boolean(bool),
     Char(u8),
     String(String),
+    Bytes(Vec<u8>),
+    Real(f32),
+    LongInt(i32),
+    Cardinal(u32),
 }
 
 /// Function/procedure parameter
@@ -132,7 +138,7 @@ pub fn hash_bucket(hash: u16, mask: u16) -> usize {
 }
 
 /// Symbol entry in the symbol table
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Symbol {
     pub kind: SymbolKind,
     /// Scope level (0 = global, 1+ = nested scopes for future Tier 2)
@@ -171,6 +177,8 @@ pub struct SymbolTable {
     current_scope: usize,
     /// Symbols by scope level
     scopes: Vec<HashMap<String, Symbol>>,
+    /// Every symbol inserted, in order, including those of exited scopes
+    declared: Vec<Symbol>,
 }
 
 impl SymbolTable {
@@ -179,6 +187,7 @@ impl SymbolTable {
         Self {
             current_scope: 0,
             scopes: vec![HashMap::new()], // Start with global scope
+            declared: Vec::new(),
         }
     }
 
@@ -209,6 +218,7 @@ impl SymbolTable {
             ));
         }
 
+        self.declared.push(symbol.clone());
         current_scope_map.insert(name, symbol);
         Ok(())
     }
@@ -239,6 +249,12 @@ impl SymbolTable {
         self.scopes[self.current_scope].values().collect()
     }
 
+    /// Every symbol inserted so far, in order, with those of scopes since
+    /// exited (for `spc emit-symbols`)
+    pub fn declared(&self) -> &[Symbol] {
+        &self.declared
+    }
+
     /// Get the current scope level
     pub fn scope_level(&self) -> usize {
         self.current_scope
@@ -554,6 +570,11 @@ mod tests {
         assert!(found.is_some());
         // The found symbol should be from the local scope
         assert_eq!(found.unwrap().scope_level, 1);
+
+        // Both stay on record once the local scope is exited
+        table.exit_scope();
+        let levels: Vec<usize> = table.declared().iter().map(|symbol| symbol.scope_level).collect();
+        assert_eq!(levels, [0, 1]);
     }
 
     #[test]
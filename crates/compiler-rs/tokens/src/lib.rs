@@ -14,36 +14,51 @@ pub struct Span {
     pub line: usize,
     /// Column number (1-based)
     pub column: usize,
+    /// Line number of the end position (1-based)
+    pub end_line: usize,
+    /// Column number of the end position (1-based, exclusive)
+    pub end_column: usize,
 }
 
 impl Span {
-    /// Create a new span
+    /// Create a new span. The span is assumed to lie on one line; use
+    /// [`Span::with_end`] for spans that cross lines.
     pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
         Self {
             start,
             end,
             line,
             column,
+            end_line: line,
+            end_column: column + end.saturating_sub(start),
         }
     }
 
     /// Create a zero-length span at a position
     pub fn at(pos: usize, line: usize, column: usize) -> Self {
+        Self::new(pos, pos, line, column)
+    }
+
+    /// Set the line and column of the end position
+    pub fn with_end(self, end_line: usize, end_column: usize) -> Self {
         Self {
-            start: pos,
-            end: pos,
-            line,
-            column,
+            end_line,
+            end_column,
+            ..self
         }
     }
 
-    /// Merge two spans (from start of first to end of second)
+    /// Merge two spans, from the earlier start to the later end, in either order
     pub fn merge(self, other: Self) -> Self {
+        let first = if other.start < self.start { other } else { self };
+        let last = if other.end > self.end { other } else { self };
         Self {
-            start: self.start.min(other.start),
-            end: self.end.max(other.end),
-            line: self.line,
-            column: self.column,
+            start: first.start,
+            end: last.end,
+            line: first.line,
+            column: first.column,
+            end_line: last.end_line,
+            end_column: last.end_column,
         }
     }
 }
@@ -65,6 +80,7 @@ pub enum TokenKind {
     KwAsm,   // ASM keyword for inline assembly
     KwBegin,
     KwBoolean,
+    KwCardinal,
     KwByte,
     KwCase,
     KwChar,
@@ -82,6 +98,7 @@ pub enum TokenKind {
     KwFunction,
     KwGoto,
     KwLabel,  // LABEL keyword for label declarations
+    KwLongint,
     KwIf,
     KwIn,      // in (set membership operator)
     KwInteger,
@@ -94,6 +111,7 @@ pub enum TokenKind {
     KwPacked,    // PACKED keyword for packed records/arrays
     KwProcedure,
     KwProgram,
+    KwReal,
     KwRecord,
     KwRepeat,
     KwSet,
@@ -164,13 +182,17 @@ pub enum TokenKind {
     // ===== Literals =====
     /// Integer literal (decimal or hexadecimal)
     IntegerLiteral {
-        value: u16,
+        value: u32,
         is_hex: bool,
     },
+    /// Real literal as written (`3.14`, `1e-3`, `2.5E+2`)
+    RealLiteral(String),
     /// Character literal
     CharLiteral(u8),
     /// String literal
     StringLiteral(String),
+    /// Raw byte array literal: $"00 FF 3A"
+    ByteArrayLiteral(Vec<u8>),
     /// Boolean literal
     BooleanLiteral(bool),
 
@@ -209,9 +231,13 @@ pub enum TokenKind {
     At,         // @
 
     // ===== Directives =====
-    /// Compiler directive: {$...}
+    /// Compiler directive: {$...}, holding the text between the delimiters untrimmed
     Directive(String),
 
+    // ===== Inline assembly =====
+    /// Source text of an ASM block, from after ASM up to its END
+    AsmText(String),
+
     // ===== Special =====
     /// End of file
     Eof,
@@ -260,6 +286,8 @@ impl Token {
                 | TokenKind::KwIf
                 | TokenKind::KwIn
                 | TokenKind::KwInteger
+                | TokenKind::KwLongint
+                | TokenKind::KwCardinal
                 | TokenKind::KwIs
                 | TokenKind::KwAs
                 | TokenKind::KwMod
@@ -269,6 +297,7 @@ impl Token {
                 | TokenKind::KwPacked
                 | TokenKind::KwProcedure
                 | TokenKind::KwProgram
+                | TokenKind::KwReal
                 | TokenKind::KwRecord
                 | TokenKind::KwRepeat
                 | TokenKind::KwSet
@@ -351,8 +380,10 @@ impl Token {
         matches!(
             self.kind,
             TokenKind::IntegerLiteral { .. }
+                | TokenKind::RealLiteral(_)
                 | TokenKind::CharLiteral(_)
                 | TokenKind::StringLiteral(_)
+                | TokenKind::ByteArrayLiteral(_)
                 | TokenKind::BooleanLiteral(_)
         )
     }
@@ -504,6 +535,7 @@ pub fn lookup_keyword(s: &str) -> Option<TokenKind> {
     if eq_ignore_ascii_case(s, "begin") { return Some(TokenKind::KwBegin); }
     if eq_ignore_ascii_case(s, "boolean") { return Some(TokenKind::KwBoolean); }
     if eq_ignore_ascii_case(s, "byte") { return Some(TokenKind::KwByte); }
+    if eq_ignore_ascii_case(s, "cardinal") { return Some(TokenKind::KwCardinal); }
     if eq_ignore_ascii_case(s, "case") { return Some(TokenKind::KwCase); }
     if eq_ignore_ascii_case(s, "char") { return Some(TokenKind::KwChar); }
     if eq_ignore_ascii_case(s, "const") { return Some(TokenKind::KwConst); }
@@ -520,6 +552,7 @@ pub fn lookup_keyword(s: &str) -> Option<TokenKind> {
     if eq_ignore_ascii_case(s, "function") { return Some(TokenKind::KwFunction); }
     if eq_ignore_ascii_case(s, "goto") { return Some(TokenKind::KwGoto); }
     if eq_ignore_ascii_case(s, "label") { return Some(TokenKind::KwLabel); }
+    if eq_ignore_ascii_case(s, "longint") { return Some(TokenKind::KwLongint); }
     if eq_ignore_ascii_case(s, "if") { return Some(TokenKind::KwIf); }
     if eq_ignore_ascii_case(s, "in") { return Some(TokenKind::KwIn); }
     if eq_ignore_ascii_case(s, "helper") { return Some(TokenKind::KwHelper); }
@@ -534,6 +567,7 @@ pub fn lookup_keyword(s: &str) -> Option<TokenKind> {
     if eq_ignore_ascii_case(s, "packed") { return Some(TokenKind::KwPacked); }
     if eq_ignore_ascii_case(s, "procedure") { return Some(TokenKind::KwProcedure); }
     if eq_ignore_ascii_case(s, "program") { return Some(TokenKind::KwProgram); }
+    if eq_ignore_ascii_case(s, "real") { return Some(TokenKind::KwReal); }
     if eq_ignore_ascii_case(s, "record") { return Some(TokenKind::KwRecord); }
     if eq_ignore_ascii_case(s, "repeat") { return Some(TokenKind::KwRepeat); }
     if eq_ignore_ascii_case(s, "set") { return Some(TokenKind::KwSet); }
@@ -698,6 +732,17 @@ mod tests {
         assert_eq!(merged.end, 15);
     }
 
+    #[test]
+    fn test_span_merge_multiline_reversed() {
+        let begin = Span::new(20, 25, 3, 5);
+        let end = Span::new(60, 63, 7, 1);
+        let merged = end.merge(begin);
+
+        assert_eq!((merged.start, merged.end), (20, 63));
+        assert_eq!((merged.line, merged.column), (3, 5));
+        assert_eq!((merged.end_line, merged.end_column), (7, 4));
+    }
+
     #[test]
     fn test_token_checks() {
         let token = Token::new(
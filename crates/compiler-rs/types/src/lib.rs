@@ -5,6 +5,14 @@
 
 // ast::Node not needed yet, will be used when converting AST types to Type
 
+/// Largest ordinal number a set element can have: sets hold at most 256
+/// elements, numbered 0..255
+pub const MAX_SET_ORDINAL: i64 = 255;
+
+/// Largest number of characters a string can hold: the length is stored in
+/// the byte before them, so plain `string` is `string[255]`
+pub const MAX_STRING_LENGTH: usize = 255;
+
 /// Type representation for SuperPascal
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
@@ -31,6 +39,17 @@ pub enum Type {
     Pointer {
         base_type: Box<Type>,
     },
+    /// Untyped pointer: `Pointer`. Compatible with every pointer type, but
+    /// must be cast to a typed pointer to be dereferenced or moved.
+    UntypedPointer,
+    /// File type: `file of T`, or an untyped `file` read and written in
+    /// blocks of records
+    File {
+        element_type: Option<Box<Type>>,
+    },
+    /// Formal type of an untyped `var`/`const` parameter: only the address
+    /// of the argument is known, so the callee must take `@` or cast it
+    Untyped,
     /// Named type (type alias)
     Named {
         name: String,
@@ -46,6 +65,45 @@ pub enum Type {
         generic_name: String,
         args: Vec<Type>,
     },
+    /// Enumerated type: (Red, Green, Blue); values are numbered from 0
+    Enum {
+        values: Vec<String>,
+    },
+    /// Distinct type: `type THandle = type word;` has the representation of
+    /// `base` but is only compatible with itself
+    Distinct {
+        name: String,
+        base: Box<Type>,
+    },
+    /// Subrange type: `1..10`, `'a'..'z'`; values of the ordinal type `base`
+    /// from `low` to `high`, as ordinal numbers
+    Subrange {
+        base: Box<Type>,
+        low: i64,
+        high: i64,
+    },
+    /// Set type: `set of T` for an ordinal `T` whose ordinal numbers are
+    /// within 0..255, stored as a bitset (see [`Type::set_layout`]). The
+    /// empty set `[]` has the element type Error.
+    Set {
+        element_type: Box<Type>,
+    },
+    /// String type: `string[n]` holds up to `max_length` characters, stored
+    /// as a length byte followed by `max_length` bytes of characters
+    String {
+        max_length: usize,
+    },
+    /// Class type: a reference to an instance of the class `name`, laid
+    /// out by its [`ClassLayout`]
+    Class {
+        name: String,
+    },
+    /// Interface type: a reference to an instance of a class implementing
+    /// the interface `name`, whose methods are listed by its
+    /// [`InterfaceLayout`]
+    Interface {
+        name: String,
+    },
     /// Variant type (dynamic typing - can hold any type)
     Variant,
     /// Error type (for error recovery)
@@ -60,6 +118,9 @@ pub enum PrimitiveType {
     Word,     // 16-bit unsigned integer
     Boolean,  // Boolean (1 byte)
     Char,     // Character (1 byte)
+    Real,     // 32-bit IEEE single precision (`real`, `single`)
+    LongInt,  // 32-bit signed integer
+    Cardinal, // 32-bit unsigned integer
 }
 
 impl PrimitiveType {
@@ -69,8 +130,10 @@ impl PrimitiveType {
             PrimitiveType::Integer => 2,
             PrimitiveType::Byte => 1,
             PrimitiveType::Word => 2,
+            PrimitiveType::LongInt | PrimitiveType::Cardinal => 4,
             PrimitiveType::Boolean => 1,
             PrimitiveType::Char => 1,
+            PrimitiveType::Real => 4,
         }
     }
 
@@ -80,8 +143,66 @@ impl PrimitiveType {
             PrimitiveType::Integer => 2,
             PrimitiveType::Byte => 1,
             PrimitiveType::Word => 2,
+            PrimitiveType::LongInt | PrimitiveType::Cardinal => 2,
             PrimitiveType::Boolean => 1,
             PrimitiveType::Char => 1,
+            PrimitiveType::Real => 2,
+        }
+    }
+
+    /// Smallest and largest value of the type; a Real's bounds are wider
+    /// than any integer it is compared with
+    pub fn range(&self) -> (i64, i64) {
+        match self {
+            PrimitiveType::Integer => (i16::MIN as i64, i16::MAX as i64),
+            PrimitiveType::Byte | PrimitiveType::Char => (0, u8::MAX as i64),
+            PrimitiveType::Word => (0, u16::MAX as i64),
+            PrimitiveType::LongInt => (i32::MIN as i64, i32::MAX as i64),
+            PrimitiveType::Cardinal => (0, u32::MAX as i64),
+            PrimitiveType::Boolean => (0, 1),
+            PrimitiveType::Real => (i64::MIN, i64::MAX),
+        }
+    }
+
+    /// Whether values of the type can be negative
+    pub fn is_signed(&self) -> bool {
+        matches!(self, PrimitiveType::Integer | PrimitiveType::LongInt | PrimitiveType::Real)
+    }
+
+    /// Whether the type is one of the 32-bit integers
+    pub fn is_32_bit_integer(&self) -> bool {
+        matches!(self, PrimitiveType::LongInt | PrimitiveType::Cardinal)
+    }
+}
+
+/// How two ordinal operands are compared once both are widened to the size
+/// of the larger. Byte and Char are zero-extended, so they compare like Word,
+/// and against Integer they always fit the signed range; so does Word
+/// against LongInt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComparisonKind {
+    /// Both operands unsigned (Byte, Word, Char, Boolean)
+    Unsigned,
+    /// Both operands fit the signed range (Integer with Integer or Byte)
+    Signed,
+    /// Signed left operand (Integer) against a Word, or LongInt against Cardinal
+    SignedUnsigned,
+    /// Word left operand against a signed right operand (Integer), or Cardinal against LongInt
+    UnsignedSigned,
+}
+
+impl ComparisonKind {
+    /// Promotion rule for comparing `left` with `right`
+    pub fn of(left: PrimitiveType, right: PrimitiveType) -> Self {
+        // An unsigned operand below the signed one's maximum compares as signed
+        let fits = |unsigned: PrimitiveType, signed: PrimitiveType| unsigned.range().1 <= signed.range().1;
+        match (left.is_signed(), right.is_signed()) {
+            (false, false) => ComparisonKind::Unsigned,
+            (true, true) => ComparisonKind::Signed,
+            (true, false) if fits(right, left) => ComparisonKind::Signed,
+            (false, true) if fits(left, right) => ComparisonKind::Signed,
+            (true, false) => ComparisonKind::SignedUnsigned,
+            (false, true) => ComparisonKind::UnsignedSigned,
         }
     }
 }
@@ -97,6 +218,282 @@ pub struct Field {
     pub offset: Option<usize>,
 }
 
+/// Variant part of a record: the fields of each variant, and the variant
+/// part nested at the end of it, if any
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RecordVariants {
+    pub variants: Vec<(Vec<Field>, Option<RecordVariants>)>,
+}
+
+impl RecordVariants {
+    /// Lay out every variant from `start`, append their fields to `fields`
+    /// and return the end of the largest
+    fn lay_out(self, fields: &mut Vec<Field>, start: usize, packed: bool) -> usize {
+        let mut end = start;
+        for (mut variant_fields, nested) in self.variants {
+            let mut variant_end = lay_out_fields(&mut variant_fields, start, packed);
+            fields.extend(variant_fields);
+            if let Some(nested) = nested {
+                variant_end = nested.lay_out(fields, variant_end, packed);
+            }
+            end = end.max(variant_end);
+        }
+        end
+    }
+}
+
+/// Give `fields` consecutive offsets from `offset`, each aligned to its
+/// type unless `packed`, and return the offset after the last
+fn lay_out_fields(fields: &mut [Field], mut offset: usize, packed: bool) -> usize {
+    for field in fields.iter_mut() {
+        // Align offset to field's alignment requirement
+        let align = if packed { 1 } else { field.field_type.alignment() };
+        offset = (offset + align - 1) / align * align;
+        field.offset = Some(offset);
+        offset += field.field_type.size().unwrap_or(0);
+    }
+    offset
+}
+
+/// Size of the VMT pointer at the start of every instance
+pub const VMT_POINTER_SIZE: usize = 2;
+
+/// What a method does to its instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MethodKind {
+    #[default]
+    Method,
+    /// Called on the class, allocates and initializes a new instance
+    Constructor,
+    /// Finalizes the instance, which is then freed
+    Destructor,
+}
+
+/// A method of a class, declared in it or inherited
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMethod {
+    pub name: String,
+    pub kind: MethodKind,
+    /// Class whose implementation a call reaches, the last to declare or
+    /// override the method
+    pub owner: String,
+    /// VMT slot of a virtual method; static methods have none
+    pub slot: Option<usize>,
+    /// Number of parameters, not counting Self
+    pub param_count: usize,
+    /// Result of a function method
+    pub return_type: Option<Type>,
+}
+
+/// Instance layout and virtual method table of a class
+///
+/// An instance starts with a pointer to its class's VMT, followed by the
+/// inherited fields and then its own. The VMT holds one routine address per
+/// virtual method: a class copies its parent's table, replaces the entries
+/// it overrides and appends those it declares `virtual`.
+///
+/// A class implementing interfaces has an interface method table (IMT) for
+/// each, one routine address per method of the interface, in slot order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassLayout {
+    pub name: String,
+    pub parent: Option<String>,
+    /// Inherited and declared fields, with their offsets in the instance
+    pub fields: Vec<Field>,
+    /// Instance size in bytes, VMT pointer included
+    pub size: usize,
+    /// Methods by name, inherited ones first
+    pub methods: Vec<ClassMethod>,
+    /// Routine symbol of each VMT slot
+    pub vmt: Vec<String>,
+    /// Interfaces implemented, inherited and base interfaces included, with
+    /// the routine symbol of each slot of their IMT
+    pub interfaces: Vec<(String, Vec<String>)>,
+}
+
+impl ClassLayout {
+    /// Layout of the class `name` deriving from `parent`, with the fields
+    /// it declares placed after the inherited ones
+    pub fn new(name: &str, parent: Option<&ClassLayout>, mut fields: Vec<Field>) -> Self {
+        let (mut all_fields, start, methods, vmt, interfaces) = match parent {
+            Some(parent) => (
+                parent.fields.clone(),
+                parent.size,
+                parent.methods.clone(),
+                parent.vmt.clone(),
+                parent.interfaces.clone(),
+            ),
+            None => (vec![], VMT_POINTER_SIZE, vec![], vec![], vec![]),
+        };
+        let end = lay_out_fields(&mut fields, start, false);
+        all_fields.extend(fields);
+        ClassLayout {
+            name: name.to_string(),
+            parent: parent.map(|p| p.name.clone()),
+            size: end,
+            fields: all_fields,
+            methods,
+            vmt,
+            interfaces,
+        }
+    }
+
+    /// Whether instances carry a VMT: the class has virtual methods or
+    /// implements interfaces, whose tables the VMT leads to
+    pub fn has_vmt(&self) -> bool {
+        !self.vmt.is_empty() || !self.interfaces.is_empty()
+    }
+
+    /// Whether the class implements the interface `name`
+    pub fn implements(&self, name: &str) -> bool {
+        self.interfaces.iter().any(|(interface, _)| interface.eq_ignore_ascii_case(name))
+    }
+
+    /// Find a field by name (case-insensitive)
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Find a method by name (case-insensitive)
+    pub fn method(&self, name: &str) -> Option<&ClassMethod> {
+        self.methods.iter().find(|m| m.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Add or replace the method `method` of this class: a virtual one gets
+    /// a new VMT slot, an override takes over the slot it inherits
+    pub fn declare_method(&mut self, mut method: ClassMethod) {
+        method.owner = self.name.clone();
+        if let Some(slot) = method.slot {
+            let symbol = method_symbol(&self.name, &method.name);
+            match self.vmt.get_mut(slot) {
+                Some(entry) => *entry = symbol,
+                None => self.vmt.push(symbol),
+            }
+        }
+        match self.methods.iter_mut().find(|m| m.name.eq_ignore_ascii_case(&method.name)) {
+            Some(inherited) => *inherited = method,
+            None => self.methods.push(method),
+        }
+    }
+
+    /// Implement `interface` with the methods of this class, replacing the
+    /// IMT an inherited implementation has. Fails with the name of the
+    /// first interface method the class lacks.
+    pub fn implement(&mut self, interface: &InterfaceLayout) -> Result<(), String> {
+        let imt = interface
+            .methods
+            .iter()
+            .map(|wanted| match self.method(&wanted.name) {
+                Some(method) if method.kind == MethodKind::Method => Ok(method_symbol(&method.owner, &method.name)),
+                _ => Err(wanted.name.clone()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match self.interfaces.iter_mut().find(|(name, _)| name.eq_ignore_ascii_case(&interface.name)) {
+            Some(inherited) => inherited.1 = imt,
+            None => self.interfaces.push((interface.name.clone(), imt)),
+        }
+        Ok(())
+    }
+}
+
+/// Methods of an interface
+///
+/// An interface reference is the instance itself. A call finds the IMT of
+/// the interface among those of the instance's class and calls the entry
+/// of the method's slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceLayout {
+    pub name: String,
+    /// Interfaces this one extends, directly or not
+    pub ancestors: Vec<String>,
+    /// Methods, inherited ones first, with their IMT slots; the owner is
+    /// the interface declaring the method
+    pub methods: Vec<ClassMethod>,
+}
+
+impl InterfaceLayout {
+    /// Layout of the interface `name` extending `bases`, with the methods
+    /// they declare; a method several bases declare takes the first's slot
+    pub fn new(name: &str, bases: &[&InterfaceLayout]) -> Self {
+        let mut layout = InterfaceLayout { name: name.to_string(), ancestors: vec![], methods: vec![] };
+        for base in bases {
+            for ancestor in std::iter::once(&base.name).chain(&base.ancestors) {
+                if !layout.extends(ancestor) {
+                    layout.ancestors.push(ancestor.clone());
+                }
+            }
+            for method in &base.methods {
+                if layout.method(&method.name).is_none() {
+                    layout.methods.push(ClassMethod { slot: Some(layout.methods.len()), ..method.clone() });
+                }
+            }
+        }
+        layout
+    }
+
+    /// Find a method by name (case-insensitive)
+    pub fn method(&self, name: &str) -> Option<&ClassMethod> {
+        self.methods.iter().find(|m| m.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Add the method `method`, declared by this interface, in the next slot
+    pub fn declare_method(&mut self, method: ClassMethod) {
+        let slot = Some(self.methods.len());
+        self.methods.push(ClassMethod { owner: self.name.clone(), slot, ..method });
+    }
+
+    /// Whether a reference to this interface is one to the interface `name`:
+    /// it is that interface or extends it
+    pub fn extends(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.ancestors.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Implicit first parameter of a method: the instance it was called on
+pub const SELF_PARAMETER: &str = "Self";
+
+/// Routine name of the method `method` implemented by `class`
+pub fn method_symbol(class: &str, method: &str) -> String {
+    format!("{}__{}", class, method)
+}
+
+/// Name of the instance of the generic type or routine `name` for the type
+/// arguments `args`, also its symbol: `TList_of_Integer`
+pub fn generic_instance_symbol(name: &str, args: &[String]) -> String {
+    format!("{}_of_{}", name, args.join("_"))
+}
+
+/// Data symbol of the VMT of `class`
+pub fn vmt_symbol(class: &str) -> String {
+    format!("__vmt_{}", class)
+}
+
+/// Data symbol of the one-byte descriptor identifying the interface `name`
+/// at run time
+pub fn interface_id_symbol(name: &str) -> String {
+    format!("__iid_{}", name)
+}
+
+/// Data symbol of the table of interfaces `class` implements
+pub fn interface_table_symbol(class: &str) -> String {
+    format!("__intf_{}", class)
+}
+
+/// Data symbol of the IMT of `interface` implemented by `class`
+pub fn imt_symbol(class: &str, interface: &str) -> String {
+    format!("__imt_{}_{}", class, interface)
+}
+
+/// Size of a record whose fields end at `end`, aligned to the record's
+/// alignment (the largest of its fields') unless `packed`
+fn record_size(fields: &[Field], end: usize, packed: bool) -> usize {
+    if packed {
+        return end;
+    }
+    let record_align = fields.iter().map(|f| f.field_type.alignment()).max().unwrap_or(1);
+    (end + record_align - 1) / record_align * record_align
+}
+
 impl Type {
     /// Create a primitive type
     pub fn primitive(prim: PrimitiveType) -> Self {
@@ -128,6 +525,19 @@ impl Type {
         Type::Primitive(PrimitiveType::Char)
     }
 
+    /// Create a real type
+    pub fn longint() -> Self {
+        Type::Primitive(PrimitiveType::LongInt)
+    }
+
+    pub fn cardinal() -> Self {
+        Type::Primitive(PrimitiveType::Cardinal)
+    }
+
+    pub fn real() -> Self {
+        Type::Primitive(PrimitiveType::Real)
+    }
+
     /// Create a variant type (dynamic typing)
     pub fn variant() -> Self {
         Type::Variant
@@ -164,11 +574,92 @@ impl Type {
         }
     }
 
+    /// Create a file type; `None` for an untyped file
+    pub fn file(element_type: Option<Type>) -> Self {
+        Type::File {
+            element_type: element_type.map(Box::new),
+        }
+    }
+
     /// Create a named type
     pub fn named(name: String) -> Self {
         Type::Named { name }
     }
 
+    /// Create a distinct type with the representation of `base`
+    pub fn distinct(name: String, base: Type) -> Self {
+        Type::Distinct {
+            name,
+            base: Box::new(base),
+        }
+    }
+
+    /// Create a subrange of the ordinal type `base`
+    pub fn subrange(base: Type, low: i64, high: i64) -> Self {
+        Type::Subrange {
+            base: Box::new(base),
+            low,
+            high,
+        }
+    }
+
+    /// Create a set of the ordinal type `element_type`
+    pub fn set(element_type: Type) -> Self {
+        Type::Set {
+            element_type: Box::new(element_type),
+        }
+    }
+
+    /// Create a string of up to `max_length` characters
+    pub fn string(max_length: usize) -> Self {
+        Type::String { max_length }
+    }
+
+    /// Check if values of this type can be used as strings: strings,
+    /// characters, and the arrays of char of string literals
+    pub fn is_string_like(&self) -> bool {
+        match self {
+            Type::String { .. } | Type::Primitive(PrimitiveType::Char) => true,
+            Type::Array { element_type, size: None, .. } => {
+                **element_type == Type::Primitive(PrimitiveType::Char)
+            }
+            _ => false,
+        }
+    }
+
+    /// Layout of a set type: the ordinal number held by bit 0 of the first
+    /// byte and the size in bytes. Each byte holds 8 elements, from the one
+    /// holding the smallest element to the one holding the largest, so
+    /// `set of 'a'..'z'` takes 4 bytes. None when the element type is not an
+    /// ordinal type within 0..255.
+    pub fn set_layout(&self) -> Option<(i64, usize)> {
+        let Type::Set { element_type } = self else {
+            return None;
+        };
+        let (low, high) = element_type.ordinal_bounds()?;
+        if low < 0 || high > MAX_SET_ORDINAL {
+            return None;
+        }
+        Some((low / 8 * 8, (high / 8 - low / 8 + 1) as usize))
+    }
+
+    /// The type a value is represented as: the base of a distinct type or
+    /// subrange
+    pub fn representation(&self) -> &Type {
+        match self {
+            Type::Distinct { base, .. } | Type::Subrange { base, .. } => base.representation(),
+            other => other,
+        }
+    }
+
+    /// The type values are computed in: the base of a subrange
+    pub fn subrange_base(&self) -> &Type {
+        match self {
+            Type::Subrange { base, .. } => base,
+            other => other,
+        }
+    }
+
     /// Check if two types are equal (structural equality)
     pub fn equals(&self, other: &Type) -> bool {
         match (self, other) {
@@ -208,10 +699,27 @@ impl Type {
                 Type::Pointer { base_type: b2 },
             ) => b1.equals(b2),
             (Type::Named { name: n1 }, Type::Named { name: n2 }) => n1 == n2,
+            (Type::Class { name: n1 }, Type::Class { name: n2 }) => n1 == n2,
+            (Type::Interface { name: n1 }, Type::Interface { name: n2 }) => n1 == n2,
             (Type::Generic { name: n1, .. }, Type::Generic { name: n2, .. }) => n1 == n2,
             (Type::Instantiated { generic_name: n1, args: a1 }, Type::Instantiated { generic_name: n2, args: a2 }) => {
                 n1 == n2 && a1.len() == a2.len() && a1.iter().zip(a2.iter()).all(|(t1, t2)| t1.equals(t2))
             },
+            (Type::Enum { values: v1 }, Type::Enum { values: v2 }) => v1 == v2,
+            (Type::Distinct { name: n1, .. }, Type::Distinct { name: n2, .. }) => n1 == n2,
+            (
+                Type::Subrange { base: b1, low: l1, high: h1 },
+                Type::Subrange { base: b2, low: l2, high: h2 },
+            ) => b1.equals(b2) && l1 == l2 && h1 == h2,
+            (Type::UntypedPointer, Type::UntypedPointer) => true,
+            (Type::Untyped, Type::Untyped) => true,
+            (Type::File { element_type: e1 }, Type::File { element_type: e2 }) => match (e1, e2) {
+                (Some(e1), Some(e2)) => e1.equals(e2),
+                (None, None) => true,
+                _ => false,
+            },
+            (Type::Set { element_type: e1 }, Type::Set { element_type: e2 }) => e1.equals(e2),
+            (Type::String { max_length: m1 }, Type::String { max_length: m2 }) => m1 == m2,
             (Type::Variant, Type::Variant) => true,
             (Type::Error, Type::Error) => true,
             _ => false,
@@ -226,6 +734,15 @@ impl Type {
             return true;
         }
 
+        // A subrange is compatible with its base type; values assigned to a
+        // subrange are checked against its bounds, at run time under {$R+}
+        if let Type::Subrange { base, .. } = self {
+            return base.is_assignable_to(target);
+        }
+        if let Type::Subrange { base, .. } = target {
+            return self.is_assignable_to(base);
+        }
+
         // Integer/Byte/Word compatibility (Tier 1: simple rules)
         match (self, target) {
             // Integer can be assigned to Integer
@@ -242,6 +759,21 @@ impl Type {
                 Type::Primitive(PrimitiveType::Word),
                 Type::Primitive(PrimitiveType::Integer),
             ) => true,
+            // Narrower integers widen to the 32-bit ones; Cardinal can be
+            // assigned to LongInt as Word can to Integer
+            (
+                Type::Primitive(PrimitiveType::Integer | PrimitiveType::Byte | PrimitiveType::Word | PrimitiveType::Cardinal),
+                Type::Primitive(PrimitiveType::LongInt),
+            ) => true,
+            (
+                Type::Primitive(PrimitiveType::Byte | PrimitiveType::Word),
+                Type::Primitive(PrimitiveType::Cardinal),
+            ) => true,
+            // Integers can be assigned to Real
+            (
+                Type::Primitive(PrimitiveType::Integer | PrimitiveType::Byte | PrimitiveType::Word),
+                Type::Primitive(PrimitiveType::Real),
+            ) => true,
             // Char can be assigned to Byte
             (
                 Type::Primitive(PrimitiveType::Char),
@@ -251,6 +783,31 @@ impl Type {
             (Type::Primitive(PrimitiveType::Boolean), Type::Primitive(PrimitiveType::Boolean)) => {
                 true
             }
+            // Sets of the same ordinal type, including integer types of any
+            // size, since a set holds the ordinal numbers; `[]` fits any set
+            (Type::Set { element_type: e1 }, Type::Set { element_type: e2 }) => {
+                let integer = |t: &Type| {
+                    matches!(
+                        t,
+                        Type::Primitive(
+                            PrimitiveType::Integer
+                                | PrimitiveType::Byte
+                                | PrimitiveType::Word
+                                | PrimitiveType::LongInt
+                                | PrimitiveType::Cardinal
+                        )
+                    )
+                };
+                let (e1, e2) = (e1.subrange_base(), e2.subrange_base());
+                e1.equals(e2) || (integer(e1) && integer(e2)) || *e1 == Type::Error || *e2 == Type::Error
+            }
+            // Strings of any length, characters and string literals can be
+            // assigned to a string; a longer value is truncated
+            (source, Type::String { .. }) if source.is_string_like() => true,
+            // Pointer converts to and from any typed pointer
+            (Type::Pointer { .. }, Type::UntypedPointer) | (Type::UntypedPointer, Type::Pointer { .. }) => true,
+            // Untyped parameters have no value to assign, not even to a Variant
+            (Type::Untyped, Type::Variant) | (Type::Variant, Type::Untyped) => false,
             // Variant can accept any type (runtime type checking)
             (_, Type::Variant) => true,
             // Variant can be assigned to any type (runtime type checking required)
@@ -261,6 +818,27 @@ impl Type {
         }
     }
 
+    /// Check if a type is ordinal (usable as a FOR control variable or CASE selector)
+    pub fn is_ordinal(&self) -> bool {
+        match self.representation() {
+            Type::Primitive(prim) => *prim != PrimitiveType::Real,
+            Type::Enum { .. } | Type::Error => true,
+            _ => false,
+        }
+    }
+
+    /// Smallest and largest ordinal number of an ordinal type, or None for
+    /// other types
+    pub fn ordinal_bounds(&self) -> Option<(i64, i64)> {
+        match self {
+            Type::Subrange { low, high, .. } => Some((*low, *high)),
+            Type::Distinct { base, .. } => base.ordinal_bounds(),
+            Type::Enum { values } => Some((0, values.len() as i64 - 1)),
+            Type::Primitive(prim) if *prim != PrimitiveType::Real => Some(prim.range()),
+            _ => None,
+        }
+    }
+
     /// Calculate the size of a type in bytes
     /// Returns None if size cannot be determined (e.g., open arrays, incomplete types)
     pub fn size(&self) -> Option<usize> {
@@ -269,11 +847,18 @@ impl Type {
             Type::Array { size, .. } => *size,
             Type::DynamicArray { .. } => None, // Dynamic arrays have no fixed size
             Type::Record { size, .. } => *size,
-            Type::Pointer { .. } => Some(2), // Pointers are 16-bit (2 bytes) on 8-bit/16-bit targets
+            Type::Pointer { .. } | Type::UntypedPointer => Some(2), // Pointers are 16-bit (2 bytes) on 8-bit/16-bit targets
+            Type::Class { .. } | Type::Interface { .. } => Some(2), // A reference to the instance
             Type::Named { .. } => None, // Need to resolve named type first
             Type::Generic { .. } => None, // Generic templates have no size until instantiated
             Type::Instantiated { .. } => None, // Need to resolve instantiated type first
+            Type::Enum { values } => Some(if values.len() <= 256 { 1 } else { 2 }),
+            Type::Distinct { base, .. } | Type::Subrange { base, .. } => base.size(),
+            Type::Set { .. } => self.set_layout().map(|(_, size)| size),
+            Type::String { max_length } => Some(max_length + 1),
             Type::Variant => None, // Variant size depends on runtime value
+            Type::Untyped => None, // Only the address is passed
+            Type::File { .. } => Some(6), // Handle, name length, record size, name pointer
             Type::Error => None,
         }
     }
@@ -284,19 +869,24 @@ impl Type {
             Type::Primitive(prim) => prim.alignment(),
             Type::Array { element_type, .. } => element_type.alignment(),
             Type::DynamicArray { element_type } => element_type.alignment(),
-            Type::Record { fields, .. } => {
-                // Record alignment is the maximum alignment of its fields
-                fields
-                    .iter()
-                    .map(|f| f.field_type.alignment())
-                    .max()
-                    .unwrap_or(1)
+            Type::Record { fields, size } => {
+                // Record alignment is the maximum alignment of its fields,
+                // unless it is packed with a field or its size off that
+                let align = fields.iter().map(|f| f.field_type.alignment()).max().unwrap_or(1);
+                let packed = fields.iter().any(|f| f.offset.is_some_and(|offset| offset % f.field_type.alignment() != 0))
+                    || size.is_some_and(|size| size % align != 0);
+                if packed { 1 } else { align }
             }
-            Type::Pointer { .. } => 2, // Pointers are 16-bit aligned
+            Type::Pointer { .. } | Type::UntypedPointer | Type::Class { .. } | Type::Interface { .. } => 2, // Pointers are 16-bit aligned
             Type::Named { .. } => 1, // Unknown, use minimum
             Type::Generic { .. } => 1, // Unknown until instantiated
             Type::Instantiated { .. } => 1, // Unknown until resolved
+            Type::Enum { .. } => 1,
+            Type::Distinct { base, .. } | Type::Subrange { base, .. } => base.alignment(),
+            Type::Set { .. } | Type::String { .. } => 1,
             Type::Variant => 1, // Variant alignment (runtime-dependent)
+            Type::Untyped => 1,
+            Type::File { .. } => 2,
             Type::Error => 1,
         }
     }
@@ -304,40 +894,53 @@ impl Type {
     /// Calculate record field offsets
     /// This should be called during semantic analysis after all fields are known
     pub fn calculate_record_offsets(&mut self) {
+        self.lay_out_record(false);
+    }
+
+    /// Calculate the field offsets of a packed record (`packed record`, or
+    /// any record under {$ALIGN 1}): each field follows the last with no
+    /// padding, and the record is not padded to a word
+    pub fn calculate_packed_record_offsets(&mut self) {
+        self.lay_out_record(true);
+    }
+
+    fn lay_out_record(&mut self, packed: bool) {
         if let Type::Record { fields, size } = self {
-            let mut offset = 0;
-            for field in fields.iter_mut() {
-                // Align offset to field's alignment requirement
-                let align = field.field_type.alignment();
-                offset = (offset + align - 1) / align * align;
-                field.offset = Some(offset);
-                offset += field.field_type.size().unwrap_or(0);
-            }
-            // Align total size to record's alignment
-            // Calculate alignment from fields (max of field alignments)
-            let record_align = fields
-                .iter()
-                .map(|f| f.field_type.alignment())
-                .max()
-                .unwrap_or(1);
-            let total_size = (offset + record_align - 1) / record_align * record_align;
-            *size = Some(total_size);
+            let end = lay_out_fields(fields, 0, packed);
+            *size = Some(record_size(fields, end, packed));
         }
     }
 
+    /// Create a record type with a variant part after its fixed fields (and
+    /// tag field), with offsets and size calculated. Each variant starts
+    /// where the fixed fields end, so the variants overlap and the record is
+    /// as large as its largest variant.
+    pub fn variant_record(mut fields: Vec<Field>, variants: RecordVariants, packed: bool) -> Self {
+        let end = lay_out_fields(&mut fields, 0, packed);
+        let end = variants.lay_out(&mut fields, end, packed);
+        let size = record_size(&fields, end, packed);
+        Type::Record { fields, size: Some(size) }
+    }
+
     /// Calculate array size
-    /// For now, we assume fixed-size arrays (Tier 1)
+    /// The index type must be a subrange, an enumeration or a one-byte
+    /// ordinal; arrays indexed by a whole Integer or Word have no size
     /// Returns None if size cannot be determined
     pub fn calculate_array_size(&mut self) -> Option<usize> {
         if let Type::Array {
-            index_type: _,
-            element_type: _,
+            index_type,
+            element_type,
             size,
         } = self
         {
-            // For Tier 1, we assume arrays are indexed by integer/byte/word
-            // Size calculation requires knowing the array bounds, which we'll add later
-            // For now, return None (will be calculated during semantic analysis)
+            let bounded = matches!(index_type.as_ref(), Type::Subrange { .. })
+                || !matches!(index_type.representation(), Type::Primitive(prim) if prim.size() > 1);
+            *size = index_type
+                .ordinal_bounds()
+                .filter(|_| bounded)
+                .and_then(|(low, high)| usize::try_from(high - low + 1).ok())
+                .zip(element_type.size())
+                .and_then(|(count, element_size)| count.checked_mul(element_size));
             *size
         } else {
             None
@@ -358,6 +961,9 @@ mod tests {
         assert_eq!(PrimitiveType::Word.size(), 2);
         assert_eq!(PrimitiveType::Boolean.size(), 1);
         assert_eq!(PrimitiveType::Char.size(), 1);
+        assert_eq!(PrimitiveType::Real.size(), 4);
+        assert_eq!(PrimitiveType::LongInt.size(), 4);
+        assert_eq!(PrimitiveType::Cardinal.size(), 4);
     }
 
     #[test]
@@ -369,6 +975,97 @@ mod tests {
         assert_eq!(PrimitiveType::Char.alignment(), 1);
     }
 
+    #[test]
+    fn test_comparison_kind_promotion() {
+        use PrimitiveType::*;
+        assert_eq!(ComparisonKind::of(Byte, Word), ComparisonKind::Unsigned);
+        assert_eq!(ComparisonKind::of(Integer, Integer), ComparisonKind::Signed);
+        assert_eq!(ComparisonKind::of(Integer, Byte), ComparisonKind::Signed);
+        assert_eq!(ComparisonKind::of(Byte, Integer), ComparisonKind::Signed);
+        assert_eq!(ComparisonKind::of(Integer, Word), ComparisonKind::SignedUnsigned);
+        assert_eq!(ComparisonKind::of(Word, Integer), ComparisonKind::UnsignedSigned);
+        assert_eq!(ComparisonKind::of(LongInt, Word), ComparisonKind::Signed);
+        assert_eq!(ComparisonKind::of(Cardinal, LongInt), ComparisonKind::UnsignedSigned);
+        assert_eq!(ComparisonKind::of(Integer, Cardinal), ComparisonKind::SignedUnsigned);
+    }
+
+    #[test]
+    fn test_is_ordinal() {
+        assert!(Type::integer().is_ordinal());
+        assert!(Type::word().is_ordinal());
+        assert!(Type::char().is_ordinal());
+        assert!(Type::boolean().is_ordinal());
+        assert!(!Type::real().is_ordinal());
+        assert!(Type::Enum { values: vec!["Red".to_string()] }.is_ordinal());
+        assert!(!Type::pointer(Type::byte()).is_ordinal());
+        assert!(!Type::array(Type::integer(), Type::byte()).is_ordinal());
+    }
+
+    #[test]
+    fn test_subrange_types() {
+        let digit = Type::subrange(Type::integer(), 0, 9);
+        assert!(digit.is_ordinal());
+        assert_eq!(digit.representation(), &Type::integer());
+        assert_eq!(digit.size(), Some(2));
+        assert_eq!(digit.ordinal_bounds(), Some((0, 9)));
+        assert!(digit.equals(&Type::subrange(Type::integer(), 0, 9)));
+        assert!(!digit.equals(&Type::subrange(Type::integer(), 1, 9)));
+        // Compatible with the base type and its other subranges both ways
+        assert!(digit.is_assignable_to(&Type::integer()));
+        assert!(Type::byte().is_assignable_to(&digit));
+        assert!(Type::subrange(Type::integer(), 5, 20).is_assignable_to(&digit));
+        assert!(!Type::char().is_assignable_to(&digit));
+        assert!(!Type::subrange(Type::char(), 97, 122).is_assignable_to(&digit));
+    }
+
+    #[test]
+    fn test_set_types() {
+        let letters = Type::set(Type::subrange(Type::char(), 97, 122));
+        // 'a' is bit 1 of the byte holding 96..103, 'z' bit 2 of the fourth
+        assert_eq!(letters.set_layout(), Some((96, 4)));
+        assert_eq!(letters.size(), Some(4));
+        assert_eq!(Type::set(Type::char()).size(), Some(32));
+        let color = Type::Enum { values: vec!["Red".to_string(), "Green".to_string()] };
+        assert_eq!(Type::set(color.clone()).size(), Some(1));
+        assert_eq!(Type::set(Type::integer()).set_layout(), None);
+        assert_eq!(Type::set(Type::subrange(Type::integer(), 250, 256)).size(), None);
+        // Sets of integer subranges mix; the empty set fits any set
+        let digits = Type::set(Type::subrange(Type::integer(), 0, 9));
+        assert!(Type::set(Type::byte()).is_assignable_to(&digits));
+        assert!(Type::set(Type::Error).is_assignable_to(&letters));
+        assert!(!letters.is_assignable_to(&digits));
+        assert!(!Type::set(color).is_assignable_to(&digits));
+    }
+
+    #[test]
+    fn test_string_types() {
+        let name = Type::string(20);
+        assert_eq!(name.size(), Some(21));
+        assert_eq!(Type::string(MAX_STRING_LENGTH).size(), Some(256));
+        assert_eq!(name.alignment(), 1);
+        // Strings of any length, chars and literals assign to a string
+        assert!(Type::string(MAX_STRING_LENGTH).is_assignable_to(&name));
+        assert!(Type::char().is_assignable_to(&name));
+        assert!(Type::array(Type::integer(), Type::char()).is_assignable_to(&name));
+        assert!(!name.equals(&Type::string(10)));
+        assert!(!Type::integer().is_assignable_to(&name));
+        assert!(!name.is_assignable_to(&Type::char()));
+        let mut fixed = Type::array(Type::subrange(Type::integer(), 1, 4), Type::char());
+        fixed.calculate_array_size();
+        assert!(!fixed.is_assignable_to(&name));
+    }
+
+    #[test]
+    fn test_array_size_from_index_bounds() {
+        let size = |index: Type, element: Type| Type::array(index, element).calculate_array_size();
+        assert_eq!(size(Type::subrange(Type::integer(), 1, 10), Type::integer()), Some(20));
+        assert_eq!(size(Type::char(), Type::byte()), Some(256));
+        let color = Type::Enum { values: vec!["Red".to_string(), "Green".to_string()] };
+        assert_eq!(size(color, Type::longint()), Some(8));
+        assert_eq!(size(Type::word(), Type::byte()), None);
+        assert_eq!(size(Type::named("TIndex".to_string()), Type::byte()), None);
+    }
+
     #[test]
     fn test_type_creation() {
         assert_eq!(Type::integer(), Type::Primitive(PrimitiveType::Integer));
@@ -469,6 +1166,22 @@ mod tests {
         assert!(Type::char().is_assignable_to(&Type::byte()));
     }
 
+    #[test]
+    fn test_assignment_compatibility_integer_to_real() {
+        assert!(Type::integer().is_assignable_to(&Type::real()));
+        assert!(Type::byte().is_assignable_to(&Type::real()));
+        assert!(!Type::real().is_assignable_to(&Type::integer()));
+    }
+
+    #[test]
+    fn test_assignment_compatibility_32_bit_integers() {
+        assert!(Type::integer().is_assignable_to(&Type::longint()));
+        assert!(Type::word().is_assignable_to(&Type::cardinal()));
+        assert!(Type::cardinal().is_assignable_to(&Type::longint()));
+        assert!(!Type::integer().is_assignable_to(&Type::cardinal()));
+        assert!(!Type::longint().is_assignable_to(&Type::integer()));
+    }
+
     #[test]
     fn test_assignment_compatibility_boolean() {
         assert!(Type::boolean().is_assignable_to(&Type::boolean()));
@@ -576,6 +1289,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_packed_record_field_offsets() {
+        let field = |name: &str, field_type: Type| Field { name: name.to_string(), field_type: Box::new(field_type), offset: None };
+        let mut rec = Type::record(vec![field("a", Type::byte()), field("b", Type::integer()), field("c", Type::byte())]);
+        rec.calculate_packed_record_offsets();
+        let Type::Record { fields, size } = &rec else { panic!("expected a record") };
+        let offsets: Vec<_> = fields.iter().map(|f| f.offset).collect();
+        assert_eq!(offsets, [Some(0), Some(1), Some(3)]);
+        assert_eq!(*size, Some(4));
+        // Placed at any offset in another record
+        assert_eq!(rec.alignment(), 1);
+
+        // Laid out as it would be unpacked, it keeps the word alignment
+        let mut rec = Type::record(vec![field("a", Type::integer()), field("b", Type::byte()), field("c", Type::byte())]);
+        rec.calculate_packed_record_offsets();
+        assert_eq!(rec.alignment(), 2);
+    }
+
     // ===== Additional Assignment Compatibility Tests =====
 
     #[test]
@@ -764,6 +1495,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_variant_record_overlaps_variants() {
+        let field = |name: &str, field_type: Type| Field {
+            name: name.to_string(),
+            field_type: Box::new(field_type),
+            offset: None,
+        };
+        // Kind: byte; case byte of 0: (W: word); 1: (Lo, Hi: byte; case byte of 0: (L: longint))
+        let nested = RecordVariants { variants: vec![(vec![field("L", Type::longint())], None)] };
+        let rec = Type::variant_record(
+            vec![field("Kind", Type::byte())],
+            RecordVariants {
+                variants: vec![
+                    (vec![field("W", Type::word())], None),
+                    (vec![field("Lo", Type::byte()), field("Hi", Type::byte())], Some(nested)),
+                ],
+            },
+            false,
+        );
+        let Type::Record { fields, size } = rec else { panic!("expected a record") };
+        let offsets: Vec<_> = fields.iter().map(|f| (f.name.as_str(), f.offset.unwrap())).collect();
+        assert_eq!(offsets, vec![("Kind", 0), ("W", 2), ("Lo", 1), ("Hi", 2), ("L", 4)]);
+        assert_eq!(size, Some(8));
+    }
+
     // ===== Type Size Edge Cases =====
 
     #[test]
@@ -963,6 +1719,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_untyped_pointer_compatibility() {
+        let typed = Type::pointer(Type::integer());
+        assert!(typed.is_assignable_to(&Type::UntypedPointer));
+        assert!(Type::UntypedPointer.is_assignable_to(&typed));
+        assert!(!typed.is_assignable_to(&Type::pointer(Type::char())));
+        assert!(!Type::word().is_assignable_to(&Type::UntypedPointer));
+        assert_eq!(Type::UntypedPointer.size(), Some(2));
+    }
+
+    #[test]
+    fn test_file_types() {
+        let untyped = Type::file(None);
+        let words = Type::file(Some(Type::word()));
+        assert!(untyped.equals(&Type::file(None)));
+        assert!(words.equals(&Type::file(Some(Type::word()))));
+        assert!(!words.is_assignable_to(&untyped));
+        assert!(!words.is_assignable_to(&Type::file(Some(Type::byte()))));
+        assert_eq!(untyped.size(), Some(6));
+    }
+
+    #[test]
+    fn test_interface_methods_and_implementation() {
+        let method = |name: &str| ClassMethod {
+            name: name.to_string(),
+            kind: MethodKind::Method,
+            owner: String::new(),
+            slot: None,
+            param_count: 0,
+            return_type: None,
+        };
+        let mut drawable = InterfaceLayout::new("IDrawable", &[]);
+        drawable.declare_method(method("Draw"));
+        let mut shape = InterfaceLayout::new("IShape", &[&drawable]);
+        shape.declare_method(method("Area"));
+        assert_eq!(shape.method("area").and_then(|m| m.slot), Some(1));
+        assert_eq!(shape.method("Draw").map(|m| m.owner.as_str()), Some("IDrawable"));
+        assert!(shape.extends("IDrawable") && !drawable.extends("IShape"));
+
+        let mut square = ClassLayout::new("TSquare", None, vec![]);
+        assert_eq!(square.implement(&shape), Err("Draw".to_string()));
+        square.declare_method(method("Area"));
+        square.declare_method(method("Draw"));
+        square.implement(&shape).unwrap();
+        assert_eq!(square.interfaces, [("IShape".to_string(), vec!["TSquare__Draw".to_string(), "TSquare__Area".to_string()])]);
+        assert!(square.has_vmt() && square.vmt.is_empty());
+
+        // A subclass implements the interface with its own methods
+        let mut big = ClassLayout::new("TBig", Some(&square), vec![]);
+        big.declare_method(method("Draw"));
+        big.implement(&shape).unwrap();
+        assert_eq!(big.interfaces[0].1, ["TBig__Draw", "TSquare__Area"]);
+    }
+
     #[test]
     fn test_type_named_helper() {
         let named = Type::named("MyInt".to_string());
@@ -0,0 +1,69 @@
+unit Audio_AYPlayer;
+
+interface
+
+uses
+  Audio_Types;
+
+// AY-3-8910 music player
+// Wraps the PT3 replay routine linked from the runtime (pt3play.zof).
+// Call MusicTick once per frame (50 Hz) from the main loop or an IM2 handler.
+
+// Start playing a music module
+// Parameters:
+//   Song: Descriptor generated by {$RESOURCE ... AS Song}
+// Returns: False if the module format is not supported by the player
+function MusicPlay(const Song: TMusicData): Boolean;
+
+// Advance playback by one frame
+procedure MusicTick;
+
+// Stop playback and silence all AY channels
+procedure MusicStop;
+
+// Check whether a module is currently playing
+function MusicIsPlaying: Boolean;
+
+implementation
+
+// PT3 replay routine entry points (Z80 assembly, runtime library)
+procedure PT3_Init(Module: Word); external 'pt3_init';
+procedure PT3_Play; external 'pt3_play';
+procedure PT3_Mute; external 'pt3_mute';
+
+var
+  Playing: Boolean;
+
+function MusicPlay(const Song: TMusicData): Boolean;
+begin
+  MusicStop;
+  if Song.Format = MUSIC_FORMAT_PT3 then
+  begin
+    PT3_Init(Song.Data);
+    Playing := True;
+  end;
+  // AY containers embed their own player and are started by the host
+  MusicPlay := Playing;
+end;
+
+procedure MusicTick;
+begin
+  if Playing then
+    PT3_Play;
+end;
+
+procedure MusicStop;
+begin
+  if Playing then
+    PT3_Mute;
+  Playing := False;
+end;
+
+function MusicIsPlaying: Boolean;
+begin
+  MusicIsPlaying := Playing;
+end;
+
+initialization
+  Playing := False;
+end.
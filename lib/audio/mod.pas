@@ -0,0 +1,27 @@
+unit Audio;
+
+interface
+
+uses
+  Audio_Types,
+  Audio_AYPlayer;
+
+// Re-export types
+type
+  TMusicData = Audio_Types.TMusicData;
+  PMusicData = Audio_Types.PMusicData;
+
+// Re-export constants
+const
+  MUSIC_FORMAT_PT3 = Audio_Types.MUSIC_FORMAT_PT3;
+  MUSIC_FORMAT_AY = Audio_Types.MUSIC_FORMAT_AY;
+
+// Functions are available through imported units:
+// - Audio_AYPlayer.MusicPlay
+// - Audio_AYPlayer.MusicTick
+// - Audio_AYPlayer.MusicStop
+// - Audio_AYPlayer.MusicIsPlaying
+
+implementation
+
+end.
@@ -0,0 +1,23 @@
+unit Audio_Types;
+
+interface
+
+type
+  // Music module descriptor
+  // Generated by the compiler for {$RESOURCE 'song.pt3' AS MySong};
+  // the layout must match MUSIC_DATA_DECLARATIONS in the resources crate.
+  TMusicData = record
+    Data: Word;    // Address of the embedded module
+    Size: Word;    // Module size in bytes
+    Format: Byte;  // MUSIC_FORMAT_PT3 or MUSIC_FORMAT_AY
+  end;
+  PMusicData = ^TMusicData;
+
+const
+  // Music module formats
+  MUSIC_FORMAT_PT3 = 1;  // ProTracker 3 / Vortex Tracker II
+  MUSIC_FORMAT_AY = 2;   // ZXAYEMUL container
+
+implementation
+
+end.
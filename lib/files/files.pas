@@ -0,0 +1,63 @@
+{$IFNDEF CPU_Z80}
+  {$ERROR Files is only available for Z80 targets}
+{$ENDIF}
+unit Files;
+
+interface
+
+// Binary file I/O
+// Files are read and written in records of the size given to Reset or
+// Rewrite. Failures do not stop the program: check IOResult after each call.
+// Zeal OS files go through the kernel; on the ZX Spectrum through esxDOS
+// (DivMMC/DivIDE). Sizes and positions are limited to 64 KB.
+
+// Set the name of a closed file
+// Parameters:
+//   Name: Character array holding the name, e.g. 'LEVEL1.DAT'
+//   Length: Number of characters in Name
+procedure Assign(var F: File; const Name; Length: Byte);
+
+// Open an existing file for reading and writing
+// Parameters:
+//   RecSize: Bytes per record, 1 to read and write byte counts
+procedure Reset(var F: File; RecSize: Word);
+
+// Create a file, or truncate an existing one, for reading and writing
+procedure Rewrite(var F: File; RecSize: Word);
+
+// Close an open file
+procedure Close(var F: File);
+
+// Read up to Count records into Buf
+// Parameters:
+//   Done: Records actually read; less than Count at the end of the file
+procedure BlockRead(var F: File; var Buf; Count: Word; var Done: Word);
+
+// Write Count records from Buf
+// Parameters:
+//   Done: Records actually written; less than Count when the disk is full
+procedure BlockWrite(var F: File; const Buf; Count: Word; var Done: Word);
+
+// Move to record RecNo (0 is the first record)
+procedure Seek(var F: File; RecNo: Word);
+
+// Number of whole records in the file
+function FileSize(var F: File): Word;
+
+// Error code of the last failed call (0 if none); reading it clears it
+function IOResult: Word;
+
+implementation
+
+// Generated by the compiler
+procedure Assign(var F: File; const Name; Length: Byte); external;
+procedure Reset(var F: File; RecSize: Word); external;
+procedure Rewrite(var F: File; RecSize: Word); external;
+procedure Close(var F: File); external;
+procedure BlockRead(var F: File; var Buf; Count: Word; var Done: Word); external;
+procedure BlockWrite(var F: File; const Buf; Count: Word; var Done: Word); external;
+procedure Seek(var F: File; RecNo: Word); external;
+function FileSize(var F: File): Word; external;
+function IOResult: Word; external;
+
+end.
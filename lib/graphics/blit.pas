@@ -0,0 +1,43 @@
+unit Graphics_Blit;
+
+interface
+
+// Sprite blitting
+// The bodies of these routines are generated by the compiler for the
+// current target: unrolled LDI copies by default, LDIR loops with -Os.
+// Any external procedure named Blit<W>x<H> (declared in an implementation
+// section or program) gets a generated body, so user code can add sizes.
+//
+// Sizes are in bytes; Dest is an address in the target framebuffer
+// (320 bytes per row on ZealZ80, a 32 bytes per row shadow buffer on
+// ZX Spectrum).
+//
+// Image resources ({$RESOURCE 'ship.png' AS Ship}) get their own
+// generated Ship_Draw(Dest) routine, compiled to PUSH writes when
+// optimizing for speed.
+
+// Copy an 8x8 sprite from Src to Dest
+procedure Blit8x8(Src, Dest: Word);
+
+// Copy a 16x16 sprite from Src to Dest
+procedure Blit16x16(Src, Dest: Word);
+
+// Copy a 32x32 sprite from Src to Dest
+procedure Blit32x32(Src, Dest: Word);
+
+// Framebuffer address of pixel (X, Y)
+function BlitAddress(Base: Word; X, Y: Integer; Stride: Word): Word;
+
+implementation
+
+// Generated by the compiler
+procedure Blit8x8(Src, Dest: Word); external;
+procedure Blit16x16(Src, Dest: Word); external;
+procedure Blit32x32(Src, Dest: Word); external;
+
+function BlitAddress(Base: Word; X, Y: Integer; Stride: Word): Word;
+begin
+  BlitAddress := Base + Y * Stride + X;
+end;
+
+end.
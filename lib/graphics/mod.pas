@@ -13,6 +13,7 @@ uses
   Graphics_Ellipse,
   Graphics_Texture,
   Graphics_Tilemap,
+  Graphics_Blit,
   Math_Types;  // For Fixed16
 
 // Re-export types
@@ -76,6 +77,12 @@ procedure RenderTilemap(
   PlotPixel: TPlotPixelProc
 );
 
+// Re-export blit functions (bodies generated by the compiler)
+procedure Blit8x8(Src, Dest: Word);
+procedure Blit16x16(Src, Dest: Word);
+procedure Blit32x32(Src, Dest: Word);
+function BlitAddress(Base: Word; X, Y: Integer; Stride: Word): Word;
+
 implementation
 
 end.
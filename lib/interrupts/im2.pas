@@ -0,0 +1,24 @@
+{$IFNDEF CPU_Z80}
+  {$ERROR Interrupts_IM2 is only available for Z80 targets}
+{$ENDIF}
+unit Interrupts_IM2;
+
+interface
+
+// Z80 interrupt mode 2 handlers
+// Requires compiling with --interrupt-mode im2. The compiler then generates
+// the 257-byte vector table, the service routine that saves all registers,
+// and the startup code (__im2_install) that loads I and enables IM2.
+
+// Install a parameterless procedure as the interrupt handler
+// Parameters:
+//   Handler: Address of the handler, e.g. SetIM2Handler(@OnFrame)
+// Note: Re-enables interrupts on return
+procedure SetIM2Handler(Handler: Word);
+
+implementation
+
+// Generated by the compiler
+procedure SetIM2Handler(Handler: Word); external;
+
+end.
@@ -0,0 +1,35 @@
+{$IFNDEF CPU_Z80}
+  {$ERROR Tasks is only available for Z80 targets}
+{$ENDIF}
+unit Tasks;
+
+interface
+
+// Cooperative tasks
+// Each task runs on its own stack and gives up the CPU by calling Yield.
+// The compiler generates the scheduler and sizes the stack pool from the
+// TaskStackSize(Entry) uses in the program: one stack per use, each large
+// enough for the deepest call chain of Entry (recursive entries are an error).
+// Threadvars are stored per task and start out zeroed in new tasks.
+
+// Create a task that starts at the next Yield
+// Parameters:
+//   Entry: Address of a parameterless procedure, e.g. @UpdateEnemies
+//   StackSize: Stack to reserve, normally TaskStackSize(UpdateEnemies)
+// Note: A task whose procedure returns keeps yielding in StartTasks
+procedure CreateTask(Entry, StackSize: Word);
+
+// Switch to the next task
+procedure Yield;
+
+// Run the created tasks forever; the main program becomes idle
+procedure StartTasks;
+
+implementation
+
+// Generated by the compiler
+procedure CreateTask(Entry, StackSize: Word); external;
+procedure Yield; external;
+procedure StartTasks; external;
+
+end.
@@ -0,0 +1,126 @@
+{$IFNDEF ZXSPECTRUM}
+  {$ERROR ZXScreen is only available for the ZX Spectrum target (spc --target zxspectrum)}
+{$ENDIF}
+unit ZXScreen;
+
+interface
+
+const
+  // Screen memory layout
+  SCREEN_PIXELS_ADDR = $4000;  // Bitmap (interleaved thirds)
+  SCREEN_PIXELS_SIZE = 6144;   // 256x192 pixels, 1 bit per pixel
+  SCREEN_ATTRS_ADDR = $5800;   // Colour attributes
+  SCREEN_ATTRS_SIZE = 768;     // 32x24 character cells
+  SCREEN_COLUMNS = 32;
+  SCREEN_ROWS = 24;
+
+  // Colours (ink and paper)
+  ZX_BLACK = 0;
+  ZX_BLUE = 1;
+  ZX_RED = 2;
+  ZX_MAGENTA = 3;
+  ZX_GREEN = 4;
+  ZX_CYAN = 5;
+  ZX_YELLOW = 6;
+  ZX_WHITE = 7;
+
+  // Attribute flags
+  ATTR_BRIGHT = $40;
+  ATTR_FLASH = $80;
+
+  // ULA port (border colour in bits 0-2)
+  ULA_PORT = $FE;
+
+type
+  TScreenPixels = array[0..SCREEN_PIXELS_SIZE - 1] of Byte;
+  TScreenAttrs = array[0..SCREEN_ROWS - 1, 0..SCREEN_COLUMNS - 1] of Byte;
+
+var
+  // Typed views over screen memory
+  ScreenPixels: TScreenPixels absolute SCREEN_PIXELS_ADDR;
+  ScreenAttrs: TScreenAttrs absolute SCREEN_ATTRS_ADDR;
+
+// Build an attribute byte
+// Parameters:
+//   Ink: Foreground colour (0-7)
+//   Paper: Background colour (0-7)
+//   Bright: Brighter colours
+//   Flash: Swap ink and paper every 16 frames
+function MakeAttr(Ink, Paper: Byte; Bright, Flash: Boolean): Byte;
+
+// Set the attribute of one character cell
+procedure SetAttr(Column, Row: Byte; Attr: Byte);
+
+// Get the attribute of one character cell
+function GetAttr(Column, Row: Byte): Byte;
+
+// Fill the whole attribute area
+procedure FillAttrs(Attr: Byte);
+
+// Address of the bitmap byte containing pixel (X, Y)
+// The Spectrum interleaves screen rows: 010T TSSS LLLC CCCC
+// (T = third, S = scanline in cell, L = cell row in third, C = column)
+function PixelAddress(X, Y: Byte): Word;
+
+// Clear the bitmap to paper colour
+procedure ClearPixels;
+
+// Set the border colour
+procedure SetBorder(Colour: Byte);
+
+implementation
+
+function MakeAttr(Ink, Paper: Byte; Bright, Flash: Boolean): Byte;
+var
+  Attr: Byte;
+begin
+  Attr := (Ink and 7) or ((Paper and 7) shl 3);
+  if Bright then
+    Attr := Attr or ATTR_BRIGHT;
+  if Flash then
+    Attr := Attr or ATTR_FLASH;
+  MakeAttr := Attr;
+end;
+
+procedure SetAttr(Column, Row: Byte; Attr: Byte);
+begin
+  ScreenAttrs[Row, Column] := Attr;
+end;
+
+function GetAttr(Column, Row: Byte): Byte;
+begin
+  GetAttr := ScreenAttrs[Row, Column];
+end;
+
+procedure FillAttrs(Attr: Byte);
+var
+  Row, Column: Byte;
+begin
+  for Row := 0 to SCREEN_ROWS - 1 do
+    for Column := 0 to SCREEN_COLUMNS - 1 do
+      ScreenAttrs[Row, Column] := Attr;
+end;
+
+function PixelAddress(X, Y: Byte): Word;
+begin
+  PixelAddress := SCREEN_PIXELS_ADDR
+    or ((Y and $C0) shl 5)
+    or ((Y and $07) shl 8)
+    or ((Y and $38) shl 2)
+    or (X shr 3);
+end;
+
+procedure ClearPixels;
+var
+  I: Word;
+begin
+  for I := 0 to SCREEN_PIXELS_SIZE - 1 do
+    ScreenPixels[I] := 0;
+end;
+
+procedure SetBorder(Colour: Byte);
+begin
+  Port[ULA_PORT] := Colour and 7;
+end;
+
+end.